@@ -1,7 +1,192 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use time::OffsetDateTime;
 use time::macros::format_description;
 
+/// The features that make up the kernel's configuration, as listed in
+/// /proc/config.gz.
+const OPTIONS: &[&str] = &[
+    "smp",
+    "net",
+    "ext4_write",
+    "fat32_write",
+    "tracing",
+    "slab_debug",
+    "kasan",
+    "alloc_profile",
+    "kmemleak",
+    "bench",
+];
+
+/// Profiles, with the options each of them leaves out.
+const PROFILES: &[(&str, &[&str])] = &[
+    ("tiny", &["net", "ext4_write", "fat32_write", "tracing"]),
+    ("debug", &[]),
+    ("hardened", &["tracing"]),
+];
+
+/// Options that can't be built together, as they each replace the global
+/// allocator.
+const CONFLICTS: &[(&str, &str)] = &[
+    ("kasan", "alloc_profile"),
+    ("kmemleak", "kasan"),
+    ("kmemleak", "alloc_profile"),
+];
+
+fn enabled(feature: &str) -> bool {
+    std::env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_some()
+}
+
+fn config_error(msg: &str) -> ! {
+    println!("cargo::error=invalid kernel configuration: {msg}");
+    std::process::exit(1);
+}
+
+/// Checks that the chosen options go together, and works out which profile
+/// they came from.
+fn check_config() -> &'static str {
+    let mut profile = None;
+
+    for (name, excluded) in PROFILES {
+        if !enabled(name) {
+            continue;
+        }
+
+        if let Some(other) = profile.replace(*name) {
+            config_error(&format!(
+                "the `{other}` and `{name}` profiles can't be enabled together"
+            ));
+        }
+
+        if let Some(opt) = excluded.iter().find(|opt| enabled(opt)) {
+            config_error(&format!(
+                "the `{name}` profile leaves out `{opt}`; build it with --no-default-features"
+            ));
+        }
+    }
+
+    for (a, b) in CONFLICTS {
+        if enabled(a) && enabled(b) {
+            config_error(&format!(
+                "the `{a}` and `{b}` features can't be enabled together"
+            ));
+        }
+    }
+
+    if enabled("kmemleak") && std::env::var_os("CARGO_CFG_DEBUG_ASSERTIONS").is_none() {
+        config_error("the `kmemleak` feature is only supported in debug builds");
+    }
+
+    if enabled("kasan")
+        && !std::env::var("CARGO_ENCODED_RUSTFLAGS")
+            .unwrap_or_default()
+            .contains("sanitizer=kernel-address")
+    {
+        println!(
+            "cargo::warning=`kasan` is enabled without -Zsanitizer=kernel-address, so nothing \
+             will be checked; see the README for the RUSTFLAGS"
+        );
+    }
+
+    profile.unwrap_or(if enabled("default") {
+        "default"
+    } else {
+        "custom"
+    })
+}
+
+/// Renders the configuration in the format of a Linux `.config`.
+fn render_config(profile: &str) -> String {
+    let mut config = format!("#\n# moss kernel configuration\n# profile: {profile}\n#\n");
+
+    for opt in OPTIONS {
+        let name = opt.to_uppercase();
+
+        if enabled(opt) {
+            config.push_str(&format!("CONFIG_{name}=y\n"));
+        } else {
+            config.push_str(&format!("# CONFIG_{name} is not set\n"));
+        }
+    }
+
+    config
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    !crc
+}
+
+/// Wraps `data` in a gzip stream made of stored (uncompressed) deflate
+/// blocks, which is all that's needed for `zcat` to read it back.
+fn gzip_stored(data: &[u8]) -> Vec<u8> {
+    // Magic, deflate, no flags, no mtime, no extra flags, unknown OS.
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    let mut chunks = data.chunks(u16::MAX as usize).peekable();
+
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+const SYSCALL_TABLE: &str = "etc/syscalls_linux_aarch64.md";
+
+/// Reads the number and name of every syscall from the table in `etc/`.
+fn read_syscall_table() -> BTreeMap<u32, String> {
+    let table = std::fs::read_to_string(SYSCALL_TABLE).unwrap();
+
+    table
+        .lines()
+        .filter_map(|line| {
+            let cells: Vec<&str> = line.split('|').map(str::trim).collect();
+            // | 0x3f (63) | read | ... | implemented |
+            let nr = cells.get(1)?.split_once('(')?.1.strip_suffix(')')?;
+
+            Some((nr.parse().ok()?, cells[2].to_string()))
+        })
+        .collect()
+}
+
+/// Generates the syscall table served by /proc/syscalls. How each syscall is
+/// handled comes from the dispatcher's own table, through `support()`.
+fn gen_syscall_table(out_dir: &Path) {
+    println!("cargo::rerun-if-changed={SYSCALL_TABLE}");
+
+    let mut out = String::from("&[\n");
+
+    for (nr, name) in read_syscall_table() {
+        out.push_str(&format!(
+            "    SyscallInfo {{ nr: {nr}, name: \"{name}\", support: support({nr}) }},\n"
+        ));
+    }
+
+    out.push(']');
+    std::fs::write(out_dir.join("syscalls.rs"), out).unwrap();
+}
+
 fn main() {
     let linker_script = match std::env::var("CARGO_CFG_TARGET_ARCH") {
         Ok(arch) if arch == "aarch64" => PathBuf::from("./src/arch/arm64/boot/linker.ld"),
@@ -15,6 +200,18 @@ fn main() {
     println!("cargo::rerun-if-changed={}", linker_script.display());
     println!("cargo::rustc-link-arg=-T{}", linker_script.display());
 
+    // Check the configuration, and keep a copy of it for /proc/config.gz.
+    let profile = check_config();
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    std::fs::write(
+        out_dir.join("config.gz"),
+        gzip_stored(render_config(profile).as_bytes()),
+    )
+    .unwrap();
+    println!("cargo::rerun-if-env-changed=CARGO_ENCODED_RUSTFLAGS");
+
+    gen_syscall_table(&out_dir);
+
     // Set an environment variable with the date and time of the build
     let now = OffsetDateTime::now_utc();
     let format = format_description!(
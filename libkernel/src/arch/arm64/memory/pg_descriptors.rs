@@ -278,6 +278,27 @@ define_descriptor!(
     },
 );
 
+impl L2Descriptor {
+    const BLOCK_BITS: u64 = 0b01;
+
+    /// Returns `true` if this is a 2MiB block descriptor.
+    pub fn is_block(self) -> bool {
+        (self.0 & 0b11) == Self::BLOCK_BITS
+    }
+
+    /// Returns the L3 page descriptor that maps the `idx`th 4KiB page of this
+    /// block with the same attributes and permissions.
+    pub fn page_descriptor(self, idx: usize) -> L3Descriptor {
+        debug_assert!(self.is_block());
+        debug_assert!(idx < 1 << (Self::MAP_SHIFT - L3Descriptor::MAP_SHIFT));
+
+        // Block and page descriptors share the same attribute layout. The bits
+        // between a page's and a block's output address are RES0 in a block
+        // descriptor, so the page offset can be placed there directly.
+        L3Descriptor((self.0 | 0b11) + ((idx as u64) << L3Descriptor::MAP_SHIFT))
+    }
+}
+
 /// The decoded state of an L3 page descriptor.
 pub enum L3DescriptorState {
     /// The entry is not present.
@@ -503,6 +524,22 @@ mod tests {
         assert_eq!(decoded.permissions(), d.permissions());
     }
 
+    #[test]
+    fn test_l2_page_descriptor() {
+        let pa = PA::from_value(3 << 21);
+        let perms = PtePermissions::rw(USER_PERMS);
+        let block = L2Descriptor::new_map_pa(pa, MemoryType::Normal, perms);
+
+        assert!(block.is_block());
+
+        for idx in [0, 1, 511] {
+            let page = block.page_descriptor(idx);
+            let expected = L3Descriptor::new_map_pa(pa.add_pages(idx), MemoryType::Normal, perms);
+
+            assert_eq!(page, expected);
+        }
+    }
+
     #[test]
     fn test_l3_page_creation() {
         let pa = PA::from_value(PAGE_SIZE * 10); // 4KiB aligned
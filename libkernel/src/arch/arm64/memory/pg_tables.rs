@@ -2,13 +2,14 @@
 
 use super::pg_descriptors::{L0Descriptor, L1Descriptor, L2Descriptor, L3Descriptor, MemoryType};
 use crate::{
-    error::{MapError, Result},
+    error::{KernelError, MapError, Result},
     memory::{
         PAGE_SIZE,
         address::{TPA, TVA, VA},
         paging::{
             PaMapper, PageAllocator, PageTableEntry, PageTableMapper, PgTable, PgTableArray,
             TLBInvalidator, TableMapper, TableMapperTable, permissions::PtePermissions,
+            walk::WalkContext,
         },
         region::{PhysMemoryRegion, VirtMemoryRegion},
     },
@@ -317,6 +318,223 @@ where
     }
 }
 
+/// Looks up the L2 descriptor covering `va`, along with the L2 table that
+/// holds it.
+///
+/// Returns `Ok(None)` if no L2 table covers `va`, or
+/// `MapError::AlreadyMapped` if it is covered by a 1GiB block.
+fn find_l2_desc<PM: PageTableMapper>(
+    l0_table: TPA<PgTableArray<L0Table>>,
+    va: VA,
+    mapper: &mut PM,
+) -> Result<Option<(TPA<PgTableArray<L2Table>>, L2Descriptor)>> {
+    unsafe {
+        let l0_desc =
+            mapper.with_page_table(l0_table, |tbl| L0Table::from_ptr(tbl).get_desc(va))?;
+
+        let Some(l1_table) = l0_desc.next_table_address() else {
+            return Ok(None);
+        };
+
+        let l1_desc =
+            mapper.with_page_table(l1_table, |tbl| L1Table::from_ptr(tbl).get_desc(va))?;
+
+        let Some(l2_table) = l1_desc.next_table_address() else {
+            if l1_desc.is_valid() {
+                Err(MapError::AlreadyMapped)?;
+            }
+
+            return Ok(None);
+        };
+
+        let l2_desc =
+            mapper.with_page_table(l2_table, |tbl| L2Table::from_ptr(tbl).get_desc(va))?;
+
+        Ok(Some((l2_table, l2_desc)))
+    }
+}
+
+/// Returns the L3 table that `desc` points to, if none of its entries are
+/// in use.
+fn empty_l3_table<PM: PageTableMapper>(
+    desc: L2Descriptor,
+    mapper: &mut PM,
+) -> Result<Option<TPA<PgTableArray<L3Table>>>> {
+    let Some(l3_table) = desc.next_table_address() else {
+        return Ok(None);
+    };
+
+    let is_empty = unsafe {
+        mapper.with_page_table(l3_table, |tbl| {
+            let table = L3Table::from_ptr(tbl);
+
+            (0..L3Table::DESCRIPTORS_PER_PAGE).all(|idx| !table.get_idx(idx).is_valid())
+        })?
+    };
+
+    Ok(is_empty.then_some(l3_table))
+}
+
+/// Returns `true` if nothing is mapped within the 2MiB region starting at
+/// `va`, so that it can be mapped with a single block.
+///
+/// An L3 table with no entries in use doesn't prevent a block mapping, but
+/// must first be removed with [`take_empty_table`].
+pub fn is_block_unmapped<PM: PageTableMapper>(
+    l0_table: TPA<PgTableArray<L0Table>>,
+    va: VA,
+    mapper: &mut PM,
+) -> Result<bool> {
+    match find_l2_desc(l0_table, va, mapper) {
+        Ok(None) => Ok(true),
+        Ok(Some((_, desc))) if !desc.is_valid() => Ok(true),
+        Ok(Some((_, desc))) => Ok(empty_l3_table(desc, mapper)?.is_some()),
+        Err(KernelError::MappingError(MapError::AlreadyMapped)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Unlinks the L3 table covering `va` if none of its entries are in use,
+/// returning it so that the caller can free it once the TLB has been
+/// invalidated.
+pub fn take_empty_table<PM: PageTableMapper>(
+    l0_table: TPA<PgTableArray<L0Table>>,
+    va: VA,
+    ctx: &mut WalkContext<PM>,
+) -> Result<Option<TPA<PgTableArray<L3Table>>>> {
+    let Some((l2_table, desc)) = find_l2_desc(l0_table, va, ctx.mapper)? else {
+        return Ok(None);
+    };
+
+    let Some(l3_table) = empty_l3_table(desc, ctx.mapper)? else {
+        return Ok(None);
+    };
+
+    unsafe {
+        ctx.mapper.with_page_table(l2_table, |tbl| {
+            L2Table::from_ptr(tbl).set_desc(va, L2Descriptor::invalid(), ctx.invalidator);
+        })?;
+    }
+
+    Ok(Some(l3_table))
+}
+
+/// Replaces every 2MiB block mapping that overlaps `region` with an L3 table
+/// mapping the same memory, page by page, with the same attributes.
+///
+/// This allows page-granular operations, such as
+/// [`walk_and_modify_region`](super::pg_walk::walk_and_modify_region), to be
+/// performed on memory that was mapped with a block descriptor. `on_split` is
+/// called with the physical region of every block that was split.
+///
+/// 1GiB block mappings are left untouched.
+///
+/// # Errors
+///
+/// Returns an error if the `allocator` in `ctx` fails to provide a new L3
+/// table, in which case any blocks already visited remain split.
+pub fn split_block_mappings<PA, PM, F>(
+    l0_table: TPA<PgTableArray<L0Table>>,
+    region: VirtMemoryRegion,
+    ctx: &mut MappingContext<PA, PM>,
+    mut on_split: F,
+) -> Result<()>
+where
+    PA: PageAllocator,
+    PM: PageTableMapper,
+    F: FnMut(PhysMemoryRegion),
+{
+    // Advance to the start of the next entry at the level with the given
+    // shift, or `None` if that would wrap around the address space.
+    let next_entry = |va: usize, shift: usize| (va | ((1 << shift) - 1)).checked_add(1);
+
+    let mut va = region
+        .start_address()
+        .align(1 << L2Descriptor::MAP_SHIFT)
+        .value();
+    let end = region.end_address().value();
+
+    while va < end {
+        let addr = VA::from_value(va);
+
+        let l0_desc = unsafe {
+            ctx.mapper
+                .with_page_table(l0_table, |tbl| L0Table::from_ptr(tbl).get_desc(addr))?
+        };
+
+        let Some(l1_table) = l0_desc.next_table_address() else {
+            match next_entry(va, L0Descriptor::MAP_SHIFT) {
+                Some(next) => va = next,
+                None => break,
+            }
+            continue;
+        };
+
+        let l1_desc = unsafe {
+            ctx.mapper
+                .with_page_table(l1_table, |tbl| L1Table::from_ptr(tbl).get_desc(addr))?
+        };
+
+        let Some(l2_table) = l1_desc.next_table_address() else {
+            match next_entry(va, L1Descriptor::MAP_SHIFT) {
+                Some(next) => va = next,
+                None => break,
+            }
+            continue;
+        };
+
+        let l2_desc = unsafe {
+            ctx.mapper
+                .with_page_table(l2_table, |tbl| L2Table::from_ptr(tbl).get_desc(addr))?
+        };
+
+        if l2_desc.is_block()
+            && let Some(block_pa) = l2_desc.mapped_address()
+        {
+            let l3_table = ctx.allocator.allocate_page_table::<L3Table>()?;
+
+            unsafe {
+                ctx.mapper.with_page_table(l3_table, |tbl| {
+                    let table = L3Table::from_ptr(tbl);
+
+                    for idx in 0..L3Table::DESCRIPTORS_PER_PAGE {
+                        table.set_desc(
+                            addr.add_pages(idx),
+                            l2_desc.page_descriptor(idx),
+                            ctx.invalidator,
+                        );
+                    }
+                })?;
+
+                // Break-before-make: the block must be removed before the
+                // table that replaces it is installed.
+                ctx.mapper.with_page_table(l2_table, |tbl| {
+                    let table = L2Table::from_ptr(tbl);
+
+                    table.set_desc(addr, L2Descriptor::invalid(), ctx.invalidator);
+                    table.set_desc(
+                        addr,
+                        L2Descriptor::new_next_table(l3_table),
+                        ctx.invalidator,
+                    );
+                })?;
+            }
+
+            on_split(PhysMemoryRegion::new(
+                block_pa,
+                1 << L2Descriptor::MAP_SHIFT,
+            ));
+        }
+
+        match next_entry(va, L2Descriptor::MAP_SHIFT) {
+            Some(next) => va = next,
+            None => break,
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 #[allow(missing_docs)]
 pub mod tests {
@@ -931,4 +1149,168 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_split_block_mapping() -> Result<()> {
+        // L0, L1, L2 and the L3 table created by the split.
+        let mut harness = TestHarness::new(4);
+
+        let block_size = 1 << 21; // 2MiB
+        let block_va = VA::from_value(0x400_0000);
+        let phys_block = PhysMemoryRegion::new(PA::from_value(0x8000_0000), 2 * block_size);
+        let perms = PtePermissions::rw(true);
+
+        // Map two adjacent 2MiB blocks.
+        map_range(
+            harness.inner.root_table,
+            MapAttributes {
+                phys: phys_block,
+                virt: VirtMemoryRegion::new(block_va, 2 * block_size),
+                mem_type: MemoryType::Normal,
+                perms,
+            },
+            &mut harness.create_map_ctx(),
+        )?;
+
+        // Only the first block overlaps the region to split.
+        let mut split = Vec::new();
+        split_block_mappings(
+            harness.inner.root_table,
+            VirtMemoryRegion::new(block_va.add_pages(3), PAGE_SIZE),
+            &mut harness.create_map_ctx(),
+            |region| split.push(region),
+        )?;
+
+        assert_eq!(
+            split,
+            [PhysMemoryRegion::new(
+                phys_block.start_address(),
+                block_size
+            )]
+        );
+
+        // Every page of the first block is now individually mapped.
+        let mut pages = Vec::new();
+        walk_and_modify_region(
+            harness.inner.root_table,
+            VirtMemoryRegion::new(block_va, block_size),
+            &mut harness.inner.create_walk_ctx(),
+            |va, desc: L3Descriptor| {
+                assert_eq!(desc.permissions(), Some(perms));
+                pages.push((va, desc.mapped_address().unwrap()));
+                desc
+            },
+        )?;
+
+        assert_eq!(pages.len(), 512);
+        for (idx, (va, pa)) in pages.into_iter().enumerate() {
+            assert_eq!(va, block_va.add_pages(idx));
+            assert_eq!(pa, phys_block.start_address().add_pages(idx));
+        }
+
+        // The second block is untouched.
+        let result = walk_and_modify_region(
+            harness.inner.root_table,
+            VirtMemoryRegion::new(block_va.add_bytes(block_size), PAGE_SIZE),
+            &mut harness.inner.create_walk_ctx(),
+            |_, desc| desc,
+        );
+
+        assert!(matches!(
+            result,
+            Err(KernelError::MappingError(MapError::NotL3Mapped))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_without_blocks_is_noop() -> Result<()> {
+        let mut harness = TestHarness::new(4);
+
+        harness.map_4k_pages(0x8_0000, 0x1_0000_0000, 4, PtePermissions::ro(true))?;
+        let allocated = harness.inner.allocator.pages_allocated;
+
+        split_block_mappings(
+            harness.inner.root_table,
+            VirtMemoryRegion::new(VA::from_value(0), 1 << 40),
+            &mut harness.create_map_ctx(),
+            |_| panic!("No block should be split"),
+        )?;
+
+        assert_eq!(harness.inner.allocator.pages_allocated, allocated);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_unmapped_and_empty_table() -> Result<()> {
+        let mut harness = TestHarness::new(5);
+        let block_va = VA::from_value(0x1_0000_0000);
+        let page_va = block_va.add_pages(7);
+
+        assert!(is_block_unmapped(
+            harness.inner.root_table,
+            block_va,
+            &mut harness.inner.mapper
+        )?);
+
+        harness.map_4k_pages(0x8_0000, page_va.value(), 1, PtePermissions::rw(true))?;
+
+        assert!(!is_block_unmapped(
+            harness.inner.root_table,
+            block_va,
+            &mut harness.inner.mapper
+        )?);
+        assert!(
+            take_empty_table(
+                harness.inner.root_table,
+                block_va,
+                &mut harness.inner.create_walk_ctx()
+            )?
+            .is_none()
+        );
+
+        // Unmapping the page leaves an empty L3 table behind.
+        walk_and_modify_region(
+            harness.inner.root_table,
+            VirtMemoryRegion::new(page_va, PAGE_SIZE),
+            &mut harness.inner.create_walk_ctx(),
+            |_, _| L3Descriptor::invalid(),
+        )?;
+
+        assert!(is_block_unmapped(
+            harness.inner.root_table,
+            block_va,
+            &mut harness.inner.mapper
+        )?);
+
+        let l3_table = take_empty_table(
+            harness.inner.root_table,
+            block_va,
+            &mut harness.inner.create_walk_ctx(),
+        )?;
+
+        assert!(l3_table.is_some());
+
+        // With the table gone, the block can be mapped.
+        map_range(
+            harness.inner.root_table,
+            MapAttributes {
+                phys: PhysMemoryRegion::new(PA::from_value(0x8000_0000), 1 << 21),
+                virt: VirtMemoryRegion::new(block_va, 1 << 21),
+                mem_type: MemoryType::Normal,
+                perms: PtePermissions::rw(true),
+            },
+            &mut harness.create_map_ctx(),
+        )?;
+
+        assert!(!is_block_unmapped(
+            harness.inner.root_table,
+            block_va,
+            &mut harness.inner.mapper
+        )?);
+
+        Ok(())
+    }
 }
@@ -2,18 +2,20 @@
 
 use super::{
     pg_descriptors::L3Descriptor,
-    pg_tables::{L0Table, L3Table},
+    pg_tables::{L0Table, L1Table, L3Table},
 };
 use crate::{
     error::{MapError, Result},
     memory::{
         PAGE_SIZE,
-        address::{TPA, VA},
+        address::{PA, TPA, VA},
         paging::{
-            NullTlbInvalidator, PageTableEntry, PageTableMapper, PgTable, PgTableArray,
-            walk::{RecursiveWalker, WalkContext},
+            NullTlbInvalidator, PaMapper, PageTableEntry, PageTableMapper, PgTable, PgTableArray,
+            TableMapper,
+            permissions::PtePermissions,
+            walk::{RecursiveWalker, Translator, WalkContext},
         },
-        region::VirtMemoryRegion,
+        region::{PhysMemoryRegion, VirtMemoryRegion},
     },
 };
 
@@ -111,6 +113,71 @@ pub fn get_pte<PM: PageTableMapper>(
     Ok(descriptor)
 }
 
+impl Translator for L0Table {
+    fn translate<PM: PageTableMapper>(
+        table_pa: TPA<PgTableArray<Self>>,
+        va: VA,
+        ctx: &mut WalkContext<PM>,
+    ) -> Result<Option<(PA, usize, PtePermissions)>> {
+        let desc = unsafe {
+            ctx.mapper
+                .with_page_table(table_pa, |pgtable| Self::from_ptr(pgtable).get_desc(va))?
+        };
+
+        match desc.next_table_address() {
+            Some(next_pa) => L1Table::translate(next_pa, va, ctx),
+            None if desc.is_valid() => Err(MapError::InvalidDescriptor.into()),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Translator for L3Table {
+    fn translate<PM: PageTableMapper>(
+        table_pa: TPA<PgTableArray<Self>>,
+        va: VA,
+        ctx: &mut WalkContext<PM>,
+    ) -> Result<Option<(PA, usize, PtePermissions)>> {
+        let desc = unsafe {
+            ctx.mapper
+                .with_page_table(table_pa, |pgtable| Self::from_ptr(pgtable).get_desc(va))?
+        };
+
+        // Swapped out (e.g. `PROT_NONE`) pages still carry an address but
+        // have no permissions; treat them as unmapped.
+        match (desc.mapped_address(), desc.permissions()) {
+            (Some(pa), Some(perms)) => Ok(Some((pa, 1 << Self::Descriptor::MAP_SHIFT, perms))),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Translates the VA into a physical region plus an offset and permissions.
+///
+/// Unlike [`get_pte`], this also resolves addresses covered by block
+/// mappings, in which case the returned region is the whole block.
+pub fn translate<PM: PageTableMapper>(
+    l0_table: TPA<PgTableArray<L0Table>>,
+    va: VA,
+    mapper: &mut PM,
+) -> Result<Option<(PhysMemoryRegion, usize, PtePermissions)>> {
+    let mut walk_ctx = WalkContext {
+        mapper,
+        // Safe to not invalidate the TLB, as we are not modifying any PTEs.
+        invalidator: &NullTlbInvalidator {},
+    };
+
+    if let Some((pa, blk_sz, perms)) = L0Table::translate(l0_table, va, &mut walk_ctx)? {
+        debug_assert!(blk_sz.is_power_of_two());
+
+        let offset = va.value() & (blk_sz - 1);
+
+        Ok(Some((PhysMemoryRegion::new(pa, blk_sz), offset, perms)))
+    } else {
+        Ok(None)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -332,6 +399,79 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn translate_page_and_block_mappings() {
+        let mut harness = TestHarness::new(10);
+        let page_va = VA::from_value(0x1_0000_0000);
+        let block_va = VA::from_value(0x4_0000_0000);
+        let block_pa = PA::from_value(0x80_0000);
+        let block_size = 1 << <L2Table as PgTable>::Descriptor::MAP_SHIFT;
+
+        harness
+            .map_4k_pages(0x8_0000, page_va.value(), 1, PtePermissions::ro(true))
+            .unwrap();
+
+        let l1 = map_at_level(
+            harness.inner.root_table,
+            block_va,
+            &mut harness.create_map_ctx(),
+        )
+        .unwrap();
+        let l2 = map_at_level(l1, block_va, &mut harness.create_map_ctx()).unwrap();
+        let l2_desc =
+            L2Descriptor::new_map_pa(block_pa, MemoryType::Normal, PtePermissions::rw(true));
+        unsafe {
+            harness
+                .inner
+                .mapper
+                .with_page_table(l2, |l2_tbl| {
+                    L2Table::from_ptr(l2_tbl).set_desc(
+                        block_va,
+                        l2_desc,
+                        &harness.inner.invalidator,
+                    );
+                })
+                .unwrap();
+        }
+
+        let (region, offset, perms) = translate(
+            harness.inner.root_table,
+            page_va.add_bytes(0x123),
+            &mut harness.inner.mapper,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            region,
+            PhysMemoryRegion::new(PA::from_value(0x8_0000), PAGE_SIZE)
+        );
+        assert_eq!(offset, 0x123);
+        assert_eq!(perms, PtePermissions::ro(true));
+
+        let (region, offset, perms) = translate(
+            harness.inner.root_table,
+            block_va.add_pages(5),
+            &mut harness.inner.mapper,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(region, PhysMemoryRegion::new(block_pa, block_size));
+        assert_eq!(offset, 5 * PAGE_SIZE);
+        assert_eq!(perms, PtePermissions::rw(true));
+
+        assert!(
+            translate(
+                harness.inner.root_table,
+                VA::from_value(0xDEADBEEF000),
+                &mut harness.inner.mapper,
+            )
+            .unwrap()
+            .is_none()
+        );
+    }
+
     #[test]
     fn walk_unmapped_region_does_nothing() {
         let mut harness = TestHarness::new(10);
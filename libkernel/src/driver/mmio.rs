@@ -0,0 +1,74 @@
+//! Access to memory-mapped device registers.
+//!
+//! Drivers that talk to their registers through [`Mmio`] rather than raw
+//! pointers can be handed a [`MmioRegion`] in the kernel and a mock register
+//! block in host unit tests.
+
+use crate::memory::address::VA;
+use alloc::sync::Arc;
+
+/// A block of 32-bit device registers, addressed by byte offset.
+pub trait Mmio: Send + Sync {
+    /// Reads the register at `offset`.
+    fn read32(&self, offset: usize) -> u32;
+
+    /// Writes `val` to the register at `offset`.
+    fn write32(&self, offset: usize, val: u32);
+
+    /// Read-modify-write of the register at `offset`.
+    fn modify32(&self, offset: usize, f: impl FnOnce(u32) -> u32) {
+        self.write32(offset, f(self.read32(offset)));
+    }
+}
+
+/// Lets a register block be shared, e.g. between a driver and the test poking
+/// at it.
+impl<M: Mmio> Mmio for Arc<M> {
+    fn read32(&self, offset: usize) -> u32 {
+        (**self).read32(offset)
+    }
+
+    fn write32(&self, offset: usize, val: u32) {
+        (**self).write32(offset, val);
+    }
+}
+
+/// Registers mapped into the kernel's address space.
+pub struct MmioRegion {
+    base: VA,
+}
+
+impl MmioRegion {
+    /// Wraps the registers mapped at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be a device mapping that stays valid for the lifetime of
+    /// the region, and must cover every offset the driver accesses.
+    pub unsafe fn new(base: VA) -> Self {
+        Self { base }
+    }
+
+    /// The address the registers are mapped at.
+    pub fn base(&self) -> VA {
+        self.base
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        debug_assert!(offset.is_multiple_of(4));
+
+        self.base.add_bytes(offset).as_ptr_mut().cast()
+    }
+}
+
+impl Mmio for MmioRegion {
+    fn read32(&self, offset: usize) -> u32 {
+        // SAFETY: The caller of `new` guaranteed that the mapping is valid.
+        unsafe { self.reg(offset).read_volatile() }
+    }
+
+    fn write32(&self, offset: usize, val: u32) {
+        // SAFETY: As above.
+        unsafe { self.reg(offset).write_volatile(val) }
+    }
+}
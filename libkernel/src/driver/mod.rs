@@ -0,0 +1,33 @@
+//! Device descriptor types and building blocks for drivers.
+
+pub mod mmio;
+pub mod pl031;
+pub mod timer;
+
+/// A major/minor pair identifying a character or block device.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct CharDevDescriptor {
+    /// The major device number (identifies the driver).
+    pub major: u64,
+    /// The minor device number (identifies the device instance).
+    pub minor: u64,
+}
+
+impl CharDevDescriptor {
+    /// Encodes the descriptor as a userspace `dev_t`, matching glibc's and
+    /// musl's `makedev`.
+    pub fn encode(&self) -> u64 {
+        ((self.major & 0xffff_f000) << 32)
+            | ((self.major & 0xfff) << 8)
+            | ((self.minor & 0xffff_ff00) << 12)
+            | (self.minor & 0xff)
+    }
+
+    /// Decodes a userspace `dev_t`, as produced by [`Self::encode`].
+    pub fn decode(dev: u64) -> Self {
+        Self {
+            major: ((dev >> 8) & 0xfff) | ((dev >> 32) & 0xffff_f000),
+            minor: (dev & 0xff) | ((dev >> 12) & 0xffff_ff00),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Register-level driver for the ARM PrimeCell PL031 real-time clock.
+
+use super::mmio::Mmio;
+
+/// Data register: the current count, in seconds.
+const RTCDR: usize = 0x00;
+/// Match register: the count at which the alarm fires.
+const RTCMR: usize = 0x04;
+/// Load register: sets the count.
+const RTCLR: usize = 0x08;
+/// Interrupt mask set/clear register.
+const RTCIMSC: usize = 0x10;
+/// Masked interrupt status register.
+const RTCMIS: usize = 0x18;
+/// Interrupt clear register.
+const RTCICR: usize = 0x1c;
+
+/// The only interrupt the device has: the count reached the match register.
+const RTC_INT: u32 = 1;
+
+/// A PL031 behind the registers `M`.
+pub struct Pl031<M> {
+    regs: M,
+}
+
+impl<M: Mmio> Pl031<M> {
+    /// Wraps the device at `regs`.
+    pub fn new(regs: M) -> Self {
+        Self { regs }
+    }
+
+    /// The current time, in seconds since the epoch.
+    pub fn time(&self) -> u32 {
+        self.regs.read32(RTCDR)
+    }
+
+    /// Sets the current time, in seconds since the epoch.
+    pub fn set_time(&self, secs: u32) {
+        self.regs.write32(RTCLR, secs);
+    }
+
+    /// Arms the alarm to interrupt once the time reaches `secs`, replacing any
+    /// alarm set before.
+    pub fn set_alarm(&self, secs: u32) {
+        self.regs.write32(RTCMR, secs);
+        // Don't let a match from the old alarm fire the new one.
+        self.regs.write32(RTCICR, RTC_INT);
+        self.regs.modify32(RTCIMSC, |imsc| imsc | RTC_INT);
+    }
+
+    /// Disarms the alarm.
+    pub fn cancel_alarm(&self) {
+        self.regs.modify32(RTCIMSC, |imsc| imsc & !RTC_INT);
+        self.regs.write32(RTCICR, RTC_INT);
+    }
+
+    /// Acknowledges the alarm interrupt. Returns `false` if the device wasn't
+    /// the one interrupting.
+    pub fn handle_irq(&self) -> bool {
+        if self.regs.read32(RTCMIS) & RTC_INT == 0 {
+            return false;
+        }
+
+        self.regs.write32(RTCICR, RTC_INT);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{MockIrq, MockMmio};
+    use alloc::sync::Arc;
+
+    /// Raw interrupt status register, which the driver has no use for.
+    const RTCRIS: usize = 0x14;
+
+    /// Models the parts of a PL031 the driver relies on: loading the count,
+    /// the interrupt status registers and the interrupt line.
+    fn model() -> (Arc<MockMmio>, MockIrq) {
+        let mmio = Arc::new(MockMmio::new());
+        let irq = MockIrq::new();
+        let line = irq.clone();
+
+        mmio.on_write(move |regs, offset, val| {
+            match offset {
+                RTCLR => regs.set(RTCDR, val),
+                RTCICR => regs.set(RTCRIS, regs.get(RTCRIS) & !val),
+                _ => regs.set(offset, val),
+            }
+
+            let mis = regs.get(RTCRIS) & regs.get(RTCIMSC);
+            regs.set(RTCMIS, mis);
+            line.set(mis != 0);
+        });
+
+        (mmio, irq)
+    }
+
+    /// Moves the count on to `secs`, firing the alarm if it's reached.
+    fn tick(mmio: &MockMmio, irq: &MockIrq, secs: u32) {
+        mmio.set(RTCDR, secs);
+
+        if secs == mmio.get(RTCMR) {
+            mmio.set(RTCRIS, RTC_INT);
+        }
+
+        let mis = mmio.get(RTCRIS) & mmio.get(RTCIMSC);
+        mmio.set(RTCMIS, mis);
+        irq.set(mis != 0);
+    }
+
+    #[test]
+    fn reads_and_sets_time() {
+        let (mmio, _) = model();
+        let rtc = Pl031::new(mmio.clone());
+
+        mmio.set(RTCDR, 1_700_000_000);
+        assert_eq!(rtc.time(), 1_700_000_000);
+
+        rtc.set_time(42);
+        assert_eq!(rtc.time(), 42);
+        assert_eq!(mmio.writes(), [(RTCLR, 42)]);
+    }
+
+    #[test]
+    fn alarm_interrupts_and_is_acked() {
+        let (mmio, irq) = model();
+        let rtc = Pl031::new(mmio.clone());
+
+        rtc.set_alarm(10);
+        tick(&mmio, &irq, 9);
+        assert!(!irq.is_raised());
+        assert!(!rtc.handle_irq());
+
+        tick(&mmio, &irq, 10);
+        assert!(irq.is_raised());
+        assert_eq!(irq.count(), 1);
+
+        assert!(rtc.handle_irq());
+        assert!(!irq.is_raised());
+        assert!(!rtc.handle_irq());
+    }
+
+    #[test]
+    fn rearming_drops_stale_alarm() {
+        let (mmio, irq) = model();
+        let rtc = Pl031::new(mmio.clone());
+
+        // A match that happened with the interrupt masked...
+        mmio.set(RTCMR, 5);
+        tick(&mmio, &irq, 5);
+        assert!(!irq.is_raised());
+
+        // ...mustn't fire as soon as an alarm is set.
+        rtc.set_alarm(20);
+        assert!(!irq.is_raised());
+
+        tick(&mmio, &irq, 20);
+        assert!(irq.is_raised());
+    }
+
+    #[test]
+    fn cancelled_alarm_stays_quiet() {
+        let (mmio, irq) = model();
+        let rtc = Pl031::new(mmio.clone());
+
+        rtc.set_alarm(10);
+        rtc.cancel_alarm();
+        tick(&mmio, &irq, 10);
+
+        assert!(!irq.is_raised());
+        assert_eq!(irq.count(), 0);
+    }
+}
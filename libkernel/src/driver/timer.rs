@@ -0,0 +1,181 @@
+//! A queue of timed events, ordered by deadline.
+
+use alloc::collections::binary_heap::BinaryHeap;
+use core::cmp::Ordering;
+
+struct Entry<D, T> {
+    when: D,
+    /// Breaks ties between equal deadlines, so that they fire in the order
+    /// they were queued.
+    seq: u64,
+    item: T,
+}
+
+impl<D: Ord, T> PartialEq for Entry<D, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<D: Ord, T> Eq for Entry<D, T> {}
+
+impl<D: Ord, T> PartialOrd for Entry<D, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<D: Ord, T> Ord for Entry<D, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` is a max-heap; the earliest entry must come out first.
+        (&self.when, self.seq)
+            .cmp(&(&other.when, other.seq))
+            .reverse()
+    }
+}
+
+/// Events of type `T`, each due at a deadline of type `D`.
+///
+/// The deadline type only needs to be ordered, so anything from raw counter
+/// ticks to a full instant can be used.
+pub struct TimerQueue<D, T> {
+    heap: BinaryHeap<Entry<D, T>>,
+    seq: u64,
+}
+
+impl<D: Ord + Copy, T> TimerQueue<D, T> {
+    /// Creates an empty queue.
+    pub const fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    /// Queues `item` to fire at `when`.
+    pub fn push(&mut self, when: D, item: T) {
+        let seq = self.seq;
+
+        self.seq = self.seq.wrapping_add(1);
+        self.heap.push(Entry { when, seq, item });
+    }
+
+    /// The deadline of the earliest event, which is when the hardware timer
+    /// should next fire.
+    pub fn next_deadline(&self) -> Option<D> {
+        self.heap.peek().map(|e| e.when)
+    }
+
+    /// Removes and returns the earliest event if it's due by `now`.
+    pub fn pop_expired(&mut self, now: D) -> Option<(D, T)> {
+        if self.heap.peek()?.when > now {
+            return None;
+        }
+
+        self.heap.pop().map(|e| (e.when, e.item))
+    }
+
+    /// Keeps only the events for which `f` returns `true`.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        self.heap.retain(|e| f(&e.item));
+    }
+
+    /// The number of queued events.
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Whether no events are queued.
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
+
+impl<D: Ord + Copy, T> Default for TimerQueue<D, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn drain(q: &mut TimerQueue<u64, u32>, now: u64) -> Vec<u32> {
+        core::iter::from_fn(|| q.pop_expired(now))
+            .map(|(_, item)| item)
+            .collect()
+    }
+
+    #[test]
+    fn fires_in_deadline_order() {
+        let mut q = TimerQueue::new();
+
+        q.push(30, 3);
+        q.push(10, 1);
+        q.push(20, 2);
+
+        assert_eq!(q.next_deadline(), Some(10));
+        assert_eq!(drain(&mut q, 100), [1, 2, 3]);
+        assert!(q.is_empty());
+        assert_eq!(q.next_deadline(), None);
+    }
+
+    #[test]
+    fn only_expired_events_fire() {
+        let mut q = TimerQueue::new();
+
+        q.push(10, 1);
+        q.push(20, 2);
+        q.push(30, 3);
+
+        assert!(drain(&mut q, 5).is_empty());
+        assert_eq!(drain(&mut q, 20), [1, 2]);
+        assert_eq!(q.next_deadline(), Some(30));
+        assert_eq!(q.len(), 1);
+    }
+
+    #[test]
+    fn equal_deadlines_fire_in_queue_order() {
+        let mut q = TimerQueue::new();
+
+        for item in 0..8 {
+            q.push(10, item);
+        }
+
+        assert_eq!(drain(&mut q, 10), [0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn retain_removes_and_rearms() {
+        let mut q = TimerQueue::new();
+
+        q.push(10, 1);
+        q.push(20, 2);
+        q.push(30, 1);
+
+        q.retain(|&item| item != 1);
+
+        assert_eq!(q.len(), 1);
+        assert_eq!(q.next_deadline(), Some(20));
+    }
+
+    #[test]
+    fn periodic_event_requeued_while_draining() {
+        let mut q = TimerQueue::new();
+        let mut fired = Vec::new();
+
+        q.push(10, 0);
+
+        // A periodic timer re-queues itself one period on from each firing,
+        // and catches up on every period that has already passed.
+        while let Some((when, item)) = q.pop_expired(35) {
+            fired.push(when);
+            q.push(when + 10, item);
+        }
+
+        assert_eq!(fired, [10, 20, 30]);
+        assert_eq!(q.next_deadline(), Some(40));
+    }
+}
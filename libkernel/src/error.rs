@@ -96,6 +96,10 @@ pub enum IoError {
     /// Corruption found in the filesystem metadata.
     #[error("Corruption found in the filesystem metadata")]
     MetadataCorruption,
+
+    /// A block failed integrity verification.
+    #[error("A block failed integrity verification")]
+    VerificationFailed,
 }
 
 /// Errors from filesystem operations.
@@ -149,6 +153,10 @@ pub enum FsError {
     #[error("Too many open files")]
     TooManyFiles,
 
+    /// Too many open files in the whole system.
+    #[error("Too many open files in system")]
+    TooManyFilesInSystem,
+
     /// The device could not be found.
     #[error("The device could not be found")]
     NoDevice,
@@ -160,6 +168,22 @@ pub enum FsError {
     /// Attempted to rename across devices.
     #[error("Attempted to rename from cross device")]
     CrossDevice,
+
+    /// The filesystem is mounted read-only.
+    #[error("The filesystem is mounted read-only")]
+    ReadOnly,
+
+    /// A disk quota hard limit would be exceeded.
+    #[error("Disk quota exceeded")]
+    QuotaExceeded,
+
+    /// No space left on the device.
+    #[error("No space left on device")]
+    NoSpace,
+
+    /// Nothing is behind the file to talk to, such as a reader of a FIFO.
+    #[error("No such device or address")]
+    NoDeviceOrAddress,
 }
 
 /// Errors that occur when loading or parsing an executable.
@@ -293,6 +317,18 @@ pub enum KernelError {
     #[error("Not a socket")]
     NotASocket,
 
+    /// Bad message, e.g. failed authentication.
+    #[error("Bad message")]
+    BadMessage,
+
+    /// Resource deadlock would occur.
+    #[error("Resource deadlock would occur")]
+    Deadlock,
+
+    /// No data available, e.g. no such extended attribute.
+    #[error("No data available")]
+    NoData,
+
     /// Other error with a static description.
     #[error("{0}")]
     Other(&'static str),
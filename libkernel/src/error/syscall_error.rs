@@ -42,13 +42,17 @@ pub const EPIPE: isize = -32;
 pub const EDOM: isize = -33;
 pub const ERANGE: isize = -34;
 pub const EWOULDBLOCK: isize = -EAGAIN;
+pub const EDEADLK: isize = -35;
 pub const ENAMETOOLONG: isize = -36;
 pub const ENOSYS: isize = -38;
 pub const ENOTEMPTY: isize = -39;
 pub const ELOOP: isize = -40;
+pub const ENODATA: isize = -61;
+pub const EBADMSG: isize = -74;
 pub const EAFNOSUPPORT: isize = -97;
 pub const EOPNOTSUPP: isize = -95;
 pub const ETIMEDOUT: isize = -110;
+pub const EDQUOT: isize = -122;
 
 pub fn kern_err_to_syscall(err: KernelError) -> isize {
     match err {
@@ -69,12 +73,20 @@ pub fn kern_err_to_syscall(err: KernelError) -> isize {
         KernelError::Fs(FsError::InvalidInput) => EINVAL, // TODO: Is this right?
         KernelError::Fs(FsError::PermissionDenied) => EACCES,
         KernelError::Fs(FsError::TooManyFiles) => EMFILE,
+        KernelError::Fs(FsError::TooManyFilesInSystem) => ENFILE,
         KernelError::Fs(FsError::NoDevice) => ENODEV,
         KernelError::Fs(FsError::Loop) => ELOOP,
+        KernelError::Fs(FsError::CrossDevice) => EXDEV,
+        KernelError::Fs(FsError::ReadOnly) => EROFS,
+        KernelError::Fs(FsError::QuotaExceeded) => EDQUOT,
+        KernelError::Fs(FsError::NoSpace) => ENOSPC,
+        KernelError::Fs(FsError::NoDeviceOrAddress) => ENXIO,
+        KernelError::Fs(FsError::OutOfBounds) => EFBIG,
         KernelError::NotATty => ENOTTY,
         KernelError::SeekPipe => ESPIPE,
         KernelError::NotSupported => ENOSYS,
         KernelError::NoMemory => ENOMEM,
+        KernelError::TooLarge => E2BIG,
         KernelError::TimedOut => ETIMEDOUT,
         KernelError::RangeError => ERANGE,
         KernelError::NoChildProcess => ECHILD,
@@ -82,6 +94,10 @@ pub fn kern_err_to_syscall(err: KernelError) -> isize {
         KernelError::Interrupted => EINTR,
         KernelError::NoProcess => ESRCH,
         KernelError::AddressFamilyNotSupported => EAFNOSUPPORT,
+        KernelError::BadMessage => EBADMSG,
+        KernelError::Deadlock => EDEADLK,
+        KernelError::NoData => ENODATA,
+        KernelError::Io(_) => EIO,
         e => todo!("{e}"),
     }
 }
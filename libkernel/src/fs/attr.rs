@@ -138,6 +138,29 @@ impl FileAttr {
     }
 }
 
+/// The user and group a new inode belongs to: the filesystem IDs of the task
+/// creating it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileOwner {
+    /// The owning user, charged for the inode under disk quotas.
+    pub uid: Uid,
+    /// The owning group.
+    pub gid: Gid,
+}
+
+impl FileOwner {
+    /// Creates a new `FileOwner`.
+    pub fn new(uid: Uid, gid: Gid) -> Self {
+        Self { uid, gid }
+    }
+
+    /// Returns root's ownership, for inodes the kernel makes on its own
+    /// behalf.
+    pub fn root() -> Self {
+        Self::new(Uid::new_root(), Gid::new_root_group())
+    }
+}
+
 impl Default for FileAttr {
     fn default() -> Self {
         Self {
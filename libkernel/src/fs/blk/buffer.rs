@@ -108,8 +108,98 @@ impl BlockBuffer {
         Ok(())
     }
 
+    /// Hints that `len` bytes starting at `offset` will be read soon.
+    pub async fn readahead(&self, offset: u64, len: u64) -> Result<()> {
+        let block_size = self.block_size as u64;
+        let start_block = offset / block_size;
+        let end_block = offset
+            .saturating_add(len)
+            .div_ceil(block_size)
+            .min(self.dev.num_blocks());
+
+        if end_block <= start_block {
+            return Ok(());
+        }
+
+        self.dev
+            .readahead(start_block, end_block - start_block)
+            .await
+    }
+
     /// Forwards a sync call to the underlying device.
     pub async fn sync(&self) -> Result<()> {
         self.dev.sync().await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{IoError, KernelError};
+    use crate::test::MockBlockDevice;
+    use alloc::{sync::Arc, vec::Vec};
+
+    const BS: usize = 16;
+
+    /// A four-block device where each byte holds its own offset.
+    fn setup() -> (Arc<MockBlockDevice>, BlockBuffer) {
+        let dev = Arc::new(MockBlockDevice::new((0..4 * BS as u8).collect(), BS));
+        let buf = BlockBuffer::new(Box::new(dev.clone()));
+
+        (dev, buf)
+    }
+
+    #[tokio::test]
+    async fn unaligned_read_spans_blocks() {
+        let (dev, buf) = setup();
+        let mut out = [0; 20];
+
+        buf.read_at(10, &mut out).await.unwrap();
+
+        assert_eq!(out.to_vec(), (10..30).collect::<Vec<u8>>());
+        assert_eq!(dev.reads(), 1);
+    }
+
+    #[tokio::test]
+    async fn read_obj_at_offset() {
+        let (_, buf) = setup();
+
+        let val: u32 = buf.read_obj(BS as u64 - 2).await.unwrap();
+
+        assert_eq!(val, u32::from_le_bytes([14, 15, 16, 17]));
+    }
+
+    #[tokio::test]
+    async fn write_preserves_rest_of_block() {
+        let (dev, buf) = setup();
+
+        buf.write_at(BS as u64 + 4, &[0xff; 20]).await.unwrap();
+
+        let mut expected: Vec<u8> = (0..4 * BS as u8).collect();
+        expected[BS + 4..BS + 24].fill(0xff);
+
+        assert_eq!(dev.contents(), expected);
+        assert_eq!(dev.writes(), 1);
+    }
+
+    #[tokio::test]
+    async fn empty_io_touches_nothing() {
+        let (dev, buf) = setup();
+
+        buf.read_at(3, &mut []).await.unwrap();
+        buf.write_at(3, &[]).await.unwrap();
+
+        assert_eq!((dev.reads(), dev.writes()), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn read_past_end_fails() {
+        let (_, buf) = setup();
+        let mut out = [0; 8];
+
+        assert!(matches!(
+            buf.read_at(4 * BS as u64 - 4, &mut out).await,
+            Err(KernelError::Io(IoError::OutOfBounds))
+        ));
+    }
+}
@@ -109,6 +109,11 @@ impl BlockDevice for RamdiskBlkDev {
         BLOCK_SIZE
     }
 
+    /// The number of blocks on the ramdisk.
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
     /// Flushes any caches to the underlying device.
     async fn sync(&self) -> Result<()> {
         Ok(())
@@ -0,0 +1,372 @@
+//! Directory entry cache.
+//!
+//! A [`DentryCache`] remembers the result of looking up a name in a directory,
+//! keyed by the directory's [`InodeId`] and the name, so that walking a path
+//! doesn't have to ask the filesystem about every component again. Both hits
+//! and misses are cached: a negative entry records that a name doesn't exist.
+//!
+//! The cache has no way of noticing changes by itself. Whoever changes a
+//! directory must invalidate the names it touched, which is why only
+//! filesystems that opt in through [`Filesystem::cache_dentries`] are cached.
+//! Since a lookup can race with such a change, results are only cached if
+//! nothing was invalidated while the filesystem was being asked.
+//!
+//! [`Filesystem::cache_dentries`]: super::Filesystem::cache_dentries
+
+use super::{Inode, InodeId};
+use crate::{CpuOps, sync::spinlock::SpinLockIrq};
+use alloc::{
+    collections::btree_map::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+/// The result of looking a name up in the cache.
+pub enum CachedLookup {
+    /// The name refers to this inode.
+    Found(Arc<dyn Inode>),
+    /// The name is known not to exist.
+    Negative,
+    /// Nothing is cached for the name; the filesystem has to be asked.
+    Miss,
+}
+
+/// Counters describing the contents of the cache, as reported through
+/// `/proc/sys/fs/dentry-state`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DentryStats {
+    /// Number of cached entries, positive and negative.
+    pub entries: usize,
+    /// Number of negative entries.
+    pub negative: usize,
+    /// Lookups answered from the cache.
+    pub hits: u64,
+    /// Lookups that had to go to the filesystem.
+    pub misses: u64,
+}
+
+struct Dentry {
+    inode: Option<Arc<dyn Inode>>,
+    /// Position of the entry in the LRU list.
+    last_used: u64,
+}
+
+struct DentryCacheInner {
+    entries: BTreeMap<(InodeId, String), Dentry>,
+    /// Keys of `entries`, least recently used first.
+    lru: BTreeMap<u64, (InodeId, String)>,
+    next_use: u64,
+    /// Bumped on every invalidation.
+    generation: u64,
+    negative: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl DentryCacheInner {
+    fn remove(&mut self, key: &(InodeId, String)) {
+        if let Some(dentry) = self.entries.remove(key) {
+            self.lru.remove(&dentry.last_used);
+
+            if dentry.inode.is_none() {
+                self.negative -= 1;
+            }
+        }
+    }
+
+    fn remove_range(&mut self, first: InodeId, last: InodeId) {
+        self.generation += 1;
+
+        let keys: Vec<_> = self
+            .entries
+            .range((first, String::new())..)
+            .map(|(key, _)| key)
+            .take_while(|(dir, _)| *dir <= last)
+            .cloned()
+            .collect();
+
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+}
+
+/// A bounded cache of directory entries, evicting the least recently used
+/// entry once full.
+pub struct DentryCache<C: CpuOps> {
+    capacity: usize,
+    inner: SpinLockIrq<DentryCacheInner, C>,
+}
+
+impl<C: CpuOps> DentryCache<C> {
+    /// Creates an empty cache holding at most `capacity` entries.
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: SpinLockIrq::new(DentryCacheInner {
+                entries: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                next_use: 0,
+                generation: 0,
+                negative: 0,
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    /// Looks up `name` in the directory `parent`.
+    pub fn lookup(&self, parent: InodeId, name: &str) -> CachedLookup {
+        let mut inner = self.inner.lock_save_irq();
+        let key = (parent, name.to_string());
+        let next_use = inner.next_use;
+
+        let Some(dentry) = inner.entries.get_mut(&key) else {
+            inner.misses += 1;
+            return CachedLookup::Miss;
+        };
+
+        let last_used = core::mem::replace(&mut dentry.last_used, next_use);
+        let result = match &dentry.inode {
+            Some(inode) => CachedLookup::Found(inode.clone()),
+            None => CachedLookup::Negative,
+        };
+
+        inner.lru.remove(&last_used);
+        inner.lru.insert(next_use, key);
+        inner.next_use += 1;
+        inner.hits += 1;
+
+        result
+    }
+
+    /// Returns a token to pass to [`DentryCache::insert`], taken before
+    /// asking the filesystem.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock_save_irq().generation
+    }
+
+    /// Caches the result of looking up `name` in `parent`: `Some(inode)` if
+    /// it was found, or `None` if it doesn't exist.
+    ///
+    /// Nothing is cached if anything was invalidated since `generation` was
+    /// taken, as the result may already be out of date.
+    pub fn insert(
+        &self,
+        parent: InodeId,
+        name: &str,
+        inode: Option<Arc<dyn Inode>>,
+        generation: u64,
+    ) {
+        let mut inner = self.inner.lock_save_irq();
+
+        if self.capacity == 0 || inner.generation != generation {
+            return;
+        }
+
+        let key = (parent, name.to_string());
+
+        inner.remove(&key);
+
+        while inner.entries.len() >= self.capacity {
+            let Some((_, oldest)) = inner.lru.pop_first() else {
+                break;
+            };
+
+            inner.remove(&oldest);
+        }
+
+        let last_used = inner.next_use;
+
+        if inode.is_none() {
+            inner.negative += 1;
+        }
+
+        inner.next_use += 1;
+        inner.lru.insert(last_used, key.clone());
+        inner.entries.insert(key, Dentry { inode, last_used });
+    }
+
+    /// Forgets what is cached for `name` in `parent`. Must be called whenever
+    /// the name is created, removed or renamed.
+    pub fn invalidate(&self, parent: InodeId, name: &str) {
+        let mut inner = self.inner.lock_save_irq();
+
+        inner.generation += 1;
+        inner.remove(&(parent, name.to_string()));
+    }
+
+    /// Forgets every entry cached in the directory `dir`.
+    pub fn invalidate_dir(&self, dir: InodeId) {
+        self.inner.lock_save_irq().remove_range(dir, dir);
+    }
+
+    /// Forgets every entry cached for directories of the filesystem `fs_id`.
+    pub fn invalidate_fs(&self, fs_id: u64) {
+        self.inner.lock_save_irq().remove_range(
+            InodeId::from_fsid_and_inodeid(fs_id, 0),
+            InodeId::from_fsid_and_inodeid(fs_id, u64::MAX),
+        );
+    }
+
+    /// Forgets every cached entry.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock_save_irq();
+
+        inner.generation += 1;
+        inner.entries.clear();
+        inner.lru.clear();
+        inner.negative = 0;
+    }
+
+    /// Returns the current counters.
+    pub fn stats(&self) -> DentryStats {
+        let inner = self.inner.lock_save_irq();
+
+        DentryStats {
+            entries: inner.entries.len(),
+            negative: inner.negative,
+            hits: inner.hits,
+            misses: inner.misses,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use core::any::Any;
+
+    struct TestInode(InodeId);
+
+    impl Inode for TestInode {
+        fn id(&self) -> InodeId {
+            self.0
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    fn id(fs: u64, ino: u64) -> InodeId {
+        InodeId::from_fsid_and_inodeid(fs, ino)
+    }
+
+    fn inode(fs: u64, ino: u64) -> Option<Arc<dyn Inode>> {
+        Some(Arc::new(TestInode(id(fs, ino))))
+    }
+
+    fn found(result: CachedLookup) -> Option<InodeId> {
+        match result {
+            CachedLookup::Found(inode) => Some(inode.id()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn positive_and_negative_entries() {
+        let cache = DentryCache::<MockCpuOps>::new(16);
+        let dir = id(10, 1);
+
+        assert!(matches!(cache.lookup(dir, "a"), CachedLookup::Miss));
+
+        cache.insert(dir, "a", inode(10, 2), 0);
+        cache.insert(dir, "b", None, 0);
+
+        assert_eq!(found(cache.lookup(dir, "a")), Some(id(10, 2)));
+        assert!(matches!(cache.lookup(dir, "b"), CachedLookup::Negative));
+        assert!(matches!(cache.lookup(id(10, 2), "a"), CachedLookup::Miss));
+
+        assert_eq!(
+            cache.stats(),
+            DentryStats {
+                entries: 2,
+                negative: 1,
+                hits: 2,
+                misses: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn insert_replaces_entry() {
+        let cache = DentryCache::<MockCpuOps>::new(16);
+        let dir = id(10, 1);
+
+        cache.insert(dir, "a", None, 0);
+        cache.insert(dir, "a", inode(10, 2), 0);
+
+        assert_eq!(found(cache.lookup(dir, "a")), Some(id(10, 2)));
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().negative, 0);
+    }
+
+    #[test]
+    fn insert_after_invalidation_is_dropped() {
+        let cache = DentryCache::<MockCpuOps>::new(16);
+        let dir = id(10, 1);
+        let generation = cache.generation();
+
+        // "a" is created while the lookup that didn't find it is in flight.
+        cache.invalidate(dir, "a");
+        cache.insert(dir, "a", None, generation);
+
+        assert!(matches!(cache.lookup(dir, "a"), CachedLookup::Miss));
+    }
+
+    #[test]
+    fn invalidation() {
+        let cache = DentryCache::<MockCpuOps>::new(16);
+
+        cache.insert(id(10, 1), "a", inode(10, 2), 0);
+        cache.insert(id(10, 1), "b", None, 0);
+        cache.insert(id(10, 3), "c", inode(10, 4), 0);
+        cache.insert(id(11, 1), "d", inode(11, 2), 0);
+
+        cache.invalidate(id(10, 1), "a");
+        assert!(matches!(cache.lookup(id(10, 1), "a"), CachedLookup::Miss));
+        assert!(matches!(
+            cache.lookup(id(10, 1), "b"),
+            CachedLookup::Negative
+        ));
+
+        cache.invalidate_dir(id(10, 1));
+        assert!(matches!(cache.lookup(id(10, 1), "b"), CachedLookup::Miss));
+        assert_eq!(found(cache.lookup(id(10, 3), "c")), Some(id(10, 4)));
+
+        cache.invalidate_fs(10);
+        assert!(matches!(cache.lookup(id(10, 3), "c"), CachedLookup::Miss));
+        assert_eq!(found(cache.lookup(id(11, 1), "d")), Some(id(11, 2)));
+
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().negative, 0);
+
+        cache.insert(id(11, 1), "e", None, cache.generation());
+        cache.clear();
+        assert!(matches!(cache.lookup(id(11, 1), "d"), CachedLookup::Miss));
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().negative, 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = DentryCache::<MockCpuOps>::new(2);
+        let dir = id(10, 1);
+
+        cache.insert(dir, "a", inode(10, 2), 0);
+        cache.insert(dir, "b", inode(10, 3), 0);
+
+        // Touch "a" so that "b" is the oldest.
+        assert!(found(cache.lookup(dir, "a")).is_some());
+
+        cache.insert(dir, "c", None, 0);
+
+        assert!(found(cache.lookup(dir, "a")).is_some());
+        assert!(matches!(cache.lookup(dir, "b"), CachedLookup::Miss));
+        assert!(matches!(cache.lookup(dir, "c"), CachedLookup::Negative));
+        assert_eq!(cache.stats().entries, 2);
+    }
+}
@@ -0,0 +1,602 @@
+//! Hash tree ("htree") directory indexes.
+//!
+//! A large directory keeps an index in its first block: a `dx_root` naming
+//! the hash used, followed by a sorted array of `(hash, block)` pairs. Each
+//! pair points at either another array (a `dx_node`) or, at the bottom of the
+//! tree, a leaf block of ordinary directory entries whose names hash at or
+//! above it. Finding a name takes one block per level of the tree plus a leaf,
+//! instead of a scan of the whole directory.
+//!
+//! `ext4plus` walks indexes built with its own hashes, but not the legacy hash
+//! or the unsigned variants older filesystems use, so we walk them ourselves.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::num::NonZeroU32;
+
+use crate::error::KernelError;
+
+/// `dx_root_info.hash_version` of the original ext3 hash.
+const DX_HASH_LEGACY: u8 = 0;
+
+/// `dx_root_info.hash_version` of the half MD4 hash.
+const DX_HASH_HALF_MD4: u8 = 1;
+
+/// `dx_root_info.hash_version` of the TEA hash.
+const DX_HASH_TEA: u8 = 2;
+
+/// Added to the signed hash versions to give their unsigned variants.
+const DX_HASH_UNSIGNED: u8 = 3;
+
+/// The highest `indirect_levels` of any index, which `largedir` allows.
+const MAX_INDIRECT_LEVELS: u8 = 2;
+
+/// Size of `dx_root_info`.
+const DX_ROOT_INFO_LEN: u8 = 8;
+
+/// Offset of `dx_root_info` in the root block, after the "." and ".." entries.
+const DX_ROOT_INFO_OFFSET: usize = 24;
+
+/// Offset of the entries in a `dx_node`, after an empty directory entry.
+const DX_NODE_ENTRIES_OFFSET: usize = 8;
+
+/// Size of a `dx_entry`.
+const DX_ENTRY_LEN: usize = 8;
+
+/// Size of the fixed part of a directory entry.
+const DIRENT_HEADER_LEN: usize = 8;
+
+/// The seed used by filesystems without one in the superblock.
+const DEFAULT_SEED: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+/// Why an index lookup failed.
+#[derive(Debug)]
+pub enum HtreeError {
+    /// The index is damaged or uses a hash we don't implement. The directory
+    /// can still be scanned.
+    Unusable(&'static str),
+    /// Reading the directory failed.
+    Io(KernelError),
+}
+
+impl From<KernelError> for HtreeError {
+    fn from(err: KernelError) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl fmt::Display for HtreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unusable(why) => f.write_str(why),
+            Self::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+type HtreeResult<T> = core::result::Result<T, HtreeError>;
+
+/// The function an index was built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirHash {
+    version: u8,
+    seed: [u32; 4],
+}
+
+impl DirHash {
+    /// Returns the hash named by `version` in a root block. `unsigned` is
+    /// whether the superblock asks for the unsigned variants, which only
+    /// differ for names with bytes above 0x7f.
+    pub fn new(version: u8, unsigned: bool, seed: [u32; 4]) -> HtreeResult<Self> {
+        if version > DX_HASH_TEA {
+            return Err(HtreeError::Unusable("unsupported hash version"));
+        }
+
+        let seed = if seed == [0; 4] { DEFAULT_SEED } else { seed };
+        let version = if unsigned {
+            version + DX_HASH_UNSIGNED
+        } else {
+            version
+        };
+
+        Ok(Self { version, seed })
+    }
+
+    /// Hashes `name`, giving the major hash that keys the index.
+    pub fn hash(&self, name: &[u8]) -> u32 {
+        let unsigned = self.version >= DX_HASH_UNSIGNED;
+        let mut buf = self.seed;
+
+        let hash = match self.version % DX_HASH_UNSIGNED {
+            DX_HASH_LEGACY => legacy_hash(name, unsigned),
+            DX_HASH_HALF_MD4 => {
+                for i in (0..name.len()).step_by(32) {
+                    half_md4_transform(&mut buf, &str_to_hash_buf(&name[i..], unsigned));
+                }
+
+                buf[1]
+            }
+            _ => {
+                for i in (0..name.len()).step_by(16) {
+                    tea_transform(&mut buf, &str_to_hash_buf::<4>(&name[i..], unsigned));
+                }
+
+                buf[0]
+            }
+        };
+
+        // The top hash is reserved to mark the end of a directory.
+        match hash & !1 {
+            0xffff_fffe => 0xffff_fffc,
+            hash => hash,
+        }
+    }
+}
+
+fn char_value(c: u8, unsigned: bool) -> u32 {
+    if unsigned { c as u32 } else { c as i8 as u32 }
+}
+
+fn legacy_hash(name: &[u8], unsigned: bool) -> u32 {
+    let (mut hash0, mut hash1) = (0x12a3fe2du32, 0x37abe8f9u32);
+
+    for &c in name {
+        let mut hash = hash1.wrapping_add(hash0 ^ char_value(c, unsigned).wrapping_mul(7152373));
+
+        if hash & 0x8000_0000 != 0 {
+            hash = hash.wrapping_sub(0x7fff_ffff);
+        }
+
+        hash1 = hash0;
+        hash0 = hash;
+    }
+
+    hash0 << 1
+}
+
+/// Packs the start of `name` into `N` words, padding with its length, as the
+/// MD4 and TEA hashes consume it.
+fn str_to_hash_buf<const N: usize>(name: &[u8], unsigned: bool) -> [u32; N] {
+    let len = name.len() as u32;
+    let pad = (len | (len << 8)) | ((len | (len << 8)) << 16);
+    let mut buf = [pad; N];
+    let mut val = pad;
+    let mut word = 0;
+
+    for (i, &c) in name.iter().take(N * 4).enumerate() {
+        val = char_value(c, unsigned).wrapping_add(val << 8);
+
+        if i % 4 == 3 {
+            buf[word] = val;
+            val = pad;
+            word += 1;
+        }
+    }
+
+    if word < N {
+        buf[word] = val;
+    }
+
+    buf
+}
+
+fn half_md4_transform(buf: &mut [u32; 4], input: &[u32; 8]) {
+    const K2: u32 = 0x5a827999;
+    const K3: u32 = 0x6ed9eba1;
+
+    let f = |x: u32, y: u32, z: u32| z ^ (x & (y ^ z));
+    let g = |x: u32, y: u32, z: u32| (x & y).wrapping_add((x ^ y) & z);
+    let h = |x: u32, y: u32, z: u32| x ^ y ^ z;
+
+    let [mut a, mut b, mut c, mut d] = *buf;
+
+    macro_rules! round {
+        ($f:ident, $a:ident, $b:ident, $c:ident, $d:ident, $x:expr, $s:expr) => {
+            $a = $a
+                .wrapping_add($f($b, $c, $d))
+                .wrapping_add($x)
+                .rotate_left($s)
+        };
+    }
+
+    round!(f, a, b, c, d, input[0], 3);
+    round!(f, d, a, b, c, input[1], 7);
+    round!(f, c, d, a, b, input[2], 11);
+    round!(f, b, c, d, a, input[3], 19);
+    round!(f, a, b, c, d, input[4], 3);
+    round!(f, d, a, b, c, input[5], 7);
+    round!(f, c, d, a, b, input[6], 11);
+    round!(f, b, c, d, a, input[7], 19);
+
+    round!(g, a, b, c, d, input[1].wrapping_add(K2), 3);
+    round!(g, d, a, b, c, input[3].wrapping_add(K2), 5);
+    round!(g, c, d, a, b, input[5].wrapping_add(K2), 9);
+    round!(g, b, c, d, a, input[7].wrapping_add(K2), 13);
+    round!(g, a, b, c, d, input[0].wrapping_add(K2), 3);
+    round!(g, d, a, b, c, input[2].wrapping_add(K2), 5);
+    round!(g, c, d, a, b, input[4].wrapping_add(K2), 9);
+    round!(g, b, c, d, a, input[6].wrapping_add(K2), 13);
+
+    round!(h, a, b, c, d, input[3].wrapping_add(K3), 3);
+    round!(h, d, a, b, c, input[7].wrapping_add(K3), 9);
+    round!(h, c, d, a, b, input[2].wrapping_add(K3), 11);
+    round!(h, b, c, d, a, input[6].wrapping_add(K3), 15);
+    round!(h, a, b, c, d, input[1].wrapping_add(K3), 3);
+    round!(h, d, a, b, c, input[5].wrapping_add(K3), 9);
+    round!(h, c, d, a, b, input[0].wrapping_add(K3), 11);
+    round!(h, b, c, d, a, input[4].wrapping_add(K3), 15);
+
+    buf[0] = buf[0].wrapping_add(a);
+    buf[1] = buf[1].wrapping_add(b);
+    buf[2] = buf[2].wrapping_add(c);
+    buf[3] = buf[3].wrapping_add(d);
+}
+
+fn tea_transform(buf: &mut [u32; 4], input: &[u32; 4]) {
+    const DELTA: u32 = 0x9e3779b9;
+
+    let [a, b, c, d] = *input;
+    let (mut b0, mut b1) = (buf[0], buf[1]);
+    let mut sum = 0u32;
+
+    for _ in 0..16 {
+        sum = sum.wrapping_add(DELTA);
+        b0 = b0.wrapping_add(
+            ((b1 << 4).wrapping_add(a)) ^ b1.wrapping_add(sum) ^ ((b1 >> 5).wrapping_add(b)),
+        );
+        b1 = b1.wrapping_add(
+            ((b0 << 4).wrapping_add(c)) ^ b0.wrapping_add(sum) ^ ((b0 >> 5).wrapping_add(d)),
+        );
+    }
+
+    buf[0] = buf[0].wrapping_add(b0);
+    buf[1] = buf[1].wrapping_add(b1);
+}
+
+/// One `(hash, block)` pair of an index node. The first pair of each node
+/// has no hash of its own; it covers everything below the second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DxEntry {
+    pub hash: u32,
+    pub block: u32,
+}
+
+/// The root of an index, parsed from a directory's first block.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DxRoot {
+    pub hash_version: u8,
+    /// Levels of `dx_node`s between the root and the leaves.
+    pub indirect_levels: u8,
+    pub entries: Vec<DxEntry>,
+}
+
+fn read_u32(block: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u16(block: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(block[offset..offset + 2].try_into().unwrap())
+}
+
+/// Parses the root of an index from `block`.
+pub fn parse_root(block: &[u8]) -> HtreeResult<DxRoot> {
+    if block.len() < DX_ROOT_INFO_OFFSET + DX_ROOT_INFO_LEN as usize {
+        return Err(HtreeError::Unusable("truncated root"));
+    }
+
+    let info = &block[DX_ROOT_INFO_OFFSET..];
+    let (hash_version, info_len, indirect_levels, flags) = (info[4], info[5], info[6], info[7]);
+
+    if info_len != DX_ROOT_INFO_LEN {
+        return Err(HtreeError::Unusable("bad root info length"));
+    }
+
+    // Bit 0 of the flags marks features we'd have to understand.
+    if flags & 1 != 0 {
+        return Err(HtreeError::Unusable("incompatible root flags"));
+    }
+
+    if indirect_levels > MAX_INDIRECT_LEVELS {
+        return Err(HtreeError::Unusable("too many levels"));
+    }
+
+    Ok(DxRoot {
+        hash_version,
+        indirect_levels,
+        entries: parse_entries(block, DX_ROOT_INFO_OFFSET + info_len as usize)?,
+    })
+}
+
+/// Parses the entries of an interior index node from `block`.
+pub fn parse_node(block: &[u8]) -> HtreeResult<Vec<DxEntry>> {
+    if block.len() < DX_NODE_ENTRIES_OFFSET {
+        return Err(HtreeError::Unusable("truncated node"));
+    }
+
+    // A node disguises itself as a block holding one empty entry, so that
+    // code unaware of indexes skips it.
+    if read_u32(block, 0) != 0 || dirent_rec_len(block, 0) != block.len() {
+        return Err(HtreeError::Unusable("node isn't an empty block"));
+    }
+
+    parse_entries(block, DX_NODE_ENTRIES_OFFSET)
+}
+
+/// Parses the `(hash, block)` array at `offset` in `block`. Its first entry's
+/// hash field holds the array's capacity and length instead.
+fn parse_entries(block: &[u8], offset: usize) -> HtreeResult<Vec<DxEntry>> {
+    if offset + DX_ENTRY_LEN > block.len() {
+        return Err(HtreeError::Unusable("truncated entries"));
+    }
+
+    let limit = read_u16(block, offset) as usize;
+    let count = read_u16(block, offset + 2) as usize;
+
+    if count == 0 || count > limit || offset + limit * DX_ENTRY_LEN > block.len() {
+        return Err(HtreeError::Unusable("bad entry count"));
+    }
+
+    let entries: Vec<_> = (0..count)
+        .map(|i| {
+            let at = offset + i * DX_ENTRY_LEN;
+
+            DxEntry {
+                hash: if i == 0 { 0 } else { read_u32(block, at) },
+                block: read_u32(block, at + 4),
+            }
+        })
+        .collect();
+
+    if entries[1..].windows(2).any(|w| w[0].hash > w[1].hash) {
+        return Err(HtreeError::Unusable("entries out of order"));
+    }
+
+    Ok(entries)
+}
+
+/// Returns the index of the entry in `entries` whose range covers `hash`.
+pub fn find_entry(entries: &[DxEntry], hash: u32) -> usize {
+    entries[1..].partition_point(|entry| entry.hash <= hash)
+}
+
+/// Returns whether the names hashing to `hash` may continue into the leaf
+/// starting at `next_hash`. A leaf that had to be split in the middle of a run
+/// of colliding names sets the low bit of its hash.
+pub fn continues_into(hash: u32, next_hash: u32) -> bool {
+    next_hash & !1 == hash
+}
+
+/// Returns the length of the directory entry at `offset` in `block`.
+fn dirent_rec_len(block: &[u8], offset: usize) -> usize {
+    match read_u16(block, offset + 4) as usize {
+        // 64KiB blocks can't store their own size.
+        0 | 0xffff if block.len() == 0x10000 => 0x10000,
+        len => (len & 0xfffc) | ((len & 3) << 16),
+    }
+}
+
+/// Searches a leaf block for the entry named `name`, returning its inode.
+pub fn scan_leaf(block: &[u8], name: &[u8]) -> HtreeResult<Option<NonZeroU32>> {
+    let mut offset = 0;
+
+    while offset + DIRENT_HEADER_LEN <= block.len() {
+        let rec_len = dirent_rec_len(block, offset);
+        let name_len = block[offset + 6] as usize;
+
+        if rec_len < DIRENT_HEADER_LEN
+            || offset + rec_len > block.len()
+            || DIRENT_HEADER_LEN + name_len > rec_len
+        {
+            return Err(HtreeError::Unusable("corrupt leaf entry"));
+        }
+
+        let entry_name = &block[offset + DIRENT_HEADER_LEN..][..name_len];
+
+        if let Some(inode) = NonZeroU32::new(read_u32(block, offset))
+            && entry_name == name
+        {
+            return Ok(Some(inode));
+        }
+
+        offset += rec_len;
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// The hash seed `12345678-9abc-def0-1234-56789abcdef0`.
+    const SEED: [u32; 4] = [0x78563412, 0xf0debc9a, 0x78563412, 0xf0debc9a];
+
+    const LONG_NAME: &[u8] = b"a-rather-long-file-name-that-spans-several-hash-blocks.txt";
+
+    // Expected values are from `debugfs -R "dx_hash -h <version> -s <seed>"`.
+    #[test]
+    fn hashes_match_e2fsprogs() {
+        let cases: [(u8, bool, &[u8], u32); 12] = [
+            (DX_HASH_LEGACY, false, b"hello", 0x32252546),
+            (DX_HASH_HALF_MD4, false, b"hello", 0x19fa2388),
+            (DX_HASH_TEA, false, b"hello", 0x4b9ab0d8),
+            (DX_HASH_LEGACY, false, "café".as_bytes(), 0x96ca5a2c),
+            (DX_HASH_HALF_MD4, false, "café".as_bytes(), 0x6b272632),
+            (DX_HASH_TEA, false, "café".as_bytes(), 0x390e3560),
+            (DX_HASH_LEGACY, true, "café".as_bytes(), 0x6dde4230),
+            (DX_HASH_HALF_MD4, true, "café".as_bytes(), 0xed36f0b4),
+            (DX_HASH_TEA, true, "café".as_bytes(), 0xa89c7908),
+            (DX_HASH_LEGACY, false, LONG_NAME, 0xa1cc5284),
+            (DX_HASH_HALF_MD4, false, LONG_NAME, 0x7994d168),
+            (DX_HASH_TEA, false, LONG_NAME, 0x7f67e806),
+        ];
+
+        for (version, unsigned, name, expected) in cases {
+            let hash = DirHash::new(version, unsigned, SEED).unwrap();
+
+            assert_eq!(hash.hash(name), expected, "version {version}");
+        }
+    }
+
+    #[test]
+    fn zero_seed_uses_default() {
+        let hash = DirHash::new(DX_HASH_HALF_MD4, false, [0; 4]).unwrap();
+
+        assert_eq!(hash.hash(b"hello"), 0x1746da32);
+    }
+
+    #[test]
+    fn rejects_unknown_hash() {
+        assert!(DirHash::new(6, false, SEED).is_err());
+    }
+
+    fn put_dirent(block: &mut [u8], offset: usize, inode: u32, rec_len: u16, name: &[u8]) {
+        block[offset..offset + 4].copy_from_slice(&inode.to_le_bytes());
+        block[offset + 4..offset + 6].copy_from_slice(&rec_len.to_le_bytes());
+        block[offset + 6] = name.len() as u8;
+        block[offset + 8..offset + 8 + name.len()].copy_from_slice(name);
+    }
+
+    fn put_entries(block: &mut [u8], offset: usize, limit: u16, entries: &[(u32, u32)]) {
+        block[offset..offset + 2].copy_from_slice(&limit.to_le_bytes());
+        block[offset + 2..offset + 4].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+
+        for (i, &(hash, blk)) in entries.iter().enumerate() {
+            let at = offset + i * DX_ENTRY_LEN;
+
+            if i != 0 {
+                block[at..at + 4].copy_from_slice(&hash.to_le_bytes());
+            }
+
+            block[at + 4..at + 8].copy_from_slice(&blk.to_le_bytes());
+        }
+    }
+
+    fn root_block(levels: u8, entries: &[(u32, u32)]) -> Vec<u8> {
+        let mut block = vec![0u8; 1024];
+
+        put_dirent(&mut block, 0, 2, 12, b".");
+        put_dirent(&mut block, 12, 2, 1012, b"..");
+        block[DX_ROOT_INFO_OFFSET + 4] = DX_HASH_HALF_MD4;
+        block[DX_ROOT_INFO_OFFSET + 5] = DX_ROOT_INFO_LEN;
+        block[DX_ROOT_INFO_OFFSET + 6] = levels;
+        put_entries(&mut block, 32, 124, entries);
+
+        block
+    }
+
+    #[test]
+    fn parses_root() {
+        let block = root_block(1, &[(0, 1), (0x100, 2), (0x200, 3)]);
+
+        assert_eq!(
+            parse_root(&block).unwrap(),
+            DxRoot {
+                hash_version: DX_HASH_HALF_MD4,
+                indirect_levels: 1,
+                entries: vec![
+                    DxEntry { hash: 0, block: 1 },
+                    DxEntry {
+                        hash: 0x100,
+                        block: 2
+                    },
+                    DxEntry {
+                        hash: 0x200,
+                        block: 3
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_corrupt_roots() {
+        let mut block = root_block(3, &[(0, 1)]);
+        assert!(parse_root(&block).is_err());
+
+        block[DX_ROOT_INFO_OFFSET + 6] = 0;
+        block[DX_ROOT_INFO_OFFSET + 7] = 1;
+        assert!(parse_root(&block).is_err());
+
+        // Out of order.
+        let block = root_block(0, &[(0, 1), (0x200, 2), (0x100, 3)]);
+        assert!(parse_root(&block).is_err());
+
+        // More entries than fit.
+        let mut block = root_block(0, &[(0, 1)]);
+        block[32..34].copy_from_slice(&200u16.to_le_bytes());
+        assert!(parse_root(&block).is_err());
+    }
+
+    #[test]
+    fn parses_node() {
+        let mut block = vec![0u8; 1024];
+        put_dirent(&mut block, 0, 0, 1024, b"");
+        put_entries(&mut block, 8, 127, &[(0, 5), (0x80, 6)]);
+
+        assert_eq!(
+            parse_node(&block).unwrap(),
+            [
+                DxEntry { hash: 0, block: 5 },
+                DxEntry {
+                    hash: 0x80,
+                    block: 6
+                }
+            ]
+        );
+
+        // A block of real entries isn't a node.
+        put_dirent(&mut block, 0, 12, 1024, b"");
+        assert!(parse_node(&block).is_err());
+    }
+
+    #[test]
+    fn finds_covering_entry() {
+        let entries = [
+            DxEntry { hash: 0, block: 1 },
+            DxEntry {
+                hash: 0x100,
+                block: 2,
+            },
+            DxEntry {
+                hash: 0x100,
+                block: 3,
+            },
+            DxEntry {
+                hash: 0x300,
+                block: 4,
+            },
+        ];
+
+        assert_eq!(find_entry(&entries, 0), 0);
+        assert_eq!(find_entry(&entries, 0xfe), 0);
+        assert_eq!(find_entry(&entries, 0x100), 2);
+        assert_eq!(find_entry(&entries, 0x2fe), 2);
+        assert_eq!(find_entry(&entries, 0xffff_fffc), 3);
+    }
+
+    #[test]
+    fn collisions_continue_into_next_leaf() {
+        assert!(continues_into(0x100, 0x101));
+        assert!(continues_into(0x100, 0x100));
+        assert!(!continues_into(0x100, 0x102));
+    }
+
+    #[test]
+    fn scans_leaf() {
+        let mut block = vec![0u8; 1024];
+        put_dirent(&mut block, 0, 12, 16, b"foo");
+        put_dirent(&mut block, 16, 0, 16, b"gone");
+        put_dirent(&mut block, 32, 13, 992, b"bar");
+
+        assert_eq!(scan_leaf(&block, b"foo").unwrap(), NonZeroU32::new(12));
+        assert_eq!(scan_leaf(&block, b"bar").unwrap(), NonZeroU32::new(13));
+        assert_eq!(scan_leaf(&block, b"gone").unwrap(), None);
+        assert_eq!(scan_leaf(&block, b"baz").unwrap(), None);
+
+        // An entry running off the end of the block.
+        put_dirent(&mut block, 32, 13, 1000, b"bar");
+        assert!(scan_leaf(&block, b"baz").is_err());
+    }
+}
@@ -0,0 +1,1379 @@
+//! The JBD2 journal: recovery of volumes that weren't cleanly unmounted, and
+//! writing through it on volumes that were.
+//!
+//! ext4 logs metadata updates to a JBD2 journal before writing them to their
+//! home location. After a crash the journal may hold committed transactions
+//! that never made it out, and the superblock carries `INCOMPAT_RECOVER`.
+//! Reading such a volume as-is yields stale or half-updated metadata.
+//!
+//! We don't write during recovery. Committed transactions are replayed into
+//! an in-memory overlay that [`JournaledDev`] lays over the device, and the
+//! volume is then mounted read-only so the overlay can never go stale.
+//!
+//! The orphan list, which links inodes that were unlinked or truncated while
+//! still open, is walked and then dropped from the recovered superblock.
+//! Without write access there is nothing to free: unlinked orphans are already
+//! unreachable, and truncated ones have their final size recorded. The list is
+//! left intact on disk for the next read-write mount to clean up.
+//!
+//! A clean volume is written through a [`JournalWriter`]. `ext4plus` doesn't
+//! say which of its writes are metadata, so every block it writes is logged,
+//! as in `data=journal` mode, which orders data before the metadata that
+//! refers to it. Each transaction is checkpointed as soon as it has
+//! committed, so the journal is empty again between them and never wraps.
+
+use super::raw::{Extent, InodeLayout, from_bytes};
+use crate::{CpuOps, sync::mutex::Mutex};
+use crate::{
+    error::{FsError, KernelError, Result},
+    fs::blk::buffer::BlockBuffer,
+    pod::Pod,
+};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, btree_map::Entry},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::{error::Error, num::NonZeroU32};
+use ext4plus::prelude::{Ext4Read, Ext4Write};
+use log::{info, warn};
+
+/// Byte offset of the primary superblock.
+const SUPERBLOCK_OFFSET: u64 = 1024;
+
+/// Size of the superblock in bytes.
+const SUPERBLOCK_SIZE: usize = 1024;
+
+/// Superblock field offsets.
+const S_INODES_COUNT: usize = 0x00;
+const S_FEATURE_COMPAT: usize = 0x5c;
+const S_FEATURE_INCOMPAT: usize = 0x60;
+const S_FEATURE_RO_COMPAT: usize = 0x64;
+const S_JOURNAL_INUM: usize = 0xe0;
+const S_JOURNAL_DEV: usize = 0xe4;
+const S_LAST_ORPHAN: usize = 0xe8;
+const S_CHECKSUM: usize = 0x3fc;
+
+const EXT4_FEATURE_COMPAT_HAS_JOURNAL: u32 = 0x4;
+const EXT4_FEATURE_INCOMPAT_RECOVER: u32 = 0x4;
+const EXT4_FEATURE_RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+
+/// `h_magic` of every JBD2 metadata block.
+const JBD2_MAGIC_NUMBER: u32 = 0xc03b_3998;
+
+/// JBD2 block types.
+const JBD2_DESCRIPTOR_BLOCK: u32 = 1;
+const JBD2_COMMIT_BLOCK: u32 = 2;
+const JBD2_SUPERBLOCK_V1: u32 = 3;
+const JBD2_SUPERBLOCK_V2: u32 = 4;
+const JBD2_REVOKE_BLOCK: u32 = 5;
+
+/// Journal superblock field offsets, all big-endian.
+const JS_BLOCKSIZE: usize = 0x0c;
+const JS_MAXLEN: usize = 0x10;
+const JS_FIRST: usize = 0x14;
+const JS_SEQUENCE: usize = 0x18;
+const JS_START: usize = 0x1c;
+const JS_FEATURE_COMPAT: usize = 0x24;
+const JS_FEATURE_INCOMPAT: usize = 0x28;
+const JS_UUID: usize = 0x30;
+const JS_CHECKSUM_TYPE: usize = 0x50;
+const JS_NUM_FC_BLKS: usize = 0x54;
+const JS_CHECKSUM: usize = 0xfc;
+
+/// Size of the journal superblock proper, which its checksum covers.
+const JOURNAL_SUPERBLOCK_SIZE: usize = 1024;
+
+/// `s_checksum_type` of a journal checksummed with CRC32C.
+const JBD2_CRC32C_CHKSUM: u8 = 4;
+
+const JBD2_FEATURE_INCOMPAT_REVOKE: u32 = 0x1;
+const JBD2_FEATURE_INCOMPAT_64BIT: u32 = 0x2;
+const JBD2_FEATURE_INCOMPAT_CSUM_V2: u32 = 0x8;
+const JBD2_FEATURE_INCOMPAT_CSUM_V3: u32 = 0x10;
+const JBD2_FEATURE_INCOMPAT_FAST_COMMIT: u32 = 0x20;
+
+/// Fast-commit area size when `s_num_fc_blks` is zero.
+const JBD2_DEFAULT_FAST_COMMIT_BLOCKS: u32 = 256;
+
+/// Size of the common header of journal metadata blocks.
+const JOURNAL_HEADER_SIZE: usize = 12;
+
+/// Size of `jbd2_journal_block_tail`, present when checksums are enabled.
+const JOURNAL_BLOCK_TAIL_SIZE: usize = 4;
+
+/// Descriptor tag flags.
+const JBD2_FLAG_ESCAPE: u32 = 0x1;
+const JBD2_FLAG_SAME_UUID: u32 = 0x2;
+const JBD2_FLAG_LAST_TAG: u32 = 0x8;
+
+/// Size of the UUID that follows a tag without `JBD2_FLAG_SAME_UUID`.
+const JBD2_UUID_SIZE: usize = 16;
+
+/// Offset of the checksum in a commit block.
+const COMMIT_CHECKSUM: usize = 0x10;
+
+/// The journal features [`JournalWriter`] can write in the format of. Fast
+/// commits are never written, only left room for.
+const JBD2_WRITABLE_INCOMPAT: u32 = JBD2_FEATURE_INCOMPAT_REVOKE
+    | JBD2_FEATURE_INCOMPAT_64BIT
+    | JBD2_FEATURE_INCOMPAT_CSUM_V2
+    | JBD2_FEATURE_INCOMPAT_CSUM_V3
+    | JBD2_FEATURE_INCOMPAT_FAST_COMMIT;
+
+/// The most blocks a transaction gathers before it's committed, whether or
+/// not the operation that filled it has finished.
+const MAX_TRANSACTION_BLOCKS: usize = 1024;
+
+fn le32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+fn be32(buf: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+fn be16(buf: &[u8], off: usize) -> u16 {
+    u16::from_be_bytes(buf[off..off + 2].try_into().unwrap())
+}
+
+fn put_be32(buf: &mut [u8], off: usize, val: u32) {
+    buf[off..off + 4].copy_from_slice(&val.to_be_bytes());
+}
+
+/// CRC32C of `data`, continuing from `crc`, without the inversions of the
+/// standard algorithm. This is the form JBD2 and ext4 chain their checksums
+/// in.
+fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0x82f6_3b78 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    crc
+}
+
+/// Sets or clears `INCOMPAT_RECOVER` in the superblock `sb`, keeping its
+/// checksum up to date.
+fn set_needs_recovery(sb: &mut [u8], needed: bool) {
+    let mut incompat = le32(sb, S_FEATURE_INCOMPAT) & !EXT4_FEATURE_INCOMPAT_RECOVER;
+
+    if needed {
+        incompat |= EXT4_FEATURE_INCOMPAT_RECOVER;
+    }
+
+    sb[S_FEATURE_INCOMPAT..S_FEATURE_INCOMPAT + 4].copy_from_slice(&incompat.to_le_bytes());
+
+    if le32(sb, S_FEATURE_RO_COMPAT) & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM != 0 {
+        let csum = crc32c(!0, &sb[..S_CHECKSUM]);
+        sb[S_CHECKSUM..S_CHECKSUM + 4].copy_from_slice(&csum.to_le_bytes());
+    }
+}
+
+/// Copies whatever `blocks`, of `bs` bytes each, hold of the bytes from
+/// `offset` over `buf`.
+fn overlay_blocks(blocks: &BTreeMap<u64, Box<[u8]>>, bs: u64, offset: u64, buf: &mut [u8]) {
+    if blocks.is_empty() || buf.is_empty() {
+        return;
+    }
+
+    let end = offset + buf.len() as u64;
+
+    for (&block, data) in blocks.range(offset / bs..=(end - 1) / bs) {
+        let block_start = block * bs;
+        let lo = offset.max(block_start);
+        let hi = end.min(block_start + bs);
+
+        buf[(lo - offset) as usize..(hi - offset) as usize]
+            .copy_from_slice(&data[(lo - block_start) as usize..(hi - block_start) as usize]);
+    }
+}
+
+/// Writes that have been made to the volume but haven't reached their home
+/// location yet, which reads have to see.
+#[async_trait]
+pub trait PendingWrites: Send + Sync {
+    /// Copies the pending contents of any bytes from `offset` over `buf`.
+    async fn overlay(&self, offset: u64, buf: &mut [u8]);
+}
+
+/// The filesystem's block device, with any blocks replayed from the journal
+/// laid over it.
+pub struct JournaledDev {
+    dev: Arc<BlockBuffer>,
+    block_size: u64,
+    overlay: BTreeMap<u64, Box<[u8]>>,
+    pending: Option<Arc<dyn PendingWrites>>,
+}
+
+impl JournaledDev {
+    /// Wraps `dev` with an empty overlay.
+    pub fn new(dev: Arc<BlockBuffer>) -> Self {
+        Self {
+            dev,
+            block_size: 0,
+            overlay: BTreeMap::new(),
+            pending: None,
+        }
+    }
+
+    /// Lays the writes `pending` has yet to make over everything read.
+    pub fn set_pending(&mut self, pending: Arc<dyn PendingWrites>) {
+        self.pending = Some(pending);
+    }
+
+    /// Reads a sequence of bytes starting at `offset`.
+    pub async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.dev.read_at(offset, buf).await?;
+        overlay_blocks(&self.overlay, self.block_size, offset, buf);
+
+        if let Some(pending) = &self.pending {
+            pending.overlay(offset, buf).await;
+        }
+
+        Ok(())
+    }
+
+    /// Hints that `len` bytes starting at `offset` will be read soon.
+    pub async fn readahead(&self, offset: u64, len: u64) -> Result<()> {
+        self.dev.readahead(offset, len).await
+    }
+
+    /// Reads a `Pod` struct at `offset`.
+    pub async fn read_obj<T: Pod>(&self, offset: u64) -> Result<T> {
+        let mut buf = vec![0; size_of::<T>()];
+
+        self.read_at(offset, &mut buf).await?;
+
+        Ok(from_bytes(&buf))
+    }
+
+    /// Flushes the underlying device.
+    pub async fn sync(&self) -> Result<()> {
+        self.dev.sync().await
+    }
+
+    /// Brings the volume to a consistent state if it wasn't cleanly unmounted.
+    ///
+    /// Returns `true` if recovery was needed, in which case the volume must
+    /// only be accessed through this device and must not be written to.
+    pub async fn recover(&mut self) -> Result<bool> {
+        let mut sb = [0; SUPERBLOCK_SIZE];
+        self.read_at(SUPERBLOCK_OFFSET, &mut sb).await?;
+
+        if le32(&sb, S_FEATURE_COMPAT) & EXT4_FEATURE_COMPAT_HAS_JOURNAL == 0
+            || le32(&sb, S_FEATURE_INCOMPAT) & EXT4_FEATURE_INCOMPAT_RECOVER == 0
+        {
+            return Ok(false);
+        }
+
+        let layout = InodeLayout::read(self).await?;
+
+        if le32(&sb, S_JOURNAL_DEV) != 0 {
+            warn!("ext4: can't recover a volume with an external journal");
+            return Err(KernelError::NotSupported);
+        }
+
+        let journal_ino = NonZeroU32::new(le32(&sb, S_JOURNAL_INUM)).ok_or(FsError::InvalidFs)?;
+
+        let replayed = Journal::open(self, &layout, journal_ino)
+            .await?
+            .replay()
+            .await?;
+
+        info!(
+            "ext4: replayed {} block(s) from the journal",
+            replayed.len()
+        );
+
+        self.block_size = layout.block_size;
+        self.overlay = replayed;
+
+        // The journal may well have carried the superblock itself.
+        self.read_at(SUPERBLOCK_OFFSET, &mut sb).await?;
+
+        self.scan_orphans(&layout, &sb).await?;
+
+        sb[S_LAST_ORPHAN..S_LAST_ORPHAN + 4].fill(0);
+        set_needs_recovery(&mut sb, false);
+
+        self.overlay_bytes(SUPERBLOCK_OFFSET, &sb).await?;
+
+        Ok(true)
+    }
+
+    /// Walks the orphan list rooted at `s_last_orphan`.
+    async fn scan_orphans(&self, layout: &InodeLayout, sb: &[u8]) -> Result<()> {
+        let limit = le32(sb, S_INODES_COUNT);
+        let mut next = le32(sb, S_LAST_ORPHAN);
+        let (mut unlinked, mut truncated) = (0u32, 0u32);
+
+        while let Some(ino) = NonZeroU32::new(next) {
+            if ino.get() > limit || unlinked + truncated >= limit {
+                warn!("ext4: orphan list is corrupt, ignoring the rest of it");
+                break;
+            }
+
+            let inode = layout.read_inode(self, ino).await?;
+
+            if inode.links_count() == 0 {
+                unlinked += 1;
+            } else {
+                truncated += 1;
+            }
+
+            next = inode.dtime();
+        }
+
+        if unlinked + truncated != 0 {
+            info!(
+                "ext4: skipping cleanup of {unlinked} unlinked and {truncated} truncated \
+                 orphan inode(s) on read-only recovery"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Overlays `data` at byte `offset`, which must lie within the device.
+    async fn overlay_bytes(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let bs = self.block_size;
+        let first = offset / bs;
+        let last = (offset + data.len() as u64 - 1) / bs;
+
+        for block in first..=last {
+            let mut buf = vec![0; bs as usize];
+            self.read_at(block * bs, &mut buf).await?;
+
+            let block_start = block * bs;
+            let lo = offset.max(block_start);
+            let hi = (offset + data.len() as u64).min(block_start + bs);
+
+            buf[(lo - block_start) as usize..(hi - block_start) as usize]
+                .copy_from_slice(&data[(lo - offset) as usize..(hi - offset) as usize]);
+
+            self.overlay.insert(block, buf.into_boxed_slice());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Ext4Read for JournaledDev {
+    async fn read(
+        &self,
+        start_byte: u64,
+        dst: &mut [u8],
+    ) -> core::result::Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(self.read_at(start_byte, dst).await?)
+    }
+}
+
+/// Writes a single transaction would make once committed.
+#[derive(Default)]
+struct Transaction {
+    writes: Vec<(u64, Box<[u8]>)>,
+    revoked: Vec<u64>,
+}
+
+/// A JBD2 journal stored in an inode of the volume.
+struct Journal<'a> {
+    dev: &'a JournaledDev,
+    extents: Vec<Extent>,
+    block_size: usize,
+    /// First block of the log area.
+    first: u32,
+    /// One past the last block of the log area.
+    last: u32,
+    /// Block holding the oldest transaction still to be checkpointed.
+    start: u32,
+    /// Sequence number of that transaction.
+    sequence: u32,
+    incompat: u32,
+}
+
+impl<'a> Journal<'a> {
+    async fn open(dev: &'a JournaledDev, layout: &InodeLayout, ino: NonZeroU32) -> Result<Self> {
+        let inode = layout.read_inode(dev, ino).await?;
+
+        if inode.has_inline_data() {
+            warn!("ext4: journal has inline data, can't use it");
+            return Err(KernelError::NotSupported);
+        }
+
+        let mut journal = Self {
+            dev,
+            extents: layout.read_mapping(dev, &inode).await?,
+            block_size: layout.block_size as usize,
+            first: 0,
+            last: 0,
+            start: 0,
+            sequence: 0,
+            incompat: 0,
+        };
+
+        let mut jsb = vec![0; journal.block_size];
+        journal.read_block(0, &mut jsb).await?;
+
+        let blocktype = be32(&jsb, 4);
+
+        if be32(&jsb, 0) != JBD2_MAGIC_NUMBER
+            || !(blocktype == JBD2_SUPERBLOCK_V1 || blocktype == JBD2_SUPERBLOCK_V2)
+        {
+            warn!("ext4: bad journal superblock");
+            return Err(FsError::InvalidFs.into());
+        }
+
+        if be32(&jsb, JS_BLOCKSIZE) as usize != journal.block_size {
+            warn!("ext4: journal block size doesn't match the filesystem's");
+            return Err(FsError::InvalidFs.into());
+        }
+
+        journal.first = be32(&jsb, JS_FIRST);
+        journal.last = be32(&jsb, JS_MAXLEN);
+        journal.start = be32(&jsb, JS_START);
+        journal.sequence = be32(&jsb, JS_SEQUENCE);
+
+        if blocktype == JBD2_SUPERBLOCK_V2 {
+            journal.incompat = be32(&jsb, JS_FEATURE_INCOMPAT);
+        }
+
+        if journal.incompat & JBD2_FEATURE_INCOMPAT_FAST_COMMIT != 0 {
+            // Fast commits live in a separate area at the end of the journal
+            // and only describe changes relative to the last full commit.
+            warn!("ext4: leaving the journal's fast commit area alone");
+
+            let fc_blocks = match be32(&jsb, JS_NUM_FC_BLKS) {
+                0 => JBD2_DEFAULT_FAST_COMMIT_BLOCKS,
+                n => n,
+            };
+
+            journal.last = journal.last.saturating_sub(fc_blocks);
+        }
+
+        if journal.first == 0 || journal.first >= journal.last {
+            warn!("ext4: journal log area is empty");
+            return Err(FsError::InvalidFs.into());
+        }
+
+        Ok(journal)
+    }
+
+    /// Reads journal block `block` into `buf`.
+    async fn read_block(&self, block: u32, buf: &mut [u8]) -> Result<()> {
+        let phys = self
+            .extents
+            .iter()
+            .find_map(|ext| ext.map(block))
+            .ok_or(FsError::InvalidFs)?;
+
+        self.dev.read_at(phys * self.block_size as u64, buf).await
+    }
+
+    /// Returns the block following `block` in the circular log.
+    fn next(&self, block: u32) -> u32 {
+        if block + 1 >= self.last {
+            self.first
+        } else {
+            block + 1
+        }
+    }
+
+    /// Size of a descriptor block tag, excluding any trailing UUID.
+    fn tag_size(&self) -> usize {
+        if self.incompat & JBD2_FEATURE_INCOMPAT_CSUM_V3 != 0 {
+            return 16;
+        }
+
+        let mut size = 12;
+
+        if self.incompat & JBD2_FEATURE_INCOMPAT_CSUM_V2 != 0 {
+            size += 2;
+        }
+
+        if self.incompat & JBD2_FEATURE_INCOMPAT_64BIT == 0 {
+            size -= 4;
+        }
+
+        size
+    }
+
+    fn has_block_tail(&self) -> bool {
+        self.incompat & (JBD2_FEATURE_INCOMPAT_CSUM_V2 | JBD2_FEATURE_INCOMPAT_CSUM_V3) != 0
+    }
+
+    /// Decodes the target block and flags of the tag at the start of `tag`.
+    fn decode_tag(&self, tag: &[u8]) -> (u64, u32) {
+        let mut target = be32(tag, 0) as u64;
+
+        let flags = if self.incompat & JBD2_FEATURE_INCOMPAT_CSUM_V3 != 0 {
+            be32(tag, 4)
+        } else {
+            be16(tag, 6) as u32
+        };
+
+        if self.incompat & JBD2_FEATURE_INCOMPAT_64BIT != 0 {
+            target |= (be32(tag, 8) as u64) << 32;
+        }
+
+        (target, flags)
+    }
+
+    /// Collects the contents of every committed transaction, keyed by
+    /// filesystem block.
+    async fn replay(&self) -> Result<BTreeMap<u64, Box<[u8]>>> {
+        let mut blocks = BTreeMap::new();
+
+        if self.start == 0 {
+            return Ok(blocks);
+        }
+
+        let mut buf = vec![0; self.block_size];
+        let mut pos = self.start;
+        let mut sequence = self.sequence;
+        let mut txn = Transaction::default();
+
+        // The log can't hold more blocks than its length, so this also bounds
+        // a scan through a journal whose sequence numbers happen to line up
+        // all the way round.
+        let mut budget = self.last - self.first;
+
+        while budget > 0 {
+            self.read_block(pos, &mut buf).await?;
+            pos = self.next(pos);
+            budget -= 1;
+
+            if be32(&buf, 0) != JBD2_MAGIC_NUMBER || be32(&buf, 8) != sequence {
+                break;
+            }
+
+            match be32(&buf, 4) {
+                JBD2_DESCRIPTOR_BLOCK => {
+                    let end = self.block_size
+                        - if self.has_block_tail() {
+                            JOURNAL_BLOCK_TAIL_SIZE
+                        } else {
+                            0
+                        };
+                    let mut off = JOURNAL_HEADER_SIZE;
+
+                    while off + self.tag_size() <= end && budget > 0 {
+                        let (target, flags) = self.decode_tag(&buf[off..]);
+
+                        off += self.tag_size();
+
+                        if flags & JBD2_FLAG_SAME_UUID == 0 {
+                            off += JBD2_UUID_SIZE;
+                        }
+
+                        let mut data = vec![0; self.block_size].into_boxed_slice();
+                        self.read_block(pos, &mut data).await?;
+                        pos = self.next(pos);
+                        budget -= 1;
+
+                        if flags & JBD2_FLAG_ESCAPE != 0 {
+                            data[..4].copy_from_slice(&JBD2_MAGIC_NUMBER.to_be_bytes());
+                        }
+
+                        txn.writes.push((target, data));
+
+                        if flags & JBD2_FLAG_LAST_TAG != 0 {
+                            break;
+                        }
+                    }
+                }
+                JBD2_REVOKE_BLOCK => {
+                    let record_size = if self.incompat & JBD2_FEATURE_INCOMPAT_64BIT != 0 {
+                        8
+                    } else {
+                        4
+                    };
+                    let count = (be32(&buf, JOURNAL_HEADER_SIZE) as usize).min(self.block_size);
+                    let mut off = JOURNAL_HEADER_SIZE + 4;
+
+                    while off + record_size <= count {
+                        txn.revoked.push(if record_size == 8 {
+                            ((be32(&buf, off) as u64) << 32) | be32(&buf, off + 4) as u64
+                        } else {
+                            be32(&buf, off) as u64
+                        });
+
+                        off += record_size;
+                    }
+                }
+                JBD2_COMMIT_BLOCK => {
+                    let txn = core::mem::take(&mut txn);
+
+                    blocks.extend(txn.writes);
+
+                    // A revoke cancels every logged copy of the block up to
+                    // and including this transaction.
+                    for block in txn.revoked {
+                        blocks.remove(&block);
+                    }
+
+                    sequence = sequence.wrapping_add(1);
+                }
+                _ => break,
+            }
+        }
+
+        if !txn.writes.is_empty() {
+            info!(
+                "ext4: discarding uncommitted transaction {sequence} ({} block(s))",
+                txn.writes.len()
+            );
+        }
+
+        Ok(blocks)
+    }
+}
+
+/// The state of a [`JournalWriter`] between commits.
+struct WriterState {
+    /// Blocks written since the last commit, by filesystem block.
+    running: BTreeMap<u64, Box<[u8]>>,
+    /// Sequence number of the next transaction to commit.
+    sequence: u32,
+    /// The journal superblock, as last written.
+    jsb: Vec<u8>,
+}
+
+/// Writes to a clean volume through its journal.
+///
+/// Writes gather in a running transaction, which [`JournalWriter::commit`]
+/// logs and then writes out to the blocks' home locations. The volume is
+/// marked as needing recovery for as long as the journal holds a committed
+/// transaction, so that a crash part way through a checkpoint is replayed.
+pub struct JournalWriter<CPU: CpuOps> {
+    dev: Arc<BlockBuffer>,
+    extents: Vec<Extent>,
+    block_size: usize,
+    /// First block of the log area.
+    first: u32,
+    incompat: u32,
+    tag_size: usize,
+    /// Tags that fit in a descriptor block.
+    tags_per_block: usize,
+    /// The most blocks a transaction can hold and still fit in the log.
+    max_blocks: usize,
+    /// The seed checksums are chained from, if the journal has them.
+    csum_seed: Option<u32>,
+    state: Mutex<WriterState, CPU>,
+}
+
+impl<CPU: CpuOps> JournalWriter<CPU> {
+    /// Opens the journal of the clean volume on `dev` for writing. Returns
+    /// `None` if there is no journal that can be written in its format, in
+    /// which case writes have to go straight to the device.
+    pub async fn open(dev: &JournaledDev) -> Result<Option<Arc<Self>>> {
+        let mut sb = [0; SUPERBLOCK_SIZE];
+        dev.read_at(SUPERBLOCK_OFFSET, &mut sb).await?;
+
+        if le32(&sb, S_FEATURE_COMPAT) & EXT4_FEATURE_COMPAT_HAS_JOURNAL == 0 {
+            return Ok(None);
+        }
+
+        if le32(&sb, S_JOURNAL_DEV) != 0 {
+            warn!("ext4: can't write through an external journal, writing without one");
+            return Ok(None);
+        }
+
+        let layout = InodeLayout::read(dev).await?;
+        let journal_ino = NonZeroU32::new(le32(&sb, S_JOURNAL_INUM)).ok_or(FsError::InvalidFs)?;
+
+        let journal = match Journal::open(dev, &layout, journal_ino).await {
+            Ok(journal) => journal,
+            Err(KernelError::NotSupported) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut jsb = vec![0; journal.block_size];
+        journal.read_block(0, &mut jsb).await?;
+
+        let compat = if be32(&jsb, 4) == JBD2_SUPERBLOCK_V2 {
+            be32(&jsb, JS_FEATURE_COMPAT)
+        } else {
+            0
+        };
+
+        if compat != 0 || journal.incompat & !JBD2_WRITABLE_INCOMPAT != 0 {
+            warn!("ext4: journal has features we can't write, writing without it");
+            return Ok(None);
+        }
+
+        let tail = if journal.has_block_tail() {
+            JOURNAL_BLOCK_TAIL_SIZE
+        } else {
+            0
+        };
+        let tags_per_block = (journal.block_size - JOURNAL_HEADER_SIZE - tail) / journal.tag_size();
+
+        // Every block logged takes a tag in a descriptor, and the commit
+        // block takes one more.
+        let room = (journal.last - journal.first) as usize - 1;
+        let max_blocks = (room - room.div_ceil(tags_per_block + 1)).min(MAX_TRANSACTION_BLOCKS);
+
+        if max_blocks == 0 {
+            warn!("ext4: journal is too small to write through, writing without it");
+            return Ok(None);
+        }
+
+        let csum_seed = journal
+            .has_block_tail()
+            .then(|| crc32c(!0, &jsb[JS_UUID..JS_UUID + JBD2_UUID_SIZE]));
+
+        // A clean journal's sequence is that of the next transaction.
+        let sequence = journal.sequence;
+        let tag_size = journal.tag_size();
+
+        Ok(Some(Arc::new(Self {
+            dev: dev.dev.clone(),
+            extents: journal.extents,
+            block_size: journal.block_size,
+            first: journal.first,
+            incompat: journal.incompat,
+            tag_size,
+            tags_per_block,
+            max_blocks,
+            csum_seed,
+            state: Mutex::new(WriterState {
+                running: BTreeMap::new(),
+                sequence,
+                jsb,
+            }),
+        })))
+    }
+
+    /// Adds a write of `src` at byte `offset` to the running transaction. A
+    /// transaction that fills up is committed there and then.
+    pub async fn write_at(&self, offset: u64, src: &[u8]) -> Result<()> {
+        let bs = self.block_size as u64;
+        let end = offset + src.len() as u64;
+        let mut state = self.state.lock().await;
+        let mut block = offset / bs;
+
+        while block * bs < end {
+            if state.running.len() >= self.max_blocks && !state.running.contains_key(&block) {
+                self.commit_locked(&mut state).await?;
+            }
+
+            let block_start = block * bs;
+            let lo = offset.max(block_start);
+            let hi = end.min(block_start + bs);
+
+            let data = match state.running.entry(block) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let mut data = vec![0; self.block_size].into_boxed_slice();
+
+                    if hi - lo < bs {
+                        self.dev.read_at(block_start, &mut data).await?;
+                    }
+
+                    e.insert(data)
+                }
+            };
+
+            data[(lo - block_start) as usize..(hi - block_start) as usize]
+                .copy_from_slice(&src[(lo - offset) as usize..(hi - offset) as usize]);
+            block += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Commits the running transaction, if anything has been written, and
+    /// writes it out to where it belongs.
+    pub async fn commit(&self) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        self.commit_locked(&mut state).await
+    }
+
+    async fn commit_locked(&self, state: &mut WriterState) -> Result<()> {
+        if state.running.is_empty() {
+            return Ok(());
+        }
+
+        self.write_log(state).await?;
+        self.checkpoint(state).await
+    }
+
+    /// Logs the running transaction and commits it. Once this has returned,
+    /// a crash leaves the transaction to be replayed.
+    async fn write_log(&self, state: &mut WriterState) -> Result<()> {
+        self.mark_needs_recovery(true).await?;
+
+        let sequence = state.sequence;
+        let mut pos = self.first;
+
+        self.write_jsb(state, self.first, sequence).await?;
+
+        let blocks: Vec<_> = state.running.iter().collect();
+
+        for chunk in blocks.chunks(self.tags_per_block) {
+            let mut desc = self.header(JBD2_DESCRIPTOR_BLOCK, sequence);
+            let desc_pos = pos;
+            let mut off = JOURNAL_HEADER_SIZE;
+
+            pos += 1;
+
+            for (i, &(&target, data)) in chunk.iter().enumerate() {
+                let mut data = data.clone();
+                let mut flags = JBD2_FLAG_SAME_UUID;
+
+                if be32(&data, 0) == JBD2_MAGIC_NUMBER {
+                    data[..4].fill(0);
+                    flags |= JBD2_FLAG_ESCAPE;
+                }
+
+                if i + 1 == chunk.len() {
+                    flags |= JBD2_FLAG_LAST_TAG;
+                }
+
+                self.encode_tag(&mut desc[off..], target, flags, sequence, &data);
+                off += self.tag_size;
+
+                self.write_block(pos, &data).await?;
+                pos += 1;
+            }
+
+            if let Some(seed) = self.csum_seed {
+                let tail = self.block_size - JOURNAL_BLOCK_TAIL_SIZE;
+                let csum = crc32c(seed, &desc);
+                put_be32(&mut desc, tail, csum);
+            }
+
+            self.write_block(desc_pos, &desc).await?;
+        }
+
+        // Everything the commit block vouches for has to be on disk first.
+        self.dev.sync().await?;
+
+        let mut commit = self.header(JBD2_COMMIT_BLOCK, sequence);
+
+        if let Some(seed) = self.csum_seed {
+            let csum = crc32c(seed, &commit);
+            put_be32(&mut commit, COMMIT_CHECKSUM, csum);
+        }
+
+        self.write_block(pos, &commit).await?;
+        self.dev.sync().await
+    }
+
+    /// Writes the committed transaction to its home locations, then empties
+    /// the journal.
+    async fn checkpoint(&self, state: &mut WriterState) -> Result<()> {
+        let bs = self.block_size as u64;
+
+        for (&block, data) in &state.running {
+            let block_start = block * bs;
+
+            // The superblock is logged as `ext4plus` left it, but has to stay
+            // marked for recovery until the journal is empty again.
+            if (block_start..block_start + bs).contains(&SUPERBLOCK_OFFSET) {
+                let mut data = data.clone();
+                let sb = (SUPERBLOCK_OFFSET - block_start) as usize;
+
+                set_needs_recovery(&mut data[sb..sb + SUPERBLOCK_SIZE], true);
+                self.dev.write_at(block_start, &data).await?;
+            } else {
+                self.dev.write_at(block_start, data).await?;
+            }
+        }
+
+        self.dev.sync().await?;
+
+        let sequence = state.sequence.wrapping_add(1);
+
+        self.write_jsb(state, 0, sequence).await?;
+        self.dev.sync().await?;
+        self.mark_needs_recovery(false).await?;
+        self.dev.sync().await?;
+
+        state.running.clear();
+        state.sequence = sequence;
+
+        Ok(())
+    }
+
+    /// Sets or clears `INCOMPAT_RECOVER` in the superblock on disk.
+    async fn mark_needs_recovery(&self, needed: bool) -> Result<()> {
+        let mut sb = [0; SUPERBLOCK_SIZE];
+
+        self.dev.read_at(SUPERBLOCK_OFFSET, &mut sb).await?;
+        set_needs_recovery(&mut sb, needed);
+        self.dev.write_at(SUPERBLOCK_OFFSET, &sb).await
+    }
+
+    /// Points the journal superblock at the transaction `sequence` starting
+    /// at `start`, or marks the journal empty if `start` is zero.
+    async fn write_jsb(&self, state: &mut WriterState, start: u32, sequence: u32) -> Result<()> {
+        let jsb = &mut state.jsb;
+
+        put_be32(jsb, JS_START, start);
+        put_be32(jsb, JS_SEQUENCE, sequence);
+
+        if self.csum_seed.is_some() {
+            jsb[JS_CHECKSUM_TYPE] = JBD2_CRC32C_CHKSUM;
+            put_be32(jsb, JS_CHECKSUM, 0);
+            let csum = crc32c(!0, &jsb[..JOURNAL_SUPERBLOCK_SIZE]);
+            put_be32(jsb, JS_CHECKSUM, csum);
+        }
+
+        let jsb = state.jsb.clone();
+        self.write_block(0, &jsb).await
+    }
+
+    /// Writes journal block `block`.
+    async fn write_block(&self, block: u32, data: &[u8]) -> Result<()> {
+        let phys = self
+            .extents
+            .iter()
+            .find_map(|ext| ext.map(block))
+            .ok_or(FsError::InvalidFs)?;
+
+        self.dev.write_at(phys * self.block_size as u64, data).await
+    }
+
+    /// A zeroed journal block starting with a header of type `blocktype`.
+    fn header(&self, blocktype: u32, sequence: u32) -> Vec<u8> {
+        let mut block = vec![0; self.block_size];
+
+        put_be32(&mut block, 0, JBD2_MAGIC_NUMBER);
+        put_be32(&mut block, 4, blocktype);
+        put_be32(&mut block, 8, sequence);
+        block
+    }
+
+    /// Writes the tag logging `data` for filesystem block `target` at the
+    /// start of `tag`.
+    fn encode_tag(&self, tag: &mut [u8], target: u64, flags: u32, sequence: u32, data: &[u8]) {
+        let csum = self
+            .csum_seed
+            .map(|seed| crc32c(crc32c(seed, &sequence.to_be_bytes()), data));
+
+        put_be32(tag, 0, target as u32);
+
+        if self.incompat & JBD2_FEATURE_INCOMPAT_CSUM_V3 != 0 {
+            put_be32(tag, 4, flags);
+            put_be32(tag, 12, csum.unwrap_or(0));
+        } else {
+            tag[4..6].copy_from_slice(&(csum.unwrap_or(0) as u16).to_be_bytes());
+            tag[6..8].copy_from_slice(&(flags as u16).to_be_bytes());
+        }
+
+        if self.incompat & JBD2_FEATURE_INCOMPAT_64BIT != 0 {
+            put_be32(tag, 8, (target >> 32) as u32);
+        }
+    }
+}
+
+#[async_trait]
+impl<CPU: CpuOps + Send + Sync> Ext4Write for JournalWriter<CPU> {
+    async fn write(
+        &self,
+        start_byte: u64,
+        src: &[u8],
+    ) -> core::result::Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(self.write_at(start_byte, src).await?)
+    }
+}
+
+#[async_trait]
+impl<CPU: CpuOps + Send + Sync> PendingWrites for JournalWriter<CPU> {
+    async fn overlay(&self, offset: u64, buf: &mut [u8]) {
+        let state = self.state.lock().await;
+
+        overlay_blocks(&state.running, self.block_size as u64, offset, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{MockBlockDevice, MockCpuOps};
+
+    const BS: usize = 1024;
+    const JOURNAL_INO: u32 = 8;
+    const JOURNAL_START: usize = 32;
+    const JOURNAL_LEN: u32 = 16;
+    const INODE_TABLE: usize = 4;
+
+    /// A volume holding just enough of ext4 to locate the journal: one block
+    /// group, a 16-block journal at block 32, and blocks 50.. filled with
+    /// their own block number.
+    struct Image {
+        data: Vec<u8>,
+    }
+
+    impl Image {
+        fn new(recover: bool) -> Self {
+            let mut img = Self {
+                data: vec![0; 64 * BS],
+            };
+
+            for block in 50..64 {
+                img.data[block * BS..(block + 1) * BS].fill(block as u8);
+            }
+
+            let sb = BS;
+            img.le32(sb + S_INODES_COUNT, 16);
+            img.le32(sb + 0x14, 1); // s_first_data_block
+            img.le32(sb + 0x28, 16); // s_inodes_per_group
+            img.data[sb + 0x38..sb + 0x3a].copy_from_slice(&0xef53u16.to_le_bytes());
+            img.le32(sb + 0x4c, 1); // s_rev_level
+            img.data[sb + 0x58..sb + 0x5a].copy_from_slice(&128u16.to_le_bytes());
+            img.le32(sb + S_FEATURE_COMPAT, EXT4_FEATURE_COMPAT_HAS_JOURNAL);
+            img.le32(
+                sb + S_FEATURE_INCOMPAT,
+                if recover {
+                    EXT4_FEATURE_INCOMPAT_RECOVER
+                } else {
+                    0
+                },
+            );
+            img.le32(sb + S_JOURNAL_INUM, JOURNAL_INO);
+
+            // Group descriptor: bg_inode_table_lo.
+            img.le32(2 * BS + 8, INODE_TABLE as u32);
+
+            // The journal inode, mapped by a single extent.
+            let inode = Self::inode_offset(JOURNAL_INO);
+            img.le32(inode + 0x20, 0x0008_0000);
+            img.data[inode + 0x28..inode + 0x34]
+                .copy_from_slice(&[0x0a, 0xf3, 1, 0, 4, 0, 0, 0, 0, 0, 0, 0]);
+            img.le32(inode + 0x34, 0);
+            img.data[inode + 0x38..inode + 0x3a]
+                .copy_from_slice(&(JOURNAL_LEN as u16).to_le_bytes());
+            img.le32(inode + 0x3c, JOURNAL_START as u32);
+
+            let jsb = JOURNAL_START * BS;
+            img.be32(jsb, JBD2_MAGIC_NUMBER);
+            img.be32(jsb + 4, JBD2_SUPERBLOCK_V2);
+            img.be32(jsb + JS_BLOCKSIZE, BS as u32);
+            img.be32(jsb + JS_MAXLEN, JOURNAL_LEN);
+            img.be32(jsb + JS_FIRST, 1);
+
+            img
+        }
+
+        fn inode_offset(ino: u32) -> usize {
+            INODE_TABLE * BS + (ino as usize - 1) * 128
+        }
+
+        fn le32(&mut self, off: usize, val: u32) {
+            self.data[off..off + 4].copy_from_slice(&val.to_le_bytes());
+        }
+
+        fn be32(&mut self, off: usize, val: u32) {
+            self.data[off..off + 4].copy_from_slice(&val.to_be_bytes());
+        }
+
+        /// Points the journal superblock at the transaction `sequence`
+        /// starting at journal block `start`.
+        fn start_at(&mut self, start: u32, sequence: u32) {
+            let jsb = JOURNAL_START * BS;
+            self.be32(jsb + JS_START, start);
+            self.be32(jsb + JS_SEQUENCE, sequence);
+        }
+
+        fn header(&mut self, jblock: u32, blocktype: u32, sequence: u32) -> usize {
+            let off = (JOURNAL_START + jblock as usize) * BS;
+            self.be32(off, JBD2_MAGIC_NUMBER);
+            self.be32(off + 4, blocktype);
+            self.be32(off + 8, sequence);
+            off
+        }
+
+        /// Writes a descriptor at `jblock` logging `tags`, each followed by
+        /// its data block. Returns the journal block after the last one.
+        fn descriptor(&mut self, jblock: u32, sequence: u32, tags: &[(u32, u16, u8)]) -> u32 {
+            let mut off =
+                self.header(jblock, JBD2_DESCRIPTOR_BLOCK, sequence) + JOURNAL_HEADER_SIZE;
+            let mut next = jblock;
+
+            for (i, &(target, flags, fill)) in tags.iter().enumerate() {
+                let last = if i + 1 == tags.len() {
+                    JBD2_FLAG_LAST_TAG as u16
+                } else {
+                    0
+                };
+
+                self.be32(off, target);
+                self.data[off + 6..off + 8].copy_from_slice(&(flags | last).to_be_bytes());
+                off += 8;
+
+                if flags & JBD2_FLAG_SAME_UUID as u16 == 0 {
+                    off += JBD2_UUID_SIZE;
+                }
+
+                next = if next + 1 >= JOURNAL_LEN { 1 } else { next + 1 };
+                let data = (JOURNAL_START + next as usize) * BS;
+                self.data[data..data + BS].fill(fill);
+            }
+
+            if next + 1 >= JOURNAL_LEN { 1 } else { next + 1 }
+        }
+
+        fn commit(&mut self, jblock: u32, sequence: u32) {
+            self.header(jblock, JBD2_COMMIT_BLOCK, sequence);
+        }
+
+        fn revoke(&mut self, jblock: u32, sequence: u32, blocks: &[u32]) {
+            let off = self.header(jblock, JBD2_REVOKE_BLOCK, sequence);
+            self.be32(off + 12, 16 + 4 * blocks.len() as u32);
+
+            for (i, &block) in blocks.iter().enumerate() {
+                self.be32(off + 16 + 4 * i, block);
+            }
+        }
+
+        async fn recover(self) -> (bool, JournaledDev) {
+            let buf = BlockBuffer::new(Box::new(MockBlockDevice::new(self.data, 1)));
+            let mut dev = JournaledDev::new(Arc::new(buf));
+            let recovered = dev.recover().await.unwrap();
+
+            (recovered, dev)
+        }
+    }
+
+    async fn block(dev: &JournaledDev, block: u64) -> Vec<u8> {
+        let mut buf = vec![0; BS];
+        dev.read_at(block * BS as u64, &mut buf).await.unwrap();
+        buf
+    }
+
+    async fn superblock(dev: &JournaledDev) -> Vec<u8> {
+        let mut buf = vec![0; SUPERBLOCK_SIZE];
+        dev.read_at(SUPERBLOCK_OFFSET, &mut buf).await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn clean_volume_is_left_alone() {
+        let mut img = Image::new(false);
+        let next = img.descriptor(1, 1, &[(50, 0, 0xaa)]);
+        img.commit(next, 1);
+        img.start_at(1, 1);
+
+        let (recovered, dev) = img.recover().await;
+
+        assert!(!recovered);
+        assert_eq!(block(&dev, 50).await, vec![50; BS]);
+    }
+
+    #[tokio::test]
+    async fn replays_committed_transactions_only() {
+        let mut img = Image::new(true);
+        let next = img.descriptor(
+            1,
+            7,
+            &[(50, 0, 0xaa), (51, JBD2_FLAG_SAME_UUID as u16, 0xbb)],
+        );
+        img.commit(next, 7);
+        let next = img.descriptor(next + 1, 8, &[(52, 0, 0xcc)]);
+        // Transaction 8 never committed.
+        img.header(next, JBD2_DESCRIPTOR_BLOCK, 9);
+        img.start_at(1, 7);
+
+        let (recovered, dev) = img.recover().await;
+
+        assert!(recovered);
+        assert_eq!(block(&dev, 50).await, vec![0xaa; BS]);
+        assert_eq!(block(&dev, 51).await, vec![0xbb; BS]);
+        assert_eq!(block(&dev, 52).await, vec![52; BS]);
+
+        // Reads straddling replayed and untouched blocks are stitched.
+        let mut buf = vec![0; 4];
+        dev.read_at(51 * BS as u64 - 2, &mut buf).await.unwrap();
+        assert_eq!(buf, [0xaa, 0xaa, 0xbb, 0xbb]);
+
+        let sb = superblock(&dev).await;
+        assert_eq!(
+            le32(&sb, S_FEATURE_INCOMPAT) & EXT4_FEATURE_INCOMPAT_RECOVER,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn later_transactions_win() {
+        let mut img = Image::new(true);
+        let next = img.descriptor(1, 3, &[(50, 0, 0xaa)]);
+        img.commit(next, 3);
+        let next = img.descriptor(next + 1, 4, &[(50, 0, 0xbb)]);
+        img.commit(next, 4);
+        img.start_at(1, 3);
+
+        let (_, dev) = img.recover().await;
+
+        assert_eq!(block(&dev, 50).await, vec![0xbb; BS]);
+    }
+
+    #[tokio::test]
+    async fn revoke_cancels_earlier_copies() {
+        let mut img = Image::new(true);
+        let next = img.descriptor(1, 3, &[(50, 0, 0xaa), (51, 0, 0xbb)]);
+        img.commit(next, 3);
+        img.revoke(next + 1, 4, &[50]);
+        img.commit(next + 2, 4);
+        img.start_at(1, 3);
+
+        let (_, dev) = img.recover().await;
+
+        assert_eq!(block(&dev, 50).await, vec![50; BS]);
+        assert_eq!(block(&dev, 51).await, vec![0xbb; BS]);
+    }
+
+    #[tokio::test]
+    async fn escaped_blocks_are_restored() {
+        let mut img = Image::new(true);
+        let next = img.descriptor(1, 3, &[(50, JBD2_FLAG_ESCAPE as u16, 0xaa)]);
+        img.commit(next, 3);
+        img.start_at(1, 3);
+
+        let (_, dev) = img.recover().await;
+
+        let data = block(&dev, 50).await;
+        assert_eq!(data[..4], JBD2_MAGIC_NUMBER.to_be_bytes());
+        assert_eq!(data[4..], vec![0xaa; BS - 4]);
+    }
+
+    #[tokio::test]
+    async fn log_wraps_around() {
+        let mut img = Image::new(true);
+        // Descriptor in the last block; its data and the commit wrap to the
+        // start of the log area.
+        let next = img.descriptor(JOURNAL_LEN - 1, 3, &[(50, 0, 0xaa)]);
+        assert_eq!(next, 2);
+        img.commit(next, 3);
+        img.start_at(JOURNAL_LEN - 1, 3);
+
+        let (_, dev) = img.recover().await;
+
+        assert_eq!(block(&dev, 50).await, vec![0xaa; BS]);
+    }
+
+    /// Opens `img` for writing, returning the device underneath so that its
+    /// contents can be checked.
+    async fn open_writer(
+        img: Image,
+    ) -> (
+        Arc<MockBlockDevice>,
+        JournaledDev,
+        Arc<JournalWriter<MockCpuOps>>,
+    ) {
+        let mock = Arc::new(MockBlockDevice::new(img.data, 1));
+        let mut dev = JournaledDev::new(Arc::new(BlockBuffer::new(Box::new(mock.clone()))));
+        let writer = JournalWriter::open(&dev).await.unwrap().unwrap();
+        dev.set_pending(writer.clone());
+
+        (mock, dev, writer)
+    }
+
+    fn disk_block(mock: &MockBlockDevice, block: usize) -> Vec<u8> {
+        mock.contents()[block * BS..(block + 1) * BS].to_vec()
+    }
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(!crc32c(!0, b"123456789"), 0xe306_9283);
+    }
+
+    #[tokio::test]
+    async fn writes_are_seen_before_they_are_committed() {
+        let (mock, dev, writer) = open_writer(Image::new(false)).await;
+
+        writer
+            .write_at(50 * BS as u64 + 10, &[0xaa; 4])
+            .await
+            .unwrap();
+
+        let mut expected = vec![50; BS];
+        expected[10..14].fill(0xaa);
+        assert_eq!(block(&dev, 50).await, expected);
+        assert_eq!(disk_block(&mock, 50), vec![50; BS]);
+
+        writer.commit().await.unwrap();
+
+        assert_eq!(disk_block(&mock, 50), expected);
+
+        // The journal is left empty, ready for the next transaction.
+        let jsb = disk_block(&mock, JOURNAL_START);
+        assert_eq!(be32(&jsb, JS_START), 0);
+        assert_eq!(be32(&jsb, JS_SEQUENCE), 1);
+        assert_eq!(
+            le32(&superblock(&dev).await, S_FEATURE_INCOMPAT) & EXT4_FEATURE_INCOMPAT_RECOVER,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn committed_log_is_replayed_after_a_crash() {
+        let (mock, _dev, writer) = open_writer(Image::new(false)).await;
+
+        writer.write_at(50 * BS as u64, &[0xaa; BS]).await.unwrap();
+        let mut magic = vec![0xbb; BS];
+        magic[..4].copy_from_slice(&JBD2_MAGIC_NUMBER.to_be_bytes());
+        writer.write_at(51 * BS as u64, &magic).await.unwrap();
+
+        // Crash after the commit block is written, before the checkpoint.
+        {
+            let mut state = writer.state.lock().await;
+            writer.write_log(&mut state).await.unwrap();
+        }
+
+        assert_eq!(disk_block(&mock, 50), vec![50; BS]);
+
+        let (recovered, dev) = Image {
+            data: mock.contents(),
+        }
+        .recover()
+        .await;
+
+        assert!(recovered);
+        assert_eq!(block(&dev, 50).await, vec![0xaa; BS]);
+        assert_eq!(block(&dev, 51).await, magic);
+    }
+
+    #[tokio::test]
+    async fn full_transactions_are_committed_early() {
+        let (mock, _dev, writer) = open_writer(Image::new(false)).await;
+
+        // The 15-block log holds 13 blocks, a descriptor and a commit block.
+        assert_eq!(writer.max_blocks, 13);
+
+        for block in 50..64 {
+            writer
+                .write_at(block as u64 * BS as u64, &[0xcc; BS])
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(disk_block(&mock, 62), vec![0xcc; BS]);
+        assert_eq!(disk_block(&mock, 63), vec![63; BS]);
+        assert_eq!(writer.state.lock().await.running.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn orphan_list_is_dropped() {
+        let mut img = Image::new(true);
+        img.le32(BS + S_LAST_ORPHAN, 12);
+        // Inode 12 links to inode 13, which ends the list.
+        img.le32(Image::inode_offset(12) + 0x14, 13);
+        img.start_at(0, 1);
+
+        let (recovered, dev) = img.recover().await;
+
+        assert!(recovered);
+        assert_eq!(le32(&superblock(&dev).await, S_LAST_ORPHAN), 0);
+    }
+}
@@ -4,18 +4,20 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
+use crate::driver::CharDevDescriptor;
 use crate::error::FsError;
 use crate::fs::path::Path;
 use crate::fs::pathbuf::PathBuf;
+use crate::fs::quota::{QuotaOps, QuotaTable};
 use crate::fs::{DirStream, Dirent};
 use crate::proc::ids::{Gid, Uid};
-use crate::sync::mutex::Mutex;
+use crate::sync::mutex::{AsyncMutexGuard, Mutex};
 use crate::{
     CpuOps,
     error::{KernelError, Result},
     fs::{
-        FileType, Filesystem, Inode, InodeId,
-        attr::{FileAttr, FilePermissions},
+        FileType, Filesystem, FsStats, Inode, InodeId,
+        attr::{FileAttr, FileOwner, FilePermissions},
         blk::buffer::BlockBuffer,
     },
 };
@@ -31,14 +33,24 @@ use core::any::Any;
 use core::error::Error;
 use core::marker::PhantomData;
 use core::num::NonZeroU32;
-use core::ops::{Deref, DerefMut};
+use core::ops::{Deref, DerefMut, Range};
 use core::time::Duration;
 use ext4plus::prelude::{
     AsyncIterator, AsyncSkip, Dir, DirEntryName, Ext4, Ext4Error, Ext4Read, Ext4Write, File,
     FollowSymlinks, Inode as ExtInode, InodeCreationOptions, InodeFlags, InodeMode, Metadata,
     PathBuf as ExtPathBuf, ReadDir, write_at,
 };
-use log::error;
+use htree::{DirHash, HtreeError};
+use journal::{JournalWriter, JournaledDev};
+use log::{error, info, warn};
+use raw::{Format, InodeLayout};
+
+mod htree;
+mod journal;
+mod raw;
+
+/// How much `copy_range` reads from the device at a time.
+const COPY_CHUNK: usize = 64 * 1024;
 
 #[async_trait]
 impl Ext4Read for BlockBuffer {
@@ -68,6 +80,7 @@ impl From<Ext4Error> for KernelError {
             Ext4Error::NotFound => KernelError::Fs(FsError::NotFound),
             Ext4Error::NotADirectory => KernelError::Fs(FsError::NotADirectory),
             Ext4Error::AlreadyExists => KernelError::Fs(FsError::AlreadyExists),
+            Ext4Error::Readonly => KernelError::Fs(FsError::ReadOnly),
             Ext4Error::Corrupt(c) => {
                 error!("Corrupt EXT4 filesystem: {c}, likely a bug");
                 KernelError::Fs(FsError::InvalidFs)
@@ -80,11 +93,16 @@ impl From<Ext4Error> for KernelError {
     }
 }
 
+/// Device nodes are reported with a zeroed device number; directory entries
+/// don't carry one. [`Ext4Inode::getattr`] fills in the real number from the
+/// inode.
 impl From<ext4plus::FileType> for FileType {
     fn from(ft: ext4plus::FileType) -> Self {
+        const NO_DEVICE: CharDevDescriptor = CharDevDescriptor { major: 0, minor: 0 };
+
         match ft {
-            ext4plus::FileType::BlockDevice => todo!(),
-            ext4plus::FileType::CharacterDevice => todo!(),
+            ext4plus::FileType::BlockDevice => FileType::BlockDevice(NO_DEVICE),
+            ext4plus::FileType::CharacterDevice => FileType::CharDevice(NO_DEVICE),
             ext4plus::FileType::Directory => FileType::Directory,
             ext4plus::FileType::Fifo => FileType::Fifo,
             ext4plus::FileType::Regular => FileType::File,
@@ -245,27 +263,242 @@ where
         Ok(total_read)
     }
 
+    async fn readahead(&self, offset: u64, len: u64) -> Result<()> {
+        let file_size = {
+            let inner = self.inner.lock().await;
+
+            if inner.file_type() != ext4plus::FileType::Regular {
+                return Ok(());
+            }
+
+            inner.size_in_bytes()
+        };
+
+        let end = offset.saturating_add(len).min(file_size);
+
+        if offset >= end {
+            return Ok(());
+        }
+
+        let fs = self.fs_ref.upgrade().unwrap();
+        let raw = fs.layout.read_inode(&fs.dev, self.id).await?;
+
+        // Inline files are only read on demand.
+        if raw.has_inline_data() {
+            return Ok(());
+        }
+
+        let bs = fs.layout.block_size;
+        let (first, last) = (offset / bs, end.div_ceil(bs));
+
+        for extent in fs.layout.read_mapping(&fs.dev, &raw).await? {
+            let ext_start = extent.logical as u64;
+            let lo = first.max(ext_start);
+            let hi = last.min(ext_start + extent.len as u64);
+
+            if lo < hi {
+                let physical = extent.physical + (lo - ext_start);
+                fs.dev.readahead(physical * bs, (hi - lo) * bs).await?;
+            }
+        }
+
+        Ok(())
+    }
+
     async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
         let mut inner = self.inner.lock().await;
         // Must be a regular file.
         if inner.file_type() != ext4plus::FileType::Regular {
             return Err(KernelError::NotSupported);
         }
 
-        let fs = self.fs_ref.upgrade().unwrap();
-        let total_written = write_at(&fs.inner, &mut inner, buf, offset).await?;
+        let owner = Uid::new(inner.uid());
+        let before = inner.blocks() * 512;
+
+        // We can't know up front how many blocks ext4plus will allocate, so
+        // charge for the bytes that lie beyond the current allocation and
+        // settle up with the real figure once the write has completed.
+        let estimate = (offset + buf.len() as u64).saturating_sub(before);
+        fs.quota.charge_space(owner, estimate)?;
+
+        let res = write_at(&fs.inner, &mut inner, buf, offset).await;
+
+        fs.settle_space(owner, estimate, before, inner.blocks() * 512);
+
+        Ok(res?)
+    }
+
+    /// Copies between files on this filesystem by reading each extent's
+    /// blocks straight off the device, rather than a block at a time through
+    /// the file. Holes aren't copied where `dst` doesn't have data to
+    /// overwrite, so sparse files stay sparse.
+    async fn copy_range(
+        &self,
+        off_in: u64,
+        dst: &dyn Inode,
+        off_out: u64,
+        len: u64,
+    ) -> Result<usize> {
+        let dst = dst
+            .as_any()
+            .downcast_ref::<Self>()
+            .filter(|dst| Weak::ptr_eq(&self.fs_ref, &dst.fs_ref))
+            .ok_or(KernelError::NotSupported)?;
+
+        let size = {
+            let inner = self.inner.lock().await;
+
+            if inner.file_type() != ext4plus::FileType::Regular {
+                return Err(KernelError::NotSupported);
+            }
+
+            inner.size_in_bytes()
+        };
+
+        let end = off_in.saturating_add(len).min(size);
+
+        if off_in >= end {
+            return Ok(0);
+        }
+
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let raw = fs.layout.read_inode(&fs.dev, self.id).await?;
+
+        // Inline files are left to the generic copy.
+        if raw.has_inline_data() {
+            return Err(KernelError::NotSupported);
+        }
+
+        let extents = fs.layout.read_mapping(&fs.dev, &raw).await?;
+        let dst_size = dst.getattr().await?.size;
+        let bs = fs.layout.block_size;
+        let mut buf = vec![0; COPY_CHUNK];
+        let mut pos = off_in;
+
+        while pos < end {
+            let block = pos / bs;
+            let next = extents
+                .iter()
+                .find(|ext| ext.logical as u64 + ext.len as u64 > block);
+
+            // Find the run of data or hole that `pos` lies in.
+            let (physical, run_end) = match next {
+                Some(ext) if ext.logical as u64 <= block => {
+                    let physical = (!ext.unwritten)
+                        .then(|| (ext.physical + block - ext.logical as u64) * bs + pos % bs);
+
+                    (physical, (ext.logical as u64 + ext.len as u64) * bs)
+                }
+                Some(ext) => (None, ext.logical as u64 * bs),
+                None => (None, end),
+            };
+
+            let run_end = run_end.min(end);
+            let out = off_out + (pos - off_in);
+
+            // A hole reads as zeroes, which only have to be written over what
+            // `dst` already holds.
+            let run_len = match physical {
+                Some(_) => run_end - pos,
+                None => (run_end - pos).min(dst_size.saturating_sub(out)),
+            };
+
+            let mut done = 0;
+
+            while done < run_len {
+                let chunk = &mut buf[..(run_len - done).min(COPY_CHUNK as u64) as usize];
+
+                match physical {
+                    Some(physical) => fs.dev.read_at(physical + done, chunk).await?,
+                    None => chunk.fill(0),
+                }
 
-        Ok(total_written)
+                let mut written = 0;
+
+                while written < chunk.len() {
+                    match dst
+                        .write_at(out + done + written as u64, &chunk[written..])
+                        .await
+                    {
+                        Ok(n) if n > 0 => written += n,
+                        res => {
+                            // Once anything has been copied, a failure just
+                            // cuts the copy short.
+                            let copied = (out + done - off_out) as usize + written;
+
+                            return match res {
+                                _ if copied > 0 => Ok(copied),
+                                Err(e) => Err(e),
+                                Ok(_) => Err(FsError::NoSpace.into()),
+                            };
+                        }
+                    }
+                }
+
+                done += chunk.len() as u64;
+            }
+
+            pos = run_end;
+        }
+
+        // A hole at the end still has to make `dst` long enough.
+        let out_end = off_out + (end - off_in);
+
+        if out_end > dst.getattr().await?.size {
+            dst.write_at(out_end - 1, &[0]).await?;
+        }
+
+        Ok((end - off_in) as usize)
+    }
+
+    async fn probe_extents(&self, range: Range<u64>) -> Result<Vec<Range<u64>>> {
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let raw = fs.layout.read_inode(&fs.dev, self.id).await?;
+
+        // Inline files are taken to be data throughout.
+        if raw.has_inline_data() {
+            return Ok(vec![range]);
+        }
+
+        let bs = fs.layout.block_size;
+        let mut extents = fs.layout.read_mapping(&fs.dev, &raw).await?;
+        extents.sort_by_key(|ext| ext.logical);
+
+        // Unwritten extents read as zeroes, so they count as holes.
+        let mut data: Vec<Range<u64>> = Vec::new();
+
+        for ext in extents.iter().filter(|ext| !ext.unwritten) {
+            let start = (ext.logical as u64 * bs).max(range.start);
+            let end = ((ext.logical as u64 + ext.len as u64) * bs).min(range.end);
+
+            if start >= end {
+                continue;
+            }
+
+            match data.last_mut() {
+                Some(prev) if prev.end == start => prev.end = end,
+                _ => data.push(start..end),
+            }
+        }
+
+        Ok(data)
     }
 
     async fn truncate(&self, size: u64) -> Result<()> {
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
         let inner = self.inner.lock().await;
         if inner.file_type() != ext4plus::FileType::Regular {
             return Err(KernelError::NotSupported);
         }
-        let fs = self.fs_ref.upgrade().unwrap();
+        let owner = Uid::new(inner.uid());
+        let before = inner.blocks() * 512;
         let mut file = File::open_inode(&fs.inner, inner.clone())?;
-        file.truncate(size).await?;
+        let res = file.truncate(size).await;
+        fs.settle_space(owner, 0, before, file.inode().blocks() * 512);
+        res?;
         Ok(())
     }
 
@@ -276,18 +509,28 @@ where
 
         attrs.id = InodeId::from_fsid_and_inodeid(fs.id(), self.id.get() as u64);
 
+        match &mut attrs.file_type {
+            FileType::BlockDevice(dev) | FileType::CharDevice(dev) => {
+                *dev = fs.layout.read_inode(&fs.dev, self.id).await?.device();
+            }
+            _ => {}
+        }
+
         Ok(attrs)
     }
 
     async fn setattr(&self, attr: FileAttr) -> Result<()> {
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
         let mut inner = self.inner.lock().await;
+        fs.quota
+            .transfer(Uid::new(inner.uid()), attr.uid, inner.blocks() * 512)?;
         inner.set_atime(attr.atime);
         inner.set_ctime(attr.ctime);
         inner.set_mtime(attr.mtime);
         inner.set_gid(attr.gid.into());
         inner.set_uid(attr.uid.into());
         inner.set_links_count(attr.nlinks as u16);
-        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
         inner.write(&fs.inner).await?;
         Ok(())
     }
@@ -297,8 +540,9 @@ where
         let inner = self.inner.lock().await;
         let child_inode = match &*inner {
             InodeInner::Directory(d) => {
-                d.get_entry(DirEntryName::try_from(name.as_bytes()).unwrap())
-                    .await?
+                let name = DirEntryName::try_from(name.as_bytes())
+                    .map_err(|_| KernelError::NameTooLong)?;
+                fs.lookup_entry(d, name).await?
             }
             _ => return Err(KernelError::NotSupported),
         };
@@ -316,45 +560,76 @@ where
         name: &str,
         file_type: FileType,
         permissions: FilePermissions,
+        owner: FileOwner,
         time: Option<Duration>,
     ) -> Result<Arc<dyn Inode>> {
-        let fs = self.fs_ref.upgrade().unwrap();
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
         let mut inner = self.inner.lock().await;
         let inner_dir = match &mut *inner {
             InodeInner::Directory(d) => d,
             _ => return Err(KernelError::NotSupported),
         };
-        let mut new_inode = if matches!(file_type, FileType::File) {
-            let inode = fs
-                .inner
+        if !matches!(
+            file_type,
+            FileType::File | FileType::Directory | FileType::Fifo
+        ) {
+            return Err(KernelError::NotSupported);
+        }
+        fs.quota.charge_inode(owner.uid)?;
+        let new_inode = if matches!(file_type, FileType::File) {
+            fs.inner
                 .create_inode(InodeCreationOptions {
                     file_type: ext4plus::FileType::Regular,
                     mode: InodeMode::S_IFREG | InodeMode::from_bits(permissions.bits()).unwrap(),
-                    uid: 0,
-                    gid: 0,
+                    uid: owner.uid.into(),
+                    gid: owner.gid.into(),
                     time: time.unwrap_or_default(),
                     flags: InodeFlags::empty(),
                 })
-                .await?;
-            InodeInner::Regular(File::open_inode(&fs.inner, inode)?)
-        } else if matches!(file_type, FileType::Directory) {
+                .await
+                .and_then(|inode| File::open_inode(&fs.inner, inode))
+                .map(InodeInner::Regular)
+        } else if matches!(file_type, FileType::Fifo) {
+            // There's nothing on disk behind a FIFO but the inode.
+            fs.inner
+                .create_inode(InodeCreationOptions {
+                    file_type: ext4plus::FileType::Fifo,
+                    mode: InodeMode::S_IFIFO | InodeMode::from_bits(permissions.bits()).unwrap(),
+                    uid: owner.uid.into(),
+                    gid: owner.gid.into(),
+                    time: time.unwrap_or_default(),
+                    flags: InodeFlags::empty(),
+                })
+                .await
+                .map(InodeInner::Other)
+        } else {
             let old_links_count = inner_dir.inode().links_count();
             inner_dir.inode_mut().set_links_count(old_links_count + 1);
-            let inode = fs
+            match fs
                 .inner
                 .create_inode(InodeCreationOptions {
                     file_type: ext4plus::FileType::Directory,
                     mode: InodeMode::S_IFDIR | InodeMode::from_bits(permissions.bits()).unwrap(),
-                    uid: 0,
-                    gid: 0,
+                    uid: owner.uid.into(),
+                    gid: owner.gid.into(),
                     time: Default::default(),
                     flags: InodeFlags::empty(),
                 })
-                .await?;
-            let dir = Dir::init(fs.inner.clone(), inode, self.id).await?;
-            InodeInner::Directory(dir)
-        } else {
-            return Err(KernelError::NotSupported);
+                .await
+            {
+                Ok(inode) => Dir::init(fs.inner.clone(), inode, self.id)
+                    .await
+                    .map(InodeInner::Directory),
+                Err(e) => Err(e),
+            }
+        };
+        let mut new_inode = match new_inode {
+            Ok(inode) => inode,
+            Err(e) => {
+                fs.quota.release_inode(owner.uid);
+                return Err(e.into());
+            }
         };
         inner_dir
             .link(
@@ -371,13 +646,56 @@ where
         }))
     }
 
+    async fn tmpfile(
+        &self,
+        permissions: FilePermissions,
+        owner: FileOwner,
+        time: Option<Duration>,
+    ) -> Result<Arc<dyn Inode>> {
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
+        if self.inner.lock().await.file_type() != ext4plus::FileType::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+        fs.quota.charge_inode(owner.uid)?;
+        // The inode starts out with no links. There's no orphan list yet, so
+        // one that's never linked in is only given back by fsck.
+        let new_inode = match fs
+            .inner
+            .create_inode(InodeCreationOptions {
+                file_type: ext4plus::FileType::Regular,
+                mode: InodeMode::S_IFREG | InodeMode::from_bits(permissions.bits()).unwrap(),
+                uid: owner.uid.into(),
+                gid: owner.gid.into(),
+                time: time.unwrap_or_default(),
+                flags: InodeFlags::empty(),
+            })
+            .await
+            .and_then(|inode| File::open_inode(&fs.inner, inode))
+        {
+            Ok(file) => file,
+            Err(e) => {
+                fs.quota.release_inode(owner.uid);
+                return Err(e.into());
+            }
+        };
+        let new_inode = InodeInner::Regular(new_inode);
+        Ok(Arc::new(Ext4Inode::<CPU> {
+            fs_ref: self.fs_ref.clone(),
+            id: new_inode.index,
+            inner: Mutex::new(new_inode),
+            path: self.path.clone(),
+        }))
+    }
+
     async fn link(&self, name: &str, inode: Arc<dyn Inode>) -> Result<()> {
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
         let mut inner = self.inner.lock().await;
         let inner_dir = match &mut *inner {
             InodeInner::Directory(d) => d,
             _ => return Err(KernelError::NotSupported),
         };
-        let fs = self.fs_ref.upgrade().unwrap();
         // TODO: This forces the other inode out of sync
         // Check fs ids match
         if inode.id().fs_id() != fs.id() {
@@ -401,15 +719,24 @@ where
     }
 
     async fn unlink(&self, name: &str) -> Result<()> {
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
         let mut inner = self.inner.lock().await;
         let inner_dir = match &mut *inner {
             InodeInner::Directory(d) => d,
             _ => return Err(KernelError::NotSupported),
         };
-        let fs = self.fs_ref.upgrade().unwrap();
         let entry = DirEntryName::try_from(name.as_bytes()).unwrap();
         let child_inode = inner_dir.get_entry(entry).await?;
+        let owner = Uid::new(child_inode.uid());
+        let blocks = child_inode.blocks();
+        let freed = child_inode.file_type() == ext4plus::FileType::Directory
+            || child_inode.links_count() <= 1;
         inner_dir.unlink(entry, child_inode).await?;
+        if freed {
+            fs.quota.release_space(owner, blocks * 512);
+            fs.quota.release_inode(owner);
+        }
         Ok(())
     }
 
@@ -431,12 +758,37 @@ where
         if inner.file_type() != ext4plus::FileType::Symlink {
             return Err(KernelError::NotSupported);
         }
-        let fs = self.fs_ref.upgrade().unwrap();
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let size = inner.size_in_bytes();
+        let raw = fs.layout.read_inode(&fs.dev, self.id).await?;
+
+        let target = if raw.is_fast_symlink(size, inner.blocks(), fs.layout.block_size) {
+            // Fast symlink: the target is stored in place of the block map.
+            let len = size as usize;
+            if len == 0 || len > raw::I_BLOCK_LEN {
+                return Err(FsError::InvalidFs.into());
+            }
+            raw.i_block()[..len].to_vec()
+        } else {
+            // Slow symlink: the target fills part of a single data block (or
+            // the inline data area). Check the size before it's used to size
+            // the buffer the target is read into.
+            if size == 0 || size >= fs.layout.block_size {
+                return Err(FsError::InvalidFs.into());
+            }
+
+            let target = fs.inner.read_inode_file(&inner).await?;
+
+            if target.len() as u64 != size {
+                return Err(FsError::InvalidFs.into());
+            }
+
+            target
+        };
+
         // Conversion has to ensure path is valid UTF-8 (O(n) time).
-        Ok(inner
-            .symlink_target(&fs.inner)
-            .await
-            .map(|p| PathBuf::from(p.to_str().unwrap()))?)
+        let target = String::from_utf8(target).map_err(|_| FsError::InvalidInput)?;
+        Ok(PathBuf::from(target))
     }
 
     async fn rename_from(
@@ -446,6 +798,8 @@ where
         new_name: &str,
         no_replace: bool,
     ) -> Result<()> {
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
         if old_name == new_name && old_parent.id().inode_id() == self.id().inode_id() {
             return Ok(());
         }
@@ -453,7 +807,6 @@ where
         if old_parent.id().fs_id() != self.id().fs_id() {
             return Err(KernelError::Fs(FsError::CrossDevice));
         }
-        let fs = self.fs_ref.upgrade().unwrap();
 
         let old_parent_inode = old_parent
             .as_any()
@@ -496,10 +849,10 @@ where
             let source_kind = child_inode.file_type();
 
             if target_kind == ext4plus::FileType::Directory {
-                let target_is_empty = fs
-                    .inner
-                    .read_dir(&self.path.join(new_name))
-                    .await?
+                // Read the target through its inode rather than its path: a
+                // cached parent may have been moved since it was looked up.
+                let target_is_empty = Dir::open_inode(&fs.inner, target_inode.clone())?
+                    .read_dir()?
                     .all(|e| {
                         let Ok(entry) = e else {
                             // If we fail to read the directory, be conservative and treat it as non-empty.
@@ -554,31 +907,50 @@ where
         Ok(())
     }
 
-    async fn symlink(&self, name: &str, target: &Path) -> Result<()> {
+    async fn symlink(&self, name: &str, target: &Path, owner: FileOwner) -> Result<()> {
+        let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
         let mut inner = self.inner.lock().await;
         let inner_dir = match &mut *inner {
             InodeInner::Directory(d) => d,
             _ => return Err(KernelError::NotSupported),
         };
-        let fs = self.fs_ref.upgrade().unwrap();
-        fs.inner
+        let name = DirEntryName::try_from(name.as_bytes()).map_err(|_| KernelError::NameTooLong)?;
+
+        // A target that doesn't fit in the inode takes a single block, as on
+        // Linux.
+        if target.as_str().len() >= fs.layout.block_size as usize {
+            return Err(KernelError::NameTooLong);
+        }
+
+        let target = ExtPathBuf::try_from(target.as_str().as_bytes())
+            .map_err(|_| KernelError::NameTooLong)?;
+        fs.quota.charge_inode(owner.uid)?;
+        if let Err(e) = fs
+            .inner
             .symlink(
                 inner_dir,
-                DirEntryName::try_from(name.as_bytes()).unwrap(),
-                ExtPathBuf::new(target.as_str().as_bytes()),
-                0,
-                0,
+                name,
+                target,
+                owner.uid.into(),
+                owner.gid.into(),
                 Duration::from_secs(0),
             )
-            .await?;
+            .await
+        {
+            fs.quota.release_inode(owner.uid);
+            return Err(e.into());
+        }
         Ok(())
     }
 
     async fn sync(&self) -> Result<()> {
-        let mut inner = self.inner.lock().await;
         let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
+        let mut inner = self.inner.lock().await;
         inner.write(&fs.inner).await?;
-        Ok(())
+        fs.commit().await?;
+        fs.dev.sync().await
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -606,8 +978,9 @@ where
     }
 
     async fn setxattr(&self, name: &str, buf: &[u8], create: bool, replace: bool) -> Result<()> {
-        let mut inner = self.inner.lock().await;
         let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
+        let mut inner = self.inner.lock().await;
         if inner.get_xattr(&fs.inner, name).await?.is_some() {
             if create {
                 return Err(KernelError::Fs(FsError::AlreadyExists));
@@ -622,8 +995,9 @@ where
     }
 
     async fn removexattr(&self, name: &str) -> Result<()> {
-        let mut inner = self.inner.lock().await;
         let fs = self.fs_ref.upgrade().ok_or(FsError::InvalidFs)?;
+        let _op = fs.begin().await?;
+        let mut inner = self.inner.lock().await;
         inner.remove_xattr(&fs.inner, name).await?;
         Ok(())
     }
@@ -637,7 +1011,18 @@ pub struct Ext4Filesystem<CPU: CpuOps> {
     inner: Ext4,
     id: u64,
     this: Weak<Ext4Filesystem<CPU>>,
-    dev: Arc<BlockBuffer>,
+    dev: Arc<JournaledDev>,
+    /// Where writes go, if the volume has a journal we can write through.
+    journal: Option<Arc<JournalWriter<CPU>>>,
+    /// Held by each operation that modifies the filesystem.
+    ops: Mutex<(), CPU>,
+    layout: InodeLayout,
+    /// Whether the volume can't be written, whatever it's mounted with.
+    read_only: bool,
+    unsigned_dir_hash: bool,
+    dir_hash_seed: [u32; 4],
+    reserved_blocks: u64,
+    quota: QuotaTable<CPU>,
     _phantom_data: PhantomData<CPU>,
 }
 
@@ -646,19 +1031,233 @@ where
     CPU: CpuOps + Send + Sync,
 {
     /// Construct a new EXT4 filesystem instance.
+    ///
+    /// A volume that wasn't cleanly unmounted is recovered in memory and
+    /// mounted read-only, as are ext2 and ext3 volumes.
     pub async fn new(dev: BlockBuffer, id: u64) -> Result<Arc<Self>> {
         let dev_arc = Arc::new(dev);
-        let inner =
-            Ext4::load_with_writer(Box::new(dev_arc.clone()), Some(Box::new(dev_arc.clone())))
-                .await?;
+        let mut journaled = JournaledDev::new(dev_arc.clone());
+
+        let recovered = journaled.recover().await?;
+        let format = raw::format(&journaled).await?;
+        let read_only = recovered || format == Format::Ext2;
+        let journal = if read_only {
+            None
+        } else {
+            JournalWriter::open(&journaled).await?
+        };
+
+        let writer: Option<Box<dyn Ext4Write>> = if recovered {
+            warn!("ext4: volume was not cleanly unmounted, mounting read-only");
+            None
+        } else if format == Format::Ext2 {
+            info!("ext4: volume is ext2 or ext3, mounting read-only");
+            None
+        } else if let Some(journal) = &journal {
+            journaled.set_pending(journal.clone());
+            Some(Box::new(journal.clone()))
+        } else {
+            Some(Box::new(dev_arc))
+        };
+
+        let journaled = Arc::new(journaled);
+        let layout = InodeLayout::read(&journaled).await?;
+        let unsigned_dir_hash = raw::unsigned_dir_hash(&journaled).await?;
+        let dir_hash_seed = raw::dir_hash_seed(&journaled).await?;
+        let reserved_blocks = raw::reserved_blocks(&journaled).await?;
+        let inner = Ext4::load_with_writer(Box::new(journaled.clone()), writer).await?;
         Ok(Arc::new_cyclic(|weak| Self {
             inner,
             id,
             this: weak.clone(),
-            dev: dev_arc,
+            dev: journaled,
+            journal,
+            ops: Mutex::new(()),
+            layout,
+            read_only,
+            unsigned_dir_hash,
+            dir_hash_seed,
+            reserved_blocks,
+            quota: QuotaTable::new(),
             _phantom_data: PhantomData,
         }))
     }
+
+    /// Starts an operation that modifies the filesystem. Operations run one
+    /// at a time, and whatever the last one wrote is committed before the
+    /// next begins, so that a transaction only ever holds whole operations.
+    /// One that writes more than a transaction can hold is the exception.
+    async fn begin(&self) -> Result<AsyncMutexGuard<'_, (), CPU>> {
+        let op = self.ops.lock().await;
+
+        self.commit().await?;
+        Ok(op)
+    }
+
+    /// Commits whatever has been written to the journal, if there is one.
+    async fn commit(&self) -> Result<()> {
+        match &self.journal {
+            Some(journal) => journal.commit().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Looks up `name` in `dir`.
+    ///
+    /// Indexed directories are searched through their hash tree. If the index
+    /// can't be used, because it was built with a hash we don't implement or
+    /// is damaged, the directory is scanned linearly instead, as Linux does.
+    async fn lookup_entry(&self, dir: &Dir, name: DirEntryName<'_>) -> Result<ExtInode> {
+        if !dir.inode().flags().contains(InodeFlags::DIRECTORY_HTREE) {
+            return Ok(dir.get_entry(name).await?);
+        }
+
+        match self.search_index(dir, name.as_ref()).await {
+            Ok(Some(inode)) => Ok(ExtInode::read(&self.inner, inode).await?),
+            Ok(None) => Err(FsError::NotFound.into()),
+            Err(HtreeError::Io(e)) => Err(e),
+            Err(e @ HtreeError::Unusable(_)) => {
+                warn!(
+                    "ext4: can't use index of directory {}: {e}, scanning it",
+                    dir.inode().index
+                );
+                self.scan_dir(dir, name).await
+            }
+        }
+    }
+
+    /// Finds `name` through the hash tree of the indexed directory `dir`,
+    /// returning its inode number.
+    async fn search_index(
+        &self,
+        dir: &Dir,
+        name: &[u8],
+    ) -> core::result::Result<Option<NonZeroU32>, HtreeError> {
+        let block_size = self.layout.block_size;
+        let blocks = dir.inode().size_in_bytes().div_ceil(block_size);
+        let mut file =
+            File::open_inode(&self.inner, dir.inode().clone()).map_err(KernelError::from)?;
+        let mut block = vec![0; block_size as usize];
+
+        let mut read_block = async |n: u32, block: &mut [u8]| {
+            if n as u64 >= blocks {
+                return Err(HtreeError::Unusable("block past the end of the directory"));
+            }
+
+            let mut done = 0;
+
+            while done < block.len() {
+                let read = file
+                    .read_bytes_at(&mut block[done..], n as u64 * block_size + done as u64)
+                    .await
+                    .map_err(KernelError::from)?;
+
+                if read == 0 {
+                    return Err(HtreeError::Unusable("short directory block"));
+                }
+
+                done += read;
+            }
+
+            Ok(())
+        };
+
+        read_block(0, &mut block).await?;
+
+        // "." and ".." are the first entries of the root block, ahead of the
+        // index, rather than in a leaf.
+        if name == b"." || name == b".." {
+            return htree::scan_leaf(&block, name);
+        }
+
+        let root = htree::parse_root(&block)?;
+        let hash = DirHash::new(
+            root.hash_version,
+            self.unsigned_dir_hash,
+            self.dir_hash_seed,
+        )?
+        .hash(name);
+
+        // The entries of each node from the root down, with the one followed.
+        let mut path = Vec::new();
+        let mut entries = root.entries;
+
+        for _ in 0..root.indirect_levels {
+            let at = htree::find_entry(&entries, hash);
+            let child = entries[at].block;
+
+            path.push((entries, at));
+            read_block(child, &mut block).await?;
+            entries = htree::parse_node(&block)?;
+        }
+
+        let at = htree::find_entry(&entries, hash);
+        path.push((entries, at));
+
+        loop {
+            let (entries, at) = path.last().unwrap();
+
+            read_block(entries[*at].block, &mut block).await?;
+
+            if let Some(inode) = htree::scan_leaf(&block, name)? {
+                return Ok(Some(inode));
+            }
+
+            // Names with the same hash may have spilled into the next leaf.
+            // Find it by stepping along the deepest node that has another
+            // entry, then down its leftmost children.
+            let Some(level) = path
+                .iter()
+                .rposition(|(entries, at)| at + 1 < entries.len())
+            else {
+                return Ok(None);
+            };
+
+            let (entries, at) = &mut path[level];
+            *at += 1;
+
+            if !htree::continues_into(hash, entries[*at].hash) {
+                return Ok(None);
+            }
+
+            let mut child = entries[*at].block;
+
+            for node in path.iter_mut().skip(level + 1) {
+                read_block(child, &mut block).await?;
+
+                let entries = htree::parse_node(&block)?;
+                child = entries[0].block;
+                *node = (entries, 0);
+            }
+        }
+    }
+
+    /// Finds `name` by walking every entry of `dir`.
+    async fn scan_dir(&self, dir: &Dir, name: DirEntryName<'_>) -> Result<ExtInode> {
+        let mut entries = dir.read_dir()?;
+
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+
+            if entry.file_name() == name {
+                return Ok(ExtInode::read(&self.inner, entry.inode).await?);
+            }
+        }
+
+        Err(FsError::NotFound.into())
+    }
+
+    /// Reconciles the `charged` bytes taken from `owner` before an operation
+    /// with the change in the inode's allocation from `before` to `after`.
+    fn settle_space(&self, owner: Uid, charged: u64, before: u64, after: u64) {
+        let used = after as i64 - before as i64 - charged as i64;
+
+        if used > 0 {
+            self.quota.charge_space_nofail(owner, used as u64);
+        } else {
+            self.quota.release_space(owner, used.unsigned_abs());
+        }
+    }
 }
 
 #[async_trait]
@@ -675,6 +1274,26 @@ where
         0xef53 // EXT4 magic number
     }
 
+    fn read_only(&self) -> bool {
+        !cfg!(feature = "ext4_write") || self.read_only
+    }
+
+    async fn statfs(&self) -> Result<FsStats> {
+        let sb = self.inner.superblock();
+        let blocks_free = sb.free_blocks_count();
+
+        Ok(FsStats {
+            magic: self.magic(),
+            block_size: self.layout.block_size,
+            blocks: sb.blocks_count(),
+            blocks_free,
+            blocks_avail: blocks_free.saturating_sub(self.reserved_blocks),
+            files: sb.inodes_per_block_group().get() as u64 * sb.num_block_groups() as u64,
+            files_free: sb.free_inodes_count() as u64,
+            name_max: 255,
+        })
+    }
+
     /// Returns the root inode of the mounted EXT4 filesystem.
     async fn root_inode(&self) -> Result<Arc<dyn Inode>> {
         let root = self.inner.read_root_inode().await?;
@@ -686,10 +1305,19 @@ where
         }))
     }
 
-    /// Flushes any dirty data to the underlying block device.  The current
-    /// stub implementation simply forwards the request to `BlockBuffer::sync`.
+    /// Commits anything still in the journal's running transaction, then
+    /// flushes the underlying block device.
     async fn sync(&self) -> Result<()> {
+        let _op = self.begin().await?;
         self.dev.sync().await?;
         Ok(())
     }
+
+    fn quota(&self) -> Option<&dyn QuotaOps> {
+        Some(&self.quota)
+    }
+
+    fn cache_inodes(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,883 @@
+//! Raw on-disk structures for the parts of ext4 that `ext4plus` doesn't
+//! expose.
+//!
+//! Special files keep their payload in fields that `ext4plus` treats as
+//! private: device nodes store the device number in `i_block`, and telling a
+//! fast symlink from a slow one requires the extended attribute block. Rather
+//! than carry a fork of the crate, we read the handful of fields we need
+//! straight from the inode table.
+
+use super::journal::JournaledDev;
+use crate::{
+    driver::CharDevDescriptor,
+    error::{FsError, KernelError, Result},
+    pod::Pod,
+};
+use alloc::{vec, vec::Vec};
+use core::num::NonZeroU32;
+use log::warn;
+
+/// Byte offset of the primary superblock.
+const SUPERBLOCK_OFFSET: u64 = 1024;
+
+/// `s_magic` for ext2/3/4.
+const EXT4_MAGIC: u16 = 0xef53;
+
+/// `s_rev_level` of filesystems with a fixed 128-byte inode.
+const EXT4_GOOD_OLD_REV: u32 = 0;
+
+/// Inode size used by `EXT4_GOOD_OLD_REV` filesystems.
+const EXT4_GOOD_OLD_INODE_SIZE: u16 = 128;
+
+/// `INCOMPAT_64BIT`: group descriptors may be larger than 32 bytes.
+const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x80;
+
+/// Group descriptor size used when `INCOMPAT_64BIT` is not set.
+const EXT4_MIN_DESC_SIZE: u16 = 32;
+
+/// Group descriptor size from which `bg_inode_table_hi` is present.
+const EXT4_MIN_DESC_SIZE_64BIT: u16 = 64;
+
+/// `EXT4_EA_INODE_FL`: the inode holds a large extended attribute value.
+const EXT4_EA_INODE_FL: u32 = 0x0020_0000;
+
+/// `EXT4_INLINE_DATA_FL`: the file data lives inside the inode.
+const EXT4_INLINE_DATA_FL: u32 = 0x1000_0000;
+
+/// `INCOMPAT_FILETYPE`: directory entries record the file type.
+const EXT4_FEATURE_INCOMPAT_FILETYPE: u32 = 0x2;
+
+/// `INCOMPAT_EXTENTS`: files may be mapped by extent trees.
+const EXT4_FEATURE_INCOMPAT_EXTENTS: u32 = 0x40;
+
+/// `EXT4_EXTENTS_FL`: `i_block` holds the root of an extent tree.
+const EXT4_EXTENTS_FL: u32 = 0x0008_0000;
+
+/// `eh_magic` of every extent tree node.
+const EXT4_EXT_MAGIC: u16 = 0xf30a;
+
+/// Extents longer than this are unwritten; their length is biased by it.
+const EXT_INIT_MAX_LEN: u16 = 1 << 15;
+
+/// Maximum depth of an extent tree.
+const EXT4_MAX_EXTENT_DEPTH: u16 = 5;
+
+/// Number of block pointers in `i_block` that map data blocks directly. They
+/// are followed by one single, one double and one triple indirect pointer.
+const EXT2_NDIR_BLOCKS: usize = 12;
+
+/// `EXT2_FLAGS_UNSIGNED_HASH`: directory index hashes treat names as
+/// unsigned chars.
+const EXT2_FLAGS_UNSIGNED_HASH: u32 = 0x2;
+
+/// Number of bytes in `i_block`.
+pub const I_BLOCK_LEN: usize = 60;
+
+#[repr(C, packed)]
+struct RawSuperblock {
+    _pad0: [u8; 0x08],
+    r_blocks_count_lo: u32,
+    _pad0a: [u8; 0x08],
+    first_data_block: u32,
+    log_block_size: u32,
+    _pad1: [u8; 0x0c],
+    inodes_per_group: u32,
+    _pad2: [u8; 0x0c],
+    magic: u16,
+    _pad3: [u8; 0x12],
+    rev_level: u32,
+    _pad4: [u8; 0x08],
+    inode_size: u16,
+    _pad5: [u8; 0x06],
+    feature_incompat: u32,
+    _pad6: [u8; 0x88],
+    hash_seed: [u8; 16],
+    _pad6a: [u8; 0x02],
+    desc_size: u16,
+    _pad7: [u8; 0x54],
+    r_blocks_count_hi: u32,
+    _pad7a: [u8; 0x08],
+    flags: u32,
+}
+
+unsafe impl Pod for RawSuperblock {}
+
+#[repr(C, packed)]
+struct RawGroupDesc {
+    _pad0: [u8; 0x08],
+    inode_table_lo: u32,
+    _pad1: [u8; 0x1c],
+    inode_table_hi: u32,
+    _pad2: [u8; 0x14],
+}
+
+unsafe impl Pod for RawGroupDesc {}
+
+/// The leading fields of an on-disk inode.
+#[repr(C, packed)]
+pub struct RawInode {
+    _pad0: [u8; 0x14],
+    dtime: u32,
+    _gid: u16,
+    links_count: u16,
+    _blocks_lo: u32,
+    flags: u32,
+    _osd1: u32,
+    block: [u8; I_BLOCK_LEN],
+    _generation: u32,
+    file_acl_lo: u32,
+    _size_high: u32,
+    _pad1: [u8; 0x06],
+    file_acl_hi: u16,
+    _pad2: [u8; 0x08],
+}
+
+unsafe impl Pod for RawInode {}
+
+impl RawInode {
+    /// Returns the contents of `i_block`.
+    pub fn i_block(&self) -> [u8; I_BLOCK_LEN] {
+        self.block
+    }
+
+    /// Returns `i_dtime`, which for inodes on the orphan list holds the
+    /// number of the next orphan.
+    pub fn dtime(&self) -> u32 {
+        self.dtime
+    }
+
+    /// Returns the number of hard links to the inode.
+    pub fn links_count(&self) -> u16 {
+        self.links_count
+    }
+
+    /// Returns whether the inode maps its blocks with an extent tree.
+    pub fn uses_extents(&self) -> bool {
+        let flags = self.flags;
+
+        flags & EXT4_EXTENTS_FL != 0
+    }
+
+    /// Returns whether the file's data lives in the inode rather than in blocks.
+    pub fn has_inline_data(&self) -> bool {
+        let flags = self.flags;
+
+        flags & EXT4_INLINE_DATA_FL != 0
+    }
+
+    /// Decodes the device number of a character or block device inode.
+    ///
+    /// Small device numbers use the old 8:8 encoding in `i_block[0]`; anything
+    /// larger is stored in `i_block[1]` using the 12:20 encoding, with
+    /// `i_block[0]` left zero.
+    pub fn device(&self) -> CharDevDescriptor {
+        let word = |idx: usize| {
+            u32::from_le_bytes(self.block[idx * 4..idx * 4 + 4].try_into().unwrap()) as u64
+        };
+
+        let old = word(0);
+
+        if old != 0 {
+            CharDevDescriptor {
+                major: (old >> 8) & 0xff,
+                minor: old & 0xff,
+            }
+        } else {
+            let new = word(1);
+
+            CharDevDescriptor {
+                major: (new & 0xfff00) >> 8,
+                minor: (new & 0xff) | ((new >> 12) & 0xfff00),
+            }
+        }
+    }
+
+    /// Returns whether a symlink with `blocks` 512-byte sectors allocated
+    /// stores its target inline in `i_block`.
+    ///
+    /// This mirrors Linux: the inode's own sector count is the authority, not
+    /// the target length, since a short target may still have been written to
+    /// a data block by another implementation.
+    pub fn is_fast_symlink(&self, size: u64, blocks: u64, block_size: u64) -> bool {
+        let flags = self.flags;
+
+        if flags & EXT4_EA_INODE_FL != 0 {
+            return size != 0 && size < I_BLOCK_LEN as u64;
+        }
+
+        if flags & EXT4_INLINE_DATA_FL != 0 {
+            return false;
+        }
+
+        let file_acl = self.file_acl_lo as u64 | ((self.file_acl_hi as u64) << 32);
+        let ea_blocks = if file_acl != 0 { block_size >> 9 } else { 0 };
+
+        blocks.saturating_sub(ea_blocks) == 0
+    }
+}
+
+#[repr(C, packed)]
+struct RawExtentHeader {
+    magic: u16,
+    entries: u16,
+    max: u16,
+    depth: u16,
+    _generation: u32,
+}
+
+unsafe impl Pod for RawExtentHeader {}
+
+/// A leaf entry of an extent tree.
+#[repr(C, packed)]
+struct RawExtent {
+    block: u32,
+    len: u16,
+    start_hi: u16,
+    start_lo: u32,
+}
+
+unsafe impl Pod for RawExtent {}
+
+/// An interior entry of an extent tree.
+#[repr(C, packed)]
+struct RawExtentIdx {
+    block: u32,
+    leaf_lo: u32,
+    leaf_hi: u16,
+    _unused: u16,
+}
+
+unsafe impl Pod for RawExtentIdx {}
+
+/// A run of contiguous blocks in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    /// First file block covered.
+    pub logical: u32,
+    /// Filesystem block backing `logical`.
+    pub physical: u64,
+    /// Number of blocks covered.
+    pub len: u32,
+    /// Whether the blocks are allocated but not yet written, and so read as
+    /// zeroes.
+    pub unwritten: bool,
+}
+
+impl Extent {
+    /// Maps `block` to a filesystem block if it is covered by this extent.
+    pub fn map(&self, block: u32) -> Option<u64> {
+        let offset = block.checked_sub(self.logical)?;
+
+        (offset < self.len).then(|| self.physical + offset as u64)
+    }
+}
+
+/// Returns whether the directory indexes on `dev` were built by hashing names
+/// as unsigned chars.
+pub async fn unsigned_dir_hash(dev: &JournaledDev) -> Result<bool> {
+    let sb: RawSuperblock = dev.read_obj(SUPERBLOCK_OFFSET).await?;
+    let flags = sb.flags;
+
+    Ok(flags & EXT2_FLAGS_UNSIGNED_HASH != 0)
+}
+
+/// Returns the seed that directory index hashes on `dev` are keyed with.
+pub async fn dir_hash_seed(dev: &JournaledDev) -> Result<[u32; 4]> {
+    let sb: RawSuperblock = dev.read_obj(SUPERBLOCK_OFFSET).await?;
+    let seed = sb.hash_seed;
+
+    Ok(core::array::from_fn(|i| {
+        u32::from_le_bytes(seed[i * 4..i * 4 + 4].try_into().unwrap())
+    }))
+}
+
+/// The on-disk format of a volume, from the features it uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// ext4, or an ext2/ext3 volume converted to use extents.
+    Ext4,
+    /// ext2 or ext3: files are block-mapped and block numbers are 32 bits.
+    /// We can read these, but writing through `ext4plus` would give new
+    /// files extents the volume doesn't allow.
+    Ext2,
+}
+
+/// Works out the format of the volume on `dev`.
+pub async fn format(dev: &JournaledDev) -> Result<Format> {
+    let sb: RawSuperblock = dev.read_obj(SUPERBLOCK_OFFSET).await?;
+    let incompat = if sb.rev_level == EXT4_GOOD_OLD_REV {
+        0
+    } else {
+        sb.feature_incompat
+    };
+
+    // `ext4plus` parses directory entries as carrying the file type, which
+    // every mke2fs since 1.x has enabled.
+    if incompat & EXT4_FEATURE_INCOMPAT_FILETYPE == 0 {
+        warn!("ext4: volume doesn't record file types in directories, can't mount it");
+        return Err(KernelError::NotSupported);
+    }
+
+    if incompat & (EXT4_FEATURE_INCOMPAT_EXTENTS | EXT4_FEATURE_INCOMPAT_64BIT) == 0 {
+        Ok(Format::Ext2)
+    } else {
+        Ok(Format::Ext4)
+    }
+}
+
+/// Returns the number of blocks on `dev` kept back for the superuser.
+pub async fn reserved_blocks(dev: &JournaledDev) -> Result<u64> {
+    let sb: RawSuperblock = dev.read_obj(SUPERBLOCK_OFFSET).await?;
+    let hi = if sb.feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+        sb.r_blocks_count_hi
+    } else {
+        0
+    };
+
+    Ok((hi as u64) << 32 | sb.r_blocks_count_lo as u64)
+}
+
+/// Where inodes live on disk, as described by the superblock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeLayout {
+    /// Filesystem block size in bytes.
+    pub block_size: u64,
+    first_data_block: u64,
+    inodes_per_group: u32,
+    inode_size: u64,
+    desc_size: u64,
+}
+
+impl InodeLayout {
+    /// Reads the inode layout from the superblock of `dev`.
+    pub async fn read(dev: &JournaledDev) -> Result<Self> {
+        let sb: RawSuperblock = dev.read_obj(SUPERBLOCK_OFFSET).await?;
+
+        if sb.magic != EXT4_MAGIC {
+            warn!("Not an ext4 volume (bad superblock magic)");
+            return Err(FsError::InvalidFs.into());
+        }
+
+        if sb.inodes_per_group == 0 || sb.log_block_size > 6 {
+            return Err(FsError::InvalidFs.into());
+        }
+
+        let inode_size = if sb.rev_level == EXT4_GOOD_OLD_REV {
+            EXT4_GOOD_OLD_INODE_SIZE
+        } else {
+            sb.inode_size
+        };
+
+        let desc_size = if sb.feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+            sb.desc_size.max(EXT4_MIN_DESC_SIZE)
+        } else {
+            EXT4_MIN_DESC_SIZE
+        };
+
+        Ok(Self {
+            block_size: 1024 << sb.log_block_size,
+            first_data_block: sb.first_data_block as u64,
+            inodes_per_group: sb.inodes_per_group,
+            inode_size: inode_size as u64,
+            desc_size: desc_size as u64,
+        })
+    }
+
+    /// Reads the on-disk inode numbered `ino`.
+    pub async fn read_inode(&self, dev: &JournaledDev, ino: NonZeroU32) -> Result<RawInode> {
+        let idx = ino.get() - 1;
+        let group = (idx / self.inodes_per_group) as u64;
+        let idx_in_group = (idx % self.inodes_per_group) as u64;
+
+        // The group descriptor table starts in the block following the
+        // superblock.
+        let gdt = (self.first_data_block + 1) * self.block_size;
+        let desc: RawGroupDesc = dev.read_obj(gdt + group * self.desc_size).await?;
+
+        let mut inode_table = desc.inode_table_lo as u64;
+
+        if self.desc_size >= EXT4_MIN_DESC_SIZE_64BIT as u64 {
+            inode_table |= (desc.inode_table_hi as u64) << 32;
+        }
+
+        dev.read_obj(inode_table * self.block_size + idx_in_group * self.inode_size)
+            .await
+    }
+
+    /// Returns the runs of blocks backing `inode`, in logical order, whether
+    /// it's mapped by an extent tree or by block pointers.
+    pub async fn read_mapping(&self, dev: &JournaledDev, inode: &RawInode) -> Result<Vec<Extent>> {
+        if inode.uses_extents() {
+            self.read_extents(dev, inode).await
+        } else {
+            self.read_block_map(dev, inode).await
+        }
+    }
+
+    /// Returns the runs of blocks backing the block-mapped `inode`, in
+    /// logical order. Holes are left out.
+    pub async fn read_block_map(
+        &self,
+        dev: &JournaledDev,
+        inode: &RawInode,
+    ) -> Result<Vec<Extent>> {
+        if inode.uses_extents() || inode.has_inline_data() {
+            return Err(FsError::InvalidFs.into());
+        }
+
+        let i_block = inode.i_block();
+        let ptrs = parse_block_ptrs(&i_block);
+        let per_block = self.block_size / 4;
+
+        // Every mapped block, as (logical, physical).
+        let mut blocks: Vec<(u64, u64)> = ptrs[..EXT2_NDIR_BLOCKS]
+            .iter()
+            .enumerate()
+            .filter(|(_, ptr)| **ptr != 0)
+            .map(|(i, ptr)| (i as u64, *ptr as u64))
+            .collect();
+
+        // Indirect blocks still to read, as (block, levels of indirection
+        // below it, first logical block it maps).
+        let mut pending = Vec::new();
+        let mut logical = EXT2_NDIR_BLOCKS as u64;
+
+        for (level, &ptr) in ptrs[EXT2_NDIR_BLOCKS..].iter().enumerate() {
+            if ptr != 0 {
+                pending.push((ptr as u64, level as u32, logical));
+            }
+
+            logical += per_block.pow(level as u32 + 1);
+        }
+
+        let mut buf = vec![0; self.block_size as usize];
+
+        while let Some((block, level, first)) = pending.pop() {
+            dev.read_at(block * self.block_size, &mut buf).await?;
+
+            let span = per_block.pow(level);
+
+            for (i, ptr) in parse_block_ptrs(&buf).into_iter().enumerate() {
+                if ptr == 0 {
+                    continue;
+                }
+
+                let logical = first + i as u64 * span;
+
+                if level == 0 {
+                    blocks.push((logical, ptr as u64));
+                } else {
+                    pending.push((ptr as u64, level - 1, logical));
+                }
+            }
+        }
+
+        blocks.sort_unstable();
+
+        let mut extents: Vec<Extent> = Vec::new();
+
+        for (logical, physical) in blocks {
+            let logical = u32::try_from(logical).map_err(|_| FsError::InvalidFs)?;
+
+            match extents.last_mut() {
+                Some(ext)
+                    if ext.logical as u64 + ext.len as u64 == logical as u64
+                        && ext.physical + ext.len as u64 == physical =>
+                {
+                    ext.len += 1;
+                }
+                _ => extents.push(Extent {
+                    logical,
+                    physical,
+                    len: 1,
+                    unwritten: false,
+                }),
+            }
+        }
+
+        Ok(extents)
+    }
+
+    /// Returns the extents of an extent-mapped `inode`, in logical order.
+    pub async fn read_extents(&self, dev: &JournaledDev, inode: &RawInode) -> Result<Vec<Extent>> {
+        if !inode.uses_extents() {
+            return Err(FsError::InvalidFs.into());
+        }
+
+        let mut extents = Vec::new();
+        let mut pending = vec![(inode.i_block().to_vec(), EXT4_MAX_EXTENT_DEPTH + 1)];
+        let mut node_buf = vec![0; self.block_size as usize];
+
+        while let Some((node, depth_limit)) = pending.pop() {
+            match parse_extent_node(&node, depth_limit)? {
+                ExtentNode::Leaf(leaf) => extents.extend(leaf),
+                ExtentNode::Index { depth, children } => {
+                    for child in children {
+                        dev.read_at(child * self.block_size, &mut node_buf).await?;
+                        pending.push((node_buf.clone(), depth));
+                    }
+                }
+            }
+        }
+
+        extents.sort_by_key(|ext| ext.logical);
+
+        // Leaves are checked one at a time, so one may still overlap another.
+        if extents
+            .windows(2)
+            .any(|w| w[0].logical as u64 + w[0].len as u64 > w[1].logical as u64)
+        {
+            warn!("ext4: overlapping extents");
+            return Err(FsError::InvalidFs.into());
+        }
+
+        Ok(extents)
+    }
+}
+
+/// Parses an array of little-endian block pointers.
+fn parse_block_ptrs(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .as_chunks::<4>()
+        .0
+        .iter()
+        .map(|ptr| u32::from_le_bytes(*ptr))
+        .collect()
+}
+
+/// One node of an extent tree, as parsed by [`parse_extent_node`].
+#[derive(Debug, PartialEq, Eq)]
+enum ExtentNode {
+    /// A leaf, with the extents it holds.
+    Leaf(Vec<Extent>),
+    /// An interior node at `depth`, with the blocks holding its children.
+    Index { depth: u16, children: Vec<u64> },
+}
+
+/// Parses one node of an extent tree, either the root in `i_block` or a block
+/// it points to. The node's depth must be below `depth_limit`, which is its
+/// parent's depth, so that a corrupt tree can't loop.
+fn parse_extent_node(node: &[u8], depth_limit: u16) -> Result<ExtentNode> {
+    let corrupt = |what| {
+        warn!("ext4: corrupt extent tree node: {what}");
+        Err(FsError::InvalidFs.into())
+    };
+
+    if node.len() < size_of::<RawExtentHeader>() {
+        return corrupt("truncated header");
+    }
+
+    let header: RawExtentHeader = from_bytes(node);
+    let (magic, entries, max, depth) = (
+        header.magic,
+        header.entries as usize,
+        header.max as usize,
+        header.depth,
+    );
+
+    if magic != EXT4_EXT_MAGIC {
+        return corrupt("bad magic");
+    }
+
+    // Each level must be strictly shallower than its parent.
+    if depth >= depth_limit {
+        return corrupt("bad depth");
+    }
+
+    if entries > max || size_of::<RawExtentHeader>() * (max + 1) > node.len() {
+        return corrupt("too many entries");
+    }
+
+    let entries = node[size_of::<RawExtentHeader>()..]
+        .as_chunks::<{ size_of::<RawExtent>() }>()
+        .0
+        .iter()
+        .take(entries);
+
+    // Entries are sorted by the first block they cover, and only leaves can
+    // tell whether they overlap.
+    let mut next_block = 0u64;
+
+    if depth == 0 {
+        let mut extents = Vec::new();
+
+        for entry in entries {
+            let ext: RawExtent = from_bytes(entry);
+            let unwritten = ext.len > EXT_INIT_MAX_LEN;
+            let len = if unwritten {
+                ext.len - EXT_INIT_MAX_LEN
+            } else {
+                ext.len
+            };
+            let physical = ext.start_lo as u64 | ((ext.start_hi as u64) << 32);
+            let end = ext.block as u64 + len as u64;
+
+            if len == 0 || physical == 0 || end > u32::MAX as u64 + 1 {
+                return corrupt("bad extent");
+            }
+
+            if (ext.block as u64) < next_block {
+                return corrupt("extents out of order");
+            }
+
+            next_block = end;
+            extents.push(Extent {
+                logical: ext.block,
+                physical,
+                len: len as u32,
+                unwritten,
+            });
+        }
+
+        Ok(ExtentNode::Leaf(extents))
+    } else {
+        let mut children = Vec::new();
+
+        for entry in entries {
+            let idx: RawExtentIdx = from_bytes(entry);
+            let leaf = idx.leaf_lo as u64 | ((idx.leaf_hi as u64) << 32);
+
+            if leaf == 0 {
+                return corrupt("bad index");
+            }
+
+            if (idx.block as u64) < next_block {
+                return corrupt("index out of order");
+            }
+
+            next_block = idx.block as u64 + 1;
+            children.push(leaf);
+        }
+
+        Ok(ExtentNode::Index { depth, children })
+    }
+}
+
+/// Copies a `Pod` value out of the start of `bytes`.
+pub fn from_bytes<T: Pod>(bytes: &[u8]) -> T {
+    assert!(bytes.len() >= size_of::<T>());
+
+    // SAFETY: `T` is `Pod` and `bytes` holds at least `size_of::<T>()` bytes.
+    unsafe { core::ptr::read_unaligned(bytes.as_ptr().cast()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::size_of;
+
+    fn inode_with(block: [u32; 2], flags: u32, file_acl: u32) -> RawInode {
+        let mut raw = [0u8; size_of::<RawInode>()];
+        raw[0x20..0x24].copy_from_slice(&flags.to_le_bytes());
+        raw[0x28..0x2c].copy_from_slice(&block[0].to_le_bytes());
+        raw[0x2c..0x30].copy_from_slice(&block[1].to_le_bytes());
+        raw[0x68..0x6c].copy_from_slice(&file_acl.to_le_bytes());
+
+        from_bytes(&raw)
+    }
+
+    #[test]
+    fn struct_layouts_match_disk_format() {
+        assert_eq!(size_of::<RawSuperblock>(), 0x164);
+        assert_eq!(size_of::<RawGroupDesc>(), 0x40);
+        assert_eq!(size_of::<RawInode>(), 0x80);
+        assert_eq!(size_of::<RawExtentHeader>(), 12);
+        assert_eq!(size_of::<RawExtent>(), 12);
+        assert_eq!(size_of::<RawExtentIdx>(), 12);
+    }
+
+    /// Builds an extent tree node at `depth` holding `entries`, each of which
+    /// is `(first block, len, physical block)`. Interior entries ignore `len`.
+    fn extent_node(depth: u16, max: u16, entries: &[(u32, u16, u64)]) -> Vec<u8> {
+        let mut node = vec![0u8; 12 * (max as usize + 1)];
+        node[0..2].copy_from_slice(&EXT4_EXT_MAGIC.to_le_bytes());
+        node[2..4].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+        node[4..6].copy_from_slice(&max.to_le_bytes());
+        node[6..8].copy_from_slice(&depth.to_le_bytes());
+
+        for (i, &(block, len, physical)) in entries.iter().enumerate() {
+            let e = &mut node[12 * (i + 1)..12 * (i + 2)];
+            e[0..4].copy_from_slice(&block.to_le_bytes());
+
+            if depth == 0 {
+                e[4..6].copy_from_slice(&len.to_le_bytes());
+                e[6..8].copy_from_slice(&((physical >> 32) as u16).to_le_bytes());
+                e[8..12].copy_from_slice(&(physical as u32).to_le_bytes());
+            } else {
+                e[4..8].copy_from_slice(&(physical as u32).to_le_bytes());
+                e[8..10].copy_from_slice(&((physical >> 32) as u16).to_le_bytes());
+            }
+        }
+
+        node
+    }
+
+    #[test]
+    fn parses_extent_leaf() {
+        let node = extent_node(0, 4, &[(0, 12, 100), (12, EXT_INIT_MAX_LEN + 3, 1 << 33)]);
+
+        assert_eq!(
+            parse_extent_node(&node, 1),
+            Ok(ExtentNode::Leaf(vec![
+                Extent {
+                    logical: 0,
+                    physical: 100,
+                    len: 12,
+                    unwritten: false,
+                },
+                Extent {
+                    logical: 12,
+                    physical: 1 << 33,
+                    len: 3,
+                    unwritten: true,
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn parses_extent_index() {
+        let node = extent_node(2, 4, &[(0, 0, 500), (1000, 0, 1 << 40)]);
+
+        assert_eq!(
+            parse_extent_node(&node, EXT4_MAX_EXTENT_DEPTH + 1),
+            Ok(ExtentNode::Index {
+                depth: 2,
+                children: vec![500, 1 << 40],
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_corrupt_extent_nodes() {
+        let corrupt = |node: &[u8], limit| {
+            assert_eq!(
+                parse_extent_node(node, limit),
+                Err(FsError::InvalidFs.into())
+            );
+        };
+
+        let mut bad_magic = extent_node(0, 4, &[(0, 1, 100)]);
+        bad_magic[0] = 0;
+        corrupt(&bad_magic, 1);
+
+        // A child as deep as its parent would let the tree loop.
+        corrupt(&extent_node(1, 4, &[(0, 0, 100)]), 1);
+
+        let mut too_many = extent_node(0, 4, &[(0, 1, 100)]);
+        too_many[2..4].copy_from_slice(&5u16.to_le_bytes());
+        corrupt(&too_many, 1);
+        corrupt(&extent_node(0, 4, &[(0, 1, 100)])[..24], 1);
+
+        corrupt(&extent_node(0, 4, &[(0, 0, 100)]), 1);
+        corrupt(&extent_node(0, 4, &[(0, 1, 0)]), 1);
+        corrupt(&extent_node(0, 4, &[(u32::MAX, 2, 100)]), 1);
+        corrupt(&extent_node(0, 4, &[(0, 8, 100), (4, 8, 200)]), 1);
+        corrupt(&extent_node(1, 4, &[(0, 0, 0)]), 2);
+        corrupt(&extent_node(1, 4, &[(8, 0, 100), (4, 0, 200)]), 2);
+    }
+
+    #[test]
+    fn decodes_old_device_encoding() {
+        // /dev/null: 1:3
+        let dev = inode_with([0x0103, 0], 0, 0).device();
+        assert_eq!(dev, CharDevDescriptor { major: 1, minor: 3 });
+    }
+
+    #[test]
+    fn decodes_new_device_encoding() {
+        // 259:300 doesn't fit the 8:8 encoding.
+        let new = (300 & 0xff) | (259 << 8) | ((300 & !0xff) << 12);
+        let dev = inode_with([0, new], 0, 0).device();
+        assert_eq!(
+            dev,
+            CharDevDescriptor {
+                major: 259,
+                minor: 300
+            }
+        );
+    }
+
+    #[test]
+    fn fast_symlink_detection() {
+        let plain = inode_with([0, 0], 0, 0);
+        assert!(plain.is_fast_symlink(10, 0, 4096));
+        assert!(!plain.is_fast_symlink(10, 8, 4096));
+
+        // A fast symlink with an xattr block (e.g. a security label) still
+        // reports the xattr block's sectors.
+        let with_xattr = inode_with([0, 0], 0, 1234);
+        assert!(with_xattr.is_fast_symlink(10, 8, 4096));
+        assert!(!with_xattr.is_fast_symlink(10, 16, 4096));
+
+        let inline = inode_with([0, 0], EXT4_INLINE_DATA_FL, 0);
+        assert!(!inline.is_fast_symlink(10, 0, 4096));
+    }
+
+    #[tokio::test]
+    async fn maps_indirect_blocks() {
+        use crate::fs::blk::buffer::BlockBuffer;
+        use crate::test::MockBlockDevice;
+        use alloc::{boxed::Box, sync::Arc};
+
+        const BS: usize = 1024;
+
+        let mut data = vec![0u8; 320 * BS];
+        let mut put = |block: usize, idx: usize, ptr: u32| {
+            let at = block * BS + idx * 4;
+            data[at..at + 4].copy_from_slice(&ptr.to_le_bytes());
+        };
+
+        // Block 200 is the single indirect block, mapping blocks 12 and 13.
+        put(200, 0, 112);
+        put(200, 1, 113);
+
+        // Block 201 is the double indirect block. Its second entry covers
+        // blocks 524 onwards.
+        put(201, 1, 202);
+        put(202, 0, 300);
+        put(202, 1, 301);
+
+        let mut raw = [0u8; size_of::<RawInode>()];
+        for (idx, ptr) in [
+            (0, 100),
+            (1, 101),
+            (3, 103),
+            (11, 111),
+            (12, 200),
+            (13, 201),
+        ] {
+            raw[0x28 + idx * 4..0x2c + idx * 4].copy_from_slice(&(ptr as u32).to_le_bytes());
+        }
+        let inode: RawInode = from_bytes(&raw);
+
+        let dev = JournaledDev::new(Arc::new(BlockBuffer::new(Box::new(MockBlockDevice::new(
+            data, BS,
+        )))));
+        let layout = InodeLayout {
+            block_size: BS as u64,
+            first_data_block: 1,
+            inodes_per_group: 8,
+            inode_size: 128,
+            desc_size: 32,
+        };
+
+        let ext = |logical, physical, len| Extent {
+            logical,
+            physical,
+            len,
+            unwritten: false,
+        };
+
+        assert_eq!(
+            layout.read_mapping(&dev, &inode).await.unwrap(),
+            [
+                ext(0, 100, 2),
+                ext(3, 103, 1),
+                // Runs carry on from the direct blocks into the indirect ones.
+                ext(11, 111, 3),
+                ext(524, 300, 2),
+            ]
+        );
+    }
+}
@@ -128,6 +128,17 @@ impl BiosParameterBlock {
         self.bytes_per_sector as _
     }
 
+    /// Returns the number of data clusters on the volume.
+    pub fn cluster_count(&self) -> u32 {
+        let total = if self._total_sectors_16 != 0 {
+            self._total_sectors_16 as u32
+        } else {
+            self._total_sectors_32
+        };
+
+        total.saturating_sub(self.data_region_start().0) / self.sectors_per_cluster as u32
+    }
+
     pub fn cluster_to_sectors(&self, cluster: Cluster) -> Result<impl Iterator<Item = Sector>> {
         if cluster.0 < 2 {
             warn!("Cannot convert sentinel cluster number");
@@ -203,6 +214,19 @@ pub mod test {
         assert_eq!(bpb.data_region_start(), Sector(2032));
     }
 
+    #[test]
+    fn cluster_count() {
+        let mut bpb = create_test_bpb();
+
+        // (10032 - 2032) / 8 sectors per cluster.
+        bpb._total_sectors_32 = 10032;
+        assert_eq!(bpb.cluster_count(), 1000);
+
+        // A partial cluster at the end doesn't count.
+        bpb._total_sectors_32 = 10039;
+        assert_eq!(bpb.cluster_count(), 1000);
+    }
+
     #[test]
     fn fat_region_lookup() {
         let bpb = create_test_bpb();
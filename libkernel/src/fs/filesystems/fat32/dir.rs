@@ -1,22 +1,26 @@
 use core::ptr;
 use core::time::Duration;
 
-use super::{Cluster, Fat32Operations, file::Fat32FileNode, reader::Fat32Reader};
+use super::{
+    Cluster, Fat32Operations, Sector,
+    file::Fat32FileNode,
+    reader::{Fat32Reader, Fat32Writer, collect_chain},
+};
 use crate::{
     error::{FsError, KernelError, Result},
     fs::{
         DirStream, Dirent, FileType, Inode, InodeId,
-        attr::{FileAttr, FilePermissions},
+        attr::{FileAttr, FileOwner, FilePermissions},
     },
 };
-use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec, vec::Vec};
 use async_trait::async_trait;
 use core::any::Any;
 use log::warn;
 
 bitflags::bitflags! {
     #[derive(Clone, Copy, Debug)]
-    struct Fat32Attributes: u8 {
+    pub struct Fat32Attributes: u8 {
         const READ_ONLY    = 0x01;
         const HIDDEN       = 0x02;
         const SYSTEM       = 0x04;
@@ -42,6 +46,51 @@ impl TryFrom<Fat32Attributes> for FileType {
     }
 }
 
+/// The inode number of the root directory, which has no entry of its own.
+const ROOT_INODE_ID: u64 = 1;
+
+/// The most entries a directory can hold.
+const MAX_ENTRIES: u32 = 65536;
+
+/// The first byte of an entry that has been deleted.
+const DELETED: u8 = 0xE5;
+
+/// Characters allowed in a short name besides letters and digits.
+const SHORT_NAME_SPECIALS: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// Where a directory entry is: the first cluster of the directory holding it,
+/// and the index of its short name entry there.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EntryLoc {
+    pub dir: Cluster,
+    pub index: u32,
+}
+
+impl EntryLoc {
+    /// Returns the inode number of what the entry is for. A directory can't
+    /// hold more than 65536 entries, so the index fits below the cluster.
+    pub fn inode_id(self) -> u64 {
+        ((self.dir.0 as u64) << 16) | self.index as u64
+    }
+
+    /// Returns the sector holding the entry, and where the entry is in it.
+    fn sector<T: Fat32Operations>(self, fs: &T) -> Result<(Sector, usize)> {
+        let pos = self.index as usize * 32;
+        let bpc = fs.bytes_per_cluster();
+        let cluster = fs
+            .iter_clusters(self.dir)
+            .nth(pos / bpc)
+            .transpose()?
+            .ok_or(FsError::NotFound)?;
+        let sector = fs
+            .cluster_to_sectors(cluster)?
+            .nth(pos % bpc / fs.sector_size())
+            .ok_or(FsError::InvalidFs)?;
+
+        Ok((sector, pos % fs.sector_size()))
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(C, packed)]
 struct LfnEntry {
@@ -56,6 +105,19 @@ struct LfnEntry {
 }
 
 impl LfnEntry {
+    fn new(sequence_number: u8, chars: &[u16; 13], checksum: u8) -> Self {
+        Self {
+            sequence_number,
+            name1: chars[0..5].try_into().unwrap(),
+            attributes: 0x0F,
+            entry_type: 0,
+            checksum,
+            name2: chars[5..11].try_into().unwrap(),
+            first_cluster: 0,
+            name3: chars[11..13].try_into().unwrap(),
+        }
+    }
+
     fn extract_chars(&self) -> Vec<u16> {
         let mut chars = Vec::with_capacity(13);
 
@@ -71,11 +133,15 @@ impl LfnEntry {
 
         chars
     }
+
+    fn to_bytes(self) -> [u8; 32] {
+        unsafe { core::mem::transmute(self) }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 #[repr(C, packed)]
-struct DirEntry {
+pub struct DirEntry {
     dos_file_name: [u8; 8],
     dos_extension: [u8; 3],
     attributes: Fat32Attributes,
@@ -92,6 +158,95 @@ struct DirEntry {
 }
 
 impl DirEntry {
+    /// Returns an entry for an empty object with the given short name, the
+    /// name and extension padded with spaces.
+    pub fn new(short_name: [u8; 11], attributes: Fat32Attributes) -> Self {
+        Self {
+            dos_file_name: short_name[..8].try_into().unwrap(),
+            dos_extension: short_name[8..].try_into().unwrap(),
+            attributes,
+            _reserved: 0,
+            ctime_ms: 0,
+            ctime: 0,
+            cdate: 0,
+            adate: 0,
+            clust_high: 0,
+            mtime: 0,
+            mdate: 0,
+            clust_low: 0,
+            size: 0,
+        }
+    }
+
+    fn from_bytes(raw: &[u8; 32]) -> Self {
+        unsafe { ptr::read_unaligned(raw.as_ptr() as *const _) }
+    }
+
+    pub fn to_bytes(self) -> [u8; 32] {
+        unsafe { core::mem::transmute(self) }
+    }
+
+    pub fn short_name(&self) -> [u8; 11] {
+        let mut name = [0; 11];
+
+        name[..8].copy_from_slice(&self.dos_file_name);
+        name[8..].copy_from_slice(&self.dos_extension);
+        name
+    }
+
+    pub fn cluster(&self) -> Cluster {
+        Cluster::from_high_low(self.clust_high, self.clust_low)
+    }
+
+    pub fn set_cluster(&mut self, cluster: Cluster) {
+        self.clust_high = (cluster.0 >> 16) as u16;
+        self.clust_low = cluster.0 as u16;
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn set_size(&mut self, size: u32) {
+        self.size = size;
+    }
+
+    /// Returns the attributes of what the entry is for, which has inode `id`.
+    pub fn attr(&self, id: InodeId) -> Result<FileAttr> {
+        Ok(FileAttr {
+            id,
+            size: self.size as u64,
+            file_type: FileType::try_from(self.attributes)?,
+            permissions: FilePermissions::from_bits_retain(0o755),
+            atime: fat_date_to_duration(self.adate),
+            mtime: fat_datetime_to_duration(self.mdate, self.mtime, 0),
+            ctime: fat_datetime_to_duration(self.cdate, self.ctime, self.ctime_ms),
+            ..Default::default()
+        })
+    }
+
+    /// Stamps the entry as created, and last modified and accessed, at `time`.
+    fn set_created(&mut self, time: Duration) {
+        let (date, time, csecs) = duration_to_fat_datetime(time);
+
+        self.cdate = date;
+        self.ctime = time;
+        self.ctime_ms = csecs;
+        self.mdate = date;
+        self.mtime = time;
+        self.adate = date;
+    }
+
+    /// Sets the access and modification times. FAT only keeps the date of the
+    /// last access.
+    pub fn set_times(&mut self, atime: Duration, mtime: Duration) {
+        let (mdate, mtime, _) = duration_to_fat_datetime(mtime);
+
+        self.adate = duration_to_fat_datetime(atime).0;
+        self.mdate = mdate;
+        self.mtime = mtime;
+    }
+
     pub fn parse_filename(&self) -> String {
         let name_part = self
             .dos_file_name
@@ -117,17 +272,210 @@ impl DirEntry {
     }
 }
 
+/// Computes the checksum of a short name that its long name entries carry.
+fn checksum_83(short_name: &[u8; 11]) -> u8 {
+    let mut sum: u8 = 0;
+    for &byte in short_name {
+        sum = (sum >> 1) | ((sum & 1) << 7); // Rotate right
+        sum = sum.wrapping_add(byte);
+    }
+    sum
+}
+
+/// Checks that `name` can be given to a file, returning it in UTF-16, as long
+/// names are stored.
+fn long_name(name: &str) -> Result<Vec<u16>> {
+    if name.is_empty()
+        || name.ends_with(['.', ' '])
+        || name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+    {
+        return Err(FsError::InvalidInput.into());
+    }
+
+    let utf16: Vec<u16> = name.encode_utf16().collect();
+
+    if utf16.len() > 255 {
+        return Err(KernelError::NameTooLong);
+    }
+
+    Ok(utf16)
+}
+
+/// Returns the long name entries for `name`, in the order they're stored in,
+/// which starts with the end of the name.
+fn long_name_entries(name: &[u16], checksum: u8) -> Vec<[u8; 32]> {
+    let count = name.len().div_ceil(13);
+
+    (0..count)
+        .rev()
+        .map(|i| {
+            let part = &name[i * 13..name.len().min((i + 1) * 13)];
+            let mut chars = [0xFFFF; 13];
+
+            chars[..part.len()].copy_from_slice(part);
+
+            // The name is only terminated if it doesn't fill the entry.
+            if part.len() < 13 {
+                chars[part.len()] = 0;
+            }
+
+            let mut sequence_number = (i + 1) as u8;
+
+            if i == count - 1 {
+                sequence_number |= 0x40;
+            }
+
+            LfnEntry::new(sequence_number, &chars, checksum).to_bytes()
+        })
+        .collect()
+}
+
+fn is_short_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || (c.is_ascii() && SHORT_NAME_SPECIALS.contains(&(c as u8)))
+}
+
+/// Returns the short name that `name` can be stored as without a long name,
+/// if it's a valid 8.3 name. Short names read back in lower case, so names
+/// with capitals in them don't qualify.
+fn plain_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+
+    if base.is_empty()
+        || base.len() > 8
+        || ext.len() > 3
+        || !base
+            .chars()
+            .chain(ext.chars())
+            .all(|c| is_short_name_char(c) && !c.is_ascii_uppercase())
+    {
+        return None;
+    }
+
+    let mut short_name = [b' '; 11];
+
+    short_name[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+    short_name[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+
+    Some(short_name)
+}
+
+/// Makes up a short name to go with the long name `name`, of the form
+/// `BASIS~N.EXT`, that isn't one of `taken`.
+fn alias_short_name(name: &str, taken: &[[u8; 11]]) -> Result<[u8; 11]> {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+
+    let clean = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                if is_short_name_char(c) {
+                    c.to_ascii_uppercase() as u8
+                } else {
+                    b'_'
+                }
+            })
+            .collect()
+    };
+
+    let basis = clean(base);
+    let ext = clean(ext);
+    let ext_len = ext.len().min(3);
+    let mut short_name = [b' '; 11];
+
+    short_name[8..8 + ext_len].copy_from_slice(&ext[..ext_len]);
+
+    for n in 1..1_000_000 {
+        let tail = format!("~{n}");
+        let len = basis.len().min(8 - tail.len());
+
+        short_name[..8].fill(b' ');
+        short_name[..len].copy_from_slice(&basis[..len]);
+        short_name[len..len + tail.len()].copy_from_slice(tail.as_bytes());
+
+        if !taken.contains(&short_name) {
+            return Ok(short_name);
+        }
+    }
+
+    Err(FsError::NoSpace.into())
+}
+
+pub async fn read_entry<T: Fat32Operations>(fs: &T, loc: EntryLoc) -> Result<DirEntry> {
+    let (sector, offset) = loc.sector(fs)?;
+    let mut raw = [0; 32];
+
+    fs.read_sector(sector, offset, &mut raw).await?;
+
+    Ok(DirEntry::from_bytes(&raw))
+}
+
+/// Writes `raw` over the start of the entry at `loc`.
+pub async fn write_entry<T: Fat32Operations>(fs: &T, loc: EntryLoc, raw: &[u8]) -> Result<()> {
+    debug_assert!(raw.len() <= 32);
+
+    let (sector, offset) = loc.sector(fs)?;
+
+    fs.write_sector(sector, offset, raw).await?;
+
+    Ok(())
+}
+
+/// Finds the entry for the directory starting at `dir`, which isn't the root,
+/// in its parent.
+async fn find_dir_entry<T: Fat32Operations>(fs: &Arc<T>, dir: Cluster) -> Result<EntryLoc> {
+    let dotdot = read_entry(&**fs, EntryLoc { dir, index: 1 }).await?;
+
+    // A `..` entry refers to the root as cluster 0.
+    let parent = match dotdot.cluster() {
+        cluster if cluster.is_valid() => cluster,
+        _ => fs.root_cluster(),
+    };
+
+    let mut stream = Fat32DirStream::new(fs.clone(), parent);
+
+    while let Some(entry) = stream.next_fat32_entry().await? {
+        if entry.cluster == dir
+            && entry.attr.file_type == FileType::Directory
+            && entry.name != "."
+            && entry.name != ".."
+        {
+            return Ok(EntryLoc {
+                dir: parent,
+                index: entry.index,
+            });
+        }
+    }
+
+    warn!("FAT32 directory at cluster {dir} is missing from its parent.");
+    Err(FsError::InvalidFs.into())
+}
+
 struct Fat32DirEntry {
     attr: FileAttr,
     cluster: Cluster,
     name: String,
     offset: u64,
+    /// The index of the short name entry.
+    index: u32,
+    /// The index of the first entry, which is the start of the long name if
+    /// there is one.
+    first: u32,
+    entry: DirEntry,
 }
 
 struct Fat32DirStream<T: Fat32Operations> {
     reader: Fat32Reader<T>,
+    fs: Arc<T>,
+    dir: Cluster,
+    /// Where the directory's own entry is, for the inode numbers of `.` and
+    /// `..`.
+    loc: Option<EntryLoc>,
     offset: u64,
     lfn_buffer: Vec<u16>,
+    lfn_start: u64,
     fs_id: u64,
 }
 
@@ -135,8 +483,12 @@ impl<T: Fat32Operations> Clone for Fat32DirStream<T> {
     fn clone(&self) -> Self {
         Self {
             reader: self.reader.clone(),
+            fs: self.fs.clone(),
+            dir: self.dir,
+            loc: self.loc,
             offset: self.offset,
             lfn_buffer: self.lfn_buffer.clone(),
+            lfn_start: self.lfn_start,
             fs_id: self.fs_id,
         }
     }
@@ -151,9 +503,13 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
         // the number of clusters in the chain such that we never read past the
         // end.
         Self {
-            reader: Fat32Reader::new(fs, root, max_sz),
+            reader: Fat32Reader::new(fs.clone(), root, max_sz),
+            fs,
+            dir: root,
+            loc: None,
             offset: 0,
             lfn_buffer: Vec::new(),
+            lfn_start: 0,
             fs_id,
         }
     }
@@ -171,8 +527,7 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
 
             match entry_bytes[0] {
                 0x00 => return Ok(None), // End of directory, no more entries
-                0xE5 => {
-                    // Deleted entry
+                DELETED => {
                     self.lfn_buffer.clear();
                     self.offset += 1;
                     continue;
@@ -187,6 +542,10 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
                 let lfn_entry: LfnEntry =
                     unsafe { ptr::read_unaligned(entry_bytes.as_ptr() as *const _) };
 
+                if self.lfn_buffer.is_empty() {
+                    self.lfn_start = self.offset;
+                }
+
                 // LFN entries are stored backwards, so we prepend.
                 let new_chars = lfn_entry.extract_chars();
                 self.lfn_buffer.splice(0..0, new_chars);
@@ -195,8 +554,7 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
                 continue;
             }
 
-            let dir_entry: DirEntry =
-                unsafe { ptr::read_unaligned(entry_bytes.as_ptr() as *const _) };
+            let dir_entry = DirEntry::from_bytes(&entry_bytes);
 
             if dir_entry.attributes.contains(Fat32Attributes::VOLUME_LABEL) {
                 self.lfn_buffer.clear();
@@ -204,48 +562,60 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
                 continue;
             }
 
-            let name = if !self.lfn_buffer.is_empty() {
+            let (name, first) = if !self.lfn_buffer.is_empty() {
                 let len = self
                     .lfn_buffer
                     .iter()
                     .position(|&c| c == 0x0000 || c == 0xFFFF)
                     .unwrap_or(self.lfn_buffer.len());
-                String::from_utf16_lossy(&self.lfn_buffer[..len])
+                (
+                    String::from_utf16_lossy(&self.lfn_buffer[..len]),
+                    self.lfn_start,
+                )
             } else {
                 // No LFN, parse the 8.3 name.
-                dir_entry.parse_filename()
+                (dir_entry.parse_filename(), self.offset)
             };
 
             // Process the metadata from the 8.3 entry
-            let file_type = FileType::try_from(dir_entry.attributes)?;
-            let cluster = Cluster::from_high_low(dir_entry.clust_high, dir_entry.clust_low);
-            let attr = FileAttr {
-                size: dir_entry.size as u64,
-                file_type,
-                permissions: FilePermissions::from_bits_retain(0o755),
-                atime: fat_date_to_duration(dir_entry.adate),
-                mtime: fat_datetime_to_duration(dir_entry.mdate, dir_entry.mtime, 0),
-                ctime: fat_datetime_to_duration(
-                    dir_entry.cdate,
-                    dir_entry.ctime,
-                    dir_entry.ctime_ms,
-                ),
-                ..Default::default()
+            let loc = EntryLoc {
+                dir: self.dir,
+                index: self.offset as u32,
             };
+            let attr =
+                dir_entry.attr(InodeId::from_fsid_and_inodeid(self.fs_id, loc.inode_id()))?;
 
             self.lfn_buffer.clear();
             self.offset += 1;
 
             return Ok(Some(Fat32DirEntry {
                 attr,
-                cluster,
+                cluster: dir_entry.cluster(),
                 name,
                 // Note that the offset should be to the *next* entry, so using
                 // the advanced entry is correct.
                 offset: self.offset,
+                index: loc.index,
+                first: first as u32,
+                entry: dir_entry,
             }));
         }
     }
+
+    /// Returns the inode number of the directory being read.
+    fn own_id(&self) -> u64 {
+        self.loc.map_or(ROOT_INODE_ID, EntryLoc::inode_id)
+    }
+
+    /// Returns the inode number of the parent of the directory being read.
+    async fn parent_id(&self) -> Result<u64> {
+        match self.loc {
+            Some(loc) if loc.dir != self.fs.root_cluster() => {
+                Ok(find_dir_entry(&self.fs, loc.dir).await?.inode_id())
+            }
+            _ => Ok(ROOT_INODE_ID),
+        }
+    }
 }
 
 /// Determines if a given year is a leap year
@@ -325,36 +695,247 @@ fn fat_datetime_to_duration(date: u16, time: u16, csecs: u8) -> Duration {
     base + Duration::from_secs(hours * 3600 + minutes * 60 + secs) + Duration::from_millis(millis)
 }
 
+/// Converts a `Duration` since the Unix epoch into FAT (date, time,
+/// centisecond) fields. Times before 1980 can't be represented, and are left
+/// unset.
+fn duration_to_fat_datetime(time: Duration) -> (u16, u16, u8) {
+    const DAYS_OFFSET: u64 = 3652;
+
+    let secs = time.as_secs();
+
+    let Some(mut days) = (secs / 86_400).checked_sub(DAYS_OFFSET) else {
+        return (0, 0, 0);
+    };
+
+    let mut year = 1980;
+
+    while days >= 365 + is_leap_year(year) as u64 {
+        days -= 365 + is_leap_year(year) as u64;
+        year += 1;
+    }
+
+    // The year is kept in seven bits.
+    if year > 2107 {
+        return (0xFF9F, 0xBF7D, 199);
+    }
+
+    let mut month = 1;
+
+    while days >= days_in_month(year, month) as u64 {
+        days -= days_in_month(year, month) as u64;
+        month += 1;
+    }
+
+    let date = ((year - 1980) << 9) | (month << 5) | (days as u32 + 1);
+    let secs_in_day = secs % 86_400;
+    let fat_time =
+        ((secs_in_day / 3600) << 11) | ((secs_in_day % 3600 / 60) << 5) | (secs_in_day % 60 / 2);
+    let csecs = (secs_in_day % 2) * 100 + time.subsec_millis() as u64 / 10;
+
+    (date as u16, fat_time as u16, csecs as u8)
+}
+
 #[async_trait]
 impl<T: Fat32Operations> DirStream for Fat32DirStream<T> {
     async fn next_entry(&mut self) -> Result<Option<Dirent>> {
-        let entry = self.next_fat32_entry().await?;
-
-        Ok(entry.map(|x| Dirent {
-            id: InodeId::from_fsid_and_inodeid(self.fs_id, x.cluster.value() as _),
-            name: x.name.clone(),
-            file_type: x.attr.file_type,
-            offset: x.offset,
+        let Some(entry) = self.next_fat32_entry().await? else {
+            return Ok(None);
+        };
+
+        let id = match entry.name.as_str() {
+            "." => self.own_id(),
+            ".." => self.parent_id().await?,
+            _ => entry.attr.id.inode_id(),
+        };
+
+        Ok(Some(Dirent {
+            id: InodeId::from_fsid_and_inodeid(self.fs_id, id),
+            name: entry.name,
+            file_type: entry.attr.file_type,
+            offset: entry.offset,
         }))
     }
 }
 
 pub struct Fat32DirNode<T: Fat32Operations> {
-    attr: FileAttr,
-    root: Cluster,
     fs: Arc<T>,
-    streamer: Fat32DirStream<T>,
+    cluster: Cluster,
+    /// Where the directory's own entry is, which the root doesn't have.
+    loc: Option<EntryLoc>,
 }
 
 impl<T: Fat32Operations> Fat32DirNode<T> {
-    pub fn new(fs: Arc<T>, root: Cluster, attr: FileAttr) -> Self {
-        let streamer = Fat32DirStream::new(fs.clone(), root);
-
+    pub fn root(fs: Arc<T>) -> Self {
         Self {
-            attr,
-            root,
+            cluster: fs.root_cluster(),
+            fs,
+            loc: None,
+        }
+    }
+
+    /// Opens the directory starting at `cluster`.
+    async fn open(fs: Arc<T>, cluster: Cluster) -> Result<Self> {
+        if cluster == fs.root_cluster() {
+            return Ok(Self::root(fs));
+        }
+
+        let loc = find_dir_entry(&fs, cluster).await?;
+
+        Ok(Self {
             fs,
-            streamer,
+            cluster,
+            loc: Some(loc),
+        })
+    }
+
+    fn stream(&self) -> Fat32DirStream<T> {
+        let mut stream = Fat32DirStream::new(self.fs.clone(), self.cluster);
+
+        stream.loc = self.loc;
+        stream
+    }
+
+    async fn find(&self, name: &str) -> Result<Fat32DirEntry> {
+        let mut dir_iter = self.stream();
+
+        while let Some(entry) = dir_iter.next_fat32_entry().await? {
+            if entry.name.eq_ignore_ascii_case(name) {
+                return Ok(entry);
+            }
+        }
+
+        Err(FsError::NotFound.into())
+    }
+
+    /// Finds the first run of `count` free entries, which may go past the end
+    /// of the directory, and returns where it starts.
+    async fn free_entries(&self, count: u32) -> Result<u32> {
+        let chain = collect_chain(&*self.fs, self.cluster)?;
+        let len = chain.len() * self.fs.bytes_per_cluster();
+        let mut data = vec![0; len];
+
+        Fat32Reader::new(self.fs.clone(), self.cluster, len as u64)
+            .read_at(0, &mut data)
+            .await?;
+
+        let mut run_start = 0;
+        let mut run_len = 0;
+
+        for (index, raw) in data.as_chunks::<32>().0.iter().enumerate() {
+            match raw[0] {
+                // Every entry from here on is free.
+                0x00 if run_len > 0 => return Ok(run_start),
+                0x00 => return Ok(index as u32),
+                DELETED => {
+                    if run_len == 0 {
+                        run_start = index as u32;
+                    }
+
+                    run_len += 1;
+
+                    if run_len == count {
+                        return Ok(run_start);
+                    }
+                }
+                _ => run_len = 0,
+            }
+        }
+
+        if run_len > 0 {
+            Ok(run_start)
+        } else {
+            Ok((len / 32) as u32)
+        }
+    }
+
+    /// Writes `entries` to consecutive free entries, growing the directory if
+    /// need be, and returns the index of the last of them.
+    async fn add_entries(&self, entries: &[[u8; 32]]) -> Result<u32> {
+        let start = self.free_entries(entries.len() as u32).await?;
+        let end = start + entries.len() as u32;
+
+        if end > MAX_ENTRIES {
+            return Err(FsError::NoSpace.into());
+        }
+
+        let chain = collect_chain(&*self.fs, self.cluster)?;
+        let bpc = self.fs.bytes_per_cluster();
+        let needed = (end as usize * 32).div_ceil(bpc);
+
+        if needed > chain.len() {
+            let first = self
+                .fs
+                .alloc_clusters(chain.last().copied(), needed - chain.len())
+                .await?;
+            let added = collect_chain(&*self.fs, first)?;
+
+            // Entries past the end of a directory must read as free.
+            Fat32Writer::new(&*self.fs, &added)
+                .zero(0..(added.len() * bpc) as u64)
+                .await?;
+        }
+
+        for (index, raw) in (start..).zip(entries) {
+            let loc = EntryLoc {
+                dir: self.cluster,
+                index,
+            };
+
+            write_entry(&*self.fs, loc, raw).await?;
+        }
+
+        Ok(end - 1)
+    }
+
+    /// Fills in the first cluster of a new subdirectory, described by
+    /// `entry`, with its `.` and `..` entries.
+    async fn init_subdir(&self, cluster: Cluster, entry: &DirEntry) -> Result<()> {
+        let chain = [cluster];
+        let writer = Fat32Writer::new(&*self.fs, &chain);
+
+        writer.zero(0..self.fs.bytes_per_cluster() as u64).await?;
+
+        let mut dot = DirEntry {
+            dos_file_name: *b".       ",
+            dos_extension: *b"   ",
+            ..*entry
+        };
+        dot.set_cluster(cluster);
+
+        let mut dotdot = DirEntry {
+            dos_file_name: *b"..      ",
+            ..dot
+        };
+        // The root is referred to as cluster 0.
+        dotdot.set_cluster(if self.loc.is_some() {
+            self.cluster
+        } else {
+            Cluster(0)
+        });
+
+        writer.write_at(0, &dot.to_bytes()).await?;
+        writer.write_at(32, &dotdot.to_bytes()).await
+    }
+
+    fn node_for(&self, entry: &Fat32DirEntry) -> Result<Arc<dyn Inode>> {
+        let loc = EntryLoc {
+            dir: self.cluster,
+            index: entry.index,
+        };
+
+        match entry.attr.file_type {
+            FileType::File => Ok(Arc::new(Fat32FileNode::new(
+                self.fs.clone(),
+                loc,
+                entry.entry,
+            ))),
+            FileType::Directory if entry.cluster.is_valid() => Ok(Arc::new(Self {
+                fs: self.fs.clone(),
+                cluster: entry.cluster,
+                loc: Some(loc),
+            })),
+            FileType::Directory => Err(FsError::InvalidFs.into()),
+            _ => Err(KernelError::NotSupported),
         }
     }
 }
@@ -362,35 +943,153 @@ impl<T: Fat32Operations> Fat32DirNode<T> {
 #[async_trait]
 impl<T: Fat32Operations> Inode for Fat32DirNode<T> {
     fn id(&self) -> InodeId {
-        InodeId::from_fsid_and_inodeid(self.fs.id(), self.root.value() as _)
+        InodeId::from_fsid_and_inodeid(
+            self.fs.id(),
+            self.loc.map_or(ROOT_INODE_ID, EntryLoc::inode_id),
+        )
     }
 
     async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
-        let mut dir_iter = self.streamer.clone();
+        match name {
+            "." => Ok(Arc::new(Self {
+                fs: self.fs.clone(),
+                cluster: self.cluster,
+                loc: self.loc,
+            })),
+            ".." => match self.loc {
+                Some(loc) => Ok(Arc::new(Self::open(self.fs.clone(), loc.dir).await?)),
+                None => Ok(Arc::new(Self::root(self.fs.clone()))),
+            },
+            _ => self.node_for(&self.find(name).await?),
+        }
+    }
+
+    async fn create(
+        &self,
+        name: &str,
+        file_type: FileType,
+        _permissions: FilePermissions,
+        _owner: FileOwner,
+        time: Option<Duration>,
+    ) -> Result<Arc<dyn Inode>> {
+        let attributes = match file_type {
+            FileType::File => Fat32Attributes::ARCHIVE,
+            FileType::Directory => Fat32Attributes::DIRECTORY,
+            // There's nowhere to keep anything else.
+            _ => return Err(KernelError::NotPermitted),
+        };
+
+        let utf16 = long_name(name)?;
+        let _op = self.fs.ops().lock().await;
+
+        let mut taken = Vec::new();
+        let mut dir_iter = self.stream();
 
         while let Some(entry) = dir_iter.next_fat32_entry().await? {
             if entry.name.eq_ignore_ascii_case(name) {
-                return match entry.attr.file_type {
-                    FileType::File => Ok(Arc::new(Fat32FileNode::new(
-                        self.fs.clone(),
-                        entry.cluster,
-                        entry.attr.clone(),
-                    )?)),
-                    FileType::Directory => Ok(Arc::new(Self::new(
-                        self.fs.clone(),
-                        entry.cluster,
-                        entry.attr.clone(),
-                    ))),
-                    _ => Err(KernelError::NotSupported),
-                };
+                return Err(FsError::AlreadyExists.into());
             }
+
+            taken.push(entry.entry.short_name());
         }
 
-        Err(FsError::NotFound.into())
+        let (short_name, mut entries) =
+            match plain_short_name(name).filter(|short_name| !taken.contains(short_name)) {
+                Some(short_name) => (short_name, Vec::new()),
+                None => {
+                    let short_name = alias_short_name(name, &taken)?;
+
+                    (
+                        short_name,
+                        long_name_entries(&utf16, checksum_83(&short_name)),
+                    )
+                }
+            };
+
+        let mut entry = DirEntry::new(short_name, attributes);
+
+        if let Some(time) = time {
+            entry.set_created(time);
+        }
+
+        if file_type == FileType::Directory {
+            let cluster = self.fs.alloc_clusters(None, 1).await?;
+
+            entry.set_cluster(cluster);
+
+            if let Err(e) = self.init_subdir(cluster, &entry).await {
+                self.fs.truncate_chain(cluster, 0).await?;
+                return Err(e);
+            }
+        }
+
+        entries.push(entry.to_bytes());
+
+        let index = match self.add_entries(&entries).await {
+            Ok(index) => index,
+            Err(e) => {
+                if entry.cluster().is_valid() {
+                    self.fs.truncate_chain(entry.cluster(), 0).await?;
+                }
+
+                return Err(e);
+            }
+        };
+
+        let loc = EntryLoc {
+            dir: self.cluster,
+            index,
+        };
+
+        if file_type == FileType::Directory {
+            Ok(Arc::new(Self {
+                fs: self.fs.clone(),
+                cluster: entry.cluster(),
+                loc: Some(loc),
+            }))
+        } else {
+            Ok(Arc::new(Fat32FileNode::new(self.fs.clone(), loc, entry)))
+        }
+    }
+
+    async fn unlink(&self, name: &str) -> Result<()> {
+        if name == "." || name == ".." {
+            return Err(FsError::InvalidInput.into());
+        }
+
+        let _op = self.fs.ops().lock().await;
+        let entry = self.find(name).await?;
+
+        if entry.attr.file_type == FileType::Directory {
+            let mut children = Fat32DirStream::new(self.fs.clone(), entry.cluster);
+
+            while let Some(child) = children.next_fat32_entry().await? {
+                if child.name != "." && child.name != ".." {
+                    return Err(FsError::DirectoryNotEmpty.into());
+                }
+            }
+        }
+
+        // Remove the name before the clusters behind it, so that nothing is
+        // left pointing at free clusters if we're interrupted.
+        for index in entry.first..=entry.index {
+            let loc = EntryLoc {
+                dir: self.cluster,
+                index,
+            };
+
+            write_entry(&*self.fs, loc, &[DELETED]).await?;
+        }
+
+        if entry.cluster.is_valid() {
+            self.fs.truncate_chain(entry.cluster, 0).await?;
+        }
+
+        Ok(())
     }
 
     async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
-        let mut iter = self.streamer.clone();
+        let mut iter = self.stream();
 
         iter.advance(start_offset);
 
@@ -398,7 +1097,29 @@ impl<T: Fat32Operations> Inode for Fat32DirNode<T> {
     }
 
     async fn getattr(&self) -> Result<FileAttr> {
-        Ok(self.attr.clone())
+        match self.loc {
+            Some(loc) => read_entry(&*self.fs, loc).await?.attr(self.id()),
+            None => Ok(FileAttr {
+                id: self.id(),
+                file_type: FileType::Directory,
+                permissions: FilePermissions::from_bits_retain(0o755),
+                ..FileAttr::default()
+            }),
+        }
+    }
+
+    /// Only the access and modification times can be kept, and the root
+    /// directory has nowhere to keep those.
+    async fn setattr(&self, attr: FileAttr) -> Result<()> {
+        let Some(loc) = self.loc else {
+            return Ok(());
+        };
+
+        let _op = self.fs.ops().lock().await;
+        let mut entry = read_entry(&*self.fs, loc).await?;
+
+        entry.set_times(attr.atime, attr.mtime);
+        write_entry(&*self.fs, loc, &entry.to_bytes()).await
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -413,15 +1134,6 @@ mod test {
 
     mod raw_test;
 
-    fn checksum_83(name: &[u8; 8], ext: &[u8; 3]) -> u8 {
-        let mut sum: u8 = 0;
-        for &byte in name.iter().chain(ext.iter()) {
-            sum = (sum >> 1) | ((sum & 1) << 7); // Rotate right
-            sum = sum.wrapping_add(byte);
-        }
-        sum
-    }
-
     /// A builder to easily create 32-byte DirEntry byte arrays for tests.
     struct DirEntryBuilder {
         entry: DirEntry,
@@ -590,10 +1302,7 @@ mod test {
             .cluster(5)
             .build();
 
-        let checksum = checksum_83(
-            &sfn[0..8].try_into().unwrap(),
-            &sfn[8..11].try_into().unwrap(),
-        );
+        let checksum = checksum_83(sfn[..11].try_into().unwrap());
         let lfn = LfnBuilder::new("testfile.txt", checksum).build();
 
         let mut data = Vec::new();
@@ -618,10 +1327,7 @@ mod test {
             .cluster(42)
             .build();
 
-        let checksum = checksum_83(
-            &sfn[0..8].try_into().unwrap(),
-            &sfn[8..11].try_into().unwrap(),
-        );
+        let checksum = checksum_83(sfn[..11].try_into().unwrap());
         let lfn = LfnBuilder::new("a very long filename indeed.log", checksum).build();
 
         let mut data = Vec::new();
@@ -725,10 +1431,7 @@ mod test {
 
         // 2. A deleted LFN entry
         let deleted_sfn = DirEntryBuilder::new("OLDLOG~1", "TMP").build();
-        let deleted_checksum = checksum_83(
-            &deleted_sfn[0..8].try_into().unwrap(),
-            &deleted_sfn[8..11].try_into().unwrap(),
-        );
+        let deleted_checksum = checksum_83(deleted_sfn[..11].try_into().unwrap());
         let mut deleted_lfn_bytes = LfnBuilder::new("old-log-file.tmp", deleted_checksum)
             .build()
             .remove(0);
@@ -740,10 +1443,7 @@ mod test {
             .attributes(Fat32Attributes::ARCHIVE)
             .cluster(4)
             .build();
-        let checksum = checksum_83(
-            &sfn[0..8].try_into().unwrap(),
-            &sfn[8..11].try_into().unwrap(),
-        );
+        let checksum = checksum_83(sfn[..11].try_into().unwrap());
         let lfn = LfnBuilder::new("my notes.md", checksum).build();
         lfn.into_iter().for_each(|e| data.extend_from_slice(&e));
         data.extend_from_slice(&sfn);
@@ -758,4 +1458,54 @@ mod test {
         assert_eq!(entries[1].name, "my notes.md");
         assert_eq!(entries[1].cluster, Cluster(4));
     }
+
+    #[test]
+    fn test_short_names() {
+        assert_eq!(plain_short_name("readme.txt"), Some(*b"README  TXT"));
+        assert_eq!(plain_short_name("efi"), Some(*b"EFI        "));
+        // These all need a long name.
+        assert_eq!(plain_short_name("README.TXT"), None);
+        assert_eq!(plain_short_name("a.b.c"), None);
+        assert_eq!(plain_short_name("longername.txt"), None);
+        assert_eq!(plain_short_name(".hidden"), None);
+
+        assert_eq!(
+            alias_short_name("A Long File Name.text", &[]).unwrap(),
+            *b"ALONGF~1TEX"
+        );
+        assert_eq!(
+            alias_short_name("a long file name.text", &[*b"ALONGF~1TEX"]).unwrap(),
+            *b"ALONGF~2TEX"
+        );
+        assert_eq!(alias_short_name(".bashrc", &[]).unwrap(), *b"BASHRC~1   ");
+        assert_eq!(alias_short_name("日本.txt", &[]).unwrap(), *b"__~1    TXT");
+    }
+
+    #[test]
+    fn test_long_name_entries() {
+        let name: Vec<u16> = "exactly 13 ch".encode_utf16().collect();
+        let entries = long_name_entries(&name, 0x42);
+        let lfn: LfnEntry = unsafe { ptr::read_unaligned(entries[0].as_ptr() as *const _) };
+
+        // A name that fills its last entry isn't terminated.
+        assert_eq!(entries.len(), 1);
+        assert_eq!(lfn.sequence_number, 0x41);
+        assert_eq!(lfn.checksum, 0x42);
+        assert_eq!(lfn.extract_chars(), name);
+    }
+
+    #[test]
+    fn test_datetime_round_trip() {
+        // 2024-02-29 13:45:31.25
+        let time = Duration::from_millis(1_709_214_331_250);
+        let (date, fat_time, csecs) = duration_to_fat_datetime(time);
+
+        assert_eq!(date, (44 << 9) | (2 << 5) | 29);
+        assert_eq!(fat_time, (13 << 11) | (45 << 5) | 15);
+        assert_eq!(csecs, 125);
+        assert_eq!(fat_datetime_to_duration(date, fat_time, csecs), time);
+
+        // FAT can't go back before 1980.
+        assert_eq!(duration_to_fat_datetime(Duration::from_secs(0)), (0, 0, 0));
+    }
 }
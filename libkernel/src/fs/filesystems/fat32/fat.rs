@@ -8,7 +8,7 @@ use alloc::vec::Vec;
 
 use super::{Cluster, bpb::BiosParameterBlock};
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum FatEntry {
     Eoc,
     NextCluster(Cluster),
@@ -30,23 +30,47 @@ impl From<u32> for FatEntry {
     }
 }
 
+impl From<FatEntry> for u32 {
+    /// Returns the low 28 bits of the entry as stored on disk. The top four
+    /// are reserved, and left as they are when the entry is written back.
+    fn from(value: FatEntry) -> Self {
+        match value {
+            FatEntry::Free => 0,
+            FatEntry::Reserved => 1,
+            FatEntry::NextCluster(next) => next.0,
+            FatEntry::Bad => 0xFFFFFF7,
+            FatEntry::Eoc => 0xFFFFFFF,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug)]
 pub struct Fat {
     data: Vec<FatEntry>,
 }
 
-pub struct ClusterChainIterator<'a> {
-    fat: &'a Fat,
+pub struct ClusterChainIterator<F> {
+    lookup: F,
     current_or_next: Option<Cluster>,
 }
 
-impl<'a> Iterator for ClusterChainIterator<'a> {
+impl<F: Fn(Cluster) -> Option<FatEntry>> ClusterChainIterator<F> {
+    /// Walks the chain starting at `root`, getting each entry from `lookup`.
+    pub fn new(lookup: F, root: Cluster) -> Self {
+        Self {
+            lookup,
+            current_or_next: Some(root),
+        }
+    }
+}
+
+impl<F: Fn(Cluster) -> Option<FatEntry>> Iterator for ClusterChainIterator<F> {
     type Item = Result<Cluster>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let cluster_to_return = self.current_or_next?;
 
-        let entry = match self.fat.data.get(cluster_to_return.value()) {
+        let entry = match (self.lookup)(cluster_to_return) {
             Some(entry) => entry,
             None => {
                 self.current_or_next = None;
@@ -59,7 +83,7 @@ impl<'a> Iterator for ClusterChainIterator<'a> {
                 self.current_or_next = None;
             }
             FatEntry::NextCluster(next) => {
-                self.current_or_next = Some(*next);
+                self.current_or_next = Some(next);
             }
             FatEntry::Bad | FatEntry::Reserved | FatEntry::Free => {
                 self.current_or_next = None;
@@ -93,70 +117,69 @@ impl Fat {
                     .0
                     .iter()
                     .map(|chunk| u32::from_le_bytes(*chunk))
-                    .map(|v| v.into()),
+                    .map(FatEntry::from),
             );
         }
 
         Ok(Self { data: fat })
     }
 
+    /// Returns how many of the first `count` data clusters are free.
+    pub fn free_clusters(&self, count: u32) -> u32 {
+        self.data
+            .iter()
+            .skip(2)
+            .take(count as usize)
+            .filter(|entry| **entry == FatEntry::Free)
+            .count() as u32
+    }
+
+    /// Returns the entry for `cluster`.
+    pub fn get(&self, cluster: Cluster) -> Option<FatEntry> {
+        self.data.get(cluster.value()).copied()
+    }
+
+    /// Sets the entry for `cluster`, which must be on the volume.
+    pub fn set(&mut self, cluster: Cluster, entry: FatEntry) {
+        self.data[cluster.value()] = entry;
+    }
+
+    /// Finds a free cluster among the first `count` data clusters, searching
+    /// upwards from `hint` and wrapping around to the start.
+    pub fn find_free(&self, hint: Cluster, count: u32) -> Option<Cluster> {
+        let end = (count as usize + 2).min(self.data.len());
+        let hint = hint.value().clamp(2, end);
+
+        (hint..end)
+            .chain(2..hint)
+            .find(|&idx| self.data[idx] == FatEntry::Free)
+            .map(|idx| Cluster(idx as u32))
+    }
+
     pub fn get_cluster_chain(&self, root: Cluster) -> impl Iterator<Item = Result<Cluster>> {
-        ClusterChainIterator {
-            fat: self,
-            current_or_next: Some(root),
-        }
+        ClusterChainIterator::new(|cluster| self.get(cluster), root)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::error::{IoError, KernelError, Result};
+    use crate::error::{IoError, KernelError};
+    use crate::fs::blk::buffer::BlockBuffer;
     use crate::fs::filesystems::fat32::Cluster;
     use crate::fs::filesystems::fat32::bpb::test::create_test_bpb;
     use crate::fs::filesystems::fat32::fat::{Fat, FatEntry};
-    use crate::fs::{BlockDevice, blk::buffer::BlockBuffer};
-    use async_trait::async_trait;
+    use crate::test::MockBlockDevice;
 
     const EOC: u32 = 0xFFFFFFFF;
     const BAD: u32 = 0xFFFFFFF7;
     const FREE: u32 = 0;
     const RESERVED: u32 = 1;
 
-    struct MemBlkDevice {
-        data: Vec<u8>,
-    }
-
-    #[async_trait]
-    impl BlockDevice for MemBlkDevice {
-        /// Read one or more blocks starting at `block_id`.
-        /// The `buf` length must be a multiple of `block_size`.
-        async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
-            buf.copy_from_slice(&self.data[block_id as usize..block_id as usize + buf.len()]);
-            Ok(())
-        }
-
-        /// Write one or more blocks starting at `block_id`.
-        /// The `buf` length must be a multiple of `block_size`.
-        async fn write(&self, _block_id: u64, _buf: &[u8]) -> Result<()> {
-            unimplemented!()
-        }
-
-        /// The size of a single block in bytes.
-        fn block_size(&self) -> usize {
-            1
-        }
-
-        /// Flushes any caches to the underlying device.
-        async fn sync(&self) -> Result<()> {
-            unimplemented!()
-        }
-    }
-
     fn setup_fat_test(fat_data: &[u32]) -> BlockBuffer {
         let mut data = Vec::new();
         data.extend(fat_data.iter().flat_map(|x| x.to_le_bytes()));
 
-        BlockBuffer::new(Box::new(MemBlkDevice { data }))
+        BlockBuffer::new(Box::new(MockBlockDevice::new(data, 1)))
     }
 
     #[tokio::test]
@@ -337,4 +360,48 @@ mod test {
             Err(KernelError::Io(IoError::OutOfBounds))
         ));
     }
+
+    #[test]
+    fn test_find_free() {
+        let fat = Fat {
+            data: [FREE, RESERVED, EOC, FREE, EOC, FREE, FREE]
+                .into_iter()
+                .map(FatEntry::from)
+                .collect(),
+        };
+
+        assert_eq!(fat.find_free(Cluster(2), 5), Some(Cluster(3)));
+        assert_eq!(fat.find_free(Cluster(4), 5), Some(Cluster(5)));
+        // The search wraps around past the end of the volume.
+        assert_eq!(fat.find_free(Cluster(4), 2), Some(Cluster(3)));
+        // Clusters 0 and 1 are never handed out.
+        assert_eq!(fat.find_free(Cluster(0), 1), None);
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        // The top four bits are reserved, and not kept.
+        for raw in [FREE, RESERVED, BAD, 5, EOC] {
+            assert_eq!(u32::from(FatEntry::from(raw)), raw & 0x0FFFFFFF);
+        }
+
+        // Any end-of-chain marker is written back as the canonical one.
+        assert_eq!(u32::from(FatEntry::from(0x0FFFFFF8)), 0x0FFFFFFF);
+    }
+
+    #[test]
+    fn test_free_clusters() {
+        let fat = Fat {
+            data: [FREE, RESERVED, EOC, FREE, BAD, FREE, FREE]
+                .into_iter()
+                .map(FatEntry::from)
+                .collect(),
+        };
+
+        // The two reserved entries at the start aren't clusters.
+        assert_eq!(fat.free_clusters(5), 3);
+        assert_eq!(fat.free_clusters(3), 1);
+        // Entries past the end of the volume aren't counted.
+        assert_eq!(fat.free_clusters(100), 3);
+    }
 }
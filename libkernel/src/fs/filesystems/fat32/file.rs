@@ -1,29 +1,71 @@
 use crate::{
-    error::Result,
+    error::{FsError, Result},
     fs::{Inode, InodeId, attr::FileAttr},
+    sync::mutex::Mutex,
 };
 use alloc::boxed::Box;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use async_trait::async_trait;
 use core::any::Any;
 
-use super::{Cluster, Fat32Operations, reader::Fat32Reader};
+use super::{
+    Cluster, Fat32Operations,
+    dir::{DirEntry, EntryLoc, read_entry, write_entry},
+    reader::{Fat32Reader, Fat32Writer, collect_chain},
+};
 
 pub struct Fat32FileNode<T: Fat32Operations> {
-    reader: Fat32Reader<T>,
-    attr: FileAttr,
+    fs: Arc<T>,
+    loc: EntryLoc,
     id: InodeId,
+    /// The file's directory entry, as last read or written.
+    entry: Mutex<DirEntry, T::Cpu>,
 }
 
 impl<T: Fat32Operations> Fat32FileNode<T> {
-    pub fn new(fs: Arc<T>, root: Cluster, attr: FileAttr) -> Result<Self> {
-        let id = InodeId::from_fsid_and_inodeid(fs.id() as _, root.value() as _);
+    pub fn new(fs: Arc<T>, loc: EntryLoc, entry: DirEntry) -> Self {
+        let id = InodeId::from_fsid_and_inodeid(fs.id() as _, loc.inode_id());
 
-        Ok(Self {
-            reader: Fat32Reader::new(fs, root, attr.size),
-            attr,
+        Self {
+            fs,
+            loc,
             id,
-        })
+            entry: Mutex::new(entry),
+        }
+    }
+
+    /// Writes `entry` back to the directory.
+    async fn store(&self, entry: &DirEntry) -> Result<()> {
+        // Make sure we're not writing over someone else's entry, should ours
+        // have been removed.
+        if read_entry(&*self.fs, self.loc).await?.short_name() != entry.short_name() {
+            return Err(FsError::NotFound.into());
+        }
+
+        write_entry(&*self.fs, self.loc, &entry.to_bytes()).await
+    }
+
+    /// Makes sure the file has enough clusters for `size` bytes, and returns
+    /// its chain.
+    async fn reserve(&self, entry: &mut DirEntry, size: u64) -> Result<Vec<Cluster>> {
+        let mut chain = collect_chain(&*self.fs, entry.cluster())?;
+        let needed = size.div_ceil(self.fs.bytes_per_cluster() as u64) as usize;
+
+        if needed > chain.len() {
+            let first = self
+                .fs
+                .alloc_clusters(chain.last().copied(), needed - chain.len())
+                .await?;
+
+            if chain.is_empty() {
+                entry.set_cluster(first);
+            }
+
+            chain.extend(collect_chain(&*self.fs, first)?);
+        }
+
+        Ok(chain)
     }
 }
 
@@ -34,11 +76,103 @@ impl<T: Fat32Operations> Inode for Fat32FileNode<T> {
     }
 
     async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
-        self.reader.read_at(offset, buf).await
+        let entry = *self.entry.lock().await;
+
+        Fat32Reader::new(self.fs.clone(), entry.cluster(), entry.size() as u64)
+            .read_at(offset, buf)
+            .await
+    }
+
+    async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Sizes are kept in 32 bits.
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end <= u32::MAX as u64)
+            .ok_or(FsError::OutOfBounds)?;
+
+        let _op = self.fs.ops().lock().await;
+        let mut entry = self.entry.lock().await;
+        let mut new_entry = *entry;
+        let size = entry.size() as u64;
+        let chain = self.reserve(&mut new_entry, end).await?;
+        let writer = Fat32Writer::new(&*self.fs, &chain);
+
+        if offset > size {
+            writer.zero(size..offset).await?;
+        }
+
+        writer.write_at(offset, buf).await?;
+
+        if end > size {
+            new_entry.set_size(end as u32);
+        }
+
+        self.store(&new_entry).await?;
+        *entry = new_entry;
+
+        Ok(buf.len())
+    }
+
+    async fn truncate(&self, size: u64) -> Result<()> {
+        if size > u32::MAX as u64 {
+            return Err(FsError::OutOfBounds.into());
+        }
+
+        let _op = self.fs.ops().lock().await;
+        let mut entry = self.entry.lock().await;
+        let mut new_entry = *entry;
+        let old_size = entry.size() as u64;
+        let first = entry.cluster();
+
+        new_entry.set_size(size as u32);
+
+        if size > old_size {
+            let chain = self.reserve(&mut new_entry, size).await?;
+
+            Fat32Writer::new(&*self.fs, &chain)
+                .zero(old_size..size)
+                .await?;
+            self.store(&new_entry).await?;
+        } else if size < old_size {
+            let keep = size.div_ceil(self.fs.bytes_per_cluster() as u64) as usize;
+
+            if keep == 0 {
+                new_entry.set_cluster(Cluster(0));
+            }
+
+            // The entry no longer refers to the clusters by the time they're
+            // freed.
+            self.store(&new_entry).await?;
+
+            if first.is_valid() {
+                self.fs.truncate_chain(first, keep).await?;
+            }
+        }
+
+        *entry = new_entry;
+
+        Ok(())
     }
 
     async fn getattr(&self) -> Result<FileAttr> {
-        Ok(self.attr.clone())
+        self.entry.lock().await.attr(self.id)
+    }
+
+    /// Only the access and modification times can be kept.
+    async fn setattr(&self, attr: FileAttr) -> Result<()> {
+        let _op = self.fs.ops().lock().await;
+        let mut entry = self.entry.lock().await;
+        let mut new_entry = *entry;
+
+        new_entry.set_times(attr.atime, attr.mtime);
+        self.store(&new_entry).await?;
+        *entry = new_entry;
+
+        Ok(())
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -48,15 +182,21 @@ impl<T: Fat32Operations> Inode for Fat32FileNode<T> {
 
 #[cfg(test)]
 pub mod test {
-    use crate::{error::FsError, fs::filesystems::fat32::Sector};
+    use crate::{
+        error::{FsError, KernelError},
+        fs::filesystems::fat32::{Sector, dir::Fat32Attributes},
+        sync::spinlock::SpinLockIrq,
+        test::MockCpuOps,
+    };
 
     use super::*;
     use alloc::{collections::BTreeMap, sync::Arc, vec};
 
     pub struct MockFs {
-        file_data: BTreeMap<u32, Vec<u8>>, // Map Sector(u32) -> data
+        file_data: SpinLockIrq<BTreeMap<u32, Vec<u8>>, MockCpuOps>, // Map Sector(u32) -> data
         sector_size: usize,
         sectors_per_cluster: usize,
+        ops: Mutex<(), MockCpuOps>,
     }
 
     impl MockFs {
@@ -72,27 +212,39 @@ pub mod test {
             }
 
             Self {
-                file_data,
+                file_data: SpinLockIrq::new(file_data),
                 sector_size,
                 sectors_per_cluster,
+                ops: Mutex::new(()),
             }
         }
     }
 
     impl Fat32Operations for MockFs {
+        type Cpu = MockCpuOps;
+
         async fn read_sector(
             &self,
             sector: Sector,
             offset: usize,
             buf: &mut [u8],
         ) -> Result<usize> {
-            let sector_data = self.file_data.get(&sector.0).ok_or(FsError::OutOfBounds)?;
+            let file_data = self.file_data.lock_save_irq();
+            let sector_data = file_data.get(&sector.0).ok_or(FsError::OutOfBounds)?;
             let bytes_in_sec = sector_data.len() - offset;
             let read_size = core::cmp::min(buf.len(), bytes_in_sec);
             buf[..read_size].copy_from_slice(&sector_data[offset..offset + read_size]);
             Ok(read_size)
         }
 
+        async fn write_sector(&self, sector: Sector, offset: usize, buf: &[u8]) -> Result<usize> {
+            let mut file_data = self.file_data.lock_save_irq();
+            let sector_data = file_data.get_mut(&sector.0).ok_or(FsError::OutOfBounds)?;
+            let write_size = core::cmp::min(buf.len(), sector_data.len() - offset);
+            sector_data[offset..offset + write_size].copy_from_slice(&buf[..write_size]);
+            Ok(write_size)
+        }
+
         fn id(&self) -> u64 {
             0
         }
@@ -116,23 +268,44 @@ pub mod test {
 
         fn iter_clusters(&self, root: Cluster) -> impl Iterator<Item = Result<Cluster>> {
             // Assume a simple contiguous chain for testing.
-            let num_clusters =
-                (self.file_data.len() + self.sectors_per_cluster - 1) / self.sectors_per_cluster;
+            let num_clusters = (self.file_data.lock_save_irq().len() + self.sectors_per_cluster
+                - 1)
+                / self.sectors_per_cluster;
             (0..num_clusters).map(move |i| Ok(Cluster((root.value() + i) as u32)))
         }
+
+        fn root_cluster(&self) -> Cluster {
+            Cluster(2)
+        }
+
+        fn ops(&self) -> &Mutex<(), MockCpuOps> {
+            &self.ops
+        }
+
+        async fn alloc_clusters(&self, _after: Option<Cluster>, _count: usize) -> Result<Cluster> {
+            Err(FsError::NoSpace.into())
+        }
+
+        async fn truncate_chain(&self, _start: Cluster, _keep: usize) -> Result<()> {
+            Err(KernelError::NotSupported)
+        }
     }
 
     async fn setup_file_test(content: &[u8]) -> Fat32FileNode<MockFs> {
         let fs = Arc::new(MockFs::new(content, 512, 4));
+        let mut entry = DirEntry::new(*b"FILE    BIN", Fat32Attributes::ARCHIVE);
+
+        entry.set_cluster(Cluster(2));
+        entry.set_size(content.len() as _);
+
         Fat32FileNode::new(
             fs,
-            Cluster(2),
-            FileAttr {
-                size: content.len() as _,
-                ..FileAttr::default()
+            EntryLoc {
+                dir: Cluster(2),
+                index: 0,
             },
+            entry,
         )
-        .unwrap()
     }
 
     #[tokio::test]
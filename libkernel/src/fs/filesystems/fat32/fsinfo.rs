@@ -0,0 +1,98 @@
+use crate::{error::Result, fs::blk::buffer::BlockBuffer, pod::Pod};
+use log::warn;
+
+use super::{Cluster, bpb::BiosParameterBlock};
+
+const LEAD_SIGNATURE: u32 = 0x41615252;
+const STRUCT_SIGNATURE: u32 = 0x61417272;
+const TRAIL_SIGNATURE: u32 = 0xAA550000;
+
+/// What the FSInfo sector holds in place of a cluster it doesn't know.
+const UNKNOWN: u32 = 0xFFFFFFFF;
+
+/// Where the free count lies in the sector, followed by the next free hint.
+const FREE_COUNT_OFFSET: u64 = 488;
+
+#[repr(C, packed)]
+struct RawFsInfo {
+    lead_signature: u32,
+    _reserved1: [u8; 480],
+    struct_signature: u32,
+    free_count: u32,
+    next_free: u32,
+    _reserved2: [u8; 12],
+    trail_signature: u32,
+}
+
+unsafe impl Pod for RawFsInfo {}
+
+/// The FSInfo sector, where a FAT32 volume keeps a count of its free clusters
+/// and a hint of where to look for the next one, so that they needn't be
+/// worked out from the whole FAT.
+#[derive(Clone, Debug)]
+pub struct FsInfo {
+    free_count: u32,
+    next_free: u32,
+    /// Where the sector is on the volume.
+    offset: u64,
+}
+
+impl FsInfo {
+    /// Reads the FSInfo sector of the volume described by `bpb`, or returns
+    /// `None` if it doesn't have a valid one.
+    pub async fn read(dev: &BlockBuffer, bpb: &BiosParameterBlock) -> Result<Option<Self>> {
+        let sector = bpb.fsinfo_sector;
+
+        // Sector 0 is the boot sector, and 0xFFFF means there's no FSInfo.
+        if sector == 0 || sector == 0xFFFF || sector >= bpb.reserved_sector_count {
+            return Ok(None);
+        }
+
+        let offset = sector as u64 * bpb.bytes_per_sector as u64;
+        let raw: RawFsInfo = dev.read_obj(offset).await?;
+
+        if raw.lead_signature != LEAD_SIGNATURE
+            || raw.struct_signature != STRUCT_SIGNATURE
+            || raw.trail_signature != TRAIL_SIGNATURE
+        {
+            warn!("FAT32 FSInfo sector has bad signatures, ignoring it.");
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            free_count: raw.free_count,
+            next_free: raw.next_free,
+            offset,
+        }))
+    }
+
+    /// Returns where to start looking for a free cluster, if it's known.
+    pub fn next_free(&self) -> Option<Cluster> {
+        (self.next_free != UNKNOWN).then_some(Cluster(self.next_free))
+    }
+
+    /// Records that `allocated` clusters have been allocated and `freed`
+    /// freed, and that `next_free` is the place to look for the next one.
+    pub fn update(&mut self, allocated: u32, freed: u32, next_free: Option<Cluster>) {
+        self.free_count = (self.free_count + freed).saturating_sub(allocated);
+
+        if let Some(next_free) = next_free {
+            self.next_free = next_free.0;
+        }
+    }
+
+    /// Sets the free cluster count, as counted from the FAT.
+    pub fn set_free_count(&mut self, free: u32) {
+        self.free_count = free;
+    }
+
+    /// Writes the count and hint back to the volume.
+    pub async fn write(&self, dev: &BlockBuffer) -> Result<()> {
+        let mut buf = [0; 8];
+
+        buf[..4].copy_from_slice(&self.free_count.to_le_bytes());
+        buf[4..].copy_from_slice(&self.next_free.to_le_bytes());
+
+        dev.write_at(self.offset + FREE_COUNT_OFFSET, &buf).await
+    }
+}
@@ -1,12 +1,17 @@
 //! FAT32 filesystem driver.
 
 use crate::{
+    CpuOps,
     error::{FsError, Result},
-    fs::{FileType, Filesystem, Inode, InodeId, attr::FileAttr, blk::buffer::BlockBuffer},
+    fs::{Filesystem, FsStats, Inode, blk::buffer::BlockBuffer},
+    sync::{mutex::Mutex, spinlock::SpinLockIrq},
 };
 use alloc::{
     boxed::Box,
+    collections::BTreeSet,
     sync::{Arc, Weak},
+    vec,
+    vec::Vec,
 };
 use async_trait::async_trait;
 use bpb::BiosParameterBlock;
@@ -16,13 +21,15 @@ use core::{
     ops::{Add, Mul},
 };
 use dir::Fat32DirNode;
-use fat::Fat;
+use fat::{ClusterChainIterator, Fat, FatEntry};
+use fsinfo::FsInfo;
 use log::warn;
 
 mod bpb;
 mod dir;
 mod fat;
 mod file;
+mod fsinfo;
 mod reader;
 
 /// A logical sector number on a FAT32 volume.
@@ -80,15 +87,19 @@ impl Display for Cluster {
 }
 
 /// A mounted FAT32 filesystem instance.
-pub struct Fat32Filesystem {
+pub struct Fat32Filesystem<CPU: CpuOps> {
     dev: BlockBuffer,
     bpb: BiosParameterBlock,
-    fat: Fat,
+    fat: SpinLockIrq<Fat, CPU>,
+    /// The volume's FSInfo sector, if it has a valid one.
+    fsinfo: SpinLockIrq<Option<FsInfo>, CPU>,
+    /// Held by each operation that modifies the filesystem.
+    ops: Mutex<(), CPU>,
     id: u64,
     this: Weak<Self>,
 }
 
-impl Fat32Filesystem {
+impl<CPU: CpuOps> Fat32Filesystem<CPU> {
     /// Creates a new FAT32 filesystem from the given block device buffer.
     pub async fn new(dev: BlockBuffer, id: u64) -> Result<Arc<Self>> {
         let bpb = BiosParameterBlock::new(&dev).await?;
@@ -103,17 +114,87 @@ impl Fat32Filesystem {
             }
         }
 
+        // The free count in FSInfo is only a hint, and may well be stale.
+        // We've the whole FAT to hand, so count them ourselves.
+        let mut fsinfo = FsInfo::read(&dev, &bpb).await?;
+
+        if let Some(fsinfo) = &mut fsinfo {
+            fsinfo.set_free_count(fat.free_clusters(bpb.cluster_count()));
+        }
+
         Ok(Arc::new_cyclic(|weak| Self {
             bpb,
             dev,
-            fat,
+            fat: SpinLockIrq::new(fat),
+            fsinfo: SpinLockIrq::new(fsinfo),
+            ops: Mutex::new(()),
             this: weak.clone(),
             id,
         }))
     }
+
+    /// Writes the FAT entries for `clusters` back to every copy of the FAT on
+    /// disk, a sector at a time.
+    async fn write_fat(&self, clusters: &[Cluster]) -> Result<()> {
+        let per_sector = self.bpb.sector_size() / 4;
+        let sectors: BTreeSet<u32> = clusters
+            .iter()
+            .map(|cluster| (cluster.value() / per_sector) as u32)
+            .collect();
+        let mut buf = vec![0; self.bpb.sector_size()];
+
+        for fat_num in 0..self.bpb.num_fats as usize {
+            let (start, _) = self.bpb.fat_region(fat_num).ok_or(FsError::InvalidFs)?;
+
+            for &sector in &sectors {
+                let offset = self.bpb.sector_offset(start + Sector(sector));
+
+                // The top four bits of each entry are reserved, so they're
+                // kept as they are on disk.
+                self.dev.read_at(offset, &mut buf).await?;
+
+                {
+                    let fat = self.fat.lock_save_irq();
+
+                    for (i, raw) in buf.as_chunks_mut::<4>().0.iter_mut().enumerate() {
+                        let cluster = Cluster(sector * per_sector as u32 + i as u32);
+
+                        // The first two entries hold the media type and the
+                        // volume's dirty flags rather than clusters.
+                        if !cluster.is_valid() {
+                            continue;
+                        }
+
+                        let Some(entry) = fat.get(cluster) else {
+                            break;
+                        };
+
+                        let old = u32::from_le_bytes(*raw);
+                        *raw = ((old & 0xF000_0000) | u32::from(entry)).to_le_bytes();
+                    }
+                }
+
+                self.dev.write_at(offset, &buf).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the FSInfo sector back to disk, if the volume has one.
+    async fn write_fsinfo(&self) -> Result<()> {
+        let fsinfo = self.fsinfo.lock_save_irq().clone();
+
+        match fsinfo {
+            Some(fsinfo) => fsinfo.write(&self.dev).await,
+            None => Ok(()),
+        }
+    }
 }
 
 trait Fat32Operations: Send + Sync + 'static {
+    type Cpu: CpuOps;
+
     fn read_sector(
         &self,
         sector: Sector,
@@ -121,6 +202,15 @@ trait Fat32Operations: Send + Sync + 'static {
         buf: &mut [u8],
     ) -> impl Future<Output = Result<usize>> + Send;
 
+    /// Writes as much of `buf` as fits in `sector` from `offset`, returning
+    /// how much that was.
+    fn write_sector(
+        &self,
+        sector: Sector,
+        offset: usize,
+        buf: &[u8],
+    ) -> impl Future<Output = Result<usize>> + Send;
+
     fn id(&self) -> u64;
     fn sector_size(&self) -> usize;
     fn sectors_per_cluster(&self) -> usize;
@@ -131,9 +221,34 @@ trait Fat32Operations: Send + Sync + 'static {
 
     fn cluster_to_sectors(&self, cluster: Cluster) -> Result<impl Iterator<Item = Sector> + Send>;
     fn iter_clusters(&self, root: Cluster) -> impl Iterator<Item = Result<Cluster>> + Send;
+
+    /// Returns the first cluster of the root directory.
+    fn root_cluster(&self) -> Cluster;
+
+    /// Returns the lock held by each operation that modifies the filesystem.
+    fn ops(&self) -> &Mutex<(), Self::Cpu>;
+
+    /// Allocates `count` clusters as a chain and appends it to the one ending
+    /// at `after`, if given. Returns the first of the new clusters, whose
+    /// contents are left as they were.
+    fn alloc_clusters(
+        &self,
+        after: Option<Cluster>,
+        count: usize,
+    ) -> impl Future<Output = Result<Cluster>> + Send;
+
+    /// Frees every cluster in the chain starting at `start` after the first
+    /// `keep`, the last of which then ends the chain.
+    fn truncate_chain(
+        &self,
+        start: Cluster,
+        keep: usize,
+    ) -> impl Future<Output = Result<()>> + Send;
 }
 
-impl Fat32Operations for Fat32Filesystem {
+impl<CPU: CpuOps> Fat32Operations for Fat32Filesystem<CPU> {
+    type Cpu = CPU;
+
     async fn read_sector(&self, sector: Sector, offset: usize, buf: &mut [u8]) -> Result<usize> {
         debug_assert!(offset < self.bpb.sector_size());
 
@@ -151,6 +266,21 @@ impl Fat32Operations for Fat32Filesystem {
         Ok(read_sz)
     }
 
+    async fn write_sector(&self, sector: Sector, offset: usize, buf: &[u8]) -> Result<usize> {
+        debug_assert!(offset < self.bpb.sector_size());
+
+        let write_sz = min(buf.len(), self.bpb.sector_size() - offset);
+
+        self.dev
+            .write_at(
+                self.bpb.sector_offset(sector) + offset as u64,
+                &buf[..write_sz],
+            )
+            .await?;
+
+        Ok(write_sz)
+    }
+
     fn id(&self) -> u64 {
         self.id
     }
@@ -168,12 +298,110 @@ impl Fat32Operations for Fat32Filesystem {
     }
 
     fn iter_clusters(&self, root: Cluster) -> impl Iterator<Item = Result<Cluster>> {
-        self.fat.get_cluster_chain(root)
+        // The FAT may change between one step and the next, so it's locked
+        // for each in turn. No chain is longer than the volume, even one
+        // that loops.
+        ClusterChainIterator::new(move |cluster| self.fat.lock_save_irq().get(cluster), root)
+            .take(self.bpb.cluster_count() as usize)
+    }
+
+    fn root_cluster(&self) -> Cluster {
+        self.bpb.root_cluster
+    }
+
+    fn ops(&self) -> &Mutex<(), CPU> {
+        &self.ops
+    }
+
+    async fn alloc_clusters(&self, after: Option<Cluster>, count: usize) -> Result<Cluster> {
+        debug_assert!(count > 0);
+
+        let hint = self
+            .fsinfo
+            .lock_save_irq()
+            .as_ref()
+            .and_then(FsInfo::next_free)
+            .unwrap_or(Cluster(2));
+        let mut changed = Vec::with_capacity(count + 1);
+
+        {
+            let mut fat = self.fat.lock_save_irq();
+            let mut hint = hint;
+
+            for _ in 0..count {
+                let Some(cluster) = fat.find_free(hint, self.bpb.cluster_count()) else {
+                    for &cluster in &changed {
+                        fat.set(cluster, FatEntry::Free);
+                    }
+
+                    return Err(FsError::NoSpace.into());
+                };
+
+                fat.set(cluster, FatEntry::Eoc);
+
+                if let Some(&prev) = changed.last() {
+                    fat.set(prev, FatEntry::NextCluster(cluster));
+                }
+
+                changed.push(cluster);
+                hint = cluster;
+            }
+
+            if let Some(after) = after {
+                fat.set(after, FatEntry::NextCluster(changed[0]));
+                changed.push(after);
+            }
+        }
+
+        let first = changed[0];
+        let last = changed[count - 1];
+
+        if let Some(fsinfo) = self.fsinfo.lock_save_irq().as_mut() {
+            fsinfo.update(count as u32, 0, Some(Cluster(last.0 + 1)));
+        }
+
+        self.write_fat(&changed).await?;
+        self.write_fsinfo().await?;
+
+        Ok(first)
+    }
+
+    async fn truncate_chain(&self, start: Cluster, keep: usize) -> Result<()> {
+        let chain = {
+            let mut fat = self.fat.lock_save_irq();
+            let chain = fat
+                .get_cluster_chain(start)
+                .take(self.bpb.cluster_count() as usize)
+                .collect::<Result<Vec<_>>>()?;
+
+            if chain.len() <= keep {
+                return Ok(());
+            }
+
+            for &cluster in &chain[keep..] {
+                fat.set(cluster, FatEntry::Free);
+            }
+
+            if keep > 0 {
+                fat.set(chain[keep - 1], FatEntry::Eoc);
+            }
+
+            chain
+        };
+
+        let freed = (chain.len() - keep) as u32;
+
+        if let Some(fsinfo) = self.fsinfo.lock_save_irq().as_mut() {
+            fsinfo.update(0, freed, None);
+        }
+
+        self.write_fat(&chain[keep.saturating_sub(1)..]).await?;
+        self.write_fsinfo().await
     }
 }
 
 #[async_trait]
-impl Filesystem for Fat32Filesystem {
+impl<CPU: CpuOps> Filesystem for Fat32Filesystem<CPU> {
     fn id(&self) -> u64 {
         self.id
     }
@@ -182,16 +410,355 @@ impl Filesystem for Fat32Filesystem {
         0x4D44 // MSDOS magic number
     }
 
+    fn read_only(&self) -> bool {
+        !cfg!(feature = "fat32_write")
+    }
+
+    async fn statfs(&self) -> Result<FsStats> {
+        let clusters = self.bpb.cluster_count();
+        let free = self.fat.lock_save_irq().free_clusters(clusters);
+
+        // FAT has no inodes, so there's nothing to count.
+        Ok(FsStats {
+            magic: self.magic(),
+            block_size: self.bytes_per_cluster() as u64,
+            blocks: clusters as u64,
+            blocks_free: free as u64,
+            blocks_avail: free as u64,
+            name_max: 255,
+            ..FsStats::default()
+        })
+    }
+
     /// Get the root inode of this filesystem.
     async fn root_inode(&self) -> Result<Arc<dyn Inode>> {
-        Ok(Arc::new(Fat32DirNode::new(
-            self.this.upgrade().unwrap(),
-            self.bpb.root_cluster,
-            FileAttr {
-                id: InodeId::from_fsid_and_inodeid(self.id, self.bpb.root_cluster.0 as _),
-                file_type: FileType::Directory,
-                ..FileAttr::default()
-            },
-        )))
+        Ok(Arc::new(Fat32DirNode::root(self.this.upgrade().unwrap())))
+    }
+
+    /// The FAT and FSInfo are written as they change, so there's only the
+    /// device to flush.
+    async fn sync(&self) -> Result<()> {
+        let _op = self.ops.lock().await;
+        self.dev.sync().await
+    }
+
+    fn cache_inodes(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        error::KernelError,
+        fs::{
+            FileType, InodeId,
+            attr::{FileOwner, FilePermissions},
+        },
+        test::{MockBlockDevice, MockCpuOps},
+    };
+    use alloc::{format, string::String};
+    use core::time::Duration;
+
+    const SECTOR_SIZE: usize = 512;
+    const RESERVED_SECTORS: usize = 32;
+    const FAT_SECTORS: usize = 2;
+    const CLUSTERS: usize = 200;
+
+    /// Formats an in-memory FAT32 volume with one sector per cluster, two
+    /// FATs and an empty root directory in cluster 2.
+    fn mkfs() -> Arc<MockBlockDevice> {
+        let total = RESERVED_SECTORS + 2 * FAT_SECTORS + CLUSTERS;
+        let mut data = vec![0; total * SECTOR_SIZE];
+
+        let put = |data: &mut Vec<u8>, offset: usize, bytes: &[u8]| {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        // Boot sector.
+        put(&mut data, 11, &(SECTOR_SIZE as u16).to_le_bytes());
+        put(&mut data, 13, &[1]);
+        put(&mut data, 14, &(RESERVED_SECTORS as u16).to_le_bytes());
+        put(&mut data, 16, &[2]);
+        put(&mut data, 21, &[0xF8]);
+        put(&mut data, 32, &(total as u32).to_le_bytes());
+        put(&mut data, 36, &(FAT_SECTORS as u32).to_le_bytes());
+        put(&mut data, 44, &2u32.to_le_bytes());
+        put(&mut data, 48, &1u16.to_le_bytes());
+        put(&mut data, 510, &[0x55, 0xAA]);
+
+        // FSInfo, with a free count that's out of date.
+        let fsinfo = SECTOR_SIZE;
+        put(&mut data, fsinfo, &0x41615252u32.to_le_bytes());
+        put(&mut data, fsinfo + 484, &0x61417272u32.to_le_bytes());
+        put(&mut data, fsinfo + 488, &0xFFFFFFFFu32.to_le_bytes());
+        put(&mut data, fsinfo + 492, &3u32.to_le_bytes());
+        put(&mut data, fsinfo + 508, &0xAA550000u32.to_le_bytes());
+
+        for fat in 0..2 {
+            let start = (RESERVED_SECTORS + fat * FAT_SECTORS) * SECTOR_SIZE;
+
+            put(&mut data, start, &0x0FFFFFF8u32.to_le_bytes());
+            put(&mut data, start + 4, &0x0FFFFFFFu32.to_le_bytes());
+            // The root directory.
+            put(&mut data, start + 8, &0x0FFFFFFFu32.to_le_bytes());
+        }
+
+        Arc::new(MockBlockDevice::new(data, SECTOR_SIZE))
+    }
+
+    async fn mount(dev: &Arc<MockBlockDevice>) -> Arc<Fat32Filesystem<MockCpuOps>> {
+        Fat32Filesystem::new(BlockBuffer::new(Box::new(dev.clone())), 10)
+            .await
+            .unwrap()
+    }
+
+    async fn create(dir: &Arc<dyn Inode>, name: &str, file_type: FileType) -> Arc<dyn Inode> {
+        dir.create(
+            name,
+            file_type,
+            FilePermissions::from_bits_retain(0o644),
+            FileOwner::root(),
+            Some(Duration::from_secs(1_709_214_331)),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn free_clusters(fs: &Fat32Filesystem<MockCpuOps>) -> u64 {
+        fs.statfs().await.unwrap().blocks_free
+    }
+
+    /// Returns the free count in the FSInfo sector on disk.
+    fn fsinfo_free(dev: &MockBlockDevice) -> u32 {
+        let data = dev.contents();
+
+        u32::from_le_bytes(data[SECTOR_SIZE + 488..][..4].try_into().unwrap())
+    }
+
+    async fn list(dir: &Arc<dyn Inode>) -> Vec<(String, InodeId)> {
+        let mut stream = dir.readdir(0).await.unwrap();
+        let mut entries = Vec::new();
+
+        while let Some(entry) = stream.next_entry().await.unwrap() {
+            entries.push((entry.name, entry.id));
+        }
+
+        entries
+    }
+
+    #[tokio::test]
+    async fn write_and_read_back() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        let free = free_clusters(&fs).await;
+
+        let file = create(&root, "A Long File Name.txt", FileType::File).await;
+        let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+
+        assert_eq!(file.write_at(0, &data).await.unwrap(), data.len());
+        assert_eq!(free_clusters(&fs).await, free - 6);
+        assert_eq!(fsinfo_free(&dev) as u64, free - 6);
+
+        // Names are looked up without regard to case.
+        let found = root.lookup("a long file name.TXT").await.unwrap();
+        let attr = found.getattr().await.unwrap();
+        let mut buf = vec![0; 4000];
+
+        assert_eq!(found.id(), file.id());
+        assert_eq!(attr.size, 3000);
+        assert_eq!(attr.file_type, FileType::File);
+        assert_eq!(attr.mtime, Duration::from_secs(1_709_214_330));
+        assert_eq!(found.read_at(0, &mut buf).await.unwrap(), 3000);
+        assert_eq!(&buf[..3000], &data[..]);
+
+        assert_eq!(
+            list(&root).await,
+            vec![(String::from("A Long File Name.txt"), file.id())]
+        );
+
+        // Everything is still there once it's mounted afresh, which also
+        // checks that both FATs were written.
+        let fs = mount(&dev).await;
+        let found = fs
+            .root_inode()
+            .await
+            .unwrap()
+            .lookup("A Long File Name.txt")
+            .await
+            .unwrap();
+
+        assert_eq!(found.read_at(0, &mut buf).await.unwrap(), 3000);
+        assert_eq!(&buf[..3000], &data[..]);
+    }
+
+    #[tokio::test]
+    async fn overwrite_and_extend() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        let file = create(&root, "data.bin", FileType::File).await;
+
+        file.write_at(0, &[1; 700]).await.unwrap();
+        file.write_at(500, &[2; 100]).await.unwrap();
+
+        // Writing past the end leaves zeroes in between.
+        file.write_at(1100, &[3; 10]).await.unwrap();
+
+        let mut buf = vec![0; 1200];
+
+        assert_eq!(file.read_at(0, &mut buf).await.unwrap(), 1110);
+        assert!(buf[..500].iter().all(|&b| b == 1));
+        assert!(buf[500..600].iter().all(|&b| b == 2));
+        assert!(buf[600..700].iter().all(|&b| b == 1));
+        assert!(buf[700..1100].iter().all(|&b| b == 0));
+        assert!(buf[1100..1110].iter().all(|&b| b == 3));
+
+        assert!(matches!(
+            file.write_at(u32::MAX as u64, &[0]).await,
+            Err(KernelError::Fs(FsError::OutOfBounds))
+        ));
+    }
+
+    #[tokio::test]
+    async fn truncate_frees_and_zeroes() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        let free = free_clusters(&fs).await;
+        let file = create(&root, "trunc", FileType::File).await;
+
+        file.write_at(0, &[0xAA; 2000]).await.unwrap();
+        assert_eq!(free_clusters(&fs).await, free - 4);
+
+        file.truncate(600).await.unwrap();
+        assert_eq!(free_clusters(&fs).await, free - 2);
+        assert_eq!(fsinfo_free(&dev) as u64, free - 2);
+
+        // Growing it again reads back as zeroes, not what was there before.
+        file.truncate(1000).await.unwrap();
+
+        let mut buf = vec![0; 1000];
+
+        assert_eq!(file.read_at(0, &mut buf).await.unwrap(), 1000);
+        assert!(buf[..600].iter().all(|&b| b == 0xAA));
+        assert!(buf[600..].iter().all(|&b| b == 0));
+
+        file.truncate(0).await.unwrap();
+        assert_eq!(free_clusters(&fs).await, free);
+        assert_eq!(file.getattr().await.unwrap().size, 0);
+    }
+
+    #[tokio::test]
+    async fn mkdir_and_rmdir() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        let free = free_clusters(&fs).await;
+
+        let sub = create(&root, "sub", FileType::Directory).await;
+        let deeper = create(&sub, "Deeper", FileType::Directory).await;
+        let file = create(&deeper, "file", FileType::File).await;
+
+        file.write_at(0, b"hello").await.unwrap();
+
+        // `.` and `..` have the same inode numbers as the directories they
+        // refer to, so that paths can be worked out from them.
+        assert_eq!(
+            list(&sub).await,
+            vec![
+                (String::from("."), sub.id()),
+                (String::from(".."), root.id()),
+                (String::from("Deeper"), deeper.id()),
+            ]
+        );
+        assert_eq!(list(&deeper).await[1], (String::from(".."), sub.id()));
+        assert_eq!(deeper.lookup("..").await.unwrap().id(), sub.id());
+        assert_eq!(sub.lookup("..").await.unwrap().id(), root.id());
+
+        assert!(matches!(
+            sub.unlink("deeper").await,
+            Err(KernelError::Fs(FsError::DirectoryNotEmpty))
+        ));
+
+        deeper.unlink("file").await.unwrap();
+        sub.unlink("deeper").await.unwrap();
+        root.unlink("sub").await.unwrap();
+
+        assert!(matches!(
+            root.lookup("sub").await,
+            Err(KernelError::Fs(FsError::NotFound))
+        ));
+        assert!(list(&root).await.is_empty());
+        assert_eq!(free_clusters(&fs).await, free);
+        assert_eq!(fsinfo_free(&dev) as u64, free);
+    }
+
+    #[tokio::test]
+    async fn create_checks_names() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+
+        create(&root, "Name", FileType::File).await;
+
+        for (name, err) in [
+            ("NAME", KernelError::Fs(FsError::AlreadyExists)),
+            ("what?", KernelError::Fs(FsError::InvalidInput)),
+            ("dot.", KernelError::Fs(FsError::InvalidInput)),
+            (&"x".repeat(256), KernelError::NameTooLong),
+        ] {
+            let result = root
+                .create(
+                    name,
+                    FileType::File,
+                    FilePermissions::empty(),
+                    FileOwner::root(),
+                    None,
+                )
+                .await;
+
+            assert_eq!(result.err(), Some(err));
+        }
+
+        let result = root
+            .create(
+                "fifo",
+                FileType::Fifo,
+                FilePermissions::empty(),
+                FileOwner::root(),
+                None,
+            )
+            .await;
+
+        assert_eq!(result.err(), Some(KernelError::NotPermitted));
+    }
+
+    #[tokio::test]
+    async fn directory_grows() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+
+        // Most of these take three entries, and a cluster only holds 16.
+        for i in 0..20 {
+            create(&root, &format!("File number {i}"), FileType::File).await;
+        }
+
+        // This takes as many entries as the one it replaces.
+        root.unlink("File number 13").await.unwrap();
+        create(&root, "Reuses the gap", FileType::File).await;
+
+        let names: Vec<_> = list(&mount(&dev).await.root_inode().await.unwrap())
+            .await
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+
+        assert_eq!(names.len(), 20);
+        assert_eq!(names[13], "Reuses the gap");
+        assert_eq!(names[19], "File number 19");
     }
 }
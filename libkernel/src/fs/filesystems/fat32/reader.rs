@@ -1,8 +1,19 @@
-use crate::error::Result;
-use alloc::sync::Arc;
+use crate::error::{FsError, Result};
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::ops::Range;
 
 use super::{Cluster, Fat32Operations};
 
+/// Returns the clusters of the chain starting at `root`, of which there are
+/// none if it's the zero cluster given to empty files.
+pub fn collect_chain<T: Fat32Operations>(fs: &T, root: Cluster) -> Result<Vec<Cluster>> {
+    if !root.is_valid() {
+        return Ok(Vec::new());
+    }
+
+    fs.iter_clusters(root).collect()
+}
+
 pub struct Fat32Reader<T: Fat32Operations> {
     fs: Arc<T>,
     root: Cluster,
@@ -104,3 +115,62 @@ impl<T: Fat32Operations> Fat32Reader<T> {
         Ok(total_bytes_read)
     }
 }
+
+/// Writes to the clusters of a chain, which must already be long enough to
+/// hold what's written.
+pub struct Fat32Writer<'a, T: Fat32Operations> {
+    fs: &'a T,
+    chain: &'a [Cluster],
+}
+
+impl<'a, T: Fat32Operations> Fat32Writer<'a, T> {
+    pub fn new(fs: &'a T, chain: &'a [Cluster]) -> Self {
+        Self { fs, chain }
+    }
+
+    pub async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<()> {
+        let bpc = self.fs.bytes_per_cluster() as u64;
+        let sector_size = self.fs.sector_size() as u64;
+        let mut written = 0;
+
+        while written < buf.len() {
+            let pos = offset + written as u64;
+            let cluster = *self
+                .chain
+                .get((pos / bpc) as usize)
+                .ok_or(FsError::OutOfBounds)?;
+            let offset_in_cluster = pos % bpc;
+            let sector = self
+                .fs
+                .cluster_to_sectors(cluster)?
+                .nth((offset_in_cluster / sector_size) as usize)
+                .ok_or(FsError::OutOfBounds)?;
+
+            written += self
+                .fs
+                .write_sector(
+                    sector,
+                    (offset_in_cluster % sector_size) as usize,
+                    &buf[written..],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Fills `range` with zeroes.
+    pub async fn zero(&self, range: Range<u64>) -> Result<()> {
+        let zeroes = vec![0; self.fs.bytes_per_cluster()];
+        let mut pos = range.start;
+
+        while pos < range.end {
+            let len = (range.end - pos).min(zeroes.len() as u64);
+
+            self.write_at(pos, &zeroes[..len as usize]).await?;
+            pos += len;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+use crate::{
+    error::{FsError, Result},
+    fs::{DirStream, Dirent, FileType, Inode, InodeId, attr::FileAttr},
+};
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::{any::Any, cmp::min, time::Duration};
+use log::warn;
+
+use super::{
+    Iso9660Filesystem, SECTOR_SIZE,
+    file::{Extent, Iso9660FileNode},
+    le32, record_time,
+    susp::RockRidge,
+};
+
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_ASSOCIATED: u8 = 0x04;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// The fixed part of a directory record, up to the file identifier.
+const RECORD_HEADER_LEN: usize = 33;
+
+/// A directory record, describing a file or directory, or one extent of a
+/// file too big for one.
+#[derive(Clone, Debug)]
+pub struct DirRecord {
+    /// Where the record is on the volume, in bytes.
+    pub offset: u64,
+    /// The first logical block of the extent.
+    pub extent: u32,
+    /// How many blocks of extended attributes come before the data.
+    pub ext_attr_len: u8,
+    /// The length of the data, in bytes.
+    pub size: u32,
+    pub recorded: Duration,
+    pub flags: u8,
+    /// The file identifier, which is 0 for `.` and 1 for `..`.
+    pub ident: Vec<u8>,
+    pub system_use: Vec<u8>,
+}
+
+impl DirRecord {
+    /// Parses the record at the start of `buf`, which lies at `offset` on the
+    /// volume.
+    pub fn parse(buf: &[u8], offset: u64) -> Result<Self> {
+        let len = buf.first().copied().unwrap_or(0) as usize;
+
+        if len <= RECORD_HEADER_LEN || len > buf.len() {
+            warn!("ISO 9660 directory record at {offset:#x} has a bad length.");
+            return Err(FsError::InvalidFs.into());
+        }
+
+        let ident_len = buf[32] as usize;
+        let ident_end = RECORD_HEADER_LEN + ident_len;
+
+        if ident_len == 0 || ident_end > len {
+            warn!("ISO 9660 directory record at {offset:#x} has a bad identifier.");
+            return Err(FsError::InvalidFs.into());
+        }
+
+        // The identifier is padded to an even length.
+        let system_use = min(ident_end + (ident_len % 2 == 0) as usize, len);
+
+        Ok(Self {
+            offset,
+            extent: le32(&buf[2..]),
+            ext_attr_len: buf[1],
+            size: le32(&buf[10..]),
+            recorded: record_time(&buf[18..25]),
+            flags: buf[25],
+            ident: buf[RECORD_HEADER_LEN..ident_end].to_vec(),
+            system_use: buf[system_use..len].to_vec(),
+        })
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.flags & FLAG_DIRECTORY != 0
+    }
+
+    pub fn is_dot(&self) -> bool {
+        self.ident == [0]
+    }
+
+    pub fn is_dotdot(&self) -> bool {
+        self.ident == [1]
+    }
+}
+
+/// Turns an ISO 9660 file identifier into a name the way Linux does: without
+/// its version number or an empty extension, and in lower case.
+fn iso_name(ident: &[u8]) -> String {
+    let name = ident.split(|&b| b == b';').next().unwrap_or_default();
+    let name = name.strip_suffix(b".").unwrap_or(name);
+
+    String::from_utf8_lossy(name).to_ascii_lowercase()
+}
+
+/// What an entry in a directory refers to.
+enum Target {
+    /// The directory starting at the given block.
+    Dir(u32),
+    /// A file, made up of the given extents.
+    File(Vec<Extent>),
+}
+
+/// A file or directory in a directory, gathered from its records.
+struct Entry {
+    name: String,
+    /// The first of the entry's records.
+    record: DirRecord,
+    rr: RockRidge,
+    target: Target,
+    /// Where the next entry starts in the directory.
+    next: u64,
+}
+
+impl Entry {
+    /// Returns the entry's inode number: for a directory, where its `.`
+    /// record is, and for anything else, where its own record is.
+    fn inode_id(&self, fs: &Iso9660Filesystem) -> u64 {
+        match self.target {
+            Target::Dir(extent) => fs.block_offset(extent),
+            Target::File(_) => self.record.offset,
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.target {
+            Target::Dir(_) => FileType::Directory,
+            Target::File(_) => self.rr.file_type(false),
+        }
+    }
+
+    async fn open(self, fs: Arc<Iso9660Filesystem>) -> Result<Arc<dyn Inode>> {
+        let id = InodeId::from_fsid_and_inodeid(fs.id, self.inode_id(&fs));
+
+        match self.target {
+            Target::Dir(extent) => Ok(Arc::new(Iso9660DirNode::read(fs, extent).await?)),
+            Target::File(extents) => {
+                let size = extents.iter().map(|extent| extent.len as u64).sum();
+                let attr = self.rr.attr(
+                    &self.record,
+                    id,
+                    self.rr.file_type(false),
+                    size,
+                    fs.block_size,
+                );
+
+                Ok(Arc::new(Iso9660FileNode::new(
+                    fs,
+                    attr,
+                    extents,
+                    self.rr.symlink,
+                )))
+            }
+        }
+    }
+}
+
+/// Reads the records of a directory a sector at a time, and gathers them
+/// into entries.
+struct Iso9660DirStream {
+    fs: Arc<Iso9660Filesystem>,
+    /// Where the directory is on the volume.
+    start: u64,
+    size: u64,
+    /// Where the next record is in the directory.
+    offset: u64,
+    /// The sector of the directory in `buf`, if there's one.
+    sector: Option<u64>,
+    buf: Vec<u8>,
+}
+
+impl Iso9660DirStream {
+    fn new(fs: Arc<Iso9660Filesystem>, extent: u32, size: u32, offset: u64) -> Self {
+        Self {
+            start: fs.block_offset(extent),
+            fs,
+            size: size as u64,
+            offset,
+            sector: None,
+            buf: vec![0; SECTOR_SIZE as usize],
+        }
+    }
+
+    async fn next_record(&mut self) -> Result<Option<DirRecord>> {
+        while self.offset < self.size {
+            let sector = self.offset / SECTOR_SIZE;
+            let in_sector = (self.offset % SECTOR_SIZE) as usize;
+
+            if self.sector != Some(sector) {
+                let len = min(SECTOR_SIZE, self.size - sector * SECTOR_SIZE) as usize;
+
+                self.buf.resize(len, 0);
+                self.fs
+                    .dev
+                    .read_at(self.start + sector * SECTOR_SIZE, &mut self.buf)
+                    .await?;
+                self.sector = Some(sector);
+            }
+
+            // Records don't cross sectors, so a zero length pads out the rest
+            // of this one.
+            match self.buf.get(in_sector) {
+                None | Some(0) => self.offset = (sector + 1) * SECTOR_SIZE,
+                Some(&len) => {
+                    let record =
+                        DirRecord::parse(&self.buf[in_sector..], self.start + self.offset)?;
+
+                    self.offset += len as u64;
+
+                    return Ok(Some(record));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn next(&mut self) -> Result<Option<Entry>> {
+        loop {
+            let Some(record) = self.next_record().await? else {
+                return Ok(None);
+            };
+
+            let mut extents = vec![Extent::of(&self.fs, &record)];
+            let mut flags = record.flags;
+
+            // A file too big for one extent has a record for each, one after
+            // the other.
+            while flags & FLAG_MULTI_EXTENT != 0 {
+                let Some(next) = self.next_record().await? else {
+                    warn!("ISO 9660 file at {:#x} is missing extents.", record.offset);
+                    return Err(FsError::InvalidFs.into());
+                };
+
+                extents.push(Extent::of(&self.fs, &next));
+                flags = next.flags;
+            }
+
+            if record.flags & FLAG_ASSOCIATED != 0 {
+                continue;
+            }
+
+            let rr = self.fs.rock_ridge(&record).await?;
+
+            // A moved directory is listed where it was moved from.
+            if rr.relocated {
+                continue;
+            }
+
+            let (name, target) = if record.is_dot() {
+                (".".to_string(), Target::Dir(record.extent))
+            } else if record.is_dotdot() {
+                let parent = rr.parent_link.unwrap_or(record.extent);
+
+                ("..".to_string(), Target::Dir(parent))
+            } else {
+                let name = match &rr.name {
+                    Some(name) => String::from_utf8_lossy(name).into_owned(),
+                    None => iso_name(&record.ident),
+                };
+
+                let target = match rr.child_link {
+                    Some(extent) => Target::Dir(extent),
+                    None if record.is_dir() => Target::Dir(record.extent),
+                    None => Target::File(extents),
+                };
+
+                (name, target)
+            };
+
+            return Ok(Some(Entry {
+                name,
+                record,
+                rr,
+                target,
+                next: self.offset,
+            }));
+        }
+    }
+}
+
+#[async_trait]
+impl DirStream for Iso9660DirStream {
+    async fn next_entry(&mut self) -> Result<Option<Dirent>> {
+        Ok(self.next().await?.map(|entry| {
+            let id = InodeId::from_fsid_and_inodeid(self.fs.id, entry.inode_id(&self.fs));
+            let file_type = entry.file_type();
+
+            Dirent::new(entry.name, id, file_type, entry.next)
+        }))
+    }
+}
+
+pub struct Iso9660DirNode {
+    fs: Arc<Iso9660Filesystem>,
+    extent: u32,
+    size: u32,
+    attr: FileAttr,
+}
+
+impl Iso9660DirNode {
+    /// Reads the directory that starts at block `extent`. Its attributes are
+    /// taken from its own `.` record, as that's the one place they're kept
+    /// for the root and for directories moved elsewhere alike.
+    pub async fn read(fs: Arc<Iso9660Filesystem>, extent: u32) -> Result<Self> {
+        let offset = fs.block_offset(extent);
+        let dot = fs.read_record(offset).await?;
+
+        if !dot.is_dot() || !dot.is_dir() {
+            warn!("ISO 9660 directory at block {extent} doesn't start with `.`.");
+            return Err(FsError::InvalidFs.into());
+        }
+
+        let rr = fs.rock_ridge(&dot).await?;
+        let id = InodeId::from_fsid_and_inodeid(fs.id, offset);
+        let attr = rr.attr(
+            &dot,
+            id,
+            FileType::Directory,
+            dot.size as u64,
+            fs.block_size,
+        );
+
+        Ok(Self {
+            extent,
+            size: dot.size,
+            attr,
+            fs,
+        })
+    }
+
+    fn stream(&self, offset: u64) -> Iso9660DirStream {
+        Iso9660DirStream::new(self.fs.clone(), self.extent, self.size, offset)
+    }
+}
+
+#[async_trait]
+impl Inode for Iso9660DirNode {
+    fn id(&self) -> InodeId {
+        self.attr.id
+    }
+
+    async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let mut stream = self.stream(0);
+
+        // Plain ISO 9660 names are all upper case on disk, so they're matched
+        // without regard to it.
+        let matches = |entry: &str| match self.fs.susp_skip {
+            Some(_) => entry == name,
+            None => entry.eq_ignore_ascii_case(name),
+        };
+
+        while let Some(entry) = stream.next().await? {
+            if matches(&entry.name) {
+                return entry.open(self.fs.clone()).await;
+            }
+        }
+
+        Err(FsError::NotFound.into())
+    }
+
+    async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
+        Ok(Box::new(self.stream(start_offset)))
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    error::{KernelError, Result},
+    fs::{FileType, Inode, InodeId, attr::FileAttr, pathbuf::PathBuf},
+};
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use async_trait::async_trait;
+use core::{any::Any, cmp::min};
+
+use super::{Iso9660Filesystem, dir::DirRecord};
+
+/// A run of a file's data on the volume.
+#[derive(Clone, Copy, Debug)]
+pub struct Extent {
+    /// Where the data starts, in bytes.
+    pub start: u64,
+    pub len: u32,
+}
+
+impl Extent {
+    /// Returns the data `record` describes, which follows any extended
+    /// attributes at the start of its extent.
+    pub fn of(fs: &Iso9660Filesystem, record: &DirRecord) -> Self {
+        Self {
+            start: fs.block_offset(record.extent) + fs.block_offset(record.ext_attr_len as u32),
+            len: record.size,
+        }
+    }
+}
+
+pub struct Iso9660FileNode {
+    fs: Arc<Iso9660Filesystem>,
+    attr: FileAttr,
+    extents: Vec<Extent>,
+    /// A symlink's target.
+    symlink: Option<Vec<u8>>,
+}
+
+impl Iso9660FileNode {
+    pub fn new(
+        fs: Arc<Iso9660Filesystem>,
+        attr: FileAttr,
+        extents: Vec<Extent>,
+        symlink: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            fs,
+            attr,
+            extents,
+            symlink,
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for Iso9660FileNode {
+    fn id(&self) -> InodeId {
+        self.attr.id
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut done = 0;
+        let mut base = 0;
+
+        for extent in &self.extents {
+            let pos = offset + done as u64;
+            let end = base + extent.len as u64;
+
+            if pos < end && done < buf.len() {
+                let len = min(buf.len() - done, (end - pos) as usize);
+
+                self.fs
+                    .dev
+                    .read_at(extent.start + (pos - base), &mut buf[done..done + len])
+                    .await?;
+                done += len;
+            }
+
+            base = end;
+        }
+
+        Ok(done)
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn readlink(&self) -> Result<PathBuf> {
+        match &self.symlink {
+            Some(target) if self.attr.file_type == FileType::Symlink => {
+                Ok(PathBuf::from(String::from_utf8_lossy(target).into_owned()))
+            }
+            _ => Err(KernelError::NotSupported),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
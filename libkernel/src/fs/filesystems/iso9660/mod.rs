@@ -0,0 +1,761 @@
+//! ISO 9660 filesystem driver, for CD and DVD images, with the Rock Ridge
+//! extensions for POSIX names, permissions and symlinks.
+
+use crate::{
+    error::{FsError, Result},
+    fs::{Filesystem, FsStats, Inode, blk::buffer::BlockBuffer},
+};
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+    vec,
+};
+use async_trait::async_trait;
+use core::{cmp::min, ops::Range, time::Duration};
+use dir::{DirRecord, Iso9660DirNode};
+use log::warn;
+use susp::RockRidge;
+
+mod dir;
+mod file;
+mod susp;
+
+/// Volume descriptors are laid out in 2048-byte sectors whatever the logical
+/// block size, and directory records never cross from one to the next.
+const SECTOR_SIZE: u64 = 2048;
+
+/// The sector of the first volume descriptor, after the system area.
+const FIRST_DESCRIPTOR: u64 = 16;
+
+/// How many volume descriptors we look through for the primary one.
+const MAX_DESCRIPTORS: u64 = 32;
+
+const DESCRIPTOR_PRIMARY: u8 = 1;
+const DESCRIPTOR_TERMINATOR: u8 = 255;
+const STANDARD_ID: &[u8] = b"CD001";
+
+/// Where the fields we need lie in the primary volume descriptor.
+const VOLUME_BLOCKS_OFFSET: usize = 80;
+const BLOCK_SIZE_OFFSET: usize = 128;
+const ROOT_RECORD_OFFSET: usize = 156;
+
+/// Reads the little-endian half of a both-endian 16-bit field.
+fn le16(buf: &[u8]) -> u16 {
+    u16::from_le_bytes([buf[0], buf[1]])
+}
+
+/// Reads the little-endian half of a both-endian 32-bit field.
+fn le32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+/// Returns the number of days from 1970-01-01 to the given date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Count from March, so that the leap day falls at the end of the year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Converts a broken-down time, `gmt_offset` quarter hours east of UTC, into
+/// a `Duration` since the Unix epoch. Unset and earlier times become the
+/// epoch itself.
+fn to_duration(
+    year: i64,
+    month: u32,
+    day: u32,
+    (hour, minute, second): (u32, u32, u32),
+    gmt_offset: i8,
+) -> Duration {
+    if !(1..=12).contains(&month) || day == 0 {
+        return Duration::ZERO;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86_400
+        + (hour * 3600 + minute * 60 + second) as i64
+        - gmt_offset as i64 * 900;
+
+    Duration::from_secs(secs.max(0) as u64)
+}
+
+/// Decodes the seven-byte date and time of a directory record.
+fn record_time(raw: &[u8]) -> Duration {
+    to_duration(
+        1900 + raw[0] as i64,
+        raw[1] as u32,
+        raw[2] as u32,
+        (raw[3] as u32, raw[4] as u32, raw[5] as u32),
+        raw[6] as i8,
+    )
+}
+
+/// Decodes the seventeen-byte date and time of a volume descriptor, which is
+/// sixteen ASCII digits down to hundredths of a second and a GMT offset.
+fn long_time(raw: &[u8]) -> Duration {
+    let field = |range: Range<usize>| {
+        raw[range]
+            .iter()
+            .try_fold(0, |acc, &digit| {
+                digit
+                    .is_ascii_digit()
+                    .then(|| acc * 10 + (digit - b'0') as u32)
+            })
+            .unwrap_or(0)
+    };
+
+    to_duration(
+        field(0..4) as i64,
+        field(4..6),
+        field(6..8),
+        (field(8..10), field(10..12), field(12..14)),
+        raw[16] as i8,
+    ) + Duration::from_millis(field(14..16) as u64 * 10)
+}
+
+/// A mounted ISO 9660 filesystem.
+pub struct Iso9660Filesystem {
+    dev: BlockBuffer,
+    /// The logical block size, in which extents are given.
+    block_size: u32,
+    /// The size of the volume in logical blocks.
+    blocks: u32,
+    /// The first block of the root directory.
+    root_extent: u32,
+    /// How many bytes at the start of each record's system use area come
+    /// before its SUSP entries, if the volume has Rock Ridge extensions.
+    susp_skip: Option<usize>,
+    id: u64,
+    this: Weak<Self>,
+}
+
+impl Iso9660Filesystem {
+    /// Mounts the ISO 9660 volume on the given block device buffer.
+    pub async fn new(dev: BlockBuffer, id: u64) -> Result<Arc<Self>> {
+        let pvd = Self::primary_descriptor(&dev).await?;
+        let block_size = le16(&pvd[BLOCK_SIZE_OFFSET..]) as u32;
+        let blocks = le32(&pvd[VOLUME_BLOCKS_OFFSET..]);
+
+        if !block_size.is_power_of_two() || !(512..=SECTOR_SIZE as u32).contains(&block_size) {
+            warn!("ISO 9660 logical block size {block_size} is invalid.");
+            return Err(FsError::InvalidFs.into());
+        }
+
+        let root = DirRecord::parse(&pvd[ROOT_RECORD_OFFSET..], 0)?;
+
+        if !root.is_dir() {
+            warn!("ISO 9660 root directory record isn't a directory.");
+            return Err(FsError::InvalidFs.into());
+        }
+
+        let mut fs = Self {
+            dev,
+            block_size,
+            blocks,
+            root_extent: root.extent,
+            susp_skip: None,
+            id,
+            this: Weak::new(),
+        };
+
+        // Rock Ridge announces itself with an `SP` entry in the root
+        // directory's own `.` record.
+        let dot = fs.read_record(fs.block_offset(root.extent)).await?;
+
+        fs.susp_skip = susp::detect(&dot.system_use);
+
+        Ok(Arc::new_cyclic(|weak| Self {
+            this: weak.clone(),
+            ..fs
+        }))
+    }
+
+    /// Finds and reads the primary volume descriptor.
+    async fn primary_descriptor(dev: &BlockBuffer) -> Result<Box<[u8]>> {
+        let mut buf = vec![0; SECTOR_SIZE as usize].into_boxed_slice();
+
+        for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+            dev.read_at(sector * SECTOR_SIZE, &mut buf).await?;
+
+            if &buf[1..6] != STANDARD_ID {
+                break;
+            }
+
+            match buf[0] {
+                DESCRIPTOR_PRIMARY => return Ok(buf),
+                DESCRIPTOR_TERMINATOR => break,
+                _ => {}
+            }
+        }
+
+        warn!("No ISO 9660 primary volume descriptor found.");
+        Err(FsError::InvalidFs.into())
+    }
+
+    /// Returns where the given logical block lies on the volume, in bytes.
+    fn block_offset(&self, block: u32) -> u64 {
+        block as u64 * self.block_size as u64
+    }
+
+    /// Reads the directory record at `offset` on the volume.
+    async fn read_record(&self, offset: u64) -> Result<DirRecord> {
+        let mut buf = vec![0; min(u8::MAX as u64, SECTOR_SIZE - offset % SECTOR_SIZE) as usize];
+
+        self.dev.read_at(offset, &mut buf).await?;
+
+        DirRecord::parse(&buf, offset)
+    }
+
+    /// Gathers the Rock Ridge entries of `record`, if the volume has them.
+    async fn rock_ridge(&self, record: &DirRecord) -> Result<RockRidge> {
+        match self.susp_skip {
+            Some(skip) => RockRidge::read(self, record, skip).await,
+            None => Ok(RockRidge::default()),
+        }
+    }
+}
+
+#[async_trait]
+impl Filesystem for Iso9660Filesystem {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn magic(&self) -> u64 {
+        0x9660 // ISOFS_SUPER_MAGIC
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    async fn statfs(&self) -> Result<FsStats> {
+        Ok(FsStats {
+            magic: self.magic(),
+            block_size: self.block_size as u64,
+            blocks: self.blocks as u64,
+            name_max: 255,
+            ..FsStats::default()
+        })
+    }
+
+    async fn root_inode(&self) -> Result<Arc<dyn Inode>> {
+        let fs = self.this.upgrade().ok_or(FsError::InvalidFs)?;
+
+        Ok(Arc::new(Iso9660DirNode::read(fs, self.root_extent).await?))
+    }
+
+    fn cache_inodes(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        error::KernelError,
+        fs::{FileType, InodeId},
+        test::MockBlockDevice,
+    };
+    use alloc::{string::String, vec::Vec};
+
+    const BLOCK: usize = 2048;
+    const ROOT: u32 = 18;
+    const SUB: u32 = 19;
+    const README: u32 = 20;
+    const BIG: u32 = 21;
+    const CONTINUATION: u32 = 23;
+    const MOVED: u32 = 24;
+    const RR_MOVED: u32 = 25;
+    const BLOCKS: u32 = 26;
+
+    const DIR: u8 = 0x02;
+    const MULTI_EXTENT: u8 = 0x80;
+
+    /// 2024-03-01 12:00:00 UTC, the time every record was made.
+    const RECORDED: [u8; 7] = [124, 3, 1, 12, 0, 0, 0];
+
+    fn both16(value: u16) -> Vec<u8> {
+        [value.to_le_bytes(), value.to_be_bytes()].concat()
+    }
+
+    fn both32(value: u32) -> Vec<u8> {
+        [value.to_le_bytes(), value.to_be_bytes()].concat()
+    }
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn record(extent: u32, size: u32, flags: u8, ident: &[u8], system_use: &[u8]) -> Vec<u8> {
+        let mut rec = vec![0; 33];
+
+        put(&mut rec, 2, &both32(extent));
+        put(&mut rec, 10, &both32(size));
+        put(&mut rec, 18, &RECORDED);
+        rec[25] = flags;
+        put(&mut rec, 28, &both16(1));
+        rec[32] = ident.len() as u8;
+        rec.extend_from_slice(ident);
+
+        if ident.len() % 2 == 0 {
+            rec.push(0);
+        }
+
+        rec.extend_from_slice(system_use);
+
+        if rec.len() % 2 == 1 {
+            rec.push(0);
+        }
+
+        rec[0] = rec.len() as u8;
+        rec
+    }
+
+    fn entry(sig: &[u8; 2], data: &[u8]) -> Vec<u8> {
+        [&sig[..], &[data.len() as u8 + 4, 1], data].concat()
+    }
+
+    fn px(mode: u32, nlinks: u32, uid: u32, gid: u32) -> Vec<u8> {
+        entry(
+            b"PX",
+            &[both32(mode), both32(nlinks), both32(uid), both32(gid)].concat(),
+        )
+    }
+
+    fn nm(name: &str) -> Vec<u8> {
+        entry(b"NM", &[&[0], name.as_bytes()].concat())
+    }
+
+    /// Lays out records one after the other from the start of `block`.
+    fn directory(data: &mut [u8], block: u32, records: &[Vec<u8>]) {
+        put(data, block as usize * BLOCK, &records.concat());
+    }
+
+    /// Builds an image with a root directory holding a file, a subdirectory,
+    /// a file in two extents, a symlink and the directory that Rock Ridge
+    /// moves deep directories into. With `rock_ridge`, the subdirectory holds
+    /// a link to a directory that was moved there.
+    fn mkiso(rock_ridge: bool) -> Arc<MockBlockDevice> {
+        let mut data = vec![0; BLOCKS as usize * BLOCK];
+        let rr = |entries: &[Vec<u8>]| -> Vec<u8> {
+            if rock_ridge {
+                entries.concat()
+            } else {
+                Vec::new()
+            }
+        };
+
+        // Primary volume descriptor and terminator.
+        let pvd = 16 * BLOCK;
+        put(&mut data, pvd, &[DESCRIPTOR_PRIMARY]);
+        put(&mut data, pvd + 1, b"CD001\x01");
+        put(&mut data, pvd + VOLUME_BLOCKS_OFFSET, &both32(BLOCKS));
+        put(&mut data, pvd + BLOCK_SIZE_OFFSET, &both16(BLOCK as u16));
+        put(
+            &mut data,
+            pvd + ROOT_RECORD_OFFSET,
+            &record(ROOT, BLOCK as u32, DIR, &[0], &[]),
+        );
+        put(&mut data, 17 * BLOCK, &[DESCRIPTOR_TERMINATOR]);
+        put(&mut data, 17 * BLOCK + 1, b"CD001\x01");
+
+        let sp = entry(b"SP", &[0xBE, 0xEF, 0]);
+        let root_px = px(0o40755, 4, 0, 0);
+
+        // The symlink's attributes and target don't fit in its record.
+        let link_entries = [
+            px(0o120777, 1, 0, 0),
+            entry(
+                b"SL",
+                &[
+                    0, 0x08, 0, 0, 3, b'u', b's', b'r', 0x01, 2, b'l', b'i', 0, 1, b'b',
+                ],
+            ),
+        ]
+        .concat();
+        put(&mut data, CONTINUATION as usize * BLOCK, &link_entries);
+
+        directory(
+            &mut data,
+            ROOT,
+            &[
+                record(ROOT, BLOCK as u32, DIR, &[0], &rr(&[sp, root_px.clone()])),
+                record(
+                    ROOT,
+                    BLOCK as u32,
+                    DIR,
+                    &[1],
+                    &rr(core::slice::from_ref(&root_px)),
+                ),
+                record(
+                    README,
+                    13,
+                    0,
+                    b"README.TXT;1",
+                    &rr(&[
+                        nm("ReadMe.txt"),
+                        px(0o100644, 1, 1000, 100),
+                        // Modified 2025-01-02 03:04:05 at UTC+1.
+                        entry(b"TF", &[0x02, 125, 1, 2, 3, 4, 5, 4]),
+                    ]),
+                ),
+                record(
+                    SUB,
+                    BLOCK as u32,
+                    DIR,
+                    b"SUB",
+                    &rr(&[nm("sub"), px(0o40700, 2, 0, 0)]),
+                ),
+                record(
+                    BIG,
+                    BLOCK as u32,
+                    MULTI_EXTENT,
+                    b"BIG.BIN;1",
+                    &rr(&[nm("big.bin"), px(0o100600, 1, 0, 0)]),
+                ),
+                record(BIG + 1, 100, 0, b"BIG.BIN;1", &[]),
+                record(
+                    0,
+                    0,
+                    0,
+                    b"LINK.",
+                    &rr(&[
+                        nm("link"),
+                        entry(
+                            b"CE",
+                            &[
+                                both32(CONTINUATION),
+                                both32(0),
+                                both32(link_entries.len() as u32),
+                            ]
+                            .concat(),
+                        ),
+                    ]),
+                ),
+                record(
+                    RR_MOVED,
+                    BLOCK as u32,
+                    DIR,
+                    b"RR_MOVED",
+                    &rr(&[nm("rr_moved"), px(0o40755, 2, 0, 0)]),
+                ),
+            ],
+        );
+
+        let sub_links = if rock_ridge {
+            vec![record(
+                0,
+                0,
+                0,
+                b"MOVED",
+                &[
+                    nm("moved"),
+                    px(0o40755, 2, 0, 0),
+                    entry(b"CL", &both32(MOVED)),
+                ]
+                .concat(),
+            )]
+        } else {
+            Vec::new()
+        };
+
+        directory(
+            &mut data,
+            SUB,
+            &[
+                vec![
+                    record(SUB, BLOCK as u32, DIR, &[0], &rr(&[px(0o40700, 2, 0, 0)])),
+                    record(
+                        ROOT,
+                        BLOCK as u32,
+                        DIR,
+                        &[1],
+                        &rr(core::slice::from_ref(&root_px)),
+                    ),
+                ],
+                sub_links,
+            ]
+            .concat(),
+        );
+
+        directory(
+            &mut data,
+            RR_MOVED,
+            &[
+                record(
+                    RR_MOVED,
+                    BLOCK as u32,
+                    DIR,
+                    &[0],
+                    &rr(&[px(0o40755, 2, 0, 0)]),
+                ),
+                record(
+                    ROOT,
+                    BLOCK as u32,
+                    DIR,
+                    &[1],
+                    &rr(core::slice::from_ref(&root_px)),
+                ),
+                record(
+                    MOVED,
+                    BLOCK as u32,
+                    DIR,
+                    b"MOVED",
+                    &rr(&[nm("moved"), entry(b"RE", &[])]),
+                ),
+            ],
+        );
+
+        directory(
+            &mut data,
+            MOVED,
+            &[
+                record(MOVED, BLOCK as u32, DIR, &[0], &rr(&[px(0o40755, 2, 0, 0)])),
+                record(
+                    RR_MOVED,
+                    BLOCK as u32,
+                    DIR,
+                    &[1],
+                    &rr(&[px(0o40755, 2, 0, 0), entry(b"PL", &both32(SUB))]),
+                ),
+            ],
+        );
+
+        put(&mut data, README as usize * BLOCK, b"Hello, world!");
+
+        for (i, byte) in data[BIG as usize * BLOCK..][..BLOCK + 100]
+            .iter_mut()
+            .enumerate()
+        {
+            *byte = (i % 251) as u8;
+        }
+
+        Arc::new(MockBlockDevice::new(data, 512))
+    }
+
+    async fn mount(dev: &Arc<MockBlockDevice>) -> Arc<Iso9660Filesystem> {
+        Iso9660Filesystem::new(BlockBuffer::new(Box::new(dev.clone())), 10)
+            .await
+            .unwrap()
+    }
+
+    async fn list(dir: &Arc<dyn Inode>, offset: u64) -> Vec<(String, InodeId)> {
+        let mut stream = dir.readdir(offset).await.unwrap();
+        let mut entries = Vec::new();
+
+        while let Some(entry) = stream.next_entry().await.unwrap() {
+            entries.push((entry.name, entry.id));
+        }
+
+        entries
+    }
+
+    fn names(entries: &[(String, InodeId)]) -> Vec<&str> {
+        entries.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn rock_ridge_names_and_attributes() {
+        let fs = mount(&mkiso(true)).await;
+        let root = fs.root_inode().await.unwrap();
+        let entries = list(&root, 0).await;
+
+        assert!(fs.read_only());
+        assert_eq!(
+            names(&entries),
+            [
+                ".",
+                "..",
+                "ReadMe.txt",
+                "sub",
+                "big.bin",
+                "link",
+                "rr_moved"
+            ]
+        );
+        assert_eq!(entries[0].1, root.id());
+        assert_eq!(entries[1].1, root.id());
+
+        let attr = root.getattr().await.unwrap();
+
+        assert_eq!(attr.file_type, FileType::Directory);
+        assert_eq!(attr.permissions.bits(), 0o755);
+        assert_eq!(attr.nlinks, 4);
+        assert_eq!(attr.mtime, Duration::from_secs(1_709_294_400));
+
+        let readme = root.lookup("ReadMe.txt").await.unwrap();
+        let attr = readme.getattr().await.unwrap();
+        let mut buf = [0; 32];
+
+        assert_eq!(readme.id(), entries[2].1);
+        assert_eq!(attr.file_type, FileType::File);
+        assert_eq!(attr.permissions.bits(), 0o644);
+        assert_eq!((u32::from(attr.uid), u32::from(attr.gid)), (1000, 100));
+        assert_eq!(attr.size, 13);
+        assert_eq!(attr.mtime, Duration::from_secs(1_735_783_445));
+        assert_eq!(attr.ctime, Duration::from_secs(1_709_294_400));
+        assert_eq!(readme.read_at(0, &mut buf).await.unwrap(), 13);
+        assert_eq!(&buf[..13], b"Hello, world!");
+        assert_eq!(readme.read_at(7, &mut buf).await.unwrap(), 6);
+        assert_eq!(&buf[..6], b"world!");
+
+        // Rock Ridge names are matched exactly.
+        assert!(matches!(
+            root.lookup("README.TXT").await,
+            Err(KernelError::Fs(FsError::NotFound))
+        ));
+
+        // Listing can pick up where it left off.
+        let rest = list(&root, 0).await;
+        let offset = dir_offset(&root, 3).await;
+
+        assert_eq!(list(&root, offset).await, rest[3..]);
+    }
+
+    /// Returns the offset to list `dir` from to start at entry `n`.
+    async fn dir_offset(dir: &Arc<dyn Inode>, n: usize) -> u64 {
+        let mut stream = dir.readdir(0).await.unwrap();
+        let mut offset = 0;
+
+        for _ in 0..n {
+            offset = stream.next_entry().await.unwrap().unwrap().offset;
+        }
+
+        offset
+    }
+
+    #[tokio::test]
+    async fn file_in_two_extents() {
+        let fs = mount(&mkiso(true)).await;
+        let big = fs
+            .root_inode()
+            .await
+            .unwrap()
+            .lookup("big.bin")
+            .await
+            .unwrap();
+        let mut buf = vec![0; 4096];
+
+        assert_eq!(big.getattr().await.unwrap().size, BLOCK as u64 + 100);
+        assert_eq!(big.read_at(0, &mut buf).await.unwrap(), BLOCK + 100);
+
+        for (i, &byte) in buf[..BLOCK + 100].iter().enumerate() {
+            assert_eq!(byte, (i % 251) as u8);
+        }
+
+        // A read that starts in one extent and ends in the next.
+        assert_eq!(big.read_at(2000, &mut buf[..100]).await.unwrap(), 100);
+        assert_eq!(buf[0], (2000 % 251) as u8);
+        assert_eq!(buf[99], (2099 % 251) as u8);
+        assert_eq!(big.read_at(BLOCK as u64 + 100, &mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn symlink_from_continuation_area() {
+        let fs = mount(&mkiso(true)).await;
+        let link = fs.root_inode().await.unwrap().lookup("link").await.unwrap();
+        let attr = link.getattr().await.unwrap();
+
+        assert_eq!(attr.file_type, FileType::Symlink);
+        assert_eq!(attr.permissions.bits(), 0o777);
+        assert_eq!(link.readlink().await.unwrap().as_str(), "/usr/lib");
+    }
+
+    #[tokio::test]
+    async fn relocated_directory() {
+        let fs = mount(&mkiso(true)).await;
+        let root = fs.root_inode().await.unwrap();
+        let sub = root.lookup("sub").await.unwrap();
+        let rr_moved = root.lookup("rr_moved").await.unwrap();
+
+        // The directory shows up where it belongs, not where it was moved.
+        assert_eq!(names(&list(&rr_moved, 0).await), [".", ".."]);
+
+        let entries = list(&sub, 0).await;
+        let moved = sub.lookup("moved").await.unwrap();
+
+        assert_eq!(names(&entries), [".", "..", "moved"]);
+        assert_eq!(entries[2].1, moved.id());
+        assert_eq!(entries[1].1, root.id());
+        assert_eq!(sub.getattr().await.unwrap().permissions.bits(), 0o700);
+        assert_eq!(
+            moved.getattr().await.unwrap().file_type,
+            FileType::Directory
+        );
+
+        // Its parent is the directory it was moved out of.
+        assert_eq!(list(&moved, 0).await[1], (String::from(".."), sub.id()));
+        assert_eq!(moved.lookup("..").await.unwrap().id(), sub.id());
+        assert_eq!(sub.lookup("..").await.unwrap().id(), root.id());
+    }
+
+    #[tokio::test]
+    async fn plain_iso9660_names() {
+        let fs = mount(&mkiso(false)).await;
+        let root = fs.root_inode().await.unwrap();
+
+        assert_eq!(
+            names(&list(&root, 0).await),
+            [
+                ".",
+                "..",
+                "readme.txt",
+                "sub",
+                "big.bin",
+                "link",
+                "rr_moved"
+            ]
+        );
+
+        // Without Rock Ridge, names are matched regardless of case.
+        let readme = root.lookup("README.TXT").await.unwrap();
+        let attr = readme.getattr().await.unwrap();
+
+        assert_eq!(attr.permissions.bits(), 0o555);
+        assert_eq!(u32::from(attr.uid), 0);
+        assert_eq!(attr.mtime, Duration::from_secs(1_709_294_400));
+        assert_eq!(
+            root.lookup("link")
+                .await
+                .unwrap()
+                .getattr()
+                .await
+                .unwrap()
+                .file_type,
+            FileType::File
+        );
+        assert_eq!(
+            root.lookup("big.bin")
+                .await
+                .unwrap()
+                .getattr()
+                .await
+                .unwrap()
+                .size,
+            BLOCK as u64 + 100
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_other_volumes() {
+        let dev = Arc::new(MockBlockDevice::new(vec![0; BLOCKS as usize * BLOCK], 512));
+        let result = Iso9660Filesystem::new(BlockBuffer::new(Box::new(dev)), 10).await;
+
+        assert!(matches!(result, Err(KernelError::Fs(FsError::InvalidFs))));
+    }
+
+    #[test]
+    fn volume_descriptor_times() {
+        assert_eq!(
+            long_time(b"2024030112000050\x08"),
+            Duration::from_secs(1_709_294_400 - 2 * 3600) + Duration::from_millis(500)
+        );
+        assert_eq!(long_time(b"0000000000000000\x00"), Duration::ZERO);
+    }
+}
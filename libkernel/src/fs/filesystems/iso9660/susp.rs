@@ -0,0 +1,313 @@
+//! Rock Ridge, by way of the System Use Sharing Protocol (SUSP).
+//!
+//! Rock Ridge keeps a file's POSIX attributes in the system use area at the
+//! end of its directory record, as entries of a two-letter signature, a
+//! length, a version and some data. Those that don't fit are put in a
+//! continuation area elsewhere, which a `CE` entry points to.
+
+use crate::{
+    driver::CharDevDescriptor,
+    error::{FsError, Result},
+    fs::{
+        FileType, InodeId,
+        attr::{FileAttr, FilePermissions},
+    },
+    proc::ids::{Gid, Uid},
+};
+use alloc::{vec, vec::Vec};
+use core::time::Duration;
+use log::warn;
+
+use super::{Iso9660Filesystem, dir::DirRecord, le32, long_time, record_time};
+
+/// The signature, length and version that start every entry.
+const ENTRY_HEADER_LEN: usize = 4;
+
+/// How many continuation areas we follow for one record, so that a loop of
+/// them can't hang us.
+const MAX_CONTINUATIONS: usize = 16;
+
+/// The name or symlink component carries on in the next one.
+const CONTINUE: u8 = 0x01;
+
+const NM_CURRENT: u8 = 0x02;
+const NM_PARENT: u8 = 0x04;
+
+const SL_CURRENT: u8 = 0x02;
+const SL_PARENT: u8 = 0x04;
+const SL_ROOT: u8 = 0x08;
+
+const TF_CREATION: u8 = 0x01;
+const TF_MODIFY: u8 = 0x02;
+const TF_ACCESS: u8 = 0x04;
+const TF_ATTRIBUTES: u8 = 0x08;
+const TF_LONG_FORM: u8 = 0x80;
+
+const S_IFMT: u32 = 0o170000;
+
+/// Returns how many bytes come before the SUSP entries in each system use
+/// area, if `system_use`, from the root directory's `.` record, starts with
+/// the `SP` entry that says the volume has them.
+pub fn detect(system_use: &[u8]) -> Option<usize> {
+    match *system_use {
+        [b'S', b'P', len, _, 0xBE, 0xEF, skip, ..] if len >= 7 => Some(skip as usize),
+        _ => None,
+    }
+}
+
+/// Decodes a `PN` device number. Linux puts the whole of its own encoding in
+/// the low half, leaving the high half zero.
+fn decode_device(high: u32, low: u32) -> CharDevDescriptor {
+    if high == 0 {
+        let low = low as u64;
+
+        CharDevDescriptor {
+            major: (low & 0xfff00) >> 8,
+            minor: (low & 0xff) | ((low >> 12) & 0xfff00),
+        }
+    } else {
+        CharDevDescriptor {
+            major: high as u64,
+            minor: low as u64,
+        }
+    }
+}
+
+/// What the Rock Ridge entries of a directory record say about its file.
+#[derive(Default, Debug)]
+pub struct RockRidge {
+    /// The mode, link count, owner and group, from `PX`.
+    mode: Option<u32>,
+    nlinks: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    /// A device node's device number, from `PN`.
+    device: Option<CharDevDescriptor>,
+    /// The file's name, from `NM`.
+    pub name: Option<Vec<u8>>,
+    /// A symlink's target, from `SL`.
+    pub symlink: Option<Vec<u8>>,
+    /// Whether the target needs a separator before its next component.
+    separator: bool,
+    /// The times, from `TF`.
+    btime: Option<Duration>,
+    mtime: Option<Duration>,
+    atime: Option<Duration>,
+    ctime: Option<Duration>,
+    /// Where a directory that was moved to keep the tree shallow really is,
+    /// from the `CL` entry left in its place.
+    pub child_link: Option<u32>,
+    /// Where a moved directory's parent is, from `PL` in its `..` record.
+    pub parent_link: Option<u32>,
+    /// Whether this is a moved directory's record in the directory it was
+    /// moved to, from `RE`.
+    pub relocated: bool,
+}
+
+impl RockRidge {
+    /// Gathers the entries of `record`, skipping the first `skip` bytes of its
+    /// system use area, and those of any continuation areas.
+    pub async fn read(fs: &Iso9660Filesystem, record: &DirRecord, skip: usize) -> Result<Self> {
+        let mut rr = Self::default();
+        let mut next = rr.parse(record.system_use.get(skip..).unwrap_or_default());
+
+        for _ in 0..MAX_CONTINUATIONS {
+            let Some((block, offset, len)) = next else {
+                return Ok(rr);
+            };
+
+            // A continuation area lies within a single block.
+            if offset
+                .checked_add(len)
+                .is_none_or(|end| end > fs.block_size)
+            {
+                warn!("ISO 9660 continuation area crosses a block boundary.");
+                return Err(FsError::InvalidFs.into());
+            }
+
+            let mut area = vec![0; len as usize];
+
+            fs.dev
+                .read_at(fs.block_offset(block) + offset as u64, &mut area)
+                .await?;
+            next = rr.parse(&area);
+        }
+
+        if next.is_some() {
+            warn!("Too many ISO 9660 continuation areas, ignoring the rest.");
+        }
+
+        Ok(rr)
+    }
+
+    /// Parses the entries in `area`, returning the block, offset and length
+    /// of the next continuation area, if there's one.
+    fn parse(&mut self, mut area: &[u8]) -> Option<(u32, u32, u32)> {
+        let mut next = None;
+
+        while let [a, b, len, _, ..] = *area {
+            let len = len as usize;
+
+            // Whatever's left is padding.
+            if len < ENTRY_HEADER_LEN || len > area.len() {
+                break;
+            }
+
+            let data = &area[ENTRY_HEADER_LEN..len];
+
+            match &[a, b] {
+                b"CE" if data.len() >= 24 => {
+                    next = Some((le32(data), le32(&data[8..]), le32(&data[16..])));
+                }
+                b"PX" if data.len() >= 32 => {
+                    self.mode = Some(le32(data));
+                    self.nlinks = Some(le32(&data[8..]));
+                    self.uid = Some(le32(&data[16..]));
+                    self.gid = Some(le32(&data[24..]));
+                }
+                b"PN" if data.len() >= 16 => {
+                    self.device = Some(decode_device(le32(data), le32(&data[8..])));
+                }
+                b"NM" if !data.is_empty() => self.parse_name(data),
+                b"SL" if !data.is_empty() => self.parse_symlink(&data[1..]),
+                b"TF" if !data.is_empty() => self.parse_times(data),
+                b"CL" if data.len() >= 8 => self.child_link = Some(le32(data)),
+                b"PL" if data.len() >= 8 => self.parent_link = Some(le32(data)),
+                b"RE" => self.relocated = true,
+                b"ST" => break,
+                _ => {}
+            }
+
+            area = &area[len..];
+        }
+
+        next
+    }
+
+    /// Appends an `NM` entry to the name. Those that stand for `.` and `..`
+    /// are left out, as they're named by their records anyway.
+    fn parse_name(&mut self, data: &[u8]) {
+        if data[0] & (NM_CURRENT | NM_PARENT) == 0 {
+            self.name
+                .get_or_insert_default()
+                .extend_from_slice(&data[1..]);
+        }
+    }
+
+    /// Appends the components of an `SL` entry to the symlink's target.
+    fn parse_symlink(&mut self, mut components: &[u8]) {
+        let target = self.symlink.get_or_insert_default();
+
+        while let [flags, len, rest @ ..] = components {
+            let Some(content) = rest.get(..*len as usize) else {
+                break;
+            };
+
+            if self.separator {
+                target.push(b'/');
+            }
+
+            match flags & (SL_CURRENT | SL_PARENT | SL_ROOT) {
+                SL_CURRENT => target.push(b'.'),
+                SL_PARENT => target.extend_from_slice(b".."),
+                SL_ROOT => target.push(b'/'),
+                _ => target.extend_from_slice(content),
+            }
+
+            // A component that carries on, and the root, need no separator
+            // before the next.
+            self.separator = flags & (CONTINUE | SL_ROOT) == 0;
+            components = &rest[content.len()..];
+        }
+    }
+
+    /// Records the times in a `TF` entry, which come in the order of the
+    /// flags that say they're there.
+    fn parse_times(&mut self, data: &[u8]) {
+        let flags = data[0];
+        let (len, decode): (usize, fn(&[u8]) -> Duration) = if flags & TF_LONG_FORM != 0 {
+            (17, long_time)
+        } else {
+            (7, record_time)
+        };
+        let mut stamps = data[1..].chunks_exact(len);
+
+        for flag in (0..7).map(|bit| 1 << bit).filter(|flag| flags & flag != 0) {
+            let Some(stamp) = stamps.next() else {
+                break;
+            };
+
+            let time = Some(decode(stamp));
+
+            match flag {
+                TF_CREATION => self.btime = time,
+                TF_MODIFY => self.mtime = time,
+                TF_ACCESS => self.atime = time,
+                TF_ATTRIBUTES => self.ctime = time,
+                // The backup, expiration and effective times.
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the file's type, going by its record when there's no `PX`.
+    pub fn file_type(&self, is_dir: bool) -> FileType {
+        let Some(mode) = self.mode else {
+            return if is_dir {
+                FileType::Directory
+            } else {
+                FileType::File
+            };
+        };
+
+        let dev = self
+            .device
+            .unwrap_or(CharDevDescriptor { major: 0, minor: 0 });
+
+        match mode & S_IFMT {
+            0o040000 => FileType::Directory,
+            0o120000 => FileType::Symlink,
+            0o020000 => FileType::CharDevice(dev),
+            0o060000 => FileType::BlockDevice(dev),
+            0o010000 => FileType::Fifo,
+            0o140000 => FileType::Socket,
+            _ => FileType::File,
+        }
+    }
+
+    /// Returns the attributes of the file `record` describes, filling in
+    /// what Rock Ridge doesn't say from the record. Without Rock Ridge,
+    /// everything is readable and executable by all and owned by root.
+    pub fn attr(
+        &self,
+        record: &DirRecord,
+        id: InodeId,
+        file_type: FileType,
+        size: u64,
+        block_size: u32,
+    ) -> FileAttr {
+        let nlinks = if file_type == FileType::Directory {
+            2
+        } else {
+            1
+        };
+
+        FileAttr {
+            id,
+            size,
+            block_size,
+            blocks: size.div_ceil(512),
+            atime: self.atime.unwrap_or(record.recorded),
+            btime: self.btime.unwrap_or(record.recorded),
+            mtime: self.mtime.unwrap_or(record.recorded),
+            ctime: self.ctime.unwrap_or(record.recorded),
+            file_type,
+            permissions: FilePermissions::from_bits_truncate(
+                (self.mode.unwrap_or(0o555) & 0o7777) as u16,
+            ),
+            nlinks: self.nlinks.unwrap_or(nlinks),
+            uid: Uid::new(self.uid.unwrap_or(0)),
+            gid: Gid::new(self.gid.unwrap_or(0)),
+        }
+    }
+}
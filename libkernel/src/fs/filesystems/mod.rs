@@ -2,5 +2,8 @@
 
 pub mod ext4;
 pub mod fat32;
+pub mod iso9660;
+#[cfg(feature = "alloc")]
+pub mod overlay;
 #[cfg(feature = "alloc")]
 pub mod tmpfs;
@@ -0,0 +1,1167 @@
+//! Overlay filesystem (overlayfs).
+//!
+//! An overlay merges a writable upper directory with one or more read-only
+//! lower directories, which may be on different filesystems, into a single
+//! tree:
+//!
+//! - A name is looked up in the upper layer first, then in each lower layer
+//!   from the top down. The first layer that has it wins, except that
+//!   directories of the same name are merged.
+//! - Nothing is ever written to a lower layer. The first change to a file or
+//!   directory that only exists below copies it up, along with the directories
+//!   above it, and the change is then made to the copy.
+//! - Removing a name that exists below leaves a whiteout in the upper layer, a
+//!   character device numbered 0:0, which hides it.
+//! - A directory made where one was removed is marked opaque, with the
+//!   `trusted.overlay.opaque` attribute, so that the lower directory's entries
+//!   don't show through it.
+//!
+//! Without an upper layer the overlay is read-only. Renaming a directory that
+//! exists in a lower layer fails with `EXDEV`, as it does on Linux without
+//! `redirect_dir`, so `mv` falls back to copying.
+
+use crate::{
+    CpuOps,
+    driver::CharDevDescriptor,
+    error::{FsError, KernelError, Result},
+    fs::{
+        DirStream, Dirent, FileType, Filesystem, Inode, InodeId, SimpleDirStream,
+        attr::{FileAttr, FileOwner, FilePermissions},
+        path::Path,
+        pathbuf::PathBuf,
+    },
+    memory::{PAGE_SIZE, page::PageFrame},
+    sync::{mutex::Mutex, spinlock::SpinLockIrq},
+};
+use alloc::{
+    boxed::Box,
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::{any::Any, ops::Range, time::Duration};
+
+/// The magic number of an overlay, as reported by `statfs()`.
+const OVERLAYFS_MAGIC: u64 = 0x794c7630;
+
+/// The device number of a whiteout.
+pub const WHITEOUT_DEV: CharDevDescriptor = CharDevDescriptor { major: 0, minor: 0 };
+
+/// The attribute that marks an upper directory as opaque.
+const OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+
+/// The prefix of the attributes the overlay keeps for itself.
+const OVERLAY_XATTR_PREFIX: &str = "trusted.overlay.";
+
+/// The layers an overlay is mounted with, given to mount(2) as a
+/// comma-separated list in its `data` argument.
+#[derive(Debug, Clone, Default)]
+pub struct OverlayOptions {
+    /// The read-only layers, topmost first, from `lowerdir=`, which separates
+    /// them with colons.
+    pub lowerdirs: Vec<String>,
+    /// The writable layer, from `upperdir=`.
+    pub upperdir: Option<String>,
+    /// The scratch directory, from `workdir=`. Copy-up writes straight to the
+    /// upper layer, so this is only checked for.
+    pub workdir: Option<String>,
+}
+
+impl OverlayOptions {
+    /// Parses the `data` argument of mount(2). At least one lower layer is
+    /// needed, and an upper layer takes a work directory.
+    pub fn parse(options: &str) -> Result<Self> {
+        let mut opts = Self::default();
+
+        for opt in options.split(',').filter(|opt| !opt.is_empty()) {
+            let (key, value) = opt.split_once('=').ok_or(KernelError::InvalidValue)?;
+
+            if value.is_empty() {
+                return Err(KernelError::InvalidValue);
+            }
+
+            match key {
+                "lowerdir" => {
+                    opts.lowerdirs = value.split(':').map(ToString::to_string).collect();
+
+                    if opts.lowerdirs.iter().any(String::is_empty) {
+                        return Err(KernelError::InvalidValue);
+                    }
+                }
+                "upperdir" => opts.upperdir = Some(value.to_string()),
+                "workdir" => opts.workdir = Some(value.to_string()),
+                _ => return Err(KernelError::InvalidValue),
+            }
+        }
+
+        if opts.lowerdirs.is_empty() || opts.upperdir.is_some() != opts.workdir.is_some() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        Ok(opts)
+    }
+}
+
+/// Returns `true` if `attr` describes a whiteout.
+fn is_whiteout(attr: &FileAttr) -> bool {
+    attr.file_type == FileType::CharDevice(WHITEOUT_DEV)
+}
+
+/// Returns `true` if the upper directory `dir` hides the directories below it.
+async fn is_opaque(dir: &Arc<dyn Inode>) -> bool {
+    dir.getxattr(OPAQUE_XATTR)
+        .await
+        .is_ok_and(|value| value == b"y")
+}
+
+/// Looks up `name` in the layer directory `dir`, returning `None` if it isn't
+/// there.
+async fn lookup_in(dir: &Arc<dyn Inode>, name: &str) -> Result<Option<(Arc<dyn Inode>, FileAttr)>> {
+    match dir.lookup(name).await {
+        Ok(inode) => {
+            let attr = inode.getattr().await?;
+
+            Ok(Some((inode, attr)))
+        }
+        Err(KernelError::Fs(FsError::NotFound)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Inode numbers, and the overlay inodes that are in use.
+struct InodeTable<C: CpuOps> {
+    next_ino: u64,
+    /// The inode number of each layer inode that has been seen, so that an
+    /// object keeps its number when it's copied up.
+    by_layer_id: BTreeMap<InodeId, u64>,
+    live: BTreeMap<u64, Weak<OverlayInode<C>>>,
+}
+
+/// An overlay of a writable upper directory on read-only lower ones.
+pub struct OverlayFs<C: CpuOps> {
+    id: u64,
+    root: Arc<OverlayInode<C>>,
+    inodes: SpinLockIrq<InodeTable<C>, C>,
+    /// Serialises copy-ups, so an object is only copied up once.
+    copy_up_lock: Mutex<(), C>,
+}
+
+impl<C: CpuOps> OverlayFs<C> {
+    /// Creates an overlay of `upper` on `lowers`, which are directories given
+    /// topmost first. Without `upper`, the overlay is read-only.
+    pub fn new(
+        fs_id: u64,
+        upper: Option<Arc<dyn Inode>>,
+        lowers: Vec<Arc<dyn Inode>>,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|weak_fs| Self {
+            id: fs_id,
+            root: Arc::new(OverlayInode {
+                fs: weak_fs.clone(),
+                ino: 1,
+                file_type: FileType::Directory,
+                parent: SpinLockIrq::new(None),
+                upper: SpinLockIrq::new(upper),
+                lowers,
+            }),
+            inodes: SpinLockIrq::new(InodeTable {
+                next_ino: 2,
+                by_layer_id: BTreeMap::new(),
+                live: BTreeMap::new(),
+            }),
+            copy_up_lock: Mutex::new(()),
+        })
+    }
+
+    /// Returns the inode number of the object whose topmost layer inode is
+    /// `layer_id`.
+    fn ino_for(&self, layer_id: InodeId) -> u64 {
+        let mut table = self.inodes.lock_save_irq();
+        let InodeTable {
+            next_ino,
+            by_layer_id,
+            ..
+        } = &mut *table;
+
+        *by_layer_id.entry(layer_id).or_insert_with(|| {
+            *next_ino += 1;
+            *next_ino - 1
+        })
+    }
+
+    /// Returns the overlay inode made of `upper` and `lowers`, called `name` in
+    /// `parent`, reusing the one in use if there is one.
+    fn get_inode(
+        &self,
+        parent: &Arc<OverlayInode<C>>,
+        name: &str,
+        file_type: FileType,
+        upper: Option<Arc<dyn Inode>>,
+        lowers: Vec<Arc<dyn Inode>>,
+    ) -> Arc<OverlayInode<C>> {
+        let layer_id = upper
+            .as_ref()
+            .or(lowers.first())
+            .expect("overlay inode without layers")
+            .id();
+        let ino = self.ino_for(layer_id);
+        let mut table = self.inodes.lock_save_irq();
+
+        if let Some(inode) = table.live.get(&ino).and_then(Weak::upgrade) {
+            // Dropping the old parent may need the table.
+            drop(table);
+            *inode.parent.lock_save_irq() = Some((parent.clone(), name.to_string()));
+            return inode;
+        }
+
+        let inode = Arc::new(OverlayInode {
+            fs: parent.fs.clone(),
+            ino,
+            file_type,
+            parent: SpinLockIrq::new(Some((parent.clone(), name.to_string()))),
+            upper: SpinLockIrq::new(upper),
+            lowers,
+        });
+
+        table.live.insert(ino, Arc::downgrade(&inode));
+
+        inode
+    }
+}
+
+#[async_trait]
+impl<C: CpuOps> Filesystem for OverlayFs<C> {
+    async fn root_inode(&self) -> Result<Arc<dyn Inode>> {
+        Ok(self.root.clone())
+    }
+
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn magic(&self) -> u64 {
+        OVERLAYFS_MAGIC
+    }
+}
+
+/// The directory an overlay inode was found in, and its name there.
+type OverlayParent<C> = (Arc<OverlayInode<C>>, String);
+
+/// A file or directory of an overlay, made of the layer inodes it's found in.
+struct OverlayInode<C: CpuOps> {
+    fs: Weak<OverlayFs<C>>,
+    ino: u64,
+    file_type: FileType,
+    /// The directory this was found in, and its name there, or nothing for the
+    /// root.
+    parent: SpinLockIrq<Option<OverlayParent<C>>, C>,
+    /// The inode in the upper layer, once there is one.
+    upper: SpinLockIrq<Option<Arc<dyn Inode>>, C>,
+    /// The inodes in the lower layers, topmost first. Only a merged directory
+    /// has more than one.
+    lowers: Vec<Arc<dyn Inode>>,
+}
+
+impl<C: CpuOps> OverlayInode<C> {
+    fn fs(&self) -> Result<Arc<OverlayFs<C>>> {
+        self.fs.upgrade().ok_or(FsError::InvalidFs.into())
+    }
+
+    fn upper(&self) -> Option<Arc<dyn Inode>> {
+        self.upper.lock_save_irq().clone()
+    }
+
+    /// Returns the layer inode that currently holds the object's data.
+    fn real(&self) -> Arc<dyn Inode> {
+        self.upper()
+            .or_else(|| self.lowers.first().cloned())
+            .expect("overlay inode without layers")
+    }
+
+    fn this(&self) -> Result<Arc<Self>> {
+        let fs = self.fs()?;
+
+        if self.ino == fs.root.ino {
+            return Ok(fs.root.clone());
+        }
+
+        fs.inodes
+            .lock_save_irq()
+            .live
+            .get(&self.ino)
+            .and_then(Weak::upgrade)
+            .ok_or(FsError::InvalidFs.into())
+    }
+
+    /// Returns the upper inode, copying the object and any directories above
+    /// it up to the upper layer first if need be.
+    async fn copy_up(&self) -> Result<Arc<dyn Inode>> {
+        if let Some(upper) = self.upper() {
+            return Ok(upper);
+        }
+
+        let fs = self.fs()?;
+
+        // The root's upper is the upper layer itself, so without that the
+        // overlay is read-only.
+        if fs.root.upper().is_none() {
+            return Err(FsError::ReadOnly.into());
+        }
+
+        let _guard = fs.copy_up_lock.lock().await;
+
+        // Copy up from the topmost directory that's still only below.
+        let mut pending = vec![self.this()?];
+
+        loop {
+            let (parent, _) = pending
+                .last()
+                .unwrap()
+                .parent
+                .lock_save_irq()
+                .clone()
+                .ok_or(FsError::InvalidFs)?;
+
+            if parent.upper().is_some() {
+                break;
+            }
+
+            pending.push(parent);
+        }
+
+        while let Some(inode) = pending.pop() {
+            if inode.upper().is_none() {
+                inode.copy_up_one(&fs).await?;
+            }
+        }
+
+        self.upper().ok_or(FsError::InvalidFs.into())
+    }
+
+    /// Copies the object into its parent's upper directory, which must exist.
+    async fn copy_up_one(&self, fs: &OverlayFs<C>) -> Result<()> {
+        let (parent, name) = self
+            .parent
+            .lock_save_irq()
+            .clone()
+            .ok_or(FsError::InvalidFs)?;
+        let parent_upper = parent.upper().ok_or(FsError::InvalidFs)?;
+        let lower = self.lowers.first().ok_or(FsError::InvalidFs)?;
+        let attr = lower.getattr().await?;
+        // The copy keeps the lower object's owner.
+        let owner = FileOwner::new(attr.uid, attr.gid);
+
+        let upper = match attr.file_type {
+            FileType::Symlink => {
+                parent_upper
+                    .symlink(&name, &lower.readlink().await?, owner)
+                    .await?;
+                parent_upper.lookup(&name).await?
+            }
+            file_type => {
+                parent_upper
+                    .create(&name, file_type, attr.permissions, owner, Some(attr.mtime))
+                    .await?
+            }
+        };
+
+        if attr.file_type == FileType::File {
+            let mut buf = vec![0; PAGE_SIZE];
+            let mut offset = 0;
+
+            while offset < attr.size {
+                let len = lower.read_at(offset, &mut buf).await?;
+
+                if len == 0 {
+                    break;
+                }
+
+                upper.write_at(offset, &buf[..len]).await?;
+                offset += len as u64;
+            }
+        }
+
+        let mut upper_attr = upper.getattr().await?;
+
+        upper_attr.permissions = attr.permissions;
+        upper_attr.uid = attr.uid;
+        upper_attr.gid = attr.gid;
+        upper_attr.atime = attr.atime;
+        upper_attr.mtime = attr.mtime;
+        upper_attr.ctime = attr.ctime;
+        upper.setattr(upper_attr).await?;
+
+        for xattr in lower.listxattr().await? {
+            let value = lower.getxattr(&xattr).await?;
+
+            match upper.setxattr(&xattr, &value, false, false).await {
+                Ok(()) | Err(KernelError::NotSupported) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // The copy keeps the object's inode number.
+        fs.inodes
+            .lock_save_irq()
+            .by_layer_id
+            .insert(upper.id(), self.ino);
+        *self.upper.lock_save_irq() = Some(upper);
+
+        Ok(())
+    }
+
+    /// Returns `true` if `name` exists in a lower layer of this directory,
+    /// so removing it takes a whiteout.
+    async fn lower_has(&self, name: &str) -> Result<bool> {
+        for dir in self.lowers.iter() {
+            if let Some((_, attr)) = lookup_in(dir, name).await? {
+                return Ok(!is_whiteout(&attr));
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Returns `true` if the upper directory has a whiteout called `name`.
+    async fn upper_has_whiteout(&self, name: &str) -> Result<bool> {
+        let Some(upper) = self.upper() else {
+            return Ok(false);
+        };
+
+        Ok(lookup_in(&upper, name)
+            .await?
+            .is_some_and(|(_, attr)| is_whiteout(&attr)))
+    }
+
+    /// Gets the upper directory ready for `name` to be made in it, clearing
+    /// any whiteout of that name. Returns the upper directory, and whether a
+    /// new directory called `name` has to be opaque to hide what was there.
+    async fn prepare_create(&self, name: &str) -> Result<(Arc<dyn Inode>, bool)> {
+        if self.lookup_merged(name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        let upper = self.copy_up().await?;
+        let whiteout = self.upper_has_whiteout(name).await?;
+
+        if whiteout {
+            upper.unlink(name).await?;
+        }
+
+        Ok((upper, whiteout || self.lower_has(name).await?))
+    }
+
+    /// Leaves a whiteout called `name` in the upper directory.
+    async fn whiteout(upper: &Arc<dyn Inode>, name: &str) -> Result<()> {
+        upper
+            .create(
+                name,
+                FileType::CharDevice(WHITEOUT_DEV),
+                FilePermissions::empty(),
+                FileOwner::root(),
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Looks up `name` in every layer of this directory, returning what it's
+    /// made of: its upper inode, its lower inodes, and its type.
+    async fn lookup_merged(
+        &self,
+        name: &str,
+    ) -> Result<Option<(Option<Arc<dyn Inode>>, Vec<Arc<dyn Inode>>, FileType)>> {
+        let mut upper = None;
+        let mut lowers = Vec::new();
+        let mut file_type = None;
+
+        if let Some(dir) = self.upper()
+            && let Some((inode, attr)) = lookup_in(&dir, name).await?
+        {
+            if is_whiteout(&attr) {
+                return Ok(None);
+            }
+
+            let merge = attr.file_type == FileType::Directory && !is_opaque(&inode).await;
+
+            upper = Some(inode);
+            file_type = Some(attr.file_type);
+
+            if !merge {
+                return Ok(Some((upper, lowers, attr.file_type)));
+            }
+        }
+
+        for dir in self.lowers.iter() {
+            let Some((inode, attr)) = lookup_in(dir, name).await? else {
+                continue;
+            };
+
+            if is_whiteout(&attr) {
+                break;
+            }
+
+            match file_type {
+                // Only directories merge; anything else below one is hidden.
+                Some(FileType::Directory) if attr.file_type != FileType::Directory => break,
+                Some(FileType::Directory) | None => {}
+                Some(_) => break,
+            }
+
+            let opaque = attr.file_type == FileType::Directory && is_opaque(&inode).await;
+
+            lowers.push(inode);
+            file_type = Some(attr.file_type);
+
+            if attr.file_type != FileType::Directory || opaque {
+                break;
+            }
+        }
+
+        Ok(file_type.map(|file_type| (upper, lowers, file_type)))
+    }
+
+    /// Returns the merged entries of this directory, without `.` and `..`.
+    async fn merged_entries(&self) -> Result<Vec<(String, InodeId, FileType)>> {
+        let mut seen = BTreeSet::new();
+        let mut entries = Vec::new();
+
+        for dir in self.upper().iter().chain(self.lowers.iter()) {
+            let mut stream = dir.readdir(0).await?;
+
+            while let Some(dirent) = stream.next_entry().await? {
+                if dirent.name == "." || dirent.name == ".." || !seen.insert(dirent.name.clone()) {
+                    continue;
+                }
+
+                // A device in the directory's entry might be a whiteout.
+                if let FileType::CharDevice(_) = dirent.file_type
+                    && is_whiteout(&dir.lookup(&dirent.name).await?.getattr().await?)
+                {
+                    continue;
+                }
+
+                entries.push((dirent.name, dirent.id, dirent.file_type));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Empties the upper directory of a merged directory that is about to be
+    /// removed, which can only hold whiteouts.
+    async fn clear_whiteouts(&self) -> Result<()> {
+        let Some(upper) = self.upper() else {
+            return Ok(());
+        };
+
+        let mut names = Vec::new();
+        let mut stream = upper.readdir(0).await?;
+
+        while let Some(dirent) = stream.next_entry().await? {
+            if dirent.name != "." && dirent.name != ".." {
+                names.push(dirent.name);
+            }
+        }
+
+        for name in names {
+            upper.unlink(&name).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure the directory `child` may be removed or replaced.
+    async fn check_removable(child: &OverlayInode<C>) -> Result<()> {
+        if child.file_type == FileType::Directory && !child.merged_entries().await?.is_empty() {
+            return Err(FsError::DirectoryNotEmpty.into());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the overlay inode of `inode`, which must be on this overlay.
+    fn downcast(&self, inode: &Arc<dyn Inode>) -> Result<Arc<Self>> {
+        let other = inode
+            .as_any()
+            .downcast_ref::<Self>()
+            .ok_or(FsError::CrossDevice)?;
+
+        if !Weak::ptr_eq(&other.fs, &self.fs) {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        other.this()
+    }
+}
+
+impl<C: CpuOps> Drop for OverlayInode<C> {
+    fn drop(&mut self) {
+        if let Some(fs) = self.fs.upgrade() {
+            let mut table = fs.inodes.lock_save_irq();
+
+            if table
+                .live
+                .get(&self.ino)
+                .is_some_and(|inode| inode.strong_count() == 0)
+            {
+                table.live.remove(&self.ino);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<C: CpuOps> Inode for OverlayInode<C> {
+    fn id(&self) -> InodeId {
+        InodeId::from_fsid_and_inodeid(self.fs.upgrade().map_or(0, |fs| fs.id), self.ino)
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.real().read_at(offset, buf).await
+    }
+
+    async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        self.copy_up().await?.write_at(offset, buf).await
+    }
+
+    async fn readahead(&self, offset: u64, len: u64) -> Result<()> {
+        self.real().readahead(offset, len).await
+    }
+
+    async fn probe_extents(&self, range: Range<u64>) -> Result<Vec<Range<u64>>> {
+        self.real().probe_extents(range).await
+    }
+
+    async fn truncate(&self, size: u64) -> Result<()> {
+        self.copy_up().await?.truncate(size).await
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        let mut attr = self.real().getattr().await?;
+
+        attr.id = self.id();
+
+        Ok(attr)
+    }
+
+    async fn setattr(&self, mut attr: FileAttr) -> Result<()> {
+        let upper = self.copy_up().await?;
+
+        attr.id = upper.getattr().await?.id;
+        upper.setattr(attr).await
+    }
+
+    async fn getxattr(&self, name: &str) -> Result<Vec<u8>> {
+        if name.starts_with(OVERLAY_XATTR_PREFIX) {
+            return Err(FsError::NotFound.into());
+        }
+
+        self.real().getxattr(name).await
+    }
+
+    async fn setxattr(&self, name: &str, buf: &[u8], create: bool, replace: bool) -> Result<()> {
+        if name.starts_with(OVERLAY_XATTR_PREFIX) {
+            return Err(FsError::PermissionDenied.into());
+        }
+
+        self.copy_up()
+            .await?
+            .setxattr(name, buf, create, replace)
+            .await
+    }
+
+    async fn removexattr(&self, name: &str) -> Result<()> {
+        if name.starts_with(OVERLAY_XATTR_PREFIX) {
+            return Err(FsError::NotFound.into());
+        }
+
+        self.copy_up().await?.removexattr(name).await
+    }
+
+    async fn listxattr(&self) -> Result<Vec<String>> {
+        let mut names = self.real().listxattr().await?;
+
+        names.retain(|name| !name.starts_with(OVERLAY_XATTR_PREFIX));
+
+        Ok(names)
+    }
+
+    async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let this = self.this()?;
+
+        match name {
+            "." => return Ok(this),
+            ".." => {
+                return Ok(match self.parent.lock_save_irq().clone() {
+                    Some((parent, _)) => parent,
+                    None => this,
+                });
+            }
+            _ => {}
+        }
+
+        let (upper, lowers, file_type) =
+            self.lookup_merged(name).await?.ok_or(FsError::NotFound)?;
+
+        Ok(self.fs()?.get_inode(&this, name, file_type, upper, lowers))
+    }
+
+    async fn create(
+        &self,
+        name: &str,
+        file_type: FileType,
+        permissions: FilePermissions,
+        owner: FileOwner,
+        time: Option<Duration>,
+    ) -> Result<Arc<dyn Inode>> {
+        let (upper, opaque) = self.prepare_create(name).await?;
+        let inode = upper
+            .create(name, file_type, permissions, owner, time)
+            .await?;
+
+        if file_type == FileType::Directory && opaque {
+            inode.setxattr(OPAQUE_XATTR, b"y", false, false).await?;
+        }
+
+        Ok(self
+            .fs()?
+            .get_inode(&self.this()?, name, file_type, Some(inode), Vec::new()))
+    }
+
+    async fn unlink(&self, name: &str) -> Result<()> {
+        let child = self.lookup(name).await?;
+        let child = self.downcast(&child)?;
+
+        Self::check_removable(&child).await?;
+
+        let upper = self.copy_up().await?;
+
+        if child.upper().is_some() {
+            child.clear_whiteouts().await?;
+            upper.unlink(name).await?;
+        }
+
+        if self.lower_has(name).await? {
+            Self::whiteout(&upper, name).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn link(&self, name: &str, inode: Arc<dyn Inode>) -> Result<()> {
+        let target = self.downcast(&inode)?.copy_up().await?;
+        let (upper, _) = self.prepare_create(name).await?;
+
+        upper.link(name, target).await
+    }
+
+    async fn symlink(&self, name: &str, target: &Path, owner: FileOwner) -> Result<()> {
+        let (upper, _) = self.prepare_create(name).await?;
+
+        upper.symlink(name, target, owner).await
+    }
+
+    async fn rename_from(
+        &self,
+        old_parent: Arc<dyn Inode>,
+        old_name: &str,
+        new_name: &str,
+        no_replace: bool,
+    ) -> Result<()> {
+        let old_parent = self.downcast(&old_parent)?;
+        let child = old_parent.lookup(old_name).await?;
+        let child = self.downcast(&child)?;
+
+        // Moving a merged directory would need its lower layers to follow it.
+        if child.file_type == FileType::Directory && !child.lowers.is_empty() {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        if Arc::ptr_eq(&old_parent, &self.this()?) && old_name == new_name {
+            return Ok(());
+        }
+
+        let replaced = match self.lookup(new_name).await {
+            Ok(inode) => Some(self.downcast(&inode)?),
+            Err(KernelError::Fs(FsError::NotFound)) => None,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(replaced) = replaced.as_ref() {
+            if no_replace {
+                return Err(FsError::AlreadyExists.into());
+            }
+
+            match (child.file_type, replaced.file_type) {
+                (FileType::Directory, FileType::Directory) => {}
+                (FileType::Directory, _) => return Err(FsError::NotADirectory.into()),
+                (_, FileType::Directory) => return Err(FsError::IsADirectory.into()),
+                _ => {}
+            }
+
+            Self::check_removable(replaced).await?;
+        }
+
+        let child_upper = child.copy_up().await?;
+        let old_upper = old_parent.copy_up().await?;
+        let new_upper = self.copy_up().await?;
+
+        // Whatever is at the new name in the upper layer makes way.
+        if let Some(replaced) = replaced.as_ref()
+            && replaced.upper().is_some()
+        {
+            replaced.clear_whiteouts().await?;
+            new_upper.unlink(new_name).await?;
+        } else if self.upper_has_whiteout(new_name).await? {
+            new_upper.unlink(new_name).await?;
+        }
+
+        let hides_lower = self.lower_has(new_name).await?;
+
+        new_upper
+            .rename_from(old_upper.clone(), old_name, new_name, false)
+            .await?;
+
+        if old_parent.lower_has(old_name).await? {
+            Self::whiteout(&old_upper, old_name).await?;
+        }
+
+        if child.file_type == FileType::Directory && hides_lower {
+            child_upper
+                .setxattr(OPAQUE_XATTR, b"y", false, false)
+                .await?;
+        }
+
+        *child.parent.lock_save_irq() = Some((self.this()?, new_name.to_string()));
+
+        Ok(())
+    }
+
+    async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
+        if self.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        let fs = self.fs()?;
+        let parent_id = self
+            .parent
+            .lock_save_irq()
+            .as_ref()
+            .map_or(self.id(), |(parent, _)| parent.id());
+
+        let mut entries = vec![
+            (".".to_string(), self.id(), FileType::Directory),
+            ("..".to_string(), parent_id, FileType::Directory),
+        ];
+
+        entries.extend(self.merged_entries().await?.into_iter().map(
+            |(name, layer_id, file_type)| {
+                let id = InodeId::from_fsid_and_inodeid(fs.id, fs.ino_for(layer_id));
+
+                (name, id, file_type)
+            },
+        ));
+
+        let dirents = entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, id, file_type))| Dirent::new(name, id, file_type, i as u64 + 1))
+            .collect();
+
+        Ok(Box::new(SimpleDirStream::new(dirents, start_offset)))
+    }
+
+    async fn readlink(&self) -> Result<PathBuf> {
+        self.real().readlink().await
+    }
+
+    async fn get_page(&self, pg_idx: u64) -> Result<PageFrame> {
+        self.upper()
+            .ok_or(KernelError::NotSupported)?
+            .get_page(pg_idx)
+            .await
+    }
+
+    fn can_share_pages(&self) -> bool {
+        // Pages of a lower file mustn't be written through a shared mapping.
+        self.upper().is_some_and(|upper| upper.can_share_pages())
+    }
+
+    async fn datasync(&self) -> Result<()> {
+        match self.upper() {
+            Some(upper) => upper.datasync().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn sync(&self) -> Result<()> {
+        match self.upper() {
+            Some(upper) => upper.sync().await,
+            None => Ok(()),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::filesystems::tmpfs::TmpFs;
+    use crate::memory::address::IdentityTranslator;
+    use crate::memory::allocators::phys::tests::TestFixture;
+    use crate::memory::allocators::phys::{FrameAllocator, PageAllocGetter};
+    use crate::sync::once_lock::OnceLock;
+    use crate::test::MockCpuOps;
+
+    static PG_ALLOC: OnceLock<FrameAllocator<MockCpuOps>, MockCpuOps> = OnceLock::new();
+
+    struct TestPgAllocGetter {}
+
+    impl PageAllocGetter<MockCpuOps> for TestPgAllocGetter {
+        fn global_page_alloc() -> &'static FrameAllocator<MockCpuOps> {
+            PG_ALLOC.get().expect("Test not initalised")
+        }
+    }
+
+    type TestTmpFs = TmpFs<MockCpuOps, TestPgAllocGetter, IdentityTranslator>;
+
+    /// Returns the root of a fresh tmpfs. Tmpfs inodes only hold a weak
+    /// reference to their filesystem, so it's leaked to outlive the test.
+    async fn new_tmpfs(id: u64) -> Arc<dyn Inode> {
+        PG_ALLOC.get_or_init(|| TestFixture::new(&[(0, 8 * 1024 * 1024)], &[]).leak_allocator());
+        let fs: Arc<TestTmpFs> = TmpFs::new(id);
+        let root = fs.root_inode().await.unwrap();
+        core::mem::forget(fs);
+        root
+    }
+
+    async fn mkfile(dir: &Arc<dyn Inode>, name: &str, data: &[u8]) -> Arc<dyn Inode> {
+        let file = dir
+            .create(
+                name,
+                FileType::File,
+                FilePermissions::all(),
+                FileOwner::root(),
+                None,
+            )
+            .await
+            .unwrap();
+        file.write_at(0, data).await.unwrap();
+        file
+    }
+
+    async fn mkdir(dir: &Arc<dyn Inode>, name: &str) -> Arc<dyn Inode> {
+        dir.create(
+            name,
+            FileType::Directory,
+            FilePermissions::all(),
+            FileOwner::root(),
+            None,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn read_all(inode: &Arc<dyn Inode>) -> Vec<u8> {
+        let mut buf = vec![0; 64];
+        let len = inode.read_at(0, &mut buf).await.unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    async fn names(dir: &Arc<dyn Inode>) -> Vec<String> {
+        let mut stream = dir.readdir(0).await.unwrap();
+        let mut names = Vec::new();
+
+        while let Some(dirent) = stream.next_entry().await.unwrap() {
+            names.push(dirent.name);
+        }
+
+        names.sort();
+        names
+    }
+
+    /// An overlay of an empty upper tmpfs on a lower one holding `/f` and
+    /// `/d/x`.
+    async fn setup() -> (Arc<OverlayFs<MockCpuOps>>, Arc<dyn Inode>, Arc<dyn Inode>) {
+        let lower = new_tmpfs(1).await;
+        let upper = new_tmpfs(2).await;
+
+        mkfile(&lower, "f", b"lower").await;
+        let d = mkdir(&lower, "d").await;
+        mkfile(&d, "x", b"x").await;
+
+        let fs = OverlayFs::new(3, Some(upper.clone()), vec![lower.clone()]);
+
+        (fs, upper, lower)
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let opts = OverlayOptions::parse("lowerdir=/a:/b,upperdir=/u,workdir=/w").unwrap();
+        assert_eq!(opts.lowerdirs, vec!["/a", "/b"]);
+        assert_eq!(opts.upperdir.as_deref(), Some("/u"));
+
+        assert!(OverlayOptions::parse("lowerdir=/a").is_ok());
+        assert!(OverlayOptions::parse("upperdir=/u,workdir=/w").is_err());
+        assert!(OverlayOptions::parse("lowerdir=/a,upperdir=/u").is_err());
+        assert!(OverlayOptions::parse("lowerdir=/a::/b").is_err());
+        assert!(OverlayOptions::parse("lowerdir=/a,bogus=1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_merged_dirs() {
+        let (fs, upper, _) = setup().await;
+        let upper_d = mkdir(&upper, "d").await;
+        mkfile(&upper_d, "y", b"y").await;
+        mkfile(&upper, "f", b"upper").await;
+
+        let root = fs.root_inode().await.unwrap();
+        assert_eq!(read_all(&root.lookup("f").await.unwrap()).await, b"upper");
+        assert_eq!(names(&root).await, vec![".", "..", "d", "f"]);
+
+        let d = root.lookup("d").await.unwrap();
+        assert_eq!(names(&d).await, vec![".", "..", "x", "y"]);
+        assert_eq!(read_all(&d.lookup("x").await.unwrap()).await, b"x");
+        assert_eq!(d.lookup("..").await.unwrap().id(), root.id());
+    }
+
+    #[tokio::test]
+    async fn test_copy_up_on_write() {
+        let (fs, upper, lower) = setup().await;
+        let root = fs.root_inode().await.unwrap();
+
+        let x = root.lookup("d").await.unwrap().lookup("x").await.unwrap();
+        let id = x.id();
+        x.write_at(1, b"yz").await.unwrap();
+
+        assert_eq!(read_all(&x).await, b"xyz");
+        assert_eq!(x.getattr().await.unwrap().id, id);
+
+        // The directory above was copied up, and the lower file is untouched.
+        let upper_x = upper.lookup("d").await.unwrap().lookup("x").await.unwrap();
+        assert_eq!(read_all(&upper_x).await, b"xyz");
+        let lower_x = lower.lookup("d").await.unwrap().lookup("x").await.unwrap();
+        assert_eq!(read_all(&lower_x).await, b"x");
+
+        // The copy keeps the inode number once it's looked up afresh.
+        drop(x);
+        let x = root.lookup("d").await.unwrap().lookup("x").await.unwrap();
+        assert_eq!(x.id(), id);
+    }
+
+    #[tokio::test]
+    async fn test_unlink_leaves_whiteout() {
+        let (fs, upper, lower) = setup().await;
+        let root = fs.root_inode().await.unwrap();
+
+        root.unlink("f").await.unwrap();
+
+        assert!(matches!(
+            root.lookup("f").await,
+            Err(KernelError::Fs(FsError::NotFound))
+        ));
+        assert_eq!(names(&root).await, vec![".", "..", "d"]);
+        assert!(is_whiteout(
+            &upper.lookup("f").await.unwrap().getattr().await.unwrap()
+        ));
+        assert!(lower.lookup("f").await.is_ok());
+
+        // Making it again replaces the whiteout.
+        let f = root
+            .create(
+                "f",
+                FileType::File,
+                FilePermissions::all(),
+                FileOwner::root(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(read_all(&f).await, b"");
+        assert_eq!(names(&root).await, vec![".", "..", "d", "f"]);
+    }
+
+    #[tokio::test]
+    async fn test_rmdir_and_opaque_mkdir() {
+        let (fs, _, _) = setup().await;
+        let root = fs.root_inode().await.unwrap();
+
+        assert!(matches!(
+            root.unlink("d").await,
+            Err(KernelError::Fs(FsError::DirectoryNotEmpty))
+        ));
+
+        let d = root.lookup("d").await.unwrap();
+        d.unlink("x").await.unwrap();
+        assert_eq!(names(&d).await, vec![".", ".."]);
+        root.unlink("d").await.unwrap();
+        assert!(root.lookup("d").await.is_err());
+
+        let d = root
+            .create(
+                "d",
+                FileType::Directory,
+                FilePermissions::all(),
+                FileOwner::root(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(names(&d).await, vec![".", ".."]);
+        assert!(d.lookup("x").await.is_err());
+
+        // The opaque marker is kept from view.
+        assert!(d.listxattr().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let (fs, _, lower) = setup().await;
+        let root = fs.root_inode().await.unwrap();
+
+        // A directory that's in a lower layer can't move.
+        assert!(matches!(
+            root.rename_from(root.clone(), "d", "e", false).await,
+            Err(KernelError::Fs(FsError::CrossDevice))
+        ));
+
+        root.rename_from(root.clone(), "f", "g", false)
+            .await
+            .unwrap();
+        assert!(root.lookup("f").await.is_err());
+        assert_eq!(read_all(&root.lookup("g").await.unwrap()).await, b"lower");
+        assert!(lower.lookup("f").await.is_ok());
+
+        let d = root.lookup("d").await.unwrap();
+        d.rename_from(root.clone(), "g", "x", false).await.unwrap();
+        assert_eq!(read_all(&d.lookup("x").await.unwrap()).await, b"lower");
+        assert_eq!(names(&root).await, vec![".", "..", "d"]);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_without_upper() {
+        let (_, _, lower) = setup().await;
+        let fs = OverlayFs::<MockCpuOps>::new(4, None, vec![lower]);
+        let root = fs.root_inode().await.unwrap();
+        let f = root.lookup("f").await.unwrap();
+
+        assert_eq!(read_all(&f).await, b"lower");
+        assert!(matches!(
+            f.write_at(0, b"x").await,
+            Err(KernelError::Fs(FsError::ReadOnly))
+        ));
+        assert!(matches!(
+            root.unlink("f").await,
+            Err(KernelError::Fs(FsError::ReadOnly))
+        ));
+    }
+}
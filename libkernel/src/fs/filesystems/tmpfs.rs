@@ -4,17 +4,20 @@ use crate::{
     CpuOps,
     error::{FsError, KernelError, Result},
     fs::{
-        DirStream, Dirent, FileType, Filesystem, Inode, InodeId,
-        attr::{FileAttr, FilePermissions},
+        DirStream, Dirent, FallocMode, FileType, Filesystem, FsStats, Inode, InodeId,
+        attr::{FileAttr, FileOwner, FilePermissions},
         path::Path,
         pathbuf::PathBuf,
+        quota::{QuotaOps, QuotaTable},
     },
     memory::{
         PAGE_SIZE,
         address::{AddressTranslator, VA},
         allocators::phys::PageAllocGetter,
         claimed_page::ClaimedPage,
+        page::PageFrame,
     },
+    proc::ids::{Gid, Uid},
     sync::spinlock::SpinLockIrq,
 };
 use alloc::{
@@ -31,6 +34,7 @@ use core::{
     cmp::min,
     marker::PhantomData,
     mem::size_of,
+    ops::Range,
     sync::atomic::{AtomicU64, Ordering},
 };
 
@@ -40,6 +44,170 @@ const BLOCK_SZ: usize = PAGE_SIZE;
 // block)
 const MAX_SZ: usize = BLOCK_SZ * (PAGE_SIZE / size_of::<*mut u8>());
 
+/// Options a tmpfs is mounted with, given to mount(2) as a comma-separated
+/// list in its `data` argument.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TmpFsOptions {
+    /// The most space the files may take up, in bytes, or zero for no limit.
+    pub size: u64,
+    /// The most inodes that may exist, or zero for no limit.
+    pub nr_inodes: u64,
+    /// The permissions of the root directory.
+    pub mode: Option<FilePermissions>,
+    /// The owner of the root directory.
+    pub uid: Option<Uid>,
+    /// The group of the root directory.
+    pub gid: Option<Gid>,
+}
+
+impl TmpFsOptions {
+    /// Parses a mount option string such as `size=64m,mode=1777`.
+    ///
+    /// Sizes take an optional `k`, `m`, `g` or `t` suffix and are rounded up
+    /// to a whole number of pages.
+    pub fn parse(options: &str) -> Result<Self> {
+        let mut opts = Self::default();
+
+        for opt in options.split(',').filter(|opt| !opt.is_empty()) {
+            let (key, value) = opt.split_once('=').ok_or(KernelError::InvalidValue)?;
+
+            match key {
+                "size" => {
+                    opts.size = parse_size(value)?
+                        .checked_next_multiple_of(PAGE_SIZE as u64)
+                        .ok_or(KernelError::InvalidValue)?;
+                }
+                "nr_inodes" => opts.nr_inodes = parse_size(value)?,
+                "mode" => {
+                    let mode =
+                        u16::from_str_radix(value, 8).map_err(|_| KernelError::InvalidValue)?;
+
+                    opts.mode =
+                        Some(FilePermissions::from_bits(mode).ok_or(KernelError::InvalidValue)?);
+                }
+                "uid" => opts.uid = Some(Uid::new(parse_id(value)?)),
+                "gid" => opts.gid = Some(Gid::new(parse_id(value)?)),
+                _ => return Err(KernelError::InvalidValue),
+            }
+        }
+
+        Ok(opts)
+    }
+}
+
+fn parse_id(value: &str) -> Result<u32> {
+    value.parse().map_err(|_| KernelError::InvalidValue)
+}
+
+fn parse_size(value: &str) -> Result<u64> {
+    let (digits, shift) = match value.as_bytes().last() {
+        Some(b'k' | b'K') => (&value[..value.len() - 1], 10),
+        Some(b'm' | b'M') => (&value[..value.len() - 1], 20),
+        Some(b'g' | b'G') => (&value[..value.len() - 1], 30),
+        Some(b't' | b'T') => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+
+    let n: u64 = digits.parse().map_err(|_| KernelError::InvalidValue)?;
+
+    n.checked_mul(1 << shift).ok_or(KernelError::InvalidValue)
+}
+
+struct UsageInner {
+    bytes: u64,
+    inodes: u64,
+}
+
+/// Space and inode accounting for a tmpfs instance.
+///
+/// Everything is charged to the filesystem as a whole, against the limits it
+/// was mounted with, and to the owning user in the per-user quota table.
+/// Running out of room on the filesystem fails with [`FsError::NoSpace`].
+struct TmpFsUsage<C: CpuOps> {
+    quota: QuotaTable<C>,
+    max_bytes: u64,
+    max_inodes: u64,
+    inner: SpinLockIrq<UsageInner, C>,
+}
+
+impl<C: CpuOps> TmpFsUsage<C> {
+    fn new(max_bytes: u64, max_inodes: u64) -> Self {
+        Self {
+            quota: QuotaTable::new(),
+            max_bytes,
+            max_inodes,
+            inner: SpinLockIrq::new(UsageInner {
+                bytes: 0,
+                inodes: 0,
+            }),
+        }
+    }
+
+    fn charge_space(&self, uid: Uid, bytes: u64) -> Result<()> {
+        {
+            let mut inner = self.inner.lock_save_irq();
+            let new_bytes = inner.bytes.saturating_add(bytes);
+
+            if bytes != 0 && self.max_bytes != 0 && new_bytes > self.max_bytes {
+                return Err(FsError::NoSpace.into());
+            }
+
+            inner.bytes = new_bytes;
+        }
+
+        self.quota.charge_space(uid, bytes).inspect_err(|_| {
+            let mut inner = self.inner.lock_save_irq();
+            inner.bytes = inner.bytes.saturating_sub(bytes);
+        })
+    }
+
+    fn release_space(&self, uid: Uid, bytes: u64) {
+        {
+            let mut inner = self.inner.lock_save_irq();
+            inner.bytes = inner.bytes.saturating_sub(bytes);
+        }
+
+        self.quota.release_space(uid, bytes);
+    }
+
+    fn charge_inode(&self, uid: Uid) -> Result<()> {
+        {
+            let mut inner = self.inner.lock_save_irq();
+
+            if self.max_inodes != 0 && inner.inodes >= self.max_inodes {
+                return Err(FsError::NoSpace.into());
+            }
+
+            inner.inodes += 1;
+        }
+
+        self.quota.charge_inode(uid).inspect_err(|_| {
+            let mut inner = self.inner.lock_save_irq();
+            inner.inodes = inner.inodes.saturating_sub(1);
+        })
+    }
+
+    fn release_inode(&self, uid: Uid) {
+        {
+            let mut inner = self.inner.lock_save_irq();
+            inner.inodes = inner.inodes.saturating_sub(1);
+        }
+
+        self.quota.release_inode(uid);
+    }
+
+    /// Returns the `(bytes, inodes)` in use.
+    fn used(&self) -> (u64, u64) {
+        let inner = self.inner.lock_save_irq();
+
+        (inner.bytes, inner.inodes)
+    }
+
+    fn transfer(&self, from: Uid, to: Uid, bytes: u64) -> Result<()> {
+        self.quota.transfer(from, to, bytes)
+    }
+}
+
 struct TmpFsRegInner<C, G, T>
 where
     C: CpuOps,
@@ -48,7 +216,10 @@ where
 {
     indirect_block: ClaimedPage<C, G, T>,
     size: usize,
+    /// The number of slots in use. A null slot below this is a punched hole.
     allocated_blocks: usize,
+    /// The number of blocks actually backed by a page.
+    resident_blocks: usize,
 }
 
 impl<C, G, T> TmpFsRegInner<C, G, T>
@@ -71,21 +242,82 @@ where
         unsafe { *self.block_slot_ptr(block_idx) }
     }
 
+    /// The number of pages `try_alloc_block(block_idx)` would allocate.
+    fn blocks_to_alloc(&mut self, block_idx: usize) -> usize {
+        if block_idx >= self.allocated_blocks {
+            block_idx + 1 - self.allocated_blocks
+        } else {
+            self.block_ptr_mut(block_idx).is_null() as usize
+        }
+    }
+
+    fn alloc_slot(&mut self, block_idx: usize) -> Result<()> {
+        let new_page = ClaimedPage::<C, G, T>::alloc_zeroed()?;
+
+        unsafe {
+            *self.block_slot_ptr(block_idx) = new_page.as_ptr_mut();
+        }
+
+        new_page.leak();
+        self.resident_blocks += 1;
+
+        Ok(())
+    }
+
     fn try_alloc_block(&mut self, block_idx: usize) -> Result<*mut u8> {
         // Ensure no discontinuity. If we write to block 5, blocks 0-4 must exist.
         // We iterate up to and including the target block_idx.
         for i in self.allocated_blocks..=block_idx {
-            let new_page = ClaimedPage::<C, G, T>::alloc_zeroed()?;
+            self.alloc_slot(i)?;
+            self.allocated_blocks += 1;
+        }
 
-            unsafe {
-                *self.block_slot_ptr(i) = new_page.as_ptr_mut();
+        // Fill in a punched hole.
+        if self.block_ptr_mut(block_idx).is_null() {
+            self.alloc_slot(block_idx)?;
+        }
+
+        Ok(self.block_ptr_mut(block_idx))
+    }
+
+    /// Returns the page behind `block_idx` to the allocator, leaving a hole.
+    /// Returns `false` if there was no page.
+    fn free_block(&mut self, block_idx: usize) -> bool {
+        let ptr_slot = self.block_slot_ptr(block_idx);
+
+        unsafe {
+            let ptr = *ptr_slot;
+
+            if ptr.is_null() {
+                return false;
             }
 
-            new_page.leak();
-            self.allocated_blocks += 1;
+            // Reconstruct the ClaimedPage to drop it (returning frame to allocator)
+            drop(ClaimedPage::<C, G, T>::from_pfn(
+                VA::from_ptr_mut(ptr.cast()).to_pa::<T>().to_pfn(),
+            ));
+
+            // Null the slot to prevent double-free in Drop
+            *ptr_slot = core::ptr::null_mut();
         }
 
-        Ok(self.block_ptr_mut(block_idx))
+        self.resident_blocks -= 1;
+
+        true
+    }
+
+    /// Zeroes `len` bytes from `offset` within block `block_idx`, if it has a
+    /// page.
+    fn zero_in_block(&mut self, block_idx: usize, offset: usize, len: usize) {
+        if block_idx >= self.allocated_blocks {
+            return;
+        }
+
+        let ptr = self.block_ptr_mut(block_idx);
+
+        if !ptr.is_null() {
+            unsafe { ptr.add(offset).write_bytes(0, len) };
+        }
     }
 }
 
@@ -122,6 +354,7 @@ where
     id: InodeId,
     attr: SpinLockIrq<FileAttr, C>,
     inner: SpinLockIrq<TmpFsRegInner<C, G, T>, C>,
+    usage: Arc<TmpFsUsage<C>>,
 }
 
 impl<C, G, T> TmpFsReg<C, G, T>
@@ -130,7 +363,12 @@ where
     G: PageAllocGetter<C>,
     T: AddressTranslator<()>,
 {
-    fn new(id: InodeId, permissions: FilePermissions) -> Result<Self> {
+    fn new(
+        id: InodeId,
+        permissions: FilePermissions,
+        owner: FileOwner,
+        usage: Arc<TmpFsUsage<C>>,
+    ) -> Result<Self> {
         Ok(Self {
             id,
             attr: SpinLockIrq::new(FileAttr {
@@ -138,16 +376,24 @@ where
                 size: 0,
                 nlinks: 1,
                 permissions,
+                uid: owner.uid,
+                gid: owner.gid,
                 ..Default::default()
             }),
             inner: SpinLockIrq::new(TmpFsRegInner {
                 indirect_block: ClaimedPage::<C, G, T>::alloc_zeroed()?,
                 size: 0,
                 allocated_blocks: 0,
+                resident_blocks: 0,
             }),
+            usage,
         })
     }
 
+    fn owner(&self) -> Uid {
+        self.attr.lock_save_irq().uid
+    }
+
     fn offset_to_block_locus(offset: usize) -> (usize, usize) {
         (offset / BLOCK_SZ, offset % BLOCK_SZ)
     }
@@ -178,18 +424,24 @@ where
         while bytes_to_read > 0 {
             let (blk_idx, blk_offset) = Self::offset_to_block_locus(offset as _);
 
-            // Check if this block is actually allocated (sparse file protection)
-            // though try_alloc_block enforces continuity, so this check is sanity.
-            if blk_idx >= inner.allocated_blocks {
-                break;
-            }
-
             let bytes_in_block = BLOCK_SZ - blk_offset;
             let chunk_len = min(bytes_to_read, bytes_in_block);
 
+            // Holes, and blocks past the end of a file extended by truncate,
+            // read as zeroes.
+            let src = if blk_idx < inner.allocated_blocks {
+                inner.block_ptr_mut(blk_idx)
+            } else {
+                core::ptr::null_mut()
+            };
+
             unsafe {
-                let src = inner.block_ptr_mut(blk_idx).add(blk_offset);
-                src.copy_to_nonoverlapping(buf_ptr, chunk_len);
+                if src.is_null() {
+                    buf_ptr.write_bytes(0, chunk_len);
+                } else {
+                    src.add(blk_offset)
+                        .copy_to_nonoverlapping(buf_ptr, chunk_len);
+                }
                 buf_ptr = buf_ptr.add(chunk_len);
             };
 
@@ -222,8 +474,28 @@ where
         while bytes_to_write > 0 {
             let (blk_idx, blk_offset) = Self::offset_to_block_locus(offset as _);
 
+            // Charge the owner for any blocks that need allocating before
+            // touching the allocator. A partial write is reported if the quota
+            // or the filesystem's space runs out part way through.
+            let new_bytes = (inner.blocks_to_alloc(blk_idx) * BLOCK_SZ) as u64;
+            let owner = self.owner();
+
+            if let Err(e) = self.usage.charge_space(owner, new_bytes) {
+                if total_written > 0 {
+                    break;
+                }
+
+                return Err(e);
+            }
+
             // Ensure the block exists
-            let block_ptr = inner.try_alloc_block(blk_idx)?;
+            let block_ptr = match inner.try_alloc_block(blk_idx) {
+                Ok(ptr) => ptr,
+                Err(e) => {
+                    self.usage.release_space(owner, new_bytes);
+                    return Err(e);
+                }
+            };
 
             let bytes_in_block = BLOCK_SZ - blk_offset;
             let chunk_len = min(bytes_to_write, bytes_in_block);
@@ -270,31 +542,19 @@ where
         if new_size < inner.size {
             // Calculate number of blocks required for the new size.
             let new_blk_count = new_size.div_ceil(BLOCK_SZ);
+            let mut freed = 0;
 
             // Free the excess blocks from the end
             while inner.allocated_blocks > new_blk_count {
                 let release_idx = inner.allocated_blocks - 1;
 
-                unsafe {
-                    let ptr_slot = inner.block_slot_ptr(release_idx);
-                    let ptr = *ptr_slot;
-
-                    if !ptr.is_null() {
-                        // Reconstruct the ClaimedPage to drop it (returning frame to allocator)
-                        let page = ClaimedPage::<C, G, T>::from_pfn(
-                            VA::from_ptr_mut(ptr.cast()).to_pa::<T>().to_pfn(),
-                        );
-
-                        drop(page);
-
-                        // Null the slot to prevent double-free in Drop
-                        *ptr_slot = core::ptr::null_mut();
-                    }
-                }
-
+                freed += inner.free_block(release_idx) as usize;
                 inner.allocated_blocks -= 1;
             }
 
+            self.usage
+                .release_space(self.owner(), (freed * BLOCK_SZ) as u64);
+
             // Zero out trailing data in the last retained page. This is POSIX
             // behavior: bytes past the new EOF must appear as zero if we extend
             // the file later.
@@ -303,12 +563,7 @@ where
                 let offset_in_block = new_size % BLOCK_SZ;
 
                 if offset_in_block > 0 {
-                    let ptr = inner.block_ptr_mut(last_blk_idx);
-                    unsafe {
-                        let tail_ptr = ptr.add(offset_in_block);
-                        let tail_len = BLOCK_SZ - offset_in_block;
-                        tail_ptr.write_bytes(0, tail_len);
-                    }
+                    inner.zero_in_block(last_blk_idx, offset_in_block, BLOCK_SZ - offset_in_block);
                 }
             }
 
@@ -319,12 +574,144 @@ where
         Ok(())
     }
 
+    async fn fallocate(&self, mode: FallocMode, offset: u64, len: u64) -> Result<()> {
+        let end = offset.checked_add(len).ok_or(FsError::OutOfBounds)? as usize;
+        let offset = offset as usize;
+        let mut inner = self.inner.lock_save_irq();
+
+        match mode {
+            FallocMode::Allocate { keep_size } => {
+                if end > MAX_SZ {
+                    return Err(FsError::OutOfBounds.into());
+                }
+
+                let owner = self.owner();
+
+                for blk_idx in offset / BLOCK_SZ..end.div_ceil(BLOCK_SZ) {
+                    let new_bytes = (inner.blocks_to_alloc(blk_idx) * BLOCK_SZ) as u64;
+
+                    self.usage.charge_space(owner, new_bytes)?;
+
+                    if let Err(e) = inner.try_alloc_block(blk_idx) {
+                        self.usage.release_space(owner, new_bytes);
+                        return Err(e);
+                    }
+                }
+
+                if !keep_size && end > inner.size {
+                    inner.size = end;
+                    self.attr.lock_save_irq().size = end as _;
+                }
+            }
+            FallocMode::PunchHole => {
+                // Nothing past the last slot has storage to free.
+                let end = min(end, inner.allocated_blocks * BLOCK_SZ);
+                let mut freed = 0;
+                let mut pos = offset;
+
+                while pos < end {
+                    let (blk_idx, blk_offset) = Self::offset_to_block_locus(pos);
+                    let chunk_len = min(end - pos, BLOCK_SZ - blk_offset);
+
+                    if chunk_len == BLOCK_SZ {
+                        freed += inner.free_block(blk_idx) as usize;
+                    } else {
+                        inner.zero_in_block(blk_idx, blk_offset, chunk_len);
+                    }
+
+                    pos += chunk_len;
+                }
+
+                self.usage
+                    .release_space(self.owner(), (freed * BLOCK_SZ) as u64);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn probe_extents(&self, range: Range<u64>) -> Result<Vec<Range<u64>>> {
+        let mut inner = self.inner.lock_save_irq();
+        let first = range.start as usize / BLOCK_SZ;
+        let last = min(
+            range.end.div_ceil(BLOCK_SZ as u64) as usize,
+            inner.allocated_blocks,
+        );
+        let mut extents: Vec<Range<u64>> = Vec::new();
+
+        // The data is in the pages that are there; a punched hole or a file
+        // extended by truncate has none behind it.
+        for blk_idx in first..last {
+            if inner.block_ptr_mut(blk_idx).is_null() {
+                continue;
+            }
+
+            let start = ((blk_idx * BLOCK_SZ) as u64).max(range.start);
+            let end = (((blk_idx + 1) * BLOCK_SZ) as u64).min(range.end);
+
+            match extents.last_mut() {
+                Some(prev) if prev.end == start => prev.end = end,
+                _ => extents.push(start..end),
+            }
+        }
+
+        Ok(extents)
+    }
+
+    async fn get_page(&self, pg_idx: u64) -> Result<PageFrame> {
+        let mut inner = self.inner.lock_save_irq();
+        let blk_idx = pg_idx as usize;
+
+        if blk_idx >= inner.size.div_ceil(BLOCK_SZ) {
+            return Err(FsError::OutOfBounds.into());
+        }
+
+        // As with a write, the owner is charged for any pages that have to be
+        // allocated to fill a hole.
+        let new_bytes = (inner.blocks_to_alloc(blk_idx) * BLOCK_SZ) as u64;
+        let owner = self.owner();
+
+        self.usage.charge_space(owner, new_bytes)?;
+
+        let block_ptr = match inner.try_alloc_block(blk_idx) {
+            Ok(ptr) => ptr,
+            Err(e) => {
+                self.usage.release_space(owner, new_bytes);
+                return Err(e);
+            }
+        };
+
+        // SAFETY: The block was allocated by `try_alloc_block`. The page is
+        // leaked again below, so the file keeps its reference.
+        let page = unsafe {
+            ClaimedPage::<C, G, T>::from_pfn(
+                VA::from_ptr_mut(block_ptr.cast()).to_pa::<T>().to_pfn(),
+            )
+        };
+        let frame = page.share();
+        page.leak();
+
+        Ok(frame)
+    }
+
+    fn can_share_pages(&self) -> bool {
+        true
+    }
+
     async fn getattr(&self) -> Result<FileAttr> {
         Ok(self.attr.lock_save_irq().clone())
     }
 
     async fn setattr(&self, attr: FileAttr) -> Result<()> {
-        self.inner.lock_save_irq().size = attr.size as _;
+        let mut inner = self.inner.lock_save_irq();
+
+        self.usage.transfer(
+            self.owner(),
+            attr.uid,
+            (inner.resident_blocks * BLOCK_SZ) as u64,
+        )?;
+
+        inner.size = attr.size as _;
         *self.attr.lock_save_irq() = attr;
         Ok(())
     }
@@ -334,6 +721,73 @@ where
     }
 }
 
+impl<C, G, T> Drop for TmpFsReg<C, G, T>
+where
+    C: CpuOps,
+    G: PageAllocGetter<C>,
+    T: AddressTranslator<()>,
+{
+    fn drop(&mut self) {
+        let owner = self.owner();
+        let allocated = self.inner.lock_save_irq().resident_blocks;
+
+        self.usage
+            .release_space(owner, (allocated * BLOCK_SZ) as u64);
+        self.usage.release_inode(owner);
+    }
+}
+
+/// The extended attributes of an inode.
+struct TmpFsXattrs<C: CpuOps>(SpinLockIrq<Vec<(String, Vec<u8>)>, C>);
+
+impl<C: CpuOps> TmpFsXattrs<C> {
+    fn new() -> Self {
+        Self(SpinLockIrq::new(Vec::new()))
+    }
+
+    fn get(&self, name: &str) -> Result<Vec<u8>> {
+        let guard = self.0.lock_save_irq();
+        if let Some((_, value)) = guard.iter().find(|(key, _)| key == name) {
+            Ok(value.clone())
+        } else {
+            Err(FsError::NotFound.into())
+        }
+    }
+
+    fn remove(&self, name: &str) -> Result<()> {
+        let mut guard = self.0.lock_save_irq();
+        if let Some(pos) = guard.iter().position(|(key, _)| key == name) {
+            guard.remove(pos);
+            Ok(())
+        } else {
+            Err(FsError::NotFound.into())
+        }
+    }
+
+    fn list(&self) -> Vec<String> {
+        let guard = self.0.lock_save_irq();
+        guard.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    fn set(&self, name: &str, buf: &[u8], create: bool, replace: bool) -> Result<()> {
+        let mut guard = self.0.lock_save_irq();
+
+        if let Some((_, value)) = guard.iter_mut().find(|(key, _)| key == name) {
+            if create {
+                return Err(FsError::AlreadyExists.into());
+            }
+            *value = buf.to_vec();
+            Ok(())
+        } else {
+            if replace {
+                return Err(FsError::NotFound.into());
+            }
+            guard.push((name.to_owned(), buf.to_vec()));
+            Ok(())
+        }
+    }
+}
+
 struct TmpFsDirEnt {
     name: String,
     id: InodeId,
@@ -352,6 +806,9 @@ where
     id: u64,
     fs: Weak<TmpFs<C, G, T>>,
     this: Weak<Self>,
+    /// The directory this one is in, or nothing for the root directory.
+    parent: SpinLockIrq<Weak<Self>, C>,
+    xattr: TmpFsXattrs<C>,
 }
 
 struct TmpFsDirReader<C, G, T>
@@ -402,6 +859,19 @@ where
     }
 
     async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        match name {
+            "." => return Ok(self.this.upgrade().unwrap()),
+            ".." => {
+                let parent = self.parent.lock_save_irq().upgrade();
+
+                return Ok(match parent {
+                    Some(parent) => parent,
+                    None => self.this.upgrade().unwrap(),
+                });
+            }
+            _ => {}
+        }
+
         self.entries
             .lock_save_irq()
             .iter()
@@ -415,7 +885,13 @@ where
     }
 
     async fn setattr(&self, attr: FileAttr) -> Result<()> {
-        *self.attrs.lock_save_irq() = attr;
+        let mut attrs = self.attrs.lock_save_irq();
+
+        if let Some(fs) = self.fs.upgrade() {
+            fs.usage.transfer(attrs.uid, attr.uid, 0)?;
+        }
+
+        *attrs = attr;
         Ok(())
     }
 
@@ -431,6 +907,7 @@ where
         name: &str,
         file_type: FileType,
         mode: FilePermissions,
+        owner: FileOwner,
         _time: Option<Duration>,
     ) -> Result<Arc<dyn Inode>> {
         let mut entries = self.entries.lock_save_irq();
@@ -440,13 +917,43 @@ where
         }
 
         let fs = self.fs.upgrade().ok_or(FsError::InvalidFs)?;
+
+        // Symlinks are made with `symlink`, which is given their target.
+        if file_type == FileType::Symlink {
+            return Err(KernelError::NotSupported);
+        }
+
+        // The inode is charged to its owner; ownership changes are accounted
+        // for in `setattr`.
+        fs.usage.charge_inode(owner.uid)?;
+
         let new_id = fs.alloc_inode_id();
         let inode_id = InodeId::from_fsid_and_inodeid(fs.id(), new_id);
 
         let inode: Arc<dyn Inode> = match file_type {
-            FileType::File => Arc::new(TmpFsReg::<C, G, T>::new(inode_id, mode)?),
-            FileType::Directory => TmpFsDirInode::<C, G, T>::new(new_id, self.fs.clone(), mode),
-            _ => return Err(KernelError::NotSupported),
+            FileType::File => {
+                match TmpFsReg::<C, G, T>::new(inode_id, mode, owner, fs.usage.clone()) {
+                    Ok(reg) => Arc::new(reg),
+                    Err(e) => {
+                        fs.usage.release_inode(owner.uid);
+                        return Err(e);
+                    }
+                }
+            }
+            FileType::Directory => TmpFsDirInode::<C, G, T>::new(
+                new_id,
+                self.fs.clone(),
+                mode,
+                owner,
+                self.this.clone(),
+            ),
+            _ => Arc::new(TmpFsNodeInode::<C>::new(
+                inode_id,
+                file_type,
+                mode,
+                owner,
+                fs.usage.clone(),
+            )),
         };
 
         entries.push(TmpFsDirEnt {
@@ -459,6 +966,30 @@ where
         Ok(inode)
     }
 
+    async fn tmpfile(
+        &self,
+        mode: FilePermissions,
+        owner: FileOwner,
+        _time: Option<Duration>,
+    ) -> Result<Arc<dyn Inode>> {
+        let fs = self.fs.upgrade().ok_or(FsError::InvalidFs)?;
+        fs.usage.charge_inode(owner.uid)?;
+
+        let inode_id = InodeId::from_fsid_and_inodeid(fs.id(), fs.alloc_inode_id());
+        let reg = match TmpFsReg::<C, G, T>::new(inode_id, mode, owner, fs.usage.clone()) {
+            Ok(reg) => reg,
+            Err(e) => {
+                fs.usage.release_inode(owner.uid);
+                return Err(e);
+            }
+        };
+
+        // Nothing refers to it until it's linked in somewhere.
+        reg.attr.lock_save_irq().nlinks = 0;
+
+        Ok(Arc::new(reg))
+    }
+
     async fn unlink(&self, name: &str) -> Result<()> {
         let mut entries = self.entries.lock_save_irq();
         let index = entries.iter().position(|e| e.name == name);
@@ -493,7 +1024,7 @@ where
         Ok(())
     }
 
-    async fn symlink(&self, name: &str, target: &Path) -> Result<()> {
+    async fn symlink(&self, name: &str, target: &Path, owner: FileOwner) -> Result<()> {
         let mut entries = self.entries.lock_save_irq();
 
         if entries.iter().any(|e| e.name == name) {
@@ -501,10 +1032,17 @@ where
         }
 
         let fs = self.fs.upgrade().ok_or(FsError::InvalidFs)?;
+        fs.usage.charge_inode(owner.uid)?;
+
         let new_id = fs.alloc_inode_id();
         let inode_id = InodeId::from_fsid_and_inodeid(fs.id(), new_id);
 
-        let inode = Arc::new(TmpFsSymlinkInode::<C>::new(inode_id, target.to_owned())?);
+        let inode = Arc::new(TmpFsSymlinkInode::<C>::new(
+            inode_id,
+            target.to_owned(),
+            owner,
+            fs.usage.clone(),
+        ));
 
         entries.push(TmpFsDirEnt {
             name: name.to_string(),
@@ -533,20 +1071,24 @@ where
         let new_name = new_name.to_owned();
         if old_parent.id().inode_id() == self.id().inode_id() {
             let mut entries = self.entries.lock_save_irq();
-            if no_replace && entries.iter().any(|e| e.name == new_name) {
-                return Err(FsError::AlreadyExists.into());
-            }
+            let source = entries
+                .iter()
+                .position(|e| e.name == old_name)
+                .ok_or(FsError::NotFound)?;
+            let target = entries.iter().position(|e| e.name == new_name);
+
+            if let Some(target) = target {
+                if no_replace {
+                    return Err(FsError::AlreadyExists.into());
+                }
 
-            let new_entry = entries.iter().position(|e| e.name == new_name);
+                Self::check_replace(&entries[source], &entries[target])?;
+            }
 
-            entries
-                .iter_mut()
-                .find(|e| e.name == old_name)
-                .ok_or(FsError::NotFound)?
-                .name = new_name;
+            entries[source].name = new_name;
 
-            if let Some(new_entry) = new_entry {
-                entries.remove(new_entry);
+            if let Some(target) = target {
+                entries.remove(target);
             }
 
             return Ok(());
@@ -576,19 +1118,7 @@ where
         } else if let Some(target_idx) = new_parent.iter().position(|e| e.name == new_name)
             && let Some(source_idx) = old_parent.iter().position(|e| e.name == old_name)
         {
-            let target = &new_parent[target_idx];
-            let source = &old_parent[source_idx];
-
-            if target.kind == FileType::Directory {
-                if !target.inode.dir_is_empty()? {
-                    return Err(FsError::DirectoryNotEmpty.into());
-                } else if source.kind != FileType::Directory {
-                    return Err(FsError::IsADirectory.into());
-                }
-            } else if source.kind != FileType::Directory {
-                return Err(FsError::NotADirectory.into());
-            }
-
+            Self::check_replace(&old_parent[source_idx], &new_parent[target_idx])?;
             new_parent.remove(target_idx);
         }
 
@@ -598,6 +1128,7 @@ where
             .ok_or(FsError::NotFound)?;
         let mut entry = old_parent.remove(idx);
         entry.name = new_name;
+        Self::reparent(&entry, self.this.clone());
         new_parent.push(entry);
 
         Ok(())
@@ -615,6 +1146,7 @@ where
 
         let second_parent = Arc::downcast::<TmpFsDirInode<C, G, T>>(second_parent)
             .map_err(|_| FsError::CrossDevice)?;
+        let second_dir = second_parent.clone();
 
         if self.id().inode_id() == second_parent.id().inode_id() {
             let mut entries = self.entries.lock_save_irq();
@@ -656,6 +1188,8 @@ where
         {
             let first = first_parent.remove(first);
             let second = second_parent.remove(second);
+            Self::reparent(&first, second_dir.this.clone());
+            Self::reparent(&second, self.this.clone());
             first_parent.push(second);
             second_parent.push(first);
             Ok(())
@@ -664,6 +1198,22 @@ where
         }
     }
 
+    async fn getxattr(&self, name: &str) -> Result<Vec<u8>> {
+        self.xattr.get(name)
+    }
+
+    async fn removexattr(&self, name: &str) -> Result<()> {
+        self.xattr.remove(name)
+    }
+
+    async fn listxattr(&self) -> Result<Vec<String>> {
+        Ok(self.xattr.list())
+    }
+
+    async fn setxattr(&self, name: &str, buf: &[u8], create: bool, replace: bool) -> Result<()> {
+        self.xattr.set(name, buf, create, replace)
+    }
+
     fn dir_is_empty(&self) -> Result<bool> {
         Ok(self.entries.lock_save_irq().is_empty())
     }
@@ -679,7 +1229,13 @@ where
     G: PageAllocGetter<C>,
     T: AddressTranslator<()>,
 {
-    pub fn new(id: u64, fs: Weak<TmpFs<C, G, T>>, permissions: FilePermissions) -> Arc<Self> {
+    pub fn new(
+        id: u64,
+        fs: Weak<TmpFs<C, G, T>>,
+        permissions: FilePermissions,
+        owner: FileOwner,
+        parent: Weak<Self>,
+    ) -> Arc<Self> {
         Arc::new_cyclic(|weak_this| Self {
             entries: SpinLockIrq::new(Vec::new()),
             attrs: SpinLockIrq::new(FileAttr {
@@ -687,20 +1243,59 @@ where
                 file_type: FileType::Directory,
                 block_size: BLOCK_SZ as _,
                 permissions,
+                uid: owner.uid,
+                gid: owner.gid,
                 ..Default::default()
             }),
             id,
             fs,
             this: weak_this.clone(),
+            parent: SpinLockIrq::new(parent),
+            xattr: TmpFsXattrs::new(),
         })
     }
+
+    /// Checks that `source` may be renamed over `target`.
+    fn check_replace(source: &TmpFsDirEnt, target: &TmpFsDirEnt) -> Result<()> {
+        match (
+            source.kind == FileType::Directory,
+            target.kind == FileType::Directory,
+        ) {
+            (true, true) if !target.inode.dir_is_empty()? => Err(FsError::DirectoryNotEmpty.into()),
+            (true, false) => Err(FsError::NotADirectory.into()),
+            (false, true) => Err(FsError::IsADirectory.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Points `entry`'s `..` at `parent` if it's a directory that has just
+    /// been moved there.
+    fn reparent(entry: &TmpFsDirEnt, parent: Weak<Self>) {
+        if let Some(dir) = entry.inode.as_any().downcast_ref::<Self>() {
+            *dir.parent.lock_save_irq() = parent;
+        }
+    }
+}
+
+impl<C, G, T> Drop for TmpFsDirInode<C, G, T>
+where
+    C: CpuOps,
+    G: PageAllocGetter<C>,
+    T: AddressTranslator<()>,
+{
+    fn drop(&mut self) {
+        if let Some(fs) = self.fs.upgrade() {
+            fs.usage.release_inode(self.attrs.lock_save_irq().uid);
+        }
+    }
 }
 
 struct TmpFsSymlinkInode<C: CpuOps> {
     id: InodeId,
     target: PathBuf,
     attr: SpinLockIrq<FileAttr, C>,
-    xattr: SpinLockIrq<Vec<(String, Vec<u8>)>, C>,
+    xattr: TmpFsXattrs<C>,
+    usage: Arc<TmpFsUsage<C>>,
 }
 
 #[async_trait]
@@ -714,7 +1309,9 @@ impl<C: CpuOps> Inode for TmpFsSymlinkInode<C> {
     }
 
     async fn setattr(&self, attr: FileAttr) -> Result<()> {
-        *self.attr.lock_save_irq() = attr;
+        let mut cur = self.attr.lock_save_irq();
+        self.usage.transfer(cur.uid, attr.uid, 0)?;
+        *cur = attr;
         Ok(())
     }
 
@@ -723,45 +1320,19 @@ impl<C: CpuOps> Inode for TmpFsSymlinkInode<C> {
     }
 
     async fn getxattr(&self, name: &str) -> Result<Vec<u8>> {
-        let guard = self.xattr.lock_save_irq();
-        if let Some((_, value)) = guard.iter().find(|(key, _)| key == name) {
-            Ok(value.clone())
-        } else {
-            Err(FsError::NotFound.into())
-        }
+        self.xattr.get(name)
     }
 
-    async fn removexattr(&self, _name: &str) -> Result<()> {
-        let mut guard = self.xattr.lock_save_irq();
-        if let Some(pos) = guard.iter().position(|(key, _)| key == _name) {
-            guard.remove(pos);
-            Ok(())
-        } else {
-            Err(FsError::NotFound.into())
-        }
+    async fn removexattr(&self, name: &str) -> Result<()> {
+        self.xattr.remove(name)
     }
 
     async fn listxattr(&self) -> Result<Vec<String>> {
-        let guard = self.xattr.lock_save_irq();
-        Ok(guard.iter().map(|(key, _)| key.clone()).collect())
+        Ok(self.xattr.list())
     }
 
     async fn setxattr(&self, name: &str, buf: &[u8], create: bool, replace: bool) -> Result<()> {
-        let mut guard = self.xattr.lock_save_irq();
-
-        if let Some((_, value)) = guard.iter_mut().find(|(key, _)| key == name) {
-            if create {
-                return Err(FsError::AlreadyExists.into());
-            }
-            *value = buf.to_vec();
-            Ok(())
-        } else {
-            if replace {
-                return Err(FsError::NotFound.into());
-            }
-            guard.push((name.to_owned(), buf.to_vec()));
-            Ok(())
-        }
+        self.xattr.set(name, buf, create, replace)
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -769,19 +1340,92 @@ impl<C: CpuOps> Inode for TmpFsSymlinkInode<C> {
     }
 }
 
+impl<C: CpuOps> Drop for TmpFsSymlinkInode<C> {
+    fn drop(&mut self) {
+        self.usage.release_inode(self.attr.lock_save_irq().uid);
+    }
+}
+
 impl<C: CpuOps> TmpFsSymlinkInode<C> {
-    fn new(id: InodeId, target: PathBuf) -> Result<Self> {
-        Ok(Self {
+    fn new(id: InodeId, target: PathBuf, owner: FileOwner, usage: Arc<TmpFsUsage<C>>) -> Self {
+        let size = target.as_str().len() as u64;
+
+        Self {
             id,
             target,
+            // A symlink's size is the length of its target, and its
+            // permissions are never checked.
             attr: SpinLockIrq::new(FileAttr {
                 file_type: FileType::Symlink,
-                size: 0,
+                size,
                 nlinks: 1,
+                permissions: FilePermissions::from_bits_retain(0o777),
+                uid: owner.uid,
+                gid: owner.gid,
                 ..Default::default()
             }),
-            xattr: SpinLockIrq::new(Vec::new()),
-        })
+            xattr: TmpFsXattrs::new(),
+            usage,
+        }
+    }
+}
+
+/// A device node, FIFO or socket. These hold no data of their own, only the
+/// attributes that say what they refer to.
+struct TmpFsNodeInode<C: CpuOps> {
+    id: InodeId,
+    attr: SpinLockIrq<FileAttr, C>,
+    usage: Arc<TmpFsUsage<C>>,
+}
+
+#[async_trait]
+impl<C: CpuOps> Inode for TmpFsNodeInode<C> {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.lock_save_irq().clone())
+    }
+
+    async fn setattr(&self, attr: FileAttr) -> Result<()> {
+        let mut cur = self.attr.lock_save_irq();
+        self.usage.transfer(cur.uid, attr.uid, 0)?;
+        *cur = attr;
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<C: CpuOps> Drop for TmpFsNodeInode<C> {
+    fn drop(&mut self) {
+        self.usage.release_inode(self.attr.lock_save_irq().uid);
+    }
+}
+
+impl<C: CpuOps> TmpFsNodeInode<C> {
+    fn new(
+        id: InodeId,
+        file_type: FileType,
+        permissions: FilePermissions,
+        owner: FileOwner,
+        usage: Arc<TmpFsUsage<C>>,
+    ) -> Self {
+        Self {
+            id,
+            attr: SpinLockIrq::new(FileAttr {
+                file_type,
+                nlinks: 1,
+                permissions,
+                uid: owner.uid,
+                gid: owner.gid,
+                ..Default::default()
+            }),
+            usage,
+        }
     }
 }
 
@@ -795,6 +1439,7 @@ where
     id: u64,
     next_inode_id: AtomicU64,
     root: Arc<TmpFsDirInode<C, G, T>>,
+    usage: Arc<TmpFsUsage<C>>,
     pg_allocator: PhantomData<G>,
     _phantom: PhantomData<T>,
 }
@@ -807,14 +1452,36 @@ where
 {
     /// Creates a new tmpfs instance with the given filesystem ID.
     pub fn new(fs_id: u64) -> Arc<Self> {
+        Self::with_options(fs_id, TmpFsOptions::default())
+    }
+
+    /// Creates a new tmpfs instance with the given filesystem ID, limited and
+    /// set up as `opts` asks.
+    pub fn with_options(fs_id: u64, opts: TmpFsOptions) -> Arc<Self> {
         Arc::new_cyclic(|weak_fs| {
-            let root =
-                TmpFsDirInode::new(1, weak_fs.clone(), FilePermissions::from_bits_retain(0o766));
+            let owner = FileOwner::new(
+                opts.uid.unwrap_or(Uid::new_root()),
+                opts.gid.unwrap_or(Gid::new_root_group()),
+            );
+            let root = TmpFsDirInode::new(
+                1,
+                weak_fs.clone(),
+                opts.mode
+                    .unwrap_or(FilePermissions::from_bits_retain(0o766)),
+                owner,
+                Weak::new(),
+            );
+            let usage = Arc::new(TmpFsUsage::new(opts.size, opts.nr_inodes));
+
+            // The root directory is charged like any other inode so that it
+            // balances out when it is eventually dropped.
+            let _ = usage.charge_inode(owner.uid);
 
             Self {
                 id: fs_id,
                 next_inode_id: AtomicU64::new(2),
                 root,
+                usage,
                 pg_allocator: PhantomData,
                 _phantom: PhantomData,
             }
@@ -845,6 +1512,34 @@ where
     fn magic(&self) -> u64 {
         0x01021994 // Tmpfs magic number
     }
+
+    async fn statfs(&self) -> Result<FsStats> {
+        let (bytes, inodes) = self.usage.used();
+        let block_size = BLOCK_SZ as u64;
+
+        let blocks = self.usage.max_bytes / block_size;
+        let blocks_free = blocks.saturating_sub(bytes.div_ceil(block_size));
+        let files = self.usage.max_inodes;
+
+        Ok(FsStats {
+            magic: self.magic(),
+            block_size,
+            blocks,
+            blocks_free,
+            blocks_avail: blocks_free,
+            files,
+            files_free: files.saturating_sub(inodes),
+            name_max: 255,
+        })
+    }
+
+    fn quota(&self) -> Option<&dyn QuotaOps> {
+        Some(&self.usage.quota)
+    }
+
+    fn cache_dentries(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(test)]
@@ -887,6 +1582,8 @@ mod tests {
         let reg = TmpFsReg::new(
             InodeId::from_fsid_and_inodeid(0, 1024),
             FilePermissions::all(),
+            FileOwner::root(),
+            fs.usage.clone(),
         )
         .unwrap();
         (fs, reg)
@@ -915,6 +1612,34 @@ mod tests {
         assert_eq!(attr.size, data.len() as u64);
     }
 
+    #[tokio::test]
+    async fn test_get_page_shares_file_data() {
+        let (_, reg) = setup_env();
+
+        reg.truncate(2 * PAGE_SIZE as u64).await.unwrap();
+        assert!(reg.get_page(2).await.is_err());
+
+        // The second page is a hole, which gets filled in.
+        let pfn = reg.get_page(1).await.unwrap();
+        let page = unsafe {
+            ClaimedPage::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::from_pfn(pfn)
+        };
+        assert!(!PG_ALLOC.get().unwrap().is_allocated_exclusive(pfn));
+
+        // Writes through the page are file writes, and vice versa.
+        unsafe { page.as_ptr_mut().write(0xaa) };
+        reg.write_at(PAGE_SIZE as u64 + 1, &[0xbb]).await.unwrap();
+
+        let mut buf = [0u8; 2];
+        reg.read_at(PAGE_SIZE as u64, &mut buf).await.unwrap();
+        assert_eq!(buf, [0xaa, 0xbb]);
+        assert_eq!(page.as_slice()[1], 0xbb);
+
+        // Dropping our reference leaves the file's.
+        drop(page);
+        assert!(PG_ALLOC.get().unwrap().is_allocated_exclusive(pfn));
+    }
+
     #[tokio::test]
     async fn test_write_across_page_boundary() {
         let (_, reg) = setup_env();
@@ -997,6 +1722,29 @@ mod tests {
         assert_eq!(&buf[5..], &[0, 0, 0, 0, 0]);
     }
 
+    #[tokio::test]
+    async fn test_probe_extents() {
+        let (_, reg) = setup_env();
+        let pg = PAGE_SIZE as u64;
+
+        reg.write_at(0, &vec![1u8; 4 * PAGE_SIZE]).await.unwrap();
+        reg.fallocate(FallocMode::PunchHole, pg, 2 * pg)
+            .await
+            .unwrap();
+        reg.truncate(6 * pg).await.unwrap();
+
+        // Data, a punched hole, data, then the tail added by truncate.
+        assert_eq!(
+            reg.probe_extents(0..6 * pg).await.unwrap(),
+            vec![0..pg, 3 * pg..4 * pg]
+        );
+        assert_eq!(
+            reg.probe_extents(pg / 2..3 * pg + 1).await.unwrap(),
+            vec![pg / 2..pg, 3 * pg..3 * pg + 1]
+        );
+        assert!(reg.probe_extents(4 * pg..6 * pg).await.unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_dir_create_and_lookup() {
         let fs = setup_fs();
@@ -1008,6 +1756,7 @@ mod tests {
                 "test_file.txt",
                 FileType::File,
                 FilePermissions::from_bits_retain(0),
+                FileOwner::root(),
                 None,
             )
             .await
@@ -1031,17 +1780,84 @@ mod tests {
             "dup",
             FileType::File,
             FilePermissions::from_bits_retain(0),
+            FileOwner::root(),
             None,
         )
         .await
         .unwrap();
 
         let res = root
-            .create("dup", FileType::File, FilePermissions::empty(), None)
+            .create(
+                "dup",
+                FileType::File,
+                FilePermissions::empty(),
+                FileOwner::root(),
+                None,
+            )
             .await;
         assert!(res.is_err(), "Should not allow duplicate file creation");
     }
 
+    #[tokio::test]
+    async fn test_symlink() {
+        let fs = setup_fs();
+        let root = fs.root_inode().await.unwrap();
+
+        root.symlink("link", Path::new("../target"), FileOwner::root())
+            .await
+            .unwrap();
+
+        let link = root.lookup("link").await.unwrap();
+        let attr = link.getattr().await.unwrap();
+        assert_eq!(attr.file_type, FileType::Symlink);
+        assert_eq!(attr.size, 9);
+        assert_eq!(attr.permissions.bits(), 0o777);
+        assert_eq!(link.readlink().await.unwrap().as_str(), "../target");
+
+        assert_eq!(
+            root.symlink("link", Path::new("other"), FileOwner::root())
+                .await,
+            Err(FsError::AlreadyExists.into())
+        );
+        assert_eq!(
+            link.readlink().await.unwrap().as_str(),
+            "../target",
+            "a failed symlink shouldn't change the existing one"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tmpfile() {
+        let fs = setup_fs();
+        let root = fs.root_inode().await.unwrap();
+
+        let file = root
+            .tmpfile(
+                FilePermissions::from_bits_retain(0o600),
+                FileOwner::root(),
+                None,
+            )
+            .await
+            .unwrap();
+        file.write_at(0, b"anon").await.unwrap();
+        assert_eq!(file.getattr().await.unwrap().nlinks, 0);
+
+        // It isn't in the directory until it's linked there.
+        let mut dir = root.readdir(0).await.unwrap();
+        while let Some(entry) = dir.next_entry().await.unwrap() {
+            assert_ne!(entry.id, file.id());
+        }
+
+        root.link("named", file.clone()).await.unwrap();
+        let found = root.lookup("named").await.unwrap();
+        assert_eq!(found.id(), file.id());
+        assert_eq!(found.getattr().await.unwrap().nlinks, 1);
+
+        let mut buf = [0; 4];
+        found.read_at(0, &mut buf).await.unwrap();
+        assert_eq!(&buf, b"anon");
+    }
+
     #[tokio::test]
     async fn test_dir_subdirectories() {
         let fs = setup_fs();
@@ -1053,6 +1869,7 @@ mod tests {
                 "subdir",
                 FileType::Directory,
                 FilePermissions::empty(),
+                FileOwner::root(),
                 None,
             )
             .await
@@ -1060,7 +1877,13 @@ mod tests {
 
         // Create /subdir/inner
         let inner = subdir
-            .create("inner", FileType::File, FilePermissions::empty(), None)
+            .create(
+                "inner",
+                FileType::File,
+                FilePermissions::empty(),
+                FileOwner::root(),
+                None,
+            )
             .await
             .unwrap();
 
@@ -1071,21 +1894,149 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_readdir() {
+    async fn test_dir_dot_dot() {
         let fs = setup_fs();
         let root = fs.root_inode().await.unwrap();
 
-        // Create files in "random" order
-        root.create("c.txt", FileType::File, FilePermissions::empty(), None)
+        let a = root
+            .create(
+                "a",
+                FileType::Directory,
+                FilePermissions::empty(),
+                FileOwner::root(),
+                None,
+            )
             .await
             .unwrap();
-        root.create("a.txt", FileType::File, FilePermissions::empty(), None)
+        let b = a
+            .create(
+                "b",
+                FileType::Directory,
+                FilePermissions::empty(),
+                FileOwner::root(),
+                None,
+            )
             .await
             .unwrap();
-        root.create("b.dir", FileType::Directory, FilePermissions::empty(), None)
+
+        assert_eq!(root.lookup("..").await.unwrap().id(), root.id());
+        assert_eq!(b.lookup(".").await.unwrap().id(), b.id());
+        assert_eq!(b.lookup("..").await.unwrap().id(), a.id());
+
+        // Moving a directory moves its `..` with it.
+        root.rename_from(a.clone(), "b", "b", false).await.unwrap();
+        assert_eq!(b.lookup("..").await.unwrap().id(), root.id());
+
+        root.create(
+            "c",
+            FileType::Directory,
+            FilePermissions::empty(),
+            FileOwner::root(),
+            None,
+        )
+        .await
+        .unwrap();
+        a.create(
+            "c",
+            FileType::File,
+            FilePermissions::empty(),
+            FileOwner::root(),
+            None,
+        )
+        .await
+        .unwrap();
+        let c = root.lookup("c").await.unwrap();
+        root.exchange("c", a.clone(), "c").await.unwrap();
+        assert_eq!(c.lookup("..").await.unwrap().id(), a.id());
+    }
+
+    #[tokio::test]
+    async fn test_rename_replace() {
+        let fs = setup_fs();
+        let root = fs.root_inode().await.unwrap();
+        let perms = FilePermissions::empty();
+
+        let sub = root
+            .create("sub", FileType::Directory, perms, FileOwner::root(), None)
+            .await
+            .unwrap();
+        let file = root
+            .create("f", FileType::File, perms, FileOwner::root(), None)
+            .await
+            .unwrap();
+        sub.create("g", FileType::File, perms, FileOwner::root(), None)
+            .await
+            .unwrap();
+        root.create("d", FileType::Directory, perms, FileOwner::root(), None)
             .await
             .unwrap();
 
+        // A file replaces a file, in the same directory or another.
+        root.create("h", FileType::File, perms, FileOwner::root(), None)
+            .await
+            .unwrap();
+        root.rename_from(root.clone(), "f", "h", false)
+            .await
+            .unwrap();
+        assert_eq!(root.lookup("h").await.unwrap().id(), file.id());
+        assert!(root.lookup("f").await.is_err());
+        sub.rename_from(root.clone(), "h", "g", false)
+            .await
+            .unwrap();
+        assert_eq!(sub.lookup("g").await.unwrap().id(), file.id());
+
+        assert_eq!(
+            root.rename_from(sub.clone(), "g", "d", false).await,
+            Err(FsError::IsADirectory.into())
+        );
+        assert_eq!(
+            sub.rename_from(root.clone(), "d", "g", false).await,
+            Err(FsError::NotADirectory.into())
+        );
+        assert_eq!(
+            root.rename_from(root.clone(), "d", "sub", false).await,
+            Err(FsError::DirectoryNotEmpty.into())
+        );
+        assert_eq!(
+            root.rename_from(sub.clone(), "g", "d", true).await,
+            Err(FsError::AlreadyExists.into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_readdir() {
+        let fs = setup_fs();
+        let root = fs.root_inode().await.unwrap();
+
+        // Create files in "random" order
+        root.create(
+            "c.txt",
+            FileType::File,
+            FilePermissions::empty(),
+            FileOwner::root(),
+            None,
+        )
+        .await
+        .unwrap();
+        root.create(
+            "a.txt",
+            FileType::File,
+            FilePermissions::empty(),
+            FileOwner::root(),
+            None,
+        )
+        .await
+        .unwrap();
+        root.create(
+            "b.dir",
+            FileType::Directory,
+            FilePermissions::empty(),
+            FileOwner::root(),
+            None,
+        )
+        .await
+        .unwrap();
+
         let mut dir_stream = root.readdir(0).await.expect("Readdir failed");
 
         let mut names = Vec::new();
@@ -1107,15 +2058,387 @@ mod tests {
         let root = fs.root_inode().await.unwrap();
 
         let f1 = root
-            .create("f1", FileType::File, FilePermissions::empty(), None)
+            .create(
+                "f1",
+                FileType::File,
+                FilePermissions::empty(),
+                FileOwner::root(),
+                None,
+            )
             .await
             .unwrap();
         let f2 = root
-            .create("f2", FileType::File, FilePermissions::empty(), None)
+            .create(
+                "f2",
+                FileType::File,
+                FilePermissions::empty(),
+                FileOwner::root(),
+                None,
+            )
             .await
             .unwrap();
 
         assert_ne!(f1.id(), f2.id());
         assert_ne!(f1.id(), root.id());
     }
+
+    #[tokio::test]
+    async fn test_quota_limits_writes() {
+        use crate::fs::quota::QuotaLimits;
+
+        let (fs, reg) = setup_env();
+        let quota = fs.quota().unwrap();
+        let root = Uid::new_root();
+
+        quota.set_limits(
+            root,
+            QuotaLimits {
+                space_hard: 2 * BLOCK_SZ as u64,
+                ..Default::default()
+            },
+        );
+        quota.set_enabled(true);
+
+        let data = vec![0xaa; 3 * BLOCK_SZ];
+
+        // The write stops short at the limit.
+        let written = reg.write_at(0, &data).await.expect("Write failed");
+        assert_eq!(written, 2 * BLOCK_SZ);
+
+        let err = reg.write_at(written as u64, &data).await;
+        assert_eq!(err, Err(FsError::QuotaExceeded.into()));
+
+        // Shrinking the file frees up space again.
+        reg.truncate(BLOCK_SZ as u64)
+            .await
+            .expect("Truncate failed");
+        assert_eq!(quota.get(root).space, BLOCK_SZ as u64);
+        reg.write_at(BLOCK_SZ as u64, &data[..BLOCK_SZ])
+            .await
+            .expect("Write failed");
+    }
+
+    #[tokio::test]
+    async fn test_quota_charges_creator() {
+        use crate::fs::quota::QuotaLimits;
+
+        init_allocator();
+        let fs = TmpFs::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::new(2);
+        let root = fs.root_inode().await.unwrap();
+        let quota = fs.quota().unwrap();
+        let user = FileOwner::new(Uid::new(1000), Gid::new(1000));
+        let root_inodes = quota.get(Uid::new_root()).inodes;
+
+        quota.set_limits(
+            user.uid,
+            QuotaLimits {
+                inodes_hard: 1,
+                ..Default::default()
+            },
+        );
+        quota.set_enabled(true);
+
+        let file = root
+            .create("f", FileType::File, FilePermissions::all(), user, None)
+            .await
+            .unwrap();
+        let attr = file.getattr().await.unwrap();
+        assert_eq!((attr.uid, attr.gid), (user.uid, user.gid));
+        assert_eq!(quota.get(user.uid).inodes, 1);
+        assert_eq!(quota.get(Uid::new_root()).inodes, root_inodes);
+
+        // The user's inode limit binds, but root is unaffected.
+        assert_eq!(
+            root.create("g", FileType::File, FilePermissions::all(), user, None)
+                .await
+                .err(),
+            Some(FsError::QuotaExceeded.into())
+        );
+        root.create(
+            "g",
+            FileType::File,
+            FilePermissions::all(),
+            FileOwner::root(),
+            None,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let opts = TmpFsOptions::parse("size=10k,nr_inodes=2k,mode=1777,uid=1000,gid=100").unwrap();
+        assert_eq!(opts.size, 3 * PAGE_SIZE as u64);
+        assert_eq!(opts.nr_inodes, 2048);
+        assert_eq!(opts.mode.unwrap().bits(), 0o1777);
+        assert_eq!(opts.uid, Some(Uid::new(1000)));
+        assert_eq!(opts.gid, Some(Gid::new(100)));
+
+        let opts = TmpFsOptions::parse("").unwrap();
+        assert_eq!((opts.size, opts.nr_inodes), (0, 0));
+        assert!(opts.mode.is_none());
+
+        for bad in ["size", "size=1x", "mode=9", "bogus=1", "size=99999999999t"] {
+            assert!(TmpFsOptions::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_size_limit() {
+        init_allocator();
+        let fs = TmpFs::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::with_options(
+            2,
+            TmpFsOptions::parse("size=8k").unwrap(),
+        );
+        let root = fs.root_inode().await.unwrap();
+        let file = root
+            .create(
+                "f",
+                FileType::File,
+                FilePermissions::all(),
+                FileOwner::root(),
+                None,
+            )
+            .await
+            .unwrap();
+        let data = vec![0x55; 3 * BLOCK_SZ];
+
+        let stats = fs.statfs().await.unwrap();
+        assert_eq!((stats.blocks, stats.blocks_free), (2, 2));
+
+        // The write stops short once the filesystem is full.
+        let written = file.write_at(0, &data).await.expect("Write failed");
+        assert_eq!(written, 2 * BLOCK_SZ);
+        assert_eq!(fs.statfs().await.unwrap().blocks_free, 0);
+        assert_eq!(
+            file.write_at(written as u64, &data).await,
+            Err(FsError::NoSpace.into())
+        );
+
+        // Space freed by one file can be used by another.
+        file.truncate(0).await.unwrap();
+        let other = root
+            .create(
+                "g",
+                FileType::File,
+                FilePermissions::all(),
+                FileOwner::root(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(other.write_at(0, &data[..BLOCK_SZ]).await, Ok(BLOCK_SZ));
+        root.unlink("g").await.unwrap();
+        drop(other);
+        assert_eq!(file.write_at(0, &data).await, Ok(2 * BLOCK_SZ));
+    }
+
+    #[tokio::test]
+    async fn test_fallocate() {
+        init_allocator();
+        let fs = TmpFs::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::with_options(
+            2,
+            TmpFsOptions::parse("size=16k").unwrap(),
+        );
+        let root = fs.root_inode().await.unwrap();
+        let file = root
+            .create(
+                "f",
+                FileType::File,
+                FilePermissions::all(),
+                FileOwner::root(),
+                None,
+            )
+            .await
+            .unwrap();
+        let blocks_free = async || fs.statfs().await.unwrap().blocks_free;
+
+        // Preallocating past the end extends the file, unless asked not to.
+        file.fallocate(FallocMode::Allocate { keep_size: true }, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(file.getattr().await.unwrap().size, 0);
+        assert_eq!(blocks_free().await, 3);
+
+        file.fallocate(
+            FallocMode::Allocate { keep_size: false },
+            BLOCK_SZ as u64,
+            BLOCK_SZ as u64,
+        )
+        .await
+        .unwrap();
+        assert_eq!(file.getattr().await.unwrap().size, 2 * BLOCK_SZ as u64);
+        assert_eq!(blocks_free().await, 2);
+
+        // Allocating what's already there costs nothing.
+        file.fallocate(FallocMode::Allocate { keep_size: false }, 0, 100)
+            .await
+            .unwrap();
+        assert_eq!(blocks_free().await, 2);
+        assert_eq!(file.getattr().await.unwrap().size, 2 * BLOCK_SZ as u64);
+
+        assert_eq!(
+            file.fallocate(
+                FallocMode::Allocate { keep_size: false },
+                0,
+                5 * BLOCK_SZ as u64
+            )
+            .await,
+            Err(FsError::NoSpace.into())
+        );
+        assert_eq!(
+            file.fallocate(FallocMode::Allocate { keep_size: true }, MAX_SZ as u64, 1)
+                .await,
+            Err(FsError::OutOfBounds.into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_punch_hole() {
+        init_allocator();
+        let fs = TmpFs::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::with_options(
+            2,
+            TmpFsOptions::parse("size=16k").unwrap(),
+        );
+        let root = fs.root_inode().await.unwrap();
+        let file = root
+            .create(
+                "f",
+                FileType::File,
+                FilePermissions::all(),
+                FileOwner::root(),
+                None,
+            )
+            .await
+            .unwrap();
+        let size = 3 * BLOCK_SZ;
+
+        file.write_at(0, &vec![0xaa; size]).await.unwrap();
+        assert_eq!(fs.statfs().await.unwrap().blocks_free, 1);
+
+        // Only the whole block in the middle is freed; the edges are zeroed.
+        file.fallocate(FallocMode::PunchHole, 100, 2 * BLOCK_SZ as u64)
+            .await
+            .unwrap();
+        assert_eq!(fs.statfs().await.unwrap().blocks_free, 2);
+        assert_eq!(file.getattr().await.unwrap().size, size as u64);
+
+        let mut buf = vec![0; size];
+        assert_eq!(file.read_at(0, &mut buf).await, Ok(size));
+        assert!(buf[..100].iter().all(|&b| b == 0xaa));
+        assert!(buf[100..2 * BLOCK_SZ + 100].iter().all(|&b| b == 0));
+        assert!(buf[2 * BLOCK_SZ + 100..].iter().all(|&b| b == 0xaa));
+
+        // Writing into the hole fills it in again.
+        file.write_at(BLOCK_SZ as u64, b"hi").await.unwrap();
+        assert_eq!(fs.statfs().await.unwrap().blocks_free, 1);
+        file.read_at(BLOCK_SZ as u64, &mut buf[..3]).await.unwrap();
+        assert_eq!(&buf[..3], b"hi\0");
+
+        // Punching past the end, or a hole twice, is harmless.
+        file.fallocate(FallocMode::PunchHole, 0, 10 * BLOCK_SZ as u64)
+            .await
+            .unwrap();
+        file.fallocate(FallocMode::PunchHole, 0, 10 * BLOCK_SZ as u64)
+            .await
+            .unwrap();
+        assert_eq!(fs.statfs().await.unwrap().blocks_free, 4);
+        assert_eq!(file.getattr().await.unwrap().size, size as u64);
+
+        // Shrinking a file with holes in it only gives back what was there.
+        file.write_at(0, b"x").await.unwrap();
+        file.truncate(0).await.unwrap();
+        assert_eq!(fs.statfs().await.unwrap().blocks_free, 4);
+    }
+
+    #[tokio::test]
+    async fn test_inode_limit() {
+        init_allocator();
+        let fs = TmpFs::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::with_options(
+            3,
+            TmpFsOptions::parse("nr_inodes=3").unwrap(),
+        );
+        let root = fs.root_inode().await.unwrap();
+        let perms = FilePermissions::all();
+
+        // The root directory takes up one of the inodes.
+        root.create("a", FileType::File, perms, FileOwner::root(), None)
+            .await
+            .unwrap();
+        root.create("b", FileType::Directory, perms, FileOwner::root(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            root.create("c", FileType::Fifo, perms, FileOwner::root(), None)
+                .await
+                .err(),
+            Some(FsError::NoSpace.into())
+        );
+        assert_eq!(
+            root.symlink("d", Path::new("a"), FileOwner::root()).await,
+            Err(FsError::NoSpace.into())
+        );
+
+        let stats = fs.statfs().await.unwrap();
+        assert_eq!((stats.files, stats.files_free), (3, 0));
+
+        root.unlink("a").await.unwrap();
+        assert_eq!(fs.statfs().await.unwrap().files_free, 1);
+        root.create("c", FileType::Fifo, perms, FileOwner::root(), None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_device_nodes() {
+        use crate::driver::CharDevDescriptor;
+
+        let fs = setup_fs();
+        let root = fs.root_inode().await.unwrap();
+        let dev = CharDevDescriptor { major: 1, minor: 3 };
+        let perms = FilePermissions::from_bits_retain(0o666);
+
+        for (name, kind) in [
+            ("null", FileType::CharDevice(dev)),
+            ("blk", FileType::BlockDevice(dev)),
+            ("fifo", FileType::Fifo),
+            ("sock", FileType::Socket),
+        ] {
+            root.create(name, kind, perms, FileOwner::root(), None)
+                .await
+                .unwrap();
+
+            let attr = root.lookup(name).await.unwrap().getattr().await.unwrap();
+            assert_eq!(attr.file_type, kind);
+            assert_eq!(attr.permissions.bits(), perms.bits());
+        }
+
+        let mut names = Vec::new();
+        let mut dir = root.readdir(0).await.unwrap();
+        while let Some(dent) = dir.next_entry().await.unwrap() {
+            names.push((dent.name, dent.file_type));
+        }
+        assert!(names.contains(&("null".into(), FileType::CharDevice(dev))));
+
+        assert_eq!(
+            root.create("link", FileType::Symlink, perms, FileOwner::root(), None)
+                .await
+                .err(),
+            Some(KernelError::NotSupported)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_root_options() {
+        init_allocator();
+        let fs = TmpFs::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::with_options(
+            4,
+            TmpFsOptions::parse("mode=1777,uid=1000,gid=1000").unwrap(),
+        );
+        let attr = fs.root_inode().await.unwrap().getattr().await.unwrap();
+
+        assert_eq!(attr.permissions.bits(), 0o1777);
+        assert_eq!(attr.uid, Uid::new(1000));
+        assert_eq!(attr.gid, Gid::new(1000));
+    }
 }
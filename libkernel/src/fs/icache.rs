@@ -0,0 +1,311 @@
+//! Inode cache.
+//!
+//! An [`InodeCache`] keeps the inodes handed out by a filesystem, keyed by
+//! their [`InodeId`], so that every lookup of the same file returns the same
+//! object instead of reading it from disk again. Sharing one object also means
+//! that a change made through one open file is seen by all the others.
+//!
+//! An inode is *in use* while anything besides the cache holds a reference to
+//! it. Only unused inodes are ever evicted, least recently used first, either
+//! when the cache grows past its capacity or when it is asked to shrink under
+//! memory pressure.
+
+use super::{Inode, InodeId};
+use crate::{CpuOps, sync::spinlock::SpinLockIrq};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+
+/// Counters describing the contents of the cache, as reported through
+/// `/proc/sys/fs/inode-nr`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InodeStats {
+    /// Number of cached inodes.
+    pub inodes: usize,
+    /// Number of cached inodes that are not in use.
+    pub unused: usize,
+}
+
+struct CachedInode {
+    inode: Arc<dyn Inode>,
+    /// Position of the inode in the LRU list.
+    last_used: u64,
+}
+
+impl CachedInode {
+    fn in_use(&self) -> bool {
+        Arc::strong_count(&self.inode) > 1
+    }
+}
+
+struct InodeCacheInner {
+    inodes: BTreeMap<InodeId, CachedInode>,
+    /// Keys of `inodes`, least recently used first.
+    lru: BTreeMap<u64, InodeId>,
+    next_use: u64,
+}
+
+impl InodeCacheInner {
+    fn touch(&mut self, id: InodeId) -> Option<Arc<dyn Inode>> {
+        let next_use = self.next_use;
+        let cached = self.inodes.get_mut(&id)?;
+        let last_used = core::mem::replace(&mut cached.last_used, next_use);
+        let inode = cached.inode.clone();
+
+        self.lru.remove(&last_used);
+        self.lru.insert(next_use, id);
+        self.next_use += 1;
+
+        Some(inode)
+    }
+
+    fn remove(&mut self, id: InodeId) {
+        if let Some(cached) = self.inodes.remove(&id) {
+            self.lru.remove(&cached.last_used);
+        }
+    }
+
+    /// Evicts up to `count` unused inodes, oldest first, returning how many
+    /// were evicted.
+    fn shrink(&mut self, count: usize) -> usize {
+        let victims: Vec<_> = self
+            .lru
+            .values()
+            .filter(|id| !self.inodes[*id].in_use())
+            .take(count)
+            .copied()
+            .collect();
+
+        for id in victims.iter() {
+            self.remove(*id);
+        }
+
+        victims.len()
+    }
+}
+
+/// A cache of inodes, evicting the least recently used unused inode once
+/// full.
+pub struct InodeCache<C: CpuOps> {
+    capacity: usize,
+    inner: SpinLockIrq<InodeCacheInner, C>,
+}
+
+impl<C: CpuOps> InodeCache<C> {
+    /// Creates an empty cache aiming to hold at most `capacity` inodes. The
+    /// cache may grow past that if more inodes than that are in use.
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: SpinLockIrq::new(InodeCacheInner {
+                inodes: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                next_use: 0,
+            }),
+        }
+    }
+
+    /// Returns the cached inode `id`, if any.
+    pub fn get(&self, id: InodeId) -> Option<Arc<dyn Inode>> {
+        self.inner.lock_save_irq().touch(id)
+    }
+
+    /// Adds `inode` to the cache, returning the object callers should use
+    /// from now on: the one already cached for its ID if there is one, or
+    /// `inode` itself otherwise.
+    pub fn insert(&self, inode: Arc<dyn Inode>) -> Arc<dyn Inode> {
+        let mut inner = self.inner.lock_save_irq();
+        let id = inode.id();
+
+        if let Some(cached) = inner.touch(id) {
+            return cached;
+        }
+
+        if self.capacity == 0 {
+            return inode;
+        }
+
+        if inner.inodes.len() >= self.capacity {
+            let excess = inner.inodes.len() + 1 - self.capacity;
+
+            inner.shrink(excess);
+        }
+
+        let last_used = inner.next_use;
+
+        inner.next_use += 1;
+        inner.lru.insert(last_used, id);
+        inner.inodes.insert(
+            id,
+            CachedInode {
+                inode: inode.clone(),
+                last_used,
+            },
+        );
+
+        inode
+    }
+
+    /// Forgets the inode `id`, e.g. once it has been changed behind the
+    /// cached object's back. Later lookups will read it afresh.
+    pub fn remove(&self, id: InodeId) {
+        self.inner.lock_save_irq().remove(id);
+    }
+
+    /// Forgets every inode of the filesystem `fs_id`.
+    pub fn remove_fs(&self, fs_id: u64) {
+        let mut inner = self.inner.lock_save_irq();
+        let ids: Vec<_> = inner
+            .inodes
+            .range(
+                InodeId::from_fsid_and_inodeid(fs_id, 0)
+                    ..=InodeId::from_fsid_and_inodeid(fs_id, u64::MAX),
+            )
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in ids {
+            inner.remove(id);
+        }
+    }
+
+    /// Evicts up to `count` unused inodes, least recently used first,
+    /// returning how many were evicted.
+    pub fn shrink(&self, count: usize) -> usize {
+        self.inner.lock_save_irq().shrink(count)
+    }
+
+    /// Evicts every unused inode, returning how many were evicted.
+    pub fn drop_unused(&self) -> usize {
+        self.shrink(usize::MAX)
+    }
+
+    /// Returns the current counters.
+    pub fn stats(&self) -> InodeStats {
+        let inner = self.inner.lock_save_irq();
+
+        InodeStats {
+            inodes: inner.inodes.len(),
+            unused: inner.inodes.values().filter(|c| !c.in_use()).count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use core::any::Any;
+
+    struct TestInode(InodeId);
+
+    impl Inode for TestInode {
+        fn id(&self) -> InodeId {
+            self.0
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    fn id(fs: u64, ino: u64) -> InodeId {
+        InodeId::from_fsid_and_inodeid(fs, ino)
+    }
+
+    fn inode(fs: u64, ino: u64) -> Arc<dyn Inode> {
+        Arc::new(TestInode(id(fs, ino)))
+    }
+
+    #[test]
+    fn insert_returns_cached_inode() {
+        let cache = InodeCache::<MockCpuOps>::new(16);
+
+        let first = cache.insert(inode(10, 1));
+        let second = cache.insert(inode(10, 1));
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(Arc::ptr_eq(&first, &cache.get(id(10, 1)).unwrap()));
+        assert!(cache.get(id(10, 2)).is_none());
+        assert_eq!(
+            cache.stats(),
+            InodeStats {
+                inodes: 1,
+                unused: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn shrink_skips_inodes_in_use() {
+        let cache = InodeCache::<MockCpuOps>::new(16);
+
+        let held = cache.insert(inode(10, 1));
+        cache.insert(inode(10, 2));
+        cache.insert(inode(10, 3));
+
+        assert_eq!(cache.stats().unused, 2);
+        assert_eq!(cache.shrink(1), 1);
+
+        // The oldest unused inode went first.
+        assert!(cache.get(id(10, 2)).is_none());
+        assert!(cache.get(id(10, 3)).is_some());
+
+        assert_eq!(cache.drop_unused(), 1);
+        assert!(Arc::ptr_eq(&held, &cache.get(id(10, 1)).unwrap()));
+        assert_eq!(cache.stats().inodes, 1);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = InodeCache::<MockCpuOps>::new(2);
+
+        cache.insert(inode(10, 1));
+        cache.insert(inode(10, 2));
+
+        // Touch inode 1 so that inode 2 is the oldest.
+        assert!(cache.get(id(10, 1)).is_some());
+
+        cache.insert(inode(10, 3));
+
+        assert!(cache.get(id(10, 1)).is_some());
+        assert!(cache.get(id(10, 2)).is_none());
+        assert!(cache.get(id(10, 3)).is_some());
+        assert_eq!(cache.stats().inodes, 2);
+    }
+
+    #[test]
+    fn grows_past_capacity_when_in_use() {
+        let cache = InodeCache::<MockCpuOps>::new(1);
+
+        let a = cache.insert(inode(10, 1));
+        let b = cache.insert(inode(10, 2));
+
+        assert_eq!(cache.stats().inodes, 2);
+
+        drop((a, b));
+        cache.insert(inode(10, 3));
+
+        assert_eq!(cache.stats().inodes, 1);
+        assert!(cache.get(id(10, 3)).is_some());
+    }
+
+    #[test]
+    fn remove_and_remove_fs() {
+        let cache = InodeCache::<MockCpuOps>::new(16);
+
+        let a = cache.insert(inode(10, 1));
+        cache.insert(inode(10, 2));
+        cache.insert(inode(11, 1));
+
+        cache.remove(id(10, 1));
+        assert!(cache.get(id(10, 1)).is_none());
+
+        // A removed inode is replaced by the next one inserted.
+        let b = cache.insert(inode(10, 1));
+        assert!(!Arc::ptr_eq(&a, &b));
+
+        cache.remove_fs(10);
+        assert!(cache.get(id(10, 1)).is_none());
+        assert!(cache.get(id(10, 2)).is_none());
+        assert!(cache.get(id(11, 1)).is_some());
+    }
+}
@@ -15,21 +15,27 @@ extern crate alloc;
 
 pub mod attr;
 pub mod blk;
+pub mod dcache;
 pub mod filesystems;
+pub mod icache;
 pub mod path;
 pub mod pathbuf;
+pub mod quota;
+pub mod readahead;
 
 use core::any::Any;
+use core::ops::Range;
 
 use crate::{
     driver::CharDevDescriptor,
     error::{FsError, KernelError, Result},
-    fs::{path::Path, pathbuf::PathBuf},
+    fs::{path::Path, pathbuf::PathBuf, quota::QuotaOps},
+    memory::page::PageFrame,
 };
 use alloc::vec::Vec;
 use alloc::{boxed::Box, string::String, sync::Arc};
 use async_trait::async_trait;
-use attr::{FileAttr, FilePermissions};
+use attr::{FileAttr, FileOwner, FilePermissions};
 use core::time::Duration;
 
 mod _open_flags {
@@ -45,10 +51,15 @@ mod _open_flags {
             const O_CREAT     = 0o100;
             const O_EXCL      = 0o200;
             const O_TRUNC     = 0o1000;
-            const O_DIRECTORY = 0o200000;
+            const O_DIRECTORY = 0o40000;
+            const O_NOFOLLOW  = 0o100000;
+            const O_DIRECT    = 0o200000;
             const O_APPEND    = 0o2000;
             const O_NONBLOCK  = 0o4000;
             const O_CLOEXEC   = 0o2000000;
+            const O_PATH      = 0o10000000;
+            /// Userspace always passes this along with `O_DIRECTORY`.
+            const O_TMPFILE   = 0o20000000;
         }
     }
 }
@@ -66,6 +77,45 @@ pub const CGROUPFS_ID: u64 = 4;
 /// Starting ID for user-mounted filesystem instances.
 pub const FS_ID_START: u64 = 10;
 
+/// Usage figures for a filesystem, as reported by `statfs()`.
+///
+/// Block counts are in units of `block_size`. A filesystem without a limit
+/// on blocks or inodes reports zero for both the total and the free count.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsStats {
+    /// The filesystem's magic number.
+    pub magic: u64,
+    /// The size of a block, in bytes.
+    pub block_size: u64,
+    /// Total data blocks.
+    pub blocks: u64,
+    /// Free blocks.
+    pub blocks_free: u64,
+    /// Free blocks available to unprivileged users.
+    pub blocks_avail: u64,
+    /// Total inodes.
+    pub files: u64,
+    /// Free inodes.
+    pub files_free: u64,
+    /// The longest a file name may be.
+    pub name_max: u64,
+}
+
+/// What `fallocate()` does to a range of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallocMode {
+    /// Backs the range with storage, so that writing to it can't fail for
+    /// lack of space. Unless `keep_size` is set, a range ending past the end
+    /// of the file extends it.
+    Allocate {
+        /// Leave the file size alone (`FALLOC_FL_KEEP_SIZE`).
+        keep_size: bool,
+    },
+    /// Frees the storage behind the range, which then reads as zeroes. The
+    /// file size doesn't change (`FALLOC_FL_PUNCH_HOLE`).
+    PunchHole,
+}
+
 /// Trait for a mounted filesystem instance. Its main role is to act as a
 /// factory for Inodes.
 #[async_trait]
@@ -79,6 +129,16 @@ pub trait Filesystem: Send + Sync {
     /// Get magic
     fn magic(&self) -> u64;
 
+    /// Returns the usage figures reported by `statfs()`.
+    ///
+    /// The default implementation only fills in the magic number.
+    async fn statfs(&self) -> Result<FsStats> {
+        Ok(FsStats {
+            magic: self.magic(),
+            ..FsStats::default()
+        })
+    }
+
     /// Flushes all pending data to the underlying storage device(s).
     ///
     /// The default implementation is a no-op so that read-only filesystems do
@@ -86,6 +146,38 @@ pub trait Filesystem: Send + Sync {
     async fn sync(&self) -> Result<()> {
         Ok(())
     }
+
+    /// Returns `true` if the filesystem can't be written at all, whatever it's
+    /// mounted with, e.g. because write support was left out of the build.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Returns the per-user quota table for this filesystem, or `None` if it
+    /// doesn't support disk quotas.
+    fn quota(&self) -> Option<&dyn QuotaOps> {
+        None
+    }
+
+    /// Returns `true` if the VFS may cache the results of lookups on this
+    /// filesystem.
+    ///
+    /// Only filesystems whose directories change solely through the VFS, and
+    /// whose lookups hand back the same inode object every time, can opt in.
+    fn cache_dentries(&self) -> bool {
+        false
+    }
+
+    /// Returns `true` if the VFS should keep the inodes looked up on this
+    /// filesystem in the inode cache, so that every lookup of a file hands
+    /// back the same object.
+    ///
+    /// Filesystems that build a new inode object from disk on every lookup
+    /// should opt in, provided they tolerate the VFS dropping an inode and
+    /// looking it up again at any time.
+    fn cache_inodes(&self) -> bool {
+        false
+    }
 }
 
 /// A unique identifier for an inode across the entire VFS, combining a filesystem ID and inode number.
@@ -127,6 +219,16 @@ pub enum FileType {
     Socket,
 }
 
+impl FileType {
+    /// Returns the device number of a character or block device node.
+    pub fn device(&self) -> Option<CharDevDescriptor> {
+        match self {
+            FileType::BlockDevice(dev) | FileType::CharDevice(dev) => Some(*dev),
+            _ => None,
+        }
+    }
+}
+
 impl From<FileType> for u32 {
     fn from(file_type: FileType) -> Self {
         match file_type {
@@ -183,6 +285,11 @@ pub enum SeekFrom {
     End(i64),
     /// Seek relative to the current position.
     Current(i64),
+    /// Seek to the first data at or after the offset (`SEEK_DATA`).
+    Data(u64),
+    /// Seek to the first hole at or after the offset (`SEEK_HOLE`). The end of
+    /// the file always counts as a hole.
+    Hole(u64),
 }
 
 /// Trait for a raw block device.
@@ -199,8 +306,48 @@ pub trait BlockDevice: Send + Sync {
     /// The size of a single block in bytes.
     fn block_size(&self) -> usize;
 
+    /// The number of blocks on the device.
+    fn num_blocks(&self) -> u64;
+
     /// Flushes any caches to the underlying device.
     async fn sync(&self) -> Result<()>;
+
+    /// Hints that `count` blocks starting at `block_id` will be read soon.
+    ///
+    /// Devices that cache blocks may start fetching them; the default
+    /// implementation does nothing.
+    async fn readahead(&self, _block_id: u64, _count: u64) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Allows a single device to be shared, e.g. between a filesystem and the
+/// devices stacked on top of it.
+#[async_trait]
+impl<T: BlockDevice + ?Sized> BlockDevice for Arc<T> {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read(block_id, buf).await
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        (**self).write(block_id, buf).await
+    }
+
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        (**self).num_blocks()
+    }
+
+    async fn sync(&self) -> Result<()> {
+        (**self).sync().await
+    }
+
+    async fn readahead(&self, block_id: u64, count: u64) -> Result<()> {
+        (**self).readahead(block_id, count).await
+    }
 }
 
 /// A stateless representation of a filesystem object.
@@ -225,11 +372,51 @@ pub trait Inode: Send + Sync + Any {
         Err(KernelError::NotSupported)
     }
 
+    /// Hints that `len` bytes from `offset` will be read soon, so that the
+    /// filesystem can start fetching them from its device.
+    ///
+    /// This is purely advisory: the default implementation does nothing, and
+    /// callers should ignore errors.
+    async fn readahead(&self, _offset: u64, _len: u64) -> Result<()> {
+        Ok(())
+    }
+
     /// Truncates the inode to a specific `size`.
     async fn truncate(&self, _size: u64) -> Result<()> {
         Err(KernelError::NotSupported)
     }
 
+    /// Allocates or frees the storage behind `len` bytes from `offset`.
+    async fn fallocate(&self, _mode: FallocMode, _offset: u64, _len: u64) -> Result<()> {
+        Err(KernelError::OpNotSupported)
+    }
+
+    /// Copies up to `len` bytes from `off_in` to `off_out` in `dst`, which
+    /// may be this inode, stopping at the end of this inode. Returns the
+    /// number of bytes copied.
+    ///
+    /// This lets a filesystem do better than reading and writing the data
+    /// itself. The default returns `NotSupported`, which the VFS takes as its
+    /// cue to do exactly that.
+    async fn copy_range(
+        &self,
+        _off_in: u64,
+        _dst: &dyn Inode,
+        _off_out: u64,
+        _len: u64,
+    ) -> Result<usize> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Returns the parts of `range` that hold data, in order, as byte ranges
+    /// within it. The rest of `range` is holes, which read as zeroes.
+    ///
+    /// This is what `SEEK_DATA` and `SEEK_HOLE` are answered from. The default
+    /// is for the file to be data throughout, which is always a safe answer.
+    async fn probe_extents(&self, range: Range<u64>) -> Result<Vec<Range<u64>>> {
+        Ok(alloc::vec![range])
+    }
+
     /// Gets the metadata for this inode.
     async fn getattr(&self) -> Result<FileAttr> {
         Err(KernelError::NotSupported)
@@ -273,17 +460,30 @@ pub trait Inode: Send + Sync + Any {
         Err(KernelError::NotSupported)
     }
 
-    /// Creates a new object within a directory.
+    /// Creates a new object within a directory, owned by `owner`.
     async fn create(
         &self,
         _name: &str,
         _file_type: FileType,
         _permissions: FilePermissions,
+        _owner: FileOwner,
         _time: Option<Duration>,
     ) -> Result<Arc<dyn Inode>> {
         Err(KernelError::NotSupported)
     }
 
+    /// Creates a regular file on this directory's filesystem that has no
+    /// name, for `O_TMPFILE`. It starts out with no links, and can be given a
+    /// name later with `link`.
+    async fn tmpfile(
+        &self,
+        _permissions: FilePermissions,
+        _owner: FileOwner,
+        _time: Option<Duration>,
+    ) -> Result<Arc<dyn Inode>> {
+        Err(KernelError::OpNotSupported)
+    }
+
     /// Removes a link to an inode from a directory.
     async fn unlink(&self, _name: &str) -> Result<()> {
         Err(KernelError::NotSupported)
@@ -294,8 +494,8 @@ pub trait Inode: Send + Sync + Any {
         Err(KernelError::NotSupported)
     }
 
-    /// Creates a new symlink
-    async fn symlink(&self, _name: &str, _target: &Path) -> Result<()> {
+    /// Creates a new symlink, owned by `owner`.
+    async fn symlink(&self, _name: &str, _target: &Path, _owner: FileOwner) -> Result<()> {
         Err(KernelError::NotSupported)
     }
 
@@ -335,6 +535,39 @@ pub trait Inode: Send + Sync + Any {
         Err(KernelError::NotSupported)
     }
 
+    /// For a symlink that stands for an open file rather than a path, such as
+    /// those in `/proc/<pid>/fd`, returns that file's inode. Following the
+    /// link goes straight to it, even if its path has since gone.
+    async fn magic_link(&self) -> Result<Option<Arc<dyn Inode>>> {
+        Ok(None)
+    }
+
+    /// Returns the page frame holding page `pg_idx` of the file's data, so that
+    /// it can be mapped directly into a process for a shared mapping.
+    ///
+    /// A reference on the frame is taken on behalf of the caller, and is
+    /// dropped when the frame is unmapped. Only filesystems whose data lives
+    /// in page frames, such as tmpfs, support this.
+    async fn get_page(&self, _pg_idx: u64) -> Result<PageFrame> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Returns `true` if the inode supports [`Inode::get_page`].
+    fn can_share_pages(&self) -> bool {
+        false
+    }
+
+    /// Notes that a shared mapping which may write to the file's pages has
+    /// been created, failing if the file can't be mapped that way (for
+    /// example a memfd with a write seal). Each success is paired with a call
+    /// to [`Inode::unmap_writable`] once the mapping is gone.
+    fn map_writable(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Undoes a successful [`Inode::map_writable`].
+    fn unmap_writable(&self) {}
+
     /// Flushes all modified data, including metadata, to the disk device containing the inode.
     ///
     /// The default implementation is a no-op so that read-only filesystems do
@@ -369,6 +602,10 @@ pub trait SimpleFile {
     async fn readlink(&self) -> Result<PathBuf> {
         Err(KernelError::NotSupported)
     }
+    /// The inode a magic link stands for, if this is one.
+    async fn magic_link(&self) -> Result<Option<Arc<dyn Inode>>> {
+        Ok(None)
+    }
 }
 
 #[allow(missing_docs)]
@@ -408,6 +645,10 @@ where
         self.readlink().await
     }
 
+    async fn magic_link(&self) -> Result<Option<Arc<dyn Inode>>> {
+        self.magic_link().await
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
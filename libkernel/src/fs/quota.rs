@@ -0,0 +1,312 @@
+//! Per-user disk quota accounting.
+//!
+//! A [`QuotaTable`] tracks the space and inode usage of every user on a single
+//! filesystem instance, along with the soft and hard limits configured for
+//! them. Filesystems charge the table as they allocate blocks and inodes and
+//! release usage as they free them; charging fails with
+//! [`FsError::QuotaExceeded`] once a hard limit would be crossed.
+//!
+//! Usage is held in memory only and starts from zero when a filesystem is
+//! mounted; nothing is persisted to quota files on disk.
+
+use crate::{
+    CpuOps,
+    error::{FsError, Result},
+    proc::ids::Uid,
+    sync::spinlock::SpinLockIrq,
+};
+use alloc::collections::btree_map::BTreeMap;
+
+/// Soft and hard limits for a single user. A limit of zero means "no limit".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaLimits {
+    /// Space hard limit, in bytes.
+    pub space_hard: u64,
+    /// Space soft limit, in bytes.
+    pub space_soft: u64,
+    /// Inode hard limit.
+    pub inodes_hard: u64,
+    /// Inode soft limit.
+    pub inodes_soft: u64,
+}
+
+/// The limits and current usage of a single user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskQuota {
+    /// Configured limits.
+    pub limits: QuotaLimits,
+    /// Space currently charged to the user, in bytes.
+    pub space: u64,
+    /// Number of inodes currently charged to the user.
+    pub inodes: u64,
+}
+
+impl DiskQuota {
+    /// Returns `true` if the user is over either of their soft limits.
+    pub fn over_soft_limit(&self) -> bool {
+        exceeds(self.space, self.limits.space_soft) || exceeds(self.inodes, self.limits.inodes_soft)
+    }
+}
+
+fn exceeds(usage: u64, limit: u64) -> bool {
+    limit != 0 && usage > limit
+}
+
+/// Administrative interface to a filesystem's quota table, used by the
+/// `quotactl` family of syscalls.
+pub trait QuotaOps: Send + Sync {
+    /// Returns `true` if limits are being enforced.
+    fn is_enabled(&self) -> bool;
+
+    /// Turns enforcement of limits on or off.
+    fn set_enabled(&self, enabled: bool);
+
+    /// Returns the limits and usage for `uid`.
+    fn get(&self, uid: Uid) -> DiskQuota;
+
+    /// Replaces the limits for `uid`, leaving its usage untouched.
+    fn set_limits(&self, uid: Uid, limits: QuotaLimits);
+}
+
+struct QuotaTableInner {
+    enabled: bool,
+    users: BTreeMap<u32, DiskQuota>,
+}
+
+/// Per-filesystem table of user quotas.
+///
+/// Usage is always tracked so that enabling enforcement later reflects the
+/// current state of the filesystem; limits are only enforced while the table
+/// is enabled.
+pub struct QuotaTable<C: CpuOps> {
+    inner: SpinLockIrq<QuotaTableInner, C>,
+}
+
+impl<C: CpuOps> Default for QuotaTable<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: CpuOps> QuotaTable<C> {
+    /// Creates an empty table with enforcement disabled.
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLockIrq::new(QuotaTableInner {
+                enabled: false,
+                users: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Charges `bytes` of space to `uid`, failing if this would take the user
+    /// past their hard limit.
+    pub fn charge_space(&self, uid: Uid, bytes: u64) -> Result<()> {
+        let mut inner = self.inner.lock_save_irq();
+        let enabled = inner.enabled;
+        let quota = inner.users.entry(uid.into()).or_default();
+        let new_space = quota.space.saturating_add(bytes);
+
+        if enabled && bytes != 0 && exceeds(new_space, quota.limits.space_hard) {
+            return Err(FsError::QuotaExceeded.into());
+        }
+
+        quota.space = new_space;
+
+        Ok(())
+    }
+
+    /// Charges `bytes` of space to `uid` without checking limits. Used when
+    /// the allocation has already happened and cannot be undone.
+    pub fn charge_space_nofail(&self, uid: Uid, bytes: u64) {
+        let mut inner = self.inner.lock_save_irq();
+        let quota = inner.users.entry(uid.into()).or_default();
+
+        quota.space = quota.space.saturating_add(bytes);
+    }
+
+    /// Returns `bytes` of space previously charged to `uid`.
+    pub fn release_space(&self, uid: Uid, bytes: u64) {
+        if let Some(quota) = self.inner.lock_save_irq().users.get_mut(&uid.into()) {
+            quota.space = quota.space.saturating_sub(bytes);
+        }
+    }
+
+    /// Charges a single inode to `uid`, failing if this would take the user
+    /// past their hard limit.
+    pub fn charge_inode(&self, uid: Uid) -> Result<()> {
+        let mut inner = self.inner.lock_save_irq();
+        let enabled = inner.enabled;
+        let quota = inner.users.entry(uid.into()).or_default();
+
+        if enabled && exceeds(quota.inodes + 1, quota.limits.inodes_hard) {
+            return Err(FsError::QuotaExceeded.into());
+        }
+
+        quota.inodes += 1;
+
+        Ok(())
+    }
+
+    /// Returns an inode previously charged to `uid`.
+    pub fn release_inode(&self, uid: Uid) {
+        if let Some(quota) = self.inner.lock_save_irq().users.get_mut(&uid.into()) {
+            quota.inodes = quota.inodes.saturating_sub(1);
+        }
+    }
+
+    /// Moves an inode and `bytes` of space from one user to another, as
+    /// happens when a file changes owner.
+    pub fn transfer(&self, from: Uid, to: Uid, bytes: u64) -> Result<()> {
+        if from == to {
+            return Ok(());
+        }
+
+        let mut inner = self.inner.lock_save_irq();
+        let enabled = inner.enabled;
+        let dst = inner.users.entry(to.into()).or_default();
+
+        if enabled
+            && (exceeds(dst.space.saturating_add(bytes), dst.limits.space_hard)
+                || exceeds(dst.inodes + 1, dst.limits.inodes_hard))
+        {
+            return Err(FsError::QuotaExceeded.into());
+        }
+
+        dst.space = dst.space.saturating_add(bytes);
+        dst.inodes += 1;
+
+        if let Some(src) = inner.users.get_mut(&from.into()) {
+            src.space = src.space.saturating_sub(bytes);
+            src.inodes = src.inodes.saturating_sub(1);
+        }
+
+        Ok(())
+    }
+}
+
+impl<C: CpuOps> QuotaOps for QuotaTable<C> {
+    fn is_enabled(&self) -> bool {
+        self.inner.lock_save_irq().enabled
+    }
+
+    fn set_enabled(&self, enabled: bool) {
+        self.inner.lock_save_irq().enabled = enabled;
+    }
+
+    fn get(&self, uid: Uid) -> DiskQuota {
+        self.inner
+            .lock_save_irq()
+            .users
+            .get(&uid.into())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn set_limits(&self, uid: Uid, limits: QuotaLimits) {
+        self.inner
+            .lock_save_irq()
+            .users
+            .entry(uid.into())
+            .or_default()
+            .limits = limits;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+
+    fn limited_table(limits: QuotaLimits) -> QuotaTable<MockCpuOps> {
+        let table = QuotaTable::new();
+        table.set_limits(Uid::new(1000), limits);
+        table.set_enabled(true);
+        table
+    }
+
+    #[test]
+    fn space_hard_limit_enforced() {
+        let table = limited_table(QuotaLimits {
+            space_hard: 8192,
+            ..Default::default()
+        });
+        let uid = Uid::new(1000);
+
+        table.charge_space(uid, 4096).unwrap();
+        table.charge_space(uid, 4096).unwrap();
+        assert_eq!(
+            table.charge_space(uid, 1),
+            Err(FsError::QuotaExceeded.into())
+        );
+
+        table.release_space(uid, 4096);
+        table.charge_space(uid, 4096).unwrap();
+        assert_eq!(table.get(uid).space, 8192);
+    }
+
+    #[test]
+    fn inode_hard_limit_enforced() {
+        let table = limited_table(QuotaLimits {
+            inodes_hard: 2,
+            ..Default::default()
+        });
+        let uid = Uid::new(1000);
+
+        table.charge_inode(uid).unwrap();
+        table.charge_inode(uid).unwrap();
+        assert!(table.charge_inode(uid).is_err());
+
+        table.release_inode(uid);
+        table.charge_inode(uid).unwrap();
+    }
+
+    #[test]
+    fn soft_limit_not_enforced() {
+        let table = limited_table(QuotaLimits {
+            space_soft: 100,
+            ..Default::default()
+        });
+        let uid = Uid::new(1000);
+
+        table.charge_space(uid, 200).unwrap();
+        assert!(table.get(uid).over_soft_limit());
+    }
+
+    #[test]
+    fn disabled_table_tracks_usage_only() {
+        let table = limited_table(QuotaLimits {
+            space_hard: 10,
+            ..Default::default()
+        });
+        let uid = Uid::new(1000);
+
+        table.set_enabled(false);
+        table.charge_space(uid, 100).unwrap();
+        assert_eq!(table.get(uid).space, 100);
+
+        table.set_enabled(true);
+        assert!(table.charge_space(uid, 1).is_err());
+    }
+
+    #[test]
+    fn transfer_moves_usage() {
+        let table = limited_table(QuotaLimits {
+            space_hard: 4096,
+            ..Default::default()
+        });
+        let root = Uid::new_root();
+        let user = Uid::new(1000);
+
+        table.charge_inode(root).unwrap();
+        table.charge_space(root, 8192).unwrap();
+
+        assert!(table.transfer(root, user, 8192).is_err());
+        table.transfer(root, user, 4096).unwrap();
+
+        assert_eq!(table.get(user).space, 4096);
+        assert_eq!(table.get(user).inodes, 1);
+        assert_eq!(table.get(root).space, 4096);
+        assert_eq!(table.get(root).inodes, 0);
+    }
+}
@@ -0,0 +1,271 @@
+//! Readahead heuristics.
+//!
+//! A [`FileReadahead`] watches the reads made through a single open file.
+//! Once they look sequential, it asks for a window of data beyond the reader
+//! to be fetched, and doubles the window each time the reader reaches it. This
+//! follows Linux's on-demand readahead:
+//!
+//! - The first sequential read opens a window that covers the read itself.
+//!   That window has to be fetched before the read is served.
+//! - When a later read crosses the window's marker, the next window is
+//!   requested. It lies entirely ahead of the reader, so it can be fetched
+//!   while the read is served.
+//! - A read that doesn't continue from the previous one closes the window.
+//!
+//! [`ReadaheadAdvice`] lets userspace tune this per file (`posix_fadvise`) or
+//! per mapping (`madvise`).
+
+use crate::memory::{PAGE_MASK, PAGE_SIZE};
+use core::ops::Range;
+
+/// Smallest window read ahead of a sequential reader.
+pub const READAHEAD_MIN: u64 = 4 * PAGE_SIZE as u64;
+
+/// Largest window read ahead without advice, matching Linux's default
+/// `read_ahead_kb` of 128.
+pub const READAHEAD_MAX: u64 = 32 * PAGE_SIZE as u64;
+
+/// The access pattern userspace expects for a file or mapping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadaheadAdvice {
+    /// Read ahead once accesses look sequential (`*_NORMAL`).
+    #[default]
+    Normal,
+    /// Expect sequential access: read ahead straight away, with twice the
+    /// usual window (`*_SEQUENTIAL`).
+    Sequential,
+    /// Expect random access: never read ahead (`*_RANDOM`).
+    Random,
+}
+
+impl ReadaheadAdvice {
+    /// Returns the largest window to read ahead under this advice.
+    pub fn max_window(self) -> u64 {
+        match self {
+            Self::Normal => READAHEAD_MAX,
+            Self::Sequential => 2 * READAHEAD_MAX,
+            Self::Random => 0,
+        }
+    }
+
+    /// Returns the range of a file to fetch when a mapping of it faults at
+    /// `offset`.
+    ///
+    /// Without advice this reads around the fault, as accesses to mapped
+    /// files, such as program text, tend to cluster. Sequential mappings
+    /// read ahead of it instead.
+    pub fn fault_window(self, offset: u64) -> Option<Range<u64>> {
+        let page = page_align_down(offset);
+        let max = self.max_window();
+
+        match self {
+            Self::Normal => {
+                let start = page.saturating_sub(max / 2);
+                Some(start..start.saturating_add(max))
+            }
+            Self::Sequential => Some(page..page.saturating_add(max)),
+            Self::Random => None,
+        }
+    }
+}
+
+fn page_align_down(offset: u64) -> u64 {
+    offset & !(PAGE_MASK as u64)
+}
+
+/// Readahead state of an open file.
+#[derive(Debug, Clone, Default)]
+pub struct FileReadahead {
+    advice: ReadaheadAdvice,
+    /// Where the previous read ended; a read starting here is sequential.
+    prev_end: u64,
+    /// The window most recently requested, or an empty range if there is
+    /// none.
+    window: Range<u64>,
+    /// A read that crosses this offset requests the next window.
+    marker: u64,
+}
+
+impl FileReadahead {
+    /// Creates the state for a newly opened file.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the advice currently applied to the file.
+    pub fn advice(&self) -> ReadaheadAdvice {
+        self.advice
+    }
+
+    /// Applies new advice, closing any open window.
+    pub fn set_advice(&mut self, advice: ReadaheadAdvice) {
+        self.advice = advice;
+        self.window = 0..0;
+    }
+
+    /// Records a read of `len` bytes at `offset` and returns the range that
+    /// should be read ahead, if any.
+    ///
+    /// A range that starts at or before `offset` covers the read itself, and
+    /// should be fetched before serving it. Otherwise the range lies ahead of
+    /// the reader and can be fetched alongside the read.
+    pub fn on_read(&mut self, offset: u64, len: usize) -> Option<Range<u64>> {
+        let end = offset.saturating_add(len as u64);
+        let sequential = offset == self.prev_end || self.advice == ReadaheadAdvice::Sequential;
+
+        self.prev_end = end;
+
+        let max = self.advice.max_window();
+
+        if max == 0 || len == 0 {
+            return None;
+        }
+
+        if !sequential {
+            self.window = 0..0;
+            return None;
+        }
+
+        if self.window.is_empty() || offset >= self.window.end {
+            // The reader has no window, or has run past it: open a new one
+            // around the read.
+            let start = page_align_down(offset);
+            let size = (len as u64)
+                .next_power_of_two()
+                .saturating_mul(4)
+                .clamp(READAHEAD_MIN, max);
+
+            self.window = start..start.saturating_add(size);
+            self.marker = end;
+
+            return Some(self.window.clone());
+        }
+
+        if end <= self.marker {
+            return None;
+        }
+
+        let size = (self.window.end - self.window.start)
+            .saturating_mul(2)
+            .min(max);
+        let start = self.window.end;
+
+        self.window = start..start.saturating_add(size);
+        self.marker = start;
+
+        Some(self.window.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PG: u64 = PAGE_SIZE as u64;
+
+    #[test]
+    fn sequential_reads_grow_the_window() {
+        let mut ra = FileReadahead::new();
+
+        assert_eq!(ra.on_read(0, PAGE_SIZE), Some(0..4 * PG));
+        assert_eq!(ra.on_read(PG, PAGE_SIZE), Some(4 * PG..12 * PG));
+
+        // Still reading data that has been requested already.
+        assert_eq!(ra.on_read(2 * PG, PAGE_SIZE), None);
+        assert_eq!(ra.on_read(3 * PG, PAGE_SIZE), None);
+
+        // Reaching the last window requests the next one.
+        assert_eq!(ra.on_read(4 * PG, PAGE_SIZE), Some(12 * PG..28 * PG));
+
+        let mut off = 5 * PG;
+        let mut last = None;
+
+        while off < 200 * PG {
+            if let Some(w) = ra.on_read(off, PAGE_SIZE) {
+                last = Some(w);
+            }
+            off += PG;
+        }
+
+        let last = last.unwrap();
+        assert_eq!(last.end - last.start, READAHEAD_MAX);
+    }
+
+    #[test]
+    fn large_first_read_gets_a_larger_window() {
+        let mut ra = FileReadahead::new();
+
+        assert_eq!(ra.on_read(0, 3 * PAGE_SIZE), Some(0..READAHEAD_MAX / 2));
+        assert_eq!(
+            FileReadahead::new().on_read(0, 1 << 20),
+            Some(0..READAHEAD_MAX)
+        );
+    }
+
+    #[test]
+    fn random_reads_close_the_window() {
+        let mut ra = FileReadahead::new();
+
+        assert!(ra.on_read(0, PAGE_SIZE).is_some());
+        assert_eq!(ra.on_read(100 * PG, PAGE_SIZE), None);
+        assert_eq!(ra.on_read(50 * PG, PAGE_SIZE), None);
+
+        // Continuing from the last read is sequential again.
+        assert_eq!(ra.on_read(51 * PG, 100), Some(51 * PG..55 * PG));
+    }
+
+    #[test]
+    fn overtaking_the_window_reopens_it() {
+        let mut ra = FileReadahead::new();
+
+        assert_eq!(ra.on_read(0, PAGE_SIZE), Some(0..4 * PG));
+
+        // A large read runs past the end of the next window.
+        assert_eq!(ra.on_read(PG, 20 * PAGE_SIZE), Some(4 * PG..12 * PG));
+        assert_eq!(ra.on_read(21 * PG, PAGE_SIZE), Some(21 * PG..25 * PG));
+    }
+
+    #[test]
+    fn advice_adjusts_the_window() {
+        let mut ra = FileReadahead::new();
+
+        ra.set_advice(ReadaheadAdvice::Random);
+        assert_eq!(ra.on_read(0, PAGE_SIZE), None);
+        assert_eq!(ra.on_read(PG, PAGE_SIZE), None);
+
+        // Sequential advice reads ahead even after a seek, and lets the
+        // window grow past the default maximum.
+        ra.set_advice(ReadaheadAdvice::Sequential);
+        assert_eq!(ra.on_read(1000 * PG, PAGE_SIZE), Some(1000 * PG..1004 * PG));
+
+        let mut off = 1001 * PG;
+        let mut last = None;
+
+        while off < 1400 * PG {
+            if let Some(w) = ra.on_read(off, PAGE_SIZE) {
+                last = Some(w);
+            }
+            off += PG;
+        }
+
+        let last = last.unwrap();
+        assert_eq!(last.end - last.start, 2 * READAHEAD_MAX);
+    }
+
+    #[test]
+    fn fault_windows() {
+        assert_eq!(
+            ReadaheadAdvice::Normal.fault_window(100 * PG + 10),
+            Some(84 * PG..116 * PG)
+        );
+        assert_eq!(
+            ReadaheadAdvice::Normal.fault_window(PG),
+            Some(0..READAHEAD_MAX)
+        );
+        assert_eq!(
+            ReadaheadAdvice::Sequential.fault_window(PG + 1),
+            Some(PG..PG + 2 * READAHEAD_MAX)
+        );
+        assert_eq!(ReadaheadAdvice::Random.fault_window(0), None);
+    }
+}
@@ -0,0 +1,561 @@
+//! Integer ID allocation.
+//!
+//! [`Idr`] hands out small integer IDs and maps each one to a value, as the
+//! IDR does on Linux for pids, watch descriptors and the like. The IDs are kept
+//! in a radix tree with 64 slots to a node, in which every branch also tracks
+//! which of its subtrees have no free IDs left. Finding a free ID skips whole
+//! runs of allocated ones that way, rather than probing them one by one.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::ops::Range;
+
+const SHIFT: u32 = 6;
+const SLOTS: usize = 1 << SHIFT;
+
+/// Returns the slot `id` goes in, in a node whose slots are `1 << shift` IDs
+/// apart.
+fn slot(id: u64, shift: u32) -> usize {
+    ((id >> shift) as usize) & (SLOTS - 1)
+}
+
+enum Node<T> {
+    Leaf {
+        /// The slots holding a value.
+        present: u64,
+        values: Box<[Option<T>; SLOTS]>,
+    },
+    Branch {
+        /// The slots holding a child.
+        occupied: u64,
+        /// The slots holding a child with no free IDs.
+        full: u64,
+        children: Box<[Option<Box<Node<T>>>; SLOTS]>,
+    },
+}
+
+impl<T> Node<T> {
+    fn new(shift: u32) -> Box<Self> {
+        Box::new(if shift == 0 {
+            Node::Leaf {
+                present: 0,
+                values: Box::new([const { None }; SLOTS]),
+            }
+        } else {
+            Node::Branch {
+                occupied: 0,
+                full: 0,
+                children: Box::new([const { None }; SLOTS]),
+            }
+        })
+    }
+
+    fn is_full(&self) -> bool {
+        match self {
+            Node::Leaf { present, .. } => *present == u64::MAX,
+            Node::Branch { full, .. } => *full == u64::MAX,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Node::Leaf { present, .. } => *present == 0,
+            Node::Branch { occupied, .. } => *occupied == 0,
+        }
+    }
+
+    fn get(&self, shift: u32, id: u64) -> Option<&T> {
+        match self {
+            Node::Leaf { values, .. } => values[slot(id, shift)].as_ref(),
+            Node::Branch { children, .. } => {
+                children[slot(id, shift)].as_ref()?.get(shift - SHIFT, id)
+            }
+        }
+    }
+
+    fn get_mut(&mut self, shift: u32, id: u64) -> Option<&mut T> {
+        match self {
+            Node::Leaf { values, .. } => values[slot(id, shift)].as_mut(),
+            Node::Branch { children, .. } => children[slot(id, shift)]
+                .as_mut()?
+                .get_mut(shift - SHIFT, id),
+        }
+    }
+
+    fn insert(&mut self, shift: u32, id: u64, value: T) -> Result<(), T> {
+        let slot = slot(id, shift);
+        let bit = 1 << slot;
+
+        match self {
+            Node::Leaf { present, values } => {
+                if *present & bit != 0 {
+                    return Err(value);
+                }
+
+                values[slot] = Some(value);
+                *present |= bit;
+            }
+            Node::Branch {
+                occupied,
+                full,
+                children,
+            } => {
+                let child = children[slot].get_or_insert_with(|| Node::new(shift - SHIFT));
+
+                *occupied |= bit;
+                child.insert(shift - SHIFT, id, value)?;
+
+                if child.is_full() {
+                    *full |= bit;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove(&mut self, shift: u32, id: u64) -> Option<T> {
+        let slot = slot(id, shift);
+        let bit = 1 << slot;
+
+        match self {
+            Node::Leaf { present, values } => {
+                let value = values[slot].take()?;
+
+                *present &= !bit;
+
+                Some(value)
+            }
+            Node::Branch {
+                occupied,
+                full,
+                children,
+            } => {
+                let child = children[slot].as_mut()?;
+                let value = child.remove(shift - SHIFT, id)?;
+
+                *full &= !bit;
+
+                if child.is_empty() {
+                    children[slot] = None;
+                    *occupied &= !bit;
+                }
+
+                Some(value)
+            }
+        }
+    }
+
+    /// Returns the lowest free ID at or above `from`, which has to lie in this
+    /// node, whose first ID is `base`.
+    fn first_free(&self, shift: u32, base: u64, from: u64) -> Option<u64> {
+        let start = slot(from, shift);
+
+        match self {
+            Node::Leaf { present, .. } => {
+                let free = !present & (u64::MAX << start);
+
+                (free != 0).then(|| base + free.trailing_zeros() as u64)
+            }
+            Node::Branch { full, children, .. } => {
+                let mut candidates = !full & (u64::MAX << start);
+
+                while candidates != 0 {
+                    let slot = candidates.trailing_zeros() as usize;
+                    let child_base = base + ((slot as u64) << shift);
+                    let from = from.max(child_base);
+
+                    candidates &= candidates - 1;
+
+                    // A child that isn't full can still be full from `from`
+                    // onwards.
+                    match &children[slot] {
+                        None => return Some(from),
+                        Some(child) => {
+                            if let Some(id) = child.first_free(shift - SHIFT, child_base, from) {
+                                return Some(id);
+                            }
+                        }
+                    }
+                }
+
+                None
+            }
+        }
+    }
+}
+
+/// A map from integer IDs to values that allocates the IDs itself.
+pub struct Idr<T> {
+    root: Option<Box<Node<T>>>,
+    /// How far apart the slots of the root node are. The tree holds IDs below
+    /// `1 << (shift + 6)`, and grows a level whenever a larger one goes in.
+    shift: u32,
+    len: usize,
+    /// Where [`Idr::alloc_cyclic`] starts looking next.
+    cursor: u32,
+}
+
+impl<T> Idr<T> {
+    /// Creates an empty map.
+    pub const fn new() -> Self {
+        Self {
+            root: None,
+            shift: 0,
+            len: 0,
+            cursor: 0,
+        }
+    }
+
+    fn capacity(&self) -> u64 {
+        1 << (self.shift + SHIFT)
+    }
+
+    /// Returns the number of allocated IDs.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no ID is allocated.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the ID after the one [`Idr::alloc_cyclic`] handed out last.
+    pub fn cursor(&self) -> u32 {
+        self.cursor
+    }
+
+    /// Returns the value of `id`, if it's allocated.
+    pub fn get(&self, id: u32) -> Option<&T> {
+        let id = id as u64;
+
+        if id >= self.capacity() {
+            return None;
+        }
+
+        self.root.as_ref()?.get(self.shift, id)
+    }
+
+    /// Returns the value of `id` mutably, if it's allocated.
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut T> {
+        let id = id as u64;
+
+        if id >= self.capacity() {
+            return None;
+        }
+
+        self.root.as_mut()?.get_mut(self.shift, id)
+    }
+
+    /// Returns `true` if `id` is allocated.
+    pub fn contains(&self, id: u32) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Allocates `id` itself, for `value`. Hands `value` back if `id` is
+    /// already allocated.
+    pub fn insert(&mut self, id: u32, value: T) -> Result<(), T> {
+        let id = id as u64;
+
+        while id >= self.capacity() {
+            if let Some(old) = self.root.take() {
+                let mut root = Node::new(self.shift + SHIFT);
+
+                if let Node::Branch {
+                    occupied,
+                    full,
+                    children,
+                } = &mut *root
+                {
+                    *occupied = 1;
+                    *full = old.is_full() as u64;
+                    children[0] = Some(old);
+                }
+
+                self.root = Some(root);
+            }
+
+            self.shift += SHIFT;
+        }
+
+        let shift = self.shift;
+
+        self.root
+            .get_or_insert_with(|| Node::new(shift))
+            .insert(shift, id, value)?;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Frees `id`, returning its value.
+    pub fn remove(&mut self, id: u32) -> Option<T> {
+        let id = id as u64;
+
+        if id >= self.capacity() {
+            return None;
+        }
+
+        let value = self.root.as_mut()?.remove(self.shift, id)?;
+
+        self.len -= 1;
+
+        Some(value)
+    }
+
+    /// Frees every ID.
+    pub fn clear(&mut self) {
+        self.root = None;
+        self.shift = 0;
+        self.len = 0;
+    }
+
+    /// Returns the lowest free ID at or above `from`.
+    pub fn first_free(&self, from: u32) -> Option<u32> {
+        let from = from as u64;
+        let capacity = self.capacity();
+
+        let id = match &self.root {
+            Some(root) if from < capacity => {
+                root.first_free(self.shift, 0, from).unwrap_or(capacity)
+            }
+            _ => from,
+        };
+
+        u32::try_from(id).ok()
+    }
+
+    /// Returns the lowest free ID in `range` that `usable` accepts.
+    fn find_free(&self, range: Range<u32>, usable: &mut impl FnMut(u32) -> bool) -> Option<u32> {
+        let mut from = range.start;
+
+        while from < range.end {
+            let id = self.first_free(from).filter(|id| *id < range.end)?;
+
+            if usable(id) {
+                return Some(id);
+            }
+
+            from = id + 1;
+        }
+
+        None
+    }
+
+    /// Allocates the lowest free ID in `range` for `value`.
+    pub fn alloc(&mut self, range: Range<u32>, value: T) -> Option<u32> {
+        let id = self.find_free(range, &mut |_| true)?;
+
+        self.insert(id, value).ok()?;
+
+        Some(id)
+    }
+
+    /// Allocates the lowest free ID in `range` that comes after the one
+    /// allocated this way last, for `value`. Once there are none left there,
+    /// it wraps around to the start of `range`. An ID that's freed isn't
+    /// handed out again until every other one has been in the meantime.
+    pub fn alloc_cyclic(&mut self, range: Range<u32>, value: T) -> Option<u32> {
+        self.alloc_cyclic_where(range, value, |_| true)
+    }
+
+    /// Like [`Idr::alloc_cyclic`], but passes over free IDs that `usable`
+    /// turns down.
+    pub fn alloc_cyclic_where(
+        &mut self,
+        range: Range<u32>,
+        value: T,
+        mut usable: impl FnMut(u32) -> bool,
+    ) -> Option<u32> {
+        let cursor = self.cursor.clamp(range.start, range.end.max(range.start));
+
+        let id = self
+            .find_free(cursor..range.end, &mut usable)
+            .or_else(|| self.find_free(range.start..cursor, &mut usable))?;
+
+        self.insert(id, value).ok()?;
+        self.cursor = id + 1;
+
+        Some(id)
+    }
+
+    /// Returns the allocated IDs and their values, lowest ID first.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            stack: self
+                .root
+                .as_deref()
+                .map(|root| (root, 0, self.shift, 0))
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl<T> Default for Idr<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An iterator over the allocated IDs of an [`Idr`] and their values.
+pub struct Iter<'a, T> {
+    /// The nodes being walked, each with its first ID, its shift and the next
+    /// slot to look at.
+    stack: Vec<(&'a Node<T>, u64, u32, usize)>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (u32, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, base, shift, next) = self.stack.last_mut()?;
+            let (node, base, shift) = (*node, *base, *shift);
+            let slot = *next;
+
+            if slot == SLOTS {
+                self.stack.pop();
+                continue;
+            }
+
+            *next += 1;
+
+            let slot_base = base + ((slot as u64) << shift);
+
+            match node {
+                Node::Leaf { values, .. } => {
+                    if let Some(value) = &values[slot] {
+                        return Some((slot_base as u32, value));
+                    }
+                }
+                Node::Branch { children, .. } => {
+                    if let Some(child) = &children[slot] {
+                        self.stack.push((child, slot_base, shift - SHIFT, 0));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alloc_hands_out_the_lowest_free_id() {
+        let mut idr = Idr::new();
+
+        assert_eq!(idr.alloc(0..10, 'a'), Some(0));
+        assert_eq!(idr.alloc(0..10, 'b'), Some(1));
+        assert_eq!(idr.alloc(0..10, 'c'), Some(2));
+        assert_eq!(idr.remove(1), Some('b'));
+        assert_eq!(idr.alloc(0..10, 'd'), Some(1));
+        assert_eq!(idr.get(1), Some(&'d'));
+        assert_eq!(idr.len(), 3);
+    }
+
+    #[test]
+    fn alloc_fails_once_the_range_is_full() {
+        let mut idr = Idr::new();
+
+        for id in 5..8 {
+            assert_eq!(idr.alloc(5..8, ()), Some(id));
+        }
+
+        assert_eq!(idr.alloc(5..8, ()), None);
+        assert_eq!(idr.len(), 3);
+    }
+
+    #[test]
+    fn insert_refuses_an_allocated_id() {
+        let mut idr = Idr::new();
+
+        assert_eq!(idr.insert(7, 1), Ok(()));
+        assert_eq!(idr.insert(7, 2), Err(2));
+        assert_eq!(idr.get(7), Some(&1));
+
+        *idr.get_mut(7).unwrap() = 3;
+
+        assert_eq!(idr.remove(7), Some(3));
+        assert_eq!(idr.remove(7), None);
+        assert!(idr.is_empty());
+    }
+
+    #[test]
+    fn ids_across_many_nodes() {
+        let mut idr = Idr::new();
+
+        for id in 0..10_000 {
+            assert_eq!(idr.alloc(0..u32::MAX, id), Some(id));
+        }
+
+        assert_eq!(idr.remove(4_097), Some(4_097));
+        assert_eq!(idr.first_free(0), Some(4_097));
+        assert_eq!(idr.first_free(4_098), Some(10_000));
+        assert_eq!(idr.alloc(0..u32::MAX, 0), Some(4_097));
+        assert_eq!(idr.alloc(0..u32::MAX, 0), Some(10_000));
+    }
+
+    #[test]
+    fn largest_ids() {
+        let mut idr = Idr::new();
+
+        assert_eq!(idr.insert(u32::MAX, ()), Ok(()));
+        assert!(idr.contains(u32::MAX));
+        assert!(!idr.contains(0));
+        assert_eq!(idr.first_free(u32::MAX), None);
+        assert_eq!(idr.first_free(u32::MAX - 1), Some(u32::MAX - 1));
+        assert_eq!(idr.alloc(1..10, ()), Some(1));
+    }
+
+    #[test]
+    fn alloc_cyclic_does_not_reuse_freed_ids_straight_away() {
+        let mut idr = Idr::new();
+
+        assert_eq!(idr.alloc_cyclic(10..14, ()), Some(10));
+        assert_eq!(idr.alloc_cyclic(10..14, ()), Some(11));
+        assert_eq!(idr.remove(10), Some(()));
+        assert_eq!(idr.alloc_cyclic(10..14, ()), Some(12));
+        assert_eq!(idr.alloc_cyclic(10..14, ()), Some(13));
+        assert_eq!(idr.cursor(), 14);
+        // Wraps around to the ID freed earlier.
+        assert_eq!(idr.alloc_cyclic(10..14, ()), Some(10));
+        assert_eq!(idr.alloc_cyclic(10..14, ()), None);
+    }
+
+    #[test]
+    fn alloc_cyclic_where_passes_over_unusable_ids() {
+        let mut idr = Idr::new();
+
+        assert_eq!(
+            idr.alloc_cyclic_where(0..100, (), |id| id % 2 == 1),
+            Some(1)
+        );
+        assert_eq!(
+            idr.alloc_cyclic_where(0..100, (), |id| id % 2 == 1),
+            Some(3)
+        );
+        assert_eq!(idr.alloc_cyclic_where(0..4, (), |id| id % 2 == 1), None);
+        assert_eq!(idr.alloc_cyclic(0..4, ()), Some(0));
+    }
+
+    #[test]
+    fn iter_yields_ids_in_order() {
+        let mut idr = Idr::new();
+
+        for id in [300, 2, 70, 5_000] {
+            idr.insert(id, id * 2).unwrap();
+        }
+
+        let items: Vec<_> = idr.iter().map(|(id, value)| (id, *value)).collect();
+
+        assert_eq!(items, [(2, 4), (70, 140), (300, 600), (5_000, 10_000)]);
+
+        idr.clear();
+
+        assert_eq!(idr.iter().count(), 0);
+        assert!(idr.is_empty());
+    }
+}
@@ -42,25 +42,31 @@
 //!   and per-CPU storage *(feature `sync`)*.
 //! - [`fs`]     — VFS traits (`Filesystem`, `Inode`, `BlockDevice`), path
 //!   manipulation, and filesystem driver scaffolding *(feature `fs`)*.
+//! - [`driver`] — Device numbers, MMIO register access and a timer queue for
+//!   drivers, unit-testable against the mocks in the `test` module.
+//! - [`idr`]    — Integer ID allocation, for pids and other small IDs.
 //! - [`proc`]   — Process identity types and Linux-compatible capabilities
 //!   *(feature `proc`)*.
 //! - [`arch`]   — Architecture-specific support code *(feature `paging`)*.
+//! - [`sched`]  — The EEVDF scheduling policy, with a deterministic host-side
+//!   simulator for testing it.
 
 #![cfg_attr(not(test), no_std)]
 #![warn(missing_docs)]
 
 #[cfg(feature = "paging")]
 pub mod arch;
-#[cfg(feature = "fs")]
 pub mod driver;
 pub mod error;
 #[cfg(feature = "fs")]
 pub mod fs;
+pub mod idr;
 pub mod memory;
 #[cfg(feature = "fs")]
 pub mod pod;
 #[cfg(feature = "proc")]
 pub mod proc;
+pub mod sched;
 #[cfg(feature = "sync")]
 pub mod sync;
 
@@ -114,33 +120,4 @@ pub trait CpuOps: 'static {
 
 #[cfg(test)]
 #[allow(missing_docs)]
-pub mod test {
-    use core::hint::spin_loop;
-
-    use crate::CpuOps;
-
-    // A CPU mock object that can be used in unit-tests.
-    pub struct MockCpuOps {}
-
-    impl CpuOps for MockCpuOps {
-        type InterruptFlags = usize;
-
-        fn id() -> usize {
-            0
-        }
-
-        fn halt() -> ! {
-            loop {
-                spin_loop();
-            }
-        }
-
-        fn disable_interrupts() -> usize {
-            0
-        }
-
-        fn restore_interrupt_state(_flags: usize) {}
-
-        fn enable_interrupts() {}
-    }
-}
+pub mod test;
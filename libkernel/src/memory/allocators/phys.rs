@@ -1,11 +1,15 @@
 //! Physical page-frame allocator (buddy allocator).
+//!
+//! Memory is split into zones, each with its own free lists, so that devices
+//! which can only address part of physical memory can be given buffers they
+//! can reach.
 
 use crate::{
     CpuOps,
     error::{KernelError, Result},
     memory::{
         PAGE_SHIFT,
-        address::AddressTranslator,
+        address::{AddressTranslator, PA},
         allocators::{
             frame::FrameState,
             slab::{SLAB_FRAME_ALLOC_ORDER, SLAB_SIZE_BYTES},
@@ -32,13 +36,63 @@ use super::{
 /// 2^MAX_ORDER pages.
 pub const MAX_ORDER: usize = 10;
 
+/// Physical addresses below this lie in [`Zone::Dma32`].
+pub const DMA32_LIMIT: usize = 1 << 32;
+
+const NR_ZONES: usize = 2;
+
+/// A range of physical memory with its own free lists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Zone {
+    /// Memory below [`DMA32_LIMIT`], reachable by devices that can only
+    /// address 32 bits.
+    Dma32 = 0,
+    /// All other memory.
+    Normal = 1,
+}
+
+bitflags::bitflags! {
+    /// Flags controlling where [`FrameAllocator::alloc_frames_flags`] takes
+    /// frames from.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct AllocFlags: u32 {
+        /// Only allocate from [`Zone::Dma32`].
+        const DMA32 = 1 << 0;
+    }
+}
+
+/// Callbacks invoked as blocks of frames are handed out and returned, e.g.
+/// to keep a sanitizer's shadow memory in step with the allocator.
+///
+/// Hooks run with the allocator lock held and must not allocate frames.
+#[cfg(feature = "kasan")]
+pub trait FrameHooks: Sync {
+    /// Called with a block of frames that has just been allocated.
+    fn on_alloc(&self, region: PhysMemoryRegion);
+    /// Called with a block of frames that has just been freed.
+    fn on_free(&self, region: PhysMemoryRegion);
+}
+
 pub(super) struct FrameAllocatorInner {
     frame_list: FrameList,
-    free_pages: usize,
-    free_lists: [LinkedList<FrameAdapter>; MAX_ORDER + 1],
+    free_pages: [usize; NR_ZONES],
+    free_lists: [[LinkedList<FrameAdapter>; MAX_ORDER + 1]; NR_ZONES],
+    /// The first frame of [`Zone::Normal`]. Since it is aligned to a
+    /// `MAX_ORDER` block, buddies always share a zone.
+    normal_start: PageFrame,
+    #[cfg(feature = "kasan")]
+    hooks: Option<&'static dyn FrameHooks>,
 }
 
 impl FrameAllocatorInner {
+    fn zone_of(&self, pfn: PageFrame) -> Zone {
+        if pfn < self.normal_start {
+            Zone::Dma32
+        } else {
+            Zone::Normal
+        }
+    }
+
     pub(super) fn free_slab(&mut self, frame: UnsafeRef<Frame>) {
         assert!(matches!(frame.state, FrameState::Slab(_)));
 
@@ -62,7 +116,7 @@ impl FrameAllocatorInner {
     /// Frees a previously allocated block of frames.
     /// The PFN can point to any page within the allocated block.
     fn free_frames(&mut self, region: PhysMemoryRegion) {
-        let head_pfn = region.start_address().to_pfn();
+        let head_pfn = self.head_of(region.start_address().to_pfn());
 
         debug_assert!(matches!(
             self.get_frame(head_pfn).state,
@@ -80,6 +134,14 @@ impl FrameAllocatorInner {
                 unreachable!("Logic error: head PFN is not an AllocatedHead");
             };
 
+        #[cfg(feature = "kasan")]
+        if let Some(hooks) = self.hooks {
+            hooks.on_free(PhysMemoryRegion::new(
+                head_pfn.pa(),
+                1 << (initial_order + PAGE_SHIFT),
+            ));
+        }
+
         // Before merging, the block we're freeing is no longer allocated. Set
         // it to a temporary state. This prevents stale AllocatedHead states if
         // this block gets absorbed by its lower buddy.
@@ -122,7 +184,20 @@ impl FrameAllocatorInner {
         }; // Add the correctly-stated block to the correct free list.
         self.add_to_free_list(current_pfn, merged_order);
 
-        self.free_pages += 1 << initial_order;
+        let zone = self.zone_of(current_pfn);
+        self.free_pages[zone as usize] += 1 << initial_order;
+    }
+
+    /// Returns the head frame of the allocated block containing `pfn`.
+    ///
+    /// The reference count of a block is held by its head, so that a page
+    /// taken from the middle of a block (e.g. a 4KiB page of a huge page)
+    /// pins the whole block.
+    fn head_of(&self, pfn: PageFrame) -> PageFrame {
+        match self.get_frame(pfn).state {
+            FrameState::AllocatedTail(TailInfo { head }) => head,
+            _ => pfn,
+        }
     }
 
     #[inline]
@@ -139,13 +214,17 @@ impl FrameAllocatorInner {
         #[cfg(test)]
         assert!(matches!(self.get_frame(pfn).state, FrameState::Free { .. }));
 
-        self.free_lists[order]
+        let zone = self.zone_of(pfn);
+
+        self.free_lists[zone as usize][order]
             .push_front(unsafe { UnsafeRef::from_raw(self.get_frame(pfn) as *const _) });
     }
 
     fn remove_from_free_list(&mut self, pfn: PageFrame, order: usize) {
+        let zone = self.zone_of(pfn);
+
         let Some(_) = (unsafe {
-            self.free_lists[order]
+            self.free_lists[zone as usize][order]
                 .cursor_mut_from_ptr(self.get_frame(pfn) as *const _)
                 .remove()
         }) else {
@@ -181,7 +260,9 @@ impl FrameAllocatorInner {
                     order: MAX_ORDER as _,
                 };
                 self.add_to_free_list(current_pfn, MAX_ORDER);
-                self.free_pages += 1 << MAX_ORDER;
+
+                let zone = self.zone_of(current_pfn);
+                self.free_pages[zone as usize] += 1 << MAX_ORDER;
             }
             current_pfn = PageFrame::from_pfn(current_pfn.value() + (1 << MAX_ORDER));
         }
@@ -237,11 +318,9 @@ impl<CPU: CpuOps> PageAllocation<'_, CPU> {
 impl<CPU: CpuOps> Clone for PageAllocation<'_, CPU> {
     fn clone(&self) -> Self {
         let mut inner = self.inner.lock_save_irq();
+        let head = inner.head_of(self.region.start_address().to_pfn());
 
-        match inner
-            .get_frame_mut(self.region.start_address().to_pfn())
-            .state
-        {
+        match inner.get_frame_mut(head).state {
             FrameState::AllocatedHead(ref mut alloc_info) => {
                 alloc_info.ref_count += 1;
             }
@@ -269,6 +348,21 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
     /// * `order`: The order of the allocation, where the number of pages is `2^order`.
     ///   `order = 0` requests a single page.
     pub fn alloc_frames(&self, order: u8) -> Result<PageAllocation<'_, CPU>> {
+        self.alloc_frames_flags(order, AllocFlags::empty())
+    }
+
+    /// Allocates a physically contiguous block of frames, as
+    /// [`alloc_frames`](Self::alloc_frames), from the zones allowed by
+    /// `flags`.
+    ///
+    /// Without [`AllocFlags::DMA32`], frames come from [`Zone::Normal`] and
+    /// only fall back to [`Zone::Dma32`] once it's exhausted, keeping the
+    /// low memory for the devices that need it.
+    pub fn alloc_frames_flags(
+        &self,
+        order: u8,
+        flags: AllocFlags,
+    ) -> Result<PageAllocation<'_, CPU>> {
         let mut inner = self.inner.lock_save_irq();
         let requested_order = order as usize;
 
@@ -276,13 +370,20 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
             return Err(KernelError::InvalidValue);
         }
 
-        // Find the smallest order >= the requested order that has a free block.
-        let Some((free_block, mut current_order)) =
+        let zones: &[Zone] = if flags.contains(AllocFlags::DMA32) {
+            &[Zone::Dma32]
+        } else {
+            &[Zone::Normal, Zone::Dma32]
+        };
+
+        // Find the smallest order >= the requested order that has a free
+        // block, in the first zone that has one.
+        let Some((free_block, mut current_order, zone)) = zones.iter().find_map(|&zone| {
             (requested_order..=MAX_ORDER).find_map(|order| {
-                let pg_block = inner.free_lists[order].pop_front()?;
-                Some((pg_block, order))
+                let pg_block = inner.free_lists[zone as usize][order].pop_front()?;
+                Some((pg_block, order, zone))
             })
-        else {
+        }) else {
             return Err(KernelError::NoMemory);
         };
 
@@ -314,14 +415,42 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
                 FrameState::AllocatedTail(TailInfo { head: block_pfn });
         }
 
-        inner.free_pages -= num_pages_in_block;
+        inner.free_pages[zone as usize] -= num_pages_in_block;
+
+        let region = PhysMemoryRegion::new(block_pfn.pa(), num_pages_in_block << PAGE_SHIFT);
+
+        #[cfg(feature = "kasan")]
+        if let Some(hooks) = inner.hooks {
+            hooks.on_alloc(region);
+        }
 
         Ok(PageAllocation {
-            region: PhysMemoryRegion::new(block_pfn.pa(), num_pages_in_block << PAGE_SHIFT),
+            region,
             inner: &self.inner,
         })
     }
 
+    /// Installs `hooks` to be told about every subsequent allocation and
+    /// free. Every block that is currently free is reported to
+    /// [`FrameHooks::on_free`] first.
+    #[cfg(feature = "kasan")]
+    pub fn set_hooks(&self, hooks: &'static dyn FrameHooks) {
+        let mut inner = self.inner.lock_save_irq();
+
+        for lists in inner.free_lists.iter() {
+            for (order, list) in lists.iter().enumerate() {
+                for frame in list.iter() {
+                    hooks.on_free(PhysMemoryRegion::new(
+                        frame.pfn.pa(),
+                        1 << (order + PAGE_SHIFT),
+                    ));
+                }
+            }
+        }
+
+        inner.hooks = Some(hooks);
+    }
+
     /// Constructs an allocation from a phys mem region.
     ///
     /// # Safety
@@ -371,7 +500,34 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
     /// Returns the current number of free pages available for allocation.
     #[inline]
     pub fn free_pages(&self) -> usize {
-        self.inner.lock_save_irq().free_pages
+        self.inner.lock_save_irq().free_pages.iter().sum()
+    }
+
+    /// Returns the number of free pages in `zone`.
+    #[inline]
+    pub fn zone_free_pages(&self, zone: Zone) -> usize {
+        self.inner.lock_save_irq().free_pages[zone as usize]
+    }
+
+    /// Returns the number of free blocks of each order in `zone`, indexed by
+    /// order.
+    pub fn zone_free_blocks(&self, zone: Zone) -> [usize; MAX_ORDER + 1] {
+        let inner = self.inner.lock_save_irq();
+
+        core::array::from_fn(|order| inner.free_lists[zone as usize][order].iter().count())
+    }
+
+    /// Returns `true` if any of the memory managed by this allocator lies in
+    /// `zone`.
+    pub fn zone_populated(&self, zone: Zone) -> bool {
+        let inner = self.inner.lock_save_irq();
+        let start = inner.frame_list.base_page();
+        let end = start.add_pages(inner.frame_list.total_pages());
+
+        match zone {
+            Zone::Dma32 => start < inner.normal_start,
+            Zone::Normal => end > inner.normal_start,
+        }
     }
 
     /// Initializes the frame allocator. This is the main bootstrap function.
@@ -473,8 +629,13 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
 
         let mut allocator = FrameAllocatorInner {
             frame_list: frame_list.clone(),
-            free_pages: 0,
-            free_lists: core::array::from_fn(|_| LinkedList::new(FrameAdapter::new())),
+            free_pages: [0; NR_ZONES],
+            free_lists: core::array::from_fn(|_| {
+                core::array::from_fn(|_| LinkedList::new(FrameAdapter::new()))
+            }),
+            normal_start: PA::from_value(DMA32_LIMIT).to_pfn(),
+            #[cfg(feature = "kasan")]
+            hooks: None,
         };
 
         for res_region in smalloc.res.iter() {
@@ -496,9 +657,10 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
     // emitting a log line summarising the resulting state.
     fn finalize(allocator: FrameAllocatorInner, frame_list: FrameList) -> (Self, FrameList) {
         info!(
-            "Buddy allocator initialized. Managing {} pages, {} free.",
+            "Buddy allocator initialized. Managing {} pages, {} free ({} in DMA32).",
             frame_list.total_pages(),
-            allocator.free_pages
+            allocator.free_pages.iter().sum::<usize>(),
+            allocator.free_pages[Zone::Dma32 as usize]
         );
 
         (
@@ -616,9 +778,14 @@ pub mod tests {
         /// Checks that the number of blocks in each free list matches the expected counts.
         fn assert_free_list_counts(&self, expected_counts: &[usize; MAX_ORDER + 1]) {
             for order in 0..=MAX_ORDER {
-                let count = self.allocator.inner.lock_save_irq().free_lists[order]
+                let count: usize = self
+                    .allocator
+                    .inner
+                    .lock_save_irq()
+                    .free_lists
                     .iter()
-                    .count();
+                    .map(|zone| zone[order].iter().count())
+                    .sum();
                 assert_eq!(
                     count, expected_counts[order],
                     "Mismatch in free list count for order {}",
@@ -628,7 +795,32 @@ pub mod tests {
         }
 
         fn free_pages(&self) -> usize {
-            self.allocator.inner.lock_save_irq().free_pages
+            self.allocator.free_pages()
+        }
+
+        /// Moves the start of `Zone::Normal` to `offset` bytes into the
+        /// backing memory, re-filing the free blocks into their new zones.
+        fn set_normal_start(&self, offset: usize) {
+            let mut inner = self.allocator.inner.lock_save_irq();
+            let mut blocks = Vec::new();
+
+            for zone in inner.free_lists.iter_mut() {
+                for (order, list) in zone.iter_mut().enumerate() {
+                    while let Some(frame) = list.pop_front() {
+                        blocks.push((frame.pfn, order));
+                    }
+                }
+            }
+
+            inner.free_pages = [0; NR_ZONES];
+            inner.normal_start = PageFrame::from_pfn((self.base_ptr as usize + offset) / PAGE_SIZE);
+
+            for (pfn, order) in blocks {
+                inner.add_to_free_list(pfn, order);
+
+                let zone = inner.zone_of(pfn);
+                inner.free_pages[zone as usize] += 1 << order;
+            }
         }
 
         pub fn from_region(
@@ -705,6 +897,7 @@ pub mod tests {
                     .lock_save_irq()
                     .free_lists
                     .iter_mut()
+                    .flatten()
                     .for_each(|x| x.clear());
 
                 std::alloc::dealloc(self.base_ptr, self.layout);
@@ -719,12 +912,9 @@ pub mod tests {
         let pages_in_max_block = 1 << MAX_ORDER;
 
         assert_eq!(fixture.free_pages(), pages_in_max_block);
-        assert!(!fixture.allocator.inner.lock_save_irq().free_lists[MAX_ORDER].is_empty());
-
-        // Check that all other lists are empty
-        for i in 0..MAX_ORDER {
-            assert!(fixture.allocator.inner.lock_save_irq().free_lists[i].is_empty());
-        }
+        let mut expected_counts = [0; MAX_ORDER + 1];
+        expected_counts[MAX_ORDER] = 1;
+        fixture.assert_free_list_counts(&expected_counts);
     }
 
     #[test]
@@ -1117,4 +1307,179 @@ pub mod tests {
         assert_eq!(fixture.free_pages(), initial_free);
         assert!(matches!(fixture.frame_state(pfn), FrameState::Free { .. }));
     }
+
+    /// A reference taken through a tail page pins the whole block.
+    #[test]
+    fn ref_count_tail_page() {
+        let fixture = TestFixture::new(&[(0, (1 << (MAX_ORDER + PAGE_SHIFT)) * 2)], &[]);
+        let initial_free = fixture.free_pages();
+
+        let block = fixture.allocator.alloc_frames(2).unwrap();
+        let head = block.region().start_address().to_pfn();
+        let tail_region = PhysMemoryRegion::new(head.add_pages(3).pa(), PAGE_SIZE);
+        block.leak();
+
+        // Hand the block's reference over to one of its tail pages, and take
+        // another one through it.
+        let tail = unsafe { fixture.allocator.alloc_from_region(tail_region) };
+        let tail_ref = tail.clone();
+
+        if let FrameState::AllocatedHead(info) = fixture.frame_state(head) {
+            assert_eq!(info.ref_count, 2);
+        } else {
+            panic!("Head page state changed unexpectedly");
+        }
+
+        drop(tail_ref);
+        assert_eq!(fixture.free_pages(), initial_free - 4);
+
+        drop(tail);
+        assert_eq!(fixture.free_pages(), initial_free);
+        assert!(matches!(fixture.frame_state(head), FrameState::Free { .. }));
+    }
+
+    /// A DMA32 allocation only takes frames below the zone boundary, and a
+    /// plain one prefers those above it.
+    #[test]
+    fn dma32_alloc_is_low() {
+        let block_size = (1 << MAX_ORDER) * PAGE_SIZE;
+        let fixture = TestFixture::new(&[(0, 4 * block_size)], &[]);
+        fixture.set_normal_start(2 * block_size);
+
+        let boundary =
+            PageFrame::from_pfn((fixture.base_ptr as usize + 2 * block_size) / PAGE_SIZE);
+        let dma_free = fixture.allocator.zone_free_pages(Zone::Dma32);
+        let normal_free = fixture.allocator.zone_free_pages(Zone::Normal);
+        assert!(dma_free > 0);
+        assert_eq!(normal_free, 2 << MAX_ORDER);
+
+        let low = fixture
+            .allocator
+            .alloc_frames_flags(2, AllocFlags::DMA32)
+            .unwrap();
+        assert!(low.region().end_address().to_pfn() <= boundary);
+        assert_eq!(fixture.allocator.zone_free_pages(Zone::Dma32), dma_free - 4);
+
+        let high = fixture.allocator.alloc_frames(2).unwrap();
+        assert!(high.region().start_address().to_pfn() >= boundary);
+        assert_eq!(
+            fixture.allocator.zone_free_pages(Zone::Normal),
+            normal_free - 4
+        );
+
+        drop(low);
+        drop(high);
+        assert_eq!(fixture.allocator.zone_free_pages(Zone::Dma32), dma_free);
+        assert_eq!(fixture.allocator.zone_free_pages(Zone::Normal), normal_free);
+    }
+
+    /// Plain allocations fall back to DMA32 memory, but DMA32 allocations
+    /// never take normal memory.
+    #[test]
+    fn dma32_zone_exhaustion() {
+        let block_size = (1 << MAX_ORDER) * PAGE_SIZE;
+        let fixture = TestFixture::new(&[(0, 4 * block_size)], &[]);
+        fixture.set_normal_start(3 * block_size);
+
+        let dma_free = fixture.allocator.zone_free_pages(Zone::Dma32);
+        let mut allocs = Vec::new();
+
+        while let Ok(alloc) = fixture.allocator.alloc_frames_flags(0, AllocFlags::DMA32) {
+            allocs.push(alloc);
+        }
+
+        assert_eq!(allocs.len(), dma_free);
+        assert_eq!(fixture.allocator.zone_free_pages(Zone::Dma32), 0);
+        assert_eq!(
+            fixture.allocator.zone_free_pages(Zone::Normal),
+            1 << MAX_ORDER
+        );
+
+        // Free the DMA32 zone again and use up the normal zone instead.
+        allocs.clear();
+
+        let normal = fixture.allocator.alloc_frames(MAX_ORDER as u8).unwrap();
+        assert_eq!(fixture.allocator.zone_free_pages(Zone::Dma32), dma_free);
+
+        let fallback = fixture.allocator.alloc_frames(0).unwrap();
+        assert_eq!(fixture.allocator.zone_free_pages(Zone::Dma32), dma_free - 1);
+
+        drop(fallback);
+        drop(normal);
+        assert_eq!(fixture.free_pages(), dma_free + (1 << MAX_ORDER));
+    }
+
+    /// Free blocks are reported per zone and order.
+    #[test]
+    fn zone_free_blocks() {
+        let block_size = (1 << MAX_ORDER) * PAGE_SIZE;
+        let fixture = TestFixture::new(&[(0, 4 * block_size)], &[]);
+        fixture.set_normal_start(3 * block_size);
+
+        assert!(fixture.allocator.zone_populated(Zone::Dma32));
+        assert!(fixture.allocator.zone_populated(Zone::Normal));
+
+        let mut expected = [0; MAX_ORDER + 1];
+        expected[MAX_ORDER] = 1;
+        assert_eq!(fixture.allocator.zone_free_blocks(Zone::Normal), expected);
+
+        // Splitting the top block leaves one free block of each lower order.
+        let alloc = fixture.allocator.alloc_frames(0).unwrap();
+        let mut expected = [1; MAX_ORDER + 1];
+        expected[MAX_ORDER] = 0;
+        assert_eq!(fixture.allocator.zone_free_blocks(Zone::Normal), expected);
+
+        let blocks = fixture.allocator.zone_free_blocks(Zone::Dma32);
+        let pages: usize = blocks.iter().enumerate().map(|(order, n)| n << order).sum();
+        assert_eq!(pages, fixture.allocator.zone_free_pages(Zone::Dma32));
+
+        drop(alloc);
+        let mut expected = [0; MAX_ORDER + 1];
+        expected[MAX_ORDER] = 1;
+        assert_eq!(fixture.allocator.zone_free_blocks(Zone::Normal), expected);
+    }
+
+    #[cfg(feature = "kasan")]
+    #[test]
+    fn frame_hooks() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct CountingHooks {
+            allocated: AtomicUsize,
+            freed: AtomicUsize,
+        }
+
+        impl FrameHooks for CountingHooks {
+            fn on_alloc(&self, region: PhysMemoryRegion) {
+                self.allocated.fetch_add(region.size(), Ordering::Relaxed);
+            }
+
+            fn on_free(&self, region: PhysMemoryRegion) {
+                self.freed.fetch_add(region.size(), Ordering::Relaxed);
+            }
+        }
+
+        let fixture = TestFixture::new(&[(0, 8 * MIB)], &[]);
+        let hooks: &'static CountingHooks = Box::leak(Box::default());
+
+        // Installing the hooks reports everything that's already free.
+        fixture.allocator.set_hooks(hooks);
+        let initially_free = fixture.allocator.free_pages() * PAGE_SIZE;
+        assert_eq!(hooks.freed.load(Ordering::Relaxed), initially_free);
+
+        let alloc = fixture.allocator.alloc_frames(2).unwrap();
+        assert_eq!(hooks.allocated.load(Ordering::Relaxed), 4 * PAGE_SIZE);
+
+        // Only the last reference frees the block.
+        let clone = alloc.clone();
+        drop(alloc);
+        assert_eq!(hooks.freed.load(Ordering::Relaxed), initially_free);
+
+        drop(clone);
+        assert_eq!(
+            hooks.freed.load(Ordering::Relaxed),
+            initially_free + 4 * PAGE_SIZE
+        );
+    }
 }
@@ -1,6 +1,7 @@
 //! A slab memory allocator.
 use super::{
-    SLAB_FRAME_ALLOC_ORDER, SLAB_MAX_OBJ_SHIFT, alloc_order,
+    SLAB_FRAME_ALLOC_ORDER, SLAB_MAX_OBJ_SHIFT, SLAB_SIZE_BYTES, alloc_order,
+    cache::CacheStats,
     slab::{Slab, SlabState},
 };
 use crate::{
@@ -19,6 +20,31 @@ use intrusive_collections::{LinkedList, UnsafeRef};
 
 const MAX_FREE_SLABS: usize = 32;
 
+/// Allocation statistics for a single size class.
+///
+/// Objects held in per-CPU caches count as allocated, since the slabs have
+/// handed them out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SlabStats {
+    /// The size of each object, in bytes.
+    pub obj_size: usize,
+    /// The number of objects that fit in a slab.
+    pub objs_per_slab: usize,
+    /// The number of objects currently allocated.
+    pub active_objs: usize,
+    /// The highest number of objects allocated at once.
+    pub peak_objs: usize,
+    /// The total number of objects ever allocated.
+    pub total_allocs: u64,
+    /// The number of slabs with at least one object allocated.
+    pub active_slabs: usize,
+    /// The number of slabs owned by this size class, including free ones.
+    pub num_slabs: usize,
+    /// How the per-CPU caches of this size class fared, summed over all CPUs.
+    /// Filled in by whoever owns the caches.
+    pub cpu_cache: CacheStats,
+}
+
 /// Slab manager for a specific size class.
 ///
 /// Manages a collection of slabs for a particular object size (size class). Two
@@ -62,6 +88,10 @@ pub struct SlabManager<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslato
     pub(super) free_list_sz: usize,
     obj_shift: usize,
     frame_list: FrameList,
+    num_slabs: usize,
+    active_objs: usize,
+    peak_objs: usize,
+    total_allocs: u64,
     phantom1: PhantomData<A>,
     phantom2: PhantomData<CPU>,
     phantom3: PhantomData<T>,
@@ -75,6 +105,10 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
             free_list_sz: 0,
             obj_shift,
             frame_list,
+            num_slabs: 0,
+            active_objs: 0,
+            peak_objs: 0,
+            total_allocs: 0,
             phantom1: PhantomData,
             phantom2: PhantomData,
             phantom3: PhantomData,
@@ -104,6 +138,8 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
                     .push_front(unsafe { UnsafeRef::from_raw(frame as *const _) });
             }
 
+            self.account_alloc();
+
             return Some(ptr);
         }
 
@@ -130,12 +166,20 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
                     .push_front(unsafe { UnsafeRef::from_raw(frame as *const _) });
             }
 
+            self.account_alloc();
+
             return Some(ptr);
         }
 
         None
     }
 
+    fn account_alloc(&mut self) {
+        self.active_objs += 1;
+        self.total_allocs += 1;
+        self.peak_objs = self.peak_objs.max(self.active_objs);
+    }
+
     /// Allocate an object for the given size class. Uses up partial and free
     /// slabs first; if none are avilable allocate a new slab from the frame
     /// allocator.
@@ -164,6 +208,9 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
                 .push_front(unsafe { UnsafeRef::from_raw(frame) });
         }
 
+        self.num_slabs += 1;
+        self.account_alloc();
+
         obj
     }
 
@@ -207,6 +254,8 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
             do_free_obj(frame, ptr, &self.frame_list, self.obj_shift)
         };
 
+        self.active_objs -= 1;
+
         // SAFETY: As above
         let is_linked = unsafe { (*frame).link.is_linked() };
 
@@ -226,6 +275,7 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
                     }
 
                     self.free_list_sz -= num_freed;
+                    self.num_slabs -= num_freed;
                 }
 
                 if is_linked {
@@ -251,6 +301,20 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
             SlabState::Full => unreachable!("we've just free'd an object"),
         }
     }
+
+    /// Returns the allocation statistics for this size class.
+    pub fn stats(&self) -> SlabStats {
+        SlabStats {
+            obj_size: 1 << self.obj_shift,
+            objs_per_slab: SLAB_SIZE_BYTES >> self.obj_shift,
+            active_objs: self.active_objs,
+            peak_objs: self.peak_objs,
+            total_allocs: self.total_allocs,
+            active_slabs: self.num_slabs - self.free_list_sz,
+            num_slabs: self.num_slabs,
+            cpu_cache: CacheStats::default(),
+        }
+    }
 }
 
 /// The top-level slab allocator, dispatching allocations to size-appropriate [`SlabManager`]s.
@@ -285,6 +349,12 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabAllocat
     ) -> Option<&SpinLockIrq<SlabManager<CPU, A, T>, CPU>> {
         Some(&self.managers[alloc_order(layout)?])
     }
+
+    /// Returns the allocation statistics of every size class, smallest first.
+    pub fn stats(&self) -> impl Iterator<Item = SlabStats> + '_ {
+        // No layout maps to order 0, see `alloc_order`.
+        self.managers[1..].iter().map(|m| m.lock_save_irq().stats())
+    }
 }
 
 #[cfg(test)]
@@ -481,6 +551,65 @@ mod tests {
         assert_eq!(alloc.free.iter().count(), 17);
     }
 
+    #[test]
+    fn stats_tracking() {
+        let allocator = create_allocator_fixture();
+
+        let layout = Layout::from_size_align(4096, 4096).unwrap();
+        let alloc = allocator.allocator_for_layout(layout).unwrap();
+
+        let ptrs: Vec<_> = (0..6).map(|_| alloc.lock_save_irq().alloc()).collect();
+
+        let stats = alloc.lock_save_irq().stats();
+        assert_eq!(stats.obj_size, 4096);
+        assert_eq!(stats.objs_per_slab, 4);
+        assert_eq!(stats.active_objs, 6);
+        assert_eq!(stats.peak_objs, 6);
+        assert_eq!(stats.total_allocs, 6);
+        assert_eq!(stats.active_slabs, 2);
+        assert_eq!(stats.num_slabs, 2);
+
+        // Empty the second slab; it stays cached on the free list.
+        for ptr in &ptrs[4..] {
+            alloc.lock_save_irq().free(*ptr);
+        }
+
+        let stats = alloc.lock_save_irq().stats();
+        assert_eq!(stats.active_objs, 4);
+        assert_eq!(stats.peak_objs, 6);
+        assert_eq!(stats.active_slabs, 1);
+        assert_eq!(stats.num_slabs, 2);
+
+        let ptr = alloc.lock_save_irq().alloc();
+        let stats = alloc.lock_save_irq().stats();
+        assert_eq!(stats.active_objs, 5);
+        assert_eq!(stats.total_allocs, 7);
+        assert_eq!(stats.active_slabs, 2);
+
+        alloc.lock_save_irq().free(ptr);
+        for ptr in &ptrs[..4] {
+            alloc.lock_save_irq().free(*ptr);
+        }
+
+        let stats: Vec<_> = allocator.stats().collect();
+        assert_eq!(stats.len(), SLAB_MAX_OBJ_SHIFT as usize);
+        assert_eq!(stats[0].obj_size, 2);
+
+        let stats_4k = stats[11];
+        assert_eq!(stats_4k.obj_size, 4096);
+        assert_eq!(stats_4k.active_objs, 0);
+        assert_eq!(stats_4k.active_slabs, 0);
+        assert_eq!(stats_4k.num_slabs, 2);
+
+        // Other size classes are untouched.
+        assert!(
+            stats
+                .iter()
+                .filter(|s| s.obj_size != 4096)
+                .all(|s| s.total_allocs == 0 && s.num_slabs == 0)
+        );
+    }
+
     #[test]
     #[should_panic(expected = "Layout mismatch")]
     fn layout_mismatch_panic() {
@@ -14,7 +14,9 @@ use crate::{
     },
 };
 use core::mem::MaybeUninit;
+use core::ops::AddAssign;
 use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 const PTRS_PER_SZ_CLASS: usize = 32;
 const NUM_PTR_CACHES: usize = SLAB_MAX_OBJ_SHIFT as usize + 1;
@@ -22,11 +24,75 @@ const NUM_PTR_CACHES: usize = SLAB_MAX_OBJ_SHIFT as usize + 1;
 // Ensure that our cache fits in a single page.
 const _: () = assert!(core::mem::size_of::<SlabCache>() <= PAGE_SIZE);
 
+/// How often a size class of a per-CPU cache could serve a request by itself,
+/// and how often it had to take the slab lock instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Allocations served from the cache.
+    pub alloc_hits: u64,
+    /// Allocations that found the cache empty and refilled it from the slabs.
+    pub alloc_misses: u64,
+    /// Frees kept in the cache.
+    pub free_hits: u64,
+    /// Frees that found the cache full and drained it into the slabs.
+    pub free_misses: u64,
+}
+
+impl AddAssign for CacheStats {
+    fn add_assign(&mut self, other: Self) {
+        self.alloc_hits += other.alloc_hits;
+        self.alloc_misses += other.alloc_misses;
+        self.free_hits += other.free_hits;
+        self.free_misses += other.free_misses;
+    }
+}
+
+/// The counters behind [`CacheStats`].
+///
+/// Only the owning CPU updates them, so they are bumped with a plain load and
+/// store rather than a locked read-modify-write; they're atomics so that other
+/// CPUs can read them. As on Linux, they are 32 bits wide and wrap.
+#[repr(C)]
+struct CacheCounters {
+    alloc_hits: AtomicU32,
+    alloc_misses: AtomicU32,
+    free_hits: AtomicU32,
+    free_misses: AtomicU32,
+}
+
+impl CacheCounters {
+    const fn new() -> Self {
+        Self {
+            alloc_hits: AtomicU32::new(0),
+            alloc_misses: AtomicU32::new(0),
+            free_hits: AtomicU32::new(0),
+            free_misses: AtomicU32::new(0),
+        }
+    }
+
+    fn bump(counter: &AtomicU32) {
+        counter.store(
+            counter.load(Ordering::Relaxed).wrapping_add(1),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            alloc_hits: self.alloc_hits.load(Ordering::Relaxed).into(),
+            alloc_misses: self.alloc_misses.load(Ordering::Relaxed).into(),
+            free_hits: self.free_hits.load(Ordering::Relaxed).into(),
+            free_misses: self.free_misses.load(Ordering::Relaxed).into(),
+        }
+    }
+}
+
 /// A fixed-size cache of recently freed pointers for a single size class.
 #[repr(C)]
 pub struct PtrCache {
     next_free: usize,
     ptrs: [*mut u8; PTRS_PER_SZ_CLASS],
+    counters: CacheCounters,
 }
 
 impl PtrCache {
@@ -35,6 +101,7 @@ impl PtrCache {
         Self {
             next_free: 0,
             ptrs: [ptr::null_mut(); PTRS_PER_SZ_CLASS],
+            counters: CacheCounters::new(),
         }
     }
 
@@ -75,9 +142,11 @@ impl PtrCache {
     /// Returns a cached pointer, or `None` if the cache is empty.
     pub fn alloc(&mut self) -> Option<*mut u8> {
         if self.is_empty() {
+            CacheCounters::bump(&self.counters.alloc_misses);
             return None;
         }
 
+        CacheCounters::bump(&self.counters.alloc_hits);
         self.next_free -= 1;
 
         Some(self.ptrs[self.next_free])
@@ -90,9 +159,11 @@ impl PtrCache {
     /// - `Ok(())` if `ptr` was cached
     pub fn free(&mut self, ptr: *mut u8) -> Result<(), *mut u8> {
         if self.is_full() {
+            CacheCounters::bump(&self.counters.free_misses);
             return Err(ptr);
         }
 
+        CacheCounters::bump(&self.counters.free_hits);
         self.ptrs[self.next_free] = ptr;
         self.next_free += 1;
 
@@ -141,6 +212,20 @@ impl SlabCache {
         ptr
     }
 
+    /// Returns the counters of every size class, smallest first.
+    ///
+    /// # Safety
+    /// `cache` must point to a cache made by [`SlabCache::from_page`]. Its CPU
+    /// may be using it at the same time, as only the counters are read.
+    pub unsafe fn stats(cache: *const Self) -> impl Iterator<Item = CacheStats> {
+        // No layout maps to order 0, see `alloc_order`.
+        (1..NUM_PTR_CACHES).map(move |order| {
+            // SAFETY: The counters are atomics, so reading them while their
+            // CPU updates them is fine.
+            unsafe { (*cache).caches[order].counters.stats() }
+        })
+    }
+
     /// Helper to get the specific cache for a size index
     pub fn get_cache(&mut self, layout: core::alloc::Layout) -> Option<&mut PtrCache> {
         Some(&mut self.caches[alloc_order(layout)?])
@@ -160,3 +245,59 @@ impl SlabCache {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ptr_cache_counters() {
+        let mut cache = PtrCache::new();
+        let mut objs = [0u8; PTRS_PER_SZ_CLASS + 1];
+
+        // An empty cache has to go to the slabs.
+        assert!(cache.alloc().is_none());
+
+        for obj in objs.iter_mut() {
+            let _ = cache.free(obj);
+        }
+
+        for _ in 0..PTRS_PER_SZ_CLASS {
+            assert!(cache.alloc().is_some());
+        }
+
+        assert_eq!(
+            cache.counters.stats(),
+            CacheStats {
+                alloc_hits: PTRS_PER_SZ_CLASS as u64,
+                alloc_misses: 1,
+                free_hits: PTRS_PER_SZ_CLASS as u64,
+                free_misses: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn cache_stats_add() {
+        let mut total = CacheStats::default();
+        let one = CacheStats {
+            alloc_hits: 1,
+            alloc_misses: 2,
+            free_hits: 3,
+            free_misses: 4,
+        };
+
+        total += one;
+        total += one;
+
+        assert_eq!(
+            total,
+            CacheStats {
+                alloc_hits: 2,
+                alloc_misses: 4,
+                free_hits: 6,
+                free_misses: 8,
+            }
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Slab debugging: redzones and poisoning.
+//!
+//! With the `slab_debug` feature enabled, the last [`REDZONE_SIZE`] bytes of
+//! every object are reserved as a redzone, and the kernel heap pads each
+//! layout so that callers never own them. The redzone records the object's
+//! state: [`RED_ACTIVE`] while allocated and [`RED_INACTIVE`] while free,
+//! each XORed with a cookie derived from the slab's secret, so that an
+//! overflow can't write back a redzone that passes as intact. Free objects are filled with [`POISON_FREE`], except for the free-list
+//! link at their start.
+//!
+//! This lets [`Slab`](super::slab::Slab) catch:
+//!
+//! - double frees, when a freed object's redzone is already inactive;
+//! - buffer overflows, when the redzone of a freed object holds neither
+//!   pattern;
+//! - use-after-free writes, when a free object's poison has been modified.
+//!   This is checked when the object is handed out again, and on every free
+//!   for the object at the head of the slab's free list.
+//!
+//! Every violation panics with the object's address and size class.
+
+use core::{alloc::Layout, slice};
+
+/// The number of bytes at the end of each object reserved as a redzone.
+pub(super) const REDZONE_SIZE: usize = 8;
+
+/// The smallest object order that holds a free-list link and a redzone.
+pub(super) const MIN_OBJ_SHIFT: usize = 4;
+
+/// Fill pattern for the body of free objects.
+const POISON_FREE: u8 = 0x6b;
+
+/// Redzone pattern of a free object.
+const RED_INACTIVE: u8 = 0xbb;
+
+/// Redzone pattern of an allocated object.
+const RED_ACTIVE: u8 = 0xcc;
+
+/// Size of the free-list link stored at the start of free objects.
+const LINK_SIZE: usize = size_of::<u16>();
+
+/// Returns the redzone contents for the pattern `fill`, keyed by `cookie`.
+fn redzone_pattern(fill: u8, cookie: u64) -> [u8; REDZONE_SIZE] {
+    (u64::from_ne_bytes([fill; REDZONE_SIZE]) ^ cookie).to_ne_bytes()
+}
+
+/// Returns `layout` grown to leave room for the redzone.
+pub(super) fn padded_layout(layout: Layout) -> Layout {
+    Layout::from_size_align(layout.size() + REDZONE_SIZE, layout.align())
+        .expect("Slab allocator: layout too large for redzone")
+}
+
+/// Returns the poisoned body of the object at `ptr`.
+///
+/// # Safety
+/// `ptr` must point to an object of order `obj_shift` within a slab.
+unsafe fn body<'a>(ptr: *mut u8, obj_shift: usize) -> &'a mut [u8] {
+    let len = (1 << obj_shift) - REDZONE_SIZE - LINK_SIZE;
+
+    unsafe { slice::from_raw_parts_mut(ptr.add(LINK_SIZE), len) }
+}
+
+/// Returns the redzone of the object at `ptr`.
+///
+/// # Safety
+/// `ptr` must point to an object of order `obj_shift` within a slab.
+unsafe fn redzone<'a>(ptr: *mut u8, obj_shift: usize) -> &'a mut [u8] {
+    unsafe { slice::from_raw_parts_mut(ptr.add((1 << obj_shift) - REDZONE_SIZE), REDZONE_SIZE) }
+}
+
+/// Poisons a free object and marks its redzone inactive.
+///
+/// # Safety
+/// `ptr` must point to an object of order `obj_shift` within a slab.
+pub(super) unsafe fn poison(ptr: *mut u8, obj_shift: usize, cookie: u64) {
+    unsafe {
+        body(ptr, obj_shift).fill(POISON_FREE);
+        redzone(ptr, obj_shift).copy_from_slice(&redzone_pattern(RED_INACTIVE, cookie));
+    }
+}
+
+/// Checks that a free object hasn't been written to since it was freed.
+///
+/// # Safety
+/// `ptr` must point to a free object of order `obj_shift` within a slab.
+pub(super) unsafe fn check_poison(ptr: *mut u8, obj_shift: usize, cookie: u64) {
+    let body = unsafe { body(ptr, obj_shift) };
+
+    if let Some(offset) = body.iter().position(|&b| b != POISON_FREE) {
+        panic!(
+            "Slab allocator: use-after-free of object {ptr:p} (size {}): byte {} is {:#x}, expected {POISON_FREE:#x}",
+            1usize << obj_shift,
+            offset + LINK_SIZE,
+            body[offset],
+        );
+    }
+
+    let redzone = unsafe { redzone(ptr, obj_shift) };
+
+    if *redzone != redzone_pattern(RED_INACTIVE, cookie) {
+        panic!(
+            "Slab allocator: use-after-free of object {ptr:p} (size {}): redzone overwritten while free",
+            1usize << obj_shift,
+        );
+    }
+}
+
+/// Marks an object as allocated.
+///
+/// # Safety
+/// `ptr` must point to an object of order `obj_shift` within a slab.
+pub(super) unsafe fn mark_active(ptr: *mut u8, obj_shift: usize, cookie: u64) {
+    unsafe { redzone(ptr, obj_shift).copy_from_slice(&redzone_pattern(RED_ACTIVE, cookie)) };
+}
+
+/// Checks an object that is being freed, then poisons it.
+///
+/// # Safety
+/// `ptr` must point within a slab of objects of order `obj_shift`, at
+/// `offset` bytes from its base.
+pub(super) unsafe fn check_free(ptr: *mut u8, obj_shift: usize, offset: usize, cookie: u64) {
+    let size = 1usize << obj_shift;
+
+    if offset & (size - 1) != 0 {
+        panic!("Slab allocator: free of {ptr:p} which is not the start of an object (size {size})");
+    }
+
+    let redzone = unsafe { redzone(ptr, obj_shift) };
+
+    if *redzone == redzone_pattern(RED_INACTIVE, cookie) {
+        panic!("Slab allocator: double free of object {ptr:p} (size {size})");
+    }
+
+    let active = redzone_pattern(RED_ACTIVE, cookie);
+
+    if let Some(offset) = redzone.iter().zip(active).position(|(&b, a)| b != a) {
+        panic!(
+            "Slab allocator: redzone of object {ptr:p} (size {size}) overwritten: byte {} is {:#x}, expected {:#x}",
+            size - REDZONE_SIZE + offset,
+            redzone[offset],
+            active[offset],
+        );
+    }
+
+    unsafe { poison(ptr, obj_shift, cookie) };
+}
@@ -1,5 +1,7 @@
 //! Kernel heap built on top of the slab allocator.
 
+#[cfg(feature = "slab_debug")]
+use super::debug;
 use super::{allocator::SlabAllocator, cache::SlabCache};
 use crate::{
     CpuOps,
@@ -23,8 +25,10 @@ pub trait SlabGetter<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<
 pub trait SlabCacheStorage {
     /// Stores the slab cache pointer (e.g. into per-CPU data).
     fn store(ptr: *mut SlabCache);
-    /// Retrieves the slab cache for the current CPU.
-    fn get() -> impl DerefMut<Target = SlabCache>;
+    /// Retrieves the slab cache for the current CPU, or `None` if one hasn't
+    /// been stored for this CPU yet. In that case the heap allocates from the
+    /// global slab allocator directly.
+    fn get() -> Option<impl DerefMut<Target = SlabCache>>;
 }
 
 /// The kernel heap allocator backed by the slab allocator and frame allocator.
@@ -36,7 +40,8 @@ where
     T: AddressTranslator<()>,
     SG: SlabGetter<CPU, PG, T>,
 {
-    phantom1: PhantomData<S>,
+    // `S` is only used for its associated functions, the heap never holds one.
+    phantom1: PhantomData<fn() -> S>,
     phantom2: PhantomData<PG>,
     phantom3: PhantomData<CPU>,
     phantom4: PhantomData<T>,
@@ -83,6 +88,53 @@ where
         pages_needed.next_power_of_two().ilog2() as usize
     }
 
+    /// Allocates directly from the frame allocator, for allocations that are
+    /// too big for the slab allocator.
+    fn alloc_huge(layout: core::alloc::Layout) -> *mut u8 {
+        PG::global_page_alloc()
+            .alloc_frames(Self::calculate_huge_order(layout) as _)
+            .unwrap()
+            .leak()
+            .start_address()
+            .to_va::<T>()
+            .cast::<u8>()
+            .as_ptr_mut()
+    }
+
+    /// Frees an allocation made by [`Self::alloc_huge`].
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by [`Self::alloc_huge`] for `layout`.
+    unsafe fn dealloc_huge(ptr: *mut u8, layout: core::alloc::Layout) {
+        let allocated_region = PhysMemoryRegion::new(
+            VA::from_ptr_mut(ptr as _).to_pa::<T>(),
+            PAGE_SIZE << Self::calculate_huge_order(layout),
+        );
+
+        unsafe {
+            PG::global_page_alloc().alloc_from_region(allocated_region);
+        }
+    }
+
+    /// Allocates without going through a per-CPU cache.
+    fn alloc_uncached(layout: core::alloc::Layout) -> *mut u8 {
+        match SG::global_slab_alloc().allocator_for_layout(layout) {
+            Some(slab) => slab.lock_save_irq().alloc(),
+            None => Self::alloc_huge(layout),
+        }
+    }
+
+    /// Frees an object without going through a per-CPU cache.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this heap with `layout`.
+    unsafe fn dealloc_uncached(ptr: *mut u8, layout: core::alloc::Layout) {
+        match SG::global_slab_alloc().allocator_for_layout(layout) {
+            Some(slab) => slab.lock_save_irq().free(ptr),
+            None => unsafe { Self::dealloc_huge(ptr, layout) },
+        }
+    }
+
     /// Initializes the per-CPU slab cache for the current CPU.
     pub fn init_for_this_cpu() {
         let page: ClaimedPage<CPU, PG, T> =
@@ -107,19 +159,24 @@ where
     SG: SlabGetter<CPU, PG, T>,
 {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let mut cache = S::get();
+        #[cfg(feature = "slab_debug")]
+        let layout = debug::padded_layout(layout);
+
+        // Objects are checked by their slab when slab_debug is enabled, so
+        // skip the per-CPU cache to have every alloc and free checked as it
+        // happens.
+        let cache = if cfg!(feature = "slab_debug") {
+            None
+        } else {
+            S::get()
+        };
+
+        let Some(mut cache) = cache else {
+            return Self::alloc_uncached(layout);
+        };
 
         let Some(cache_line) = cache.get_cache(layout) else {
-            // Allocation is too big for SLAB. Defer to using the frame
-            // allocator directly.
-            return PG::global_page_alloc()
-                .alloc_frames(Self::calculate_huge_order(layout) as _)
-                .unwrap()
-                .leak()
-                .start_address()
-                .to_va::<T>()
-                .cast::<u8>()
-                .as_ptr_mut();
+            return Self::alloc_huge(layout);
         };
 
         if let Some(ptr) = cache_line.alloc() {
@@ -143,20 +200,22 @@ where
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-        let mut cache = S::get();
+        #[cfg(feature = "slab_debug")]
+        let layout = debug::padded_layout(layout);
 
-        let Some(cache_line) = cache.get_cache(layout) else {
-            // If the allocation didn't fit in the slab, we must have used the
-            // FA directly.
-            let allocated_region = PhysMemoryRegion::new(
-                VA::from_ptr_mut(ptr as _).to_pa::<T>(),
-                PAGE_SIZE << Self::calculate_huge_order(layout),
-            );
-
-            unsafe {
-                PG::global_page_alloc().alloc_from_region(allocated_region);
-            }
+        let cache = if cfg!(feature = "slab_debug") {
+            None
+        } else {
+            S::get()
+        };
 
+        let Some(mut cache) = cache else {
+            unsafe { Self::dealloc_uncached(ptr, layout) };
+            return;
+        };
+
+        let Some(cache_line) = cache.get_cache(layout) else {
+            unsafe { Self::dealloc_huge(ptr, layout) };
             return;
         };
 
@@ -254,12 +313,10 @@ mod tests {
             });
         }
 
-        fn get() -> impl Deref<Target = SlabCache> + DerefMut {
-            let ptr = TLS_CACHE.with(|c| {
-                c.borrow()
-                    .expect("Thread cache not initialized for this thread")
-            });
-            ThreadCacheGuard { ptr }
+        fn get() -> Option<impl Deref<Target = SlabCache> + DerefMut> {
+            TLS_CACHE
+                .with(|c| *c.borrow())
+                .map(|ptr| ThreadCacheGuard { ptr })
         }
     }
 
@@ -291,16 +348,29 @@ mod tests {
                 let barrier = barrier.clone();
 
                 handles.push(thread::spawn(move || {
-                    TestHeap::init_for_this_cpu();
-
-                    barrier.wait();
-
                     let heap = TestHeap::new();
                     let mut rng = rng();
 
                     // Track allocations: (Ptr, Layout, PatternByte)
                     let mut allocations: Vec<(*mut u8, core::alloc::Layout, u8)> = Vec::new();
 
+                    // Allocations made before this thread has a cache must go
+                    // to the slab allocator, and may later be freed through
+                    // the cache.
+                    let layout = core::alloc::Layout::from_size_align(64, 64).unwrap();
+                    for pattern in 0..16 {
+                        unsafe {
+                            let ptr = heap.alloc(layout);
+                            assert!(!ptr.is_null(), "Uncached allocation failed");
+                            std::ptr::write_bytes(ptr, pattern, layout.size());
+                            allocations.push((ptr, layout, pattern));
+                        }
+                    }
+
+                    TestHeap::init_for_this_cpu();
+
+                    barrier.wait();
+
                     for _ in 0..ops_per_thread {
                         // Randomly decide to Alloc (70%) or Free (30%)
                         // Bias towards Alloc to build up memory pressure
@@ -361,9 +431,9 @@ mod tests {
 
                     // Purge the per-cpu caches.
                     let slab = SLAB_ALLOCATOR.get().unwrap();
-                    ThreadLocalCacheStorage::get().purge_into(&slab);
+                    ThreadLocalCacheStorage::get().unwrap().purge_into(&slab);
 
-                    let addr = ThreadLocalCacheStorage::get().deref() as *const SlabCache;
+                    let addr = ThreadLocalCacheStorage::get().unwrap().deref() as *const SlabCache;
 
                     // Return the slab cache page.
                     unsafe {
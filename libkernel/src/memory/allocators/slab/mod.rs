@@ -1,6 +1,7 @@
 //! Slab allocator for small, fixed-size kernel objects.
 
 use crate::memory::PAGE_SIZE;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 // Allocations of order 2 (4 pages) from the FA for slabs.
 pub(super) const SLAB_FRAME_ALLOC_ORDER: usize = 2;
@@ -9,10 +10,32 @@ const SLAB_MAX_OBJ_SHIFT: u32 = SLAB_SIZE_BYTES.ilog2() - 1;
 
 pub mod allocator;
 pub mod cache;
+#[cfg(feature = "slab_debug")]
+mod debug;
 pub mod heap;
 #[allow(clippy::module_inception)]
 pub(super) mod slab;
 
+/// Per-boot secret used to harden slabs, or zero if none has been set.
+static SLAB_SECRET: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the secret that slabs created from now on use to obfuscate their free
+/// lists and, with `slab_debug`, to key their redzone cookies. Slabs that
+/// already exist keep their free lists in the clear, so this should be called
+/// as early in boot as random bytes can be had.
+pub fn set_slab_secret(secret: u64) {
+    SLAB_SECRET.store(secret, Ordering::Relaxed);
+}
+
+/// Returns the secret for a slab based at `base`. Mixing in the address keeps
+/// a secret recovered from one slab from unlocking the others.
+fn slab_secret(base: usize) -> u64 {
+    match SLAB_SECRET.load(Ordering::Relaxed) {
+        0 => 0,
+        secret => secret ^ (base as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+    }
+}
+
 /// Returns the index into the slab/cache list for a given layout.
 fn alloc_order(layout: core::alloc::Layout) -> Option<usize> {
     // We must take alignemnt into account too.
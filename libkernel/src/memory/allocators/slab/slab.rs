@@ -1,4 +1,6 @@
-use super::SLAB_SIZE_BYTES;
+#[cfg(feature = "slab_debug")]
+use super::debug;
+use super::{SLAB_SIZE_BYTES, slab_secret};
 use crate::{
     CpuOps,
     memory::{
@@ -14,6 +16,9 @@ pub struct Slab {
     num_free: usize,
     next_free: Option<u16>,
     base: VA,
+    /// Key for the free-list links stored in free objects, and for the
+    /// redzone cookies with `slab_debug`.
+    secret: u64,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -27,6 +32,16 @@ impl Slab {
     pub fn new<T: AddressTranslator<()>, CPU: CpuOps>(
         alloc: &PageAllocation<'_, CPU>,
         obj_shift: usize,
+    ) -> Self {
+        let secret = slab_secret(alloc.region().start_address().to_va::<T>().value());
+
+        Self::with_secret::<T, CPU>(alloc, obj_shift, secret)
+    }
+
+    fn with_secret<T: AddressTranslator<()>, CPU: CpuOps>(
+        alloc: &PageAllocation<'_, CPU>,
+        obj_shift: usize,
+        secret: u64,
     ) -> Self {
         assert_eq!(alloc.region().size(), SLAB_SIZE_BYTES);
 
@@ -36,6 +51,10 @@ impl Slab {
         // We don't go bigger than 4 pages.
         assert!(obj_shift <= SLAB_MAX_OBJ_SHIFT as usize);
 
+        // Debug objects also need room for a redzone.
+        #[cfg(feature = "slab_debug")]
+        assert!(obj_shift >= debug::MIN_OBJ_SHIFT);
+
         let num_objs = SLAB_SIZE_BYTES >> obj_shift;
 
         // Write free list at object slots.
@@ -45,13 +64,22 @@ impl Slab {
 
         for i in 0..num_objs {
             unsafe {
-                base.byte_add(i * (1 << obj_shift))
-                    .write(if i == num_objs - 1 {
+                base.byte_add(i * (1 << obj_shift)).write(mask_link(
+                    if i == num_objs - 1 {
                         // Sential value for no next list.
                         u16::MAX
                     } else {
                         (i + 1) as u16
-                    });
+                    },
+                    secret,
+                ));
+
+                #[cfg(feature = "slab_debug")]
+                debug::poison(
+                    base.byte_add(i * (1 << obj_shift)).cast(),
+                    obj_shift,
+                    secret,
+                );
             }
         }
 
@@ -60,6 +88,7 @@ impl Slab {
             num_free: num_objs,
             next_free: Some(0),
             base: va,
+            secret,
         }
     }
 
@@ -74,7 +103,22 @@ impl Slab {
 
         let va = self.calc_obj_idx(self.next_free.unwrap());
 
-        let next_free = unsafe { va.cast::<u16>().as_ptr().read() };
+        #[cfg(feature = "slab_debug")]
+        unsafe {
+            debug::check_poison(va.cast::<u8>().as_ptr_mut(), self.obj_shift, self.secret);
+        }
+
+        let next_free = mask_link(unsafe { va.cast::<u16>().as_ptr().read() }, self.secret);
+
+        // A link that doesn't decode to an object means the free object has
+        // been written to, either by accident or to steer the allocator.
+        if next_free != u16::MAX && next_free as usize >= self.capacity() {
+            panic!(
+                "Slab allocator: corrupted free list at object {:p} (size {}): link {next_free} is out of range",
+                va.cast::<u8>().as_ptr(),
+                1usize << self.obj_shift,
+            );
+        }
 
         self.next_free = if next_free == u16::MAX {
             None
@@ -84,6 +128,11 @@ impl Slab {
 
         self.num_free -= 1;
 
+        #[cfg(feature = "slab_debug")]
+        unsafe {
+            debug::mark_active(va.cast::<u8>().as_ptr_mut(), self.obj_shift, self.secret);
+        }
+
         Some(va.cast::<u8>().as_ptr_mut())
     }
 
@@ -92,9 +141,27 @@ impl Slab {
         // Eneusre ptr is within our slab.
         assert!(VirtMemoryRegion::new(self.base, SLAB_SIZE_BYTES).contains_address(va));
 
-        let idx = (va.value() - self.base.value()) >> self.obj_shift;
+        let offset = va.value() - self.base.value();
+        let idx = offset >> self.obj_shift;
+
+        #[cfg(feature = "slab_debug")]
+        unsafe {
+            debug::check_free(ptr, self.obj_shift, offset, self.secret);
+
+            // Catch writes to recently freed objects without waiting for them
+            // to be reallocated.
+            if let Some(head) = self.next_free {
+                debug::check_poison(
+                    self.calc_obj_idx(head).cast::<u8>().as_ptr_mut(),
+                    self.obj_shift,
+                    self.secret,
+                );
+            }
+        }
+
+        let link = mask_link(self.next_free.unwrap_or(u16::MAX), self.secret);
 
-        unsafe { ptr.cast::<u16>().write(self.next_free.unwrap_or(u16::MAX)) };
+        unsafe { ptr.cast::<u16>().write(link) };
 
         self.num_free += 1;
         self.next_free = Some(idx as u16);
@@ -118,6 +185,12 @@ impl Slab {
         self.obj_shift
     }
 }
+/// Obfuscates a free-list link before it is stored in a free object, or
+/// recovers it after it's read back. With no secret, links are stored as is.
+fn mask_link(link: u16, secret: u64) -> u16 {
+    link ^ (secret ^ (secret >> 16) ^ (secret >> 32) ^ (secret >> 48)) as u16
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -284,13 +357,19 @@ mod tests {
         // 128 byte objects -> 128 objects
         let mut slab = Slab::new::<IdentityTranslator, MockCpuOps>(&alloc, 7);
 
+        // Leave the redzone, if any, untouched.
+        #[cfg(feature = "slab_debug")]
+        let obj_size = 128 - debug::REDZONE_SIZE;
+        #[cfg(not(feature = "slab_debug"))]
+        let obj_size = 128;
+
         // Allocate all objects and write a specific pattern to them
         let mut ptrs = Vec::new();
         for i in 0..128 {
             let ptr = slab.alloc_object().unwrap();
             unsafe {
                 // Fill the object with a pattern
-                ptr::write_bytes(ptr, (i as u8) + 1, 128);
+                ptr::write_bytes(ptr, (i as u8) + 1, obj_size);
             }
             ptrs.push((i + 1, ptr));
         }
@@ -299,7 +378,7 @@ mod tests {
         // allocation tracking)
         for (i, ptr) in ptrs.iter() {
             unsafe {
-                let slice = core::slice::from_raw_parts(*ptr, 128);
+                let slice = core::slice::from_raw_parts(*ptr, obj_size);
                 for byte in slice {
                     assert_eq!(*byte, (*i as u8));
                 }
@@ -318,7 +397,7 @@ mod tests {
 
         for (i, ptr) in ptrs.iter() {
             unsafe {
-                let slice = core::slice::from_raw_parts(*ptr, 128);
+                let slice = core::slice::from_raw_parts(*ptr, obj_size);
                 for byte in slice {
                     assert_eq!(*byte, (*i as u8));
                 }
@@ -353,4 +432,148 @@ mod tests {
         let new_ptr = slab.alloc_object().unwrap();
         assert_eq!(new_ptr, ptr);
     }
+
+    #[test]
+    fn slab_free_list_obfuscated() {
+        let fixture = create_slab_fixture().leak_allocator();
+        let alloc = fixture.alloc_frames(SLAB_FRAME_ALLOC_ORDER as _).unwrap();
+        let secret = 0xdead_beef_cafe_f00d;
+        let mut slab = Slab::with_secret::<IdentityTranslator, MockCpuOps>(&alloc, 6, secret);
+
+        let base_ptr = alloc.region().start_address().value() as *const u16;
+        assert_ne!(unsafe { *base_ptr }, 1);
+        assert_eq!(mask_link(unsafe { *base_ptr }, secret), 1);
+
+        let ptr1 = slab.alloc_object().unwrap();
+        let ptr2 = slab.alloc_object().unwrap();
+        assert_eq!(unsafe { ptr1.byte_add(64) }, ptr2);
+
+        slab.put_object(ptr1);
+        assert_eq!(slab.alloc_object(), Some(ptr1));
+    }
+
+    #[test]
+    #[should_panic(expected = "corrupted free list")]
+    fn slab_forged_free_list_link() {
+        let fixture = create_slab_fixture().leak_allocator();
+        let alloc = fixture.alloc_frames(SLAB_FRAME_ALLOC_ORDER as _).unwrap();
+        let mut slab = Slab::with_secret::<IdentityTranslator, MockCpuOps>(&alloc, 6, 0xfeed_f00d);
+
+        let stale = slab.alloc_object().unwrap();
+        slab.put_object(stale);
+
+        // Without the secret, a link written in the clear decodes to garbage.
+        unsafe { stale.cast::<u16>().write(2) };
+
+        slab.alloc_object();
+    }
+
+    #[test]
+    #[cfg(feature = "slab_debug")]
+    #[should_panic(expected = "(size 64) overwritten: byte 56")]
+    fn slab_debug_forged_redzone() {
+        let fixture = create_slab_fixture().leak_allocator();
+        let alloc = fixture.alloc_frames(SLAB_FRAME_ALLOC_ORDER as _).unwrap();
+        let mut slab = Slab::with_secret::<IdentityTranslator, MockCpuOps>(&alloc, 6, 0xfeed_f00d);
+
+        // An overflow that writes the unkeyed redzone pattern is still caught.
+        let ptr = slab.alloc_object().unwrap();
+        unsafe { ptr::write_bytes(ptr.add(64 - debug::REDZONE_SIZE), 0xcc, debug::REDZONE_SIZE) };
+
+        slab.put_object(ptr);
+    }
+
+    #[test]
+    #[cfg(feature = "slab_debug")]
+    fn slab_debug_reuse_after_free() {
+        let fixture = create_slab_fixture().leak_allocator();
+        let alloc = fixture.alloc_frames(SLAB_FRAME_ALLOC_ORDER as _).unwrap();
+        let mut slab = Slab::new::<IdentityTranslator, MockCpuOps>(&alloc, 6);
+
+        // Well-behaved users may fill everything up to the redzone.
+        for _ in 0..3 {
+            let ptr = slab.alloc_object().unwrap();
+            unsafe { ptr::write_bytes(ptr, 0xff, 64 - debug::REDZONE_SIZE) };
+            slab.put_object(ptr);
+        }
+
+        assert_eq!(slab.state(), SlabState::Free);
+    }
+
+    #[test]
+    #[cfg(feature = "slab_debug")]
+    #[should_panic(expected = "double free of object")]
+    fn slab_debug_double_free() {
+        let fixture = create_slab_fixture().leak_allocator();
+        let alloc = fixture.alloc_frames(SLAB_FRAME_ALLOC_ORDER as _).unwrap();
+        let mut slab = Slab::new::<IdentityTranslator, MockCpuOps>(&alloc, 6);
+
+        let ptr = slab.alloc_object().unwrap();
+        let _other = slab.alloc_object().unwrap();
+
+        slab.put_object(ptr);
+        slab.put_object(ptr);
+    }
+
+    #[test]
+    #[cfg(feature = "slab_debug")]
+    #[should_panic(expected = "(size 64) overwritten: byte 56")]
+    fn slab_debug_redzone_overflow() {
+        let fixture = create_slab_fixture().leak_allocator();
+        let alloc = fixture.alloc_frames(SLAB_FRAME_ALLOC_ORDER as _).unwrap();
+        let mut slab = Slab::new::<IdentityTranslator, MockCpuOps>(&alloc, 6);
+
+        let ptr = slab.alloc_object().unwrap();
+        unsafe { ptr::write_bytes(ptr, 0, 64 - debug::REDZONE_SIZE + 1) };
+
+        slab.put_object(ptr);
+    }
+
+    #[test]
+    #[cfg(feature = "slab_debug")]
+    #[should_panic(expected = "use-after-free of object")]
+    fn slab_debug_use_after_free_on_free() {
+        let fixture = create_slab_fixture().leak_allocator();
+        let alloc = fixture.alloc_frames(SLAB_FRAME_ALLOC_ORDER as _).unwrap();
+        let mut slab = Slab::new::<IdentityTranslator, MockCpuOps>(&alloc, 6);
+
+        let stale = slab.alloc_object().unwrap();
+        let other = slab.alloc_object().unwrap();
+
+        slab.put_object(stale);
+
+        // `stale` now heads the free list, so the next free spots the write.
+        unsafe { stale.add(8).write(0x42) };
+
+        slab.put_object(other);
+    }
+
+    #[test]
+    #[cfg(feature = "slab_debug")]
+    #[should_panic(expected = "byte 16 is 0x42, expected 0x6b")]
+    fn slab_debug_use_after_free_on_alloc() {
+        let fixture = create_slab_fixture().leak_allocator();
+        let alloc = fixture.alloc_frames(SLAB_FRAME_ALLOC_ORDER as _).unwrap();
+        let mut slab = Slab::new::<IdentityTranslator, MockCpuOps>(&alloc, 6);
+
+        let stale = slab.alloc_object().unwrap();
+        slab.put_object(stale);
+
+        unsafe { stale.add(16).write(0x42) };
+
+        slab.alloc_object();
+    }
+
+    #[test]
+    #[cfg(feature = "slab_debug")]
+    #[should_panic(expected = "not the start of an object (size 64)")]
+    fn slab_debug_interior_free() {
+        let fixture = create_slab_fixture().leak_allocator();
+        let alloc = fixture.alloc_frames(SLAB_FRAME_ALLOC_ORDER as _).unwrap();
+        let mut slab = Slab::new::<IdentityTranslator, MockCpuOps>(&alloc, 6);
+
+        let ptr = slab.alloc_object().unwrap();
+
+        slab.put_object(unsafe { ptr.add(8) });
+    }
 }
@@ -105,4 +105,10 @@ impl<A: CpuOps, G: PageAllocGetter<A>, T: AddressTranslator<()>> ClaimedPage<A,
     pub fn leak(self) -> PageFrame {
         self.0.leak().start_address().to_pfn()
     }
+
+    /// Takes an additional reference on the page, returning its page frame.
+    /// The reference can be released with [`ClaimedPage::from_pfn`].
+    pub fn share(&self) -> PageFrame {
+        self.0.clone().leak().start_address().to_pfn()
+    }
 }
@@ -9,7 +9,12 @@ use crate::{
 };
 use alloc::sync::Arc;
 use core::num::NonZeroUsize;
-use core::{cmp::min, future, mem::MaybeUninit, task::Poll};
+use core::{
+    cmp::min,
+    future,
+    mem::MaybeUninit,
+    task::{Context, Poll},
+};
 use ringbuf::{
     SharedRb,
     storage::Storage,
@@ -126,6 +131,26 @@ impl<T, S: Storage<Item = T>, C: CpuOps> KBufCore<T, S, C> {
     pub fn capacity(&self) -> NonZeroUsize {
         self.inner.lock_save_irq().buf.capacity()
     }
+
+    /// Returns `true` if `self` and `other` are the same buffer.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Returns how many objs could be pushed without waiting.
+    pub fn vacant_len(&self) -> usize {
+        self.inner.lock_save_irq().buf.vacant_len()
+    }
+
+    /// Returns how many objs are waiting to be popped.
+    pub fn len(&self) -> usize {
+        self.inner.lock_save_irq().buf.occupied_len()
+    }
+
+    /// Returns `true` if there's nothing to pop.
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock_save_irq().buf.is_empty()
+    }
 }
 
 impl<T: Copy, S: Storage<Item = T>, C: CpuOps> KBufCore<T, S, C> {
@@ -203,86 +228,134 @@ impl<T: Copy, S: Storage<Item = T>, C: CpuOps> KBufCore<T, S, C> {
     /// intermediate stack buffer. It also handles async waiting and deadlock
     /// avoidance.
     pub async fn splice_from(&self, source: &KBufCore<T, S, C>, count: usize) -> usize {
+        self.transfer_from(source, count, true).await
+    }
+
+    /// Copies up to `count` objs from `source` KBuf into `self`, leaving them
+    /// in `source` to be read again, waiting as [`Self::splice_from`] does.
+    pub async fn tee_from(&self, source: &KBufCore<T, S, C>, count: usize) -> usize {
+        self.transfer_from(source, count, false).await
+    }
+
+    /// Like [`Self::splice_from`], but returns 0 rather than waiting.
+    pub fn try_splice_from(&self, source: &KBufCore<T, S, C>, count: usize) -> usize {
+        self.try_transfer(source, count, true, None).unwrap_or(0)
+    }
+
+    /// Like [`Self::tee_from`], but returns 0 rather than waiting.
+    pub fn try_tee_from(&self, source: &KBufCore<T, S, C>, count: usize) -> usize {
+        self.try_transfer(source, count, false, None).unwrap_or(0)
+    }
+
+    async fn transfer_from(
+        &self,
+        source: &KBufCore<T, S, C>,
+        count: usize,
+        consume: bool,
+    ) -> usize {
+        future::poll_fn(
+            |cx| match self.try_transfer(source, count, consume, Some(cx)) {
+                Some(copied) => Poll::Ready(copied),
+                None => Poll::Pending,
+            },
+        )
+        .await
+    }
+
+    /// Copies up to `count` objs from `source` into `self`, consuming them if
+    /// `consume` is set. Returns `None` if nothing could be copied, after
+    /// registering the waker of `cx`, if given, to be woken once something
+    /// might.
+    fn try_transfer(
+        &self,
+        source: &KBufCore<T, S, C>,
+        count: usize,
+        consume: bool,
+        cx: Option<&mut Context<'_>>,
+    ) -> Option<usize> {
         if count == 0 {
-            return 0;
+            return Some(0);
         }
 
         // Splicing from a buffer to itself is a no-op that would instantly
         // deadlock.
-        if Arc::ptr_eq(&self.inner, &source.inner) {
-            return 0;
+        if self.ptr_eq(source) {
+            return Some(0);
         }
 
-        future::poll_fn(|cx| -> Poll<usize> {
-            // Lock two KBufs with the lower memory address first to prevent
-            // AB-BA deadlocks.
-            let self_ptr = Arc::as_ptr(&self.inner);
-            let source_ptr = Arc::as_ptr(&source.inner);
-
-            let (mut self_guard, mut source_guard) = if self_ptr < source_ptr {
-                (self.inner.lock_save_irq(), source.inner.lock_save_irq())
-            } else {
-                let source_g = source.inner.lock_save_irq();
-                let self_g = self.inner.lock_save_irq();
-                (self_g, source_g)
-            };
-
-            let (_, source_consumer) = source_guard.buf.split_ref();
-            let (mut self_producer, _) = self_guard.buf.split_ref();
-
-            // Determine the maximum number of bytes we can move in one go.
-            let bytes_to_move = min(
-                count,
-                min(source_consumer.occupied_len(), self_producer.vacant_len()),
-            );
-
-            if bytes_to_move > 0 {
-                // We can move data. Get the memory slices for direct copy.
-                let (src_head, src_tail) = source_consumer.occupied_slices();
-                let (dst_head, dst_tail) = self_producer.vacant_slices_mut();
-
-                // Perform the copy, which may involve multiple steps if the
-                // source or destination wraps around the end of the ring
-                // buffer.
-                let copied =
-                    Self::copy_slices((src_head, src_tail), (dst_head, dst_tail), bytes_to_move);
-
-                // Advance the read/write heads in the ring buffers.
-                unsafe {
+        // Lock two KBufs with the lower memory address first to prevent AB-BA
+        // deadlocks.
+        let self_ptr = Arc::as_ptr(&self.inner);
+        let source_ptr = Arc::as_ptr(&source.inner);
+
+        let (mut self_guard, mut source_guard) = if self_ptr < source_ptr {
+            (self.inner.lock_save_irq(), source.inner.lock_save_irq())
+        } else {
+            let source_g = source.inner.lock_save_irq();
+            let self_g = self.inner.lock_save_irq();
+            (self_g, source_g)
+        };
+
+        let (_, source_consumer) = source_guard.buf.split_ref();
+        let (mut self_producer, _) = self_guard.buf.split_ref();
+
+        // Determine the maximum number of bytes we can move in one go.
+        let bytes_to_move = min(
+            count,
+            min(source_consumer.occupied_len(), self_producer.vacant_len()),
+        );
+
+        if bytes_to_move > 0 {
+            // We can move data. Get the memory slices for direct copy.
+            let (src_head, src_tail) = source_consumer.occupied_slices();
+            let (dst_head, dst_tail) = self_producer.vacant_slices_mut();
+
+            // Perform the copy, which may involve multiple steps if the source
+            // or destination wraps around the end of the ring buffer.
+            let copied =
+                Self::copy_slices((src_head, src_tail), (dst_head, dst_tail), bytes_to_move);
+
+            // Advance the read/write heads in the ring buffers. A tee leaves
+            // the source as it was.
+            unsafe {
+                if consume {
                     source_consumer.advance_read_index(copied);
-                    self_producer.advance_write_index(copied);
                 }
+                self_producer.advance_write_index(copied);
+            }
 
-                drop(source_consumer);
-                drop(self_producer);
+            drop(source_consumer);
+            drop(self_producer);
 
-                // Wake up anyone waiting for the opposite condition. A reader
-                // might be waiting for data in `self`.
-                self_guard.read_waiters.wake_one();
+            // Wake up anyone waiting for the opposite condition. A reader
+            // might be waiting for data in `self`.
+            self_guard.read_waiters.wake_one();
 
-                // A writer might be waiting for space in `source`.
+            // A writer might be waiting for space in `source`.
+            if consume {
                 source_guard.write_waiters.wake_one();
+            }
 
-                Poll::Ready(copied)
-            } else {
-                // We can't move data. We need to wait. If source is empty, we
-                // must wait for a writer on the source.
-                if source_consumer.is_empty() {
-                    drop(source_consumer);
-                    source_guard.read_waiters.register(cx.waker());
-                }
+            return Some(copied);
+        }
 
-                // If destination is full, we must wait for a reader on the
-                // destination.
-                if self_producer.is_full() {
-                    drop(self_producer);
-                    self_guard.write_waiters.register(cx.waker());
-                }
+        if let Some(cx) = cx {
+            // We can't move data. We need to wait. If source is empty, we must
+            // wait for a writer on the source.
+            if source_consumer.is_empty() {
+                drop(source_consumer);
+                source_guard.read_waiters.register(cx.waker());
+            }
 
-                Poll::Pending
+            // If destination is full, we must wait for a reader on the
+            // destination.
+            if self_producer.is_full() {
+                drop(self_producer);
+                self_guard.write_waiters.register(cx.waker());
             }
-        })
-        .await
+        }
+
+        None
     }
 
     /// Helper function to copy data between pairs of buffer slices.
@@ -727,4 +800,69 @@ mod tests {
         src.pop_slice(&mut remaining_src_data).await;
         assert_eq!(&remaining_src_data[..], &splice_data[20..50]); // The last 30 bytes
     }
+
+    // --- Tee Tests ---
+
+    #[tokio::test]
+    async fn tee_leaves_source_intact() {
+        let src = make_kbuf(PAGE_SIZE);
+        let dest = make_kbuf(PAGE_SIZE);
+        let data: Vec<u8> = (0..100).collect();
+
+        src.push_slice(&data).await;
+
+        assert_eq!(dest.tee_from(&src, 60).await, 60);
+        assert_eq!(src.inner.lock_save_irq().buf.occupied_len(), 100);
+
+        let mut out_buf = vec![0; 60];
+        dest.pop_slice(&mut out_buf).await;
+        assert_eq!(out_buf, &data[..60]);
+
+        // The source still reads back in full.
+        let mut out_buf = vec![0; 100];
+        src.pop_slice(&mut out_buf).await;
+        assert_eq!(out_buf, data);
+    }
+
+    #[tokio::test]
+    async fn tee_blocks_on_empty_source_and_wakes() {
+        let src = make_kbuf(100);
+        let dest = make_kbuf(100);
+        let (src_clone, dest_clone) = (src.clone(), dest.clone());
+
+        let tee_task = tokio::spawn(async move { dest_clone.tee_from(&src_clone, 10).await });
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(!tee_task.is_finished());
+
+        src.push_slice(&[7; 4]).await;
+
+        let teed = timeout(Duration::from_millis(50), tee_task)
+            .await
+            .expect("Tee task should have completed")
+            .unwrap();
+        assert_eq!(teed, 4);
+        assert_eq!(src.inner.lock_save_irq().buf.occupied_len(), 4);
+        assert_eq!(dest.inner.lock_save_irq().buf.occupied_len(), 4);
+    }
+
+    #[tokio::test]
+    async fn try_variants_dont_wait() {
+        let src = make_kbuf(100);
+        let dest = make_kbuf(50);
+
+        // Nothing to move.
+        assert_eq!(dest.try_splice_from(&src, 10), 0);
+        assert_eq!(dest.try_tee_from(&src, 10), 0);
+
+        src.push_slice(&[1; 80]).await;
+        assert_eq!(dest.try_tee_from(&src, 30), 30);
+        assert_eq!(dest.try_splice_from(&src, 80), 20);
+        assert_eq!(src.inner.lock_save_irq().buf.occupied_len(), 60);
+
+        // No room left.
+        assert_eq!(dest.vacant_len(), 0);
+        assert_eq!(dest.try_splice_from(&src, 10), 0);
+        assert!(!dest.is_empty());
+    }
 }
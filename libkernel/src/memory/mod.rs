@@ -28,3 +28,10 @@ pub const PAGE_SIZE: usize = 4096;
 pub const PAGE_SHIFT: usize = PAGE_SIZE.trailing_zeros() as usize;
 /// Bitmask for extracting the within-page offset from an address.
 pub const PAGE_MASK: usize = PAGE_SIZE - 1;
+/// The number of bits to shift to convert between byte offsets and huge page
+/// numbers. A huge page is mapped with a single level 2 block descriptor.
+pub const HUGE_PAGE_SHIFT: usize = 21;
+/// The huge page size in bytes (2 MiB).
+pub const HUGE_PAGE_SIZE: usize = 1 << HUGE_PAGE_SHIFT;
+/// The buddy allocator order of a huge page.
+pub const HUGE_PAGE_ORDER: u8 = (HUGE_PAGE_SHIFT - PAGE_SHIFT) as u8;
@@ -84,6 +84,29 @@ pub trait UserAddressSpace: Send + Sync {
     /// intermediate page tables cannot be allocated.
     fn map_page(&mut self, page: PageFrame, va: VA, perms: PtePermissions) -> Result<()>;
 
+    /// Maps a huge page to a virtual address with a single block entry.
+    ///
+    /// `page` is the first frame of `HUGE_PAGE_SIZE` bytes of physically
+    /// contiguous memory, and both it and `va` must be aligned to
+    /// `HUGE_PAGE_SIZE`. The mapping holds a single reference on the
+    /// allocation.
+    ///
+    /// Operations that act on individual pages, such as [`Self::unmap_range`]
+    /// or [`Self::protect_range`], transparently split a huge page mapping
+    /// into regular page mappings first. Each of those then holds its own
+    /// reference on the huge page allocation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any page in the range is already mapped, or if
+    /// memory for intermediate page tables cannot be allocated.
+    fn map_huge_page(&mut self, page: PageFrame, va: VA, perms: PtePermissions) -> Result<()>;
+
+    /// Returns `true` if nothing is mapped within the huge page sized region
+    /// starting at `va`, i.e. a call to [`Self::map_huge_page`] for it won't
+    /// fail because of an existing mapping.
+    fn can_map_huge_page(&self, va: VA) -> bool;
+
     /// Unmaps a single virtual page, returning the physical page it was mapped
     /// to.
     ///
@@ -0,0 +1,55 @@
+//! System-wide accounting of committed memory.
+//!
+//! Memory is committed when a process maps something it may write to without
+//! the kernel having backing pages for it yet, see
+//! [`VMArea::commit_charge`](super::vmarea::VMArea::commit_charge). Each
+//! [`ProcessVM`](super::ProcessVM) keeps its share of the total up to date, and
+//! the kernel's overcommit policy decides how far the total may exceed the
+//! memory actually available.
+
+use crate::error::{KernelError, Result};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static COMMITTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of bytes committed by all processes.
+pub fn committed() -> usize {
+    COMMITTED.load(Ordering::Relaxed)
+}
+
+/// Adds `bytes` to the committed total.
+pub fn charge(bytes: usize) {
+    COMMITTED.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Adds `bytes` to the committed total, unless that would take it over
+/// `limit`.
+///
+/// # Returns
+/// * `Ok(())` if the bytes were charged.
+/// * `Err(KernelError::NoMemory)` if the limit would be exceeded.
+pub fn try_charge(bytes: usize, limit: usize) -> Result<()> {
+    let mut committed = COMMITTED.load(Ordering::Relaxed);
+
+    loop {
+        let total = committed
+            .checked_add(bytes)
+            .filter(|&total| total <= limit)
+            .ok_or(KernelError::NoMemory)?;
+
+        match COMMITTED.compare_exchange_weak(
+            committed,
+            total,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return Ok(()),
+            Err(current) => committed = current,
+        }
+    }
+}
+
+/// Removes `bytes` from the committed total.
+pub fn uncharge(bytes: usize) {
+    COMMITTED.fetch_sub(bytes, Ordering::Relaxed);
+}
@@ -6,19 +6,29 @@ use super::{
 };
 use crate::{
     error::{KernelError, Result},
+    fs::readahead::ReadaheadAdvice,
     memory::{
-        PAGE_MASK, PAGE_SIZE, address::VA, page::PageFrame, paging::permissions::PtePermissions,
-        region::VirtMemoryRegion,
+        HUGE_PAGE_SIZE, PAGE_MASK, PAGE_SIZE, address::VA, page::PageFrame,
+        paging::permissions::PtePermissions, region::VirtMemoryRegion,
     },
 };
 use alloc::{collections::BTreeMap, string::String, vec::Vec};
 
-const MMAP_BASE: usize = 0x4000_0000_0000;
+/// The default address below which `mmap` places mappings that don't request
+/// a fixed address.
+pub const MMAP_BASE: usize = 0x4000_0000_0000;
+
+/// The default gap kept free below a downward-growing stack, so that a stack
+/// overflow faults instead of running into the mapping below it. Matches
+/// Linux's default `stack_guard_gap` of 256 pages.
+pub const STACK_GUARD_GAP: usize = 256 * PAGE_SIZE;
 
 /// Manages mappings in a process's address space.
 pub struct MemoryMap<AS: UserAddressSpace> {
     pub(super) vmas: BTreeMap<VA, VMArea>,
     address_space: AS,
+    mmap_base: VA,
+    stack_guard_gap: usize,
 }
 
 /// Specifies how the kernel should choose the virtual address for a mapping.
@@ -43,6 +53,8 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         Ok(Self {
             vmas: BTreeMap::new(),
             address_space: AS::new()?,
+            mmap_base: VA::from_value(MMAP_BASE),
+            stack_guard_gap: STACK_GUARD_GAP,
         })
     }
 
@@ -50,22 +62,93 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         Self {
             vmas: BTreeMap::new(),
             address_space,
+            mmap_base: VA::from_value(MMAP_BASE),
+            stack_guard_gap: STACK_GUARD_GAP,
         }
     }
 
     /// Create an address space from a pre-populated list of VMAs. Used by the
     /// ELF loader.
+    ///
+    /// A VMA replaces the parts of earlier ones that it overlaps, as with
+    /// `MAP_FIXED`. This happens when two ELF segments share a page.
     pub fn from_vmas(vmas: Vec<VMArea>) -> Result<Self> {
-        let mut map = BTreeMap::new();
+        let mut map = Self {
+            vmas: BTreeMap::new(),
+            address_space: AS::new()?,
+            mmap_base: VA::from_value(MMAP_BASE),
+            stack_guard_gap: STACK_GUARD_GAP,
+        };
 
         for vma in vmas {
-            map.insert(vma.region.start_address(), vma);
+            map.unmap_region(vma.region, None)?;
+            map.vmas.insert(vma.region.start_address(), vma);
         }
 
-        Ok(Self {
-            vmas: map,
-            address_space: AS::new()?,
-        })
+        Ok(map)
+    }
+
+    /// Returns the address below which non-fixed mappings are placed.
+    pub fn mmap_base(&self) -> VA {
+        self.mmap_base
+    }
+
+    /// Sets the address below which non-fixed mappings are placed. Used by the
+    /// ELF loader to randomize the layout of a new process.
+    pub fn set_mmap_base(&mut self, base: VA) {
+        self.mmap_base = base.align(PAGE_SIZE);
+    }
+
+    /// Sets the size of the gap kept free below downward-growing VMAs.
+    pub fn set_stack_guard_gap(&mut self, gap: usize) {
+        self.stack_guard_gap = gap.next_multiple_of(PAGE_SIZE);
+    }
+
+    /// Returns the lowest address that must stay free for `vma` to be able to
+    /// grow: its start, less the guard gap for a stack.
+    fn vma_start_gap(&self, vma: &VMArea) -> VA {
+        let start = vma.region.start_address();
+
+        if vma.grows_down {
+            VA::from_value(start.value().saturating_sub(self.stack_guard_gap))
+        } else {
+            start
+        }
+    }
+
+    /// Extends the downward-growing VMA directly above `addr` so that it
+    /// covers `addr`.
+    ///
+    /// # Returns
+    /// * `Ok(())` if the VMA now covers `addr`.
+    /// * `Err(KernelError::Fault)` if there is no stack directly above `addr`.
+    /// * `Err(KernelError::NoMemory)` if the stack would grow beyond
+    ///   `max_size` bytes or into the guard gap above the next mapping down.
+    pub fn expand_stack(&mut self, addr: VA, max_size: usize) -> Result<()> {
+        let (&start, vma) = self.vmas.range(addr..).next().ok_or(KernelError::Fault)?;
+
+        if !vma.grows_down {
+            return Err(KernelError::Fault);
+        }
+
+        let end = vma.region.end_address();
+        let new_start = addr.page_aligned();
+
+        if end.value() - new_start.value() > max_size {
+            return Err(KernelError::NoMemory);
+        }
+
+        if let Some((_, prev)) = self.vmas.range(..start).next_back()
+            && prev.region.end_address().value() + self.stack_guard_gap > new_start.value()
+        {
+            return Err(KernelError::NoMemory);
+        }
+
+        let mut vma = self.vmas.remove(&start).unwrap();
+        vma.region = VirtMemoryRegion::from_start_end_address(new_start, end);
+        self.vmas.insert(new_start, vma);
+
+        Ok(())
     }
 
     /// Finds the `VMArea` that contains the given virtual address.
@@ -109,29 +192,80 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
             len = (len & !PAGE_MASK) + PAGE_SIZE;
         }
 
-        let region = match requested_address {
-            AddressRequest::Any => self.find_free_region(len).ok_or(KernelError::NoMemory)?,
+        let region = self.place_mapping(requested_address, len, PAGE_SIZE)?;
+
+        // At this point, `start_addr` points to a valid, free region.
+        // We can now create and insert the new VMA, handling merges.
+        let mut new_vma = VMArea::new(region, kind, perms);
+
+        new_vma.set_name(name);
+
+        self.insert_and_merge(new_vma);
+
+        Ok(region.start_address())
+    }
+
+    /// Maps an anonymous region of memory backed by huge pages, as for
+    /// `MAP_HUGETLB`.
+    ///
+    /// The length is rounded up to a multiple of `HUGE_PAGE_SIZE` and the
+    /// region is placed on a huge page boundary, so that the demand pager can
+    /// back all of it with huge pages.
+    pub fn mmap_huge(
+        &mut self,
+        requested_address: AddressRequest,
+        len: usize,
+        perms: VMAPermissions,
+        name: String,
+    ) -> Result<VA> {
+        if len == 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let len = len
+            .checked_next_multiple_of(HUGE_PAGE_SIZE)
+            .ok_or(KernelError::NoMemory)?;
+
+        let region = self.place_mapping(requested_address, len, HUGE_PAGE_SIZE)?;
+
+        let mut new_vma = VMArea::new(region, VMAreaKind::Anon, perms);
+
+        new_vma.set_name(name);
+        new_vma.set_huge_pages(true);
+
+        self.insert_and_merge(new_vma);
+
+        Ok(region.start_address())
+    }
+
+    /// Chooses the region for a new mapping of `len` bytes, starting on an
+    /// `align` boundary.
+    fn place_mapping(
+        &self,
+        requested_address: AddressRequest,
+        len: usize,
+        align: usize,
+    ) -> Result<VirtMemoryRegion> {
+        match requested_address {
+            AddressRequest::Any => self
+                .find_free_region(len, align)
+                .ok_or(KernelError::NoMemory),
             AddressRequest::Hint(address) => {
                 // Be more permissive when it's a hint.
-                let address = if !address.is_page_aligned() {
-                    address.page_aligned()
-                } else {
-                    address
-                };
-
-                let region = VirtMemoryRegion::new(address, len);
+                let region = VirtMemoryRegion::new(address.align(align), len);
 
                 if self.is_region_free(region) {
-                    region
+                    Ok(region)
                 } else {
-                    self.find_free_region(len).ok_or(KernelError::NoMemory)?
+                    self.find_free_region(len, align)
+                        .ok_or(KernelError::NoMemory)
                 }
             }
             AddressRequest::Fixed {
                 address,
                 permit_overlap,
             } => {
-                if !address.is_page_aligned() {
+                if !address.value().is_multiple_of(align) {
                     return Err(KernelError::InvalidValue);
                 }
 
@@ -141,19 +275,9 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
                     return Err(KernelError::InvalidValue);
                 }
 
-                region
+                Ok(region)
             }
-        };
-
-        // At this point, `start_addr` points to a valid, free region.
-        // We can now create and insert the new VMA, handling merges.
-        let mut new_vma = VMArea::new(region, kind, perms);
-
-        new_vma.set_name(name);
-
-        self.insert_and_merge(new_vma);
-
-        Ok(region.start_address())
+        }
     }
 
     /// Unmaps a region of memory, similar to the `munmap` syscall.
@@ -181,6 +305,51 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         self.unmap_region(range.align_to_page_boundary(), None)
     }
 
+    /// Discards the contents of a page-aligned region, as for
+    /// `MADV_DONTNEED`.
+    ///
+    /// Unlike `munmap`, the VMAs covering the region are left in place, so the
+    /// next access demand-faults a fresh page: zero-filled for anonymous
+    /// mappings, or re-read from the backing file for file mappings.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<PageFrame>)` containing the pages that were unmapped.
+    /// * `Err(KernelError::NoMemory)` if any part of the region is unmapped.
+    pub fn discard_region(&mut self, region: VirtMemoryRegion) -> Result<Vec<PageFrame>> {
+        if !region.is_page_aligned() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut pages = Vec::new();
+
+        for intersection in self.covering_regions(region)? {
+            pages.append(&mut self.address_space.unmap_range(intersection)?);
+        }
+
+        Ok(pages)
+    }
+
+    /// Returns the parts of `region` covered by each VMA, in address order.
+    /// Fails with `KernelError::NoMemory` if the VMAs leave a hole anywhere in
+    /// the region.
+    pub fn covering_regions(&self, region: VirtMemoryRegion) -> Result<Vec<VirtMemoryRegion>> {
+        let mut regions = Vec::new();
+        let mut next = region.start_address();
+
+        while next < region.end_address() {
+            let vma = self.find_vma(next).ok_or(KernelError::NoMemory)?;
+            let intersection = vma
+                .region
+                .intersection(region)
+                .ok_or(KernelError::NoMemory)?;
+
+            next = intersection.end_address();
+            regions.push(intersection);
+        }
+
+        Ok(regions)
+    }
+
     /// Changes the memory protection flags for a page-aligned region.
     pub fn mprotect(
         &mut self,
@@ -195,52 +364,135 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
             return Err(KernelError::InvalidValue);
         }
 
-        let affected_vma_addr = self
-            .find_vma(protect_region.start_address())
-            .map(|x| x.region.start_address())
-            .ok_or(KernelError::NoMemory)?;
+        // The whole region must be mapped, possibly by several VMAs (e.g. the
+        // RELRO part of an ELF image), before anything is changed.
+        let parts = self.covering_regions(protect_region)?;
+
+        // A mapping that couldn't have been created writable can't be made
+        // writable now either.
+        if new_perms.write
+            && let Some(err) = parts.iter().find_map(|part| {
+                self.find_vma(part.start_address())
+                    .and_then(|vma| vma.write_denied())
+            })
+        {
+            return Err(err.clone());
+        }
 
-        let affected_vma = self
-            .vmas
-            .remove(&affected_vma_addr)
-            .expect("Should have the same key as the start address");
+        for part in parts {
+            let shared = self
+                .find_vma(part.start_address())
+                .is_some_and(|vma| vma.is_shared());
 
-        // Easy case, the entire VMA is changing.
-        if affected_vma.region == protect_region {
-            let old_vma = affected_vma.clone();
-            let mut new_vma = old_vma.clone();
-            new_vma.permissions = new_perms;
+            // Pages of a private mapping may be shared, with a forked process
+            // or the page cache, so they only become writable through a CoW
+            // fault.
+            let mut pte_perms = PtePermissions::from(new_perms);
 
-            self.insert_and_merge(new_vma.clone());
-            self.address_space
-                .protect_range(protect_region, new_perms.into())?;
+            if pte_perms.is_write() && !shared {
+                pte_perms = pte_perms.into_cow();
+            }
 
-            return Ok(());
+            self.address_space.protect_range(part, pte_perms)?;
         }
 
-        // Next case, a sub-region of a VMA is changing, requring a split.
-        if affected_vma.region.contains(protect_region) {
-            let (left, right) = affected_vma.region.punch_hole(protect_region);
-            let mut new_vma = affected_vma.clone().shrink_to(protect_region);
-            new_vma.permissions = new_perms;
+        self.modify_region(protect_region, |vma| vma.permissions = new_perms)
+    }
+
+    /// Applies readahead `advice` to the page-aligned `region`, splitting the
+    /// VMAs that cover it at its boundaries.
+    pub fn set_readahead(
+        &mut self,
+        region: VirtMemoryRegion,
+        advice: ReadaheadAdvice,
+    ) -> Result<()> {
+        self.modify_region(region, |vma| vma.readahead = advice)
+    }
+
+    /// Marks the page-aligned `region` as left out of commit accounting, or
+    /// back in, splitting the VMAs that cover it at its boundaries.
+    pub fn set_noreserve(&mut self, region: VirtMemoryRegion, noreserve: bool) -> Result<()> {
+        self.modify_region(region, |vma| vma.noreserve = noreserve)
+    }
+
+    /// Returns the number of bytes charged for the whole map, see
+    /// [`VMArea::commit_charge`].
+    pub fn committed(&self) -> usize {
+        self.vmas.values().map(VMArea::commit_charge).sum()
+    }
+
+    /// Returns the number of bytes of `region` that are currently charged.
+    pub fn committed_in(&self, region: VirtMemoryRegion) -> usize {
+        self.vmas_overlapping(region)
+            .map(|vma| vma.commit_charge_with(region, vma.permissions))
+            .sum()
+    }
+
+    /// Returns the number of bytes of `region` that would be charged if the
+    /// mappings in it were given the permissions `perms`.
+    pub fn committed_in_with(&self, region: VirtMemoryRegion, perms: VMAPermissions) -> usize {
+        self.vmas_overlapping(region)
+            .map(|vma| vma.commit_charge_with(region, perms))
+            .sum()
+    }
+
+    /// Returns the VMAs that overlap `region`, highest address first.
+    fn vmas_overlapping(&self, region: VirtMemoryRegion) -> impl Iterator<Item = &VMArea> {
+        self.vmas
+            .range(..region.end_address())
+            .rev()
+            .map(|(_, vma)| vma)
+            .take_while(move |vma| vma.region.end_address() > region.start_address())
+    }
+
+    /// Registers the page-aligned `region` with the userfaultfd context `id`,
+    /// or unregisters it with `None`, splitting the VMAs that cover it at its
+    /// boundaries.
+    pub fn set_userfault(&mut self, region: VirtMemoryRegion, id: Option<u64>) -> Result<()> {
+        self.modify_region(region, |vma| vma.userfault = id)
+    }
+
+    /// Makes [`MemoryMap::mprotect`] fail with `err` when asked to make the
+    /// page-aligned `region` writable, or lifts that with `None`, splitting
+    /// the VMAs that cover it at its boundaries.
+    pub fn set_write_denied(
+        &mut self,
+        region: VirtMemoryRegion,
+        err: Option<KernelError>,
+    ) -> Result<()> {
+        self.modify_region(region, |vma| vma.write_denied = err.clone())
+    }
+
+    /// Applies `f` to the parts of the VMAs covering `region`, splitting them
+    /// at its boundaries and merging the results where possible.
+    fn modify_region(&mut self, region: VirtMemoryRegion, f: impl Fn(&mut VMArea)) -> Result<()> {
+        for part in self.covering_regions(region)? {
+            let vma_start = self
+                .find_vma(part.start_address())
+                .map(|x| x.region.start_address())
+                .ok_or(KernelError::NoMemory)?;
+
+            let vma = self
+                .vmas
+                .remove(&vma_start)
+                .expect("Should have the same key as the start address");
+
+            let (left, right) = vma.region.punch_hole(part);
+            let mut new_vma = vma.shrink_to(part);
+            f(&mut new_vma);
 
             if let Some(left) = left {
-                self.insert_and_merge(affected_vma.shrink_to(left));
+                self.insert_and_merge(vma.shrink_to(left));
             }
 
-            self.address_space
-                .protect_range(protect_region, new_perms.into())?;
             self.insert_and_merge(new_vma);
 
             if let Some(right) = right {
-                self.insert_and_merge(affected_vma.shrink_to(right));
+                self.insert_and_merge(vma.shrink_to(right));
             }
-
-            return Ok(());
         }
 
-        // TODO: protecting over contiguous VMAreas.
-        Err(KernelError::NoMemory)
+        Ok(())
     }
 
     /// Checks if a given virtual memory region is completely free.
@@ -266,40 +518,33 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         }
     }
 
-    /// Finds a free region of at least `len` bytes. Searches downwards from
-    /// `MMAP_BASE`.
-    fn find_free_region(&self, len: usize) -> Option<VirtMemoryRegion> {
-        let mut last_vma_end = VA::from_value(MMAP_BASE);
+    /// Finds a free region of at least `len` bytes, starting on an `align`
+    /// boundary. Searches downwards from the mmap base.
+    fn find_free_region(&self, len: usize, align: usize) -> Option<VirtMemoryRegion> {
+        let mut last_vma_end = self.mmap_base;
+
+        // Place the new mapping at the top of the gap between `gap_start` and
+        // `gap_end`, if it fits.
+        let fit = |gap_start: VA, gap_end: VA| {
+            let start = VA::from_value(gap_end.value().checked_sub(len)?).align(align);
+
+            (start >= gap_start).then(|| VirtMemoryRegion::new(start, len))
+        };
 
         // Iterate through VMAs in reverse order to find a gap.
         for (_, vma) in self.vmas.iter().rev() {
-            let vma_start = vma.region.start_address();
             let vma_end = vma.region.end_address();
 
-            if last_vma_end >= vma_end {
-                let gap_start = vma_end;
-                let gap_size = last_vma_end.value() - gap_start.value();
-
-                if gap_size >= len {
-                    // Found a large enough gap. Place the new mapping at the top of it.
-                    return Some(VirtMemoryRegion::new(
-                        VA::from_value(last_vma_end.value() - len),
-                        len,
-                    ));
-                }
+            if last_vma_end >= vma_end
+                && let Some(region) = fit(vma_end, last_vma_end)
+            {
+                return Some(region);
             }
-            last_vma_end = vma_start;
+            last_vma_end = last_vma_end.min(self.vma_start_gap(vma));
         }
 
         // Check the final gap at the beginning of the mmap area.
-        if last_vma_end.value() >= len {
-            Some(VirtMemoryRegion::new(
-                VA::from_value(last_vma_end.value() - len),
-                len,
-            ))
-        } else {
-            None
-        }
+        fit(VA::null(), last_vma_end)
     }
 
     /// Inserts a new VMA, handling overlaps and merging it with neighbors if
@@ -485,17 +730,21 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
     }
 
     /// Attempts to clone this memory map, sharing any already-mapped writable
-    /// pages as CoW pages. If the VMA isn't writable, the ref count is
-    /// incremented.
+    /// pages as CoW pages. If the VMA isn't writable, or is a shared mapping,
+    /// the ref count is incremented.
     pub fn clone_as_cow(&mut self) -> Result<Self> {
         let mut new_as = AS::new()?;
-        let new_vmas = self.vmas.clone();
+        let mut new_vmas = self.vmas.clone();
+
+        for vma in new_vmas.values_mut() {
+            // The child isn't registered with any userfaultfd context.
+            vma.userfault = None;
 
-        for vma in new_vmas.values() {
             let mut pte_perms = PtePermissions::from(vma.permissions);
 
-            // Mark all writable pages as CoW.
-            if pte_perms.is_write() {
+            // Mark all writable pages as CoW. Pages of shared mappings remain
+            // writable by both processes.
+            if pte_perms.is_write() && !vma.is_shared() {
                 pte_perms = pte_perms.into_cow();
             }
 
@@ -509,6 +758,8 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         Ok(Self {
             vmas: new_vmas,
             address_space: new_as,
+            mmap_base: self.mmap_base,
+            stack_guard_gap: self.stack_guard_gap,
         })
     }
 
@@ -1,15 +1,15 @@
 use super::MemoryMap;
 use crate::{
-    error::Result,
-    fs::Inode,
+    error::{FsError, KernelError, Result},
+    fs::{Inode, readahead::ReadaheadAdvice},
     memory::{
-        PAGE_SIZE,
+        HUGE_PAGE_SIZE, PAGE_SIZE,
         address::VA,
         page::PageFrame,
         paging::permissions::PtePermissions,
         proc_vm::{
             address_space::{PageInfo, UserAddressSpace},
-            memory_map::{AddressRequest, MMAP_BASE},
+            memory_map::{AddressRequest, MMAP_BASE, STACK_GUARD_GAP},
             vmarea::{VMAPermissions, VMArea, VMAreaKind, VMFileMapping, tests::DummyTestInode},
         },
         region::VirtMemoryRegion,
@@ -52,6 +52,14 @@ impl UserAddressSpace for MockAddressSpace {
         panic!("Should be called by the demand-pager");
     }
 
+    fn map_huge_page(&mut self, _page: PageFrame, _va: VA, _perms: PtePermissions) -> Result<()> {
+        panic!("Should be called by the demand-pager");
+    }
+
+    fn can_map_huge_page(&self, _va: VA) -> bool {
+        panic!("Should be called by the demand-pager");
+    }
+
     fn unmap(&mut self, va: VA) -> Result<PageFrame> {
         let region = VirtMemoryRegion::new(va, PAGE_SIZE);
         self.ops_log
@@ -126,6 +134,8 @@ fn create_file_vma(
             file: inode,
             offset,
             len: size as u64,
+            shared: false,
+            writable: None,
         }),
         perms,
     )
@@ -245,6 +255,214 @@ fn test_mmap_any_with_existing() {
     assert!(pvm.address_space.ops_log.lock().unwrap().is_empty());
 }
 
+#[test]
+fn test_mmap_any_below_mmap_base() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let size = 2 * PAGE_SIZE;
+    let base = MMAP_BASE - 0x1000_0000;
+
+    // A mapping above the base (e.g. the stack) mustn't pull the search
+    // upwards.
+    pvm.insert_and_merge(create_anon_vma(
+        MMAP_BASE + 0x1000_0000,
+        size,
+        VMAPermissions::rw(),
+    ));
+    pvm.set_mmap_base(VA::from_value(base));
+
+    let addr = pvm
+        .mmap(
+            AddressRequest::Any,
+            size,
+            VMAPermissions::rw(),
+            VMAreaKind::Anon,
+            String::new(),
+        )
+        .unwrap();
+
+    assert_eq!(addr.value(), base - size);
+    assert_vma_exists(&pvm, base - size, size);
+}
+
+fn create_stack_vma(end: usize, size: usize) -> VMArea {
+    let mut vma = create_anon_vma(end - size, size, VMAPermissions::rw());
+    vma.set_name("[stack]");
+    vma.set_grows_down(true);
+    vma
+}
+
+#[test]
+fn test_expand_stack() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let end = MMAP_BASE + 0x1000_0000;
+
+    pvm.insert_and_merge(create_stack_vma(end, 4 * PAGE_SIZE));
+
+    // A fault part-way into a page below the stack pulls in the whole page.
+    pvm.expand_stack(VA::from_value(end - 7 * PAGE_SIZE + 8), 16 * PAGE_SIZE)
+        .unwrap();
+
+    assert_eq!(pvm.vma_count(), 1);
+    assert_vma_exists(&pvm, end - 7 * PAGE_SIZE, 7 * PAGE_SIZE);
+    assert_eq!(
+        pvm.find_vma(VA::from_value(end - PAGE_SIZE))
+            .unwrap()
+            .name(),
+        "[stack]"
+    );
+}
+
+#[test]
+fn test_expand_stack_limit() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let end = MMAP_BASE + 0x1000_0000;
+
+    pvm.insert_and_merge(create_stack_vma(end, 4 * PAGE_SIZE));
+
+    assert!(matches!(
+        pvm.expand_stack(VA::from_value(end - 9 * PAGE_SIZE), 8 * PAGE_SIZE),
+        Err(KernelError::NoMemory)
+    ));
+    assert_vma_exists(&pvm, end - 4 * PAGE_SIZE, 4 * PAGE_SIZE);
+
+    pvm.expand_stack(VA::from_value(end - 8 * PAGE_SIZE), 8 * PAGE_SIZE)
+        .unwrap();
+    assert_vma_exists(&pvm, end - 8 * PAGE_SIZE, 8 * PAGE_SIZE);
+}
+
+#[test]
+fn test_expand_stack_guard_gap() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let end = MMAP_BASE + 0x1000_0000;
+    let stack_start = end - 4 * PAGE_SIZE;
+    let below_end = stack_start - STACK_GUARD_GAP - 2 * PAGE_SIZE;
+
+    pvm.insert_and_merge(create_stack_vma(end, 4 * PAGE_SIZE));
+    pvm.insert_and_merge(create_anon_vma(
+        below_end - PAGE_SIZE,
+        PAGE_SIZE,
+        VMAPermissions::rw(),
+    ));
+
+    // Growing into the guard gap above the mapping below is an overflow.
+    assert!(matches!(
+        pvm.expand_stack(
+            VA::from_value(below_end + STACK_GUARD_GAP - PAGE_SIZE),
+            usize::MAX
+        ),
+        Err(KernelError::NoMemory)
+    ));
+
+    pvm.expand_stack(VA::from_value(below_end + STACK_GUARD_GAP), usize::MAX)
+        .unwrap();
+    assert_vma_exists(
+        &pvm,
+        below_end + STACK_GUARD_GAP,
+        end - below_end - STACK_GUARD_GAP,
+    );
+}
+
+#[test]
+fn test_expand_stack_needs_stack() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = MMAP_BASE + 0x1000_0000;
+
+    pvm.insert_and_merge(create_anon_vma(start, PAGE_SIZE, VMAPermissions::rw()));
+
+    assert!(matches!(
+        pvm.expand_stack(VA::from_value(start - PAGE_SIZE), usize::MAX),
+        Err(KernelError::Fault)
+    ));
+    assert!(matches!(
+        pvm.expand_stack(VA::from_value(start + PAGE_SIZE), usize::MAX),
+        Err(KernelError::Fault)
+    ));
+}
+
+#[test]
+fn test_mmap_any_avoids_stack_guard_gap() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let size = 2 * PAGE_SIZE;
+    let stack_start = MMAP_BASE - 4 * PAGE_SIZE;
+
+    pvm.insert_and_merge(create_stack_vma(MMAP_BASE, 4 * PAGE_SIZE));
+
+    let addr = pvm
+        .mmap(
+            AddressRequest::Any,
+            size,
+            VMAPermissions::rw(),
+            VMAreaKind::Anon,
+            String::new(),
+        )
+        .unwrap();
+
+    assert_eq!(addr.value(), stack_start - STACK_GUARD_GAP - size);
+}
+
+#[test]
+fn test_mmap_huge_any() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+
+    // Leave the top of the mmap area unaligned.
+    pvm.insert_and_merge(create_anon_vma(
+        MMAP_BASE - PAGE_SIZE,
+        PAGE_SIZE,
+        VMAPermissions::rw(),
+    ));
+
+    let addr = pvm
+        .mmap_huge(
+            AddressRequest::Any,
+            HUGE_PAGE_SIZE + PAGE_SIZE,
+            VMAPermissions::rw(),
+            String::new(),
+        )
+        .unwrap();
+
+    // The length is rounded up to whole huge pages, and the mapping placed on
+    // a huge page boundary below the existing VMA.
+    assert_eq!(addr.value(), MMAP_BASE - 3 * HUGE_PAGE_SIZE);
+    assert_vma_exists(&pvm, addr.value(), 2 * HUGE_PAGE_SIZE);
+    assert!(pvm.find_vma(addr).unwrap().uses_huge_pages());
+    assert_eq!(pvm.vmas.len(), 2);
+
+    // A regular mapping placed directly below isn't merged into it.
+    let below = pvm
+        .mmap(
+            AddressRequest::Fixed {
+                address: addr.sub_bytes(PAGE_SIZE),
+                permit_overlap: false,
+            },
+            PAGE_SIZE,
+            VMAPermissions::rw(),
+            VMAreaKind::Anon,
+            String::new(),
+        )
+        .unwrap();
+
+    assert!(!pvm.find_vma(below).unwrap().uses_huge_pages());
+    assert_eq!(pvm.vmas.len(), 3);
+}
+
+#[test]
+fn test_mmap_huge_fixed_unaligned() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+
+    let result = pvm.mmap_huge(
+        AddressRequest::Fixed {
+            address: VA::from_value(MMAP_BASE - HUGE_PAGE_SIZE - PAGE_SIZE),
+            permit_overlap: false,
+        },
+        HUGE_PAGE_SIZE,
+        VMAPermissions::rw(),
+        String::new(),
+    );
+
+    assert!(matches!(result, Err(KernelError::InvalidValue)));
+    assert!(pvm.vmas.is_empty());
+}
+
 #[test]
 fn test_mmap_hint_free() {
     let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
@@ -600,6 +818,63 @@ fn test_no_merge_file_backed_non_contiguous() {
     assert!(pvm.address_space.ops_log.lock().unwrap().is_empty());
 }
 
+#[test]
+fn test_discard_region_keeps_vmas() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let addr = MMAP_BASE - 10 * PAGE_SIZE;
+    let size = 5 * PAGE_SIZE;
+    pvm.insert_and_merge(create_anon_vma(addr, size, VMAPermissions::rw()));
+
+    let region = VirtMemoryRegion::new(VA::from_value(addr + PAGE_SIZE), 2 * PAGE_SIZE);
+    pvm.discard_region(region).unwrap();
+
+    assert_eq!(pvm.vmas.len(), 1);
+    assert_vma_exists(&pvm, addr, size);
+    assert_eq!(
+        *pvm.address_space.ops_log.lock().unwrap(),
+        &[MockPageTableOp::UnmapRange { region }]
+    );
+}
+
+#[test]
+fn test_discard_region_spanning_vmas() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let addr = MMAP_BASE - 10 * PAGE_SIZE;
+    pvm.insert_and_merge(create_anon_vma(addr, 2 * PAGE_SIZE, VMAPermissions::rw()));
+    pvm.insert_and_merge(create_anon_vma(
+        addr + 2 * PAGE_SIZE,
+        2 * PAGE_SIZE,
+        VMAPermissions::ro(),
+    ));
+
+    let region = VirtMemoryRegion::new(VA::from_value(addr + PAGE_SIZE), 2 * PAGE_SIZE);
+    pvm.discard_region(region).unwrap();
+
+    assert_eq!(pvm.vmas.len(), 2);
+    assert_eq!(
+        *pvm.address_space.ops_log.lock().unwrap(),
+        &[
+            MockPageTableOp::UnmapRange {
+                region: VirtMemoryRegion::new(VA::from_value(addr + PAGE_SIZE), PAGE_SIZE)
+            },
+            MockPageTableOp::UnmapRange {
+                region: VirtMemoryRegion::new(VA::from_value(addr + 2 * PAGE_SIZE), PAGE_SIZE)
+            }
+        ]
+    );
+}
+
+#[test]
+fn test_discard_region_hole_fails() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let addr = MMAP_BASE - 10 * PAGE_SIZE;
+    pvm.insert_and_merge(create_anon_vma(addr, 2 * PAGE_SIZE, VMAPermissions::rw()));
+
+    let region = VirtMemoryRegion::new(VA::from_value(addr), 3 * PAGE_SIZE);
+    assert!(pvm.discard_region(region).is_err());
+    assert!(pvm.address_space.ops_log.lock().unwrap().is_empty());
+}
+
 #[test]
 fn test_munmap_full_vma() {
     let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
@@ -902,3 +1177,280 @@ fn test_mprotect_merge_restoration() {
     assert_vma_exists(&pvm, start, size);
     assert_vma_perms(&pvm, start, VMAPermissions::rw());
 }
+
+#[test]
+fn test_set_readahead_split_and_restore() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0x60000;
+    let inode = new_inode();
+
+    // Two VMAs that don't merge: [0x60000 - 0x63000) and [0x63000 - 0x65000).
+    pvm.insert_and_merge(create_file_vma(
+        start,
+        3 * PAGE_SIZE,
+        VMAPermissions::rx(),
+        0,
+        inode.clone(),
+    ));
+    pvm.insert_and_merge(create_anon_vma(
+        start + 3 * PAGE_SIZE,
+        2 * PAGE_SIZE,
+        VMAPermissions::rw(),
+    ));
+
+    // Advise [0x61000 - 0x64000), spanning both.
+    let region = VirtMemoryRegion::new(VA::from_value(start + PAGE_SIZE), 3 * PAGE_SIZE);
+    pvm.set_readahead(region, ReadaheadAdvice::Sequential)
+        .unwrap();
+
+    assert_eq!(pvm.vmas.len(), 4);
+    assert_vma_exists(&pvm, start, PAGE_SIZE);
+    assert_vma_exists(&pvm, start + PAGE_SIZE, 2 * PAGE_SIZE);
+    assert_vma_exists(&pvm, start + 3 * PAGE_SIZE, PAGE_SIZE);
+    assert_vma_exists(&pvm, start + 4 * PAGE_SIZE, PAGE_SIZE);
+
+    let middle = pvm.find_vma(VA::from_value(start + PAGE_SIZE)).unwrap();
+    assert_eq!(middle.readahead(), ReadaheadAdvice::Sequential);
+    if let VMAreaKind::File(f) = &middle.kind {
+        assert_eq!(f.offset, PAGE_SIZE as u64);
+    } else {
+        panic!("Middle VMA lost file backing");
+    }
+
+    let left = pvm.find_vma(VA::from_value(start)).unwrap();
+    assert_eq!(left.readahead(), ReadaheadAdvice::Normal);
+
+    // Restoring the default merges the VMAs back together.
+    pvm.set_readahead(region, ReadaheadAdvice::Normal).unwrap();
+    assert_eq!(pvm.vmas.len(), 2);
+    assert_vma_exists(&pvm, start, 3 * PAGE_SIZE);
+    assert_vma_exists(&pvm, start + 3 * PAGE_SIZE, 2 * PAGE_SIZE);
+}
+
+#[test]
+fn test_set_readahead_hole_fails() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0x70000;
+
+    pvm.insert_and_merge(create_anon_vma(start, PAGE_SIZE, VMAPermissions::rw()));
+
+    let region = VirtMemoryRegion::new(VA::from_value(start), 2 * PAGE_SIZE);
+    assert!(pvm.set_readahead(region, ReadaheadAdvice::Random).is_err());
+    assert_eq!(
+        pvm.find_vma(VA::from_value(start)).unwrap().readahead(),
+        ReadaheadAdvice::Normal
+    );
+}
+
+#[test]
+fn test_set_userfault_split_and_restore() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0x80000;
+
+    pvm.insert_and_merge(create_anon_vma(start, 4 * PAGE_SIZE, VMAPermissions::rw()));
+
+    let region = VirtMemoryRegion::new(VA::from_value(start + PAGE_SIZE), 2 * PAGE_SIZE);
+    pvm.set_userfault(region, Some(7)).unwrap();
+
+    assert_eq!(pvm.vmas.len(), 3);
+    assert_vma_exists(&pvm, start + PAGE_SIZE, 2 * PAGE_SIZE);
+    assert_eq!(
+        pvm.find_vma(VA::from_value(start + PAGE_SIZE))
+            .unwrap()
+            .userfault(),
+        Some(7)
+    );
+    assert_eq!(
+        pvm.find_vma(VA::from_value(start)).unwrap().userfault(),
+        None
+    );
+
+    // Unregistering merges the VMAs back together.
+    pvm.set_userfault(region, None).unwrap();
+    assert_eq!(pvm.vmas.len(), 1);
+    assert_vma_exists(&pvm, start, 4 * PAGE_SIZE);
+}
+
+#[test]
+fn test_mprotect_private_write_is_cow() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0x90000;
+    let size = 2 * PAGE_SIZE;
+
+    pvm.insert_and_merge(create_file_vma(
+        start,
+        size,
+        VMAPermissions::ro(),
+        0,
+        new_inode(),
+    ));
+
+    let region = VirtMemoryRegion::new(VA::from_value(start), size);
+    pvm.mprotect(region, VMAPermissions::rw()).unwrap();
+
+    // The pages may be shared with the page cache, so they're only made
+    // writable by a CoW fault.
+    assert_vma_perms(&pvm, start, VMAPermissions::rw());
+    let log = pvm.address_space.ops_log.lock().unwrap();
+    assert!(log.iter().any(|op| matches!(
+        op,
+        MockPageTableOp::ProtectRange { region: r, perms }
+            if *r == region && *perms == PtePermissions::from(VMAPermissions::rw()).into_cow()
+    )));
+}
+
+#[test]
+fn test_mprotect_across_vmas() {
+    // As when the dynamic linker applies RELRO over the end of one segment
+    // and the start of the next.
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0xa0000;
+    let inode = new_inode();
+
+    pvm.insert_and_merge(create_file_vma(
+        start,
+        2 * PAGE_SIZE,
+        VMAPermissions::rw(),
+        0,
+        inode.clone(),
+    ));
+    pvm.insert_and_merge(create_anon_vma(
+        start + 2 * PAGE_SIZE,
+        2 * PAGE_SIZE,
+        VMAPermissions::rw(),
+    ));
+    assert_eq!(pvm.vmas.len(), 2);
+
+    let region = VirtMemoryRegion::new(VA::from_value(start + PAGE_SIZE), 2 * PAGE_SIZE);
+    pvm.mprotect(region, VMAPermissions::ro()).unwrap();
+
+    assert_eq!(pvm.vmas.len(), 4);
+    assert_vma_exists(&pvm, start, PAGE_SIZE);
+    assert_vma_perms(&pvm, start, VMAPermissions::rw());
+    assert_vma_exists(&pvm, start + PAGE_SIZE, PAGE_SIZE);
+    assert_vma_perms(&pvm, start + PAGE_SIZE, VMAPermissions::ro());
+    assert_vma_exists(&pvm, start + 2 * PAGE_SIZE, PAGE_SIZE);
+    assert_vma_perms(&pvm, start + 2 * PAGE_SIZE, VMAPermissions::ro());
+    assert_vma_exists(&pvm, start + 3 * PAGE_SIZE, PAGE_SIZE);
+    assert_vma_perms(&pvm, start + 3 * PAGE_SIZE, VMAPermissions::rw());
+
+    // The file offset follows the split.
+    if let VMAreaKind::File(mapping) = &pvm
+        .find_vma(VA::from_value(start + PAGE_SIZE))
+        .unwrap()
+        .kind
+    {
+        assert_eq!(mapping.offset, PAGE_SIZE as u64);
+    } else {
+        panic!("Expected a file mapping");
+    }
+
+    // A hole in the region fails without changing anything.
+    let region = VirtMemoryRegion::new(VA::from_value(start + 3 * PAGE_SIZE), 2 * PAGE_SIZE);
+    assert!(matches!(
+        pvm.mprotect(region, VMAPermissions::ro()),
+        Err(KernelError::NoMemory)
+    ));
+    assert_vma_perms(&pvm, start + 3 * PAGE_SIZE, VMAPermissions::rw());
+}
+
+#[test]
+fn test_from_vmas_overlap_replaces_earlier() {
+    // A text segment whose last page is shared with the data segment.
+    let inode = new_inode();
+    let start = 0xb0000;
+
+    let text = create_file_vma(start, 3 * PAGE_SIZE, VMAPermissions::rx(), 0, inode.clone());
+    let data = create_file_vma(
+        start + 2 * PAGE_SIZE,
+        2 * PAGE_SIZE,
+        VMAPermissions::rw(),
+        2 * PAGE_SIZE as u64,
+        inode,
+    );
+
+    let pvm: MemoryMap<MockAddressSpace> = MemoryMap::from_vmas(vec![text, data]).unwrap();
+
+    assert_eq!(pvm.vmas.len(), 2);
+    assert_vma_exists(&pvm, start, 2 * PAGE_SIZE);
+    assert_vma_perms(&pvm, start, VMAPermissions::rx());
+    assert_vma_exists(&pvm, start + 2 * PAGE_SIZE, 2 * PAGE_SIZE);
+    assert_vma_perms(&pvm, start + 2 * PAGE_SIZE, VMAPermissions::rw());
+}
+
+#[test]
+fn test_commit_charge() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0xa0000;
+
+    // Only private, writable memory is charged.
+    pvm.insert_and_merge(create_anon_vma(start, 4 * PAGE_SIZE, VMAPermissions::rw()));
+    pvm.insert_and_merge(create_anon_vma(
+        start + 8 * PAGE_SIZE,
+        2 * PAGE_SIZE,
+        VMAPermissions::ro(),
+    ));
+    pvm.insert_and_merge(create_file_vma(
+        start + 16 * PAGE_SIZE,
+        PAGE_SIZE,
+        VMAPermissions::rw(),
+        0,
+        new_inode(),
+    ));
+
+    assert_eq!(pvm.committed(), 5 * PAGE_SIZE);
+
+    let region = VirtMemoryRegion::new(VA::from_value(start + 2 * PAGE_SIZE), 8 * PAGE_SIZE);
+    assert_eq!(pvm.committed_in(region), 2 * PAGE_SIZE);
+    assert_eq!(
+        pvm.committed_in_with(region, VMAPermissions::rw()),
+        4 * PAGE_SIZE
+    );
+}
+
+#[test]
+fn test_commit_charge_noreserve() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0xb0000;
+
+    pvm.insert_and_merge(create_anon_vma(start, 4 * PAGE_SIZE, VMAPermissions::rw()));
+
+    let region = VirtMemoryRegion::new(VA::from_value(start), 2 * PAGE_SIZE);
+    pvm.set_noreserve(region, true).unwrap();
+
+    assert_eq!(pvm.vmas.len(), 2);
+    assert_eq!(pvm.committed(), 2 * PAGE_SIZE);
+    assert_eq!(pvm.committed_in(region), 0);
+
+    pvm.set_noreserve(region, false).unwrap();
+
+    assert_eq!(pvm.vmas.len(), 1);
+    assert_eq!(pvm.committed(), 4 * PAGE_SIZE);
+}
+
+#[test]
+fn test_mprotect_write_denied() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+    let start = 0x98000;
+    let size = 2 * PAGE_SIZE;
+    let mut vma = VMArea::new(
+        VirtMemoryRegion::new(VA::from_value(start), size),
+        VMAreaKind::new_shared_file(new_inode(), 0, size as u64),
+        VMAPermissions::ro(),
+    );
+
+    // As for a file opened read-only.
+    vma.set_write_denied(Some(FsError::PermissionDenied.into()));
+    pvm.insert_and_merge(vma);
+
+    let region = VirtMemoryRegion::new(VA::from_value(start), size);
+    assert!(matches!(
+        pvm.mprotect(region, VMAPermissions::rw()),
+        Err(KernelError::Fs(FsError::PermissionDenied))
+    ));
+    assert_vma_perms(&pvm, start, VMAPermissions::ro());
+
+    // Changes that don't add write are still fine.
+    pvm.mprotect(region, VMAPermissions::rx()).unwrap();
+    assert_vma_perms(&pvm, start, VMAPermissions::rx());
+}
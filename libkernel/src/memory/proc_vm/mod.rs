@@ -9,6 +9,7 @@ use memory_map::{AddressRequest, MemoryMap};
 use vmarea::{AccessKind, FaultValidation, VMAPermissions, VMArea, VMAreaKind};
 
 pub mod address_space;
+pub mod commit;
 pub mod memory_map;
 pub mod pg_offset;
 pub mod vmarea;
@@ -19,6 +20,8 @@ const BRK_PERMISSIONS: VMAPermissions = VMAPermissions::rw();
 pub struct ProcessVM<AS: UserAddressSpace> {
     mm: MemoryMap<AS>,
     brk: VirtMemoryRegion,
+    /// The bytes of the [`commit`] total that this VM accounts for.
+    committed: usize,
 }
 
 impl<AS: UserAddressSpace> ProcessVM<AS> {
@@ -35,7 +38,7 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
 
         let brk = VirtMemoryRegion::new(vma.region.end_address().align_up(PAGE_SIZE), 0);
 
-        Self { mm, brk }
+        Self::new(mm, brk)
     }
 
     /// Constructs a new Process VM structure from the given VMA. The heap is
@@ -47,7 +50,7 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
 
         let brk = VirtMemoryRegion::new(vma.region.end_address().align_up(PAGE_SIZE), 0);
 
-        Ok(Self { mm, brk })
+        Ok(Self::new(mm, brk))
     }
 
     /// Constructs a `ProcessVM` from an existing memory map.
@@ -63,18 +66,58 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
             // VMAs should already be page-aligned, but just in case.
             .align_up(PAGE_SIZE);
 
-        Self {
-            mm: map,
-            brk: VirtMemoryRegion::new(brk, 0),
-        }
+        Self::new(map, VirtMemoryRegion::new(brk, 0))
+    }
+
+    /// Moves the start of the program break up by `offset` bytes, rounded up
+    /// to a page. Used by the ELF loader to randomize the heap location; must
+    /// be called before the heap has grown.
+    pub fn offset_brk(&mut self, offset: usize) {
+        debug_assert_eq!(self.brk.size(), 0);
+
+        let start = self
+            .brk
+            .start_address()
+            .add_bytes(offset)
+            .align_up(PAGE_SIZE);
+
+        self.brk = VirtMemoryRegion::new(start, 0);
     }
 
     /// Creates an empty `ProcessVM` with no mappings and a zero-sized heap.
     pub fn empty() -> Result<Self> {
-        Ok(Self {
-            mm: MemoryMap::new()?,
-            brk: VirtMemoryRegion::empty(),
-        })
+        Ok(Self::new(MemoryMap::new()?, VirtMemoryRegion::empty()))
+    }
+
+    fn new(mm: MemoryMap<AS>, brk: VirtMemoryRegion) -> Self {
+        let mut vm = Self {
+            mm,
+            brk,
+            committed: 0,
+        };
+
+        vm.recharge();
+
+        vm
+    }
+
+    /// Returns the number of bytes of memory this VM has committed.
+    pub fn committed(&self) -> usize {
+        self.committed
+    }
+
+    /// Brings this VM's share of the [`commit`] total up to date. Must be
+    /// called after changing the memory map through [`Self::mm_mut`].
+    pub fn recharge(&mut self) {
+        let committed = self.mm.committed();
+
+        if committed > self.committed {
+            commit::charge(committed - self.committed);
+        } else {
+            commit::uncharge(self.committed - committed);
+        }
+
+        self.committed = committed;
     }
 
     /// Finds the VMA covering `addr` if the given access type is permitted.
@@ -158,6 +201,7 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
             )?;
 
             self.brk = new_brk_region;
+            self.recharge();
 
             return Ok(new_end_addr);
         }
@@ -169,16 +213,20 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
         self.mm.munmap(unmap_region)?;
 
         self.brk = new_brk_region;
+        self.recharge();
 
         Ok(new_end_addr)
     }
 
     /// Clones this process VM, marking all writable pages as copy-on-write.
     pub fn clone_as_cow(&mut self) -> Result<Self> {
-        Ok(Self {
-            mm: self.mm.clone_as_cow()?,
-            brk: self.brk,
-        })
+        Ok(Self::new(self.mm.clone_as_cow()?, self.brk))
+    }
+}
+
+impl<AS: UserAddressSpace> Drop for ProcessVM<AS> {
+    fn drop(&mut self) {
+        commit::uncharge(self.committed);
     }
 }
 
@@ -187,6 +235,7 @@ mod tests {
     use super::memory_map::tests::MockAddressSpace;
     use super::*;
     use crate::error::KernelError;
+    use crate::fs::readahead::ReadaheadAdvice;
 
     fn setup_vm() -> ProcessVM<MockAddressSpace> {
         let text_vma = VMArea {
@@ -194,6 +243,12 @@ mod tests {
             kind: VMAreaKind::Anon, // Simplification for test
             permissions: VMAPermissions::rx(),
             name: String::new(),
+            huge_pages: false,
+            grows_down: false,
+            readahead: ReadaheadAdvice::Normal,
+            userfault: None,
+            noreserve: false,
+            write_denied: None,
         };
 
         ProcessVM::from_vma(text_vma).unwrap()
@@ -213,6 +268,22 @@ mod tests {
         assert!(vm.mm.find_vma(initial_brk_start).is_none());
     }
 
+    #[test]
+    fn test_brk_offset() {
+        let mut vm = setup_vm();
+        let initial_brk_start = vm.start_brk();
+
+        vm.offset_brk(3 * PAGE_SIZE + 1);
+
+        let start = initial_brk_start.add_bytes(4 * PAGE_SIZE);
+        assert_eq!(vm.start_brk(), start);
+        assert_eq!(vm.current_brk(), start);
+
+        vm.resize_brk(start.add_bytes(1)).unwrap();
+        assert!(vm.mm.find_vma(initial_brk_start).is_none());
+        assert_eq!(vm.mm.find_vma(start).unwrap().region.start_address(), start);
+    }
+
     #[test]
     fn test_brk_first_growth() {
         // Given: a VM with a zero-sized heap
@@ -284,6 +355,22 @@ mod tests {
         assert!(vm.mm.find_vma(initial_brk_start).is_some());
     }
 
+    #[test]
+    fn test_brk_commit_charge() {
+        // Given: a VM whose only mapping is read-only text
+        let mut vm = setup_vm();
+        let initial_brk_start = vm.brk.start_address();
+        assert_eq!(vm.committed(), 0);
+
+        // When: the heap grows and shrinks, its pages are charged and
+        // uncharged.
+        vm.resize_brk(initial_brk_start.add_pages(3)).unwrap();
+        assert_eq!(vm.committed(), 3 * PAGE_SIZE);
+
+        vm.resize_brk(initial_brk_start.add_pages(1)).unwrap();
+        assert_eq!(vm.committed(), PAGE_SIZE);
+    }
+
     #[test]
     fn test_brk_shrink_to_zero() {
         // Given: a VM with a 2-page heap
@@ -350,6 +437,12 @@ mod tests {
             kind: VMAreaKind::Anon,
             permissions: VMAPermissions::ro(),
             name: String::new(),
+            huge_pages: false,
+            grows_down: false,
+            readahead: ReadaheadAdvice::Normal,
+            userfault: None,
+            noreserve: false,
+            write_denied: None,
         };
         vm.mm.insert_and_merge(obstacle_vma);
         assert_eq!(vm.mm.vma_count(), 2);
@@ -12,8 +12,9 @@
 use core::cmp;
 
 use crate::{
-    fs::{Inode, InodeId},
-    memory::{PAGE_MASK, PAGE_SIZE, address::VA, region::VirtMemoryRegion},
+    error::{KernelError, Result},
+    fs::{Inode, InodeId, readahead::ReadaheadAdvice},
+    memory::{HUGE_PAGE_SIZE, PAGE_MASK, PAGE_SIZE, address::VA, region::VirtMemoryRegion},
 };
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
@@ -107,6 +108,17 @@ pub struct VMAFileRead {
     pub inode: Arc<dyn Inode>,
 }
 
+/// A shared mapping's hold on its file's count of writable mappings, taken
+/// by [`Inode::map_writable`] and dropped once every VMA cut from the mapping
+/// is gone.
+pub(super) struct WritableMapping(Arc<dyn Inode>);
+
+impl Drop for WritableMapping {
+    fn drop(&mut self) {
+        self.0.unmap_writable();
+    }
+}
+
 /// Represents a mapping to a region of a file that backs a `VMArea`.
 ///
 /// This specifies a "slice" of a file, defined by an `offset` and `len`, that
@@ -117,11 +129,16 @@ pub struct VMFileMapping {
     pub(super) file: Arc<dyn Inode>,
     pub(super) offset: u64,
     pub(super) len: u64,
+    pub(super) shared: bool,
+    pub(super) writable: Option<Arc<WritableMapping>>,
 }
 
 impl PartialEq for VMFileMapping {
     fn eq(&self, other: &Self) -> bool {
-        Arc::ptr_eq(&self.file, &other.file) && self.offset == other.offset && self.len == other.len
+        Arc::ptr_eq(&self.file, &other.file)
+            && self.offset == other.offset
+            && self.len == other.len
+            && self.shared == other.shared
     }
 }
 
@@ -140,6 +157,12 @@ impl VMFileMapping {
     pub fn file_len(&self) -> u64 {
         self.len
     }
+
+    /// Returns `true` if the file's pages are mapped directly, so that writes
+    /// are visible to every other mapping of the file (`MAP_SHARED`).
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
 }
 
 /// Defines the backing source for a `VMArea`.
@@ -169,7 +192,44 @@ impl VMAreaKind {
 
     /// Creates a new file-backed VMA kind.
     pub fn new_file(file: Arc<dyn Inode>, offset: u64, len: u64) -> Self {
-        Self::File(VMFileMapping { file, offset, len })
+        Self::File(VMFileMapping {
+            file,
+            offset,
+            len,
+            shared: false,
+            writable: None,
+        })
+    }
+
+    /// Creates a new file-backed VMA kind whose pages are shared with the file
+    /// (`MAP_SHARED`). `offset` must be page-aligned.
+    pub fn new_shared_file(file: Arc<dyn Inode>, offset: u64, len: u64) -> Self {
+        debug_assert_eq!(offset & PAGE_MASK as u64, 0);
+
+        Self::File(VMFileMapping {
+            file,
+            offset,
+            len,
+            shared: true,
+            writable: None,
+        })
+    }
+
+    /// Like [`VMAreaKind::new_shared_file`], for a mapping that may write to
+    /// the file's pages, now or after an `mprotect`. The file is told about
+    /// it through [`Inode::map_writable`], which may refuse.
+    pub fn new_writable_shared_file(file: Arc<dyn Inode>, offset: u64, len: u64) -> Result<Self> {
+        debug_assert_eq!(offset & PAGE_MASK as u64, 0);
+
+        file.map_writable()?;
+
+        Ok(Self::File(VMFileMapping {
+            writable: Some(Arc::new(WritableMapping(file.clone()))),
+            file,
+            offset,
+            len,
+            shared: true,
+        }))
     }
 }
 
@@ -186,6 +246,12 @@ pub struct VMArea {
     pub(super) name: String,
     pub(super) kind: VMAreaKind,
     pub(super) permissions: VMAPermissions,
+    pub(super) huge_pages: bool,
+    pub(super) grows_down: bool,
+    pub(super) readahead: ReadaheadAdvice,
+    pub(super) userfault: Option<u64>,
+    pub(super) noreserve: bool,
+    pub(super) write_denied: Option<KernelError>,
 }
 
 impl VMArea {
@@ -201,6 +267,12 @@ impl VMArea {
             kind,
             permissions,
             name: String::new(),
+            huge_pages: false,
+            grows_down: false,
+            readahead: ReadaheadAdvice::Normal,
+            userfault: None,
+            noreserve: false,
+            write_denied: None,
         }
     }
 
@@ -209,6 +281,43 @@ impl VMArea {
         self.name = s.as_ref().to_string();
     }
 
+    /// Allows the demand pager to back this VMA with huge pages where
+    /// possible (e.g. for `MAP_HUGETLB`).
+    pub fn set_huge_pages(&mut self, enable: bool) {
+        self.huge_pages = enable;
+    }
+
+    /// Marks this VMA as a stack that the fault handler may extend downwards
+    /// (`VM_GROWSDOWN`).
+    pub fn set_grows_down(&mut self, enable: bool) {
+        self.grows_down = enable;
+    }
+
+    /// Sets how the fault handler reads ahead in the backing file
+    /// (`MADV_SEQUENTIAL`, `MADV_RANDOM`).
+    pub fn set_readahead(&mut self, advice: ReadaheadAdvice) {
+        self.readahead = advice;
+    }
+
+    /// Registers this VMA with the userfaultfd context `id`, or unregisters it
+    /// with `None`.
+    pub fn set_userfault(&mut self, id: Option<u64>) {
+        self.userfault = id;
+    }
+
+    /// Leaves this VMA out of commit accounting (`MAP_NORESERVE`).
+    pub fn set_noreserve(&mut self, enable: bool) {
+        self.noreserve = enable;
+    }
+
+    /// Stops `mprotect` from making this VMA writable, failing with `err`
+    /// instead, or lifts that restriction with `None`. A shared file mapping
+    /// may only become writable if it could have been mapped writable in the
+    /// first place (`VM_MAYWRITE`).
+    pub fn set_write_denied(&mut self, err: Option<KernelError>) {
+        self.write_denied = err;
+    }
+
     /// Creates a file-backed `VMArea` directly from an ELF program header.
     ///
     /// This is a convenience function used by the ELF loader. It parses the
@@ -261,9 +370,17 @@ impl VMArea {
                 file: f,
                 offset: hdr.p_offset(endian) - mappable_region.offset() as u64,
                 len: hdr.p_filesz(endian) + mappable_region.offset() as u64,
+                shared: false,
+                writable: None,
             }),
             permissions,
             name: String::new(),
+            huge_pages: false,
+            grows_down: false,
+            readahead: ReadaheadAdvice::Normal,
+            userfault: None,
+            noreserve: false,
+            write_denied: None,
         }
     }
 
@@ -381,11 +498,101 @@ impl VMArea {
         })
     }
 
+    /// Resolves a page fault within a shared file mapping.
+    ///
+    /// # Returns
+    /// * `Some((inode, page_index))` identifying the page of the file that
+    ///   should be mapped at `faulting_addr`.
+    /// * `None` if the VMA isn't a shared file mapping.
+    pub fn resolve_shared_fault(&self, faulting_addr: VA) -> Option<(Arc<dyn Inode>, u64)> {
+        let mapping = match &self.kind {
+            VMAreaKind::File(mapping) if mapping.shared => mapping,
+            _ => return None,
+        };
+
+        let page_offset =
+            (faulting_addr.page_aligned().value() - self.region.start_address().value()) as u64;
+
+        Some((
+            mapping.file.clone(),
+            (mapping.offset + page_offset) / PAGE_SIZE as u64,
+        ))
+    }
+
+    /// Returns the huge page sized region that a fault at `faulting_addr`
+    /// should populate with a single huge page.
+    ///
+    /// # Returns
+    /// * `Some(region)`: the naturally aligned region around `faulting_addr`,
+    ///   if this is an anonymous VMA with huge pages enabled that covers all
+    ///   of it.
+    /// * `None` if the fault must be served with a regular page.
+    pub fn huge_page_region(&self, faulting_addr: VA) -> Option<VirtMemoryRegion> {
+        if !self.huge_pages || !matches!(self.kind, VMAreaKind::Anon) {
+            return None;
+        }
+
+        let region = VirtMemoryRegion::new(faulting_addr.align(HUGE_PAGE_SIZE), HUGE_PAGE_SIZE);
+
+        self.region.contains(region).then_some(region)
+    }
+
+    /// Returns `true` if huge pages are enabled for this VMA.
+    pub fn uses_huge_pages(&self) -> bool {
+        self.huge_pages
+    }
+
+    /// Returns `true` if this VMA is a stack that grows downwards on demand.
+    pub fn grows_down(&self) -> bool {
+        self.grows_down
+    }
+
+    /// Returns the readahead advice applied to this VMA.
+    pub fn readahead(&self) -> ReadaheadAdvice {
+        self.readahead
+    }
+
+    /// Returns the userfaultfd context that missing pages of this VMA are
+    /// reported to, if any.
+    pub fn userfault(&self) -> Option<u64> {
+        self.userfault
+    }
+
+    /// Returns `true` if this VMA is a shared file mapping.
+    pub fn is_shared(&self) -> bool {
+        matches!(&self.kind, VMAreaKind::File(mapping) if mapping.shared)
+    }
+
+    /// Returns the error `mprotect` fails with when asked to make this VMA
+    /// writable, if it may not be, see [`VMArea::set_write_denied`].
+    pub fn write_denied(&self) -> Option<&KernelError> {
+        self.write_denied.as_ref()
+    }
+
     /// Returns the memory permissions for this VMA.
     pub fn permissions(&self) -> VMAPermissions {
         self.permissions
     }
 
+    /// Returns the number of bytes of `region` that count towards the commit
+    /// charge if this VMA had the permissions `perms`.
+    ///
+    /// Only private, writable memory is charged: anything else is either never
+    /// written or can be dropped and read back from its file. Huge page and
+    /// `MAP_NORESERVE` mappings aren't charged either.
+    pub fn commit_charge_with(&self, region: VirtMemoryRegion, perms: VMAPermissions) -> usize {
+        if !perms.write || self.is_shared() || self.huge_pages || self.noreserve {
+            return 0;
+        }
+
+        self.region.intersection(region).map_or(0, |r| r.size())
+    }
+
+    /// Returns the number of bytes this VMA adds to the commit charge.
+    pub fn commit_charge(&self) -> usize {
+        self.commit_charge_with(self.region, self.permissions)
+    }
+
     /// Returns `true` if the given virtual address falls within this VMA.
     pub fn contains_address(&self, addr: VA) -> bool {
         self.region.contains_address(addr)
@@ -397,7 +604,14 @@ impl VMArea {
     /// Merging is possible if permissions are identical and the backing storage
     /// is of a compatible and contiguous nature.
     pub(super) fn can_merge_with(&self, other: &VMArea) -> bool {
-        if self.permissions != other.permissions {
+        if self.permissions != other.permissions
+            || self.huge_pages != other.huge_pages
+            || self.grows_down != other.grows_down
+            || self.readahead != other.readahead
+            || self.userfault != other.userfault
+            || self.noreserve != other.noreserve
+            || self.write_denied != other.write_denied
+        {
             return false;
         }
 
@@ -405,8 +619,10 @@ impl VMArea {
             (VMAreaKind::Anon, VMAreaKind::Anon) => true,
 
             (VMAreaKind::File(self_map), VMAreaKind::File(other_map)) => {
-                // Check that they point to the same inode.
-                let same_file = Arc::ptr_eq(&self_map.file, &other_map.file);
+                // Check that they point to the same inode, shared in the same
+                // way.
+                let same_file = Arc::ptr_eq(&self_map.file, &other_map.file)
+                    && self_map.shared == other_map.shared;
 
                 // Check that the file offsets are contiguous. `other` VMA's
                 // offset must be `self`'s offset + `self`'s size.
@@ -462,6 +678,8 @@ impl VMArea {
                         file: vmfile_mapping.file.clone(),
                         offset: vmfile_mapping.offset + start_offset as u64,
                         len: new_sz,
+                        shared: vmfile_mapping.shared,
+                        writable: vmfile_mapping.writable.clone(),
                     });
                 }
 
@@ -492,6 +710,29 @@ impl VMArea {
         }
     }
 
+    /// Returns the whole pages of this VMA that map the data of the file `id`
+    /// at or beyond `offset`, or `None` if it doesn't map any.
+    pub fn file_region_from(&self, id: InodeId, offset: u64) -> Option<VirtMemoryRegion> {
+        let mapping = match &self.kind {
+            VMAreaKind::File(mapping) if mapping.file.id() == id => mapping,
+            _ => return None,
+        };
+
+        let base = self.region.start_address();
+        let skip = offset.saturating_sub(mapping.offset) as usize;
+        let len = cmp::min(mapping.len as usize, self.region.size());
+
+        if skip >= len {
+            return None;
+        }
+
+        Some(VirtMemoryRegion::from_start_end_address(
+            base.add_bytes(skip).align_up(PAGE_SIZE),
+            base.add_bytes(len).align_up(PAGE_SIZE),
+        ))
+        .filter(|region| !region.is_empty())
+    }
+
     /// Returns the human-readable name of this VMA.
     pub fn name(&self) -> &str {
         &self.name
@@ -513,7 +754,7 @@ pub mod tests {
     #[async_trait]
     impl Inode for DummyTestInode {
         fn id(&self) -> InodeId {
-            unreachable!("Not called")
+            InodeId::dummy()
         }
 
         fn as_any(&self) -> &dyn Any {
@@ -529,11 +770,35 @@ pub mod tests {
                 file: dummy_inode,
                 offset: file_offset,
                 len: filesz,
+                shared: false,
+                writable: None,
             }),
             VMAPermissions::rw(),
         )
     }
 
+    #[test]
+    fn file_region_from_offset() {
+        let vma = create_test_vma(0x20000, 0x4000, 0x1000, 0x3000);
+        let id = InodeId::dummy();
+        let region = |start: usize, end: usize| {
+            VirtMemoryRegion::from_start_end_address(VA::from_value(start), VA::from_value(end))
+        };
+
+        // Only the pages that map the file count, not the zero fill after.
+        assert_eq!(vma.file_region_from(id, 0), Some(region(0x20000, 0x23000)));
+        assert_eq!(
+            vma.file_region_from(id, 0x2000),
+            Some(region(0x21000, 0x23000))
+        );
+        // A page that's still partly inside the file is kept.
+        assert_eq!(
+            vma.file_region_from(id, 0x2800),
+            Some(region(0x22000, 0x23000))
+        );
+        assert_eq!(vma.file_region_from(id, 0x4000), None);
+    }
+
     #[test]
     fn simple_aligned_segment() {
         // A segment that is perfectly aligned to page boundaries.
@@ -808,4 +1073,43 @@ pub mod tests {
 
         assert!(matches!(result.kind, VMAreaKind::Anon));
     }
+
+    #[test]
+    fn huge_page_region_requires_covering_anon_vma() {
+        // [ 1MiB | 2MiB aligned | 1MiB ]
+        let start = VA::from_value(HUGE_PAGE_SIZE - HUGE_PAGE_SIZE / 2);
+        let mut vma = VMArea::new(
+            VirtMemoryRegion::new(start, 2 * HUGE_PAGE_SIZE),
+            VMAreaKind::Anon,
+            VMAPermissions::rw(),
+        );
+
+        let inner = VA::from_value(HUGE_PAGE_SIZE + 0x1234);
+
+        // Huge pages haven't been requested.
+        assert_eq!(vma.huge_page_region(inner), None);
+
+        vma.set_huge_pages(true);
+
+        assert_eq!(
+            vma.huge_page_region(inner),
+            Some(VirtMemoryRegion::new(
+                VA::from_value(HUGE_PAGE_SIZE),
+                HUGE_PAGE_SIZE
+            ))
+        );
+
+        // The aligned regions around the edges aren't fully covered.
+        assert_eq!(vma.huge_page_region(start), None);
+        assert_eq!(
+            vma.huge_page_region(VA::from_value(2 * HUGE_PAGE_SIZE + 0x1000)),
+            None
+        );
+
+        // File mappings are never backed by huge pages.
+        let mut file_vma = create_test_vma(0, 2 * HUGE_PAGE_SIZE, 0, 0x1000);
+        file_vma.set_huge_pages(true);
+
+        assert_eq!(file_vma.huge_page_region(VA::from_value(0x1000)), None);
+    }
 }
@@ -0,0 +1,265 @@
+//! The Earliest Eligible Virtual Deadline First policy.
+//!
+//! Each task is owed a share of the CPU in proportion to its weight. The
+//! virtual clock advances at the rate one unit of weight would receive
+//! service, and a task is eligible once it hasn't received more than it's
+//! owed: when its virtual eligible time has been reached. Of the eligible
+//! tasks, the one with the earliest virtual deadline runs.
+//!
+//! This module only holds the policy. Running tasks, preemption and deciding
+//! when to call into it are the kernel's business.
+
+use alloc::collections::binary_heap::BinaryHeap;
+use core::{cmp::Ordering, time::Duration};
+
+/// Fixed-point configuration for virtual-time accounting.
+/// We now use a 65.63 format (65 integer bits, 63 fractional bits) as
+/// recommended by the EEVDF paper to minimise rounding error accumulation.
+pub const VT_FIXED_SHIFT: u32 = 63;
+/// One unit of virtual time.
+pub const VT_ONE: u128 = 1u128 << VT_FIXED_SHIFT;
+/// Tolerance used when comparing virtual-time values (see EEVDF, Fixed-Point Arithmetic).
+/// Two virtual-time instants whose integer parts differ by no more than this constant are considered equal.
+pub const VCLOCK_EPSILON: u128 = VT_ONE;
+
+/// Converts `real` time into virtual time at `weight`.
+fn to_virtual(real: Duration, weight: u64) -> u128 {
+    (real.as_nanos() << VT_FIXED_SHIFT) / weight as u128
+}
+
+/// A task's standing with the scheduler, in virtual time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EevdfEntity {
+    /// The service received, scaled by weight.
+    pub v_runtime: u128,
+    /// Virtual time at which the task becomes eligible (v_ei).
+    pub v_eligible: u128,
+    /// Virtual deadline (v_di).
+    pub v_deadline: u128,
+}
+
+impl EevdfEntity {
+    /// Places a task joining the runqueue at virtual time `vclock`. It's
+    /// eligible straight away, with a deadline one `slice` on.
+    pub fn place(&mut self, vclock: u128, weight: u32, slice: Duration) {
+        self.v_eligible = vclock;
+        self.v_deadline = vclock + to_virtual(slice, weight as u64);
+    }
+
+    /// Charges the task for running for `ran`. Returns `true` once it has used
+    /// up its slice, at which point it's given a new deadline and should make
+    /// way for another task.
+    pub fn charge(&mut self, ran: Duration, weight: u32, slice: Duration) -> bool {
+        let dv = to_virtual(ran, weight as u64);
+
+        self.v_runtime = self.v_runtime.saturating_add(dv);
+
+        // Advance its eligible time by the virtual run time it just used
+        // (EEVDF: v_ei += t_used / w_i).
+        self.v_eligible = self.v_eligible.saturating_add(dv);
+
+        // Has the task exceeded its deadline?
+        if self.v_eligible >= self.v_deadline {
+            self.v_deadline = self.v_eligible + to_virtual(slice, weight as u64);
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Orders tasks by how soon they should run.
+    pub fn compare(&self, other: &Self) -> Ordering {
+        self.v_deadline
+            .cmp(&other.v_deadline)
+            .then_with(|| self.v_runtime.cmp(&other.v_runtime))
+    }
+}
+
+/// The virtual clock of a runqueue.
+#[derive(Debug, Default)]
+pub struct VClock {
+    last_update: Option<Duration>,
+    clk: u128,
+}
+
+impl VClock {
+    /// A clock at virtual time zero.
+    pub const fn new() -> Self {
+        Self {
+            last_update: None,
+            clk: 0,
+        }
+    }
+
+    /// The current virtual time.
+    pub fn now(&self) -> u128 {
+        self.clk
+    }
+
+    /// Whether `entity` may run now.
+    pub fn is_eligible(&self, entity: &EevdfEntity) -> bool {
+        entity.v_eligible.saturating_sub(self.clk) <= VCLOCK_EPSILON
+    }
+
+    /// Fast forward the clk to the specified clock, `new_clk`.
+    pub fn fast_forward(&mut self, new_clk: u128) {
+        self.clk = new_clk;
+    }
+
+    /// Advances the clock to the real time `now`, with `weight` being the
+    /// total weight that was runnable since the last update:
+    /// v += (delta t << VT_FIXED_SHIFT) / sum w.
+    pub fn advance(&mut self, now: Duration, weight: u64) {
+        if let Some(prev) = self.last_update
+            && weight > 0
+        {
+            let delta_vt = to_virtual(now.saturating_sub(prev), weight);
+            self.clk = self.clk.saturating_add(delta_vt);
+        }
+
+        self.last_update = Some(now);
+    }
+}
+
+/// Something the EEVDF queue can hold.
+pub trait EevdfTask {
+    /// The task's standing with the scheduler.
+    fn entity(&self) -> &EevdfEntity;
+}
+
+// Wrapper for the Ineligible Heap (Min-Heap ordered by v_eligible)
+struct ByEligible<T>(T);
+
+impl<T: EevdfTask> PartialEq for ByEligible<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.entity().v_eligible == other.0.entity().v_eligible
+    }
+}
+
+impl<T: EevdfTask> Eq for ByEligible<T> {}
+
+impl<T: EevdfTask> Ord for ByEligible<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.entity().v_eligible.cmp(&self.0.entity().v_eligible)
+    }
+}
+
+impl<T: EevdfTask> PartialOrd for ByEligible<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Wrapper for the Eligible Heap (Min-Heap ordered by deadline)
+struct ByDeadline<T>(T);
+
+impl<T: EevdfTask> PartialEq for ByDeadline<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: EevdfTask> Eq for ByDeadline<T> {}
+
+impl<T: EevdfTask> Ord for ByDeadline<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed so BinaryHeap acts as a MIN-heap
+        other.0.entity().compare(self.0.entity())
+    }
+}
+
+impl<T: EevdfTask> PartialOrd for ByDeadline<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The tasks waiting to run, along with the virtual clock that decides which
+/// of them are eligible.
+pub struct EevdfQueue<T> {
+    ineligible: BinaryHeap<ByEligible<T>>,
+    eligible: BinaryHeap<ByDeadline<T>>,
+    v_clock: VClock,
+}
+
+impl<T: EevdfTask> EevdfQueue<T> {
+    /// An empty queue.
+    pub const fn new() -> Self {
+        Self {
+            ineligible: BinaryHeap::new(),
+            eligible: BinaryHeap::new(),
+            v_clock: VClock::new(),
+        }
+    }
+
+    /// The queue's virtual clock.
+    pub fn v_clock(&self) -> &VClock {
+        &self.v_clock
+    }
+
+    /// See [`VClock::advance`].
+    pub fn advance(&mut self, now: Duration, weight: u64) {
+        self.v_clock.advance(now, weight);
+    }
+
+    /// Queues `task`.
+    pub fn push(&mut self, task: T) {
+        if self.v_clock.is_eligible(task.entity()) {
+            self.eligible.push(ByDeadline(task));
+        } else {
+            self.ineligible.push(ByEligible(task));
+        }
+    }
+
+    /// Takes the eligible task with the earliest deadline. If nothing is
+    /// eligible yet, the clock is fast-forwarded to the first task that will
+    /// be, rather than leaving the CPU idle.
+    pub fn pop(&mut self) -> Option<T> {
+        loop {
+            // Pops any tasks that were ineligible which have become eligible
+            // from the ineligible queue.
+            while let Some(ByEligible(tsk)) = self.ineligible.peek()
+                && self.v_clock.is_eligible(tsk.entity())
+            {
+                let ByEligible(tsk) = self.ineligible.pop()?;
+                self.eligible.push(ByDeadline(tsk));
+            }
+
+            if let Some(ByDeadline(best)) = self.eligible.pop() {
+                return Some(best);
+            }
+
+            // Fast forward logic, if we have non-eligible, don't go idle.
+            // Fast-forward vclk to the next earliest `v_eligible`.
+            let ByEligible(tsk) = self.ineligible.peek()?;
+            let v_eligible = tsk.entity().v_eligible;
+
+            self.v_clock.fast_forward(v_eligible);
+        }
+    }
+
+    /// The queued tasks, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.eligible
+            .iter()
+            .map(|t| &t.0)
+            .chain(self.ineligible.iter().map(|t| &t.0))
+    }
+
+    /// The number of queued tasks.
+    pub fn len(&self) -> usize {
+        self.eligible.len() + self.ineligible.len()
+    }
+
+    /// Whether no tasks are queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: EevdfTask> Default for EevdfQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
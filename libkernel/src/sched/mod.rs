@@ -0,0 +1,9 @@
+//! Scheduling policy.
+//!
+//! The policy is kept apart from the kernel's runqueue so that it can be
+//! driven by the simulator in `sim` and checked on the host.
+
+pub mod eevdf;
+
+#[cfg(test)]
+mod sim;
@@ -0,0 +1,472 @@
+//! A deterministic, host-side simulation of one CPU scheduled by EEVDF.
+//!
+//! Tasks follow a scripted trace on a virtual clock, and the simulator calls
+//! into [`EevdfQueue`] the way the kernel's runqueue does: the running task is
+//! charged and possibly replaced when its slice runs out or it goes to sleep,
+//! a woken task is placed at the current virtual time, and an idle CPU picks
+//! up a woken task straight away.
+//!
+//! Every pick is checked against the EEVDF ordering rules, and the service
+//! each task gets is tracked against what an ideal fluid scheduler would have
+//! given it, so that fairness can be asserted on.
+
+use super::eevdf::{EevdfEntity, EevdfQueue, EevdfTask};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// The kernel's `DEFAULT_TIME_SLICE`.
+pub const SLICE: Duration = Duration::from_millis(4);
+
+/// What a task does once it has arrived.
+#[derive(Clone, Copy, Debug)]
+pub enum Behaviour {
+    /// Never sleeps.
+    CpuBound,
+    /// Runs for `run`, then sleeps for `sleep`, forever.
+    Periodic { run: Duration, sleep: Duration },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TaskSpec {
+    pub weight: u32,
+    pub arrive: Duration,
+    pub behaviour: Behaviour,
+}
+
+impl TaskSpec {
+    pub fn cpu_bound(weight: u32) -> Self {
+        Self {
+            weight,
+            arrive: Duration::ZERO,
+            behaviour: Behaviour::CpuBound,
+        }
+    }
+
+    pub fn arriving_at(self, arrive: Duration) -> Self {
+        Self { arrive, ..self }
+    }
+
+    pub fn periodic(weight: u32, run: Duration, sleep: Duration) -> Self {
+        Self {
+            weight,
+            arrive: Duration::ZERO,
+            behaviour: Behaviour::Periodic { run, sleep },
+        }
+    }
+}
+
+struct SimTask {
+    id: usize,
+    weight: u32,
+    entity: EevdfEntity,
+}
+
+impl EevdfTask for SimTask {
+    fn entity(&self) -> &EevdfEntity {
+        &self.entity
+    }
+}
+
+struct Running {
+    task: SimTask,
+    /// When the task was last charged.
+    exec_start: Duration,
+    /// When the preemption timer fires.
+    slice_end: Duration,
+}
+
+/// A point at which a task was switched to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dispatch {
+    pub at: Duration,
+    pub task: usize,
+}
+
+pub struct Sim {
+    specs: Vec<TaskSpec>,
+    now: Duration,
+    queue: EevdfQueue<SimTask>,
+    running: Option<Running>,
+    total_weight: u64,
+    /// Tasks that haven't arrived yet or are asleep, and when they wake.
+    wake_at: Vec<Option<Duration>>,
+    /// How much longer each periodic task runs before it sleeps.
+    burst_left: Vec<Duration>,
+    /// Whether each task is queued or running.
+    runnable: Vec<bool>,
+    /// CPU time each task has had.
+    service: Vec<Duration>,
+    /// CPU time each task would have had from a fluid scheduler, in ns.
+    ideal: Vec<f64>,
+    /// For each task that has woken but not yet run, when it woke.
+    woken_at: Vec<Option<Duration>>,
+    /// The longest any task has waited to run after waking.
+    pub max_wake_latency: Vec<Duration>,
+    /// The largest difference between any task's service and its ideal.
+    pub max_lag: Duration,
+    pub dispatches: Vec<Dispatch>,
+}
+
+impl Sim {
+    pub fn new(specs: &[TaskSpec]) -> Self {
+        let n = specs.len();
+
+        Self {
+            specs: specs.to_vec(),
+            now: Duration::ZERO,
+            queue: EevdfQueue::new(),
+            running: None,
+            total_weight: 0,
+            wake_at: specs.iter().map(|s| Some(s.arrive)).collect(),
+            burst_left: specs
+                .iter()
+                .map(|s| match s.behaviour {
+                    Behaviour::CpuBound => Duration::MAX,
+                    Behaviour::Periodic { run, .. } => run,
+                })
+                .collect(),
+            runnable: vec![false; n],
+            service: vec![Duration::ZERO; n],
+            ideal: vec![0.0; n],
+            woken_at: vec![None; n],
+            max_wake_latency: vec![Duration::ZERO; n],
+            max_lag: Duration::ZERO,
+            dispatches: Vec::new(),
+        }
+    }
+
+    /// The CPU time `task` has had.
+    pub fn service(&self, task: usize) -> Duration {
+        self.service[task]
+    }
+
+    /// Runs the trace until `end`.
+    pub fn run_until(&mut self, end: Duration) {
+        while self.now < end {
+            let mut next = end;
+
+            if let Some(wake) = self.wake_at.iter().flatten().min() {
+                next = next.min(*wake);
+            }
+
+            if let Some(r) = &self.running {
+                let burst_end = self.now.saturating_add(self.burst_left[r.task.id]);
+
+                next = next.min(r.slice_end).min(burst_end);
+            }
+
+            self.elapse(next);
+            self.events();
+        }
+    }
+
+    /// Moves the clock on to `to`, accounting for the time in between.
+    fn elapse(&mut self, to: Duration) {
+        let dt = to - self.now;
+
+        if let Some(r) = &self.running {
+            let id = r.task.id;
+
+            self.service[id] += dt;
+
+            if self.burst_left[id] != Duration::MAX {
+                self.burst_left[id] -= dt;
+            }
+        }
+
+        let weight: u64 = (0..self.specs.len())
+            .filter(|&i| self.runnable[i])
+            .map(|i| self.specs[i].weight as u64)
+            .sum();
+
+        for i in 0..self.specs.len() {
+            if self.runnable[i] {
+                self.ideal[i] += dt.as_nanos() as f64 * self.specs[i].weight as f64 / weight as f64;
+            }
+
+            let lag = (self.service[i].as_nanos() as f64 - self.ideal[i]).abs();
+            self.max_lag = self.max_lag.max(Duration::from_nanos(lag as u64));
+        }
+
+        self.now = to;
+    }
+
+    fn events(&mut self) {
+        let mut woke = false;
+
+        for id in 0..self.specs.len() {
+            if self.wake_at[id].is_some_and(|t| t <= self.now) {
+                self.wake(id);
+                woke = true;
+            }
+        }
+
+        let preempt = self
+            .running
+            .as_ref()
+            .is_some_and(|r| r.slice_end <= self.now || self.burst_left[r.task.id].is_zero());
+
+        // A woken task only preempts an idle CPU; a running task keeps going
+        // until its next tick.
+        if preempt || (woke && self.running.is_none()) {
+            self.schedule();
+        }
+    }
+
+    /// Mirrors `RunQueue::add_work`.
+    fn wake(&mut self, id: usize) {
+        let weight = self.specs[id].weight;
+        let mut entity = EevdfEntity::default();
+
+        entity.place(self.queue.v_clock().now(), weight, SLICE);
+
+        self.wake_at[id] = None;
+        self.runnable[id] = true;
+        self.woken_at[id] = Some(self.now);
+        self.total_weight += weight as u64;
+        self.queue.push(SimTask { id, weight, entity });
+    }
+
+    /// Mirrors `RunQueue::schedule`.
+    fn schedule(&mut self) {
+        let before = self.queue.v_clock().now();
+        self.queue.advance(self.now, self.total_weight);
+        assert!(
+            self.queue.v_clock().now() >= before,
+            "virtual clock went back"
+        );
+
+        let mut next = None;
+        let prev = self.running.as_ref().map(|r| r.task.id);
+
+        if let Some(mut r) = self.running.take() {
+            let id = r.task.id;
+
+            if self.burst_left[id].is_zero() {
+                // The task goes to sleep.
+                let Behaviour::Periodic { run, sleep } = self.specs[id].behaviour else {
+                    unreachable!()
+                };
+
+                self.burst_left[id] = run;
+                self.wake_at[id] = Some(self.now + sleep);
+                self.runnable[id] = false;
+                self.total_weight -= r.task.weight as u64;
+            } else if r
+                .task
+                .entity
+                .charge(self.now - r.exec_start, r.task.weight, SLICE)
+            {
+                self.queue.push(r.task);
+            } else {
+                r.exec_start = self.now;
+                r.slice_end = self.now + SLICE;
+                next = Some(r);
+            }
+        }
+
+        let next = next.or_else(|| {
+            let task = self.queue.pop()?;
+            self.check_pick(&task);
+
+            Some(Running {
+                task,
+                exec_start: self.now,
+                slice_end: self.now + SLICE,
+            })
+        });
+
+        if let Some(r) = &next {
+            let id = r.task.id;
+
+            if let Some(woken) = self.woken_at[id].take() {
+                let latency = self.now - woken;
+                self.max_wake_latency[id] = self.max_wake_latency[id].max(latency);
+            }
+
+            if prev != Some(id) {
+                self.dispatches.push(Dispatch {
+                    at: self.now,
+                    task: id,
+                });
+            }
+        }
+
+        self.running = next;
+    }
+
+    /// Checks that `picked` is what EEVDF should have picked from the queue.
+    fn check_pick(&self, picked: &SimTask) {
+        let v_clock = self.queue.v_clock();
+
+        assert!(
+            v_clock.is_eligible(&picked.entity),
+            "task {} picked before it was eligible",
+            picked.id
+        );
+
+        for other in self.queue.iter() {
+            if v_clock.is_eligible(&other.entity) {
+                assert!(
+                    picked.entity.compare(&other.entity).is_le(),
+                    "task {} picked over task {} with an earlier deadline",
+                    picked.id,
+                    other.id
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NICE_0: u32 = 1024;
+    const NICE_5: u32 = 335;
+    const NICE_M5: u32 = 3121;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn share(sim: &Sim, task: usize, of: Duration) -> f64 {
+        sim.service(task).as_secs_f64() / of.as_secs_f64()
+    }
+
+    #[test]
+    fn equal_weights_share_evenly() {
+        let mut sim = Sim::new(&[TaskSpec::cpu_bound(NICE_0); 3]);
+
+        sim.run_until(ms(3000));
+
+        for task in 0..3 {
+            assert!((share(&sim, task, ms(3000)) - 1.0 / 3.0).abs() < 0.01);
+        }
+
+        assert!(sim.max_lag <= 2 * SLICE, "lag {:?}", sim.max_lag);
+    }
+
+    #[test]
+    fn weights_set_the_share() {
+        let mut sim = Sim::new(&[
+            TaskSpec::cpu_bound(NICE_0),
+            TaskSpec::cpu_bound(NICE_M5),
+            TaskSpec::cpu_bound(NICE_5),
+        ]);
+        let total = (NICE_0 + NICE_M5 + NICE_5) as f64;
+
+        sim.run_until(ms(5000));
+
+        for (task, weight) in [NICE_0, NICE_M5, NICE_5].into_iter().enumerate() {
+            let want = weight as f64 / total;
+            assert!((share(&sim, task, ms(5000)) - want).abs() < 0.01);
+        }
+
+        assert!(sim.max_lag <= 2 * SLICE, "lag {:?}", sim.max_lag);
+    }
+
+    #[test]
+    fn late_arrival_neither_starves_nor_catches_up() {
+        let mut sim = Sim::new(&[
+            TaskSpec::cpu_bound(NICE_0),
+            TaskSpec::cpu_bound(NICE_0),
+            TaskSpec::cpu_bound(NICE_0).arriving_at(ms(1000)),
+        ]);
+
+        sim.run_until(ms(1000));
+        let before = [sim.service(0), sim.service(1)];
+        sim.run_until(ms(4000));
+
+        // The newcomer runs within a slice of arriving...
+        assert!(sim.max_wake_latency[2] <= SLICE);
+
+        // ...and from then on the three share the CPU evenly, with no credit
+        // for the time before it arrived.
+        for (task, before) in before.into_iter().enumerate() {
+            let after = sim.service(task) - before;
+            assert!((after.as_secs_f64() / 3.0 - 1.0 / 3.0).abs() < 0.01);
+        }
+
+        assert!((share(&sim, 2, ms(3000)) - 1.0 / 3.0).abs() < 0.01);
+        assert!(sim.max_lag <= 2 * SLICE, "lag {:?}", sim.max_lag);
+    }
+
+    #[test]
+    fn sleeper_gets_its_demand_promptly() {
+        let mut sim = Sim::new(&[
+            TaskSpec::cpu_bound(NICE_0),
+            TaskSpec::cpu_bound(NICE_0),
+            TaskSpec::periodic(NICE_0, ms(1), ms(9)),
+        ]);
+
+        sim.run_until(ms(2000));
+
+        // Wakeups don't preempt, so the sleeper can wait out the rest of a
+        // hog's slice and then lose one more pick to the other hog's earlier
+        // deadline, but no more than that.
+        assert!(
+            sim.max_wake_latency[2] <= 2 * SLICE,
+            "latency {:?}",
+            sim.max_wake_latency[2]
+        );
+
+        // It wants less than its fair share, so it gets what it asks for,
+        // give or take that latency.
+        let worst = 1.0 / (1.0 + 9.0 + 2.0 * SLICE.as_millis() as f64);
+        assert!(share(&sim, 2, ms(2000)) >= worst);
+
+        // The hogs split the rest.
+        let diff = sim.service(0).abs_diff(sim.service(1));
+        assert!(diff <= 2 * SLICE, "hogs differ by {diff:?}");
+    }
+
+    #[test]
+    fn cpu_idles_until_first_arrival() {
+        let mut sim = Sim::new(&[
+            TaskSpec::cpu_bound(NICE_0).arriving_at(ms(50)),
+            TaskSpec::periodic(NICE_0, ms(2), ms(100)).arriving_at(ms(20)),
+        ]);
+
+        sim.run_until(ms(60));
+
+        // Nothing runs before 20ms, and the CPU idles again from 22ms until
+        // the second task turns up.
+        assert_eq!(
+            sim.dispatches,
+            [
+                Dispatch {
+                    at: ms(20),
+                    task: 1
+                },
+                Dispatch {
+                    at: ms(50),
+                    task: 0
+                },
+            ]
+        );
+        assert_eq!(sim.service(1), ms(2));
+        assert_eq!(sim.service(0), ms(10));
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        let trace = [
+            TaskSpec::cpu_bound(NICE_0),
+            TaskSpec::cpu_bound(NICE_M5).arriving_at(ms(37)),
+            TaskSpec::periodic(NICE_5, ms(3), ms(7)),
+            TaskSpec::periodic(NICE_0, ms(1), ms(13)).arriving_at(ms(5)),
+        ];
+
+        let run = || {
+            let mut sim = Sim::new(&trace);
+            sim.run_until(ms(1000));
+            sim.dispatches
+        };
+
+        let first = run();
+
+        assert!(first.len() > 100);
+        assert_eq!(first, run());
+    }
+}
@@ -140,6 +140,22 @@ impl<T: Send, CPU: CpuOps> PerCpu<T, CPU> {
         unsafe { self.get_for_cpu(cpu_id) }
     }
 
+    /// Returns a reference to the underlying data for the current CPU, or
+    /// `None` if the `PerCpu` variable has not been initialized yet.
+    ///
+    /// This is for code that can run before [`setup_percpu`], such as the
+    /// kernel heap.
+    pub fn try_get(&self) -> Option<&T> {
+        let base_ptr = self.ptr.load(Ordering::Acquire);
+
+        if base_ptr.is_null() {
+            return None;
+        }
+
+        // SAFETY: `init` guarantees the allocation is valid for every CPU id.
+        Some(unsafe { &*base_ptr.add(CPU::id()) })
+    }
+
     /// Returns a reference to the underlying data for a different CPU.
     ///
     /// # Safety
@@ -365,6 +381,18 @@ mod tests {
         let _ = data.borrow();
     }
 
+    #[test]
+    fn test_try_get_before_and_after_init() {
+        let data: PerCpu<_, MockArch> = PerCpu::new(|| 7u32);
+        MOCK_CPU_ID.with(|id| id.set(1));
+
+        assert!(data.try_get().is_none());
+
+        data.init(2);
+
+        assert_eq!(data.try_get(), Some(&7));
+    }
+
     #[test]
     #[should_panic(expected = "PerCpu::init called more than once")]
     fn test_panic_on_double_init() {
@@ -0,0 +1,96 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    error::{IoError, Result},
+    fs::BlockDevice,
+};
+
+/// A block device backed by a `Vec`, counting the I/O done to it.
+///
+/// Share it through an `Arc` to look at the contents and counters after
+/// handing it to the code under test.
+pub struct MockBlockDevice {
+    data: Mutex<Vec<u8>>,
+    block_size: usize,
+    reads: AtomicUsize,
+    writes: AtomicUsize,
+}
+
+impl MockBlockDevice {
+    /// A device holding `data`, which must be a whole number of blocks.
+    pub fn new(data: Vec<u8>, block_size: usize) -> Self {
+        assert!(data.len().is_multiple_of(block_size));
+
+        Self {
+            data: Mutex::new(data),
+            block_size,
+            reads: AtomicUsize::new(0),
+            writes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.data.lock().unwrap().clone()
+    }
+
+    /// How many `read` calls have been made.
+    pub fn reads(&self) -> usize {
+        self.reads.load(Ordering::SeqCst)
+    }
+
+    /// How many `write` calls have been made.
+    pub fn writes(&self) -> usize {
+        self.writes.load(Ordering::SeqCst)
+    }
+
+    fn range(&self, block_id: u64, len: usize) -> Result<std::ops::Range<usize>> {
+        assert!(len.is_multiple_of(self.block_size));
+
+        let start = block_id as usize * self.block_size;
+        let end = start + len;
+
+        if end > self.data.lock().unwrap().len() {
+            return Err(IoError::OutOfBounds.into());
+        }
+
+        Ok(start..end)
+    }
+}
+
+#[async_trait]
+impl BlockDevice for MockBlockDevice {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+
+        let range = self.range(block_id, buf.len())?;
+        buf.copy_from_slice(&self.data.lock().unwrap()[range]);
+
+        Ok(())
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        self.writes.fetch_add(1, Ordering::SeqCst);
+
+        let range = self.range(block_id, buf.len())?;
+        self.data.lock().unwrap()[range].copy_from_slice(buf);
+
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn num_blocks(&self) -> u64 {
+        (self.data.lock().unwrap().len() / self.block_size) as u64
+    }
+
+    async fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
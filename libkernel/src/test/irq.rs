@@ -0,0 +1,47 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+#[derive(Default)]
+struct Line {
+    raised: AtomicBool,
+    count: AtomicUsize,
+}
+
+/// A level-triggered interrupt line. Clones share the line, so a device model
+/// can drive it while the test watches.
+#[derive(Clone, Default)]
+pub struct MockIrq(Arc<Line>);
+
+impl MockIrq {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drives the line to `level`.
+    pub fn set(&self, level: bool) {
+        let was = self.0.raised.swap(level, Ordering::SeqCst);
+
+        if level && !was {
+            self.0.count.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    pub fn raise(&self) {
+        self.set(true);
+    }
+
+    pub fn lower(&self) {
+        self.set(false);
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.0.raised.load(Ordering::SeqCst)
+    }
+
+    /// How many times the line has been raised.
+    pub fn count(&self) -> usize {
+        self.0.count.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::driver::mmio::Mmio;
+
+/// The contents of a [`MockMmio`]. Registers that were never written read as
+/// zero.
+#[derive(Default)]
+pub struct MockRegs(BTreeMap<usize, u32>);
+
+impl MockRegs {
+    pub fn get(&self, offset: usize) -> u32 {
+        self.0.get(&offset).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, offset: usize, val: u32) {
+        self.0.insert(offset, val);
+    }
+}
+
+type WriteHook = Box<dyn FnMut(&mut MockRegs, usize, u32) + Send>;
+
+#[derive(Default)]
+struct State {
+    regs: MockRegs,
+    writes: Vec<(usize, u32)>,
+    on_write: Option<WriteHook>,
+}
+
+/// A register block in host memory that records what the driver writes.
+///
+/// By default a write just stores the value. Registers with side effects
+/// (write-one-to-clear, load registers, doorbells) are modelled with
+/// [`Self::on_write`], and the test plays the device by setting registers
+/// directly with [`Self::set`].
+#[derive(Default)]
+pub struct MockMmio {
+    state: Mutex<State>,
+}
+
+impl MockMmio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Replaces the default store with `hook`, which decides what a driver's
+    /// write does to the registers.
+    pub fn on_write(&self, hook: impl FnMut(&mut MockRegs, usize, u32) + Send + 'static) {
+        self.state().on_write = Some(Box::new(hook));
+    }
+
+    /// Reads a register without going through the driver's accessors.
+    pub fn get(&self, offset: usize) -> u32 {
+        self.state().regs.get(offset)
+    }
+
+    /// Sets a register as the device would. This isn't recorded as a write.
+    pub fn set(&self, offset: usize, val: u32) {
+        self.state().regs.set(offset, val);
+    }
+
+    /// Every write the driver made, oldest first.
+    pub fn writes(&self) -> Vec<(usize, u32)> {
+        self.state().writes.clone()
+    }
+}
+
+impl Mmio for MockMmio {
+    fn read32(&self, offset: usize) -> u32 {
+        assert!(offset.is_multiple_of(4), "unaligned read at {offset:#x}");
+
+        self.get(offset)
+    }
+
+    fn write32(&self, offset: usize, val: u32) {
+        assert!(offset.is_multiple_of(4), "unaligned write at {offset:#x}");
+
+        let mut state = self.state();
+        let State {
+            regs,
+            writes,
+            on_write,
+        } = &mut *state;
+
+        writes.push((offset, val));
+
+        match on_write {
+            Some(hook) => hook(regs, offset, val),
+            None => regs.set(offset, val),
+        }
+    }
+}
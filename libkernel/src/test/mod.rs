@@ -0,0 +1,45 @@
+//! Mocks for unit-testing kernel code on the host.
+//!
+//! Besides [`MockCpuOps`] for the synchronisation primitives, this provides
+//! stand-ins for the hardware that drivers talk to: a register block
+//! ([`MockMmio`]), an interrupt line ([`MockIrq`]) and a block device
+//! ([`MockBlockDevice`]).
+
+use core::hint::spin_loop;
+
+use crate::CpuOps;
+
+#[cfg(feature = "fs")]
+mod blk;
+mod irq;
+mod mmio;
+
+#[cfg(feature = "fs")]
+pub use blk::MockBlockDevice;
+pub use irq::MockIrq;
+pub use mmio::{MockMmio, MockRegs};
+
+// A CPU mock object that can be used in unit-tests.
+pub struct MockCpuOps {}
+
+impl CpuOps for MockCpuOps {
+    type InterruptFlags = usize;
+
+    fn id() -> usize {
+        0
+    }
+
+    fn halt() -> ! {
+        loop {
+            spin_loop();
+        }
+    }
+
+    fn disable_interrupts() -> usize {
+        0
+    }
+
+    fn restore_interrupt_state(_flags: usize) {}
+
+    fn enable_interrupts() {}
+}
@@ -16,6 +16,7 @@ parser.add_argument("elf_executable", help="Location of compiled ELF executable
 # QEMU options
 parser.add_argument("--init", default="/bin/sh", help="Location of the init process (in the rootfs)")
 parser.add_argument("--rootfs", default="moss.img", help="Location of the root filesystem image to use")
+parser.add_argument("--rootfs-type", default="ext4fs", help="Filesystem driver to mount the root image with (e.g. iso9660fs)")
 parser.add_argument("--cpu", default="cortex-a72")
 parser.add_argument("--smp", default=4, help="Number of CPU cores to use")
 parser.add_argument("--memory", default="2G")
@@ -49,7 +50,7 @@ default_args = {
     "-nographic": None,
     "-s": None,
     "-kernel": bin_executable_location,
-    "-append": f"{append_args} --rootfs=ext4fs --automount=/dev,devfs --automount=/tmp,tmpfs --automount=/proc,procfs --automount=/sys,sysfs",
+    "-append": f"{append_args} --rootfs={args.rootfs_type} --automount=/dev,devfs --automount=/tmp,tmpfs --automount=/proc,procfs --automount=/sys,sysfs",
 }
 
 # Arguments that can appear multiple times (e.g. -device)
@@ -0,0 +1,48 @@
+//! Frame-pointer based stack unwinding.
+
+use super::boot::memory::{KERNEL_STACK_AREA, KERNEL_STACK_SZ};
+use core::arch::asm;
+use libkernel::memory::address::VA;
+
+/// Walks the chain of frame records on the current kernel stack, storing the
+/// return address of each frame into `frames`. Returns the number of
+/// addresses stored.
+///
+/// Each record is validated before it's read, so a missing or corrupt frame
+/// pointer ends the walk rather than faulting.
+#[inline(never)]
+pub fn backtrace(frames: &mut [usize]) -> usize {
+    let mut fp: usize;
+
+    unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack)) };
+
+    // Kernel stacks are naturally aligned, so a frame record can't lie above
+    // the top of the stack that holds the first one.
+    let stack_top = (fp & !(KERNEL_STACK_SZ - 1)).wrapping_add(KERNEL_STACK_SZ);
+    let mut depth = 0;
+
+    while depth < frames.len()
+        && fp.is_multiple_of(16)
+        && KERNEL_STACK_AREA.contains_address(VA::from_value(fp))
+        && fp + 16 <= stack_top
+    {
+        // SAFETY: `fp` points at a frame record within a mapped kernel stack.
+        let (next_fp, lr) = unsafe { (*(fp as *const usize), *((fp + 8) as *const usize)) };
+
+        if lr == 0 {
+            break;
+        }
+
+        frames[depth] = lr;
+        depth += 1;
+
+        // The stack grows down, so the caller's record is always higher.
+        if next_fp <= fp {
+            break;
+        }
+
+        fp = next_fp;
+    }
+
+    depth
+}
@@ -21,7 +21,7 @@ use libkernel::{
 use log::info;
 
 const KERNEL_STACK_SHIFT: usize = 15; // 32KiB.
-const KERNEL_STACK_SZ: usize = 1 << KERNEL_STACK_SHIFT;
+pub const KERNEL_STACK_SZ: usize = 1 << KERNEL_STACK_SHIFT;
 pub const KERNEL_STACK_PG_ORDER: usize = (KERNEL_STACK_SZ / PAGE_SIZE).ilog2() as usize;
 
 pub const KERNEL_STACK_AREA: VirtMemoryRegion = VirtMemoryRegion::from_start_end_address(
@@ -102,15 +102,15 @@ pub fn setup_allocator(dtb_ptr: TPA<u8>, image_start: PA, image_end: PA) -> Resu
     Ok(())
 }
 
-pub fn allocate_kstack_region() -> VirtMemoryRegion {
-    // Start allocating stacks at the second valid stack slot in
-    // `KERNEL_STACK_AREA`. This ensures that the faulting address of a stack
-    // overflow on the first allocated stack would still lie withing
-    // `KERNEL_STACK_AREA`.
-    static mut CURRENT_VA: VA = KERNEL_STACK_AREA
-        .start_address()
-        .add_bytes(KERNEL_STACK_SZ * 2);
+// Start allocating stacks at the second valid stack slot in
+// `KERNEL_STACK_AREA`. This ensures that the faulting address of a stack
+// overflow on the first allocated stack would still lie withing
+// `KERNEL_STACK_AREA`.
+static mut CURRENT_VA: VA = KERNEL_STACK_AREA
+    .start_address()
+    .add_bytes(KERNEL_STACK_SZ * 2);
 
+pub fn allocate_kstack_region() -> VirtMemoryRegion {
     let range = VirtMemoryRegion::new(unsafe { CURRENT_VA }, KERNEL_STACK_SZ);
 
     // Add a guard region between allocations, this ensures that the
@@ -121,6 +121,17 @@ pub fn allocate_kstack_region() -> VirtMemoryRegion {
     range
 }
 
+/// Returns every kernel stack handed out by [`allocate_kstack_region`] so far.
+pub fn kstack_regions() -> impl Iterator<Item = VirtMemoryRegion> {
+    // SAFETY: Stacks are only handed out, and mapped, while CPUs are brought
+    // up during boot.
+    let end = unsafe { CURRENT_VA };
+
+    (KERNEL_STACK_AREA.start_address().value() + KERNEL_STACK_SZ * 2..end.value())
+        .step_by(KERNEL_STACK_SZ * 2)
+        .map(|addr| VirtMemoryRegion::new(VA::from_value(addr), KERNEL_STACK_SZ))
+}
+
 // Returns the address that should be loaded into the SP.
 pub fn setup_stack_and_heap(pgtbl_base: TPA<PgTableArray<L0Table>>) -> Result<VA> {
     let mut alloc = INITAL_ALLOCATOR.lock_save_irq();
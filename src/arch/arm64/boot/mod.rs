@@ -8,21 +8,27 @@ use super::{
     proc::vdso::vdso_init,
 };
 use crate::drivers::timer::kick_current_cpu;
+#[cfg(feature = "kasan")]
+use crate::memory::kasan;
 use crate::{
     arch::{ArchImpl, arm64::exceptions::exceptions_init},
-    console::setup_console_logger,
+    console::{setup_console_logger, setup_from_cmdline},
     drivers::{
-        fdt_prober::{probe_for_fdt_devices, set_fdt_va},
-        init::run_initcalls,
+        fdt_prober::{get_fdt, probe_for_fdt_devices, set_fdt_va},
+        init::{InitLevel, run_initcalls, start_async_probes},
     },
     interrupts::{cpu_messenger::cpu_messenger_init, get_interrupt_root},
+    kernel::{
+        rand::{add_bootloader_entropy, early_random_u64},
+        stack_protector::stack_guard,
+    },
     kmain,
     memory::{INITAL_ALLOCATOR, PAGE_ALLOC},
     sched::{sched_init_secondary, uspc_ret::dispatch_userspace_task},
 };
 use aarch64_cpu::{
     asm::{self, barrier},
-    registers::{ReadWriteable, SCTLR_EL1, TCR_EL1, TTBR0_EL1},
+    registers::{CNTPCT_EL0, ReadWriteable, Readable, SCTLR_EL1, TCR_EL1, TTBR0_EL1},
 };
 use core::arch::global_asm;
 use libkernel::{
@@ -31,7 +37,10 @@ use libkernel::{
     error::Result,
     memory::{
         address::{PA, TPA, VA},
-        allocators::{phys::FrameAllocator, slab::allocator::SlabAllocator},
+        allocators::{
+            phys::FrameAllocator,
+            slab::{allocator::SlabAllocator, set_slab_secret},
+        },
         paging::PgTableArray,
     },
     sync::per_cpu::setup_percpu,
@@ -94,6 +103,26 @@ fn arch_init_stage1(
     .unwrap_or_else(|_| park_cpu())
 }
 
+/// Picks the per-boot secrets that harden the kernel against memory
+/// corruption, seeding the entropy pool from the device tree first.
+///
+/// Called by the boot assembly between the two init stages. The slab secret
+/// is set here, before the slab allocator exists; the returned stack guard is
+/// installed by the assembly itself, since any Rust frame live when it
+/// changed would fail its check on return.
+#[unsafe(no_mangle)]
+extern "C" fn arch_init_secrets() -> u64 {
+    if let Some(chosen) = get_fdt().find_nodes("/chosen").next()
+        && let Some(seed) = chosen.find_property("rng-seed")
+    {
+        add_bootloader_entropy(seed.raw_value());
+    }
+
+    set_slab_secret(early_random_u64(CNTPCT_EL0.get()));
+
+    stack_guard(early_random_u64(CNTPCT_EL0.get()))
+}
+
 #[unsafe(no_mangle)]
 fn arch_init_stage2(frame: *mut ExceptionState) -> *mut ExceptionState {
     // Save the ID map addr for booting secondaries.
@@ -104,22 +133,31 @@ fn arch_init_stage2(frame: *mut ExceptionState) -> *mut ExceptionState {
     barrier::isb(barrier::SY);
 
     // We now have enough memory setup to switch to the real page allocator.
-    let smalloc = INITAL_ALLOCATOR
+    #[cfg_attr(not(feature = "kasan"), allow(unused_mut))]
+    let mut smalloc = INITAL_ALLOCATOR
         .lock_save_irq()
         .take()
         .expect("Smalloc should not have been taken yet");
 
+    #[cfg(feature = "kasan")]
+    let shadow = kasan::reserve_shadow(&mut smalloc);
+
     let (page_alloc, frame_list) = unsafe { FrameAllocator::init(smalloc) };
 
     if PAGE_ALLOC.set(page_alloc).is_err() {
         panic!("Cannot setup physical memory allocator");
     }
 
+    #[cfg(feature = "kasan")]
+    kasan::init(shadow);
+
     if SLAB_ALLOC.set(SlabAllocator::new(frame_list)).is_err() {
         panic!("Cannot setup slab allocator");
     }
 
-    KernelHeap::init_for_this_cpu();
+    // With the heap up, consoles named on the command line can be set up
+    // ahead of the devices being probed.
+    setup_from_cmdline(&super::fdt::get_cmdline().unwrap_or_default());
 
     // Don't trap wfi/wfe in el0.
     SCTLR_EL1.modify(SCTLR_EL1::NTWE::DontTrap + SCTLR_EL1::NTWI::DontTrap);
@@ -127,11 +165,18 @@ fn arch_init_stage2(frame: *mut ExceptionState) -> *mut ExceptionState {
     exceptions_init().expect("Failed to initialize exceptions");
     ArchImpl::enable_interrupts();
 
-    unsafe { run_initcalls() };
+    for level in InitLevel::BEFORE_PROBE {
+        unsafe { run_initcalls(level) };
+    }
     probe_for_fdt_devices();
+    unsafe { run_initcalls(InitLevel::Late) };
 
     unsafe { setup_percpu(cpu_count()) };
 
+    // Until now the heap has used the locked slab allocator directly, give
+    // this CPU its own cache.
+    KernelHeap::init_for_this_cpu();
+
     cpu_messenger_init(cpu_count());
 
     if let Err(e) = vdso_init() {
@@ -144,6 +189,9 @@ fn arch_init_stage2(frame: *mut ExceptionState) -> *mut ExceptionState {
 
     boot_secondaries();
 
+    // With every CPU up, the probes that can run in parallel do.
+    start_async_probes();
+
     // Prove that we can send IPIs through the messenger.
     frame
 }
@@ -49,7 +49,7 @@ _start:
 2:  adr_r   x0, __boot_stack      // Address of early boot stack
     mov     sp, x0                // Set stack pointer
 
-    // Sanitize TPIDR_EL1 so Rust allocators know per-cpu data isn't ready.
+    // Sanitize TPIDR_EL1 before anything can read it.
     msr     tpidr_el1, xzr
 
     // Transition to EL1 (if not already there)
@@ -75,6 +75,11 @@ _start:
     // Update SP to the highmem kernel stack returned from stage1
 1:  mov     sp, x0
 
+    // Install this boot's stack canary while no Rust frame is live.
+    bl      arch_init_secrets
+    adr_r   x1, __stack_chk_guard
+    str     x0, [x1]
+
     // Allocate a context switch frame
     sub     sp, sp, #(16 * 18)
 
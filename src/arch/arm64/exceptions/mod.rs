@@ -22,6 +22,7 @@ use libkernel::{
         region::{PhysMemoryRegion, VirtMemoryRegion},
     },
 };
+pub use syscall::SYSCALLS;
 use syscall::handle_syscall;
 use tock_registers::interfaces::Writeable;
 
@@ -146,6 +147,30 @@ extern "C" fn el1_serror_spx(state: &mut ExceptionState) {
     default_handler(state);
 }
 
+/// Runs system call `nr` for the current kernel thread, as if it had trapped
+/// in from userspace with `args` in its registers.
+#[cfg(feature = "bench")]
+pub async fn kernel_syscall(mut ctx: ProcessCtx, nr: usize, args: [usize; 6]) -> isize {
+    {
+        let regs = ctx.task_mut().ctx.user_mut();
+        regs.x[8] = nr as u64;
+
+        for (reg, arg) in regs.x.iter_mut().zip(args) {
+            *reg = arg as u64;
+        }
+    }
+
+    // SAFETY: `ctx` isn't touched again until the syscall has finished with
+    // its copy.
+    handle_syscall(unsafe { ctx.clone() }).await;
+
+    // A kernel thread never leaves the kernel, so all its time stays system
+    // time.
+    ctx.task_mut().in_syscall = true;
+
+    ctx.task().ctx.user().x[0] as isize
+}
+
 #[unsafe(no_mangle)]
 extern "C" fn el0_sync(state_ptr: *mut ExceptionState) -> *const ExceptionState {
     // SAFETY: Since we've just entered form EL0, there *cannot* be another
@@ -0,0 +1,121 @@
+//! The `AT_HWCAP` and `AT_HWCAP2` bits passed to new programs, decoded from
+//! the CPU's ID registers as Linux does.
+//!
+//! Only features that need nothing from the kernel are reported: SVE and
+//! pointer authentication are left out, since they aren't enabled for EL0.
+
+use aarch64_cpu::registers::{ID_AA64ISAR0_EL1, ID_AA64ISAR1_EL1, ID_AA64PFR0_EL1, Readable};
+
+const HWCAP_FP: u64 = 1 << 0;
+const HWCAP_ASIMD: u64 = 1 << 1;
+const HWCAP_AES: u64 = 1 << 3;
+const HWCAP_PMULL: u64 = 1 << 4;
+const HWCAP_SHA1: u64 = 1 << 5;
+const HWCAP_SHA2: u64 = 1 << 6;
+const HWCAP_CRC32: u64 = 1 << 7;
+const HWCAP_ATOMICS: u64 = 1 << 8;
+const HWCAP_FPHP: u64 = 1 << 9;
+const HWCAP_ASIMDHP: u64 = 1 << 10;
+const HWCAP_ASIMDRDM: u64 = 1 << 12;
+const HWCAP_JSCVT: u64 = 1 << 13;
+const HWCAP_FCMA: u64 = 1 << 14;
+const HWCAP_LRCPC: u64 = 1 << 15;
+const HWCAP_DCPOP: u64 = 1 << 16;
+const HWCAP_SHA3: u64 = 1 << 17;
+const HWCAP_SM3: u64 = 1 << 18;
+const HWCAP_SM4: u64 = 1 << 19;
+const HWCAP_ASIMDDP: u64 = 1 << 20;
+const HWCAP_SHA512: u64 = 1 << 21;
+const HWCAP_ASIMDFHM: u64 = 1 << 23;
+const HWCAP_DIT: u64 = 1 << 24;
+const HWCAP_ILRCPC: u64 = 1 << 26;
+const HWCAP_FLAGM: u64 = 1 << 27;
+const HWCAP_SB: u64 = 1 << 29;
+
+const HWCAP2_DCPODP: u64 = 1 << 0;
+const HWCAP2_FLAGM2: u64 = 1 << 7;
+const HWCAP2_FRINT: u64 = 1 << 8;
+const HWCAP2_I8MM: u64 = 1 << 13;
+const HWCAP2_BF16: u64 = 1 << 14;
+const HWCAP2_DGH: u64 = 1 << 15;
+const HWCAP2_RNG: u64 = 1 << 16;
+
+/// Returns the 4-bit ID register field at `shift`.
+fn field(reg: u64, shift: u32) -> u64 {
+    (reg >> shift) & 0xf
+}
+
+/// Returns the `(AT_HWCAP, AT_HWCAP2)` bits of the current CPU.
+pub fn hwcaps() -> (u64, u64) {
+    let isar0 = ID_AA64ISAR0_EL1.get();
+    let isar1 = ID_AA64ISAR1_EL1.get();
+    let pfr0 = ID_AA64PFR0_EL1.get();
+
+    let mut hwcap = 0;
+
+    // For FP and AdvSIMD, 0xf means not implemented and 1 adds half
+    // precision.
+    match field(pfr0, 16) {
+        0 => hwcap |= HWCAP_FP,
+        1 => hwcap |= HWCAP_FP | HWCAP_FPHP,
+        _ => {}
+    }
+
+    match field(pfr0, 20) {
+        0 => hwcap |= HWCAP_ASIMD,
+        1 => hwcap |= HWCAP_ASIMD | HWCAP_ASIMDHP,
+        _ => {}
+    }
+
+    if field(pfr0, 48) >= 1 {
+        hwcap |= HWCAP_DIT;
+    }
+
+    let isar0_caps = [
+        (4, 1, HWCAP_AES),
+        (4, 2, HWCAP_PMULL),
+        (8, 1, HWCAP_SHA1),
+        (12, 1, HWCAP_SHA2),
+        (12, 2, HWCAP_SHA512),
+        (16, 1, HWCAP_CRC32),
+        (20, 2, HWCAP_ATOMICS),
+        (28, 1, HWCAP_ASIMDRDM),
+        (32, 1, HWCAP_SHA3),
+        (36, 1, HWCAP_SM3),
+        (40, 1, HWCAP_SM4),
+        (44, 1, HWCAP_ASIMDDP),
+        (48, 1, HWCAP_ASIMDFHM),
+        (52, 1, HWCAP_FLAGM),
+    ];
+
+    let isar1_caps = [
+        (0, 1, HWCAP_DCPOP),
+        (12, 1, HWCAP_JSCVT),
+        (16, 1, HWCAP_FCMA),
+        (20, 1, HWCAP_LRCPC),
+        (20, 2, HWCAP_ILRCPC),
+        (36, 1, HWCAP_SB),
+    ];
+
+    let isar0_caps2 = [(52, 2, HWCAP2_FLAGM2), (60, 1, HWCAP2_RNG)];
+
+    let isar1_caps2 = [
+        (0, 2, HWCAP2_DCPODP),
+        (32, 1, HWCAP2_FRINT),
+        (44, 1, HWCAP2_BF16),
+        (48, 1, HWCAP2_DGH),
+        (52, 1, HWCAP2_I8MM),
+    ];
+
+    // Each feature is present from a minimum value of its field up.
+    let decode = |reg: u64, caps: &[(u32, u64, u64)]| {
+        caps.iter()
+            .filter(|&&(shift, min, _)| field(reg, shift) >= min)
+            .fold(0, |acc, &(_, _, cap)| acc | cap)
+    };
+
+    hwcap |= decode(isar0, &isar0_caps) | decode(isar1, &isar1_caps);
+    let hwcap2 = decode(isar0, &isar0_caps2) | decode(isar1, &isar1_caps2);
+
+    (hwcap, hwcap2)
+}
@@ -12,13 +12,16 @@ use alloc::vec::Vec;
 use libkernel::{
     arch::arm64::memory::{
         pg_descriptors::{L3Descriptor, MemoryType},
-        pg_tables::{L0Table, MapAttributes, MappingContext, map_range},
+        pg_tables::{
+            L0Table, MapAttributes, MappingContext, is_block_unmapped, map_range,
+            split_block_mappings, take_empty_table,
+        },
         pg_tear_down::tear_down_address_space,
-        pg_walk::{get_pte, walk_and_modify_region},
+        pg_walk::{translate, walk_and_modify_region},
     },
     error::{KernelError, MapError, Result},
     memory::{
-        PAGE_SIZE,
+        HUGE_PAGE_SIZE, PAGE_SIZE,
         address::{TPA, VA},
         page::PageFrame,
         paging::{
@@ -38,6 +41,32 @@ pub struct Arm64ProcessAddressSpace {
 unsafe impl Send for Arm64ProcessAddressSpace {}
 unsafe impl Sync for Arm64ProcessAddressSpace {}
 
+impl Arm64ProcessAddressSpace {
+    /// Splits any huge page mappings overlapping `region` into page mappings,
+    /// so that the page tables can be walked at page granularity.
+    fn split_huge_pages(&mut self, region: VirtMemoryRegion) -> Result<()> {
+        let mut ctx = MappingContext {
+            allocator: &mut PageTableAllocator::new(),
+            mapper: &mut PageOffsetPgTableMapper {},
+            invalidator: &AllEl0TlbInvalidator::new(),
+        };
+
+        split_block_mappings(self.l0_table, region, &mut ctx, |block| {
+            // SAFETY: The block was mapped by `map_huge_page`, which owned a
+            // reference on the allocation.
+            let alloc = unsafe { PAGE_ALLOC.get().unwrap().alloc_from_region(block) };
+
+            // Each page mapping now holds its own reference; the block's
+            // reference is handed over to the first one.
+            for _ in 1..block.size() / PAGE_SIZE {
+                alloc.clone().leak();
+            }
+
+            alloc.leak();
+        })
+    }
+}
+
 impl UserAddressSpace for Arm64ProcessAddressSpace {
     fn new() -> Result<Self>
     where
@@ -81,11 +110,65 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
         )
     }
 
+    fn map_huge_page(&mut self, page: PageFrame, va: VA, perms: PtePermissions) -> Result<()> {
+        if !page.pa().value().is_multiple_of(HUGE_PAGE_SIZE) {
+            Err(MapError::PhysNotAligned)?;
+        }
+
+        if !va.value().is_multiple_of(HUGE_PAGE_SIZE) {
+            Err(MapError::VirtNotAligned)?;
+        }
+
+        // An L3 table left behind by earlier page mappings would stop the
+        // block from being installed.
+        let empty_table = take_empty_table(
+            self.l0_table,
+            va,
+            &mut WalkContext {
+                mapper: &mut PageOffsetPgTableMapper {},
+                invalidator: &AllEl0TlbInvalidator::new(),
+            },
+        )?;
+
+        // The TLB has been invalidated by now, so nothing refers to the table.
+        if let Some(table) = empty_table {
+            drop(unsafe {
+                PAGE_ALLOC
+                    .get()
+                    .unwrap()
+                    .alloc_from_region(PhysMemoryRegion::new(table.to_untyped(), PAGE_SIZE))
+            });
+        }
+
+        let mut ctx = MappingContext {
+            allocator: &mut PageTableAllocator::new(),
+            mapper: &mut PageOffsetPgTableMapper {},
+            invalidator: &AllEl0TlbInvalidator::new(),
+        };
+
+        map_range(
+            self.l0_table,
+            MapAttributes {
+                phys: PhysMemoryRegion::new(page.pa(), HUGE_PAGE_SIZE),
+                virt: VirtMemoryRegion::new(va, HUGE_PAGE_SIZE),
+                mem_type: MemoryType::Normal,
+                perms,
+            },
+            &mut ctx,
+        )
+    }
+
+    fn can_map_huge_page(&self, va: VA) -> bool {
+        is_block_unmapped(self.l0_table, va, &mut PageOffsetPgTableMapper {}).unwrap_or(false)
+    }
+
     fn unmap(&mut self, _va: VA) -> Result<PageFrame> {
         todo!()
     }
 
     fn protect_range(&mut self, va_range: VirtMemoryRegion, perms: PtePermissions) -> Result<()> {
+        self.split_huge_pages(va_range)?;
+
         let mut walk_ctx = WalkContext {
             mapper: &mut PageOffsetPgTableMapper {},
             invalidator: &AllEl0TlbInvalidator::new(),
@@ -100,6 +183,8 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
     }
 
     fn unmap_range(&mut self, va_range: VirtMemoryRegion) -> Result<Vec<PageFrame>> {
+        self.split_huge_pages(va_range)?;
+
         let mut walk_ctx = WalkContext {
             mapper: &mut PageOffsetPgTableMapper {},
             invalidator: &AllEl0TlbInvalidator::new(),
@@ -118,6 +203,8 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
     }
 
     fn remap(&mut self, va: VA, new_page: PageFrame, perms: PtePermissions) -> Result<PageFrame> {
+        self.split_huge_pages(va.page_region())?;
+
         let mut walk_ctx = WalkContext {
             mapper: &mut PageOffsetPgTableMapper {},
             invalidator: &AllEl0TlbInvalidator::new(),
@@ -137,16 +224,12 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
     }
 
     fn translate(&self, va: VA) -> Option<PageInfo> {
-        let pte = get_pte(
-            self.l0_table,
-            va.page_aligned(),
-            &mut PageOffsetPgTableMapper {},
-        )
-        .unwrap()?;
+        let (region, offset, perms) =
+            translate(self.l0_table, va, &mut PageOffsetPgTableMapper {}).unwrap()?;
 
         Some(PageInfo {
-            pfn: pte.mapped_address()?.to_pfn(),
-            perms: pte.permissions()?,
+            pfn: region.start_address().add_bytes(offset).to_pfn(),
+            perms,
         })
     }
 
@@ -159,6 +242,9 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
     where
         Self: Sized,
     {
+        // Huge pages are never shared; the child gets CoW page mappings.
+        self.split_huge_pages(region)?;
+
         let mut walk_ctx = WalkContext {
             mapper: &mut PageOffsetPgTableMapper {},
             invalidator: &AllEl0TlbInvalidator::new(),
@@ -45,6 +45,7 @@ fn run_mem_fault_handler(
     proc_vm: Arc<SpinLock<ProcVM>>,
     exception: Exception,
     info: AbortIss,
+    user_access: bool,
 ) -> Result<FaultResolution> {
     let access_kind = determine_access_kind(exception, info);
 
@@ -53,7 +54,7 @@ fn run_mem_fault_handler(
 
         match info.ifsc.category() {
             IfscCategory::TranslationFault => {
-                handle_demand_fault(proc_vm.clone(), fault_addr, access_kind)
+                handle_demand_fault(proc_vm.clone(), fault_addr, access_kind, user_access)
             }
             IfscCategory::PermissionFault => {
                 let mut vm = proc_vm.lock_save_irq();
@@ -74,7 +75,7 @@ fn run_mem_fault_handler(
 }
 
 fn handle_uacess_abort(exception: Exception, info: AbortIss, state: &mut ExceptionState) {
-    match run_mem_fault_handler(current_work().vm.shared_vm(), exception, info) {
+    match run_mem_fault_handler(current_work().vm.shared_vm(), exception, info, false) {
         // We mapped in a page, the uacess handler can proceed.
         Ok(FaultResolution::Resolved) => (),
         // If the fault couldn't be resolved, signal to the uacess fixup that
@@ -124,7 +125,7 @@ pub fn handle_kernel_mem_fault(exception: Exception, info: AbortIss, state: &mut
 }
 
 pub fn handle_mem_fault(ctx: &mut ProcessCtx, exception: Exception, info: AbortIss) {
-    match run_mem_fault_handler(ctx.shared().vm.shared_vm(), exception, info) {
+    match run_mem_fault_handler(ctx.shared().vm.shared_vm(), exception, info, true) {
         Ok(FaultResolution::Resolved) => {}
         Ok(FaultResolution::Denied) => {
             ctx.task().process.deliver_signal(SigId::SIGSEGV);
@@ -133,8 +134,10 @@ pub fn handle_mem_fault(ctx: &mut ProcessCtx, exception: Exception, info: AbortI
         // spawn that work on the process, since there is no other
         // kernel work happening.
         Ok(FaultResolution::Deferred(fut)) => spawn_kernel_work(ctx, async {
+            // The backing page couldn't be brought in, e.g. an access
+            // beyond the end of a shared file mapping.
             if Box::into_pin(fut).await.is_err() {
-                panic!("Page fault defered error, SIGBUS on process");
+                current_work().process.deliver_signal(SigId::SIGBUS);
             }
         }),
         Err(_) => panic!("Page fault handler error, SIGBUS on process"),
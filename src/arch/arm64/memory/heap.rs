@@ -1,18 +1,20 @@
 use crate::{
-    arch::ArchImpl,
+    arch::{Arch, ArchImpl},
     memory::{PageOffsetTranslator, page::PgAllocGetter},
+    per_cpu_shared,
     sync::OnceLock,
 };
+use alloc::vec::Vec;
 use core::{
-    arch::asm,
     ops::{Deref, DerefMut},
     ptr,
+    sync::atomic::{AtomicPtr, Ordering},
 };
 use libkernel::{
     CpuOps,
     memory::allocators::slab::{
         allocator::SlabAllocator,
-        cache::SlabCache,
+        cache::{CacheStats, SlabCache},
         heap::{KHeap, SlabCacheStorage, SlabGetter},
     },
 };
@@ -29,36 +31,42 @@ impl SlabGetter<ArchImpl, PgAllocGetter, PageOffsetTranslator> for StaticSlabGet
     }
 }
 
-pub struct PerCpuCache {
-    flags: u64,
+per_cpu_shared! {
+    static SLAB_CACHE: AtomicPtr<SlabCache> = || AtomicPtr::new(ptr::null_mut());
 }
 
-impl PerCpuCache {
-    fn get_ptr() -> *mut SlabCache {
-        let mut cache: *mut SlabCache = ptr::null_mut();
-
-        unsafe { asm!("mrs {}, TPIDR_EL1", out(reg) cache, options(nostack, nomem)) };
-
-        if cache.is_null() {
-            panic!("Attempted to use alloc/free before CPU initalisation!");
-        }
-
-        cache
-    }
+/// Access to this CPU's slab cache, with interrupts disabled for as long as it
+/// is held.
+pub struct PerCpuCache {
+    cache: *mut SlabCache,
+    flags: u64,
 }
 
 impl SlabCacheStorage for PerCpuCache {
     fn store(ptr: *mut SlabCache) {
-        #[allow(clippy::pointers_in_nomem_asm_block)]
-        unsafe {
-            asm!("msr TPIDR_EL1, {}", in(reg) ptr, options(nostack, nomem));
-        };
+        SLAB_CACHE
+            .try_get()
+            .expect("Per-CPU data must be set up before the heap cache")
+            .store(ptr, Ordering::Relaxed);
     }
 
-    fn get() -> impl DerefMut<Target = SlabCache> {
+    fn get() -> Option<impl DerefMut<Target = SlabCache>> {
+        // Disable interrupts before looking up the CPU id so that we can't be
+        // preempted and moved to another CPU while holding its cache.
         let flags = ArchImpl::disable_interrupts();
 
-        Self { flags }
+        // Until per-CPU data has been set up (and this CPU has created its
+        // cache) the heap falls back to the locked slab allocator.
+        let cache = SLAB_CACHE
+            .try_get()
+            .map_or(ptr::null_mut(), |c| c.load(Ordering::Relaxed));
+
+        if cache.is_null() {
+            ArchImpl::restore_interrupt_state(flags);
+            return None;
+        }
+
+        Some(Self { cache, flags })
     }
 }
 
@@ -66,19 +74,19 @@ impl Deref for PerCpuCache {
     type Target = SlabCache;
 
     fn deref(&self) -> &Self::Target {
-        // SAFETY: The pointer uses a CPU-banked register for access. We've
-        // disabled interrupts so we know we cannot be preempted, therefore
-        // mutable access to the cache is safe.
-        unsafe { &(*Self::get_ptr()) }
+        // SAFETY: The cache belongs to this CPU. We've disabled interrupts so
+        // we know we cannot be preempted, therefore access to the cache is
+        // safe.
+        unsafe { &(*self.cache) }
     }
 }
 
 impl DerefMut for PerCpuCache {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        // SAFETY: The pointer uses a CPU-banked register for access. We've
-        // disabled interrupts so we know we cannot be preempted, therefore
-        // mutable access to the cache is safe.
-        unsafe { &mut (*Self::get_ptr()) }
+        // SAFETY: The cache belongs to this CPU. We've disabled interrupts so
+        // we know we cannot be preempted, therefore mutable access to the
+        // cache is safe.
+        unsafe { &mut (*self.cache) }
     }
 }
 
@@ -88,8 +96,54 @@ impl Drop for PerCpuCache {
     }
 }
 
+/// Returns the per-CPU cache counters of every size class, smallest first,
+/// summed over all CPUs.
+pub fn cache_stats() -> Vec<CacheStats> {
+    let mut totals = Vec::new();
+
+    if SLAB_CACHE.try_get().is_none() {
+        return totals;
+    }
+
+    for cpu in 0..ArchImpl::cpu_count() {
+        let cache = SLAB_CACHE.get_by_cpu(cpu).load(Ordering::Relaxed);
+
+        if cache.is_null() {
+            continue;
+        }
+
+        // SAFETY: Per-CPU caches are never freed.
+        let stats = unsafe { SlabCache::stats(cache) };
+
+        for (order, stats) in stats.enumerate() {
+            match totals.get_mut(order) {
+                Some(total) => *total += stats,
+                None => totals.push(stats),
+            }
+        }
+    }
+
+    totals
+}
+
 pub type KernelHeap =
     KHeap<ArchImpl, PerCpuCache, PgAllocGetter, PageOffsetTranslator, StaticSlabGetter>;
 
+#[cfg(not(any(feature = "kasan", feature = "alloc_profile", feature = "kmemleak")))]
 #[global_allocator]
 static K_HEAP: KernelHeap = KernelHeap::new();
+
+#[cfg(feature = "kasan")]
+#[global_allocator]
+static K_HEAP: crate::memory::kasan::KasanHeap<KernelHeap> =
+    crate::memory::kasan::KasanHeap::new(KernelHeap::new());
+
+#[cfg(feature = "alloc_profile")]
+#[global_allocator]
+static K_HEAP: crate::memory::alloc_profile::ProfilingHeap<KernelHeap> =
+    crate::memory::alloc_profile::ProfilingHeap::new(KernelHeap::new());
+
+#[cfg(feature = "kmemleak")]
+#[global_allocator]
+static K_HEAP: crate::memory::kmemleak::LeakTrackingHeap<KernelHeap> =
+    crate::memory::kmemleak::LeakTrackingHeap::new(KernelHeap::new());
@@ -4,6 +4,7 @@ use aarch64_cpu::{
 };
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use cpu_ops::{local_irq_restore, local_irq_save};
 use exceptions::ExceptionState;
 use libkernel::{
@@ -12,13 +13,16 @@ use libkernel::{
     error::Result,
     memory::{
         address::{UA, VA},
+        allocators::slab::allocator::SlabStats,
         paging::PgTableArray,
         proc_vm::address_space::VirtualMemory,
+        region::VirtMemoryRegion,
     },
 };
 use memory::{
     PAGE_OFFSET,
     address_space::Arm64ProcessAddressSpace,
+    heap::{self, SLAB_ALLOC},
     mmu::{Arm64KernelAddressSpace, KERN_ADDR_SPC},
     uaccess::{Arm64CopyFromUser, Arm64CopyStrnFromUser, Arm64CopyToUser, try_copy_from_user},
 };
@@ -34,12 +38,14 @@ use crate::{
     sync::SpinLock,
 };
 
-use super::Arch;
+use super::{Arch, SyscallInfo};
 
+mod backtrace;
 mod boot;
 mod cpu_ops;
 mod exceptions;
 mod fdt;
+mod hwcap;
 mod memory;
 mod proc;
 pub mod psci;
@@ -121,6 +127,19 @@ impl Arch for Aarch64 {
         proc::signal::do_signal_return(ctx)
     }
 
+    #[cfg(feature = "bench")]
+    fn kernel_syscall(
+        ctx: ProcessCtx,
+        nr: usize,
+        args: [usize; 6],
+    ) -> impl Future<Output = isize> + Send {
+        exceptions::kernel_syscall(ctx, nr, args)
+    }
+
+    fn syscalls() -> &'static [SyscallInfo] {
+        exceptions::SYSCALLS
+    }
+
     fn context_switch(new: Arc<Task>) {
         proc::context_switch(new);
     }
@@ -155,6 +174,49 @@ impl Arch for Aarch64 {
         fdt::get_cmdline()
     }
 
+    fn hwcaps() -> (u64, u64) {
+        hwcap::hwcaps()
+    }
+
+    fn slab_stats() -> Vec<SlabStats> {
+        let mut stats: Vec<SlabStats> = SLAB_ALLOC
+            .get()
+            .map(|slab| slab.stats().collect())
+            .unwrap_or_default();
+
+        for (stats, cpu_cache) in stats.iter_mut().zip(heap::cache_stats()) {
+            stats.cpu_cache = cpu_cache;
+        }
+
+        stats
+    }
+
+    fn backtrace(frames: &mut [usize]) -> usize {
+        backtrace::backtrace(frames)
+    }
+
+    fn for_each_root_region(mut f: impl FnMut(VirtMemoryRegion)) {
+        unsafe extern "C" {
+            static __data_start: u8;
+            static __data_end: u8;
+            static __bss_start: u8;
+            static __bss_end: u8;
+        }
+
+        let data = VirtMemoryRegion::from_start_end_address(
+            VA::from_value((&raw const __data_start).addr()),
+            VA::from_value((&raw const __data_end).addr()),
+        );
+        let bss = VirtMemoryRegion::from_start_end_address(
+            VA::from_value((&raw const __bss_start).addr()),
+            VA::from_value((&raw const __bss_end).addr()),
+        );
+
+        f(data);
+        f(bss);
+        boot::memory::kstack_regions().for_each(f);
+    }
+
     unsafe fn copy_from_user(
         src: UA,
         dst: *mut (),
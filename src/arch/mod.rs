@@ -19,15 +19,36 @@ use crate::{
 };
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use libkernel::{
     CpuOps,
     error::Result,
     memory::{
         address::{UA, VA},
+        allocators::slab::allocator::SlabStats,
         proc_vm::address_space::VirtualMemory,
+        region::VirtMemoryRegion,
     },
 };
 
+/// How the kernel deals with a system call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyscallSupport {
+    /// The call has a handler.
+    Implemented,
+    /// The call is known, and quietly fails with `ENOSYS`.
+    Stubbed,
+    /// The call isn't handled at all. It fails with `ENOSYS`, and is logged.
+    Missing,
+}
+
+/// An entry of the system call table of an architecture's ABI.
+pub struct SyscallInfo {
+    pub nr: u32,
+    pub name: &'static str,
+    pub support: SyscallSupport,
+}
+
 pub trait Arch: CpuOps + VirtualMemory {
     /// The type representing the state saved to the stack on an exception or
     /// context switch. The kernel's scheduler and exception handlers will work
@@ -64,6 +85,22 @@ pub trait Arch: CpuOps + VirtualMemory {
 
     fn get_cmdline() -> Option<String>;
 
+    /// Returns the `AT_HWCAP` and `AT_HWCAP2` bits, describing the CPU
+    /// features available to userspace.
+    fn hwcaps() -> (u64, u64);
+
+    /// Returns the allocation statistics of each kernel heap size class.
+    fn slab_stats() -> Vec<SlabStats>;
+
+    /// Stores the return addresses of the current kernel call stack into
+    /// `frames`, innermost first, and returns how many were stored.
+    fn backtrace(frames: &mut [usize]) -> usize;
+
+    /// Calls `f` with each region of kernel memory outside the heap that may
+    /// hold pointers into it: the kernel image's writable data and the kernel
+    /// stacks.
+    fn for_each_root_region(f: impl FnMut(VirtMemoryRegion));
+
     /// Call a user-specified signal handler in the current process.
     fn do_signal(
         ctx: ProcessCtx,
@@ -76,6 +113,20 @@ pub trait Arch: CpuOps + VirtualMemory {
         ctx: ProcessCtx,
     ) -> impl Future<Output = Result<<Self as Arch>::UserContext>>;
 
+    /// Makes system call `nr` with `args` from a kernel thread, through the
+    /// same dispatcher a trap from userspace goes to, and returns its raw
+    /// result.
+    #[cfg(feature = "bench")]
+    fn kernel_syscall(
+        ctx: ProcessCtx,
+        nr: usize,
+        args: [usize; 6],
+    ) -> impl Future<Output = isize> + Send;
+
+    /// Returns every system call of the architecture's ABI, in number order,
+    /// along with how the kernel deals with it.
+    fn syscalls() -> &'static [SyscallInfo];
+
     /// Copies a block of memory from userspace to the kernel.
     ///
     /// This is the raw, unsafe primitive for transferring data from a
@@ -0,0 +1,421 @@
+//! In-kernel microbenchmarks, run at boot with `--bench` or on demand by
+//! reading `/proc/bench`.
+//!
+//! The benchmarks run in a kernel thread of their own, which makes its system
+//! calls through the same dispatcher as userspace and works on anonymous
+//! memory mapped into its own address space. Every result is printed as one
+//! line of `key=value` pairs, so that runs before and after a change can be
+//! compared by a script.
+
+use crate::{
+    arch::{Arch, ArchImpl},
+    drivers::timer::{sleep, uptime},
+    memory::uaccess::{copy_from_user, copy_to_user_slice},
+    process::{
+        kthread::spawn_kthread,
+        threading::futex::{key::FutexKey, wake_key},
+    },
+    sched::{current_work, syscall_ctx::ProcessCtx},
+    sync::CondVar,
+};
+use alloc::{string::String, vec::Vec};
+use core::{fmt::Write, time::Duration};
+use libkernel::{
+    error::{KernelError, Result},
+    memory::{
+        PAGE_SIZE,
+        address::{TUA, UA},
+    },
+    sync::condvar::WakeupType,
+};
+use log::{error, info};
+use stats::Summary;
+
+mod stats;
+
+const SYS_CLOSE: usize = 57;
+const SYS_PIPE2: usize = 59;
+const SYS_READ: usize = 63;
+const SYS_WRITE: usize = 64;
+const SYS_FUTEX: usize = 98;
+const SYS_GETPPID: usize = 173;
+const SYS_MUNMAP: usize = 215;
+const SYS_MMAP: usize = 222;
+
+const PROT_READ_WRITE: usize = 0x3;
+const MAP_PRIVATE_ANONYMOUS: usize = 0x22;
+const FUTEX_WAIT_PRIVATE: usize = 128;
+
+const WARMUP: usize = 100;
+const SYSCALL_ITERS: usize = 10_000;
+const SWITCH_ITERS: usize = 2_000;
+const PIPE_ITERS: usize = 2_000;
+const PIPE_CHUNK: usize = 4096;
+const FAULT_PAGES: usize = 256;
+const FAULT_ROUNDS: usize = 8;
+const FUTEX_ITERS: usize = 1_000;
+
+/// The outcome of one benchmark.
+pub struct BenchResult {
+    pub name: &'static str,
+    pub summary: Summary,
+    /// Bytes moved by each iteration, for benchmarks that measure throughput.
+    pub bytes: Option<usize>,
+}
+
+impl BenchResult {
+    fn new(name: &'static str, samples: Vec<u64>) -> Result<Self> {
+        Ok(Self {
+            name,
+            summary: Summary::new(samples).ok_or(KernelError::InvalidValue)?,
+            bytes: None,
+        })
+    }
+}
+
+impl core::fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "bench={} {}", self.name, self.summary)?;
+
+        // Throughput is worked out from the median, which a stray interrupt
+        // can't drag about.
+        if let Some(bytes) = self.bytes
+            && self.summary.p50 > 0
+        {
+            let mib_s = bytes as u128 * 1_000_000_000 / self.summary.p50 as u128 / (1 << 20);
+            write!(f, " bytes={bytes} mib_s={mib_s}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Makes system call `nr` from the current kernel thread.
+async fn syscall(nr: usize, args: [usize; 6]) -> Result<usize> {
+    // SAFETY: Only this kernel thread's own work runs on its task.
+    let ctx = unsafe { ProcessCtx::from_current() };
+    let ret = ArchImpl::kernel_syscall(ctx, nr, args).await;
+
+    if ret < 0 {
+        error!("benchmark syscall {nr} failed: {ret}");
+        Err(KernelError::InvalidValue)
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+/// Maps `len` bytes of fresh anonymous memory into the current kernel
+/// thread's address space.
+async fn map_scratch(len: usize) -> Result<UA> {
+    let addr = syscall(
+        SYS_MMAP,
+        [
+            0,
+            len,
+            PROT_READ_WRITE,
+            MAP_PRIVATE_ANONYMOUS,
+            usize::MAX,
+            0,
+        ],
+    )
+    .await?;
+
+    Ok(UA::from_value(addr))
+}
+
+async fn unmap_scratch(addr: UA, len: usize) -> Result<()> {
+    syscall(SYS_MUNMAP, [addr.value(), len, 0, 0, 0, 0])
+        .await
+        .map(|_| ())
+}
+
+fn elapsed_ns(since: Duration) -> u64 {
+    (uptime() - since).as_nanos() as u64
+}
+
+/// The cost of a system call that does next to nothing, from dispatch to
+/// return value.
+async fn bench_syscall() -> Result<BenchResult> {
+    let mut samples = Vec::with_capacity(SYSCALL_ITERS);
+
+    for i in 0..WARMUP + SYSCALL_ITERS {
+        let start = uptime();
+        syscall(SYS_GETPPID, [0; 6]).await?;
+
+        if i >= WARMUP {
+            samples.push(elapsed_ns(start));
+        }
+    }
+
+    BenchResult::new("syscall_getppid", samples)
+}
+
+#[derive(Default)]
+struct PingPong {
+    turn: u64,
+    stop: bool,
+}
+
+/// The time to hand the CPU from one kernel thread to another, as half of a
+/// round trip through a pair of threads taking turns.
+async fn bench_context_switch() -> Result<BenchResult> {
+    let state = CondVar::new(PingPong::default());
+    let pong = state.clone();
+
+    spawn_kthread("kbench-pong", async move {
+        loop {
+            let stop = pong
+                .wait_until(|s| (s.stop || s.turn % 2 == 1).then_some(s.stop))
+                .await;
+
+            if stop {
+                break;
+            }
+
+            pong.update(|s| {
+                s.turn += 1;
+                WakeupType::All
+            });
+        }
+    })?;
+
+    let mut samples = Vec::with_capacity(SWITCH_ITERS);
+
+    for i in 0..WARMUP + SWITCH_ITERS {
+        let start = uptime();
+        let next = (i as u64 + 1) * 2;
+
+        state.update(|s| {
+            s.turn += 1;
+            WakeupType::All
+        });
+        state
+            .wait_until(move |s| (s.turn == next).then_some(()))
+            .await;
+
+        if i >= WARMUP {
+            samples.push(elapsed_ns(start) / 2);
+        }
+    }
+
+    state.update(|s| {
+        s.stop = true;
+        WakeupType::All
+    });
+
+    BenchResult::new("context_switch", samples)
+}
+
+/// Pipe throughput: a page written into a pipe and read back out again.
+async fn bench_pipe() -> Result<BenchResult> {
+    let scratch = map_scratch(PIPE_CHUNK * 2).await?;
+    let fds_ptr = scratch.add_bytes(PIPE_CHUNK);
+
+    syscall(SYS_PIPE2, [fds_ptr.value(), 0, 0, 0, 0, 0]).await?;
+    let fds: [i32; 2] = copy_from_user(TUA::from_value(fds_ptr.value())).await?;
+    let (rfd, wfd) = (fds[0] as usize, fds[1] as usize);
+
+    let samples = async {
+        let mut samples = Vec::with_capacity(PIPE_ITERS);
+
+        for i in 0..WARMUP + PIPE_ITERS {
+            let start = uptime();
+            syscall(SYS_WRITE, [wfd, scratch.value(), PIPE_CHUNK, 0, 0, 0]).await?;
+            syscall(SYS_READ, [rfd, scratch.value(), PIPE_CHUNK, 0, 0, 0]).await?;
+
+            if i >= WARMUP {
+                samples.push(elapsed_ns(start));
+            }
+        }
+
+        Ok::<_, KernelError>(samples)
+    }
+    .await;
+
+    syscall(SYS_CLOSE, [rfd, 0, 0, 0, 0, 0]).await?;
+    syscall(SYS_CLOSE, [wfd, 0, 0, 0, 0, 0]).await?;
+    unmap_scratch(scratch, PIPE_CHUNK * 2).await?;
+
+    Ok(BenchResult {
+        bytes: Some(PIPE_CHUNK),
+        ..BenchResult::new("pipe_4k", samples?)?
+    })
+}
+
+/// The cost of demand-faulting in a page of anonymous memory on first touch.
+async fn bench_page_fault() -> Result<BenchResult> {
+    let len = FAULT_PAGES * PAGE_SIZE;
+    let mut samples = Vec::with_capacity(FAULT_PAGES * FAULT_ROUNDS);
+
+    for _ in 0..FAULT_ROUNDS {
+        let scratch = map_scratch(len).await?;
+
+        let res = async {
+            for page in 0..FAULT_PAGES {
+                let start = uptime();
+                copy_to_user_slice(&[1], scratch.add_bytes(page * PAGE_SIZE)).await?;
+                samples.push(elapsed_ns(start));
+            }
+
+            Ok::<_, KernelError>(())
+        }
+        .await;
+
+        unmap_scratch(scratch, len).await?;
+        res?;
+    }
+
+    BenchResult::new("page_fault", samples)
+}
+
+#[derive(Default)]
+struct FutexState {
+    key: Option<FutexKey>,
+    woken_at: Option<Duration>,
+    stop: bool,
+    exited: bool,
+}
+
+/// The time from a futex wake to the waiter running again.
+async fn bench_futex_wake() -> Result<BenchResult> {
+    let state = CondVar::new(FutexState::default());
+    let waiter = state.clone();
+
+    spawn_kthread("kbench-futex", async move {
+        let res = async {
+            let word = map_scratch(PAGE_SIZE).await?;
+            let tgid = current_work().process.tgid.value();
+
+            waiter.update(|s| {
+                s.key = Some(FutexKey::Private {
+                    pid: tgid,
+                    addr: word.value(),
+                });
+                WakeupType::All
+            });
+
+            // The word stays zero, so each wait sleeps until woken.
+            while !waiter.wait_until(|s| Some(s.stop)).await {
+                syscall(SYS_FUTEX, [word.value(), FUTEX_WAIT_PRIVATE, 0, 0, 0, 0]).await?;
+                let woken_at = uptime();
+
+                waiter.update(|s| {
+                    s.woken_at = Some(woken_at);
+                    WakeupType::All
+                });
+            }
+
+            unmap_scratch(word, PAGE_SIZE).await
+        }
+        .await;
+
+        if let Err(e) = res {
+            error!("futex benchmark waiter failed: {e}");
+        }
+
+        waiter.update(|s| {
+            s.exited = true;
+            WakeupType::All
+        });
+    })?;
+
+    let key = state
+        .wait_until(|s| match (s.key, s.exited) {
+            (Some(key), _) => Some(Some(key)),
+            (None, true) => Some(None),
+            (None, false) => None,
+        })
+        .await
+        .ok_or(KernelError::InvalidValue)?;
+    let mut samples = Vec::with_capacity(FUTEX_ITERS);
+
+    for i in 0..WARMUP + FUTEX_ITERS {
+        // Keep knocking until the waiter has gone to sleep.
+        let start = loop {
+            if state.wait_until(|s| Some(s.exited)).await {
+                return Err(KernelError::InvalidValue);
+            }
+
+            let start = uptime();
+
+            if wake_key(1, key, u32::MAX) == 1 {
+                break start;
+            }
+
+            sleep(Duration::from_micros(10)).await;
+        };
+
+        let woken_at = state.wait_until(|s| s.woken_at.take()).await;
+
+        if i >= WARMUP {
+            samples.push((woken_at - start).as_nanos() as u64);
+        }
+    }
+
+    state.update(|s| {
+        s.stop = true;
+        WakeupType::All
+    });
+
+    // It may already be asleep again.
+    while !state.wait_until(|s| Some(s.exited)).await {
+        wake_key(1, key, u32::MAX);
+        sleep(Duration::from_micros(10)).await;
+    }
+
+    BenchResult::new("futex_wake", samples)
+}
+
+async fn run_suite() -> Result<Vec<BenchResult>> {
+    Ok(alloc::vec![
+        bench_syscall().await?,
+        bench_context_switch().await?,
+        bench_pipe().await?,
+        bench_page_fault().await?,
+        bench_futex_wake().await?,
+    ])
+}
+
+/// Runs every benchmark in a kernel thread of its own, and returns their
+/// results.
+pub async fn run() -> Result<Vec<BenchResult>> {
+    let done = CondVar::new(None);
+    let tx = done.clone();
+
+    spawn_kthread("kbench", async move {
+        let results = run_suite().await;
+
+        tx.update(|r| {
+            *r = Some(results);
+            WakeupType::All
+        });
+    })?;
+
+    done.wait_until(|r: &mut Option<Result<Vec<BenchResult>>>| r.take())
+        .await
+}
+
+/// Runs the benchmarks and formats their results, one per line.
+pub async fn report() -> Result<String> {
+    let mut out = String::new();
+
+    for result in run().await? {
+        let _ = writeln!(out, "{result}");
+    }
+
+    Ok(out)
+}
+
+/// Runs the benchmarks and prints their results to the console.
+pub async fn run_at_boot() {
+    info!("running benchmarks");
+
+    match run().await {
+        Ok(results) => {
+            for result in results {
+                info!("{result}");
+            }
+        }
+        Err(e) => error!("benchmarks failed: {e}"),
+    }
+}
@@ -0,0 +1,90 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+
+/// A summary of a benchmark's samples, all in nanoseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub iters: usize,
+    pub min: u64,
+    pub p50: u64,
+    pub mean: u64,
+    pub p99: u64,
+    pub max: u64,
+    pub stddev: u64,
+}
+
+impl Summary {
+    /// Summarises `samples`, which needn't be in any order. Returns `None`
+    /// if there are none.
+    pub fn new(mut samples: Vec<u64>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        samples.sort_unstable();
+
+        let n = samples.len();
+        let sum: u128 = samples.iter().map(|&s| s as u128).sum();
+        let mean = sum / n as u128;
+        let variance = samples
+            .iter()
+            .map(|&s| (s as i128 - mean as i128).unsigned_abs().pow(2))
+            .sum::<u128>()
+            / n as u128;
+
+        // The sample at or below which `pct` percent of them fall.
+        let percentile = |pct: usize| samples[(n * pct).div_ceil(100).saturating_sub(1)];
+
+        Some(Self {
+            iters: n,
+            min: samples[0],
+            p50: percentile(50),
+            mean: mean as u64,
+            p99: percentile(99),
+            max: samples[n - 1],
+            stddev: variance.isqrt() as u64,
+        })
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "iters={} min_ns={} p50_ns={} mean_ns={} p99_ns={} max_ns={} stddev_ns={}",
+            self.iters, self.min, self.p50, self.mean, self.p99, self.max, self.stddev
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use moss_macros::ktest;
+
+    #[ktest]
+    fn summary_of_samples() {
+        let summary = Summary::new((1..=100).rev().collect()).unwrap();
+
+        assert_eq!(summary.iters, 100);
+        assert_eq!(summary.min, 1);
+        assert_eq!(summary.p50, 50);
+        assert_eq!(summary.mean, 50);
+        assert_eq!(summary.p99, 99);
+        assert_eq!(summary.max, 100);
+        assert_eq!(summary.stddev, 28);
+    }
+
+    #[ktest]
+    fn summary_of_one_sample() {
+        let summary = Summary::new(vec![7]).unwrap();
+
+        assert_eq!(
+            (summary.min, summary.p50, summary.p99, summary.max),
+            (7, 7, 7, 7)
+        );
+        assert_eq!(summary.stddev, 0);
+        assert!(Summary::new(Vec::new()).is_none());
+    }
+}
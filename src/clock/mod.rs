@@ -1,15 +1,36 @@
 pub mod realtime;
 pub mod syscalls;
+pub mod timens;
 pub mod timer;
 pub mod timespec;
 
-use core::time::Duration;
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
 use futures::FutureExt;
 
 use crate::drivers::timer::{sleep, uptime};
 use realtime::{clock_set_generation, clock_was_set_since, date};
 
+/// Time the system has spent suspended, in nanoseconds. `CLOCK_BOOTTIME`
+/// counts it, while `CLOCK_MONOTONIC` stops across a suspend.
+static SUSPENDED_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the time since boot, including any time spent suspended.
+pub fn boottime() -> Duration {
+    uptime() + Duration::from_nanos(SUSPENDED_NS.load(Ordering::Relaxed))
+}
+
+/// Records that the system was suspended for `duration`. The resume path
+/// should call this once the system timer is running again; nothing can
+/// suspend yet.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn account_suspend(duration: Duration) {
+    SUSPENDED_NS.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+}
+
 /// An absolute deadline expressed against a particular clock.
 ///
 /// Keeping the clock alongside the deadline (rather than pre-flattening to a
@@ -123,3 +144,24 @@ impl TryFrom<i32> for ClockId {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{account_suspend, boottime};
+    use crate::drivers::timer::uptime;
+    use core::time::Duration;
+    use moss_macros::ktest;
+
+    #[ktest]
+    fn boottime_counts_suspend() {
+        const SUSPENDED: Duration = Duration::from_secs(1);
+
+        let offset = boottime().saturating_sub(uptime());
+
+        account_suspend(SUSPENDED);
+
+        // Read MONOTONIC first, so that BOOTTIME can only have moved further.
+        let monotonic = uptime();
+        assert!(boottime() >= monotonic + offset + SUSPENDED);
+    }
+}
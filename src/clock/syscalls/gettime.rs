@@ -7,8 +7,8 @@ use libkernel::{
 
 use crate::clock::{ClockId, realtime::date, timespec::TimeSpec};
 use crate::drivers::timer::{Instant, now};
+use crate::memory::uaccess::copy_to_user;
 use crate::sched::syscall_ctx::ProcessCtx;
-use crate::{drivers::timer::uptime, memory::uaccess::copy_to_user};
 
 pub async fn sys_clock_gettime(
     ctx: &ProcessCtx,
@@ -17,7 +17,12 @@ pub async fn sys_clock_gettime(
 ) -> Result<usize> {
     let time = match ClockId::try_from(clockid).map_err(|_| KernelError::InvalidValue)? {
         ClockId::Realtime => date(),
-        ClockId::Monotonic => uptime(),
+        ClockId::Monotonic | ClockId::MonotonicCoarse | ClockId::MonotonicRaw => {
+            ctx.shared().time_ns.lock_save_irq().monotonic()
+        }
+        ClockId::BootTime | ClockId::BootTimeAlarm => {
+            ctx.shared().time_ns.lock_save_irq().boottime()
+        }
         ClockId::ProcessCpuTimeId => {
             let task = ctx.shared();
             let total_time = task.process.stime.load(Ordering::Relaxed) as u64
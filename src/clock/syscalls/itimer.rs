@@ -98,7 +98,7 @@ pub fn itimer_irq_handler(tid: Tid, id: u64) -> Option<Instant> {
 async fn getitimer(current_task: &Task, which: ITimerType) -> libkernel::error::Result<ITimerVal> {
     let now = match which {
         ITimerType::Real => now().unwrap(),
-        _ => unimplemented!(),
+        _ => return Err(libkernel::error::KernelError::NotSupported),
     };
     Ok(current_task
         .i_timers
@@ -193,7 +193,7 @@ pub async fn sys_setitimer(
                     );
             }
         }
-        _ => unimplemented!(),
+        _ => return Err(libkernel::error::KernelError::NotSupported),
     }
     Ok(0)
 }
@@ -0,0 +1,228 @@
+//! Time namespaces.
+//!
+//! A time namespace shifts what `CLOCK_MONOTONIC` and `CLOCK_BOOTTIME` read
+//! for the tasks inside it, so that a container restored on another machine
+//! (e.g. by CRIU) sees its clocks carry on from where they were.
+//!
+//! As on Linux, `unshare(CLONE_NEWTIME)` doesn't move the caller, but creates
+//! the namespace its children will be born into. Until the first of them is,
+//! the offsets can be set through `/proc/<pid>/timens_offsets`.
+
+use super::{ClockId, boottime};
+use crate::{
+    drivers::timer::uptime,
+    sync::{OnceLock, SpinLock},
+};
+use alloc::{format, string::String, sync::Arc};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use libkernel::error::{FsError, KernelError, Result};
+
+const NSEC_PER_SEC: i64 = 1_000_000_000;
+
+/// Offsets are limited so that they can't overflow a clock, as on Linux.
+const MAX_OFFSET_SECS: i64 = i64::MAX / NSEC_PER_SEC / 2;
+
+/// Per-clock offsets of a time namespace, in nanoseconds.
+#[derive(Clone, Copy, Default)]
+pub struct TimeOffsets {
+    pub monotonic: i64,
+    pub boottime: i64,
+}
+
+pub struct TimeNamespace {
+    offsets: SpinLock<TimeOffsets>,
+    /// Set once a task has entered the namespace, after which its offsets
+    /// can't change.
+    entered: AtomicBool,
+}
+
+impl TimeNamespace {
+    fn new(offsets: TimeOffsets) -> Self {
+        Self {
+            offsets: SpinLock::new(offsets),
+            entered: AtomicBool::new(false),
+        }
+    }
+
+    pub fn offsets(&self) -> TimeOffsets {
+        *self.offsets.lock_save_irq()
+    }
+
+    /// Sets the offset of `clock` to `secs` and `nsecs`.
+    ///
+    /// # Returns
+    /// * `Err(FsError::PermissionDenied)` if a task has already entered the
+    ///   namespace.
+    /// * `Err(KernelError::RangeError)` if the offset is out of range.
+    /// * `Err(KernelError::InvalidValue)` if `clock` has no offset, or the
+    ///   offset would make the clock negative.
+    pub fn set_offset(&self, clock: ClockId, secs: i64, nsecs: i64) -> Result<()> {
+        if !(0..NSEC_PER_SEC).contains(&nsecs) {
+            return Err(KernelError::InvalidValue);
+        }
+
+        if !(-MAX_OFFSET_SECS..=MAX_OFFSET_SECS).contains(&secs) {
+            return Err(KernelError::RangeError);
+        }
+
+        let offset = secs * NSEC_PER_SEC + nsecs;
+
+        let now = match clock {
+            ClockId::Monotonic => uptime(),
+            ClockId::BootTime => boottime(),
+            _ => return Err(KernelError::InvalidValue),
+        };
+
+        if shift(now, offset).is_none() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut offsets = self.offsets.lock_save_irq();
+
+        if self.entered.load(Ordering::Acquire) {
+            return Err(FsError::PermissionDenied.into());
+        }
+
+        match clock {
+            ClockId::Monotonic => offsets.monotonic = offset,
+            _ => offsets.boottime = offset,
+        }
+
+        Ok(())
+    }
+
+    fn enter(&self) {
+        // Taking the lock orders this against a concurrent `set_offset`.
+        let _offsets = self.offsets.lock_save_irq();
+
+        self.entered.store(true, Ordering::Release);
+    }
+}
+
+/// Shifts `time` by `offset` nanoseconds, or returns `None` if that would take
+/// it below zero.
+fn shift(time: Duration, offset: i64) -> Option<Duration> {
+    let offset_abs = Duration::from_nanos(offset.unsigned_abs());
+
+    if offset < 0 {
+        time.checked_sub(offset_abs)
+    } else {
+        time.checked_add(offset_abs)
+    }
+}
+
+fn init_time_ns() -> Arc<TimeNamespace> {
+    static INIT_TIME_NS: OnceLock<Arc<TimeNamespace>> = OnceLock::new();
+
+    INIT_TIME_NS
+        .get_or_init(|| {
+            let ns = TimeNamespace::new(TimeOffsets::default());
+
+            ns.entered.store(true, Ordering::Relaxed);
+
+            Arc::new(ns)
+        })
+        .clone()
+}
+
+/// The time namespaces of a task: the one its clocks are read through, and the
+/// one its children will be created in.
+#[derive(Clone)]
+pub struct TimeNsProxy {
+    pub current: Arc<TimeNamespace>,
+    pub for_children: Arc<TimeNamespace>,
+}
+
+impl TimeNsProxy {
+    /// The namespaces of the initial task.
+    pub fn init() -> Self {
+        let ns = init_time_ns();
+
+        Self {
+            current: ns.clone(),
+            for_children: ns,
+        }
+    }
+
+    /// Returns `true` if children would be created in a different namespace.
+    pub fn is_switching(&self) -> bool {
+        !Arc::ptr_eq(&self.current, &self.for_children)
+    }
+
+    /// The namespaces of a new child, or of this task after `execve()`: both
+    /// are the namespace meant for children, which is now in use.
+    pub fn for_child(&self) -> Self {
+        self.for_children.enter();
+
+        Self {
+            current: self.for_children.clone(),
+            for_children: self.for_children.clone(),
+        }
+    }
+
+    /// Creates a new namespace for future children, starting from the offsets
+    /// of the current one.
+    pub fn unshare(&mut self) {
+        self.for_children = Arc::new(TimeNamespace::new(self.current.offsets()));
+    }
+
+    /// Reads `CLOCK_MONOTONIC` as seen in the task's namespace.
+    pub fn monotonic(&self) -> Duration {
+        shift(uptime(), self.current.offsets().monotonic).unwrap_or_default()
+    }
+
+    /// Reads `CLOCK_BOOTTIME` as seen in the task's namespace.
+    pub fn boottime(&self) -> Duration {
+        shift(boottime(), self.current.offsets().boottime).unwrap_or_default()
+    }
+
+    /// Converts a `CLOCK_MONOTONIC` reading taken in the task's namespace back
+    /// to the kernel's own clock.
+    pub fn monotonic_to_host(&self, time: Duration) -> Duration {
+        shift(time, -self.current.offsets().monotonic).unwrap_or_default()
+    }
+}
+
+/// Formats `offsets` as read from `/proc/<pid>/timens_offsets`.
+pub fn format_offsets(offsets: TimeOffsets) -> String {
+    let split = |offset: i64| {
+        (
+            offset.div_euclid(NSEC_PER_SEC),
+            offset.rem_euclid(NSEC_PER_SEC),
+        )
+    };
+
+    let (mono_secs, mono_nsecs) = split(offsets.monotonic);
+    let (boot_secs, boot_nsecs) = split(offsets.boottime);
+
+    format!("monotonic {mono_secs} {mono_nsecs}\nboottime {boot_secs} {boot_nsecs}\n")
+}
+
+/// Parses one `<clock> <secs> <nsecs>` line written to
+/// `/proc/<pid>/timens_offsets`. The clock may be given by name or number.
+pub fn parse_offset(line: &str) -> Result<(ClockId, i64, i64)> {
+    let mut fields = line.split_whitespace();
+    let (Some(clock), Some(secs), Some(nsecs), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err(KernelError::InvalidValue);
+    };
+
+    let clock = match clock {
+        "monotonic" => ClockId::Monotonic,
+        "boottime" => ClockId::BootTime,
+        _ => clock
+            .parse::<i32>()
+            .ok()
+            .and_then(|id| ClockId::try_from(id).ok())
+            .ok_or(KernelError::InvalidValue)?,
+    };
+
+    let secs = secs.parse().map_err(|_| KernelError::InvalidValue)?;
+    let nsecs = nsecs.parse().map_err(|_| KernelError::InvalidValue)?;
+
+    Ok((clock, secs, nsecs))
+}
@@ -31,6 +31,25 @@ impl From<Duration> for TimeSpec {
     }
 }
 
+/// A time in seconds and microseconds, as `struct timeval`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeVal {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+unsafe impl UserCopyable for TimeVal {}
+
+impl From<Duration> for TimeVal {
+    fn from(value: Duration) -> Self {
+        TimeVal {
+            tv_sec: value.as_secs() as _,
+            tv_usec: value.subsec_micros() as _,
+        }
+    }
+}
+
 impl TimeSpec {
     pub async fn copy_from_user(src: TUA<Self>) -> Result<Self> {
         let timespec = copy_from_user(src).await?;
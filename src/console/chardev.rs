@@ -15,7 +15,7 @@ use libkernel::{
     fs::{OpenFlags, attr::FilePermissions},
 };
 
-use super::CONSOLE;
+use super::primary_console;
 
 struct TtyDev {}
 
@@ -35,14 +35,11 @@ struct ConsoleDev {}
 
 impl OpenableDevice for ConsoleDev {
     fn open(&self, flags: OpenFlags) -> Result<Arc<OpenFile>> {
-        let char_dev_desc = match *CONSOLE.lock_save_irq() {
-            super::ConsoleState::Buffered => return Err(FsError::NoDevice.into()),
-            super::ConsoleState::Device(_, char_dev_descriptor) => char_dev_descriptor,
-        };
+        let char_dev_desc = primary_console().ok_or(FsError::NoDevice)?;
 
         let char_driver = DM
             .lock_save_irq()
-            .find_char_driver(char_dev_desc.major)
+            .find_char_driver(char_dev_desc.major, char_dev_desc.minor)
             .ok_or(FsError::NoDevice)?;
 
         char_driver
@@ -74,7 +71,8 @@ impl ConsoleCharDev {
                 major: ReservedMajors::Console as _,
                 minor: 0,
             },
-            FilePermissions::from_bits_retain(0o600),
+            // Every process may open its own controlling terminal.
+            FilePermissions::from_bits_retain(0o666),
         )?;
 
         Ok(Self {
@@ -1,14 +1,21 @@
-use alloc::sync::{Arc, Weak};
+use alloc::{
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::{
     fmt::{self, Write},
     ptr::addr_of_mut,
     str,
 };
-use libkernel::{driver::CharDevDescriptor, error::KernelError};
-use log::{LevelFilter, Log};
+use libkernel::driver::CharDevDescriptor;
+use log::{LevelFilter, Log, info, warn};
 use tty::TtyInputHandler;
 
-use crate::{drivers::timer::uptime, sync::SpinLock};
+use crate::{
+    drivers::{timer::uptime, uart::earlycon},
+    sync::SpinLock,
+};
 
 mod buf;
 pub mod tty;
@@ -21,61 +28,202 @@ pub trait Console: Send + Sync {
     fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result;
     fn write_buf(&self, buf: &[u8]);
 
+    /// Blocks until all output buffered by the console has been sent to the
+    /// device.
+    fn flush(&self) {}
+
     /// Registers a handler that will receive input bytes.
     fn register_input_handler(&self, handler: Weak<dyn TtyInputHandler>);
 }
 
 static mut EARLY_BOOT_BUFFER: BufConsole = BufConsole::new();
 
+/// A console device that receives kernel output.
+struct ConsoleSink {
+    console: Arc<dyn Console>,
+    char_dev: CharDevDescriptor,
+    /// Which sink backs `/dev/console`: the highest ranked one wins.
+    rank: usize,
+}
+
 /// Current console state.
-enum ConsoleState {
-    /// Early boot, messages are written to a temporary memory buffer.
-    Buffered,
-    /// A real console driver has been initialized.
-    Device(Arc<dyn Console>, CharDevDescriptor),
+struct ConsoleState {
+    /// A polled console set up with `--earlycon`, used until the first real
+    /// console registers.
+    boot: Option<Arc<dyn Console>>,
+    /// Real console drivers, all of which receive every message. While there
+    /// are none, messages are kept in `EARLY_BOOT_BUFFER`.
+    sinks: Vec<ConsoleSink>,
+}
+
+static CONSOLE: SpinLock<ConsoleState> = SpinLock::new(ConsoleState {
+    boot: None,
+    sinks: Vec::new(),
+});
+
+/// A console asked for on the command line with `--console=NAME[,BAUD]`.
+struct ConsoleSelection {
+    name: String,
+    baud: Option<u32>,
 }
 
-static CONSOLE: SpinLock<ConsoleState> = SpinLock::new(ConsoleState::Buffered);
+static SELECTED: SpinLock<Vec<ConsoleSelection>> = SpinLock::new(Vec::new());
 
-/// Writes formatted output to the active console.
+/// Writes formatted output to every registered console.
 pub fn write_fmt(args: fmt::Arguments) -> fmt::Result {
     let console_state = CONSOLE.lock_save_irq();
 
-    match *console_state {
-        ConsoleState::Buffered => {
-            // SAFETY: The lock on CONSOLE_STATE ensures that no other thread
-            // can be reading or writing to the buffer at the same time.
-            unsafe { (*addr_of_mut!(EARLY_BOOT_BUFFER)).write_fmt(args) }
+    if console_state.sinks.is_empty() {
+        if let Some(ref boot) = console_state.boot {
+            let _ = boot.write_fmt(args);
+        }
+
+        // SAFETY: The lock on CONSOLE_STATE ensures that no other thread
+        // can be reading or writing to the buffer at the same time.
+        return unsafe { (*addr_of_mut!(EARLY_BOOT_BUFFER)).write_fmt(args) };
+    }
+
+    for sink in console_state.sinks.iter() {
+        sink.console.write_fmt(args)?;
+    }
+
+    Ok(())
+}
+
+/// Waits for all output written so far to reach the console devices.
+///
+/// Consoles may send output in the background, call this before stopping the
+/// machine so the last messages aren't lost.
+pub fn flush() {
+    for sink in CONSOLE.lock_save_irq().sinks.iter() {
+        sink.console.flush();
+    }
+}
+
+/// Reads the console options from the kernel command line.
+///
+/// Any number of `--console=NAME[,BAUD]` options pick the devices that kernel
+/// output goes to, the last one also backing `/dev/console`. Without them, the
+/// firmware's choice of console is used. `--earlycon[=DRIVER,ADDRESS]` prints
+/// messages from before device probing as they happen, rather than once the
+/// console driver comes up.
+///
+/// Must be called before devices are probed.
+pub fn setup_from_cmdline(cmdline: &str) {
+    let mut earlycon = None;
+
+    for arg in cmdline.split(' ') {
+        if let Some(value) = arg.strip_prefix("--console=") {
+            let mut parts = value.split(',');
+            let name = parts.next().unwrap_or_default();
+
+            // Options look like `115200n8`; only the speed is configurable.
+            let baud = parts.next().and_then(|opts| {
+                let digits = opts
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(opts.len());
+
+                opts[..digits].parse().ok()
+            });
+
+            SELECTED.lock_save_irq().push(ConsoleSelection {
+                name: name.to_string(),
+                baud,
+            });
+        } else if arg == "--earlycon" {
+            earlycon = Some(None);
+        } else if let Some(value) = arg.strip_prefix("--earlycon=") {
+            earlycon = Some(Some(value));
+        }
+    }
+
+    if let Some(spec) = earlycon {
+        match earlycon::setup(spec) {
+            Ok(console) => CONSOLE.lock_save_irq().boot = Some(console),
+            Err(e) => warn!("Could not set up early console: {e}"),
         }
-        ConsoleState::Device(ref console, _) => console.write_fmt(args),
     }
 }
 
-/// Switches the active console from buffer to a real device and flushes output.
-pub fn set_active_console(
+/// Returns the baud rate asked for on the command line for the console
+/// `name`, if any.
+pub fn console_baud(name: &str) -> Option<u32> {
+    SELECTED
+        .lock_save_irq()
+        .iter()
+        .rev()
+        .find(|sel| sel.name == name)
+        .and_then(|sel| sel.baud)
+}
+
+/// Offers a console device for kernel output.
+///
+/// The device is used if it was selected on the command line or, when nothing
+/// was, if `firmware_default` says the firmware picked it. The first console
+/// to be used is given the messages logged so far.
+///
+/// # Returns
+/// * `true` if the device now receives kernel output.
+pub fn register_console(
+    name: String,
     console: Arc<dyn Console>,
     char_dev: CharDevDescriptor,
-) -> Result<(), KernelError> {
-    let mut console_state = CONSOLE.lock_save_irq();
-
-    let old_state = core::mem::replace(
-        &mut *console_state,
-        ConsoleState::Device(console.clone(), char_dev),
-    );
-
-    // If the old state was the buffer, flush its contents to the new device.
-    if let ConsoleState::Buffered = old_state {
-        // SAFETY: We still hold the lock, and since we just transitioned the
-        // state away from `Buffered`, we have exclusive, one-time access to
-        // read the buffer's contents. No new writers can appear.
-        let buf_contents = unsafe { (*addr_of_mut!(EARLY_BOOT_BUFFER)).data() };
-
-        if let Ok(s) = str::from_utf8(buf_contents) {
-            let _ = console.write_fmt(format_args!("{s}"));
+    firmware_default: bool,
+) -> bool {
+    let rank = {
+        let selected = SELECTED.lock_save_irq();
+
+        if selected.is_empty() {
+            firmware_default.then_some(0)
+        } else {
+            selected.iter().rposition(|sel| sel.name == name)
         }
+    };
+
+    let Some(rank) = rank else {
+        return false;
+    };
+
+    {
+        let mut console_state = CONSOLE.lock_save_irq();
+
+        // The early buffer has already been shown if there was a boot console,
+        // which the real one takes over from.
+        if console_state.sinks.is_empty() && console_state.boot.take().is_none() {
+            // SAFETY: We hold the lock, and once the first sink is registered
+            // nothing writes to the buffer again. No new writers can appear.
+            let buf_contents = unsafe { (*addr_of_mut!(EARLY_BOOT_BUFFER)).data() };
+
+            if str::from_utf8(buf_contents).is_ok() {
+                // The console may only buffer so much output before dropping
+                // it, so hand the backlog over a piece at a time.
+                for chunk in buf_contents.chunks(1024) {
+                    console.write_buf(chunk);
+                    console.flush();
+                }
+            }
+        }
+
+        console_state.sinks.push(ConsoleSink {
+            console,
+            char_dev,
+            rank,
+        });
     }
 
-    Ok(())
+    info!("console [{name}] enabled");
+
+    true
+}
+
+/// Returns the device that backs `/dev/console`.
+fn primary_console() -> Option<CharDevDescriptor> {
+    CONSOLE
+        .lock_save_irq()
+        .sinks
+        .iter()
+        .max_by_key(|sink| sink.rank)
+        .map(|sink| sink.char_dev)
 }
 
 struct ConsoleLogger;
@@ -100,10 +248,19 @@ impl Log for ConsoleLogger {
         ));
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        flush();
+    }
 }
 
 pub fn setup_console_logger() {
     let _ = log::set_logger(&CONSOLE_LOGGER);
-    log::set_max_level(LevelFilter::Trace);
+
+    // Without the `tracing` feature, debug and trace messages are dropped
+    // before they're formatted.
+    log::set_max_level(if cfg!(feature = "tracing") {
+        LevelFilter::Trace
+    } else {
+        LevelFilter::Info
+    });
 }
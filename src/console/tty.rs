@@ -4,7 +4,7 @@ use crate::{
     memory::uaccess::{copy_from_user, copy_from_user_slice, copy_to_user},
     process::thread_group::{
         Pgid,
-        signal::{InterruptResult, Interruptable},
+        signal::{InterruptResult, Interruptable, SigId, kill::send_signal_to_pg},
     },
     sched::current_work,
     sync::SpinLock,
@@ -21,9 +21,10 @@ use libkernel::{
     error::{KernelError, Result},
     fs::SeekFrom,
     memory::address::{TUA, UA},
+    proc::caps::CapabilitiesFlags,
 };
 use meta::{
-    TCGETS, TCGETS2, TCSETS, TCSETS2, TCSETSW, TCSETSW2, TIOCGPGRP, TIOCGWINSZ, TIOCSPGRP,
+    TCGETS, TCGETS2, TCSETS, TCSETS2, TCSETSW, TCSETSW2, TIOCGPGRP, TIOCGWINSZ, TIOCSPGRP, TIOCSTI,
     TIOCSWINSZ, Termios, Termios2, TermiosOutputFlags, TtyMetadata,
 };
 
@@ -82,6 +83,36 @@ impl Tty {
             }
         }
     }
+
+    /// Checks that the current process may change the terminal's settings.
+    ///
+    /// A process outside the foreground process group is stopped with
+    /// `SIGTTOU`, unless it blocks or ignores it, as shells do while handing
+    /// the terminal between jobs.
+    fn check_change(&self) -> Result<()> {
+        let task = current_work();
+
+        let Some(fg_pg) = self.meta.lock_save_irq().fg_pg else {
+            return Ok(());
+        };
+
+        let pgid = *task.process.pgid.lock_save_irq();
+
+        if pgid == fg_pg
+            || task.sig_mask.load().contains(SigId::SIGTTOU.into())
+            || task
+                .process
+                .signals
+                .lock_save_irq()
+                .is_ignored(SigId::SIGTTOU)
+        {
+            return Ok(());
+        }
+
+        send_signal_to_pg(pgid, SigId::SIGTTOU);
+
+        Err(KernelError::Interrupted)
+    }
 }
 
 #[async_trait]
@@ -118,6 +149,10 @@ impl FileOps for Tty {
         }
     }
 
+    fn is_stream(&self) -> bool {
+        true
+    }
+
     fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
         let (read_ready_fut, eof_fut) = {
             let cooker = self.input_cooker.lock_save_irq();
@@ -200,10 +235,38 @@ impl FileOps for Tty {
             TIOCSPGRP => {
                 let pgid: Pgid = copy_from_user(TUA::from_value(argp)).await?;
 
+                if (pgid.value() as i32) < 0 {
+                    return Err(KernelError::InvalidValue);
+                }
+
+                self.check_change()?;
+
+                // The new foreground group must be in the caller's session.
+                let sid = pgid.session().ok_or(KernelError::NoProcess)?;
+
+                if sid != *current_work().process.sid.lock_save_irq() {
+                    return Err(KernelError::NotPermitted);
+                }
+
                 self.meta.lock_save_irq().fg_pg = Some(pgid);
 
                 return Ok(0);
             }
+            TIOCSTI => {
+                // Faking input lets a process run commands as whoever reads
+                // the terminal, so it's reserved for the administrator.
+                current_work()
+                    .creds
+                    .lock_save_irq()
+                    .caps()
+                    .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)?;
+
+                let byte: u8 = copy_from_user(TUA::from_value(argp)).await?;
+
+                self.input_cooker.push_byte(byte);
+
+                return Ok(0);
+            }
             TCGETS => {
                 let termios: Termios = self.meta.lock_save_irq().termios.into();
 
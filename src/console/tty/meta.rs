@@ -92,6 +92,7 @@ pub const TIOCGWINSZ: usize = 0x5413;
 pub const TIOCSWINSZ: usize = 0x5414;
 pub const TIOCGPGRP: usize = 0x540F;
 pub const TIOCSPGRP: usize = 0x5410;
+pub const TIOCSTI: usize = 0x5412;
 pub const TCGETS2: usize = 0x802c542a;
 pub const TCSETS2: usize = 0x402c542b;
 pub const TCSETSW2: usize = 0x402c542c;
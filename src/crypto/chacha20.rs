@@ -0,0 +1,179 @@
+//! The ChaCha20 stream cipher, as specified in RFC 8439.
+//!
+//! This is the output stage of the kernel's CSPRNG: each CPU's generator is
+//! a ChaCha20 keystream keyed from the entropy pool.
+
+/// Size of a ChaCha20 key, in bytes.
+pub const KEY_LEN: usize = 32;
+/// Size of a ChaCha20 nonce, in bytes.
+pub const NONCE_LEN: usize = 12;
+
+const BLOCK_LEN: usize = 64;
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+fn chacha20_block(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; NONCE_LEN]) -> [u8; BLOCK_LEN] {
+    let mut init = [0u32; 16];
+
+    // "expand 32-byte k"
+    init[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+
+    for (w, chunk) in init[4..12].iter_mut().zip(key.as_chunks::<4>().0) {
+        *w = u32::from_le_bytes(*chunk);
+    }
+
+    init[12] = counter;
+
+    for (w, chunk) in init[13..].iter_mut().zip(nonce.as_chunks::<4>().0) {
+        *w = u32::from_le_bytes(*chunk);
+    }
+
+    let mut s = init;
+
+    for _ in 0..10 {
+        quarter_round(&mut s, 0, 4, 8, 12);
+        quarter_round(&mut s, 1, 5, 9, 13);
+        quarter_round(&mut s, 2, 6, 10, 14);
+        quarter_round(&mut s, 3, 7, 11, 15);
+        quarter_round(&mut s, 0, 5, 10, 15);
+        quarter_round(&mut s, 1, 6, 11, 12);
+        quarter_round(&mut s, 2, 7, 8, 13);
+        quarter_round(&mut s, 3, 4, 9, 14);
+    }
+
+    let mut out = [0; BLOCK_LEN];
+
+    for ((chunk, s), i) in out.as_chunks_mut::<4>().0.iter_mut().zip(s).zip(init) {
+        *chunk = s.wrapping_add(i).to_le_bytes();
+    }
+
+    out
+}
+
+/// A ChaCha20 keystream.
+pub struct ChaCha20 {
+    key: [u8; KEY_LEN],
+    nonce: [u8; NONCE_LEN],
+    counter: u32,
+    /// The current block, of which the bytes from `used` on haven't been
+    /// handed out yet.
+    block: [u8; BLOCK_LEN],
+    used: usize,
+}
+
+impl ChaCha20 {
+    /// Starts the keystream of `key` and `nonce` at block `counter`.
+    pub fn new(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], counter: u32) -> Self {
+        Self {
+            key: *key,
+            nonce: *nonce,
+            counter,
+            block: [0; BLOCK_LEN],
+            used: BLOCK_LEN,
+        }
+    }
+
+    fn next_block(&mut self) {
+        self.block = chacha20_block(&self.key, self.counter, &self.nonce);
+        self.used = 0;
+        self.counter = self.counter.wrapping_add(1);
+
+        // Past the 256 GiB RFC 8439 allows for one nonce, carry into the
+        // nonce rather than repeat the stream.
+        if self.counter == 0 {
+            let word = u32::from_le_bytes(self.nonce[..4].try_into().unwrap());
+            self.nonce[..4].copy_from_slice(&word.wrapping_add(1).to_le_bytes());
+        }
+    }
+
+    /// Fills `buf` with the next bytes of the keystream.
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        let mut buf = buf;
+
+        while !buf.is_empty() {
+            if self.used == BLOCK_LEN {
+                self.next_block();
+            }
+
+            let n = (BLOCK_LEN - self.used).min(buf.len());
+            let (head, rest) = buf.split_at_mut(n);
+
+            head.copy_from_slice(&self.block[self.used..self.used + n]);
+            self.used += n;
+            buf = rest;
+        }
+    }
+}
+
+impl Drop for ChaCha20 {
+    fn drop(&mut self) {
+        self.key.fill(0);
+        self.block.fill(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChaCha20;
+    use alloc::vec::Vec;
+    use moss_macros::ktest;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // RFC 8439, section 2.3.2.
+    #[ktest]
+    fn chacha20_block_rfc8439() {
+        let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let nonce = hex("000000090000004a00000000");
+        let mut out = [0; 64];
+
+        ChaCha20::new(&key, nonce.as_slice().try_into().unwrap(), 1).fill(&mut out);
+
+        assert_eq!(
+            out.as_slice(),
+            hex(
+                "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4e\
+                 d2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e"
+            )
+        );
+    }
+
+    // RFC 8439, section 2.4.2, filled in uneven pieces.
+    #[ktest]
+    fn chacha20_keystream_rfc8439() {
+        let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let nonce = hex("000000000000004a00000000");
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one \
+                          tip for the future, sunscreen would be it.";
+        let expected = hex(
+            "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b\
+             f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8\
+             07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736\
+             5af90bbf74a35be6b40b8eedf2785e42874d",
+        );
+
+        let mut stream = ChaCha20::new(&key, nonce.as_slice().try_into().unwrap(), 1);
+        let mut ks = [0; 114];
+
+        for chunk in ks.chunks_mut(37) {
+            stream.fill(chunk);
+        }
+
+        let ct: Vec<u8> = plaintext.iter().zip(ks).map(|(p, k)| p ^ k).collect();
+        assert_eq!(ct, expected);
+    }
+}
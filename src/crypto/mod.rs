@@ -0,0 +1,47 @@
+//! In-kernel cryptographic primitives.
+//!
+//! This is a deliberately small set of algorithms shared by kernel
+//! subsystems that need them: ChaCha20 drives the CSPRNG, and SHA-256 the
+//! block-level integrity checking. Hash users should go through the [`Hash`]
+//! trait rather than a specific algorithm where they can, so that algorithms
+//! can be added without changing them.
+
+pub mod chacha20;
+pub mod sha256;
+
+/// A cryptographic hash function.
+pub trait Hash: Clone {
+    /// Size of the digest, in bytes.
+    const OUTPUT_LEN: usize;
+
+    /// The digest type.
+    type Output: AsRef<[u8]> + Copy;
+
+    /// Creates a new hasher in its initial state.
+    fn new() -> Self;
+
+    /// Feeds `data` into the hash.
+    fn update(&mut self, data: &[u8]);
+
+    /// Consumes the hasher and returns the digest.
+    fn finalize(self) -> Self::Output;
+
+    /// Hashes `data` in one go.
+    fn digest(data: &[u8]) -> Self::Output {
+        let mut h = Self::new();
+        h.update(data);
+        h.finalize()
+    }
+}
+
+/// Compares two byte strings in time that depends only on their lengths.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+
+    // Keep the compiler from turning the fold into an early-exit comparison.
+    core::hint::black_box(diff) == 0
+}
@@ -17,6 +17,8 @@ use libkernel::{
     memory::address::UA,
 };
 
+const NULL_MINOR: u64 = 3;
+
 /// `/dev/null` file operations.
 struct NullFileOps;
 
@@ -61,8 +63,8 @@ impl NullCharDev {
         devfs().mknod(
             "null".to_string(),
             CharDevDescriptor {
-                major: ReservedMajors::Null as _,
-                minor: 0,
+                major: ReservedMajors::Mem as _,
+                minor: NULL_MINOR,
             },
             // World-writable, world-readable like on Linux.
             FilePermissions::from_bits_retain(0o666),
@@ -76,7 +78,7 @@ impl NullCharDev {
 
 impl CharDriver for NullCharDev {
     fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>> {
-        if minor == 0 {
+        if minor == NULL_MINOR {
             Some(self.null_dev.clone())
         } else {
             None
@@ -86,7 +88,11 @@ impl CharDriver for NullCharDev {
 
 pub fn null_chardev_init(_bus: &mut PlatformBus, dm: &mut DriverManager) -> Result<()> {
     let cdev = NullCharDev::new()?;
-    dm.register_char_driver(ReservedMajors::Null as _, Arc::new(cdev))
+    dm.register_char_region(
+        ReservedMajors::Mem as _,
+        NULL_MINOR..NULL_MINOR + 1,
+        Arc::new(cdev),
+    )
 }
 
 kernel_driver!(null_chardev_init);
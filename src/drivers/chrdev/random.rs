@@ -21,6 +21,8 @@ use libkernel::{
     memory::address::UA,
 };
 
+const RANDOM_MINOR: u64 = 8;
+
 struct RandomFileOps;
 
 #[async_trait]
@@ -65,8 +67,8 @@ impl RandomCharDev {
         devfs().mknod(
             "random".to_string(),
             CharDevDescriptor {
-                major: ReservedMajors::Random as _,
-                minor: 0,
+                major: ReservedMajors::Mem as _,
+                minor: RANDOM_MINOR,
             },
             FilePermissions::from_bits_retain(0o666),
         )?;
@@ -79,7 +81,7 @@ impl RandomCharDev {
 
 impl CharDriver for RandomCharDev {
     fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>> {
-        if minor == 0 {
+        if minor == RANDOM_MINOR {
             Some(self.random_dev.clone())
         } else {
             None
@@ -90,7 +92,11 @@ impl CharDriver for RandomCharDev {
 /// Driver initialisation entry point invoked during kernel boot.
 pub fn random_chardev_init(_bus: &mut PlatformBus, dm: &mut DriverManager) -> Result<()> {
     let cdev = RandomCharDev::new()?;
-    dm.register_char_driver(ReservedMajors::Random as _, Arc::new(cdev))
+    dm.register_char_region(
+        ReservedMajors::Mem as _,
+        RANDOM_MINOR..RANDOM_MINOR + 1,
+        Arc::new(cdev),
+    )
 }
 
 kernel_driver!(random_chardev_init);
@@ -17,6 +17,8 @@ use libkernel::{
     memory::address::UA,
 };
 
+const ZERO_MINOR: u64 = 5;
+
 const USER_COPY_CHUNK_SIZE: usize = 0x100;
 
 static ZERO_BUF: [u8; USER_COPY_CHUNK_SIZE] = [0u8; USER_COPY_CHUNK_SIZE];
@@ -77,8 +79,8 @@ impl ZeroCharDev {
         devfs().mknod(
             "zero".to_string(),
             CharDevDescriptor {
-                major: ReservedMajors::Zero as _,
-                minor: 0,
+                major: ReservedMajors::Mem as _,
+                minor: ZERO_MINOR,
             },
             FilePermissions::from_bits_retain(0o666),
         )?;
@@ -91,7 +93,7 @@ impl ZeroCharDev {
 
 impl CharDriver for ZeroCharDev {
     fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>> {
-        if minor == 0 {
+        if minor == ZERO_MINOR {
             Some(self.zero_dev.clone())
         } else {
             None
@@ -102,7 +104,11 @@ impl CharDriver for ZeroCharDev {
 /// Driver initialisation entry point invoked during kernel boot.
 pub fn zero_chardev_init(_bus: &mut PlatformBus, dm: &mut DriverManager) -> Result<()> {
     let cdev = ZeroCharDev::new()?;
-    dm.register_char_driver(ReservedMajors::Zero as _, Arc::new(cdev))
+    dm.register_char_region(
+        ReservedMajors::Mem as _,
+        ZERO_MINOR..ZERO_MINOR + 1,
+        Arc::new(cdev),
+    )
 }
 
 kernel_driver!(zero_chardev_init);
@@ -170,10 +170,9 @@ impl FileOps for FbFileOps {
         const FBIOPAN_DISPLAY: usize = 0x4606;
 
         match request {
-            FBIOGET_VSCREENINFO => todo!(),
-            FBIOPUT_VSCREENINFO => todo!(),
-            FBIOGET_FSCREENINFO => todo!(),
-            FBIOPAN_DISPLAY => todo!(),
+            FBIOGET_VSCREENINFO | FBIOPUT_VSCREENINFO | FBIOGET_FSCREENINFO | FBIOPAN_DISPLAY => {
+                Err(KernelError::NotSupported)
+            }
             _ => Err(KernelError::InvalidValue),
         }
     }
@@ -203,8 +202,8 @@ impl FbCharDev {
                 major: ReservedMajors::Fb as _,
                 minor: 0,
             },
-            // World-writable, world-readable like on Linux.
-            FilePermissions::from_bits_retain(0o666),
+            // Like Linux, only root and the video group may draw to it.
+            FilePermissions::from_bits_retain(0o660),
         )?;
 
         Ok(Self {
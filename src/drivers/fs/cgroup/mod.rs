@@ -25,7 +25,7 @@ use libkernel::{
     fs::{
         BlockDevice, CGROUPFS_ID, DirStream, Dirent, FileType, Filesystem, Inode, InodeId,
         SimpleDirStream,
-        attr::{FileAttr, FilePermissions},
+        attr::{FileAttr, FileOwner, FilePermissions},
     },
 };
 use log::warn;
@@ -282,6 +282,7 @@ impl Inode for CgroupDirInode {
         name: &str,
         file_type: FileType,
         _permissions: FilePermissions,
+        _owner: FileOwner,
         _time: Option<core::time::Duration>,
     ) -> Result<Arc<dyn Inode>> {
         if file_type != FileType::Directory {
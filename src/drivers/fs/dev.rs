@@ -1,3 +1,4 @@
+use crate::clock::realtime::date;
 use crate::drivers::{Driver, FilesystemDriver};
 use crate::sync::{OnceLock, SpinLock};
 use alloc::{
@@ -5,11 +6,13 @@ use alloc::{
     collections::BTreeMap,
     string::{String, ToString},
     sync::Arc,
+    vec::Vec,
 };
 use async_trait::async_trait;
 use core::any::Any;
 use core::sync::atomic::{AtomicU64, Ordering};
 use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::pathbuf::PathBuf;
 use libkernel::fs::{BlockDevice, DirStream, Dirent, Filesystem};
 use libkernel::{
     driver::CharDevDescriptor,
@@ -25,31 +28,42 @@ pub struct DevFs {
 
 impl DevFs {
     pub fn new() -> Arc<Self> {
-        let shm = DevFsINode {
-            id: InodeId::from_fsid_and_inodeid(DEVFS_ID, 1),
-            attr: SpinLock::new(FileAttr {
-                file_type: FileType::Directory,
-                permissions: FilePermissions::from_bits_retain(0o755),
-                ..FileAttr::default()
-            }),
-            kind: InodeKind::Directory(SpinLock::new(BTreeMap::new())),
-        };
+        // Like /tmp, anyone may make shared memory objects in /dev/shm.
+        let shm = DevFsINode::new(
+            InodeId::from_fsid_and_inodeid(DEVFS_ID, 1),
+            FilePermissions::from_bits_retain(0o1777),
+            InodeKind::Directory(SpinLock::new(DevDir::new(BTreeMap::new()))),
+        );
         let mut root_children = BTreeMap::new();
         root_children.insert("shm".to_string(), Arc::new(shm));
-        let root_inode = Arc::new(DevFsINode {
-            id: InodeId::from_fsid_and_inodeid(DEVFS_ID, 0),
-            attr: SpinLock::new(FileAttr {
-                file_type: FileType::Directory,
-                permissions: FilePermissions::from_bits_retain(0o755),
-                ..FileAttr::default()
-            }),
-            kind: InodeKind::Directory(SpinLock::new(root_children)),
-        });
+        let root_inode = Arc::new(DevFsINode::new(
+            InodeId::from_fsid_and_inodeid(DEVFS_ID, 0),
+            FilePermissions::from_bits_retain(0o755),
+            InodeKind::Directory(SpinLock::new(DevDir::new(root_children))),
+        ));
 
-        Arc::new(Self {
+        let devfs = Arc::new(Self {
             root: root_inode,
             next_inode_id: AtomicU64::new(2),
-        })
+        });
+
+        // The links through to the calling process's open files.
+        for (name, target) in [
+            ("fd", "/proc/self/fd"),
+            ("stdin", "/proc/self/fd/0"),
+            ("stdout", "/proc/self/fd/1"),
+            ("stderr", "/proc/self/fd/2"),
+        ] {
+            devfs
+                .add_node(
+                    name.to_string(),
+                    InodeKind::Symlink(PathBuf::from(target)),
+                    FilePermissions::from_bits_retain(0o777),
+                )
+                .expect("devfs starts out empty");
+        }
+
+        devfs
     }
 
     pub fn mknod(
@@ -58,13 +72,41 @@ impl DevFs {
         device_id: CharDevDescriptor,
         permissions: FilePermissions,
     ) -> Result<()> {
+        self.add_node(name, InodeKind::CharDevice { device_id }, permissions)
+    }
+
+    /// Creates a block device node.
+    pub fn mknod_blk(
+        &self,
+        name: String,
+        device_id: CharDevDescriptor,
+        permissions: FilePermissions,
+    ) -> Result<()> {
+        self.add_node(name, InodeKind::BlockDevice { device_id }, permissions)
+    }
+
+    /// Removes a device node.
+    pub fn remove_node(&self, name: &str) -> Result<()> {
+        let InodeKind::Directory(ref children) = self.root.kind else {
+            return Err(FsError::InvalidFs.into());
+        };
+
+        let mut dir = children.lock_save_irq();
+
+        dir.children.remove(name).ok_or(FsError::NotFound)?;
+        dir.listing = None;
+
+        Ok(())
+    }
+
+    fn add_node(&self, name: String, kind: InodeKind, permissions: FilePermissions) -> Result<()> {
         let InodeKind::Directory(ref children) = self.root.kind else {
             // This should be impossible as the root is always a directory.
             return Err(FsError::InvalidFs.into());
         };
 
-        let mut children = children.lock_save_irq();
-        if children.contains_key(&name) {
+        let mut dir = children.lock_save_irq();
+        if dir.children.contains_key(&name) {
             return Err(KernelError::InUse);
         }
 
@@ -73,19 +115,10 @@ impl DevFs {
             self.next_inode_id.fetch_add(1, Ordering::SeqCst),
         );
 
-        let new_inode = Arc::new(DevFsINode {
-            id,
-            attr: SpinLock::new(FileAttr {
-                id,
-                file_type: FileType::CharDevice(device_id),
-                permissions,
-                ..FileAttr::default()
-            }),
-            // This is the crucial part: we store the device handle.
-            kind: InodeKind::CharDevice { device_id },
-        });
+        dir.children
+            .insert(name, Arc::new(DevFsINode::new(id, permissions, kind)));
+        dir.listing = None;
 
-        children.insert(name.to_string(), new_inode);
         Ok(())
     }
 }
@@ -107,29 +140,82 @@ impl Filesystem for DevFs {
     }
 }
 
+/// A directory's entries in the order they were made.
+type DevListing = Arc<[(String, Arc<DevFsINode>)]>;
+
+/// A directory's entries, by name.
+struct DevDir {
+    children: BTreeMap<String, Arc<DevFsINode>>,
+    /// The entries in listing order, made by the first readdir since they
+    /// last changed, so that listings don't copy and sort them under the
+    /// lock every time.
+    listing: Option<DevListing>,
+}
+
+impl DevDir {
+    fn new(children: BTreeMap<String, Arc<DevFsINode>>) -> Self {
+        Self {
+            children,
+            listing: None,
+        }
+    }
+
+    /// Returns the entries in the order they were made.
+    fn listing(&mut self) -> &DevListing {
+        self.listing.get_or_insert_with(|| {
+            let mut listing: Vec<_> = self
+                .children
+                .iter()
+                .map(|(name, inode)| (name.clone(), inode.clone()))
+                .collect();
+            listing.sort_by_key(|(_, inode)| inode.id.inode_id());
+
+            listing.into()
+        })
+    }
+}
+
 enum InodeKind {
     /// A directory, which contains a map of names to child inodes.
-    Directory(SpinLock<BTreeMap<String, Arc<DevFsINode>>>),
+    Directory(SpinLock<DevDir>),
     /// A character device, which stores its major/minor handle (`dev_t`).
     CharDevice { device_id: CharDevDescriptor },
+    /// A block device, which stores its major/minor handle (`dev_t`).
+    BlockDevice { device_id: CharDevDescriptor },
+    /// A symbolic link, which stores its target.
+    Symlink(PathBuf),
 }
 
+impl InodeKind {
+    fn file_type(&self) -> FileType {
+        match *self {
+            InodeKind::CharDevice { device_id } => FileType::CharDevice(device_id),
+            InodeKind::BlockDevice { device_id } => FileType::BlockDevice(device_id),
+            InodeKind::Directory(_) => FileType::Directory,
+            InodeKind::Symlink(_) => FileType::Symlink,
+        }
+    }
+}
+
+/// Lists a directory in the order its entries were made. Each entry's offset
+/// is one past its inode number, so a listing picks up where it left off even
+/// if devices come and go in between.
 struct DevDirStreamer {
-    children: BTreeMap<String, Arc<DevFsINode>>,
+    children: DevListing,
     idx: usize,
 }
 
 #[async_trait]
 impl DirStream for DevDirStreamer {
     async fn next_entry(&mut self) -> Result<Option<Dirent>> {
-        if let Some((name, inode)) = self.children.iter().nth(self.idx) {
+        if let Some((name, inode)) = self.children.get(self.idx) {
             self.idx += 1;
 
             Ok(Some(Dirent {
                 id: inode.id,
                 name: name.clone(),
-                file_type: inode.attr.lock_save_irq().file_type,
-                offset: self.idx as u64,
+                file_type: inode.kind.file_type(),
+                offset: inode.id.inode_id() + 1,
             }))
         } else {
             Ok(None)
@@ -144,6 +230,33 @@ struct DevFsINode {
     kind: InodeKind,
 }
 
+impl DevFsINode {
+    fn new(id: InodeId, permissions: FilePermissions, kind: InodeKind) -> Self {
+        let now = date();
+        let size = match &kind {
+            InodeKind::Symlink(target) => target.as_str().len() as u64,
+            _ => 0,
+        };
+
+        Self {
+            id,
+            attr: SpinLock::new(FileAttr {
+                id,
+                file_type: kind.file_type(),
+                permissions,
+                size,
+                atime: now,
+                btime: now,
+                mtime: now,
+                ctime: now,
+                ..FileAttr::default()
+            }),
+            // This is the crucial part: we store the device handle.
+            kind,
+        }
+    }
+}
+
 #[async_trait]
 impl Inode for DevFsINode {
     fn id(&self) -> InodeId {
@@ -153,34 +266,50 @@ impl Inode for DevFsINode {
     async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
         match &self.kind {
             InodeKind::Directory(children) => {
-                let children = children.lock_save_irq();
-                children
+                let dir = children.lock_save_irq();
+                dir.children
                     .get(name)
                     .map(|inode| inode.clone() as Arc<dyn Inode>)
                     .ok_or_else(|| FsError::NotFound.into())
             }
-            InodeKind::CharDevice { .. } => Err(FsError::NotADirectory.into()),
+            _ => Err(FsError::NotADirectory.into()),
         }
     }
 
     async fn getattr(&self) -> Result<FileAttr> {
         let mut attr = self.attr.lock_save_irq().clone();
-        if let InodeKind::CharDevice { device_id } = self.kind {
-            attr.file_type = FileType::CharDevice(device_id);
+
+        // A directory is linked from its parent, from its own `.`, and from
+        // the `..` of each directory within it.
+        if let InodeKind::Directory(children) = &self.kind {
+            let subdirs = children
+                .lock_save_irq()
+                .children
+                .values()
+                .filter(|child| matches!(child.kind, InodeKind::Directory(_)))
+                .count();
+            attr.nlinks = 2 + subdirs as u32;
         }
+
         Ok(attr)
     }
 
+    async fn readlink(&self) -> Result<PathBuf> {
+        match &self.kind {
+            InodeKind::Symlink(target) => Ok(target.clone()),
+            _ => Err(KernelError::NotSupported),
+        }
+    }
+
     async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
         match &self.kind {
             InodeKind::Directory(children) => {
-                let children = children.lock_save_irq().clone();
-                Ok(Box::new(DevDirStreamer {
-                    children,
-                    idx: start_offset as usize,
-                }))
+                let children = children.lock_save_irq().listing().clone();
+                let idx = children.partition_point(|(_, inode)| inode.id.inode_id() < start_offset);
+
+                Ok(Box::new(DevDirStreamer { children, idx }))
             }
-            InodeKind::CharDevice { .. } => Err(FsError::NotADirectory.into()),
+            _ => Err(FsError::NotADirectory.into()),
         }
     }
 
@@ -0,0 +1,45 @@
+use crate::{drivers::Driver, fs::FilesystemDriver};
+use alloc::{boxed::Box, sync::Arc};
+use async_trait::async_trait;
+use libkernel::{
+    error::{KernelError, Result},
+    fs::{
+        BlockDevice, Filesystem, blk::buffer::BlockBuffer, filesystems::iso9660::Iso9660Filesystem,
+    },
+};
+use log::warn;
+
+pub struct Iso9660FsDriver {}
+
+impl Iso9660FsDriver {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Driver for Iso9660FsDriver {
+    fn name(&self) -> &'static str {
+        "iso9660fs"
+    }
+
+    fn as_filesystem_driver(self: Arc<Self>) -> Option<Arc<dyn FilesystemDriver>> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl FilesystemDriver for Iso9660FsDriver {
+    async fn construct(
+        &self,
+        fs_id: u64,
+        device: Option<Box<dyn BlockDevice>>,
+    ) -> Result<Arc<dyn Filesystem>> {
+        match device {
+            Some(dev) => Ok(Iso9660Filesystem::new(BlockBuffer::new(dev), fs_id).await?),
+            None => {
+                warn!("Could not mount iso9660 fs with no block device");
+                Err(KernelError::InvalidValue)
+            }
+        }
+    }
+}
@@ -3,6 +3,8 @@ use cgroup::CgroupFsDriver;
 use dev::DevFsDriver;
 use ext4::Ext4FsDriver;
 use fat32::Fat32FsDriver;
+use iso9660::Iso9660FsDriver;
+use overlay::OverlayFsDriver;
 use proc::ProcFsDriver;
 use sys::SysFsDriver;
 use tmpfs::TmpFsDriver;
@@ -13,6 +15,8 @@ pub mod cgroup;
 pub mod dev;
 pub mod ext4;
 pub mod fat32;
+pub mod iso9660;
+pub mod overlay;
 pub mod proc;
 pub mod sys;
 pub mod tmpfs;
@@ -22,9 +26,11 @@ pub fn register_fs_drivers() {
 
     dm.insert_driver(Arc::new(Ext4FsDriver::new()));
     dm.insert_driver(Arc::new(Fat32FsDriver::new()));
+    dm.insert_driver(Arc::new(Iso9660FsDriver::new()));
     dm.insert_driver(Arc::new(DevFsDriver::new()));
     dm.insert_driver(Arc::new(ProcFsDriver::new()));
     dm.insert_driver(Arc::new(SysFsDriver::new()));
     dm.insert_driver(Arc::new(TmpFsDriver::new()));
     dm.insert_driver(Arc::new(CgroupFsDriver::new()));
+    dm.insert_driver(Arc::new(OverlayFsDriver::new()));
 }
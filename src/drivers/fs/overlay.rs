@@ -0,0 +1,91 @@
+use crate::{arch::ArchImpl, drivers::Driver, fs::FilesystemDriver, fs::VFS, sched::current_work};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use async_trait::async_trait;
+use libkernel::{
+    error::{FsError, KernelError, Result},
+    fs::{
+        BlockDevice, FileType, Filesystem, Inode,
+        filesystems::overlay::{OverlayFs, OverlayOptions},
+        path::Path,
+    },
+};
+use log::warn;
+
+pub struct OverlayFsDriver {}
+
+impl OverlayFsDriver {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Driver for OverlayFsDriver {
+    fn name(&self) -> &'static str {
+        "overlay"
+    }
+
+    fn as_filesystem_driver(self: Arc<Self>) -> Option<Arc<dyn FilesystemDriver>> {
+        Some(self)
+    }
+}
+
+/// Looks up one of the layer directories named in the mount options, from the
+/// mounting task's point of view.
+async fn resolve_layer(path: &str) -> Result<Arc<dyn Inode>> {
+    let task = current_work();
+    let cwd = task.cwd.lock_save_irq().0.clone();
+    let inode = VFS.resolve_path(Path::new(path), cwd, &task).await?;
+
+    if inode.getattr().await?.file_type != FileType::Directory {
+        return Err(FsError::NotADirectory.into());
+    }
+
+    Ok(inode)
+}
+
+#[async_trait]
+impl FilesystemDriver for OverlayFsDriver {
+    async fn construct(
+        &self,
+        _fs_id: u64,
+        _device: Option<Box<dyn BlockDevice>>,
+    ) -> Result<Arc<dyn Filesystem>> {
+        // There's nothing to overlay without `lowerdir=`.
+        Err(KernelError::InvalidValue)
+    }
+
+    async fn construct_with_options(
+        &self,
+        fs_id: u64,
+        device: Option<Box<dyn BlockDevice>>,
+        options: &str,
+    ) -> Result<Arc<dyn Filesystem>> {
+        if device.is_some() {
+            warn!("Unexpected block device for overlay");
+            return Err(KernelError::InvalidValue);
+        }
+
+        let opts = OverlayOptions::parse(options)?;
+
+        let mut lowers = Vec::with_capacity(opts.lowerdirs.len());
+        for dir in opts.lowerdirs.iter() {
+            lowers.push(resolve_layer(dir).await?);
+        }
+
+        let upper = match (&opts.upperdir, &opts.workdir) {
+            (Some(upperdir), Some(workdir)) => {
+                let upper = resolve_layer(upperdir).await?;
+                resolve_layer(workdir).await?;
+
+                // The overlay changes the upper layer directly, so the dentry
+                // cache wouldn't hear about it.
+                VFS.stop_caching_dentries(upper.id().fs_id());
+
+                Some(upper)
+            }
+            _ => None,
+        };
+
+        Ok(OverlayFs::<ArchImpl>::new(fs_id, upper, lowers))
+    }
+}
@@ -1,9 +1,23 @@
 #![allow(clippy::module_name_repetitions)]
 
+#[cfg(feature = "alloc_profile")]
+mod allocinfo;
+#[cfg(feature = "bench")]
+mod bench;
+mod block_cache;
+mod buddyinfo;
 mod cmdline;
+mod config;
+mod interrupts;
+#[cfg(feature = "kmemleak")]
+mod kmemleak;
 mod meminfo;
+mod mounts;
 mod root;
+mod slabinfo;
 mod stat;
+mod sys;
+mod syscalls;
 mod task;
 
 use crate::drivers::{Driver, FilesystemDriver};
@@ -13,7 +27,8 @@ use async_trait::async_trait;
 use core::hash::Hasher;
 use libkernel::{
     error::{KernelError, Result},
-    fs::{BlockDevice, Filesystem, Inode, PROCFS_ID},
+    fs::{BlockDevice, Filesystem, FsStats, Inode, PROCFS_ID},
+    memory::PAGE_SIZE,
 };
 use log::warn;
 use root::ProcRootInode;
@@ -55,6 +70,16 @@ impl Filesystem for ProcFs {
     fn magic(&self) -> u64 {
         0x9fa0 // procfs magic number
     }
+
+    async fn statfs(&self) -> Result<FsStats> {
+        // Nothing here takes up space.
+        Ok(FsStats {
+            magic: self.magic(),
+            block_size: PAGE_SIZE as u64,
+            name_max: 255,
+            ..FsStats::default()
+        })
+    }
 }
 
 static PROCFS_INSTANCE: OnceLock<Arc<ProcFs>> = OnceLock::new();
@@ -0,0 +1,76 @@
+use crate::memory::alloc_profile;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::fmt::Write;
+use libkernel::fs::attr::FileAttr;
+use libkernel::fs::{InodeId, SimpleFile};
+
+pub struct ProcAllocinfoInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcAllocinfoInode {
+    pub fn new(inode_id: InodeId) -> Self {
+        Self {
+            id: inode_id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcAllocinfoInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        let sites = alloc_profile::sites();
+        let mut allocinfo_content = String::from("allocinfo - version: 1.0\n");
+
+        allocinfo_content.push_str(&format!("# unrecorded: {}\n", alloc_profile::unrecorded()));
+
+        // The size columns count allocations of up to 8 bytes, then of up to
+        // each power of two to 4096, then of anything larger.
+        allocinfo_content.push_str(
+            "# <live_bytes> <live_objs> <allocs> <frees> : sizes <8> <16> <32> <64> <128> \
+             <256> <512> <1024> <2048> <4096> <large> : trace <return addresses>\n",
+        );
+
+        for site in sites {
+            let _ = write!(
+                allocinfo_content,
+                "{:12} {:8} {} {} : sizes",
+                site.live_bytes,
+                site.live_objs(),
+                site.allocs,
+                site.frees
+            );
+
+            for count in site.sizes.iter() {
+                let _ = write!(allocinfo_content, " {count}");
+            }
+
+            allocinfo_content.push_str(" : trace");
+
+            for addr in site.trace.iter().filter(|addr| **addr != 0) {
+                let _ = write!(allocinfo_content, " {addr:#x}");
+            }
+
+            allocinfo_content.push('\n');
+        }
+
+        Ok(allocinfo_content.into_bytes())
+    }
+}
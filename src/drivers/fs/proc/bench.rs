@@ -0,0 +1,49 @@
+use crate::bench;
+use crate::sync::Mutex;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::fs::attr::FileAttr;
+use libkernel::fs::{InodeId, SimpleFile};
+
+pub struct ProcBenchInode {
+    id: InodeId,
+    attr: FileAttr,
+    /// The report, made on the first read so that the rest of it comes from
+    /// the same run.
+    report: Mutex<Option<Vec<u8>>>,
+}
+
+impl ProcBenchInode {
+    pub fn new(inode_id: InodeId) -> Self {
+        Self {
+            id: inode_id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                ..FileAttr::default()
+            },
+            report: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcBenchInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        let mut report = self.report.lock().await;
+
+        if report.is_none() {
+            *report = Some(bench::report().await?.into_bytes());
+        }
+
+        Ok(report.clone().unwrap_or_default())
+    }
+}
@@ -0,0 +1,62 @@
+use crate::fs::blk::cache::{cache_limit, cached_bytes, stats};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{InodeId, SimpleFile};
+
+pub struct ProcBlockCacheInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcBlockCacheInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                permissions: FilePermissions::from_bits_retain(0o444),
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcBlockCacheInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        // The cache's size and limit, then a line for each cached device.
+        let mut content = format!(
+            "cached {} kB limit {} kB\n\
+             # device          <block_size> <blocks> <dirty> <hits> <misses> <evictions> <writebacks>\n",
+            cached_bytes() / 1024,
+            cache_limit() / 1024,
+        );
+
+        for dev in stats() {
+            content.push_str(&format!(
+                "{:<17} {:6} {:8} {:7} {} {} {} {}\n",
+                dev.name,
+                dev.block_size,
+                dev.blocks,
+                dev.dirty,
+                dev.hits,
+                dev.misses,
+                dev.evictions,
+                dev.writebacks,
+            ));
+        }
+
+        Ok(content.into_bytes())
+    }
+}
@@ -0,0 +1,58 @@
+use crate::memory::PAGE_ALLOC;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::fs::attr::FileAttr;
+use libkernel::fs::{InodeId, SimpleFile};
+use libkernel::memory::allocators::phys::Zone;
+
+pub struct ProcBuddyinfoInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcBuddyinfoInode {
+    pub fn new(inode_id: InodeId) -> Self {
+        Self {
+            id: inode_id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcBuddyinfoInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        let page_alloc = PAGE_ALLOC.get().expect("PAGE_ALLOC must be initialised");
+        let mut buddyinfo_content = String::new();
+
+        for (zone, name) in [(Zone::Dma32, "DMA32"), (Zone::Normal, "Normal")] {
+            if !page_alloc.zone_populated(zone) {
+                continue;
+            }
+
+            buddyinfo_content.push_str(&format!("Node 0, zone {name:>8} "));
+
+            for count in page_alloc.zone_free_blocks(zone) {
+                buddyinfo_content.push_str(&format!("{count:6} "));
+            }
+
+            buddyinfo_content.push('\n');
+        }
+
+        Ok(buddyinfo_content.into_bytes())
+    }
+}
@@ -0,0 +1,42 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::fs::attr::FileAttr;
+use libkernel::fs::{InodeId, SimpleFile};
+
+/// The configuration the kernel was built with, gzipped by build.rs.
+static CONFIG_GZ: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/config.gz"));
+
+pub struct ProcConfigInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcConfigInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                permissions: libkernel::fs::attr::FilePermissions::from_bits_retain(0o444),
+                size: CONFIG_GZ.len() as u64,
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcConfigInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        Ok(CONFIG_GZ.to_vec())
+    }
+}
@@ -0,0 +1,85 @@
+use crate::arch::{Arch, ArchImpl};
+use crate::drivers::Driver;
+use crate::interrupts::{InterruptDescriptor, TriggerMode, get_interrupt_root};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::fs::attr::FileAttr;
+use libkernel::fs::{InodeId, SimpleFile};
+
+pub struct ProcInterruptsInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcInterruptsInode {
+    pub fn new(inode_id: InodeId) -> Self {
+        Self {
+            id: inode_id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+/// Appends one column per CPU from `counts`, which may be short.
+fn push_counts(content: &mut String, counts: &[usize], nr_cpus: usize) {
+    for cpu in 0..nr_cpus {
+        content.push_str(&format!(" {:>10}", counts.get(cpu).copied().unwrap_or(0)));
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcInterruptsInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        let nr_cpus = ArchImpl::cpu_count();
+        let mut content = String::from("     ");
+
+        for cpu in 0..nr_cpus {
+            content.push_str(&format!(" {:>10}", format!("CPU{cpu}")));
+        }
+        content.push('\n');
+
+        let Some(root) = get_interrupt_root() else {
+            return Ok(content.into_bytes());
+        };
+
+        for stat in root.irq_stats() {
+            let (label, kind, num) = match stat.desc {
+                InterruptDescriptor::Spi(n) => (format!("{}", n + 32), "SPI", n),
+                InterruptDescriptor::Ppi(n) => (format!("{}", n + 16), "PPI", n),
+                InterruptDescriptor::Ipi(n) => (format!("IPI{n}"), "IPI", n),
+            };
+            let trigger = match stat.trigger {
+                TriggerMode::EdgeRising | TriggerMode::EdgeFalling => "Edge",
+                TriggerMode::LevelHigh | TriggerMode::LevelLow => "Level",
+            };
+
+            content.push_str(&format!("{label:>5}:"));
+            push_counts(&mut content, &stat.counts, nr_cpus);
+            content.push_str(&format!(
+                "  {} {kind} {num:>3} {trigger:<5}  {}\n",
+                root.name(),
+                stat.names.join(", ")
+            ));
+        }
+
+        content.push_str("  Err:");
+        push_counts(&mut content, &root.spurious_counts(), nr_cpus);
+        content.push('\n');
+
+        Ok(content.into_bytes())
+    }
+}
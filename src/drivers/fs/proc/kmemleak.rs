@@ -0,0 +1,56 @@
+use crate::memory::kmemleak;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::fmt::Write;
+use libkernel::fs::attr::FileAttr;
+use libkernel::fs::{InodeId, SimpleFile};
+
+pub struct ProcKmemleakInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcKmemleakInode {
+    pub fn new(inode_id: InodeId) -> Self {
+        Self {
+            id: inode_id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcKmemleakInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        let mut kmemleak_content = String::new();
+
+        for leak in kmemleak::leaks() {
+            let _ = writeln!(
+                kmemleak_content,
+                "unreferenced object {:#x} (size {}):\n  age {}ms\n  backtrace:",
+                leak.addr,
+                leak.size,
+                leak.age.as_millis()
+            );
+
+            for addr in leak.trace.iter().take_while(|addr| **addr != 0) {
+                let _ = writeln!(kmemleak_content, "    {addr:#018x}");
+            }
+        }
+
+        Ok(kmemleak_content.into_bytes())
+    }
+}
@@ -1,4 +1,5 @@
-use crate::memory::PAGE_ALLOC;
+use crate::fs::blk::cache::cached_bytes;
+use crate::memory::{PAGE_ALLOC, overcommit::commit_limit};
 use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::String;
@@ -6,7 +7,7 @@ use alloc::vec::Vec;
 use async_trait::async_trait;
 use libkernel::fs::attr::FileAttr;
 use libkernel::fs::{InodeId, SimpleFile};
-use libkernel::memory::PAGE_SIZE;
+use libkernel::memory::{PAGE_SIZE, proc_vm::commit};
 
 pub struct ProcMeminfoInode {
     id: InodeId,
@@ -43,10 +44,16 @@ impl SimpleFile for ProcMeminfoInode {
         let free_pages = page_alloc.free_pages();
 
         let total_ram = (total_pages * PAGE_SIZE) / 1024;
-        let free_ram = (free_pages * PAGE_SIZE) / 1204;
+        let free_ram = (free_pages * PAGE_SIZE) / 1024;
         let mut meminfo_content = String::new();
         meminfo_content.push_str(&format!("MemTotal: {total_ram} kB\n"));
         meminfo_content.push_str(&format!("MemFree: {free_ram} kB\n"));
+        meminfo_content.push_str(&format!("Buffers: {} kB\n", cached_bytes() / 1024));
+        meminfo_content.push_str(&format!("CommitLimit: {} kB\n", commit_limit() / 1024));
+        meminfo_content.push_str(&format!(
+            "Committed_AS: {} kB\n",
+            commit::committed() / 1024
+        ));
         Ok(meminfo_content.into_bytes())
     }
 }
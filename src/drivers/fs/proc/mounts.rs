@@ -0,0 +1,133 @@
+use crate::fs::mnt_ns::MountNamespace;
+use crate::fs::{MntFlags, MountInfo, VFS};
+use crate::sched::current_work;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::fs::attr::FileAttr;
+use libkernel::fs::{InodeId, SimpleFile};
+
+pub struct ProcMountsInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcMountsInode {
+    pub fn new(inode_id: InodeId) -> Self {
+        Self {
+            id: inode_id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcMountsInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        let mnt_ns = current_work().mnt_ns.lock_save_irq().clone();
+
+        Ok(format_mounts(&mnt_ns).into_bytes())
+    }
+}
+
+/// Formats the mount table of `mnt_ns` as read from `/proc/mounts`.
+pub fn format_mounts(mnt_ns: &MountNamespace) -> String {
+    let mut mounts_content = String::new();
+
+    for mount in VFS.mounts(mnt_ns) {
+        mounts_content.push_str(&format!(
+            "{} {} {} {} 0 0\n",
+            escape(&mount.source),
+            escape(mount.path.as_str()),
+            mount.fs_type,
+            mount_options(&mount),
+        ));
+    }
+
+    mounts_content
+}
+
+/// Formats the mount table of `mnt_ns` as read from `/proc/<pid>/mountinfo`.
+pub fn format_mountinfo(mnt_ns: &MountNamespace) -> String {
+    let mut content = String::new();
+
+    for mount in VFS.mounts(mnt_ns) {
+        // The filesystem ID is what stat() gives as the device, so it's split
+        // up the way the C library takes a `dev_t` apart.
+        let major = ((mount.dev >> 32) & 0xfffff000) | ((mount.dev >> 8) & 0xfff);
+        let minor = ((mount.dev >> 12) & 0xffffff00) | (mount.dev & 0xff);
+
+        content.push_str(&format!(
+            "{} {} {major}:{minor} {} {} {}",
+            mount.id,
+            mount.parent_id,
+            escape(mount.root.as_str()),
+            escape(mount.path.as_str()),
+            mount_options(&mount),
+        ));
+
+        if let Some(group) = mount.peer_group {
+            content.push_str(&format!(" shared:{group}"));
+        }
+
+        if let Some(group) = mount.master {
+            content.push_str(&format!(" master:{group}"));
+        }
+
+        if mount.unbindable {
+            content.push_str(" unbindable");
+        }
+
+        content.push_str(&format!(
+            " - {} {} {}\n",
+            mount.fs_type,
+            escape(&mount.source),
+            if mount.read_only { "ro" } else { "rw" },
+        ));
+    }
+
+    content
+}
+
+/// Returns the options of `mount`, as listed in the mount table.
+fn mount_options(mount: &MountInfo) -> String {
+    let mut options = String::from(if mount.read_only { "ro" } else { "rw" });
+
+    if mount.flags.contains(MntFlags::MNT_NOSUID) {
+        options.push_str(",nosuid");
+    }
+
+    if mount.flags.contains(MntFlags::MNT_NOEXEC) {
+        options.push_str(",noexec");
+    }
+
+    options
+}
+
+/// Escapes the characters that would break up a field of the mount table as
+/// octal, as Linux does.
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+
+    for c in field.chars() {
+        match c {
+            ' ' | '\t' | '\n' | '\\' => escaped.push_str(&format!("\\{:03o}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
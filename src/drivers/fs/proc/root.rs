@@ -1,11 +1,26 @@
+#[cfg(feature = "alloc_profile")]
+use crate::drivers::fs::proc::allocinfo::ProcAllocinfoInode;
+#[cfg(feature = "bench")]
+use crate::drivers::fs::proc::bench::ProcBenchInode;
+use crate::drivers::fs::proc::block_cache::ProcBlockCacheInode;
+use crate::drivers::fs::proc::buddyinfo::ProcBuddyinfoInode;
 use crate::drivers::fs::proc::cmdline::ProcCmdlineInode;
+use crate::drivers::fs::proc::config::ProcConfigInode;
 use crate::drivers::fs::proc::get_inode_id;
+use crate::drivers::fs::proc::interrupts::ProcInterruptsInode;
+#[cfg(feature = "kmemleak")]
+use crate::drivers::fs::proc::kmemleak::ProcKmemleakInode;
 use crate::drivers::fs::proc::meminfo::ProcMeminfoInode;
+use crate::drivers::fs::proc::mounts::ProcMountsInode;
+use crate::drivers::fs::proc::slabinfo::ProcSlabinfoInode;
 use crate::drivers::fs::proc::stat::ProcStatInode;
+use crate::drivers::fs::proc::sys::{ProcSysDirInode, SysDir};
+use crate::drivers::fs::proc::syscalls::ProcSyscallsInode;
 use crate::drivers::fs::proc::task::ProcTaskInode;
 use crate::process::thread_group::pid::PidT;
-use crate::process::{TASK_LIST, TaskDescriptor, Tid, find_task_by_tid};
+use crate::process::{TASK_LIST, TaskDescriptor, Tid, find_task_by_tid, task_list_generation};
 use crate::sched::current_work;
+use crate::sync::SpinLock;
 use alloc::boxed::Box;
 use alloc::string::ToString;
 use alloc::sync::Arc;
@@ -16,6 +31,46 @@ use libkernel::error::FsError;
 use libkernel::fs::attr::{FileAttr, FilePermissions};
 use libkernel::fs::{DirStream, Dirent, FileType, Inode, InodeId, PROCFS_ID, SimpleDirStream};
 
+/// The task directories as last listed, and the `TASK_LIST` generation they
+/// were listed at.
+static TASK_DIRENTS: SpinLock<Option<(u64, Arc<[Dirent]>)>> = SpinLock::new(None);
+
+/// Returns an entry for each live task, listing them again only if tasks have
+/// come or gone since the last time.
+fn task_dirents() -> Arc<[Dirent]> {
+    let generation = task_list_generation();
+
+    if let Some((listed_at, entries)) = &*TASK_DIRENTS.lock_save_irq()
+        && *listed_at == generation
+    {
+        return entries.clone();
+    }
+
+    // Hold the task list's lock only for as long as it takes to copy the tids
+    // out; every clone and exit needs it.
+    let tids: Vec<Tid> = TASK_LIST
+        .lock_save_irq()
+        .iter()
+        .filter(|(_, task)| task.strong_count() > 0)
+        .map(|(tid, _)| *tid)
+        .collect();
+
+    let entries: Arc<[Dirent]> = tids
+        .iter()
+        .enumerate()
+        .map(|(i, tid)| {
+            let name = tid.value().to_string();
+            let inode_id = InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&[&name]));
+
+            Dirent::new(name, inode_id, FileType::Directory, (i + 1) as u64)
+        })
+        .collect();
+
+    *TASK_DIRENTS.lock_save_irq() = Some((generation, entries.clone()));
+
+    entries
+}
+
 pub struct ProcRootInode {
     id: InodeId,
     attr: FileAttr,
@@ -43,6 +98,27 @@ impl Inode for ProcRootInode {
     async fn lookup(&self, name: &str) -> error::Result<Arc<dyn Inode>> {
         let current = current_work();
 
+        #[cfg(feature = "alloc_profile")]
+        if name == "allocinfo" {
+            return Ok(Arc::new(ProcAllocinfoInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["allocinfo"])),
+            )));
+        }
+
+        #[cfg(feature = "bench")]
+        if name == "bench" {
+            return Ok(Arc::new(ProcBenchInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["bench"])),
+            )));
+        }
+
+        #[cfg(feature = "kmemleak")]
+        if name == "kmemleak" {
+            return Ok(Arc::new(ProcKmemleakInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["kmemleak"])),
+            )));
+        }
+
         // Lookup a PID directory.
         let desc = if name == "self" {
             // FIXME: The group leader may have exited.
@@ -57,10 +133,43 @@ impl Inode for ProcRootInode {
             return Ok(Arc::new(ProcMeminfoInode::new(
                 InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["meminfo"])),
             )));
+        } else if name == "mounts" {
+            return Ok(Arc::new(ProcMountsInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["mounts"])),
+            )));
+        } else if name == "slabinfo" {
+            return Ok(Arc::new(ProcSlabinfoInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["slabinfo"])),
+            )));
+        } else if name == "buddyinfo" {
+            return Ok(Arc::new(ProcBuddyinfoInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["buddyinfo"])),
+            )));
+        } else if name == "block_cache" {
+            return Ok(Arc::new(ProcBlockCacheInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["block_cache"])),
+            )));
+        } else if name == "interrupts" {
+            return Ok(Arc::new(ProcInterruptsInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["interrupts"])),
+            )));
         } else if name == "cmdline" {
             return Ok(Arc::new(ProcCmdlineInode::new(
                 InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["cmdline"])),
             )));
+        } else if name == "config.gz" {
+            return Ok(Arc::new(ProcConfigInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["config.gz"])),
+            )));
+        } else if name == "syscalls" {
+            return Ok(Arc::new(ProcSyscallsInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["syscalls"])),
+            )));
+        } else if name == "sys" {
+            return Ok(Arc::new(ProcSysDirInode::new(
+                SysDir::Root,
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["sys"])),
+            )));
         } else {
             let pid: PidT = name.parse().map_err(|_| FsError::NotFound)?;
             // Search for the task descriptor.
@@ -81,26 +190,7 @@ impl Inode for ProcRootInode {
     }
 
     async fn readdir(&self, start_offset: u64) -> error::Result<Box<dyn DirStream>> {
-        let mut entries: Vec<Dirent> = Vec::new();
-        // Gather task list under interrupt-safe lock.
-        let task_list = TASK_LIST.lock_save_irq();
-        for (tid, _) in task_list
-            .iter()
-            .filter(|(_, task)| task.upgrade().is_some())
-        {
-            let name = tid.value().to_string();
-            let inode_id = InodeId::from_fsid_and_inodeid(
-                PROCFS_ID,
-                get_inode_id(&[&tid.value().to_string()]),
-            );
-            let next_offset = (entries.len() + 1) as u64;
-            entries.push(Dirent::new(
-                name,
-                inode_id,
-                FileType::Directory,
-                next_offset,
-            ));
-        }
+        let mut entries: Vec<Dirent> = task_dirents().to_vec();
 
         let current = current_work();
 
@@ -134,12 +224,81 @@ impl Inode for ProcRootInode {
             FileType::File,
             (entries.len() + 1) as u64,
         ));
+        entries.push(Dirent::new(
+            "mounts".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["mounts"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        entries.push(Dirent::new(
+            "slabinfo".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["slabinfo"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        entries.push(Dirent::new(
+            "buddyinfo".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["buddyinfo"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        entries.push(Dirent::new(
+            "block_cache".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["block_cache"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        entries.push(Dirent::new(
+            "interrupts".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["interrupts"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
         entries.push(Dirent::new(
             "cmdline".to_string(),
             InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["cmdline"])),
             FileType::File,
             (entries.len() + 1) as u64,
         ));
+        entries.push(Dirent::new(
+            "config.gz".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["config.gz"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        entries.push(Dirent::new(
+            "syscalls".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["syscalls"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        #[cfg(feature = "alloc_profile")]
+        entries.push(Dirent::new(
+            "allocinfo".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["allocinfo"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        #[cfg(feature = "bench")]
+        entries.push(Dirent::new(
+            "bench".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["bench"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        #[cfg(feature = "kmemleak")]
+        entries.push(Dirent::new(
+            "kmemleak".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["kmemleak"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        entries.push(Dirent::new(
+            "sys".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["sys"])),
+            FileType::Directory,
+            (entries.len() + 1) as u64,
+        ));
 
         Ok(Box::new(SimpleDirStream::new(entries, start_offset)))
     }
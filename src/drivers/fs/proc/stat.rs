@@ -1,4 +1,5 @@
 use crate::arch::{Arch, ArchImpl};
+use crate::clock::realtime;
 use crate::drivers::timer::uptime;
 use crate::kernel::cpu_id::CpuId;
 use crate::process::TASK_LIST;
@@ -102,7 +103,12 @@ impl SimpleFile for ProcStatInode {
             "ctxt {}\n",
             NUM_CONTEXT_SWITCHES.load(Ordering::Relaxed)
         ));
-        stat_content.push_str(&format!("btime {}\n", uptime().as_secs()));
+        // Process start times are reported relative to this, so it has to be
+        // the wall-clock time the kernel booted at.
+        stat_content.push_str(&format!(
+            "btime {}\n",
+            realtime::date().saturating_sub(uptime()).as_secs()
+        ));
         stat_content.push_str(&format!(
             "processes {}\n",
             NUM_FORKS.load(Ordering::Relaxed)
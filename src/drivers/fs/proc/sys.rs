@@ -0,0 +1,370 @@
+use crate::drivers::fs::proc::get_inode_id;
+use crate::fs::blk::cache::{self as block_cache, cache_limit, set_cache_limit};
+use crate::fs::{VFS, page_cache};
+use crate::kernel::rand::boot_id;
+use crate::memory::overcommit::{
+    overcommit_memory, overcommit_ratio, set_overcommit_memory, set_overcommit_ratio,
+};
+use crate::process::clone::{set_threads_max, threads_max};
+use crate::process::exec::aslr::{randomize_va_space, set_randomize_va_space};
+use crate::process::fd_table::{NR_OPEN, file_max, nr_open_fds, set_file_max};
+use crate::process::{pid_max, set_pid_max};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::error::{FsError, KernelError, Result};
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{DirStream, Dirent, FileType, Inode, InodeId, PROCFS_ID, SimpleDirStream};
+
+/// A directory under `/proc/sys`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SysDir {
+    Root,
+    Fs,
+    Kernel,
+    KernelRandom,
+    Vm,
+}
+
+impl SysDir {
+    fn path(self) -> &'static [&'static str] {
+        match self {
+            SysDir::Root => &["sys"],
+            SysDir::Fs => &["sys", "fs"],
+            SysDir::Kernel => &["sys", "kernel"],
+            SysDir::KernelRandom => &["sys", "kernel", "random"],
+            SysDir::Vm => &["sys", "vm"],
+        }
+    }
+
+    fn entries(self) -> &'static [(&'static str, SysEntry)] {
+        match self {
+            SysDir::Root => &[
+                ("fs", SysEntry::Dir(SysDir::Fs)),
+                ("kernel", SysEntry::Dir(SysDir::Kernel)),
+                ("vm", SysEntry::Dir(SysDir::Vm)),
+            ],
+            SysDir::Fs => &[
+                ("dentry-state", SysEntry::Knob(Sysctl::DentryState)),
+                ("file-max", SysEntry::Knob(Sysctl::FileMax)),
+                ("file-nr", SysEntry::Knob(Sysctl::FileNr)),
+                ("inode-nr", SysEntry::Knob(Sysctl::InodeNr)),
+                ("nr_open", SysEntry::Knob(Sysctl::NrOpen)),
+            ],
+            SysDir::Kernel => &[
+                ("pid_max", SysEntry::Knob(Sysctl::PidMax)),
+                ("random", SysEntry::Dir(SysDir::KernelRandom)),
+                (
+                    "randomize_va_space",
+                    SysEntry::Knob(Sysctl::RandomizeVaSpace),
+                ),
+                ("threads-max", SysEntry::Knob(Sysctl::ThreadsMax)),
+            ],
+            SysDir::KernelRandom => &[("boot_id", SysEntry::Knob(Sysctl::BootId))],
+            SysDir::Vm => &[
+                (
+                    "block_cache_kbytes",
+                    SysEntry::Knob(Sysctl::BlockCacheKbytes),
+                ),
+                ("drop_caches", SysEntry::Knob(Sysctl::DropCaches)),
+                (
+                    "overcommit_memory",
+                    SysEntry::Knob(Sysctl::OvercommitMemory),
+                ),
+                ("overcommit_ratio", SysEntry::Knob(Sysctl::OvercommitRatio)),
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SysEntry {
+    Dir(SysDir),
+    Knob(Sysctl),
+}
+
+/// A tunable kernel parameter.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Sysctl {
+    DentryState,
+    FileMax,
+    FileNr,
+    InodeNr,
+    NrOpen,
+    PidMax,
+    BootId,
+    RandomizeVaSpace,
+    ThreadsMax,
+    BlockCacheKbytes,
+    DropCaches,
+    OvercommitMemory,
+    OvercommitRatio,
+}
+
+impl Sysctl {
+    /// Returns the permissions of the parameter's file: read-only for those
+    /// that only report kernel state, write-only for those that only trigger
+    /// an action.
+    fn mode(self) -> u16 {
+        match self {
+            Sysctl::DentryState
+            | Sysctl::FileNr
+            | Sysctl::InodeNr
+            | Sysctl::NrOpen
+            | Sysctl::BootId => 0o444,
+            Sysctl::DropCaches => 0o200,
+            _ => 0o644,
+        }
+    }
+
+    fn read(self) -> Vec<u8> {
+        match self {
+            Sysctl::DentryState => {
+                let stats = VFS.dcache_stats();
+
+                // Every cached entry can be reclaimed, so all of them count as
+                // unused. The age limit is Linux's default.
+                format!(
+                    "{}\t{}\t45\t0\t{}\t0\n",
+                    stats.entries, stats.entries, stats.negative
+                )
+                .into_bytes()
+            }
+            Sysctl::FileMax => format!("{}\n", file_max()).into_bytes(),
+            // Descriptors are freed as soon as they're closed, so none are
+            // ever allocated but unused.
+            Sysctl::FileNr => format!("{}\t0\t{}\n", nr_open_fds(), file_max()).into_bytes(),
+            Sysctl::InodeNr => {
+                let stats = VFS.icache_stats();
+
+                format!("{}\t{}\n", stats.inodes, stats.unused).into_bytes()
+            }
+            Sysctl::NrOpen => format!("{NR_OPEN}\n").into_bytes(),
+            Sysctl::PidMax => format!("{}\n", pid_max()).into_bytes(),
+            Sysctl::BootId => {
+                let id = boot_id();
+                let hex =
+                    |bytes: &[u8]| bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+
+                format!(
+                    "{}-{}-{}-{}-{}\n",
+                    hex(&id[..4]),
+                    hex(&id[4..6]),
+                    hex(&id[6..8]),
+                    hex(&id[8..10]),
+                    hex(&id[10..])
+                )
+                .into_bytes()
+            }
+            Sysctl::ThreadsMax => format!("{}\n", threads_max()).into_bytes(),
+            Sysctl::BlockCacheKbytes => format!("{}\n", cache_limit() / 1024).into_bytes(),
+            // Dropping caches is a one-off action; there's no setting to show.
+            Sysctl::DropCaches => b"0\n".to_vec(),
+            Sysctl::RandomizeVaSpace => format!("{}\n", randomize_va_space()).into_bytes(),
+            Sysctl::OvercommitMemory => format!("{}\n", overcommit_memory()).into_bytes(),
+            Sysctl::OvercommitRatio => format!("{}\n", overcommit_ratio()).into_bytes(),
+        }
+    }
+
+    fn write(self, value: &str) -> Result<()> {
+        match self {
+            Sysctl::DentryState
+            | Sysctl::FileNr
+            | Sysctl::InodeNr
+            | Sysctl::NrOpen
+            | Sysctl::BootId => Err(FsError::PermissionDenied.into()),
+            Sysctl::FileMax => set_file_max(value.parse().map_err(|_| KernelError::InvalidValue)?),
+            Sysctl::PidMax => set_pid_max(value.parse().map_err(|_| KernelError::InvalidValue)?),
+            Sysctl::ThreadsMax => {
+                set_threads_max(value.parse().map_err(|_| KernelError::InvalidValue)?)
+            }
+            Sysctl::BlockCacheKbytes => {
+                let kbytes: usize = value.parse().map_err(|_| KernelError::InvalidValue)?;
+
+                set_cache_limit(kbytes.checked_mul(1024).ok_or(KernelError::InvalidValue)?);
+
+                Ok(())
+            }
+            Sysctl::DropCaches => {
+                // 1 drops the page and block caches, 2 the dentry and inode
+                // caches, and 3 both.
+                let what: u8 = value.parse().map_err(|_| KernelError::InvalidValue)?;
+
+                if !(1..=3).contains(&what) {
+                    return Err(KernelError::InvalidValue);
+                }
+
+                if what & 1 != 0 {
+                    page_cache::drop_all();
+                    block_cache::drop_clean();
+                }
+
+                if what & 2 != 0 {
+                    VFS.drop_caches();
+                }
+
+                Ok(())
+            }
+            Sysctl::RandomizeVaSpace => {
+                set_randomize_va_space(value.parse().map_err(|_| KernelError::InvalidValue)?)
+            }
+            Sysctl::OvercommitMemory => {
+                set_overcommit_memory(value.parse().map_err(|_| KernelError::InvalidValue)?)
+            }
+            Sysctl::OvercommitRatio => {
+                set_overcommit_ratio(value.parse().map_err(|_| KernelError::InvalidValue)?);
+
+                Ok(())
+            }
+        }
+    }
+}
+
+pub struct ProcSysDirInode {
+    id: InodeId,
+    attr: FileAttr,
+    dir: SysDir,
+}
+
+impl ProcSysDirInode {
+    pub fn new(dir: SysDir, id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::Directory,
+                permissions: FilePermissions::from_bits_retain(0o555),
+                ..FileAttr::default()
+            },
+            dir,
+        }
+    }
+
+    fn child_id(&self, name: &str) -> InodeId {
+        let mut path = self.dir.path().to_vec();
+        path.push(name);
+
+        InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&path))
+    }
+}
+
+#[async_trait]
+impl Inode for ProcSysDirInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let (_, entry) = self
+            .dir
+            .entries()
+            .iter()
+            .find(|(n, _)| *n == name)
+            .ok_or(FsError::NotFound)?;
+
+        let id = self.child_id(name);
+
+        Ok(match *entry {
+            SysEntry::Dir(dir) => Arc::new(ProcSysDirInode::new(dir, id)),
+            SysEntry::Knob(knob) => Arc::new(ProcSysctlInode::new(knob, id)),
+        })
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
+        let entries = self
+            .dir
+            .entries()
+            .iter()
+            .enumerate()
+            .map(|(i, (name, entry))| {
+                let file_type = match entry {
+                    SysEntry::Dir(_) => FileType::Directory,
+                    SysEntry::Knob(_) => FileType::File,
+                };
+
+                Dirent::new(
+                    name.to_string(),
+                    self.child_id(name),
+                    file_type,
+                    (i + 1) as u64,
+                )
+            })
+            .collect();
+
+        Ok(Box::new(SimpleDirStream::new(entries, start_offset)))
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
+
+pub struct ProcSysctlInode {
+    id: InodeId,
+    attr: FileAttr,
+    knob: Sysctl,
+}
+
+impl ProcSysctlInode {
+    fn new(knob: Sysctl, id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::File,
+                permissions: FilePermissions::from_bits_retain(knob.mode()),
+                ..FileAttr::default()
+            },
+            knob,
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for ProcSysctlInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let data = self.knob.read();
+        let start = offset as usize;
+        if start >= data.len() {
+            return Ok(0);
+        }
+
+        let end = usize::min(start + buf.len(), data.len());
+        let slice = &data[start..end];
+        buf[..slice.len()].copy_from_slice(slice);
+        Ok(slice.len())
+    }
+
+    async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        if offset != 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let value = str::from_utf8(buf).map_err(|_| KernelError::InvalidValue)?;
+
+        self.knob.write(value.trim())?;
+
+        Ok(buf.len())
+    }
+
+    async fn truncate(&self, _size: u64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
@@ -0,0 +1,56 @@
+use crate::arch::{Arch, ArchImpl, SyscallSupport};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::fs::attr::FileAttr;
+use libkernel::fs::{InodeId, SimpleFile};
+
+/// Lists every system call of the ABI, one per line, as its number, its name
+/// and one of `implemented`, `stub` (fails with ENOSYS on purpose) or
+/// `missing`.
+pub struct ProcSyscallsInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcSyscallsInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                permissions: libkernel::fs::attr::FilePermissions::from_bits_retain(0o444),
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcSyscallsInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        let mut content = String::new();
+
+        for syscall in ArchImpl::syscalls() {
+            let support = match syscall.support {
+                SyscallSupport::Implemented => "implemented",
+                SyscallSupport::Stubbed => "stub",
+                SyscallSupport::Missing => "missing",
+            };
+
+            content.push_str(&format!("{} {} {support}\n", syscall.nr, syscall.name));
+        }
+
+        Ok(content.into_bytes())
+    }
+}
@@ -1,7 +1,7 @@
 use crate::drivers::fs::proc::{get_inode_id, procfs};
-use crate::process::fd_table::Fd;
+use crate::fs::open_file::OpenFile;
+use crate::process::fd_table::{Fd, FdFlags};
 use crate::process::{Tid, find_task_by_tid};
-use crate::sched::current_work;
 use alloc::borrow::ToOwned;
 use alloc::boxed::Box;
 use alloc::format;
@@ -11,6 +11,7 @@ use alloc::vec::Vec;
 use async_trait::async_trait;
 use libkernel::error::Result;
 use libkernel::error::{FsError, KernelError};
+use libkernel::fs::OpenFlags;
 use libkernel::fs::attr::FileAttr;
 use libkernel::fs::pathbuf::PathBuf;
 use libkernel::fs::{
@@ -55,9 +56,8 @@ impl Inode for ProcFdInode {
 
     async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
         let fd: i32 = name.parse().map_err(|_| FsError::NotFound)?;
-        let task = current_work();
-        let fd_table = task.fd_table.lock_save_irq();
-        if fd_table.get(Fd(fd)).is_none() {
+        let task = find_task_by_tid(self.tid).ok_or(FsError::NotFound)?;
+        if task.fd_table.lock_save_irq().get_raw(Fd(fd)).is_none() {
             return Err(FsError::NotFound.into());
         }
         let fs = procfs();
@@ -75,13 +75,10 @@ impl Inode for ProcFdInode {
 
     async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
         let task = find_task_by_tid(self.tid).ok_or(FsError::NotFound)?;
-        let fd_table = task.fd_table.lock_save_irq();
+        let fds = task.fd_table.lock_save_irq().open_fds(0..=usize::MAX);
         let mut entries = Vec::new();
-        for fd in 0..fd_table.len() {
-            if fd_table.get(Fd(fd as i32)).is_none() {
-                continue;
-            }
-            let fd_str = fd.to_string();
+        for fd in fds {
+            let fd_str = fd.as_raw().to_string();
             let next_offset = (entries.len() + 1) as u64;
             entries.push(Dirent {
                 id: InodeId::from_fsid_and_inodeid(
@@ -102,8 +99,6 @@ impl Inode for ProcFdInode {
     }
 }
 
-// TODO: Support fd links in /proc/[pid]/fd/
-
 pub struct ProcFdFile {
     id: InodeId,
     attr: FileAttr,
@@ -130,6 +125,13 @@ impl ProcFdFile {
             fd,
         }
     }
+
+    fn open_file(&self) -> Result<Arc<OpenFile>> {
+        let task = find_task_by_tid(self.tid).ok_or(FsError::NotFound)?;
+        let file = task.fd_table.lock_save_irq().get_raw(Fd(self.fd));
+
+        file.ok_or(FsError::NotFound.into())
+    }
 }
 
 #[async_trait]
@@ -143,40 +145,69 @@ impl SimpleFile for ProcFdFile {
     }
 
     async fn read(&self) -> Result<Vec<u8>> {
+        if !self.fd_info {
+            return Err(KernelError::NotSupported);
+        }
+
         let task = find_task_by_tid(self.tid).ok_or(FsError::NotFound)?;
-        let fd_entry = task
-            .fd_table
-            .lock_save_irq()
-            .get(Fd(self.fd))
-            .ok_or(FsError::NotFound)?;
+        let (fd_entry, fd_flags) = {
+            let fd_table = task.fd_table.lock_save_irq();
+            let file = fd_table.get_raw(Fd(self.fd)).ok_or(FsError::NotFound)?;
+            (file, fd_table.flags(Fd(self.fd)).unwrap_or_default())
+        };
+        let inode_id = fd_entry.inode().map(|inode| inode.id());
         let (_, ctx) = &mut *fd_entry.lock().await;
-        let info_string = format!("pos: {}\nflags: {}", ctx.pos, ctx.flags.bits());
-        if self.fd_info {
-            Ok(info_string.into_bytes())
-        } else {
-            Err(KernelError::NotSupported)
+
+        // Like Linux, the close-on-exec flag is shown among the open flags.
+        let mut flags = ctx.flags;
+        if fd_flags.contains(FdFlags::CLOEXEC) {
+            flags |= OpenFlags::O_CLOEXEC;
         }
+
+        Ok(format!(
+            "pos:\t{}\nflags:\t0{:o}\nmnt_id:\t{}\nino:\t{}\n",
+            ctx.pos,
+            flags.bits(),
+            inode_id.map_or(0, |id| id.fs_id()),
+            inode_id.map_or(0, |id| id.inode_id()),
+        )
+        .into_bytes())
     }
 
     async fn readlink(&self) -> Result<PathBuf> {
-        if !self.fd_info {
-            if let Some(task) = find_task_by_tid(self.tid) {
-                let Some(file) = task.fd_table.lock_save_irq().get(Fd(self.fd)) else {
-                    return Err(FsError::NotFound.into());
+        if self.fd_info {
+            return Err(KernelError::NotSupported);
+        }
+
+        let file = self.open_file()?;
+
+        match (file.path(), file.inode()) {
+            (Some(path), _) if !path.as_str().is_empty() => Ok(path.to_owned()),
+            // Like Linux, name the files that have no path by their kind.
+            (_, Some(inode)) => {
+                let kind = match inode.getattr().await?.file_type {
+                    FileType::Fifo => "pipe",
+                    FileType::Socket => "socket",
+                    _ => return Err(KernelError::NotSupported),
                 };
-                if let Some(path) = file.path() {
-                    Ok(path.to_owned())
-                } else {
-                    // TODO: Find file type
-                    todo!(
-                        "Implement readlink for /proc/[pid]/fd/[fd] when fd doesn't refer to a file with an inode"
-                    )
-                }
-            } else {
-                Err(FsError::NotFound.into())
+
+                Ok(PathBuf::from(format!("{kind}:[{}]", inode.id().inode_id())))
             }
-        } else {
-            Err(KernelError::NotSupported)
+            // TODO: Name the rest, as Linux does with e.g. "anon_inode:[eventfd]".
+            _ => Err(KernelError::NotSupported),
+        }
+    }
+
+    async fn magic_link(&self) -> Result<Option<Arc<dyn Inode>>> {
+        if self.fd_info {
+            return Ok(None);
         }
+
+        // Opening the link opens the file afresh, so there must be an inode
+        // to open.
+        self.open_file()?
+            .inode()
+            .map(Some)
+            .ok_or(FsError::NoDeviceOrAddress.into())
     }
 }
@@ -3,10 +3,11 @@ mod fd;
 #[expect(clippy::module_inception)]
 mod task;
 mod task_file;
+mod timens;
 
 use crate::drivers::fs::proc::task::task_file::{ProcTaskFileInode, TaskFileType};
 use crate::drivers::fs::proc::{get_inode_id, procfs};
-use crate::process::Tid;
+use crate::process::{PidRef, Tid, find_pid_ref};
 use alloc::boxed::Box;
 use alloc::string::ToString;
 use alloc::sync::Arc;
@@ -24,6 +25,8 @@ pub struct ProcTaskInode {
     attr: FileAttr,
     tid: Tid,
     is_task_dir: bool,
+    /// Keeps `tid` from naming another task once this one is gone.
+    _pid_ref: Option<PidRef>,
 }
 
 impl ProcTaskInode {
@@ -37,6 +40,7 @@ impl ProcTaskInode {
             },
             tid,
             is_task_dir,
+            _pid_ref: find_pid_ref(tid),
         }
     }
 }
@@ -59,6 +63,10 @@ impl Inode for ProcTaskInode {
             return Ok(Arc::new(fd::ProcFdInode::new(self.tid, false, inode_id)));
         } else if name == "task" && !self.is_task_dir {
             return Ok(Arc::new(task::ProcTaskDirInode::new(self.tid, inode_id)));
+        } else if name == "timens_offsets" {
+            return Ok(Arc::new(timens::ProcTimensOffsetsInode::new(
+                self.tid, inode_id,
+            )));
         }
         if let Ok(file_type) = TaskFileType::try_from(name) {
             Ok(Arc::new(ProcTaskFileInode::new(
@@ -139,12 +147,39 @@ impl Inode for ProcTaskInode {
             FileType::File,
             10,
         ));
+        entries.push(Dirent::new(
+            "timens_offsets".to_string(),
+            InodeId::from_fsid_and_inodeid(
+                PROCFS_ID,
+                get_inode_id(&[&initial_str, "timens_offsets"]),
+            ),
+            FileType::File,
+            11,
+        ));
+        entries.push(Dirent::new(
+            "mounts".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&[&initial_str, "mounts"])),
+            FileType::File,
+            12,
+        ));
+        entries.push(Dirent::new(
+            "limits".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&[&initial_str, "limits"])),
+            FileType::File,
+            13,
+        ));
+        entries.push(Dirent::new(
+            "mountinfo".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&[&initial_str, "mountinfo"])),
+            FileType::File,
+            14,
+        ));
         if !self.is_task_dir {
             entries.push(Dirent::new(
                 "task".to_string(),
                 InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&[&initial_str, "task"])),
                 FileType::Directory,
-                11,
+                15,
             ));
         }
 
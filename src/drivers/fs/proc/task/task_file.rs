@@ -1,6 +1,14 @@
 use crate::{
-    drivers::fs::cgroup::cgroup_path_for_thread_group,
-    process::{Tid, find_task_by_tid},
+    drivers::{
+        fs::{
+            cgroup::cgroup_path_for_thread_group,
+            proc::mounts::{format_mountinfo, format_mounts},
+        },
+        timer::USER_HZ,
+    },
+    fs::VFS,
+    process::{Tid, find_task_by_tid, thread_group::rsrc_lim::format_limits},
+    sched::{current_work, priority_to_nice},
 };
 use alloc::boxed::Box;
 use alloc::format;
@@ -23,6 +31,9 @@ pub enum TaskFileType {
     Maps,
     Exe,
     Cgroup,
+    Mounts,
+    Mountinfo,
+    Limits,
 }
 
 impl TryFrom<&str> for TaskFileType {
@@ -39,6 +50,9 @@ impl TryFrom<&str> for TaskFileType {
             "maps" => Ok(TaskFileType::Maps),
             "exe" => Ok(TaskFileType::Exe),
             "cgroup" => Ok(TaskFileType::Cgroup),
+            "mounts" => Ok(TaskFileType::Mounts),
+            "mountinfo" => Ok(TaskFileType::Mountinfo),
+            "limits" => Ok(TaskFileType::Limits),
             _ => Err(()),
         }
     }
@@ -63,7 +77,10 @@ impl ProcTaskFileInode {
                     | TaskFileType::State
                     | TaskFileType::Maps
                     | TaskFileType::Stat
-                    | TaskFileType::Cgroup => FileType::File,
+                    | TaskFileType::Cgroup
+                    | TaskFileType::Mounts
+                    | TaskFileType::Mountinfo
+                    | TaskFileType::Limits => FileType::File,
                     TaskFileType::Cwd | TaskFileType::Root | TaskFileType::Exe => FileType::Symlink,
                 },
                 permissions: FilePermissions::from_bits_retain(0o444),
@@ -91,7 +108,7 @@ impl SimpleFile for ProcTaskFileInode {
 
         let status_string = if let Some(task) = task_details {
             let state = task.state.load(core::sync::atomic::Ordering::Relaxed);
-            let name = task.comm.lock_save_irq();
+            let name = *task.comm.lock_save_irq();
             match self.file_type {
                 TaskFileType::Status => format!(
                     "Name:\t{name}
@@ -102,7 +119,7 @@ Pid:\t{pid}
 Threads:\t{tasks}\n",
                     name = name.as_str(),
                     tgid = task.process.tgid,
-                    fd_size = task.fd_table.lock_save_irq().len(),
+                    fd_size = task.fd_table.lock_save_irq().stats().capacity,
                     pid = task.tid.value(),
                     tasks = task.process.tasks.lock_save_irq().len(),
                 ),
@@ -170,11 +187,15 @@ Threads:\t{tasks}\n",
                     }
                     output.push_str(&format!("{} ", 0)); // cutime
                     output.push_str(&format!("{} ", 0)); // cstime
-                    output.push_str(&format!("{} ", *task.process.priority.lock_save_irq())); // priority
-                    output.push_str(&format!("{} ", 0)); // nice
+                    let nice = priority_to_nice(*task.process.priority.lock_save_irq());
+                    output.push_str(&format!("{} ", 20 + nice)); // priority
+                    output.push_str(&format!("{nice} ")); // nice
                     output.push_str(&format!("{} ", task.process.tasks.lock_save_irq().len())); // num_threads
                     output.push_str(&format!("{} ", 0)); // itrealvalue
-                    output.push_str(&format!("{} ", 0)); // starttime
+                    output.push_str(&format!(
+                        "{} ",
+                        task.process.start_time.as_nanos() * USER_HZ as u128 / 1_000_000_000
+                    )); // starttime
                     output.push_str(&format!("{vsize} ")); // vsize
                     output.push_str(&format!("{} ", 0)); // rss
                     output.push_str(&format!("{} ", 0)); // rsslim
@@ -199,7 +220,7 @@ Threads:\t{tasks}\n",
                             .map_or(0, |s| s.last_cpu)
                     )); // processor
                     output.push_str(&format!("{} ", 0)); // rt_priority
-                    output.push_str(&format!("{} ", 0)); // policy
+                    output.push_str(&format!("{} ", task.policy.lock_save_irq().as_raw())); // policy
                     output.push_str(&format!("{} ", 0)); // delayacct_blkio_ticks
                     output.push_str(&format!("{} ", 0)); // guest_time
                     output.push_str(&format!("{} ", 0)); // cguest_time
@@ -214,7 +235,13 @@ Threads:\t{tasks}\n",
                     output.push('\n');
                     output
                 }
-                TaskFileType::Cwd => task.cwd.lock_save_irq().clone().1.as_str().to_string(),
+                TaskFileType::Cwd => {
+                    let cwd = task.cwd.lock_save_irq().0.clone();
+                    VFS.path_of(cwd, &current_work())
+                        .await?
+                        .as_str()
+                        .to_string()
+                }
                 TaskFileType::Root => task.root.lock_save_irq().1.as_str().to_string(),
                 TaskFileType::Maps => {
                     let mut output = String::new();
@@ -229,7 +256,7 @@ Threads:\t{tasks}\n",
                             if vma.permissions().read { "r" } else { "-" },
                             if vma.permissions().write { "w" } else { "-" },
                             if vma.permissions().execute { "x" } else { "-" },
-                            "p", // Don't suport shared mappings... yet!
+                            if vma.is_shared() { "s" } else { "p" },
                             vma.file_offset().unwrap_or_default(),
                             vma.name()
                         ));
@@ -248,6 +275,17 @@ Threads:\t{tasks}\n",
                 TaskFileType::Cgroup => {
                     format!("0::{}\n", cgroup_path_for_thread_group(task.process.tgid))
                 }
+                TaskFileType::Mounts => {
+                    let mnt_ns = task.mnt_ns.lock_save_irq().clone();
+
+                    format_mounts(&mnt_ns)
+                }
+                TaskFileType::Mountinfo => {
+                    let mnt_ns = task.mnt_ns.lock_save_irq().clone();
+
+                    format_mountinfo(&mnt_ns)
+                }
+                TaskFileType::Limits => format_limits(&task.process.rsrc_lim.lock_save_irq()),
             }
         } else {
             "State:\tGone\n".to_string()
@@ -259,8 +297,8 @@ Threads:\t{tasks}\n",
         if let TaskFileType::Cwd = self.file_type {
             let task = find_task_by_tid(self.tid);
             return if let Some(task) = task {
-                let cwd = task.cwd.lock_save_irq();
-                Ok(cwd.1.clone())
+                let cwd = task.cwd.lock_save_irq().0.clone();
+                VFS.path_of(cwd, &current_work()).await
             } else {
                 Err(FsError::NotFound.into())
             };
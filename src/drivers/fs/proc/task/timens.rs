@@ -0,0 +1,90 @@
+use crate::clock::timens::{format_offsets, parse_offset};
+use crate::process::{Tid, find_task_by_tid};
+use crate::sched::current_work;
+use alloc::boxed::Box;
+use async_trait::async_trait;
+use libkernel::error::{FsError, KernelError, Result};
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{FileType, Inode, InodeId};
+use libkernel::proc::caps::CapabilitiesFlags;
+
+/// `/proc/<pid>/timens_offsets`: the clock offsets of the time namespace the
+/// task's children will be created in.
+pub struct ProcTimensOffsetsInode {
+    id: InodeId,
+    attr: FileAttr,
+    tid: Tid,
+}
+
+impl ProcTimensOffsetsInode {
+    pub fn new(tid: Tid, id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::File,
+                permissions: FilePermissions::from_bits_retain(0o644),
+                ..FileAttr::default()
+            },
+            tid,
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for ProcTimensOffsetsInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let task = find_task_by_tid(self.tid).ok_or(FsError::NotFound)?;
+        let offsets = task.time_ns.lock_save_irq().for_children.offsets();
+        let data = format_offsets(offsets).into_bytes();
+
+        let start = offset as usize;
+        if start >= data.len() {
+            return Ok(0);
+        }
+
+        let end = usize::min(start + buf.len(), data.len());
+        let slice = &data[start..end];
+        buf[..slice.len()].copy_from_slice(slice);
+        Ok(slice.len())
+    }
+
+    async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        if offset != 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        current_work()
+            .creds
+            .lock_save_irq()
+            .caps()
+            .check_capable(CapabilitiesFlags::CAP_SYS_TIME)?;
+
+        let task = find_task_by_tid(self.tid).ok_or(FsError::NotFound)?;
+        let ns = task.time_ns.lock_save_irq().for_children.clone();
+        let value = str::from_utf8(buf).map_err(|_| KernelError::InvalidValue)?;
+
+        for line in value.lines().filter(|line| !line.trim().is_empty()) {
+            let (clock, secs, nsecs) = parse_offset(line)?;
+
+            ns.set_offset(clock, secs, nsecs)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    async fn truncate(&self, _size: u64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
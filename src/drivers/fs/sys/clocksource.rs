@@ -0,0 +1,123 @@
+//! The clock devices in `/sys/devices/system/clocksource` and
+//! `/sys/devices/system/clockevents`.
+
+use crate::drivers::Driver;
+use crate::drivers::timer::SYS_TIMER;
+use crate::drivers::timer::clocksource::{
+    available_clocksources, current_clocksource, request_clocksource,
+};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::error::{KernelError, Result};
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{FileType, Inode, InodeId, SimpleFile};
+
+pub struct CurrentClocksourceInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl CurrentClocksourceInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::File,
+                permissions: FilePermissions::from_bits_retain(0o644),
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for CurrentClocksourceInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let data = format!("{}\n", current_clocksource().unwrap_or("none")).into_bytes();
+        let start = offset as usize;
+        if start >= data.len() {
+            return Ok(0);
+        }
+
+        let end = usize::min(start + buf.len(), data.len());
+        let slice = &data[start..end];
+        buf[..slice.len()].copy_from_slice(slice);
+        Ok(slice.len())
+    }
+
+    async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        if offset != 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let name = str::from_utf8(buf).map_err(|_| KernelError::InvalidValue)?;
+
+        request_clocksource(name.trim())?;
+
+        Ok(buf.len())
+    }
+
+    async fn truncate(&self, _size: u64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
+
+macro_rules! clock_name_file {
+    ($name:ident, $names:expr) => {
+        pub struct $name {
+            id: InodeId,
+            attr: FileAttr,
+        }
+
+        impl $name {
+            pub fn new(id: InodeId) -> Self {
+                Self {
+                    id,
+                    attr: FileAttr {
+                        file_type: FileType::File,
+                        permissions: FilePermissions::from_bits_retain(0o444),
+                        ..FileAttr::default()
+                    },
+                }
+            }
+        }
+
+        #[async_trait]
+        impl SimpleFile for $name {
+            fn id(&self) -> InodeId {
+                self.id
+            }
+
+            async fn getattr(&self) -> Result<FileAttr> {
+                Ok(self.attr.clone())
+            }
+
+            async fn read(&self) -> Result<Vec<u8>> {
+                let names: Vec<&'static str> = $names;
+                let mut list = names.join(" ");
+                list.push('\n');
+                Ok(list.into_bytes())
+            }
+        }
+    };
+}
+
+clock_name_file!(AvailableClocksourceInode, available_clocksources());
+clock_name_file!(
+    CurrentClockeventInode,
+    Vec::from([SYS_TIMER.get().map(|timer| timer.name()).unwrap_or("none")])
+);
@@ -0,0 +1,55 @@
+//! The CPU lists in `/sys/devices/system/cpu`.
+
+use crate::sched::isolation::{format_cpu_list, isolated_mask, possible_mask};
+use crate::sched::sched_task::CpuMask;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::error::Result;
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{FileType, InodeId, SimpleFile};
+
+macro_rules! cpu_list_file {
+    ($name:ident, $mask:expr) => {
+        pub struct $name {
+            id: InodeId,
+            attr: FileAttr,
+        }
+
+        impl $name {
+            pub fn new(id: InodeId) -> Self {
+                Self {
+                    id,
+                    attr: FileAttr {
+                        file_type: FileType::File,
+                        permissions: FilePermissions::from_bits_retain(0o444),
+                        ..FileAttr::default()
+                    },
+                }
+            }
+        }
+
+        #[async_trait]
+        impl SimpleFile for $name {
+            fn id(&self) -> InodeId {
+                self.id
+            }
+
+            async fn getattr(&self) -> Result<FileAttr> {
+                Ok(self.attr.clone())
+            }
+
+            async fn read(&self) -> Result<Vec<u8>> {
+                let mask: CpuMask = $mask;
+                let mut list = format_cpu_list(&mask);
+                list.push('\n');
+                Ok(list.into_bytes())
+            }
+        }
+    };
+}
+
+// Every CPU that's found is brought up, so the two lists are the same.
+cpu_list_file!(CpuPossibleInode, possible_mask());
+cpu_list_file!(CpuOnlineInode, possible_mask());
+cpu_list_file!(CpuIsolatedInode, isolated_mask());
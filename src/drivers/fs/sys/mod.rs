@@ -1,4 +1,8 @@
 use crate::drivers::Driver;
+use crate::drivers::fs::sys::clocksource::{
+    AvailableClocksourceInode, CurrentClockeventInode, CurrentClocksourceInode,
+};
+use crate::drivers::fs::sys::cpu::{CpuIsolatedInode, CpuOnlineInode, CpuPossibleInode};
 use crate::fs::FilesystemDriver;
 use crate::sync::OnceLock;
 use alloc::boxed::Box;
@@ -18,6 +22,9 @@ use libkernel::{
 };
 use log::warn;
 
+mod clocksource;
+mod cpu;
+
 /// Deterministically generates an inode ID for the given path segments within the sysfs filesystem.
 fn get_inode_id(path_segments: &[&str]) -> u64 {
     let mut hasher = rustc_hash::FxHasher::default();
@@ -120,9 +127,51 @@ static_dir! {
     "char" => FileType::Directory, DevCharInode,
 }
 
+static_dir! {
+    CpuInode,
+    "devices/system/cpu",
+    "isolated" => FileType::File, CpuIsolatedInode,
+    "online" => FileType::File, CpuOnlineInode,
+    "possible" => FileType::File, CpuPossibleInode,
+}
+
+static_dir! {
+    Clocksource0Inode,
+    "devices/system/clocksource/clocksource0",
+    "available_clocksource" => FileType::File, AvailableClocksourceInode,
+    "current_clocksource" => FileType::File, CurrentClocksourceInode,
+}
+
+static_dir! {
+    ClocksourceInode,
+    "devices/system/clocksource",
+    "clocksource0" => FileType::Directory, Clocksource0Inode,
+}
+
+static_dir! {
+    Clockevent0Inode,
+    "devices/system/clockevents/clockevent0",
+    "current_device" => FileType::File, CurrentClockeventInode,
+}
+
+static_dir! {
+    ClockeventsInode,
+    "devices/system/clockevents",
+    "clockevent0" => FileType::Directory, Clockevent0Inode,
+}
+
+static_dir! {
+    SystemInode,
+    "devices/system",
+    "clockevents" => FileType::Directory, ClockeventsInode,
+    "clocksource" => FileType::Directory, ClocksourceInode,
+    "cpu" => FileType::Directory, CpuInode,
+}
+
 static_dir! {
     DevicesInode,
     "devices",
+    "system" => FileType::Directory, SystemInode,
 }
 
 static_dir! {
@@ -8,7 +8,10 @@ use alloc::{boxed::Box, sync::Arc};
 use async_trait::async_trait;
 use libkernel::{
     error::{KernelError, Result},
-    fs::{BlockDevice, Filesystem},
+    fs::{
+        BlockDevice, Filesystem,
+        filesystems::tmpfs::{TmpFs, TmpFsOptions},
+    },
 };
 use log::warn;
 
@@ -36,17 +39,27 @@ impl FilesystemDriver for TmpFsDriver {
         &self,
         fs_id: u64,
         device: Option<Box<dyn BlockDevice>>,
+    ) -> Result<Arc<dyn Filesystem>> {
+        self.construct_with_options(fs_id, device, "").await
+    }
+
+    async fn construct_with_options(
+        &self,
+        fs_id: u64,
+        device: Option<Box<dyn BlockDevice>>,
+        options: &str,
     ) -> Result<Arc<dyn Filesystem>> {
         match device {
             Some(_) => {
                 warn!("Unexpected block device for tmpfs");
                 Err(KernelError::InvalidValue)
             }
-            None => Ok(libkernel::fs::filesystems::tmpfs::TmpFs::<
-                ArchImpl,
-                PgAllocGetter,
-                PageOffsetTranslator,
-            >::new(fs_id)),
+            None => Ok(
+                TmpFs::<ArchImpl, PgAllocGetter, PageOffsetTranslator>::with_options(
+                    fs_id,
+                    TmpFsOptions::parse(options)?,
+                ),
+            ),
         }
     }
 }
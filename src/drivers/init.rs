@@ -1,22 +1,58 @@
 use super::{
     Driver, DriverManager,
-    probe::{DeviceDescriptor, DeviceMatchType, ProbeFn},
+    probe::{AsyncProbeFn, DeviceDescriptor, DeviceMatchType, ProbeFn},
 };
-use crate::{drivers::DM, sync::SpinLock};
-use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
-use libkernel::error::{KernelError, ProbeError, Result};
-use log::error;
+use crate::{
+    drivers::DM,
+    process::kthread::spawn_kthread,
+    sync::{CondVar, OnceLock, SpinLock},
+};
+use alloc::{collections::btree_map::BTreeMap, format, sync::Arc, vec::Vec};
+use libkernel::{
+    error::{KernelError, ProbeError, Result},
+    sync::condvar::WakeupType,
+};
+use log::{error, warn};
 
 pub type InitFunc = fn(&mut PlatformBus, &mut DriverManager) -> Result<()>;
 
+/// When during boot an init call runs. Levels run in order, and every level
+/// but [`InitLevel::Late`] runs before the device tree is probed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InitLevel {
+    /// Core infrastructure that everything after relies on.
+    Early,
+    /// Architecture devices: interrupt controllers and timers.
+    Arch,
+    /// Subsystems that drivers plug into.
+    Subsys,
+    /// Ordinary drivers. This is the default.
+    Device,
+    /// Runs once every device found at boot has been probed.
+    Late,
+}
+
+impl InitLevel {
+    /// The levels that run before the device tree is probed, in order.
+    pub const BEFORE_PROBE: [Self; 4] = [Self::Early, Self::Arch, Self::Subsys, Self::Device];
+}
+
+/// An entry in the `.driver_inits` section, made by [`kernel_driver!`].
+pub struct InitCall {
+    pub level: InitLevel,
+    pub func: InitFunc,
+}
+
 pub struct PlatformBus {
     probers: BTreeMap<DeviceMatchType, Vec<ProbeFn>>,
+    async_probers: BTreeMap<DeviceMatchType, AsyncProbeFn>,
 }
 
 impl PlatformBus {
     pub const fn new() -> Self {
         Self {
             probers: BTreeMap::new(),
+            async_probers: BTreeMap::new(),
         }
     }
 
@@ -26,6 +62,21 @@ impl PlatformBus {
         self.probers.entry(match_type).or_default().push(probe_fn);
     }
 
+    /// Like [`Self::register_platform_driver`], but for devices that don't
+    /// depend on anything probed alongside them. Their probes are held back
+    /// until all CPUs are up, then run in parallel, each on its own kernel
+    /// thread. Init is started once they've all finished.
+    ///
+    /// A compatible string with a synchronous prober never reaches an
+    /// asynchronous one.
+    pub fn register_async_platform_driver(
+        &mut self,
+        match_type: DeviceMatchType,
+        probe_fn: AsyncProbeFn,
+    ) {
+        self.async_probers.insert(match_type, probe_fn);
+    }
+
     /// Called by the FDT prober to find the right driver and probe.
     pub fn probe_device(
         &self,
@@ -41,7 +92,9 @@ impl PlatformBus {
 
                         let match_type = DeviceMatchType::FdtCompatible(compat_str);
 
-                        if self.probers.contains_key(&match_type) {
+                        if self.probers.contains_key(&match_type)
+                            || self.async_probers.contains_key(&match_type)
+                        {
                             return Some(match_type);
                         }
                     }
@@ -51,8 +104,8 @@ impl PlatformBus {
             }
         };
 
-        if let Some(match_type) = matcher
-            && let Some(probe_fns) = self.probers.get(&match_type)
+        if let Some(match_type) = &matcher
+            && let Some(probe_fns) = self.probers.get(match_type)
         {
             // Try each registered probe function until one claims the device.
             for probe_fn in probe_fns {
@@ -73,32 +126,42 @@ impl PlatformBus {
             return Err(KernelError::Probe(ProbeError::NoMatch));
         }
 
+        if let Some(match_type) = &matcher
+            && let Some(probe_fn) = self.async_probers.get(match_type)
+        {
+            // There's no driver yet, it's made once the probe runs.
+            queue_async_probe(descr, probe_fn.clone());
+        }
+
         Ok(None)
     }
 }
 
-/// Run all init calls for all internal kernel drivers.
+/// Run the init calls at `level` for all internal kernel drivers.
 ///
-/// SAFETY: The function should only be called once during boot.
-pub unsafe fn run_initcalls() {
+/// SAFETY: The function should only be called once per level during boot.
+pub unsafe fn run_initcalls(level: InitLevel) {
     unsafe extern "C" {
         static __driver_inits_start: u8;
         static __driver_inits_end: u8;
     }
 
     unsafe {
-        let start = &__driver_inits_start as *const _ as *const InitFunc;
-        let end = &__driver_inits_end as *const _ as *const InitFunc;
+        let start = &__driver_inits_start as *const _ as *const InitCall;
+        let end = &__driver_inits_end as *const _ as *const InitCall;
         let mut current = start;
 
         let mut bus = PLATFORM_BUS.lock_save_irq();
         let mut dm = DM.lock_save_irq();
 
         while current < end {
-            let init_func = &*current;
-            // Call each driver's init function
-            if let Err(e) = init_func(&mut bus, &mut dm) {
-                error!("A driver failed to initialize: {e}");
+            let init_call = &*current;
+
+            if init_call.level == level {
+                // Call each driver's init function
+                if let Err(e) = (init_call.func)(&mut bus, &mut dm) {
+                    error!("A driver failed to initialize: {e}");
+                }
             }
 
             current = current.add(1);
@@ -106,14 +169,95 @@ pub unsafe fn run_initcalls() {
     }
 }
 
+#[derive(Default)]
+struct AsyncProbes {
+    queued: Vec<(DeviceDescriptor, AsyncProbeFn)>,
+    /// Probes queued or running.
+    outstanding: usize,
+}
+
+static ASYNC_PROBES: OnceLock<CondVar<AsyncProbes>> = OnceLock::new();
+
+fn async_probes() -> &'static CondVar<AsyncProbes> {
+    ASYNC_PROBES.get_or_init(|| CondVar::new(AsyncProbes::default()))
+}
+
+fn queue_async_probe(descr: DeviceDescriptor, probe_fn: AsyncProbeFn) {
+    async_probes().update(|probes| {
+        probes.queued.push((descr, probe_fn));
+        probes.outstanding += 1;
+        WakeupType::None
+    });
+}
+
+fn run_async_probe(descr: DeviceDescriptor, probe_fn: AsyncProbeFn) {
+    match probe_fn(descr.clone()) {
+        Ok(driver) => DM.lock_save_irq().insert_driver(driver),
+        Err(KernelError::Probe(ProbeError::NoMatch)) => {}
+        Err(KernelError::Probe(ProbeError::Deferred)) => {
+            // Everything else has been probed by now, so nothing will come.
+            warn!("Could not probe device \"{descr}\" due to missing dependencies.");
+        }
+        Err(e) => error!("Fatal error while probing device \"{descr}\": {e}"),
+    }
+
+    async_probes().update(|probes| {
+        probes.outstanding -= 1;
+
+        if probes.outstanding == 0 {
+            WakeupType::All
+        } else {
+            WakeupType::None
+        }
+    });
+}
+
+/// Starts the probes held back by
+/// [`PlatformBus::register_async_platform_driver`], one kernel thread each.
+/// Called once the secondary CPUs are up, so that they can share the work.
+pub fn start_async_probes() {
+    let mut queued = Vec::new();
+
+    async_probes().update(|probes| {
+        queued = core::mem::take(&mut probes.queued);
+        WakeupType::None
+    });
+
+    for (descr, probe_fn) in queued {
+        let name = format!("probe/{descr}");
+        let (d, p) = (descr.clone(), probe_fn.clone());
+
+        if let Err(e) = spawn_kthread(&name, async move { run_async_probe(d, p) }) {
+            warn!("Could not start {name}: {e}, probing in place");
+            run_async_probe(descr, probe_fn);
+        }
+    }
+}
+
+/// Waits for every probe started by [`start_async_probes`] to finish.
+pub async fn wait_for_async_probes() {
+    async_probes()
+        .wait_until(|probes| (probes.outstanding == 0).then_some(()))
+        .await;
+}
+
+/// Registers `$init_func` to be run at boot, at [`InitLevel::Device`] unless
+/// another level is given.
 #[macro_export]
 macro_rules! kernel_driver {
     ($init_func:expr) => {
+        $crate::kernel_driver!($init_func, Device);
+    };
+    ($init_func:expr, $level:ident) => {
         paste::paste! {
             #[unsafe(no_mangle)]
             #[unsafe(link_section = ".driver_inits")]
             #[used(linker)]
-            static [<DRIVER_INIT_ $init_func>]: $crate::drivers::init::InitFunc = $init_func;
+            static [<DRIVER_INIT_ $init_func>]: $crate::drivers::init::InitCall =
+                $crate::drivers::init::InitCall {
+                    level: $crate::drivers::init::InitLevel::$level,
+                    func: $init_func,
+                };
         }
     };
 }
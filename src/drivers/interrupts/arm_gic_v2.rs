@@ -426,4 +426,4 @@ pub fn arm_gicv2_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<
     Ok(())
 }
 
-kernel_driver!(arm_gicv2_init);
+kernel_driver!(arm_gicv2_init, Arch);
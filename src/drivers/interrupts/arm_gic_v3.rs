@@ -515,4 +515,4 @@ pub fn arm_gicv3_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<
     Ok(())
 }
 
-kernel_driver!(arm_gicv3_init);
+kernel_driver!(arm_gicv3_init, Arch);
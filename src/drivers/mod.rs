@@ -1,13 +1,10 @@
 use core::{
     any::Any,
+    ops::Range,
     sync::atomic::{AtomicU64, Ordering},
 };
 
-use alloc::{
-    collections::btree_map::{BTreeMap, Entry},
-    sync::Arc,
-    vec::Vec,
-};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
 use libkernel::{
     error::{KernelError, Result},
     fs::OpenFlags,
@@ -35,13 +32,14 @@ mod virtio_hal;
 
 #[repr(u64)]
 pub enum ReservedMajors {
-    Null = 1,
-    Zero = 2,
-    Random = 3,
+    /// `/dev/null`, `/dev/zero` and `/dev/random`, each at its own minor.
+    Mem = 1,
+    Uart = 4,
     Console = 5,
-    Fb = 6,
-    Uart = 10,
-    End = 11,
+    /// Assorted single devices, such as the device-mapper control node.
+    Misc = 10,
+    Fb = 29,
+    End = 30,
 }
 
 pub trait Driver: Send + Sync + Any {
@@ -68,12 +66,18 @@ pub trait CharDriver: Send + Sync + 'static {
     fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>>;
 }
 
+/// A run of minor numbers under one major that a single driver looks after.
+struct CharRegion {
+    minors: Range<u64>,
+    driver: Arc<dyn CharDriver>,
+}
+
 pub struct DriverManager {
     /// Every driver instance in the system.
     active_drivers: Vec<Arc<dyn Driver>>,
     _next_major: AtomicU64,
-    /// Maps a major number to an instance of a CharDriver.
-    char_drivers: BTreeMap<u64, Arc<dyn CharDriver>>,
+    /// Maps a major number to the drivers for its minors.
+    char_drivers: BTreeMap<u64, Vec<CharRegion>>,
 }
 
 impl DriverManager {
@@ -103,18 +107,38 @@ impl DriverManager {
         self._next_major.fetch_add(1, Ordering::SeqCst)
     }
 
+    /// Registers `driver` for every minor of `major`.
     pub fn register_char_driver(&mut self, major: u64, driver: Arc<dyn CharDriver>) -> Result<()> {
-        match self.char_drivers.entry(major) {
-            Entry::Vacant(vacant_entry) => {
-                vacant_entry.insert(driver);
-                Ok(())
-            }
-            Entry::Occupied(_) => Err(KernelError::InUse),
+        self.register_char_region(major, 0..u64::MAX, driver)
+    }
+
+    /// Registers `driver` for the `minors` of `major`, which mustn't overlap
+    /// any already registered.
+    pub fn register_char_region(
+        &mut self,
+        major: u64,
+        minors: Range<u64>,
+        driver: Arc<dyn CharDriver>,
+    ) -> Result<()> {
+        let regions = self.char_drivers.entry(major).or_default();
+
+        if regions
+            .iter()
+            .any(|r| r.minors.start < minors.end && minors.start < r.minors.end)
+        {
+            return Err(KernelError::InUse);
         }
+
+        regions.push(CharRegion { minors, driver });
+        Ok(())
     }
 
-    pub fn find_char_driver(&self, major: u64) -> Option<Arc<dyn CharDriver>> {
-        self.char_drivers.get(&major).cloned()
+    pub fn find_char_driver(&self, major: u64, minor: u64) -> Option<Arc<dyn CharDriver>> {
+        self.char_drivers
+            .get(&major)?
+            .iter()
+            .find(|r| r.minors.contains(&minor))
+            .map(|r| r.driver.clone())
     }
 }
 
@@ -33,3 +33,7 @@ impl Display for DeviceDescriptor {
 
 pub type ProbeFn =
     Box<dyn Fn(&mut DriverManager, DeviceDescriptor) -> Result<Arc<dyn Driver>> + Send>;
+
+/// A probe function that runs on its own, without the driver manager locked,
+/// so that it can run in parallel with others.
+pub type AsyncProbeFn = Arc<dyn Fn(DeviceDescriptor) -> Result<Arc<dyn Driver>> + Send + Sync>;
@@ -2,11 +2,13 @@ use crate::arch::ArchImpl;
 use crate::drivers::init::PlatformBus;
 use crate::drivers::probe::{DeviceDescriptor, DeviceMatchType};
 use crate::drivers::rtc::{Rtc, set_rtc_driver};
+use crate::drivers::timer::clocksource::{ClockSource, register_clocksource};
 use crate::drivers::{Driver, DriverManager};
 use crate::kernel_driver;
-use alloc::boxed::Box;
 use alloc::sync::Arc;
 use core::time::Duration;
+use libkernel::driver::mmio::MmioRegion;
+use libkernel::driver::pl031::Pl031;
 use libkernel::error::{ProbeError, Result};
 use libkernel::memory::address::{PA, VA};
 use libkernel::memory::proc_vm::address_space::{KernAddressSpace, VirtualMemory};
@@ -14,36 +16,59 @@ use libkernel::memory::region::PhysMemoryRegion;
 
 /// Driver for a PL031 real-time clock.
 pub struct PL031 {
-    inner: arm_pl031::Rtc,
+    inner: Pl031<MmioRegion>,
 }
 
 impl PL031 {
     /// Constructs a new instance of the RTC driver for a PL031 device with the
     /// given base address.
     pub fn new(base_addr: VA) -> Self {
-        let rtc = unsafe { arm_pl031::Rtc::new(base_addr.as_ptr_mut() as _) };
-        Self { inner: rtc }
+        // SAFETY: The caller hands us the device's MMIO mapping.
+        let regs = unsafe { MmioRegion::new(base_addr) };
+        Self {
+            inner: Pl031::new(regs),
+        }
     }
 }
 
 impl Rtc for PL031 {
     fn time(&self) -> Option<Duration> {
-        Some(Duration::new(self.inner.get_unix_timestamp() as u64, 0))
+        Some(Duration::new(self.inner.time() as u64, 0))
     }
 
     fn set_time(&mut self, time: Duration) -> libkernel::error::Result<()> {
-        self.inner.set_unix_timestamp(time.as_secs() as _);
+        self.inner.set_time(time.as_secs() as _);
         Ok(())
     }
 }
 
+/// The RTC only counts whole seconds, so it's only fit to stand in for, or
+/// keep an eye on, a better source.
+impl ClockSource for PL031 {
+    fn name(&self) -> &'static str {
+        "rtc-pl031"
+    }
+
+    fn rating(&self) -> u32 {
+        10
+    }
+
+    fn read(&self) -> u64 {
+        self.inner.time() as u64
+    }
+
+    fn freq(&self) -> u64 {
+        1
+    }
+}
+
 impl Driver for PL031 {
     fn name(&self) -> &'static str {
         "ARM PrimeCell Real Time Clock"
     }
 }
 
-pub fn pl031_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+pub fn pl031_probe(d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
     match d {
         DeviceDescriptor::Fdt(fdt_node, _flags) => {
             let region = fdt_node
@@ -64,15 +89,16 @@ pub fn pl031_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<d
 
             let dev = Arc::new(PL031::new(mem));
             set_rtc_driver(dev.clone());
+            register_clocksource(dev.clone());
             Ok(dev)
         }
     }
 }
 
 pub fn pl031_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
-    bus.register_platform_driver(
+    bus.register_async_platform_driver(
         DeviceMatchType::FdtCompatible("arm,pl031"),
-        Box::new(pl031_probe),
+        Arc::new(pl031_probe),
     );
 
     Ok(())
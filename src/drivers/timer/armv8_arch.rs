@@ -12,7 +12,10 @@ use crate::{
         Driver, DriverManager,
         init::PlatformBus,
         probe::{DeviceDescriptor, DeviceMatchType},
-        timer::{SYS_TIMER, SysTimer},
+        timer::{
+            SYS_TIMER, SysTimer,
+            clocksource::{ClockSource, register_clocksource},
+        },
     },
     interrupts::{ClaimedInterrupt, InterruptDescriptor::Ppi},
     kernel_driver,
@@ -50,6 +53,24 @@ impl HwTimer for Armv8Timer {
     }
 }
 
+impl ClockSource for Armv8Timer {
+    fn name(&self) -> &'static str {
+        "arch_sys_counter"
+    }
+
+    fn rating(&self) -> u32 {
+        400
+    }
+
+    fn read(&self) -> u64 {
+        CNTPCT_EL0.get()
+    }
+
+    fn freq(&self) -> u64 {
+        self.freq
+    }
+}
+
 fn armv8_timer_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
     match d {
         DeviceDescriptor::Fdt(fdt_node, _) => {
@@ -77,6 +98,8 @@ fn armv8_timer_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
 
             let interrupt = el1_phys_timer_interrupt.ok_or(NoInterrupts)?;
 
+            let mut clocksource = None;
+
             let sys_timer = interrupt_manager.claim_interrupt(interrupt, |claimed_interrupt| {
                 let base_driver = Arc::new(Armv8Timer {
                     fdt_name: Some(fdt_node.name),
@@ -85,6 +108,7 @@ fn armv8_timer_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
                 });
 
                 base_driver.schedule_interrupt(Some(base_driver.now() + Duration::from_secs(5)));
+                clocksource = Some(base_driver.clone());
 
                 SysTimer::from_driver(base_driver)
             })?;
@@ -93,6 +117,10 @@ fn armv8_timer_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
                 warn!("Failed to set system timer");
             }
 
+            if let Some(clocksource) = clocksource {
+                register_clocksource(clocksource);
+            }
+
             Ok(sys_timer)
         }
     }
@@ -107,4 +135,4 @@ pub fn armv8_timer_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Resul
     Ok(())
 }
 
-kernel_driver!(armv8_timer_init);
+kernel_driver!(armv8_timer_init, Arch);
@@ -0,0 +1,378 @@
+//! Clocksources: the free-running counters the kernel keeps time with.
+//!
+//! Every counter that can be read registers itself here with a rating, and the
+//! highest-rated one that hasn't been found unstable drives [`super::uptime`].
+//! Switching sources carries the uptime over, so time never jumps.
+//!
+//! Once there's more than one source, a watchdog thread periodically compares
+//! the current source against the next best. If the two drift apart by more
+//! than their resolution allows, the current source is marked unstable and the
+//! next one takes over.
+//!
+//! The choice can be overridden by writing a name to
+//! `/sys/devices/system/clocksource/clocksource0/current_clocksource`.
+//!
+//! Reading the time is far more common than any of this, so the current
+//! source is published under a sequence count and [`uptime`] never takes the
+//! timekeeper's lock.
+
+use super::{Instant, SYS_TIMER, sleep};
+use crate::process::kthread::spawn_kthread;
+use crate::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering, fence};
+use core::time::Duration;
+use libkernel::error::{KernelError, Result};
+use log::{error, info, warn};
+
+/// How often the watchdog compares the current source against another.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How far the two sources may drift apart over one interval, on top of the
+/// resolution of both.
+const WATCHDOG_MAX_SKEW: Duration = Duration::from_millis(100);
+
+pub trait ClockSource: Send + Sync {
+    /// The name shown in sysfs.
+    fn name(&self) -> &'static str;
+
+    /// How good this source is. Anything below 100 is only used when there's
+    /// nothing better.
+    fn rating(&self) -> u32;
+
+    /// Reads the counter.
+    fn read(&self) -> u64;
+
+    /// The counter frequency, in Hz.
+    fn freq(&self) -> u64;
+}
+
+fn read(source: &dyn ClockSource) -> Instant {
+    Instant {
+        ticks: source.read(),
+        freq: source.freq(),
+    }
+}
+
+/// The smallest step `source` can measure.
+fn resolution(source: &dyn ClockSource) -> Duration {
+    Duration::from_nanos(1_000_000_000u64.div_ceil(source.freq()))
+}
+
+struct Registered {
+    /// Sources are never unregistered, so each is leaked once to give
+    /// [`Published`] a pointer that stays valid.
+    source: &'static Arc<dyn ClockSource>,
+    unstable: bool,
+}
+
+/// A pair of readings taken by the watchdog.
+#[derive(Clone, Copy)]
+struct WatchdogSample {
+    current: usize,
+    watchdog: usize,
+    current_read: Instant,
+    watchdog_read: Instant,
+}
+
+/// A source the watchdog has given up on.
+struct Unstable {
+    name: &'static str,
+    skew: Duration,
+    replacement: Option<&'static str>,
+}
+
+/// The current source and where it started, as read by [`uptime`]. Only
+/// written with the timekeeper's lock held; `seq` is odd while an update is
+/// in progress.
+struct Published {
+    seq: AtomicU64,
+    source: AtomicPtr<Arc<dyn ClockSource>>,
+    start: AtomicU64,
+    base: AtomicU64,
+}
+
+impl Published {
+    const fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            source: AtomicPtr::new(ptr::null_mut()),
+            start: AtomicU64::new(0),
+            base: AtomicU64::new(0),
+        }
+    }
+
+    fn store(&self, source: Option<&'static Arc<dyn ClockSource>>, start: Instant, base: Duration) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        self.source.store(
+            source.map_or(ptr::null_mut(), |source| ptr::from_ref(source).cast_mut()),
+            Ordering::Relaxed,
+        );
+        self.start.store(start.ticks, Ordering::Relaxed);
+        self.base.store(base.as_nanos() as u64, Ordering::Relaxed);
+
+        self.seq.fetch_add(1, Ordering::Release);
+    }
+
+    fn load(&self) -> Option<(&'static dyn ClockSource, Instant, Duration)> {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+
+            if seq % 2 == 1 {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            let source = self.source.load(Ordering::Relaxed);
+            let start = self.start.load(Ordering::Relaxed);
+            let base = self.base.load(Ordering::Relaxed);
+
+            fence(Ordering::Acquire);
+
+            if self.seq.load(Ordering::Relaxed) != seq {
+                continue;
+            }
+
+            // SAFETY: The pointer is either null or comes from a leaked
+            // `Registered::source`.
+            let source: &'static Arc<dyn ClockSource> = unsafe { source.as_ref() }?;
+
+            return Some((
+                source.as_ref(),
+                Instant {
+                    ticks: start,
+                    freq: source.freq(),
+                },
+                Duration::from_nanos(base),
+            ));
+        }
+    }
+}
+
+static PUBLISHED: Published = Published::new();
+
+struct Timekeeper {
+    sources: Vec<Registered>,
+    current: Option<usize>,
+    /// The uptime when `current` was selected.
+    base: Duration,
+    /// What `current` read when it was selected.
+    start: Instant,
+    /// The source asked for through sysfs, if any.
+    requested: Option<&'static str>,
+}
+
+impl Timekeeper {
+    const fn new() -> Self {
+        Self {
+            sources: Vec::new(),
+            current: None,
+            base: Duration::ZERO,
+            start: Instant { ticks: 0, freq: 1 },
+            requested: None,
+        }
+    }
+
+    fn uptime(&self) -> Option<Duration> {
+        let current = &self.sources[self.current?];
+
+        Some(self.base + (read(current.source.as_ref()) - self.start))
+    }
+
+    /// The best stable source, other than `exclude`.
+    fn best(&self, exclude: Option<usize>) -> Option<usize> {
+        self.sources
+            .iter()
+            .enumerate()
+            .filter(|(i, s)| !s.unstable && Some(*i) != exclude)
+            .max_by_key(|(_, s)| s.source.rating())
+            .map(|(i, _)| i)
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.sources
+            .iter()
+            .position(|s| !s.unstable && s.source.name() == name)
+    }
+
+    /// Picks the source to use, switching over to it if it isn't the current
+    /// one. Returns the new source's name if there was a switch.
+    fn select(&mut self) -> Option<&'static str> {
+        let want = self
+            .requested
+            .and_then(|name| self.find(name))
+            .or_else(|| self.best(None));
+
+        if want == self.current {
+            return None;
+        }
+
+        // Carry the uptime over so that it stays monotonic.
+        self.base = self.uptime().unwrap_or_else(|| {
+            SYS_TIMER
+                .get()
+                .map(|timer| timer.uptime())
+                .unwrap_or(Duration::ZERO)
+        });
+        self.current = want;
+
+        let source = want.map(|i| self.sources[i].source);
+
+        if let Some(source) = source {
+            self.start = read(source.as_ref());
+        }
+
+        PUBLISHED.store(source, self.start, self.base);
+
+        source.map(|source| source.name())
+    }
+
+    fn watchdog(&mut self, last: &mut Option<WatchdogSample>) -> Option<Unstable> {
+        let Some((current, watchdog)) = self
+            .current
+            .and_then(|current| Some((current, self.best(Some(current))?)))
+        else {
+            *last = None;
+            return None;
+        };
+
+        let current_src = self.sources[current].source;
+        let watchdog_src = self.sources[watchdog].source;
+
+        let sample = WatchdogSample {
+            current,
+            watchdog,
+            current_read: read(current_src.as_ref()),
+            watchdog_read: read(watchdog_src.as_ref()),
+        };
+
+        let prev = last
+            .replace(sample)
+            .filter(|prev| prev.current == current && prev.watchdog == watchdog)?;
+
+        // A counter that went backwards reads as no time passing, which shows
+        // up as skew.
+        let skew = (sample.current_read - prev.current_read)
+            .abs_diff(sample.watchdog_read - prev.watchdog_read);
+
+        let margin = WATCHDOG_MAX_SKEW
+            + resolution(current_src.as_ref())
+            + resolution(watchdog_src.as_ref());
+
+        if skew <= margin {
+            return None;
+        }
+
+        self.sources[current].unstable = true;
+        *last = None;
+        self.select();
+
+        Some(Unstable {
+            name: current_src.name(),
+            skew,
+            replacement: self.current.map(|i| self.sources[i].source.name()),
+        })
+    }
+}
+
+static TIMEKEEPER: SpinLock<Timekeeper> = SpinLock::new(Timekeeper::new());
+
+static WATCHDOG_STARTED: AtomicBool = AtomicBool::new(false);
+
+async fn watchdog() {
+    let mut last = None;
+
+    loop {
+        sleep(WATCHDOG_INTERVAL).await;
+
+        // Log outside the lock; the console reads the uptime.
+        let unstable = TIMEKEEPER.lock_save_irq().watchdog(&mut last);
+
+        if let Some(unstable) = unstable {
+            warn!(
+                "clocksource: {} is unstable (skew {:?}), switching to {}",
+                unstable.name,
+                unstable.skew,
+                unstable.replacement.unwrap_or("none")
+            );
+        }
+    }
+}
+
+/// Adds `source` to the registry, switching to it if it's now the best one.
+pub fn register_clocksource(source: Arc<dyn ClockSource>) {
+    let (switched, count) = {
+        let mut tk = TIMEKEEPER.lock_save_irq();
+
+        tk.sources.push(Registered {
+            source: Box::leak(Box::new(source)),
+            unstable: false,
+        });
+
+        (tk.select(), tk.sources.len())
+    };
+
+    if let Some(name) = switched {
+        info!("clocksource: switched to {name}");
+    }
+
+    if count > 1
+        && !WATCHDOG_STARTED.swap(true, Ordering::Relaxed)
+        && let Err(e) = spawn_kthread("clocksource-watchdog", watchdog())
+    {
+        error!("clocksource: could not start watchdog: {e}");
+    }
+}
+
+/// The time since boot according to the current source, if there is one.
+pub fn uptime() -> Option<Duration> {
+    let (source, start, base) = PUBLISHED.load()?;
+
+    Some(base + (read(source) - start))
+}
+
+/// The name of the source in use.
+pub fn current_clocksource() -> Option<&'static str> {
+    let tk = TIMEKEEPER.lock_save_irq();
+
+    tk.current.map(|i| tk.sources[i].source.name())
+}
+
+/// The names of the sources that can be selected.
+pub fn available_clocksources() -> Vec<&'static str> {
+    TIMEKEEPER
+        .lock_save_irq()
+        .sources
+        .iter()
+        .filter(|s| !s.unstable)
+        .map(|s| s.source.name())
+        .collect()
+}
+
+/// Overrides the rating-based choice with the source called `name`. An empty
+/// name goes back to picking by rating.
+pub fn request_clocksource(name: &str) -> Result<()> {
+    let switched = {
+        let mut tk = TIMEKEEPER.lock_save_irq();
+
+        if name.is_empty() {
+            tk.requested = None;
+        } else {
+            let i = tk.find(name).ok_or(KernelError::InvalidValue)?;
+            tk.requested = Some(tk.sources[i].source.name());
+        }
+
+        tk.select()
+    };
+
+    if let Some(name) = switched {
+        info!("clocksource: switched to {name}");
+    }
+
+    Ok(())
+}
@@ -1,20 +1,23 @@
 use super::Driver;
-use crate::interrupts::{InterruptDescriptor, InterruptHandler};
+use crate::interrupts::{InterruptDescriptor, InterruptHandler, IrqReturn};
 use crate::per_cpu_private;
 use crate::process::Tid;
 use crate::sync::OnceLock;
 use alloc::boxed::Box;
-use alloc::{collections::binary_heap::BinaryHeap, sync::Arc};
+use alloc::sync::Arc;
 use core::{
     future::poll_fn,
     ops::{Add, Sub},
     task::{Poll, Waker},
     time::Duration,
 };
+use libkernel::driver::timer::TimerQueue;
 
 pub mod armv8_arch;
+pub mod clocksource;
 
-const USER_HZ: u64 = 100;
+/// The tick rate userspace assumes for clock ticks, as reported by `AT_CLKTCK`.
+pub const USER_HZ: u64 = 100;
 
 /// Represents a fixed point in monotonic time.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -87,32 +90,6 @@ enum WakeupKind {
 unsafe impl Send for WakeupKind {}
 unsafe impl Sync for WakeupKind {}
 
-struct WakeupEvent {
-    when: Instant,
-    what: WakeupKind,
-}
-
-impl PartialEq for WakeupEvent {
-    fn eq(&self, other: &Self) -> bool {
-        self.when == other.when
-    }
-}
-
-impl Eq for WakeupEvent {}
-
-#[allow(clippy::non_canonical_partial_ord_impl)]
-impl PartialOrd for WakeupEvent {
-    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
-        Some(self.when.cmp(&other.when).reverse())
-    }
-}
-
-impl Ord for WakeupEvent {
-    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
-    }
-}
-
 impl Add<Duration> for Instant {
     type Output = Self;
 
@@ -172,52 +149,60 @@ impl Driver for SysTimer {
 }
 
 impl InterruptHandler for SysTimer {
-    fn handle_irq(&self, _desc: InterruptDescriptor) {
+    fn handle_irq(&self, _desc: InterruptDescriptor) -> IrqReturn {
         let mut wake_q = WAKEUP_Q.borrow_mut();
 
-        while let Some(next_event) = wake_q.peek() {
-            if next_event.when <= self.driver.now() {
-                let event = wake_q.pop().unwrap(); // We know it's there from peek()
-
-                match event.what {
-                    WakeupKind::Task(waker) => waker.wake(),
-                    WakeupKind::Preempt => {
-                        // Do nothing, the IRQ return-to-userspace code will
-                        // call schedule() for us.
-                    }
-                    WakeupKind::Timer(tid, timer_id, callback) => {
-                        if let Some(next_instant) = callback(tid, timer_id) {
-                            // Re-schedule the timer for its next expiration.
-                            wake_q.push(WakeupEvent {
-                                when: next_instant,
-                                what: WakeupKind::Timer(tid, timer_id, callback),
-                            });
-                        }
+        while let Some((_, what)) = wake_q.pop_expired(self.expiry_now()) {
+            match what {
+                WakeupKind::Task(waker) => waker.wake(),
+                WakeupKind::Preempt => {
+                    // Do nothing, the IRQ return-to-userspace code will
+                    // call schedule() for us.
+                }
+                WakeupKind::Timer(tid, timer_id, callback) => {
+                    if let Some(next_instant) = callback(tid, timer_id) {
+                        // Re-schedule the timer for its next expiration.
+                        wake_q.push(next_instant, WakeupKind::Timer(tid, timer_id, callback));
                     }
                 }
-            } else {
-                // The next event is in the future, so we're done.
-                break;
             }
         }
 
         // Always re-arm: either next task/event, or a periodic/preemption tick.
-        let next_deadline = wake_q.peek().map(|e| e.when).or_else(|| {
+        let next_deadline = wake_q.next_deadline().or_else(|| {
             // fallback: schedule a preemption tick in 50 ms
             // TODO: Remove when feeling more secure about scheduling
-            let when = self.driver.now() + Duration::from_millis(50);
+            let when = self.now() + Duration::from_millis(50);
             Some(when)
         });
 
         self.driver.schedule_interrupt(next_deadline);
+
+        IrqReturn::Handled
     }
 }
 
 impl SysTimer {
+    /// The time since the timer was brought up, by the timer's own counter.
+    /// [`uptime`] prefers the current clocksource.
     pub fn uptime(&self) -> Duration {
         self.driver.now() - self.start_time
     }
 
+    /// The current instant in the timer's terms, derived from [`uptime`] so
+    /// that deadlines agree with it whichever clocksource is in use.
+    pub fn now(&self) -> Instant {
+        self.start_time + uptime()
+    }
+
+    /// The instant to check deadlines against. The interrupt fires by the
+    /// timer's own counter; should that be ahead of the clocksource, going by
+    /// the clocksource alone would re-arm for a deadline the counter has
+    /// already passed.
+    fn expiry_now(&self) -> Instant {
+        self.now().max(self.driver.now())
+    }
+
     fn from_driver(driver: Arc<dyn HwTimer>) -> Self {
         Self {
             start_time: driver.now(),
@@ -226,23 +211,20 @@ impl SysTimer {
     }
 
     pub async fn sleep(&self, duration: Duration) -> () {
-        let when = self.driver.now() + duration;
+        let when = self.now() + duration;
 
         poll_fn(|cx| {
-            if self.driver.now() >= when {
+            if self.expiry_now() >= when {
                 Poll::Ready(())
             } else {
                 let mut wakeup_q = WAKEUP_Q.borrow_mut();
 
-                wakeup_q.push(WakeupEvent {
-                    when,
-                    what: WakeupKind::Task(cx.waker().clone()),
-                });
+                wakeup_q.push(when, WakeupKind::Task(cx.waker().clone()));
 
                 // After pushing, we must update the hardware timer in case our
                 // new event is the earliest one.
-                if let Some(next_event) = wakeup_q.peek() {
-                    self.driver.schedule_interrupt(Some(next_event.when));
+                if let Some(next) = wakeup_q.next_deadline() {
+                    self.driver.schedule_interrupt(Some(next));
                 }
 
                 Poll::Pending
@@ -260,15 +242,12 @@ impl SysTimer {
     ) {
         let mut wakeup_q = WAKEUP_Q.borrow_mut();
 
-        wakeup_q.push(WakeupEvent {
-            when,
-            what: WakeupKind::Timer(tid, id, callback),
-        });
+        wakeup_q.push(when, WakeupKind::Timer(tid, id, callback));
 
         // After pushing, we must update the hardware timer in case our
         // new event is the earliest one.
-        if let Some(next_event) = wakeup_q.peek() {
-            self.driver.schedule_interrupt(Some(next_event.when));
+        if let Some(next) = wakeup_q.next_deadline() {
+            self.driver.schedule_interrupt(Some(next));
         }
     }
 
@@ -276,8 +255,8 @@ impl SysTimer {
         let mut wakeup_q = WAKEUP_Q.borrow_mut();
 
         // Remove any timers matching the given tid and id.
-        wakeup_q.retain(|event| {
-            if let WakeupKind::Timer(event_tid, event_id, _) = &event.what {
+        wakeup_q.retain(|what| {
+            if let WakeupKind::Timer(event_tid, event_id, _) = what {
                 !(event_tid == &tid && event_id == &id)
             } else {
                 true
@@ -286,8 +265,8 @@ impl SysTimer {
 
         // After removing, we must update the hardware timer in case we removed
         // the earliest event.
-        if let Some(next_event) = wakeup_q.peek() {
-            self.driver.schedule_interrupt(Some(next_event.when));
+        if let Some(next) = wakeup_q.next_deadline() {
+            self.driver.schedule_interrupt(Some(next));
         }
     }
 
@@ -296,45 +275,46 @@ impl SysTimer {
         let mut wake_q = WAKEUP_Q.borrow_mut();
 
         // Insert the preemption event.
-        wake_q.push(WakeupEvent {
-            when,
-            what: WakeupKind::Preempt,
-        });
+        wake_q.push(when, WakeupKind::Preempt);
 
         // Ensure the hardware timer is armed for the earliest event.
-        if let Some(next_event) = wake_q.peek() {
-            self.driver.schedule_interrupt(Some(next_event.when));
+        if let Some(next) = wake_q.next_deadline() {
+            self.driver.schedule_interrupt(Some(next));
         }
     }
 
     /// Arms the hardware timer on the current CPU so that the next scheduled
-    /// `WakeupEvent` (or the fallback preemption tick) will fire.
+    /// wakeup (or the fallback preemption tick) will fire.
     /// Secondary CPUs should call this right after they have enabled their
     /// interrupt controller so that they start receiving timer interrupts.
     pub fn kick_current_cpu(&self) {
         let wake_q = WAKEUP_Q.borrow_mut();
 
-        let next_deadline = wake_q.peek().map(|e| e.when).or_else(|| {
+        let next_deadline = wake_q.next_deadline().or_else(|| {
             // Fallback: re-use the same 15 ms periodic tick as the primary CPU.
-            Some(self.driver.now() + Duration::from_millis(15))
+            Some(self.now() + Duration::from_millis(15))
         });
 
         self.driver.schedule_interrupt(next_deadline);
     }
 }
 
-/// Convenience function for obtaining the current system time. If no
-/// `SYS_TIMER` has been setup by the kernel yet, returns a zero duration.
+/// Convenience function for obtaining the current system time, as kept by the
+/// current clocksource. Before any clocksource has been registered this falls
+/// back to `SYS_TIMER`, and before that returns a zero duration.
 pub fn uptime() -> Duration {
-    SYS_TIMER
-        .get()
-        .map(|timer| timer.uptime())
-        .unwrap_or(Duration::ZERO)
+    clocksource::uptime().unwrap_or_else(|| {
+        SYS_TIMER
+            .get()
+            .map(|timer| timer.uptime())
+            .unwrap_or(Duration::ZERO)
+    })
 }
 
-/// Returns the current instant, if the system timer has been initialised.
+/// Returns the current instant, if the system timer has been initialised. It
+/// keeps time with [`uptime`].
 pub fn now() -> Option<Instant> {
-    SYS_TIMER.get().map(|timer| timer.driver.now())
+    SYS_TIMER.get().map(|timer| timer.now())
 }
 
 /// Puts the current task to sleep for `duration`. If no timer driver has yet
@@ -369,7 +349,7 @@ pub fn schedule_preempt(when: Instant) {
 pub static SYS_TIMER: OnceLock<Arc<SysTimer>> = OnceLock::new();
 
 per_cpu_private! {
-    static WAKEUP_Q: BinaryHeap<WakeupEvent> = BinaryHeap::new;
+    static WAKEUP_Q: TimerQueue<Instant, WakeupKind> = TimerQueue::new;
 }
 
 per_cpu_private! {
@@ -2,7 +2,7 @@ use crate::{
     arch::ArchImpl,
     console::{Console, tty::TtyInputHandler},
     drivers::{DeviceDescriptor, DeviceMatchType, Driver, DriverManager, fdt_prober},
-    interrupts::{ClaimedInterrupt, InterruptHandler},
+    interrupts::{ClaimedInterrupt, InterruptHandler, IrqReturn},
     register_driver,
     sync::SpinLock,
 };
@@ -168,7 +168,7 @@ impl Driver for Bcm2835AuxUart {
 }
 
 impl InterruptHandler for Bcm2835AuxUart {
-    fn handle_irq(&self, _desc: crate::interrupts::InterruptDescriptor) {
+    fn handle_irq(&self, _desc: crate::interrupts::InterruptDescriptor) -> IrqReturn {
         let regs = self.regs.lock_save_irq();
         regs.iir.get();
         let data = regs.io.read(AUX_MU_IO_REG::DATA) as u8;
@@ -178,6 +178,8 @@ impl InterruptHandler for Bcm2835AuxUart {
         {
             handler.push_byte(data);
         }
+
+        IrqReturn::Handled
     }
 }
 
@@ -0,0 +1,72 @@
+//! Polled consoles for printing boot messages before devices are probed.
+//!
+//! `--earlycon` uses the UART the firmware names in `/chosen/stdout-path`,
+//! while `--earlycon=DRIVER,ADDRESS` gives it explicitly, e.g.
+//! `--earlycon=pl011,0x9000000`. Only the PL011 is supported.
+
+use super::pl011::PL011EarlyCon;
+use crate::{arch::ArchImpl, console::Console, drivers::fdt_prober::get_fdt};
+use alloc::sync::Arc;
+use libkernel::{
+    error::{FsError, KernelError, ProbeError, Result},
+    memory::{
+        PAGE_SIZE,
+        address::PA,
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::PhysMemoryRegion,
+    },
+};
+
+/// Sets up the early console described by `spec`, the value given to
+/// `--earlycon`, if any.
+pub fn setup(spec: Option<&str>) -> Result<Arc<dyn Console>> {
+    let (driver, addr) = match spec {
+        Some(spec) => {
+            let (driver, addr) = spec.split_once(',').ok_or(KernelError::InvalidValue)?;
+            let addr = addr.strip_prefix("0x").ok_or(KernelError::InvalidValue)?;
+
+            (
+                driver,
+                usize::from_str_radix(addr, 16).map_err(|_| KernelError::InvalidValue)?,
+            )
+        }
+        None => firmware_console()?,
+    };
+
+    if driver != "pl011" {
+        return Err(KernelError::NotSupported);
+    }
+
+    let mem = ArchImpl::kern_address_space()
+        .lock_save_irq()
+        .map_mmio(PhysMemoryRegion::new(PA::from_value(addr), PAGE_SIZE))?;
+
+    Ok(Arc::new(PL011EarlyCon::new(mem)))
+}
+
+/// Returns the driver and register address of the firmware's console.
+fn firmware_console() -> Result<(&'static str, usize)> {
+    let fdt = get_fdt();
+    let node = fdt
+        .chosen()
+        .and_then(|chosen| chosen.stdout())
+        .ok_or(FsError::NoDevice)?
+        .node;
+
+    let driver = if node
+        .compatible()
+        .is_some_and(|mut compats| compats.any(|c| c.is_ok_and(|c| c == "arm,pl011")))
+    {
+        "pl011"
+    } else {
+        return Err(KernelError::NotSupported);
+    };
+
+    let region = node
+        .reg()
+        .ok_or(ProbeError::NoReg)?
+        .next()
+        .ok_or(ProbeError::NoReg)?;
+
+    Ok((driver, region.address as usize))
+}
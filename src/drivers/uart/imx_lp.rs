@@ -57,6 +57,25 @@ register_bitfields![u32,
         RIE OFFSET(21) NUMBITS(1) [
             Disable = 0,
             Enable = 1
+        ],
+        /// Transmit Interrupt Enable
+        TIE OFFSET(23) NUMBITS(1) [
+            Disable = 0,
+            Enable = 1
+        ]
+    ],
+
+    /// Modem IrDA Register
+    MODIR [
+        /// Transmitter clear-to-send enable
+        TXCTSE OFFSET(0) NUMBITS(1) [
+            Disable = 0,
+            Enable = 1
+        ],
+        /// Receiver request-to-send enable
+        RXRTSE OFFSET(3) NUMBITS(1) [
+            Disable = 0,
+            Enable = 1
         ]
     ],
 
@@ -79,7 +98,7 @@ register_structs! {
         (0x018 => ctrl: ReadWrite<u32, CTRL::Register>),
         (0x01C => data: ReadWrite<u32, DATA::Register>),
         (0x020 => r#match: ReadWrite<u32>),
-        (0x024 => modem: ReadWrite<u32>),
+        (0x024 => modir: ReadWrite<u32, MODIR::Register>),
         (0x028 => fifo: ReadWrite<u32>),
         (0x02C => water: ReadWrite<u32>),
         (0x030 => @END),
@@ -118,6 +137,39 @@ impl UartDriver for Imx8UlpLp {
         }
     }
 
+    fn fill_tx_fifo(&mut self, buf: &[u8]) -> usize {
+        let mut written = 0;
+
+        while written < buf.len() && self.regs.stat.is_set(STAT::TDRE) {
+            self.regs.data.write(DATA::DATA.val(buf[written] as u32));
+            written += 1;
+        }
+
+        written
+    }
+
+    fn set_tx_interrupt(&mut self, enabled: bool) {
+        if enabled {
+            self.regs.ctrl.modify(CTRL::TIE::Enable);
+        } else {
+            self.regs.ctrl.modify(CTRL::TIE::Disable);
+        }
+    }
+
+    fn set_flow_control(&mut self, enabled: bool) -> Result<()> {
+        if enabled {
+            self.regs
+                .modir
+                .modify(MODIR::TXCTSE::Enable + MODIR::RXRTSE::Enable);
+        } else {
+            self.regs
+                .modir
+                .modify(MODIR::TXCTSE::Disable + MODIR::RXRTSE::Disable);
+        }
+
+        Ok(())
+    }
+
     fn drain_uart_rx(&mut self, buf: &mut [u8]) -> usize {
         let mut bytes_read = 0;
 
@@ -133,14 +185,6 @@ impl UartDriver for Imx8UlpLp {
     }
 }
 
-impl core::fmt::Write for Imx8UlpLp {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.write_buf(s.as_bytes());
-
-        Ok(())
-    }
-}
-
 pub fn imx8ulp_lpuart_probe(
     dm: &mut DriverManager,
     d: DeviceDescriptor,
@@ -179,8 +223,14 @@ pub fn imx8ulp_lpuart_probe(
                         size,
                     ))?;
 
+            let mut lpuart = Imx8UlpLp::new(mem);
+
+            if fdt_node.find_property("uart-has-rtscts").is_some() {
+                lpuart.set_flow_control(true)?;
+            }
+
             let dev = interrupt_manager.claim_interrupt(interrupt_config, |claimed_interrupt| {
-                Uart::new(Imx8UlpLp::new(mem), claimed_interrupt, fdt_node.name)
+                Uart::new(lpuart, claimed_interrupt, fdt_node.name)
             })?;
 
             uart_cdev.register_console(dev.clone(), flags.contains(FdtFlags::ACTIVE_CONSOLE))?;
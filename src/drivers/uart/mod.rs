@@ -15,6 +15,8 @@
 //! 2. `Uart<D>` Struct: A high-level, generic struct that wraps a concrete `UartDriver`
 //!    implementation. It handles all the common boilerplate:
 //!    - Implementing the `Console` trait for `printk!`-style formatted output.
+//!    - Buffering output so that writers don't wait for the hardware. Bytes
+//!      are fed to the TX FIFO from the interrupt handler as it drains.
 //!    - Implementing the `InterruptHandler` trait to read incoming bytes.
 //!    - Interfacing with a TTY layer via `TtyInputHandler`.
 //!
@@ -22,7 +24,10 @@
 //!     UART as a char device to obtain a `DriverDescriptor`. Also exposes the
 //!     device to userspace via `devfs`.
 
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use super::{
     CharDriver, Driver, DriverManager, OpenableDevice, ReservedMajors, fs::dev::devfs,
@@ -30,17 +35,20 @@ use super::{
 };
 use crate::{
     console::{
-        Console, set_active_console,
+        Console, console_baud, register_console,
         tty::{Tty, TtyInputHandler},
     },
     fs::open_file::OpenFile,
-    interrupts::{ClaimedInterrupt, InterruptHandler},
+    interrupts::{ClaimedInterrupt, InterruptHandler, IrqReturn},
     kernel_driver,
     sync::{OnceLock, SpinLock},
 };
 use alloc::{
     boxed::Box,
-    collections::btree_map::{BTreeMap, Entry},
+    collections::{
+        VecDeque,
+        btree_map::{BTreeMap, Entry},
+    },
     format,
     sync::{Arc, Weak},
 };
@@ -51,6 +59,7 @@ use libkernel::{
 };
 
 //pub mod bcm2835_aux;
+pub mod earlycon;
 pub mod imx_lp;
 pub mod pl011;
 
@@ -60,16 +69,27 @@ pub mod pl011;
 /// manipulation required to send and receive bytes. The methods are designed to
 /// be called from a higher-level context that handles locking and interrupt
 /// management.
-///
-/// In addition to this trait, a driver must also implement `core::fmt::Write`
-/// to be used with the generic `Uart` wrapper.
-pub trait UartDriver: core::fmt::Write + Send + Sync + 'static {
+pub trait UartDriver: Send + Sync + 'static {
     /// Writes a raw byte slice to the UART's transmit buffer.
     ///
     /// This method should block until all bytes in the buffer have been
-    /// successfully written to the hardware's transmit FIFO.
+    /// successfully written to the hardware's transmit FIFO. It is only used
+    /// when output has to reach the wire before we carry on, such as during a
+    /// panic.
     fn write_buf(&mut self, buf: &[u8]);
 
+    /// Writes as many bytes from `buf` as the transmit FIFO will accept
+    /// without blocking.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes that were written to the FIFO.
+    fn fill_tx_fifo(&mut self, buf: &[u8]) -> usize;
+
+    /// Enables or disables the interrupt raised when the transmit FIFO has
+    /// room for more data.
+    fn set_tx_interrupt(&mut self, enabled: bool);
+
     /// Reads all available bytes from the UART's receive buffer into `buf`.
     ///
     /// This method should read from the hardware's receive FIFO until it is
@@ -81,6 +101,127 @@ pub trait UartDriver: core::fmt::Write + Send + Sync + 'static {
     /// The number of bytes that were actually read from the FIFO and written
     /// into `buf`.
     fn drain_uart_rx(&mut self, buf: &mut [u8]) -> usize;
+
+    /// Changes the baud rate of the line.
+    fn set_baud_rate(&mut self, _baud: u32) -> Result<()> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Enables or disables RTS/CTS hardware flow control.
+    fn set_flow_control(&mut self, _enabled: bool) -> Result<()> {
+        Err(KernelError::NotSupported)
+    }
+}
+
+/// How many bytes of output are buffered in front of the hardware.
+const TX_BUF_SZ: usize = 16 * 1024;
+
+/// `ttyS0` is minor 64 of the UART major, as on Linux.
+const UART_MINOR_BASE: u64 = 64;
+
+/// Output waiting for room in the transmit FIFO.
+///
+/// When the buffer fills up, e.g. during a log storm on a slow line, the oldest
+/// bytes are dropped so that writers never have to wait for the hardware.
+struct TxBuf {
+    bytes: VecDeque<u8>,
+    dropped: usize,
+}
+
+impl TxBuf {
+    fn new() -> Self {
+        Self {
+            bytes: VecDeque::with_capacity(TX_BUF_SZ),
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, buf: &[u8]) {
+        for &byte in buf {
+            if self.bytes.len() == TX_BUF_SZ {
+                self.bytes.pop_front();
+                self.dropped += 1;
+            }
+
+            self.bytes.push_back(byte);
+        }
+    }
+}
+
+impl Write for TxBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+
+        Ok(())
+    }
+}
+
+struct UartInner<D: UartDriver> {
+    driver: D,
+    tx: TxBuf,
+}
+
+impl<D: UartDriver> UartInner<D> {
+    /// Queues `buf` for transmission.
+    fn write_buf(&mut self, buf: &[u8]) {
+        // Nothing is queued, so go straight to the FIFO to avoid a round trip
+        // through the interrupt handler.
+        let buf = if self.tx.bytes.is_empty() {
+            &buf[self.driver.fill_tx_fifo(buf)..]
+        } else {
+            buf
+        };
+
+        if buf.is_empty() {
+            return;
+        }
+
+        self.tx.push(buf);
+        self.driver.set_tx_interrupt(true);
+    }
+
+    /// Moves queued bytes into the TX FIFO until either runs out.
+    fn refill_tx_fifo(&mut self) {
+        while !self.tx.bytes.is_empty() {
+            let (chunk, _) = self.tx.bytes.as_slices();
+            let chunk_len = chunk.len();
+            let written = self.driver.fill_tx_fifo(chunk);
+
+            self.tx.bytes.drain(..written);
+
+            if written < chunk_len {
+                return;
+            }
+
+            if self.tx.bytes.is_empty() && self.tx.dropped != 0 {
+                // Let whoever is reading know that output went missing.
+                let dropped = core::mem::take(&mut self.tx.dropped);
+                let _ = write!(self.tx, "\r\n[{dropped} bytes dropped]\r\n");
+            }
+        }
+
+        self.driver.set_tx_interrupt(false);
+    }
+
+    /// Sends everything that is queued, waiting for the hardware if needed.
+    fn flush(&mut self) {
+        let (a, b) = self.tx.bytes.as_slices();
+
+        self.driver.write_buf(a);
+        self.driver.write_buf(b);
+
+        self.tx.bytes.clear();
+        self.driver.set_tx_interrupt(false);
+    }
+}
+
+/// Lets `write_fmt` queue output without formatting into a temporary buffer.
+impl<D: UartDriver> Write for UartInner<D> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_buf(s.as_bytes());
+
+        Ok(())
+    }
 }
 
 /// A generic, high-level UART device.
@@ -89,7 +230,7 @@ pub trait UartDriver: core::fmt::Write + Send + Sync + 'static {
 /// providing common high-level functionality such as console integration,
 /// interrupt handling, and TTY input routing.
 pub struct Uart<D: UartDriver> {
-    driver: SpinLock<D>,
+    inner: SpinLock<UartInner<D>>,
     name: &'static str,
     _interrupt: ClaimedInterrupt,
     tty_handler: SpinLock<Option<Weak<dyn TtyInputHandler>>>,
@@ -97,15 +238,21 @@ pub struct Uart<D: UartDriver> {
 
 impl<D: UartDriver> Console for Uart<D> {
     fn write_char(&self, c: char) {
-        let _ = self.driver.lock_save_irq().write_char(c);
+        let mut buf = [0; 4];
+
+        self.write_buf(c.encode_utf8(&mut buf).as_bytes());
     }
 
     fn write_fmt(&self, args: core::fmt::Arguments) -> core::fmt::Result {
-        self.driver.lock_save_irq().write_fmt(args)
+        self.inner.lock_save_irq().write_fmt(args)
     }
 
     fn write_buf(&self, buf: &[u8]) {
-        self.driver.lock_save_irq().write_buf(buf);
+        self.inner.lock_save_irq().write_buf(buf);
+    }
+
+    fn flush(&self) {
+        self.inner.lock_save_irq().flush();
     }
 
     fn register_input_handler(&self, handler: Weak<dyn TtyInputHandler>) {
@@ -124,12 +271,23 @@ impl<D: UartDriver> Uart<D> {
     /// * `name`: A static string-slice to identify this device.
     pub fn new(driver: D, interrupt: ClaimedInterrupt, name: &'static str) -> Self {
         Self {
-            driver: SpinLock::new(driver),
+            inner: SpinLock::new(UartInner {
+                driver,
+                tx: TxBuf::new(),
+            }),
             name,
             _interrupt: interrupt,
             tty_handler: SpinLock::new(None),
         }
     }
+
+    /// Changes the line speed, once the output queued so far has been sent.
+    pub fn set_baud_rate(&self, baud: u32) -> Result<()> {
+        let mut inner = self.inner.lock_save_irq();
+
+        inner.flush();
+        inner.driver.set_baud_rate(baud)
+    }
 }
 
 impl<D: UartDriver> Driver for Uart<D> {
@@ -143,8 +301,9 @@ impl<D: UartDriver> InterruptHandler for Uart<D> {
     /// The interrupt handler function.
     ///
     /// The handler drains the UART's receive FIFO and forwards the bytes to the
-    /// registered TTY input handler.
-    fn handle_irq(&self, _desc: crate::interrupts::InterruptDescriptor) {
+    /// registered TTY input handler, then refills the transmit FIFO from any
+    /// queued output.
+    fn handle_irq(&self, _desc: crate::interrupts::InterruptDescriptor) -> IrqReturn {
         const BUF_CAPACITY: usize = 32;
         // Guard against drivers that always report progress.
         const MAX_DRAIN_ITERS: usize = 128;
@@ -159,7 +318,11 @@ impl<D: UartDriver> InterruptHandler for Uart<D> {
 
         for _ in 0..MAX_DRAIN_ITERS {
             // Drain phase: Lock the driver and call its drain method.
-            let bytes_read = self.driver.lock_save_irq().drain_uart_rx(&mut byte_buf);
+            let bytes_read = self
+                .inner
+                .lock_save_irq()
+                .driver
+                .drain_uart_rx(&mut byte_buf);
 
             // Processing phase: If bytes were read, forward them to the TTY.
             if bytes_read == 0 {
@@ -173,6 +336,10 @@ impl<D: UartDriver> InterruptHandler for Uart<D> {
                     .for_each(|b| handler.push_byte(b));
             }
         }
+
+        self.inner.lock_save_irq().refill_tx_fifo();
+
+        IrqReturn::Handled
     }
 }
 
@@ -200,16 +367,18 @@ impl CharDriver for UartCharDev {
 }
 
 impl UartCharDev {
-    fn allocate_minor(&self) -> u64 {
+    fn allocate_index(&self) -> u64 {
         self.next_instance.fetch_add(1, Ordering::SeqCst)
     }
 
-    fn register_console(
+    fn register_console<D: UartDriver>(
         &self,
-        driver: Arc<dyn Console>,
-        active_console: bool,
+        driver: Arc<Uart<D>>,
+        firmware_console: bool,
     ) -> Result<CharDevDescriptor> {
-        let minor = self.allocate_minor();
+        let index = self.allocate_index();
+        let name = format!("ttyS{index}");
+        let minor = UART_MINOR_BASE + index;
 
         let desc = CharDevDescriptor {
             major: ReservedMajors::Uart as _,
@@ -222,16 +391,14 @@ impl UartCharDev {
                     driver: driver.clone(),
                 }));
 
-                devfs().mknod(
-                    format!("ttyS{minor}"),
-                    desc,
-                    FilePermissions::from_bits_retain(0o600),
-                )?;
+                devfs().mknod(name.clone(), desc, FilePermissions::from_bits_retain(0o600))?;
 
-                if active_console {
-                    set_active_console(driver, desc)?;
+                if let Some(baud) = console_baud(&name) {
+                    driver.set_baud_rate(baud)?;
                 }
 
+                register_console(name, driver, desc, firmware_console);
+
                 Ok(desc)
             }
             Entry::Occupied(_) => Err(KernelError::InUse),
@@ -1,19 +1,29 @@
 use crate::{
     arch::ArchImpl,
+    console::{Console, tty::TtyInputHandler},
     drivers::{
         DeviceDescriptor, Driver, DriverManager,
         init::PlatformBus,
         probe::{DeviceMatchType, FdtFlags},
     },
     kernel_driver,
+    sync::SpinLock,
+};
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
 };
-use alloc::{boxed::Box, sync::Arc};
 use arm_pl011_uart::{
-    DataBits, Interrupts, LineConfig, PL011Registers, Parity, StopBits, UniqueMmioPointer,
+    DataBits, FifoLevel, Interrupts, LineConfig, PL011Registers, Parity, StopBits,
+    UniqueMmioPointer,
+};
+use core::{
+    fmt::{self, Write},
+    hint::spin_loop,
+    ptr::NonNull,
 };
-use core::ptr::NonNull;
 use libkernel::{
-    error::{ProbeError, Result},
+    error::{KernelError, ProbeError, Result},
     memory::{
         address::{PA, VA},
         proc_vm::address_space::{KernAddressSpace, VirtualMemory},
@@ -23,12 +33,31 @@ use libkernel::{
 
 use super::{UART_CHAR_DEV, Uart, UartDriver};
 
+/// Offset of the control register, which holds the flow control enables that
+/// `arm_pl011_uart` doesn't expose.
+const UARTCR: usize = 0x30;
+const UARTCR_RTSEN: u32 = 1 << 14;
+const UARTCR_CTSEN: u32 = 1 << 15;
+
+/// Reference clock assumed when the device tree doesn't give one.
+const DEFAULT_UARTCLK: u32 = 16_000_000;
+const DEFAULT_BAUD: u32 = 115_200;
+
+const LINE_CONFIG: LineConfig = LineConfig {
+    data_bits: DataBits::Bits8,
+    parity: Parity::None,
+    stop_bits: StopBits::One,
+};
+
 pub struct PL011 {
     inner: arm_pl011_uart::Uart<'static>,
+    base_addr: VA,
+    uartclk: u32,
+    flow_control: bool,
 }
 
 impl PL011 {
-    pub fn new(base_addr: VA) -> Self {
+    pub fn new(base_addr: VA, uartclk: u32) -> Self {
         let ptr = unsafe {
             UniqueMmioPointer::new(NonNull::new_unchecked(
                 base_addr.as_ptr_mut().cast::<PL011Registers>(),
@@ -37,36 +66,105 @@ impl PL011 {
 
         let mut uart = arm_pl011_uart::Uart::new(ptr);
 
-        let line_config = LineConfig {
-            data_bits: DataBits::Bits8,
-            parity: Parity::None,
-            stop_bits: StopBits::One,
-        };
+        uart.enable(LINE_CONFIG, DEFAULT_BAUD, uartclk).unwrap();
 
-        uart.enable(line_config, 115_200, 16_000_000).unwrap();
+        // Raise the TX interrupt once the FIFO has drained to an eighth full,
+        // leaving time to refill it before the line goes idle.
+        uart.set_interrupt_fifo_levels(FifoLevel::Bytes16, FifoLevel::Bytes4);
 
         // Interrupts not enabled yet, just mask RX interrupts in hardware
         uart.set_interrupt_masks(Interrupts::RXI);
 
-        Self { inner: uart }
+        Self {
+            inner: uart,
+            base_addr,
+            uartclk,
+            flow_control: false,
+        }
+    }
+
+    fn update_control(&mut self, set: u32, clear: u32) {
+        let cr = (self.base_addr.value() + UARTCR) as *mut u32;
+
+        // SAFETY: `base_addr` maps the PL011 register block, which we own.
+        unsafe { cr.write_volatile((cr.read_volatile() & !clear) | set) };
     }
 }
 
-impl core::fmt::Write for PL011 {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.write_buf(s.as_bytes());
+/// An output-only PL011 for `--earlycon`. It polls the TX FIFO rather than
+/// waiting on interrupts, and relies on the firmware having set the line up.
+pub struct PL011EarlyCon {
+    inner: SpinLock<arm_pl011_uart::Uart<'static>>,
+}
 
-        Ok(())
+impl PL011EarlyCon {
+    pub fn new(base_addr: VA) -> Self {
+        let ptr = unsafe {
+            UniqueMmioPointer::new(NonNull::new_unchecked(
+                base_addr.as_ptr_mut().cast::<PL011Registers>(),
+            ))
+        };
+
+        Self {
+            inner: SpinLock::new(arm_pl011_uart::Uart::new(ptr)),
+        }
+    }
+}
+
+impl Console for PL011EarlyCon {
+    fn write_char(&self, c: char) {
+        let _ = self.inner.lock_save_irq().write_char(c);
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.inner.lock_save_irq().write_fmt(args)
+    }
+
+    fn write_buf(&self, buf: &[u8]) {
+        let mut uart = self.inner.lock_save_irq();
+
+        for c in buf {
+            while uart.is_tx_fifo_full() {
+                spin_loop();
+            }
+
+            uart.write_word(*c);
+        }
     }
+
+    fn register_input_handler(&self, _handler: Weak<dyn TtyInputHandler>) {}
 }
 
 impl UartDriver for PL011 {
     fn write_buf(&mut self, buf: &[u8]) {
         for c in buf {
+            while self.inner.is_tx_fifo_full() {
+                spin_loop();
+            }
+
             self.inner.write_word(*c);
         }
     }
 
+    fn fill_tx_fifo(&mut self, buf: &[u8]) -> usize {
+        let mut written = 0;
+
+        while written < buf.len() && !self.inner.is_tx_fifo_full() {
+            self.inner.write_word(buf[written]);
+            written += 1;
+        }
+
+        written
+    }
+
+    fn set_tx_interrupt(&mut self, enabled: bool) {
+        let mut masks = self.inner.interrupt_masks();
+
+        masks.set(Interrupts::TXI, enabled);
+
+        self.inner.set_interrupt_masks(masks);
+    }
+
     fn drain_uart_rx(&mut self, buf: &mut [u8]) -> usize {
         let mut bytes_read = 0;
 
@@ -86,6 +184,34 @@ impl UartDriver for PL011 {
 
         bytes_read
     }
+
+    fn set_baud_rate(&mut self, baud: u32) -> Result<()> {
+        // Don't cut off whatever is still being sent at the old rate.
+        while self.inner.is_busy() {
+            spin_loop();
+        }
+
+        self.inner
+            .enable(LINE_CONFIG, baud, self.uartclk)
+            .map_err(|_| KernelError::InvalidValue)?;
+
+        // Enabling the UART resets the control register.
+        self.set_flow_control(self.flow_control)
+    }
+
+    fn set_flow_control(&mut self, enabled: bool) -> Result<()> {
+        let bits = UARTCR_RTSEN | UARTCR_CTSEN;
+
+        if enabled {
+            self.update_control(bits, 0);
+        } else {
+            self.update_control(0, bits);
+        }
+
+        self.flow_control = enabled;
+
+        Ok(())
+    }
 }
 
 pub fn pl011_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
@@ -128,8 +254,24 @@ pub fn pl011_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dy
 
             let interrupt_config = interrupt_manager.parse_fdt_interrupt_regs(&mut interrupts)?;
 
+            let uartclk = fdt_node
+                .clocks()
+                .find(|clk| clk.name == Some("uartclk"))
+                .and_then(|clk| clk.clock_frequency)
+                .unwrap_or(DEFAULT_UARTCLK);
+
+            let mut pl011 = PL011::new(mem, uartclk);
+
+            if let Some(speed) = fdt_node.find_property("current-speed") {
+                pl011.set_baud_rate(speed.u32())?;
+            }
+
+            if fdt_node.find_property("uart-has-rtscts").is_some() {
+                pl011.set_flow_control(true)?;
+            }
+
             let dev = interrupt_manager.claim_interrupt(interrupt_config, |claimed_interrupt| {
-                Uart::new(PL011::new(mem), claimed_interrupt, fdt_node.name)
+                Uart::new(pl011, claimed_interrupt, fdt_node.name)
             })?;
 
             uart_cdev.register_console(dev.clone(), flags.contains(FdtFlags::ACTIVE_CONSOLE))?;
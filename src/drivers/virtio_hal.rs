@@ -3,6 +3,7 @@ use crate::memory::PageOffsetTranslator;
 use core::ptr::NonNull;
 use libkernel::memory::PAGE_SIZE;
 use libkernel::memory::address::{PA, TPA};
+use libkernel::memory::allocators::phys::AllocFlags;
 use libkernel::memory::region::PhysMemoryRegion;
 use log::trace;
 use virtio_drivers::{BufferDirection, Hal, PhysAddr};
@@ -22,10 +23,12 @@ unsafe impl Hal for VirtioHal {
     fn dma_alloc(pages: usize, _direction: BufferDirection) -> (PhysAddr, NonNull<u8>) {
         let order = Self::pages_to_order(pages);
 
+        // Keep buffers within reach of devices that only take 32-bit
+        // addresses (e.g. legacy virtio-mmio queues).
         let region = crate::memory::PAGE_ALLOC
             .get()
             .expect("PAGE_ALLOC not initialized")
-            .alloc_frames(order)
+            .alloc_frames_flags(order, AllocFlags::DMA32)
             .expect("virtio dma_alloc: out of memory")
             .leak();
 
@@ -0,0 +1,721 @@
+//! A write-back cache of recently used blocks.
+//!
+//! Mounted filesystems see their device through a [`CachedBlkDev`], which
+//! keeps the most recently used blocks in memory. This is what readahead
+//! fetches into: a hint covering blocks that aren't cached yet turns into a
+//! single large read of the device, so the reads that follow are served from
+//! memory.
+//!
+//! Writes land in the cache and reach the device when it is synced, or when
+//! the blocks are evicted. The cache of every device counts towards one limit,
+//! set with `--block-cache-kbytes` or `/proc/sys/vm/block_cache_kbytes`. A
+//! device that goes over it evicts its own least recently used blocks,
+//! writing dirty ones back first; the caches of other devices shrink as they
+//! are next used.
+//!
+//! Each block remembers the most favoured I/O priority class of the tasks that
+//! read or wrote it. Blocks only used by idle-class tasks are evicted before
+//! any best-effort ones, and those before any real-time ones.
+
+use crate::{
+    process::ioprio::{IoPrioClass, current_ioprio_class},
+    sync::SpinLock,
+};
+use alloc::{
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use libkernel::{error::Result, fs::BlockDevice};
+
+/// Default for the bytes of block data kept in memory, across all devices.
+const DEFAULT_CACHE_BYTES: usize = 8 * 1024 * 1024;
+
+static CACHE_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_CACHE_BYTES);
+
+/// Bytes of block data cached for all devices.
+static CACHED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The caches of every device, for statistics and for shrinking.
+static CACHES: SpinLock<Vec<Weak<SharedCache>>> = SpinLock::new(Vec::new());
+
+/// Returns the value of the `block_cache_kbytes` sysctl, in bytes.
+pub fn cache_limit() -> usize {
+    CACHE_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Sets the value of the `block_cache_kbytes` sysctl, in bytes. Clean blocks
+/// over the new limit are dropped straight away; dirty ones once they've been
+/// written back.
+pub fn set_cache_limit(bytes: usize) {
+    CACHE_LIMIT.store(bytes, Ordering::Relaxed);
+
+    for cache in live_caches() {
+        let excess = cached_bytes().saturating_sub(bytes);
+
+        cache.state.lock_save_irq().drop_clean(excess);
+    }
+}
+
+/// Returns the bytes of block data cached for all devices.
+pub fn cached_bytes() -> usize {
+    CACHED_BYTES.load(Ordering::Relaxed)
+}
+
+/// Drops every clean cached block, as asked for through
+/// `/proc/sys/vm/drop_caches`.
+pub fn drop_clean() {
+    for cache in live_caches() {
+        cache.state.lock_save_irq().drop_clean(usize::MAX);
+    }
+}
+
+/// What the cache of one device holds, and has done.
+pub struct BlockCacheStats {
+    pub name: String,
+    pub block_size: usize,
+    pub blocks: usize,
+    pub dirty: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub writebacks: u64,
+}
+
+/// Returns the statistics of the cache of each device.
+pub fn stats() -> Vec<BlockCacheStats> {
+    live_caches()
+        .iter()
+        .map(|cache| {
+            let state = cache.state.lock_save_irq();
+
+            BlockCacheStats {
+                name: cache.name.clone(),
+                block_size: cache.block_size,
+                blocks: state.blocks.len(),
+                dirty: state.blocks.values().filter(|b| b.dirty).count(),
+                hits: state.hits,
+                misses: state.misses,
+                evictions: state.evictions,
+                writebacks: state.writebacks,
+            }
+        })
+        .collect()
+}
+
+fn live_caches() -> Vec<Arc<SharedCache>> {
+    let mut caches = CACHES.lock_save_irq();
+
+    caches.retain(|cache| cache.strong_count() > 0);
+    caches.iter().filter_map(Weak::upgrade).collect()
+}
+
+struct CachedBlock {
+    data: Box<[u8]>,
+    /// Set while the block holds data the device doesn't have yet.
+    dirty: bool,
+    /// The most favoured class of the tasks that used the block.
+    class: IoPrioClass,
+    /// When the block was last used, which along with `class` is its key in
+    /// `CacheState::lru`.
+    stamp: u64,
+}
+
+impl CachedBlock {
+    fn lru_key(&self) -> (IoPrioClass, u64) {
+        (self.class, self.stamp)
+    }
+}
+
+struct CacheState {
+    blocks: BTreeMap<u64, CachedBlock>,
+    /// Block numbers in eviction order: least favoured class first, then
+    /// least recently used.
+    lru: BTreeMap<(IoPrioClass, u64), u64>,
+    next_stamp: u64,
+    /// Bumped by every write, so that a read which raced with one doesn't
+    /// cache what may be stale data.
+    generation: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    writebacks: u64,
+}
+
+impl CacheState {
+    fn touch(&mut self, block: u64, class: IoPrioClass) {
+        if let Some(entry) = self.blocks.get_mut(&block) {
+            self.lru.remove(&entry.lru_key());
+            entry.class = entry.class.max(class);
+            entry.stamp = self.next_stamp;
+            self.lru.insert(entry.lru_key(), block);
+            self.next_stamp += 1;
+        }
+    }
+
+    /// Copies `block` into `buf` if it is cached.
+    fn get(&mut self, block: u64, buf: &mut [u8], class: IoPrioClass) -> bool {
+        let Some(entry) = self.blocks.get(&block) else {
+            self.misses += 1;
+            return false;
+        };
+
+        buf.copy_from_slice(&entry.data);
+        self.touch(block, class);
+        self.hits += 1;
+
+        true
+    }
+
+    /// Caches consecutive blocks starting at `block`, used by a task of
+    /// `class`. Clean blocks, just read from the device, don't replace ones
+    /// that are already cached.
+    fn insert(
+        &mut self,
+        block: u64,
+        data: &[u8],
+        block_size: usize,
+        dirty: bool,
+        class: IoPrioClass,
+    ) {
+        for (i, chunk) in data.chunks(block_size).enumerate() {
+            let block = block + i as u64;
+
+            if let Some(entry) = self.blocks.get_mut(&block) {
+                if dirty {
+                    entry.data.copy_from_slice(chunk);
+                    entry.dirty = true;
+                }
+
+                self.touch(block, class);
+                continue;
+            }
+
+            let entry = CachedBlock {
+                data: chunk.into(),
+                dirty,
+                class,
+                stamp: self.next_stamp,
+            };
+
+            self.lru.insert(entry.lru_key(), block);
+            self.blocks.insert(block, entry);
+            self.next_stamp += 1;
+            CACHED_BYTES.fetch_add(block_size, Ordering::Relaxed);
+        }
+    }
+
+    fn remove(&mut self, block: u64) {
+        if let Some(entry) = self.blocks.remove(&block) {
+            self.lru.remove(&entry.lru_key());
+            CACHED_BYTES.fetch_sub(entry.data.len(), Ordering::Relaxed);
+        }
+    }
+
+    /// Drops clean blocks, in eviction order, until at least `bytes` have
+    /// been freed.
+    fn drop_clean(&mut self, bytes: usize) {
+        let clean: Vec<u64> = self
+            .lru
+            .values()
+            .copied()
+            .filter(|block| !self.blocks[block].dirty)
+            .collect();
+        let mut freed = 0;
+
+        for block in clean {
+            if freed >= bytes {
+                break;
+            }
+
+            freed += self.blocks[&block].data.len();
+            self.remove(block);
+            self.evictions += 1;
+        }
+    }
+
+    /// Evicts blocks, in eviction order, until at least `bytes` have been
+    /// freed. Clean blocks go straight away; the dirty ones are returned, to
+    /// be written back before they go.
+    fn evict(&mut self, bytes: usize) -> Vec<u64> {
+        let lru: Vec<u64> = self.lru.values().copied().collect();
+        let mut freed = 0;
+        let mut dirty = Vec::new();
+
+        for block in lru {
+            if freed >= bytes {
+                break;
+            }
+
+            freed += self.blocks[&block].data.len();
+
+            if self.blocks[&block].dirty {
+                dirty.push(block);
+            } else {
+                self.remove(block);
+                self.evictions += 1;
+            }
+        }
+
+        dirty
+    }
+
+    /// Marks dirty blocks clean, returning copies of them to write back.
+    fn take_dirty(&mut self, blocks: impl Iterator<Item = u64>) -> Vec<(u64, Box<[u8]>)> {
+        blocks
+            .filter_map(|block| {
+                let entry = self.blocks.get_mut(&block)?;
+
+                entry.dirty.then(|| {
+                    entry.dirty = false;
+                    (block, entry.data.clone())
+                })
+            })
+            .collect()
+    }
+}
+
+struct SharedCache {
+    name: String,
+    block_size: usize,
+    state: SpinLock<CacheState>,
+}
+
+pub struct CachedBlkDev {
+    dev: Box<dyn BlockDevice>,
+    cache: Arc<SharedCache>,
+    /// Bytes this device may cache, overriding the global limit.
+    limit: Option<usize>,
+}
+
+impl CachedBlkDev {
+    /// Wraps `dev`, whose statistics are listed under `name`.
+    pub fn new(dev: Box<dyn BlockDevice>, name: String) -> Self {
+        let cache = Arc::new(SharedCache {
+            name,
+            block_size: dev.block_size(),
+            state: SpinLock::new(CacheState {
+                blocks: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                next_stamp: 0,
+                generation: 0,
+                hits: 0,
+                misses: 0,
+                evictions: 0,
+                writebacks: 0,
+            }),
+        });
+
+        CACHES.lock_save_irq().push(Arc::downgrade(&cache));
+
+        Self {
+            dev,
+            cache,
+            limit: None,
+        }
+    }
+
+    /// Returns the bytes by which the cache is over its limit.
+    fn excess(&self, state: &CacheState) -> usize {
+        match self.limit {
+            Some(limit) => (state.blocks.len() * self.block_size()).saturating_sub(limit),
+            None => cached_bytes().saturating_sub(cache_limit()),
+        }
+    }
+
+    /// Reads `buf.len() / block_size` blocks from the device and caches them,
+    /// unless a write completed in the meantime.
+    async fn fill(&self, block_id: u64, buf: &mut [u8], class: IoPrioClass) -> Result<()> {
+        let generation = self.cache.state.lock_save_irq().generation;
+
+        self.dev.read(block_id, buf).await?;
+
+        {
+            let mut state = self.cache.state.lock_save_irq();
+
+            if state.generation == generation {
+                state.insert(block_id, buf, self.block_size(), false, class);
+            }
+        }
+
+        self.shrink().await
+    }
+
+    /// Writes `blocks`, in block order, to the device, merging runs of
+    /// consecutive blocks into a single write. Blocks that fail to be written
+    /// are marked dirty again.
+    async fn write_back(&self, blocks: Vec<(u64, Box<[u8]>)>) -> Result<()> {
+        let mut blocks = blocks.into_iter().peekable();
+
+        while let Some((start, data)) = blocks.next() {
+            let mut run = data.into_vec();
+            let mut end = start + 1;
+
+            while let Some((_, data)) = blocks.next_if(|(block, _)| *block == end) {
+                run.extend_from_slice(&data);
+                end += 1;
+            }
+
+            if let Err(e) = self.dev.write(start, &run).await {
+                let mut state = self.cache.state.lock_save_irq();
+
+                for block in start..end {
+                    if let Some(entry) = state.blocks.get_mut(&block) {
+                        entry.dirty = true;
+                    }
+                }
+
+                return Err(e);
+            }
+
+            self.cache.state.lock_save_irq().writebacks += end - start;
+        }
+
+        Ok(())
+    }
+
+    /// Evicts blocks until the cache is within its limit, or has nothing
+    /// left. Dirty blocks are written back before they go.
+    async fn shrink(&self) -> Result<()> {
+        let dirty = {
+            let mut state = self.cache.state.lock_save_irq();
+            let excess = self.excess(&state);
+
+            if excess == 0 {
+                return Ok(());
+            }
+
+            let mut victims = state.evict(excess);
+
+            if victims.is_empty() {
+                return Ok(());
+            }
+
+            victims.sort_unstable();
+            state.take_dirty(victims.into_iter())
+        };
+
+        let blocks: Vec<u64> = dirty.iter().map(|(block, _)| *block).collect();
+
+        self.write_back(dirty).await?;
+
+        let mut state = self.cache.state.lock_save_irq();
+
+        // Blocks written again since are dirty once more, and stay.
+        for block in blocks {
+            if state.blocks.get(&block).is_some_and(|entry| !entry.dirty) {
+                state.remove(block);
+                state.evictions += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for CachedBlkDev {
+    fn drop(&mut self) {
+        let mut state = self.cache.state.lock_save_irq();
+        let blocks: Vec<u64> = state.blocks.keys().copied().collect();
+
+        for block in blocks {
+            state.remove(block);
+        }
+    }
+}
+
+#[async_trait]
+impl BlockDevice for CachedBlkDev {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        let class = current_ioprio_class();
+        let block_size = self.block_size();
+        let count = buf.len() / block_size;
+        let mut i = 0;
+
+        while i < count {
+            if self.cache.state.lock_save_irq().get(
+                block_id + i as u64,
+                &mut buf[i * block_size..(i + 1) * block_size],
+                class,
+            ) {
+                i += 1;
+                continue;
+            }
+
+            // Fetch the whole run of missing blocks in one go.
+            let mut end = i + 1;
+            {
+                let state = self.cache.state.lock_save_irq();
+                while end < count && !state.blocks.contains_key(&(block_id + end as u64)) {
+                    end += 1;
+                }
+            }
+
+            self.fill(
+                block_id + i as u64,
+                &mut buf[i * block_size..end * block_size],
+                class,
+            )
+            .await?;
+
+            i = end;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        let class = current_ioprio_class();
+
+        {
+            let mut state = self.cache.state.lock_save_irq();
+
+            state.generation += 1;
+            state.insert(block_id, buf, self.block_size(), true, class);
+        }
+
+        self.shrink().await
+    }
+
+    fn block_size(&self) -> usize {
+        self.dev.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.dev.num_blocks()
+    }
+
+    async fn sync(&self) -> Result<()> {
+        let dirty = {
+            let mut state = self.cache.state.lock_save_irq();
+            let blocks: Vec<u64> = state.blocks.keys().copied().collect();
+
+            state.take_dirty(blocks.into_iter())
+        };
+
+        self.write_back(dirty).await?;
+        self.shrink().await?;
+        self.dev.sync().await
+    }
+
+    async fn readahead(&self, block_id: u64, count: u64) -> Result<()> {
+        let class = current_ioprio_class();
+        let capacity = (self.limit.unwrap_or_else(cache_limit) / self.block_size()) as u64;
+
+        // Don't let a single hint flush most of the cache.
+        let end = block_id
+            .saturating_add(count.min(capacity / 2))
+            .min(self.num_blocks());
+        let mut block = block_id;
+
+        while block < end {
+            let (start, run_end) = {
+                let state = self.cache.state.lock_save_irq();
+                let start = (block..end)
+                    .find(|b| !state.blocks.contains_key(b))
+                    .unwrap_or(end);
+                let run_end = (start..end)
+                    .find(|b| state.blocks.contains_key(b))
+                    .unwrap_or(end);
+
+                (start, run_end)
+            };
+
+            if start == end {
+                break;
+            }
+
+            let mut buf = vec![0; (run_end - start) as usize * self.block_size()];
+            self.fill(start, &mut buf, class).await?;
+
+            block = run_end;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CachedBlkDev;
+    use crate::{sched, sync::SpinLock};
+    use alloc::{boxed::Box, string::ToString, sync::Arc, vec, vec::Vec};
+    use async_trait::async_trait;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use libkernel::{error::Result, fs::BlockDevice};
+    use moss_macros::ktest;
+
+    const BLOCK_SIZE: usize = 512;
+
+    /// An in-memory device that counts the read requests it receives.
+    #[derive(Clone)]
+    struct MemDev {
+        data: Arc<SpinLock<Vec<u8>>>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl MemDev {
+        fn new(blocks: usize) -> Self {
+            Self {
+                data: Arc::new(SpinLock::new(
+                    (0..blocks * BLOCK_SIZE)
+                        .map(|i| (i / BLOCK_SIZE) as u8)
+                        .collect(),
+                )),
+                reads: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+
+        fn reads(&self) -> usize {
+            self.reads.load(Ordering::Relaxed)
+        }
+    }
+
+    #[async_trait]
+    impl BlockDevice for MemDev {
+        async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            let off = block_id as usize * BLOCK_SIZE;
+            buf.copy_from_slice(&self.data.lock_save_irq()[off..off + buf.len()]);
+            Ok(())
+        }
+
+        async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+            let off = block_id as usize * BLOCK_SIZE;
+            self.data.lock_save_irq()[off..off + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+
+        fn num_blocks(&self) -> u64 {
+            (self.data.lock_save_irq().len() / BLOCK_SIZE) as u64
+        }
+
+        async fn sync(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Wraps `mem` in a cache of `blocks` blocks, so that what other devices
+    /// have cached doesn't matter.
+    fn cached(mem: &MemDev, blocks: usize) -> CachedBlkDev {
+        let mut dev = CachedBlkDev::new(Box::new(mem.clone()), "test".to_string());
+        dev.limit = Some(blocks * BLOCK_SIZE);
+
+        dev
+    }
+
+    #[ktest]
+    async fn blk_cache_serves_repeated_reads() {
+        let mem = MemDev::new(16);
+        let dev = cached(&mem, 64);
+        let mut buf = vec![0; 4 * BLOCK_SIZE];
+
+        dev.read(2, &mut buf).await.unwrap();
+        assert_eq!(buf[3 * BLOCK_SIZE], 5);
+        assert_eq!(mem.reads(), 1);
+
+        dev.read(2, &mut buf).await.unwrap();
+        assert_eq!(mem.reads(), 1);
+
+        // Only the uncached tail goes to the device.
+        dev.read(4, &mut buf).await.unwrap();
+        assert_eq!(buf[3 * BLOCK_SIZE], 7);
+        assert_eq!(mem.reads(), 2);
+    }
+
+    #[ktest]
+    async fn blk_cache_writes_back_on_sync() {
+        let mem = MemDev::new(16);
+        let dev = cached(&mem, 64);
+        let mut buf = vec![0; BLOCK_SIZE];
+
+        dev.read(3, &mut buf).await.unwrap();
+        dev.write(3, &[0xaa; BLOCK_SIZE]).await.unwrap();
+
+        assert_eq!(mem.data.lock_save_irq()[3 * BLOCK_SIZE], 3);
+
+        dev.read(3, &mut buf).await.unwrap();
+        assert_eq!(buf, [0xaa; BLOCK_SIZE]);
+        assert_eq!(mem.reads(), 1);
+
+        dev.sync().await.unwrap();
+        assert_eq!(mem.data.lock_save_irq()[3 * BLOCK_SIZE], 0xaa);
+    }
+
+    #[ktest]
+    async fn blk_cache_writes_back_before_eviction() {
+        let mem = MemDev::new(16);
+        let dev = cached(&mem, 2);
+        let mut buf = vec![0; BLOCK_SIZE];
+
+        dev.write(0, &[0xaa; 2 * BLOCK_SIZE]).await.unwrap();
+        assert_eq!(mem.data.lock_save_irq()[0], 0);
+
+        // Makes room by writing back and evicting block 0.
+        dev.read(5, &mut buf).await.unwrap();
+        assert_eq!(mem.data.lock_save_irq()[0], 0xaa);
+        assert_eq!(mem.data.lock_save_irq()[BLOCK_SIZE], 1);
+
+        dev.read(0, &mut buf).await.unwrap();
+        assert_eq!(buf, [0xaa; BLOCK_SIZE]);
+        assert_eq!(mem.reads(), 2);
+    }
+
+    #[ktest]
+    async fn blk_cache_evicts_idle_class_first() {
+        // IOPRIO_CLASS_IDLE, level 0.
+        const IOPRIO_IDLE: u16 = 3 << 13;
+
+        let mem = MemDev::new(16);
+        let dev = cached(&mem, 2);
+        let mut buf = vec![0; BLOCK_SIZE];
+
+        dev.read(0, &mut buf).await.unwrap();
+
+        let task = sched::current_work();
+        let ioprio = task.ioprio.swap(IOPRIO_IDLE, Ordering::Relaxed);
+        dev.read(1, &mut buf).await.unwrap();
+        task.ioprio.store(ioprio, Ordering::Relaxed);
+
+        // Block 1 makes room, even though block 0 was used less recently.
+        dev.read(2, &mut buf).await.unwrap();
+        assert_eq!(mem.reads(), 3);
+
+        dev.read(0, &mut buf).await.unwrap();
+        assert_eq!(mem.reads(), 3);
+
+        dev.read(1, &mut buf).await.unwrap();
+        assert_eq!(mem.reads(), 4);
+    }
+
+    #[ktest]
+    async fn blk_cache_readahead_fetches_missing_runs() {
+        let mem = MemDev::new(64);
+        let dev = cached(&mem, 64);
+        let mut buf = vec![0; BLOCK_SIZE];
+
+        dev.read(10, &mut buf).await.unwrap();
+
+        // Blocks 0..10 and 11..32 are fetched with one request each.
+        dev.readahead(0, 32).await.unwrap();
+        assert_eq!(mem.reads(), 3);
+
+        let mut buf = vec![0; 32 * BLOCK_SIZE];
+        dev.read(0, &mut buf).await.unwrap();
+        assert_eq!(buf[31 * BLOCK_SIZE], 31);
+        assert_eq!(mem.reads(), 3);
+
+        // Hints past the end of the device are clipped.
+        dev.readahead(60, 100).await.unwrap();
+        assert_eq!(mem.reads(), 4);
+    }
+}
@@ -0,0 +1,237 @@
+//! Userspace access to a raw block device, e.g. `/dev/ram0`.
+
+use crate::{
+    fs::{fops::FileOps, open_file::FileCtx},
+    memory::{
+        PageOffsetTranslator,
+        uaccess::{copy_from_user_slice, copy_to_user, copy_to_user_slice},
+    },
+    sched::current_work,
+};
+use alloc::{boxed::Box, sync::Arc, vec};
+use async_trait::async_trait;
+use core::{cmp::min, future::Future, pin::Pin, slice};
+use libkernel::{
+    error::{FsError, KernelError, Result},
+    fs::{BlockDevice, SeekFrom},
+    memory::{
+        PAGE_SIZE,
+        address::{TUA, UA},
+        proc_vm::vmarea::AccessKind,
+    },
+};
+
+/// Flush buffers, `_IO(0x12, 97)`.
+const BLKFLSBUF: usize = 0x1261;
+/// Device size in 512-byte sectors, `_IO(0x12, 96)`.
+const BLKGETSIZE: usize = 0x1260;
+/// Logical block size, `_IO(0x12, 104)`.
+const BLKSSZGET: usize = 0x1268;
+/// Device size in bytes, `_IOR(0x12, 114, size_t)`.
+const BLKGETSIZE64: usize = 0x8008_1272;
+
+pub struct BlkDevFile {
+    dev: Arc<dyn BlockDevice>,
+    /// Opened with `O_DIRECT`: transfers go straight between the device and
+    /// the user's pages, with no bounce buffer.
+    direct: bool,
+}
+
+impl BlkDevFile {
+    pub fn new(dev: Arc<dyn BlockDevice>, direct: bool) -> Self {
+        Self { dev, direct }
+    }
+
+    fn size(&self) -> u64 {
+        self.dev.num_blocks() * self.dev.block_size() as u64
+    }
+
+    /// Size of the bounce buffer used to move data between userspace and the
+    /// device; always a whole number of blocks.
+    fn bounce_size(&self) -> usize {
+        let bs = self.dev.block_size();
+        PAGE_SIZE.max(bs) / bs * bs
+    }
+
+    /// Moves `count` bytes between the device at `offset` and the user's
+    /// buffer, a page at a time, pinning each page for the length of the
+    /// transfer. The buffer, count and offset must all be whole blocks, so
+    /// that no block straddles two pages.
+    async fn direct_io(&self, buf: UA, count: usize, offset: u64, write: bool) -> Result<usize> {
+        let bs = self.dev.block_size();
+
+        if !buf.value().is_multiple_of(bs)
+            || !count.is_multiple_of(bs)
+            || !offset.is_multiple_of(bs as u64)
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        // The device is a whole number of blocks, so this stays aligned.
+        let count = min(count as u64, self.size() - offset) as usize;
+        let access = if write {
+            AccessKind::Read
+        } else {
+            AccessKind::Write
+        };
+        let task = current_work();
+        let mut done = 0;
+
+        while done < count {
+            let va = buf.add_bytes(done);
+            let n = min(count - done, PAGE_SIZE - va.page_offset());
+            let block = (offset + done as u64) / bs as u64;
+
+            // SAFETY: The page is only read from for a write to the device,
+            // and only written to for a read.
+            let page = unsafe { task.get_page(va, access).await? };
+            let ptr = page
+                .region()
+                .start_address()
+                .to_va::<PageOffsetTranslator>()
+                .cast::<u8>()
+                .add_bytes(va.page_offset())
+                .as_ptr_mut();
+
+            // SAFETY: The page is pinned until `page` is dropped, and `n`
+            // doesn't run past its end.
+            let chunk = unsafe { slice::from_raw_parts_mut(ptr, n) };
+
+            if write {
+                self.dev.write(block, chunk).await?;
+            } else {
+                self.dev.read(block, chunk).await?;
+            }
+
+            done += n;
+        }
+
+        Ok(done)
+    }
+}
+
+#[async_trait]
+impl FileOps for BlkDevFile {
+    async fn readat(&mut self, mut buf: UA, count: usize, mut offset: u64) -> Result<usize> {
+        let size = self.size();
+
+        if offset >= size {
+            return Ok(0);
+        }
+
+        if self.direct {
+            return self.direct_io(buf, count, offset, false).await;
+        }
+
+        let bs = self.dev.block_size();
+        let mut count = min(count as u64, size - offset) as usize;
+        let mut kbuf = vec![0u8; self.bounce_size()];
+        let mut total = 0;
+
+        while count > 0 {
+            let block = offset / bs as u64;
+            let boff = (offset % bs as u64) as usize;
+            let len = min(kbuf.len(), (boff + count).div_ceil(bs) * bs);
+            let n = min(len - boff, count);
+
+            self.dev.read(block, &mut kbuf[..len]).await?;
+            copy_to_user_slice(&kbuf[boff..boff + n], buf).await?;
+
+            buf = buf.add_bytes(n);
+            offset += n as u64;
+            count -= n;
+            total += n;
+        }
+
+        Ok(total)
+    }
+
+    async fn writeat(&mut self, mut buf: UA, count: usize, mut offset: u64) -> Result<usize> {
+        let size = self.size();
+
+        if count == 0 {
+            return Ok(0);
+        }
+
+        if offset >= size {
+            return Err(FsError::NoSpace.into());
+        }
+
+        if self.direct {
+            return self.direct_io(buf, count, offset, true).await;
+        }
+
+        let bs = self.dev.block_size();
+        let mut count = min(count as u64, size - offset) as usize;
+        let mut kbuf = vec![0u8; self.bounce_size()];
+        let mut total = 0;
+
+        while count > 0 {
+            let block = offset / bs as u64;
+            let boff = (offset % bs as u64) as usize;
+            let len = min(kbuf.len(), (boff + count).div_ceil(bs) * bs);
+            let n = min(len - boff, count);
+
+            // Partial blocks at either end need their existing contents
+            // preserved.
+            if boff != 0 || n != len {
+                self.dev.read(block, &mut kbuf[..len]).await?;
+            }
+
+            copy_from_user_slice(buf, &mut kbuf[boff..boff + n]).await?;
+            self.dev.write(block, &kbuf[..len]).await?;
+
+            buf = buf.add_bytes(n);
+            offset += n as u64;
+            count -= n;
+            total += n;
+        }
+
+        Ok(total)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    async fn seek(&mut self, ctx: &mut FileCtx, pos: SeekFrom) -> Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => self.size().checked_add_signed(x),
+            SeekFrom::Current(x) => ctx.pos.checked_add_signed(x),
+            // A device is data from start to end.
+            SeekFrom::Data(x) | SeekFrom::Hole(x) if x >= self.size() => {
+                return Err(FsError::NoDeviceOrAddress.into());
+            }
+            SeekFrom::Data(x) => Some(x),
+            SeekFrom::Hole(_) => Some(self.size()),
+        };
+
+        // Like Linux, a device can't be seeked past its end.
+        ctx.pos = new_pos
+            .filter(|&pos| pos <= self.size())
+            .ok_or(KernelError::InvalidValue)?;
+
+        Ok(ctx.pos)
+    }
+
+    async fn ioctl(&mut self, _ctx: &mut FileCtx, request: usize, argp: usize) -> Result<usize> {
+        match request {
+            BLKGETSIZE64 => copy_to_user(TUA::from_value(argp), self.size()).await?,
+            BLKGETSIZE => copy_to_user(TUA::from_value(argp), self.size() / 512).await?,
+            BLKSSZGET => copy_to_user(TUA::from_value(argp), self.dev.block_size() as u32).await?,
+            BLKFLSBUF => self.dev.sync().await?,
+            _ => return Err(KernelError::NotATty),
+        }
+
+        Ok(0)
+    }
+
+    async fn flush(&self, _ctx: &FileCtx) -> Result<()> {
+        self.dev.sync().await
+    }
+}
@@ -0,0 +1,600 @@
+//! Stacked block devices built from a table of linear and striped targets.
+//!
+//! A mapped device is described by a table of targets, each covering a
+//! contiguous range of the device's blocks and redirecting it to one or more
+//! underlying devices. Any registered block device, including another mapped
+//! device, may be used as an underlying device. Devices are created and
+//! removed through ioctls on `/dev/dm-control`.
+
+use super::{find_block_device, register_block_device, unregister_block_device};
+use crate::{
+    drivers::{
+        CharDriver, DriverManager, OpenableDevice, ReservedMajors, fs::dev::devfs,
+        init::PlatformBus,
+    },
+    fs::{fops::FileOps, open_file::FileCtx, open_file::OpenFile},
+    kernel_driver,
+    memory::uaccess::{UserCopyable, copy_from_user, copy_obj_array_from_user},
+    sched::current_work,
+    sync::SpinLock,
+};
+use alloc::{
+    boxed::Box,
+    collections::btree_set::BTreeSet,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::cmp::min;
+use libkernel::{
+    driver::CharDevDescriptor,
+    error::{FsError, IoError, KernelError, Result},
+    fs::{BlockDevice, OpenFlags, attr::FilePermissions},
+    memory::address::{TUA, UA},
+    proc::caps::CapabilitiesFlags,
+};
+
+/// Where a target sends the blocks it covers.
+pub enum Target {
+    /// Maps blocks one-to-one onto `dev`, starting at block `offset`.
+    Linear {
+        dev: Arc<dyn BlockDevice>,
+        offset: u64,
+    },
+    /// Spreads blocks over `devs` in round-robin chunks of `chunk` blocks.
+    /// Each device is paired with the block at which its data starts.
+    Striped {
+        devs: Vec<(Arc<dyn BlockDevice>, u64)>,
+        chunk: u64,
+    },
+}
+
+/// A target covering `len` blocks of the mapped device from block `start`.
+pub struct TableEntry {
+    pub start: u64,
+    pub len: u64,
+    pub target: Target,
+}
+
+impl TableEntry {
+    fn end(&self) -> u64 {
+        self.start + self.len
+    }
+
+    /// Returns the underlying device and block that `block` maps to, along
+    /// with the number of blocks from there that are contiguous on that
+    /// device.
+    fn map(&self, block: u64) -> (&dyn BlockDevice, u64, u64) {
+        let rel = block - self.start;
+
+        match &self.target {
+            Target::Linear { dev, offset } => (dev.as_ref(), offset + rel, self.end() - block),
+            Target::Striped { devs, chunk } => {
+                let chunk_idx = rel / chunk;
+                let within = rel % chunk;
+                let (dev, offset) = &devs[(chunk_idx % devs.len() as u64) as usize];
+                let dev_block = offset + (chunk_idx / devs.len() as u64) * chunk + within;
+
+                (dev.as_ref(), dev_block, chunk - within)
+            }
+        }
+    }
+
+    /// The underlying devices and the highest block used on each.
+    fn extents(&self) -> Vec<(&dyn BlockDevice, u64)> {
+        match &self.target {
+            Target::Linear { dev, offset } => alloc::vec![(dev.as_ref(), offset + self.len)],
+            Target::Striped { devs, chunk } => {
+                let per_dev = self.len / devs.len() as u64;
+
+                debug_assert!(per_dev.is_multiple_of(*chunk));
+
+                devs.iter()
+                    .map(|(dev, offset)| (dev.as_ref(), offset + per_dev))
+                    .collect()
+            }
+        }
+    }
+}
+
+pub struct MappedBlkDev {
+    table: Vec<TableEntry>,
+    block_size: usize,
+}
+
+impl MappedBlkDev {
+    /// Builds a device from `table`.
+    ///
+    /// The targets must be given in order, cover the device contiguously from
+    /// block zero, and fit on their underlying devices, which must all share
+    /// the same block size. A striped target's length must be a whole number
+    /// of stripes.
+    pub fn new(table: Vec<TableEntry>) -> Result<Self> {
+        let mut block_size = None;
+        let mut next = 0;
+
+        for entry in &table {
+            if entry.start != next || entry.len == 0 {
+                return Err(KernelError::InvalidValue);
+            }
+
+            if let Target::Striped { devs, chunk } = &entry.target
+                && (devs.is_empty()
+                    || *chunk == 0
+                    || !entry.len.is_multiple_of(chunk * devs.len() as u64))
+            {
+                return Err(KernelError::InvalidValue);
+            }
+
+            for (dev, end) in entry.extents() {
+                if *block_size.get_or_insert(dev.block_size()) != dev.block_size() {
+                    return Err(KernelError::InvalidValue);
+                }
+
+                if end > dev.num_blocks() {
+                    return Err(KernelError::InvalidValue);
+                }
+            }
+
+            next = entry.end();
+        }
+
+        Ok(Self {
+            block_size: block_size.ok_or(KernelError::InvalidValue)?,
+            table,
+        })
+    }
+
+    /// Splits the `buf.len()` bytes starting at `block_id` into runs which
+    /// are contiguous on a single underlying device.
+    fn runs(
+        &self,
+        mut block_id: u64,
+        len: usize,
+    ) -> Result<impl Iterator<Item = (&dyn BlockDevice, u64, core::ops::Range<usize>)>> {
+        let nblocks = (len / self.block_size) as u64;
+
+        if !len.is_multiple_of(self.block_size)
+            || block_id
+                .checked_add(nblocks)
+                .is_none_or(|end| end > self.num_blocks())
+        {
+            return Err(IoError::OutOfBounds.into());
+        }
+
+        let mut remaining = nblocks;
+        let mut pos = 0;
+
+        Ok(core::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+
+            let entry = self.table.iter().find(|e| block_id < e.end())?;
+            let (dev, dev_block, run) = entry.map(block_id);
+            let run = min(run, remaining);
+            let bytes = run as usize * self.block_size;
+            let range = pos..pos + bytes;
+
+            block_id += run;
+            remaining -= run;
+            pos += bytes;
+
+            Some((dev, dev_block, range))
+        }))
+    }
+}
+
+#[async_trait]
+impl BlockDevice for MappedBlkDev {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        for (dev, dev_block, range) in self.runs(block_id, buf.len())? {
+            dev.read(dev_block, &mut buf[range]).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        for (dev, dev_block, range) in self.runs(block_id, buf.len())? {
+            dev.write(dev_block, &buf[range]).await?;
+        }
+
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.table.last().map_or(0, |e| e.end())
+    }
+
+    async fn sync(&self) -> Result<()> {
+        for entry in &self.table {
+            for (dev, _) in entry.extents() {
+                dev.sync().await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Names of the devices created through `/dev/dm-control`.
+static MAPPED_DEVICES: SpinLock<BTreeSet<String>> = SpinLock::new(BTreeSet::new());
+
+const DM_NAME_LEN: usize = 32;
+const DM_MAX_TARGETS: usize = 256;
+const DM_MAX_STRIPES: usize = 8;
+
+const DM_TARGET_LINEAR: u32 = 0;
+const DM_TARGET_STRIPED: u32 = 1;
+
+/// Create a mapped device, `_IOW(0xfd, 0, struct dm_create)`.
+const DM_DEV_CREATE: usize = 0x4030_fd00;
+/// Remove a mapped device, `_IOW(0xfd, 1, char[DM_NAME_LEN])`.
+const DM_DEV_REMOVE: usize = 0x4020_fd01;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DmDevSpec {
+    name: [u8; DM_NAME_LEN],
+    offset: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DmTargetSpec {
+    start: u64,
+    len: u64,
+    kind: u32,
+    num_devs: u32,
+    chunk: u64,
+    devs: [DmDevSpec; DM_MAX_STRIPES],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DmCreate {
+    name: [u8; DM_NAME_LEN],
+    num_targets: u32,
+    _pad: u32,
+    targets: TUA<DmTargetSpec>,
+}
+
+unsafe impl UserCopyable for DmDevSpec {}
+unsafe impl UserCopyable for DmTargetSpec {}
+unsafe impl UserCopyable for DmCreate {}
+
+fn parse_name(name: &[u8; DM_NAME_LEN]) -> Result<&str> {
+    let len = name
+        .iter()
+        .position(|&c| c == 0)
+        .ok_or(KernelError::InvalidValue)?;
+    let name = core::str::from_utf8(&name[..len]).map_err(|_| KernelError::InvalidValue)?;
+
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(KernelError::InvalidValue);
+    }
+
+    Ok(name)
+}
+
+fn lookup_dev(spec: &DmDevSpec) -> Result<(Arc<dyn BlockDevice>, u64)> {
+    let dev = find_block_device(parse_name(&spec.name)?).ok_or(FsError::NoDevice)?;
+
+    Ok((dev, spec.offset))
+}
+
+fn build_entry(spec: &DmTargetSpec) -> Result<TableEntry> {
+    let target = match spec.kind {
+        DM_TARGET_LINEAR if spec.num_devs == 1 => {
+            let (dev, offset) = lookup_dev(&spec.devs[0])?;
+            Target::Linear { dev, offset }
+        }
+        DM_TARGET_STRIPED if (1..=DM_MAX_STRIPES).contains(&(spec.num_devs as usize)) => {
+            Target::Striped {
+                devs: spec.devs[..spec.num_devs as usize]
+                    .iter()
+                    .map(lookup_dev)
+                    .collect::<Result<_>>()?,
+                chunk: spec.chunk,
+            }
+        }
+        _ => return Err(KernelError::InvalidValue),
+    };
+
+    Ok(TableEntry {
+        start: spec.start,
+        len: spec.len,
+        target,
+    })
+}
+
+async fn dm_dev_create(argp: TUA<DmCreate>) -> Result<()> {
+    let req = copy_from_user(argp).await?;
+    let name = parse_name(&req.name)?;
+    let num_targets = req.num_targets as usize;
+
+    if num_targets == 0 || num_targets > DM_MAX_TARGETS {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let table = copy_obj_array_from_user(req.targets, num_targets)
+        .await?
+        .iter()
+        .map(build_entry)
+        .collect::<Result<_>>()?;
+
+    let dev = MappedBlkDev::new(table)?;
+
+    let mut mapped = MAPPED_DEVICES.lock_save_irq();
+    register_block_device(name, Arc::new(dev))?;
+    mapped.insert(name.to_string());
+
+    Ok(())
+}
+
+async fn dm_dev_remove(argp: TUA<[u8; DM_NAME_LEN]>) -> Result<()> {
+    let name = copy_from_user(argp).await?;
+    let name = parse_name(&name)?;
+
+    let mut mapped = MAPPED_DEVICES.lock_save_irq();
+
+    // Only devices created here may be removed here.
+    if !mapped.contains(name) {
+        return Err(FsError::NoDevice.into());
+    }
+
+    unregister_block_device(name)?;
+    mapped.remove(name);
+
+    Ok(())
+}
+
+struct DmControlFileOps;
+
+#[async_trait]
+impl FileOps for DmControlFileOps {
+    async fn readat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::InvalidValue)
+    }
+
+    async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::InvalidValue)
+    }
+
+    async fn ioctl(&mut self, _ctx: &mut FileCtx, request: usize, argp: usize) -> Result<usize> {
+        current_work()
+            .creds
+            .lock_save_irq()
+            .caps()
+            .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)?;
+
+        match request {
+            DM_DEV_CREATE => dm_dev_create(TUA::from_value(argp)).await?,
+            DM_DEV_REMOVE => dm_dev_remove(TUA::from_value(argp)).await?,
+            _ => return Err(KernelError::NotATty),
+        }
+
+        Ok(0)
+    }
+}
+
+struct DmControlDev;
+
+impl OpenableDevice for DmControlDev {
+    fn open(&self, flags: OpenFlags) -> Result<Arc<OpenFile>> {
+        Ok(Arc::new(OpenFile::new(Box::new(DmControlFileOps), flags)))
+    }
+}
+
+struct DmControlCharDev {
+    dev: Arc<dyn OpenableDevice>,
+}
+
+/// The misc minor Linux gives the device-mapper control node.
+const DM_CONTROL_MINOR: u64 = 236;
+
+impl CharDriver for DmControlCharDev {
+    fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>> {
+        if minor == DM_CONTROL_MINOR {
+            Some(self.dev.clone())
+        } else {
+            None
+        }
+    }
+}
+
+/// Driver initialisation entry point invoked during kernel boot.
+pub fn dm_control_init(_bus: &mut PlatformBus, dm: &mut DriverManager) -> Result<()> {
+    devfs().mknod(
+        "dm-control".to_string(),
+        CharDevDescriptor {
+            major: ReservedMajors::Misc as _,
+            minor: DM_CONTROL_MINOR,
+        },
+        FilePermissions::from_bits_retain(0o600),
+    )?;
+
+    dm.register_char_region(
+        ReservedMajors::Misc as _,
+        DM_CONTROL_MINOR..DM_CONTROL_MINOR + 1,
+        Arc::new(DmControlCharDev {
+            dev: Arc::new(DmControlDev),
+        }),
+    )
+}
+
+kernel_driver!(dm_control_init);
+
+#[cfg(test)]
+mod tests {
+    use super::{MappedBlkDev, TableEntry, Target};
+    use crate::sync::SpinLock;
+    use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+    use async_trait::async_trait;
+    use libkernel::{
+        error::{IoError, KernelError, Result},
+        fs::BlockDevice,
+    };
+    use moss_macros::ktest;
+
+    const BLOCK_SIZE: usize = 16;
+
+    struct MemDev(SpinLock<Vec<u8>>);
+
+    impl MemDev {
+        /// A device whose every byte holds `tag` plus its block number.
+        fn new(tag: u8, blocks: usize) -> Arc<Self> {
+            let data = (0..blocks)
+                .flat_map(|b| [tag.wrapping_add(b as u8); BLOCK_SIZE])
+                .collect();
+
+            Arc::new(Self(SpinLock::new(data)))
+        }
+    }
+
+    #[async_trait]
+    impl BlockDevice for MemDev {
+        async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+            let off = block_id as usize * BLOCK_SIZE;
+            buf.copy_from_slice(&self.0.lock_save_irq()[off..off + buf.len()]);
+            Ok(())
+        }
+
+        async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+            let off = block_id as usize * BLOCK_SIZE;
+            self.0.lock_save_irq()[off..off + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+
+        fn num_blocks(&self) -> u64 {
+            (self.0.lock_save_irq().len() / BLOCK_SIZE) as u64
+        }
+
+        async fn sync(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Reads every block of `dev` and returns the first byte of each.
+    async fn block_tags(dev: &dyn BlockDevice) -> Vec<u8> {
+        let mut buf = vec![0; dev.num_blocks() as usize * BLOCK_SIZE];
+        dev.read(0, &mut buf).await.unwrap();
+        buf.chunks(BLOCK_SIZE).map(|b| b[0]).collect()
+    }
+
+    #[ktest]
+    async fn mapper_linear_concatenates() {
+        let a = MemDev::new(0x10, 4);
+        let b = MemDev::new(0x20, 4);
+
+        let dev = MappedBlkDev::new(vec![
+            TableEntry {
+                start: 0,
+                len: 2,
+                target: Target::Linear {
+                    dev: a.clone(),
+                    offset: 1,
+                },
+            },
+            TableEntry {
+                start: 2,
+                len: 3,
+                target: Target::Linear {
+                    dev: b.clone(),
+                    offset: 0,
+                },
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(dev.num_blocks(), 5);
+        assert_eq!(block_tags(&dev).await, [0x11, 0x12, 0x20, 0x21, 0x22]);
+
+        // A write spanning both targets lands on both devices.
+        dev.write(1, &[0xff; 2 * BLOCK_SIZE]).await.unwrap();
+        assert_eq!(block_tags(a.as_ref()).await, [0x10, 0x11, 0xff, 0x13]);
+        assert_eq!(block_tags(b.as_ref()).await, [0xff, 0x21, 0x22, 0x23]);
+
+        let mut buf = vec![0; BLOCK_SIZE];
+        assert_eq!(
+            dev.read(5, &mut buf).await,
+            Err(KernelError::Io(IoError::OutOfBounds))
+        );
+
+        // Rather than wrap round to the start of the device.
+        assert_eq!(
+            dev.read(u64::MAX, &mut buf).await,
+            Err(KernelError::Io(IoError::OutOfBounds))
+        );
+    }
+
+    #[ktest]
+    async fn mapper_striped_interleaves_chunks() {
+        let a = MemDev::new(0x10, 8);
+        let b = MemDev::new(0x20, 8);
+
+        let dev = MappedBlkDev::new(vec![TableEntry {
+            start: 0,
+            len: 8,
+            target: Target::Striped {
+                devs: vec![(a.clone(), 0), (b.clone(), 2)],
+                chunk: 2,
+            },
+        }])
+        .unwrap();
+
+        assert_eq!(
+            block_tags(&dev).await,
+            [0x10, 0x11, 0x22, 0x23, 0x12, 0x13, 0x24, 0x25]
+        );
+
+        // Reads starting mid-chunk are split correctly too.
+        let mut buf = vec![0; 3 * BLOCK_SIZE];
+        dev.read(3, &mut buf).await.unwrap();
+        assert_eq!(
+            [buf[0], buf[BLOCK_SIZE], buf[2 * BLOCK_SIZE]],
+            [0x23, 0x12, 0x13]
+        );
+    }
+
+    #[ktest]
+    fn mapper_rejects_bad_tables() {
+        let a = MemDev::new(0, 4);
+        let linear = |start, len, offset| TableEntry {
+            start,
+            len,
+            target: Target::Linear {
+                dev: a.clone(),
+                offset,
+            },
+        };
+
+        // Gap between targets.
+        assert!(MappedBlkDev::new(vec![linear(0, 1, 0), linear(2, 1, 0)]).is_err());
+        // Runs off the end of the underlying device.
+        assert!(MappedBlkDev::new(vec![linear(0, 4, 1)]).is_err());
+        // Not a whole number of stripes.
+        assert!(
+            MappedBlkDev::new(vec![TableEntry {
+                start: 0,
+                len: 3,
+                target: Target::Striped {
+                    devs: vec![(a.clone(), 0), (a.clone(), 2)],
+                    chunk: 1,
+                },
+            }])
+            .is_err()
+        );
+        assert!(MappedBlkDev::new(vec![]).is_err());
+    }
+}
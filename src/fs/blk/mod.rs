@@ -0,0 +1,125 @@
+//! Block device registry and stacked block devices.
+//!
+//! Block devices registered here are given a node in devfs so that
+//! userspace can read and write them directly, and can be looked up by name
+//! as the backing devices of stacked devices.
+
+use crate::{drivers::fs::dev::devfs, fs::open_file::OpenFile, sync::SpinLock};
+use alloc::{
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+};
+use file::BlkDevFile;
+use libkernel::{
+    driver::CharDevDescriptor,
+    error::{FsError, KernelError, Result},
+    fs::{BlockDevice, OpenFlags, attr::FilePermissions},
+};
+
+pub mod cache;
+pub mod file;
+pub mod mapper;
+pub mod verity;
+
+/// Major number shared by all registered block devices; each device is
+/// identified by its minor number.
+pub const BLOCK_MAJOR: u64 = 259;
+
+struct BlkDevEntry {
+    name: String,
+    dev: Arc<dyn BlockDevice>,
+}
+
+struct BlkDevRegistry {
+    devices: BTreeMap<u64, BlkDevEntry>,
+    next_minor: u64,
+}
+
+static BLOCK_DEVICES: SpinLock<BlkDevRegistry> = SpinLock::new(BlkDevRegistry {
+    devices: BTreeMap::new(),
+    next_minor: 0,
+});
+
+/// Registers `dev` under `name`, creating `/dev/<name>`.
+pub fn register_block_device(name: &str, dev: Arc<dyn BlockDevice>) -> Result<()> {
+    let mut reg = BLOCK_DEVICES.lock_save_irq();
+
+    if reg.devices.values().any(|e| e.name == name) {
+        return Err(FsError::AlreadyExists.into());
+    }
+
+    let minor = reg.next_minor;
+
+    devfs().mknod_blk(
+        name.to_string(),
+        CharDevDescriptor {
+            major: BLOCK_MAJOR,
+            minor,
+        },
+        FilePermissions::from_bits_retain(0o660),
+    )?;
+
+    reg.next_minor += 1;
+    reg.devices.insert(
+        minor,
+        BlkDevEntry {
+            name: name.to_string(),
+            dev,
+        },
+    );
+
+    Ok(())
+}
+
+/// Removes the device registered under `name`.
+///
+/// Fails with `EBUSY` if anything other than the registry still holds a
+/// reference to the device, such as an open file or a stacked device.
+pub fn unregister_block_device(name: &str) -> Result<()> {
+    let mut reg = BLOCK_DEVICES.lock_save_irq();
+
+    let (&minor, entry) = reg
+        .devices
+        .iter()
+        .find(|(_, e)| e.name == name)
+        .ok_or(FsError::NotFound)?;
+
+    if Arc::strong_count(&entry.dev) > 1 {
+        return Err(KernelError::InUse);
+    }
+
+    devfs().remove_node(name)?;
+    reg.devices.remove(&minor);
+
+    Ok(())
+}
+
+/// Looks up a registered block device by name.
+pub fn find_block_device(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES
+        .lock_save_irq()
+        .devices
+        .values()
+        .find(|e| e.name == name)
+        .map(|e| e.dev.clone())
+}
+
+/// Opens the block device identified by `desc`.
+pub fn open_block_device(desc: CharDevDescriptor, flags: OpenFlags) -> Result<OpenFile> {
+    if desc.major != BLOCK_MAJOR {
+        return Err(FsError::NoDevice.into());
+    }
+
+    let dev = BLOCK_DEVICES
+        .lock_save_irq()
+        .devices
+        .get(&desc.minor)
+        .map(|e| e.dev.clone())
+        .ok_or(FsError::NoDevice)?;
+
+    let direct = flags.contains(OpenFlags::O_DIRECT);
+
+    Ok(OpenFile::new(Box::new(BlkDevFile::new(dev, direct)), flags))
+}
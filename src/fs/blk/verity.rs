@@ -0,0 +1,400 @@
+//! A read-only block device which verifies every block it returns against a
+//! Merkle tree of SHA-256 hashes, in the manner of Linux's dm-verity.
+//!
+//! The hash tree uses the dm-verity (format 1) layout, so images can be
+//! prepared with `veritysetup format`:
+//!
+//! - Data and hash blocks are the size of the underlying device's blocks.
+//! - Each block is hashed as `SHA-256(salt || block)`.
+//! - Hash blocks hold `block_size / 32` digests, zero-padded at the end.
+//! - Levels are stored from the top of the tree (closest to the root)
+//!   downwards, starting at `hash_start`.
+//! - The root hash is the hash of the single top-level hash block (or of the
+//!   only data block, for a one-block device).
+
+use crate::{
+    crypto::{
+        Hash, ct_eq,
+        sha256::{DIGEST_LEN, Sha256},
+    },
+    sync::SpinLock,
+};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, vec, vec::Vec};
+use async_trait::async_trait;
+use libkernel::{
+    error::{FsError, IoError, KernelError, Result},
+    fs::BlockDevice,
+};
+use log::error;
+
+/// Parameters describing a verity-protected device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerityParams {
+    /// Number of data blocks covered by the tree.
+    pub data_blocks: u64,
+    /// Block at which the hash tree starts.
+    pub hash_start: u64,
+    /// Expected root hash.
+    pub root_hash: [u8; DIGEST_LEN],
+    /// Salt prepended to every hashed block.
+    pub salt: Vec<u8>,
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or(KernelError::InvalidValue)
+        })
+        .collect()
+}
+
+impl VerityParams {
+    /// Parses `<data_blocks>,<hash_start>,<root_hash>[,<salt>]`, with the
+    /// hash and salt in hex. A salt of `-` means no salt.
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut parts = s.split(',');
+        let mut next = || parts.next().ok_or(KernelError::InvalidValue);
+
+        let data_blocks = next()?.parse().map_err(|_| KernelError::InvalidValue)?;
+        let hash_start = next()?.parse().map_err(|_| KernelError::InvalidValue)?;
+        let root_hash = parse_hex(next()?)?
+            .try_into()
+            .map_err(|_| KernelError::InvalidValue)?;
+        let salt = match next() {
+            Ok("-") | Err(_) => Vec::new(),
+            Ok(salt) => parse_hex(salt)?,
+        };
+
+        Ok(Self {
+            data_blocks,
+            hash_start,
+            root_hash,
+            salt,
+        })
+    }
+}
+
+pub struct VerityBlkDev {
+    dev: Box<dyn BlockDevice>,
+    params: VerityParams,
+    /// log2 of the number of digests held by a hash block.
+    hash_per_block_bits: u32,
+    /// The first block of each level of the tree, indexed from the bottom.
+    level_start: Vec<u64>,
+    /// Hash blocks which have already been verified.
+    verified: SpinLock<BTreeMap<u64, Box<[u8]>>>,
+}
+
+impl VerityBlkDev {
+    pub fn new(dev: Box<dyn BlockDevice>, params: VerityParams) -> Result<Self> {
+        let block_size = dev.block_size();
+
+        if !block_size.is_power_of_two() || block_size < DIGEST_LEN * 2 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let bits = (block_size / DIGEST_LEN).ilog2();
+
+        // The number of levels needed for a single top-level block to cover
+        // all the data blocks.
+        let mut levels = 0;
+        while bits * levels < u64::BITS
+            && (params.data_blocks.saturating_sub(1) >> (bits * levels)) != 0
+        {
+            levels += 1;
+        }
+
+        let mut level_start = vec![0; levels as usize];
+        let mut pos = params.hash_start;
+
+        for (i, start) in level_start.iter_mut().enumerate().rev() {
+            let shift = (i as u32 + 1) * bits;
+            *start = pos;
+            pos += params
+                .data_blocks
+                .div_ceil(1u64.checked_shl(shift).unwrap_or(u64::MAX));
+        }
+
+        // Both the data and the whole tree must fit on the device.
+        if params.data_blocks > dev.num_blocks() || pos > dev.num_blocks() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        Ok(Self {
+            dev,
+            params,
+            hash_per_block_bits: bits,
+            level_start,
+            verified: SpinLock::new(BTreeMap::new()),
+        })
+    }
+
+    fn hash_block(&self, block: &[u8]) -> [u8; DIGEST_LEN] {
+        let mut h = Sha256::new();
+        h.update(&self.params.salt);
+        h.update(block);
+        h.finalize()
+    }
+
+    /// Reads the hash block `block`, checking it against `expected` unless
+    /// it has been verified before.
+    ///
+    /// Every hash block has a single parent entry, so a cached block needs no
+    /// further checks; keeping the verified contents in memory also means
+    /// later changes to the underlying device can't affect them.
+    async fn read_hash_block(&self, block: u64, expected: &[u8]) -> Result<Box<[u8]>> {
+        if let Some(buf) = self.verified.lock_save_irq().get(&block) {
+            return Ok(buf.clone());
+        }
+
+        let mut buf = vec![0; self.dev.block_size()].into_boxed_slice();
+        self.dev.read(block, &mut buf).await?;
+
+        if !ct_eq(&self.hash_block(&buf), expected) {
+            error!("verity: hash block {block} failed verification");
+            return Err(IoError::VerificationFailed.into());
+        }
+
+        self.verified.lock_save_irq().insert(block, buf.clone());
+
+        Ok(buf)
+    }
+
+    /// Reads and verifies a single data block.
+    async fn read_block(&self, block: u64, buf: &mut [u8]) -> Result<()> {
+        let bits = self.hash_per_block_bits;
+        let mut expected = self.params.root_hash;
+
+        for (level, start) in self.level_start.iter().enumerate().rev() {
+            let hash_block = start + (block >> ((level as u32 + 1) * bits));
+            let idx = ((block >> (level as u32 * bits)) & ((1 << bits) - 1)) as usize;
+
+            let hashes = self.read_hash_block(hash_block, &expected).await?;
+            expected.copy_from_slice(&hashes[idx * DIGEST_LEN..(idx + 1) * DIGEST_LEN]);
+        }
+
+        self.dev.read(block, buf).await?;
+
+        if !ct_eq(&self.hash_block(buf), &expected) {
+            error!("verity: data block {block} failed verification");
+            return Err(IoError::VerificationFailed.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlockDevice for VerityBlkDev {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        let block_size = self.block_size();
+
+        debug_assert!(buf.len().is_multiple_of(block_size));
+
+        if block_id + (buf.len() / block_size) as u64 > self.params.data_blocks {
+            return Err(IoError::OutOfBounds.into());
+        }
+
+        for (i, chunk) in buf.chunks_mut(block_size).enumerate() {
+            self.read_block(block_id + i as u64, chunk).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&self, _block_id: u64, _buf: &[u8]) -> Result<()> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn block_size(&self) -> usize {
+        self.dev.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.params.data_blocks
+    }
+
+    async fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DIGEST_LEN, VerityBlkDev, VerityParams};
+    use crate::{
+        crypto::{Hash, sha256::Sha256},
+        sync::SpinLock,
+    };
+    use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+    use async_trait::async_trait;
+    use libkernel::{
+        error::{IoError, KernelError, Result},
+        fs::BlockDevice,
+    };
+    use moss_macros::ktest;
+
+    const BLOCK_SIZE: usize = 64;
+    const SALT: &[u8] = b"salt";
+
+    #[derive(Clone)]
+    struct MemDev(Arc<SpinLock<Vec<u8>>>);
+
+    #[async_trait]
+    impl BlockDevice for MemDev {
+        async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+            let off = block_id as usize * BLOCK_SIZE;
+            buf.copy_from_slice(&self.0.lock_save_irq()[off..off + buf.len()]);
+            Ok(())
+        }
+
+        async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+            let off = block_id as usize * BLOCK_SIZE;
+            self.0.lock_save_irq()[off..off + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+
+        fn num_blocks(&self) -> u64 {
+            (self.0.lock_save_irq().len() / BLOCK_SIZE) as u64
+        }
+
+        async fn sync(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn hash(block: &[u8]) -> [u8; DIGEST_LEN] {
+        let mut h = Sha256::new();
+        h.update(SALT);
+        h.update(block);
+        h.finalize()
+    }
+
+    /// Builds an image of `data_blocks` data blocks followed by their hash
+    /// tree, in the same way `veritysetup format` does.
+    fn build_image(data_blocks: usize) -> (Vec<u8>, VerityParams) {
+        let mut image: Vec<u8> = (0..data_blocks * BLOCK_SIZE)
+            .map(|i| (i * 7) as u8)
+            .collect();
+
+        // Hash each level, bottom-up.
+        let mut levels: Vec<Vec<u8>> = Vec::new();
+        let mut cur: Vec<[u8; DIGEST_LEN]> = image.chunks(BLOCK_SIZE).map(hash).collect();
+
+        while cur.len() > 1 {
+            let mut level = Vec::new();
+
+            for hashes in cur.chunks(BLOCK_SIZE / DIGEST_LEN) {
+                let mut block = vec![0; BLOCK_SIZE];
+                for (i, h) in hashes.iter().enumerate() {
+                    block[i * DIGEST_LEN..(i + 1) * DIGEST_LEN].copy_from_slice(h);
+                }
+                level.extend_from_slice(&block);
+            }
+
+            cur = level.chunks(BLOCK_SIZE).map(hash).collect();
+            levels.push(level);
+        }
+
+        // Store the top level first.
+        for level in levels.iter().rev() {
+            image.extend_from_slice(level);
+        }
+
+        let params = VerityParams {
+            data_blocks: data_blocks as u64,
+            hash_start: data_blocks as u64,
+            root_hash: cur[0],
+            salt: SALT.to_vec(),
+        };
+
+        (image, params)
+    }
+
+    fn verity_dev(image: Vec<u8>, params: VerityParams) -> (VerityBlkDev, MemDev) {
+        let mem = MemDev(Arc::new(SpinLock::new(image)));
+        let dev = VerityBlkDev::new(Box::new(mem.clone()), params).unwrap();
+        (dev, mem)
+    }
+
+    #[ktest]
+    fn verity_parse_params() {
+        let root = "00".repeat(31) + "ff";
+        let params = VerityParams::parse(&alloc::format!("100,100,{root},abcd")).unwrap();
+
+        assert_eq!(params.data_blocks, 100);
+        assert_eq!(params.root_hash[31], 0xff);
+        assert_eq!(params.salt, [0xab, 0xcd]);
+        assert_eq!(
+            VerityParams::parse(&alloc::format!("100,100,{root},-"))
+                .unwrap()
+                .salt,
+            []
+        );
+        assert!(VerityParams::parse("100,100,abc").is_err());
+    }
+
+    #[ktest]
+    async fn verity_reads_intact_blocks() {
+        // Two digests fit in a hash block, so 9 blocks need a four-level
+        // tree.
+        let (image, params) = build_image(9);
+        let expected = image[..9 * BLOCK_SIZE].to_vec();
+        let (dev, _) = verity_dev(image, params);
+
+        let mut buf = vec![0; 9 * BLOCK_SIZE];
+        dev.read(0, &mut buf).await.unwrap();
+        assert_eq!(buf, expected);
+
+        let mut buf = vec![0; BLOCK_SIZE];
+        assert_eq!(
+            dev.read(9, &mut buf).await,
+            Err(IoError::OutOfBounds.into())
+        );
+    }
+
+    #[ktest]
+    async fn verity_detects_corruption() {
+        let (image, params) = build_image(9);
+        let (dev, mem) = verity_dev(image, params);
+        let mut buf = vec![0; BLOCK_SIZE];
+
+        // Corrupt a data block.
+        mem.0.lock_save_irq()[5 * BLOCK_SIZE] ^= 1;
+        assert_eq!(
+            dev.read(5, &mut buf).await,
+            Err(KernelError::Io(IoError::VerificationFailed))
+        );
+        dev.read(4, &mut buf).await.unwrap();
+
+        // Corrupt the leaf hash block covering block 8. The levels above it
+        // take up 1 + 2 + 3 blocks.
+        let (image, params) = build_image(9);
+        let leaf = params.hash_start as usize + 6 + 4;
+        let (dev, mem) = verity_dev(image, params);
+        mem.0.lock_save_irq()[leaf * BLOCK_SIZE] ^= 1;
+        assert!(dev.read(8, &mut buf).await.is_err());
+        dev.read(0, &mut buf).await.unwrap();
+    }
+
+    #[ktest]
+    async fn verity_single_block() {
+        let (image, params) = build_image(1);
+        let (dev, _) = verity_dev(image, params);
+        let mut buf = vec![0; BLOCK_SIZE];
+
+        dev.read(0, &mut buf).await.unwrap();
+        assert!(dev.write(0, &buf).await.is_err());
+    }
+}
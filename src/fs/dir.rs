@@ -207,3 +207,32 @@ pub async fn sys_getdents64(ctx: &ProcessCtx, fd: Fd, mut ubuf: UA, size: u32) -
         Ok(bytes_written)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Dirent64Hdr, DirentFileType};
+    use core::mem::{offset_of, size_of};
+    use moss_macros::ktest;
+
+    // The fixed part of `struct linux_dirent64`; the name follows straight
+    // on.
+    #[ktest]
+    fn dirent64_layout() {
+        assert_eq!(size_of::<Dirent64Hdr>(), 19);
+        assert_eq!(offset_of!(Dirent64Hdr, _ino), 0);
+        assert_eq!(offset_of!(Dirent64Hdr, _off), 8);
+        assert_eq!(offset_of!(Dirent64Hdr, _reclen), 16);
+        assert_eq!(offset_of!(Dirent64Hdr, _kind), 18);
+    }
+
+    #[ktest]
+    fn dirent64_type_values() {
+        assert_eq!(DirentFileType::Fifo as u8, 1);
+        assert_eq!(DirentFileType::Char as u8, 2);
+        assert_eq!(DirentFileType::Dir as u8, 4);
+        assert_eq!(DirentFileType::Block as u8, 6);
+        assert_eq!(DirentFileType::Reg as u8, 8);
+        assert_eq!(DirentFileType::Link as u8, 10);
+        assert_eq!(DirentFileType::Socket as u8, 12);
+    }
+}
@@ -2,9 +2,10 @@ use core::pin::Pin;
 
 use alloc::boxed::Box;
 use async_trait::async_trait;
+use futures::FutureExt;
 use libkernel::{
     error::{FsError, KernelError, Result},
-    fs::SeekFrom,
+    fs::{FallocMode, SeekFrom},
     memory::address::UA,
 };
 
@@ -13,7 +14,7 @@ use crate::kernel::kpipe::KPipe;
 use super::{dir::OpenFileDirIter, open_file::FileCtx, syscalls::iov::IoVec};
 
 macro_rules! process_iovec {
-    ($iovecs:expr, |$addr:ident, $count:ident| $call:expr) => {
+    ($iovecs:expr, $ready:expr, |$addr:ident, $count:ident, $done:ident| $call:expr) => {
         async {
             let mut total_bytes = 0;
             for vec in $iovecs {
@@ -21,10 +22,22 @@ macro_rules! process_iovec {
                     continue;
                 }
 
+                // Only the first iovec is waited on; the rest take whatever
+                // can be had straight away.
+                if total_bytes > 0 && !$ready {
+                    break;
+                }
+
                 let $addr = vec.iov_base;
                 let $count = vec.iov_len;
+                let $done = total_bytes as u64;
 
-                let bytes = { $call }.await?;
+                let bytes = match { $call }.await {
+                    Ok(bytes) => bytes,
+                    // What was already transferred is reported, not the error.
+                    Err(_) if total_bytes > 0 => break,
+                    Err(e) => return Err(e),
+                };
 
                 total_bytes += bytes;
 
@@ -48,6 +61,7 @@ pub trait FileOps: Send + Sync {
         Ok(total_bytes_read)
     }
 
+    /// Reads data from `offset` into `buf`, leaving the file's cursor alone.
     async fn readat(&mut self, buf: UA, count: usize, offset: u64) -> Result<usize>;
 
     /// Writes data from `buf` to the current file position.
@@ -58,14 +72,25 @@ pub trait FileOps: Send + Sync {
         Ok(total_bytes_written)
     }
 
+    /// Writes data from `buf` at `offset`, leaving the file's cursor alone.
     async fn writeat(&mut self, buf: UA, count: usize, offset: u64) -> Result<usize>;
 
     async fn readv(&mut self, ctx: &mut FileCtx, iovecs: &[IoVec]) -> Result<usize> {
-        process_iovec!(iovecs, |addr, count| self.read(ctx, addr, count)).await
+        process_iovec!(
+            iovecs,
+            self.poll_read_ready().now_or_never().is_some(),
+            |addr, count, _done| self.read(ctx, addr, count)
+        )
+        .await
     }
 
     async fn readvat(&mut self, iovecs: &[IoVec], offset: u64) -> Result<usize> {
-        process_iovec!(iovecs, |addr, count| self.readat(addr, count, offset)).await
+        process_iovec!(iovecs, true, |addr, count, done| self.readat(
+            addr,
+            count,
+            offset + done
+        ))
+        .await
     }
 
     async fn readdir<'a>(&'a mut self, _ctx: &'a mut FileCtx) -> Result<OpenFileDirIter<'a>> {
@@ -73,11 +98,28 @@ pub trait FileOps: Send + Sync {
     }
 
     async fn writev(&mut self, ctx: &mut FileCtx, iovecs: &[IoVec]) -> Result<usize> {
-        process_iovec!(iovecs, |addr, count| self.write(ctx, addr, count)).await
+        process_iovec!(
+            iovecs,
+            self.poll_write_ready().now_or_never().is_some(),
+            |addr, count, _done| self.write(ctx, addr, count)
+        )
+        .await
     }
 
     async fn writevat(&mut self, iovecs: &[IoVec], offset: u64) -> Result<usize> {
-        process_iovec!(iovecs, |addr, count| self.writeat(addr, count, offset)).await
+        process_iovec!(iovecs, true, |addr, count, done| self.writeat(
+            addr,
+            count,
+            offset + done
+        ))
+        .await
+    }
+
+    /// Whether the file is a stream with no position of its own, like a pipe
+    /// or a socket. `readat()` and `writeat()` on a stream ignore the offset,
+    /// so the positional syscalls refuse it instead.
+    fn is_stream(&self) -> bool {
+        false
     }
 
     /// Puts the current task to sleep until a call to `read()` would no longer
@@ -109,6 +151,17 @@ pub trait FileOps: Send + Sync {
         Err(KernelError::InvalidValue)
     }
 
+    /// Allocates or frees the storage behind part of the file.
+    async fn fallocate(
+        &mut self,
+        _ctx: &FileCtx,
+        _mode: FallocMode,
+        _offset: u64,
+        _len: u64,
+    ) -> Result<()> {
+        Err(FsError::NoDevice.into())
+    }
+
     /// Flushes any pending writes to the hardware.
     async fn flush(&self, _ctx: &FileCtx) -> Result<()> {
         Ok(())
@@ -138,6 +191,11 @@ pub trait FileOps: Send + Sync {
         Err(KernelError::InvalidValue)
     }
 
+    /// The pipe behind the file, if it's either end of a pipe or a FIFO.
+    fn as_pipe(&self) -> Option<&super::pipe::PipeInner> {
+        None
+    }
+
     fn as_socket(&mut self) -> Option<&mut dyn crate::net::SocketOps> {
         None
     }
@@ -155,4 +213,12 @@ pub trait FileOps: Send + Sync {
     fn as_inotify(&mut self) -> Option<&mut crate::process::inotify::Inotify> {
         None
     }
+
+    fn as_fanotify(&mut self) -> Option<&mut crate::process::fanotify::Fanotify> {
+        None
+    }
+
+    fn as_pidfd(&mut self) -> Option<&mut crate::process::pidfd::PidFile> {
+        None
+    }
 }
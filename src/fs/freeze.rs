@@ -0,0 +1,235 @@
+//! Per-filesystem write state used to implement `FIFREEZE`/`FITHAW` and
+//! read-only remounts.
+//!
+//! Every operation that modifies a filesystem first takes a [`WriteGuard`] on
+//! it. Freezing or remounting read-only blocks new writers and then waits for
+//! the in-flight ones to drain before flushing the filesystem, so a snapshot
+//! of the backing device taken afterwards is consistent.
+//!
+//! Files opened for writing hold a [`WriteAccess`] on their filesystem for as
+//! long as they are open, which keeps it from being remounted read-only under
+//! them.
+
+use crate::sync::CondVar;
+use libkernel::{
+    error::{FsError, KernelError, Result},
+    sync::condvar::WakeupType,
+};
+
+struct SbWriteState {
+    /// Number of in-flight write operations.
+    writers: usize,
+    /// Set while the filesystem is frozen; new writers sleep until thawed.
+    frozen: bool,
+    /// Set when the filesystem is mounted (or remounted) read-only.
+    read_only: bool,
+    /// Number of files open for writing.
+    open_writers: usize,
+}
+
+#[derive(Clone)]
+pub struct SbState {
+    inner: CondVar<SbWriteState>,
+}
+
+impl SbState {
+    pub fn new(read_only: bool) -> Self {
+        Self {
+            inner: CondVar::new(SbWriteState {
+                writers: 0,
+                frozen: false,
+                read_only,
+                open_writers: 0,
+            }),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        let mut read_only = false;
+
+        self.inner.update(|s| {
+            read_only = s.read_only;
+            WakeupType::None
+        });
+
+        read_only
+    }
+
+    /// Registers a new writer, sleeping whilst the filesystem is frozen.
+    pub async fn begin_write(&self) -> Result<WriteGuard> {
+        self.inner
+            .wait_until(|s| {
+                if s.read_only {
+                    Some(Err(KernelError::from(FsError::ReadOnly)))
+                } else if s.frozen {
+                    None
+                } else {
+                    s.writers += 1;
+                    Some(Ok(()))
+                }
+            })
+            .await?;
+
+        Ok(WriteGuard {
+            state: Some(self.clone()),
+        })
+    }
+
+    /// Blocks new writers and waits for all in-flight writers to finish.
+    ///
+    /// Returns `FsError::Busy` if the filesystem is already frozen.
+    pub async fn freeze(&self) -> Result<()> {
+        let mut res = Ok(());
+
+        self.inner.update(|s| {
+            if s.frozen {
+                res = Err(KernelError::from(FsError::Busy));
+            } else {
+                s.frozen = true;
+            }
+
+            WakeupType::None
+        });
+
+        res?;
+
+        self.drain_writers().await;
+
+        Ok(())
+    }
+
+    /// Thaws a frozen filesystem, waking any writers that were blocked.
+    pub fn thaw(&self) -> Result<()> {
+        let mut res = Ok(());
+
+        self.inner.update(|s| {
+            if s.frozen {
+                s.frozen = false;
+                WakeupType::All
+            } else {
+                res = Err(KernelError::InvalidValue);
+                WakeupType::None
+            }
+        });
+
+        res
+    }
+
+    /// Registers a file opened for writing, failing with `FsError::ReadOnly`
+    /// if the filesystem is mounted read-only.
+    pub fn get_write_access(&self) -> Result<WriteAccess> {
+        let mut res = Ok(());
+
+        self.inner.update(|s| {
+            if s.read_only {
+                res = Err(KernelError::from(FsError::ReadOnly));
+            } else {
+                s.open_writers += 1;
+            }
+
+            WakeupType::None
+        });
+
+        res?;
+
+        Ok(WriteAccess {
+            state: Some(self.clone()),
+        })
+    }
+
+    /// Switches the filesystem between read-only and read-write. When going
+    /// read-only, this waits for in-flight writers to finish.
+    ///
+    /// Returns `KernelError::InUse` if going read-only whilst files are still
+    /// open for writing.
+    pub async fn set_read_only(&self, read_only: bool) -> Result<()> {
+        let mut res = Ok(());
+
+        self.inner.update(|s| {
+            if read_only && s.open_writers != 0 {
+                res = Err(KernelError::InUse);
+                return WakeupType::None;
+            }
+
+            s.read_only = read_only;
+            WakeupType::All
+        });
+
+        res?;
+
+        if read_only {
+            self.drain_writers().await;
+        }
+
+        Ok(())
+    }
+
+    async fn drain_writers(&self) {
+        self.inner
+            .wait_until(|s| if s.writers == 0 { Some(()) } else { None })
+            .await;
+    }
+
+    fn put_write_access(&self) {
+        self.inner.update(|s| {
+            s.open_writers -= 1;
+            WakeupType::None
+        });
+    }
+
+    fn end_write(&self) {
+        self.inner.update(|s| {
+            s.writers -= 1;
+
+            if s.writers == 0 {
+                WakeupType::All
+            } else {
+                WakeupType::None
+            }
+        });
+    }
+}
+
+/// An in-flight write against a filesystem. Dropping the guard allows a
+/// pending freeze or read-only remount to proceed.
+pub struct WriteGuard {
+    state: Option<SbState>,
+}
+
+impl WriteGuard {
+    /// A guard for inodes that don't belong to a mounted filesystem (memfds,
+    /// pipes, etc.) and so can never be frozen.
+    pub fn untracked() -> Self {
+        Self { state: None }
+    }
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.take() {
+            state.end_write();
+        }
+    }
+}
+
+/// Write access held by a file for as long as it's open for writing.
+/// Dropping it allows the filesystem to be remounted read-only again.
+pub struct WriteAccess {
+    state: Option<SbState>,
+}
+
+impl WriteAccess {
+    /// Write access to a file that doesn't belong to a mounted filesystem,
+    /// and so can never be remounted read-only.
+    pub fn untracked() -> Self {
+        Self { state: None }
+    }
+}
+
+impl Drop for WriteAccess {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.take() {
+            state.put_write_access();
+        }
+    }
+}
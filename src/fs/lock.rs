@@ -0,0 +1,318 @@
+//! Advisory file locks, as taken with `flock()` and `fcntl(F_SETLK)`.
+//!
+//! Both kinds live in one table, keyed by inode, but they never conflict with
+//! each other. `flock()` locks cover the whole file and belong to the open file
+//! description, so they're shared across `dup()` and `fork()`, and go away
+//! when the last descriptor for it is closed. POSIX record locks cover a byte
+//! range and belong to the process; they go away as soon as the process closes
+//! *any* descriptor for the file, or exits.
+
+use crate::{
+    process::thread_group::{
+        Tgid,
+        signal::{InterruptResult, Interruptable},
+    },
+    sync::{CondVar, OnceLock},
+};
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use libkernel::{
+    error::{KernelError, Result},
+    fs::InodeId,
+    sync::condvar::WakeupType,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockOwner {
+    /// A `flock()` lock, held by the open file description at this address.
+    File(usize),
+    /// A POSIX record lock, held by a process.
+    Process(Tgid),
+}
+
+impl LockOwner {
+    /// `flock()` and POSIX locks are independent of each other.
+    fn same_kind(self, other: Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::File(_), Self::File(_)) | (Self::Process(_), Self::Process(_))
+        )
+    }
+}
+
+/// A lock on the bytes `start..=end` of a file.
+#[derive(Clone, Copy, Debug)]
+pub struct FileLock {
+    pub owner: LockOwner,
+    pub kind: LockKind,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl FileLock {
+    fn conflicts(&self, owner: LockOwner, kind: LockKind, start: u64, end: u64) -> bool {
+        self.owner != owner
+            && self.owner.same_kind(owner)
+            && self.start <= end
+            && start <= self.end
+            && (self.kind == LockKind::Exclusive || kind == LockKind::Exclusive)
+    }
+}
+
+#[derive(Default)]
+struct LockTable {
+    locks: BTreeMap<InodeId, Vec<FileLock>>,
+    /// Who each blocked owner is waiting on, to catch deadlocks.
+    blocked_on: BTreeMap<LockOwner, LockOwner>,
+}
+
+impl LockTable {
+    fn conflicting(
+        &self,
+        id: InodeId,
+        owner: LockOwner,
+        kind: LockKind,
+        start: u64,
+        end: u64,
+    ) -> Option<FileLock> {
+        self.locks
+            .get(&id)?
+            .iter()
+            .find(|lock| lock.conflicts(owner, kind, start, end))
+            .copied()
+    }
+
+    /// Sets the lock `owner` has on `start..=end` to `kind`, or unlocks it if
+    /// `kind` is `None`, splitting and merging the owner's other locks as
+    /// needed.
+    fn set(&mut self, id: InodeId, owner: LockOwner, kind: Option<LockKind>, start: u64, end: u64) {
+        let mut locks = Vec::new();
+
+        for lock in self.locks.remove(&id).unwrap_or_default() {
+            if lock.owner != owner || lock.end < start || lock.start > end {
+                locks.push(lock);
+                continue;
+            }
+
+            // Keep whatever sticks out either side of the range.
+            if lock.start < start {
+                locks.push(FileLock {
+                    end: start - 1,
+                    ..lock
+                });
+            }
+
+            if lock.end > end {
+                locks.push(FileLock {
+                    start: end + 1,
+                    ..lock
+                });
+            }
+        }
+
+        if let Some(kind) = kind {
+            let mut new = FileLock {
+                owner,
+                kind,
+                start,
+                end,
+            };
+
+            // Swallow the owner's locks of the same kind that touch this one.
+            locks.retain(|lock| {
+                let touches = lock.start <= new.end.saturating_add(1)
+                    && new.start <= lock.end.saturating_add(1);
+
+                if lock.owner == owner && lock.kind == kind && touches {
+                    new.start = new.start.min(lock.start);
+                    new.end = new.end.max(lock.end);
+                    false
+                } else {
+                    true
+                }
+            });
+
+            locks.push(new);
+        }
+
+        if !locks.is_empty() {
+            self.locks.insert(id, locks);
+        }
+    }
+
+    /// Returns `true` if `owner` waiting on `blocker` would close a cycle.
+    fn would_deadlock(&self, owner: LockOwner, blocker: LockOwner) -> bool {
+        let mut cur = blocker;
+
+        // Every step is a distinct waiter, so this can't go round forever.
+        for _ in 0..=self.blocked_on.len() {
+            if cur == owner {
+                return true;
+            }
+
+            match self.blocked_on.get(&cur) {
+                Some(next) => cur = *next,
+                None => return false,
+            }
+        }
+
+        false
+    }
+}
+
+static LOCKS: OnceLock<CondVar<LockTable>> = OnceLock::new();
+
+fn locks() -> &'static CondVar<LockTable> {
+    LOCKS.get_or_init(|| CondVar::new(LockTable::default()))
+}
+
+/// Takes a lock on `start..=end` of the file `id`, or drops it if `kind` is
+/// `None`.
+///
+/// If someone else holds a conflicting lock, this fails with `EAGAIN`, unless
+/// `wait` is set, in which case it waits for them. Waiting on a process that is
+/// itself waiting, through some chain, on this owner fails with `EDEADLK`.
+pub async fn set_lock(
+    id: InodeId,
+    owner: LockOwner,
+    kind: Option<LockKind>,
+    start: u64,
+    end: u64,
+    wait: bool,
+) -> Result<()> {
+    let Some(kind) = kind else {
+        locks().update(|table| {
+            table.set(id, owner, None, start, end);
+            WakeupType::All
+        });
+
+        return Ok(());
+    };
+
+    let mut blocked = false;
+    locks().update(|table| {
+        if table.conflicting(id, owner, kind, start, end).is_some() {
+            blocked = true;
+            WakeupType::None
+        } else {
+            // This may have downgraded a lock someone's waiting on.
+            table.set(id, owner, Some(kind), start, end);
+            WakeupType::All
+        }
+    });
+
+    if !blocked {
+        return Ok(());
+    }
+
+    if !wait {
+        return Err(KernelError::TryAgain);
+    }
+
+    let res = locks()
+        .wait_until(
+            move |table| match table.conflicting(id, owner, kind, start, end) {
+                None => {
+                    table.blocked_on.remove(&owner);
+                    table.set(id, owner, Some(kind), start, end);
+                    Some(Ok(()))
+                }
+                Some(blocker) => {
+                    if matches!(owner, LockOwner::Process(_))
+                        && table.would_deadlock(owner, blocker.owner)
+                    {
+                        table.blocked_on.remove(&owner);
+                        Some(Err(KernelError::Deadlock))
+                    } else {
+                        table.blocked_on.insert(owner, blocker.owner);
+                        None
+                    }
+                }
+            },
+        )
+        .interruptable()
+        .await;
+
+    match res {
+        InterruptResult::Interrupted => {
+            locks().update(|table| {
+                table.blocked_on.remove(&owner);
+                WakeupType::None
+            });
+
+            Err(KernelError::Interrupted)
+        }
+        InterruptResult::Uninterrupted(res) => res,
+    }
+}
+
+/// Returns a lock that would stop `owner` from locking `start..=end` of the
+/// file `id` as `kind`, if there is one.
+pub fn test_lock(
+    id: InodeId,
+    owner: LockOwner,
+    kind: LockKind,
+    start: u64,
+    end: u64,
+) -> Option<FileLock> {
+    let mut conflict = None;
+
+    locks().update(|table| {
+        conflict = table.conflicting(id, owner, kind, start, end);
+        WakeupType::None
+    });
+
+    conflict
+}
+
+/// Drops every lock `owner` holds on the file `id`.
+pub fn release(id: InodeId, owner: LockOwner) {
+    let Some(table) = LOCKS.get() else {
+        return;
+    };
+
+    table.update(|table| {
+        let Some(locks) = table.locks.get_mut(&id) else {
+            return WakeupType::None;
+        };
+
+        let before = locks.len();
+        locks.retain(|lock| lock.owner != owner);
+
+        if locks.len() == before {
+            return WakeupType::None;
+        }
+
+        if locks.is_empty() {
+            table.locks.remove(&id);
+        }
+
+        WakeupType::All
+    });
+}
+
+/// Drops every POSIX lock held by the process `tgid`, when it exits.
+pub fn release_process(tgid: Tgid) {
+    let Some(table) = LOCKS.get() else {
+        return;
+    };
+
+    let owner = LockOwner::Process(tgid);
+
+    table.update(|table| {
+        table.blocked_on.remove(&owner);
+
+        for locks in table.locks.values_mut() {
+            locks.retain(|lock| lock.owner != owner);
+        }
+
+        table.locks.retain(|_, locks| !locks.is_empty());
+
+        WakeupType::All
+    });
+}
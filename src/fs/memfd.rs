@@ -1,107 +1,319 @@
-use crate::fs::fops::FileOps;
-use crate::fs::open_file::{FileCtx, OpenFile};
-use crate::memory::uaccess::{copy_from_user_slice, copy_to_user_slice};
+use super::{VFS, file_owner};
+use crate::clock::realtime::date;
+use crate::fs::open_file::OpenFile;
+use crate::fs::reg::RegFile;
+use crate::memory::uaccess::cstr::UserCStr;
 use crate::process::fd_table::FdFlags;
 use crate::sched::syscall_ctx::ProcessCtx;
 use crate::sync::Mutex;
 use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
-use alloc::vec::Vec;
 use async_trait::async_trait;
+use core::any::Any;
 use core::ffi::c_char;
-use libkernel::fs::{OpenFlags, SeekFrom};
-use libkernel::memory::address::{TUA, UA};
+use core::sync::atomic::{AtomicIsize, AtomicU64, Ordering};
+use libkernel::error::{KernelError, Result};
+use libkernel::fs::attr::{FileAttr, FileOwner, FilePermissions};
+use libkernel::fs::pathbuf::PathBuf;
+use libkernel::fs::{FallocMode, FileType, Inode, InodeId, OpenFlags};
+use libkernel::memory::address::TUA;
+use libkernel::memory::page::PageFrame;
 
-pub struct MemFd {
-    data: Mutex<Vec<u8>>,
+const MFD_CLOEXEC: u32 = 0x0001;
+const MFD_ALLOW_SEALING: u32 = 0x0002;
+const MFD_HUGETLB: u32 = 0x0004;
+const MFD_NOEXEC_SEAL: u32 = 0x0008;
+const MFD_EXEC: u32 = 0x0010;
+
+/// Longest name accepted by `memfd_create`, excluding the NUL terminator.
+const MFD_NAME_MAX_LEN: usize = 249;
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct SealFlags: u32 {
+        const F_SEAL_SEAL = 0x0001;         // Prevent further seals from being set.
+        const F_SEAL_SHRINK = 0x0002;       // Prevent the file from shrinking.
+        const F_SEAL_GROW = 0x0004;         // Prevent the file from growing.
+        const F_SEAL_WRITE = 0x0008;        // Prevent writes.
+        const F_SEAL_FUTURE_WRITE = 0x0010; // Prevent future writes while mapped.
+        const F_SEAL_EXEC = 0x0020;         // Prevent chmod of the exec bits.
+    }
 }
 
-impl MemFd {
-    fn new() -> Self {
-        Self {
-            data: Mutex::new(Vec::new()),
+/// An anonymous in-memory inode backing a memfd.
+///
+/// Storage is provided by a regular file on a kernel-internal tmpfs instance
+/// which is unlinked as soon as it is created; this wrapper enforces the
+/// file's seals on top of it. Since seals live on the inode, they apply to
+/// every open file description referring to it.
+pub struct MemFdInode {
+    inner: Arc<dyn Inode>,
+    seals: Mutex<SealFlags>,
+    /// Number of shared mappings that may write to the file, or -1 once
+    /// `F_SEAL_WRITE` has ruled them out.
+    writable_mappings: AtomicIsize,
+}
+
+impl MemFdInode {
+    /// Returns the seals currently applied to the file.
+    pub async fn seals(&self) -> SealFlags {
+        *self.seals.lock().await
+    }
+
+    /// Applies `seals` in addition to any already set.
+    pub async fn add_seals(&self, seals: SealFlags) -> Result<()> {
+        let mut cur = self.seals.lock().await;
+
+        if cur.contains(SealFlags::F_SEAL_SEAL) {
+            return Err(KernelError::NotPermitted);
         }
+
+        // Writes through an existing shared mapping couldn't be stopped.
+        if seals.contains(SealFlags::F_SEAL_WRITE)
+            && !cur.contains(SealFlags::F_SEAL_WRITE)
+            && self
+                .writable_mappings
+                .compare_exchange(0, -1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+        {
+            return Err(KernelError::InUse);
+        }
+
+        cur.insert(seals);
+
+        Ok(())
     }
 }
 
 #[async_trait]
-impl FileOps for MemFd {
-    async fn readat(
-        &mut self,
-        buf: UA,
-        count: usize,
-        offset: u64,
-    ) -> libkernel::error::Result<usize> {
-        if count == 0 {
-            return Ok(0);
+impl Inode for MemFdInode {
+    fn id(&self) -> InodeId {
+        self.inner.id()
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.inner.read_at(offset, buf).await
+    }
+
+    async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        // Hold the seals across the write so that it can't race with the
+        // file being sealed.
+        let seals = self.seals.lock().await;
+
+        if seals.intersects(SealFlags::F_SEAL_WRITE | SealFlags::F_SEAL_FUTURE_WRITE) {
+            return Err(KernelError::NotPermitted);
         }
-        let data = self.data.lock().await;
-        let offset = offset as usize;
-        if offset >= data.len() {
-            return Ok(0);
+
+        if seals.contains(SealFlags::F_SEAL_GROW) {
+            let size = self.inner.getattr().await?.size;
+
+            if offset.saturating_add(buf.len() as u64) > size {
+                return Err(KernelError::NotPermitted);
+            }
         }
-        let available = data.len() - offset;
-        let read_len = available.min(count);
-        copy_to_user_slice(&data[offset..offset + read_len], buf).await?;
-        Ok(read_len)
-    }
-
-    async fn writeat(
-        &mut self,
-        buf: UA,
-        count: usize,
-        offset: u64,
-    ) -> libkernel::error::Result<usize> {
-        if count == 0 {
-            return Ok(0);
+
+        self.inner.write_at(offset, buf).await
+    }
+
+    async fn truncate(&self, new_size: u64) -> Result<()> {
+        let seals = self.seals.lock().await;
+        let size = self.inner.getattr().await?.size;
+
+        if (new_size < size && seals.contains(SealFlags::F_SEAL_SHRINK))
+            || (new_size > size && seals.contains(SealFlags::F_SEAL_GROW))
+        {
+            return Err(KernelError::NotPermitted);
         }
-        let mut data = self.data.lock().await;
-        let offset = offset as usize;
-        let end = offset + count;
-        if end > data.len() {
-            data.resize(end, 0);
+
+        self.inner.truncate(new_size).await
+    }
+
+    async fn fallocate(&self, mode: FallocMode, offset: u64, len: u64) -> Result<()> {
+        let seals = self.seals.lock().await;
+
+        let denied = match mode {
+            // Like Linux, this holds even when the size is kept.
+            FallocMode::Allocate { .. } => {
+                seals.contains(SealFlags::F_SEAL_GROW)
+                    && offset.saturating_add(len) > self.inner.getattr().await?.size
+            }
+            FallocMode::PunchHole => {
+                seals.intersects(SealFlags::F_SEAL_WRITE | SealFlags::F_SEAL_FUTURE_WRITE)
+            }
+        };
+
+        if denied {
+            return Err(KernelError::NotPermitted);
         }
-        copy_from_user_slice(buf, &mut data[offset..end]).await?;
-        Ok(count)
+
+        self.inner.fallocate(mode, offset, len).await
     }
 
-    async fn truncate(&mut self, _ctx: &FileCtx, new_size: usize) -> libkernel::error::Result<()> {
-        let mut data = self.data.lock().await;
-        data.resize(new_size, 0);
-        Ok(())
+    async fn get_page(&self, pg_idx: u64) -> Result<PageFrame> {
+        self.inner.get_page(pg_idx).await
+    }
+
+    fn can_share_pages(&self) -> bool {
+        self.inner.can_share_pages()
+    }
+
+    fn map_writable(&self) -> Result<()> {
+        self.writable_mappings
+            .try_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n >= 0).then_some(n + 1)
+            })
+            .map(|_| ())
+            .map_err(|_| KernelError::NotPermitted)
+    }
+
+    fn unmap_writable(&self) {
+        self.writable_mappings.fetch_sub(1, Ordering::SeqCst);
     }
 
-    async fn seek(&mut self, ctx: &mut FileCtx, pos: SeekFrom) -> libkernel::error::Result<u64> {
-        fn saturating_add_signed(u: u64, i: i64) -> u64 {
-            if i >= 0 {
-                u.saturating_add(i as u64)
-            } else {
-                u.saturating_sub((-i) as u64)
+    async fn getattr(&self) -> Result<FileAttr> {
+        self.inner.getattr().await
+    }
+
+    async fn setattr(&self, attr: FileAttr) -> Result<()> {
+        let exec_bits =
+            FilePermissions::S_IXUSR | FilePermissions::S_IXGRP | FilePermissions::S_IXOTH;
+
+        if self.seals.lock().await.contains(SealFlags::F_SEAL_EXEC) {
+            let old = self.inner.getattr().await?.permissions;
+
+            if (old ^ attr.permissions).intersects(exec_bits) {
+                return Err(KernelError::NotPermitted);
             }
         }
 
-        let size = self.data.lock().await.len() as u64;
-        ctx.pos = match pos {
-            SeekFrom::Start(x) => x,
-            SeekFrom::End(x) => saturating_add_signed(size, x),
-            SeekFrom::Current(x) => saturating_add_signed(ctx.pos, x),
-        };
+        self.inner.setattr(attr).await
+    }
+
+    async fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn datasync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
 
-        Ok(ctx.pos)
+/// Root of the kernel-internal tmpfs instance which backs all memfds.
+static MEMFD_ROOT: Mutex<Option<Arc<dyn Inode>>> = Mutex::new(None);
+
+static NEXT_MEMFD_ID: AtomicU64 = AtomicU64::new(0);
+
+async fn memfd_root() -> Result<Arc<dyn Inode>> {
+    let mut root = MEMFD_ROOT.lock().await;
+
+    if let Some(root) = root.as_ref() {
+        return Ok(root.clone());
     }
+
+    let fs = VFS.create_fs_instance("tmpfs", None, "").await?;
+    let inode = fs.root_inode().await?;
+
+    *root = Some(inode.clone());
+
+    Ok(inode)
+}
+
+/// Creates a new anonymous memfd inode with the given permissions.
+async fn create_memfd_inode(
+    mode: FilePermissions,
+    owner: FileOwner,
+    seals: SealFlags,
+) -> Result<Arc<MemFdInode>> {
+    let root = memfd_root().await?;
+    let name = format!("memfd.{}", NEXT_MEMFD_ID.fetch_add(1, Ordering::Relaxed));
+
+    let inner = root
+        .create(&name, FileType::File, mode, owner, Some(date()))
+        .await?;
+    root.unlink(&name).await?;
+
+    Ok(Arc::new(MemFdInode {
+        inner,
+        seals: Mutex::new(seals),
+        writable_mappings: AtomicIsize::new(0),
+    }))
 }
 
-pub async fn sys_memfd_create(
-    ctx: &ProcessCtx,
-    _name: TUA<c_char>,
-    _flags: u32,
-) -> libkernel::error::Result<usize> {
-    let memfd = MemFd::new();
-    let open_file = Arc::new(OpenFile::new(Box::new(memfd), OpenFlags::empty()));
+/// Creates the backing file for a shared anonymous mapping of `len` bytes.
+pub async fn create_shared_anon_inode(len: u64) -> Result<Arc<dyn Inode>> {
+    let inode = create_memfd_inode(
+        FilePermissions::from_bits_retain(0o600),
+        FileOwner::root(),
+        SealFlags::F_SEAL_SEAL,
+    )
+    .await?;
+
+    inode.truncate(len).await?;
+
+    Ok(inode)
+}
+
+pub async fn sys_memfd_create(ctx: &ProcessCtx, name: TUA<c_char>, flags: u32) -> Result<usize> {
+    if flags & !(MFD_CLOEXEC | MFD_ALLOW_SEALING | MFD_HUGETLB | MFD_NOEXEC_SEAL | MFD_EXEC) != 0
+        || (flags & MFD_EXEC != 0 && flags & MFD_NOEXEC_SEAL != 0)
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    if flags & MFD_HUGETLB != 0 {
+        return Err(KernelError::NotSupported);
+    }
+
+    let mut buf = [0; MFD_NAME_MAX_LEN + 1];
+    let name: String = match UserCStr::from_ptr(name).copy_from_user(&mut buf).await {
+        Ok(name) => name.into(),
+        Err(KernelError::BufferFull) => return Err(KernelError::InvalidValue),
+        Err(e) => return Err(e),
+    };
+
+    let (mode, seals) = if flags & MFD_NOEXEC_SEAL != 0 {
+        // MFD_NOEXEC_SEAL implies MFD_ALLOW_SEALING.
+        (
+            FilePermissions::from_bits_retain(0o666),
+            SealFlags::F_SEAL_EXEC,
+        )
+    } else if flags & MFD_ALLOW_SEALING != 0 {
+        (FilePermissions::from_bits_retain(0o777), SealFlags::empty())
+    } else {
+        (
+            FilePermissions::from_bits_retain(0o777),
+            SealFlags::F_SEAL_SEAL,
+        )
+    };
+
+    // The file belongs to its creator.
+    let inode = create_memfd_inode(mode, file_owner(ctx.shared()), seals).await?;
+
+    let inode: Arc<dyn Inode> = inode;
+    let mut open_file = OpenFile::new(Box::new(RegFile::new(inode.clone())), OpenFlags::O_RDWR);
+    open_file.update(inode, PathBuf::from(format!("/memfd:{name}")));
+
+    let fd_flags = if flags & MFD_CLOEXEC != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+
     Ok(ctx
         .shared()
         .fd_table
         .lock_save_irq()
-        .insert_with_flags(open_file, FdFlags::empty())?
+        .insert_with_flags(Arc::new(open_file), fd_flags)?
         .as_raw() as usize)
 }
+
+/// Returns the memfd inode behind `inode`, if it is one.
+pub fn as_memfd(inode: &Arc<dyn Inode>) -> Option<&MemFdInode> {
+    inode.as_any().downcast_ref::<MemFdInode>()
+}
@@ -0,0 +1,289 @@
+//! Mount namespaces.
+//!
+//! Each task resolves paths through the mount table of its mount namespace.
+//! `clone(CLONE_NEWNS)` and `unshare(CLONE_NEWNS)` give a task a namespace of
+//! its own, starting out as a copy of the one it was in; from then on, what's
+//! mounted or unmounted in one of the two isn't seen in the other.
+//!
+//! Unless propagation says otherwise. Every mount has a propagation type, set
+//! with `mount(MS_SHARED | MS_PRIVATE | MS_SLAVE | MS_UNBINDABLE)`:
+//!
+//! - A shared mount belongs to a peer group, and mounting or unmounting inside
+//!   it happens in every other namespace's mount of the group too. The copies
+//!   of a shared mount made for a new namespace are its peers.
+//! - A slave mount receives mounts and unmounts from the peer group it used to
+//!   belong to, but doesn't send its own back.
+//! - A private mount does neither, which is what mounts are to begin with.
+//! - An unbindable mount is private, and can't be bind mounted either.
+//!
+//! Mounts are attached to the inodes they're mounted on, and the copies of a
+//! namespace's mounts are on the very same inodes, so a propagated mount goes
+//! on the same mount point in every namespace that receives it.
+
+use super::{Mount, MountTable, VFS, VfsState, next_mount_id};
+use crate::sync::OnceLock;
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use libkernel::fs::{Filesystem, InodeId};
+
+/// How mounting and unmounting inside a mount spreads to other namespaces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Propagation {
+    Shared,
+    Private,
+    Slave,
+    Unbindable,
+}
+
+pub struct MountNamespace {
+    id: u64,
+}
+
+impl MountNamespace {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Creates a namespace holding a copy of this one's mounts.
+    pub fn copy(&self) -> Arc<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+        VFS.state.lock_save_irq().copy_namespace(self.id, id);
+
+        Arc::new(Self { id })
+    }
+}
+
+impl Drop for MountNamespace {
+    /// Once the last task has left the namespace, its mounts are taken out as
+    /// with a lazy unmount.
+    fn drop(&mut self) {
+        let released = VFS.state.lock_save_irq().drop_namespace(self.id);
+
+        for fs in released {
+            VFS.dcache.invalidate_fs(fs.id());
+            VFS.icache.remove_fs(fs.id());
+        }
+    }
+}
+
+/// Returns the namespace the root filesystem is mounted in, and that the
+/// initial task starts out in.
+pub fn init_mnt_ns() -> Arc<MountNamespace> {
+    static INIT_MNT_NS: OnceLock<Arc<MountNamespace>> = OnceLock::new();
+
+    INIT_MNT_NS
+        .get_or_init(|| Arc::new(MountNamespace { id: 0 }))
+        .clone()
+}
+
+impl VfsState {
+    fn new_peer_group(&mut self) -> u64 {
+        let group = self.next_peer_group;
+
+        self.next_peer_group += 1;
+
+        group
+    }
+
+    /// Returns the namespaces that a mount or unmount on `mount_point_id` in
+    /// the namespace `ns` propagates to, and for each, whether it receives it
+    /// as a peer rather than as a slave.
+    fn receivers(&self, ns: u64, mount_point_id: InodeId) -> Vec<(u64, bool)> {
+        let fs_id = mount_point_id.fs_id();
+        let mut groups: Vec<_> = self
+            .table(ns)
+            .mounts_of(fs_id)
+            .filter_map(|mount| mount.peer_group)
+            .collect();
+        let mut seen = Vec::new();
+        let mut found: Vec<(u64, bool)> = Vec::new();
+
+        while let Some(group) = groups.pop() {
+            if seen.contains(&group) {
+                continue;
+            }
+
+            seen.push(group);
+
+            for (&other, table) in self.namespaces.iter() {
+                if other == ns || found.iter().any(|(found, _)| *found == other) {
+                    continue;
+                }
+
+                for mount in table.mounts_of(fs_id) {
+                    if mount.peer_group == Some(group) {
+                        found.push((other, true));
+                        break;
+                    }
+
+                    if mount.master == Some(group) {
+                        found.push((other, false));
+                        // A slave that's shared too passes on what it receives
+                        // to its own peers.
+                        groups.extend(mount.peer_group);
+                        break;
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Copies `mount`, about to go on `mount_point_id` in the namespace `ns`,
+    /// to wherever that propagates to.
+    pub(super) fn propagate_mount(&mut self, ns: u64, mount_point_id: InodeId, mount: &mut Mount) {
+        if !self
+            .table(ns)
+            .mounts_of(mount_point_id.fs_id())
+            .any(|parent| parent.peer_group.is_some())
+        {
+            return;
+        }
+
+        // A mount made inside a shared mount is shared too, so that what's
+        // mounted inside it later on propagates the same way.
+        let group = match mount.peer_group {
+            Some(group) => group,
+            None => {
+                let group = self.new_peer_group();
+                mount.peer_group = Some(group);
+                group
+            }
+        };
+
+        for (other, is_peer) in self.receivers(ns, mount_point_id) {
+            let table = self.table_mut(other);
+
+            if table.mounts.contains_key(&mount_point_id) {
+                continue;
+            }
+
+            let mut copy = mount.clone();
+
+            copy.id = next_mount_id();
+
+            if !is_peer {
+                copy.peer_group = None;
+                copy.master = Some(group);
+            }
+
+            table.mounts.insert(mount_point_id, copy);
+        }
+    }
+
+    /// Removes the copies of the mount on `mount_point_id` in the namespace
+    /// `ns` from wherever its unmounting propagates to. The mount itself is
+    /// left for the caller to remove.
+    pub(super) fn propagate_unmount(&mut self, ns: u64, mount_point_id: InodeId) {
+        let Some(mount) = self.table(ns).mounts.get(&mount_point_id) else {
+            return;
+        };
+
+        let fs_id = mount.fs.id();
+        let root_id = mount.root_inode.id();
+
+        for (other, _) in self.receivers(ns, mount_point_id) {
+            let table = self.table_mut(other);
+
+            if table
+                .mounts
+                .get(&mount_point_id)
+                .is_some_and(|copy| copy.fs.id() == fs_id && copy.root_inode.id() == root_id)
+            {
+                table.mounts.remove(&mount_point_id);
+            }
+        }
+    }
+
+    /// Gives the mount on `mount_point_id` in the namespace `ns` the
+    /// propagation type `propagation`.
+    pub(super) fn change_propagation(
+        &mut self,
+        ns: u64,
+        mount_point_id: InodeId,
+        propagation: Propagation,
+    ) {
+        let Some(group) = self
+            .table(ns)
+            .mounts
+            .get(&mount_point_id)
+            .map(|mount| mount.peer_group)
+        else {
+            return;
+        };
+
+        let has_peers = group.is_some_and(|group| {
+            self.namespaces
+                .values()
+                .flat_map(|table| table.mounts.values())
+                .filter(|mount| mount.peer_group == Some(group))
+                .count()
+                > 1
+        });
+        let new_group =
+            (propagation == Propagation::Shared && group.is_none()).then(|| self.new_peer_group());
+
+        let Some(mount) = self.table_mut(ns).mounts.get_mut(&mount_point_id) else {
+            return;
+        };
+
+        mount.unbindable = propagation == Propagation::Unbindable;
+
+        match propagation {
+            Propagation::Shared => {
+                mount.peer_group = mount.peer_group.or(new_group);
+            }
+            Propagation::Private | Propagation::Unbindable => {
+                mount.peer_group = None;
+                mount.master = None;
+            }
+            // A shared mount becomes a slave of its peers, or private if it
+            // has none. Anything else stays as it is.
+            Propagation::Slave => {
+                if let Some(group) = mount.peer_group.take() {
+                    mount.master = has_peers.then_some(group);
+                }
+            }
+        }
+    }
+
+    /// Gives the new namespace `to` a copy of the mounts of `from`.
+    pub(super) fn copy_namespace(&mut self, from: u64, to: u64) {
+        let mut mounts: Vec<_> = self.table(from).mounts.iter().collect();
+
+        mounts.sort_by_key(|(_, mount)| mount.id);
+
+        let mut table = MountTable::new();
+
+        for (mount_point_id, mount) in mounts {
+            let mut copy = mount.clone();
+
+            copy.id = next_mount_id();
+            table.mounts.insert(*mount_point_id, copy);
+        }
+
+        self.namespaces.insert(to, table);
+    }
+
+    /// Removes the namespace `ns` and all of its mounts, returning the
+    /// filesystems that are no longer mounted anywhere.
+    pub(super) fn drop_namespace(&mut self, ns: u64) -> Vec<Arc<dyn Filesystem>> {
+        let Some(table) = self.namespaces.remove(&ns) else {
+            return Vec::new();
+        };
+
+        let mut fs_ids: Vec<_> = table.mounts.values().map(|mount| mount.fs.id()).collect();
+
+        fs_ids.sort_unstable();
+        fs_ids.dedup();
+
+        fs_ids
+            .into_iter()
+            .filter_map(|fs_id| self.release_fs(fs_id))
+            .collect()
+    }
+}
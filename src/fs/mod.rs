@@ -1,37 +1,71 @@
 use crate::clock::realtime::date;
 use crate::{
+    arch::ArchImpl,
     drivers::{DM, Driver},
+    memory::{low_on_memory, mmap::unmap_file_from, page::ClaimedPage},
     process::{
-        Task,
+        TASK_LIST, Task, fanotify,
         inotify::{notify_create, notify_delete, notify_delete_self, notify_modify, notify_move},
     },
     sync::SpinLock,
 };
-use alloc::{borrow::ToOwned, boxed::Box, collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use alloc::{
+    borrow::ToOwned,
+    boxed::Box,
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 use async_trait::async_trait;
+use blk::cache::CachedBlkDev;
 use core::any::Any;
 use core::sync::atomic::{AtomicU64, Ordering};
 use dir::DirFile;
+use freeze::{SbState, WriteAccess, WriteGuard};
 use libkernel::{
     error::{FsError, KernelError, Result},
     fs::{
         BlockDevice, FS_ID_START, FileType, Filesystem, Inode, InodeId, OpenFlags,
-        attr::FilePermissions, path::Path,
+        attr::{FileOwner, FilePermissions},
+        dcache::{CachedLookup, DentryCache, DentryStats},
+        icache::{InodeCache, InodeStats},
+        path::Path,
+        pathbuf::PathBuf,
     },
+    memory::PAGE_SIZE,
     proc::caps::CapabilitiesFlags,
 };
+use mnt_ns::{MountNamespace, Propagation, init_mnt_ns};
+use namei::ResolveFlags;
 use open_file::OpenFile;
+use path_file::PathFile;
 use reg::RegFile;
 
+pub mod blk;
 pub mod dir;
 pub mod fops;
+pub mod freeze;
+pub mod lock;
 pub mod memfd;
+pub mod mnt_ns;
+pub mod namei;
 pub mod open_file;
+pub mod page_cache;
+pub mod path_file;
 pub mod pipe;
 pub mod reg;
 pub mod syscalls;
 
-const MAX_SYMLINK: u32 = 40;
+/// The most directory entries kept in the dentry cache.
+const DCACHE_CAPACITY: usize = 4096;
+
+/// The most unused inodes kept in the inode cache.
+const ICACHE_CAPACITY: usize = 1024;
+
+/// How many unused inodes to evict at a time when memory runs low.
+const ICACHE_SHRINK_BATCH: usize = ICACHE_CAPACITY / 4;
 
 /// A dummy inode used as a placeholder before the root filesystem is mounted.
 pub struct DummyInode {}
@@ -46,10 +80,67 @@ impl Inode for DummyInode {
     }
 }
 
+bitflags::bitflags! {
+    /// Options of a single mount, on top of whether its filesystem is
+    /// read-only.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct MntFlags: u32 {
+        /// Set-user-ID and set-group-ID bits are ignored. Exec doesn't honour
+        /// them anywhere yet, so this is only recorded.
+        const MNT_NOSUID = 1 << 0;
+        /// Files can't be executed, or mapped executable.
+        const MNT_NOEXEC = 1 << 1;
+    }
+}
+
 /// Represents a mounted filesystem.
+#[derive(Clone)]
 struct Mount {
+    /// Mounts are numbered in the order they were made.
+    id: u64,
     fs: Arc<dyn Filesystem>,
+    /// The root of the mount: the filesystem's root, or for a bind mount, the
+    /// inode that was bound.
     root_inode: Arc<dyn Inode>,
+    /// The inode the filesystem is mounted on, or `None` for the root
+    /// filesystem.
+    mount_point: Option<Arc<dyn Inode>>,
+    /// Where the filesystem was mounted, as given when mounting it.
+    path: PathBuf,
+    /// Where `root_inode` is inside the filesystem: `/`, unless this is a
+    /// bind mount of something further down.
+    root: PathBuf,
+    /// What was mounted, e.g. a device name.
+    source: String,
+    /// The name of the filesystem's driver.
+    fs_type: String,
+    flags: MntFlags,
+    /// The peer group of a shared mount.
+    peer_group: Option<u64>,
+    /// The peer group a slave mount receives mounts and unmounts from.
+    master: Option<u64>,
+    /// Whether the mount refuses to be bind mounted.
+    unbindable: bool,
+}
+
+/// An entry of the mount table, as listed in `/proc/mounts` and
+/// `/proc/<pid>/mountinfo`.
+pub struct MountInfo {
+    pub id: u64,
+    /// The mount this one is mounted inside. The root filesystem is its own
+    /// parent.
+    pub parent_id: u64,
+    /// The ID of the mounted filesystem, as given by `stat()` in `st_dev`.
+    pub dev: u64,
+    pub root: PathBuf,
+    pub source: String,
+    pub path: PathBuf,
+    pub fs_type: String,
+    pub read_only: bool,
+    pub flags: MntFlags,
+    pub peer_group: Option<u64>,
+    pub master: Option<u64>,
+    pub unbindable: bool,
 }
 
 /// This trait represents a type of filesystem, like "ext4" or "tmpfs". It acts
@@ -61,39 +152,134 @@ pub trait FilesystemDriver: Driver + Send + Sync {
         fs_id: u64,
         blk_dev: Option<Box<dyn BlockDevice>>,
     ) -> Result<Arc<dyn Filesystem>>;
+
+    /// Constructs an instance set up by `options`, the comma-separated option
+    /// string given to mount() in its `data` argument.
+    ///
+    /// Filesystems that take no options ignore them.
+    async fn construct_with_options(
+        &self,
+        fs_id: u64,
+        blk_dev: Option<Box<dyn BlockDevice>>,
+        _options: &str,
+    ) -> Result<Arc<dyn Filesystem>> {
+        self.construct(fs_id, blk_dev).await
+    }
 }
 
-/// The internal state of the VFS.
-///
-/// This struct consolidates the filesystem-wide collections (the list of all
-/// registered filesystem instances and the mapping of mount points).
-struct VfsState {
+/// The mounts of a single mount namespace.
+struct MountTable {
     /// A map from an InodeId of a directory to the Mount that is mounted there.
+    /// A filesystem has an entry for every place it's mounted, bind mounts
+    /// included.
     mounts: BTreeMap<InodeId, Mount>,
-    /// A map from a filesystem ID to the corresponding filesystem instance.
-    filesystems: BTreeMap<u64, Arc<dyn Filesystem>>,
 }
 
-impl VfsState {
-    /// Creates a new, empty VfsState.
+/// What a namespace with no mounts of its own yet sees.
+static EMPTY_MOUNT_TABLE: MountTable = MountTable::new();
+
+impl MountTable {
     const fn new() -> Self {
         Self {
             mounts: BTreeMap::new(),
-            filesystems: BTreeMap::new(),
         }
     }
 
-    /// Registers a new filesystem and its mount point.
-    fn add_mount(&mut self, mount_point_id: InodeId, mount: Mount) {
-        self.filesystems.insert(mount.fs.id(), mount.fs.clone());
-        self.mounts.insert(mount_point_id, mount);
+    /// Returns the mounts of the filesystem `fs_id`.
+    fn mounts_of(&self, fs_id: u64) -> impl Iterator<Item = &Mount> {
+        self.mounts
+            .values()
+            .filter(move |mount| mount.fs.id() == fs_id)
+    }
+
+    /// Returns the first mount of the filesystem `fs_id`.
+    fn get_mount(&self, fs_id: u64) -> Option<&Mount> {
+        self.mounts_of(fs_id).min_by_key(|mount| mount.id)
     }
 
-    /// Removes a mount point by its inode ID.
-    fn remove_mount(&mut self, mount_point_id: &InodeId) -> Option<()> {
-        let mount = self.mounts.remove(mount_point_id)?;
-        self.filesystems.remove(&mount.fs.id())?;
-        Some(())
+    /// Returns the mount of the filesystem `fs_id` that `path` is reached
+    /// through: the one mounted at the longest leading part of it, and the
+    /// last one made of those.
+    fn mount_of(&self, fs_id: u64, path: &Path) -> Option<&Mount> {
+        self.mounts_of(fs_id)
+            .filter(|mount| path_within(path, &mount.path).is_some())
+            .max_by_key(|mount| (mount.path.components().count(), mount.id))
+    }
+
+    /// Returns the mount that `mount` is mounted inside, or `None` for the
+    /// root filesystem.
+    fn parent_of(&self, mount: &Mount) -> Option<&Mount> {
+        let fs_id = mount.mount_point.as_ref()?.id().fs_id();
+
+        self.mounts_of(fs_id)
+            .filter(|parent| {
+                parent.id != mount.id && path_within(&mount.path, &parent.path).is_some()
+            })
+            .max_by_key(|parent| (parent.path.components().count(), parent.id))
+    }
+
+    /// Returns where `path`, on the filesystem `fs_id`, is inside that
+    /// filesystem, going by the mount it's reached through.
+    fn root_of(&self, fs_id: u64, path: &Path) -> PathBuf {
+        match self.mount_of(fs_id, path) {
+            Some(mount) => {
+                let mut root = mount.root.clone();
+
+                for name in path_within(path, &mount.path).into_iter().flatten() {
+                    root.push(name);
+                }
+
+                root
+            }
+            None => PathBuf::from("/"),
+        }
+    }
+
+    /// Returns the mount rooted at `root_id` which is mounted at `path`, or
+    /// failing that, the last one made.
+    fn find_mount(&self, root_id: InodeId, path: &Path) -> Option<(InodeId, &Mount)> {
+        let mut mounts: Vec<_> = self
+            .mounts
+            .iter()
+            .filter(|(_, mount)| mount.root_inode.id() == root_id)
+            .collect();
+
+        mounts.sort_by_key(|(_, mount)| mount.id);
+
+        mounts
+            .iter()
+            .find(|(_, mount)| mount.path.components().eq(path.components()))
+            .or(mounts.last())
+            .map(|(id, mount)| (**id, *mount))
+    }
+
+    /// Returns the mount points of everything mounted somewhere inside the
+    /// filesystem `fs_id`, directly or not.
+    ///
+    /// A filesystem that's also mounted outside of `fs_id` is counted, but not
+    /// what's mounted inside it.
+    fn submounts(&self, fs_id: u64) -> Vec<InodeId> {
+        let mut found = Vec::new();
+        let mut parents = alloc::vec![fs_id];
+
+        while let Some(parent) = parents.pop() {
+            for (id, mount) in self.mounts.iter() {
+                if mount
+                    .mount_point
+                    .as_ref()
+                    .is_some_and(|point| point.id().fs_id() == parent)
+                    && !found.contains(id)
+                {
+                    found.push(*id);
+
+                    if self.mounts_of(mount.fs.id()).count() == 1 {
+                        parents.push(mount.fs.id());
+                    }
+                }
+            }
+        }
+
+        found
     }
 
     /// Checks if an inode is a mount point and returns the root inode of the
@@ -104,9 +290,209 @@ impl VfsState {
             .map(|mount| mount.root_inode.clone())
     }
 
+    /// Returns the directory that the filesystem rooted at `root_id` is
+    /// mounted on, if `root_id` is the root of a mounted filesystem. If it's
+    /// the root of more than one mount, the last one made wins.
+    fn get_mount_point(&self, root_id: InodeId) -> Option<Arc<dyn Inode>> {
+        self.mounts
+            .values()
+            .filter(|mount| mount.root_inode.id() == root_id)
+            .max_by_key(|mount| mount.id)
+            .and_then(|mount| mount.mount_point.clone())
+    }
+}
+
+/// The internal state of the VFS.
+///
+/// This struct consolidates the filesystem-wide collections (the list of all
+/// registered filesystem instances and the mount table of every mount
+/// namespace).
+struct VfsState {
+    /// A map from a mount namespace's ID to its mounts.
+    namespaces: BTreeMap<u64, MountTable>,
+    /// A map from a filesystem ID to the corresponding filesystem instance.
+    filesystems: BTreeMap<u64, Arc<dyn Filesystem>>,
+    /// A map from a filesystem ID to its freeze/read-only write state.
+    sb_states: BTreeMap<u64, SbState>,
+    /// The ID of the next peer group of shared mounts.
+    next_peer_group: u64,
+    /// Files made with `O_TMPFILE`, but not `O_EXCL`, that may still be given
+    /// a name.
+    linkable_tmpfiles: BTreeSet<InodeId>,
+    /// Filesystems whose directory entries mustn't be cached, even if they
+    /// allow it, as they may be changed behind the VFS's back.
+    uncached_dentries: BTreeSet<u64>,
+}
+
+impl VfsState {
+    /// Creates a new, empty VfsState.
+    const fn new() -> Self {
+        Self {
+            namespaces: BTreeMap::new(),
+            filesystems: BTreeMap::new(),
+            sb_states: BTreeMap::new(),
+            next_peer_group: 1,
+            linkable_tmpfiles: BTreeSet::new(),
+            uncached_dentries: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the mounts of the namespace `ns`.
+    fn table(&self, ns: u64) -> &MountTable {
+        self.namespaces.get(&ns).unwrap_or(&EMPTY_MOUNT_TABLE)
+    }
+
+    fn table_mut(&mut self, ns: u64) -> &mut MountTable {
+        self.namespaces.entry(ns).or_insert_with(MountTable::new)
+    }
+
+    /// Registers a mount in the namespace `ns`, along with its filesystem if
+    /// that isn't mounted anywhere else yet. The mount is propagated to the
+    /// peers and slaves of the mount it's made inside.
+    fn add_mount(
+        &mut self,
+        ns: u64,
+        mount_point_id: InodeId,
+        mut mount: Mount,
+        read_only: bool,
+    ) -> Result<()> {
+        // Something is mounted on this very inode already, e.g. the root
+        // filesystem on its own root.
+        if self.table(ns).mounts.contains_key(&mount_point_id) {
+            return Err(FsError::Busy.into());
+        }
+
+        self.filesystems
+            .entry(mount.fs.id())
+            .or_insert_with(|| mount.fs.clone());
+        self.sb_states
+            .entry(mount.fs.id())
+            .or_insert_with(|| SbState::new(read_only || mount.fs.read_only()));
+
+        self.propagate_mount(ns, mount_point_id, &mut mount);
+        self.table_mut(ns).mounts.insert(mount_point_id, mount);
+
+        Ok(())
+    }
+
+    /// Removes the mount on `mount_point_id` in the namespace `ns`, returning
+    /// its filesystem if that isn't mounted anywhere else.
+    fn remove_mount(&mut self, ns: u64, mount_point_id: InodeId) -> Option<Arc<dyn Filesystem>> {
+        let fs_id = self.table_mut(ns).mounts.remove(&mount_point_id)?.fs.id();
+
+        self.release_fs(fs_id)
+    }
+
+    /// Forgets the filesystem `fs_id` and returns it, if it's no longer
+    /// mounted in any namespace.
+    fn release_fs(&mut self, fs_id: u64) -> Option<Arc<dyn Filesystem>> {
+        if self.mount_count(fs_id) != 0 {
+            return None;
+        }
+
+        self.sb_states.remove(&fs_id);
+        self.filesystems.remove(&fs_id)
+    }
+
+    /// Returns the first mount of the filesystem `fs_id` in any namespace.
+    fn get_mount(&self, fs_id: u64) -> Option<&Mount> {
+        self.namespaces
+            .values()
+            .filter_map(|table| table.get_mount(fs_id))
+            .min_by_key(|mount| mount.id)
+    }
+
+    /// Returns the number of places the filesystem `fs_id` is mounted, in all
+    /// namespaces.
+    fn mount_count(&self, fs_id: u64) -> usize {
+        self.namespaces
+            .values()
+            .map(|table| table.mounts_of(fs_id).count())
+            .sum()
+    }
+
     fn get_fs(&self, inode_id: InodeId) -> Option<Arc<dyn Filesystem>> {
         self.filesystems.get(&inode_id.fs_id()).cloned()
     }
+
+    fn get_sb_state(&self, inode_id: InodeId) -> Option<SbState> {
+        self.sb_states.get(&inode_id.fs_id()).cloned()
+    }
+}
+
+/// Returns the components of `path` past `base`, if `path` is `base` or lies
+/// beneath it.
+fn path_within<'a>(path: &'a Path, base: &Path) -> Option<impl Iterator<Item = &'a str>> {
+    let depth = base.components().count();
+
+    path.components()
+        .take(depth)
+        .eq(base.components())
+        .then(|| path.components().skip(depth))
+}
+
+/// Returns the owner of inodes created by `task`: its filesystem user and
+/// group IDs, which are the effective ones.
+pub fn file_owner(task: &Task) -> FileOwner {
+    let creds = task.creds.lock_save_irq();
+
+    FileOwner::new(creds.euid(), creds.egid())
+}
+
+/// Returns the ID of a new mount.
+fn next_mount_id() -> u64 {
+    static NEXT_MOUNT_ID: AtomicU64 = AtomicU64::new(0);
+
+    NEXT_MOUNT_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+/// Copies between two inodes through a page-sized buffer. Once anything has
+/// been copied, an error just cuts the copy short.
+async fn copy_range_generic(
+    src: &Arc<dyn Inode>,
+    off_in: u64,
+    dst: &Arc<dyn Inode>,
+    off_out: u64,
+    len: u64,
+) -> Result<usize> {
+    let mut pg = ClaimedPage::alloc_zeroed()?;
+    let buf = pg.as_slice_mut();
+    let mut copied = 0;
+
+    while (copied as u64) < len {
+        let chunk = (len - copied as u64).min(PAGE_SIZE as u64) as usize;
+
+        let read = match src.read_at(off_in + copied as u64, &mut buf[..chunk]).await {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(_) if copied > 0 => break,
+            Err(e) => return Err(e),
+        };
+
+        let mut written = 0;
+
+        while written < read {
+            match dst
+                .write_at(off_out + (copied + written) as u64, &buf[written..read])
+                .await
+            {
+                Ok(n) if n > 0 => written += n,
+                res => {
+                    copied += written;
+
+                    return match res {
+                        _ if copied > 0 => Ok(copied),
+                        Err(e) => Err(e),
+                        Ok(_) => Err(FsError::NoSpace.into()),
+                    };
+                }
+            }
+        }
+
+        copied += read;
+    }
+
+    Ok(copied)
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -114,6 +500,8 @@ pub struct VFS {
     next_fs_id: AtomicU64,
     state: SpinLock<VfsState>,
     root_inode: SpinLock<Option<Arc<dyn Inode>>>,
+    dcache: DentryCache<ArchImpl>,
+    icache: InodeCache<ArchImpl>,
 }
 
 impl VFS {
@@ -122,17 +510,21 @@ impl VFS {
             next_fs_id: AtomicU64::new(FS_ID_START),
             state: SpinLock::new(VfsState::new()),
             root_inode: SpinLock::new(None),
+            dcache: DentryCache::new(DCACHE_CAPACITY),
+            icache: InodeCache::new(ICACHE_CAPACITY),
         }
     }
 
     /// Creates an instance of a filesystem from a registered driver.
     ///
     /// This does not mount the filesystem, but prepares an instance that can
-    /// then be attached to a mount point.
+    /// then be attached to a mount point. `options` are the driver-specific
+    /// mount options.
     async fn create_fs_instance(
         &self,
         driver_name: &str,
         blkdev: Option<Box<dyn BlockDevice>>,
+        options: &str,
     ) -> Result<Arc<dyn Filesystem>> {
         let driver = DM
             .lock_save_irq()
@@ -143,25 +535,51 @@ impl VFS {
 
         let id = self.next_fs_id.fetch_add(1, Ordering::SeqCst);
 
-        driver.construct(id, blkdev).await
+        // Filesystems get their blocks through a cache, which is also where
+        // file readahead lands.
+        let blkdev = blkdev.map(|dev| {
+            Box::new(CachedBlkDev::new(dev, driver_name.to_string())) as Box<dyn BlockDevice>
+        });
+
+        driver.construct_with_options(id, blkdev, options).await
     }
 
-    /// Mounts the root filesystem.
+    /// Mounts the root filesystem, in the initial mount namespace.
     pub async fn mount_root(
         &self,
         driver_name: &str,
         blkdev: Option<Box<dyn BlockDevice>>,
+        read_only: bool,
     ) -> Result<()> {
-        let fs = self.create_fs_instance(driver_name, blkdev).await?;
-        let root_inode = fs.root_inode().await?;
+        let fs = self.create_fs_instance(driver_name, blkdev, "").await?;
+        let mut root_inode = fs.root_inode().await?;
+
+        if fs.cache_inodes() {
+            root_inode = self.icache.insert(root_inode);
+        }
 
         let mount = Mount {
+            id: next_mount_id(),
             fs,
             root_inode: root_inode.clone(),
+            mount_point: None,
+            path: PathBuf::from("/"),
+            root: PathBuf::from("/"),
+            source: driver_name.to_string(),
+            fs_type: driver_name.to_string(),
+            flags: MntFlags::empty(),
+            peer_group: None,
+            master: None,
+            unbindable: false,
         };
 
         // Lock the state to add the new mount and filesystem.
-        self.state.lock_save_irq().add_mount(root_inode.id(), mount);
+        self.state.lock_save_irq().add_mount(
+            init_mnt_ns().id(),
+            root_inode.id(),
+            mount,
+            read_only,
+        )?;
 
         // Set the global root inode.
         *self.root_inode.lock_save_irq() = Some(root_inode);
@@ -169,42 +587,276 @@ impl VFS {
         Ok(())
     }
 
-    /// Mounts a filesystem at a given directory (mount point).
+    /// Mounts a filesystem at a given directory (mount point), found at
+    /// `path`, in the namespace `ns`. `source` is what's being mounted, for
+    /// the mount table, and `options` are handed to the filesystem driver.
+    #[allow(clippy::too_many_arguments)]
     pub async fn mount(
         &self,
+        ns: &MountNamespace,
         mount_point: Arc<dyn Inode>,
+        path: &Path,
+        source: &str,
         driver_name: &str,
-        blkdev: Option<Box<dyn BlockDevice>>,
+        options: &str,
+        read_only: bool,
+        flags: MntFlags,
     ) -> Result<()> {
         if mount_point.getattr().await?.file_type != FileType::Directory {
             return Err(FsError::NotADirectory.into());
         }
 
-        let fs = self.create_fs_instance(driver_name, blkdev).await?;
+        let fs = self.create_fs_instance(driver_name, None, options).await?;
         let mount_point_id = mount_point.id();
-        let root_inode = fs.root_inode().await?;
+        let mut root_inode = fs.root_inode().await?;
 
-        let new_mount = Mount { fs, root_inode };
+        if fs.cache_inodes() {
+            root_inode = self.icache.insert(root_inode);
+        }
+
+        let new_mount = Mount {
+            id: next_mount_id(),
+            fs,
+            root_inode,
+            mount_point: Some(mount_point),
+            path: path.to_owned(),
+            root: PathBuf::from("/"),
+            source: source.to_string(),
+            fs_type: driver_name.to_string(),
+            flags,
+            peer_group: None,
+            master: None,
+            unbindable: false,
+        };
 
         // Lock the state and insert the new mount.
         self.state
             .lock_save_irq()
-            .add_mount(mount_point_id, new_mount);
+            .add_mount(ns.id(), mount_point_id, new_mount, read_only)
+    }
+
+    /// Bind mounts `source`, found at `source_path`, on `mount_point`, found
+    /// at `path`, in the namespace `ns`, making the subtree under `source`
+    /// visible there too.
+    ///
+    /// The bind shares its filesystem with `source`, so it takes on the
+    /// source mount's options, and is a peer of the source mount if that's
+    /// shared. Mounts are attached to the inodes they're mounted on, so
+    /// whatever is mounted inside `source` shows through the bind as well:
+    /// every bind is recursive, as with `MS_REC`.
+    pub async fn bind(
+        &self,
+        ns: &MountNamespace,
+        source: Arc<dyn Inode>,
+        source_path: &Path,
+        mount_point: Arc<dyn Inode>,
+        path: &Path,
+    ) -> Result<()> {
+        let source_is_dir = source.getattr().await?.file_type == FileType::Directory;
+        let target_is_dir = mount_point.getattr().await?.file_type == FileType::Directory;
+
+        if source_is_dir != target_is_dir {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        let mut state = self.state.lock_save_irq();
+        let source_mount = state
+            .table(ns.id())
+            .get_mount(source.id().fs_id())
+            .ok_or(KernelError::InvalidValue)?;
+
+        if source_mount.unbindable {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let root = state
+            .table(ns.id())
+            .root_of(source.id().fs_id(), source_path);
+
+        let new_mount = Mount {
+            id: next_mount_id(),
+            fs: source_mount.fs.clone(),
+            root_inode: source,
+            path: path.to_owned(),
+            root,
+            source: source_mount.source.clone(),
+            fs_type: source_mount.fs_type.clone(),
+            flags: source_mount.flags,
+            mount_point: Some(mount_point.clone()),
+            peer_group: source_mount.peer_group,
+            master: source_mount.master,
+            unbindable: false,
+        };
+
+        state.add_mount(ns.id(), mount_point.id(), new_mount, false)
+    }
+
+    /// Unmounts the mount whose root is `mount_root` from the namespace `ns`.
+    /// Where that's the root of several mounts, as with bind mounts, the one
+    /// at `path` goes.
+    ///
+    /// Unless `detach` is set, this fails with `EBUSY` while the filesystem is
+    /// in use: mounted on, or holding a task's open file, working directory or
+    /// root. With `detach`, the filesystem and everything mounted inside it
+    /// are taken out of the tree at once, and are released once their last
+    /// user is gone.
+    ///
+    /// A filesystem that's mounted elsewhere too stays in use whatever
+    /// happens, so only this one mount of it is removed.
+    pub async fn unmount(
+        &self,
+        ns: &MountNamespace,
+        mount_root: Arc<dyn Inode>,
+        path: &Path,
+        detach: bool,
+    ) -> Result<()> {
+        let (mount_point_id, fs, submounts) = {
+            let state = self.state.lock_save_irq();
+            let table = state.table(ns.id());
+            let (mount_point_id, mount) = table
+                .find_mount(mount_root.id(), path)
+                .ok_or(KernelError::InvalidValue)?;
+
+            // The root filesystem is always in use.
+            if mount.mount_point.is_none() {
+                return Err(FsError::Busy.into());
+            }
+
+            let fs_id = mount.fs.id();
+
+            // Whatever is mounted inside shows through the other mounts of the
+            // filesystem in this namespace, if there are any.
+            let submounts = if table.mounts_of(fs_id).count() > 1 {
+                Vec::new()
+            } else {
+                table.submounts(fs_id)
+            };
+
+            let fs = (state.mount_count(fs_id) == 1).then(|| mount.fs.clone());
+
+            (mount_point_id, fs, submounts)
+        };
+
+        if !detach {
+            if !submounts.is_empty() {
+                return Err(FsError::Busy.into());
+            }
+
+            if let Some(fs) = &fs {
+                if fs_in_use(fs.id()) {
+                    return Err(FsError::Busy.into());
+                }
+
+                fs.sync().await?;
+            }
+        }
+
+        let mut removed = Vec::new();
+
+        {
+            let mut state = self.state.lock_save_irq();
+
+            state.propagate_unmount(ns.id(), mount_point_id);
+
+            for id in core::iter::once(mount_point_id).chain(submounts) {
+                removed.extend(state.remove_mount(ns.id(), id));
+            }
+        }
+
+        for removed_fs in removed {
+            self.dcache.invalidate_fs(removed_fs.id());
+            self.icache.remove_fs(removed_fs.id());
+
+            // Anything still open carries on writing to a detached
+            // filesystem, but what's there now should reach the disk. The same
+            // goes for one whose last mounts were in other namespaces, and
+            // went with this one.
+            if detach || fs.is_none() {
+                let _ = removed_fs.sync().await;
+            }
+        }
 
         Ok(())
     }
 
-    #[expect(unused)]
-    pub async fn unmount(&self, mount_point: Arc<dyn Inode>) -> Result<()> {
-        let mount_point_id = mount_point.id();
+    /// Changes the propagation type of the mount whose root is `mount_root`
+    /// in the namespace `ns`, and with `recursive`, of everything mounted
+    /// inside it.
+    pub fn set_propagation(
+        &self,
+        ns: &MountNamespace,
+        mount_root: Arc<dyn Inode>,
+        path: &Path,
+        propagation: Propagation,
+        recursive: bool,
+    ) -> Result<()> {
+        let mut state = self.state.lock_save_irq();
+        let table = state.table(ns.id());
+        let (mount_point_id, mount) = table
+            .find_mount(mount_root.id(), path)
+            .ok_or(KernelError::InvalidValue)?;
+
+        let mut mount_points = alloc::vec![mount_point_id];
+
+        if recursive {
+            mount_points.extend(table.submounts(mount.fs.id()));
+        }
 
-        // Lock the state and remove the mount.
+        for mount_point_id in mount_points {
+            state.change_propagation(ns.id(), mount_point_id, propagation);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the options of the mount that `inode_id` belongs to.
+    pub fn mount_flags(&self, inode_id: InodeId) -> MntFlags {
+        self.state
+            .lock_save_irq()
+            .get_mount(inode_id.fs_id())
+            .map_or(MntFlags::empty(), |mount| mount.flags)
+    }
+
+    /// Returns the ID of the mount that `inode_id` is reached through in the
+    /// namespace `ns`. Bind mounts of one filesystem aren't told apart: the
+    /// first mount made is taken.
+    pub fn mount_id(&self, ns: &MountNamespace, inode_id: InodeId) -> Option<u64> {
         self.state
             .lock_save_irq()
-            .remove_mount(&mount_point_id)
-            .ok_or(FsError::NotFound)?;
+            .table(ns.id())
+            .get_mount(inode_id.fs_id())
+            .map(|mount| mount.id)
+    }
 
-        Ok(())
+    /// Returns the mount table of the namespace `ns`, in the order mounts
+    /// were made.
+    pub fn mounts(&self, ns: &MountNamespace) -> Vec<MountInfo> {
+        let state = self.state.lock_save_irq();
+        let table = state.table(ns.id());
+        let mut mounts: Vec<_> = table.mounts.values().collect();
+
+        mounts.sort_by_key(|mount| mount.id);
+
+        mounts
+            .into_iter()
+            .map(|mount| MountInfo {
+                id: mount.id,
+                parent_id: table.parent_of(mount).map_or(mount.id, |parent| parent.id),
+                dev: mount.fs.id(),
+                root: mount.root.clone(),
+                source: mount.source.clone(),
+                path: mount.path.clone(),
+                fs_type: mount.fs_type.clone(),
+                read_only: state
+                    .sb_states
+                    .get(&mount.fs.id())
+                    .is_some_and(|sb| sb.is_read_only()),
+                flags: mount.flags,
+                peer_group: mount.peer_group,
+                master: mount.master,
+                unbindable: mount.unbindable,
+            })
+            .collect()
     }
 
     pub async fn get_fs(&self, inode: Arc<dyn Inode>) -> Result<Arc<dyn Filesystem>> {
@@ -214,123 +866,199 @@ impl VFS {
             .ok_or(KernelError::from(FsError::NoDevice))
     }
 
-    /// Resolves a path string to an Inode, starting from a given root for
-    /// relative paths.
-    pub async fn resolve_path(
-        &self,
-        path: &Path,
-        root: Arc<dyn Inode>,
-        task: &Arc<Task>,
-    ) -> Result<Arc<dyn Inode>> {
-        let root = if path.is_absolute() {
-            task.root.lock_save_irq().0.clone() // use the task's root inode, in case a custom chroot was set
-        } else {
-            root
-        };
+    fn get_sb_state(&self, inode_id: InodeId) -> Result<SbState> {
+        self.state
+            .lock_save_irq()
+            .get_sb_state(inode_id)
+            .ok_or(KernelError::from(FsError::NoDevice))
+    }
 
-        self.resolve_path_internal(path, root, true).await
+    /// Registers an in-flight modification of the filesystem containing
+    /// `inode_id`.
+    ///
+    /// Sleeps whilst the filesystem is frozen and fails with
+    /// `FsError::ReadOnly` if it is mounted read-only. Inodes which don't
+    /// belong to a mounted filesystem are never blocked.
+    pub async fn begin_write(&self, inode_id: InodeId) -> Result<WriteGuard> {
+        let state = self.state.lock_save_irq().get_sb_state(inode_id);
+
+        match state {
+            Some(state) => state.begin_write().await,
+            None => Ok(WriteGuard::untracked()),
+        }
     }
 
-    /// Resolves a path string to an Inode, starting from a given root for
-    /// relative paths, without following the final symbolic link.
-    pub async fn resolve_path_nofollow(
-        &self,
-        path: &Path,
-        root: Arc<dyn Inode>,
-        task: &Arc<Task>,
-    ) -> Result<Arc<dyn Inode>> {
-        let root = if path.is_absolute() {
-            task.root.lock_save_irq().0.clone()
-        } else {
-            root
-        };
+    /// Takes write access to the filesystem containing `inode_id` for a file
+    /// being opened for writing.
+    ///
+    /// Fails with `FsError::ReadOnly` if it is mounted read-only. Inodes which
+    /// don't belong to a mounted filesystem are never refused.
+    fn get_write_access(&self, inode_id: InodeId) -> Result<WriteAccess> {
+        let state = self.state.lock_save_irq().get_sb_state(inode_id);
+
+        match state {
+            Some(state) => state.get_write_access(),
+            None => Ok(WriteAccess::untracked()),
+        }
+    }
 
-        self.resolve_path_internal(path, root, false).await
+    /// Returns `true` if the filesystem containing `inode_id` is mounted
+    /// read-only.
+    pub fn is_read_only(&self, inode_id: InodeId) -> bool {
+        self.state
+            .lock_save_irq()
+            .get_sb_state(inode_id)
+            .is_some_and(|state| state.is_read_only())
     }
 
-    /// Resolves a path string to an Inode, starting from a given root for
-    /// relative paths, and using the filesystem root inode for absolute paths.
-    pub async fn resolve_path_absolute(
-        &self,
-        path: &Path,
-        root: Arc<dyn Inode>,
-    ) -> Result<Arc<dyn Inode>> {
-        let root = if path.is_absolute() {
-            self.root_inode
-                .lock_save_irq()
-                .as_ref()
-                .cloned()
-                .ok_or(FsError::NotFound)?
-        } else {
-            root
-        };
+    /// Freezes the filesystem containing `inode`, quiescing all writers and
+    /// flushing dirty state to the underlying device.
+    pub async fn freeze(&self, inode: Arc<dyn Inode>) -> Result<()> {
+        let sb_state = self.get_sb_state(inode.id())?;
+        let fs = self.get_fs(inode).await?;
+
+        sb_state.freeze().await?;
 
-        self.resolve_path_internal(path, root, true).await
+        if let Err(e) = fs.sync().await {
+            // Don't leave the filesystem wedged if we couldn't flush it.
+            let _ = sb_state.thaw();
+            return Err(e);
+        }
+
+        Ok(())
     }
 
-    async fn resolve_path_internal(
+    /// Thaws a filesystem previously frozen with [`VFS::freeze`].
+    pub fn thaw(&self, inode: Arc<dyn Inode>) -> Result<()> {
+        self.get_sb_state(inode.id())?.thaw()
+    }
+
+    /// Changes the read-only state of the filesystem containing `inode`.
+    ///
+    /// When remounting read-only, in-flight writers are drained and the
+    /// filesystem is flushed before the new mount flags take effect; if the
+    /// flush fails, the filesystem is left as it was. This fails with
+    /// `KernelError::InUse` whilst files are open for writing on it.
+    pub async fn remount(
         &self,
-        path: &Path,
-        root: Arc<dyn Inode>,
-        follow_last_sym: bool,
-    ) -> Result<Arc<dyn Inode>> {
-        let mut current_inode = root;
-        let mut symlink_count = 0;
-
-        let mut components: Vec<_> = path.components().map(|s| s.to_owned()).collect();
-        components.reverse();
-
-        while let Some(component) = components.pop() {
-            // Before looking up the component, check if the current inode is a
-            // mount point. If so, traverse into the mounted filesystem's root.
-            if let Some(mount_root) = self
-                .state
-                .lock_save_irq()
-                .get_mount_root(&current_inode.id())
-            {
-                current_inode = mount_root;
-            }
+        inode: Arc<dyn Inode>,
+        read_only: bool,
+        flags: MntFlags,
+    ) -> Result<()> {
+        let sb_state = self.get_sb_state(inode.id())?;
+        let fs = self.get_fs(inode.clone()).await?;
 
-            let next_inode = current_inode.lookup(&component).await?;
+        if !read_only && fs.read_only() {
+            return Err(FsError::ReadOnly.into());
+        }
 
-            let attr = next_inode.getattr().await?;
+        let was_read_only = sb_state.is_read_only();
 
-            if attr.file_type == FileType::Symlink && (follow_last_sym || !components.is_empty()) {
-                symlink_count += 1;
-                if symlink_count > MAX_SYMLINK {
-                    return Err(FsError::Loop.into()); // prevent infinite looping
-                }
+        sb_state.set_read_only(read_only).await?;
 
-                let target = next_inode.readlink().await?;
-                let mut new_components: Vec<_> =
-                    target.components().map(|s| s.to_owned()).collect();
-                new_components.reverse();
-                for comp in new_components {
-                    components.push(comp);
-                }
+        if read_only && let Err(e) = fs.sync().await {
+            sb_state.set_read_only(was_read_only).await?;
+            return Err(e);
+        }
 
-                if target.is_absolute() {
-                    // if absolute, restart from root
-                    current_inode = self.root_inode.lock_save_irq().as_ref().unwrap().clone();
-                }
+        // Options belong to the filesystem, so its bind mounts, and its mounts
+        // in other namespaces, change too.
+        for mount in self
+            .state
+            .lock_save_irq()
+            .namespaces
+            .values_mut()
+            .flat_map(|table| table.mounts.values_mut())
+            .filter(|mount| mount.fs.id() == inode.id().fs_id())
+        {
+            mount.flags = flags;
+        }
 
-                continue;
-            }
+        Ok(())
+    }
+
+    /// Looks `name` up in the directory `dir`, going through the dentry cache
+    /// if `dir`'s filesystem allows it.
+    async fn lookup(&self, dir: &Arc<dyn Inode>, name: &str) -> Result<Arc<dyn Inode>> {
+        let dir_id = dir.id();
+
+        let cacheable = name != "." && name != ".." && {
+            let state = self.state.lock_save_irq();
 
-            // Delegate the lookup to the underlying filesystem.
-            current_inode = next_inode;
+            !state.uncached_dentries.contains(&dir_id.fs_id())
+                && state.get_fs(dir_id).is_some_and(|fs| fs.cache_dentries())
+        };
+
+        if !cacheable {
+            return dir.lookup(name).await.map(|inode| self.cache_inode(inode));
+        }
+
+        match self.dcache.lookup(dir_id, name) {
+            CachedLookup::Found(inode) => return Ok(inode),
+            CachedLookup::Negative => return Err(FsError::NotFound.into()),
+            CachedLookup::Miss => {}
         }
 
-        // After the final lookup, check if the destination is itself a mount point.
-        if let Some(mount_root) = self
+        let generation = self.dcache.generation();
+
+        match dir.lookup(name).await {
+            Ok(inode) => {
+                let inode = self.cache_inode(inode);
+                self.dcache
+                    .insert(dir_id, name, Some(inode.clone()), generation);
+                Ok(inode)
+            }
+            Err(KernelError::Fs(FsError::NotFound)) => {
+                self.dcache.insert(dir_id, name, None, generation);
+                Err(FsError::NotFound.into())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Hands `inode` to the inode cache if its filesystem keeps one, returning
+    /// the object to use in its place.
+    fn cache_inode(&self, inode: Arc<dyn Inode>) -> Arc<dyn Inode> {
+        let cacheable = self
             .state
             .lock_save_irq()
-            .get_mount_root(&current_inode.id())
-        {
-            current_inode = mount_root;
+            .get_fs(inode.id())
+            .is_some_and(|fs| fs.cache_inodes());
+
+        if !cacheable {
+            return inode;
+        }
+
+        if low_on_memory() {
+            self.icache.shrink(ICACHE_SHRINK_BATCH);
         }
 
-        Ok(current_inode)
+        self.icache.insert(inode)
+    }
+
+    /// Stops caching the directory entries of the filesystem `fs_id`, whose
+    /// directories are going to be changed without going through the VFS.
+    /// An overlay does that to its upper layer.
+    pub fn stop_caching_dentries(&self, fs_id: u64) {
+        self.state.lock_save_irq().uncached_dentries.insert(fs_id);
+        self.dcache.invalidate_fs(fs_id);
+    }
+
+    /// Returns the current dentry cache counters.
+    pub fn dcache_stats(&self) -> DentryStats {
+        self.dcache.stats()
+    }
+
+    /// Returns the current inode cache counters.
+    pub fn icache_stats(&self) -> InodeStats {
+        self.icache.stats()
+    }
+
+    /// Drops every cached directory entry and every inode that isn't in use,
+    /// as asked for through `/proc/sys/vm/drop_caches`.
+    pub fn drop_caches(&self) {
+        self.dcache.clear();
+        self.icache.drop_unused();
     }
 
     /// Returns a clone of the root inode.
@@ -342,12 +1070,27 @@ impl VFS {
         &self,
         path: &Path,
         flags: OpenFlags,
+        resolve: ResolveFlags,
         root: Arc<dyn Inode>,
         mode: FilePermissions,
         task: &Arc<Task>,
     ) -> Result<Arc<OpenFile>> {
+        if flags.contains(OpenFlags::O_TMPFILE) {
+            return self
+                .open_tmpfile(path, flags, resolve, root, mode, task)
+                .await;
+        }
+
         // Attempt to resolve the full path first.
-        let resolve_result = self.resolve_path(path, root.clone(), task).await;
+        let resolve_result = self
+            .resolve_path_restricted(
+                path,
+                root.clone(),
+                task,
+                !flags.contains(OpenFlags::O_NOFOLLOW),
+                resolve,
+            )
+            .await;
 
         let target_inode = match resolve_result {
             // The file/directory exists.
@@ -370,7 +1113,8 @@ impl VFS {
                     // (cwd or dirfd) as the parent directory.
                     let file_name = path.file_name().ok_or(FsError::InvalidInput)?;
                     let parent_inode = if let Some(parent_path) = path.parent() {
-                        self.resolve_path(parent_path, root.clone(), task).await?
+                        self.resolve_path_restricted(parent_path, root.clone(), task, true, resolve)
+                            .await?
                     } else {
                         root.clone()
                     };
@@ -381,11 +1125,19 @@ impl VFS {
                         return Err(FsError::NotADirectory.into());
                     }
 
+                    let _guard = self.begin_write(parent_inode.id()).await?;
                     let target_inode = parent_inode
-                        .create(file_name, FileType::File, mode, Some(date()))
+                        .create(
+                            file_name,
+                            FileType::File,
+                            mode,
+                            file_owner(task),
+                            Some(date()),
+                        )
                         .await?;
+                    self.dcache.invalidate(parent_inode.id(), file_name);
                     notify_create(parent_inode.id(), file_name, false).await;
-                    target_inode
+                    self.cache_inode(target_inode)
                 } else {
                     // O_CREAT was not specified, so NotFound is the correct error.
                     return Err(FsError::NotFound.into());
@@ -403,19 +1155,36 @@ impl VFS {
             return Err(FsError::NotADirectory.into());
         }
 
+        // The file is only named, not opened, so none of what follows applies.
+        if flags.contains(OpenFlags::O_PATH) {
+            let mut open_file = OpenFile::new(Box::new(PathFile), flags);
+            open_file.update(target_inode, path.to_owned());
+
+            return Ok(Arc::new(open_file));
+        }
+
         if attr.file_type == FileType::Directory
             && (flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR))
         {
             return Err(FsError::IsADirectory.into());
         }
 
+        let write_access = if attr.file_type == FileType::File
+            && (flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR))
+        {
+            Some(self.get_write_access(target_inode.id())?)
+        } else {
+            None
+        };
+
+        fanotify::check_open(&target_inode, attr.file_type, path).await?;
+
         if flags.contains(OpenFlags::O_TRUNC)
             && attr.file_type == FileType::File
             && (flags.contains(OpenFlags::O_WRONLY) || flags.contains(OpenFlags::O_RDWR))
         {
             // TODO: Check for write permissions on the inode itself.
-            target_inode.truncate(0).await?;
-            notify_modify(target_inode.id()).await;
+            self.truncate(&target_inode, 0).await?;
         }
 
         match attr.file_type {
@@ -424,6 +1193,10 @@ impl VFS {
                     OpenFile::new(Box::new(RegFile::new(target_inode.clone())), flags);
                 open_file.update(target_inode, path.to_owned());
 
+                if let Some(write_access) = write_access {
+                    open_file.hold_write_access(write_access);
+                }
+
                 Ok(Arc::new(open_file))
             }
             FileType::Directory => {
@@ -433,12 +1206,18 @@ impl VFS {
 
                 Ok(Arc::new(open_file))
             }
-            FileType::Symlink => unimplemented!(), // this is implemented at resolve_path_internal
-            FileType::BlockDevice(_) => todo!(),
+            // Only reachable with O_NOFOLLOW; symlinks are followed otherwise.
+            FileType::Symlink => Err(FsError::Loop.into()),
+            FileType::BlockDevice(blk_dev_descriptor) => {
+                let mut open_file = blk::open_block_device(blk_dev_descriptor, flags)?;
+                open_file.update(target_inode, path.to_owned());
+
+                Ok(Arc::new(open_file))
+            }
             FileType::CharDevice(char_dev_descriptor) => {
                 let char_driver = DM
                     .lock_save_irq()
-                    .find_char_driver(char_dev_descriptor.major)
+                    .find_char_driver(char_dev_descriptor.major, char_dev_descriptor.minor)
                     .ok_or(FsError::NoDevice)?;
 
                 let mut open_file = char_driver
@@ -452,11 +1231,60 @@ impl VFS {
 
                 Ok(open_file)
             }
-            FileType::Fifo => todo!(),
-            FileType::Socket => todo!(),
+            FileType::Fifo => {
+                let mut open_file = pipe::open_fifo(&target_inode, flags).await?;
+                open_file.update(target_inode, path.to_owned());
+
+                Ok(Arc::new(open_file))
+            }
+            // Sockets are reached with connect(), not open().
+            FileType::Socket => Err(FsError::NoDeviceOrAddress.into()),
         }
     }
 
+    /// Opens a new regular file with no name on the filesystem of the
+    /// directory at `path`, for `O_TMPFILE`. Unless `O_EXCL` is given, it can
+    /// be linked in somewhere later.
+    async fn open_tmpfile(
+        &self,
+        path: &Path,
+        flags: OpenFlags,
+        resolve: ResolveFlags,
+        root: Arc<dyn Inode>,
+        mode: FilePermissions,
+        task: &Arc<Task>,
+    ) -> Result<Arc<OpenFile>> {
+        let dir = self
+            .resolve_path_restricted(path, root, task, true, resolve)
+            .await?;
+
+        if dir.getattr().await?.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        let write_access = self.get_write_access(dir.id())?;
+
+        let inode = {
+            let _guard = self.begin_write(dir.id()).await?;
+            self.cache_inode(dir.tmpfile(mode, file_owner(task), Some(date())).await?)
+        };
+
+        if !flags.contains(OpenFlags::O_EXCL) {
+            self.state
+                .lock_save_irq()
+                .linkable_tmpfiles
+                .insert(inode.id());
+        }
+
+        // Named the way Linux shows such files in /proc/<pid>/fd.
+        let name = format!("#{}", inode.id().inode_id());
+        let mut open_file = OpenFile::new(Box::new(RegFile::new(inode.clone())), flags);
+        open_file.update(inode, path.join(Path::new(&name)));
+        open_file.hold_write_access(write_access);
+
+        Ok(Arc::new(open_file))
+    }
+
     pub async fn mkdir(
         &self,
         path: &Path,
@@ -464,15 +1292,29 @@ impl VFS {
         mode: FilePermissions,
         task: &Arc<Task>,
     ) -> Result<()> {
-        // Try to resolve the target directory first.
+        self.mknod(path, root, FileType::Directory, mode, task)
+            .await
+    }
+
+    /// Creates a new inode of type `file_type` at `path`, which mustn't exist
+    /// already.
+    pub async fn mknod(
+        &self,
+        path: &Path,
+        root: Arc<dyn Inode>,
+        file_type: FileType,
+        mode: FilePermissions,
+        task: &Arc<Task>,
+    ) -> Result<()> {
+        // Try to resolve the target path first.
         match self.resolve_path(path, root.clone(), task).await {
             // The path already exists, this is an error.
             Ok(_) => Err(FsError::AlreadyExists.into()),
 
             // The path does not exist, we need to create it.
             Err(KernelError::Fs(FsError::NotFound)) => {
-                // Determine the new directory name.
-                let dir_name = path.file_name().ok_or(FsError::InvalidInput)?;
+                // Determine the new inode's name.
+                let name = path.file_name().ok_or(FsError::InvalidInput)?;
 
                 // Resolve the parent directory.  If the path has no parent
                 // component (e.g., \"foo\"), treat the provided `root`
@@ -489,10 +1331,12 @@ impl VFS {
                 }
 
                 // Delegate the creation to the filesystem-specific inode.
+                let _guard = self.begin_write(parent_inode.id()).await?;
                 parent_inode
-                    .create(dir_name, FileType::Directory, mode, Some(date()))
+                    .create(name, file_type, mode, file_owner(task), Some(date()))
                     .await?;
-                notify_create(parent_inode.id(), dir_name, true).await;
+                self.dcache.invalidate(parent_inode.id(), name);
+                notify_create(parent_inode.id(), name, file_type == FileType::Directory).await;
 
                 Ok(())
             }
@@ -554,8 +1398,16 @@ impl VFS {
         // Extract the final component (name) and perform the unlink on the parent.
         let name = path.file_name().ok_or(FsError::InvalidInput)?;
 
+        let _guard = self.begin_write(parent_inode.id()).await?;
         parent_inode.unlink(name).await?;
         let is_dir = attr.file_type == FileType::Directory;
+        self.dcache.invalidate(parent_inode.id(), name);
+        // The filesystem may have updated the inode's link count behind the
+        // cached object's back.
+        self.icache.remove(target_inode.id());
+        if is_dir {
+            self.dcache.invalidate_dir(target_inode.id());
+        }
         notify_delete(parent_inode.id(), name, is_dir).await;
         notify_delete_self(target_inode.id(), is_dir).await;
 
@@ -568,12 +1420,37 @@ impl VFS {
         new_parent: Arc<dyn Inode>,
         name: &str,
     ) -> Result<()> {
-        // just delegate to inode only, all handling is done at the syscall level
+        // A file with no links left can only be given a name if it was made
+        // with `O_TMPFILE` and not `O_EXCL`.
+        let unlinked = target.getattr().await?.nlinks == 0;
+
+        if unlinked
+            && !self
+                .state
+                .lock_save_irq()
+                .linkable_tmpfiles
+                .contains(&target.id())
+        {
+            return Err(FsError::NotFound.into());
+        }
+
+        // Otherwise delegate to the inode, all handling is done at the syscall
+        // level.
+        let _guard = self.begin_write(new_parent.id()).await?;
+        let id = target.id();
         new_parent.link(name, target).await?;
+
+        if unlinked {
+            self.state.lock_save_irq().linkable_tmpfiles.remove(&id);
+        }
+
+        self.dcache.invalidate(new_parent.id(), name);
         notify_create(new_parent.id(), name, false).await;
         Ok(())
     }
 
+    /// Creates a symbolic link at `link` pointing to `target`. The target is
+    /// stored as given, and isn't resolved until the link is followed.
     pub async fn symlink(
         &self,
         target: &Path,
@@ -581,7 +1458,13 @@ impl VFS {
         root: Arc<dyn Inode>,
         task: &Arc<Task>,
     ) -> Result<()> {
-        match self.resolve_path(link, root.clone(), task).await {
+        if target.as_str().is_empty() {
+            return Err(FsError::NotFound.into());
+        }
+
+        // Whatever is already at `link` is in the way, even a dangling or
+        // looping symlink.
+        match self.resolve_path_nofollow(link, root.clone(), task).await {
             Ok(_) => Err(FsError::AlreadyExists.into()),
             Err(KernelError::Fs(FsError::NotFound)) => {
                 let name = link.file_name().ok_or(FsError::InvalidInput)?;
@@ -597,7 +1480,9 @@ impl VFS {
                     return Err(FsError::NotADirectory.into());
                 }
 
-                parent_inode.symlink(name, target).await?;
+                let _guard = self.begin_write(parent_inode.id()).await?;
+                parent_inode.symlink(name, target, file_owner(task)).await?;
+                self.dcache.invalidate(parent_inode.id(), name);
                 notify_create(parent_inode.id(), name, false).await;
                 Ok(())
             }
@@ -605,6 +1490,9 @@ impl VFS {
         }
     }
 
+    /// Moves `old_name` in `old_parent_inode` to `new_name` in
+    /// `new_parent_inode`, replacing whatever is there unless `no_replace` is
+    /// set. Both directories must be on the same filesystem.
     pub async fn rename(
         &self,
         old_parent_inode: Arc<dyn Inode>,
@@ -613,12 +1501,56 @@ impl VFS {
         new_name: &str,
         no_replace: bool,
     ) -> Result<()> {
-        let target_inode = old_parent_inode.lookup(old_name).await?;
+        if old_parent_inode.id().fs_id() != new_parent_inode.id().fs_id() {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        let target_inode = self.lookup(&old_parent_inode, old_name).await?;
         let target_attr = target_inode.getattr().await?;
+        let replaced = match self.lookup(&new_parent_inode, new_name).await {
+            Ok(inode) => Some(inode),
+            Err(KernelError::Fs(FsError::NotFound)) => None,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(replaced) = replaced.as_ref() {
+            if no_replace {
+                return Err(FsError::AlreadyExists.into());
+            }
 
+            // Two links to the same file; there's nothing to do.
+            if replaced.id() == target_inode.id() {
+                return Ok(());
+            }
+
+            let is_dir = target_attr.file_type == FileType::Directory;
+            match (
+                is_dir,
+                replaced.getattr().await?.file_type == FileType::Directory,
+            ) {
+                (true, false) => return Err(FsError::NotADirectory.into()),
+                (false, true) => return Err(FsError::IsADirectory.into()),
+                _ => {}
+            }
+        }
+
+        // A directory can't be moved inside itself.
+        if target_attr.file_type == FileType::Directory
+            && self.is_subdir(&new_parent_inode, target_inode.id()).await?
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let _guard = self.begin_write(new_parent_inode.id()).await?;
         new_parent_inode
             .rename_from(old_parent_inode.clone(), old_name, new_name, no_replace)
             .await?;
+        self.dcache.invalidate(old_parent_inode.id(), old_name);
+        self.dcache.invalidate(new_parent_inode.id(), new_name);
+        self.icache.remove(target_inode.id());
+        if let Some(replaced) = replaced {
+            self.icache.remove(replaced.id());
+        }
 
         notify_move(
             old_parent_inode.id(),
@@ -633,6 +1565,9 @@ impl VFS {
         Ok(())
     }
 
+    /// Swaps `old_name` in `old_parent_inode` with `new_name` in
+    /// `new_parent_inode`, both of which must exist. Filesystems that can't
+    /// do this in one step refuse it with `EINVAL`.
     pub async fn exchange(
         &self,
         old_parent_inode: Arc<dyn Inode>,
@@ -640,14 +1575,112 @@ impl VFS {
         new_parent_inode: Arc<dyn Inode>,
         new_name: &str,
     ) -> Result<()> {
+        if old_parent_inode.id().fs_id() != new_parent_inode.id().fs_id() {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        let old_inode = self.lookup(&old_parent_inode, old_name).await?;
+        let new_inode = self.lookup(&new_parent_inode, new_name).await?;
+
+        if old_inode.id() == new_inode.id() {
+            return Ok(());
+        }
+
+        let old_is_dir = old_inode.getattr().await?.file_type == FileType::Directory;
+        let new_is_dir = new_inode.getattr().await?.file_type == FileType::Directory;
+
+        // Neither directory may end up inside itself.
+        if (old_is_dir && self.is_subdir(&new_parent_inode, old_inode.id()).await?)
+            || (new_is_dir && self.is_subdir(&old_parent_inode, new_inode.id()).await?)
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let _guard = self.begin_write(old_parent_inode.id()).await?;
         old_parent_inode
-            .exchange(old_name, new_parent_inode, new_name)
+            .exchange(old_name, new_parent_inode.clone(), new_name)
             .await
+            .map_err(|e| match e {
+                KernelError::NotSupported => KernelError::InvalidValue,
+                e => e,
+            })?;
+        self.dcache.invalidate(old_parent_inode.id(), old_name);
+        self.dcache.invalidate(new_parent_inode.id(), new_name);
+        self.icache.remove(old_inode.id());
+        self.icache.remove(new_inode.id());
+
+        notify_move(
+            old_parent_inode.id(),
+            old_name,
+            new_parent_inode.id(),
+            new_name,
+            old_inode.id(),
+            old_is_dir,
+        )
+        .await;
+        notify_move(
+            new_parent_inode.id(),
+            new_name,
+            old_parent_inode.id(),
+            old_name,
+            new_inode.id(),
+            new_is_dir,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    /// Sets the size of the regular file `inode` to `size`, dropping its
+    /// cached pages and unmapping whatever lies beyond the new end. A file
+    /// that grows reads as zeroes up to its new size.
+    pub async fn truncate(&self, inode: &Arc<dyn Inode>, size: u64) -> Result<()> {
+        let _guard = self.begin_write(inode.id()).await?;
+        inode.truncate(size).await?;
+        page_cache::invalidate(inode.id());
+        unmap_file_from(inode.id(), size)?;
+        notify_modify(inode.id()).await;
+        Ok(())
+    }
+
+    /// Copies up to `len` bytes from `off_in` in `src` to `off_out` in `dst`,
+    /// stopping at the end of `src`. The filesystem gets the first go; if it
+    /// can't do the copy itself, the data is read and written a page at a
+    /// time.
+    pub async fn copy_file_range(
+        &self,
+        src: &Arc<dyn Inode>,
+        off_in: u64,
+        dst: &Arc<dyn Inode>,
+        off_out: u64,
+        len: u64,
+    ) -> Result<usize> {
+        fanotify::check_access(src).await?;
+
+        let _guard = self.begin_write(dst.id()).await?;
+
+        let copied = match src.copy_range(off_in, dst.as_ref(), off_out, len).await {
+            Err(KernelError::NotSupported) => {
+                copy_range_generic(src, off_in, dst, off_out, len).await
+            }
+            res => res,
+        }?;
+
+        if copied > 0 {
+            page_cache::invalidate(dst.id());
+            notify_modify(dst.id()).await;
+            fanotify::notify_access(src).await;
+            fanotify::notify_modify(dst).await;
+        }
+
+        Ok(copied)
     }
 
-    pub fn is_mount_root(&self, id: InodeId) -> bool {
+    /// Returns `true` if `id` is the root of a mount in the namespace `ns`.
+    pub fn is_mount_root(&self, ns: &MountNamespace, id: InodeId) -> bool {
         self.state
             .lock_save_irq()
+            .table(ns.id())
             .mounts
             .values()
             .any(|mount| mount.root_inode.id() == id)
@@ -656,6 +1689,25 @@ impl VFS {
 
 pub static VFS: VFS = VFS::new();
 
+/// Returns `true` if any task has an open file, working directory or root in
+/// the filesystem `fs_id`.
+fn fs_in_use(fs_id: u64) -> bool {
+    let tasks: Vec<_> = TASK_LIST
+        .lock_save_irq()
+        .values()
+        .filter_map(|task| task.upgrade())
+        .collect();
+
+    tasks.iter().any(|task| {
+        task.cwd.lock_save_irq().0.id().fs_id() == fs_id
+            || task.root.lock_save_irq().0.id().fs_id() == fs_id
+            || task.fd_table.lock_save_irq().files().any(|file| {
+                file.inode()
+                    .is_some_and(|inode| inode.id().fs_id() == fs_id)
+            })
+    })
+}
+
 impl VFS {
     /// Flushes all mounted filesystems and their underlying block devices.
     /// Any individual error is logged and ignored so that a single faulty
@@ -0,0 +1,501 @@
+//! Path resolution.
+//!
+//! Every path the kernel is handed is turned into an inode here, one component
+//! at a time:
+//!
+//! - Symbolic links are followed, up to [`MAX_SYMLINK`] of them per walk, after
+//!   which the walk fails with `ELOOP`. Absolute link targets start again from
+//!   the task's root, not the global one.
+//! - A link in the final component is only followed with
+//!   [`LookupFlags::FOLLOW`], which `O_NOFOLLOW` and `AT_SYMLINK_NOFOLLOW`
+//!   clear.
+//! - A trailing slash means the path has to name a directory, so a final
+//!   symlink is followed regardless, and anything else fails with `ENOTDIR`.
+//! - `..` never climbs above the task's root, and climbs out of a mounted
+//!   filesystem through the directory it is mounted on.
+//! - Mount points are crossed as the task's mount namespace has them.
+//!
+//! `openat2()` can restrict the walk further with [`ResolveFlags`], e.g. to
+//! keep it from leaving the directory it starts in.
+
+use super::VFS;
+use super::mnt_ns::{MountNamespace, init_mnt_ns};
+use crate::process::Task;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use libkernel::{
+    error::{FsError, Result},
+    fs::{FileType, Inode, InodeId, path::Path, pathbuf::PathBuf},
+};
+
+/// The most symbolic links followed while resolving a single path, as on
+/// Linux.
+const MAX_SYMLINK: u32 = 40;
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct LookupFlags: u32 {
+        /// Follow a symbolic link in the final component.
+        const FOLLOW = 1 << 0;
+        /// The final component must be a directory.
+        const DIRECTORY = 1 << 1;
+        /// Fail with `ELOOP` rather than follow any symbolic link.
+        const NO_SYMLINKS = 1 << 2;
+        /// Fail with `EXDEV` rather than leave the starting filesystem.
+        const NO_XDEV = 1 << 3;
+        /// Fail with `EXDEV` rather than leave the root, or start from it.
+        const BENEATH = 1 << 4;
+        /// Fail with `ELOOP` rather than follow a magic link.
+        const NO_MAGICLINKS = 1 << 5;
+    }
+}
+
+bitflags::bitflags! {
+    /// The `resolve` flags of `openat2()`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct ResolveFlags: u64 {
+        /// Don't cross a mount point, in either direction.
+        const RESOLVE_NO_XDEV = 0x01;
+        /// Don't follow magic links, such as those in `/proc/<pid>/fd`.
+        const RESOLVE_NO_MAGICLINKS = 0x02;
+        /// Don't follow any symbolic link.
+        const RESOLVE_NO_SYMLINKS = 0x04;
+        /// Don't leave the starting directory, through `..`, an absolute path
+        /// or a symlink.
+        const RESOLVE_BENEATH = 0x08;
+        /// Resolve as though the starting directory were the root.
+        const RESOLVE_IN_ROOT = 0x10;
+        /// Only resolve from the caches, without blocking.
+        const RESOLVE_CACHED = 0x20;
+    }
+}
+
+impl VFS {
+    /// Resolves a path string to an Inode, starting from a given root for
+    /// relative paths.
+    pub async fn resolve_path(
+        &self,
+        path: &Path,
+        root: Arc<dyn Inode>,
+        task: &Arc<Task>,
+    ) -> Result<Arc<dyn Inode>> {
+        self.namei(
+            &task_mnt_ns(task),
+            path,
+            root,
+            task_root(task),
+            LookupFlags::FOLLOW,
+        )
+        .await
+    }
+
+    /// Resolves a path string to an Inode, starting from a given root for
+    /// relative paths, without following the final symbolic link.
+    pub async fn resolve_path_nofollow(
+        &self,
+        path: &Path,
+        root: Arc<dyn Inode>,
+        task: &Arc<Task>,
+    ) -> Result<Arc<dyn Inode>> {
+        self.namei(
+            &task_mnt_ns(task),
+            path,
+            root,
+            task_root(task),
+            LookupFlags::empty(),
+        )
+        .await
+    }
+
+    /// Resolves a path string to an Inode, starting from a given root for
+    /// relative paths, and using the filesystem root inode for absolute paths.
+    /// Mounts are those of the initial mount namespace.
+    pub async fn resolve_path_absolute(
+        &self,
+        path: &Path,
+        root: Arc<dyn Inode>,
+    ) -> Result<Arc<dyn Inode>> {
+        let fs_root = self
+            .root_inode
+            .lock_save_irq()
+            .as_ref()
+            .cloned()
+            .ok_or(FsError::NotFound)?;
+
+        self.namei(&init_mnt_ns(), path, root, fs_root, LookupFlags::FOLLOW)
+            .await
+    }
+
+    /// Resolves a path string to an Inode, starting from `start`, under the
+    /// restrictions of `resolve`. The final symbolic link is followed if
+    /// `follow` is set.
+    pub async fn resolve_path_restricted(
+        &self,
+        path: &Path,
+        start: Arc<dyn Inode>,
+        task: &Arc<Task>,
+        follow: bool,
+        resolve: ResolveFlags,
+    ) -> Result<Arc<dyn Inode>> {
+        let mut flags = LookupFlags::empty();
+
+        flags.set(LookupFlags::FOLLOW, follow);
+        flags.set(
+            LookupFlags::NO_SYMLINKS,
+            resolve.contains(ResolveFlags::RESOLVE_NO_SYMLINKS),
+        );
+        flags.set(
+            LookupFlags::NO_MAGICLINKS,
+            resolve.contains(ResolveFlags::RESOLVE_NO_MAGICLINKS),
+        );
+        flags.set(
+            LookupFlags::NO_XDEV,
+            resolve.contains(ResolveFlags::RESOLVE_NO_XDEV),
+        );
+        flags.set(
+            LookupFlags::BENEATH,
+            resolve.contains(ResolveFlags::RESOLVE_BENEATH),
+        );
+
+        let root =
+            if resolve.intersects(ResolveFlags::RESOLVE_BENEATH | ResolveFlags::RESOLVE_IN_ROOT) {
+                start.clone()
+            } else {
+                task_root(task)
+            };
+
+        self.namei(&task_mnt_ns(task), path, start, root, flags)
+            .await
+    }
+
+    /// Walks `path`, starting at `start` if it's relative and at `root` if it
+    /// is absolute. `root` is also as far up as `..` can go. Mount points are
+    /// crossed as the mount namespace `ns` has them.
+    async fn namei(
+        &self,
+        ns: &MountNamespace,
+        path: &Path,
+        start: Arc<dyn Inode>,
+        root: Arc<dyn Inode>,
+        mut flags: LookupFlags,
+    ) -> Result<Arc<dyn Inode>> {
+        if path.as_str().is_empty() {
+            return Err(FsError::NotFound.into());
+        }
+
+        if path.is_absolute() && flags.contains(LookupFlags::BENEATH) {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        if has_trailing_slash(path) {
+            flags |= LookupFlags::FOLLOW | LookupFlags::DIRECTORY;
+        }
+
+        let root = self.follow_mounts(ns, root);
+        let start = self.follow_mounts(ns, start);
+        // The filesystem the walk has to stay on with `NO_XDEV`.
+        let start_fs = start.id().fs_id();
+        let mut current = if path.is_absolute() {
+            root.clone()
+        } else {
+            start
+        };
+        let mut current_type = None;
+        let mut symlinks = 0;
+        // The directories walked down through, so that `..` can go back up
+        // without asking filesystems that don't keep parent links.
+        let mut ancestors = Vec::new();
+
+        // Components still to walk, last one first.
+        let mut components = reversed_components(path);
+
+        while let Some(component) = components.pop() {
+            let is_last = components.is_empty();
+
+            if flags.contains(LookupFlags::NO_XDEV) && current.id().fs_id() != start_fs {
+                return Err(FsError::CrossDevice.into());
+            }
+
+            if current_type.is_some_and(|t| t != FileType::Directory) {
+                return Err(FsError::NotADirectory.into());
+            }
+
+            if component == ".." {
+                current = if current.id() == root.id() {
+                    if flags.contains(LookupFlags::BENEATH) {
+                        return Err(FsError::CrossDevice.into());
+                    }
+
+                    current
+                } else if let Some(parent) = ancestors.pop() {
+                    parent
+                } else {
+                    self.parent(ns, current, &root).await?
+                };
+                current_type = Some(FileType::Directory);
+                continue;
+            }
+
+            let next = self.lookup(&current, &component).await?;
+            let file_type = next.getattr().await?.file_type;
+
+            if file_type == FileType::Symlink && (!is_last || flags.contains(LookupFlags::FOLLOW)) {
+                if flags.contains(LookupFlags::NO_SYMLINKS) {
+                    return Err(FsError::Loop.into());
+                }
+
+                symlinks += 1;
+
+                if symlinks > MAX_SYMLINK {
+                    return Err(FsError::Loop.into());
+                }
+
+                // A magic link jumps straight to the file it stands for.
+                if let Some(target) = next.magic_link().await? {
+                    if flags.contains(LookupFlags::NO_MAGICLINKS) {
+                        return Err(FsError::Loop.into());
+                    }
+
+                    if flags.contains(LookupFlags::BENEATH) {
+                        return Err(FsError::CrossDevice.into());
+                    }
+
+                    current_type = Some(target.getattr().await?.file_type);
+                    current = self.follow_mounts(ns, target);
+                    ancestors.clear();
+                    continue;
+                }
+
+                let target = next.readlink().await?;
+
+                if target.as_str().is_empty() {
+                    return Err(FsError::NotFound.into());
+                }
+
+                // A link to "dir/" must resolve to a directory too.
+                if is_last && has_trailing_slash(&target) {
+                    flags |= LookupFlags::DIRECTORY;
+                }
+
+                components.extend(reversed_components(&target));
+
+                // The target is resolved relative to the directory holding the
+                // link, which is where we still are, unless it's absolute.
+                if target.is_absolute() {
+                    if flags.contains(LookupFlags::BENEATH) {
+                        return Err(FsError::CrossDevice.into());
+                    }
+
+                    current = root.clone();
+                    current_type = Some(FileType::Directory);
+                    ancestors.clear();
+                }
+
+                continue;
+            }
+
+            ancestors.push(core::mem::replace(
+                &mut current,
+                self.follow_mounts(ns, next),
+            ));
+            current_type = Some(file_type);
+        }
+
+        if flags.contains(LookupFlags::NO_XDEV) && current.id().fs_id() != start_fs {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        if flags.contains(LookupFlags::DIRECTORY)
+            && current_type.is_some_and(|t| t != FileType::Directory)
+        {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        Ok(current)
+    }
+
+    /// Returns the parent of the directory `dir`, without leaving `root`.
+    async fn parent(
+        &self,
+        ns: &MountNamespace,
+        dir: Arc<dyn Inode>,
+        root: &Arc<dyn Inode>,
+    ) -> Result<Arc<dyn Inode>> {
+        let mut dir = dir;
+
+        loop {
+            if dir.id() == root.id() {
+                return Ok(dir);
+            }
+
+            // The parent of a mounted filesystem's root is the parent of the
+            // directory it's mounted on. A directory bound onto itself is its
+            // own mount point.
+            match self
+                .state
+                .lock_save_irq()
+                .table(ns.id())
+                .get_mount_point(dir.id())
+            {
+                Some(mount_point) if mount_point.id() != dir.id() => dir = mount_point,
+                _ => break,
+            }
+        }
+
+        let parent = self.lookup(&dir, "..").await?;
+
+        Ok(self.follow_mounts(ns, parent))
+    }
+
+    /// Works out the path of the directory `dir` as `task` sees it, by going up
+    /// through `..` to the task's root and finding each directory's name in
+    /// its parent. This follows renames, and fails with `ENOENT` once `dir` has
+    /// been removed.
+    ///
+    /// A directory that isn't under the task's root, as after a `chroot()`, is
+    /// given its path from the top of the tree with `(unreachable)` in front,
+    /// as on Linux.
+    pub async fn path_of(&self, dir: Arc<dyn Inode>, task: &Arc<Task>) -> Result<PathBuf> {
+        let ns = task_mnt_ns(task);
+        let root = task_root(task);
+        let top = self.root_inode().id();
+        let mut dir = dir;
+        let mut names = Vec::new();
+
+        let reachable = loop {
+            if dir.id() == root.id() {
+                break true;
+            }
+
+            // Climb out of a mounted filesystem through the directory it's
+            // mounted on, which is where it has a name.
+            let mount_point = self
+                .state
+                .lock_save_irq()
+                .table(ns.id())
+                .get_mount_point(dir.id());
+
+            if let Some(mount_point) = mount_point
+                && mount_point.id() != dir.id()
+            {
+                dir = mount_point;
+                continue;
+            }
+
+            if dir.id() == top {
+                break false;
+            }
+
+            let parent = self.lookup(&dir, "..").await?;
+
+            if parent.id() == dir.id() {
+                break false;
+            }
+
+            names.push(self.name_in(&parent, &dir).await?);
+            dir = parent;
+        };
+
+        let mut path = PathBuf::from(if reachable { "/" } else { "(unreachable)/" });
+
+        for name in names.iter().rev() {
+            path.push(name.as_str());
+        }
+
+        Ok(path)
+    }
+
+    /// Returns `true` if the directory `dir` is `ancestor`, or lies somewhere
+    /// beneath it on the same filesystem.
+    pub async fn is_subdir(&self, dir: &Arc<dyn Inode>, ancestor: InodeId) -> Result<bool> {
+        let mut dir = dir.clone();
+
+        loop {
+            if dir.id() == ancestor {
+                return Ok(true);
+            }
+
+            let parent = self.lookup(&dir, "..").await?;
+
+            if parent.id() == dir.id() || parent.id().fs_id() != ancestor.fs_id() {
+                return Ok(false);
+            }
+
+            dir = parent;
+        }
+    }
+
+    /// Returns the name `child` has in the directory `dir`.
+    async fn name_in(&self, dir: &Arc<dyn Inode>, child: &Arc<dyn Inode>) -> Result<String> {
+        let mut subdirs = Vec::new();
+        let mut entries = dir.readdir(0).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+
+            if entry.id == child.id() {
+                return Ok(entry.name);
+            }
+
+            if entry.file_type == FileType::Directory {
+                subdirs.push(entry.name);
+            }
+        }
+
+        // Not every filesystem lists its entries under the ids of their
+        // inodes, so fall back to looking the directories up.
+        for name in subdirs {
+            if self
+                .lookup(dir, &name)
+                .await
+                .is_ok_and(|inode| inode.id() == child.id())
+            {
+                return Ok(name);
+            }
+        }
+
+        Err(FsError::NotFound.into())
+    }
+
+    /// If `inode` has filesystems mounted on it in the namespace `ns`, returns
+    /// the root of the one mounted last.
+    fn follow_mounts(&self, ns: &MountNamespace, mut inode: Arc<dyn Inode>) -> Arc<dyn Inode> {
+        let state = self.state.lock_save_irq();
+        let table = state.table(ns.id());
+
+        while let Some(mount_root) = table.get_mount_root(&inode.id()) {
+            // The root filesystem is recorded as mounted on its own root.
+            if mount_root.id() == inode.id() {
+                break;
+            }
+
+            inode = mount_root;
+        }
+
+        inode
+    }
+}
+
+/// Returns the root directory of `task`, which may have been changed by
+/// `chroot()`.
+fn task_root(task: &Arc<Task>) -> Arc<dyn Inode> {
+    task.root.lock_save_irq().0.clone()
+}
+
+/// Returns the mount namespace `task` resolves paths in.
+fn task_mnt_ns(task: &Arc<Task>) -> Arc<MountNamespace> {
+    task.mnt_ns.lock_save_irq().clone()
+}
+
+/// Returns the components of `path`, last one first.
+fn reversed_components(path: &Path) -> Vec<String> {
+    let mut components: Vec<_> = path.components().map(String::from).collect();
+    components.reverse();
+    components
+}
+
+/// Returns `true` if `path` ends in a slash after a component, as in `dir/`.
+fn has_trailing_slash(path: &Path) -> bool {
+    path.as_str().ends_with('/') && path.components().next().is_some()
+}
@@ -1,4 +1,8 @@
-use super::fops::FileOps;
+use super::{
+    fops::FileOps,
+    freeze::WriteAccess,
+    lock::{self, LockOwner},
+};
 use crate::{
     process::fd_table::select::PollFlags,
     sync::{AsyncMutexGuard, Mutex},
@@ -7,23 +11,34 @@ use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::{future, pin::Pin, task::Poll};
 use libkernel::{
     error::Result,
-    fs::{Inode, OpenFlags, path::Path, pathbuf::PathBuf},
+    fs::{Inode, OpenFlags, path::Path, pathbuf::PathBuf, readahead::FileReadahead},
 };
 
 pub struct FileCtx {
     pub flags: OpenFlags,
     pub pos: u64,
+    /// Access pattern tracking for readahead.
+    pub ra: FileReadahead,
 }
 
 impl FileCtx {
     pub fn new(flags: OpenFlags) -> Self {
-        Self { flags, pos: 0 }
+        Self {
+            flags,
+            pos: 0,
+            ra: FileReadahead::new(),
+        }
     }
 }
 
 pub struct OpenFile {
     inode: Option<Arc<dyn Inode>>,
     path: Option<PathBuf>,
+    /// Opened with `O_PATH`, so only good for naming the file.
+    path_only: bool,
+    /// Keeps the filesystem from being remounted read-only whilst the file is
+    /// open for writing.
+    write_access: Option<WriteAccess>,
     state: Mutex<(Box<dyn FileOps>, FileCtx)>,
 }
 
@@ -33,6 +48,8 @@ impl OpenFile {
             state: Mutex::new((ops, FileCtx::new(flags))),
             inode: None,
             path: None,
+            path_only: flags.contains(OpenFlags::O_PATH),
+            write_access: None,
         }
     }
 
@@ -41,6 +58,11 @@ impl OpenFile {
         self.path = Some(path);
     }
 
+    /// Holds `access` to the file's filesystem until the file is closed.
+    pub fn hold_write_access(&mut self, access: WriteAccess) {
+        self.write_access = Some(access);
+    }
+
     pub fn inode(&self) -> Option<Arc<dyn Inode>> {
         self.inode.clone()
     }
@@ -49,6 +71,15 @@ impl OpenFile {
         self.path.as_deref()
     }
 
+    pub fn is_path_only(&self) -> bool {
+        self.path_only
+    }
+
+    /// The owner of the `flock()` locks taken through this open file.
+    pub fn lock_owner(&self) -> LockOwner {
+        LockOwner::File(self as *const Self as usize)
+    }
+
     pub async fn flags(&self) -> OpenFlags {
         self.state.lock().await.1.flags
     }
@@ -112,3 +143,12 @@ impl OpenFile {
         })
     }
 }
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        // Any `flock()` lock goes with the last reference to the description.
+        if let Some(inode) = &self.inode {
+            lock::release(inode.id(), self.lock_owner());
+        }
+    }
+}
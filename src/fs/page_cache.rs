@@ -0,0 +1,191 @@
+//! A cache of file pages, shared between the private mappings of a file.
+//!
+//! Most of what gets mapped from files is program text and read-only data,
+//! which is never written. Rather than every process reading its own copy of
+//! such a page, the fault handler maps the cached page read-only, and
+//! copy-on-write if the mapping is writable. Running many copies of a program
+//! then only costs its text once, and a program that has run recently starts
+//! without touching the disk.
+//!
+//! Entries are keyed by inode and page index. Each carries the size and
+//! times of the inode when it was read, so that a page is never served for a
+//! file that has changed, or been replaced by another file with the same
+//! inode number, since. Writes through the VFS drop the file's pages
+//! straight away.
+//!
+//! Files whose data already lives in page frames (see
+//! [`Inode::can_share_pages`]) aren't cached here.
+
+use crate::{memory::page::ClaimedPage, sync::SpinLock};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use core::time::Duration;
+use libkernel::{
+    error::Result,
+    fs::{Inode, InodeId},
+    memory::{PAGE_SIZE, page::PageFrame},
+};
+
+/// Maximum number of pages kept in the cache.
+const CACHE_PAGES: usize = 4096;
+
+/// What a cached page was read from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FileVersion {
+    size: u64,
+    mtime: Duration,
+    ctime: Duration,
+}
+
+struct CachedPage {
+    frame: PageFrame,
+    version: FileVersion,
+    /// Key of the page in `PageCacheState::lru`.
+    stamp: u64,
+}
+
+struct PageCacheState {
+    pages: BTreeMap<(InodeId, u64), CachedPage>,
+    /// Keys of `pages`, least recently used first.
+    lru: BTreeMap<u64, (InodeId, u64)>,
+    next_stamp: u64,
+    /// Bumped by every invalidation, so that a read which raced with a write
+    /// doesn't cache what may be stale data.
+    generation: u64,
+}
+
+impl PageCacheState {
+    /// Drops the cache's reference on the page at `key`.
+    fn remove(&mut self, key: &(InodeId, u64)) {
+        if let Some(page) = self.pages.remove(key) {
+            self.lru.remove(&page.stamp);
+
+            // SAFETY: The cache owns a reference on every page it holds.
+            drop(unsafe { ClaimedPage::from_pfn(page.frame) });
+        }
+    }
+
+    /// Returns a new reference to the page at `key`, if it is cached and was
+    /// read from `version` of the file.
+    fn get(&mut self, key: (InodeId, u64), version: FileVersion) -> Option<ClaimedPage> {
+        let page = self.pages.get_mut(&key)?;
+
+        if page.version != version {
+            self.remove(&key);
+            return None;
+        }
+
+        self.lru.remove(&page.stamp);
+        page.stamp = self.next_stamp;
+        self.lru.insert(self.next_stamp, key);
+        self.next_stamp += 1;
+
+        // SAFETY: The cache owns a reference on the page, which is leaked
+        // again once we've taken one for the caller with `share`.
+        let cached = unsafe { ClaimedPage::from_pfn(page.frame) };
+        let frame = cached.share();
+        cached.leak();
+
+        Some(unsafe { ClaimedPage::from_pfn(frame) })
+    }
+
+    fn insert(&mut self, key: (InodeId, u64), page: &ClaimedPage, version: FileVersion) {
+        self.remove(&key);
+
+        self.pages.insert(
+            key,
+            CachedPage {
+                frame: page.share(),
+                version,
+                stamp: self.next_stamp,
+            },
+        );
+        self.lru.insert(self.next_stamp, key);
+        self.next_stamp += 1;
+
+        while self.pages.len() > CACHE_PAGES
+            && let Some(victim) = self.lru.first_key_value().map(|(_, key)| *key)
+        {
+            self.remove(&victim);
+        }
+    }
+}
+
+static PAGE_CACHE: SpinLock<PageCacheState> = SpinLock::new(PageCacheState {
+    pages: BTreeMap::new(),
+    lru: BTreeMap::new(),
+    next_stamp: 0,
+    generation: 0,
+});
+
+/// Returns page `pg_idx` of `inode`, reading it into the cache if it isn't
+/// there already.
+///
+/// The returned page holds its own reference, and must not be written to:
+/// other mappings of the file may be sharing it.
+pub async fn get(inode: &Arc<dyn Inode>, pg_idx: u64) -> Result<ClaimedPage> {
+    let attr = inode.getattr().await?;
+    let version = FileVersion {
+        size: attr.size,
+        mtime: attr.mtime,
+        ctime: attr.ctime,
+    };
+    let key = (inode.id(), pg_idx);
+
+    let generation = {
+        let mut cache = PAGE_CACHE.lock_save_irq();
+
+        if let Some(page) = cache.get(key, version) {
+            return Ok(page);
+        }
+
+        cache.generation
+    };
+
+    let mut page = ClaimedPage::alloc_zeroed()?;
+    let read = inode
+        .read_at(pg_idx * PAGE_SIZE as u64, page.as_slice_mut())
+        .await?;
+
+    let mut cache = PAGE_CACHE.lock_save_irq();
+
+    // A short read means the file has shrunk under us; don't keep the page
+    // around.
+    if read == PAGE_SIZE && cache.generation == generation {
+        cache.insert(key, &page, version);
+    }
+
+    Ok(page)
+}
+
+/// Drops the cached pages of the inode `id`, after its data has been
+/// modified.
+///
+/// Mappings keep the pages they already have.
+pub fn invalidate(id: InodeId) {
+    let mut cache = PAGE_CACHE.lock_save_irq();
+
+    cache.generation += 1;
+
+    let keys: Vec<_> = cache
+        .pages
+        .range((id, 0)..=(id, u64::MAX))
+        .map(|(k, _)| *k)
+        .collect();
+
+    for key in keys {
+        cache.remove(&key);
+    }
+}
+
+/// Drops every cached page, as asked for through `/proc/sys/vm/drop_caches`.
+///
+/// Mappings keep the pages they already have.
+pub fn drop_all() {
+    let mut cache = PAGE_CACHE.lock_save_irq();
+
+    cache.generation += 1;
+
+    while let Some(key) = cache.pages.keys().next().copied() {
+        cache.remove(&key);
+    }
+}
@@ -0,0 +1,26 @@
+use alloc::boxed::Box;
+use async_trait::async_trait;
+use libkernel::{
+    error::{KernelError, Result},
+    memory::address::UA,
+};
+
+use super::fops::FileOps;
+
+/// The file operations behind an `O_PATH` descriptor, which names a file
+/// without opening it for I/O.
+///
+/// Such descriptors aren't handed out by `FileDescriptorTable::get`, so these
+/// are only reached through the few callers that look past that.
+pub struct PathFile;
+
+#[async_trait]
+impl FileOps for PathFile {
+    async fn readat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::BadFd)
+    }
+
+    async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::BadFd)
+    }
+}
@@ -3,15 +3,15 @@ use crate::{
     kernel::kpipe::KPipe,
     memory::uaccess::copy_to_user,
     process::{
-        fd_table::Fd,
+        fd_table::{Fd, FdFlags},
         thread_group::signal::{InterruptResult, Interruptable, SigId},
     },
     sched::{current_work, syscall_ctx::ProcessCtx},
-    sync::CondVar,
+    sync::{CondVar, SpinLock},
 };
 use core::pin::Pin;
 
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc};
 use async_trait::async_trait;
 use core::any::Any;
 use core::{
@@ -23,7 +23,7 @@ use core::{
 };
 use futures::FutureExt;
 use libkernel::{
-    error::{KernelError, Result},
+    error::{FsError, KernelError, Result},
     fs::{
         FileType, Inode, InodeId, OpenFlags, SeekFrom,
         attr::{FileAttr, FilePermissions},
@@ -47,6 +47,8 @@ struct PipeInode {
     time: Duration,
     uid: Uid,
     gid: Gid,
+    /// The pipe's buffer, for reopening it through `/proc/<pid>/fd`.
+    inner: PipeInner,
 }
 
 #[async_trait]
@@ -78,27 +80,187 @@ impl Inode for PipeInode {
     }
 }
 
+/// How many of each end of a pipe are open. The number of times each end has
+/// ever been opened lets someone opening a FIFO see that the other end turned
+/// up, even if it's gone again by the time they look.
+#[derive(Default)]
+struct PipeEnds {
+    readers: usize,
+    writers: usize,
+    reader_opens: u64,
+    writer_opens: u64,
+}
+
+/// The buffer of a pipe or FIFO, shared by its ends.
 #[derive(Clone)]
-struct PipeInner {
+pub struct PipeInner {
     buf: KPipe,
-    other_side_gone: CondVar<bool>,
+    ends: CondVar<PipeEnds>,
+    /// The FIFO this is the buffer of, if it isn't an anonymous pipe.
+    fifo: Option<InodeId>,
 }
 
-impl PipeInner {}
+impl PipeInner {
+    fn new(fifo: Option<InodeId>) -> Result<Self> {
+        Ok(Self {
+            buf: KPipe::new()?,
+            ends: CondVar::new(PipeEnds::default()),
+            fifo,
+        })
+    }
+
+    /// Waits until every reader has gone.
+    fn readers_gone(&self) -> impl Future<Output = ()> + use<> {
+        self.ends
+            .wait_until(|ends| if ends.readers == 0 { Some(()) } else { None })
+    }
+
+    /// Waits until every writer has gone.
+    fn writers_gone(&self) -> impl Future<Output = ()> + use<> {
+        self.ends
+            .wait_until(|ends| if ends.writers == 0 { Some(()) } else { None })
+    }
+
+    /// Closes one end of the pipe, for which `update` drops the count. A FIFO
+    /// whose ends have all gone is forgotten, and its buffer with it.
+    fn close_end(&self, update: impl FnOnce(&mut PipeEnds)) {
+        self.ends.update(|ends| {
+            update(ends);
+            WakeupType::All
+        });
+
+        if let Some(id) = self.fifo {
+            let mut fifos = FIFOS.lock_save_irq();
+
+            if fifos.get(&id).is_some_and(|fifo| fifo.is_unused()) {
+                fifos.remove(&id);
+            }
+        }
+    }
+
+    /// Returns `true` if `self` and `other` are the same pipe.
+    pub fn ptr_eq(&self, other: &PipeInner) -> bool {
+        self.buf.ptr_eq(&other.buf)
+    }
+
+    /// The buffer data passes through.
+    pub fn buf(&self) -> &KPipe {
+        &self.buf
+    }
+
+    /// Waits until there's something to read. Returns `false` at end of file,
+    /// when the pipe is empty and every writer has gone. With `nonblock`,
+    /// fails with `EAGAIN` rather than waiting.
+    pub async fn wait_readable(&self, nonblock: bool) -> Result<bool> {
+        let mut ready = pin!(self.buf.read_ready());
+        let mut gone = pin!(self.writers_gone());
+
+        match future::poll_fn(move |cx| {
+            // As with a read, drain the buffer before reporting end of file.
+            if ready.as_mut().poll(cx).is_ready() {
+                Poll::Ready(Ok(true))
+            } else if gone.as_mut().poll(cx).is_ready() {
+                Poll::Ready(Ok(false))
+            } else if nonblock {
+                Poll::Ready(Err(KernelError::TryAgain))
+            } else {
+                Poll::Pending
+            }
+        })
+        .interruptable()
+        .await
+        {
+            InterruptResult::Interrupted => Err(KernelError::Interrupted),
+            InterruptResult::Uninterrupted(r) => r,
+        }
+    }
+
+    /// Waits until there's room to write. Writing to a pipe nobody reads
+    /// raises `SIGPIPE` and fails with `EPIPE`. With `nonblock`, fails with
+    /// `EAGAIN` rather than waiting.
+    pub async fn wait_writable(&self, nonblock: bool) -> Result<()> {
+        let mut ready = pin!(self.buf.write_ready());
+        let mut gone = pin!(self.readers_gone());
+
+        match future::poll_fn(move |cx| {
+            if gone.as_mut().poll(cx).is_ready() {
+                current_work().process.deliver_signal(SigId::SIGPIPE);
+                Poll::Ready(Err(KernelError::BrokenPipe))
+            } else if ready.as_mut().poll(cx).is_ready() {
+                Poll::Ready(Ok(()))
+            } else if nonblock {
+                Poll::Ready(Err(KernelError::TryAgain))
+            } else {
+                Poll::Pending
+            }
+        })
+        .interruptable()
+        .await
+        {
+            InterruptResult::Interrupted => Err(KernelError::Interrupted),
+            InterruptResult::Uninterrupted(r) => r,
+        }
+    }
+
+    /// Moves up to `count` bytes into the pipe `dst`. With `tee`, they're
+    /// copied instead, and can still be read from `self`.
+    pub async fn splice_to(
+        &self,
+        dst: &PipeInner,
+        count: usize,
+        tee: bool,
+        nonblock: bool,
+    ) -> Result<usize> {
+        loop {
+            if !self.wait_readable(nonblock).await? {
+                return Ok(0);
+            }
+
+            dst.wait_writable(nonblock).await?;
+
+            let moved = if tee {
+                dst.buf.try_tee_from(&self.buf, count)
+            } else {
+                dst.buf.try_splice_from(&self.buf, count)
+            };
+
+            // Someone else may have got in first, in which case wait again.
+            if moved > 0 {
+                return Ok(moved);
+            }
+        }
+    }
+
+    fn is_unused(&self) -> bool {
+        let mut unused = false;
+
+        self.ends.update(|ends| {
+            unused = ends.readers == 0 && ends.writers == 0;
+            WakeupType::None
+        });
+
+        unused
+    }
+}
 
 struct PipeReader {
     inner: PipeInner,
 }
 
 impl PipeReader {
+    fn new(inner: PipeInner) -> Self {
+        inner.ends.update(|ends| {
+            ends.readers += 1;
+            ends.reader_opens += 1;
+            WakeupType::All
+        });
+
+        Self { inner }
+    }
+
     async fn do_read(&self, read_fut: impl Future<Output = Result<usize>>) -> Result<usize> {
         let mut read_fut = pin!(read_fut);
-        let mut gone_fut =
-            pin!(
-                self.inner
-                    .other_side_gone
-                    .wait_until(|gone| if *gone { Some(()) } else { None })
-            );
+        let mut gone_fut = pin!(self.inner.writers_gone());
 
         match future::poll_fn(move |cx| {
             // Check the consumption future first, before we check whether the
@@ -127,12 +289,7 @@ impl FileOps for PipeReader {
         let inner = self.inner.clone();
         Box::pin(async move {
             let mut read_fut = Box::pin(inner.buf.read_ready().fuse());
-            let mut gone_cond = Box::pin(
-                inner
-                    .other_side_gone
-                    .wait_until(|gone| if *gone { Some(()) } else { None })
-                    .fuse(),
-            );
+            let mut gone_cond = Box::pin(inner.writers_gone().fuse());
 
             futures::select_biased! {
                 _ = read_fut => Ok(()),
@@ -162,6 +319,10 @@ impl FileOps for PipeReader {
         Err(KernelError::BadFd)
     }
 
+    fn is_stream(&self) -> bool {
+        true
+    }
+
     async fn seek(&mut self, _ctx: &mut FileCtx, _pos: SeekFrom) -> Result<u64> {
         Err(KernelError::SeekPipe)
     }
@@ -175,15 +336,16 @@ impl FileOps for PipeReader {
         self.do_read(async { Ok(kbuf.splice_from(&self.inner.buf, count).await) })
             .await
     }
+
+    fn as_pipe(&self) -> Option<&PipeInner> {
+        Some(&self.inner)
+    }
 }
 
 impl Drop for PipeReader {
     fn drop(&mut self) {
-        // notify any writers that the read end of the pipe has gone.
-        self.inner.other_side_gone.update(|gone| {
-            *gone = true;
-            WakeupType::All
-        });
+        // notify any writers if the read end of the pipe has gone.
+        self.inner.close_end(|ends| ends.readers -= 1);
     }
 }
 
@@ -192,14 +354,19 @@ struct PipeWriter {
 }
 
 impl PipeWriter {
+    fn new(inner: PipeInner) -> Self {
+        inner.ends.update(|ends| {
+            ends.writers += 1;
+            ends.writer_opens += 1;
+            WakeupType::All
+        });
+
+        Self { inner }
+    }
+
     async fn do_write(&self, write_fut: impl Future<Output = Result<usize>>) -> Result<usize> {
         let mut write_fut = pin!(write_fut);
-        let mut gone_fut =
-            pin!(
-                self.inner
-                    .other_side_gone
-                    .wait_until(|gone| if *gone { Some(()) } else { None })
-            );
+        let mut gone_fut = pin!(self.inner.readers_gone());
 
         future::poll_fn(move |cx| {
             // Check the gone future first, before we write data into the
@@ -224,11 +391,7 @@ impl FileOps for PipeWriter {
         let inner = self.inner.clone();
         Box::pin(async move {
             let mut write_fut = Box::pin(inner.buf.write_ready());
-            let mut gone_cond = Box::pin(
-                inner
-                    .other_side_gone
-                    .wait_until(|gone| if *gone { Some(()) } else { None }),
-            );
+            let mut gone_cond = Box::pin(inner.readers_gone());
 
             future::poll_fn(move |cx| {
                 if write_fut.as_mut().poll(cx).is_ready() || gone_cond.as_mut().poll(cx).is_ready()
@@ -263,6 +426,10 @@ impl FileOps for PipeWriter {
             .await
     }
 
+    fn is_stream(&self) -> bool {
+        true
+    }
+
     async fn seek(&mut self, _ctx: &mut FileCtx, _pos: SeekFrom) -> Result<u64> {
         Err(KernelError::SeekPipe)
     }
@@ -276,34 +443,162 @@ impl FileOps for PipeWriter {
         self.do_write(async { Ok(self.inner.buf.splice_from(kbuf, count).await) })
             .await
     }
+
+    fn as_pipe(&self) -> Option<&PipeInner> {
+        Some(&self.inner)
+    }
 }
 
 impl Drop for PipeWriter {
     fn drop(&mut self) {
-        // notify any readers that the write end of the pipe has gone.
-        self.inner.other_side_gone.update(|gone| {
-            *gone = true;
-            WakeupType::All
-        });
+        // notify any readers if the write end of the pipe has gone.
+        self.inner.close_end(|ends| ends.writers -= 1);
     }
 }
 
-pub async fn sys_pipe2(ctx: &ProcessCtx, fds: TUA<[Fd; 2]>, flags: u32) -> Result<usize> {
-    let flags = OpenFlags::from_bits_retain(flags);
+/// A FIFO opened for both reading and writing, which is both ends at once.
+struct FifoReadWriter {
+    reader: PipeReader,
+    writer: PipeWriter,
+}
+
+#[async_trait]
+impl FileOps for FifoReadWriter {
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        self.reader.poll_read_ready()
+    }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        self.writer.poll_write_ready()
+    }
+
+    async fn readat(&mut self, u_buf: UA, count: usize, offset: u64) -> Result<usize> {
+        self.reader.readat(u_buf, count, offset).await
+    }
+
+    async fn writeat(&mut self, u_buf: UA, count: usize, offset: u64) -> Result<usize> {
+        self.writer.writeat(u_buf, count, offset).await
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    async fn seek(&mut self, _ctx: &mut FileCtx, _pos: SeekFrom) -> Result<u64> {
+        Err(KernelError::SeekPipe)
+    }
+
+    async fn splice_into(
+        &mut self,
+        ctx: &mut FileCtx,
+        kbuf: &KPipe,
+        count: usize,
+    ) -> Result<usize> {
+        self.reader.splice_into(ctx, kbuf, count).await
+    }
 
-    let kbuf = KPipe::new()?;
-    let condvar = CondVar::new(false);
+    async fn splice_from(
+        &mut self,
+        ctx: &mut FileCtx,
+        kbuf: &KPipe,
+        count: usize,
+    ) -> Result<usize> {
+        self.writer.splice_from(ctx, kbuf, count).await
+    }
+
+    fn as_pipe(&self) -> Option<&PipeInner> {
+        self.reader.as_pipe()
+    }
+}
+
+/// The buffers of the FIFOs that are open, by inode.
+static FIFOS: SpinLock<BTreeMap<InodeId, PipeInner>> = SpinLock::new(BTreeMap::new());
+
+/// Opens the FIFO `inode`, joining whoever else has it open. An anonymous
+/// pipe reopened through `/proc/<pid>/fd` is joined in the same way.
+///
+/// Opening one end waits until the other end has been opened too, unless
+/// `O_NONBLOCK` is given. Then a reader goes ahead regardless, but a writer
+/// fails with `ENXIO` if there's no reader. Opening both ends never waits.
+pub async fn open_fifo(inode: &Arc<dyn Inode>, flags: OpenFlags) -> Result<OpenFile> {
+    let accmode = flags & OpenFlags::O_ACCMODE;
+    let nonblock = flags.contains(OpenFlags::O_NONBLOCK);
+
+    // The new end is counted under the lock, so that the FIFO can't be
+    // forgotten in between.
+    let (ops, inner): (Box<dyn FileOps>, _) = {
+        let mut fifos = FIFOS.lock_save_irq();
+        let anon = inode.as_any().downcast_ref::<PipeInode>();
+        let inner = match anon.map(|pipe| &pipe.inner).or(fifos.get(&inode.id())) {
+            Some(inner) => inner.clone(),
+            None => {
+                let inner = PipeInner::new(Some(inode.id()))?;
+                fifos.insert(inode.id(), inner.clone());
+                inner
+            }
+        };
+
+        let ops: Box<dyn FileOps> = if accmode == OpenFlags::O_RDWR {
+            Box::new(FifoReadWriter {
+                reader: PipeReader::new(inner.clone()),
+                writer: PipeWriter::new(inner.clone()),
+            })
+        } else if accmode == OpenFlags::O_WRONLY {
+            Box::new(PipeWriter::new(inner.clone()))
+        } else {
+            Box::new(PipeReader::new(inner.clone()))
+        };
 
-    let inner = PipeInner {
-        buf: kbuf,
-        other_side_gone: condvar,
+        (ops, inner)
     };
 
-    let reader = PipeReader {
-        inner: inner.clone(),
+    // The count of the other end's opens, as of now, and the count of it open.
+    let other_end = move |ends: &PipeEnds| {
+        if accmode == OpenFlags::O_WRONLY {
+            (ends.reader_opens, ends.readers)
+        } else {
+            (ends.writer_opens, ends.writers)
+        }
     };
 
-    let writer = PipeWriter { inner };
+    let mut seen = (0, 0);
+    inner.ends.update(|ends| {
+        seen = other_end(ends);
+        WakeupType::None
+    });
+
+    if accmode != OpenFlags::O_RDWR && seen.1 == 0 {
+        if nonblock {
+            if accmode == OpenFlags::O_WRONLY {
+                return Err(FsError::NoDeviceOrAddress.into());
+            }
+        } else if let InterruptResult::Interrupted = inner
+            .ends
+            .wait_until(move |ends| {
+                let (opens, open) = other_end(ends);
+
+                if open > 0 || opens != seen.0 {
+                    Some(())
+                } else {
+                    None
+                }
+            })
+            .interruptable()
+            .await
+        {
+            return Err(KernelError::Interrupted);
+        }
+    }
+
+    Ok(OpenFile::new(ops, flags))
+}
+
+pub async fn sys_pipe2(ctx: &ProcessCtx, fds: TUA<[Fd; 2]>, flags: u32) -> Result<usize> {
+    let flags = OpenFlags::from_bits_retain(flags);
+
+    let inner = PipeInner::new(None)?;
+    let reader = PipeReader::new(inner.clone());
+    let writer = PipeWriter::new(inner.clone());
 
     let (read_fd, write_fd) = {
         static INODE_ID: AtomicU64 = AtomicU64::new(0);
@@ -316,17 +611,25 @@ pub async fn sys_pipe2(ctx: &ProcessCtx, fds: TUA<[Fd; 2]>, flags: u32) -> Resul
                 time: date(),
                 uid: creds.uid(),
                 gid: creds.gid(),
+                inner,
             })
         };
 
-        let mut read_file = OpenFile::new(Box::new(reader), flags);
-        let mut write_file = OpenFile::new(Box::new(writer), flags);
+        // Each end is open for the one kind of access it supports.
+        let flags = flags.difference(OpenFlags::O_ACCMODE);
+        let mut read_file = OpenFile::new(Box::new(reader), flags | OpenFlags::O_RDONLY);
+        let mut write_file = OpenFile::new(Box::new(writer), flags | OpenFlags::O_WRONLY);
 
         read_file.update(inode.clone(), PathBuf::new());
         write_file.update(inode, PathBuf::new());
 
-        let read_fd = fds.insert(Arc::new(read_file))?;
-        let write_fd = fds.insert(Arc::new(write_file))?;
+        let fd_flags = if flags.contains(OpenFlags::O_CLOEXEC) {
+            FdFlags::CLOEXEC
+        } else {
+            FdFlags::empty()
+        };
+        let read_fd = fds.insert_with_flags(Arc::new(read_file), fd_flags.clone())?;
+        let write_fd = fds.insert_with_flags(Arc::new(write_file), fd_flags)?;
 
         (read_fd, write_fd)
     };
@@ -1,103 +1,255 @@
-use super::{fops::FileOps, open_file::FileCtx};
+use super::{fops::FileOps, open_file::FileCtx, page_cache, syscalls::iov::IoVec};
 use crate::{
+    fs::VFS,
     kernel::kpipe::KPipe,
     memory::{
         page::ClaimedPage,
         uaccess::{copy_from_user_slice, copy_to_user_slice},
     },
-    process::inotify::notify_modify,
+    process::{fanotify, inotify::notify_modify},
 };
 use alloc::{boxed::Box, sync::Arc};
 use async_trait::async_trait;
 use core::{cmp::min, pin::Pin};
+use futures::future::join;
 use libkernel::{
-    error::Result,
-    fs::{Inode, SeekFrom},
+    error::{FsError, Result},
+    fs::{FallocMode, Inode, SeekFrom},
     memory::{PAGE_SIZE, address::UA},
 };
 
-const SPLICE_BUF_SZ: usize = 32;
-
 pub struct RegFile {
     inode: Arc<dyn Inode>,
+    /// Whether accesses through this file generate fanotify events.
+    fanotify: bool,
 }
 
 impl RegFile {
     pub fn new(inode: Arc<dyn Inode>) -> Self {
-        Self { inode }
+        Self {
+            inode,
+            fanotify: true,
+        }
+    }
+
+    /// Creates a file that never generates fanotify events, for handing to
+    /// fanotify listeners.
+    pub fn new_nonotify(inode: Arc<dyn Inode>) -> Self {
+        Self {
+            inode,
+            fanotify: false,
+        }
     }
 }
 
 #[async_trait]
 impl FileOps for RegFile {
+    /// Reads from the current file position, reading ahead of sequential
+    /// readers.
+    async fn read(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        self.readv(
+            ctx,
+            &[IoVec {
+                iov_base: buf,
+                iov_len: count,
+            }],
+        )
+        .await
+    }
+
     /// Reads data from the current file position into `buf`. The file's cursor
     /// is advanced by the number of bytes read.
-    async fn readat(
-        &mut self,
-        mut user_buf: UA,
-        mut count: usize,
-        mut offset: u64,
-    ) -> Result<usize> {
-        let mut pg = ClaimedPage::alloc_zeroed()?;
-        let kbuf = pg.as_slice_mut();
-        let mut total_bytes_read = 0;
-
-        while count > 0 {
-            let chunk_sz = min(PAGE_SIZE, count);
-            copy_from_user_slice(user_buf, &mut kbuf[..chunk_sz]).await?;
+    async fn readat(&mut self, user_buf: UA, count: usize, offset: u64) -> Result<usize> {
+        self.readvat(
+            &[IoVec {
+                iov_base: user_buf,
+                iov_len: count,
+            }],
+            offset,
+        )
+        .await
+    }
 
-            let bytes_read = self.inode.read_at(offset, &mut kbuf[..chunk_sz]).await?;
+    /// Writes data from `buf` to the current file position.
+    /// The file's cursor is advanced by the number of bytes written.
+    async fn writeat(&mut self, buf: UA, count: usize, offset: u64) -> Result<usize> {
+        self.writevat(
+            &[IoVec {
+                iov_base: buf,
+                iov_len: count,
+            }],
+            offset,
+        )
+        .await
+    }
 
-            if bytes_read == 0 {
-                break;
+    /// Reads into each of `iovecs` in turn from the current file position, as
+    /// one read, reading ahead of sequential readers.
+    async fn readv(&mut self, ctx: &mut FileCtx, iovecs: &[IoVec]) -> Result<usize> {
+        let inode = self.inode.clone();
+        let pos = ctx.pos;
+        let count = iovecs.iter().map(|iov| iov.iov_len).sum();
+
+        // Readahead is only a hint, so its errors are dropped; the read
+        // reports any that matter.
+        let total_bytes_read = match ctx.ra.on_read(pos, count) {
+            // The window covers this read: fill it first, so the read is
+            // served from the cache.
+            Some(window) if window.start <= pos => {
+                let _ = inode
+                    .readahead(window.start, window.end - window.start)
+                    .await;
+                self.readvat(iovecs, pos).await?
             }
+            // The window lies ahead of us: fetch it while we read.
+            Some(window) => {
+                let (_, res) = join(
+                    inode.readahead(window.start, window.end - window.start),
+                    self.readvat(iovecs, pos),
+                )
+                .await;
+                res?
+            }
+            None => self.readvat(iovecs, pos).await?,
+        };
 
-            copy_to_user_slice(&kbuf[..bytes_read], user_buf).await?;
-
-            offset += bytes_read as u64;
-            total_bytes_read += bytes_read;
-            user_buf = user_buf.add_bytes(bytes_read);
-            count -= bytes_read;
-        }
+        ctx.pos += total_bytes_read as u64;
 
         Ok(total_bytes_read)
     }
 
-    /// Writes data from `buf` to the current file position.
-    /// The file's cursor is advanced by the number of bytes written.
-    async fn writeat(&mut self, mut buf: UA, mut count: usize, mut offset: u64) -> Result<usize> {
+    /// Reads into each of `iovecs` in turn from `offset`, as one read: a
+    /// fanotify listener hears of it once.
+    async fn readvat(&mut self, iovecs: &[IoVec], mut offset: u64) -> Result<usize> {
+        if self.fanotify {
+            fanotify::check_access(&self.inode).await?;
+        }
+
         let mut pg = ClaimedPage::alloc_zeroed()?;
         let kbuf = pg.as_slice_mut();
-        let mut total_bytes_written = 0;
+        let mut total_bytes_read = 0;
 
-        while count > 0 {
-            let chunk_sz = min(PAGE_SIZE, count);
+        'iovecs: for iov in iovecs {
+            let mut user_buf = iov.iov_base;
+            let mut count = iov.iov_len;
+
+            while count > 0 {
+                let chunk_sz = min(PAGE_SIZE, count);
+
+                let res = async {
+                    copy_from_user_slice(user_buf, &mut kbuf[..chunk_sz]).await?;
+                    let bytes_read = self.inode.read_at(offset, &mut kbuf[..chunk_sz]).await?;
+                    copy_to_user_slice(&kbuf[..bytes_read], user_buf).await?;
+                    Ok(bytes_read)
+                }
+                .await;
+
+                let bytes_read = match res {
+                    Ok(0) => break 'iovecs,
+                    Ok(n) => n,
+                    // What was already read is reported, not the error.
+                    Err(_) if total_bytes_read > 0 => break 'iovecs,
+                    Err(e) => return Err(e),
+                };
+
+                offset += bytes_read as u64;
+                total_bytes_read += bytes_read;
+                user_buf = user_buf.add_bytes(bytes_read);
+                count -= bytes_read;
+            }
+        }
 
-            copy_from_user_slice(buf, &mut kbuf[..chunk_sz]).await?;
+        if self.fanotify && total_bytes_read > 0 {
+            fanotify::notify_access(&self.inode).await;
+        }
 
-            let bytes_written = self.inode.write_at(offset, &kbuf[..chunk_sz]).await?;
+        Ok(total_bytes_read)
+    }
 
-            // If we wrote 0 bytes, the disk might be full or the file cannot be
-            // extended.
-            if bytes_written == 0 {
-                break;
-            }
+    /// Writes each of `iovecs` in turn at the current file position, as one
+    /// write. The file's cursor is advanced by the number of bytes written.
+    async fn writev(&mut self, ctx: &mut FileCtx, iovecs: &[IoVec]) -> Result<usize> {
+        let total_bytes_written = self.writevat(iovecs, ctx.pos).await?;
+        ctx.pos += total_bytes_written as u64;
+        Ok(total_bytes_written)
+    }
 
-            offset += bytes_written as u64;
-            total_bytes_written += bytes_written;
-            count -= bytes_written;
-            buf = buf.add_bytes(bytes_written);
+    /// Writes each of `iovecs` in turn from `offset`, as one write: the file
+    /// is held for writing throughout, and watchers hear of it once.
+    async fn writevat(&mut self, iovecs: &[IoVec], mut offset: u64) -> Result<usize> {
+        let _guard = VFS.begin_write(self.inode.id()).await?;
+        let mut pg = ClaimedPage::alloc_zeroed()?;
+        let kbuf = pg.as_slice_mut();
+        let mut total_bytes_written = 0;
+        let mut error = None;
+
+        'iovecs: for iov in iovecs {
+            let mut buf = iov.iov_base;
+            let mut count = iov.iov_len;
+
+            while count > 0 {
+                let chunk_sz = min(PAGE_SIZE, count);
+
+                let res = async {
+                    copy_from_user_slice(buf, &mut kbuf[..chunk_sz]).await?;
+                    self.inode.write_at(offset, &kbuf[..chunk_sz]).await
+                }
+                .await;
+
+                let bytes_written = match res {
+                    // If we wrote 0 bytes, the disk might be full or the file
+                    // cannot be extended.
+                    Ok(0) => break 'iovecs,
+                    Ok(n) => n,
+                    Err(e) => {
+                        error = Some(e);
+                        break 'iovecs;
+                    }
+                };
+
+                offset += bytes_written as u64;
+                total_bytes_written += bytes_written;
+                count -= bytes_written;
+                buf = buf.add_bytes(bytes_written);
+            }
         }
 
         if total_bytes_written > 0 {
+            page_cache::invalidate(self.inode.id());
             notify_modify(self.inode.id()).await;
+
+            if self.fanotify {
+                fanotify::notify_modify(&self.inode).await;
+            }
         }
 
-        Ok(total_bytes_written)
+        // What was already written is reported, not the error.
+        match error {
+            Some(e) if total_bytes_written == 0 => Err(e),
+            _ => Ok(total_bytes_written),
+        }
     }
 
     async fn truncate(&mut self, _ctx: &FileCtx, new_size: usize) -> Result<()> {
-        self.inode.truncate(new_size as _).await?;
+        VFS.truncate(&self.inode, new_size as _).await
+    }
+
+    async fn fallocate(
+        &mut self,
+        _ctx: &FileCtx,
+        mode: FallocMode,
+        offset: u64,
+        len: u64,
+    ) -> Result<()> {
+        let _guard = VFS.begin_write(self.inode.id()).await?;
+        self.inode.fallocate(mode, offset, len).await?;
+
+        // Preallocation leaves the contents alone, but a hole punched in the
+        // file now reads as zeroes.
+        if mode == FallocMode::PunchHole {
+            page_cache::invalidate(self.inode.id());
+        }
+
         notify_modify(self.inode.id()).await;
         Ok(())
     }
@@ -129,37 +281,142 @@ impl FileOps for RegFile {
             SeekFrom::Start(x) => ctx.pos = x,
             SeekFrom::End(x) => ctx.pos = saturating_add_signed(size, x),
             SeekFrom::Current(x) => ctx.pos = saturating_add_signed(ctx.pos, x),
+            SeekFrom::Data(x) | SeekFrom::Hole(x) => {
+                // There's neither data nor a hole to find past the end.
+                if x >= size {
+                    return Err(FsError::NoDeviceOrAddress.into());
+                }
+
+                let extents = self.inode.probe_extents(x..size).await?;
+
+                ctx.pos = if let SeekFrom::Data(_) = pos {
+                    extents
+                        .first()
+                        .map(|data| data.start.max(x))
+                        .ok_or(FsError::NoDeviceOrAddress)?
+                } else {
+                    let mut hole = x;
+
+                    for data in &extents {
+                        if data.start > hole {
+                            break;
+                        }
+
+                        hole = hole.max(data.end);
+                    }
+
+                    hole.min(size)
+                };
+            }
         }
 
         Ok(ctx.pos)
     }
 
+    /// Feeds `kbuf` from the page cache, up to the end of the page at the
+    /// file position.
     async fn splice_into(
         &mut self,
         ctx: &mut FileCtx,
         kbuf: &KPipe,
         count: usize,
     ) -> Result<usize> {
-        let mut buf = [0; SPLICE_BUF_SZ];
+        if self.fanotify {
+            fanotify::check_access(&self.inode).await?;
+        }
 
-        let bytes_read = self
-            .inode
-            .read_at(ctx.pos, &mut buf[..min(SPLICE_BUF_SZ, count)])
-            .await?;
+        let size = self.inode.getattr().await?.size;
 
-        if bytes_read == 0 {
+        if ctx.pos >= size || count == 0 {
             return Ok(0);
         }
 
-        ctx.pos += bytes_read as u64;
+        let pg_idx = ctx.pos / PAGE_SIZE as u64;
+        let pg_off = (ctx.pos % PAGE_SIZE as u64) as usize;
+        let len = min(min(count, PAGE_SIZE - pg_off) as u64, size - ctx.pos) as usize;
+
+        // Filesystems that keep their data in page frames aren't cached; a
+        // plain read from them is as cheap, and doesn't fill in holes the way
+        // `get_page` would.
+        let page = if self.inode.can_share_pages() {
+            let mut page = ClaimedPage::alloc_zeroed()?;
+            let read = self
+                .inode
+                .read_at(ctx.pos, &mut page.as_slice_mut()[pg_off..pg_off + len])
+                .await?;
+
+            if read == 0 {
+                return Ok(0);
+            }
 
-        let mut data_to_write = &buf[..bytes_read];
+            page
+        } else {
+            page_cache::get(&self.inode, pg_idx).await?
+        };
+
+        let mut data = &page.as_slice()[pg_off..pg_off + len];
+
+        while !data.is_empty() {
+            let pushed = kbuf.push_slice(data).await;
+            data = &data[pushed..];
+        }
+
+        ctx.pos += len as u64;
+
+        if self.fanotify {
+            fanotify::notify_access(&self.inode).await;
+        }
+
+        Ok(len)
+    }
+
+    /// Writes what `kbuf` holds, up to `count` bytes, at the file position.
+    async fn splice_from(
+        &mut self,
+        ctx: &mut FileCtx,
+        kbuf: &KPipe,
+        count: usize,
+    ) -> Result<usize> {
+        let mut pg = ClaimedPage::alloc_zeroed()?;
+        let buf = &mut pg.as_slice_mut()[..min(PAGE_SIZE, count)];
+        let popped = kbuf.pop_slice(buf).await;
+
+        if popped == 0 {
+            return Ok(0);
+        }
+
+        let _guard = VFS.begin_write(self.inode.id()).await?;
+        let mut written = 0;
+
+        // What's been taken out of the pipe can't be put back, so it all has
+        // to go in.
+        while written < popped {
+            let n = self
+                .inode
+                .write_at(ctx.pos + written as u64, &buf[written..popped])
+                .await?;
+
+            if n == 0 {
+                break;
+            }
+
+            written += n;
+        }
+
+        if written > 0 {
+            ctx.pos += written as u64;
+            page_cache::invalidate(self.inode.id());
+            notify_modify(self.inode.id()).await;
+
+            if self.fanotify {
+                fanotify::notify_modify(&self.inode).await;
+            }
+        }
 
-        while !data_to_write.is_empty() {
-            let written = kbuf.push_slice(data_to_write).await;
-            data_to_write = &data_to_write[written..];
+        if written < popped {
+            return Err(FsError::NoSpace.into());
         }
 
-        Ok(bytes_read)
+        Ok(written)
     }
 }
@@ -1,12 +1,14 @@
 use super::{AtFlags, resolve_at_start_node};
 use crate::{
-    fs::syscalls::at::resolve_path_flags, memory::uaccess::cstr::UserCStr, process::fd_table::Fd,
+    fs::{VFS, syscalls::at::resolve_path_flags},
+    memory::uaccess::cstr::UserCStr,
+    process::fd_table::Fd,
     sched::syscall_ctx::ProcessCtx,
 };
 use core::ffi::c_char;
 use libkernel::{
-    error::Result,
-    fs::{attr::AccessMode, path::Path},
+    error::{FsError, KernelError, Result},
+    fs::{FileType, attr::AccessMode, path::Path},
     memory::address::TUA,
 };
 
@@ -29,9 +31,16 @@ pub async fn sys_faccessat2(
     let mut buf = [0; 1024];
 
     let task = ctx.shared().clone();
-    let access_mode = AccessMode::from_bits_retain(mode);
-    let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
+    let access_mode = AccessMode::from_bits(mode).ok_or(KernelError::InvalidValue)?;
     let at_flags = AtFlags::from_bits_retain(flags);
+
+    if !(AtFlags::AT_EACCESS | AtFlags::AT_SYMLINK_NOFOLLOW | AtFlags::AT_EMPTY_PATH)
+        .contains(at_flags)
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
     let start_node = resolve_at_start_node(ctx, dirfd, path, at_flags).await?;
     let node = resolve_path_flags(dirfd, path, start_node, &task, at_flags).await?;
 
@@ -42,17 +51,33 @@ pub async fn sys_faccessat2(
     }
 
     let attrs = node.getattr().await?;
-    let creds = task.creds.lock_save_irq();
 
-    // Determine which user and group IDs to use for the check. By default, use
-    // the real UID and GID. If AT_EACCESS is set, use effective IDs.
-    let (uid, gid) = if at_flags.contains(AtFlags::AT_EACCESS) {
-        (creds.euid(), creds.egid())
+    // Device nodes, FIFOs and sockets can still be written on a read-only
+    // mount; anything else can't.
+    if access_mode.contains(AccessMode::W_OK)
+        && matches!(
+            attrs.file_type,
+            FileType::File | FileType::Directory | FileType::Symlink
+        )
+        && VFS.is_read_only(node.id())
+    {
+        return Err(FsError::ReadOnly.into());
+    }
+
+    // By default the check is made with the real IDs, so that a set-user-ID
+    // program can ask whether its invoker may access the file. AT_EACCESS
+    // asks about the effective IDs instead.
+    let creds = if at_flags.contains(AtFlags::AT_EACCESS) {
+        task.creds.lock_save_irq().clone()
     } else {
-        (creds.uid(), creds.gid())
+        task.creds.lock_save_irq().for_real_access()
     };
 
     attrs
-        .check_access(uid, gid, creds.caps(), access_mode)
+        .check_access(creds.euid(), creds.egid(), creds.caps(), access_mode)
+        .map_err(|e| match e {
+            KernelError::NotPermitted => FsError::PermissionDenied.into(),
+            e => e,
+        })
         .map(|_| 0)
 }
@@ -0,0 +1,56 @@
+use crate::fs::VFS;
+use crate::fs::syscalls::at::{AtFlags, resolve_at_start_node};
+use crate::memory::uaccess::cstr::UserCStr;
+use crate::process::fd_table::Fd;
+use crate::sched::syscall_ctx::ProcessCtx;
+use core::ffi::c_char;
+use libkernel::driver::CharDevDescriptor;
+use libkernel::error::{KernelError, Result};
+use libkernel::fs::FileType;
+use libkernel::fs::attr::{FileMode, FilePermissions};
+use libkernel::fs::path::Path;
+use libkernel::memory::address::TUA;
+use libkernel::proc::caps::CapabilitiesFlags;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = FileMode::S_IFREG.bits() as _;
+const S_IFCHR: u32 = FileMode::S_IFCHR.bits() as _;
+const S_IFBLK: u32 = FileMode::S_IFBLK.bits() as _;
+const S_IFIFO: u32 = FileMode::S_IFIFO.bits() as _;
+const S_IFSOCK: u32 = FileMode::S_IFSOCK.bits() as _;
+
+pub async fn sys_mknodat(
+    ctx: &ProcessCtx,
+    dirfd: Fd,
+    path: TUA<c_char>,
+    mode: u32,
+    dev: u64,
+) -> Result<usize> {
+    let file_type = match mode & S_IFMT {
+        0 | S_IFREG => FileType::File,
+        S_IFCHR => FileType::CharDevice(CharDevDescriptor::decode(dev)),
+        S_IFBLK => FileType::BlockDevice(CharDevDescriptor::decode(dev)),
+        S_IFIFO => FileType::Fifo,
+        // There's nothing behind a socket inode that could open it yet.
+        S_IFSOCK => return Err(KernelError::OpNotSupported),
+        _ => return Err(KernelError::InvalidValue),
+    };
+
+    if file_type.device().is_some() {
+        ctx.shared()
+            .creds
+            .lock_save_irq()
+            .caps()
+            .check_capable(CapabilitiesFlags::CAP_MKNOD)?;
+    }
+
+    let mut buf = [0; 1024];
+
+    let task = ctx.shared().clone();
+    let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
+    let start_node = resolve_at_start_node(ctx, dirfd, path, AtFlags::empty()).await?;
+    let mode = FilePermissions::from_bits_truncate(mode as u16);
+
+    VFS.mknod(path, start_node, file_type, mode, &task).await?;
+    Ok(0)
+}
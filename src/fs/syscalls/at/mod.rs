@@ -15,6 +15,7 @@ pub mod chown;
 pub mod handle;
 pub mod link;
 pub mod mkdir;
+pub mod mknod;
 pub mod open;
 pub mod readlink;
 pub mod rename;
@@ -37,7 +38,7 @@ bitflags::bitflags! {
 
 /// Given the paraters to one of the sys_{action}at syscalls, resolve the
 /// arguments to a start node to which path should be applied.
-async fn resolve_at_start_node(
+pub(crate) async fn resolve_at_start_node(
     ctx: &ProcessCtx,
     dirfd: Fd,
     path: &Path,
@@ -60,7 +61,7 @@ async fn resolve_at_start_node(
         let file = task
             .fd_table
             .lock_save_irq()
-            .get(dirfd)
+            .get_raw(dirfd)
             .ok_or(KernelError::BadFd)?;
 
         let inode = file.inode().ok_or(KernelError::NotSupported)?;
@@ -75,7 +76,7 @@ async fn resolve_at_start_node(
     Ok(start_node)
 }
 
-async fn resolve_path_flags(
+pub(crate) async fn resolve_path_flags(
     dirfd: Fd,
     path: &Path,
     root: Arc<dyn Inode>,
@@ -90,7 +91,7 @@ async fn resolve_path_flags(
             let file = task
                 .fd_table
                 .lock_save_irq()
-                .get(dirfd)
+                .get_raw(dirfd)
                 .ok_or(KernelError::BadFd)?;
 
             file.inode().ok_or(KernelError::NotSupported)?
@@ -1,36 +1,183 @@
 use crate::{
-    fs::{VFS, syscalls::at::AtFlags},
-    memory::uaccess::cstr::UserCStr,
-    process::fd_table::Fd,
+    fs::{VFS, namei::ResolveFlags, syscalls::at::AtFlags},
+    memory::uaccess::{UserCopyable, copy_from_user, copy_from_user_slice, cstr::UserCStr},
+    process::fd_table::{Fd, FdFlags},
     sched::syscall_ctx::ProcessCtx,
 };
 use core::ffi::c_char;
 use libkernel::{
-    error::Result,
+    error::{KernelError, Result},
     fs::{OpenFlags, attr::FilePermissions, path::Path},
-    memory::address::TUA,
+    memory::{PAGE_SIZE, address::TUA},
 };
 
 use super::resolve_at_start_node;
 
+/// Every flag `open()` knows of on arm64, including the ones we ignore.
+const VALID_OPEN_FLAGS: u64 = 0o37777703;
+
+/// The only flags that mean anything alongside `O_PATH`.
+const O_PATH_FLAGS: OpenFlags = OpenFlags::O_PATH
+    .union(OpenFlags::O_DIRECTORY)
+    .union(OpenFlags::O_NOFOLLOW)
+    .union(OpenFlags::O_CLOEXEC);
+
+/// The arguments of `openat2()`, which may grow new fields at the end.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+unsafe impl UserCopyable for OpenHow {}
+
 pub async fn sys_openat(
     ctx: &ProcessCtx,
     dirfd: Fd,
     path: TUA<c_char>,
     flags: u32,
     mode: u16,
+) -> Result<usize> {
+    do_openat(
+        ctx,
+        dirfd,
+        path,
+        OpenFlags::from_bits_truncate(flags),
+        ResolveFlags::empty(),
+        mode,
+    )
+    .await
+}
+
+pub async fn sys_openat2(
+    ctx: &ProcessCtx,
+    dirfd: Fd,
+    path: TUA<c_char>,
+    how: TUA<OpenHow>,
+    size: usize,
+) -> Result<usize> {
+    if size < size_of::<OpenHow>() {
+        return Err(KernelError::InvalidValue);
+    }
+
+    if size > PAGE_SIZE {
+        return Err(KernelError::TooLarge);
+    }
+
+    // Fields from a newer version of the struct than ours must be unset.
+    let mut chunk = [0; 64];
+    let mut offset = size_of::<OpenHow>();
+
+    while offset < size {
+        let len = chunk.len().min(size - offset);
+
+        copy_from_user_slice(how.to_untyped().add_bytes(offset), &mut chunk[..len]).await?;
+
+        if chunk[..len].iter().any(|b| *b != 0) {
+            return Err(KernelError::TooLarge);
+        }
+
+        offset += len;
+    }
+
+    let how = copy_from_user(how).await?;
+
+    if how.flags & !VALID_OPEN_FLAGS != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    // Unlike `openat()`, a mode is only accepted if a file may be created.
+    let creat = (OpenFlags::O_CREAT | OpenFlags::O_TMPFILE).bits() as u64;
+    if how.mode & !0o7777 != 0 || (how.mode != 0 && how.flags & creat == 0) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    // Nor are flags that `O_PATH` would have ignored.
+    if how.flags & OpenFlags::O_PATH.bits() as u64 != 0
+        && how.flags & !(O_PATH_FLAGS.bits() as u64) != 0
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let resolve = ResolveFlags::from_bits(how.resolve).ok_or(KernelError::InvalidValue)?;
+
+    if resolve.contains(ResolveFlags::RESOLVE_BENEATH | ResolveFlags::RESOLVE_IN_ROOT) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    // Lookups may always block here, which callers are told to handle by
+    // retrying without the flag.
+    if resolve.contains(ResolveFlags::RESOLVE_CACHED) {
+        return Err(KernelError::TryAgain);
+    }
+
+    do_openat(
+        ctx,
+        dirfd,
+        path,
+        OpenFlags::from_bits_truncate(how.flags as u32),
+        resolve,
+        how.mode as u16,
+    )
+    .await
+}
+
+async fn do_openat(
+    ctx: &ProcessCtx,
+    dirfd: Fd,
+    path: TUA<c_char>,
+    flags: OpenFlags,
+    resolve: ResolveFlags,
+    mode: u16,
 ) -> Result<usize> {
     let mut buf = [0; 1024];
 
+    let flags = if flags.contains(OpenFlags::O_TMPFILE) {
+        // An anonymous file has to be written to, and can't also be created
+        // by name.
+        if !flags.contains(OpenFlags::O_DIRECTORY)
+            || flags.contains(OpenFlags::O_CREAT)
+            || flags & OpenFlags::O_ACCMODE == OpenFlags::O_RDONLY
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        flags
+    } else if flags.contains(OpenFlags::O_PATH) {
+        flags & O_PATH_FLAGS
+    } else {
+        flags
+    };
+
     let task = ctx.shared().clone();
-    let flags = OpenFlags::from_bits_truncate(flags);
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let start_node = resolve_at_start_node(ctx, dirfd, path, AtFlags::empty()).await?;
+
+    // An absolute path is still confined to `dirfd` by these.
+    let start_path =
+        if resolve.intersects(ResolveFlags::RESOLVE_BENEATH | ResolveFlags::RESOLVE_IN_ROOT) {
+            Path::new(".")
+        } else {
+            path
+        };
+
+    let start_node = resolve_at_start_node(ctx, dirfd, start_path, AtFlags::empty()).await?;
     let mode = FilePermissions::from_bits_retain(mode);
 
-    let file = VFS.open(path, flags, start_node, mode, &task).await?;
+    let file = VFS
+        .open(path, flags, resolve, start_node, mode, &task)
+        .await?;
 
-    let fd = task.fd_table.lock_save_irq().insert(file)?;
+    let fd_flags = if flags.contains(OpenFlags::O_CLOEXEC) {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+    let fd = task
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(file, fd_flags)?;
 
     Ok(fd.as_raw() as _)
 }
@@ -1,7 +1,7 @@
 use crate::{
     fs::{
         VFS,
-        syscalls::at::{AtFlags, resolve_at_start_node},
+        syscalls::at::{AtFlags, resolve_at_start_node, resolve_path_flags},
     },
     memory::uaccess::{copy_to_user_slice, cstr::UserCStr},
     process::fd_table::Fd,
@@ -9,7 +9,7 @@ use crate::{
 };
 use core::{cmp::min, ffi::c_char};
 use libkernel::{
-    error::{FsError, Result},
+    error::{FsError, KernelError, Result},
     fs::{FileType, path::Path},
     memory::address::{TUA, UA},
 };
@@ -23,6 +23,10 @@ pub async fn sys_readlinkat(
 ) -> Result<usize> {
     let mut path_buf = [0; 1024];
 
+    if size == 0 || size > isize::MAX as usize {
+        return Err(KernelError::InvalidValue);
+    }
+
     let task = ctx.shared().clone();
     let path = Path::new(
         UserCStr::from_ptr(path)
@@ -30,16 +34,19 @@ pub async fn sys_readlinkat(
             .await?,
     );
 
-    let start = resolve_at_start_node(ctx, dirfd, path, AtFlags::empty()).await?;
-    let name = path.file_name().ok_or(FsError::InvalidInput)?;
+    let inode = if path.as_str().is_empty() {
+        // An empty path reads the link that `dirfd` itself was opened on,
+        // with `O_PATH | O_NOFOLLOW`.
+        if dirfd.is_atcwd() {
+            return Err(FsError::NotFound.into());
+        }
 
-    let parent = if let Some(p) = path.parent() {
-        VFS.resolve_path_nofollow(p, start.clone(), &task).await?
+        let start = resolve_at_start_node(ctx, dirfd, path, AtFlags::AT_EMPTY_PATH).await?;
+        resolve_path_flags(dirfd, path, start, &task, AtFlags::AT_EMPTY_PATH).await?
     } else {
-        start
+        let start = resolve_at_start_node(ctx, dirfd, path, AtFlags::empty()).await?;
+        VFS.resolve_path_nofollow(path, start, &task).await?
     };
-
-    let inode = parent.lookup(name).await?;
     let attr = inode.getattr().await?;
 
     if attr.file_type != FileType::Symlink {
@@ -44,7 +44,7 @@ pub async fn sys_renameat2(
     let exchange = flags & AT_RENAME_EXCHANGE != 0;
     let whiteout = flags & AT_RENAME_WHITEOUT != 0; // TODO: implement whiteout, at some point
 
-    if whiteout {
+    if whiteout || flags & !(AT_RENAME_NOREPLACE | AT_RENAME_EXCHANGE | AT_RENAME_WHITEOUT) != 0 {
         return Err(KernelError::InvalidValue);
     }
 
@@ -6,7 +6,7 @@ use crate::{
 };
 use core::ffi::c_char;
 use libkernel::{
-    error::{KernelError, Result},
+    error::Result,
     fs::{attr::FileAttr, path::Path},
     memory::address::TUA,
 };
@@ -49,12 +49,12 @@ impl From<FileAttr> for Stat {
             st_nlink: value.nlinks,
             st_uid: value.uid.into(),
             st_gid: value.gid.into(),
-            st_rdev: 0,
+            st_rdev: value.file_type.device().map_or(0, |dev| dev.encode()),
             __pad1: 0,
             st_size: value.size as _,
             st_blksize: value.block_size as _,
             __pad2: 0,
-            st_blocks: 0,
+            st_blocks: value.blocks as _,
             st_atime: value.atime.as_secs() as _,
             st_atime_nsec: value.atime.subsec_nanos() as _,
             st_mtime: value.mtime.as_secs() as _,
@@ -80,11 +80,7 @@ pub async fn sys_newfstatat(
     let flags = AtFlags::from_bits_truncate(flags);
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
 
-    let start_node = match resolve_at_start_node(ctx, dirfd, path, flags).await {
-        Ok(node) => node,
-        Err(err) if err != KernelError::NotSupported => panic!("{err}"),
-        Err(err) => return Err(err),
-    };
+    let start_node = resolve_at_start_node(ctx, dirfd, path, flags).await?;
     let node = resolve_path_flags(dirfd, path, start_node, &task, flags).await?;
 
     let attr = node.getattr().await?;
@@ -93,3 +89,32 @@ pub async fn sys_newfstatat(
 
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Stat;
+    use core::mem::{offset_of, size_of};
+    use moss_macros::ktest;
+
+    // The generic `struct stat` that arm64 uses.
+    #[ktest]
+    fn stat_layout() {
+        assert_eq!(size_of::<Stat>(), 128);
+        assert_eq!(offset_of!(Stat, st_dev), 0);
+        assert_eq!(offset_of!(Stat, st_ino), 8);
+        assert_eq!(offset_of!(Stat, st_mode), 16);
+        assert_eq!(offset_of!(Stat, st_nlink), 20);
+        assert_eq!(offset_of!(Stat, st_uid), 24);
+        assert_eq!(offset_of!(Stat, st_gid), 28);
+        assert_eq!(offset_of!(Stat, st_rdev), 32);
+        assert_eq!(offset_of!(Stat, st_size), 48);
+        assert_eq!(offset_of!(Stat, st_blksize), 56);
+        assert_eq!(offset_of!(Stat, st_blocks), 64);
+        assert_eq!(offset_of!(Stat, st_atime), 72);
+        assert_eq!(offset_of!(Stat, st_atime_nsec), 80);
+        assert_eq!(offset_of!(Stat, st_mtime), 88);
+        assert_eq!(offset_of!(Stat, st_mtime_nsec), 96);
+        assert_eq!(offset_of!(Stat, st_ctime), 104);
+        assert_eq!(offset_of!(Stat, st_ctime_nsec), 112);
+    }
+}
@@ -70,13 +70,15 @@ pub struct StatX {
     pub stx_btime: StatXTimestamp, // Creation time
     pub stx_ctime: StatXTimestamp, // Change time
     pub stx_mtime: StatXTimestamp, // Modification time
-    pub stx_mnt_id: u64,           // Mount ID
+
+    pub stx_rdev_major: u32, // Device major ID
+    pub stx_rdev_minor: u32, // Device minor ID
 
     // Currently not supported on any current filesystems
-    pub stx_rdev_major: u32,                // Device major ID
-    pub stx_rdev_minor: u32,                // Device minor ID
-    pub stx_dev_major: u32,                 // Filesystem major ID
-    pub stx_dev_minor: u32,                 // Filesystem minor ID
+    pub stx_dev_major: u32, // Filesystem major ID
+    pub stx_dev_minor: u32, // Filesystem minor ID
+
+    pub stx_mnt_id: u64,                    // Mount ID
     pub stx_dio_mem_align: u32,             // Alignment of memory for direct I/O
     pub stx_dio_offset_align: u32,          // Alignment of offset for direct I/O
     pub stx_subvol: u64,                    // Subvolume ID
@@ -87,12 +89,8 @@ pub struct StatX {
     pub stx_atomic_write_unit_max_opt: u32, // Maximum size optimized for atomic writes
 
     // Unused
-    pub __unused1: u64,
-    pub __unused2: u64,
-    pub __unused3: u64,
-    pub __unused4: u64,
-    pub __unused5: u64,
-    pub __unused6: u64,
+    pub __spare2: u32,
+    pub __spare3: [u64; 8],
 }
 
 #[repr(C)]
@@ -205,8 +203,14 @@ pub async fn sys_statx(
         stat_x.stx_mnt_id = attr.id.fs_id();
     }
 
+    if let Some(dev) = attr.file_type.device() {
+        stat_x.stx_rdev_major = dev.major as u32;
+        stat_x.stx_rdev_minor = dev.minor as u32;
+    }
+
     stat_x.stx_attributes_mask = StatXAttr::STATX_ATTR_MOUNT_ROOT.bits();
-    if VFS.is_mount_root(attr.id) {
+    let mnt_ns = task.mnt_ns.lock_save_irq().clone();
+    if VFS.is_mount_root(&mnt_ns, attr.id) {
         stat_x.stx_attributes |= StatXAttr::STATX_ATTR_MOUNT_ROOT.bits();
     }
 
@@ -214,3 +218,56 @@ pub async fn sys_statx(
 
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{StatX, StatXAttr, StatXMask, StatXTimestamp};
+    use core::mem::{offset_of, size_of};
+    use moss_macros::ktest;
+
+    // `struct statx`, which is the same on every architecture.
+    #[ktest]
+    fn statx_layout() {
+        assert_eq!(size_of::<StatX>(), 256);
+        assert_eq!(size_of::<StatXTimestamp>(), 16);
+        assert_eq!(offset_of!(StatX, stx_mask), 0);
+        assert_eq!(offset_of!(StatX, stx_blksize), 4);
+        assert_eq!(offset_of!(StatX, stx_attributes), 8);
+        assert_eq!(offset_of!(StatX, stx_nlink), 16);
+        assert_eq!(offset_of!(StatX, stx_uid), 20);
+        assert_eq!(offset_of!(StatX, stx_gid), 24);
+        assert_eq!(offset_of!(StatX, stx_mode), 28);
+        assert_eq!(offset_of!(StatX, stx_ino), 32);
+        assert_eq!(offset_of!(StatX, stx_size), 40);
+        assert_eq!(offset_of!(StatX, stx_blocks), 48);
+        assert_eq!(offset_of!(StatX, stx_attributes_mask), 56);
+        assert_eq!(offset_of!(StatX, stx_atime), 64);
+        assert_eq!(offset_of!(StatX, stx_btime), 80);
+        assert_eq!(offset_of!(StatX, stx_ctime), 96);
+        assert_eq!(offset_of!(StatX, stx_mtime), 112);
+        assert_eq!(offset_of!(StatX, stx_rdev_major), 128);
+        assert_eq!(offset_of!(StatX, stx_rdev_minor), 132);
+        assert_eq!(offset_of!(StatX, stx_dev_major), 136);
+        assert_eq!(offset_of!(StatX, stx_dev_minor), 140);
+        assert_eq!(offset_of!(StatX, stx_mnt_id), 144);
+        assert_eq!(offset_of!(StatX, stx_dio_mem_align), 152);
+        assert_eq!(offset_of!(StatX, stx_dio_offset_align), 156);
+        assert_eq!(offset_of!(StatX, stx_subvol), 160);
+        assert_eq!(offset_of!(StatX, stx_atomic_write_unit_min), 168);
+        assert_eq!(offset_of!(StatX, stx_atomic_write_unit_max), 172);
+        assert_eq!(offset_of!(StatX, stx_atomic_write_segments_max), 176);
+        assert_eq!(offset_of!(StatX, stx_dio_read_offset_align), 180);
+        assert_eq!(offset_of!(StatX, stx_atomic_write_unit_max_opt), 184);
+    }
+
+    #[ktest]
+    fn statx_flag_values() {
+        assert_eq!(StatXMask::STATX_BASIC_STATS.bits(), 0x07ff);
+        assert_eq!(StatXMask::STATX_BTIME.bits(), 0x0800);
+        assert_eq!(StatXMask::STATX_MNT_ID.bits(), 0x1000);
+        assert_eq!(StatXAttr::STATX_ATTR_IMMUTABLE.bits(), 0x0010);
+        assert_eq!(StatXAttr::STATX_ATTR_APPEND.bits(), 0x0020);
+        assert_eq!(StatXAttr::STATX_ATTR_MOUNT_ROOT.bits(), 0x2000);
+        assert_eq!(StatXAttr::STATX_ATTR_VERITY.bits(), 0x100000);
+    }
+}
@@ -12,13 +12,16 @@ use crate::{
     sched::syscall_ctx::ProcessCtx,
 };
 
+/// The longest link target, including its NUL, as on Linux.
+const SYMLINK_MAX: usize = 4096;
+
 pub async fn sys_symlinkat(
     ctx: &ProcessCtx,
     old_name: TUA<c_char>,
     new_dirfd: Fd,
     new_name: TUA<c_char>,
 ) -> Result<usize> {
-    let mut buf = [0; 1024];
+    let mut buf = [0; SYMLINK_MAX];
     let mut buf2 = [0; 1024];
 
     let task = ctx.shared().clone();
@@ -1,31 +1,47 @@
 use crate::{
     fs::VFS,
     memory::uaccess::{copy_to_user_slice, cstr::UserCStr},
-    process::fd_table::Fd,
+    process::{Task, fd_table::Fd},
     sched::syscall_ctx::ProcessCtx,
 };
-use alloc::{borrow::ToOwned, ffi::CString, string::ToString};
+use alloc::{borrow::ToOwned, ffi::CString, sync::Arc};
 use core::{ffi::c_char, str::FromStr};
 use libkernel::{
-    error::{KernelError, Result},
-    fs::path::Path,
+    error::{FsError, KernelError, Result},
+    fs::{FileType, Inode, attr::AccessMode, path::Path},
     memory::address::{TUA, UA},
     proc::caps::CapabilitiesFlags,
 };
 
 pub async fn sys_getcwd(ctx: &ProcessCtx, buf: UA, len: usize) -> Result<usize> {
     let task = ctx.shared().clone();
-    let path = task.cwd.lock_save_irq().1.as_str().to_string();
-    let cstr = CString::from_str(&path).map_err(|_| KernelError::InvalidValue)?;
+    let cwd = task.cwd.lock_save_irq().0.clone();
+    let path = VFS.path_of(cwd, &task).await?;
+    let cstr = CString::from_str(path.as_str()).map_err(|_| KernelError::InvalidValue)?;
     let slice = cstr.as_bytes_with_nul();
 
     if slice.len() > len {
-        return Err(KernelError::TooLarge);
+        return Err(KernelError::RangeError);
     }
 
     copy_to_user_slice(slice, buf).await?;
 
-    Ok(buf.value())
+    Ok(slice.len())
+}
+
+/// Checks that `inode` is a directory `task` may search, so that it can become
+/// the working directory.
+async fn check_chdir(task: &Arc<Task>, inode: &Arc<dyn Inode>) -> Result<()> {
+    let attr = inode.getattr().await?;
+
+    if attr.file_type != FileType::Directory {
+        return Err(FsError::NotADirectory.into());
+    }
+
+    let creds = task.creds.lock_save_irq();
+
+    attr.check_access(creds.euid(), creds.egid(), creds.caps(), AccessMode::X_OK)
+        .map_err(|_| FsError::PermissionDenied.into())
 }
 
 pub async fn sys_chdir(ctx: &ProcessCtx, path: TUA<c_char>) -> Result<usize> {
@@ -37,12 +53,12 @@ pub async fn sys_chdir(ctx: &ProcessCtx, path: TUA<c_char>) -> Result<usize> {
     let new_path = task.cwd.lock_save_irq().1.join(path);
 
     let node = VFS.resolve_path(path, current_path, &task).await?;
+    check_chdir(&task, &node).await?;
 
     *task.cwd.lock_save_irq() = (node, new_path);
 
     Ok(0)
 }
-
 pub async fn sys_chroot(ctx: &ProcessCtx, path: TUA<c_char>) -> Result<usize> {
     let task = ctx.shared().clone();
     task.creds
@@ -68,13 +84,15 @@ pub async fn sys_fchdir(ctx: &ProcessCtx, fd: Fd) -> Result<usize> {
     let file = task
         .fd_table
         .lock_save_irq()
-        .get(fd)
+        .get_raw(fd)
         .ok_or(KernelError::BadFd)?;
+    let inode = file.inode().ok_or(FsError::NotADirectory)?;
+
+    check_chdir(&task, &inode).await?;
 
-    *task.cwd.lock_save_irq() = (
-        file.inode().ok_or(KernelError::BadFd)?,
-        file.path().ok_or(KernelError::BadFd)?.to_owned(),
-    );
+    // The path is only a record of how the directory was opened; getcwd()
+    // works out where it is now.
+    *task.cwd.lock_save_irq() = (inode, file.path().unwrap_or(Path::new("")).to_owned());
 
     Ok(0)
 }
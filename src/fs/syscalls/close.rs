@@ -1,8 +1,31 @@
-use crate::{process::fd_table::Fd, sched::syscall_ctx::ProcessCtx};
+use crate::{
+    fs::{
+        lock::{self, LockOwner},
+        open_file::OpenFile,
+    },
+    process::fd_table::{Fd, FdFlags},
+    sched::syscall_ctx::ProcessCtx,
+};
 use alloc::sync::Arc;
 use bitflags::bitflags;
 use libkernel::error::{KernelError, Result};
 
+/// Finishes closing a descriptor for `file` once it has been taken out of
+/// the fd table, releasing the file itself if that was the last reference.
+pub async fn release_file(ctx: &ProcessCtx, file: Arc<OpenFile>) -> Result<()> {
+    // Closing any descriptor for a file drops all of the process's record
+    // locks on it.
+    if let Some(inode) = file.inode() {
+        lock::release(inode.id(), LockOwner::Process(ctx.shared().process.tgid));
+    }
+
+    if let Some(file) = Arc::into_inner(file) {
+        let (ops, ctx) = &mut *file.lock().await;
+        ops.release(ctx).await?;
+    }
+    Ok(())
+}
+
 async fn close(ctx: &ProcessCtx, fd: Fd) -> Result<()> {
     let file = ctx
         .shared()
@@ -11,11 +34,7 @@ async fn close(ctx: &ProcessCtx, fd: Fd) -> Result<()> {
         .remove(fd)
         .ok_or(KernelError::BadFd)?;
 
-    if let Some(file) = Arc::into_inner(file) {
-        let (ops, ctx) = &mut *file.lock().await;
-        ops.release(ctx).await?;
-    }
-    Ok(())
+    release_file(ctx, file).await
 }
 
 pub async fn sys_close(ctx: &ProcessCtx, fd: Fd) -> Result<usize> {
@@ -30,17 +49,44 @@ bitflags! {
     }
 }
 
-pub async fn sys_close_range(ctx: &ProcessCtx, first: Fd, last: Fd, flags: i32) -> Result<usize> {
-    let flags = CloseRangeFlags::from_bits_truncate(flags);
-    if flags.contains(CloseRangeFlags::CLOSE_RANGE_UNSHARE) {
-        todo!("Implement CLOSE_RANGE_UNSHARE");
+/// Closes every open descriptor from `first` to `last`, or with
+/// `CLOSE_RANGE_CLOEXEC` marks them close-on-exec instead. Descriptors in the
+/// range that aren't open are skipped.
+///
+/// The fd table can't yet be swapped for a private copy, so
+/// `CLOSE_RANGE_UNSHARE` is only accepted when the table isn't shared.
+pub async fn sys_close_range(ctx: &ProcessCtx, first: u32, last: u32, flags: i32) -> Result<usize> {
+    let flags = CloseRangeFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
+
+    if first > last {
+        return Err(KernelError::InvalidValue);
     }
-    if flags.contains(CloseRangeFlags::CLOSE_RANGE_CLOEXEC) {
-        todo!("Implement CLOSE_RANGE_CLOEXEC");
+
+    let task = ctx.shared();
+
+    if flags.contains(CloseRangeFlags::CLOSE_RANGE_UNSHARE) && Arc::strong_count(&task.fd_table) > 1
+    {
+        return Err(KernelError::NotSupported);
     }
 
-    for i in first.as_raw()..=last.as_raw() {
-        close(ctx, Fd(i)).await?;
+    let fds = {
+        let mut fd_table = task.fd_table.lock_save_irq();
+        let fds = fd_table.open_fds(first as usize..=last as usize);
+
+        if flags.contains(CloseRangeFlags::CLOSE_RANGE_CLOEXEC) {
+            for fd in fds {
+                fd_table.add_flags(fd, FdFlags::CLOEXEC)?;
+            }
+
+            return Ok(0);
+        }
+
+        fds
+    };
+
+    for fd in fds {
+        close(ctx, fd).await?;
     }
+
     Ok(0)
 }
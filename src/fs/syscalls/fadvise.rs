@@ -0,0 +1,59 @@
+use crate::{process::fd_table::Fd, sched::syscall_ctx::ProcessCtx};
+use libkernel::{
+    error::{KernelError, Result},
+    fs::{FileType, readahead::ReadaheadAdvice},
+};
+
+const POSIX_FADV_NORMAL: u32 = 0;
+const POSIX_FADV_RANDOM: u32 = 1;
+const POSIX_FADV_SEQUENTIAL: u32 = 2;
+const POSIX_FADV_WILLNEED: u32 = 3;
+const POSIX_FADV_DONTNEED: u32 = 4;
+const POSIX_FADV_NOREUSE: u32 = 5;
+
+pub async fn sys_fadvise64_64(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    offset: i64,
+    len: i64,
+    advice: u32,
+) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+
+    let inode = file.inode().ok_or(KernelError::SeekPipe)?;
+
+    if inode.getattr().await?.file_type == FileType::Fifo {
+        return Err(KernelError::SeekPipe);
+    }
+
+    if offset < 0 || len < 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let ra_advice = match advice {
+        POSIX_FADV_NORMAL => ReadaheadAdvice::Normal,
+        POSIX_FADV_RANDOM => ReadaheadAdvice::Random,
+        POSIX_FADV_SEQUENTIAL => ReadaheadAdvice::Sequential,
+        POSIX_FADV_WILLNEED => {
+            // A length of zero means "to the end of the file".
+            let len = if len == 0 { u64::MAX } else { len as u64 };
+
+            // Like the advice itself, a failure to act on it isn't reported.
+            let _ = inode.readahead(offset as u64, len).await;
+
+            return Ok(0);
+        }
+        // We don't keep file data around beyond the block cache.
+        POSIX_FADV_DONTNEED | POSIX_FADV_NOREUSE => return Ok(0),
+        _ => return Err(KernelError::InvalidValue),
+    };
+
+    file.lock().await.1.ra.set_advice(ra_advice);
+
+    Ok(0)
+}
@@ -0,0 +1,54 @@
+use crate::{process::fd_table::Fd, sched::syscall_ctx::ProcessCtx};
+use libkernel::{
+    error::{FsError, KernelError, Result},
+    fs::{FallocMode, FileType, OpenFlags},
+};
+
+const FALLOC_FL_KEEP_SIZE: u32 = 0x01;
+const FALLOC_FL_PUNCH_HOLE: u32 = 0x02;
+
+pub async fn sys_fallocate(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    mode: u32,
+    offset: i64,
+    len: i64,
+) -> Result<usize> {
+    let mode = match mode {
+        0 => FallocMode::Allocate { keep_size: false },
+        FALLOC_FL_KEEP_SIZE => FallocMode::Allocate { keep_size: true },
+        // A hole never changes the size, and has to be asked for that way.
+        m if m == FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE => FallocMode::PunchHole,
+        // Collapsing, zeroing and inserting ranges aren't supported.
+        _ => return Err(KernelError::OpNotSupported),
+    };
+
+    if offset < 0 || len <= 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+
+    if file.flags().await.intersection(OpenFlags::O_ACCMODE) == OpenFlags::O_RDONLY {
+        return Err(KernelError::BadFd);
+    }
+
+    let inode = file.inode().ok_or(KernelError::SeekPipe)?;
+
+    match inode.getattr().await?.file_type {
+        FileType::Fifo => return Err(KernelError::SeekPipe),
+        FileType::Directory => return Err(FsError::IsADirectory.into()),
+        _ => {}
+    }
+
+    let (ops, ctx) = &mut *file.lock().await;
+
+    ops.fallocate(ctx, mode, offset as u64, len as u64)
+        .await
+        .map(|_| 0)
+}
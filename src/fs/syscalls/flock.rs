@@ -0,0 +1,40 @@
+use crate::{
+    fs::lock::{LockKind, set_lock},
+    process::fd_table::Fd,
+    sched::syscall_ctx::ProcessCtx,
+};
+use libkernel::error::{KernelError, Result};
+
+const LOCK_SH: u32 = 1; // Shared lock.
+const LOCK_EX: u32 = 2; // Exclusive lock.
+const LOCK_NB: u32 = 4; // Don't block when locking.
+const LOCK_UN: u32 = 8; // Unlock.
+
+pub async fn sys_flock(ctx: &ProcessCtx, fd: Fd, op: u32) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+    let inode = file.inode().ok_or(KernelError::InvalidValue)?;
+
+    let kind = match op & !LOCK_NB {
+        LOCK_SH => Some(LockKind::Shared),
+        LOCK_EX => Some(LockKind::Exclusive),
+        LOCK_UN => None,
+        _ => return Err(KernelError::InvalidValue),
+    };
+
+    set_lock(
+        inode.id(),
+        file.lock_owner(),
+        kind,
+        0,
+        u64::MAX,
+        op & LOCK_NB == 0,
+    )
+    .await?;
+
+    Ok(0)
+}
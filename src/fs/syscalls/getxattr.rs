@@ -1,4 +1,4 @@
-use crate::fs::VFS;
+use super::xattr::{check_xattr_access, no_such_xattr, resolve_xattr_path};
 use crate::memory::uaccess::copy_to_user_slice;
 use crate::memory::uaccess::cstr::UserCStr;
 use crate::process::fd_table::Fd;
@@ -10,8 +10,22 @@ use libkernel::fs::Inode;
 use libkernel::fs::path::Path;
 use libkernel::memory::address::{TUA, UA};
 
-async fn getxattr(node: Arc<dyn Inode>, name: &str, ua: UA, size: usize) -> Result<usize> {
-    let value = node.getxattr(name).await?;
+async fn getxattr(
+    ctx: &ProcessCtx,
+    node: Arc<dyn Inode>,
+    name: &str,
+    ua: UA,
+    size: usize,
+) -> Result<usize> {
+    check_xattr_access(&ctx.shared().clone(), &node, name, false).await?;
+
+    let value = node.getxattr(name).await.map_err(no_such_xattr)?;
+
+    // A zero size asks how big the value is.
+    if size == 0 {
+        return Ok(value.len());
+    }
+
     if size < value.len() {
         Err(KernelError::RangeError)
     } else {
@@ -30,11 +44,10 @@ pub async fn sys_getxattr(
     let mut buf = [0; 1024];
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let task = ctx.shared().clone();
-
-    let node = VFS.resolve_path(path, VFS.root_inode(), &task).await?;
+    let node = resolve_xattr_path(ctx, path, false).await?;
     let mut buf = [0; 1024];
     getxattr(
+        ctx,
         node,
         UserCStr::from_ptr(name).copy_from_user(&mut buf).await?,
         value,
@@ -53,13 +66,10 @@ pub async fn sys_lgetxattr(
     let mut buf = [0; 1024];
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let task = ctx.shared().clone();
-
-    let node = VFS
-        .resolve_path_nofollow(path, VFS.root_inode(), &task)
-        .await?;
+    let node = resolve_xattr_path(ctx, path, true).await?;
     let mut buf = [0; 1024];
     getxattr(
+        ctx,
         node,
         UserCStr::from_ptr(name).copy_from_user(&mut buf).await?,
         value,
@@ -87,6 +97,7 @@ pub async fn sys_fgetxattr(
     };
     let mut buf = [0; 1024];
     getxattr(
+        ctx,
         node,
         UserCStr::from_ptr(name).copy_from_user(&mut buf).await?,
         value,
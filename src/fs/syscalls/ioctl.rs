@@ -1,5 +1,13 @@
-use crate::{process::fd_table::Fd, sched::syscall_ctx::ProcessCtx};
-use libkernel::error::{KernelError, Result};
+use crate::{fs::VFS, process::fd_table::Fd, sched::syscall_ctx::ProcessCtx};
+use libkernel::{
+    error::{KernelError, Result},
+    proc::caps::CapabilitiesFlags,
+};
+
+/// Freeze the filesystem containing the file, `_IOWR('X', 119, int)`.
+const FIFREEZE: usize = 0xc0045877;
+/// Thaw a frozen filesystem, `_IOWR('X', 120, int)`.
+const FITHAW: usize = 0xc0045878;
 
 pub async fn sys_ioctl(ctx: &ProcessCtx, fd: Fd, request: usize, arg: usize) -> Result<usize> {
     let fd = ctx
@@ -9,6 +17,26 @@ pub async fn sys_ioctl(ctx: &ProcessCtx, fd: Fd, request: usize, arg: usize) ->
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
+    // Filesystem-wide requests are handled by the VFS rather than being passed
+    // down to the file.
+    if matches!(request, FIFREEZE | FITHAW) {
+        ctx.shared()
+            .creds
+            .lock_save_irq()
+            .caps()
+            .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)?;
+
+        let inode = fd.inode().ok_or(KernelError::NotSupported)?;
+
+        if request == FIFREEZE {
+            VFS.freeze(inode).await?;
+        } else {
+            VFS.thaw(inode)?;
+        }
+
+        return Ok(0);
+    }
+
     let (ops, ctx) = &mut *fd.lock().await;
     ops.ioctl(ctx, request, arg).await
 }
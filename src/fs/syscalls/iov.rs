@@ -1,13 +1,21 @@
+use super::splice::MAX_RW_COUNT;
 use crate::{
     memory::uaccess::{UserCopyable, copy_obj_array_from_user},
     process::fd_table::Fd,
     sched::syscall_ctx::ProcessCtx,
 };
+use alloc::vec::Vec;
 use libkernel::{
     error::{KernelError, Result},
     memory::address::{TUA, UA},
 };
 
+/// The most iovecs a single call takes, as on Linux.
+const IOV_MAX: usize = 1024;
+
+/// A high priority request, which is only a hint.
+const RWF_HIPRI: u32 = 0x1;
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub struct IoVec {
@@ -18,6 +26,43 @@ pub struct IoVec {
 // SAFETY: An IoVec is safe to copy to-and-from userspace.
 unsafe impl UserCopyable for IoVec {}
 
+/// Copies in the iovecs for a vectored transfer, checking there aren't too
+/// many and that no length is negative. Like a plain read or write, the
+/// transfer is cut short at `MAX_RW_COUNT` bytes.
+pub async fn copy_iovecs_from_user(iov_ptr: TUA<IoVec>, no_iov: usize) -> Result<Vec<IoVec>> {
+    if no_iov > IOV_MAX {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let mut iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;
+    let mut total = 0;
+
+    for iov in iovs.iter_mut() {
+        if iov.iov_len > isize::MAX as usize {
+            return Err(KernelError::InvalidValue);
+        }
+
+        iov.iov_len = iov.iov_len.min(MAX_RW_COUNT - total);
+        total += iov.iov_len;
+    }
+
+    Ok(iovs)
+}
+
+/// Checks the flags and offset given to one of the positioned calls. Returns
+/// `None` for an offset of -1, which means the file position is used instead.
+fn positioned_offset(offset: u64, flags: u32) -> Result<Option<u64>> {
+    if flags & !RWF_HIPRI != 0 {
+        return Err(KernelError::OpNotSupported);
+    }
+
+    match offset as i64 {
+        -1 => Ok(None),
+        ..0 => Err(KernelError::InvalidValue),
+        _ => Ok(Some(offset)),
+    }
+}
+
 pub async fn sys_writev(
     ctx: &ProcessCtx,
     fd: Fd,
@@ -31,7 +76,7 @@ pub async fn sys_writev(
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
-    let iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;
+    let iovs = copy_iovecs_from_user(iov_ptr, no_iov).await?;
 
     let (ops, state) = &mut *file.lock().await;
 
@@ -51,7 +96,7 @@ pub async fn sys_readv(
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
-    let iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;
+    let iovs = copy_iovecs_from_user(iov_ptr, no_iov).await?;
 
     let (ops, state) = &mut *file.lock().await;
 
@@ -65,6 +110,11 @@ pub async fn sys_pwritev(
     no_iov: usize,
     offset: u64,
 ) -> Result<usize> {
+    // Only the `2` variants take -1 to mean the file position.
+    if (offset as i64) < 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
     sys_pwritev2(ctx, fd, iov_ptr, no_iov, offset, 0).await
 }
 
@@ -75,6 +125,10 @@ pub async fn sys_preadv(
     no_iov: usize,
     offset: u64,
 ) -> Result<usize> {
+    if (offset as i64) < 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
     sys_preadv2(ctx, fd, iov_ptr, no_iov, offset, 0).await
 }
 
@@ -84,8 +138,9 @@ pub async fn sys_pwritev2(
     iov_ptr: TUA<IoVec>,
     no_iov: usize,
     offset: u64,
-    _flags: u32, // TODO: implement these flags
+    flags: u32,
 ) -> Result<usize> {
+    let offset = positioned_offset(offset, flags)?;
     let file = ctx
         .shared()
         .fd_table
@@ -93,11 +148,15 @@ pub async fn sys_pwritev2(
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
-    let iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;
+    let iovs = copy_iovecs_from_user(iov_ptr, no_iov).await?;
 
-    let (ops, _state) = &mut *file.lock().await;
+    let (ops, state) = &mut *file.lock().await;
 
-    ops.writevat(&iovs, offset).await
+    match offset {
+        Some(_) if ops.is_stream() => Err(KernelError::SeekPipe),
+        Some(offset) => ops.writevat(&iovs, offset).await,
+        None => ops.writev(state, &iovs).await,
+    }
 }
 
 pub async fn sys_preadv2(
@@ -106,8 +165,9 @@ pub async fn sys_preadv2(
     iov_ptr: TUA<IoVec>,
     no_iov: usize,
     offset: u64,
-    _flags: u32,
+    flags: u32,
 ) -> Result<usize> {
+    let offset = positioned_offset(offset, flags)?;
     let file = ctx
         .shared()
         .fd_table
@@ -115,9 +175,13 @@ pub async fn sys_preadv2(
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
-    let iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;
+    let iovs = copy_iovecs_from_user(iov_ptr, no_iov).await?;
 
-    let (ops, _state) = &mut *file.lock().await;
+    let (ops, state) = &mut *file.lock().await;
 
-    ops.readvat(&iovs, offset).await
+    match offset {
+        Some(_) if ops.is_stream() => Err(KernelError::SeekPipe),
+        Some(offset) => ops.readvat(&iovs, offset).await,
+        None => ops.readv(state, &iovs).await,
+    }
 }
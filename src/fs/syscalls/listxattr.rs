@@ -1,19 +1,33 @@
-use crate::fs::VFS;
+use super::xattr::{resolve_xattr_path, xattr_listable};
 use crate::memory::uaccess::copy_to_user_slice;
 use crate::memory::uaccess::cstr::UserCStr;
 use crate::process::fd_table::Fd;
 use crate::sched::syscall_ctx::ProcessCtx;
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 use libkernel::error::{KernelError, Result};
 use libkernel::fs::Inode;
 use libkernel::fs::path::Path;
 use libkernel::memory::address::{TUA, UA};
 
-async fn listxattr(node: Arc<dyn Inode>, ua: UA, size: usize) -> Result<usize> {
-    let list = node.listxattr().await?;
-    // Join with \0
-    let list = list.join("\0");
-    let list_bytes = list.as_bytes();
+async fn listxattr(ctx: &ProcessCtx, node: Arc<dyn Inode>, ua: UA, size: usize) -> Result<usize> {
+    let task = ctx.shared().clone();
+    let mut list = Vec::new();
+
+    // Each name is NUL-terminated.
+    for name in node.listxattr().await? {
+        if xattr_listable(&task, &name) {
+            list.extend_from_slice(name.as_bytes());
+            list.push(0);
+        }
+    }
+
+    let list_bytes = &list[..];
+
+    // A zero size asks how big the list is.
+    if size == 0 {
+        return Ok(list_bytes.len());
+    }
+
     if size < list_bytes.len() {
         Err(KernelError::RangeError)
     } else {
@@ -31,10 +45,8 @@ pub async fn sys_listxattr(
     let mut buf = [0; 1024];
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let task = ctx.shared().clone();
-
-    let node = VFS.resolve_path(path, VFS.root_inode(), &task).await?;
-    listxattr(node, list, size).await
+    let node = resolve_xattr_path(ctx, path, false).await?;
+    listxattr(ctx, node, list, size).await
 }
 
 pub async fn sys_llistxattr(
@@ -46,12 +58,8 @@ pub async fn sys_llistxattr(
     let mut buf = [0; 1024];
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let task = ctx.shared().clone();
-
-    let node = VFS
-        .resolve_path_nofollow(path, VFS.root_inode(), &task)
-        .await?;
-    listxattr(node, list, size).await
+    let node = resolve_xattr_path(ctx, path, true).await?;
+    listxattr(ctx, node, list, size).await
 }
 
 pub async fn sys_flistxattr(ctx: &ProcessCtx, fd: Fd, list: UA, size: usize) -> Result<usize> {
@@ -65,5 +73,5 @@ pub async fn sys_flistxattr(ctx: &ProcessCtx, fd: Fd, list: UA, size: usize) ->
 
         file.inode().ok_or(KernelError::BadFd)?
     };
-    listxattr(node, list, size).await
+    listxattr(ctx, node, list, size).await
 }
@@ -4,12 +4,16 @@ pub mod chmod;
 pub mod chown;
 pub mod close;
 pub mod copy_file_range;
+pub mod fadvise;
+pub mod fallocate;
+pub mod flock;
 pub mod getxattr;
 pub mod ioctl;
 pub mod iov;
 pub mod listxattr;
 pub mod mount;
 pub mod open;
+pub mod quota;
 pub mod removexattr;
 pub mod rw;
 pub mod seek;
@@ -19,3 +23,4 @@ pub mod stat;
 pub mod statfs;
 pub mod sync;
 pub mod trunc;
+pub mod xattr;
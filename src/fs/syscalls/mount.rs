@@ -1,11 +1,17 @@
-use crate::fs::VFS;
+use crate::fs::mnt_ns::{MountNamespace, Propagation};
+use crate::fs::{MntFlags, VFS};
 use crate::memory::uaccess::cstr::UserCStr;
 use crate::sched::syscall_ctx::ProcessCtx;
+use alloc::borrow::ToOwned;
+use alloc::sync::Arc;
 use bitflags::bitflags;
 use core::ffi::c_char;
 use libkernel::error::{KernelError, Result};
+use libkernel::fs::Inode;
 use libkernel::fs::path::Path;
+use libkernel::fs::pathbuf::PathBuf;
 use libkernel::memory::address::{TUA, UA};
+use libkernel::proc::caps::CapabilitiesFlags;
 
 bitflags! {
     #[derive(Debug)]
@@ -45,19 +51,97 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Debug)]
+    pub struct UmountFlags: i32 {
+        /// Unmount even if busy. Nothing here can be forced, so this is the
+        /// same as a plain unmount.
+        const MNT_FORCE = 1;
+        /// Detach the filesystem now, and release it once it's unused.
+        const MNT_DETACH = 2;
+        const MNT_EXPIRE = 4;
+        /// Don't follow a symlink in the final component of the target.
+        const UMOUNT_NOFOLLOW = 8;
+    }
+}
+
+impl MountFlags {
+    fn mnt_flags(&self) -> MntFlags {
+        let mut flags = MntFlags::empty();
+
+        flags.set(MntFlags::MNT_NOSUID, self.contains(MountFlags::MS_NOSUID));
+        flags.set(MntFlags::MNT_NOEXEC, self.contains(MountFlags::MS_NOEXEC));
+
+        flags
+    }
+}
+
+fn check_sys_admin(ctx: &ProcessCtx) -> Result<()> {
+    ctx.shared()
+        .creds
+        .lock_save_irq()
+        .caps()
+        .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)
+}
+
+/// Resolves the target of `mount()` or `umount2()`, relative to the working
+/// directory.
+async fn resolve_target(ctx: &ProcessCtx, path: &Path, follow: bool) -> Result<Arc<dyn Inode>> {
+    let task = ctx.shared();
+    let cwd = task.cwd.lock_save_irq().0.clone();
+
+    if follow {
+        VFS.resolve_path(path, cwd, task).await
+    } else {
+        VFS.resolve_path_nofollow(path, cwd, task).await
+    }
+}
+
+/// Returns the mount namespace of the calling task.
+fn task_mnt_ns(ctx: &ProcessCtx) -> Arc<MountNamespace> {
+    ctx.shared().mnt_ns.lock_save_irq().clone()
+}
+
+/// Returns `path` as an absolute path, for the mount table.
+async fn absolute_path(ctx: &ProcessCtx, path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_owned());
+    }
+
+    let cwd = ctx.shared().cwd.lock_save_irq().0.clone();
+
+    Ok(VFS.path_of(cwd, ctx.shared()).await?.join(path))
+}
+
 pub async fn sys_mount(
     ctx: &ProcessCtx,
     dev_name: TUA<c_char>,
     dir_name: TUA<c_char>,
     type_: TUA<c_char>,
     flags: i64,
-    _data: UA,
+    data: UA,
 ) -> Result<usize> {
     let flags = MountFlags::from_bits_truncate(flags as u64);
-    if flags.contains(MountFlags::MS_REC) {
-        // TODO: Handle later
-        return Ok(0);
+
+    check_sys_admin(ctx)?;
+
+    if flags.contains(MountFlags::MS_REMOUNT) {
+        return sys_remount(ctx, dir_name, flags).await;
+    }
+
+    if flags.contains(MountFlags::MS_BIND) {
+        return sys_bind(ctx, dev_name, dir_name).await;
+    }
+
+    if flags.intersects(
+        MountFlags::MS_SHARED
+            | MountFlags::MS_PRIVATE
+            | MountFlags::MS_SLAVE
+            | MountFlags::MS_UNBINDABLE,
+    ) {
+        return sys_set_propagation(ctx, dir_name, flags).await;
     }
+
     let mut buf = [0u8; 1024];
     let dev_name = if dev_name.is_null() {
         None
@@ -72,9 +156,7 @@ pub async fn sys_mount(
     let dir_name = UserCStr::from_ptr(dir_name)
         .copy_from_user(&mut buf)
         .await?;
-    let mount_point = VFS
-        .resolve_path(Path::new(dir_name), VFS.root_inode(), ctx.shared())
-        .await?;
+    let mount_point = resolve_target(ctx, Path::new(dir_name), true).await?;
     let mut buf = [0u8; 1024];
     let fs_type = if type_.is_null() {
         None
@@ -82,6 +164,7 @@ pub async fn sys_mount(
         Some(UserCStr::from_ptr(type_).copy_from_user(&mut buf).await?)
     };
 
+    let source = dev_name.or(fs_type).unwrap_or("none");
     let fs_name = fs_type.or(dev_name).ok_or(KernelError::NotSupported)?;
     let fs_name = match fs_name {
         "proc" => "procfs",
@@ -91,6 +174,161 @@ pub async fn sys_mount(
         s => s,
     };
 
-    VFS.mount(mount_point, fs_name, None).await?;
+    let mut buf = [0u8; 1024];
+    let options = if data.is_null() {
+        ""
+    } else {
+        UserCStr::from_ptr(TUA::from_value(data.value()))
+            .copy_from_user(&mut buf)
+            .await?
+    };
+
+    let path = absolute_path(ctx, Path::new(dir_name)).await?;
+
+    VFS.mount(
+        &task_mnt_ns(ctx),
+        mount_point,
+        &path,
+        source,
+        fs_name,
+        options,
+        flags.contains(MountFlags::MS_RDONLY),
+        flags.mnt_flags(),
+    )
+    .await?;
+    Ok(0)
+}
+
+/// Handles `MS_BIND`, with or without `MS_REC`, which makes the subtree at
+/// `dev_name` visible at `dir_name` too.
+async fn sys_bind(ctx: &ProcessCtx, dev_name: TUA<c_char>, dir_name: TUA<c_char>) -> Result<usize> {
+    if dev_name.is_null() {
+        return Err(KernelError::Fault);
+    }
+
+    let mut buf = [0u8; 1024];
+    let dev_name = UserCStr::from_ptr(dev_name)
+        .copy_from_user(&mut buf)
+        .await?;
+    let source = resolve_target(ctx, Path::new(dev_name), true).await?;
+
+    let mut buf = [0u8; 1024];
+    let dir_name = UserCStr::from_ptr(dir_name)
+        .copy_from_user(&mut buf)
+        .await?;
+    let mount_point = resolve_target(ctx, Path::new(dir_name), true).await?;
+
+    VFS.bind(
+        &task_mnt_ns(ctx),
+        source,
+        &absolute_path(ctx, Path::new(dev_name)).await?,
+        mount_point,
+        &absolute_path(ctx, Path::new(dir_name)).await?,
+    )
+    .await?;
+
+    Ok(0)
+}
+
+/// Handles `MS_SHARED`, `MS_PRIVATE`, `MS_SLAVE` and `MS_UNBINDABLE`, which
+/// change how mounts inside the mount at `dir_name` propagate. With `MS_REC`,
+/// everything mounted inside it changes too.
+async fn sys_set_propagation(
+    ctx: &ProcessCtx,
+    dir_name: TUA<c_char>,
+    flags: MountFlags,
+) -> Result<usize> {
+    let propagation = if flags.contains(MountFlags::MS_SHARED) {
+        Propagation::Shared
+    } else if flags.contains(MountFlags::MS_PRIVATE) {
+        Propagation::Private
+    } else if flags.contains(MountFlags::MS_SLAVE) {
+        Propagation::Slave
+    } else {
+        Propagation::Unbindable
+    };
+
+    // Exactly one type can be asked for.
+    if (flags.bits()
+        & (MountFlags::MS_SHARED
+            | MountFlags::MS_PRIVATE
+            | MountFlags::MS_SLAVE
+            | MountFlags::MS_UNBINDABLE)
+            .bits())
+    .count_ones()
+        != 1
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let mut buf = [0u8; 1024];
+    let dir_name = UserCStr::from_ptr(dir_name)
+        .copy_from_user(&mut buf)
+        .await?;
+    let mount_root = resolve_target(ctx, Path::new(dir_name), true).await?;
+
+    VFS.set_propagation(
+        &task_mnt_ns(ctx),
+        mount_root,
+        &absolute_path(ctx, Path::new(dir_name)).await?,
+        propagation,
+        flags.contains(MountFlags::MS_REC),
+    )?;
+
+    Ok(0)
+}
+
+/// Handles `MS_REMOUNT`, toggling the read-only state of an existing mount.
+async fn sys_remount(ctx: &ProcessCtx, dir_name: TUA<c_char>, flags: MountFlags) -> Result<usize> {
+    let mut buf = [0u8; 1024];
+    let dir_name = UserCStr::from_ptr(dir_name)
+        .copy_from_user(&mut buf)
+        .await?;
+
+    // Resolution crosses into the mounted filesystem, so this is the root inode
+    // of the mount being changed.
+    let mount_root = resolve_target(ctx, Path::new(dir_name), true).await?;
+
+    if !VFS.is_mount_root(&task_mnt_ns(ctx), mount_root.id()) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    VFS.remount(
+        mount_root,
+        flags.contains(MountFlags::MS_RDONLY),
+        flags.mnt_flags(),
+    )
+    .await?;
+
+    Ok(0)
+}
+
+pub async fn sys_umount2(ctx: &ProcessCtx, target: TUA<c_char>, flags: i32) -> Result<usize> {
+    let flags = UmountFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
+
+    // Mounts are never marked as expiring.
+    if flags.contains(UmountFlags::MNT_EXPIRE) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    check_sys_admin(ctx)?;
+
+    let mut buf = [0u8; 1024];
+    let target = UserCStr::from_ptr(target).copy_from_user(&mut buf).await?;
+    let mount_root = resolve_target(
+        ctx,
+        Path::new(target),
+        !flags.contains(UmountFlags::UMOUNT_NOFOLLOW),
+    )
+    .await?;
+
+    VFS.unmount(
+        &task_mnt_ns(ctx),
+        mount_root,
+        &absolute_path(ctx, Path::new(target)).await?,
+        flags.contains(UmountFlags::MNT_DETACH),
+    )
+    .await?;
+
     Ok(0)
 }
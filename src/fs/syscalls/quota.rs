@@ -0,0 +1,187 @@
+use super::at::{AtFlags, resolve_at_start_node, resolve_path_flags};
+use crate::fs::VFS;
+use crate::memory::uaccess::cstr::UserCStr;
+use crate::memory::uaccess::{UserCopyable, copy_from_user, copy_to_user};
+use crate::process::fd_table::{AT_FDCWD, Fd};
+use crate::sched::syscall_ctx::ProcessCtx;
+use core::ffi::c_char;
+use libkernel::error::{KernelError, Result};
+use libkernel::fs::path::Path;
+use libkernel::memory::address::{TUA, UA};
+use libkernel::pod::Pod;
+use libkernel::proc::caps::CapabilitiesFlags;
+use libkernel::proc::ids::Uid;
+
+const SUBCMDSHIFT: u32 = 8;
+const SUBCMDMASK: u32 = 0x00ff;
+
+const USRQUOTA: u32 = 0;
+
+const Q_SYNC: u32 = 0x800001;
+const Q_QUOTAON: u32 = 0x800002;
+const Q_QUOTAOFF: u32 = 0x800003;
+const Q_GETFMT: u32 = 0x800004;
+const Q_GETINFO: u32 = 0x800005;
+const Q_SETINFO: u32 = 0x800006;
+const Q_GETQUOTA: u32 = 0x800007;
+const Q_SETQUOTA: u32 = 0x800008;
+
+/// Quota format reported by `Q_GETFMT`.
+const QFMT_VFS_V1: u32 = 4;
+
+/// Block limits in `if_dqblk` are expressed in units of this many bytes.
+const QIF_DQBLKSIZE: u64 = 1024;
+
+const QIF_BLIMITS: u32 = 1;
+const QIF_SPACE: u32 = 2;
+const QIF_ILIMITS: u32 = 4;
+const QIF_INODES: u32 = 8;
+const QIF_BTIME: u32 = 16;
+const QIF_ITIME: u32 = 32;
+const QIF_USAGE: u32 = QIF_SPACE | QIF_INODES;
+const QIF_TIMES: u32 = QIF_BTIME | QIF_ITIME;
+const QIF_LIMITS: u32 = QIF_BLIMITS | QIF_ILIMITS;
+const QIF_ALL: u32 = QIF_LIMITS | QIF_USAGE | QIF_TIMES;
+
+/// Default grace period, in seconds, reported by `Q_GETINFO`.
+const MAX_DQ_TIME: u64 = 604800;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IfDqblk {
+    dqb_bhardlimit: u64,
+    dqb_bsoftlimit: u64,
+    dqb_curspace: u64,
+    dqb_ihardlimit: u64,
+    dqb_isoftlimit: u64,
+    dqb_curinodes: u64,
+    dqb_btime: u64,
+    dqb_itime: u64,
+    dqb_valid: u32,
+}
+
+unsafe impl Pod for IfDqblk {}
+
+unsafe impl UserCopyable for IfDqblk {}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IfDqinfo {
+    dqi_bgrace: u64,
+    dqi_igrace: u64,
+    dqi_flags: u32,
+    dqi_valid: u32,
+}
+
+unsafe impl Pod for IfDqinfo {}
+
+unsafe impl UserCopyable for IfDqinfo {}
+
+/// Manipulate user disk quotas.
+///
+/// Quotas are kept per filesystem and `special` may name any path on the
+/// filesystem in question, not just its backing block device (which tmpfs
+/// doesn't have). Only user quotas are supported.
+pub async fn sys_quotactl(
+    ctx: &ProcessCtx,
+    cmd: u32,
+    special: TUA<c_char>,
+    id: u32,
+    addr: UA,
+) -> Result<usize> {
+    let subcmd = cmd >> SUBCMDSHIFT;
+    let qtype = cmd & SUBCMDMASK;
+
+    if qtype != USRQUOTA {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let task = ctx.shared().clone();
+
+    // Users may always query their own quota; everything else is an
+    // administrative operation.
+    {
+        let creds = task.creds.lock_save_irq();
+        let own_quota = subcmd == Q_GETQUOTA && creds.uid() == Uid::new(id);
+
+        if !own_quota && !matches!(subcmd, Q_GETFMT | Q_GETINFO | Q_SYNC) {
+            creds
+                .caps()
+                .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)?;
+        }
+    }
+
+    let mut buf = [0; 1024];
+    let path = Path::new(UserCStr::from_ptr(special).copy_from_user(&mut buf).await?);
+    let dirfd = Fd(AT_FDCWD);
+    let start_node = resolve_at_start_node(ctx, dirfd, path, AtFlags::empty()).await?;
+    let inode = resolve_path_flags(dirfd, path, start_node, &task, AtFlags::empty()).await?;
+    let fs = VFS.get_fs(inode).await?;
+    let quota = fs.quota().ok_or(KernelError::NotSupported)?;
+
+    match subcmd {
+        Q_QUOTAON => quota.set_enabled(true),
+        Q_QUOTAOFF => quota.set_enabled(false),
+        Q_SYNC => {}
+        Q_GETFMT => {
+            if !quota.is_enabled() {
+                return Err(KernelError::NoProcess);
+            }
+
+            copy_to_user(TUA::<u32>::from_value(addr.value()), QFMT_VFS_V1).await?;
+        }
+        Q_GETINFO => {
+            if !quota.is_enabled() {
+                return Err(KernelError::NoProcess);
+            }
+
+            let info = IfDqinfo {
+                dqi_bgrace: MAX_DQ_TIME,
+                dqi_igrace: MAX_DQ_TIME,
+                dqi_flags: 0,
+                dqi_valid: 0x7,
+            };
+
+            copy_to_user(TUA::from_value(addr.value()), info).await?;
+        }
+        // Grace periods are fixed, so there's nothing to update.
+        Q_SETINFO => {}
+        Q_GETQUOTA => {
+            let dq = quota.get(Uid::new(id));
+
+            let dqblk = IfDqblk {
+                dqb_bhardlimit: dq.limits.space_hard / QIF_DQBLKSIZE,
+                dqb_bsoftlimit: dq.limits.space_soft / QIF_DQBLKSIZE,
+                dqb_curspace: dq.space,
+                dqb_ihardlimit: dq.limits.inodes_hard,
+                dqb_isoftlimit: dq.limits.inodes_soft,
+                dqb_curinodes: dq.inodes,
+                dqb_btime: 0,
+                dqb_itime: 0,
+                dqb_valid: QIF_ALL,
+            };
+
+            copy_to_user(TUA::from_value(addr.value()), dqblk).await?;
+        }
+        Q_SETQUOTA => {
+            let dqblk: IfDqblk = copy_from_user(TUA::from_value(addr.value())).await?;
+            let uid = Uid::new(id);
+            let mut limits = quota.get(uid).limits;
+
+            if dqblk.dqb_valid & QIF_BLIMITS != 0 {
+                limits.space_hard = dqblk.dqb_bhardlimit.saturating_mul(QIF_DQBLKSIZE);
+                limits.space_soft = dqblk.dqb_bsoftlimit.saturating_mul(QIF_DQBLKSIZE);
+            }
+
+            if dqblk.dqb_valid & QIF_ILIMITS != 0 {
+                limits.inodes_hard = dqblk.dqb_ihardlimit;
+                limits.inodes_soft = dqblk.dqb_isoftlimit;
+            }
+
+            quota.set_limits(uid, limits);
+        }
+        _ => return Err(KernelError::InvalidValue),
+    }
+
+    Ok(0)
+}
@@ -1,6 +1,6 @@
-use crate::fs::VFS;
+use super::xattr::{check_xattr_access, no_such_xattr, resolve_xattr_path};
 use crate::memory::uaccess::cstr::UserCStr;
-use crate::process::fd_table::Fd;
+use crate::process::{fd_table::Fd, inotify::notify_attrib};
 use crate::sched::syscall_ctx::ProcessCtx;
 use alloc::sync::Arc;
 use core::ffi::c_char;
@@ -9,8 +9,11 @@ use libkernel::fs::Inode;
 use libkernel::fs::path::Path;
 use libkernel::memory::address::TUA;
 
-async fn removexattr(node: Arc<dyn Inode>, name: &str) -> Result<()> {
-    node.removexattr(name).await?;
+async fn removexattr(ctx: &ProcessCtx, node: Arc<dyn Inode>, name: &str) -> Result<()> {
+    check_xattr_access(&ctx.shared().clone(), &node, name, true).await?;
+
+    node.removexattr(name).await.map_err(no_such_xattr)?;
+    notify_attrib(node.id()).await;
     Ok(())
 }
 
@@ -22,11 +25,10 @@ pub async fn sys_removexattr(
     let mut buf = [0; 1024];
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let task = ctx.shared().clone();
-
-    let node = VFS.resolve_path(path, VFS.root_inode(), &task).await?;
+    let node = resolve_xattr_path(ctx, path, false).await?;
     let mut buf = [0; 1024];
     removexattr(
+        ctx,
         node,
         UserCStr::from_ptr(name).copy_from_user(&mut buf).await?,
     )
@@ -42,13 +44,10 @@ pub async fn sys_lremovexattr(
     let mut buf = [0; 1024];
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let task = ctx.shared().clone();
-
-    let node = VFS
-        .resolve_path_nofollow(path, VFS.root_inode(), &task)
-        .await?;
+    let node = resolve_xattr_path(ctx, path, true).await?;
     let mut buf = [0; 1024];
     removexattr(
+        ctx,
         node,
         UserCStr::from_ptr(name).copy_from_user(&mut buf).await?,
     )
@@ -69,6 +68,7 @@ pub async fn sys_fremovexattr(ctx: &ProcessCtx, fd: Fd, name: TUA<c_char>) -> Re
     };
     let mut buf = [0; 1024];
     removexattr(
+        ctx,
         node,
         UserCStr::from_ptr(name).copy_from_user(&mut buf).await?,
     )
@@ -37,6 +37,10 @@ pub async fn sys_pwrite64(
     count: usize,
     offset: u64,
 ) -> Result<usize> {
+    if (offset as i64) < 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
     let file = ctx
         .shared()
         .fd_table
@@ -46,6 +50,10 @@ pub async fn sys_pwrite64(
 
     let (ops, _ctx) = &mut *file.lock().await;
 
+    if ops.is_stream() {
+        return Err(KernelError::SeekPipe);
+    }
+
     ops.writeat(user_buf, count, offset).await
 }
 
@@ -56,6 +64,10 @@ pub async fn sys_pread64(
     count: usize,
     offset: u64,
 ) -> Result<usize> {
+    if (offset as i64) < 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
     let file = ctx
         .shared()
         .fd_table
@@ -65,5 +77,9 @@ pub async fn sys_pread64(
 
     let (ops, _ctx) = &mut *file.lock().await;
 
+    if ops.is_stream() {
+        return Err(KernelError::SeekPipe);
+    }
+
     ops.readat(user_buf, count, offset).await
 }
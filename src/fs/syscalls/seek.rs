@@ -7,12 +7,16 @@ use libkernel::{
 const SEEK_SET: i32 = 0;
 const SEEK_CUR: i32 = 1;
 const SEEK_END: i32 = 2;
+const SEEK_DATA: i32 = 3;
+const SEEK_HOLE: i32 = 4;
 
 pub async fn sys_lseek(ctx: &ProcessCtx, fd: Fd, offset: isize, whence: i32) -> Result<usize> {
     let seek_from = match whence {
         SEEK_SET => SeekFrom::Start(offset as _),
         SEEK_CUR => SeekFrom::Current(offset as _),
         SEEK_END => SeekFrom::End(offset as _),
+        SEEK_DATA => SeekFrom::Data(offset as _),
+        SEEK_HOLE => SeekFrom::Hole(offset as _),
         _ => return Err(KernelError::InvalidValue),
     };
 
@@ -1,4 +1,4 @@
-use crate::fs::VFS;
+use super::xattr::{XATTR_SIZE_MAX, check_xattr_access, no_such_xattr, resolve_xattr_path};
 use crate::memory::uaccess::copy_from_user_slice;
 use crate::memory::uaccess::cstr::UserCStr;
 use crate::process::{fd_table::Fd, inotify::notify_attrib};
@@ -20,6 +20,7 @@ bitflags! {
 }
 
 async fn setxattr(
+    ctx: &ProcessCtx,
     node: Arc<dyn Inode>,
     name: &str,
     value: UA,
@@ -32,12 +33,12 @@ async fn setxattr(
         2 => SetXattrFlags::REPLACE,
         _ => return Err(KernelError::InvalidValue),
     };
-    if name.is_empty() || name.len() > 255 {
-        return Err(KernelError::RangeError);
-    }
-    if size > 2 * 1024 * 1024 {
-        return Err(KernelError::RangeError);
+    if size > XATTR_SIZE_MAX {
+        return Err(KernelError::TooLarge);
     }
+
+    check_xattr_access(&ctx.shared().clone(), &node, name, true).await?;
+
     let mut value_vec = vec![0u8; size];
     copy_from_user_slice(value, &mut value_vec[..]).await?;
     node.setxattr(
@@ -46,7 +47,8 @@ async fn setxattr(
         flags.contains(SetXattrFlags::CREATE),
         flags.contains(SetXattrFlags::REPLACE),
     )
-    .await?;
+    .await
+    .map_err(no_such_xattr)?;
     notify_attrib(node.id()).await;
     Ok(size)
 }
@@ -62,11 +64,10 @@ pub async fn sys_setxattr(
     let mut buf = [0; 1024];
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let task = ctx.shared().clone();
-
-    let node = VFS.resolve_path(path, VFS.root_inode(), &task).await?;
+    let node = resolve_xattr_path(ctx, path, false).await?;
     let mut buf = [0; 1024];
     setxattr(
+        ctx,
         node,
         UserCStr::from_ptr(name).copy_from_user(&mut buf).await?,
         value,
@@ -87,13 +88,10 @@ pub async fn sys_lsetxattr(
     let mut buf = [0; 1024];
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let task = ctx.shared().clone();
-
-    let node = VFS
-        .resolve_path_nofollow(path, VFS.root_inode(), &task)
-        .await?;
+    let node = resolve_xattr_path(ctx, path, true).await?;
     let mut buf = [0; 1024];
     setxattr(
+        ctx,
         node,
         UserCStr::from_ptr(name).copy_from_user(&mut buf).await?,
         value,
@@ -123,6 +121,7 @@ pub async fn sys_fsetxattr(
     };
     let mut buf = [0; 1024];
     setxattr(
+        ctx,
         node,
         UserCStr::from_ptr(name).copy_from_user(&mut buf).await?,
         value,
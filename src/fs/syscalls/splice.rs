@@ -1,17 +1,32 @@
-use crate::{kernel::kpipe::KPipe, process::fd_table::Fd, sched::syscall_ctx::ProcessCtx};
-use alloc::sync::Arc;
+use crate::{
+    fs::{fops::FileOps, open_file::FileCtx, open_file::OpenFile},
+    kernel::kpipe::KPipe,
+    memory::uaccess::{copy_from_user, copy_to_user},
+    process::fd_table::Fd,
+    sched::syscall_ctx::ProcessCtx,
+};
+use alloc::{boxed::Box, sync::Arc};
 use libkernel::{
     error::{KernelError, Result},
+    fs::OpenFlags,
     memory::address::TUA,
 };
 
-pub async fn sys_sendfile(
+/// The most a single call transfers, as on Linux.
+pub(super) const MAX_RW_COUNT: usize = 0x7fff_f000;
+
+const SPLICE_F_MOVE: u32 = 0x01;
+const SPLICE_F_NONBLOCK: u32 = 0x02;
+const SPLICE_F_MORE: u32 = 0x04;
+const SPLICE_F_GIFT: u32 = 0x08;
+
+/// Looks up `in_fd` and `out_fd`, which have to be open for reading and
+/// writing respectively.
+pub(super) async fn get_ends(
     ctx: &ProcessCtx,
-    out_fd: Fd,
     in_fd: Fd,
-    _offset: TUA<u64>,
-    mut count: usize,
-) -> Result<usize> {
+    out_fd: Fd,
+) -> Result<(Arc<OpenFile>, Arc<OpenFile>)> {
     let (reader, writer) = {
         let task = ctx.shared();
         let fds = task.fd_table.lock_save_irq();
@@ -22,34 +37,268 @@ pub async fn sys_sendfile(
         (reader, writer)
     };
 
+    if reader.flags().await.intersection(OpenFlags::O_ACCMODE) == OpenFlags::O_WRONLY
+        || writer.flags().await.intersection(OpenFlags::O_ACCMODE) == OpenFlags::O_RDONLY
+    {
+        return Err(KernelError::BadFd);
+    }
+
+    Ok((reader, writer))
+}
+
+/// Reads the offset at `ptr`, if there is one.
+pub(super) async fn read_offset(ptr: TUA<i64>) -> Result<Option<u64>> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+
+    let off = copy_from_user(ptr).await?;
+
+    if off < 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    Ok(Some(off as u64))
+}
+
+/// Copies `in_fd` to `out_fd` without going through userspace. With `offset`,
+/// reading starts from `*offset` rather than the file position, which is left
+/// alone, and `*offset` is moved past what was sent.
+pub async fn sys_sendfile(
+    ctx: &ProcessCtx,
+    out_fd: Fd,
+    in_fd: Fd,
+    offset: TUA<i64>,
+    count: usize,
+) -> Result<usize> {
+    let (reader, writer) = get_ends(ctx, in_fd, out_fd).await?;
+
     if Arc::ptr_eq(&reader, &writer) {
         return Err(KernelError::InvalidValue);
     }
 
+    let start = read_offset(offset).await?;
     let kbuf = KPipe::new()?;
 
     let (reader_ops, reader_ctx) = &mut *reader.lock().await;
     let (writer_ops, writer_ctx) = &mut *writer.lock().await;
 
-    let mut total_written = 0;
+    let Some(start) = start else {
+        return transfer(
+            reader_ops,
+            reader_ctx,
+            writer_ops,
+            writer_ctx,
+            &kbuf,
+            count.min(MAX_RW_COUNT),
+        )
+        .await;
+    };
 
-    while count > 0 {
-        let read = reader_ops.splice_into(reader_ctx, &kbuf, count).await?;
+    let saved_pos = reader_ctx.pos;
+    reader_ctx.pos = start;
 
-        if read == 0 {
-            return Ok(total_written);
-        }
+    let res = transfer(
+        reader_ops,
+        reader_ctx,
+        writer_ops,
+        writer_ctx,
+        &kbuf,
+        count.min(MAX_RW_COUNT),
+    )
+    .await;
+
+    let end = reader_ctx.pos;
+    reader_ctx.pos = saved_pos;
+
+    copy_to_user(offset, end as i64).await?;
+
+    res
+}
+
+/// Moves up to `count` bytes from the reader to the writer through `kbuf`.
+/// Once anything has been sent, an error just cuts the transfer short.
+async fn transfer(
+    reader_ops: &mut Box<dyn FileOps>,
+    reader_ctx: &mut FileCtx,
+    writer_ops: &mut Box<dyn FileOps>,
+    writer_ctx: &mut FileCtx,
+    kbuf: &KPipe,
+    count: usize,
+) -> Result<usize> {
+    let mut total_written = 0;
+
+    while total_written < count {
+        let read = match reader_ops
+            .splice_into(reader_ctx, kbuf, count - total_written)
+            .await
+        {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(_) if total_written > 0 => break,
+            Err(e) => return Err(e),
+        };
 
         let mut to_write = read;
 
         while to_write > 0 {
-            let written = writer_ops.splice_from(writer_ctx, &kbuf, to_write).await?;
-            to_write -= written;
-            total_written += written;
-        }
+            match writer_ops.splice_from(writer_ctx, kbuf, to_write).await {
+                Ok(written) if written > 0 => {
+                    to_write -= written;
+                    total_written += written;
+                }
+                res => {
+                    // Step the reader back over what never made it out, so
+                    // that it's sent again next time.
+                    reader_ctx.pos = reader_ctx.pos.saturating_sub(to_write as u64);
 
-        count -= read;
+                    return match res {
+                        Err(e) if total_written == 0 => Err(e),
+                        _ => Ok(total_written),
+                    };
+                }
+            }
+        }
     }
 
     Ok(total_written)
 }
+
+/// Moves data between a pipe and another file, or between two pipes, through
+/// the pipe's buffer. An offset may only be given for the end that isn't a
+/// pipe, and is used and moved on in place of the file position.
+pub async fn sys_splice(
+    ctx: &ProcessCtx,
+    fd_in: Fd,
+    off_in: TUA<i64>,
+    fd_out: Fd,
+    off_out: TUA<i64>,
+    len: usize,
+    flags: u32,
+) -> Result<usize> {
+    if flags & !(SPLICE_F_MOVE | SPLICE_F_NONBLOCK | SPLICE_F_MORE | SPLICE_F_GIFT) != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    // Pages are always copied, so there's nothing for the other flags to do.
+    let nonblock = flags & SPLICE_F_NONBLOCK != 0;
+    let (reader, writer) = get_ends(ctx, fd_in, fd_out).await?;
+
+    if len == 0 {
+        return Ok(0);
+    }
+
+    if Arc::ptr_eq(&reader, &writer) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let len = len.min(MAX_RW_COUNT);
+
+    let (in_ops, in_ctx) = &mut *reader.lock().await;
+    let (out_ops, out_ctx) = &mut *writer.lock().await;
+
+    match (in_ops.as_pipe().cloned(), out_ops.as_pipe().cloned()) {
+        (Some(src), Some(dst)) => {
+            if !off_in.is_null() || !off_out.is_null() {
+                return Err(KernelError::SeekPipe);
+            }
+
+            if src.ptr_eq(&dst) {
+                return Err(KernelError::InvalidValue);
+            }
+
+            src.splice_to(&dst, len, false, nonblock).await
+        }
+        (Some(src), None) => {
+            if !off_in.is_null() {
+                return Err(KernelError::SeekPipe);
+            }
+
+            let start = read_offset(off_out).await?;
+
+            if !src.wait_readable(nonblock).await? {
+                return Ok(0);
+            }
+
+            // Don't ask the writer for more than is there, or it may wait for
+            // the rest.
+            let len = len.min(src.buf().len());
+            let saved_pos = out_ctx.pos;
+
+            if let Some(start) = start {
+                out_ctx.pos = start;
+            }
+
+            let res = out_ops.splice_from(out_ctx, src.buf(), len).await;
+
+            if start.is_some() {
+                let end = out_ctx.pos;
+                out_ctx.pos = saved_pos;
+                copy_to_user(off_out, end as i64).await?;
+            }
+
+            res
+        }
+        (None, Some(dst)) => {
+            if !off_out.is_null() {
+                return Err(KernelError::SeekPipe);
+            }
+
+            let start = read_offset(off_in).await?;
+
+            dst.wait_writable(nonblock).await?;
+
+            // Nor the reader for more than there's room for.
+            let len = len.min(dst.buf().vacant_len());
+            let saved_pos = in_ctx.pos;
+
+            if let Some(start) = start {
+                in_ctx.pos = start;
+            }
+
+            let res = in_ops.splice_into(in_ctx, dst.buf(), len).await;
+
+            if start.is_some() {
+                let end = in_ctx.pos;
+                in_ctx.pos = saved_pos;
+                copy_to_user(off_in, end as i64).await?;
+            }
+
+            res
+        }
+        (None, None) => Err(KernelError::InvalidValue),
+    }
+}
+
+/// Copies data from one pipe to another, leaving it to be read from the first
+/// as well.
+pub async fn sys_tee(
+    ctx: &ProcessCtx,
+    fd_in: Fd,
+    fd_out: Fd,
+    len: usize,
+    flags: u32,
+) -> Result<usize> {
+    if flags & !(SPLICE_F_MOVE | SPLICE_F_NONBLOCK | SPLICE_F_MORE | SPLICE_F_GIFT) != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let nonblock = flags & SPLICE_F_NONBLOCK != 0;
+    let (reader, writer) = get_ends(ctx, fd_in, fd_out).await?;
+
+    if len == 0 {
+        return Ok(0);
+    }
+
+    // Only the pipes are needed, so don't hold on to the files.
+    let src = reader.lock().await.0.as_pipe().cloned();
+    let dst = writer.lock().await.0.as_pipe().cloned();
+
+    match (src, dst) {
+        (Some(src), Some(dst)) if !src.ptr_eq(&dst) => {
+            src.splice_to(&dst, len.min(MAX_RW_COUNT), true, nonblock)
+                .await
+        }
+        _ => Err(KernelError::InvalidValue),
+    }
+}
@@ -1,19 +1,32 @@
+use super::at::{AtFlags, resolve_at_start_node, resolve_path_flags};
 use crate::fs::VFS;
 use crate::memory::uaccess::cstr::UserCStr;
 use crate::memory::uaccess::{UserCopyable, copy_to_user};
-use crate::process::fd_table::Fd;
+use crate::process::fd_table::{AT_FDCWD, Fd};
 use crate::sched::syscall_ctx::ProcessCtx;
 use alloc::sync::Arc;
 use core::ffi::c_char;
 use libkernel::error::KernelError;
-use libkernel::fs::Inode;
 use libkernel::fs::path::Path;
+use libkernel::fs::{FsStats, Inode};
+use libkernel::memory::PAGE_SIZE;
 use libkernel::memory::address::TUA;
 use libkernel::pod::Pod;
 
-type FswordT = u32;
+/// `__statfs_word`, which is a `long` on 64-bit architectures.
+type FswordT = u64;
+
+/// Mount is read-only.
+const ST_RDONLY: FswordT = 1;
+/// `f_flags` is filled in.
+const ST_VALID: FswordT = 0x20;
 type FsBlockCntT = u64;
 
+/// The magic numbers of the pseudo filesystems behind files without an inode.
+const ANON_INODE_FS_MAGIC: u64 = 0x0904_1934;
+const PIDFS_MAGIC: u64 = 0x5049_4446;
+const SOCKFS_MAGIC: u64 = 0x534f_434b;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct StatFs {
@@ -40,29 +53,37 @@ pub struct StatFs {
     /// Mount flags of filesystem (since Linux 2.6.36)
     f_flags: FswordT,
     /// Padding bytes reserved for future use
-    f_spare: [FswordT; 6],
+    f_spare: [FswordT; 4],
 }
 
 unsafe impl Pod for StatFs {}
 
 unsafe impl UserCopyable for StatFs {}
 
+impl StatFs {
+    fn new(stats: FsStats, fsid: u64, read_only: bool) -> Self {
+        Self {
+            f_type: stats.magic as _,
+            f_bsize: stats.block_size as _,
+            f_blocks: stats.blocks,
+            f_bfree: stats.blocks_free,
+            f_bavail: stats.blocks_avail,
+            f_files: stats.files,
+            f_ffree: stats.files_free,
+            f_fsid: fsid,
+            f_namelen: stats.name_max as _,
+            f_frsize: stats.block_size as _,
+            f_flags: ST_VALID | if read_only { ST_RDONLY } else { 0 },
+            f_spare: [0; 4],
+        }
+    }
+}
+
 async fn statfs_impl(inode: Arc<dyn Inode>) -> libkernel::error::Result<StatFs> {
+    let read_only = VFS.is_read_only(inode.id());
     let fs = VFS.get_fs(inode).await?;
-    Ok(StatFs {
-        f_type: fs.magic() as _,
-        f_bsize: 0,
-        f_blocks: 0,
-        f_bfree: 0,
-        f_bavail: 0,
-        f_files: 0,
-        f_ffree: 0,
-        f_fsid: fs.id(),
-        f_namelen: 0,
-        f_frsize: 0,
-        f_flags: 0,
-        f_spare: [0; 6],
-    })
+
+    Ok(StatFs::new(fs.statfs().await?, fs.id(), read_only))
 }
 
 pub async fn sys_statfs(
@@ -72,9 +93,11 @@ pub async fn sys_statfs(
 ) -> libkernel::error::Result<usize> {
     let mut buf = [0; 1024];
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let inode = VFS
-        .resolve_path(path, VFS.root_inode(), &ctx.shared().clone())
-        .await?;
+    let dirfd = Fd(AT_FDCWD);
+    let task = ctx.shared().clone();
+
+    let start_node = resolve_at_start_node(ctx, dirfd, path, AtFlags::empty()).await?;
+    let inode = resolve_path_flags(dirfd, path, start_node, &task, AtFlags::empty()).await?;
     let statfs = statfs_impl(inode).await?;
     copy_to_user(stat, statfs).await?;
     Ok(0)
@@ -89,9 +112,59 @@ pub async fn sys_fstatfs(
         .shared()
         .fd_table
         .lock_save_irq()
-        .get(fd)
+        .get_raw(fd)
         .ok_or(KernelError::BadFd)?;
-    let statfs = statfs_impl(fd.inode().ok_or(KernelError::InvalidValue)?).await?;
+
+    let statfs = match fd.inode() {
+        Some(inode) => statfs_impl(inode).await?,
+        None => {
+            // Files made by pidfd_open(), socket(), eventfd() and the like
+            // live on an internal pseudo filesystem.
+            let mut lock = fd.lock().await;
+            let magic = if lock.0.as_pidfd().is_some() {
+                PIDFS_MAGIC
+            } else if lock.0.as_socket().is_some() {
+                SOCKFS_MAGIC
+            } else {
+                ANON_INODE_FS_MAGIC
+            };
+
+            let stats = FsStats {
+                magic,
+                block_size: PAGE_SIZE as u64,
+                ..FsStats::default()
+            };
+
+            StatFs::new(stats, 0, false)
+        }
+    };
+
     copy_to_user(stat, statfs).await?;
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::StatFs;
+    use core::mem::{offset_of, size_of};
+    use moss_macros::ktest;
+
+    // `struct statfs` on a 64-bit architecture, where every word is a
+    // `long`.
+    #[ktest]
+    fn statfs_layout() {
+        assert_eq!(size_of::<StatFs>(), 120);
+        assert_eq!(offset_of!(StatFs, f_type), 0);
+        assert_eq!(offset_of!(StatFs, f_bsize), 8);
+        assert_eq!(offset_of!(StatFs, f_blocks), 16);
+        assert_eq!(offset_of!(StatFs, f_bfree), 24);
+        assert_eq!(offset_of!(StatFs, f_bavail), 32);
+        assert_eq!(offset_of!(StatFs, f_files), 40);
+        assert_eq!(offset_of!(StatFs, f_ffree), 48);
+        assert_eq!(offset_of!(StatFs, f_fsid), 56);
+        assert_eq!(offset_of!(StatFs, f_namelen), 64);
+        assert_eq!(offset_of!(StatFs, f_frsize), 72);
+        assert_eq!(offset_of!(StatFs, f_flags), 80);
+        assert_eq!(offset_of!(StatFs, f_spare), 88);
+    }
+}
@@ -1,37 +1,64 @@
 use core::ffi::c_char;
 
 use crate::{
-    fs::VFS, memory::uaccess::cstr::UserCStr, process::fd_table::Fd, sched::syscall_ctx::ProcessCtx,
+    fs::{
+        VFS,
+        syscalls::at::{AtFlags, resolve_at_start_node},
+    },
+    memory::uaccess::cstr::UserCStr,
+    process::fd_table::{AT_FDCWD, Fd},
+    sched::syscall_ctx::ProcessCtx,
 };
 use libkernel::{
-    error::{KernelError, Result},
-    fs::{OpenFlags, attr::FilePermissions, path::Path},
+    error::{FsError, KernelError, Result},
+    fs::{FileType, OpenFlags, attr::AccessMode, path::Path},
     memory::address::TUA,
 };
 
+/// Checks a length passed to `truncate()` or `ftruncate()`, which is signed
+/// in userspace.
+fn check_length(len: usize) -> Result<usize> {
+    if len > i64::MAX as usize {
+        return Err(KernelError::InvalidValue);
+    }
+
+    Ok(len)
+}
+
 pub async fn sys_truncate(ctx: &ProcessCtx, path: TUA<c_char>, new_size: usize) -> Result<usize> {
     let mut buf = [0; 1024];
 
+    let new_size = check_length(new_size)?;
     let task = ctx.shared().clone();
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
 
-    let root = task.root.lock_save_irq().0.clone();
-    let file = VFS
-        .open(
-            path,
-            OpenFlags::O_WRONLY,
-            root,
-            FilePermissions::empty(),
-            &task,
-        )
-        .await?;
+    // The file is never opened, so a FIFO or device isn't woken up by this.
+    let start = resolve_at_start_node(ctx, Fd(AT_FDCWD), path, AtFlags::empty()).await?;
+    let inode = VFS.resolve_path(path, start, &task).await?;
+    let attr = inode.getattr().await?;
 
-    let (ops, ctx) = &mut *file.lock().await;
+    match attr.file_type {
+        FileType::File => {}
+        FileType::Directory => return Err(FsError::IsADirectory.into()),
+        _ => return Err(KernelError::InvalidValue),
+    }
 
-    ops.truncate(ctx, new_size).await.map(|_| 0)
+    {
+        let creds = task.creds.lock_save_irq();
+
+        attr.check_access(creds.euid(), creds.egid(), creds.caps(), AccessMode::W_OK)
+            .map_err(|_| FsError::PermissionDenied)?;
+    }
+
+    if VFS.is_read_only(inode.id()) {
+        return Err(FsError::ReadOnly.into());
+    }
+
+    VFS.truncate(&inode, new_size as u64).await.map(|_| 0)
 }
 
 pub async fn sys_ftruncate(ctx: &ProcessCtx, fd: Fd, new_size: usize) -> Result<usize> {
+    let new_size = check_length(new_size)?;
     let fd = ctx
         .shared()
         .fd_table
@@ -41,5 +68,9 @@ pub async fn sys_ftruncate(ctx: &ProcessCtx, fd: Fd, new_size: usize) -> Result<
 
     let (ops, ctx) = &mut *fd.lock().await;
 
+    if ctx.flags & OpenFlags::O_ACCMODE == OpenFlags::O_RDONLY {
+        return Err(KernelError::InvalidValue);
+    }
+
     ops.truncate(ctx, new_size).await.map(|_| 0)
 }
@@ -0,0 +1,147 @@
+//! Checks shared by the extended attribute syscalls.
+//!
+//! Attribute names are namespaced by their prefix:
+//!
+//! - `user.*` attributes are for anyone who can read or write the file, and
+//!   only exist on regular files and directories.
+//! - `trusted.*` attributes are only visible to `CAP_SYS_ADMIN`.
+//! - `security.*` attributes are readable by anyone, but changing them needs
+//!   `CAP_SYS_ADMIN`, or `CAP_SETFCAP` for `security.capability`.
+//! - `system.*` attributes are left to the filesystem.
+
+use super::at::{AtFlags, resolve_at_start_node, resolve_path_flags};
+use crate::{
+    process::{
+        Task,
+        fd_table::{AT_FDCWD, Fd},
+    },
+    sched::syscall_ctx::ProcessCtx,
+};
+use alloc::sync::Arc;
+use libkernel::{
+    error::{FsError, KernelError, Result},
+    fs::{FileType, Inode, attr::AccessMode, path::Path},
+    proc::caps::CapabilitiesFlags,
+};
+
+/// The longest an attribute's name may be.
+const XATTR_NAME_MAX: usize = 255;
+
+/// The largest an attribute's value may be.
+pub const XATTR_SIZE_MAX: usize = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum XattrNamespace {
+    User,
+    Trusted,
+    Security,
+    System,
+}
+
+fn xattr_namespace(name: &str) -> Result<XattrNamespace> {
+    if name.is_empty() || name.len() > XATTR_NAME_MAX {
+        return Err(KernelError::RangeError);
+    }
+
+    [
+        ("user.", XattrNamespace::User),
+        ("trusted.", XattrNamespace::Trusted),
+        ("security.", XattrNamespace::Security),
+        ("system.", XattrNamespace::System),
+    ]
+    .into_iter()
+    .find(|(prefix, _)| name.len() > prefix.len() && name.starts_with(prefix))
+    .map(|(_, ns)| ns)
+    .ok_or(KernelError::OpNotSupported)
+}
+
+/// Checks that `task` may read (or, with `write`, set or remove) the attribute
+/// `name` of `node`.
+///
+/// An attribute that `task` isn't allowed to see reads as if it weren't there.
+pub async fn check_xattr_access(
+    task: &Arc<Task>,
+    node: &Arc<dyn Inode>,
+    name: &str,
+    write: bool,
+) -> Result<()> {
+    let denied = if write {
+        KernelError::NotPermitted
+    } else {
+        KernelError::NoData
+    };
+
+    let attr = node.getattr().await?;
+    let creds = task.creds.lock_save_irq().clone();
+
+    match xattr_namespace(name)? {
+        XattrNamespace::Trusted => {
+            if !creds.caps().is_capable(CapabilitiesFlags::CAP_SYS_ADMIN) {
+                return Err(denied);
+            }
+
+            Ok(())
+        }
+        XattrNamespace::User => {
+            if !matches!(attr.file_type, FileType::File | FileType::Directory) {
+                return Err(denied);
+            }
+
+            let mode = if write {
+                AccessMode::W_OK
+            } else {
+                AccessMode::R_OK
+            };
+
+            attr.check_access(creds.euid(), creds.egid(), creds.caps(), mode)
+        }
+        XattrNamespace::Security if write => {
+            let cap = if name == "security.capability" {
+                CapabilitiesFlags::CAP_SETFCAP
+            } else {
+                CapabilitiesFlags::CAP_SYS_ADMIN
+            };
+
+            creds.caps().check_capable(cap)
+        }
+        XattrNamespace::Security | XattrNamespace::System => Ok(()),
+    }
+}
+
+/// Returns whether `task` may see that the attribute `name` exists, when
+/// listing.
+pub fn xattr_listable(task: &Arc<Task>, name: &str) -> bool {
+    !name.starts_with("trusted.")
+        || task
+            .creds
+            .lock_save_irq()
+            .caps()
+            .is_capable(CapabilitiesFlags::CAP_SYS_ADMIN)
+}
+
+/// Turns the filesystem's "not found" for a missing attribute into `ENODATA`.
+pub fn no_such_xattr(e: KernelError) -> KernelError {
+    match e {
+        KernelError::Fs(FsError::NotFound) => KernelError::NoData,
+        e => e,
+    }
+}
+
+/// Resolves the path given to one of the path-based attribute syscalls,
+/// following a final symlink unless `nofollow` is set.
+pub async fn resolve_xattr_path(
+    ctx: &ProcessCtx,
+    path: &Path,
+    nofollow: bool,
+) -> Result<Arc<dyn Inode>> {
+    let flags = if nofollow {
+        AtFlags::AT_SYMLINK_NOFOLLOW
+    } else {
+        AtFlags::empty()
+    };
+    let dirfd = Fd(AT_FDCWD);
+    let task = ctx.shared().clone();
+
+    let start_node = resolve_at_start_node(ctx, dirfd, path, flags).await?;
+    resolve_path_flags(dirfd, path, start_node, &task, flags).await
+}
@@ -3,7 +3,8 @@
 use core::task::Waker;
 
 use super::{
-    ClaimedInterrupt, InterruptConfig, InterruptDescriptor, InterruptHandler, get_interrupt_root,
+    ClaimedInterrupt, InterruptConfig, InterruptDescriptor, InterruptHandler, IrqReturn,
+    get_interrupt_root,
 };
 use crate::kernel::cpu_id::CpuId;
 use crate::sched::sched_task::Work;
@@ -39,7 +40,7 @@ impl Driver for CpuMessenger {
 }
 
 impl InterruptHandler for CpuMessenger {
-    fn handle_irq(&self, _desc: InterruptDescriptor) {
+    fn handle_irq(&self, _desc: InterruptDescriptor) -> IrqReturn {
         while let Some(message) = CPU_MESSENGER
             .get()
             .unwrap()
@@ -54,6 +55,8 @@ impl InterruptHandler for CpuMessenger {
                 Message::WakeupTask(waker) => waker.wake(),
             }
         }
+
+        IrqReturn::Handled
     }
 }
 
@@ -1,14 +1,25 @@
 use alloc::{
     boxed::Box,
     collections::BTreeMap,
+    format,
     sync::{Arc, Weak},
+    vec::Vec,
+};
+use async_trait::async_trait;
+use bitflags::bitflags;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use libkernel::{
+    CpuOps,
+    error::{KernelError, Result},
+    sync::condvar::WakeupType,
 };
-use libkernel::error::{KernelError, Result};
 use log::{debug, info, warn};
 
 use crate::{
+    arch::ArchImpl,
     drivers::Driver,
-    sync::{OnceLock, SpinLock},
+    process::kthread::spawn_kthread,
+    sync::{CondVar, OnceLock, SpinLock},
 };
 
 pub mod cpu_messenger;
@@ -64,14 +75,114 @@ pub trait InterruptController: Send + Sync {
     ) -> Result<InterruptConfig>;
 }
 
+/// What an interrupt handler made of an interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(test), expect(dead_code))]
+pub enum IrqReturn {
+    /// The interrupt wasn't from this handler's device.
+    NotHandled,
+    /// The interrupt was dealt with.
+    Handled,
+    /// The interrupt was acknowledged, and the rest of the work should be done
+    /// by the handler's thread, see [`ThreadedInterruptHandler`].
+    WakeThread,
+}
+
 pub trait InterruptHandler: Send + Sync {
-    fn handle_irq(&self, desc: InterruptDescriptor);
+    /// Handles an interrupt, in interrupt context. On a shared line, this is
+    /// called for every interrupt on it, and must say whether its device was
+    /// the source.
+    fn handle_irq(&self, desc: InterruptDescriptor) -> IrqReturn;
+}
+
+/// A handler that does the bulk of its work in a dedicated kernel thread,
+/// where it's free to sleep.
+///
+/// The thread runs [`handle_irq_thread`](Self::handle_irq_thread) each time
+/// [`handle_irq`](InterruptHandler::handle_irq) returns
+/// [`IrqReturn::WakeThread`]. A level-triggered line is kept masked until the
+/// thread has finished, so the device needn't be quietened in interrupt
+/// context.
+#[async_trait]
+pub trait ThreadedInterruptHandler: InterruptHandler {
+    async fn handle_irq_thread(&self, desc: InterruptDescriptor);
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IrqFlags: u32 {
+        /// Share the line with other handlers that also ask for this.
+        const SHARED = 1 << 0;
+    }
+}
+
+/// How many times in a row an interrupt may fire with nobody handling it
+/// before it's disabled at the controller.
+const SPURIOUS_LIMIT: usize = 1000;
+
+/// Adds one to `cpu`'s entry in a per-CPU count.
+fn count_on_cpu(counts: &mut Vec<usize>, cpu: usize) {
+    if counts.len() <= cpu {
+        counts.resize(cpu + 1, 0);
+    }
+
+    counts[cpu] += 1;
+}
+
+fn is_level(trigger: TriggerMode) -> bool {
+    matches!(trigger, TriggerMode::LevelHigh | TriggerMode::LevelLow)
+}
+
+/// One handler on an interrupt line.
+struct IrqAction {
+    id: usize,
+    handler: Weak<dyn InterruptHandler>,
+    /// The name of the driver that claimed it.
+    name: &'static str,
+    shared: bool,
+    /// Set to wake the handler's thread, if it has one.
+    thread: Option<CondVar<bool>>,
+    /// Whether the thread has been woken and hasn't finished yet.
+    woken: bool,
+}
+
+struct IrqLine {
+    config: InterruptConfig,
+    actions: Vec<IrqAction>,
+    /// How many times it has fired, on each CPU.
+    counts: Vec<usize>,
+    /// How many of the actions' threads are woken.
+    threads_woken: usize,
+}
+
+#[derive(Default)]
+struct SpuriousIrqs {
+    /// Interrupts that nobody handled, on each CPU.
+    counts: Vec<usize>,
+    /// How many times in a row each interrupt has gone unhandled.
+    unhandled: BTreeMap<InterruptDescriptor, usize>,
+}
+
+/// A snapshot of one claimed interrupt's activity, for `/proc/interrupts`.
+pub struct IrqStat {
+    pub desc: InterruptDescriptor,
+    pub trigger: TriggerMode,
+    /// The drivers handling it.
+    pub names: Vec<&'static str>,
+    /// How many times it has fired, indexed by CPU.
+    pub counts: Vec<usize>,
 }
 
+/// A handler to run for an interrupt, with the id of its action.
+type ActiveHandler = (usize, Arc<dyn InterruptHandler>);
+
+static NEXT_ACTION_ID: AtomicUsize = AtomicUsize::new(0);
+
 pub struct InterruptManager {
     name: &'static str,
     controller: Arc<SpinLock<dyn InterruptController>>,
-    claimed_interrupts: SpinLock<BTreeMap<InterruptDescriptor, ClaimedInterrupt>>,
+    claimed_interrupts: SpinLock<BTreeMap<InterruptDescriptor, IrqLine>>,
+    spurious: SpinLock<SpuriousIrqs>,
 }
 
 impl InterruptManager {
@@ -85,6 +196,7 @@ impl InterruptManager {
         Arc::new(Self {
             name,
             claimed_interrupts: SpinLock::new(BTreeMap::new()),
+            spurious: SpinLock::new(SpuriousIrqs::default()),
             controller: driver,
         })
     }
@@ -98,6 +210,8 @@ impl InterruptManager {
             .parse_fdt_interrupt_regs(iter)
     }
 
+    /// Claims an interrupt line for the driver made by `constructor`, which
+    /// must be the line's only handler.
     pub fn claim_interrupt<T, FConstructor>(
         self: &Arc<Self>,
         config: InterruptConfig,
@@ -107,27 +221,118 @@ impl InterruptManager {
         T: 'static + Send + Sync + Driver + InterruptHandler,
         FConstructor: FnOnce(ClaimedInterrupt) -> T,
     {
-        let mut claimed_int = self.claimed_interrupts.lock_save_irq();
+        self.claim(config, IrqFlags::empty(), None, constructor)
+            .map(|(driver, _)| driver)
+    }
 
-        if claimed_int.contains_key(&config.descriptor) {
-            return Err(KernelError::InUse);
-        }
+    /// Claims an interrupt line for the driver made by `constructor`, sharing
+    /// it with other handlers that claimed it as shared too.
+    #[cfg_attr(not(test), expect(dead_code))]
+    pub fn claim_shared_interrupt<T, FConstructor>(
+        self: &Arc<Self>,
+        config: InterruptConfig,
+        constructor: FConstructor,
+    ) -> Result<Arc<T>>
+    where
+        T: 'static + Send + Sync + Driver + InterruptHandler,
+        FConstructor: FnOnce(ClaimedInterrupt) -> T,
+    {
+        self.claim(config, IrqFlags::SHARED, None, constructor)
+            .map(|(driver, _)| driver)
+    }
 
-        let driver: Arc<T> = Arc::new_cyclic(|driver_weak: &Weak<T>| {
-            let handle = ClaimedInterrupt {
-                desc: config.descriptor,
-                manager: Arc::clone(self),
-                handler: driver_weak.clone(),
-            };
+    /// Claims an interrupt line for the driver made by `constructor`, and
+    /// starts a kernel thread to run its threaded handler.
+    #[expect(dead_code)]
+    pub fn claim_threaded_interrupt<T, FConstructor>(
+        self: &Arc<Self>,
+        config: InterruptConfig,
+        flags: IrqFlags,
+        constructor: FConstructor,
+    ) -> Result<Arc<T>>
+    where
+        T: 'static + Send + Sync + Driver + ThreadedInterruptHandler,
+        FConstructor: FnOnce(ClaimedInterrupt) -> T,
+    {
+        let pending = CondVar::new(false);
+        let (driver, id) = self.claim(config, flags, Some(pending.clone()), constructor)?;
+
+        // If this fails, dropping the driver gives the line back.
+        spawn_kthread(
+            &format!("irq/{}", driver.name()),
+            irq_thread(
+                Arc::clone(self),
+                config.descriptor,
+                id,
+                Arc::downgrade(&driver),
+                pending,
+            ),
+        )?;
+
+        Ok(driver)
+    }
 
-            let driver = constructor(handle.clone());
+    fn claim<T, FConstructor>(
+        self: &Arc<Self>,
+        config: InterruptConfig,
+        flags: IrqFlags,
+        thread: Option<CondVar<bool>>,
+        constructor: FConstructor,
+    ) -> Result<(Arc<T>, usize)>
+    where
+        T: 'static + Send + Sync + Driver + InterruptHandler,
+        FConstructor: FnOnce(ClaimedInterrupt) -> T,
+    {
+        let shared = flags.contains(IrqFlags::SHARED);
+        let mut claimed_int = self.claimed_interrupts.lock_save_irq();
 
-            claimed_int.insert(config.descriptor, handle);
+        if let Some(line) = claimed_int.get(&config.descriptor)
+            && (!shared
+                || line.actions.iter().any(|action| !action.shared)
+                || line.config.trigger != config.trigger)
+        {
+            return Err(KernelError::InUse);
+        }
 
-            driver
-        });
+        let id = NEXT_ACTION_ID.fetch_add(1, Ordering::Relaxed);
+
+        let driver = Arc::new(constructor(ClaimedInterrupt {
+            desc: config.descriptor,
+            id,
+            manager: Arc::clone(self),
+        }));
+
+        let handler: Weak<T> = Arc::downgrade(&driver);
+        let action = IrqAction {
+            id,
+            handler,
+            name: driver.name(),
+            shared,
+            thread,
+            woken: false,
+        };
 
-        self.controller.lock_save_irq().enable_interrupt(config);
+        match claimed_int.get_mut(&config.descriptor) {
+            Some(line) => line.actions.push(action),
+            None => {
+                claimed_int.insert(
+                    config.descriptor,
+                    IrqLine {
+                        config,
+                        actions: alloc::vec![action],
+                        counts: Vec::new(),
+                        threads_woken: 0,
+                    },
+                );
+
+                self.spurious
+                    .lock_save_irq()
+                    .unhandled
+                    .remove(&config.descriptor);
+
+                self.controller.lock_save_irq().enable_interrupt(config);
+            }
+        }
 
         debug!(
             "Device {} claimed interrupt: {:?}",
@@ -135,35 +340,195 @@ impl InterruptManager {
             config.descriptor,
         );
 
-        Ok(driver)
+        Ok((driver, id))
     }
 
-    fn remove_interrupt(&self, desc: InterruptDescriptor) {
+    fn remove_interrupt(&self, desc: InterruptDescriptor, id: usize) {
         let mut claimed_int = self.claimed_interrupts.lock_save_irq();
 
-        if claimed_int.remove(&desc).is_some() {
+        let Some(line) = claimed_int.get_mut(&desc) else {
+            return;
+        };
+
+        let Some(idx) = line.actions.iter().position(|action| action.id == id) else {
+            return;
+        };
+
+        let action = line.actions.remove(idx);
+
+        // Let its thread see that the handler has gone, and exit.
+        if let Some(thread) = action.thread {
+            thread.update(|pending| {
+                *pending = true;
+                WakeupType::One
+            });
+        }
+
+        if line.actions.is_empty() {
+            claimed_int.remove(&desc);
             self.controller.lock_save_irq().disable_interrupt(desc);
+            return;
+        }
+
+        // If its thread was the last one running, nothing else will unmask
+        // the line for the handlers left on it.
+        if action.woken {
+            line.threads_woken -= 1;
+
+            if is_level(line.config.trigger) && line.threads_woken == 0 {
+                self.controller
+                    .lock_save_irq()
+                    .enable_interrupt(line.config);
+            }
         }
     }
 
-    fn get_active_handler(&self) -> Option<(Arc<dyn InterruptHandler>, InterruptDescriptor)> {
+    fn get_active_handlers(&self, cpu: usize) -> Option<(InterruptDescriptor, Vec<ActiveHandler>)> {
         let mut claimed_ints = self.claimed_interrupts.lock_save_irq();
 
-        let ctx = self.controller.lock_save_irq().read_active_interrupt()?;
+        let Some(ctx) = self.controller.lock_save_irq().read_active_interrupt() else {
+            // The controller had nothing pending by the time we asked.
+            count_on_cpu(&mut self.spurious.lock_save_irq().counts, cpu);
+            return None;
+        };
+
         let desc = ctx.descriptor();
-        let irq = claimed_ints.get_mut(&desc)?;
-        let handler = irq.handler.upgrade()?;
 
-        Some((handler, desc))
+        let handlers = match claimed_ints.get_mut(&desc) {
+            Some(line) => {
+                count_on_cpu(&mut line.counts, cpu);
+
+                line.actions
+                    .iter()
+                    .filter_map(|action| Some((action.id, action.handler.upgrade()?)))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        Some((desc, handlers))
+    }
+
+    /// Records an interrupt that fired with nobody to handle it, disabling it
+    /// if it keeps on doing so.
+    fn note_unhandled(&self, desc: InterruptDescriptor, cpu: usize) {
+        let mut spurious = self.spurious.lock_save_irq();
+
+        count_on_cpu(&mut spurious.counts, cpu);
+
+        let fired = spurious.unhandled.entry(desc).or_default();
+        *fired += 1;
+
+        if *fired == 1 {
+            warn!("Spurious IRQ {desc:?} fired with no handler");
+        } else if *fired == SPURIOUS_LIMIT {
+            warn!("Spurious IRQ {desc:?} fired {SPURIOUS_LIMIT} times with no handler, disabling");
+            self.controller.lock_save_irq().disable_interrupt(desc);
+        }
     }
 
     pub fn handle_interrupt(&self) {
-        let Some((handler, desc)) = self.get_active_handler() else {
-            warn!("IRQ fired for stale IRQ handle");
+        let cpu = ArchImpl::id();
+
+        let Some((desc, handlers)) = self.get_active_handlers(cpu) else {
+            return;
+        };
+
+        let mut handled = false;
+
+        for (id, handler) in handlers {
+            match handler.handle_irq(desc) {
+                IrqReturn::NotHandled => {}
+                IrqReturn::Handled => handled = true,
+                IrqReturn::WakeThread => {
+                    handled = true;
+                    self.wake_thread(desc, id);
+                }
+            }
+        }
+
+        if handled {
+            self.spurious.lock_save_irq().unhandled.remove(&desc);
+        } else {
+            self.note_unhandled(desc, cpu);
+        }
+    }
+
+    fn wake_thread(&self, desc: InterruptDescriptor, id: usize) {
+        let mut claimed_ints = self.claimed_interrupts.lock_save_irq();
+
+        let Some(line) = claimed_ints.get_mut(&desc) else {
+            return;
+        };
+
+        let Some(action) = line.actions.iter_mut().find(|action| action.id == id) else {
+            return;
+        };
+
+        let Some(thread) = &action.thread else {
+            warn!("IRQ handler {} has no thread to wake", action.name);
             return;
         };
 
-        handler.handle_irq(desc);
+        if !action.woken {
+            action.woken = true;
+            line.threads_woken += 1;
+
+            // A level-triggered line would fire again straight away, until the
+            // thread has dealt with the device.
+            if is_level(line.config.trigger) && line.threads_woken == 1 {
+                self.controller.lock_save_irq().disable_interrupt(desc);
+            }
+        }
+
+        thread.update(|pending| {
+            *pending = true;
+            WakeupType::One
+        });
+    }
+
+    fn thread_done(&self, desc: InterruptDescriptor, id: usize) {
+        let mut claimed_ints = self.claimed_interrupts.lock_save_irq();
+
+        let Some(line) = claimed_ints.get_mut(&desc) else {
+            return;
+        };
+
+        let Some(action) = line.actions.iter_mut().find(|action| action.id == id) else {
+            return;
+        };
+
+        if !action.woken {
+            return;
+        }
+
+        action.woken = false;
+        line.threads_woken -= 1;
+
+        if is_level(line.config.trigger) && line.threads_woken == 0 {
+            self.controller
+                .lock_save_irq()
+                .enable_interrupt(line.config);
+        }
+    }
+
+    /// Returns the activity of every claimed interrupt.
+    pub fn irq_stats(&self) -> Vec<IrqStat> {
+        self.claimed_interrupts
+            .lock_save_irq()
+            .iter()
+            .map(|(desc, line)| IrqStat {
+                desc: *desc,
+                trigger: line.config.trigger,
+                names: line.actions.iter().map(|action| action.name).collect(),
+                counts: line.counts.clone(),
+            })
+            .collect()
+    }
+
+    /// Returns how many interrupts nobody handled, indexed by CPU.
+    pub fn spurious_counts(&self) -> Vec<usize> {
+        self.spurious.lock_save_irq().counts.clone()
     }
 
     pub fn raise_ipi(&self, cpu: usize) {
@@ -185,16 +550,39 @@ impl Driver for InterruptManager {
     }
 }
 
-#[derive(Clone)]
+/// A driver's hold on an interrupt line, which it gives up when dropped.
 pub struct ClaimedInterrupt {
     desc: InterruptDescriptor,
+    id: usize,
     manager: Arc<InterruptManager>,
-    handler: Weak<dyn InterruptHandler>,
 }
 
 impl Drop for ClaimedInterrupt {
     fn drop(&mut self) {
-        self.manager.remove_interrupt(self.desc);
+        self.manager.remove_interrupt(self.desc, self.id);
+    }
+}
+
+/// The body of a threaded handler's kernel thread.
+async fn irq_thread<T: ThreadedInterruptHandler + 'static>(
+    manager: Arc<InterruptManager>,
+    desc: InterruptDescriptor,
+    id: usize,
+    handler: Weak<T>,
+    pending: CondVar<bool>,
+) {
+    loop {
+        pending
+            .wait_until(|pending| core::mem::take(pending).then_some(()))
+            .await;
+
+        let Some(handler) = handler.upgrade() else {
+            return;
+        };
+
+        handler.handle_irq_thread(desc).await;
+
+        manager.thread_done(desc, id);
     }
 }
 
@@ -210,3 +598,283 @@ pub fn set_interrupt_root(root: Arc<InterruptManager>) {
 pub fn get_interrupt_root() -> Option<Arc<InterruptManager>> {
     ROOT_INTERRUPT_CONTROLLER.get().cloned()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ClaimedInterrupt, InterruptConfig, InterruptContext, InterruptController,
+        InterruptDescriptor, InterruptHandler, InterruptManager, IrqFlags, IrqReturn,
+        ThreadedInterruptHandler, TriggerMode, irq_thread,
+    };
+    use crate::{
+        drivers::Driver,
+        sync::{CondVar, SpinLock},
+    };
+    use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
+    use async_trait::async_trait;
+    use core::{
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{Context, Poll},
+    };
+    use libkernel::error::{KernelError, Result};
+    use moss_macros::ktest;
+
+    const LINE: InterruptConfig = InterruptConfig {
+        descriptor: InterruptDescriptor::Spi(40),
+        trigger: TriggerMode::LevelHigh,
+    };
+
+    struct MockCtx(InterruptDescriptor);
+
+    impl InterruptContext for MockCtx {
+        fn descriptor(&self) -> InterruptDescriptor {
+            self.0
+        }
+    }
+
+    /// A controller with a single interrupt pending at a time.
+    #[derive(Default)]
+    struct MockController {
+        enabled: BTreeMap<InterruptDescriptor, bool>,
+        active: Option<InterruptDescriptor>,
+    }
+
+    impl InterruptController for MockController {
+        fn enable_interrupt(&mut self, i: InterruptConfig) {
+            self.enabled.insert(i.descriptor, true);
+        }
+
+        fn disable_interrupt(&mut self, i: InterruptDescriptor) {
+            self.enabled.insert(i, false);
+        }
+
+        fn read_active_interrupt(&mut self) -> Option<Box<dyn InterruptContext>> {
+            self.active
+                .take()
+                .map(|desc| Box::new(MockCtx(desc)) as Box<dyn InterruptContext>)
+        }
+
+        fn raise_ipi(&mut self, _target_cpu_id: usize) {}
+
+        fn enable_core(&mut self, _cpu_id: usize) {}
+
+        fn parse_fdt_interrupt_regs(
+            &self,
+            _iter: &mut dyn Iterator<Item = u32>,
+        ) -> Result<InterruptConfig> {
+            Err(KernelError::NotSupported)
+        }
+    }
+
+    struct Mock {
+        controller: Arc<SpinLock<MockController>>,
+        manager: Arc<InterruptManager>,
+    }
+
+    impl Mock {
+        fn new() -> Self {
+            let controller = Arc::new(SpinLock::new(MockController::default()));
+            let manager = InterruptManager::new("mock-intc", controller.clone());
+
+            Self {
+                controller,
+                manager,
+            }
+        }
+
+        fn fire(&self) {
+            self.controller.lock_save_irq().active = Some(LINE.descriptor);
+            self.manager.handle_interrupt();
+        }
+
+        fn enabled(&self) -> bool {
+            self.controller.lock_save_irq().enabled[&LINE.descriptor]
+        }
+
+        fn unhandled(&self) -> usize {
+            let spurious = self.manager.spurious.lock_save_irq();
+
+            spurious
+                .unhandled
+                .get(&LINE.descriptor)
+                .copied()
+                .unwrap_or(0)
+        }
+
+        /// Claims the line for a threaded handler, without starting its
+        /// thread.
+        fn claim_threaded(&self, ret: IrqReturn) -> (Arc<TestDev>, IrqThread) {
+            let pending = CondVar::new(false);
+            let (dev, id) = self
+                .manager
+                .claim(
+                    LINE,
+                    IrqFlags::SHARED,
+                    Some(pending.clone()),
+                    TestDev::new(ret),
+                )
+                .unwrap();
+            let thread = irq_thread(
+                self.manager.clone(),
+                LINE.descriptor,
+                id,
+                Arc::downgrade(&dev),
+                pending,
+            );
+
+            (dev, Box::pin(thread))
+        }
+    }
+
+    /// The body of a handler's thread, run by hand.
+    type IrqThread = Pin<Box<dyn Future<Output = ()>>>;
+
+    fn poll(fut: Pin<&mut dyn Future<Output = ()>>) -> Poll<()> {
+        let waker = crate::sched::current_work_waker();
+
+        fut.poll(&mut Context::from_waker(&waker))
+    }
+
+    struct TestDev {
+        _irq: ClaimedInterrupt,
+        ret: IrqReturn,
+        calls: AtomicUsize,
+        thread_runs: AtomicUsize,
+    }
+
+    impl TestDev {
+        fn new(ret: IrqReturn) -> impl FnOnce(ClaimedInterrupt) -> Self {
+            move |irq| Self {
+                _irq: irq,
+                ret,
+                calls: AtomicUsize::new(0),
+                thread_runs: AtomicUsize::new(0),
+            }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::Relaxed)
+        }
+
+        fn thread_runs(&self) -> usize {
+            self.thread_runs.load(Ordering::Relaxed)
+        }
+    }
+
+    impl Driver for TestDev {
+        fn name(&self) -> &'static str {
+            "test-irq"
+        }
+    }
+
+    impl InterruptHandler for TestDev {
+        fn handle_irq(&self, _desc: InterruptDescriptor) -> IrqReturn {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.ret
+        }
+    }
+
+    #[async_trait]
+    impl ThreadedInterruptHandler for TestDev {
+        async fn handle_irq_thread(&self, _desc: InterruptDescriptor) {
+            self.thread_runs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[ktest]
+    fn irq_shared_dispatch() {
+        let mock = Mock::new();
+        let claim = |ret| mock.manager.claim_shared_interrupt(LINE, TestDev::new(ret));
+
+        let a = claim(IrqReturn::NotHandled).unwrap();
+        let b = claim(IrqReturn::Handled).unwrap();
+        let c = claim(IrqReturn::NotHandled).unwrap();
+        assert!(mock.enabled());
+
+        // Only shared claims can join the line.
+        assert_eq!(
+            mock.manager
+                .claim_interrupt(LINE, TestDev::new(IrqReturn::Handled))
+                .err(),
+            Some(KernelError::InUse)
+        );
+
+        // Every handler sees the interrupt, and one of them owning up is
+        // enough for it to count as handled.
+        mock.fire();
+        assert_eq!([a.calls(), b.calls(), c.calls()], [1, 1, 1]);
+        assert_eq!(mock.unhandled(), 0);
+
+        drop(b);
+        mock.fire();
+        assert_eq!([a.calls(), c.calls()], [2, 2]);
+        assert_eq!(mock.unhandled(), 1);
+        assert_eq!(mock.manager.spurious_counts().iter().sum::<usize>(), 1);
+
+        drop(a);
+        assert!(mock.enabled());
+        drop(c);
+        assert!(!mock.enabled());
+    }
+
+    #[ktest]
+    fn irq_threaded_wake() {
+        let mock = Mock::new();
+        let (dev, mut thread) = mock.claim_threaded(IrqReturn::WakeThread);
+
+        // Nothing to do until the interrupt fires.
+        assert_eq!(poll(thread.as_mut()), Poll::Pending);
+        assert_eq!(dev.thread_runs(), 0);
+
+        mock.fire();
+        assert_eq!(dev.calls(), 1);
+        assert_eq!(mock.unhandled(), 0);
+
+        assert_eq!(poll(thread.as_mut()), Poll::Pending);
+        assert_eq!(dev.thread_runs(), 1);
+
+        // Once the handler has gone, so does its thread.
+        drop(dev);
+        assert_eq!(poll(thread.as_mut()), Poll::Ready(()));
+    }
+
+    #[ktest]
+    fn irq_threaded_masks_level_line() {
+        let mock = Mock::new();
+        let (a, mut thread_a) = mock.claim_threaded(IrqReturn::WakeThread);
+        let (b, mut thread_b) = mock.claim_threaded(IrqReturn::WakeThread);
+
+        // The line stays masked until every woken thread has finished.
+        mock.fire();
+        assert!(!mock.enabled());
+
+        assert_eq!(poll(thread_a.as_mut()), Poll::Pending);
+        assert_eq!(a.thread_runs(), 1);
+        assert!(!mock.enabled());
+
+        assert_eq!(poll(thread_b.as_mut()), Poll::Pending);
+        assert_eq!(b.thread_runs(), 1);
+        assert!(mock.enabled());
+    }
+
+    #[ktest]
+    fn irq_remove_with_thread_pending() {
+        let mock = Mock::new();
+        let (a, mut thread_a) = mock.claim_threaded(IrqReturn::WakeThread);
+        let (b, _thread_b) = mock.claim_threaded(IrqReturn::NotHandled);
+
+        mock.fire();
+        assert!(!mock.enabled());
+
+        // Its thread never gets to finish, so the line has to be unmasked for
+        // the handler left on it.
+        drop(a);
+        assert!(mock.enabled());
+        assert_eq!(poll(thread_a.as_mut()), Poll::Ready(()));
+
+        mock.fire();
+        assert_eq!(b.calls(), 2);
+    }
+}
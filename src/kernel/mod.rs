@@ -4,5 +4,6 @@ pub mod hostname;
 pub mod kpipe;
 pub mod power;
 pub mod rand;
+pub mod stack_protector;
 pub mod sysinfo;
 pub mod uname;
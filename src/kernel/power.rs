@@ -1,4 +1,4 @@
-use crate::{ArchImpl, arch::Arch, sched::syscall_ctx::ProcessCtx};
+use crate::{ArchImpl, arch::Arch, console, sched::syscall_ctx::ProcessCtx};
 use core::sync::atomic::AtomicBool;
 use libkernel::{
     error::{KernelError, Result},
@@ -44,9 +44,13 @@ pub async fn sys_reboot(
     match op {
         LINUX_REBOOT_CMD_POWER_OFF => {
             // User is supposed to sync first.
+            console::flush();
             ArchImpl::power_off()
         }
-        LINUX_REBOOT_CMD_RESTART => ArchImpl::restart(),
+        LINUX_REBOOT_CMD_RESTART => {
+            console::flush();
+            ArchImpl::restart()
+        }
         LINUX_REBOOT_CMD_CAD_ON => {
             CAD_ENABLED.store(true, core::sync::atomic::Ordering::SeqCst);
             Ok(0)
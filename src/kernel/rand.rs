@@ -3,16 +3,15 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 use alloc::{sync::Arc, vec::Vec};
 
 use crate::{
+    crypto::chacha20::{self, ChaCha20},
     drivers::timer::uptime,
     memory::uaccess::copy_to_user_slice,
     per_cpu_private,
     sync::{CondVar, OnceLock, SpinLock},
 };
 use blake2::{Blake2s256, Digest};
-use chacha20::ChaCha20Rng;
 use libkernel::memory::address::TUA;
 use libkernel::{error::Result, sync::condvar::WakeupType};
-use rand::{Rng, SeedableRng};
 
 /// A hardware or software source of entropy that the pool can query.
 pub trait EntropySource: Send + Sync {
@@ -134,24 +133,22 @@ pub fn register_entropy_source(source: Arc<dyn EntropySource>) {
 static ENTROPY_POOL: OnceLock<EntropyPool> = OnceLock::new();
 
 struct CpuRng {
-    rng: ChaCha20Rng,
+    rng: ChaCha20,
     seeded: bool,
     bytes_since_reseed: usize,
 }
 
-unsafe impl Send for CpuRng {}
-
 impl CpuRng {
     fn new() -> Self {
         Self {
-            rng: ChaCha20Rng::from_seed([0u8; 32]),
+            rng: ChaCha20::new(&[0; chacha20::KEY_LEN], &[0; chacha20::NONCE_LEN], 0),
             seeded: false,
             bytes_since_reseed: 0,
         }
     }
 
     fn apply_seed(&mut self, seed: [u8; 32]) {
-        self.rng = ChaCha20Rng::from_seed(seed);
+        self.rng = ChaCha20::new(&seed, &[0; chacha20::NONCE_LEN], 0);
         self.seeded = true;
         self.bytes_since_reseed = 0;
     }
@@ -160,7 +157,7 @@ impl CpuRng {
     /// then constructing a new ChaCha20 instance from the combined material.
     fn reseed_with_blake(&mut self, blake_seed: [u8; 32]) {
         let mut self_bytes = [0u8; 32];
-        self.rng.fill_bytes(&mut self_bytes);
+        self.rng.fill(&mut self_bytes);
 
         let mut new_seed = [0u8; 32];
 
@@ -168,12 +165,12 @@ impl CpuRng {
             new_seed[i] = blake_seed[i] ^ self_bytes[i];
         }
 
-        self.rng = ChaCha20Rng::from_seed(new_seed);
+        self.rng = ChaCha20::new(&new_seed, &[0; chacha20::NONCE_LEN], 0);
         self.bytes_since_reseed = 0;
     }
 
     fn fill(&mut self, buf: &mut [u8]) {
-        self.rng.fill_bytes(buf);
+        self.rng.fill(buf);
         self.bytes_since_reseed += buf.len();
     }
 }
@@ -205,6 +202,74 @@ pub async fn fill_random_bytes(buf: &mut [u8]) {
     CPU_RNG.borrow_mut().fill(buf);
 }
 
+/// Fill `buf` with random bytes without waiting for the entropy pool.
+///
+/// If this CPU's RNG hasn't been seeded yet, it is keyed from whatever the pool
+/// holds so far, mixed with the current uptime. That is good enough for
+/// layout randomization early in boot, but not for key material; use
+/// [`fill_random_bytes`] for that.
+pub fn fill_random_bytes_nowait(buf: &mut [u8]) {
+    if !CPU_RNG.borrow().seeded {
+        let pool = entropy_pool();
+
+        if let Some(seed) = pool.try_extract_seed() {
+            CPU_RNG.borrow_mut().apply_seed(seed);
+        } else {
+            // Don't credit the uptime; it's only there so that successive
+            // callers don't see the same stream.
+            pool.state
+                .lock_save_irq()
+                .update(uptime().as_nanos().to_le_bytes());
+
+            let seed = pool.extract_seed_inner();
+            CPU_RNG.borrow_mut().reseed_with_blake(seed);
+        }
+    }
+
+    CPU_RNG.borrow_mut().fill(buf);
+}
+
+static BOOT_ID: OnceLock<[u8; 16]> = OnceLock::new();
+
+/// Returns this boot's random (version 4) UUID, generated the first time it's
+/// asked for. Userspace pairs it with process start times to tell processes
+/// from different boots apart.
+pub fn boot_id() -> [u8; 16] {
+    *BOOT_ID.get_or_init(|| {
+        let mut id = [0; 16];
+
+        fill_random_bytes_nowait(&mut id);
+
+        id[6] = (id[6] & 0x0f) | 0x40;
+        id[8] = (id[8] & 0x3f) | 0x80;
+
+        id
+    })
+}
+
+/// Credits `seed`, handed over by the bootloader, to the entropy pool. As Linux
+/// does by default, the bootloader is trusted to have drawn it from a real
+/// entropy source.
+pub fn add_bootloader_entropy(seed: &[u8]) {
+    entropy_pool().add_entropy(seed, seed.len() * 8);
+}
+
+/// Returns 64 random bits straight from the entropy pool, for secrets needed
+/// before the per-CPU generators exist.
+///
+/// Like [`fill_random_bytes_nowait`], this doesn't wait for the pool to be
+/// seeded. `noise` is mixed in without being credited, so that boots without
+/// any entropy source still differ.
+pub fn early_random_u64(noise: u64) -> u64 {
+    let pool = entropy_pool();
+
+    pool.state.lock_save_irq().update(noise.to_le_bytes());
+
+    let seed = pool.extract_seed_inner();
+
+    u64::from_le_bytes(seed[..8].try_into().unwrap())
+}
+
 const GETRANDOM_CHUNK: usize = 256;
 
 pub async fn sys_getrandom(ubuf: TUA<u8>, size: isize, _flags: u32) -> Result<usize> {
@@ -0,0 +1,29 @@
+//! Stack smashing protection.
+//!
+//! The kernel is built with `-Zstack-protector=strong`: functions that keep
+//! arrays or address-taken locals on the stack save `__stack_chk_guard` in
+//! their frame on entry, and check that it is still there before returning.
+//! The guard is picked at random early in boot, so an overflow can't write it
+//! back without first leaking it.
+
+use core::sync::atomic::AtomicU64;
+
+/// The canary value. Zero until the boot code installs the per-boot value,
+/// which it must do while no Rust frame that saved the old one is live.
+#[unsafe(no_mangle)]
+#[allow(non_upper_case_globals)]
+static __stack_chk_guard: AtomicU64 = AtomicU64::new(0);
+
+/// Called by a function that found its canary overwritten.
+#[unsafe(no_mangle)]
+extern "C" fn __stack_chk_fail() -> ! {
+    panic!("Kernel stack smashing detected");
+}
+
+/// Derives the stack guard for this boot from `random`.
+///
+/// As on Linux, the low byte is zeroed so that an overflow through a string
+/// copy stops at the canary instead of running past it.
+pub fn stack_guard(random: u64) -> u64 {
+    random & !0xff
+}
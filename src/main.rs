@@ -5,25 +5,35 @@
 #![allow(internal_features)]
 #![cfg_attr(test, feature(core_intrinsics))]
 #![feature(custom_test_frameworks)]
+#![cfg_attr(feature = "kasan", feature(sanitize))]
 #![reexport_test_harness_main = "test_main"]
 #![test_runner(crate::testing::test_runner)]
 
 use alloc::{
     boxed::Box,
     string::{String, ToString},
+    sync::Arc,
     vec,
     vec::Vec,
 };
 use arch::{Arch, ArchImpl};
 use core::panic::PanicInfo;
-use drivers::{fdt_prober::get_fdt, fs::register_fs_drivers};
-use fs::VFS;
+use drivers::{fdt_prober::get_fdt, fs::register_fs_drivers, init::wait_for_async_probes};
+use fs::{
+    MntFlags, VFS,
+    blk::{
+        register_block_device,
+        verity::{VerityBlkDev, VerityParams},
+    },
+    mnt_ns::init_mnt_ns,
+    namei::ResolveFlags,
+};
 use getargs::{Opt, Options};
 use libkernel::{
     CpuOps,
     fs::{
-        BlockDevice, OpenFlags, attr::FilePermissions, blk::ramdisk::RamdiskBlkDev, path::Path,
-        pathbuf::PathBuf,
+        BlockDevice, Inode, OpenFlags, attr::FilePermissions, blk::ramdisk::RamdiskBlkDev,
+        path::Path, pathbuf::PathBuf,
     },
     memory::{
         address::{PA, VA},
@@ -34,15 +44,23 @@ use libkernel::{
 use log::{error, warn};
 use process::ctx::UserCtx;
 use sched::{
-    sched_init, spawn_kernel_work, syscall_ctx::ProcessCtx, uspc_ret::dispatch_userspace_task,
+    isolation::{isolate_cpus, parse_cpu_list},
+    sched_init,
+    sched_task::CpuMask,
+    spawn_kernel_work,
+    syscall_ctx::ProcessCtx,
+    uspc_ret::dispatch_userspace_task,
 };
 
 extern crate alloc;
 extern crate moss_macros;
 
 mod arch;
+#[cfg(feature = "bench")]
+mod bench;
 mod clock;
 mod console;
+mod crypto;
 mod drivers;
 mod fs;
 mod interrupts;
@@ -73,17 +91,49 @@ fn on_panic(info: &PanicInfo) -> ! {
         error!("Kernel panicked at unknown location: {panic_msg}");
     }
 
+    console::flush();
+
     ArchImpl::power_off();
 }
 
+async fn mount_shm(mount_point: Arc<dyn Inode>) -> libkernel::error::Result<()> {
+    // Anyone may create objects, but only remove their own.
+    VFS.mount(
+        &init_mnt_ns(),
+        mount_point,
+        Path::new("/dev/shm"),
+        "tmpfs",
+        "tmpfs",
+        "mode=1777",
+        false,
+        MntFlags::MNT_NOSUID,
+    )
+    .await
+}
+
 async fn launch_init(mut ctx: ProcessCtx, mut opts: KOptions) {
     let init = opts
         .init
         .unwrap_or_else(|| panic!("No init specified in kernel command line"));
 
+    if opts.norandmaps {
+        process::exec::aslr::set_randomize_va_space(0).unwrap();
+    }
+
+    if let Some(pages) = opts.stack_guard_gap {
+        memory::fault::set_stack_guard_gap(pages);
+    }
+
+    if let Some(kbytes) = opts.block_cache_kbytes {
+        fs::blk::cache::set_cache_limit(kbytes.saturating_mul(1024));
+    }
+
+    // Init may want any device found at boot.
+    wait_for_async_probes().await;
+
     let dt = get_fdt();
 
-    let initrd_block_dev: Option<Box<dyn BlockDevice>> = if let Some(chosen) =
+    let mut initrd_block_dev: Option<Box<dyn BlockDevice>> = if let Some(chosen) =
         dt.find_nodes("/chosen").next()
         && let Some(start_addr) = chosen
             .find_property("linux,initrd-start")
@@ -97,14 +147,21 @@ async fn launch_init(mut ctx: ProcessCtx, mut opts: KOptions) {
             PA::from_value(end_addr as _),
         );
 
-        Some(Box::new(
+        let dev: Arc<dyn BlockDevice> = Arc::new(
             RamdiskBlkDev::new(
                 region,
                 VA::from_value(0xffff_9800_0000_0000),
                 &mut *ArchImpl::kern_address_space().lock_save_irq(),
             )
             .unwrap(),
-        ))
+        );
+
+        // Expose the raw device as /dev/ram0, e.g. as a base for stacked
+        // devices.
+        register_block_device("ram0", dev.clone())
+            .unwrap_or_else(|e| panic!("Failed to register initrd block device: {e}"));
+
+        Some(Box::new(dev))
     } else {
         None
     };
@@ -120,7 +177,22 @@ async fn launch_init(mut ctx: ProcessCtx, mut opts: KOptions) {
         .root_fs
         .unwrap_or_else(|| panic!("No root FS driver specified in kernel command line"));
 
-    VFS.mount_root(&root_fs, initrd_block_dev)
+    // Wrap the root device so that every block is checked against its hash
+    // tree. The device can't be written, so mount it read-only.
+    let read_only = opts.verity.is_some();
+
+    if let Some(verity) = opts.verity.take() {
+        let dev = initrd_block_dev
+            .take()
+            .unwrap_or_else(|| panic!("--verity given without a root block device"));
+
+        initrd_block_dev = Some(Box::new(
+            VerityBlkDev::new(dev, verity)
+                .unwrap_or_else(|e| panic!("Failed to set up verity root device: {e}")),
+        ));
+    }
+
+    VFS.mount_root(&root_fs, initrd_block_dev, read_only)
         .await
         .unwrap_or_else(|e| panic!("Failed to mount root FS: {e}"));
 
@@ -131,9 +203,30 @@ async fn launch_init(mut ctx: ProcessCtx, mut opts: KOptions) {
             .await
             .unwrap_or_else(|e| panic!("Could not find automount path: {}. {e}", path.as_str()));
 
-        VFS.mount(mount_point, fs, None)
+        VFS.mount(
+            &init_mnt_ns(),
+            mount_point,
+            path,
+            fs,
+            fs,
+            "",
+            false,
+            MntFlags::empty(),
+        )
+        .await
+        .unwrap_or_else(|e| panic!("Automount failed: {e}"));
+    }
+
+    // POSIX shared memory objects (shm_open) are files in a tmpfs at /dev/shm.
+    if let Ok(shm) = VFS
+        .resolve_path_absolute(Path::new("/dev/shm"), VFS.root_inode())
+        .await
+    {
+        mount_shm(shm)
             .await
-            .unwrap_or_else(|e| panic!("Automount failed: {e}"));
+            .unwrap_or_else(|e| panic!("Failed to mount /dev/shm: {e}"));
+    } else {
+        warn!("No /dev/shm directory, POSIX shared memory is unavailable");
     }
 
     let inode = VFS
@@ -155,6 +248,7 @@ async fn launch_init(mut ctx: ProcessCtx, mut opts: KOptions) {
         .open(
             Path::new("/dev/console"),
             OpenFlags::O_RDWR,
+            ResolveFlags::empty(),
             VFS.root_inode(),
             FilePermissions::empty(),
             &task,
@@ -177,6 +271,14 @@ async fn launch_init(mut ctx: ProcessCtx, mut opts: KOptions) {
             .expect("Could not clone FD");
     }
 
+    if opts.bench {
+        #[cfg(feature = "bench")]
+        bench::run_at_boot().await;
+
+        #[cfg(not(feature = "bench"))]
+        warn!("--bench given, but the kernel was built without the bench feature");
+    }
+
     #[cfg(test)]
     test_main();
 
@@ -194,16 +296,28 @@ async fn launch_init(mut ctx: ProcessCtx, mut opts: KOptions) {
 struct KOptions {
     init: Option<PathBuf>,
     root_fs: Option<String>,
+    verity: Option<VerityParams>,
     automounts: Vec<(PathBuf, String)>,
     init_args: Vec<String>,
+    norandmaps: bool,
+    stack_guard_gap: Option<usize>,
+    block_cache_kbytes: Option<usize>,
+    isolcpus: Option<CpuMask>,
+    bench: bool,
 }
 
 fn parse_args(args: &str) -> KOptions {
     let mut kopts = KOptions {
         init: None,
         root_fs: None,
+        verity: None,
         automounts: Vec::new(),
         init_args: Vec::new(),
+        norandmaps: false,
+        stack_guard_gap: None,
+        block_cache_kbytes: None,
+        isolcpus: None,
+        bench: false,
     };
 
     let mut opts = Options::new(args.split(" "));
@@ -214,6 +328,39 @@ fn parse_args(args: &str) -> KOptions {
                 Opt::Long("init") => kopts.init = Some(PathBuf::from(opts.value().unwrap())),
                 Opt::Long("init-arg") => kopts.init_args.push(opts.value().unwrap().to_string()),
                 Opt::Long("rootfs") => kopts.root_fs = Some(opts.value().unwrap().to_string()),
+                Opt::Long("verity") => {
+                    // Never fall back to an unverified root.
+                    kopts.verity = Some(
+                        VerityParams::parse(opts.value().unwrap())
+                            .unwrap_or_else(|e| panic!("Invalid --verity parameters: {e}")),
+                    );
+                }
+                Opt::Long("norandmaps") => kopts.norandmaps = true,
+                Opt::Long("bench") => kopts.bench = true,
+                Opt::Long("stack-guard-gap") => {
+                    let value = opts.value().unwrap();
+
+                    match value.parse() {
+                        Ok(pages) => kopts.stack_guard_gap = Some(pages),
+                        Err(_) => warn!("Invalid --stack-guard-gap value {value}, ignoring."),
+                    }
+                }
+                Opt::Long("block-cache-kbytes") => {
+                    let value = opts.value().unwrap();
+
+                    match value.parse() {
+                        Ok(kbytes) => kopts.block_cache_kbytes = Some(kbytes),
+                        Err(_) => warn!("Invalid --block-cache-kbytes value {value}, ignoring."),
+                    }
+                }
+                Opt::Long("isolcpus") => {
+                    let value = opts.value().unwrap();
+
+                    match parse_cpu_list(value) {
+                        Some(mask) => kopts.isolcpus = Some(mask),
+                        None => warn!("Invalid --isolcpus value {value}, ignoring."),
+                    }
+                }
                 Opt::Long("automount") => {
                     let string = opts.value().unwrap();
                     let mut split = string.split(",");
@@ -222,6 +369,13 @@ fn parse_args(args: &str) -> KOptions {
 
                     kopts.automounts.push((PathBuf::from(path), fs.to_string()));
                 }
+                // Consumed by the console layer before devices are probed.
+                Opt::Long("console") => {
+                    let _ = opts.value();
+                }
+                Opt::Long("earlycon") => {
+                    let _ = opts.value_opt();
+                }
                 Opt::Long(x) => warn!("Unknown option {x}"),
                 Opt::Short(x) => warn!("Unknown option {x}"),
             },
@@ -232,12 +386,17 @@ fn parse_args(args: &str) -> KOptions {
 }
 
 pub fn kmain(args: String, ctx_frame: *mut UserCtx) {
+    let kopts = parse_args(&args);
+
+    // Before any task is made, so that none starts out on an isolated CPU.
+    if let Some(mask) = kopts.isolcpus {
+        isolate_cpus(mask);
+    }
+
     sched_init();
 
     register_fs_drivers();
 
-    let kopts = parse_args(&args);
-
     {
         // SAFETY: kmain is called prior to init being launched. Thefore, we
         // will be the only access to `ctx` at this point.
@@ -0,0 +1,224 @@
+//! Heap allocation profiling.
+//!
+//! With the `alloc_profile` feature, [`ProfilingHeap`] accounts every heap
+//! allocation to its call site, identified by the return addresses on the
+//! stack when it was made. For each site it keeps the number of allocations
+//! and frees, the bytes still live, and a histogram of the sizes requested.
+//! The table is read through `/proc/allocinfo`, largest live bytes first, and
+//! the addresses can be resolved with `addr2line -e <kernel image>`. A site
+//! whose live bytes keep growing over a long test run is a leak, or at least
+//! bloat worth looking at.
+//!
+//! Each object carries a hidden header recording its site, so that the free
+//! can be accounted to it, and every allocation and free takes a global lock:
+//! this is a debugging aid, not something to leave on. The kernel has to be
+//! built with frame pointers for the call sites to be found.
+
+use crate::{
+    arch::{Arch, ArchImpl},
+    sync::SpinLock,
+};
+use alloc::vec::Vec;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cmp::Reverse,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Number of return addresses identifying a call site. The innermost few are
+/// always in the allocator itself, so this has to reach past them.
+pub const SITE_DEPTH: usize = 6;
+
+/// Number of size classes in a site's histogram: up to 8 bytes, then each
+/// power of two up to a page, then anything larger.
+pub const NR_SIZE_CLASSES: usize = 11;
+
+/// Number of call sites that can be told apart. Allocations from any further
+/// sites are only counted as unrecorded.
+const NR_SITES: usize = 1024;
+
+/// How many slots are tried for a site before giving up on it.
+const MAX_PROBE: usize = 16;
+
+/// Header value of an object whose site wasn't recorded.
+const NO_SITE: usize = usize::MAX;
+
+/// Allocation counters of one call site.
+#[derive(Clone, Copy)]
+pub struct AllocSite {
+    /// Return addresses of the call site, innermost first.
+    pub trace: [usize; SITE_DEPTH],
+    /// Allocations made by the site.
+    pub allocs: u64,
+    /// Allocations made by the site that have since been freed.
+    pub frees: u64,
+    /// Bytes allocated by the site that are still live.
+    pub live_bytes: usize,
+    /// Number of allocations in each size class.
+    pub sizes: [u64; NR_SIZE_CLASSES],
+}
+
+impl AllocSite {
+    const EMPTY: Self = Self {
+        trace: [0; SITE_DEPTH],
+        allocs: 0,
+        frees: 0,
+        live_bytes: 0,
+        sizes: [0; NR_SIZE_CLASSES],
+    };
+
+    /// Number of objects allocated by the site that are still live.
+    pub fn live_objs(&self) -> u64 {
+        self.allocs - self.frees
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allocs == 0
+    }
+}
+
+static SITES: SpinLock<[AllocSite; NR_SITES]> = SpinLock::new([AllocSite::EMPTY; NR_SITES]);
+
+/// Allocations made while the site table was full.
+static UNRECORDED: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the size class of an allocation of `size` bytes.
+fn size_class(size: usize) -> usize {
+    let order = size.max(1).next_power_of_two().trailing_zeros() as usize;
+
+    order.saturating_sub(3).min(NR_SIZE_CLASSES - 1)
+}
+
+fn hash(trace: &[usize; SITE_DEPTH]) -> usize {
+    trace.iter().fold(0, |hash: usize, addr| {
+        (hash.rotate_left(5) ^ addr).wrapping_mul(0x9e37_79b9)
+    })
+}
+
+/// Accounts an allocation of `size` bytes to the current call site, returning
+/// the site's slot, or `NO_SITE` if the table is full.
+fn record_alloc(size: usize) -> usize {
+    let mut frames = [0; SITE_DEPTH + 1];
+
+    ArchImpl::backtrace(&mut frames);
+
+    // The first frame is this function's caller, which is always the same.
+    let mut trace = [0; SITE_DEPTH];
+    trace.copy_from_slice(&frames[1..]);
+
+    let mut sites = SITES.lock_save_irq();
+    let start = hash(&trace);
+
+    for probe in 0..MAX_PROBE {
+        let slot = (start + probe) % NR_SITES;
+        let site = &mut sites[slot];
+
+        if site.is_empty() {
+            site.trace = trace;
+        } else if site.trace != trace {
+            continue;
+        }
+
+        site.allocs += 1;
+        site.live_bytes += size;
+        site.sizes[size_class(size)] += 1;
+
+        return slot;
+    }
+
+    UNRECORDED.fetch_add(1, Ordering::Relaxed);
+
+    NO_SITE
+}
+
+fn record_free(slot: usize, size: usize) {
+    if slot == NO_SITE {
+        return;
+    }
+
+    let mut sites = SITES.lock_save_irq();
+    let site = &mut sites[slot];
+
+    site.frees += 1;
+    site.live_bytes -= size;
+}
+
+/// Returns every call site seen so far, the one with the most live bytes
+/// first.
+pub fn sites() -> Vec<AllocSite> {
+    // Reserve up front: allocating with the table locked would deadlock.
+    let mut snapshot = Vec::with_capacity(NR_SITES);
+
+    snapshot.extend(
+        SITES
+            .lock_save_irq()
+            .iter()
+            .filter(|site| !site.is_empty())
+            .copied(),
+    );
+
+    snapshot.sort_unstable_by_key(|site| Reverse(site.live_bytes));
+    snapshot
+}
+
+/// Returns the number of allocations that couldn't be accounted to a site.
+pub fn unrecorded() -> u64 {
+    UNRECORDED.load(Ordering::Relaxed)
+}
+
+/// Returns the layout actually allocated for `layout`, and the offset of the
+/// object within it. The site header sits just before the object.
+fn padded_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let offset = layout.align().max(size_of::<usize>());
+    let size = layout.size().checked_add(offset)?;
+
+    Some((Layout::from_size_align(size, layout.align()).ok()?, offset))
+}
+
+pub struct ProfilingHeap<H: GlobalAlloc> {
+    heap: H,
+}
+
+impl<H: GlobalAlloc> ProfilingHeap<H> {
+    pub const fn new(heap: H) -> Self {
+        Self { heap }
+    }
+}
+
+unsafe impl<H: GlobalAlloc> GlobalAlloc for ProfilingHeap<H> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some((padded, offset)) = padded_layout(layout) else {
+            return core::ptr::null_mut();
+        };
+
+        let base = unsafe { self.heap.alloc(padded) };
+
+        if base.is_null() {
+            return base;
+        }
+
+        let slot = record_alloc(layout.size());
+
+        // SAFETY: `offset` is within the allocation and at least a word past
+        // its start, and both are aligned to a word or more.
+        unsafe {
+            let ptr = base.add(offset);
+
+            ptr.cast::<usize>().sub(1).write(slot);
+
+            ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // The layout was padded successfully when the object was allocated.
+        let (padded, offset) = padded_layout(layout).unwrap();
+
+        // SAFETY: `ptr` was returned by `alloc` above, which wrote the header.
+        let slot = unsafe { ptr.cast::<usize>().sub(1).read() };
+
+        record_free(slot, layout.size());
+
+        unsafe { self.heap.dealloc(ptr.sub(offset), padded) };
+    }
+}
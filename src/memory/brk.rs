@@ -1,7 +1,8 @@
 use core::convert::Infallible;
 
-use libkernel::memory::address::VA;
+use libkernel::memory::{PAGE_SIZE, address::VA};
 
+use super::overcommit;
 use crate::sched::syscall_ctx::ProcessCtx;
 
 /// Handles the `brk` system call.
@@ -29,6 +30,16 @@ pub async fn sys_brk(ctx: &ProcessCtx, addr: VA) -> Result<usize, Infallible> {
         return Ok(current_brk_val);
     }
 
+    // Growing the heap commits the new pages, if the policy allows it.
+    let growth = addr
+        .value()
+        .checked_next_multiple_of(PAGE_SIZE)
+        .map_or(0, |end| end.saturating_sub(vm.current_brk().value()));
+
+    let Ok(_reservation) = overcommit::reserve(growth) else {
+        return Ok(vm.current_brk().value());
+    };
+
     // For non-null addresses, attempt to resize the break.
     let resize_result = vm.resize_brk(addr);
 
@@ -1,19 +1,27 @@
-use crate::{process::ProcVM, sync::SpinLock};
+use crate::{
+    fs::page_cache,
+    process::{ProcVM, thread_group::rsrc_lim::RlimitId},
+    sched::current_work,
+    sync::SpinLock,
+};
 use alloc::boxed::Box;
 use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use libkernel::{
     error::{KernelError, MapError, Result},
     memory::{
+        HUGE_PAGE_ORDER, PAGE_SIZE,
         address::VA,
         paging::permissions::PtePermissions,
         proc_vm::{
             address_space::{PageInfo, UserAddressSpace},
+            memory_map::STACK_GUARD_GAP,
             vmarea::AccessKind,
         },
     },
 };
 
-use super::{PAGE_ALLOC, page::ClaimedPage};
+use super::{PAGE_ALLOC, PageOffsetTranslator, overcommit, page::ClaimedPage, userfaultfd};
 
 /// Represents the outcome of a page fault handling attempt.
 ///
@@ -43,31 +51,200 @@ pub enum FaultResolution {
     Deferred(Box<dyn Future<Output = Result<()>> + 'static + Send>),
 }
 
+static GUARD_GAP: AtomicUsize = AtomicUsize::new(STACK_GUARD_GAP);
+
+/// Returns the gap, in bytes, kept free below stacks of new processes.
+pub fn stack_guard_gap() -> usize {
+    GUARD_GAP.load(Ordering::Relaxed)
+}
+
+/// Sets the gap kept free below stacks of processes started from now on, in
+/// pages.
+pub fn set_stack_guard_gap(pages: usize) {
+    GUARD_GAP.store(pages.saturating_mul(PAGE_SIZE), Ordering::Relaxed);
+}
+
 /// Handle a page fault when a PTE is not present.
+///
+/// `user_access` is `false` when the kernel faulted while accessing user
+/// memory on the task's behalf.
 pub fn handle_demand_fault(
     proc_vm: Arc<SpinLock<ProcVM>>,
     faulting_addr: VA,
     access_kind: AccessKind,
+    user_access: bool,
 ) -> Result<FaultResolution> {
     let mut vm = proc_vm.lock_save_irq();
 
+    // An access below a stack grows it, within the stack size limit.
+    if vm.mm().find_vma(faulting_addr).is_none() {
+        let limit = current_work()
+            .process
+            .rsrc_lim
+            .lock_save_irq()
+            .get(RlimitId::STACK)
+            .rlim_cur;
+
+        // The pages the stack grows by are committed.
+        let growth = vm
+            .mm()
+            .iter_vmas()
+            .find(|vma| vma.region().start_address() > faulting_addr)
+            .map_or(0, |vma| {
+                vma.region().start_address().value() - faulting_addr.page_aligned().value()
+            });
+
+        let Ok(_reservation) = overcommit::reserve(growth) else {
+            return Ok(FaultResolution::Denied);
+        };
+
+        if vm
+            .mm_mut()
+            .expand_stack(faulting_addr, limit as usize)
+            .is_err()
+        {
+            return Ok(FaultResolution::Denied);
+        }
+
+        vm.recharge();
+    }
+
     let vma = match vm.find_vma_for_fault(faulting_addr, access_kind) {
         Some(vma) => vma,
         None => return Ok(FaultResolution::Denied),
     }
     .clone();
 
-    let mut new_page = ClaimedPage::alloc_zeroed()?;
     let page_va = faulting_addr.page_aligned();
 
+    // Missing pages of a registered range are populated by the userfaultfd
+    // monitor.
+    if let Some(uffd) = vma.userfault().and_then(userfaultfd::lookup) {
+        drop(vm);
+
+        if !user_access && uffd.user_mode_only() {
+            return Ok(FaultResolution::Denied);
+        }
+
+        let tid = current_work().tid.value();
+
+        return Ok(FaultResolution::Deferred(Box::new(uffd.handle_fault(
+            proc_vm,
+            faulting_addr,
+            access_kind,
+            tid,
+        ))));
+    }
+
+    if let Some((inode, pg_idx)) = vma.resolve_shared_fault(faulting_addr) {
+        drop(vm);
+
+        // Shared file mapping: map the file's own page, so that writes are
+        // seen by every mapping of the file.
+        return Ok(FaultResolution::Deferred(Box::new(async move {
+            // SAFETY: `get_page` takes a reference on the page for us.
+            let page = unsafe { ClaimedPage::from_pfn(inode.get_page(pg_idx).await?) };
+
+            let mut vm = proc_vm.lock_save_irq();
+
+            // As for private file mappings below, the VMA may have changed
+            // while we slept.
+            let is_vma_still_valid = vm
+                .find_vma_for_fault(faulting_addr, access_kind)
+                .is_some_and(|validated_vma| *validated_vma == vma);
+
+            if !is_vma_still_valid {
+                return Ok(());
+            }
+
+            match vm.mm_mut().address_space_mut().map_page(
+                page.pa().to_pfn(),
+                page_va,
+                PtePermissions::from(vma.permissions()),
+            ) {
+                Ok(_) => {
+                    // The page table now holds our reference.
+                    page.leak();
+
+                    Ok(())
+                }
+                // Another CPU mapped the same page; drop our reference.
+                Err(KernelError::MappingError(MapError::AlreadyMapped)) => Ok(()),
+                e => e,
+            }
+        })));
+    }
+
+    if let Some(huge_region) = vma.huge_page_region(faulting_addr)
+        && vm
+            .mm_mut()
+            .address_space_mut()
+            .can_map_huge_page(huge_region.start_address())
+        && let Ok(huge_page) = PAGE_ALLOC.get().unwrap().alloc_frames(HUGE_PAGE_ORDER)
+    {
+        let region = *huge_page.region();
+
+        unsafe {
+            region
+                .start_address()
+                .to_va::<PageOffsetTranslator>()
+                .as_ptr_mut()
+                .cast::<u8>()
+                .write_bytes(0, region.size());
+        }
+
+        vm.mm_mut().address_space_mut().map_huge_page(
+            region.start_address().to_pfn(),
+            huge_region.start_address(),
+            vma.permissions().into(),
+        )?;
+
+        // The block mapping now holds the reference; it's released by the
+        // address-space tear-down code.
+        huge_page.leak();
+
+        return Ok(FaultResolution::Resolved);
+    }
+
     if let Some(vma_read) = vma.resolve_fault(faulting_addr) {
         drop(vm);
 
+        // Pages lying wholly within the file are shared with the file's other
+        // private mappings through the page cache, until they're written to.
+        let from_cache = access_kind != AccessKind::Write
+            && vma_read.page_offset == 0
+            && vma_read.read_len == PAGE_SIZE
+            && vma_read.file_offset.is_multiple_of(PAGE_SIZE as u64)
+            && !vma_read.inode.can_share_pages();
+
         Ok(FaultResolution::Deferred(Box::new(async move {
-            let pg_buf = &mut new_page.as_slice_mut()
-                [vma_read.page_offset..vma_read.page_offset + vma_read.read_len];
+            // Pull in the part of the file around the fault too, so that the
+            // faults that follow are served from the block cache.
+            if let Some(window) = vma.readahead().fault_window(vma_read.file_offset) {
+                let _ = vma_read
+                    .inode
+                    .readahead(window.start, window.end - window.start)
+                    .await;
+            }
+
+            let mut perms = PtePermissions::from(vma.permissions());
+
+            let new_page = if from_cache {
+                // Writes to a shared page must go to a private copy.
+                if perms.is_write() {
+                    perms = perms.into_cow();
+                }
 
-            vma_read.inode.read_at(vma_read.file_offset, pg_buf).await?;
+                page_cache::get(&vma_read.inode, vma_read.file_offset / PAGE_SIZE as u64).await?
+            } else {
+                let mut new_page = ClaimedPage::alloc_zeroed()?;
+                let pg_buf = &mut new_page.as_slice_mut()
+                    [vma_read.page_offset..vma_read.page_offset + vma_read.read_len];
+
+                vma_read.inode.read_at(vma_read.file_offset, pg_buf).await?;
+
+                new_page
+            };
 
             // Since the above may have put the task to sleep, revalidate the
             // VMA access.
@@ -84,11 +261,11 @@ pub fn handle_demand_fault(
                 return Ok(());
             }
 
-            match vm.mm_mut().address_space_mut().map_page(
-                new_page.pa().to_pfn(),
-                page_va,
-                PtePermissions::from(vma.permissions()),
-            ) {
+            match vm
+                .mm_mut()
+                .address_space_mut()
+                .map_page(new_page.pa().to_pfn(), page_va, perms)
+            {
                 Ok(_) => {
                     // We mapped our page, leak it for reclamation by the
                     // address-space tear-down code.
@@ -109,6 +286,8 @@ pub fn handle_demand_fault(
             }
         })))
     } else {
+        let new_page = ClaimedPage::alloc_zeroed()?;
+
         // Anonymous mapping, no need to defer.
         match vm.mm_mut().address_space_mut().map_page(
             new_page.pa().to_pfn(),
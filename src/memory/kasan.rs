@@ -0,0 +1,513 @@
+//! Kernel address sanitizer (KASAN).
+//!
+//! When the kernel is built with the `kasan` feature and
+//! `-Zsanitizer=kernel-address` (see the README), the compiler precedes every
+//! load and store with a call to one of the `__asan_*` checks below. Each
+//! check consults the shadow memory, which holds one byte for every 8-byte
+//! granule of RAM:
+//!
+//! - `0` means the whole granule is accessible;
+//! - `1..=7` means only that many leading bytes are accessible;
+//! - a negative value means none of it is, and says why.
+//!
+//! The frame allocator poisons pages as they're freed, and [`KasanHeap`]
+//! surrounds every heap object with a redzone and poisons the object once
+//! it's freed. Only accesses through the linear map are checked; the kernel
+//! image and kernel stacks are not covered.
+//!
+//! Bad accesses are reported to the console with the faulting address and
+//! backtraces of the access, the allocation and, for a use-after-free, the
+//! free. Only the first report is printed, since later ones are usually
+//! fallout from it.
+
+use crate::{
+    arch::{Arch, ArchImpl},
+    memory::{PAGE_ALLOC, PageOffsetTranslator},
+    sched::sched_task::NR_CPUS,
+    sync::SpinLock,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use libkernel::{
+    CpuOps,
+    memory::{
+        PAGE_SIZE,
+        allocators::{phys::FrameHooks, smalloc::Smalloc},
+        region::PhysMemoryRegion,
+    },
+};
+use log::error;
+
+const GRANULE_SHIFT: usize = 3;
+const GRANULE_SIZE: usize = 1 << GRANULE_SHIFT;
+
+/// Shadow value of a page owned by the frame allocator.
+const PAGE_FREE: i8 = 0xffu8 as i8;
+/// Shadow value of the redzone following a heap object.
+const HEAP_REDZONE: i8 = 0xfcu8 as i8;
+/// Shadow value of a freed heap object.
+const HEAP_FREE: i8 = 0xfbu8 as i8;
+
+/// Bytes of redzone added after every heap object, on top of the slack needed
+/// to round it up to a granule.
+const HEAP_REDZONE_SIZE: usize = 2 * GRANULE_SIZE;
+
+/// Number of return addresses kept per backtrace.
+const TRACE_DEPTH: usize = 8;
+
+/// Number of heap objects whose backtraces can be remembered at once.
+const NR_RECORDS: usize = 4096;
+
+// The linear-map addresses of the start and end of RAM, and of its shadow.
+// These are set once, before any secondary CPU is booted, and are read
+// directly by the checks: unlike atomics, plain loads can't call back into
+// instrumented code.
+static mut RAM_START: usize = 0;
+static mut RAM_END: usize = 0;
+static mut SHADOW_START: usize = 0;
+
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Per-CPU depth of allocator calls, during which checks are suppressed. The
+/// allocators legitimately touch the memory they keep poisoned, e.g. the
+/// free-list link inside a free heap object.
+static SUPPRESS: [AtomicUsize; NR_CPUS] = [const { AtomicUsize::new(0) }; NR_CPUS];
+
+#[derive(Clone, Copy)]
+struct AllocRecord {
+    addr: usize,
+    size: usize,
+    alloc_trace: [usize; TRACE_DEPTH],
+    free_trace: Option<[usize; TRACE_DEPTH]>,
+}
+
+impl AllocRecord {
+    const EMPTY: Self = Self {
+        addr: 0,
+        size: 0,
+        alloc_trace: [0; TRACE_DEPTH],
+        free_trace: None,
+    };
+}
+
+/// Records of recent heap allocations, indexed by a hash of their address. A
+/// new object evicts whatever older object shares its slot.
+static RECORDS: SpinLock<[AllocRecord; NR_RECORDS]> =
+    SpinLock::new([AllocRecord::EMPTY; NR_RECORDS]);
+
+fn record_slot(addr: usize) -> usize {
+    (addr >> GRANULE_SHIFT) % NR_RECORDS
+}
+
+fn backtrace() -> [usize; TRACE_DEPTH] {
+    let mut trace = [0; TRACE_DEPTH];
+    ArchImpl::backtrace(&mut trace);
+    trace
+}
+
+/// Suppresses checks on this CPU while the allocators run.
+struct SuppressGuard {
+    cpu: usize,
+    flags: <ArchImpl as CpuOps>::InterruptFlags,
+}
+
+impl SuppressGuard {
+    fn new() -> Self {
+        // Stay on this CPU until the guard is dropped.
+        let flags = ArchImpl::disable_interrupts();
+        let cpu = ArchImpl::id();
+
+        SUPPRESS[cpu].fetch_add(1, Ordering::Relaxed);
+
+        Self { cpu, flags }
+    }
+}
+
+impl Drop for SuppressGuard {
+    fn drop(&mut self) {
+        SUPPRESS[self.cpu].fetch_sub(1, Ordering::Relaxed);
+        ArchImpl::restore_interrupt_state(self.flags);
+    }
+}
+
+/// Returns the shadow byte covering `addr`, if it's in RAM and the shadow has
+/// been set up.
+#[sanitize(address = "off")]
+fn shadow_of(addr: usize) -> Option<*mut i8> {
+    // SAFETY: These are only written by `init`, before other CPUs run.
+    let (start, end, shadow) = unsafe { (RAM_START, RAM_END, SHADOW_START) };
+
+    if addr < start || addr >= end {
+        return None;
+    }
+
+    Some((shadow + ((addr - start) >> GRANULE_SHIFT)) as *mut i8)
+}
+
+/// Sets the shadow of the granule-aligned range `[addr, addr + size)` to
+/// `value`.
+#[sanitize(address = "off")]
+fn poison(addr: usize, size: usize, value: i8) {
+    let mut granule = addr;
+
+    while granule < addr + size {
+        if let Some(shadow) = shadow_of(granule) {
+            // SAFETY: `shadow_of` only returns bytes within the shadow.
+            unsafe { *shadow = value };
+        }
+
+        granule += GRANULE_SIZE;
+    }
+}
+
+/// Marks `[addr, addr + size)` accessible, where `addr` is granule-aligned.
+#[sanitize(address = "off")]
+fn unpoison(addr: usize, size: usize) {
+    poison(addr, size & !(GRANULE_SIZE - 1), 0);
+
+    let tail = size & (GRANULE_SIZE - 1);
+
+    if tail != 0
+        && let Some(shadow) = shadow_of(addr + size - tail)
+    {
+        // SAFETY: `shadow_of` only returns bytes within the shadow.
+        unsafe { *shadow = tail as i8 };
+    }
+}
+
+/// Returns the first inaccessible byte of `[addr, addr + size)`, along with
+/// the shadow value that describes it.
+#[sanitize(address = "off")]
+fn find_bad_byte(addr: usize, size: usize) -> Option<(usize, i8)> {
+    let last = addr + size - 1;
+    let mut granule = addr & !(GRANULE_SIZE - 1);
+
+    while granule <= last {
+        // SAFETY: `shadow_of` only returns bytes within the shadow.
+        let value = shadow_of(granule).map_or(0, |shadow| unsafe { *shadow });
+
+        if value != 0 {
+            let first = addr.max(granule);
+
+            if value < 0 {
+                return Some((first, value));
+            }
+
+            let limit = granule + value as usize;
+
+            if last >= limit {
+                // A partial granule is followed by its object's redzone.
+                return Some((first.max(limit), HEAP_REDZONE));
+            }
+        }
+
+        granule += GRANULE_SIZE;
+    }
+
+    None
+}
+
+#[sanitize(address = "off")]
+fn check_access(addr: usize, size: usize, write: bool) {
+    if size == 0 || shadow_of(addr).is_none() {
+        return;
+    }
+
+    // Neither call can recurse: both only touch the kernel image, which isn't
+    // checked.
+    if REPORTED.load(Ordering::Relaxed) || SUPPRESS[ArchImpl::id()].load(Ordering::Relaxed) != 0 {
+        return;
+    }
+
+    if let Some((bad_addr, value)) = find_bad_byte(addr, size) {
+        report_access(addr, size, write, bad_addr, value);
+    }
+}
+
+fn describe(value: i8) -> &'static str {
+    match value {
+        PAGE_FREE => "use-after-free of a page",
+        HEAP_FREE => "heap-use-after-free",
+        HEAP_REDZONE => "heap-out-of-bounds",
+        _ => "wild access",
+    }
+}
+
+fn print_trace(what: &str, trace: &[usize]) {
+    error!("{what}:");
+
+    for addr in trace.iter().take_while(|&&addr| addr != 0) {
+        error!("  {addr:#018x}");
+    }
+}
+
+/// Prints the heap object that `addr` falls within or just after, if it's
+/// still remembered.
+fn print_object(addr: usize) {
+    let records = RECORDS.lock_save_irq();
+
+    let Some(record) = records
+        .iter()
+        .filter(|r| r.addr != 0 && r.addr <= addr)
+        .filter(|r| addr < r.addr + padded_size(r.size))
+        .max_by_key(|r| r.addr)
+    else {
+        error!("No allocation record for {addr:#x}");
+        return;
+    };
+
+    error!(
+        "{addr:#x} is {} bytes into the {}-byte object at {:#x}",
+        addr - record.addr,
+        record.size,
+        record.addr
+    );
+
+    print_trace("Allocated at", &record.alloc_trace);
+
+    if let Some(free_trace) = record.free_trace {
+        print_trace("Freed at", &free_trace);
+    }
+}
+
+/// Returns `true` if this is the first report, after which checking stops.
+fn begin_report() -> bool {
+    !REPORTED.swap(true, Ordering::Relaxed)
+}
+
+fn report_access(addr: usize, size: usize, write: bool, bad_addr: usize, value: i8) {
+    if !begin_report() {
+        return;
+    }
+
+    error!("==================================================================");
+    error!(
+        "KASAN: {} at {bad_addr:#x}: {} of size {size} at {addr:#x}",
+        describe(value),
+        if write { "write" } else { "read" },
+    );
+
+    print_trace("Accessed at", &backtrace());
+    print_object(bad_addr);
+    error!("==================================================================");
+}
+
+fn report_free(addr: usize, value: i8) {
+    if !begin_report() {
+        return;
+    }
+
+    error!("==================================================================");
+    error!(
+        "KASAN: {} of {addr:#x}",
+        if value == HEAP_FREE {
+            "double-free"
+        } else {
+            "invalid-free"
+        }
+    );
+
+    print_trace("Freed at", &backtrace());
+    print_object(addr);
+    error!("==================================================================");
+}
+
+fn padded_size(size: usize) -> usize {
+    size.next_multiple_of(GRANULE_SIZE) + HEAP_REDZONE_SIZE
+}
+
+/// Returns `layout` grown to leave room for the redzone.
+fn padded_layout(layout: Layout) -> Layout {
+    Layout::from_size_align(padded_size(layout.size()), layout.align())
+        .expect("KASAN: layout too large for redzone")
+}
+
+/// A [`GlobalAlloc`] that maintains the shadow of the objects handed out by
+/// the heap `H`.
+pub struct KasanHeap<H: GlobalAlloc> {
+    heap: H,
+}
+
+impl<H: GlobalAlloc> KasanHeap<H> {
+    pub const fn new(heap: H) -> Self {
+        Self { heap }
+    }
+}
+
+unsafe impl<H: GlobalAlloc> GlobalAlloc for KasanHeap<H> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = {
+            let _guard = SuppressGuard::new();
+            unsafe { self.heap.alloc(padded_layout(layout)) }
+        };
+
+        if ptr.is_null() {
+            return ptr;
+        }
+
+        let addr = ptr as usize;
+
+        unpoison(addr, layout.size());
+        poison(
+            (addr + layout.size()).next_multiple_of(GRANULE_SIZE),
+            HEAP_REDZONE_SIZE,
+            HEAP_REDZONE,
+        );
+
+        RECORDS.lock_save_irq()[record_slot(addr)] = AllocRecord {
+            addr,
+            size: layout.size(),
+            alloc_trace: backtrace(),
+            free_trace: None,
+        };
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let addr = ptr as usize;
+
+        // SAFETY: `shadow_of` only returns bytes within the shadow.
+        if let Some(value) = shadow_of(addr).map(|shadow| unsafe { *shadow })
+            && value < 0
+        {
+            // Leak the object rather than corrupt the heap.
+            report_free(addr, value);
+            return;
+        }
+
+        poison(addr, padded_size(layout.size()), HEAP_FREE);
+
+        if let Some(record) = RECORDS
+            .lock_save_irq()
+            .get_mut(record_slot(addr))
+            .filter(|record| record.addr == addr)
+        {
+            record.free_trace = Some(backtrace());
+        }
+
+        let _guard = SuppressGuard::new();
+        unsafe { self.heap.dealloc(ptr, padded_layout(layout)) };
+    }
+}
+
+struct ShadowHooks;
+
+impl FrameHooks for ShadowHooks {
+    fn on_alloc(&self, region: PhysMemoryRegion) {
+        let va = region.start_address().to_va::<PageOffsetTranslator>();
+        unpoison(va.value(), region.size());
+    }
+
+    fn on_free(&self, region: PhysMemoryRegion) {
+        let va = region.start_address().to_va::<PageOffsetTranslator>();
+        poison(va.value(), region.size(), PAGE_FREE);
+    }
+}
+
+/// Shadow memory reserved by [`reserve_shadow`], to be handed to [`init`].
+pub struct ReservedShadow {
+    ram: PhysMemoryRegion,
+    shadow: PhysMemoryRegion,
+}
+
+/// Reserves the shadow for all of RAM from `smalloc`, before the frame
+/// allocator takes over the remaining memory.
+pub fn reserve_shadow(smalloc: &mut Smalloc<PageOffsetTranslator>) -> ReservedShadow {
+    let start = smalloc
+        .base_ram_base_address()
+        .expect("No memory regions in smalloc");
+
+    let end = smalloc
+        .iter_memory()
+        .last()
+        .expect("No memory regions in smalloc")
+        .end_address();
+
+    let ram = PhysMemoryRegion::from_start_end_address(start, end);
+    let size = (ram.size() >> GRANULE_SHIFT).next_multiple_of(PAGE_SIZE);
+
+    let shadow = smalloc
+        .alloc(size, PAGE_SIZE)
+        .expect("KASAN: cannot allocate shadow memory");
+
+    // SAFETY: We just allocated this region and it lies within the linear map.
+    unsafe {
+        shadow
+            .to_va::<PageOffsetTranslator>()
+            .cast::<u8>()
+            .as_ptr_mut()
+            .write_bytes(0, size);
+    }
+
+    ReservedShadow {
+        ram,
+        shadow: PhysMemoryRegion::new(shadow, size),
+    }
+}
+
+/// Starts checking accesses, using the shadow returned by [`reserve_shadow`].
+///
+/// Must be called on the boot CPU once the frame allocator is set up, but
+/// before the heap is first used.
+pub fn init(ReservedShadow { ram, shadow }: ReservedShadow) {
+    // SAFETY: Only the boot CPU is running and nothing has been checked yet.
+    unsafe {
+        SHADOW_START = shadow
+            .start_address()
+            .to_va::<PageOffsetTranslator>()
+            .value();
+        RAM_START = ram.start_address().to_va::<PageOffsetTranslator>().value();
+        RAM_END = ram.end_address().to_va::<PageOffsetTranslator>().value();
+    }
+
+    PAGE_ALLOC
+        .get()
+        .expect("KASAN: frame allocator not initialised")
+        .set_hooks(&ShadowHooks);
+}
+
+macro_rules! define_checks {
+    ($($size:literal => $load:ident, $store:ident;)*) => {
+        $(
+            #[unsafe(no_mangle)]
+            #[sanitize(address = "off")]
+            extern "C" fn $load(addr: usize) {
+                check_access(addr, $size, false);
+            }
+
+            #[unsafe(no_mangle)]
+            #[sanitize(address = "off")]
+            extern "C" fn $store(addr: usize) {
+                check_access(addr, $size, true);
+            }
+        )*
+    };
+}
+
+define_checks! {
+    1 => __asan_load1_noabort, __asan_store1_noabort;
+    2 => __asan_load2_noabort, __asan_store2_noabort;
+    4 => __asan_load4_noabort, __asan_store4_noabort;
+    8 => __asan_load8_noabort, __asan_store8_noabort;
+    16 => __asan_load16_noabort, __asan_store16_noabort;
+}
+
+#[unsafe(no_mangle)]
+#[sanitize(address = "off")]
+extern "C" fn __asan_loadN_noabort(addr: usize, size: usize) {
+    check_access(addr, size, false);
+}
+
+#[unsafe(no_mangle)]
+#[sanitize(address = "off")]
+extern "C" fn __asan_storeN_noabort(addr: usize, size: usize) {
+    check_access(addr, size, true);
+}
+
+/// Called before a `noreturn` function. Nothing to do, as stacks aren't
+/// poisoned.
+#[unsafe(no_mangle)]
+extern "C" fn __asan_handle_no_return() {}
@@ -0,0 +1,502 @@
+//! Kernel memory leak detector (kmemleak).
+//!
+//! With the `kmemleak` feature, [`LeakTrackingHeap`] keeps every live heap
+//! object on a list, along with the backtrace of its allocation. Every
+//! [`SCAN_INTERVAL`] an idle CPU sweeps kernel memory for pointers to those
+//! objects, the way a conservative garbage collector marks:
+//!
+//! - the kernel image's writable data and the kernel stacks are the roots;
+//! - any word in them that points into an object marks that object, and the
+//!   contents of marked objects are scanned in turn.
+//!
+//! An object that nothing points to can never be freed, so it has leaked. It
+//! is reported to the console with its allocation backtrace, which can be
+//! resolved with `addr2line -e <kernel image>`, and listed in `/proc/kmemleak`
+//! for as long as it stays unreferenced.
+//!
+//! The scan can't see pointers held only in another CPU's registers, or
+//! objects found through anything but a pointer into them (e.g. a physical
+//! address handed to a device). To keep those from being reported, only
+//! objects older than [`MIN_AGE`] that two scans in a row failed to find are.
+//! Stale words left on a stack can hide a leak instead, so a leak may take a
+//! few scans to show up, or never show up at all.
+//!
+//! Every allocation and free takes a global lock, which a scan holds with
+//! interrupts disabled throughout: this is a debugging aid for debug builds,
+//! not something to leave on. The kernel has to be built with frame pointers
+//! for the backtraces to be found.
+
+use crate::{
+    arch::{Arch, ArchImpl},
+    drivers::timer::uptime,
+    memory::{PAGE_ALLOC, PageOffsetTranslator},
+    sync::SpinLock,
+};
+use alloc::vec::Vec;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use libkernel::memory::{PAGE_SIZE, region::VirtMemoryRegion};
+use log::{error, warn};
+
+/// Number of return addresses kept per backtrace.
+pub const TRACE_DEPTH: usize = 8;
+
+/// Time between scans.
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Objects younger than this are never reported, as they may still be on
+/// their way to wherever they'll be kept.
+pub const MIN_AGE: Duration = Duration::from_secs(5);
+
+/// Number of consecutive scans that must find an object unreferenced before
+/// it's reported.
+const MIN_UNREFERENCED_SCANS: u32 = 2;
+
+/// Largest number of new leaks printed after a scan. Any more are only
+/// counted, and can be read from `/proc/kmemleak`.
+const MAX_PRINTED: usize = 16;
+
+/// Largest number of leaks listed by [`leaks`].
+const MAX_LISTED: usize = 1024;
+
+/// Largest order of the frame allocation holding a scan's object index.
+const MAX_INDEX_ORDER: usize = 10;
+
+/// Bookkeeping that precedes every heap object.
+struct Header {
+    next: *mut Header,
+    prev: *mut Header,
+    size: usize,
+    allocated_at: Duration,
+    trace: [usize; TRACE_DEPTH],
+    /// Number of consecutive scans that found nothing pointing at the object.
+    unreferenced: u32,
+    reported: bool,
+}
+
+impl Header {
+    fn payload(&self) -> usize {
+        ptr::from_ref(self).addr() + size_of::<Header>()
+    }
+
+    fn is_leak(&self) -> bool {
+        self.reported && self.unreferenced != 0
+    }
+
+    fn leak(&self, now: Duration) -> Leak {
+        Leak {
+            addr: self.payload(),
+            size: self.size,
+            age: now.saturating_sub(self.allocated_at),
+            trace: self.trace,
+        }
+    }
+}
+
+/// Every live heap object, newest first.
+struct Objects {
+    head: *mut Header,
+    count: usize,
+}
+
+// SAFETY: The headers are only ever accessed with the list locked.
+unsafe impl Send for Objects {}
+
+impl Objects {
+    /// Iterates over the headers on the list.
+    fn iter(&self) -> impl Iterator<Item = *mut Header> {
+        // SAFETY: Every header on the list belongs to a live object, which
+        // can't be freed while the list is locked.
+        core::iter::successors((!self.head.is_null()).then_some(self.head), |&header| {
+            let next = unsafe { (*header).next };
+
+            (!next.is_null()).then_some(next)
+        })
+    }
+}
+
+static OBJECTS: SpinLock<Objects> = SpinLock::new(Objects {
+    head: ptr::null_mut(),
+    count: 0,
+});
+
+/// Uptime of the last scan, in milliseconds.
+static LAST_SCAN: AtomicU64 = AtomicU64::new(0);
+
+/// Set while a scan is running.
+static SCANNING: AtomicBool = AtomicBool::new(false);
+
+/// A heap object that nothing points to.
+#[derive(Clone, Copy)]
+pub struct Leak {
+    /// Address of the object.
+    pub addr: usize,
+    /// Size the object was allocated with.
+    pub size: usize,
+    /// Time since the object was allocated.
+    pub age: Duration,
+    /// Return addresses of the allocation, innermost first.
+    pub trace: [usize; TRACE_DEPTH],
+}
+
+/// An object in a scan's index.
+#[derive(Clone, Copy)]
+struct IndexEntry {
+    start: usize,
+    end: usize,
+    header: *mut Header,
+    marked: bool,
+}
+
+/// The state of one scan: every object on the list, sorted by address, and
+/// the marked objects whose contents are still to be scanned.
+struct Scan<'a> {
+    index: &'a mut [IndexEntry],
+    pending: &'a mut [usize],
+    nr_pending: usize,
+}
+
+impl Scan<'_> {
+    /// Marks the object that `addr` points into, if any.
+    fn mark(&mut self, addr: usize) {
+        let pos = self.index.partition_point(|entry| entry.start <= addr);
+
+        let Some(entry) = pos.checked_sub(1).map(|pos| &mut self.index[pos]) else {
+            return;
+        };
+
+        if addr >= entry.end || entry.marked {
+            return;
+        }
+
+        entry.marked = true;
+        self.pending[self.nr_pending] = pos - 1;
+        self.nr_pending += 1;
+    }
+
+    /// Marks every object pointed to by an aligned word of `[start, end)`.
+    fn scan_range(&mut self, start: usize, end: usize) {
+        let mut word = start.next_multiple_of(size_of::<usize>());
+
+        while word + size_of::<usize>() <= end {
+            // SAFETY: The range is mapped, and may be written behind our back
+            // by another CPU, hence the volatile read.
+            let value = unsafe { ptr::read_volatile(word as *const usize) };
+
+            self.mark(value);
+            word += size_of::<usize>();
+        }
+    }
+
+    /// Scans the contents of marked objects until none are left.
+    fn scan_marked(&mut self) {
+        while self.nr_pending > 0 {
+            self.nr_pending -= 1;
+
+            let entry = self.index[self.pending[self.nr_pending]];
+
+            self.scan_range(entry.start, entry.end);
+        }
+    }
+}
+
+/// Returns the frame allocation order needed for the index of `count`
+/// objects.
+fn index_order(count: usize) -> usize {
+    let bytes = count * (size_of::<IndexEntry>() + size_of::<usize>());
+
+    bytes.div_ceil(PAGE_SIZE).max(1).next_power_of_two().ilog2() as usize
+}
+
+fn print_trace(trace: &[usize]) {
+    for addr in trace.iter().take_while(|&&addr| addr != 0) {
+        error!("  {addr:#018x}");
+    }
+}
+
+/// Scans for leaks, printing any new ones to the console.
+pub fn scan() {
+    if SCANNING.swap(true, Ordering::Acquire) {
+        return;
+    }
+
+    let mut printed = [None; MAX_PRINTED];
+    let new_leaks = scan_locked(&mut printed);
+
+    SCANNING.store(false, Ordering::Release);
+
+    if new_leaks == 0 {
+        return;
+    }
+
+    warn!("kmemleak: {new_leaks} new suspected memory leaks (see /proc/kmemleak)");
+
+    for leak in printed.iter().flatten() {
+        error!(
+            "kmemleak: unreferenced object {:#x} (size {}, age {}ms), allocated at:",
+            leak.addr,
+            leak.size,
+            leak.age.as_millis()
+        );
+        print_trace(&leak.trace);
+    }
+
+    // Left on the stack, the addresses would be found by the next scan and
+    // make the leaks look referenced again.
+    //
+    // SAFETY: `printed` is a live local.
+    unsafe { ptr::write_volatile(&mut printed, [None; MAX_PRINTED]) };
+}
+
+/// Runs a scan with the object list locked, storing the first new leaks in
+/// `printed`, and returns the number of new leaks found.
+fn scan_locked(printed: &mut [Option<Leak>]) -> usize {
+    let Some(page_alloc) = PAGE_ALLOC.get() else {
+        return 0;
+    };
+
+    // The index is allocated outside the heap, so that it isn't itself
+    // scanned, with room for objects allocated before the list is locked.
+    let count = OBJECTS.lock_save_irq().count;
+    let order = index_order(count + count / 8 + 64);
+
+    if order > MAX_INDEX_ORDER {
+        warn!("kmemleak: too many objects ({count}) to scan");
+        return 0;
+    }
+
+    let Ok(pages) = page_alloc.alloc_frames(order as _) else {
+        warn!("kmemleak: can't allocate the scan index");
+        return 0;
+    };
+
+    let capacity = pages.region().size() / (size_of::<IndexEntry>() + size_of::<usize>());
+    let base = pages
+        .region()
+        .start_address()
+        .to_va::<PageOffsetTranslator>()
+        .value();
+
+    // SAFETY: The pages are ours until `pages` is dropped, and are big enough
+    // for `capacity` entries and pending indices, in that order.
+    let (index, pending) = unsafe {
+        (
+            core::slice::from_raw_parts_mut(base as *mut IndexEntry, capacity),
+            core::slice::from_raw_parts_mut(
+                (base + capacity * size_of::<IndexEntry>()) as *mut usize,
+                capacity,
+            ),
+        )
+    };
+
+    let objects = OBJECTS.lock_save_irq();
+
+    if objects.count > capacity {
+        // The heap grew too much in the meantime; try again next time.
+        return 0;
+    }
+
+    for (entry, header) in index.iter_mut().zip(objects.iter()) {
+        // SAFETY: See `Objects::iter`.
+        let header_ref = unsafe { &*header };
+
+        *entry = IndexEntry {
+            start: header_ref.payload(),
+            end: header_ref.payload() + header_ref.size,
+            header,
+            marked: false,
+        };
+    }
+
+    let index = &mut index[..objects.count];
+
+    index.sort_unstable_by_key(|entry| entry.start);
+
+    let mut scan = Scan {
+        index,
+        pending,
+        nr_pending: 0,
+    };
+
+    ArchImpl::for_each_root_region(|region: VirtMemoryRegion| {
+        scan.scan_range(region.start_address().value(), region.end_address().value());
+        scan.scan_marked();
+    });
+
+    let now = uptime();
+    let mut new_leaks = 0;
+
+    for entry in scan.index.iter() {
+        // SAFETY: See `Objects::iter`.
+        let header = unsafe { &mut *entry.header };
+
+        if entry.marked {
+            header.unreferenced = 0;
+            continue;
+        }
+
+        if now.saturating_sub(header.allocated_at) < MIN_AGE {
+            continue;
+        }
+
+        header.unreferenced += 1;
+
+        if header.unreferenced >= MIN_UNREFERENCED_SCANS && !header.reported {
+            header.reported = true;
+
+            if let Some(slot) = printed.get_mut(new_leaks) {
+                *slot = Some(header.leak(now));
+            }
+
+            new_leaks += 1;
+        }
+    }
+
+    drop(objects);
+
+    new_leaks
+}
+
+/// Scans for leaks if [`SCAN_INTERVAL`] has passed since the last scan. Called
+/// by CPUs with nothing else to do.
+pub fn scan_if_due() {
+    let now = uptime().as_millis() as u64;
+    let last = LAST_SCAN.load(Ordering::Relaxed);
+
+    if now < last + SCAN_INTERVAL.as_millis() as u64 {
+        return;
+    }
+
+    // Only one of the CPUs that notice at the same time gets to scan.
+    if LAST_SCAN
+        .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+    {
+        scan();
+    }
+}
+
+/// Returns the objects reported as leaks that are still unreferenced, oldest
+/// first.
+pub fn leaks() -> Vec<Leak> {
+    // Reserve up front: allocating with the list locked would deadlock.
+    let mut leaks = Vec::with_capacity(MAX_LISTED);
+    let now = uptime();
+
+    {
+        let objects = OBJECTS.lock_save_irq();
+
+        // SAFETY: See `Objects::iter`.
+        leaks.extend(
+            objects
+                .iter()
+                .map(|header| unsafe { &*header })
+                .filter(|header| header.is_leak())
+                .map(|header| header.leak(now))
+                .take(MAX_LISTED),
+        );
+    }
+
+    leaks.reverse();
+    leaks
+}
+
+/// Returns the layout actually allocated for `layout`, and the offset of the
+/// object within it. The header sits just before the object.
+fn padded_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let align = layout.align().max(align_of::<Header>());
+    let offset = size_of::<Header>().next_multiple_of(align);
+    let size = layout.size().checked_add(offset)?;
+
+    Some((Layout::from_size_align(size, align).ok()?, offset))
+}
+
+/// A [`GlobalAlloc`] that tracks the objects handed out by the heap `H`, so
+/// that they can be scanned for leaks.
+pub struct LeakTrackingHeap<H: GlobalAlloc> {
+    heap: H,
+}
+
+impl<H: GlobalAlloc> LeakTrackingHeap<H> {
+    pub const fn new(heap: H) -> Self {
+        Self { heap }
+    }
+}
+
+unsafe impl<H: GlobalAlloc> GlobalAlloc for LeakTrackingHeap<H> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some((padded, offset)) = padded_layout(layout) else {
+            return ptr::null_mut();
+        };
+
+        let base = unsafe { self.heap.alloc(padded) };
+
+        if base.is_null() {
+            return base;
+        }
+
+        let allocated_at = uptime();
+        let mut trace = [0; TRACE_DEPTH];
+
+        ArchImpl::backtrace(&mut trace);
+
+        // SAFETY: `offset` is within the allocation and leaves room for the
+        // header just before the object, both suitably aligned.
+        let ptr = unsafe { base.add(offset) };
+        let header = unsafe { ptr.cast::<Header>().sub(1) };
+
+        let mut objects = OBJECTS.lock_save_irq();
+
+        unsafe {
+            header.write(Header {
+                next: objects.head,
+                prev: ptr::null_mut(),
+                size: layout.size(),
+                allocated_at,
+                trace,
+                unreferenced: 0,
+                reported: false,
+            });
+
+            if !objects.head.is_null() {
+                (*objects.head).prev = header;
+            }
+        }
+
+        objects.head = header;
+        objects.count += 1;
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // The layout was padded successfully when the object was allocated.
+        let (padded, offset) = padded_layout(layout).unwrap();
+
+        // SAFETY: `ptr` was returned by `alloc` above, which wrote the header
+        // and put it on the list.
+        unsafe {
+            let header = ptr.cast::<Header>().sub(1);
+            let mut objects = OBJECTS.lock_save_irq();
+            let (next, prev) = ((*header).next, (*header).prev);
+
+            if prev.is_null() {
+                objects.head = next;
+            } else {
+                (*prev).next = next;
+            }
+
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+
+            objects.count -= 1;
+        }
+
+        unsafe { self.heap.dealloc(ptr.sub(offset), padded) };
+    }
+}
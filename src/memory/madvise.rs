@@ -0,0 +1,131 @@
+use super::{
+    fault::{FaultResolution, handle_demand_fault},
+    mmap::free_unmapped_pages,
+};
+use crate::sched::syscall_ctx::ProcessCtx;
+use alloc::{boxed::Box, vec::Vec};
+use libkernel::{
+    error::{KernelError, Result},
+    fs::readahead::ReadaheadAdvice,
+    memory::{
+        address::VA,
+        proc_vm::{address_space::UserAddressSpace, vmarea::AccessKind},
+        region::VirtMemoryRegion,
+    },
+};
+
+const MADV_NORMAL: u32 = 0;
+const MADV_RANDOM: u32 = 1;
+const MADV_SEQUENTIAL: u32 = 2;
+const MADV_WILLNEED: u32 = 3;
+const MADV_DONTNEED: u32 = 4;
+const MADV_FREE: u32 = 8;
+const MADV_DONTFORK: u32 = 10;
+const MADV_DOFORK: u32 = 11;
+const MADV_MERGEABLE: u32 = 12;
+const MADV_UNMERGEABLE: u32 = 13;
+const MADV_HUGEPAGE: u32 = 14;
+const MADV_NOHUGEPAGE: u32 = 15;
+const MADV_DONTDUMP: u32 = 16;
+const MADV_DODUMP: u32 = 17;
+const MADV_WIPEONFORK: u32 = 18;
+const MADV_KEEPONFORK: u32 = 19;
+const MADV_COLD: u32 = 20;
+const MADV_PAGEOUT: u32 = 21;
+
+pub async fn sys_madvise(ctx: &ProcessCtx, addr: VA, len: usize, advice: u32) -> Result<usize> {
+    if !addr.is_page_aligned() {
+        return Err(KernelError::InvalidValue);
+    }
+
+    if len == 0 {
+        return Ok(0);
+    }
+
+    let region = VirtMemoryRegion::new(addr, len).align_to_page_boundary();
+    let proc_vm = ctx.shared().vm.shared_vm();
+
+    match advice {
+        MADV_DONTNEED | MADV_FREE => {
+            let pages = {
+                let mut vm = proc_vm.lock_save_irq();
+                let mm = vm.mm_mut();
+
+                // Lazy freeing only makes sense for anonymous memory; since we
+                // don't reclaim lazily, we simply drop the pages up front.
+                if advice == MADV_FREE {
+                    for part in mm.covering_regions(region)? {
+                        if mm
+                            .find_vma(part.start_address())
+                            .is_none_or(|vma| vma.is_file_backed())
+                        {
+                            return Err(KernelError::InvalidValue);
+                        }
+                    }
+                }
+
+                mm.discard_region(region)?
+            };
+
+            free_unmapped_pages(pages)?;
+        }
+        MADV_WILLNEED => {
+            // Anonymous memory has nothing to read ahead, so only prefault
+            // file-backed parts of the region.
+            let file_regions: Vec<_> = {
+                let mut vm = proc_vm.lock_save_irq();
+                let mm = vm.mm_mut();
+
+                mm.covering_regions(region)?
+                    .into_iter()
+                    .filter(|part| {
+                        mm.find_vma(part.start_address())
+                            .is_some_and(|vma| vma.is_file_backed())
+                    })
+                    .collect()
+            };
+
+            for va in file_regions.into_iter().flat_map(|r| r.iter_pages()) {
+                let resident = proc_vm
+                    .lock_save_irq()
+                    .mm_mut()
+                    .address_space_mut()
+                    .translate(va)
+                    .is_some();
+
+                if resident {
+                    continue;
+                }
+
+                match handle_demand_fault(proc_vm.clone(), va, AccessKind::Read, false)? {
+                    FaultResolution::Resolved => {}
+                    // Not readable (e.g. PROT_NONE); there's nothing to
+                    // prefault.
+                    FaultResolution::Denied => {}
+                    FaultResolution::Deferred(fut) => Box::into_pin(fut).await?,
+                }
+            }
+        }
+        MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL => {
+            let advice = match advice {
+                MADV_RANDOM => ReadaheadAdvice::Random,
+                MADV_SEQUENTIAL => ReadaheadAdvice::Sequential,
+                _ => ReadaheadAdvice::Normal,
+            };
+
+            proc_vm
+                .lock_save_irq()
+                .mm_mut()
+                .set_readahead(region, advice)?;
+        }
+        MADV_DONTFORK | MADV_DOFORK | MADV_MERGEABLE | MADV_UNMERGEABLE | MADV_HUGEPAGE
+        | MADV_NOHUGEPAGE | MADV_DONTDUMP | MADV_DODUMP | MADV_WIPEONFORK | MADV_KEEPONFORK
+        | MADV_COLD | MADV_PAGEOUT => {
+            // Purely advisory; just check the range is mapped.
+            proc_vm.lock_save_irq().mm_mut().covering_regions(region)?;
+        }
+        _ => return Err(KernelError::InvalidValue),
+    }
+
+    Ok(0)
+}
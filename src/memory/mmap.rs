@@ -1,11 +1,23 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::{process::fd_table::Fd, sched::syscall_ctx::ProcessCtx};
+use super::overcommit;
+use crate::{
+    fs::{
+        MntFlags, VFS,
+        memfd::{SealFlags, as_memfd, create_shared_anon_inode},
+    },
+    process::{TASK_LIST, fd_table::Fd},
+    sched::syscall_ctx::ProcessCtx,
+};
 use alloc::string::{String, ToString};
+use alloc::{sync::Arc, vec::Vec};
 use libkernel::{
-    error::{KernelError, Result},
+    error::{FsError, KernelError, Result},
+    fs::{InodeId, OpenFlags},
     memory::{
+        HUGE_PAGE_SHIFT, PAGE_MASK, PAGE_SIZE,
         address::VA,
+        page::PageFrame,
         proc_vm::{
             memory_map::AddressRequest,
             vmarea::{VMAPermissions, VMAreaKind},
@@ -24,6 +36,13 @@ const MAP_FIXED: u64 = 0x0010;
 const MAP_FIXED_NOREPLACE: u64 = 0x100000;
 const MAP_ANON: u64 = 0x0020;
 const MAP_ANONYMOUS: u64 = 0x0020;
+const MAP_NORESERVE: u64 = 0x4000;
+const MAP_HUGETLB: u64 = 0x40000;
+
+/// The huge page size requested with `MAP_HUGETLB`, as a log2 value in these
+/// bits. Zero selects the default size.
+const MAP_HUGE_SHIFT: u64 = 26;
+const MAP_HUGE_MASK: u64 = 0x3f;
 
 /// Determines the minimal address that user-space is allowed to specify for
 /// MAP_FIXED{,_NOREPLACE}.
@@ -63,10 +82,7 @@ pub async fn sys_mmap(
         return Err(KernelError::InvalidValue);
     }
 
-    // TODO: Shared Mappings.
-    if (flags & MAP_SHARED) != 0 {
-        return Err(KernelError::NotSupported);
-    }
+    let shared = (flags & MAP_SHARED) != 0;
 
     // `MAP_FIXED` and `MAP_FIXED_NOREPLACE` are mutually exclusive.
     if (flags & MAP_FIXED) != 0 && (flags & MAP_FIXED_NOREPLACE) != 0 {
@@ -87,8 +103,37 @@ pub async fn sys_mmap(
 
     let requested_len = len as usize;
 
+    // Huge pages are only available for private anonymous memory, and only in
+    // the one size.
+    let huge = (flags & MAP_HUGETLB) != 0;
+
+    if huge {
+        let huge_shift = (flags >> MAP_HUGE_SHIFT) & MAP_HUGE_MASK;
+
+        if shared
+            || (flags & (MAP_ANON | MAP_ANONYMOUS)) == 0
+            || (huge_shift != 0 && huge_shift != HUGE_PAGE_SHIFT as u64)
+        {
+            return Err(KernelError::InvalidValue);
+        }
+    }
+
+    // Why `mprotect` may not later make the mapping writable, if it may not.
+    let mut write_denied = None;
+
     let (kind, name) = if (flags & (MAP_ANON | MAP_ANONYMOUS)) != 0 {
-        (VMAreaKind::Anon, String::new())
+        if shared {
+            // Shared anonymous memory is backed by an unlinked tmpfs file, so
+            // that it stays shared with children across fork().
+            let inode = create_shared_anon_inode(len).await?;
+
+            (
+                VMAreaKind::new_shared_file(inode, 0, len),
+                String::from("/dev/zero (deleted)"),
+            )
+        } else {
+            (VMAreaKind::Anon, String::new())
+        }
     } else {
         // File-backed mapping: require a valid fd and use the provided offset.
         let fd = ctx
@@ -99,12 +144,76 @@ pub async fn sys_mmap(
             .ok_or(KernelError::BadFd)?;
 
         let inode = fd.inode().ok_or(KernelError::BadFd)?;
+
+        if permissions.execute && VFS.mount_flags(inode.id()).contains(MntFlags::MNT_NOEXEC) {
+            return Err(KernelError::NotPermitted);
+        }
+
         let name = fd
             .path()
             .map(|x| x.as_str().to_string())
             .unwrap_or_default();
 
-        (VMAreaKind::new_file(inode, offset, len), name)
+        let kind = if shared {
+            if offset & PAGE_MASK as u64 != 0 {
+                return Err(KernelError::InvalidValue);
+            }
+
+            let accmode = fd.flags().await & OpenFlags::O_ACCMODE;
+
+            // Pages are always readable, so a shared mapping needs read
+            // access whatever the protection asked for.
+            if accmode == OpenFlags::O_WRONLY {
+                return Err(FsError::PermissionDenied.into());
+            }
+
+            let rdwr = accmode == OpenFlags::O_RDWR;
+            let sealed = match as_memfd(&inode) {
+                Some(memfd) => memfd
+                    .seals()
+                    .await
+                    .intersects(SealFlags::F_SEAL_WRITE | SealFlags::F_SEAL_FUTURE_WRITE),
+                None => false,
+            };
+
+            if permissions.write {
+                if !rdwr {
+                    return Err(FsError::PermissionDenied.into());
+                }
+
+                if sealed {
+                    return Err(KernelError::NotPermitted);
+                }
+            }
+
+            if !rdwr {
+                write_denied = Some(FsError::PermissionDenied.into());
+            } else if sealed {
+                write_denied = Some(KernelError::NotPermitted);
+            }
+
+            if inode.can_share_pages() {
+                if write_denied.is_none() {
+                    VMAreaKind::new_writable_shared_file(inode, offset, len)?
+                } else {
+                    VMAreaKind::new_shared_file(inode, offset, len)
+                }
+            } else if !permissions.write {
+                // The file's pages can't be mapped directly, but a read-only
+                // mapping can be served from a private copy instead. Writes
+                // to that copy wouldn't reach the file, so it stays
+                // read-only.
+                write_denied = write_denied.or(Some(FsError::NoDevice.into()));
+
+                VMAreaKind::new_file(inode, offset, len)
+            } else {
+                return Err(FsError::NoDevice.into());
+            }
+        } else {
+            VMAreaKind::new_file(inode, offset, len)
+        };
+
+        (kind, name)
     };
 
     let address_request = if addr.is_null() {
@@ -126,15 +235,51 @@ pub async fn sys_mmap(
         AddressRequest::Hint(addr)
     };
 
+    let noreserve = (flags & MAP_NORESERVE) != 0 && overcommit::noreserve_allowed();
+    let mapped_len = requested_len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .ok_or(KernelError::NoMemory)?;
+
     // Lock the task and call the core memory manager to perform the mapping.
     let proc_vm = ctx.shared().vm.shared_vm();
-    let new_mapping_addr = proc_vm.lock_save_irq().mm_mut().mmap(
-        address_request,
-        requested_len,
-        permissions,
-        kind,
-        name,
-    )?;
+    let mut vm = proc_vm.lock_save_irq();
+
+    // Only private writable memory needs backing that might not exist. A
+    // `MAP_FIXED` mapping gives back whatever it replaces.
+    let charge = if shared || huge || noreserve || !permissions.write {
+        0
+    } else if let AddressRequest::Fixed { address, .. } = address_request {
+        mapped_len.saturating_sub(
+            vm.mm()
+                .committed_in(VirtMemoryRegion::new(address, mapped_len)),
+        )
+    } else {
+        mapped_len
+    };
+
+    let _reservation = overcommit::reserve(charge)?;
+
+    let new_mapping_addr = if huge {
+        vm.mm_mut()
+            .mmap_huge(address_request, requested_len, permissions, name)?
+    } else {
+        vm.mm_mut()
+            .mmap(address_request, requested_len, permissions, kind, name)?
+    };
+
+    if noreserve {
+        vm.mm_mut()
+            .set_noreserve(VirtMemoryRegion::new(new_mapping_addr, mapped_len), true)?;
+    }
+
+    if write_denied.is_some() {
+        vm.mm_mut().set_write_denied(
+            VirtMemoryRegion::new(new_mapping_addr, mapped_len),
+            write_denied,
+        )?;
+    }
+
+    vm.recharge();
 
     Ok(new_mapping_addr.value())
 }
@@ -143,32 +288,110 @@ pub async fn sys_munmap(ctx: &ProcessCtx, addr: VA, len: usize) -> Result<usize>
     let region = VirtMemoryRegion::new(addr, len);
 
     let proc_vm = ctx.shared().vm.shared_vm();
-    let pages = proc_vm.lock_save_irq().mm_mut().munmap(region)?;
-
-    // Free any physical frames that were unmapped.
-    if !pages.is_empty() {
-        // The frames returned by munmap are no longer mapped and belong to this process;
-        // creating temporary allocations from these regions allows the allocator to reclaim them on drop.
-        let allocator = crate::memory::PAGE_ALLOC
-            .get()
-            .ok_or(KernelError::NoMemory)?;
-
-        for p in pages {
-            // Create a temporary allocation from the single-page region and drop it immediately to free.
-            let tmp = unsafe { allocator.alloc_from_region(p.as_phys_range()) };
-            drop(tmp);
-        }
-    }
+    let pages = {
+        let mut vm = proc_vm.lock_save_irq();
+        let pages = vm.mm_mut().munmap(region)?;
+
+        vm.recharge();
+
+        pages
+    };
+
+    free_unmapped_pages(pages)?;
 
     Ok(0)
 }
 
+/// Releases the process's reference on frames that have been removed from its
+/// page tables.
+pub fn free_unmapped_pages(pages: Vec<PageFrame>) -> Result<()> {
+    if pages.is_empty() {
+        return Ok(());
+    }
+
+    // The frames are no longer mapped and belong to this process; creating
+    // temporary allocations from these regions allows the allocator to reclaim
+    // them on drop.
+    let allocator = crate::memory::PAGE_ALLOC
+        .get()
+        .ok_or(KernelError::NoMemory)?;
+
+    for p in pages {
+        // Create a temporary allocation from the single-page region and drop it immediately to free.
+        let tmp = unsafe { allocator.alloc_from_region(p.as_phys_range()) };
+        drop(tmp);
+    }
+
+    Ok(())
+}
+
+/// Unmaps the pages of every process's mappings of the file `id` that lie
+/// wholly at or beyond `size`, after the file has been truncated. The next
+/// access faults them back in, which raises `SIGBUS` for a shared mapping if
+/// the file is still too short.
+pub fn unmap_file_from(id: InodeId, size: u64) -> Result<()> {
+    let mut vms: Vec<_> = TASK_LIST
+        .lock_save_irq()
+        .values()
+        .filter_map(|task| task.upgrade())
+        .map(|task| task.vm.shared_vm())
+        .collect();
+
+    vms.sort_by_key(Arc::as_ptr);
+    vms.dedup_by(|a, b| Arc::ptr_eq(a, b));
+
+    for vm in vms {
+        let pages = {
+            let mut vm = vm.lock_save_irq();
+            let mm = vm.mm_mut();
+            let regions: Vec<_> = mm
+                .iter_vmas()
+                .filter_map(|vma| vma.file_region_from(id, size))
+                .collect();
+            let mut pages = Vec::new();
+
+            for region in regions {
+                pages.append(&mut mm.discard_region(region)?);
+            }
+
+            pages
+        };
+
+        free_unmapped_pages(pages)?;
+    }
+
+    Ok(())
+}
+
 pub fn sys_mprotect(ctx: &ProcessCtx, addr: VA, len: usize, prot: u64) -> Result<usize> {
     let perms = prot_to_perms(prot);
+
+    if !addr.is_page_aligned() {
+        return Err(KernelError::InvalidValue);
+    }
+
+    // As on Linux, the length is rounded up to a whole number of pages.
+    if len == 0 {
+        return Ok(0);
+    }
+
+    let len = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .ok_or(KernelError::NoMemory)?;
     let region = VirtMemoryRegion::new(addr, len);
 
     let proc_vm = ctx.shared().vm.shared_vm();
-    proc_vm.lock_save_irq().mm_mut().mprotect(region, perms)?;
+    let mut vm = proc_vm.lock_save_irq();
+
+    // Making private memory writable commits it.
+    let _reservation = overcommit::reserve(
+        vm.mm()
+            .committed_in_with(region, perms)
+            .saturating_sub(vm.mm().committed_in(region)),
+    )?;
+
+    vm.mm_mut().mprotect(region, perms)?;
+    vm.recharge();
 
     Ok(0)
 }
@@ -10,13 +10,22 @@ use libkernel::memory::{
     region::PhysMemoryRegion,
 };
 
+#[cfg(feature = "alloc_profile")]
+pub mod alloc_profile;
 pub mod brk;
 pub mod fault;
+#[cfg(feature = "kasan")]
+pub mod kasan;
+#[cfg(feature = "kmemleak")]
+pub mod kmemleak;
+pub mod madvise;
 pub mod mincore;
 pub mod mmap;
+pub mod overcommit;
 pub mod page;
 pub mod process_vm;
 pub mod uaccess;
+pub mod userfaultfd;
 
 pub type PageOffsetTranslator =
     libkernel::memory::proc_vm::pg_offset::PageOffsetTranslator<{ ArchImpl::PAGE_OFFSET }>;
@@ -37,3 +46,14 @@ pub static INITAL_ALLOCATOR: SpinLock<Option<Smalloc<PageOffsetTranslator>>> =
 
 // Main page allocator, setup by consuming smalloc.
 pub static PAGE_ALLOC: OnceLock<FrameAllocator<ArchImpl>> = OnceLock::new();
+
+/// Caches are shrunk once less than 1/`LOW_MEMORY_DIVISOR` of RAM is free.
+const LOW_MEMORY_DIVISOR: usize = 32;
+
+/// Returns `true` if free memory has fallen below the point where caches
+/// should start giving memory back.
+pub fn low_on_memory() -> bool {
+    PAGE_ALLOC
+        .get()
+        .is_some_and(|alloc| alloc.free_pages() < alloc.total_pages() / LOW_MEMORY_DIVISOR)
+}
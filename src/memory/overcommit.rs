@@ -0,0 +1,102 @@
+//! Overcommit policy, following Linux's `overcommit_memory` sysctl:
+//!
+//! - `0`: heuristic. Refuse only requests that could never be satisfied, i.e.
+//!   that are larger than all of RAM.
+//! - `1`: always overcommit, never refuse.
+//! - `2`: never overcommit. The committed total may not exceed
+//!   `overcommit_ratio` percent of RAM.
+//!
+//! Refusing at `mmap`/`brk` time gives the caller an `ENOMEM` it can handle,
+//! instead of finding out at fault time that there is nothing to back a page.
+
+use super::PAGE_ALLOC;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use libkernel::{
+    error::{KernelError, Result},
+    memory::{PAGE_SIZE, proc_vm::commit},
+};
+
+const OVERCOMMIT_GUESS: u8 = 0;
+const OVERCOMMIT_ALWAYS: u8 = 1;
+const OVERCOMMIT_NEVER: u8 = 2;
+
+static OVERCOMMIT_MEMORY: AtomicU8 = AtomicU8::new(OVERCOMMIT_GUESS);
+static OVERCOMMIT_RATIO: AtomicUsize = AtomicUsize::new(50);
+
+/// Returns the current overcommit policy.
+pub fn overcommit_memory() -> u8 {
+    OVERCOMMIT_MEMORY.load(Ordering::Relaxed)
+}
+
+/// Sets the overcommit policy.
+pub fn set_overcommit_memory(policy: u8) -> Result<()> {
+    if policy > OVERCOMMIT_NEVER {
+        return Err(KernelError::InvalidValue);
+    }
+
+    OVERCOMMIT_MEMORY.store(policy, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Returns the percentage of RAM that may be committed under the "never"
+/// policy.
+pub fn overcommit_ratio() -> usize {
+    OVERCOMMIT_RATIO.load(Ordering::Relaxed)
+}
+
+/// Sets the percentage of RAM that may be committed under the "never" policy.
+pub fn set_overcommit_ratio(ratio: usize) {
+    OVERCOMMIT_RATIO.store(ratio, Ordering::Relaxed);
+}
+
+fn total_ram() -> usize {
+    PAGE_ALLOC
+        .get()
+        .map_or(0, |alloc| alloc.total_pages() * PAGE_SIZE)
+}
+
+/// Returns the most memory that may be committed under the "never" policy.
+pub fn commit_limit() -> usize {
+    total_ram() / 100 * overcommit_ratio()
+}
+
+/// Returns `true` if `MAP_NORESERVE` should be honoured. With overcommit
+/// disabled, every mapping is accounted.
+pub fn noreserve_allowed() -> bool {
+    overcommit_memory() != OVERCOMMIT_NEVER
+}
+
+/// Reserves `bytes` of commit charge for an operation that is about to map
+/// memory, if the policy allows it.
+///
+/// The reservation is released when the returned guard is dropped, by which
+/// time the VM should have been recharged with what the operation actually
+/// committed.
+pub fn reserve(bytes: usize) -> Result<CommitReservation> {
+    match overcommit_memory() {
+        OVERCOMMIT_ALWAYS => commit::charge(bytes),
+        OVERCOMMIT_NEVER => commit::try_charge(bytes, commit_limit())?,
+        _ => {
+            if bytes > total_ram() {
+                return Err(KernelError::NoMemory);
+            }
+
+            commit::charge(bytes);
+        }
+    }
+
+    Ok(CommitReservation { bytes })
+}
+
+/// Commit charge held while an operation changes a memory map.
+#[must_use]
+pub struct CommitReservation {
+    bytes: usize,
+}
+
+impl Drop for CommitReservation {
+    fn drop(&mut self) {
+        commit::uncharge(self.bytes);
+    }
+}
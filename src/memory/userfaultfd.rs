@@ -0,0 +1,686 @@
+//! userfaultfd: page faults handled by userspace.
+//!
+//! A monitor registers ranges of anonymous memory with a userfaultfd. When a
+//! task touches a page in such a range that hasn't been populated yet, the
+//! fault isn't resolved with a zeroed page; instead a `UFFD_EVENT_PAGEFAULT`
+//! message is queued on the userfaultfd and the task sleeps. The monitor reads
+//! the message and populates the page with `UFFDIO_COPY` or `UFFDIO_ZEROPAGE`,
+//! which wakes the task so that it retries the access.
+//!
+//! Only missing-page tracking (`UFFDIO_REGISTER_MODE_MISSING`) of anonymous,
+//! non-huge mappings is supported. A forked child isn't registered with its
+//! parent's userfaultfd, and no fork or remap events are reported.
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::{
+    future::Future,
+    mem::size_of,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use libkernel::{
+    error::{FsError, KernelError, MapError, Result, syscall_error::kern_err_to_syscall},
+    fs::OpenFlags,
+    memory::{
+        PAGE_SIZE,
+        address::{TUA, UA, VA},
+        proc_vm::{address_space::UserAddressSpace, vmarea::AccessKind},
+        region::VirtMemoryRegion,
+    },
+    proc::caps::CapabilitiesFlags,
+    sync::condvar::WakeupType,
+};
+
+use super::{
+    page::ClaimedPage,
+    uaccess::{UserCopyable, copy_from_user, copy_from_user_slice, copy_to_user},
+};
+use crate::{
+    fs::{
+        fops::FileOps,
+        open_file::{FileCtx, OpenFile},
+    },
+    process::{ProcVM, fd_table::FdFlags},
+    sched::syscall_ctx::ProcessCtx,
+    sync::{CondVar, SpinLock},
+};
+
+const UFFD_API: u64 = 0xaa;
+
+const UFFD_USER_MODE_ONLY: u32 = 1;
+const UFFD_INIT_ALLOWED: u32 =
+    UFFD_USER_MODE_ONLY | OpenFlags::O_CLOEXEC.bits() | OpenFlags::O_NONBLOCK.bits();
+
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+const UFFD_PAGEFAULT_FLAG_WRITE: u64 = 1 << 0;
+
+const UFFD_FEATURE_THREAD_ID: u64 = 1 << 8;
+const UFFD_FEATURES: u64 = UFFD_FEATURE_THREAD_ID;
+
+const UFFDIO_REGISTER_MODE_MISSING: u64 = 1 << 0;
+const UFFDIO_COPY_MODE_DONTWAKE: u64 = 1 << 0;
+const UFFDIO_ZEROPAGE_MODE_DONTWAKE: u64 = 1 << 0;
+
+// ioctl numbers, which double as the bit positions reported in `ioctls`.
+const _UFFDIO_REGISTER: u64 = 0x00;
+const _UFFDIO_UNREGISTER: u64 = 0x01;
+const _UFFDIO_WAKE: u64 = 0x02;
+const _UFFDIO_COPY: u64 = 0x03;
+const _UFFDIO_ZEROPAGE: u64 = 0x04;
+const _UFFDIO_API: u64 = 0x3f;
+
+const UFFD_API_IOCTLS: u64 = 1 << _UFFDIO_REGISTER | 1 << _UFFDIO_UNREGISTER | 1 << _UFFDIO_API;
+const UFFD_RANGE_IOCTLS: u64 = 1 << _UFFDIO_WAKE | 1 << _UFFDIO_COPY | 1 << _UFFDIO_ZEROPAGE;
+
+const UFFDIO_API: usize = 0xc018_aa3f;
+const UFFDIO_REGISTER: usize = 0xc020_aa00;
+const UFFDIO_UNREGISTER: usize = 0x8010_aa01;
+const UFFDIO_WAKE: usize = 0x8010_aa02;
+const UFFDIO_COPY: usize = 0xc028_aa03;
+const UFFDIO_ZEROPAGE: usize = 0xc020_aa04;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct UffdMsg {
+    event: u8,
+    _reserved1: u8,
+    _reserved2: u16,
+    _reserved3: u32,
+    flags: u64,
+    address: u64,
+    ptid: u32,
+    _pad: u32,
+}
+
+unsafe impl UserCopyable for UffdMsg {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioApi {
+    api: u64,
+    features: u64,
+    ioctls: u64,
+}
+
+unsafe impl UserCopyable for UffdioApi {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioRange {
+    start: u64,
+    len: u64,
+}
+
+unsafe impl UserCopyable for UffdioRange {}
+
+impl UffdioRange {
+    /// Returns the region described, which must be non-empty and
+    /// page-aligned.
+    fn region(&self) -> Result<VirtMemoryRegion> {
+        let start = self.start as usize;
+        let len = self.len as usize;
+
+        if len == 0 || !start.is_multiple_of(PAGE_SIZE) || !len.is_multiple_of(PAGE_SIZE) {
+            return Err(KernelError::InvalidValue);
+        }
+
+        start.checked_add(len).ok_or(KernelError::InvalidValue)?;
+
+        Ok(VirtMemoryRegion::new(VA::from_value(start), len))
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioRegister {
+    range: UffdioRange,
+    mode: u64,
+    ioctls: u64,
+}
+
+unsafe impl UserCopyable for UffdioRegister {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioCopy {
+    dst: u64,
+    src: u64,
+    len: u64,
+    mode: u64,
+    copy: i64,
+}
+
+unsafe impl UserCopyable for UffdioCopy {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioZeropage {
+    range: UffdioRange,
+    mode: u64,
+    zeropage: i64,
+}
+
+unsafe impl UserCopyable for UffdioZeropage {}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static CONTEXTS: SpinLock<BTreeMap<u64, Weak<UserfaultCtx>>> = SpinLock::new(BTreeMap::new());
+
+/// Returns the live context registered VMAs refer to by `id`.
+pub fn lookup(id: u64) -> Option<Arc<UserfaultCtx>> {
+    CONTEXTS.lock_save_irq().get(&id).and_then(Weak::upgrade)
+}
+
+#[derive(Default)]
+struct UserfaultState {
+    /// Features agreed on with `UFFDIO_API`; `None` until then.
+    features: Option<u64>,
+    queue: VecDeque<UffdMsg>,
+    /// Pages that tasks are waiting on.
+    pending: BTreeSet<usize>,
+    /// Set once the monitor has gone away; blocked tasks are let through.
+    released: bool,
+}
+
+pub struct UserfaultCtx {
+    id: u64,
+    user_mode_only: bool,
+    /// The address space whose faults are reported.
+    vm: Arc<SpinLock<ProcVM>>,
+    state: CondVar<UserfaultState>,
+}
+
+impl UserfaultCtx {
+    /// Returns `true` if faults taken by the kernel while accessing user
+    /// memory aren't reported, and fail instead.
+    pub fn user_mode_only(&self) -> bool {
+        self.user_mode_only
+    }
+
+    /// Reports a fault on a missing page to the monitor and waits for it to
+    /// be resolved. The faulting access should then be retried.
+    pub async fn handle_fault(
+        self: Arc<Self>,
+        proc_vm: Arc<SpinLock<ProcVM>>,
+        faulting_addr: VA,
+        access_kind: AccessKind,
+        tid: u32,
+    ) -> Result<()> {
+        let page = faulting_addr.page_aligned();
+        let mut wait = false;
+
+        self.state.update(|s| {
+            // The monitor may have populated the page, or unregistered it,
+            // since we faulted; if so, there's nothing to wait for.
+            let still_missing = {
+                let mut vm = proc_vm.lock_save_irq();
+
+                vm.mm()
+                    .find_vma(page)
+                    .is_some_and(|vma| vma.userfault() == Some(self.id))
+                    && vm.mm_mut().address_space_mut().translate(page).is_none()
+            };
+
+            if s.released || !still_missing {
+                return WakeupType::None;
+            }
+
+            wait = true;
+
+            // Other tasks faulting on the same page share its message.
+            if !s.pending.insert(page.value()) {
+                return WakeupType::None;
+            }
+
+            let thread_id = s.features.unwrap_or(0) & UFFD_FEATURE_THREAD_ID != 0;
+
+            s.queue.push_back(UffdMsg {
+                event: UFFD_EVENT_PAGEFAULT,
+                flags: if access_kind == AccessKind::Write {
+                    UFFD_PAGEFAULT_FLAG_WRITE
+                } else {
+                    0
+                },
+                address: page.value() as u64,
+                ptid: if thread_id { tid } else { 0 },
+                ..Default::default()
+            });
+
+            WakeupType::All
+        });
+
+        if wait {
+            self.state
+                .wait_until(move |s| {
+                    (s.released || !s.pending.contains(&page.value())).then_some(())
+                })
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// Wakes the tasks waiting on pages in `region`.
+    fn wake(&self, region: VirtMemoryRegion) {
+        self.state.update(|s| {
+            let pages: Vec<_> = s
+                .pending
+                .range(region.start_address().value()..region.end_address().value())
+                .copied()
+                .collect();
+
+            for page in &pages {
+                s.pending.remove(page);
+            }
+
+            if pages.is_empty() {
+                WakeupType::None
+            } else {
+                WakeupType::All
+            }
+        });
+    }
+
+    fn features(&self) -> Option<u64> {
+        let mut features = None;
+
+        self.state.update(|s| {
+            features = s.features;
+            WakeupType::None
+        });
+
+        features
+    }
+
+    fn api(&self, api: UffdioApi) -> Result<UffdioApi> {
+        if api.api != UFFD_API || api.features & !UFFD_FEATURES != 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut res = Err(KernelError::InvalidValue);
+
+        self.state.update(|s| {
+            if s.features.is_none() {
+                s.features = Some(api.features);
+                res = Ok(());
+            }
+            WakeupType::None
+        });
+
+        res?;
+
+        Ok(UffdioApi {
+            api: UFFD_API,
+            features: UFFD_FEATURES,
+            ioctls: UFFD_API_IOCTLS,
+        })
+    }
+
+    fn register(&self, region: VirtMemoryRegion, mode: u64) -> Result<()> {
+        if mode != UFFDIO_REGISTER_MODE_MISSING {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut vm = self.vm.lock_save_irq();
+        let mm = vm.mm_mut();
+
+        for part in mm
+            .covering_regions(region)
+            .map_err(|_| KernelError::InvalidValue)?
+        {
+            let vma = mm
+                .find_vma(part.start_address())
+                .ok_or(KernelError::InvalidValue)?;
+
+            if vma.is_file_backed() || vma.uses_huge_pages() {
+                return Err(KernelError::InvalidValue);
+            }
+
+            if vma.userfault().is_some_and(|id| id != self.id) {
+                return Err(KernelError::InUse);
+            }
+        }
+
+        mm.set_userfault(region, Some(self.id))
+    }
+
+    fn unregister(&self, region: VirtMemoryRegion) -> Result<()> {
+        {
+            let mut vm = self.vm.lock_save_irq();
+            let mm = vm.mm_mut();
+
+            let ours: Vec<_> = mm
+                .covering_regions(region)
+                .map_err(|_| KernelError::InvalidValue)?
+                .into_iter()
+                .filter(|part| {
+                    mm.find_vma(part.start_address())
+                        .is_some_and(|vma| vma.userfault() == Some(self.id))
+                })
+                .collect();
+
+            for part in ours {
+                mm.set_userfault(part, None)?;
+            }
+        }
+
+        // Waiting tasks retry, and are now given zeroed pages.
+        self.wake(region);
+
+        Ok(())
+    }
+
+    /// Maps pages filled from `src` at `region`, stopping at the first
+    /// failure. Returns the number of bytes mapped, or the error if nothing
+    /// was.
+    async fn populate(&self, region: VirtMemoryRegion, src: Option<UA>) -> Result<usize> {
+        let mut done = 0;
+
+        for va in region.iter_pages() {
+            let res = async {
+                let mut page = ClaimedPage::alloc_zeroed()?;
+
+                if let Some(src) = src {
+                    copy_from_user_slice(src.add_bytes(done), page.as_slice_mut()).await?;
+                }
+
+                let mut vm = self.vm.lock_save_irq();
+                let perms = vm
+                    .mm()
+                    .find_vma(va)
+                    .filter(|vma| vma.userfault() == Some(self.id))
+                    .ok_or(FsError::NotFound)?
+                    .permissions();
+
+                match vm
+                    .mm_mut()
+                    .address_space_mut()
+                    .map_page(page.pa().to_pfn(), va, perms.into())
+                {
+                    Ok(()) => {
+                        // The page table now holds our reference.
+                        page.leak();
+                        Ok(())
+                    }
+                    Err(KernelError::MappingError(MapError::AlreadyMapped)) => {
+                        Err(FsError::AlreadyExists.into())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            .await;
+
+            match res {
+                Ok(()) => done += PAGE_SIZE,
+                Err(e) if done == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+
+        Ok(done)
+    }
+
+    /// Completes a `UFFDIO_COPY` or `UFFDIO_ZEROPAGE`: wakes the tasks
+    /// waiting on what was mapped, and fails with `EAGAIN` if that wasn't the
+    /// whole region.
+    fn finish_populate(&self, region: VirtMemoryRegion, done: usize, wake: bool) -> Result<()> {
+        if wake {
+            self.wake(VirtMemoryRegion::new(region.start_address(), done));
+        }
+
+        if done == region.size() {
+            Ok(())
+        } else {
+            Err(KernelError::TryAgain)
+        }
+    }
+
+    async fn copy(&self, argp: TUA<UffdioCopy>) -> Result<()> {
+        let mut args = copy_from_user(argp).await?;
+
+        if args.mode & !UFFDIO_COPY_MODE_DONTWAKE != 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let region = UffdioRange {
+            start: args.dst,
+            len: args.len,
+        }
+        .region()?;
+
+        args.src
+            .checked_add(args.len)
+            .ok_or(KernelError::InvalidValue)?;
+
+        let res = self
+            .populate(region, Some(UA::from_value(args.src as usize)))
+            .await;
+
+        args.copy = populate_result(&res);
+        copy_to_user(argp, args).await?;
+
+        self.finish_populate(region, res?, args.mode & UFFDIO_COPY_MODE_DONTWAKE == 0)
+    }
+
+    async fn zeropage(&self, argp: TUA<UffdioZeropage>) -> Result<()> {
+        let mut args = copy_from_user(argp).await?;
+
+        if args.mode & !UFFDIO_ZEROPAGE_MODE_DONTWAKE != 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let region = args.range.region()?;
+        let res = self.populate(region, None).await;
+
+        args.zeropage = populate_result(&res);
+        copy_to_user(argp, args).await?;
+
+        self.finish_populate(region, res?, args.mode & UFFDIO_ZEROPAGE_MODE_DONTWAKE == 0)
+    }
+
+    fn release(&self) {
+        CONTEXTS.lock_save_irq().remove(&self.id);
+
+        {
+            let mut vm = self.vm.lock_save_irq();
+            let mm = vm.mm_mut();
+
+            let ours: Vec<_> = mm
+                .iter_vmas()
+                .filter(|vma| vma.userfault() == Some(self.id))
+                .map(|vma| vma.region)
+                .collect();
+
+            for region in ours {
+                let _ = mm.set_userfault(region, None);
+            }
+        }
+
+        self.state.update(|s| {
+            s.released = true;
+            s.queue.clear();
+            s.pending.clear();
+            WakeupType::All
+        });
+    }
+}
+
+/// Returns what `UFFDIO_COPY` and `UFFDIO_ZEROPAGE` report back: the number
+/// of bytes mapped, or a negated errno.
+fn populate_result(res: &Result<usize>) -> i64 {
+    match res {
+        Ok(done) => *done as i64,
+        Err(e) => kern_err_to_syscall(e.clone()) as i64,
+    }
+}
+
+pub struct Userfaultfd {
+    ctx: Arc<UserfaultCtx>,
+}
+
+impl Userfaultfd {
+    fn new(vm: Arc<SpinLock<ProcVM>>, user_mode_only: bool) -> Self {
+        let ctx = Arc::new(UserfaultCtx {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            user_mode_only,
+            vm,
+            state: CondVar::new(UserfaultState::default()),
+        });
+
+        CONTEXTS
+            .lock_save_irq()
+            .insert(ctx.id, Arc::downgrade(&ctx));
+
+        Self { ctx }
+    }
+
+    async fn read_impl(&mut self, buf: UA, count: usize, nonblock: bool) -> Result<usize> {
+        const MSG_LEN: usize = size_of::<UffdMsg>();
+
+        if count < MSG_LEN || self.ctx.features().is_none() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut bytes_read = 0;
+
+        while count - bytes_read >= MSG_LEN {
+            let msg = if bytes_read == 0 && !nonblock {
+                self.ctx.state.wait_until(|s| s.queue.pop_front()).await
+            } else {
+                let mut msg = None;
+                self.ctx.state.update(|s| {
+                    msg = s.queue.pop_front();
+                    WakeupType::None
+                });
+
+                match msg {
+                    Some(msg) => msg,
+                    None if bytes_read == 0 => return Err(KernelError::TryAgain),
+                    None => break,
+                }
+            };
+
+            copy_to_user(TUA::from_value(buf.add_bytes(bytes_read).value()), msg).await?;
+            bytes_read += MSG_LEN;
+        }
+
+        Ok(bytes_read)
+    }
+}
+
+#[async_trait]
+impl FileOps for Userfaultfd {
+    async fn read(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        self.read_impl(buf, count, ctx.flags.contains(OpenFlags::O_NONBLOCK))
+            .await
+    }
+
+    async fn readat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        self.read_impl(buf, count, false).await
+    }
+
+    async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::InvalidValue)
+    }
+
+    async fn ioctl(&mut self, _ctx: &mut FileCtx, request: usize, argp: usize) -> Result<usize> {
+        if request == UFFDIO_API {
+            let argp = TUA::from_value(argp);
+            let api = self.ctx.api(copy_from_user(argp).await?)?;
+            copy_to_user(argp, api).await?;
+            return Ok(0);
+        }
+
+        if self.ctx.features().is_none() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        match request {
+            UFFDIO_REGISTER => {
+                let argp = TUA::from_value(argp);
+                let mut args: UffdioRegister = copy_from_user(argp).await?;
+
+                self.ctx.register(args.range.region()?, args.mode)?;
+
+                args.ioctls = UFFD_RANGE_IOCTLS;
+                copy_to_user(argp, args).await?;
+            }
+            UFFDIO_UNREGISTER => {
+                let range: UffdioRange = copy_from_user(TUA::from_value(argp)).await?;
+                self.ctx.unregister(range.region()?)?;
+            }
+            UFFDIO_WAKE => {
+                let range: UffdioRange = copy_from_user(TUA::from_value(argp)).await?;
+                self.ctx.wake(range.region()?);
+            }
+            UFFDIO_COPY => self.ctx.copy(TUA::from_value(argp)).await?,
+            UFFDIO_ZEROPAGE => self.ctx.zeropage(TUA::from_value(argp)).await?,
+            _ => return Err(KernelError::InvalidValue),
+        }
+
+        Ok(0)
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let state = self.ctx.state.clone();
+
+        Box::pin(async move {
+            state
+                .wait_until(|s| (!s.queue.is_empty()).then_some(()))
+                .await;
+            Ok(())
+        })
+    }
+
+    async fn release(&mut self, _ctx: &FileCtx) -> Result<()> {
+        self.ctx.release();
+        Ok(())
+    }
+}
+
+pub async fn sys_userfaultfd(ctx: &ProcessCtx, flags: u32) -> Result<usize> {
+    if flags & !UFFD_INIT_ALLOWED != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let user_mode_only = flags & UFFD_USER_MODE_ONLY != 0;
+
+    // Stalling the kernel's own accesses to user memory is privileged.
+    if !user_mode_only {
+        ctx.shared()
+            .creds
+            .lock_save_irq()
+            .caps()
+            .check_capable(CapabilitiesFlags::CAP_SYS_PTRACE)?;
+    }
+
+    let file_flags = if flags & OpenFlags::O_NONBLOCK.bits() != 0 {
+        OpenFlags::O_NONBLOCK
+    } else {
+        OpenFlags::empty()
+    };
+    let fd_flags = if flags & OpenFlags::O_CLOEXEC.bits() != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+
+    let uffd = Userfaultfd::new(ctx.shared().vm.shared_vm(), user_mode_only);
+    let file = Arc::new(OpenFile::new(Box::new(uffd), file_flags));
+    let fd = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(file, fd_flags)?;
+
+    Ok(fd.as_raw() as usize)
+}
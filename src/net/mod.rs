@@ -1,32 +1,43 @@
 mod sops;
 pub mod syscalls;
+#[cfg(feature = "net")]
 mod tcp;
 mod unix;
 
+#[cfg(feature = "net")]
 use crate::drivers::timer::now;
 use crate::memory::uaccess::{copy_from_user, copy_from_user_slice};
-use crate::sync::OnceLock;
-use crate::sync::SpinLock;
+#[cfg(feature = "net")]
+use crate::sync::{OnceLock, SpinLock};
+#[cfg(feature = "net")]
 use alloc::vec;
 use alloc::vec::Vec;
+#[cfg(feature = "net")]
 use core::net::Ipv4Addr;
 use libkernel::error::KernelError;
 use libkernel::memory::address::UA;
+#[cfg(feature = "net")]
 use libkernel::sync::waker_set::WakerSet;
+#[cfg(feature = "net")]
 use smoltcp::iface::SocketSet;
+#[cfg(feature = "net")]
 use smoltcp::wire::{IpAddress, IpEndpoint};
 pub use sops::SocketOps;
 
+#[cfg(feature = "net")]
 static SOCKETS: OnceLock<SpinLock<SocketSet>> = OnceLock::new();
 
+#[cfg(feature = "net")]
 fn sockets() -> &'static SpinLock<SocketSet<'static>> {
     SOCKETS.get_or_init(|| SpinLock::new(SocketSet::new(vec![])))
 }
 
 // static INTERFACE: OnceLock<SpinLock<EthernetInterface<OurDevice>>> = OnceLock::new();
 
+#[cfg(feature = "net")]
 static SOCKET_WAIT_QUEUE: OnceLock<SpinLock<WakerSet>> = OnceLock::new();
 
+#[cfg(feature = "net")]
 fn socket_wait_queue() -> &'static SpinLock<WakerSet> {
     SOCKET_WAIT_QUEUE.get_or_init(|| SpinLock::new(WakerSet::new()))
 }
@@ -36,6 +47,7 @@ pub const AF_INET: i32 = 2;
 pub const SOCK_STREAM: i32 = 1;
 pub const SOCK_DGRAM: i32 = 2;
 pub const SOCK_SEQPACKET: i32 = 5;
+#[cfg(feature = "net")]
 pub const IPPROTO_TCP: i32 = 6;
 #[expect(dead_code)]
 pub const IPPROTO_UDP: i32 = 17;
@@ -116,6 +128,7 @@ pub struct SockAddrUn {
 unsafe impl crate::memory::uaccess::UserCopyable for SockAddrIn {}
 unsafe impl crate::memory::uaccess::UserCopyable for SockAddrUn {}
 
+#[cfg(feature = "net")]
 impl TryFrom<SockAddr> for IpEndpoint {
     type Error = KernelError;
     fn try_from(sockaddr: SockAddr) -> Result<IpEndpoint, KernelError> {
@@ -129,6 +142,7 @@ impl TryFrom<SockAddr> for IpEndpoint {
     }
 }
 
+#[cfg(feature = "net")]
 impl From<IpEndpoint> for SockAddr {
     fn from(endpoint: IpEndpoint) -> SockAddr {
         SockAddr::In(SockAddrIn {
@@ -143,6 +157,7 @@ impl From<IpEndpoint> for SockAddr {
     }
 }
 
+#[cfg(feature = "net")]
 pub fn process_packets() {
     // For now, just wake any tasks waiting on socket progress.
     let _ = sockets().lock_save_irq();
@@ -1,9 +1,13 @@
 use crate::fs::fops::FileOps;
-use crate::fs::open_file::FileCtx;
+use crate::fs::open_file::{FileCtx, OpenFile};
+use crate::kernel::kpipe::KPipe;
 use crate::net::{ShutdownHow, SockAddr};
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use async_trait::async_trait;
 use bitflags::bitflags;
+use core::pin::Pin;
 use libkernel::error::KernelError;
 use libkernel::memory::address::UA;
 
@@ -77,10 +81,50 @@ pub trait SocketOps: Send + Sync {
         addr: SockAddr,
     ) -> libkernel::error::Result<usize>;
 
+    /// Sends up to `count` bytes taken from `kbuf`, for `sendfile`.
+    async fn splice_send(
+        &mut self,
+        _ctx: &mut FileCtx,
+        _kbuf: &KPipe,
+        _count: usize,
+    ) -> libkernel::error::Result<usize> {
+        Err(KernelError::InvalidValue)
+    }
+
     async fn shutdown(&self, _how: ShutdownHow) -> libkernel::error::Result<()> {
         Err(KernelError::NotSupported)
     }
 
+    /// Resolves once a receive wouldn't block, or there's a connection to
+    /// accept.
+    fn poll_recv_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        Box::pin(async { Err(KernelError::NotSupported) })
+    }
+
+    /// Resolves once a send wouldn't block.
+    fn poll_send_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        Box::pin(async { Err(KernelError::NotSupported) })
+    }
+
+    /// Passes `files` to the peer, or to the socket at `addr`, to be picked
+    /// up by its next `recvmsg`, for `SCM_RIGHTS`.
+    fn send_rights(
+        &self,
+        _addr: Option<SockAddr>,
+        _files: Vec<Arc<OpenFile>>,
+    ) -> libkernel::error::Result<()> {
+        Err(KernelError::OpNotSupported)
+    }
+
+    /// Takes the oldest batch of files passed to this socket.
+    fn recv_rights(&self) -> Option<Vec<Arc<OpenFile>>> {
+        None
+    }
+
     fn as_file(self: Box<Self>) -> Box<dyn FileOps>;
 }
 
@@ -127,6 +171,31 @@ where
         Err(KernelError::NotSupported)
     }
 
+    async fn splice_from(
+        &mut self,
+        ctx: &mut FileCtx,
+        kbuf: &KPipe,
+        count: usize,
+    ) -> libkernel::error::Result<usize> {
+        self.splice_send(ctx, kbuf, count).await
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn poll_read_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        self.poll_recv_ready()
+    }
+
+    fn poll_write_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        self.poll_send_ready()
+    }
+
     fn as_socket(&mut self) -> Option<&mut dyn SocketOps> {
         Some(self)
     }
@@ -1,7 +1,8 @@
 use crate::fs::open_file::OpenFile;
 use crate::memory::uaccess::{copy_from_user, copy_to_user, copy_to_user_slice};
 use crate::net::SocketLen;
-use crate::process::fd_table::Fd;
+use crate::net::syscalls::socket::{CLOSE_ON_EXEC, NONBLOCK};
+use crate::process::fd_table::{Fd, FdFlags};
 use crate::sched::syscall_ctx::ProcessCtx;
 use libkernel::error::KernelError;
 use libkernel::fs::OpenFlags;
@@ -12,8 +13,18 @@ pub async fn sys_accept4(
     fd: Fd,
     addr: UA,
     addrlen: TUA<SocketLen>,
-    _flags: i32,
+    flags: i32,
 ) -> libkernel::error::Result<usize> {
+    if flags & !(CLOSE_ON_EXEC | NONBLOCK) != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let fd_flags = if (flags & CLOSE_ON_EXEC) != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+
     let file = ctx
         .shared()
         .fd_table
@@ -30,12 +41,12 @@ pub async fn sys_accept4(
         .await?;
     let new_socket = new_socket.as_file();
 
-    let open_file = OpenFile::new(new_socket, OpenFlags::empty());
+    let open_file = OpenFile::new(new_socket, OpenFlags::O_RDWR);
     let new_fd = ctx
         .shared()
         .fd_table
         .lock_save_irq()
-        .insert(alloc::sync::Arc::new(open_file))?;
+        .insert_with_flags(alloc::sync::Arc::new(open_file), fd_flags)?;
     if !addr.is_null() {
         if addrlen.is_null() {
             return Err(KernelError::InvalidValue);
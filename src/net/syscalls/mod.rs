@@ -2,6 +2,7 @@ pub mod accept;
 pub mod bind;
 pub mod connect;
 pub mod listen;
+pub mod msg;
 pub mod recv;
 pub mod send;
 pub mod shutdown;
@@ -0,0 +1,319 @@
+use crate::fs::open_file::OpenFile;
+use crate::fs::syscalls::iov::{IoVec, copy_iovecs_from_user};
+use crate::memory::uaccess::{
+    UserCopyable, copy_from_user, copy_from_user_slice, copy_to_user, copy_to_user_slice,
+};
+use crate::net::parse_sockaddr;
+use crate::net::sops::{RecvFlags, SendFlags};
+use crate::process::fd_table::{Fd, FdFlags};
+use crate::sched::syscall_ctx::ProcessCtx;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use futures::FutureExt;
+use libkernel::error::{KernelError, Result};
+use libkernel::memory::address::{TUA, UA};
+
+const SOL_SOCKET: i32 = 1;
+const SCM_RIGHTS: i32 = 1;
+/// The most files a single `SCM_RIGHTS` message can carry, as on Linux.
+const SCM_MAX_FD: usize = 253;
+/// The most ancillary data accepted in one `sendmsg`.
+const MAX_CONTROL_LEN: usize = 4096;
+
+const MSG_CTRUNC: i32 = 0x8;
+const MSG_CMSG_CLOEXEC: i32 = 0x4000_0000;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct MsgHdr {
+    name: UA,
+    namelen: u32,
+    iov: TUA<IoVec>,
+    iovlen: usize,
+    control: UA,
+    controllen: usize,
+    flags: i32,
+}
+
+// SAFETY: A MsgHdr is plain old data.
+unsafe impl UserCopyable for MsgHdr {}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct CmsgHdr {
+    len: usize,
+    level: i32,
+    type_: i32,
+}
+
+const CMSG_HDR_LEN: usize = size_of::<CmsgHdr>();
+
+const fn cmsg_align(len: usize) -> usize {
+    len.next_multiple_of(size_of::<usize>())
+}
+
+/// Pulls the files out of the `SCM_RIGHTS` messages in `control`.
+async fn parse_rights(ctx: &ProcessCtx, control: UA, len: usize) -> Result<Vec<Arc<OpenFile>>> {
+    if len > MAX_CONTROL_LEN {
+        return Err(KernelError::NoMemory);
+    }
+
+    let mut buf = alloc::vec![0u8; len];
+    copy_from_user_slice(control, &mut buf).await?;
+
+    let mut files = Vec::new();
+    let mut off = 0;
+
+    while off + CMSG_HDR_LEN <= len {
+        let hdr = &buf[off..off + CMSG_HDR_LEN];
+        let cmsg_len = usize::from_ne_bytes(hdr[..8].try_into().unwrap());
+        let level = i32::from_ne_bytes(hdr[8..12].try_into().unwrap());
+        let type_ = i32::from_ne_bytes(hdr[12..16].try_into().unwrap());
+
+        if cmsg_len < CMSG_HDR_LEN || cmsg_len > len - off {
+            return Err(KernelError::InvalidValue);
+        }
+
+        if level != SOL_SOCKET || type_ != SCM_RIGHTS {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let fds = &buf[off + CMSG_HDR_LEN..off + cmsg_len];
+
+        if files.len() + fds.len() / 4 > SCM_MAX_FD {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let fd_table = ctx.shared().fd_table.lock_save_irq();
+
+        for fd in fds.as_chunks::<4>().0 {
+            let fd = Fd(i32::from_ne_bytes(*fd));
+            files.push(fd_table.get_raw(fd).ok_or(KernelError::BadFd)?);
+        }
+
+        off += cmsg_align(cmsg_len);
+    }
+
+    Ok(files)
+}
+
+/// Sends the data in the message's iovecs, along with any files passed in
+/// its `SCM_RIGHTS` ancillary data. The files go ahead of the data, to be
+/// picked up by the peer's next `recvmsg`. Each iovec is sent on its own, so
+/// on a datagram socket each makes a message.
+pub async fn sys_sendmsg(ctx: &ProcessCtx, fd: Fd, msg: TUA<MsgHdr>, flags: i32) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+
+    let msg = copy_from_user(msg).await?;
+    let iovs = copy_iovecs_from_user(msg.iov, msg.iovlen).await?;
+
+    let addr = if msg.name.is_null() || msg.namelen == 0 {
+        None
+    } else {
+        Some(parse_sockaddr(msg.name, msg.namelen as _).await?)
+    };
+
+    let rights = if msg.controllen > 0 {
+        parse_rights(ctx, msg.control, msg.controllen).await?
+    } else {
+        Vec::new()
+    };
+
+    let (ops, ctx) = &mut *file.lock().await;
+    let socket = ops.as_socket().ok_or(KernelError::NotASocket)?;
+    let flags = SendFlags::from_bits_truncate(flags as u32);
+
+    if !rights.is_empty() {
+        socket.send_rights(addr.clone(), rights)?;
+    }
+
+    let mut total = 0;
+
+    for iov in iovs.iter().filter(|iov| iov.iov_len > 0) {
+        let sent = match &addr {
+            Some(addr) => {
+                socket
+                    .sendto(ctx, iov.iov_base, iov.iov_len, flags, addr.clone())
+                    .await
+            }
+            None => socket.send(ctx, iov.iov_base, iov.iov_len, flags).await,
+        };
+
+        match sent {
+            Ok(n) => {
+                total += n;
+
+                if n < iov.iov_len {
+                    break;
+                }
+            }
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(total)
+}
+
+/// Installs `files` in the caller's fd table and writes out an `SCM_RIGHTS`
+/// message for them, as far as `len` bytes of `control` allow. Returns the
+/// length of ancillary data written and whether any files didn't fit.
+async fn put_rights(
+    ctx: &ProcessCtx,
+    control: UA,
+    len: usize,
+    files: Vec<Arc<OpenFile>>,
+    cloexec: bool,
+) -> Result<(usize, bool)> {
+    let room = len.saturating_sub(CMSG_HDR_LEN) / size_of::<i32>();
+    let fd_flags = if cloexec {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+    let mut fds = Vec::new();
+    let mut dropped = Vec::new();
+
+    {
+        let mut fd_table = ctx.shared().fd_table.lock_save_irq();
+
+        for file in files {
+            if fds.len() == room {
+                dropped.push(file);
+                continue;
+            }
+
+            match fd_table.insert_with_flags(file.clone(), fd_flags.clone()) {
+                Ok(fd) => fds.push(fd),
+                Err(_) => dropped.push(file),
+            }
+        }
+    }
+
+    // Whatever doesn't fit is closed.
+    let truncated = !dropped.is_empty();
+
+    for file in dropped {
+        if let Some(file) = Arc::into_inner(file) {
+            let (ops, ctx) = &mut *file.lock().await;
+            let _ = ops.release(ctx).await;
+        }
+    }
+
+    if fds.is_empty() {
+        return Ok((0, truncated));
+    }
+
+    let cmsg_len = CMSG_HDR_LEN + fds.len() * size_of::<i32>();
+    let mut buf = Vec::with_capacity(cmsg_align(cmsg_len));
+    buf.extend_from_slice(&cmsg_len.to_ne_bytes());
+    buf.extend_from_slice(&SOL_SOCKET.to_ne_bytes());
+    buf.extend_from_slice(&SCM_RIGHTS.to_ne_bytes());
+
+    for fd in &fds {
+        buf.extend_from_slice(&fd.as_raw().to_ne_bytes());
+    }
+
+    buf.resize(cmsg_align(cmsg_len).min(len), 0);
+    copy_to_user_slice(&buf, control).await?;
+
+    Ok((buf.len(), truncated))
+}
+
+/// Receives into the message's iovecs, handing over any files the sender
+/// passed with `SCM_RIGHTS` as ancillary data.
+pub async fn sys_recvmsg(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    msg_ptr: TUA<MsgHdr>,
+    flags: i32,
+) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+
+    let mut msg = copy_from_user(msg_ptr).await?;
+    let iovs = copy_iovecs_from_user(msg.iov, msg.iovlen).await?;
+    let cloexec = flags & MSG_CMSG_CLOEXEC != 0;
+
+    let (total, addr, rights) = {
+        let (ops, fctx) = &mut *file.lock().await;
+        let socket = ops.as_socket().ok_or(KernelError::NotASocket)?;
+        let recv_flags = RecvFlags::from_bits_truncate(flags as u32);
+        let mut total = 0;
+        let mut addr = None;
+
+        for iov in iovs.iter().filter(|iov| iov.iov_len > 0) {
+            // Only wait for the first iovec to be filled in; the rest take
+            // whatever is already there.
+            if total > 0 && socket.poll_recv_ready().now_or_never().is_none() {
+                break;
+            }
+
+            let (n, from) = match socket
+                .recv(fctx, iov.iov_base, iov.iov_len, recv_flags)
+                .await
+            {
+                Ok(res) => res,
+                Err(_) if total > 0 => break,
+                Err(e) => return Err(e),
+            };
+
+            total += n;
+
+            // A datagram comes with its sender, and is received whole into
+            // the first iovec.
+            if from.is_some() {
+                addr = from;
+                break;
+            }
+
+            if n < iov.iov_len {
+                break;
+            }
+        }
+
+        let rights = if total > 0 {
+            socket.recv_rights()
+        } else {
+            None
+        };
+
+        (total, addr, rights)
+    };
+
+    msg.flags = 0;
+
+    let (controllen, truncated) = match rights {
+        Some(files) => put_rights(ctx, msg.control, msg.controllen, files, cloexec).await?,
+        None => (0, false),
+    };
+
+    msg.controllen = controllen;
+
+    if truncated {
+        msg.flags |= MSG_CTRUNC;
+    }
+
+    match addr {
+        Some(addr) if !msg.name.is_null() => {
+            let bytes = addr.to_bytes();
+            let to_copy = bytes.len().min(msg.namelen as usize);
+            copy_to_user_slice(&bytes[..to_copy], msg.name).await?;
+            msg.namelen = bytes.len() as u32;
+        }
+        _ => msg.namelen = 0,
+    }
+
+    copy_to_user(msg_ptr, msg).await?;
+
+    Ok(total)
+}
@@ -1,13 +1,19 @@
 use crate::fs::fops::FileOps;
 use crate::fs::open_file::OpenFile;
+use crate::memory::uaccess::copy_to_user;
+#[cfg(feature = "net")]
 use crate::net::tcp::TcpSocket;
 use crate::net::unix::UnixSocket;
-use crate::net::{AF_INET, AF_UNIX, IPPROTO_TCP, SOCK_DGRAM, SOCK_SEQPACKET, SOCK_STREAM};
+#[cfg(feature = "net")]
+use crate::net::{AF_INET, IPPROTO_TCP};
+use crate::net::{AF_UNIX, SOCK_DGRAM, SOCK_SEQPACKET, SOCK_STREAM};
+use crate::process::fd_table::{Fd, FdFlags};
 use crate::sched::syscall_ctx::ProcessCtx;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 use libkernel::error::KernelError;
 use libkernel::fs::OpenFlags;
+use libkernel::memory::address::TUA;
 
 pub const CLOSE_ON_EXEC: i32 = 0x80000;
 pub const NONBLOCK: i32 = 0x800;
@@ -18,11 +24,16 @@ pub async fn sys_socket(
     type_: i32,
     protocol: i32,
 ) -> libkernel::error::Result<usize> {
-    let _close_on_exec = (type_ & CLOSE_ON_EXEC) != 0;
+    let fd_flags = if (type_ & CLOSE_ON_EXEC) != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
     let _nonblock = (type_ & NONBLOCK) != 0;
     // Mask out flags
     let type_ = type_ & !(CLOSE_ON_EXEC | NONBLOCK);
     let new_socket: Box<dyn FileOps> = match (domain, type_, protocol) {
+        #[cfg(feature = "net")]
         (AF_INET, SOCK_STREAM, 0) | (AF_INET, SOCK_STREAM, IPPROTO_TCP) => {
             Box::new(TcpSocket::new())
         }
@@ -32,11 +43,65 @@ pub async fn sys_socket(
         _ => return Err(KernelError::AddressFamilyNotSupported),
     };
     // TODO: Correct flags
-    let open_file = OpenFile::new(new_socket, OpenFlags::empty());
+    let open_file = OpenFile::new(new_socket, OpenFlags::O_RDWR);
     let fd = ctx
         .shared()
         .fd_table
         .lock_save_irq()
-        .insert(Arc::new(open_file))?;
+        .insert_with_flags(Arc::new(open_file), fd_flags)?;
     Ok(fd.as_raw() as usize)
 }
+
+/// Creates a pair of connected Unix sockets.
+pub async fn sys_socketpair(
+    ctx: &ProcessCtx,
+    domain: i32,
+    type_: i32,
+    _protocol: i32,
+    sv: TUA<[Fd; 2]>,
+) -> libkernel::error::Result<usize> {
+    let fd_flags = if (type_ & CLOSE_ON_EXEC) != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+    let _nonblock = (type_ & NONBLOCK) != 0;
+    let type_ = type_ & !(CLOSE_ON_EXEC | NONBLOCK);
+    let socket = match (domain, type_) {
+        (AF_UNIX, SOCK_STREAM) => UnixSocket::new_stream(),
+        (AF_UNIX, SOCK_DGRAM) => UnixSocket::new_datagram(),
+        (AF_UNIX, SOCK_SEQPACKET) => UnixSocket::new_seqpacket(),
+        (AF_UNIX, _) => return Err(KernelError::InvalidValue),
+        // Only Unix sockets can be paired up.
+        _ => return Err(KernelError::OpNotSupported),
+    };
+    let peer = socket.new_peer();
+
+    let (fd0, fd1) = {
+        let mut fds = ctx.shared().fd_table.lock_save_irq();
+        let fd0 = fds.insert_with_flags(
+            Arc::new(OpenFile::new(Box::new(socket), OpenFlags::O_RDWR)),
+            fd_flags.clone(),
+        )?;
+        let fd1 = match fds.insert_with_flags(
+            Arc::new(OpenFile::new(Box::new(peer), OpenFlags::O_RDWR)),
+            fd_flags,
+        ) {
+            Ok(fd) => fd,
+            Err(e) => {
+                fds.remove(fd0);
+                return Err(e);
+            }
+        };
+        (fd0, fd1)
+    };
+
+    if let Err(e) = copy_to_user(sv, [fd0, fd1]).await {
+        let mut fds = ctx.shared().fd_table.lock_save_irq();
+        fds.remove(fd0);
+        fds.remove(fd1);
+        return Err(e);
+    }
+
+    Ok(0)
+}
@@ -1,4 +1,4 @@
-use crate::fs::open_file::FileCtx;
+use crate::fs::open_file::{FileCtx, OpenFile};
 use crate::kernel::kpipe::KPipe;
 use crate::memory::uaccess::{copy_from_user_slice, copy_to_user_slice};
 use crate::net::sops::{RecvFlags, SendFlags};
@@ -12,6 +12,7 @@ use alloc::vec;
 use alloc::vec::Vec;
 use async_trait::async_trait;
 use core::future::poll_fn;
+use core::pin::Pin;
 use core::task::Poll;
 use core::task::Waker;
 use libkernel::error::{FsError, KernelError, Result};
@@ -23,25 +24,39 @@ struct Message {
 }
 
 #[derive(Clone)]
-enum Inbox {
+enum Queue {
     Pipe(Arc<KPipe>),
     Datagram(Arc<Mutex<VecDeque<Message>>>),
 }
 
+/// Where data sent to a socket waits to be received, along with any files
+/// passed with it.
+#[derive(Clone)]
+struct Inbox {
+    queue: Queue,
+    /// Batches of files sent with `SCM_RIGHTS`, oldest first.
+    rights: Arc<SpinLock<VecDeque<Vec<Arc<OpenFile>>>>>,
+}
+
 impl Inbox {
     fn new(socket_type: SocketType) -> Self {
-        match socket_type {
+        let queue = match socket_type {
             SocketType::Stream | SocketType::SeqPacket => {
-                Inbox::Pipe(Arc::new(KPipe::new().expect("KPipe creation failed")))
+                Queue::Pipe(Arc::new(KPipe::new().expect("KPipe creation failed")))
             }
-            SocketType::Datagram => Inbox::Datagram(Arc::new(Mutex::new(VecDeque::new()))),
+            SocketType::Datagram => Queue::Datagram(Arc::new(Mutex::new(VecDeque::new()))),
+        };
+
+        Inbox {
+            queue,
+            rights: Arc::new(SpinLock::new(VecDeque::new())),
         }
     }
 
     async fn send(&self, origin: SockAddrUn, buf: UA, count: usize) -> Result<usize> {
-        match self {
-            Inbox::Pipe(pipe) => pipe.copy_from_user(buf, count).await,
-            Inbox::Datagram(queue) => {
+        match &self.queue {
+            Queue::Pipe(pipe) => pipe.copy_from_user(buf, count).await,
+            Queue::Datagram(queue) => {
                 let mut data = vec![0u8; count];
                 copy_from_user_slice(buf, &mut data).await?;
                 let msg = Message {
@@ -54,10 +69,29 @@ impl Inbox {
         }
     }
 
+    /// Moves up to `count` bytes out of `kbuf`, as a single message for a
+    /// datagram socket.
+    async fn splice_from(&self, origin: SockAddrUn, kbuf: &KPipe, count: usize) -> Result<usize> {
+        match &self.queue {
+            Queue::Pipe(pipe) => Ok(pipe.splice_from(kbuf, count).await),
+            Queue::Datagram(queue) => {
+                let mut data = vec![0u8; count];
+                let n = kbuf.pop_slice(&mut data).await;
+                data.truncate(n);
+                let msg = Message {
+                    sender: origin,
+                    data,
+                };
+                queue.lock().await.push_back(msg);
+                Ok(n)
+            }
+        }
+    }
+
     async fn recv(&self, buf: UA, count: usize) -> Result<(usize, Option<SockAddrUn>)> {
-        match self {
-            Inbox::Pipe(pipe) => Ok((pipe.copy_to_user(buf, count).await?, None)),
-            Inbox::Datagram(queue) => {
+        match &self.queue {
+            Queue::Pipe(pipe) => Ok((pipe.copy_to_user(buf, count).await?, None)),
+            Queue::Datagram(queue) => {
                 let mut q = queue.lock().await;
                 if let Some(msg) = q.pop_front() {
                     let n = msg.data.len().min(count);
@@ -69,6 +103,34 @@ impl Inbox {
             }
         }
     }
+
+    /// Resolves once a receive wouldn't block. Datagram receives never do.
+    fn read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        match &self.queue {
+            Queue::Pipe(pipe) => {
+                let ready = pipe.read_ready();
+                Box::pin(async move {
+                    ready.await;
+                    Ok(())
+                })
+            }
+            Queue::Datagram(_) => Box::pin(async { Ok(()) }),
+        }
+    }
+
+    /// Resolves once a send wouldn't block.
+    fn write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        match &self.queue {
+            Queue::Pipe(pipe) => {
+                let pipe = pipe.clone();
+                Box::pin(async move {
+                    pipe.write_ready().await;
+                    Ok(())
+                })
+            }
+            Queue::Datagram(_) => Box::pin(async { Ok(()) }),
+        }
+    }
 }
 
 /// Registry mapping Unix socket path bytes to endpoint inbox and listening state
@@ -125,6 +187,32 @@ impl UnixSocket {
         }
     }
 
+    /// Returns the inbox that sends go to, along with the address they come
+    /// from.
+    fn peer(&self) -> Result<(Inbox, SockAddrUn)> {
+        if *self.wr_shutdown.lock_save_irq() {
+            return Err(KernelError::BrokenPipe);
+        }
+        match self.socket_type {
+            SocketType::Stream | SocketType::SeqPacket => {
+                if !*self.connected.lock_save_irq() {
+                    return Err(KernelError::InvalidValue);
+                }
+            }
+            SocketType::Datagram => {}
+        }
+        let Some(peer) = self.peer_inbox.lock_save_irq().clone() else {
+            return Err(KernelError::InvalidValue);
+        };
+        let local_addr = {
+            self.local_addr.lock_save_irq().unwrap_or(SockAddrUn {
+                family: crate::net::AF_UNIX as u16,
+                path: [0; 108],
+            })
+        };
+        Ok((peer, local_addr))
+    }
+
     pub fn new_stream() -> Self {
         Self::new(SocketType::Stream)
     }
@@ -135,6 +223,19 @@ impl UnixSocket {
         Self::new(SocketType::SeqPacket)
     }
 
+    /// Creates a socket of the same type that's connected to this one, as
+    /// for `socketpair`.
+    pub fn new_peer(&self) -> Self {
+        let peer = Self::new(self.socket_type);
+
+        *peer.peer_inbox.lock_save_irq() = Some(self.inbox.clone());
+        *peer.connected.lock_save_irq() = true;
+        *self.peer_inbox.lock_save_irq() = Some(peer.inbox.clone());
+        *self.connected.lock_save_irq() = true;
+
+        peer
+    }
+
     fn path_bytes(saun: &crate::net::SockAddrUn) -> Option<Vec<u8>> {
         // Unix path is a sun_path-like fixed-size buffer which may be null-terminated
         let mut end = saun.path.len();
@@ -207,8 +308,8 @@ impl SocketOps for UnixSocket {
                     *self.connected.lock_save_irq() = true;
 
                     ep.pending.push(server_sock);
-                    // Wake one waiter if present
-                    if let Some(w) = ep.waiters.pop() {
+                    // Wake anyone accepting or polling for the connection.
+                    for w in ep.waiters.drain(..) {
                         w.wake();
                     }
                     Ok(())
@@ -335,29 +436,23 @@ impl SocketOps for UnixSocket {
         if count == 0 {
             return Ok(0);
         }
-        if *self.wr_shutdown.lock_save_irq() {
-            return Err(KernelError::BrokenPipe);
-        }
-        match self.socket_type {
-            SocketType::Stream | SocketType::SeqPacket => {
-                if !*self.connected.lock_save_irq() {
-                    return Err(KernelError::InvalidValue);
-                }
-            }
-            SocketType::Datagram => {}
-        }
-        let Some(peer) = self.peer_inbox.lock_save_irq().clone() else {
-            return Err(KernelError::InvalidValue);
-        };
-        let local_addr = {
-            self.local_addr.lock_save_irq().unwrap_or(SockAddrUn {
-                family: crate::net::AF_UNIX as u16,
-                path: [0; 108],
-            })
-        };
+        let (peer, local_addr) = self.peer()?;
         peer.send(local_addr, buf, count).await
     }
 
+    async fn splice_send(
+        &mut self,
+        _ctx: &mut FileCtx,
+        kbuf: &KPipe,
+        count: usize,
+    ) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
+        let (peer, local_addr) = self.peer()?;
+        peer.splice_from(local_addr, kbuf, count).await
+    }
+
     async fn sendto(
         &mut self,
         _ctx: &mut FileCtx,
@@ -404,6 +499,66 @@ impl SocketOps for UnixSocket {
         Ok(())
     }
 
+    fn poll_recv_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        if !*self.listening.lock_save_irq() {
+            return self.inbox.read_ready();
+        }
+
+        let path = self
+            .local_addr
+            .lock_save_irq()
+            .as_ref()
+            .and_then(UnixSocket::path_bytes);
+
+        Box::pin(poll_fn(move |cx| {
+            let mut reg = endpoints().lock_save_irq();
+            let Some(ep) = path.as_ref().and_then(|path| reg.get_mut(path)) else {
+                return Poll::Ready(Err(KernelError::InvalidValue));
+            };
+
+            if !ep.pending.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+
+            if !ep.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                ep.waiters.push(cx.waker().clone());
+            }
+
+            Poll::Pending
+        }))
+    }
+
+    fn poll_send_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        match &*self.peer_inbox.lock_save_irq() {
+            Some(peer) => peer.write_ready(),
+            None => Box::pin(async { Ok(()) }),
+        }
+    }
+
+    fn send_rights(&self, addr: Option<SockAddr>, files: Vec<Arc<OpenFile>>) -> Result<()> {
+        let inbox = match addr {
+            Some(SockAddr::Un(saun)) => {
+                let Some(path) = UnixSocket::path_bytes(&saun) else {
+                    return Err(KernelError::InvalidValue);
+                };
+                let reg = endpoints().lock_save_irq();
+                let Some(ep) = reg.get(&path) else {
+                    return Err(KernelError::Fs(FsError::NotFound));
+                };
+                ep.inbox.clone()
+            }
+            Some(_) => return Err(KernelError::InvalidValue),
+            None => self.peer()?.0,
+        };
+
+        inbox.rights.lock_save_irq().push_back(files);
+        Ok(())
+    }
+
+    fn recv_rights(&self) -> Option<Vec<Arc<OpenFile>>> {
+        self.inbox.rights.lock_save_irq().pop_front()
+    }
+
     fn as_file(self: Box<Self>) -> Box<dyn crate::fs::fops::FileOps> {
         self
     }
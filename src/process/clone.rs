@@ -1,34 +1,63 @@
 use super::owned::OwnedTask;
 use super::ptrace::{PTrace, TracePoint, ptrace_stop};
-use super::{ITimers, Tid, VmHandle};
+use super::{ITimers, PidRef, VmHandle};
 use super::{
     ctx::Context,
-    thread_group::signal::{AtomicSigSet, SigSet},
+    thread_group::{
+        ProcessState,
+        signal::{AtomicSigSet, SigSet},
+    },
 };
-use crate::memory::uaccess::copy_to_user;
+use crate::memory::{overcommit, uaccess::copy_to_user};
 use crate::sched::sched_task::Work;
 use crate::sched::syscall_ctx::ProcessCtx;
 use crate::{
-    process::{TASK_LIST, Task},
+    process::{TASK_LIST, Task, add_to_task_list},
     sched::{self},
     sync::SpinLock,
 };
 use alloc::boxed::Box;
 use bitflags::bitflags;
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
 use libkernel::memory::address::TUA;
 use libkernel::{
     error::{KernelError, Result},
     memory::address::UA,
+    proc::caps::CapabilitiesFlags,
     sync::waker_set::WakerSet,
 };
 use ringbuf::Arc;
 
 pub static NUM_FORKS: AtomicUsize = AtomicUsize::new(0);
 
+/// Largest value `threads-max` can be set to: tids have to fit in the bits
+/// of a futex word that hold them.
+const THREADS_MAX_LIMIT: usize = 0x3fff_ffff;
+
+/// Most tasks that may exist at once.
+static THREADS_MAX: AtomicUsize = AtomicUsize::new(16384);
+
+/// Returns the value of the `threads-max` sysctl.
+pub fn threads_max() -> usize {
+    THREADS_MAX.load(Ordering::Relaxed)
+}
+
+/// Sets the value of the `threads-max` sysctl. Tasks already running above it
+/// carry on, but no more can be created.
+pub fn set_threads_max(threads_max: usize) -> Result<()> {
+    if !(20..=THREADS_MAX_LIMIT).contains(&threads_max) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    THREADS_MAX.store(threads_max, Ordering::Relaxed);
+
+    Ok(())
+}
+
 bitflags! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy)]
     pub struct CloneFlags: u32 {
+        const CLONE_NEWTIME = 0x80;
         const CLONE_VM = 0x100;
         const CLONE_FS = 0x200;
         const CLONE_FILES = 0x400;
@@ -55,6 +84,10 @@ bitflags! {
     }
 }
 
+/// The bits of `clone()`'s flags that give the signal sent to the parent when
+/// the child exits.
+const CSIGNAL: u32 = 0xff;
+
 pub async fn sys_clone(
     ctx: &ProcessCtx,
     flags: u32,
@@ -63,7 +96,9 @@ pub async fn sys_clone(
     child_tidptr: TUA<u32>,
     tls: usize,
 ) -> Result<usize> {
-    let flags = CloneFlags::from_bits_truncate(flags);
+    // The low byte holds the exit signal, so `CLONE_NEWTIME` can only be asked
+    // for with `unshare()`.
+    let flags = CloneFlags::from_bits_truncate(flags & !CSIGNAL);
 
     let trace_point = if flags.contains(CloneFlags::CLONE_THREAD) {
         TracePoint::Clone
@@ -71,12 +106,17 @@ pub async fn sys_clone(
         TracePoint::Fork
     };
 
+    if TASK_LIST.lock_save_irq().len() >= threads_max() {
+        return Err(KernelError::TryAgain);
+    }
+
     // TODO: differentiate between `TracePoint::Fork`, `TracePoint::Clone` and
     // `TracePoint::VFork`.
     let should_trace_new_tsk = ptrace_stop(ctx, trace_point).await;
 
     let new_task = {
-        let tid = Tid::next_tid();
+        let pid_ref = PidRef::alloc()?;
+        let tid = pid_ref.tid();
 
         let current_task = ctx.task();
 
@@ -90,35 +130,6 @@ pub async fn sys_clone(
             user_ctx.tpid_el0 = tls as _;
         }
 
-        let tg = if flags.contains(CloneFlags::CLONE_THREAD) {
-            if !flags.contains(CloneFlags::CLONE_SIGHAND & CloneFlags::CLONE_VM) {
-                // CLONE_THREAD requires both CLONE_SIGHAND and CLONE_VM to be
-                // set.
-                return Err(KernelError::InvalidValue);
-            }
-            user_ctx.sp_el0 = newsp.value() as _;
-
-            // A new task within this thread group.
-            current_task.process.clone()
-        } else {
-            let tgid_parent = if flags.contains(CloneFlags::CLONE_PARENT) {
-                // Use the parent's parent as the new parent.
-                current_task
-                    .process
-                    .parent
-                    .lock_save_irq()
-                    .clone()
-                    .and_then(|p| p.upgrade())
-                    // We cannot call CLONE_PARENT on the init process (which
-                    // should be the only process which doesn't have a parent).
-                    .ok_or(KernelError::InvalidValue)?
-            } else {
-                current_task.process.clone()
-            };
-
-            tgid_parent.new_child(flags.contains(CloneFlags::CLONE_SIGHAND), tid)
-        };
-
         let vm = if flags.contains(CloneFlags::CLONE_VM) {
             if flags.contains(CloneFlags::CLONE_THREAD) {
                 current_task.vm.clone()
@@ -127,7 +138,16 @@ pub async fn sys_clone(
             }
         } else {
             let proc_vm = current_task.vm.shared_vm();
-            Arc::new(VmHandle::new(proc_vm.lock_save_irq().clone_as_cow()?))
+            // Every fault, `mmap()`, `munmap()` and the like in the caller's
+            // other threads takes this lock too, so holding it for the copy
+            // gives the child the address space as it was at a single point
+            // in time, rather than half-way through one of those.
+            let mut proc_vm = proc_vm.lock_save_irq();
+
+            // The child's private writable pages are committed all over again.
+            let _reservation = overcommit::reserve(proc_vm.committed())?;
+
+            Arc::new(VmHandle::new(proc_vm.clone_as_cow()?))
         };
 
         let files = if flags.contains(CloneFlags::CLONE_FILES) {
@@ -156,6 +176,74 @@ pub async fn sys_clone(
 
         let creds = current_task.creds.lock_save_irq().clone();
 
+        let time_ns = {
+            let time_ns = current_task.time_ns.lock_save_irq();
+
+            // As on Linux, a task sharing its parent's memory can't be in
+            // another time namespace.
+            if time_ns.is_switching()
+                && flags.contains(CloneFlags::CLONE_VM)
+                && !flags.contains(CloneFlags::CLONE_VFORK)
+            {
+                return Err(KernelError::InvalidValue);
+            }
+
+            time_ns.for_child()
+        };
+
+        let mnt_ns = {
+            let mnt_ns = current_task.mnt_ns.lock_save_irq().clone();
+
+            if flags.contains(CloneFlags::CLONE_NEWNS) {
+                // A working directory and root shared with a task in another
+                // namespace would be resolved through the wrong mounts.
+                if flags.contains(CloneFlags::CLONE_FS) {
+                    return Err(KernelError::InvalidValue);
+                }
+
+                current_task
+                    .creds
+                    .lock_save_irq()
+                    .caps()
+                    .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)?;
+
+                mnt_ns.copy()
+            } else {
+                mnt_ns
+            }
+        };
+
+        // The new process goes in its parent's children last, so that there's
+        // nothing to undo if one of the steps above fails.
+        let tg = if flags.contains(CloneFlags::CLONE_THREAD) {
+            if !flags.contains(CloneFlags::CLONE_SIGHAND & CloneFlags::CLONE_VM) {
+                // CLONE_THREAD requires both CLONE_SIGHAND and CLONE_VM to be
+                // set.
+                return Err(KernelError::InvalidValue);
+            }
+            user_ctx.sp_el0 = newsp.value() as _;
+
+            // A new task within this thread group.
+            current_task.process.clone()
+        } else {
+            let tgid_parent = if flags.contains(CloneFlags::CLONE_PARENT) {
+                // Use the parent's parent as the new parent.
+                current_task
+                    .process
+                    .parent
+                    .lock_save_irq()
+                    .clone()
+                    .and_then(|p| p.upgrade())
+                    // We cannot call CLONE_PARENT on the init process (which
+                    // should be the only process which doesn't have a parent).
+                    .ok_or(KernelError::InvalidValue)?
+            } else {
+                current_task.process.clone()
+            };
+
+            tgid_parent.new_child(flags.contains(CloneFlags::CLONE_SIGHAND), &pid_ref)
+        };
+
         let new_sigmask = AtomicSigSet::new(current_task.sig_mask.load());
 
         let initial_signals = if should_trace_new_tsk {
@@ -178,6 +266,7 @@ pub async fn sys_clone(
             },
             t_shared: Arc::new(Task {
                 tid,
+                pid_ref: Some(pid_ref),
                 comm: Arc::new(SpinLock::new(*current_task.comm.lock_save_irq())),
                 process: tg,
                 vm,
@@ -185,6 +274,8 @@ pub async fn sys_clone(
                 cwd,
                 root,
                 i_timers: SpinLock::new(ITimers::default()),
+                time_ns: SpinLock::new(time_ns),
+                mnt_ns: SpinLock::new(mnt_ns),
                 creds: SpinLock::new(creds),
                 ptrace: SpinLock::new(ptrace),
                 sig_mask: new_sigmask,
@@ -193,6 +284,7 @@ pub async fn sys_clone(
                 utime: AtomicUsize::new(0),
                 stime: AtomicUsize::new(0),
                 last_account: AtomicUsize::new(0),
+                ioprio: AtomicU16::new(current_task.ioprio.load(Ordering::Relaxed)),
             }),
             in_syscall: false,
         }
@@ -204,22 +296,54 @@ pub async fn sys_clone(
 
     let desc = new_task.descriptor();
     let work = Work::new(Box::new(new_task));
+    // The child may run wherever the parent may, isolated CPUs included.
+    *work.cpu_mask.lock_save_irq() = *sched::current_work().cpu_mask.lock_save_irq();
     let vfork_process = flags
         .contains(CloneFlags::CLONE_VFORK)
         .then(|| work.process.clone());
 
-    TASK_LIST
-        .lock_save_irq()
-        .insert(desc.tid(), Arc::downgrade(&work));
+    {
+        // Another of the caller's threads may have called `exit_group()`, or
+        // the process been killed, while we were copying it. Checking under
+        // the state lock orders us with `do_exit_group()`: either it finds
+        // the new task in `tasks` and finishes it, or we see the process going
+        // away and back out, so that no thread or child outlives it.
+        let process = &ctx.shared().process;
+        let state = process.state.lock_save_irq();
+
+        if *state != ProcessState::Running
+            || process
+                .pending_signals
+                .lock_save_irq()
+                .contains(SigSet::SIGKILL)
+        {
+            drop(state);
+
+            if !flags.contains(CloneFlags::CLONE_THREAD)
+                && let Some(parent) = work
+                    .process
+                    .parent
+                    .lock_save_irq()
+                    .as_ref()
+                    .and_then(|parent| parent.upgrade())
+            {
+                parent.children.lock_save_irq().remove(&work.process.tgid);
+            }
+
+            return Err(KernelError::Interrupted);
+        }
 
-    work.process
-        .tasks
-        .lock_save_irq()
-        .insert(desc.tid, Arc::downgrade(&work));
+        add_to_task_list(&work);
+
+        work.process
+            .tasks
+            .lock_save_irq()
+            .insert(desc.tid, Arc::downgrade(&work));
+    }
 
     sched::insert_work_cross_cpu(work);
 
-    NUM_FORKS.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+    NUM_FORKS.fetch_add(1, Ordering::Relaxed);
 
     // Honour CLONE_*SETTID semantics for the parent and (shared-VM) child.
     if flags.contains(CloneFlags::CLONE_PARENT_SETTID) && !parent_tidptr.is_null() {
@@ -235,3 +359,44 @@ pub async fn sys_clone(
 
     Ok(desc.tid.value() as _)
 }
+
+/// Handles `unshare()`. Only `CLONE_NEWTIME`, which takes effect for the
+/// caller's future children, and `CLONE_NEWNS`, which moves the caller into a
+/// copy of its mount namespace, are supported.
+pub fn sys_unshare(ctx: &ProcessCtx, flags: u32) -> Result<usize> {
+    let flags = CloneFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
+
+    if !(CloneFlags::CLONE_NEWTIME | CloneFlags::CLONE_NEWNS).contains(flags) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    if flags.contains(CloneFlags::CLONE_NEWTIME) {
+        let task = ctx.shared();
+
+        task.creds
+            .lock_save_irq()
+            .caps()
+            .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)?;
+
+        task.time_ns.lock_save_irq().unshare();
+    }
+
+    if flags.contains(CloneFlags::CLONE_NEWNS) {
+        let task = ctx.shared();
+
+        task.creds
+            .lock_save_irq()
+            .caps()
+            .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)?;
+
+        let current_ns = task.mnt_ns.lock_save_irq().clone();
+        let new_ns = current_ns.copy();
+        let old_ns = core::mem::replace(&mut *task.mnt_ns.lock_save_irq(), new_ns);
+
+        // Leaving the namespace may tear it down, which mustn't happen under
+        // the task's lock.
+        drop(old_ns);
+    }
+
+    Ok(0)
+}
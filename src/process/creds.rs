@@ -7,6 +7,7 @@ use crate::{
 };
 use libkernel::{
     error::{KernelError, Result},
+    fs::attr::{FileAttr, FilePermissions},
     memory::address::TUA,
     proc::{
         caps::{Capabilities, CapabilitiesFlags},
@@ -68,6 +69,76 @@ impl Credentials {
     pub fn caps(&self) -> Capabilities {
         self.caps
     }
+
+    /// Applies the set-user-ID and set-group-ID bits of a program being
+    /// executed, whose file has the attributes `attr`.
+    pub fn exec_set_ids(&mut self, attr: &FileAttr) {
+        let was_root = self.euid.is_root();
+
+        if attr.permissions.contains(FilePermissions::S_ISUID) {
+            self.euid = attr.uid;
+        }
+
+        // Without group execute permission, set-group-ID marks a file for
+        // mandatory locking instead.
+        if attr
+            .permissions
+            .contains(FilePermissions::S_ISGID | FilePermissions::S_IXGRP)
+        {
+            self.egid = attr.gid;
+        }
+
+        self.suid = self.euid;
+        self.sgid = self.egid;
+
+        // As on Linux, a set-user-ID-root program gets every capability the
+        // bounding set allows.
+        if self.euid.is_root() && !was_root {
+            let bounding = self.caps.bounding();
+
+            self.caps = Capabilities::new(
+                bounding,
+                bounding,
+                self.caps.inheritable(),
+                self.caps.ambient(),
+                bounding,
+            );
+        }
+    }
+
+    /// Returns the credentials an `access()`-style check runs with when it
+    /// asks about the real IDs rather than the effective ones.
+    ///
+    /// As on Linux, the real IDs stand in for the effective ones and the
+    /// capabilities follow the real user: one that isn't root has none, while
+    /// root has everything it's permitted.
+    pub fn for_real_access(&self) -> Self {
+        let effective = if self.uid.is_root() {
+            self.caps.permitted()
+        } else {
+            CapabilitiesFlags::empty()
+        };
+
+        Self {
+            euid: self.uid,
+            egid: self.gid,
+            caps: Capabilities::new(
+                effective,
+                self.caps.permitted(),
+                self.caps.inheritable(),
+                self.caps.ambient(),
+                self.caps.bounding(),
+            ),
+            ..self.clone()
+        }
+    }
+
+    /// Returns whether a program running with these credentials mustn't trust
+    /// its environment, as its effective IDs differ from the real ones. This
+    /// is passed to it as `AT_SECURE`.
+    pub fn is_secure_exec(&self) -> bool {
+        self.euid != self.uid || self.egid != self.gid
+    }
 }
 
 pub fn sys_getuid(ctx: &ProcessCtx) -> core::result::Result<usize, Infallible> {
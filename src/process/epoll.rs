@@ -10,7 +10,10 @@ use crate::{
     drivers::timer::sleep,
     fs::{fops::FileOps, open_file::OpenFile},
     memory::uaccess::{UserCopyable, copy_from_user, copy_objs_to_user},
-    process::{fd_table::Fd, fd_table::select::PollFlags, thread_group::signal::SigSet},
+    process::{
+        fd_table::{Fd, FdFlags, select::PollFlags},
+        thread_group::signal::SigSet,
+    },
     sched::syscall_ctx::ProcessCtx,
     sync::Mutex,
 };
@@ -76,6 +79,10 @@ impl FileOps for Epoll {
         Err(KernelError::NotSupported)
     }
 
+    fn is_stream(&self) -> bool {
+        true
+    }
+
     fn as_epoll(&mut self) -> Option<&mut dyn EpollOps> {
         Some(self)
     }
@@ -89,9 +96,15 @@ pub async fn sys_epoll_create1(ctx: &ProcessCtx, flags: u32) -> Result<usize> {
     let task = ctx.shared();
     let epoll = Box::new(Epoll::new());
     let file = Arc::new(OpenFile::new(epoll, OpenFlags::empty()));
-    let fd = task.fd_table.lock_save_irq().insert(file)?;
-
-    // We don't implement CLOEXEC yet, but returning success is enough.
+    let fd_flags = if flags != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+    let fd = task
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(file, fd_flags)?;
 
     Ok(fd.as_raw() as usize)
 }
@@ -278,3 +291,18 @@ pub async fn sys_epoll_pwait(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::EpollEvent;
+    use core::mem::{offset_of, size_of};
+    use moss_macros::ktest;
+
+    // `struct epoll_event`, which is only packed on x86-64; arm64 pads it.
+    #[ktest]
+    fn epoll_event_layout() {
+        assert_eq!(size_of::<EpollEvent>(), 16);
+        assert_eq!(offset_of!(EpollEvent, events), 0);
+        assert_eq!(offset_of!(EpollEvent, data), 8);
+    }
+}
@@ -1,32 +1,45 @@
 use crate::ArchImpl;
+use crate::fs::syscalls::at::{AtFlags, resolve_at_start_node, resolve_path_flags};
+use crate::fs::syscalls::close::release_file;
+use crate::process::fd_table::Fd;
 use crate::process::ptrace::{TracePoint, ptrace_stop};
 use crate::process::{Comm, ITimers};
 use crate::sched::syscall_ctx::ProcessCtx;
 use crate::{
     arch::Arch,
-    fs::VFS,
+    drivers::timer::USER_HZ,
+    fs::{MntFlags, VFS},
+    kernel::rand::fill_random_bytes,
     memory::{
+        fault::stack_guard_gap,
         page::ClaimedPage,
         uaccess::{copy_from_user, cstr::UserCStr},
     },
-    process::{ctx::Context, thread_group::signal::SignalActionState},
+    process::{
+        ctx::Context,
+        thread_group::{rsrc_lim::RlimitId, signal::SignalActionState},
+    },
 };
 use alloc::borrow::ToOwned;
-use alloc::{string::String, vec};
+use alloc::{format, string::String, vec};
 use alloc::{string::ToString, sync::Arc, vec::Vec};
-use auxv::{AT_BASE, AT_ENTRY, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM};
+use aslr::LayoutOffsets;
+use auxv::{
+    AT_BASE, AT_CLKTCK, AT_EGID, AT_ENTRY, AT_EUID, AT_EXECFN, AT_FLAGS, AT_GID, AT_HWCAP,
+    AT_HWCAP2, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM, AT_SECURE, AT_UID,
+};
 use core::{ffi::c_char, mem, slice};
 use libkernel::memory::proc_vm::address_space::{UserAddressSpace, VirtualMemory};
 use libkernel::{
-    error::{ExecError, KernelError, Result},
-    fs::{Inode, path::Path},
+    error::{ExecError, FsError, KernelError, Result},
+    fs::{FileType, Inode, path::Path, pathbuf::PathBuf},
     memory::{
         PAGE_SIZE,
         address::{TUA, VA},
         paging::permissions::PtePermissions,
         proc_vm::{
             ProcessVM,
-            memory_map::MemoryMap,
+            memory_map::{MMAP_BASE, MemoryMap},
             vmarea::{VMAPermissions, VMArea, VMAreaKind},
         },
         region::VirtMemoryRegion,
@@ -36,47 +49,56 @@ use object::Endian;
 use object::elf::{ET_DYN, ProgramHeader64};
 use object::{
     LittleEndian,
-    elf::{self, PT_LOAD},
+    elf::{self, PF_X, PT_GNU_STACK, PT_LOAD, PT_PHDR},
     read::elf::{FileHeader, ProgramHeader},
 };
 
+pub mod aslr;
 mod auxv;
 
 const LINKER_BIAS: usize = 0x0000_7000_0000_0000;
 const PROG_BIAS: usize = 0x0000_5000_0000_0000;
 
 const STACK_END: usize = 0x0000_8000_0000_0000;
-const STACK_SZ: usize = 0x2000 * 0x400;
-const STACK_START: usize = STACK_END - STACK_SZ;
+
+/// Initial size of the stack VMA. It grows on demand up to `RLIMIT_STACK`.
+const STACK_INITIAL_SZ: usize = 128 * 1024;
 
 /// Process a set of progream headers from an ELF. Create VMAs for all `PT_LOAD`
 /// segments, optionally applying `bias` to the load address.
 ///
-/// If a VMA was found that contains the headers themselves, the address of the
-/// *VMA* is returned.
+/// Returns the address the program headers, found at `phoff` in the file, are
+/// loaded at: as given by `PT_PHDR`, or else within the `PT_LOAD` segment that
+/// holds them, if any.
 fn process_prog_headers<E: Endian>(
     hdrs: &[ProgramHeader64<E>],
     vmas: &mut Vec<VMArea>,
     bias: Option<usize>,
     elf_file: Arc<dyn Inode>,
     path: &Path,
+    phoff: u64,
     endian: E,
 ) -> Option<VA> {
+    let load_addr = |vaddr: u64| VA::from_value(vaddr as usize + bias.unwrap_or(0));
     let mut hdr_addr = None;
 
     for hdr in hdrs {
-        if hdr.p_type(endian) == PT_LOAD {
-            let mut vma = VMArea::from_pheader(elf_file.clone(), *hdr, endian, bias);
+        match hdr.p_type(endian) {
+            // `PT_PHDR` precedes any `PT_LOAD`.
+            PT_PHDR => hdr_addr = Some(load_addr(hdr.p_vaddr(endian))),
+            PT_LOAD => {
+                let mut vma = VMArea::from_pheader(elf_file.clone(), *hdr, endian, bias);
+                let offset = hdr.p_offset(endian);
 
-            // Find PHDR: Assumption segment with p_offset == 0 contains
-            // headers.
-            if hdr.p_offset.get(endian) == 0 {
-                hdr_addr = Some(vma.region().start_address());
-            }
+                if hdr_addr.is_none() && (offset..offset + hdr.p_filesz(endian)).contains(&phoff) {
+                    hdr_addr = Some(load_addr(hdr.p_vaddr(endian) + (phoff - offset)));
+                }
 
-            vma.set_name(path.as_str());
+                vma.set_name(path.as_str());
 
-            vmas.push(vma);
+                vmas.push(vma);
+            }
+            _ => {}
         }
     }
 
@@ -129,6 +151,14 @@ async fn exec_elf(
         }
     }
 
+    // The stack is only executable if PT_GNU_STACK asks for it.
+    //
+    // PT_GNU_RELRO is left to the dynamic linker, or libc in a static PIE: it
+    // can only be made read-only with `mprotect` once relocations are done.
+    let exec_stack = hdrs
+        .iter()
+        .any(|hdr| hdr.p_type(endian) == PT_GNU_STACK && hdr.p_flags(endian) & PF_X != 0);
+
     // Set up a program bias for PIE.
     let main_bias = if elf.e_type.get(endian) == ET_DYN {
         Some(PROG_BIAS)
@@ -136,21 +166,60 @@ async fn exec_elf(
         None
     };
 
+    // Set-user-ID and set-group-ID programs run as the owner of the file,
+    // unless they're being traced. The new credentials take effect once the
+    // exec is committed, below.
+    let mut creds = ctx.shared().creds.lock_save_irq().clone();
+
+    if !ctx.shared().ptrace.lock_save_irq().is_being_traced() {
+        creds.exec_set_ids(&inode.getattr().await?);
+    }
+
+    let (hwcap, hwcap2) = ArchImpl::hwcaps();
+    let uid: u32 = creds.uid().into();
+    let euid: u32 = creds.euid().into();
+    let gid: u32 = creds.gid().into();
+    let egid: u32 = creds.egid().into();
+
     let mut auxv = vec![
         AT_PHNUM,
         elf.e_phnum.get(endian) as _,
         AT_PHENT,
         elf.e_phentsize(endian) as _,
+        AT_HWCAP,
+        hwcap,
+        AT_HWCAP2,
+        hwcap2,
+        AT_FLAGS,
+        0,
+        AT_CLKTCK,
+        USER_HZ,
+        AT_UID,
+        uid as _,
+        AT_EUID,
+        euid as _,
+        AT_GID,
+        gid as _,
+        AT_EGID,
+        egid as _,
+        AT_SECURE,
+        creds.is_secure_exec() as _,
     ];
 
     let mut vmas = Vec::new();
 
     // Process the binary program headers.
-    if let Some(hdr_addr) =
-        process_prog_headers(hdrs, &mut vmas, main_bias, inode.clone(), path, endian)
-    {
+    if let Some(hdr_addr) = process_prog_headers(
+        hdrs,
+        &mut vmas,
+        main_bias,
+        inode.clone(),
+        path,
+        elf.e_phoff(endian),
+        endian,
+    ) {
         auxv.push(AT_PHDR);
-        auxv.push(hdr_addr.add_bytes(elf.e_phoff(endian) as _).value() as _);
+        auxv.push(hdr_addr.value() as _);
     }
 
     let main_entry = VA::from_value(elf.e_entry(endian) as usize + main_bias.unwrap_or(0));
@@ -170,24 +239,60 @@ async fn exec_elf(
         main_entry
     };
 
+    let offsets = LayoutOffsets::new();
+    let stack_end = STACK_END - offsets.stack;
+
     let mut stack_vma = VMArea::new(
-        VirtMemoryRegion::new(VA::from_value(STACK_START), STACK_SZ),
+        VirtMemoryRegion::new(
+            VA::from_value(stack_end - STACK_INITIAL_SZ),
+            STACK_INITIAL_SZ,
+        ),
         VMAreaKind::Anon,
-        VMAPermissions::rw(),
+        VMAPermissions {
+            execute: exec_stack,
+            ..VMAPermissions::rw()
+        },
     );
 
     stack_vma.set_name("[stack]");
+    stack_vma.set_grows_down(true);
 
     vmas.push(stack_vma);
 
+    let stack_limit = ctx
+        .shared()
+        .process
+        .rsrc_lim
+        .lock_save_irq()
+        .get(RlimitId::STACK)
+        .rlim_cur as usize;
+
     let mut mem_map = MemoryMap::from_vmas(vmas)?;
-    let stack_ptr = setup_user_stack(&mut mem_map, &argv, &envp, auxv)?;
+    mem_map.set_mmap_base(VA::from_value(MMAP_BASE - offsets.mmap));
+    mem_map.set_stack_guard_gap(stack_guard_gap());
+
+    let mut random = [0; 16];
+    fill_random_bytes(&mut random).await;
+
+    let stack_ptr = setup_user_stack(
+        &mut mem_map,
+        stack_end,
+        stack_limit,
+        StackInfo {
+            argv: &argv,
+            envp: &envp,
+            execfn: path.as_str(),
+            random: &random,
+        },
+        auxv,
+    )?;
 
     // We are now committed to the exec.  Inform ptrace.
     ptrace_stop(ctx, TracePoint::Exec).await;
 
     let user_ctx = ArchImpl::new_user_context(entry_addr, stack_ptr);
-    let vm = ProcessVM::from_map(mem_map);
+    let mut vm = ProcessVM::from_map(mem_map);
+    vm.offset_brk(offsets.brk);
     let new_comm = argv.first().map(|s| Comm::new(s.as_str()));
 
     {
@@ -198,6 +303,11 @@ async fn exec_elf(
         }
 
         current_task.ctx = Context::from_user_ctx(user_ctx);
+        *current_task.creds.lock_save_irq() = creds;
+
+        // A fresh image starts out in the time namespace meant for children.
+        let time_ns = current_task.time_ns.lock_save_irq().for_child();
+        *current_task.time_ns.lock_save_irq() = time_ns;
         current_task.vm.replace(vm);
         current_task.vm.activate();
         *current_task.process.signals.lock_save_irq() = SignalActionState::new_default();
@@ -208,9 +318,10 @@ async fn exec_elf(
     ctx.shared().process.complete_vfork();
 
     // Close all the CLOEXEC FDs.
-    let mut fd_table = ctx.shared().fd_table.lock_save_irq().clone();
-    fd_table.close_cloexec_entries().await;
-    *ctx.shared().fd_table.lock_save_irq() = fd_table;
+    let cloexec_files = ctx.shared().fd_table.lock_save_irq().take_cloexec_entries();
+    for file in cloexec_files {
+        let _ = release_file(ctx, file).await;
+    }
     *ctx.shared().process.executable.lock_save_irq() = Some(path.to_owned());
     *ctx.shared().i_timers.lock_save_irq() = ITimers::default();
 
@@ -263,6 +374,10 @@ pub async fn kernel_exec(
     argv: Vec<String>,
     envp: Vec<String>,
 ) -> Result<()> {
+    if VFS.mount_flags(inode.id()).contains(MntFlags::MNT_NOEXEC) {
+        return Err(FsError::PermissionDenied.into());
+    }
+
     let mut buf = [0u8; 4];
     inode.read_at(0, &mut buf).await?;
     if buf == [0x7F, b'E', b'L', b'F'] {
@@ -274,46 +389,66 @@ pub async fn kernel_exec(
     }
 }
 
+/// What goes on the stack of a new program, besides its auxiliary vector.
+struct StackInfo<'a> {
+    argv: &'a [String],
+    envp: &'a [String],
+    /// The path the program was executed by, for `AT_EXECFN`.
+    execfn: &'a str,
+    /// The bytes `AT_RANDOM` points to.
+    random: &'a [u8; 16],
+}
+
 // Sets up the user stack according to the System V ABI.
 //
 // The stack layout from high addresses to low addresses is:
-// - Argument and Environment strings
+// - The `AT_EXECFN` string
+// - Environment and argument strings
+// - The 16 `AT_RANDOM` bytes
 // - Padding to 16-byte boundary
 // - Auxiliary Vector (auxv)
 // - Environment pointers (envp)
 // - Argument pointers (argv)
 // - Argument count (argc)
 //
-// The final stack pointer will point to `argc`.
-fn setup_user_stack(
+// The final stack pointer will point to `argc`, and is 16-byte aligned.
+fn setup_user_stack<'a>(
     mm: &mut MemoryMap<<ArchImpl as VirtualMemory>::ProcessAddressSpace>,
-    argv: &[String],
-    envp: &[String],
+    stack_end: usize,
+    stack_limit: usize,
+    info: StackInfo<'a>,
     mut auxv: Vec<u64>,
 ) -> Result<VA> {
-    // Calculate the space needed and the virtual addresses for all strings and
-    // pointers.
-    let mut string_addrs = Vec::new();
-    let mut total_string_size = 0;
-
-    // We add strings to the stack from top-down.
-    for s in envp.iter().chain(argv.iter()) {
-        let len = s.len() + 1; // +1 for null terminator
-        total_string_size += len;
-        string_addrs.push(len); // Temporarily store length
-    }
-
-    let mut current_va = STACK_END;
-    for len in string_addrs.iter_mut().rev() {
-        // Now calculate the final virtual address of each string.
-        current_va -= *len;
-        *len = current_va; // Replace length with the VA
-    }
+    // Place the strings and random bytes from the top down, recording where
+    // each ends up. Strings are NUL terminated by the zeroed stack image.
+    let mut data: Vec<(usize, &'a [u8])> = Vec::new();
+    let mut top = stack_end;
+    let mut push = |bytes: &'a [u8], pad: usize| {
+        top -= bytes.len() + pad;
+        data.push((top, bytes));
+        top
+    };
 
-    let (envp_addrs, argv_addrs) = string_addrs.split_at(envp.len());
+    let execfn_addr = push(info.execfn.as_bytes(), 1);
+    let mut envp_addrs: Vec<usize> = info
+        .envp
+        .iter()
+        .rev()
+        .map(|s| push(s.as_bytes(), 1))
+        .collect();
+    let mut argv_addrs: Vec<usize> = info
+        .argv
+        .iter()
+        .rev()
+        .map(|s| push(s.as_bytes(), 1))
+        .collect();
+    let random_addr = push(info.random, 0);
+
+    envp_addrs.reverse();
+    argv_addrs.reverse();
 
     let mut info_block = Vec::<u64>::new();
-    info_block.push(argv.len() as u64); // argc
+    info_block.push(info.argv.len() as u64); // argc
     info_block.extend(argv_addrs.iter().map(|&addr| addr as u64));
     info_block.push(0); // Null terminator for argv
     info_block.extend(envp_addrs.iter().map(|&addr| addr as u64));
@@ -323,8 +458,9 @@ fn setup_user_stack(
     auxv.push(AT_PAGESZ);
     auxv.push(PAGE_SIZE as u64);
     auxv.push(AT_RANDOM);
-    // TODO: SECURITY: Actually make this a random value.
-    auxv.push(STACK_END as u64 - 0x10);
+    auxv.push(random_addr as u64);
+    auxv.push(AT_EXECFN);
+    auxv.push(execfn_addr as u64);
     auxv.push(AT_NULL);
     auxv.push(0);
 
@@ -332,34 +468,33 @@ fn setup_user_stack(
 
     let info_block_size = info_block.len() * mem::size_of::<u64>();
 
-    // The top of the info block must be 16-byte aligned. The stack pointer on
-    // entry to the new process must also be 16-byte aligned.
-    let strings_base_va = STACK_END - total_string_size;
-    let final_sp_unaligned = strings_base_va - info_block_size;
-    let final_sp_val = final_sp_unaligned & !0xF; // Align down to 16 bytes
+    // The stack pointer on entry to the new process must be 16-byte aligned,
+    // with `argc` at the stack pointer.
+    let final_sp_val = (random_addr - info_block_size) & !0xF;
 
-    let total_stack_size = STACK_END - final_sp_val;
-    if total_stack_size > STACK_SZ {
+    // As on Linux, the arguments and environment may take up to a quarter of
+    // the stack size limit.
+    let total_stack_size = stack_end - final_sp_val;
+    if total_stack_size > stack_limit / 4 {
         return Err(KernelError::TooLarge);
     }
 
+    if total_stack_size > STACK_INITIAL_SZ {
+        mm.expand_stack(VA::from_value(final_sp_val), stack_limit)?;
+    }
+
     let mut stack_image = vec![0u8; total_stack_size];
 
-    // Write strings into the image
-    let mut string_cursor = STACK_END;
-    for s in envp.iter().chain(argv.iter()).rev() {
-        string_cursor -= s.len() + 1;
-        let offset = total_stack_size - (STACK_END - string_cursor);
-        stack_image[offset..offset + s.len()].copy_from_slice(s.as_bytes());
-        // Null terminator is already there from vec![0;...].
+    // Write strings and random bytes into the image
+    for (addr, bytes) in data {
+        let offset = addr - final_sp_val;
+        stack_image[offset..offset + bytes.len()].copy_from_slice(bytes);
     }
 
-    // Write info block into the image
+    // Write info block into the image, at the stack pointer
     let info_block_bytes: &[u8] =
         unsafe { slice::from_raw_parts(info_block.as_ptr().cast(), info_block_size) };
-    let info_block_offset = total_stack_size - (STACK_END - final_sp_val);
-    stack_image[info_block_offset..info_block_offset + info_block_size]
-        .copy_from_slice(info_block_bytes);
+    stack_image[..info_block_size].copy_from_slice(info_block_bytes);
 
     // Allocate pages, copy image, and map into user space
     let num_pages = total_stack_size.div_ceil(PAGE_SIZE);
@@ -377,7 +512,7 @@ fn setup_user_stack(
         page_slice[PAGE_SIZE - image_slice.len()..].copy_from_slice(image_slice);
 
         // Map the page to the correct virtual address
-        let page_va = VA::from_value(STACK_END - (i + 1) * PAGE_SIZE);
+        let page_va = VA::from_value(stack_end - (i + 1) * PAGE_SIZE);
         mm.address_space_mut()
             .map_page(page.leak(), page_va, PtePermissions::rw(true))?;
     }
@@ -421,6 +556,7 @@ async fn process_interp(
         Some(LINKER_BIAS),
         interp_inode,
         path,
+        interp_elf.e_phoff(iendian),
         iendian,
     );
 
@@ -429,40 +565,36 @@ async fn process_interp(
     Ok(interp_entry)
 }
 
-pub async fn sys_execve(
-    ctx: &mut ProcessCtx,
-    path: TUA<c_char>,
-    mut usr_argv: TUA<TUA<c_char>>,
-    mut usr_env: TUA<TUA<c_char>>,
-) -> Result<usize> {
-    let task = ctx.shared().clone();
+/// Copies a NULL-terminated array of user strings, such as `argv`.
+async fn copy_user_strings(mut ptrs: TUA<TUA<c_char>>) -> Result<Vec<String>> {
     let mut buf = [0; 1024];
-    let mut argv = Vec::new();
-    let mut envp = Vec::new();
+    let mut strings = Vec::new();
 
     loop {
-        let ptr = copy_from_user(usr_argv).await?;
+        let ptr = copy_from_user(ptrs).await?;
 
         if ptr.is_null() {
             break;
         }
 
         let str = UserCStr::from_ptr(ptr).copy_from_user(&mut buf).await?;
-        argv.push(str.to_string());
-        usr_argv = usr_argv.add_objs(1);
+        strings.push(str.to_string());
+        ptrs = ptrs.add_objs(1);
     }
 
-    loop {
-        let ptr = copy_from_user(usr_env).await?;
-
-        if ptr.is_null() {
-            break;
-        }
+    Ok(strings)
+}
 
-        let str = UserCStr::from_ptr(ptr).copy_from_user(&mut buf).await?;
-        envp.push(str.to_string());
-        usr_env = usr_env.add_objs(1);
-    }
+pub async fn sys_execve(
+    ctx: &mut ProcessCtx,
+    path: TUA<c_char>,
+    usr_argv: TUA<TUA<c_char>>,
+    usr_env: TUA<TUA<c_char>>,
+) -> Result<usize> {
+    let task = ctx.shared().clone();
+    let mut buf = [0; 1024];
+    let argv = copy_user_strings(usr_argv).await?;
+    let envp = copy_user_strings(usr_env).await?;
 
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
     let inode = VFS.resolve_path(path, VFS.root_inode(), &task).await?;
@@ -471,3 +603,54 @@ pub async fn sys_execve(
 
     Ok(0)
 }
+
+/// Like `execve`, but `path` is resolved relative to `dirfd`. With
+/// `AT_EMPTY_PATH` and an empty `path`, the file referred to by `dirfd` is
+/// executed, which allows running programs that have no name in the
+/// filesystem, such as those held in a memfd.
+pub async fn sys_execveat(
+    ctx: &mut ProcessCtx,
+    dirfd: Fd,
+    path: TUA<c_char>,
+    usr_argv: TUA<TUA<c_char>>,
+    usr_env: TUA<TUA<c_char>>,
+    flags: i32,
+) -> Result<usize> {
+    let flags = AtFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
+
+    if flags.intersects(!(AtFlags::AT_EMPTY_PATH | AtFlags::AT_SYMLINK_NOFOLLOW)) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let task = ctx.shared().clone();
+    let mut buf = [0; 1024];
+    let argv = copy_user_strings(usr_argv).await?;
+    let envp = copy_user_strings(usr_env).await?;
+
+    let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
+    let start_node = resolve_at_start_node(ctx, dirfd, path, flags).await?;
+    let inode = resolve_path_flags(dirfd, path, start_node, &task, flags).await?;
+
+    if inode.getattr().await?.file_type == FileType::Symlink {
+        return Err(FsError::Loop.into());
+    }
+
+    // Name the program after the file it came from when executing an fd.
+    let exec_path = if path.as_str().is_empty() {
+        let file = task
+            .fd_table
+            .lock_save_irq()
+            .get_raw(dirfd)
+            .ok_or(KernelError::BadFd)?;
+
+        file.path()
+            .map(|p| p.to_owned())
+            .unwrap_or_else(|| PathBuf::from(format!("/dev/fd/{}", dirfd.as_raw())))
+    } else {
+        path.to_owned()
+    };
+
+    kernel_exec(ctx, &exec_path, inode, argv, envp).await?;
+
+    Ok(0)
+}
@@ -0,0 +1,80 @@
+//! Address space layout randomization for new program images.
+//!
+//! The policy follows Linux's `randomize_va_space` sysctl:
+//!
+//! - `0`: no randomization.
+//! - `1`: randomize the mmap base and the top of the stack.
+//! - `2`: additionally randomize the start of the heap (`brk`).
+
+use crate::kernel::rand::fill_random_bytes_nowait;
+use core::sync::atomic::{AtomicU8, Ordering};
+use libkernel::{
+    error::{KernelError, Result},
+    memory::PAGE_SIZE,
+};
+
+/// The highest supported `randomize_va_space` level.
+const MAX_LEVEL: u8 = 2;
+
+/// How far each region may be shifted. These match arm64 Linux with 4K pages
+/// (18 bits of page-granular entropy).
+const MMAP_RND_RANGE: usize = 1 << 30;
+const STACK_RND_RANGE: usize = 1 << 30;
+const BRK_RND_RANGE: usize = 1 << 30;
+
+static RANDOMIZE_VA_SPACE: AtomicU8 = AtomicU8::new(MAX_LEVEL);
+
+/// Returns the current randomization level.
+pub fn randomize_va_space() -> u8 {
+    RANDOMIZE_VA_SPACE.load(Ordering::Relaxed)
+}
+
+/// Sets the randomization level applied to subsequent `execve` calls.
+pub fn set_randomize_va_space(level: u8) -> Result<()> {
+    if level > MAX_LEVEL {
+        return Err(KernelError::InvalidValue);
+    }
+
+    RANDOMIZE_VA_SPACE.store(level, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Page-aligned offsets applied to the default layout of a new image.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LayoutOffsets {
+    /// Distance below `MMAP_BASE` of the mmap base.
+    pub mmap: usize,
+    /// Distance below the default stack top.
+    pub stack: usize,
+    /// Distance above the end of the image of the heap start.
+    pub brk: usize,
+}
+
+impl LayoutOffsets {
+    /// Draws a fresh set of offsets according to the current policy.
+    pub fn new() -> Self {
+        let level = randomize_va_space();
+        let mut offsets = Self::default();
+
+        if level >= 1 {
+            offsets.mmap = random_offset(MMAP_RND_RANGE);
+            offsets.stack = random_offset(STACK_RND_RANGE);
+        }
+
+        if level >= 2 {
+            offsets.brk = random_offset(BRK_RND_RANGE);
+        }
+
+        offsets
+    }
+}
+
+/// Returns a random page-aligned offset in `0..range`.
+fn random_offset(range: usize) -> usize {
+    let mut buf = [0u8; 8];
+
+    fill_random_bytes_nowait(&mut buf);
+
+    (u64::from_le_bytes(buf) as usize % (range / PAGE_SIZE)) * PAGE_SIZE
+}
@@ -1,10 +1,12 @@
 use super::{
-    TASK_LIST, Task,
+    Task,
     ptrace::{TracePoint, ptrace_stop},
+    remove_from_task_list,
     thread_group::{ProcessState, Tgid, ThreadGroup, signal::SigId, wait::ChildState},
     threading::futex::{self, key::FutexKey},
 };
 use crate::clock::syscalls::itimer::cleanup_itimers;
+use crate::fs::lock::release_process;
 use crate::memory::uaccess::copy_to_user;
 use crate::sched::syscall_ctx::ProcessCtx;
 use crate::sched::{self};
@@ -66,6 +68,10 @@ pub fn do_exit_group(task: &Arc<Task>, exit_code: ChildState) {
     // soon as we are guaranteed not to run in the shared address space again.
     process.complete_vfork();
 
+    // Record locks belong to the process, so they go now rather than whenever
+    // the last reference to its files is dropped.
+    release_process(process.tgid);
+
     // Reparent children to `init`
     {
         let mut our_children = process.children.lock_save_irq();
@@ -85,9 +91,8 @@ pub fn do_exit_group(task: &Arc<Task>, exit_code: ChildState) {
 
     parent.children.lock_save_irq().remove(&process.tgid);
 
-    parent
-        .child_notifiers
-        .child_update(task.descriptor().tgid(), exit_code);
+    let uid = task.creds.lock_save_irq().uid();
+    parent.child_notifiers.child_exit(&process, uid, exit_code);
 
     parent.queue_signal(SigId::SIGCHLD);
 
@@ -141,7 +146,7 @@ pub async fn sys_exit(ctx: &mut ProcessCtx, exit_code: usize) -> Result<usize> {
         .filter(|t| t.upgrade().is_some())
         .count();
 
-    TASK_LIST.lock_save_irq().remove(&task.descriptor().tid());
+    remove_from_task_list(task.descriptor().tid());
 
     if live_tasks <= 1 {
         // We are the last task. This is equivalent to an exit_group. The exit
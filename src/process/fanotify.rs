@@ -0,0 +1,729 @@
+//! fanotify: filesystem-wide notification and on-access permission events.
+//!
+//! A listener marks inodes or whole filesystems with the events it's
+//! interested in. Notification events (`FAN_OPEN`, `FAN_ACCESS`, `FAN_MODIFY`)
+//! are queued and the operation carries on. Permission events
+//! (`FAN_OPEN_PERM`, `FAN_ACCESS_PERM`) block the task performing the
+//! operation until the listener writes back a `FAN_ALLOW` or `FAN_DENY`
+//! response for the file descriptor it was handed.
+//!
+//! Events are only generated for the marked object itself;
+//! `FAN_EVENT_ON_CHILD` is not supported, so listeners wanting to watch a
+//! directory's contents should use a mount or filesystem mark instead. A mount
+//! mark sees what's done through that mount in its own namespace, while a
+//! filesystem mark sees everything done to the filesystem, wherever it's
+//! mounted. Bind mounts of one filesystem in the same namespace share their
+//! marks.
+//!
+//! Each event read comes with a new file descriptor for its object, opened in
+//! the reader with the group's `event_f_flags`. It's the reader's to close, and
+//! is what names a permission event in the response. Once more events are
+//! queued than the group allows, the rest are dropped in favour of a single
+//! `FAN_Q_OVERFLOW` event with no file descriptor; a permission event that
+//! doesn't fit is allowed without asking.
+
+use alloc::{
+    borrow::ToOwned,
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::{ffi::c_char, future::Future, mem::size_of, pin::Pin};
+use libkernel::{
+    error::{FsError, KernelError, Result},
+    fs::{FileType, Inode, OpenFlags, path::Path, pathbuf::PathBuf},
+    memory::address::{TUA, UA},
+    proc::caps::CapabilitiesFlags,
+    sync::condvar::WakeupType,
+};
+
+use crate::{
+    fs::{
+        VFS,
+        dir::DirFile,
+        fops::FileOps,
+        open_file::{FileCtx, OpenFile},
+        reg::RegFile,
+    },
+    memory::uaccess::{UserCopyable, copy_from_user, copy_to_user, cstr::UserCStr},
+    process::fd_table::{Fd, FdFlags},
+    sched::{current_work, syscall_ctx::ProcessCtx},
+    sync::{CondVar, SpinLock},
+};
+
+pub const FAN_ACCESS: u64 = 0x0000_0001;
+pub const FAN_MODIFY: u64 = 0x0000_0002;
+pub const FAN_OPEN: u64 = 0x0000_0020;
+pub const FAN_Q_OVERFLOW: u64 = 0x0000_4000;
+pub const FAN_OPEN_PERM: u64 = 0x0001_0000;
+pub const FAN_ACCESS_PERM: u64 = 0x0002_0000;
+pub const FAN_ONDIR: u64 = 0x4000_0000;
+
+const FAN_PERM_EVENTS: u64 = FAN_OPEN_PERM | FAN_ACCESS_PERM;
+const FAN_ALL_EVENTS: u64 = FAN_ACCESS | FAN_MODIFY | FAN_OPEN | FAN_PERM_EVENTS;
+
+const FAN_CLOEXEC: u32 = 0x0000_0001;
+const FAN_NONBLOCK: u32 = 0x0000_0002;
+const FAN_CLASS_NOTIF: u32 = 0x0000_0000;
+const FAN_CLASS_CONTENT: u32 = 0x0000_0004;
+const FAN_CLASS_PRE_CONTENT: u32 = 0x0000_0008;
+const FAN_CLASS_MASK: u32 = FAN_CLASS_CONTENT | FAN_CLASS_PRE_CONTENT;
+const FAN_UNLIMITED_QUEUE: u32 = 0x0000_0010;
+const FAN_UNLIMITED_MARKS: u32 = 0x0000_0020;
+const FAN_INIT_ALLOWED: u32 =
+    FAN_CLOEXEC | FAN_NONBLOCK | FAN_CLASS_MASK | FAN_UNLIMITED_QUEUE | FAN_UNLIMITED_MARKS;
+
+const FAN_MARK_ADD: u32 = 0x0000_0001;
+const FAN_MARK_REMOVE: u32 = 0x0000_0002;
+const FAN_MARK_DONT_FOLLOW: u32 = 0x0000_0004;
+const FAN_MARK_ONLYDIR: u32 = 0x0000_0008;
+const FAN_MARK_MOUNT: u32 = 0x0000_0010;
+const FAN_MARK_FLUSH: u32 = 0x0000_0080;
+const FAN_MARK_FILESYSTEM: u32 = 0x0000_0100;
+const FAN_MARK_ACTIONS: u32 = FAN_MARK_ADD | FAN_MARK_REMOVE | FAN_MARK_FLUSH;
+const FAN_MARK_ALLOWED: u32 = FAN_MARK_ACTIONS
+    | FAN_MARK_DONT_FOLLOW
+    | FAN_MARK_ONLYDIR
+    | FAN_MARK_MOUNT
+    | FAN_MARK_FILESYSTEM;
+
+const FAN_ALLOW: u32 = 0x01;
+const FAN_DENY: u32 = 0x02;
+const FAN_AUDIT: u32 = 0x10;
+
+const FAN_NOFD: i32 = -1;
+/// How many events a group may queue, without `FAN_UNLIMITED_QUEUE`.
+const FANOTIFY_DEFAULT_MAX_EVENTS: usize = 16384;
+const FANOTIFY_METADATA_VERSION: u8 = 3;
+
+/// Every live fanotify group. Groups are few, so events are matched against
+/// each in turn.
+static GROUPS: SpinLock<Vec<Weak<FanotifyGroup>>> = SpinLock::new(Vec::new());
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct FanotifyEventMetadata {
+    pub event_len: u32,
+    pub vers: u8,
+    pub reserved: u8,
+    pub metadata_len: u16,
+    pub mask: u64,
+    pub fd: i32,
+    pub pid: i32,
+}
+
+unsafe impl UserCopyable for FanotifyEventMetadata {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct FanotifyResponse {
+    pub fd: i32,
+    pub response: u32,
+}
+
+unsafe impl UserCopyable for FanotifyResponse {}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MarkObject {
+    Inode(libkernel::fs::InodeId),
+    Mount(u64),
+    Filesystem(u64),
+}
+
+struct QueuedEvent {
+    id: u64,
+    mask: u64,
+    inode: Arc<dyn Inode>,
+    file_type: FileType,
+    path: Option<PathBuf>,
+    pid: i32,
+}
+
+#[derive(Default)]
+struct FanotifyState {
+    marks: BTreeMap<MarkObject, u64>,
+    queue: VecDeque<QueuedEvent>,
+    /// Permission events that have been read, keyed by the fd handed to the
+    /// listener alongside them.
+    awaiting_response: BTreeMap<i32, u64>,
+    /// Responses written by the listener, keyed by event ID.
+    responses: BTreeMap<u64, u32>,
+    next_event_id: u64,
+    /// Set once the listener has gone away; blocked tasks are let through.
+    released: bool,
+}
+
+struct FanotifyGroup {
+    class: u32,
+    event_f_flags: OpenFlags,
+    /// How many events may be queued, or `None` for no limit.
+    max_events: Option<usize>,
+    state: CondVar<FanotifyState>,
+}
+
+impl FanotifyGroup {
+    fn interest(&self, object: &Arc<dyn Inode>, file_type: FileType, mount: Option<u64>) -> u64 {
+        let id = object.id();
+        let mut mask = 0;
+
+        self.state.update(|s| {
+            let keys = [
+                Some(MarkObject::Inode(id)),
+                mount.map(MarkObject::Mount),
+                Some(MarkObject::Filesystem(id.fs_id())),
+            ];
+
+            for key in keys.into_iter().flatten() {
+                mask |= s.marks.get(&key).copied().unwrap_or(0);
+            }
+            WakeupType::None
+        });
+
+        if file_type == FileType::Directory && mask & FAN_ONDIR == 0 {
+            0
+        } else {
+            mask & FAN_ALL_EVENTS
+        }
+    }
+
+    /// Queues an event, returning its ID, or `None` if the queue is full.
+    fn enqueue(
+        &self,
+        mask: u64,
+        inode: &Arc<dyn Inode>,
+        file_type: FileType,
+        path: Option<&Path>,
+    ) -> Option<u64> {
+        let mut id = None;
+        let pid = current_work().process.tgid.value() as _;
+
+        self.state.update(|s| {
+            let event_id = s.next_event_id;
+            s.next_event_id += 1;
+
+            let mut event = QueuedEvent {
+                id: event_id,
+                mask,
+                inode: inode.clone(),
+                file_type,
+                path: path.map(|p| p.to_owned()),
+                pid,
+            };
+
+            if self.max_events.is_some_and(|max| s.queue.len() >= max) {
+                // Only the one overflow event goes on the end of a full queue.
+                if s.queue.back().is_some_and(|e| e.mask == FAN_Q_OVERFLOW) {
+                    return WakeupType::None;
+                }
+
+                event.mask = FAN_Q_OVERFLOW;
+            } else {
+                id = Some(event_id);
+            }
+
+            s.queue.push_back(event);
+            WakeupType::All
+        });
+
+        id
+    }
+
+    async fn wait_for_response(&self, id: u64) -> u32 {
+        self.state
+            .wait_until(move |s| {
+                if s.released {
+                    Some(FAN_ALLOW)
+                } else {
+                    s.responses.remove(&id)
+                }
+            })
+            .await
+    }
+
+    fn release(&self) {
+        self.state.update(|s| {
+            s.released = true;
+            s.marks.clear();
+            s.queue.clear();
+            s.awaiting_response.clear();
+            WakeupType::All
+        });
+    }
+}
+
+fn live_groups() -> Vec<Arc<FanotifyGroup>> {
+    let mut groups = GROUPS.lock_save_irq();
+    let mut live = Vec::new();
+
+    groups.retain(|g| {
+        if let Some(g) = g.upgrade() {
+            live.push(g);
+            true
+        } else {
+            false
+        }
+    });
+
+    live
+}
+
+/// Delivers `mask` to every interested group. If `perm` is a permission event,
+/// blocks until every group that asked for it has responded.
+async fn send_event(
+    inode: &Arc<dyn Inode>,
+    file_type: FileType,
+    path: Option<&Path>,
+    perm: u64,
+    mask: u64,
+) -> Result<()> {
+    let ns = current_work().mnt_ns.lock_save_irq().clone();
+    let mount = VFS.mount_id(&ns, inode.id());
+
+    for group in live_groups() {
+        let interest = group.interest(inode, file_type, mount);
+        let dir_flag = if file_type == FileType::Directory {
+            FAN_ONDIR
+        } else {
+            0
+        };
+
+        if interest & perm != 0
+            && let Some(id) = group.enqueue(perm | dir_flag, inode, file_type, path)
+            && group.wait_for_response(id).await & FAN_DENY != 0
+        {
+            return Err(KernelError::NotPermitted);
+        }
+
+        if interest & mask != 0 {
+            group.enqueue(mask | dir_flag, inode, file_type, path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Called when a file is opened. Waits on any `FAN_OPEN_PERM` listeners and,
+/// if they allow it, reports `FAN_OPEN`.
+pub async fn check_open(inode: &Arc<dyn Inode>, file_type: FileType, path: &Path) -> Result<()> {
+    send_event(inode, file_type, Some(path), FAN_OPEN_PERM, FAN_OPEN).await
+}
+
+/// Called before file contents are read. Waits on any `FAN_ACCESS_PERM`
+/// listeners.
+pub async fn check_access(inode: &Arc<dyn Inode>) -> Result<()> {
+    send_event(inode, FileType::File, None, FAN_ACCESS_PERM, 0).await
+}
+
+pub async fn notify_access(inode: &Arc<dyn Inode>) {
+    let _ = send_event(inode, FileType::File, None, 0, FAN_ACCESS).await;
+}
+
+pub async fn notify_modify(inode: &Arc<dyn Inode>) {
+    let _ = send_event(inode, FileType::File, None, 0, FAN_MODIFY).await;
+}
+
+pub struct Fanotify {
+    group: Arc<FanotifyGroup>,
+}
+
+impl Fanotify {
+    fn new(class: u32, event_f_flags: OpenFlags, max_events: Option<usize>) -> Self {
+        let group = Arc::new(FanotifyGroup {
+            class,
+            event_f_flags,
+            max_events,
+            state: CondVar::new(FanotifyState::default()),
+        });
+
+        GROUPS.lock_save_irq().push(Arc::downgrade(&group));
+
+        Self { group }
+    }
+
+    fn mark(&mut self, flags: u32, mask: u64, object: MarkObject) -> Result<()> {
+        let mut res = Ok(());
+
+        self.group.state.update(|s| {
+            match flags & FAN_MARK_ACTIONS {
+                FAN_MARK_ADD => *s.marks.entry(object).or_default() |= mask,
+                FAN_MARK_REMOVE => match s.marks.get_mut(&object) {
+                    Some(m) => {
+                        *m &= !mask;
+                        if *m & FAN_ALL_EVENTS == 0 {
+                            s.marks.remove(&object);
+                        }
+                    }
+                    None => res = Err(FsError::NotFound.into()),
+                },
+                _ => unreachable!(),
+            }
+            WakeupType::None
+        });
+
+        res
+    }
+
+    /// Removes every inode mark, or with `FAN_MARK_MOUNT` or
+    /// `FAN_MARK_FILESYSTEM` in `flags`, every mark of that kind.
+    fn flush(&mut self, flags: u32) {
+        self.group.state.update(|s| {
+            s.marks.retain(|k, _| match k {
+                MarkObject::Inode(_) => flags & (FAN_MARK_MOUNT | FAN_MARK_FILESYSTEM) != 0,
+                MarkObject::Mount(_) => flags & FAN_MARK_MOUNT == 0,
+                MarkObject::Filesystem(_) => flags & FAN_MARK_FILESYSTEM == 0,
+            });
+            WakeupType::None
+        });
+    }
+
+    /// Opens a file descriptor for the event's object in the reading task's
+    /// file table. The new file never generates fanotify events itself,
+    /// otherwise a listener reading an `FAN_ACCESS_PERM` file would wait on
+    /// itself.
+    fn open_event_fd(&self, event: &QueuedEvent) -> Result<i32> {
+        if event.mask == FAN_Q_OVERFLOW {
+            return Ok(FAN_NOFD);
+        }
+
+        let flags = self.group.event_f_flags;
+        let ops: Box<dyn FileOps> = match event.file_type {
+            FileType::File => Box::new(RegFile::new_nonotify(event.inode.clone())),
+            FileType::Directory => Box::new(DirFile::new(event.inode.clone())),
+            _ => return Ok(FAN_NOFD),
+        };
+
+        let mut file = OpenFile::new(ops, flags);
+        file.update(event.inode.clone(), event.path.clone().unwrap_or_default());
+
+        let fd_flags = if flags.contains(OpenFlags::O_CLOEXEC) {
+            FdFlags::CLOEXEC
+        } else {
+            FdFlags::empty()
+        };
+
+        let fd = current_work()
+            .fd_table
+            .lock_save_irq()
+            .insert_with_flags(Arc::new(file), fd_flags)?;
+
+        Ok(fd.as_raw())
+    }
+
+    fn respond(&self, id: u64, response: u32) {
+        self.group.state.update(|s| {
+            s.responses.insert(id, response);
+            WakeupType::All
+        });
+    }
+
+    async fn read_impl(&mut self, buf: UA, count: usize, nonblock: bool) -> Result<usize> {
+        const META_LEN: usize = size_of::<FanotifyEventMetadata>();
+
+        if count < META_LEN {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut bytes_read = 0;
+
+        while count - bytes_read >= META_LEN {
+            let event = if bytes_read == 0 && !nonblock {
+                self.group.state.wait_until(|s| s.queue.pop_front()).await
+            } else {
+                let mut event = None;
+                self.group.state.update(|s| {
+                    event = s.queue.pop_front();
+                    WakeupType::None
+                });
+
+                match event {
+                    Some(event) => event,
+                    None if bytes_read == 0 => return Err(KernelError::TryAgain),
+                    None => break,
+                }
+            };
+
+            let is_perm = event.mask & FAN_PERM_EVENTS != 0;
+
+            let fd = match self.open_event_fd(&event) {
+                Ok(fd) => fd,
+                Err(e) => {
+                    // Don't leave the task that caused the event stuck, nor
+                    // let it through unchecked.
+                    if is_perm {
+                        self.respond(event.id, FAN_DENY);
+                    }
+                    return if bytes_read > 0 {
+                        Ok(bytes_read)
+                    } else {
+                        Err(e)
+                    };
+                }
+            };
+
+            let metadata = FanotifyEventMetadata {
+                event_len: META_LEN as u32,
+                vers: FANOTIFY_METADATA_VERSION,
+                reserved: 0,
+                metadata_len: META_LEN as u16,
+                mask: event.mask,
+                fd,
+                pid: event.pid,
+            };
+
+            if let Err(e) =
+                copy_to_user(TUA::from_value(buf.add_bytes(bytes_read).value()), metadata).await
+            {
+                // The listener never saw the fd, so take it back and deny
+                // the event as if the fd had failed to open.
+                if fd != FAN_NOFD {
+                    current_work().fd_table.lock_save_irq().remove(Fd(fd));
+                }
+                if is_perm {
+                    self.respond(event.id, FAN_DENY);
+                }
+                return if bytes_read > 0 {
+                    Ok(bytes_read)
+                } else {
+                    Err(e)
+                };
+            }
+
+            if is_perm {
+                if fd == FAN_NOFD {
+                    self.respond(event.id, FAN_ALLOW);
+                } else {
+                    self.group.state.update(|s| {
+                        s.awaiting_response.insert(fd, event.id);
+                        WakeupType::None
+                    });
+                }
+            }
+
+            bytes_read += META_LEN;
+        }
+
+        Ok(bytes_read)
+    }
+
+    async fn write_impl(&mut self, buf: UA, count: usize) -> Result<usize> {
+        const RESP_LEN: usize = size_of::<FanotifyResponse>();
+
+        if count < RESP_LEN {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let resp: FanotifyResponse = copy_from_user(TUA::from_value(buf.value())).await?;
+        let verdict = resp.response & !FAN_AUDIT;
+
+        if verdict != FAN_ALLOW && verdict != FAN_DENY {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut id = None;
+
+        self.group.state.update(|s| {
+            id = s.awaiting_response.remove(&resp.fd);
+            WakeupType::None
+        });
+
+        self.respond(id.ok_or(FsError::NotFound)?, verdict);
+
+        Ok(RESP_LEN)
+    }
+}
+
+impl Drop for Fanotify {
+    fn drop(&mut self) {
+        self.group.release();
+    }
+}
+
+#[async_trait]
+impl FileOps for Fanotify {
+    async fn read(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        self.read_impl(buf, count, ctx.flags.contains(OpenFlags::O_NONBLOCK))
+            .await
+    }
+
+    async fn readat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        self.read_impl(buf, count, false).await
+    }
+
+    async fn write(&mut self, _ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        self.write_impl(buf, count).await
+    }
+
+    async fn writeat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        self.write_impl(buf, count).await
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let state = self.group.state.clone();
+
+        Box::pin(async move {
+            state
+                .wait_until(|s| (!s.queue.is_empty()).then_some(()))
+                .await;
+            Ok(())
+        })
+    }
+
+    async fn release(&mut self, _ctx: &FileCtx) -> Result<()> {
+        self.group.release();
+        Ok(())
+    }
+
+    fn as_fanotify(&mut self) -> Option<&mut Fanotify> {
+        Some(self)
+    }
+}
+
+pub async fn sys_fanotify_init(ctx: &ProcessCtx, flags: u32, event_f_flags: u32) -> Result<usize> {
+    ctx.shared()
+        .creds
+        .lock_save_irq()
+        .caps()
+        .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)?;
+
+    let class = flags & FAN_CLASS_MASK;
+
+    if flags & !FAN_INIT_ALLOWED != 0 || class == FAN_CLASS_MASK {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let file_flags = if flags & FAN_NONBLOCK != 0 {
+        OpenFlags::O_NONBLOCK
+    } else {
+        OpenFlags::empty()
+    };
+    let fd_flags = if flags & FAN_CLOEXEC != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+
+    let max_events = if flags & FAN_UNLIMITED_QUEUE != 0 {
+        None
+    } else {
+        Some(FANOTIFY_DEFAULT_MAX_EVENTS)
+    };
+
+    let fanotify = Fanotify::new(
+        class,
+        OpenFlags::from_bits_truncate(event_f_flags),
+        max_events,
+    );
+    let file = Arc::new(OpenFile::new(Box::new(fanotify), file_flags));
+    let fd = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(file, fd_flags)?;
+
+    Ok(fd.as_raw() as usize)
+}
+
+pub async fn sys_fanotify_mark(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    flags: u32,
+    mask: u64,
+    dirfd: Fd,
+    pathname: TUA<c_char>,
+) -> Result<usize> {
+    let action = flags & FAN_MARK_ACTIONS;
+
+    if flags & !FAN_MARK_ALLOWED != 0
+        || action.count_ones() != 1
+        || flags & (FAN_MARK_MOUNT | FAN_MARK_FILESYSTEM) == FAN_MARK_MOUNT | FAN_MARK_FILESYSTEM
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    if mask & !(FAN_ALL_EVENTS | FAN_ONDIR) != 0
+        || (action != FAN_MARK_FLUSH && mask & FAN_ALL_EVENTS == 0)
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let task = ctx.shared().clone();
+    let fanotify_file = task
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+
+    let (ops, _) = &mut *fanotify_file.lock().await;
+    let fanotify = ops.as_fanotify().ok_or(KernelError::InvalidValue)?;
+
+    if mask & FAN_PERM_EVENTS != 0 && fanotify.group.class == FAN_CLASS_NOTIF {
+        return Err(KernelError::InvalidValue);
+    }
+
+    if action == FAN_MARK_FLUSH {
+        fanotify.flush(flags);
+        return Ok(0);
+    }
+
+    let dir_inode = || -> Result<Arc<dyn Inode>> {
+        task.fd_table
+            .lock_save_irq()
+            .get_raw(dirfd)
+            .ok_or(KernelError::BadFd)?
+            .inode()
+            .ok_or(KernelError::BadFd)
+    };
+
+    let inode = if pathname.is_null() {
+        if dirfd.is_atcwd() {
+            task.cwd.lock_save_irq().0.clone()
+        } else {
+            dir_inode()?
+        }
+    } else {
+        let mut buf = [0; 1024];
+        let path = Path::new(
+            UserCStr::from_ptr(pathname)
+                .copy_from_user(&mut buf)
+                .await?,
+        );
+
+        let start = if path.is_absolute() {
+            task.root.lock_save_irq().0.clone()
+        } else if dirfd.is_atcwd() {
+            task.cwd.lock_save_irq().0.clone()
+        } else {
+            dir_inode()?
+        };
+
+        if flags & FAN_MARK_DONT_FOLLOW != 0 {
+            VFS.resolve_path_nofollow(path, start, &task).await?
+        } else {
+            VFS.resolve_path(path, start, &task).await?
+        }
+    };
+
+    if flags & FAN_MARK_ONLYDIR != 0 && inode.getattr().await?.file_type != FileType::Directory {
+        return Err(FsError::NotADirectory.into());
+    }
+
+    let object = if flags & FAN_MARK_MOUNT != 0 {
+        let ns = task.mnt_ns.lock_save_irq().clone();
+
+        MarkObject::Mount(VFS.mount_id(&ns, inode.id()).ok_or(FsError::NotFound)?)
+    } else if flags & FAN_MARK_FILESYSTEM != 0 {
+        MarkObject::Filesystem(inode.id().fs_id())
+    } else {
+        MarkObject::Inode(inode.id())
+    };
+
+    fanotify.mark(flags, mask, object)?;
+
+    Ok(0)
+}
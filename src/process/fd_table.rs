@@ -1,5 +1,12 @@
-use crate::{fs::open_file::OpenFile, memory::uaccess::UserCopyable};
+use crate::{
+    fs::open_file::OpenFile, memory::uaccess::UserCopyable,
+    process::thread_group::rsrc_lim::RlimitId, sched::current_work,
+};
 use alloc::{sync::Arc, vec::Vec};
+use core::{
+    ops::RangeInclusive,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use libkernel::error::{FsError, KernelError, Result};
 
 pub mod dup;
@@ -45,13 +52,120 @@ pub struct FileDescriptorEntry {
     flags: FdFlags,
 }
 
-#[derive(Clone)]
+/// A process's file descriptors.
+///
+/// Entries live in a flat array indexed by descriptor, so lookups are a single
+/// index. A bitmap of the open slots lets the lowest free descriptor be found
+/// a word at a time, and the array grows in powers of two, as on Linux.
 pub struct FileDescriptorTable {
     entries: Vec<Option<FileDescriptorEntry>>,
+    /// One bit for each slot in `entries`, set where a descriptor is open.
+    open_fds: Vec<u64>,
+    /// Number of descriptors open in this table.
+    nr_open: usize,
+    /// Every slot below this is known to be in use.
     next_fd_hint: usize,
 }
 
-const MAX_FDS: usize = 8192;
+/// Statistics about a single descriptor table.
+#[derive(Clone, Copy, Debug)]
+pub struct FdTableStats {
+    /// Number of descriptors open.
+    pub open: usize,
+    /// Number of descriptors the table has room for before it has to grow.
+    pub capacity: usize,
+    /// The highest-numbered open descriptor.
+    pub max_fd: Option<Fd>,
+}
+
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// The smallest a table grows to, as on Linux.
+const MIN_CAPACITY: usize = 64;
+
+/// Most descriptors a table can ever hold, whatever `RLIMIT_NOFILE` says. This
+/// is the `nr_open` sysctl.
+pub const NR_OPEN: usize = 1024 * 1024;
+
+/// Returns the number of descriptors the current process may have open, as
+/// set by its `RLIMIT_NOFILE`. Every descriptor must be below this.
+fn nofile_limit() -> usize {
+    let limit = current_work()
+        .process
+        .rsrc_lim
+        .lock_save_irq()
+        .get(RlimitId::NOFILE)
+        .rlim_cur;
+
+    limit.min(NR_OPEN as u64) as usize
+}
+
+/// Largest value `file-max` can be set to.
+const FILE_MAX_LIMIT: usize = isize::MAX as usize;
+
+/// Most file descriptors that may be open at once, across every table.
+static FILE_MAX: AtomicUsize = AtomicUsize::new(65536);
+
+/// Number of file descriptors open across every table.
+static NR_OPEN_FDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the value of the `file-max` sysctl.
+pub fn file_max() -> usize {
+    FILE_MAX.load(Ordering::Relaxed)
+}
+
+/// Sets the value of the `file-max` sysctl. Descriptors already open above it
+/// stay open, but no more can be.
+pub fn set_file_max(file_max: usize) -> Result<()> {
+    if file_max > FILE_MAX_LIMIT {
+        return Err(KernelError::InvalidValue);
+    }
+
+    FILE_MAX.store(file_max, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Returns the number of file descriptors open across every table.
+pub fn nr_open_fds() -> usize {
+    NR_OPEN_FDS.load(Ordering::Relaxed)
+}
+
+/// Accounts for a new file descriptor, failing with `ENFILE` if `file-max` of
+/// them are open already.
+fn charge_fd() -> Result<()> {
+    NR_OPEN_FDS
+        .try_update(Ordering::Relaxed, Ordering::Relaxed, |nr| {
+            (nr < file_max()).then_some(nr + 1)
+        })
+        .map(|_| ())
+        .map_err(|_| FsError::TooManyFilesInSystem.into())
+}
+
+fn uncharge_fds(nr: usize) {
+    NR_OPEN_FDS.fetch_sub(nr, Ordering::Relaxed);
+}
+
+impl Clone for FileDescriptorTable {
+    fn clone(&self) -> Self {
+        // Like Linux, a copy of a table isn't held to `file-max`: no files are
+        // opened.
+        NR_OPEN_FDS.fetch_add(self.len(), Ordering::Relaxed);
+
+        Self {
+            entries: self.entries.clone(),
+            open_fds: self.open_fds.clone(),
+            nr_open: self.nr_open,
+            next_fd_hint: self.next_fd_hint,
+        }
+    }
+}
+
+impl Drop for FileDescriptorTable {
+    fn drop(&mut self) {
+        uncharge_fds(self.len());
+    }
+}
 
 impl Default for FileDescriptorTable {
     fn default() -> Self {
@@ -63,14 +177,24 @@ impl FileDescriptorTable {
     pub fn new() -> Self {
         Self {
             entries: Vec::new(),
+            open_fds: Vec::new(),
+            nr_open: 0,
             next_fd_hint: 0,
         }
     }
 
-    /// Gets the file object associated with a given file descriptor.
+    /// Gets the file object associated with a given file descriptor. `O_PATH`
+    /// descriptors are left out, as they can't be used for I/O; see
+    /// [`Self::get_raw`].
     pub fn get(&self, fd: Fd) -> Option<Arc<OpenFile>> {
+        self.get_raw(fd).filter(|file| !file.is_path_only())
+    }
+
+    /// Gets the file object associated with a given file descriptor, including
+    /// `O_PATH` ones, for callers that only need the file's inode or path.
+    pub fn get_raw(&self, fd: Fd) -> Option<Arc<OpenFile>> {
         self.entries
-            .get(fd.0 as usize)
+            .get(usize::try_from(fd.0).ok()?)
             .and_then(|entry| entry.as_ref())
             .map(|entry| entry.file.clone())
     }
@@ -82,49 +206,98 @@ impl FileDescriptorTable {
 
     /// Inserts a new file into the table with descriptor flags.
     pub fn insert_with_flags(&mut self, file: Arc<OpenFile>, flags: FdFlags) -> Result<Fd> {
-        let fd = self.find_free_fd()?;
+        self.alloc_fd(0, FileDescriptorEntry { file, flags })
+    }
 
-        let entry = FileDescriptorEntry { file, flags };
+    /// Insert the given entry at or above the specified index with descriptor
+    /// flags, returning the file descriptor used. Fails with `EINVAL` if
+    /// `min_fd` isn't below `RLIMIT_NOFILE`.
+    fn insert_above(&mut self, min_fd: Fd, file: Arc<OpenFile>, flags: FdFlags) -> Result<Fd> {
+        let min_fd = usize::try_from(min_fd.0).map_err(|_| KernelError::InvalidValue)?;
+
+        if min_fd >= nofile_limit() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        self.alloc_fd(min_fd, FileDescriptorEntry { file, flags })
+    }
+
+    /// Puts `entry` in the lowest free slot at or above `min_fd`, failing with
+    /// `EMFILE` if there's none below `RLIMIT_NOFILE`.
+    fn alloc_fd(&mut self, min_fd: usize, entry: FileDescriptorEntry) -> Result<Fd> {
+        // Nothing below the hint is free, so there's no need to look there.
+        let start = min_fd.max(self.next_fd_hint);
+        let fd = self.find_free_fd(start);
+
+        if fd >= nofile_limit() {
+            return Err(FsError::TooManyFiles.into());
+        }
+
+        charge_fd()?;
+
+        if min_fd <= self.next_fd_hint {
+            self.next_fd_hint = fd + 1;
+        }
 
         self.insert_at(fd, entry);
 
-        Ok(fd)
+        Ok(Fd(fd as i32))
     }
 
     /// Insert the given entry at the specified index. If there was an entry at
     /// that index `Some(entry)` is returned. Otherwise, `None` is returned.
-    fn insert_at(&mut self, fd: Fd, entry: FileDescriptorEntry) -> Option<FileDescriptorEntry> {
-        let fd_idx = fd.0 as usize;
+    ///
+    /// The caller must have charged for the descriptor if the index was free.
+    fn insert_at(&mut self, fd: usize, entry: FileDescriptorEntry) -> Option<FileDescriptorEntry> {
+        if fd >= self.entries.len() {
+            self.grow(fd);
+        }
+
+        let old = self.entries[fd].replace(entry);
 
-        if fd_idx >= self.entries.len() {
-            // We need to resize the vector to accommodate the new FD.
-            self.entries.resize_with(fd_idx + 1, || None);
+        if old.is_none() {
+            self.open_fds[fd / BITS_PER_WORD] |= 1 << (fd % BITS_PER_WORD);
+            self.nr_open += 1;
         }
 
-        self.entries[fd_idx].replace(entry)
+        old
     }
 
-    /// Insert the given entry at or above the specified index, returning the
-    /// file descriptor used.
-    fn insert_above(&mut self, min_fd: Fd, file: Arc<OpenFile>) -> Result<Fd> {
-        let start_idx = min_fd.0 as usize;
-        let entry = FileDescriptorEntry {
-            file,
-            flags: FdFlags::default(),
-        };
+    /// Grows the table so that it has a slot for `fd`.
+    fn grow(&mut self, fd: usize) {
+        let capacity = (fd + 1).next_power_of_two().max(MIN_CAPACITY);
 
-        for i in start_idx..self.entries.len() {
-            if self.entries[i].is_none() {
-                let fd = Fd(i as i32);
-                self.insert_at(fd, entry);
-                return Ok(fd);
-            }
+        self.entries.resize_with(capacity, || None);
+        self.open_fds.resize(capacity / BITS_PER_WORD, 0);
+    }
+
+    /// Insert the given entry at the specified index, replacing and returning
+    /// any entry already there. Fails with `EBADF` if `fd` isn't below
+    /// `RLIMIT_NOFILE`.
+    fn replace_at(
+        &mut self,
+        fd: Fd,
+        entry: FileDescriptorEntry,
+    ) -> Result<Option<FileDescriptorEntry>> {
+        let fd_idx = usize::try_from(fd.0).map_err(|_| KernelError::BadFd)?;
+
+        if fd_idx >= nofile_limit() {
+            return Err(KernelError::BadFd);
+        }
+
+        if self.get(fd).is_none() {
+            charge_fd()?;
         }
 
-        // No free slot found, so we need to expand the table.
-        let fd = Fd(self.entries.len() as i32);
-        self.entries.push(Some(entry));
-        Ok(fd)
+        Ok(self.insert_at(fd_idx, entry))
+    }
+
+    /// Gets the descriptor flags of `fd`, if it's open.
+    pub fn flags(&self, fd: Fd) -> Option<FdFlags> {
+        self.entries
+            .get(usize::try_from(fd.0).ok()?)
+            .and_then(|entry| entry.as_ref())
+            .map(|entry| entry.flags.clone())
     }
 
     pub fn add_flags(&mut self, fd: Fd, flags: FdFlags) -> Result<()> {
@@ -137,72 +310,98 @@ impl FileDescriptorTable {
         Ok(())
     }
 
+    /// Returns the open descriptors numbered within `range`, lowest first.
+    pub fn open_fds(&self, range: RangeInclusive<usize>) -> Vec<Fd> {
+        self.entries
+            .iter()
+            .enumerate()
+            .skip(*range.start())
+            .take_while(|(i, _)| range.contains(i))
+            .filter(|(_, entry)| entry.is_some())
+            .map(|(i, _)| Fd(i as i32))
+            .collect()
+    }
+
     /// Removes a file descriptor from the table, returning the file if it
     /// existed.
     pub fn remove(&mut self, fd: Fd) -> Option<Arc<OpenFile>> {
-        let fd_idx = fd.0 as usize;
-
-        if let Some(entry) = self.entries.get_mut(fd_idx)
-            && let Some(old_entry) = entry.take()
-        {
-            // Update the hint to speed up the next search.
-            self.next_fd_hint = self.next_fd_hint.min(fd_idx);
-            return Some(old_entry.file);
-        }
+        let fd_idx = usize::try_from(fd.0).ok()?;
+        let old_entry = self.entries.get_mut(fd_idx)?.take()?;
+
+        self.open_fds[fd_idx / BITS_PER_WORD] &= !(1 << (fd_idx % BITS_PER_WORD));
+        self.nr_open -= 1;
 
-        None
+        // Update the hint to speed up the next search.
+        self.next_fd_hint = self.next_fd_hint.min(fd_idx);
+        uncharge_fds(1);
+
+        Some(old_entry.file)
     }
 
-    /// Called during an `execve`; closes all FDs marked with `CLOEXEC` flag.
-    pub async fn close_cloexec_entries(&mut self) {
+    /// Called during an `execve`; removes every descriptor marked `CLOEXEC` in
+    /// one go, so that no other thread sharing the table sees some closed and
+    /// others not. The files are returned for the caller to release once the
+    /// table is unlocked.
+    pub fn take_cloexec_entries(&mut self) -> Vec<Arc<OpenFile>> {
         let fds_to_close = self
             .entries
             .iter()
             .enumerate()
-            .filter_map(|(i, fd)| {
-                if let Some(fd) = fd
-                    && fd.flags.contains(FdFlags::CLOEXEC)
-                {
-                    Some(i)
-                } else {
-                    None
-                }
+            .filter(|(_, entry)| {
+                entry
+                    .as_ref()
+                    .is_some_and(|entry| entry.flags.contains(FdFlags::CLOEXEC))
             })
+            .map(|(i, _)| Fd(i as _))
             .collect::<Vec<_>>();
 
-        for fd in fds_to_close {
-            if let Some(fd) = self.remove(Fd(fd as _))
-                && let Some(file) = Arc::into_inner(fd)
-            {
-                let (ops, ctx) = &mut *file.lock().await;
-                let _ = ops.release(ctx).await;
-            }
-        }
+        fds_to_close
+            .into_iter()
+            .filter_map(|fd| self.remove(fd))
+            .collect()
     }
 
-    /// Finds the lowest-numbered available file descriptor.
-    fn find_free_fd(&mut self) -> Result<Fd> {
-        // Start searching from our hint.
-        for i in self.next_fd_hint..self.entries.len() {
-            if self.entries[i].is_none() {
-                self.next_fd_hint = i + 1;
-                return Ok(Fd(i as i32));
+    /// Finds the lowest-numbered free slot at or above `start`, which may be
+    /// past the end of the table.
+    fn find_free_fd(&self, start: usize) -> usize {
+        let first_word = start / BITS_PER_WORD;
+
+        for (i, &word) in self.open_fds.iter().enumerate().skip(first_word) {
+            // Treat the slots below `start` as taken.
+            let word = if i == first_word {
+                word | ((1 << (start % BITS_PER_WORD)) - 1)
+            } else {
+                word
+            };
+
+            if word != u64::MAX {
+                return i * BITS_PER_WORD + word.trailing_ones() as usize;
             }
         }
 
-        // We didn't find a free slot in the existing capacity
-        let next = self.entries.len();
-
-        if next >= MAX_FDS {
-            Err(FsError::TooManyFiles.into())
-        } else {
-            self.next_fd_hint = next + 1;
-            Ok(Fd(next as i32))
-        }
+        start.max(self.entries.len())
     }
 
     /// Number of file descriptors in use.
     pub fn len(&self) -> usize {
-        self.entries.iter().filter(|e| e.is_some()).count()
+        self.nr_open
+    }
+
+    pub fn stats(&self) -> FdTableStats {
+        let max_fd = self.open_fds.iter().rposition(|&word| word != 0).map(|i| {
+            let bit = BITS_PER_WORD - 1 - self.open_fds[i].leading_zeros() as usize;
+            Fd((i * BITS_PER_WORD + bit) as i32)
+        });
+
+        FdTableStats {
+            open: self.nr_open,
+            capacity: self.entries.len(),
+            max_fd,
+        }
+    }
+
+    /// Returns every open file in the table.
+    pub fn files(&self) -> impl Iterator<Item = &Arc<OpenFile>> {
+        self.entries.iter().flatten().map(|entry| &entry.file)
     }
 }
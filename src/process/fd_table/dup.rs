@@ -1,4 +1,4 @@
-use crate::sched::syscall_ctx::ProcessCtx;
+use crate::{fs::syscalls::close::release_file, sched::syscall_ctx::ProcessCtx};
 use libkernel::{
     error::{KernelError, Result},
     fs::OpenFlags,
@@ -6,27 +6,30 @@ use libkernel::{
 
 use super::{Fd, FdFlags, FileDescriptorEntry};
 
-pub fn dup_fd(ctx: &ProcessCtx, fd: Fd, min_fd: Option<Fd>) -> Result<Fd> {
+/// Duplicates `fd` onto the lowest free descriptor, or the lowest at or above
+/// `min_fd`. The new descriptor gets `flags` as it's inserted, so no other
+/// thread can see it without them.
+pub fn dup_fd(ctx: &ProcessCtx, fd: Fd, min_fd: Option<Fd>, flags: FdFlags) -> Result<Fd> {
     let task = ctx.shared();
     let mut files = task.fd_table.lock_save_irq();
 
-    let file = files.get(fd).ok_or(KernelError::BadFd)?;
+    let file = files.get_raw(fd).ok_or(KernelError::BadFd)?;
 
     let new_fd = match min_fd {
-        Some(min_fd) => files.insert_above(min_fd, file.clone())?,
-        None => files.insert(file.clone())?,
+        Some(min_fd) => files.insert_above(min_fd, file, flags)?,
+        None => files.insert_with_flags(file, flags)?,
     };
 
     Ok(new_fd)
 }
 
 pub fn sys_dup(ctx: &ProcessCtx, fd: Fd) -> Result<usize> {
-    let new_fd = dup_fd(ctx, fd, None)?;
+    let new_fd = dup_fd(ctx, fd, None, FdFlags::empty())?;
 
     Ok(new_fd.as_raw() as _)
 }
 
-pub fn sys_dup3(ctx: &ProcessCtx, oldfd: Fd, newfd: Fd, flags: u32) -> Result<usize> {
+pub async fn sys_dup3(ctx: &ProcessCtx, oldfd: Fd, newfd: Fd, flags: u32) -> Result<usize> {
     if oldfd == newfd {
         return Err(KernelError::InvalidValue);
     }
@@ -38,22 +41,28 @@ pub fn sys_dup3(ctx: &ProcessCtx, oldfd: Fd, newfd: Fd, flags: u32) -> Result<us
         return Err(KernelError::InvalidValue);
     }
 
-    let task = ctx.shared();
-    let mut files = task.fd_table.lock_save_irq();
-
-    let old_file = files.get(oldfd).ok_or(KernelError::BadFd)?;
+    let replaced = {
+        let mut files = ctx.shared().fd_table.lock_save_irq();
+        let old_file = files.get_raw(oldfd).ok_or(KernelError::BadFd)?;
 
-    files.insert_at(
-        newfd,
-        FileDescriptorEntry {
-            file: old_file.clone(),
-            flags: if flags.contains(OpenFlags::O_CLOEXEC) {
-                FdFlags::CLOEXEC
-            } else {
-                FdFlags::empty()
+        files.replace_at(
+            newfd,
+            FileDescriptorEntry {
+                file: old_file,
+                flags: if flags.contains(OpenFlags::O_CLOEXEC) {
+                    FdFlags::CLOEXEC
+                } else {
+                    FdFlags::empty()
+                },
             },
-        },
-    );
+        )?
+    };
+
+    // Replacing a descriptor closes it. Like Linux, errors from closing it
+    // aren't reported.
+    if let Some(entry) = replaced {
+        let _ = release_file(ctx, entry.file).await;
+    }
 
     Ok(newfd.as_raw() as _)
 }
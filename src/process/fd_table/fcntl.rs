@@ -1,29 +1,181 @@
 use super::Fd;
+use crate::fs::lock::{LockKind, LockOwner, set_lock, test_lock};
+use crate::fs::memfd::{SealFlags, as_memfd};
+use crate::fs::open_file::OpenFile;
+use crate::memory::uaccess::{UserCopyable, copy_from_user, copy_to_user};
 use crate::process::fd_table::dup::dup_fd;
 use crate::{process::fd_table::FdFlags, sched::syscall_ctx::ProcessCtx};
 use bitflags::Flags;
 use libkernel::error::{KernelError, Result};
 use libkernel::fs::OpenFlags;
+use libkernel::memory::address::TUA;
 
 const F_DUPFD: u32 = 0; // Duplicate file descriptor.
 const F_GETFD: u32 = 1; // Get file descriptor flags.
 const F_SETFD: u32 = 2; // Set file descriptor flags.
 const F_GETFL: u32 = 3; // Get file status flags.
 const F_SETFL: u32 = 4; // Set file status flags.
+const F_GETLK: u32 = 5; // Get the first lock that blocks a record lock.
+const F_SETLK: u32 = 6; // Set or clear a record lock, without blocking.
+const F_SETLKW: u32 = 7; // Set or clear a record lock, waiting if blocked.
 const F_LINUX_SPECIFIC_BASE: u32 = 1024;
 const F_DUPFD_CLOEXEC: u32 = F_LINUX_SPECIFIC_BASE + 6; // Duplicate file descriptor with FD_CLOEXEC.
+const F_ADD_SEALS: u32 = F_LINUX_SPECIFIC_BASE + 9; // Add seals to a memfd.
+const F_GET_SEALS: u32 = F_LINUX_SPECIFIC_BASE + 10; // Get the seals of a memfd.
+
+const F_RDLCK: i16 = 0;
+const F_WRLCK: i16 = 1;
+const F_UNLCK: i16 = 2;
+
+const SEEK_SET: i16 = 0;
+const SEEK_CUR: i16 = 1;
+const SEEK_END: i16 = 2;
+
+/// `struct flock`, describing a record lock.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Flock {
+    l_type: i16,
+    l_whence: i16,
+    _pad0: u32,
+    l_start: i64,
+    l_len: i64,
+    l_pid: i32,
+    _pad1: u32,
+}
+
+unsafe impl UserCopyable for Flock {}
+
+/// Works out the bytes covered by `fl`, as an inclusive range.
+async fn flock_range(file: &OpenFile, fl: &Flock) -> Result<(u64, u64)> {
+    let base = match fl.l_whence {
+        SEEK_SET => 0,
+        SEEK_CUR => file.lock().await.1.pos as i64,
+        SEEK_END => {
+            let inode = file.inode().ok_or(KernelError::InvalidValue)?;
+            inode.getattr().await?.size as i64
+        }
+        _ => return Err(KernelError::InvalidValue),
+    };
+
+    let start = base
+        .checked_add(fl.l_start)
+        .ok_or(KernelError::InvalidValue)?;
+
+    // A length of zero runs to the end of the file, however far it grows, and a
+    // negative one ends just before `start`.
+    let (start, end) = match fl.l_len {
+        0 => (start, i64::MAX),
+        len if len > 0 => (start, start.saturating_add(len - 1)),
+        len => (
+            start.checked_add(len).ok_or(KernelError::InvalidValue)?,
+            start - 1,
+        ),
+    };
+
+    if start < 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let end = if end == i64::MAX {
+        u64::MAX
+    } else {
+        end as u64
+    };
+
+    Ok((start as u64, end))
+}
+
+async fn record_lock(ctx: &ProcessCtx, fd: Fd, op: u32, arg: TUA<Flock>) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+    let inode = file.inode().ok_or(KernelError::InvalidValue)?;
+    let owner = LockOwner::Process(ctx.shared().process.tgid);
+
+    let mut fl = copy_from_user(arg).await?;
+    let (start, end) = flock_range(&file, &fl).await?;
+
+    let kind = match fl.l_type {
+        F_RDLCK => Some(LockKind::Shared),
+        F_WRLCK => Some(LockKind::Exclusive),
+        F_UNLCK => None,
+        _ => return Err(KernelError::InvalidValue),
+    };
+
+    if op == F_GETLK {
+        let kind = kind.ok_or(KernelError::InvalidValue)?;
+
+        match test_lock(inode.id(), owner, kind, start, end) {
+            Some(lock) => {
+                fl.l_type = match lock.kind {
+                    LockKind::Shared => F_RDLCK,
+                    LockKind::Exclusive => F_WRLCK,
+                };
+                fl.l_whence = SEEK_SET;
+                fl.l_start = lock.start as i64;
+                fl.l_len = if lock.end == u64::MAX {
+                    0
+                } else {
+                    (lock.end - lock.start + 1) as i64
+                };
+                fl.l_pid = match lock.owner {
+                    LockOwner::Process(tgid) => tgid.value() as i32,
+                    LockOwner::File(_) => -1,
+                };
+            }
+            None => fl.l_type = F_UNLCK,
+        }
+
+        copy_to_user(arg, fl).await?;
+        return Ok(0);
+    }
+
+    // A lock needs the file to be open for the matching kind of access.
+    let accmode = file.flags().await & OpenFlags::O_ACCMODE;
+    match kind {
+        Some(LockKind::Shared) if accmode == OpenFlags::O_WRONLY => {
+            return Err(KernelError::BadFd);
+        }
+        Some(LockKind::Exclusive) if accmode == OpenFlags::O_RDONLY => {
+            return Err(KernelError::BadFd);
+        }
+        _ => {}
+    }
+
+    set_lock(inode.id(), owner, kind, start, end, op == F_SETLKW).await?;
+
+    Ok(0)
+}
 
 pub async fn sys_fcntl(ctx: &ProcessCtx, fd: Fd, op: u32, arg: usize) -> Result<usize> {
     let task = ctx.shared();
 
+    // An `O_PATH` descriptor only takes the commands that leave the file alone.
+    if !matches!(op, F_DUPFD | F_DUPFD_CLOEXEC | F_GETFD | F_SETFD | F_GETFL)
+        && task
+            .fd_table
+            .lock_save_irq()
+            .get_raw(fd)
+            .is_some_and(|file| file.is_path_only())
+    {
+        return Err(KernelError::BadFd);
+    }
+
     match op {
-        F_DUPFD => dup_fd(ctx, fd, Some(Fd(arg as i32))).map(|new_fd| new_fd.as_raw() as _),
-        F_DUPFD_CLOEXEC => {
-            let new_fd = dup_fd(ctx, fd, Some(Fd(arg as i32)))?;
-            task.fd_table
-                .lock_save_irq()
-                .add_flags(new_fd, FdFlags::CLOEXEC)?;
-            Ok(new_fd.as_raw() as _)
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            // Anything too big for an fd is over `RLIMIT_NOFILE` anyway.
+            let min_fd = i32::try_from(arg).map_err(|_| KernelError::InvalidValue)?;
+            let flags = if op == F_DUPFD_CLOEXEC {
+                FdFlags::CLOEXEC
+            } else {
+                FdFlags::empty()
+            };
+
+            dup_fd(ctx, fd, Some(Fd(min_fd)), flags).map(|new_fd| new_fd.as_raw() as _)
         }
         F_GETFD => {
             let fds = task.fd_table.lock_save_irq();
@@ -82,6 +234,34 @@ pub async fn sys_fcntl(ctx: &ProcessCtx, fd: Fd, op: u32, arg: usize) -> Result<
             open_fd.set_flags(fl).await;
             Ok(0)
         }
+        F_GETLK | F_SETLK | F_SETLKW => record_lock(ctx, fd, op, TUA::from_value(arg)).await,
+        F_ADD_SEALS | F_GET_SEALS => {
+            let file = task
+                .fd_table
+                .lock_save_irq()
+                .get(fd)
+                .ok_or(KernelError::BadFd)?;
+            let inode = file.inode().ok_or(KernelError::InvalidValue)?;
+            let memfd = as_memfd(&inode).ok_or(KernelError::InvalidValue)?;
+
+            if op == F_GET_SEALS {
+                return Ok(memfd.seals().await.bits() as _);
+            }
+
+            let seals = SealFlags::from_bits(arg as u32).ok_or(KernelError::InvalidValue)?;
+
+            // Sealing requires a writable file description.
+            if !file
+                .flags()
+                .await
+                .intersects(OpenFlags::O_WRONLY | OpenFlags::O_RDWR)
+            {
+                return Err(KernelError::NotPermitted);
+            }
+
+            memfd.add_seals(seals).await?;
+            Ok(0)
+        }
         _ => Err(KernelError::InvalidValue),
     }
 }
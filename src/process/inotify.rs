@@ -16,6 +16,7 @@ use core::{
 use libkernel::{
     error::{FsError, KernelError, Result},
     fs::{FileType, Inode, InodeId, OpenFlags, attr::AccessMode, path::Path},
+    idr::Idr,
     memory::address::{TUA, UA},
 };
 
@@ -125,27 +126,17 @@ fn encode_name(name: &str) -> Vec<u8> {
 
 #[derive(Clone, Copy)]
 struct Watch {
-    wd: i32,
     inode_id: InodeId,
     mask: u32,
 }
 
 #[derive(Default)]
 struct InotifyState {
-    next_wd: i32,
-    watches_by_wd: BTreeMap<i32, Watch>,
+    watches_by_wd: Idr<Watch>,
     wd_by_inode: BTreeMap<InodeId, i32>,
     queue: VecDeque<QueuedEvent>,
 }
 
-impl InotifyState {
-    fn alloc_wd(&mut self) -> i32 {
-        let wd = self.next_wd.max(1);
-        self.next_wd = wd.saturating_add(1);
-        wd
-    }
-}
-
 pub struct Inotify {
     inner: Arc<InotifyInner>,
 }
@@ -230,6 +221,10 @@ impl FileOps for Inotify {
         Err(KernelError::InvalidValue)
     }
 
+    fn is_stream(&self) -> bool {
+        true
+    }
+
     fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
         let inner = self.inner.clone();
         Box::pin(async move {
@@ -270,7 +265,7 @@ impl InotifyInner {
             if let Some(&wd) = state.wd_by_inode.get(&inode_id) {
                 let watch = state
                     .watches_by_wd
-                    .get_mut(&wd)
+                    .get_mut(wd as u32)
                     .ok_or(KernelError::InvalidValue)?;
 
                 if mask_create {
@@ -295,14 +290,17 @@ impl InotifyInner {
 
         let wd = {
             let mut state = self.state.lock().await;
-            let wd = state.alloc_wd();
             let watch = Watch {
-                wd,
                 inode_id,
                 mask: requested,
             };
+            // As on Linux, a removed watch's descriptor isn't reused until the
+            // others have all been handed out.
+            let wd = state
+                .watches_by_wd
+                .alloc_cyclic(1..i32::MAX as u32, watch)
+                .ok_or(FsError::NoSpace)? as i32;
             state.wd_by_inode.insert(inode_id, wd);
-            state.watches_by_wd.insert(wd, watch);
             wd
         };
 
@@ -316,7 +314,7 @@ impl InotifyInner {
             let mut state = self.state.lock().await;
             let watch = state
                 .watches_by_wd
-                .remove(&wd)
+                .remove(wd as u32)
                 .ok_or(KernelError::InvalidValue)?;
             state.wd_by_inode.remove(&watch.inode_id);
             watch.inode_id
@@ -333,8 +331,8 @@ impl InotifyInner {
             let mut state = self.state.lock().await;
             let removed = state
                 .watches_by_wd
-                .values()
-                .map(|watch| (watch.inode_id, watch.wd))
+                .iter()
+                .map(|(wd, watch)| (watch.inode_id, wd as i32))
                 .collect::<Vec<_>>();
             state.watches_by_wd.clear();
             state.wd_by_inode.clear();
@@ -350,7 +348,7 @@ impl InotifyInner {
     async fn enqueue_filtered(&self, wd: i32, mask: u32, cookie: u32, name: Option<&str>) {
         let should_enqueue = {
             let state = self.state.lock().await;
-            let Some(watch) = state.watches_by_wd.get(&wd) else {
+            let Some(watch) = state.watches_by_wd.get(wd as u32) else {
                 return;
             };
 
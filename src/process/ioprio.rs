@@ -0,0 +1,177 @@
+//! I/O priorities, as set with `ioprio_set(2)`.
+//!
+//! A priority packs a class and a level within it the way Linux does. Tasks
+//! start with no class, which counts as best effort, and a child inherits its
+//! parent's priority. The block cache keeps the blocks of more favoured
+//! classes around for longer; levels aren't used yet.
+
+use super::{
+    TASK_LIST, Tid, find_task_by_tid,
+    thread_group::{Pgid, pid::PidT},
+};
+use crate::sched::{self, sched_task::Work, syscall_ctx::ProcessCtx};
+use alloc::{sync::Arc, vec::Vec};
+use core::cmp::Reverse;
+use core::sync::atomic::Ordering;
+use libkernel::{
+    error::{KernelError, Result},
+    proc::{caps::CapabilitiesFlags, ids::Uid},
+};
+
+const IOPRIO_WHO_PROCESS: u32 = 1;
+const IOPRIO_WHO_PGRP: u32 = 2;
+const IOPRIO_WHO_USER: u32 = 3;
+
+const IOPRIO_CLASS_SHIFT: u16 = 13;
+const IOPRIO_LEVEL_MASK: u16 = (1 << IOPRIO_CLASS_SHIFT) - 1;
+
+const IOPRIO_CLASS_NONE: u16 = 0;
+const IOPRIO_CLASS_RT: u16 = 1;
+const IOPRIO_CLASS_BE: u16 = 2;
+const IOPRIO_CLASS_IDLE: u16 = 3;
+
+/// Levels within the real-time and best-effort classes.
+const IOPRIO_NR_LEVELS: u16 = 8;
+
+/// The class of an I/O priority, least favoured first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IoPrioClass {
+    Idle,
+    BestEffort,
+    RealTime,
+}
+
+/// Returns the class of `ioprio`. Having no class means best effort.
+pub fn ioprio_class(ioprio: u16) -> IoPrioClass {
+    match ioprio >> IOPRIO_CLASS_SHIFT {
+        IOPRIO_CLASS_RT => IoPrioClass::RealTime,
+        IOPRIO_CLASS_IDLE => IoPrioClass::Idle,
+        _ => IoPrioClass::BestEffort,
+    }
+}
+
+/// Returns the I/O priority class of the current task.
+pub fn current_ioprio_class() -> IoPrioClass {
+    ioprio_class(sched::current_work().ioprio.load(Ordering::Relaxed))
+}
+
+/// Returns the tasks selected by a `(which, who)` pair.
+fn find_targets(ctx: &ProcessCtx, which: u32, who: PidT) -> Result<Vec<Arc<Work>>> {
+    let task = ctx.shared();
+
+    if who < 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let all_tasks = || -> Vec<Arc<Work>> {
+        TASK_LIST
+            .lock_save_irq()
+            .values()
+            .filter_map(|work| work.upgrade())
+            .collect()
+    };
+
+    match which {
+        IOPRIO_WHO_PROCESS => {
+            let task = if who == 0 {
+                sched::current_work()
+            } else {
+                find_task_by_tid(Tid::from_pid_t(who)).ok_or(KernelError::NoProcess)?
+            };
+
+            Ok(alloc::vec![task])
+        }
+        IOPRIO_WHO_PGRP => {
+            let pgid = if who == 0 {
+                *task.process.pgid.lock_save_irq()
+            } else {
+                Pgid(who as _)
+            };
+
+            Ok(all_tasks()
+                .into_iter()
+                .filter(|t| *t.process.pgid.lock_save_irq() == pgid)
+                .collect())
+        }
+        IOPRIO_WHO_USER => {
+            let uid = if who == 0 {
+                task.creds.lock_save_irq().uid()
+            } else {
+                Uid::new(who as _)
+            };
+
+            Ok(all_tasks()
+                .into_iter()
+                .filter(|t| t.creds.lock_save_irq().uid() == uid)
+                .collect())
+        }
+        _ => Err(KernelError::InvalidValue),
+    }
+}
+
+/// Returns the most favoured I/O priority of the tasks selected by `which`
+/// and `who`.
+pub fn sys_ioprio_get(ctx: &ProcessCtx, which: u32, who: PidT) -> Result<usize> {
+    find_targets(ctx, which, who)?
+        .iter()
+        .map(|task| task.ioprio.load(Ordering::Relaxed))
+        .max_by_key(|&ioprio| (ioprio_class(ioprio), Reverse(ioprio & IOPRIO_LEVEL_MASK)))
+        .map(usize::from)
+        .ok_or(KernelError::NoProcess)
+}
+
+/// Sets the I/O priority of every task selected by `which` and `who`.
+///
+/// The real-time class needs `CAP_SYS_NICE` or `CAP_SYS_ADMIN`, as do tasks
+/// owned by another user. If any task can't be changed, the error is returned
+/// after trying the rest.
+pub fn sys_ioprio_set(ctx: &ProcessCtx, which: u32, who: PidT, ioprio: u32) -> Result<usize> {
+    let ioprio = u16::try_from(ioprio).map_err(|_| KernelError::InvalidValue)?;
+    let level = ioprio & IOPRIO_LEVEL_MASK;
+
+    let (uid, euid, can_sys_nice, can_sys_admin) = {
+        let creds = ctx.shared().creds.lock_save_irq();
+
+        (
+            creds.uid(),
+            creds.euid(),
+            creds.caps().is_capable(CapabilitiesFlags::CAP_SYS_NICE),
+            creds.caps().is_capable(CapabilitiesFlags::CAP_SYS_ADMIN),
+        )
+    };
+
+    match ioprio >> IOPRIO_CLASS_SHIFT {
+        IOPRIO_CLASS_RT if !can_sys_nice && !can_sys_admin => {
+            return Err(KernelError::NotPermitted);
+        }
+        IOPRIO_CLASS_RT | IOPRIO_CLASS_BE if level >= IOPRIO_NR_LEVELS => {
+            return Err(KernelError::InvalidValue);
+        }
+        IOPRIO_CLASS_NONE if level != 0 => return Err(KernelError::InvalidValue),
+        IOPRIO_CLASS_NONE | IOPRIO_CLASS_RT | IOPRIO_CLASS_BE | IOPRIO_CLASS_IDLE => {}
+        _ => return Err(KernelError::InvalidValue),
+    }
+
+    let targets = find_targets(ctx, which, who)?;
+    let mut error = None;
+
+    if targets.is_empty() {
+        return Err(KernelError::NoProcess);
+    }
+
+    for task in targets {
+        let t_uid = task.creds.lock_save_irq().uid();
+
+        if t_uid != uid && t_uid != euid && !can_sys_nice {
+            error = Some(KernelError::NotPermitted);
+            continue;
+        }
+
+        task.ioprio.store(ioprio, Ordering::Relaxed);
+    }
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(0),
+    }
+}
@@ -0,0 +1,78 @@
+//! Kernel threads: tasks with no userspace of their own, which run a single
+//! future in task context, where it's free to sleep.
+
+use super::{add_to_task_list, owned::OwnedTask};
+use crate::{
+    sched::{self, current_work, sched_task::Work},
+    sync::SpinLock,
+};
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::pin::Pin;
+use libkernel::error::Result;
+use log::error;
+
+type KthreadWork = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Kernel threads asked for before the scheduler was up. `None` once it is.
+static EARLY_KTHREADS: SpinLock<Option<Vec<(String, KthreadWork)>>> =
+    SpinLock::new(Some(Vec::new()));
+
+fn start_kthread(name: &str, work: KthreadWork, early: bool) -> Result<()> {
+    let mut task = OwnedTask::create_kernel_thread(name)?;
+
+    task.ctx.put_kernel_work(Box::pin(async move {
+        work.await;
+
+        // Don't fall through to the (nonexistent) userspace.
+        current_work().state.finish();
+    }));
+
+    let desc = task.descriptor();
+    let work = Work::new(Box::new(task));
+
+    add_to_task_list(&work);
+    work.process
+        .tasks
+        .lock_save_irq()
+        .insert(desc.tid, Arc::downgrade(&work));
+
+    if early {
+        // The other CPUs may not be up yet.
+        sched::insert_work(work);
+    } else {
+        sched::insert_work_cross_cpu(work);
+    }
+
+    Ok(())
+}
+
+/// Starts a kernel thread called `name` running `work`. The thread exits once
+/// `work` completes.
+///
+/// This can be called before the scheduler is up, in which case the thread is
+/// started along with it.
+pub fn spawn_kthread(name: &str, work: impl Future<Output = ()> + Send + 'static) -> Result<()> {
+    let work: KthreadWork = Box::pin(work);
+
+    {
+        let mut early = EARLY_KTHREADS.lock_save_irq();
+
+        if let Some(early) = early.as_mut() {
+            early.push((String::from(name), work));
+            return Ok(());
+        }
+    }
+
+    start_kthread(name, work, false)
+}
+
+/// Starts the kernel threads asked for before the scheduler was up.
+pub fn start_early_kthreads() {
+    let early = EARLY_KTHREADS.lock_save_irq().take().unwrap_or_default();
+
+    for (name, work) in early {
+        if let Err(e) = start_kthread(&name, work, true) {
+            error!("Could not start kernel thread {name}: {e}");
+        }
+    }
+}
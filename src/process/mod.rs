@@ -1,4 +1,6 @@
+use crate::clock::timens::TimeNsProxy;
 use crate::drivers::timer::Instant;
+use crate::fs::mnt_ns::MountNamespace;
 use crate::sched::CPU_STAT;
 use crate::sched::sched_task::Work;
 use crate::{
@@ -15,7 +17,7 @@ use alloc::{
     collections::btree_map::BTreeMap,
     sync::{Arc, Weak},
 };
-use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use core::time::Duration;
 use creds::Credentials;
 use fd_table::FileDescriptorTable;
@@ -23,6 +25,7 @@ use libkernel::memory::proc_vm::address_space::{UserAddressSpace, VirtualMemory}
 use libkernel::{
     error::{KernelError, Result},
     fs::{Inode, pathbuf::PathBuf},
+    idr::Idr,
     memory::{
         address::{UA, VA},
         allocators::phys::PageAllocation,
@@ -42,8 +45,11 @@ pub mod ctx;
 pub mod epoll;
 pub mod exec;
 pub mod exit;
+pub mod fanotify;
 pub mod fd_table;
 pub mod inotify;
+pub mod ioprio;
+pub mod kthread;
 pub mod owned;
 pub mod pidfd;
 pub mod prctl;
@@ -52,8 +58,98 @@ pub mod sleep;
 pub mod thread_group;
 pub mod threading;
 
-// the idle process (0) and the init process (1) are allocated manually.
-static NEXT_TID: AtomicU32 = AtomicU32::new(2);
+/// The tids in use. A tid stays allocated for as long as there's a [`PidRef`]
+/// to it.
+static PIDS: SpinLock<Idr<()>> = SpinLock::new(Idr::new());
+
+/// Tids handed out after wrapping around start from here, to stay clear of
+/// the daemons started at boot, as on Linux.
+const RESERVED_TIDS: u32 = 300;
+
+/// Largest value `pid_max` can be set to, as on Linux.
+const PID_MAX_LIMIT: u32 = 4 * 1024 * 1024;
+
+/// One more than the largest tid handed out.
+static PID_MAX: AtomicU32 = AtomicU32::new(32768);
+
+/// Returns the value of the `pid_max` sysctl.
+pub fn pid_max() -> u32 {
+    PID_MAX.load(Ordering::Relaxed)
+}
+
+/// Sets the value of the `pid_max` sysctl. Tids already handed out above it
+/// stay in use.
+pub fn set_pid_max(pid_max: u32) -> Result<()> {
+    if !(RESERVED_TIDS + 1..=PID_MAX_LIMIT).contains(&pid_max) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    PID_MAX.store(pid_max, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// A reference to an allocated tid.
+///
+/// Besides the task it names, its thread group holds one until it's reaped,
+/// and so does anything else that must never find the tid naming a different
+/// task later on, like a pidfd or a `/proc/<pid>` directory. The tid isn't
+/// handed out again until the last reference is gone.
+#[derive(Clone)]
+pub struct PidRef(Arc<PidNumber>);
+
+struct PidNumber(Tid);
+
+impl Drop for PidNumber {
+    fn drop(&mut self) {
+        PIDS.lock_save_irq().remove(self.0.value());
+    }
+}
+
+impl PidRef {
+    /// Allocates an unused tid below `pid_max`, carrying on from the one
+    /// allocated last and wrapping around once `pid_max` is reached. Fails
+    /// with `EAGAIN` if every tid is in use.
+    pub fn alloc() -> Result<Self> {
+        let pid_max = pid_max();
+        // The ids of process groups and sessions outlive their leaders, so
+        // they're passed over as long as they have members.
+        let groups = thread_group::group_ids();
+        let mut pids = PIDS.lock_save_irq();
+        // The idle process (0) and the init process (1) are allocated
+        // manually. As on Linux, only the first pass through the tids starts
+        // below `RESERVED_TIDS`.
+        let min = if pids.cursor() > RESERVED_TIDS {
+            RESERVED_TIDS
+        } else {
+            2
+        };
+
+        let tid = pids
+            .alloc_cyclic_where(min..pid_max, (), |id| !groups.contains(&id))
+            .ok_or(KernelError::TryAgain)?;
+
+        Ok(Self(Arc::new(PidNumber(Tid(tid)))))
+    }
+
+    /// Allocates `tid` itself, for a task created at boot.
+    fn reserve(tid: Tid) -> Self {
+        PIDS.lock_save_irq()
+            .insert(tid.value(), ())
+            .expect("Boot task's tid is already in use");
+
+        Self(Arc::new(PidNumber(tid)))
+    }
+
+    pub fn tid(&self) -> Tid {
+        self.0.0
+    }
+}
+
+/// Returns a reference to the tid of the task `tid` names, if there is one.
+pub fn find_pid_ref(tid: Tid) -> Option<PidRef> {
+    find_task_by_tid(tid).and_then(|task| task.pid_ref.clone())
+}
 
 // Thread Id.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -72,10 +168,6 @@ impl Tid {
         Self(CpuId::this().value() as _)
     }
 
-    pub fn next_tid() -> Self {
-        Self(NEXT_TID.fetch_add(1, Ordering::Relaxed))
-    }
-
     pub fn from_pid_t(pid: PidT) -> Self {
         Self(pid as _)
     }
@@ -225,6 +317,9 @@ pub struct ITimers {
 
 pub struct Task {
     pub tid: Tid,
+    /// Keeps `tid` allocated. The idle tasks' tids aren't allocated, so they
+    /// have none.
+    pub pid_ref: Option<PidRef>,
     pub comm: Arc<SpinLock<Comm>>,
     pub process: Arc<ThreadGroup>,
     pub vm: Arc<VmHandle>,
@@ -232,6 +327,8 @@ pub struct Task {
     pub root: Arc<SpinLock<(Arc<dyn Inode>, PathBuf)>>,
     pub creds: SpinLock<Credentials>,
     pub i_timers: SpinLock<ITimers>,
+    pub time_ns: SpinLock<TimeNsProxy>,
+    pub mnt_ns: SpinLock<Arc<MountNamespace>>,
     pub fd_table: Arc<SpinLock<FileDescriptorTable>>,
     pub ptrace: SpinLock<PTrace>,
     pub sig_mask: AtomicSigSet,
@@ -240,6 +337,8 @@ pub struct Task {
     pub utime: AtomicUsize,
     pub stime: AtomicUsize,
     pub last_account: AtomicUsize,
+    /// The task's I/O priority, see [`ioprio`].
+    pub ioprio: AtomicU16,
 }
 
 impl Task {
@@ -348,7 +447,7 @@ impl Task {
             }
 
             // Try to handle the fault.
-            match handle_demand_fault(proc_vm.clone(), va, access_kind)? {
+            match handle_demand_fault(proc_vm.clone(), va, access_kind, false)? {
                 // Resolved the fault.   Try again
                 FaultResolution::Resolved => continue,
                 FaultResolution::Denied => return Err(KernelError::Fault),
@@ -404,5 +503,30 @@ pub fn find_task_by_tid(tid: Tid) -> Option<Arc<Work>> {
 
 pub static TASK_LIST: SpinLock<BTreeMap<Tid, Weak<Work>>> = SpinLock::new(BTreeMap::new());
 
+/// Bumped whenever a task is added to or removed from `TASK_LIST`, so that
+/// listings of it can be cached.
+static TASK_LIST_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the current generation of `TASK_LIST`.
+pub fn task_list_generation() -> u64 {
+    TASK_LIST_GENERATION.load(Ordering::Acquire)
+}
+
+/// Adds `work` to `TASK_LIST`.
+pub fn add_to_task_list(work: &Arc<Work>) {
+    let mut task_list = TASK_LIST.lock_save_irq();
+
+    task_list.insert(work.task.descriptor().tid(), Arc::downgrade(work));
+    TASK_LIST_GENERATION.fetch_add(1, Ordering::Release);
+}
+
+/// Removes the task `tid` from `TASK_LIST`.
+pub fn remove_from_task_list(tid: Tid) {
+    let mut task_list = TASK_LIST.lock_save_irq();
+
+    task_list.remove(&tid);
+    TASK_LIST_GENERATION.fetch_add(1, Ordering::Release);
+}
+
 unsafe impl Send for Task {}
 unsafe impl Sync for Task {}
@@ -1,25 +1,31 @@
 use super::{
-    Comm, ITimers, Task, Tid, VmHandle,
+    Comm, ITimers, PidRef, Task, Tid, VmHandle,
     creds::Credentials,
     ctx::{Context, UserCtx},
     fd_table::FileDescriptorTable,
     ptrace::PTrace,
     thread_group::{
-        Tgid,
+        Tgid, ThreadGroup,
         builder::ThreadGroupBuilder,
         signal::{AtomicSigSet, SignalActionState},
     },
     threading::RobustListHead,
 };
-use crate::{arch::Arch, fs::DummyInode, sync::SpinLock};
+use crate::{
+    arch::Arch,
+    fs::{DummyInode, mnt_ns::init_mnt_ns},
+    sync::SpinLock,
+};
 use crate::{
     arch::ArchImpl,
+    clock::timens::TimeNsProxy,
     drivers::timer::{Instant, now},
 };
 use alloc::sync::Arc;
 use core::ops::Deref;
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicU16, AtomicUsize};
 use libkernel::{
+    error::Result,
     fs::pathbuf::PathBuf,
     memory::{
         address::{TUA, VA},
@@ -65,6 +71,7 @@ impl OwnedTask {
 
         let task = Task {
             tid: Tid::idle_for_cpu(),
+            pid_ref: None,
             comm: Arc::new(SpinLock::new(Comm::new("idle"))),
             process: thread_group_builder.build(),
             cwd: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
@@ -73,10 +80,13 @@ impl OwnedTask {
             vm: Arc::new(VmHandle::new(vm)),
             fd_table: Arc::new(SpinLock::new(FileDescriptorTable::new())),
             i_timers: SpinLock::new(ITimers::default()),
+            time_ns: SpinLock::new(TimeNsProxy::init()),
+            mnt_ns: SpinLock::new(init_mnt_ns()),
             ptrace: SpinLock::new(PTrace::new()),
             utime: AtomicUsize::new(0),
             stime: AtomicUsize::new(0),
             last_account: AtomicUsize::new(0),
+            ioprio: AtomicU16::new(0),
             pending_signals: AtomicSigSet::empty(),
             signal_notifier: SpinLock::new(WakerSet::new()),
             sig_mask: AtomicSigSet::empty(),
@@ -93,10 +103,15 @@ impl OwnedTask {
     }
 
     pub fn create_init_task() -> Self {
+        let pid_ref = PidRef::reserve(Tid(1));
+
         let task = Task {
             tid: Tid(1),
+            pid_ref: Some(pid_ref.clone()),
             comm: Arc::new(SpinLock::new(Comm::new("init"))),
-            process: ThreadGroupBuilder::new(Tgid::init()).build(),
+            process: ThreadGroupBuilder::new(Tgid::init())
+                .with_pid_ref(pid_ref)
+                .build(),
             cwd: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
             root: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
             creds: SpinLock::new(Credentials::new_root()),
@@ -104,9 +119,12 @@ impl OwnedTask {
                 ProcessVM::empty().expect("Could not create init process's VM"),
             )),
             i_timers: SpinLock::new(ITimers::default()),
+            time_ns: SpinLock::new(TimeNsProxy::init()),
+            mnt_ns: SpinLock::new(init_mnt_ns()),
             fd_table: Arc::new(SpinLock::new(FileDescriptorTable::new())),
             ptrace: SpinLock::new(PTrace::new()),
             last_account: AtomicUsize::new(0),
+            ioprio: AtomicU16::new(0),
             utime: AtomicUsize::new(0),
             stime: AtomicUsize::new(0),
             pending_signals: AtomicSigSet::empty(),
@@ -127,6 +145,57 @@ impl OwnedTask {
         }
     }
 
+    /// Creates a task that only ever runs kernel work, see
+    /// [`spawn_kthread`](super::kthread::spawn_kthread).
+    pub fn create_kernel_thread(name: &str) -> Result<Self> {
+        let pid_ref = PidRef::alloc()?;
+        let tid = pid_ref.tid();
+
+        let mut thread_group_builder = ThreadGroupBuilder::new(Tgid(tid.0))
+            .with_pid_ref(pid_ref.clone())
+            .with_sigstate(Arc::new(SpinLock::new(SignalActionState::new_ignore())));
+
+        if let Some(init) = ThreadGroup::get(Tgid::init()) {
+            thread_group_builder = thread_group_builder.with_parent(init);
+        }
+
+        let task = Task {
+            tid,
+            pid_ref: Some(pid_ref),
+            comm: Arc::new(SpinLock::new(Comm::new(name))),
+            process: thread_group_builder.build(),
+            cwd: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
+            root: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
+            creds: SpinLock::new(Credentials::new_root()),
+            vm: Arc::new(VmHandle::new(ProcessVM::empty()?)),
+            i_timers: SpinLock::new(ITimers::default()),
+            time_ns: SpinLock::new(TimeNsProxy::init()),
+            mnt_ns: SpinLock::new(init_mnt_ns()),
+            fd_table: Arc::new(SpinLock::new(FileDescriptorTable::new())),
+            ptrace: SpinLock::new(PTrace::new()),
+            last_account: AtomicUsize::new(0),
+            ioprio: AtomicU16::new(0),
+            utime: AtomicUsize::new(0),
+            stime: AtomicUsize::new(0),
+            pending_signals: AtomicSigSet::empty(),
+            signal_notifier: SpinLock::new(WakerSet::new()),
+            sig_mask: AtomicSigSet::empty(),
+        };
+
+        Ok(Self {
+            priority: None,
+            ctx: Context::from_user_ctx(<ArchImpl as Arch>::new_user_context(
+                VA::null(),
+                VA::null(),
+            )),
+            robust_list: None,
+            child_tid_ptr: None,
+            t_shared: Arc::new(task),
+            // It never leaves the kernel, so all of its time is system time.
+            in_syscall: true,
+        })
+    }
+
     pub fn priority(&self) -> i8 {
         self.priority
             .unwrap_or_else(|| *self.process.priority.lock_save_irq())
@@ -1,7 +1,8 @@
 use crate::fs::fops::FileOps;
 use crate::fs::open_file::OpenFile;
+use crate::process::fd_table::FdFlags;
 use crate::process::thread_group::pid::PidT;
-use crate::process::{Tid, find_task_by_tid};
+use crate::process::{PidRef, Tid, find_pid_ref, find_task_by_tid};
 use crate::sched::syscall_ctx::ProcessCtx;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
@@ -20,18 +21,30 @@ bitflags! {
 }
 
 pub struct PidFile {
-    _pid: Tid,
-    _flags: PidfdFlags,
+    pid: Tid,
+    flags: PidfdFlags,
+    /// Keeps `pid` from naming another task once this one is gone.
+    _pid_ref: Option<PidRef>,
 }
 
 impl PidFile {
     pub fn new(pid: Tid, flags: PidfdFlags) -> Self {
         Self {
-            _pid: pid,
-            _flags: flags,
+            pid,
+            flags,
+            _pid_ref: find_pid_ref(pid),
         }
     }
 
+    /// The task this pidfd refers to.
+    pub fn pid(&self) -> Tid {
+        self.pid
+    }
+
+    pub fn flags(&self) -> PidfdFlags {
+        self.flags
+    }
+
     pub fn new_open_file(pid: Tid, flags: PidfdFlags) -> Arc<OpenFile> {
         let file = PidFile::new(pid, flags);
         Arc::new(OpenFile::new(
@@ -50,19 +63,34 @@ impl FileOps for PidFile {
     async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
         Err(KernelError::InvalidValue)
     }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn as_pidfd(&mut self) -> Option<&mut PidFile> {
+        Some(self)
+    }
 }
 
 pub async fn sys_pidfd_open(ctx: &ProcessCtx, pid: PidT, flags: u32) -> Result<usize> {
     let pid = Tid::from_pid_t(pid);
     let flags = PidfdFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
-    if !flags.contains(PidfdFlags::PIDFD_THREAD) {
-        // Ensure the pid exists and is a thread group leader.
-        let _ = find_task_by_tid(pid).unwrap();
+    let task = find_task_by_tid(pid).ok_or(KernelError::NoProcess)?;
+
+    if !flags.contains(PidfdFlags::PIDFD_THREAD) && task.tid != Tid::from_tgid(task.process.tgid) {
+        // Without `PIDFD_THREAD`, the pid has to be a thread group leader.
+        return Err(KernelError::InvalidValue);
     }
 
     let file = PidFile::new_open_file(pid, flags);
 
-    let fd = ctx.task().fd_table.lock_save_irq().insert(file)?;
+    // Like Linux, a pidfd is always close-on-exec.
+    let fd = ctx
+        .task()
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(file, FdFlags::CLOEXEC)?;
 
     Ok(fd.as_raw() as _)
 }
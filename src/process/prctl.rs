@@ -116,6 +116,6 @@ pub async fn sys_prctl(ctx: &ProcessCtx, op: i32, arg1: u64, arg2: u64) -> Resul
         PR_GET_SECUREBITS => Ok(0),
         PR_GET_NO_NEW_PRIVS => Ok(0),
         PR_CAP_AMBIENT => pr_cap_ambient(ctx, arg1, arg2).await,
-        _ => todo!("prctl op: {}", op),
+        _ => Err(KernelError::InvalidValue),
     }
 }
@@ -96,6 +96,11 @@ impl PTrace {
         self.state.is_some()
     }
 
+    /// Returns `true` if `tg` is tracing this task.
+    pub fn is_traced_by(&self, tg: &Arc<ThreadGroup>) -> bool {
+        self.is_being_traced() && self.tracer.as_ref().is_some_and(|t| Arc::ptr_eq(t, tg))
+    }
+
     /// Tells ptrace that the task has hit one of the trace points in the
     /// kernel. If tracing is in progress *and* the trace point is active within
     /// `break_points`, `true` is returned and the kernel should yield to allow
@@ -340,6 +345,20 @@ pub async fn sys_ptrace(ctx: &ProcessCtx, op: i32, pid: PidT, addr: UA, data: UA
         }
         PtraceOperation::SetOptions => {
             let opts = PTraceOptions::from_bits_truncate(data.value());
+
+            // Options we can't honour are refused outright, rather than half
+            // applied.
+            if opts.intersects(
+                !(PTraceOptions::PTRACE_O_TRACESYSGOOD
+                    | PTraceOptions::PTRACE_O_TRACECLONE
+                    | PTraceOptions::PTRACE_O_TRACEEXIT
+                    | PTraceOptions::PTRACE_O_TRACEFORK
+                    | PTraceOptions::PTRACE_O_TRACEVFORK
+                    | PTraceOptions::PTRACE_O_TRACEEXEC),
+            ) {
+                return Err(KernelError::InvalidValue);
+            }
+
             let mut ptrace = target_task.ptrace.lock_save_irq();
 
             // Reset to defaults.
@@ -349,7 +368,6 @@ pub async fn sys_ptrace(ctx: &ProcessCtx, op: i32, pid: PidT, addr: UA, data: UA
             for opt in opts.iter() {
                 match opt {
                     PTraceOptions::PTRACE_O_TRACESYSGOOD => ptrace.sysgood = true,
-                    PTraceOptions::PTRACE_O_EXITKILL => todo!(),
                     PTraceOptions::PTRACE_O_TRACECLONE => {
                         ptrace.break_points.insert(TracePoint::Clone);
                     }
@@ -362,7 +380,7 @@ pub async fn sys_ptrace(ctx: &ProcessCtx, op: i32, pid: PidT, addr: UA, data: UA
                     PTraceOptions::PTRACE_O_TRACEEXEC => {
                         ptrace.break_points.insert(TracePoint::Exec);
                     }
-                    _ => todo!(),
+                    _ => unreachable!(),
                 }
             }
 
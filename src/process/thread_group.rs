@@ -1,4 +1,4 @@
-use super::Tid;
+use super::{PidRef, Tid};
 use crate::{
     drivers::fs::cgroup,
     memory::uaccess::UserCopyable,
@@ -9,12 +9,13 @@ use crate::{
     sync::{CondVar, SpinLock},
 };
 use alloc::{
-    collections::btree_map::BTreeMap,
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
     sync::{Arc, Weak},
     vec::Vec,
 };
 use builder::ThreadGroupBuilder;
 use core::sync::atomic::AtomicUsize;
+use core::time::Duration;
 use core::{fmt::Display, sync::atomic::Ordering};
 use libkernel::{fs::pathbuf::PathBuf, sync::condvar::WakeupType};
 use pid::PidT;
@@ -24,6 +25,7 @@ use wait::Notifiers;
 
 pub mod builder;
 pub mod pid;
+pub mod priority;
 pub mod rsrc_lim;
 pub mod signal;
 pub mod umask;
@@ -78,6 +80,17 @@ impl Pgid {
     pub fn value(self) -> u32 {
         self.0
     }
+
+    /// Returns the session this process group belongs to, or `None` if the
+    /// group has no members.
+    pub fn session(self) -> Option<Sid> {
+        TG_LIST
+            .lock_save_irq()
+            .values()
+            .filter_map(|tg| tg.upgrade())
+            .find(|tg| *tg.pgid.lock_save_irq() == self)
+            .map(|tg| *tg.sid.lock_save_irq())
+    }
 }
 
 unsafe impl UserCopyable for Pgid {}
@@ -100,6 +113,9 @@ pub enum ProcessState {
 
 pub struct ThreadGroup {
     pub tgid: Tgid,
+    /// Keeps `tgid` allocated until the process is reaped. The idle process's
+    /// tgid isn't allocated, so it has none.
+    pub pid_ref: Option<PidRef>,
     pub pgid: SpinLock<Pgid>,
     pub sid: SpinLock<Sid>,
     pub state: SpinLock<ProcessState>,
@@ -110,6 +126,9 @@ pub struct ThreadGroup {
     pub signals: Arc<SpinLock<SignalActionState>>,
     pub rsrc_lim: Arc<SpinLock<ResourceLimits>>,
     pub pending_signals: SpinLock<SigSet>,
+    /// The process's nice value, negated. See [`priority_to_nice`].
+    ///
+    /// [`priority_to_nice`]: crate::sched::priority_to_nice
     pub priority: SpinLock<i8>,
     pub child_notifiers: Notifiers,
     /// `true` while a parent is blocked in `CLONE_VFORK` waiting for this
@@ -117,15 +136,25 @@ pub struct ThreadGroup {
     pub vfork_blocked_parent: CondVar<bool>,
     pub utime: AtomicUsize,
     pub stime: AtomicUsize,
+    /// CPU time of children that have exited and been waited for, as
+    /// reported to `wait4()` and `waitid()`.
+    pub cutime: AtomicUsize,
+    pub cstime: AtomicUsize,
     pub last_account: AtomicUsize,
+    /// When the process was created, since boot.
+    pub start_time: Duration,
+    /// When the process was created, since the epoch.
+    pub start_realtime: Duration,
     pub executable: SpinLock<Option<PathBuf>>,
 }
 
 unsafe impl Send for ThreadGroup {}
 
 impl ThreadGroup {
-    pub fn new_child(self: Arc<Self>, share_state: bool, tid: Tid) -> Arc<ThreadGroup> {
-        let mut builder = ThreadGroupBuilder::new(Tgid::from_tid(tid)).with_parent(self.clone());
+    pub fn new_child(self: Arc<Self>, share_state: bool, pid: &PidRef) -> Arc<ThreadGroup> {
+        let mut builder = ThreadGroupBuilder::new(Tgid::from_tid(pid.tid()))
+            .with_pid_ref(pid.clone())
+            .with_parent(self.clone());
 
         if share_state {
             builder = builder
@@ -258,3 +287,22 @@ impl Drop for ThreadGroup {
 }
 
 static TG_LIST: SpinLock<BTreeMap<Tgid, Weak<ThreadGroup>>> = SpinLock::new(BTreeMap::new());
+
+/// Returns the ids of the process groups and sessions that have members left.
+pub(super) fn group_ids() -> BTreeSet<u32> {
+    let groups: Vec<_> = TG_LIST
+        .lock_save_irq()
+        .values()
+        .filter_map(|tg| tg.upgrade())
+        .collect();
+
+    groups
+        .iter()
+        .flat_map(|tg| {
+            [
+                tg.pgid.lock_save_irq().value(),
+                tg.sid.lock_save_irq().value(),
+            ]
+        })
+        .collect()
+}
@@ -3,7 +3,9 @@ use core::sync::atomic::AtomicUsize;
 use alloc::{collections::btree_map::BTreeMap, sync::Arc};
 
 use crate::{
-    drivers::fs::cgroup,
+    clock::realtime,
+    drivers::{fs::cgroup, timer::uptime},
+    process::PidRef,
     sync::{CondVar, SpinLock},
 };
 
@@ -17,6 +19,7 @@ use super::{
 /// A builder for creating ThreadGroup instances.
 pub struct ThreadGroupBuilder {
     tgid: Tgid,
+    pid_ref: Option<PidRef>,
     parent: Option<Arc<ThreadGroup>>,
     umask: Option<u32>,
     pri: Option<i8>,
@@ -29,6 +32,7 @@ impl ThreadGroupBuilder {
     pub fn new(tgid: Tgid) -> Self {
         ThreadGroupBuilder {
             tgid,
+            pid_ref: None,
             parent: None,
             umask: None,
             sigstate: None,
@@ -37,6 +41,12 @@ impl ThreadGroupBuilder {
         }
     }
 
+    /// Sets the reference keeping the thread group's ID allocated.
+    pub fn with_pid_ref(mut self, pid_ref: PidRef) -> Self {
+        self.pid_ref = Some(pid_ref);
+        self
+    }
+
     /// Sets the parent of the thread group.
     pub fn with_parent(mut self, parent: Arc<ThreadGroup>) -> Self {
         self.parent = Some(parent);
@@ -65,6 +75,7 @@ impl ThreadGroupBuilder {
     pub fn build(self) -> Arc<ThreadGroup> {
         let ret = Arc::new(ThreadGroup {
             tgid: self.tgid,
+            pid_ref: self.pid_ref,
             pgid: SpinLock::new(
                 self.parent
                     .as_ref()
@@ -87,7 +98,11 @@ impl ThreadGroupBuilder {
             priority: SpinLock::new(self.pri.unwrap_or(0)),
             utime: AtomicUsize::new(0),
             stime: AtomicUsize::new(0),
+            cutime: AtomicUsize::new(0),
+            cstime: AtomicUsize::new(0),
             last_account: AtomicUsize::new(0),
+            start_time: uptime(),
+            start_realtime: realtime::date(),
             // Don't start from '0'. Since clone expects the parent to return
             // the tid and the child to return '0', if we started from '0' we
             // couldn't then differentiate between a child and a parent.
@@ -0,0 +1,147 @@
+use super::{Pgid, TG_LIST, ThreadGroup, pid::PidT, rsrc_lim::RlimitId};
+use crate::{
+    process::{Tid, find_task_by_tid},
+    sched::{NICE_MAX, NICE_MIN, nice_to_priority, priority_to_nice, syscall_ctx::ProcessCtx},
+};
+use alloc::{sync::Arc, vec::Vec};
+use libkernel::{
+    error::{FsError, KernelError, Result},
+    proc::{caps::CapabilitiesFlags, ids::Uid},
+};
+
+const PRIO_PROCESS: u32 = 0;
+const PRIO_PGRP: u32 = 1;
+const PRIO_USER: u32 = 2;
+
+/// Returns the real and effective user IDs of a process, taken from any of
+/// its live threads.
+fn process_uids(tg: &ThreadGroup) -> Option<(Uid, Uid)> {
+    let task = tg
+        .tasks
+        .lock_save_irq()
+        .values()
+        .find_map(|task| task.upgrade())?;
+
+    let creds = task.creds.lock_save_irq();
+
+    Some((creds.uid(), creds.euid()))
+}
+
+/// Returns the processes selected by a `(which, who)` pair.
+fn find_targets(ctx: &ProcessCtx, which: u32, who: PidT) -> Result<Vec<Arc<ThreadGroup>>> {
+    let task = ctx.shared();
+
+    if who < 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    match which {
+        PRIO_PROCESS => {
+            let tg = if who == 0 {
+                task.process.clone()
+            } else {
+                find_task_by_tid(Tid::from_pid_t(who))
+                    .map(|task| task.process.clone())
+                    .ok_or(KernelError::NoProcess)?
+            };
+
+            Ok(alloc::vec![tg])
+        }
+        PRIO_PGRP => {
+            let pgid = if who == 0 {
+                *task.process.pgid.lock_save_irq()
+            } else {
+                Pgid(who as _)
+            };
+
+            Ok(TG_LIST
+                .lock_save_irq()
+                .values()
+                .filter_map(|tg| tg.upgrade())
+                .filter(|tg| *tg.pgid.lock_save_irq() == pgid)
+                .collect())
+        }
+        PRIO_USER => {
+            let uid = if who == 0 {
+                task.creds.lock_save_irq().uid()
+            } else {
+                Uid::new(who as _)
+            };
+
+            Ok(TG_LIST
+                .lock_save_irq()
+                .values()
+                .filter_map(|tg| tg.upgrade())
+                .filter(|tg| process_uids(tg).is_some_and(|(ruid, _)| ruid == uid))
+                .collect())
+        }
+        _ => Err(KernelError::InvalidValue),
+    }
+}
+
+/// Returns the nice value of the most favoured process selected by `which`
+/// and `who`, as `20 - nice` so that it's never negative.
+pub fn sys_getpriority(ctx: &ProcessCtx, which: u32, who: PidT) -> Result<usize> {
+    let nice = find_targets(ctx, which, who)?
+        .iter()
+        .map(|tg| priority_to_nice(*tg.priority.lock_save_irq()))
+        .min()
+        .ok_or(KernelError::NoProcess)?;
+
+    Ok((20 - nice) as usize)
+}
+
+/// Sets the nice value of every process selected by `which` and `who`.
+///
+/// Processes owned by another user can only be changed with
+/// `CAP_SYS_NICE`, as can lowering a nice value below what the target's
+/// `RLIMIT_NICE` allows. If any process can't be changed, the error is
+/// returned after trying the rest.
+pub fn sys_setpriority(ctx: &ProcessCtx, which: u32, who: PidT, nice: i32) -> Result<usize> {
+    let nice = nice.clamp(NICE_MIN, NICE_MAX);
+    let targets = find_targets(ctx, which, who)?;
+
+    let (euid, can_sys_nice) = {
+        let creds = ctx.shared().creds.lock_save_irq();
+
+        (
+            creds.euid(),
+            creds.caps().is_capable(CapabilitiesFlags::CAP_SYS_NICE),
+        )
+    };
+
+    let mut found = false;
+    let mut error = None;
+
+    for tg in targets {
+        let Some((ruid, t_euid)) = process_uids(&tg) else {
+            continue;
+        };
+
+        found = true;
+
+        if ruid != euid && t_euid != euid && !can_sys_nice {
+            error = Some(KernelError::NotPermitted);
+            continue;
+        }
+
+        let mut priority = tg.priority.lock_save_irq();
+
+        // RLIMIT_NICE caps how favourable a nice value can be made, as
+        // `20 - nice`.
+        let rlim = tg.rsrc_lim.lock_save_irq().get(RlimitId::NICE).rlim_cur;
+
+        if nice < priority_to_nice(*priority) && (20 - nice) as u64 > rlim && !can_sys_nice {
+            error = Some(KernelError::Fs(FsError::PermissionDenied));
+            continue;
+        }
+
+        *priority = nice_to_priority(nice);
+    }
+
+    match error {
+        Some(e) => Err(e),
+        None if found => Ok(0),
+        None => Err(KernelError::NoProcess),
+    }
+}
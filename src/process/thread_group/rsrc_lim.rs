@@ -1,3 +1,8 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::fmt::Write;
 use libkernel::{
     error::{KernelError, Result},
     memory::address::TUA,
@@ -5,7 +10,7 @@ use libkernel::{
 
 use crate::{
     memory::uaccess::{UserCopyable, copy_from_user, copy_to_user},
-    process::{Tid, find_task_by_tid},
+    process::{Tid, fd_table::NR_OPEN, find_task_by_tid},
     sched::syscall_ctx::ProcessCtx,
 };
 
@@ -156,6 +161,58 @@ impl Default for ResourceLimits {
     }
 }
 
+/// How each limit is described in `/proc/<pid>/limits`, with its units.
+const LIMIT_NAMES: [(&str, &str); RlimitId::NLIMITS.as_usize()] = [
+    ("Max cpu time", "seconds"),
+    ("Max file size", "bytes"),
+    ("Max data size", "bytes"),
+    ("Max stack size", "bytes"),
+    ("Max core file size", "bytes"),
+    ("Max resident set", "bytes"),
+    ("Max processes", "processes"),
+    ("Max open files", "files"),
+    ("Max locked memory", "bytes"),
+    ("Max address space", "bytes"),
+    ("Max file locks", "locks"),
+    ("Max pending signals", "signals"),
+    ("Max msgqueue size", "bytes"),
+    ("Max nice priority", ""),
+    ("Max realtime priority", ""),
+    ("Max realtime timeout", "us"),
+];
+
+/// Formats `limits` as Linux does in `/proc/<pid>/limits`.
+pub fn format_limits(limits: &ResourceLimits) -> String {
+    let value = |v: u64| {
+        if v == RLIM_INFINITY {
+            "unlimited".to_string()
+        } else {
+            v.to_string()
+        }
+    };
+    let mut out = format!(
+        "{:<25} {:<20} {:<20} {:<10}\n",
+        "Limit", "Soft Limit", "Hard Limit", "Units"
+    );
+
+    for (limit, (name, units)) in limits.limits.iter().zip(LIMIT_NAMES) {
+        let _ = write!(
+            out,
+            "{name:<25} {:<20} {:<20} ",
+            value(limit.rlim_cur),
+            value(limit.rlim_max)
+        );
+
+        if !units.is_empty() {
+            let _ = write!(out, "{units:<10}");
+        }
+
+        out.push('\n');
+    }
+
+    out
+}
+
 impl ResourceLimits {
     pub fn get(&self, id: RlimitId) -> RLimit {
         self.limits[id.as_usize()]
@@ -176,6 +233,12 @@ impl ResourceLimits {
             return Err(KernelError::NotPermitted);
         }
 
+        // No table can hold more descriptors than `nr_open`, whatever the
+        // limit says.
+        if matches!(id, RlimitId::NOFILE) && new_limit.rlim_max > NR_OPEN as u64 {
+            return Err(KernelError::NotPermitted);
+        }
+
         // The new values are valid. Commit them.
         self.limits[id.as_usize()] = new_limit;
 
@@ -297,6 +297,11 @@ impl SignalActionState {
         }
     }
 
+    /// Returns `true` if `id` has been explicitly set to be ignored.
+    pub fn is_ignored(&self, id: SigId) -> bool {
+        matches!(self.action[id], SigActionState::Ignore)
+    }
+
     pub fn action_signal(&self, id: SigId) -> Option<KSignalAction> {
         match self.action[id] {
             SigActionState::Ignore => None, // look for another signal,
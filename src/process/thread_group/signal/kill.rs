@@ -1,13 +1,14 @@
 use crate::{
     process::{
-        Tid,
+        TASK_LIST, Tid,
         thread_group::{Pgid, Tgid, ThreadGroup, pid::PidT},
     },
-    sched::syscall_ctx::ProcessCtx,
+    sched::{sched_task::Work, syscall_ctx::ProcessCtx, waker::create_waker},
 };
 
 use super::{SigId, uaccess::UserSigId};
 use crate::process::thread_group::TG_LIST;
+use alloc::sync::Arc;
 use libkernel::error::{KernelError, Result};
 
 pub fn sys_kill(ctx: &ProcessCtx, pid: PidT, signal: UserSigId) -> Result<usize> {
@@ -63,27 +64,56 @@ pub fn sys_kill(ctx: &ProcessCtx, pid: PidT, signal: UserSigId) -> Result<usize>
     Ok(0)
 }
 
-pub fn sys_tkill(ctx: &ProcessCtx, tid: PidT, signal: UserSigId) -> Result<usize> {
-    let target_tid = Tid(tid as _);
-    let current_task = ctx.shared();
+/// Sends `signal` to the thread `task` alone, waking it so that a blocking
+/// call can be interrupted. A fatal `SIGKILL` still takes the whole group.
+fn signal_thread(task: Arc<Work>, signal: SigId) {
+    if signal == SigId::SIGKILL {
+        task.process.deliver_signal(signal);
+        return;
+    }
+
+    task.raise_task_signal(signal);
+    create_waker(task).wake();
+}
+
+pub fn sys_tkill(_ctx: &ProcessCtx, tid: PidT, signal: UserSigId) -> Result<usize> {
+    if tid <= 0 {
+        return Err(KernelError::InvalidValue);
+    }
 
     let signal: SigId = signal.try_into()?;
 
-    // The fast-path case.
-    if current_task.tid == target_tid {
-        current_task.process.queue_signal(signal);
-    } else {
-        let task = current_task
-            .process
-            .tasks
-            .lock_save_irq()
-            .get(&target_tid)
-            .and_then(|t| t.upgrade())
-            .ok_or(KernelError::NoProcess)?;
-
-        task.process.queue_signal(signal);
+    let task = TASK_LIST
+        .lock_save_irq()
+        .get(&Tid(tid as _))
+        .and_then(|t| t.upgrade())
+        .ok_or(KernelError::NoProcess)?;
+
+    signal_thread(task, signal);
+
+    Ok(0)
+}
+
+pub fn sys_tgkill(_ctx: &ProcessCtx, tgid: PidT, tid: PidT, signal: UserSigId) -> Result<usize> {
+    if tgid <= 0 || tid <= 0 {
+        return Err(KernelError::InvalidValue);
     }
 
+    let signal: SigId = signal.try_into()?;
+
+    // The thread has to still be in the group, so that a recycled TID isn't
+    // signalled by mistake.
+    let task = ThreadGroup::get(Tgid(tgid as _))
+        .and_then(|tg| {
+            tg.tasks
+                .lock_save_irq()
+                .get(&Tid(tid as _))
+                .and_then(|t| t.upgrade())
+        })
+        .ok_or(KernelError::NoProcess)?;
+
+    signal_thread(task, signal);
+
     Ok(0)
 }
 
@@ -13,9 +13,9 @@ bitflags! {
     #[repr(C)]
     #[derive(Clone, Copy, Debug)]
     pub struct SigActionFlags: u64 {
-        const SA_NOCLDSTOP      = 1 << 1;
-        const SA_NOCLDWAIT      = 1 << 2;
-        const SA_SIGINFO        = 1 << 3;
+        const SA_NOCLDSTOP      = 1 << 0;
+        const SA_NOCLDWAIT      = 1 << 1;
+        const SA_SIGINFO        = 1 << 2;
         const SA_UNSUPPORTED    = 1 << 10;
         const SA_EXPOSE_TAGBITS = 1 << 11;
         const SA_RESTORER       = 1 << 26;
@@ -129,3 +129,45 @@ pub async fn sys_rt_sigaction(
 
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{SigActionFlags, UserSigAction};
+    use crate::process::thread_group::signal::{SigId, SigSet};
+    use core::mem::{offset_of, size_of};
+    use moss_macros::ktest;
+
+    // The kernel's `struct sigaction` on arm64, which has `sa_restorer` and
+    // a single-word mask.
+    #[ktest]
+    fn sigaction_layout() {
+        assert_eq!(size_of::<UserSigAction>(), 32);
+        assert_eq!(offset_of!(UserSigAction, sa_sigaction), 0);
+        assert_eq!(offset_of!(UserSigAction, sa_flags), 8);
+        assert_eq!(offset_of!(UserSigAction, sa_restorer), 16);
+        assert_eq!(offset_of!(UserSigAction, sa_mask), 24);
+        assert_eq!(size_of::<SigSet>(), 8);
+    }
+
+    #[ktest]
+    fn sigaction_flag_values() {
+        assert_eq!(SigActionFlags::SA_NOCLDSTOP.bits(), 0x1);
+        assert_eq!(SigActionFlags::SA_NOCLDWAIT.bits(), 0x2);
+        assert_eq!(SigActionFlags::SA_SIGINFO.bits(), 0x4);
+        assert_eq!(SigActionFlags::SA_RESTORER.bits(), 0x0400_0000);
+        assert_eq!(SigActionFlags::SA_ONSTACK.bits(), 0x0800_0000);
+        assert_eq!(SigActionFlags::SA_RESTART.bits(), 0x1000_0000);
+        assert_eq!(SigActionFlags::SA_NODEFER.bits(), 0x4000_0000);
+        assert_eq!(SigActionFlags::SA_RESETHAND.bits(), 0x8000_0000);
+    }
+
+    #[ktest]
+    fn signal_numbers() {
+        assert_eq!(SigId::SIGKILL.user_id(), 9);
+        assert_eq!(SigId::SIGUSR1.user_id(), 10);
+        assert_eq!(SigId::SIGSEGV.user_id(), 11);
+        assert_eq!(SigId::SIGCHLD.user_id(), 17);
+        assert_eq!(SigId::SIGSTOP.user_id(), 19);
+        assert_eq!(SigSet::SIGCHLD.bits(), 1 << 16);
+    }
+}
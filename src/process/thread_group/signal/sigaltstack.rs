@@ -116,3 +116,21 @@ pub async fn sys_sigaltstack(
 
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{SigAltStackFlags, UserSigAltStack};
+    use core::mem::{offset_of, size_of};
+    use moss_macros::ktest;
+
+    // `stack_t`, as taken by `sigaltstack`.
+    #[ktest]
+    fn stack_t_layout() {
+        assert_eq!(size_of::<UserSigAltStack>(), 24);
+        assert_eq!(offset_of!(UserSigAltStack, ss_sp), 0);
+        assert_eq!(offset_of!(UserSigAltStack, ss_flags), 8);
+        assert_eq!(offset_of!(UserSigAltStack, ss_size), 16);
+        assert_eq!(SigAltStackFlags::SS_ONSTACK.bits(), 1);
+        assert_eq!(SigAltStackFlags::SS_DISABLE.bits(), 2);
+    }
+}
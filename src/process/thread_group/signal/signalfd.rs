@@ -213,6 +213,10 @@ impl FileOps for SignalFd {
         Err(KernelError::InvalidValue)
     }
 
+    fn is_stream(&self) -> bool {
+        true
+    }
+
     fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
         let mask = self.mask;
         Box::pin(async move {
@@ -4,36 +4,73 @@ use super::{
     signal::{InterruptResult, Interruptable, SigId},
 };
 use crate::memory::uaccess::{UserCopyable, copy_to_user};
+use crate::process::fd_table::Fd;
 use crate::sched::syscall_ctx::ProcessCtx;
 use crate::sync::CondVar;
-use crate::{clock::timespec::TimeSpec, process::Tid};
-use alloc::collections::btree_map::BTreeMap;
+use crate::{
+    clock::timespec::TimeVal,
+    drivers::timer::Instant,
+    process::{Tid, find_task_by_tid, pidfd::PidfdFlags},
+};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
 use bitflags::Flags;
+use core::{sync::atomic::Ordering, time::Duration};
 use libkernel::sync::condvar::WakeupType;
 use libkernel::{
     error::{KernelError, Result},
     memory::address::TUA,
+    proc::ids::Uid,
 };
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct RUsage {
-    pub ru_utime: TimeSpec, // user time used
-    pub ru_stime: TimeSpec, // system time used
-    pub ru_maxrss: i64,     // maximum resident set size
-    pub ru_ixrss: i64,      // integral shared memory size
-    pub ru_idrss: i64,      // integral unshared data size
-    pub ru_isrss: i64,      // integral unshared stack size
-    pub ru_minflt: i64,     // page reclaims
-    pub ru_majflt: i64,     // page faults
-    pub ru_nswap: i64,      // swaps
-    pub ru_inblock: i64,    // block input operations
-    pub ru_oublock: i64,    // block output operations
-    pub ru_msgsnd: i64,     // messages sent
-    pub ru_msgrcv: i64,     // messages received
-    pub ru_nsignals: i64,   // signals received
-    pub ru_nvcsw: i64,      // voluntary context switches
-    pub ru_nivcsw: i64,     // involuntary context switches
+    pub ru_utime: TimeVal, // user time used
+    pub ru_stime: TimeVal, // system time used
+    pub ru_maxrss: i64,    // maximum resident set size
+    pub ru_ixrss: i64,     // integral shared memory size
+    pub ru_idrss: i64,     // integral unshared data size
+    pub ru_isrss: i64,     // integral unshared stack size
+    pub ru_minflt: i64,    // page reclaims
+    pub ru_majflt: i64,    // page faults
+    pub ru_nswap: i64,     // swaps
+    pub ru_inblock: i64,   // block input operations
+    pub ru_oublock: i64,   // block output operations
+    pub ru_msgsnd: i64,    // messages sent
+    pub ru_msgrcv: i64,    // messages received
+    pub ru_nsignals: i64,  // signals received
+    pub ru_nvcsw: i64,     // voluntary context switches
+    pub ru_nivcsw: i64,    // involuntary context switches
+}
+
+unsafe impl UserCopyable for RUsage {}
+
+/// CPU time used by a process and its waited-for children, in `USER_HZ`
+/// ticks.
+#[derive(Clone, Copy, Debug, Default)]
+struct CpuTimes {
+    utime: usize,
+    stime: usize,
+}
+
+impl CpuTimes {
+    fn of(tg: &ThreadGroup) -> Self {
+        Self {
+            utime: tg.utime.load(Ordering::Relaxed) + tg.cutime.load(Ordering::Relaxed),
+            stime: tg.stime.load(Ordering::Relaxed) + tg.cstime.load(Ordering::Relaxed),
+        }
+    }
+
+    fn to_rusage(self) -> RUsage {
+        let to_timeval =
+            |ticks: usize| Duration::from(Instant::from_user_normalized(ticks as u64)).into();
+
+        RUsage {
+            ru_utime: to_timeval(self.utime),
+            ru_stime: to_timeval(self.stime),
+            ..RUsage::default()
+        }
+    }
 }
 
 bitflags::bitflags! {
@@ -50,13 +87,46 @@ bitflags::bitflags! {
     }
 }
 
-// TODO: more fields needed for full compatibility
+/// `siginfo_t`, as filled in for `SIGCHLD` by `waitid()`.
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct SigInfo {
     pub signo: i32,
-    pub code: i32,
     pub errno: i32,
+    pub code: i32,
+    _pad: i32,
+    pub pid: PidT,
+    pub uid: u32,
+    pub status: i32,
+    _pad2: i32,
+    pub utime: i64,
+    pub stime: i64,
+    _rest: [u8; 80],
+}
+
+impl SigInfo {
+    fn new_sigchld(code: i32, pid: PidT, uid: u32, status: i32, times: CpuTimes) -> Self {
+        Self {
+            signo: SigId::SIGCHLD.user_id() as i32,
+            errno: 0,
+            code,
+            _pad: 0,
+            pid,
+            uid,
+            status,
+            _pad2: 0,
+            utime: times.utime as _,
+            stime: times.stime as _,
+            _rest: [0; 80],
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
+            signo: 0,
+            ..Self::new_sigchld(0, 0, 0, 0, CpuTimes::default())
+        }
+    }
 }
 
 unsafe impl UserCopyable for SigInfo {}
@@ -110,6 +180,24 @@ impl ChildState {
 struct NotifierState {
     children: BTreeMap<Tgid, ChildState>,
     ptrace: BTreeMap<Tid, TraceTrap>,
+    /// Accounting for exited children, until they're waited for.
+    exited: BTreeMap<Tgid, ExitRecord>,
+}
+
+/// What is left of an exited child for its parent to collect.
+#[derive(Clone, Copy, Debug)]
+struct ExitRecord {
+    times: CpuTimes,
+    uid: u32,
+}
+
+/// A collected wait event.
+struct Waited {
+    pid: PidT,
+    event: WaitEvent,
+    times: CpuTimes,
+    /// The real user ID of the child.
+    uid: u32,
 }
 
 impl NotifierState {
@@ -117,6 +205,7 @@ impl NotifierState {
         Self {
             children: BTreeMap::new(),
             ptrace: BTreeMap::new(),
+            exited: BTreeMap::new(),
         }
     }
 }
@@ -148,6 +237,21 @@ impl Notifiers {
         });
     }
 
+    /// Records that the child process `tg` has exited.
+    pub fn child_exit(&self, tg: &ThreadGroup, uid: Uid, exit_state: ChildState) {
+        let record = ExitRecord {
+            times: CpuTimes::of(tg),
+            uid: uid.into(),
+        };
+
+        self.inner.update(|state| {
+            state.children.insert(tg.tgid, exit_state);
+            state.exited.insert(tg.tgid, record);
+
+            WakeupType::All
+        });
+    }
+
     pub fn ptrace_notify(&self, tid: Tid, ptrace_trap: TraceTrap) {
         self.inner.update(|state| {
             state.ptrace.insert(tid, ptrace_trap);
@@ -251,6 +355,141 @@ fn find_event(
         .or_else(|| find_child_event(&mut state.children, pid, flags, remove_entry))
 }
 
+/// Collects an event selected by `pid`, along with the exit record of an
+/// exited child. The event is left in place if `consume` is `false`.
+fn collect_event(
+    state: &mut NotifierState,
+    pid: PidT,
+    flags: WaitFlags,
+    consume: bool,
+) -> Option<(PidT, WaitEvent, Option<ExitRecord>)> {
+    let (ret_pid, event) = find_event(state, pid, flags, consume)?;
+
+    let record = match event {
+        WaitEvent::Child(ChildState::NormalExit { .. } | ChildState::SignalExit { .. }) => {
+            let tgid = Tgid::from_pid_t(ret_pid);
+
+            if consume {
+                state.exited.remove(&tgid)
+            } else {
+                state.exited.get(&tgid).copied()
+            }
+        }
+        _ => None,
+    };
+
+    Some((ret_pid, event, record))
+}
+
+/// Returns `true` if `process` has a child or tracee, selected by `pid`, that
+/// it could wait for.
+fn has_waitable(process: &Arc<ThreadGroup>, pid: PidT) -> bool {
+    {
+        let children = process.children.lock_save_irq();
+
+        if pid == -1 {
+            return !children.is_empty();
+        }
+
+        if pid < -1 {
+            let target_pgid = Pgid((-pid) as u32);
+
+            return children
+                .values()
+                .any(|child| *child.pgid.lock_save_irq() == target_pgid);
+        }
+
+        if children.contains_key(&Tgid::from_pid_t(pid)) {
+            return true;
+        }
+    }
+
+    let tid = Tid::from_pid_t(pid);
+
+    // A thread can never wait for itself or its siblings, even when tracing
+    // them.
+    if process.tasks.lock_save_irq().contains_key(&tid) {
+        return false;
+    }
+
+    find_task_by_tid(tid).is_some_and(|task| task.ptrace.lock_save_irq().is_traced_by(process))
+}
+
+/// The common body of the wait family: waits for an event selected by `pid`
+/// (in `wait4()`'s encoding).
+///
+/// Returns `None` if `WNOHANG` was given and nothing is ready yet. Unless
+/// `WNOWAIT` was given, the event is consumed and the CPU time of an exited
+/// child is added to `process`'s child times.
+async fn do_wait(
+    process: &Arc<ThreadGroup>,
+    pid: PidT,
+    flags: WaitFlags,
+) -> Result<Option<Waited>> {
+    let consume = !flags.contains(WaitFlags::WNOWAIT);
+    let waitable = has_waitable(process, pid);
+
+    let (pid, event, record) = if !waitable || flags.contains(WaitFlags::WNOHANG) {
+        // Nothing to sleep for. See if there are any pending child
+        // notification events (e.g. from children that have already exited),
+        // and if there are none and nothing to wait for, return ECHILD.
+        let mut ret = None;
+        process.child_notifiers.inner.update(|s| {
+            ret = collect_event(s, pid, flags, consume);
+            WakeupType::None
+        });
+
+        match ret {
+            Some(ret) => ret,
+            None if !waitable => return Err(KernelError::NoChildProcess),
+            None => return Ok(None),
+        }
+    } else {
+        match process
+            .child_notifiers
+            .inner
+            .wait_until(|s| collect_event(s, pid, flags, consume))
+            .interruptable()
+            .await
+        {
+            InterruptResult::Interrupted => return Err(KernelError::Interrupted),
+            InterruptResult::Uninterrupted(r) => r,
+        }
+    };
+
+    let record = match record {
+        Some(record) => {
+            if consume {
+                process
+                    .cutime
+                    .fetch_add(record.times.utime, Ordering::Relaxed);
+                process
+                    .cstime
+                    .fetch_add(record.times.stime, Ordering::Relaxed);
+            }
+
+            record
+        }
+        // A child that is still around reports its usage so far.
+        None => find_task_by_tid(Tid::from_pid_t(pid))
+            .map(|task| ExitRecord {
+                times: CpuTimes::of(&task.process),
+                uid: task.creds.lock_save_irq().uid().into(),
+            })
+            .unwrap_or(ExitRecord {
+                times: CpuTimes::default(),
+                uid: 0,
+            }),
+    };
+
+    Ok(Some(Waited {
+        pid,
+        event,
+        times: record.times,
+        uid: record.uid,
+    }))
+}
+
 pub async fn sys_wait4(
     ctx: &ProcessCtx,
     pid: PidT,
@@ -282,46 +521,21 @@ pub async fn sys_wait4(
     // wait4 implies WEXITED.
     flags.insert(WaitFlags::WEXITED);
 
-    if !rusage.is_null() {
-        // TODO: Funky waiting.
-        return Err(KernelError::NotSupported);
-    }
-
     let task = ctx.shared();
 
-    let child_proc_count = task.process.children.lock_save_irq().iter().count();
-
-    let (ret_pid, event) = if child_proc_count == 0 || flags.contains(WaitFlags::WNOHANG) {
-        // Special case for no children. See if there are any pending child
-        // notification events without sleeping. If there are no children and no
-        // pending events, return ECHILD.
-        let mut ret = None;
-        task.process.child_notifiers.inner.update(|s| {
-            ret = find_event(s, pid, flags, true);
-            WakeupType::None
-        });
-
-        match ret {
-            Some(ret) => ret,
-            None if child_proc_count == 0 => return Err(KernelError::NoChildProcess),
-            None => return Ok(0),
-        }
+    // A pid of 0 selects children in our own process group.
+    let pid = if pid == 0 {
+        -(task.process.pgid.lock_save_irq().value() as PidT)
     } else {
-        match task
-            .process
-            .child_notifiers
-            .inner
-            .wait_until(|state| find_event(state, pid, flags, true))
-            .interruptable()
-            .await
-        {
-            InterruptResult::Interrupted => return Err(KernelError::Interrupted),
-            InterruptResult::Uninterrupted(r) => r,
-        }
+        pid
+    };
+
+    let Some(waited) = do_wait(&task.process, pid, flags).await? else {
+        return Ok(0);
     };
 
     if !stat_addr.is_null() {
-        match event {
+        match waited.event {
             WaitEvent::Child(ChildState::NormalExit { code }) => {
                 copy_to_user(stat_addr, (code as i32 & 0xff) << 8).await?;
             }
@@ -348,7 +562,11 @@ pub async fn sys_wait4(
         }
     }
 
-    Ok(ret_pid as _)
+    if !rusage.is_null() {
+        copy_to_user(rusage, waited.times.to_rusage()).await?;
+    }
+
+    Ok(waited.pid as _)
 }
 
 // idtype for waitid
@@ -359,6 +577,7 @@ pub enum IdType {
     P_ALL = 0,
     P_PID = 1,
     P_PGID = 2,
+    P_PIDFD = 3,
 }
 
 pub async fn sys_waitid(
@@ -373,10 +592,11 @@ pub async fn sys_waitid(
         0 => IdType::P_ALL,
         1 => IdType::P_PID,
         2 => IdType::P_PGID,
+        3 => IdType::P_PIDFD,
         _ => return Err(KernelError::InvalidValue),
     };
 
-    let flags = WaitFlags::from_bits_retain(options);
+    let mut flags = WaitFlags::from_bits_retain(options);
 
     if flags.contains_unknown_bits() {
         return Err(KernelError::InvalidValue);
@@ -396,82 +616,116 @@ pub async fn sys_waitid(
         return Err(KernelError::InvalidValue);
     }
 
-    if !rusage.is_null() {
-        todo!();
+    // There must be something to wait for.
+    if !flags.intersects(WaitFlags::WEXITED | WaitFlags::WSTOPPED | WaitFlags::WCONTINUED) {
+        return Err(KernelError::InvalidValue);
     }
 
+    let task = ctx.shared();
+    let mut pidfd_nonblock = false;
+
     // Map which/id to pid selection used by our wait helpers
     let sel_pid: PidT = match which {
         IdType::P_ALL => -1,
+        IdType::P_PID if id <= 0 => return Err(KernelError::InvalidValue),
         IdType::P_PID => id,
-        IdType::P_PGID => -id.abs(), // negative means select by PGID in helpers
-    };
-
-    let task = ctx.shared();
-
-    let child_proc_count = task.process.children.lock_save_irq().iter().count();
+        IdType::P_PGID if id < 0 => return Err(KernelError::InvalidValue),
+        // An id of 0 selects our own process group.
+        IdType::P_PGID if id == 0 => -(task.process.pgid.lock_save_irq().value() as PidT),
+        IdType::P_PGID => -id, // negative means select by PGID in helpers
+        IdType::P_PIDFD => {
+            let file = task
+                .fd_table
+                .lock_save_irq()
+                .get(Fd(id))
+                .ok_or(KernelError::BadFd)?;
+
+            let mut lock = file.lock().await;
+            let pidfd = lock.0.as_pidfd().ok_or(KernelError::InvalidValue)?;
+
+            // A non-blocking pidfd makes the wait non-blocking too, failing
+            // with EAGAIN rather than returning nothing.
+            if pidfd.flags().contains(PidfdFlags::PIDFD_NONBLOCK)
+                && !flags.contains(WaitFlags::WNOHANG)
+            {
+                pidfd_nonblock = true;
+                flags.insert(WaitFlags::WNOHANG);
+            }
 
-    // Try immediate check if no children or WNOHANG
-    let event = if child_proc_count == 0 || flags.contains(WaitFlags::WNOHANG) {
-        let mut ret: Option<WaitEvent> = None;
+            pidfd.pid().value() as PidT
+        }
+    };
 
-        task.process.child_notifiers.inner.update(|s| {
-            // Don't consume on WNOWAIT.
-            ret = find_event(s, sel_pid, flags, !flags.contains(WaitFlags::WNOWAIT))
-                .map(|(_, event)| event);
-            WakeupType::None
-        });
+    let Some(waited) = do_wait(&task.process, sel_pid, flags).await? else {
+        if pidfd_nonblock {
+            return Err(KernelError::TryAgain);
+        }
 
-        match ret {
-            Some(ret) => ret,
-            None if child_proc_count == 0 => return Err(KernelError::NoChildProcess),
-            None => return Ok(0),
+        // Nothing is ready: report that with a zeroed si_pid.
+        if !infop.is_null() {
+            copy_to_user(infop, SigInfo::empty()).await?;
         }
-    } else {
-        // Wait until a child matches; first find key, then remove conditionally
-        task.process
-            .child_notifiers
-            .inner
-            .wait_until(|s| {
-                // Don't consume on WNOWAIT.
-                find_event(s, sel_pid, flags, !flags.contains(WaitFlags::WNOWAIT))
-            })
-            .await
-            .1
+
+        return Ok(0);
     };
 
     // Populate siginfo
     if !infop.is_null() {
-        let mut siginfo = SigInfo {
-            signo: SigId::SIGCHLD.user_id() as i32,
-            code: 0,
-            errno: 0,
-        };
-        match event {
-            WaitEvent::Child(ChildState::NormalExit { code }) => {
-                siginfo.code = CLD_EXITED;
-                siginfo.errno = code as i32;
-            }
-            WaitEvent::Child(ChildState::SignalExit { signal, core }) => {
-                siginfo.code = if core { CLD_DUMPED } else { CLD_KILLED };
-                siginfo.errno = signal.user_id() as i32;
-            }
-            WaitEvent::Child(ChildState::Stop { signal }) => {
-                siginfo.code = CLD_STOPPED;
-                siginfo.errno = signal.user_id() as i32;
-            }
-            WaitEvent::Ptrace(TraceTrap { signal, .. }) => {
-                siginfo.code = CLD_TRAPPED;
-                siginfo.errno = signal.user_id() as i32;
-            }
+        let (code, status) = match waited.event {
+            WaitEvent::Child(ChildState::NormalExit { code }) => (CLD_EXITED, code as i32),
+            WaitEvent::Child(ChildState::SignalExit { signal, core }) => (
+                if core { CLD_DUMPED } else { CLD_KILLED },
+                signal.user_id() as i32,
+            ),
+            WaitEvent::Child(ChildState::Stop { signal }) => (CLD_STOPPED, signal.user_id() as i32),
+            WaitEvent::Ptrace(TraceTrap { signal, .. }) => (CLD_TRAPPED, signal.user_id() as i32),
             WaitEvent::Child(ChildState::Continue) => {
-                siginfo.code = CLD_CONTINUED;
+                (CLD_CONTINUED, SigId::SIGCONT.user_id() as i32)
             }
-        }
-        copy_to_user(infop, siginfo).await?;
+        };
+
+        copy_to_user(
+            infop,
+            SigInfo::new_sigchld(code, waited.pid, waited.uid, status, waited.times),
+        )
+        .await?;
+    }
+
+    if !rusage.is_null() {
+        copy_to_user(rusage, waited.times.to_rusage()).await?;
     }
 
-    // If WNOWAIT was specified, don't consume the state; our helpers already honored that
-    // Return 0 on success
+    // If WNOWAIT was specified, the event has been left in place for the next
+    // wait.
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CLD_CONTINUED, CLD_EXITED, CLD_KILLED, CLD_STOPPED, SigInfo};
+    use core::mem::{offset_of, size_of};
+    use moss_macros::ktest;
+
+    // `siginfo_t` with the `SIGCHLD` fields of its union, on a 64-bit
+    // architecture.
+    #[ktest]
+    fn siginfo_layout() {
+        assert_eq!(size_of::<SigInfo>(), 128);
+        assert_eq!(offset_of!(SigInfo, signo), 0);
+        assert_eq!(offset_of!(SigInfo, errno), 4);
+        assert_eq!(offset_of!(SigInfo, code), 8);
+        assert_eq!(offset_of!(SigInfo, pid), 16);
+        assert_eq!(offset_of!(SigInfo, uid), 20);
+        assert_eq!(offset_of!(SigInfo, status), 24);
+        assert_eq!(offset_of!(SigInfo, utime), 32);
+        assert_eq!(offset_of!(SigInfo, stime), 40);
+    }
+
+    #[ktest]
+    fn sigchld_code_values() {
+        assert_eq!(CLD_EXITED, 1);
+        assert_eq!(CLD_KILLED, 2);
+        assert_eq!(CLD_STOPPED, 4);
+        assert_eq!(CLD_CONTINUED, 6);
+    }
+}
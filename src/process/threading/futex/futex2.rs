@@ -17,6 +17,7 @@ use super::{futex_wait_single, requeue_key, wake_key};
 use crate::clock::Deadline;
 use crate::clock::timespec::TimeSpec;
 use crate::memory::uaccess::{UserCopyable, copy_from_user, copy_obj_array_from_user};
+use crate::sched::current_work;
 use crate::sched::syscall_ctx::ProcessCtx;
 
 const FUTEX2_SIZE_U32: u32 = 0x02;
@@ -60,9 +61,9 @@ fn check_flags(flags: u32) -> Result<bool> {
     Ok(flags & FUTEX2_PRIVATE != 0)
 }
 
-/// Builds a [`FutexKey`] for a futex2 user address, enforcing the natural
-/// alignment futex2 requires.
-fn make_key(ctx: &ProcessCtx, uaddr: u64, private: bool) -> Result<(FutexKey, TUA<u32>)> {
+/// Checks a futex2 user address, enforcing the natural alignment futex2
+/// requires.
+fn check_uaddr(uaddr: u64) -> Result<TUA<u32>> {
     let addr = TUA::<u32>::from_value(uaddr as usize);
 
     if addr.is_null() {
@@ -73,6 +74,13 @@ fn make_key(ctx: &ProcessCtx, uaddr: u64, private: bool) -> Result<(FutexKey, TU
         return Err(KernelError::InvalidValue);
     }
 
+    Ok(addr)
+}
+
+/// Builds a [`FutexKey`] for a futex2 user address.
+fn make_key(ctx: &ProcessCtx, uaddr: u64, private: bool) -> Result<(FutexKey, TUA<u32>)> {
+    let addr = check_uaddr(uaddr)?;
+
     let key = if private {
         FutexKey::new_private(ctx, addr)
     } else {
@@ -97,7 +105,13 @@ async fn abs_timeout(timeout: TUA<TimeSpec>, clockid: u32) -> Result<Option<Dead
 
     let deadline = match clockid {
         CLOCK_REALTIME => Deadline::Realtime(deadline),
-        CLOCK_MONOTONIC => Deadline::Monotonic(deadline),
+        // The deadline was read in the caller's time namespace.
+        CLOCK_MONOTONIC => Deadline::Monotonic(
+            current_work()
+                .time_ns
+                .lock_save_irq()
+                .monotonic_to_host(deadline),
+        ),
         _ => return Err(KernelError::InvalidValue),
     };
 
@@ -187,22 +201,28 @@ pub async fn sys_futex_waitv(
 
     let entries = copy_obj_array_from_user(uwaiters, nr_futexes as usize).await?;
 
-    let mut waiters = Vec::with_capacity(entries.len());
-    for entry in entries {
+    let mut futexes = Vec::with_capacity(entries.len());
+    for entry in &entries {
         if entry.reserved != 0 || entry.val > u64::from(u32::MAX) {
             return Err(KernelError::InvalidValue);
         }
 
-        let private = check_flags(entry.flags)?;
-        let (key, uaddr) = make_key(ctx, entry.uaddr, private)?;
+        futexes.push((check_uaddr(entry.uaddr)?, check_flags(entry.flags)?));
+    }
 
-        waiters.push(ParsedWaiter {
+    let keys = FutexKey::new_many(ctx, &futexes)?;
+
+    let waiters: Vec<_> = entries
+        .iter()
+        .zip(futexes)
+        .zip(keys)
+        .map(|((entry, (uaddr, _)), key)| ParsedWaiter {
             key,
             uaddr,
             val: entry.val as u32,
             mask: FUTEX_BITSET_MATCH_ANY as u32,
-        });
-    }
+        })
+        .collect();
 
     futex_wait_multi(&waiters, timeout).await
 }
@@ -1,4 +1,6 @@
+use crate::process::ProcVM;
 use crate::sched::syscall_ctx::ProcessCtx;
+use alloc::vec::Vec;
 use libkernel::error::{KernelError, Result};
 use libkernel::memory::address::{TUA, VA};
 use libkernel::memory::proc_vm::address_space::UserAddressSpace;
@@ -20,12 +22,54 @@ impl FutexKey {
     }
 
     pub fn new_shared(ctx: &ProcessCtx, uaddr: TUA<u32>) -> Result<Self> {
+        let pid = ctx.shared().process.tgid.value();
+        let proc_vm = ctx.shared().vm.shared_vm();
+
+        Self::shared_in(&mut proc_vm.lock_save_irq(), pid, uaddr)
+    }
+
+    /// Returns the keys of several futexes, each given with whether it's
+    /// private, looking the shared ones up under a single lock of the address
+    /// space.
+    pub fn new_many(ctx: &ProcessCtx, futexes: &[(TUA<u32>, bool)]) -> Result<Vec<Self>> {
+        let pid = ctx.shared().process.tgid.value();
         let proc_vm = ctx.shared().vm.shared_vm();
-        let pg_info = proc_vm
-            .lock_save_irq()
+        let mut vm = proc_vm.lock_save_irq();
+
+        futexes
+            .iter()
+            .map(|&(uaddr, private)| {
+                if private {
+                    Ok(Self::Private {
+                        pid,
+                        addr: uaddr.value(),
+                    })
+                } else {
+                    Self::shared_in(&mut vm, pid, uaddr)
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the key of the shared futex at `uaddr` in `vm`, the address
+    /// space of the process `pid`.
+    fn shared_in(vm: &mut ProcVM, pid: u32, uaddr: TUA<u32>) -> Result<Self> {
+        let va = VA::from_value(uaddr.value());
+
+        // A private mapping's pages are only shared with another process as
+        // copy-on-write pages after a fork. They're still separate memory, so
+        // as on Linux, a futex in one is private to the process all the same.
+        if vm.mm().find_vma(va).is_some_and(|vma| !vma.is_shared()) {
+            return Ok(Self::Private {
+                pid,
+                addr: uaddr.value(),
+            });
+        }
+
+        let pg_info = vm
             .mm_mut()
             .address_space_mut()
-            .translate(VA::from_value(uaddr.value()))
+            .translate(va)
             .ok_or(KernelError::Fault)?;
 
         Ok(Self::Shared {
@@ -46,6 +46,20 @@ fn get_or_create_queue(key: FutexKey) -> FutexQueue {
         .clone()
 }
 
+/// Returns the queues of `keys`, in the same order, taking the futex table
+/// lock just once for all of them.
+fn get_or_create_queues(keys: impl Iterator<Item = FutexKey>) -> Vec<FutexQueue> {
+    let mut table = futex_table().lock_save_irq();
+
+    keys.map(|key| {
+        table
+            .entry(key)
+            .or_insert_with(|| Arc::new(SpinLock::new(WakerSet::new())))
+            .clone()
+    })
+    .collect()
+}
+
 pub fn wake_key(nr_wake: usize, key: FutexKey, mask: u32) -> usize {
     let mut wakers = Vec::new();
 
@@ -8,9 +8,9 @@ use libkernel::{
     memory::address::TUA,
 };
 
-use super::get_or_create_queue;
+use super::get_or_create_queues;
 use super::key::FutexKey;
-use super::waiter::WaiterCell;
+use super::waiter::{FutexQueue, WaiterCell};
 use crate::clock::Deadline;
 use crate::memory::uaccess::{copy_from_user, try_copy_from_user};
 use crate::process::thread_group::signal::{InterruptResult, Interruptable};
@@ -77,14 +77,19 @@ enum Setup {
     NeedFault(usize),
 }
 
-/// Enqueues each waiter, checking its futex value under the queue lock.
+/// Enqueues each waiter on its queue in `queues`, checking its futex value
+/// under the queue lock.
 ///
 /// Synchronous; runs inside a single poll so no wake can slip between the
 /// value check and registration of any one waiter (the queue lock covers
 /// both). Holds at most one queue lock at a time.
-fn setup_all(waiters: &[ParsedWaiter], waker: &Waker, guard: &mut WaitGuard) -> Setup {
-    for (idx, waiter) in waiters.iter().enumerate() {
-        let queue = get_or_create_queue(waiter.key);
+fn setup_all(
+    waiters: &[ParsedWaiter],
+    queues: &[FutexQueue],
+    waker: &Waker,
+    guard: &mut WaitGuard,
+) -> Setup {
+    for (idx, (waiter, queue)) in waiters.iter().zip(queues).enumerate() {
         let mut queue_guard = queue.lock_save_irq();
 
         match try_copy_from_user(waiter.uaddr) {
@@ -115,12 +120,18 @@ pub async fn futex_wait_multi(
     waiters: &[ParsedWaiter],
     timeout: Option<Deadline>,
 ) -> Result<usize> {
+    // The queues are looked up once, in one pass over the futex table, and
+    // kept across retries. A queue is never taken out of the table, so they
+    // stay the ones wakes go to.
+    let queues = get_or_create_queues(waiters.iter().map(|waiter| waiter.key));
+
     loop {
         let mut guard = WaitGuard::default();
 
         // poll_fn is used purely to obtain the task's waker; setup itself
         // never returns Pending.
-        let setup = poll_fn(|cx| Poll::Ready(setup_all(waiters, cx.waker(), &mut guard))).await;
+        let setup =
+            poll_fn(|cx| Poll::Ready(setup_all(waiters, &queues, cx.waker(), &mut guard))).await;
 
         match setup {
             Setup::NeedFault(idx) => {
@@ -0,0 +1,200 @@
+//! The deadline scheduling class, `SCHED_DEADLINE`.
+//!
+//! Deadline tasks run earliest-deadline-first, ahead of every EEVDF task. Each
+//! one is a constant bandwidth server (CBS): it may run for `runtime` in every
+//! `period`, and should have done so by `deadline` after the period starts. A
+//! task that uses up its runtime is throttled until its deadline, when it gets
+//! a fresh runtime and a deadline one period later, so it can never take more
+//! than its share of a CPU.
+//!
+//! Admission control keeps the bandwidth (`runtime / period`) reserved by all
+//! deadline tasks within [`DL_BW_LIMIT`] of the CPUs' capacity, which leaves
+//! some time over for everything else.
+
+use crate::{
+    arch::{Arch, ArchImpl},
+    drivers::timer::Instant,
+    sync::SpinLock,
+};
+use core::time::Duration;
+use libkernel::error::{KernelError, Result};
+
+/// Bandwidths are fractions of a CPU, in fixed point with this many
+/// fractional bits.
+const BW_SHIFT: u32 = 20;
+
+/// The share of each CPU that deadline tasks may reserve: 95%, as Linux
+/// allows by default.
+const DL_BW_LIMIT: u64 = (95 << BW_SHIFT) / 100;
+
+/// The shortest runtime that can be accounted for meaningfully.
+const DL_RUNTIME_MIN: Duration = Duration::from_nanos(1 << 10);
+
+/// The range of periods a deadline task may have, as Linux's defaults for
+/// `sched_deadline_period_{min,max}_us`.
+const DL_PERIOD_MIN: Duration = Duration::from_micros(100);
+const DL_PERIOD_MAX: Duration = Duration::from_micros(1 << 22);
+
+/// The sum of the bandwidths reserved by every deadline task.
+static DL_BW_USED: SpinLock<u64> = SpinLock::new(0);
+
+/// The parameters of a deadline task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DlParams {
+    /// How long the task may run in each period.
+    pub runtime: Duration,
+    /// How long after the start of a period the runtime must have been given.
+    pub deadline: Duration,
+    /// How often the task is given a new runtime.
+    pub period: Duration,
+}
+
+impl DlParams {
+    /// Checks the parameters given to `sched_setattr()`, in nanoseconds. A
+    /// period of zero is taken to be the same as the deadline.
+    pub fn new(runtime: u64, deadline: u64, period: u64) -> Result<Self> {
+        let period = if period == 0 { deadline } else { period };
+
+        let params = Self {
+            runtime: Duration::from_nanos(runtime),
+            deadline: Duration::from_nanos(deadline),
+            period: Duration::from_nanos(period),
+        };
+
+        if params.runtime < DL_RUNTIME_MIN
+            || params.runtime > params.deadline
+            || params.deadline > params.period
+            || !(DL_PERIOD_MIN..=DL_PERIOD_MAX).contains(&params.period)
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        Ok(params)
+    }
+
+    /// Returns the share of a CPU a task with these parameters reserves.
+    fn bandwidth(&self) -> u64 {
+        ((self.runtime.as_nanos() << BW_SHIFT) / self.period.as_nanos()) as u64
+    }
+}
+
+/// Trades the bandwidth a task has reserved with `old` for what it needs with
+/// `new`, either of which may be nothing.
+///
+/// Fails with [`KernelError::InUse`] if `new` needs more than a single CPU
+/// can give, or more than is left over.
+pub fn dl_admit(old: Option<&DlParams>, new: Option<&DlParams>) -> Result<()> {
+    let old_bw = old.map_or(0, DlParams::bandwidth);
+    let new_bw = new.map_or(0, DlParams::bandwidth);
+    let capacity = DL_BW_LIMIT * ArchImpl::cpu_count() as u64;
+
+    let mut used = DL_BW_USED.lock_save_irq();
+    let used_after = used.saturating_sub(old_bw) + new_bw;
+
+    if new_bw > DL_BW_LIMIT || (new_bw > old_bw && used_after > capacity) {
+        return Err(KernelError::InUse);
+    }
+
+    *used = used_after;
+
+    Ok(())
+}
+
+/// The CBS state of a deadline task.
+#[derive(Clone, Debug)]
+pub struct DlEntity {
+    pub params: DlParams,
+    /// What's left of this period's runtime, in nanoseconds. This goes
+    /// negative when the task overruns, and the overrun is paid back out of
+    /// the following periods.
+    runtime: i64,
+    /// The absolute deadline of the current period, or `None` until the task
+    /// first runs.
+    pub deadline: Option<Instant>,
+    /// When a task that has used up its runtime gets more.
+    pub throttled_until: Option<Instant>,
+}
+
+impl DlEntity {
+    pub fn new(params: DlParams) -> Self {
+        Self {
+            params,
+            runtime: 0,
+            deadline: None,
+            throttled_until: None,
+        }
+    }
+
+    /// Starts a new period at `now`, with a full runtime.
+    pub fn start_period(&mut self, now: Instant) {
+        self.runtime = self.params.runtime.as_nanos() as i64;
+        self.deadline = Some(now + self.params.deadline);
+    }
+
+    /// Called when the task wakes up at `now`.
+    ///
+    /// This is the CBS wake-up rule: the task keeps its current deadline and
+    /// runtime, unless running out the runtime before the deadline would take
+    /// more than its bandwidth, in which case it starts a new period.
+    pub fn wake(&mut self, now: Instant) {
+        self.throttled_until = None;
+
+        if let Some(deadline) = self.deadline
+            && deadline > now
+            && self.runtime > 0
+        {
+            let left = (deadline - now).as_nanos();
+
+            if self.runtime as u128 * self.params.deadline.as_nanos()
+                <= left * self.params.runtime.as_nanos()
+            {
+                return;
+            }
+        }
+
+        self.start_period(now);
+    }
+
+    /// Charges the task for running for `ran`. Returns `true` if that used up
+    /// its runtime, throttling it until its deadline.
+    pub fn charge(&mut self, ran: Duration) -> bool {
+        self.runtime = self
+            .runtime
+            .saturating_sub(ran.as_nanos().try_into().unwrap_or(i64::MAX));
+
+        if self.runtime > 0 {
+            return false;
+        }
+
+        self.throttled_until = self.deadline;
+
+        true
+    }
+
+    /// Gives a throttled task its runtime back at `now`, pushing its deadline
+    /// back a period for every runtime it takes to pay off any overrun.
+    pub fn replenish(&mut self, now: Instant) {
+        self.throttled_until = None;
+
+        let Some(mut deadline) = self.deadline else {
+            return self.start_period(now);
+        };
+
+        while self.runtime <= 0 {
+            self.runtime += self.params.runtime.as_nanos() as i64;
+            deadline = deadline + self.params.period;
+        }
+
+        // A task that has fallen too far behind starts afresh.
+        if deadline <= now {
+            return self.start_period(now);
+        }
+
+        self.deadline = Some(deadline);
+    }
+
+    /// Returns how long the task may run before it's throttled.
+    pub fn runtime_left(&self) -> Duration {
+        Duration::from_nanos(self.runtime.max(0) as u64)
+    }
+}
@@ -0,0 +1,129 @@
+//! CPU isolation, set up with the `--isolcpus` boot option.
+//!
+//! Isolated CPUs are left out of every task's affinity mask by default, so
+//! nothing is balanced onto them. Only tasks that are pinned to them with
+//! `sched_setaffinity()` run there, which keeps them free of interference for
+//! latency-sensitive work, and leaves the rest for the kernel's own
+//! housekeeping.
+
+use super::sched_task::{CPU_MASK_SIZE, CpuMask, NR_CPUS};
+use crate::{
+    arch::{Arch, ArchImpl},
+    sync::SpinLock,
+};
+use alloc::string::String;
+use core::fmt::Write;
+use log::warn;
+
+/// The CPUs kept out of general scheduling.
+static ISOLATED_CPUS: SpinLock<CpuMask> = SpinLock::new([0; CPU_MASK_SIZE]);
+
+/// Returns `true` if `cpu` is set in `mask`.
+pub fn mask_has_cpu(mask: &CpuMask, cpu: usize) -> bool {
+    cpu < NR_CPUS && mask[cpu / 8] & (1 << (cpu % 8)) != 0
+}
+
+/// Returns the mask of every CPU in the system.
+pub fn possible_mask() -> CpuMask {
+    let mut mask = [0; CPU_MASK_SIZE];
+
+    for cpu in 0..ArchImpl::cpu_count().min(NR_CPUS) {
+        mask[cpu / 8] |= 1 << (cpu % 8);
+    }
+
+    mask
+}
+
+/// Returns the mask of the isolated CPUs.
+pub fn isolated_mask() -> CpuMask {
+    *ISOLATED_CPUS.lock_save_irq()
+}
+
+/// Returns the mask of the CPUs that aren't isolated, which new tasks are
+/// allowed to run on.
+pub fn housekeeping_mask() -> CpuMask {
+    let isolated = isolated_mask();
+    let mut mask = possible_mask();
+
+    for (byte, isolated) in mask.iter_mut().zip(isolated) {
+        *byte &= !isolated;
+    }
+
+    mask
+}
+
+/// Keeps the CPUs in `mask` out of general scheduling. This must be done
+/// before the first task is made, as only new tasks pick it up. At least one
+/// CPU has to be left over; if there isn't, nothing is isolated.
+pub fn isolate_cpus(mask: CpuMask) {
+    let possible = possible_mask();
+
+    if possible
+        .iter()
+        .zip(mask)
+        .all(|(cpu, isolated)| cpu & !isolated == 0)
+    {
+        warn!("isolcpus would leave no CPU for housekeeping, ignoring.");
+        return;
+    }
+
+    *ISOLATED_CPUS.lock_save_irq() = mask;
+}
+
+/// Parses a CPU list, such as `1,3-5`, as used by `--isolcpus` and sysfs.
+pub fn parse_cpu_list(list: &str) -> Option<CpuMask> {
+    let mut mask = [0; CPU_MASK_SIZE];
+
+    for range in list.split(',').filter(|range| !range.is_empty()) {
+        let (first, last): (usize, usize) = match range.split_once('-') {
+            Some((first, last)) => (first.parse().ok()?, last.parse().ok()?),
+            None => {
+                let cpu = range.parse().ok()?;
+                (cpu, cpu)
+            }
+        };
+
+        if first > last || last >= NR_CPUS {
+            return None;
+        }
+
+        for cpu in first..=last {
+            mask[cpu / 8] |= 1 << (cpu % 8);
+        }
+    }
+
+    Some(mask)
+}
+
+/// Formats `mask` as a CPU list, the inverse of [`parse_cpu_list`].
+pub fn format_cpu_list(mask: &CpuMask) -> String {
+    let mut list = String::new();
+    let mut cpu = 0;
+
+    while cpu < NR_CPUS {
+        if !mask_has_cpu(mask, cpu) {
+            cpu += 1;
+            continue;
+        }
+
+        let first = cpu;
+
+        while mask_has_cpu(mask, cpu + 1) {
+            cpu += 1;
+        }
+
+        if !list.is_empty() {
+            list.push(',');
+        }
+
+        if first == cpu {
+            let _ = write!(list, "{first}");
+        } else {
+            let _ = write!(list, "{first}-{cpu}");
+        }
+
+        cpu += 1;
+    }
+
+    list
+}
@@ -3,20 +3,24 @@ use crate::drivers::timer::now;
 #[cfg(feature = "smp")]
 use crate::interrupts::cpu_messenger::{Message, message_cpu};
 use crate::kernel::cpu_id::CpuId;
-use crate::process::owned::OwnedTask;
-use crate::sched::sched_task::{CPU_MASK_SIZE, CpuMask};
-use crate::{per_cpu_private, per_cpu_shared, process::TASK_LIST};
+use crate::process::{kthread::start_early_kthreads, owned::OwnedTask};
+use crate::sched::sched_task::CpuMask;
+use crate::{per_cpu_private, per_cpu_shared, process::add_to_task_list};
 use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::fmt::Debug;
 use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use core::task::Waker;
 use core::time::Duration;
+use deadline::DlParams;
+use isolation::mask_has_cpu;
 use log::warn;
 use runqueue::RunQueue;
 use sched_task::{RunnableTask, Work};
 use syscall_ctx::ProcessCtx;
 use waker::create_waker;
 
+pub mod deadline;
+pub mod isolation;
 mod runqueue;
 pub mod sched_task;
 pub mod syscall_ctx;
@@ -79,19 +83,59 @@ per_cpu_shared! {
 /// Default time-slice assigned to runnable tasks.
 const DEFAULT_TIME_SLICE: Duration = Duration::from_millis(4);
 
-/// Fixed-point configuration for virtual-time accounting.
-/// We now use a 65.63 format (65 integer bits, 63 fractional bits) as
-/// recommended by the EEVDF paper to minimise rounding error accumulation.
-pub const VT_FIXED_SHIFT: u32 = 63;
-pub const VT_ONE: u128 = 1u128 << VT_FIXED_SHIFT;
-/// Tolerance used when comparing virtual-time values (see EEVDF, Fixed-Point Arithmetic).
-/// Two virtual-time instants whose integer parts differ by no more than this constant are considered equal.
-pub const VCLOCK_EPSILON: u128 = VT_ONE;
+/// The most favourable nice value.
+pub const NICE_MIN: i32 = -20;
+/// The least favourable nice value.
+pub const NICE_MAX: i32 = 19;
+
+/// Scheduling weight (`w_i` in EEVDF paper) of each nice value, from
+/// [`NICE_MIN`] to [`NICE_MAX`]. Nice 0 has a weight of 1024 and each step
+/// changes a task's share of the CPU by about 10%.
+const NICE_TO_WEIGHT: [u32; (NICE_MAX - NICE_MIN + 1) as usize] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// Returns the nice value of a task priority. Priorities are nice values
+/// negated, so that higher priorities are favoured; anything below the lowest
+/// nice priority, such as the idle task's, is treated as [`NICE_MAX`].
+pub fn priority_to_nice(priority: i8) -> i32 {
+    (-(priority as i32)).clamp(NICE_MIN, NICE_MAX)
+}
+
+/// Returns the task priority of a nice value, clamping it into range.
+pub fn nice_to_priority(nice: i32) -> i8 {
+    -nice.clamp(NICE_MIN, NICE_MAX) as i8
+}
 
-/// Scheduler base weight to ensure tasks always have a strictly positive
-/// scheduling weight. The value is added to a task's priority to obtain its
-/// effective weight (`w_i` in EEVDF paper).
-pub const SCHED_WEIGHT_BASE: i32 = 1024;
+/// Returns the scheduling weight of a task priority.
+pub fn priority_to_weight(priority: i8) -> u32 {
+    NICE_TO_WEIGHT[(priority_to_nice(priority) - NICE_MIN) as usize]
+}
+
+/// How a task is scheduled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// `SCHED_OTHER`: a share of the CPU by EEVDF, weighted by nice value.
+    #[default]
+    Normal,
+    /// `SCHED_DEADLINE`: earliest deadline first, within a reserved bandwidth.
+    Deadline(DlParams),
+}
+
+pub const SCHED_OTHER: u32 = 0;
+pub const SCHED_DEADLINE: u32 = 6;
+
+impl SchedPolicy {
+    /// Returns the number userspace knows the policy by.
+    pub fn as_raw(&self) -> u32 {
+        match self {
+            SchedPolicy::Normal => SCHED_OTHER,
+            SchedPolicy::Deadline(_) => SCHED_DEADLINE,
+        }
+    }
+}
 
 /// Schedule a new task.
 ///
@@ -113,6 +157,17 @@ pub const SCHED_WEIGHT_BASE: i32 = 1024;
 /// Nothing, but the CPU context will be set to the next runnable task. See
 /// `userspace_return` for how this is invoked.
 fn schedule() {
+    // With one CPU there's nowhere else to go.
+    schedule_on_this_cpu(cfg!(feature = "smp"));
+}
+
+/// Like [`schedule`], but never moves the current task to another CPU, for
+/// when it's still running kernel code here.
+fn schedule_in_place() {
+    schedule_on_this_cpu(false);
+}
+
+fn schedule_on_this_cpu(can_migrate: bool) {
     // Reentrancy Check
     if SCHED_STATE.try_borrow_mut().is_none() {
         warn!(
@@ -122,7 +177,7 @@ fn schedule() {
         return;
     }
 
-    let deferred = SCHED_STATE.borrow_mut().do_schedule();
+    let deferred = SCHED_STATE.borrow_mut().do_schedule(can_migrate);
 
     // Drop the old RunnableTask outside the SCHED_STATE borrow. This ensures
     // that any destructors that may be called by dropping the task will be
@@ -140,11 +195,7 @@ fn get_best_cpu(cpu_mask: CpuMask) -> CpuId {
     let r = 0..ArchImpl::cpu_count();
     r.enumerate()
         // Filter to only CPUs in the mask
-        .filter(|(i, _)| {
-            let byte_index = i / 8;
-            let bit_index = i % 8;
-            (cpu_mask[byte_index] & (1 << bit_index)) != 0
-        })
+        .filter(|(i, _)| mask_has_cpu(&cpu_mask, *i))
         .map(|(_, cpu_id)| cpu_id)
         // Find optimal CPU based on least run queue weight
         .min_by(|&x, &y| {
@@ -169,28 +220,23 @@ pub fn insert_work(work: Arc<Work>) {
 
 #[cfg(feature = "smp")]
 pub fn insert_work_cross_cpu(work: Arc<Work>) {
-    let sched_data = work.sched_data.lock_save_irq();
-    let last_cpu = sched_data
+    let last_cpu = work
+        .sched_data
+        .lock_save_irq()
         .as_ref()
         .map(|s| s.last_cpu)
         .unwrap_or(usize::MAX);
-    let mask = sched_data
-        .as_ref()
-        .map(|s| s.cpu_mask)
-        .unwrap_or([u8::MAX; CPU_MASK_SIZE]);
+    let mask = *work.cpu_mask.lock_save_irq();
     let cpu = if last_cpu == usize::MAX {
         get_best_cpu(mask)
     } else {
         // Check if the last CPU is still in the affinity mask, and if so, prefer it to improve cache locality.
-        let byte_index = last_cpu / 8;
-        let bit_index = last_cpu % 8;
-        if (mask[byte_index] & (1 << bit_index)) != 0 {
+        if mask_has_cpu(&mask, last_cpu) {
             CpuId::from_value(last_cpu)
         } else {
             get_best_cpu(mask)
         }
     };
-    drop(sched_data);
     if cpu == CpuId::this() {
         SCHED_STATE.borrow_mut().run_q.add_work(work);
     } else {
@@ -231,7 +277,7 @@ impl SchedState {
         // No-op on single-core systems.
     }
 
-    pub fn do_schedule(&mut self) -> Vec<RunnableTask> {
+    pub fn do_schedule(&mut self, can_migrate: bool) -> Vec<RunnableTask> {
         self.update_global_least_tasked_cpu_info();
 
         let now_inst = now().expect("System timer not initialised");
@@ -246,7 +292,7 @@ impl SchedState {
             current.work.reset_last_account(now_inst);
         }
 
-        self.run_q.schedule(now_inst)
+        self.run_q.schedule(now_inst, can_migrate)
     }
 }
 
@@ -267,17 +313,12 @@ pub fn sched_init() {
 
     let init_work = Work::new(Box::new(init_task));
 
-    {
-        let mut task_list = TASK_LIST.lock_save_irq();
-
-        task_list.insert(
-            init_work.task.descriptor().tid(),
-            Arc::downgrade(&init_work),
-        );
-    }
+    add_to_task_list(&init_work);
 
     insert_work(init_work);
 
+    start_early_kthreads();
+
     schedule();
 }
 
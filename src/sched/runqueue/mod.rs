@@ -1,60 +1,37 @@
 use super::{
-    NUM_CONTEXT_SWITCHES,
+    NUM_CONTEXT_SWITCHES, insert_work_cross_cpu,
     sched_task::{RunnableTask, Work, state::TaskState},
 };
 use crate::{
     arch::{Arch, ArchImpl},
-    drivers::timer::Instant,
+    drivers::timer::{self, Instant, schedule_preempt},
 };
 use alloc::{boxed::Box, collections::binary_heap::BinaryHeap, sync::Arc, vec::Vec};
 use core::{cmp, ptr, sync::atomic::Ordering};
-use libkernel::CpuOps;
-use vclock::VClock;
+use libkernel::{CpuOps, sched::eevdf::EevdfQueue};
 
-mod vclock;
+// Wrapper for the deadline class's queue (Min-Heap ordered by absolute
+// deadline)
+struct ByAbsDeadline(RunnableTask);
 
-// Wrapper for the Ineligible Heap (Min-Heap ordered by v_eligible)
-struct ByEligible(RunnableTask);
-
-impl PartialEq for ByEligible {
-    fn eq(&self, other: &Self) -> bool {
-        self.0.v_eligible == other.0.v_eligible
-    }
-}
-
-impl Eq for ByEligible {}
-
-impl Ord for ByEligible {
-    fn cmp(&self, other: &Self) -> cmp::Ordering {
-        other.0.v_eligible.cmp(&self.0.v_eligible)
-    }
-}
-
-impl PartialOrd for ByEligible {
-    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-// Wrapper for the Eligible Heap (Min-Heap ordered by deadline)
-struct ByDeadline(RunnableTask);
-
-impl PartialEq for ByDeadline {
+impl PartialEq for ByAbsDeadline {
     fn eq(&self, other: &Self) -> bool {
-        other.0.compare_with(&self.0) == cmp::Ordering::Equal
+        self.0.dl_deadline() == other.0.dl_deadline()
     }
 }
 
-impl Eq for ByDeadline {}
+impl Eq for ByAbsDeadline {}
 
-impl Ord for ByDeadline {
+impl Ord for ByAbsDeadline {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        // Reversed so BinaryHeap acts as a MIN-heap
-        other.0.compare_with(&self.0)
+        // Reversed so BinaryHeap acts as a MIN-heap. Tasks yet to start their
+        // first period sort first, as they're about to get a deadline from
+        // now.
+        other.0.dl_deadline().cmp(&self.0.dl_deadline())
     }
 }
 
-impl PartialOrd for ByDeadline {
+impl PartialOrd for ByAbsDeadline {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
         Some(self.cmp(other))
     }
@@ -65,12 +42,15 @@ impl PartialOrd for ByDeadline {
 /// Invariants:
 /// 1. `total_weight` = Sum(queue tasks) + Weight(running_task) (excluding the idle task).
 /// 2. `running_task` is NOT in `queue`.
+///
+/// Deadline tasks are queued apart from the EEVDF tasks and always run first,
+/// except while they're throttled, but they're weighed like any other task.
 pub struct RunQueue {
     total_weight: u64,
-    ineligible: BinaryHeap<ByEligible>,
-    eligible: BinaryHeap<ByDeadline>,
+    eevdf: EevdfQueue<RunnableTask>,
+    deadline: BinaryHeap<ByAbsDeadline>,
+    throttled: Vec<RunnableTask>,
     pub(super) running_task: Option<RunnableTask>,
-    v_clock: VClock,
     idle: RunnableTask,
 }
 
@@ -80,34 +60,48 @@ impl RunQueue {
 
         Self {
             total_weight: 0,
-            ineligible: BinaryHeap::new(),
-            eligible: BinaryHeap::new(),
+            eevdf: EevdfQueue::new(),
+            deadline: BinaryHeap::new(),
+            throttled: Vec::new(),
             running_task: None,
-            v_clock: VClock::new(),
             idle,
         }
     }
 
-    /// Picks the next task to execute and performs the context switch.
+    /// Picks the next task to execute and performs the context switch. With
+    /// `can_migrate`, a running task whose affinity no longer takes in this
+    /// CPU is sent to one that it does.
     ///
     /// Returns `RunnableTask`s that must be dropped after the caller releases
     /// `SCHED_STATE`. Dropping inside the borrow can trigger waker calls that
     /// re-enter `SCHED_STATE` and panic.
-    pub fn schedule(&mut self, now: Instant) -> Vec<RunnableTask> {
-        self.v_clock.advance(now, self.weight());
+    pub fn schedule(&mut self, now: Instant, can_migrate: bool) -> Vec<RunnableTask> {
+        self.eevdf.advance(now.into(), self.weight());
 
         let mut prev_task = ptr::null();
         let mut next_task = None;
         let mut deferred_drops: Vec<RunnableTask> = Vec::new();
 
+        self.replenish_throttled(now);
+
         if let Some(mut cur_task) = self.running_task.take() {
             prev_task = Arc::as_ptr(&cur_task.work);
             let state = cur_task.work.state.load(Ordering::Acquire);
             match state {
+                TaskState::Running | TaskState::Woken
+                    if can_migrate && !cur_task.work.allowed_on(ArchImpl::id()) =>
+                {
+                    let work = cur_task.work.clone();
+                    cur_task.sched_data.last_cpu = ArchImpl::id();
+                    self.total_weight = self.total_weight.saturating_sub(cur_task.weight() as u64);
+                    drop(cur_task);
+
+                    insert_work_cross_cpu(work);
+                }
                 TaskState::Running | TaskState::Woken => {
-                    if cur_task.tick(now) {
+                    if cur_task.tick(now) || self.dl_task_preempts(&cur_task) {
                         // Deadline exceeded — requeue for the next time slice.
-                        self.enqueue(cur_task);
+                        self.enqueue(cur_task, now);
                     } else {
                         // Still has budget — keep running.
                         next_task = Some(cur_task);
@@ -156,17 +150,27 @@ impl RunQueue {
         deferred_drops
     }
 
-    /// Pops any tasks that were ineligible which have become eligible from the
-    /// ineligible queue.
-    fn pop_now_eligible_task(&mut self) -> Option<RunnableTask> {
-        let ByEligible(tsk) = self.ineligible.peek()?;
+    /// Returns `true` if a queued deadline task should run ahead of `cur_task`.
+    fn dl_task_preempts(&self, cur_task: &RunnableTask) -> bool {
+        let Some(ByAbsDeadline(tsk)) = self.deadline.peek() else {
+            return false;
+        };
 
-        if self.v_clock.is_task_eligible(tsk) {
-            let ByEligible(tsk) = self.ineligible.pop()?;
+        cur_task.dl.is_none() || tsk.dl_deadline() < cur_task.dl_deadline()
+    }
 
-            Some(tsk)
-        } else {
-            None
+    /// Gives the throttled deadline tasks whose time has come their runtime
+    /// back, and queues them again.
+    fn replenish_throttled(&mut self, now: Instant) {
+        let mut i = 0;
+
+        while i < self.throttled.len() {
+            if self.throttled[i].throttled_until().is_none_or(|t| t <= now) {
+                let tsk = self.throttled.swap_remove(i);
+                self.enqueue(tsk, now);
+            } else {
+                i += 1;
+            }
         }
     }
 
@@ -179,11 +183,7 @@ impl RunQueue {
     /// - `None` when no runnable task can be found (the runqueue is empty).
     /// - `Some(tsk)` when the current task should be replaced with `tsk`.
     fn find_next_task(&mut self, deferred_drops: &mut Vec<RunnableTask>) -> Option<RunnableTask> {
-        while let Some(tsk) = self.pop_now_eligible_task() {
-            self.eligible.push(ByDeadline(tsk));
-        }
-
-        while let Some(ByDeadline(best)) = self.eligible.pop() {
+        while let Some(ByAbsDeadline(best)) = self.deadline.pop() {
             if best.work.state.load(Ordering::Acquire).is_finished() {
                 self.total_weight = self.total_weight.saturating_sub(best.weight() as u64);
                 deferred_drops.push(best);
@@ -192,37 +192,55 @@ impl RunQueue {
             return Some(best);
         }
 
-        // Fast forward logic, if we have non-eligible, don't go idle.
-        // Fast-forward vclk to the next earliest `v_eligible`.
-        if let Some(ByEligible(tsk)) = self.ineligible.peek() {
-            self.v_clock.fast_forward(tsk.v_eligible);
-            return self.find_next_task(deferred_drops);
+        while let Some(best) = self.eevdf.pop() {
+            if best.work.state.load(Ordering::Acquire).is_finished() {
+                self.total_weight = self.total_weight.saturating_sub(best.weight() as u64);
+                deferred_drops.push(best);
+                continue;
+            }
+            return Some(best);
         }
 
         // The runqueues are completely empty.  Go idle.
         None
     }
 
-    fn enqueue(&mut self, mut task: RunnableTask) {
+    fn enqueue(&mut self, mut task: RunnableTask, now: Instant) {
         task.refresh_priority();
+        task.refresh_policy();
         task.work.state.mark_runnable();
 
-        if self.v_clock.is_task_eligible(&task) {
-            self.eligible.push(ByDeadline(task));
+        if let Some(dl) = task.dl.as_mut() {
+            match dl.throttled_until {
+                Some(until) if until > now => {
+                    schedule_preempt(until);
+                    self.throttled.push(task);
+                }
+                Some(_) => {
+                    dl.replenish(now);
+                    self.deadline.push(ByAbsDeadline(task));
+                }
+                None => self.deadline.push(ByAbsDeadline(task)),
+            }
         } else {
-            self.ineligible.push(ByEligible(task));
+            self.eevdf.push(task);
         }
     }
 
     /// Inserts `new_task` into this CPU's run-queue.
     pub fn add_work(&mut self, new_task: Arc<Work>) {
         let mut new_task = new_task.into_runnable();
+        let now = timer::now().expect("System timer not initialised");
 
-        new_task.inserting_into_runqueue(self.v_clock.now());
+        new_task.inserting_into_runqueue(self.eevdf.v_clock().now());
+
+        if let Some(dl) = new_task.dl.as_mut() {
+            dl.wake(now);
+        }
 
         self.total_weight = self.total_weight.saturating_add(new_task.weight() as u64);
 
-        self.enqueue(new_task);
+        self.enqueue(new_task, now);
     }
 
     pub fn weight(&self) -> u64 {
@@ -1,9 +1,11 @@
-use core::{
-    cmp::Ordering,
-    ops::{Deref, DerefMut},
-};
+use core::ops::{Deref, DerefMut};
 
-use super::{DEFAULT_TIME_SLICE, SCHED_WEIGHT_BASE, VT_FIXED_SHIFT};
+use super::{
+    DEFAULT_TIME_SLICE, SchedPolicy,
+    deadline::{DlEntity, dl_admit},
+    isolation::{housekeeping_mask, mask_has_cpu},
+    priority_to_weight,
+};
 use crate::{
     arch::{Arch, ArchImpl},
     drivers::timer::{Instant, schedule_preempt},
@@ -12,6 +14,7 @@ use crate::{
 };
 
 use alloc::{boxed::Box, sync::Arc};
+use libkernel::sched::eevdf::{EevdfEntity, EevdfTask};
 use state::TaskStateMachine;
 
 pub mod state;
@@ -22,31 +25,25 @@ pub type CpuMask = [u8; CPU_MASK_SIZE];
 
 #[derive(Clone)]
 pub struct SchedulerData {
-    pub v_runtime: u128,
-    /// Virtual time at which the task becomes eligible (v_ei).
-    pub v_eligible: u128,
-    /// Virtual deadline (v_di) used by the EEVDF scheduler.
-    pub v_deadline: u128,
+    /// The task's standing with the EEVDF scheduler.
+    pub eevdf: EevdfEntity,
     pub exec_start: Option<Instant>,
     pub deadline: Option<Instant>,
-    pub last_run: Option<Instant>,
     pub last_cpu: usize,
-    pub cpu_mask: CpuMask,
     pub priority: i8,
+    /// CBS state, for a task in the deadline class.
+    pub dl: Option<DlEntity>,
 }
 
 impl SchedulerData {
     fn new(task: &OwnedTask) -> Self {
         Self {
-            v_runtime: 0,
-            v_eligible: 0,
-            v_deadline: 0,
+            eevdf: EevdfEntity::default(),
             exec_start: None,
             deadline: None,
-            last_run: None,
             last_cpu: usize::MAX,
-            cpu_mask: [u8::MAX; CPU_MASK_SIZE],
             priority: task.priority(),
+            dl: None,
         }
     }
 }
@@ -55,6 +52,12 @@ pub struct Work {
     pub task: Box<OwnedTask>,
     pub state: TaskStateMachine,
     pub sched_data: SpinLock<Option<SchedulerData>>,
+    /// The scheduling policy, which takes effect the next time the task is
+    /// queued.
+    pub policy: SpinLock<SchedPolicy>,
+    /// The CPUs the task may run on. A change moves it the next time it's
+    /// scheduled.
+    pub cpu_mask: SpinLock<CpuMask>,
 }
 
 impl Deref for Work {
@@ -79,9 +82,16 @@ impl Work {
             task,
             state: TaskStateMachine::new(),
             sched_data: SpinLock::new(Some(sched_data)),
+            policy: SpinLock::new(SchedPolicy::Normal),
+            cpu_mask: SpinLock::new(housekeeping_mask()),
         })
     }
 
+    /// Returns `true` if the task's affinity allows it to run on `cpu`.
+    pub fn allowed_on(&self, cpu: usize) -> bool {
+        mask_has_cpu(&self.cpu_mask.lock_save_irq(), cpu)
+    }
+
     pub fn into_runnable(self: Arc<Self>) -> RunnableTask {
         let mut sd = self
             .sched_data
@@ -92,9 +102,20 @@ impl Work {
         // Refresh priority.
         sd.priority = self.task.priority();
 
-        RunnableTask {
+        let mut task = RunnableTask {
             work: self,
             sched_data: sd,
+        };
+
+        task.refresh_policy();
+        task
+    }
+}
+
+impl Drop for Work {
+    fn drop(&mut self) {
+        if let SchedPolicy::Deadline(params) = *self.policy.lock_save_irq() {
+            let _ = dl_admit(Some(&params), None);
         }
     }
 }
@@ -125,80 +146,52 @@ impl DerefMut for RunnableTask {
     }
 }
 
-impl RunnableTask {
-    /// Re-issue a virtual deadline
-    fn replenish_deadline(&mut self) {
-        let q_ns: u128 = DEFAULT_TIME_SLICE.as_nanos();
-        let v_delta = (q_ns << VT_FIXED_SHIFT) / self.weight() as u128;
-        self.v_deadline = self.v_eligible + v_delta;
+impl EevdfTask for RunnableTask {
+    fn entity(&self) -> &EevdfEntity {
+        &self.sched_data.eevdf
     }
+}
 
+impl RunnableTask {
     /// Update accounting info for this task given the latest time. Returns
     /// `true` when we should try to reschedule another task, `false` otherwise.
     pub fn tick(&mut self, now: Instant) -> bool {
-        let dv_increment = if let Some(start) = self.exec_start {
-            let delta = now - start;
-            let w = self.weight() as u128;
-            ((delta.as_nanos()) << VT_FIXED_SHIFT) / w
-        } else {
-            0
-        };
+        if let Some(dl) = self.sched_data.dl.as_mut() {
+            let ran = self.sched_data.exec_start.map(|start| now - start);
+            self.sched_data.exec_start = Some(now);
+
+            // A task that has just become a deadline task is queued to start
+            // its first period.
+            if dl.deadline.is_none() {
+                return true;
+            }
 
-        self.v_runtime = self.v_runtime.saturating_add(dv_increment);
+            return dl.charge(ran.unwrap_or_default());
+        }
 
-        // Advance its eligible time by the virtual run time it just used
-        // (EEVDF: v_ei += t_used / w_i).
-        self.v_eligible = self.v_eligible.saturating_add(dv_increment);
+        let ran = self.exec_start.map(|start| now - start).unwrap_or_default();
+        let weight = self.weight();
 
         self.exec_start = Some(now);
 
-        // Has the task exceeded its deadline?
-        if self.v_eligible >= self.v_deadline {
-            self.replenish_deadline();
-
-            true
-        } else {
-            // Task still has budget. Do nothing. Return to userspace
-            // immediately.
-            false
-        }
+        self.sched_data
+            .eevdf
+            .charge(ran, weight, DEFAULT_TIME_SLICE)
     }
 
-    /// Compute this task's scheduling weight.
-    ///
-    /// weight = priority + SCHED_WEIGHT_BASE
-    /// The sum is clamped to a minimum of 1
+    /// Compute this task's scheduling weight from its nice value.
     pub fn weight(&self) -> u32 {
-        let w = self.sched_data.priority as i32 + SCHED_WEIGHT_BASE;
-        if w <= 0 { 1 } else { w as u32 }
-    }
-
-    pub fn compare_with(&self, other: &Self) -> core::cmp::Ordering {
-        self.v_deadline
-            .cmp(&other.v_deadline)
-            .then_with(|| self.v_runtime.cmp(&other.v_runtime))
-            // If completely equal, prefer the one that hasn't run in a while?
-            // Or prefer the one already running to avoid cache thrashing?
-            // Usually irrelevant for EEVDF but strict ordering is good for
-            // stability.
-            .then_with(|| match (self.last_run, other.last_run) {
-                (Some(a), Some(b)) => a.cmp(&b),
-                (Some(_), None) => Ordering::Less,
-                (None, Some(_)) => Ordering::Greater,
-                (None, None) => Ordering::Equal,
-            })
+        priority_to_weight(self.sched_data.priority)
     }
 
     /// Update accounting information when the task is about to be inserted into
     /// a runqueue.
     pub fn inserting_into_runqueue(&mut self, vclock: u128) {
-        // A freshly enqueued task becomes eligible immediately.
-        self.v_eligible = vclock;
+        let weight = self.weight();
 
-        // Grant it an initial virtual deadline proportional to its weight.
-        let q_ns: u128 = DEFAULT_TIME_SLICE.as_nanos();
-        let v_delta = (q_ns << VT_FIXED_SHIFT) / self.weight() as u128;
-        self.v_deadline = vclock + v_delta;
+        self.sched_data
+            .eevdf
+            .place(vclock, weight, DEFAULT_TIME_SLICE);
 
         // Since the task is not executing yet, its exec_start must be `None`.
         self.exec_start = None;
@@ -209,6 +202,19 @@ impl RunnableTask {
         self.exec_start = Some(now);
         self.work.state.activate();
 
+        // A deadline task runs until its runtime is used up.
+        if let Some(dl) = self.sched_data.dl.as_mut() {
+            if dl.deadline.is_none() {
+                dl.start_period(now);
+            }
+
+            let d = now + dl.runtime_left();
+            self.sched_data.deadline = Some(d);
+            schedule_preempt(d);
+
+            return;
+        }
+
         // Deadline logic
         if self.deadline.is_none_or(|d| d <= now + DEFAULT_TIME_SLICE) {
             self.deadline = Some(now + DEFAULT_TIME_SLICE);
@@ -226,4 +232,37 @@ impl RunnableTask {
     pub fn refresh_priority(&mut self) {
         self.sched_data.priority = self.work.task.priority();
     }
+
+    /// Picks up any change to the task's scheduling policy.
+    pub fn refresh_policy(&mut self) {
+        let policy = *self.work.policy.lock_save_irq();
+
+        match policy {
+            SchedPolicy::Deadline(params) => {
+                if self
+                    .sched_data
+                    .dl
+                    .as_ref()
+                    .is_none_or(|dl| dl.params != params)
+                {
+                    self.sched_data.dl = Some(DlEntity::new(params));
+                }
+            }
+            SchedPolicy::Normal => self.sched_data.dl = None,
+        }
+    }
+
+    /// Returns the absolute deadline of a deadline task, which is `None` for
+    /// one that has yet to start its first period.
+    pub fn dl_deadline(&self) -> Option<Instant> {
+        self.sched_data.dl.as_ref().and_then(|dl| dl.deadline)
+    }
+
+    /// Returns when a deadline task that has used up its runtime gets more.
+    pub fn throttled_until(&self) -> Option<Instant> {
+        self.sched_data
+            .dl
+            .as_ref()
+            .and_then(|dl| dl.throttled_until)
+    }
 }
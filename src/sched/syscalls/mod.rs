@@ -1,14 +1,47 @@
-use crate::arch::{Arch, ArchImpl};
-use crate::memory::uaccess::{copy_from_user_slice, copy_to_user_slice};
+use crate::memory::uaccess::{
+    UserCopyable, copy_from_user, copy_from_user_slice, copy_to_user, copy_to_user_slice,
+};
 use crate::process::thread_group::pid::PidT;
-use crate::sched::sched_task::CPU_MASK_SIZE;
+use crate::process::{Tid, find_task_by_tid};
+use crate::sched::deadline::{DlParams, dl_admit};
+use crate::sched::isolation::possible_mask;
+use crate::sched::sched_task::{CPU_MASK_SIZE, CpuMask, Work};
 use crate::sched::syscall_ctx::ProcessCtx;
-use crate::sched::{current_work, schedule};
+use crate::sched::{
+    NICE_MAX, NICE_MIN, SCHED_DEADLINE, SCHED_OTHER, SchedPolicy, current_work, nice_to_priority,
+    priority_to_nice, schedule_in_place,
+};
+use alloc::sync::Arc;
 use alloc::vec;
-use libkernel::memory::address::UA;
+use libkernel::error::{KernelError, Result};
+use libkernel::memory::PAGE_SIZE;
+use libkernel::memory::address::{TUA, UA};
+use libkernel::proc::caps::CapabilitiesFlags;
+
+/// Accepted, but changes nothing: children never inherit the deadline class.
+const SCHED_FLAG_RESET_ON_FORK: u64 = 1;
+
+/// The arguments of `sched_setattr()` and `sched_getattr()`, as of the first
+/// version of the struct. Later versions add utilisation clamps, which aren't
+/// supported.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SchedAttr {
+    size: u32,
+    sched_policy: u32,
+    sched_flags: u64,
+    sched_nice: i32,
+    sched_priority: u32,
+    sched_runtime: u64,
+    sched_deadline: u64,
+    sched_period: u64,
+}
+
+unsafe impl UserCopyable for SchedAttr {}
 
 pub fn sys_sched_yield() -> libkernel::error::Result<usize> {
-    schedule();
+    // We're in the middle of the syscall, so the task mustn't move CPU.
+    schedule_in_place();
     Ok(0)
 }
 
@@ -18,19 +51,8 @@ pub async fn sys_sched_getaffinity(
     size: usize,
     mask: UA,
 ) -> libkernel::error::Result<usize> {
-    let task = if pid == 0 {
-        current_work()
-    } else {
-        // TODO: Support getting affinity of other tasks if PERM_NICE
-        return Err(libkernel::error::KernelError::InvalidValue);
-    };
-    let cpu_mask = {
-        let sched_data = task.sched_data.lock_save_irq();
-        sched_data
-            .as_ref()
-            .map(|data| data.cpu_mask)
-            .unwrap_or([u8::MAX; CPU_MASK_SIZE])
-    };
+    let task = find_work(pid)?;
+    let cpu_mask = *task.cpu_mask.lock_save_irq();
     let mut cpu_mask: &[u8] = &cpu_mask;
     if CPU_MASK_SIZE > size {
         cpu_mask = &cpu_mask[..size];
@@ -39,39 +61,208 @@ pub async fn sys_sched_getaffinity(
     Ok(cpu_mask.len())
 }
 
+/// Sets the CPUs the thread `pid` may run on, which may include isolated
+/// ones. The thread moves the next time it enters the kernel, if it has to.
 pub async fn sys_sched_setaffinity(
-    _ctx: &ProcessCtx,
+    ctx: &ProcessCtx,
     pid: PidT,
     size: usize,
     mask: UA,
 ) -> libkernel::error::Result<usize> {
     let mut cpu_set = vec![0u8; size];
     copy_from_user_slice(mask, cpu_set.as_mut_slice()).await?;
-    let task = if pid == 0 {
-        current_work()
-    } else {
-        // TODO: Support setting affinity of other tasks if PERM_NICE
-        return Err(libkernel::error::KernelError::InvalidValue);
-    };
-    let mut sched_data = task.sched_data.lock_save_irq();
     if CPU_MASK_SIZE > size {
         return Err(libkernel::error::KernelError::InvalidValue);
     }
     cpu_set.truncate(CPU_MASK_SIZE);
-    // Check if this turns off all CPUs, which is not allowed.
-    let mut any_true = false;
-    for i in 0..ArchImpl::cpu_count() {
-        let byte_index = i / 8;
-        let bit_index = i % 8;
-        if (cpu_set[byte_index] & (1 << bit_index)) != 0 {
-            any_true = true;
-            break;
-        }
+    let mut cpu_mask: CpuMask = cpu_set.try_into().unwrap();
+    // CPUs that don't exist are dropped, and some other CPU must be left.
+    for (byte, possible) in cpu_mask.iter_mut().zip(possible_mask()) {
+        *byte &= possible;
     }
-    if !any_true {
+    if cpu_mask.iter().all(|byte| *byte == 0) {
         return Err(libkernel::error::KernelError::InvalidValue);
     }
-    sched_data.as_mut().unwrap().cpu_mask = cpu_set.try_into().unwrap();
-    // TODO: apply the new affinity immediately if the current CPU is no longer in the set
+    let task = find_work(pid)?;
+    check_can_change(ctx, &task)?;
+    *task.cpu_mask.lock_save_irq() = cpu_mask;
+    Ok(0)
+}
+
+/// Returns the task `pid` refers to, which is the caller for zero.
+fn find_work(pid: PidT) -> Result<Arc<Work>> {
+    match pid {
+        0 => Ok(current_work()),
+        pid if pid < 0 => Err(KernelError::InvalidValue),
+        pid => find_task_by_tid(Tid::from_pid_t(pid)).ok_or(KernelError::NoProcess),
+    }
+}
+
+/// Checks that the caller may change how `work` is scheduled, which takes
+/// `CAP_SYS_NICE` for another user's thread. Returns whether the caller has
+/// `CAP_SYS_NICE`.
+fn check_can_change(ctx: &ProcessCtx, work: &Arc<Work>) -> Result<bool> {
+    let (euid, can_sys_nice) = {
+        let creds = ctx.shared().creds.lock_save_irq();
+
+        (
+            creds.euid(),
+            creds.caps().is_capable(CapabilitiesFlags::CAP_SYS_NICE),
+        )
+    };
+
+    if !Arc::ptr_eq(work, &current_work()) && !can_sys_nice {
+        let target = work.creds.lock_save_irq();
+
+        if euid != target.uid() && euid != target.euid() {
+            return Err(KernelError::NotPermitted);
+        }
+    }
+
+    Ok(can_sys_nice)
+}
+
+/// Tells the caller of `sched_setattr()` the size of `sched_attr` we know.
+async fn sched_attr_too_big(uattr: TUA<SchedAttr>) -> Result<usize> {
+    copy_to_user(
+        TUA::<u32>::from_value(uattr.value()),
+        size_of::<SchedAttr>() as u32,
+    )
+    .await?;
+
+    Err(KernelError::TooLarge)
+}
+
+/// Copies in the `sched_attr` at `uattr`, whose size is given by its first
+/// field.
+async fn copy_sched_attr(uattr: TUA<SchedAttr>) -> Result<SchedAttr> {
+    let size = match copy_from_user(TUA::<u32>::from_value(uattr.value())).await? {
+        0 => size_of::<SchedAttr>(),
+        size => size as usize,
+    };
+
+    if size < size_of::<SchedAttr>() || size > PAGE_SIZE {
+        sched_attr_too_big(uattr).await?;
+    }
+
+    // Fields from a newer version of the struct than ours must be unset.
+    let mut chunk = [0; 64];
+    let mut offset = size_of::<SchedAttr>();
+
+    while offset < size {
+        let len = chunk.len().min(size - offset);
+
+        copy_from_user_slice(uattr.to_untyped().add_bytes(offset), &mut chunk[..len]).await?;
+
+        if chunk[..len].iter().any(|b| *b != 0) {
+            sched_attr_too_big(uattr).await?;
+        }
+
+        offset += len;
+    }
+
+    copy_from_user(uattr).await
+}
+
+/// Sets the scheduling policy of the thread `pid`, and its nice value or
+/// deadline parameters.
+///
+/// Only `SCHED_OTHER` and `SCHED_DEADLINE` are supported. Changing another
+/// user's thread, becoming a deadline task or lowering the nice value takes
+/// `CAP_SYS_NICE`, and a deadline task is only admitted if there's bandwidth
+/// to spare for it.
+pub async fn sys_sched_setattr(
+    ctx: &ProcessCtx,
+    pid: PidT,
+    uattr: TUA<SchedAttr>,
+    flags: u32,
+) -> Result<usize> {
+    if uattr.is_null() || pid < 0 || flags != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let attr = copy_sched_attr(uattr).await?;
+
+    if attr.sched_flags & !SCHED_FLAG_RESET_ON_FORK != 0 || attr.sched_priority != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let policy = match attr.sched_policy {
+        SCHED_OTHER => SchedPolicy::Normal,
+        SCHED_DEADLINE => SchedPolicy::Deadline(DlParams::new(
+            attr.sched_runtime,
+            attr.sched_deadline,
+            attr.sched_period,
+        )?),
+        _ => return Err(KernelError::InvalidValue),
+    };
+
+    let work = find_work(pid)?;
+    let can_sys_nice = check_can_change(ctx, &work)?;
+
+    let nice = attr.sched_nice.clamp(NICE_MIN, NICE_MAX);
+
+    match policy {
+        SchedPolicy::Deadline(_) if !can_sys_nice => return Err(KernelError::NotPermitted),
+        SchedPolicy::Normal if nice < priority_to_nice(work.task.priority()) && !can_sys_nice => {
+            return Err(KernelError::NotPermitted);
+        }
+        _ => {}
+    }
+
+    {
+        let mut cur = work.policy.lock_save_irq();
+        let params = |policy: &SchedPolicy| match policy {
+            SchedPolicy::Deadline(params) => Some(*params),
+            SchedPolicy::Normal => None,
+        };
+
+        dl_admit(params(&cur).as_ref(), params(&policy).as_ref())?;
+        *cur = policy;
+    }
+
+    if policy == SchedPolicy::Normal {
+        *work.process.priority.lock_save_irq() = nice_to_priority(nice);
+    }
+
+    Ok(0)
+}
+
+/// Reads the scheduling policy of the thread `pid` into `uattr`, a buffer of
+/// `size` bytes.
+pub async fn sys_sched_getattr(
+    _ctx: &ProcessCtx,
+    pid: PidT,
+    uattr: TUA<SchedAttr>,
+    size: u32,
+    flags: u32,
+) -> Result<usize> {
+    if uattr.is_null()
+        || pid < 0
+        || flags != 0
+        || (size as usize) < size_of::<SchedAttr>()
+        || size as usize > PAGE_SIZE
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let work = find_work(pid)?;
+    let policy = *work.policy.lock_save_irq();
+
+    let mut attr = SchedAttr {
+        size: size_of::<SchedAttr>() as u32,
+        sched_policy: policy.as_raw(),
+        sched_nice: priority_to_nice(work.task.priority()),
+        ..Default::default()
+    };
+
+    if let SchedPolicy::Deadline(params) = policy {
+        attr.sched_runtime = params.runtime.as_nanos() as u64;
+        attr.sched_deadline = params.deadline.as_nanos() as u64;
+        attr.sched_period = params.period.as_nanos() as u64;
+    }
+
+    copy_to_user(uattr, attr).await?;
+
     Ok(0)
 }
@@ -184,6 +184,11 @@ pub fn dispatch_userspace_task(frame: *mut UserCtx) {
 
                 // We never handle signals for the idle task.
                 if ctx.task().is_idle_task() {
+                    // With nothing else to do, this CPU may as well look for
+                    // leaks.
+                    #[cfg(feature = "kmemleak")]
+                    crate::memory::kmemleak::scan_if_due();
+
                     state = State::ReturnToUserspace;
                     continue;
                 }
@@ -1,5 +1,5 @@
 use crate::arch::{Arch, ArchImpl};
-use crate::console::write_fmt;
+use crate::console::{self, write_fmt};
 use crate::drivers::timer::uptime;
 use alloc::format;
 use core::fmt::Display;
@@ -57,6 +57,7 @@ pub fn test_runner(tests: &[&Test]) {
         duration.subsec_millis() / 10
     ))
     .unwrap();
+    console::flush();
     ArchImpl::power_off();
 }
 
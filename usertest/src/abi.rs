@@ -0,0 +1,377 @@
+//! Golden layouts of the structs the kernel fills in, checked byte by byte
+//! against the arm64 Linux ABI. Each call goes straight to the kernel with a
+//! buffer full of a sentinel, so that neither libc's view of the struct nor a
+//! short copy can hide a field in the wrong place.
+
+use crate::register_test;
+use std::ffi::CString;
+
+const SENTINEL: u8 = 0xa5;
+/// Room left after each struct, which the kernel mustn't touch.
+const SLACK: usize = 16;
+const STATX_BASIC_STATS: u32 = 0x7ff;
+
+fn errno() -> i32 {
+    unsafe { *libc::__errno_location() }
+}
+
+/// A buffer for a `len`-byte struct, with slack after it.
+fn golden_buf(len: usize) -> Vec<u8> {
+    vec![SENTINEL; len + SLACK]
+}
+
+fn assert_untouched_after(buf: &[u8], len: usize) {
+    assert!(
+        buf[len..].iter().all(|&b| b == SENTINEL),
+        "wrote past the {len}-byte struct"
+    );
+}
+
+fn u16_at(buf: &[u8], off: usize) -> u16 {
+    u16::from_ne_bytes(buf[off..off + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], off: usize) -> u32 {
+    u32::from_ne_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], off: usize) -> u64 {
+    u64::from_ne_bytes(buf[off..off + 8].try_into().unwrap())
+}
+
+fn test_abi_stat() {
+    let path = CString::new("/tmp/abi_stat").unwrap();
+    std::fs::write("/tmp/abi_stat", vec![7u8; 1234]).unwrap();
+
+    let mut buf = golden_buf(128);
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_newfstatat,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            buf.as_mut_ptr(),
+            0,
+        )
+    };
+    assert_eq!(ret, 0);
+    assert_untouched_after(&buf, 128);
+
+    let ino = u64_at(&buf, 8);
+    assert_ne!(ino, 0);
+    assert_eq!(u32_at(&buf, 16) & libc::S_IFMT, libc::S_IFREG);
+    assert_eq!(u32_at(&buf, 20), 1, "st_nlink");
+    assert_eq!(u32_at(&buf, 24), unsafe { libc::getuid() }, "st_uid");
+    assert_eq!(u64_at(&buf, 48), 1234, "st_size");
+    assert!(u32_at(&buf, 56).is_power_of_two(), "st_blksize");
+    assert_ne!(u64_at(&buf, 88), 0, "st_mtime");
+    assert!(u64_at(&buf, 96) < 1_000_000_000, "st_mtime_nsec");
+
+    // The same file as seen by statx.
+    let mut sx = golden_buf(256);
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_statx,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            0,
+            STATX_BASIC_STATS,
+            sx.as_mut_ptr(),
+        )
+    };
+    assert_eq!(ret, 0);
+    assert_untouched_after(&sx, 256);
+
+    assert_eq!(u32_at(&sx, 0) & STATX_BASIC_STATS, STATX_BASIC_STATS);
+    assert_eq!(u32_at(&sx, 16), 1, "stx_nlink");
+    assert_eq!(u16_at(&sx, 28) as u32 & libc::S_IFMT, libc::S_IFREG);
+    assert_eq!(u64_at(&sx, 32), ino, "stx_ino");
+    assert_eq!(u64_at(&sx, 40), 1234, "stx_size");
+    assert_eq!(u64_at(&sx, 112), u64_at(&buf, 88), "stx_mtime");
+
+    std::fs::remove_file("/tmp/abi_stat").unwrap();
+}
+
+register_test!(test_abi_stat);
+
+fn test_abi_statx_device() {
+    let path = CString::new("/dev/null").unwrap();
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    assert_eq!(unsafe { libc::stat(path.as_ptr(), &mut st) }, 0);
+
+    let mut sx = golden_buf(256);
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_statx,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            0,
+            STATX_BASIC_STATS,
+            sx.as_mut_ptr(),
+        )
+    };
+    assert_eq!(ret, 0);
+    assert_untouched_after(&sx, 256);
+
+    // The device numbers come straight after the timestamps.
+    assert_eq!(u16_at(&sx, 28) as u32 & libc::S_IFMT, libc::S_IFCHR);
+    assert_eq!(u32_at(&sx, 128), libc::major(st.st_rdev), "stx_rdev_major");
+    assert_eq!(u32_at(&sx, 132), libc::minor(st.st_rdev), "stx_rdev_minor");
+}
+
+register_test!(test_abi_statx_device);
+
+fn test_abi_dirent64() {
+    let dir = "/tmp/abi_dirent";
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir(dir).unwrap();
+    std::fs::write(format!("{dir}/golden"), b"").unwrap();
+    std::fs::create_dir(format!("{dir}/subdir")).unwrap();
+
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    let file = CString::new(format!("{dir}/golden")).unwrap();
+    assert_eq!(unsafe { libc::stat(file.as_ptr(), &mut st) }, 0);
+
+    let cdir = CString::new(dir).unwrap();
+    let fd = unsafe { libc::open(cdir.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY) };
+    assert!(fd >= 0);
+
+    let mut buf = golden_buf(1024);
+    let len = unsafe { libc::syscall(libc::SYS_getdents64, fd, buf.as_mut_ptr(), 1024) };
+    assert!(len > 0);
+    let len = len as usize;
+    assert_untouched_after(&buf, len);
+
+    // d_ino, d_off, d_reclen, d_type, then the name with its NUL.
+    let mut seen = Vec::new();
+    let mut off = 0;
+
+    while off < len {
+        let reclen = u16_at(&buf, off + 16) as usize;
+        assert!(
+            reclen >= 20 && reclen.is_multiple_of(8),
+            "d_reclen {reclen}"
+        );
+
+        let kind = buf[off + 18];
+        let name = &buf[off + 19..off + reclen];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap()];
+
+        match name {
+            b"golden" => {
+                assert_eq!(u64_at(&buf, off), st.st_ino, "d_ino");
+                assert_eq!(kind, libc::DT_REG);
+            }
+            b"subdir" | b"." | b".." => assert_eq!(kind, libc::DT_DIR),
+            _ => panic!("unexpected entry {:?}", String::from_utf8_lossy(name)),
+        }
+
+        seen.push(name.to_vec());
+        off += reclen;
+    }
+
+    assert_eq!(off, len);
+    assert!(seen.iter().any(|n| n == b"golden"));
+    assert!(seen.iter().any(|n| n == b"subdir"));
+
+    unsafe { libc::close(fd) };
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+register_test!(test_abi_dirent64);
+
+fn test_abi_siginfo() {
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0);
+    if pid == 0 {
+        unsafe { libc::_exit(42) };
+    }
+
+    let mut buf = golden_buf(128);
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_waitid,
+            libc::P_PID,
+            pid,
+            buf.as_mut_ptr(),
+            libc::WEXITED,
+            std::ptr::null_mut::<libc::rusage>(),
+        )
+    };
+    assert_eq!(ret, 0);
+    assert_untouched_after(&buf, 128);
+
+    assert_eq!(u32_at(&buf, 0) as i32, libc::SIGCHLD, "si_signo");
+    assert_eq!(u32_at(&buf, 4), 0, "si_errno");
+    assert_eq!(u32_at(&buf, 8) as i32, libc::CLD_EXITED, "si_code");
+    assert_eq!(u32_at(&buf, 16) as i32, pid, "si_pid");
+    assert_eq!(u32_at(&buf, 20), unsafe { libc::getuid() }, "si_uid");
+    assert_eq!(u32_at(&buf, 24), 42, "si_status");
+}
+
+register_test!(test_abi_siginfo);
+
+extern "C" fn abi_handler(_: libc::c_int) {}
+
+fn test_abi_sigaction() {
+    // The kernel's own `struct sigaction`: handler, flags, restorer, mask.
+    let mut act = [0u8; 32];
+    let flags = (libc::SA_SIGINFO | libc::SA_RESTART | libc::SA_NOCLDSTOP) as u64;
+    let mask = 1u64 << (libc::SIGUSR1 - 1);
+    act[0..8].copy_from_slice(&(abi_handler as *const () as u64).to_ne_bytes());
+    act[8..16].copy_from_slice(&flags.to_ne_bytes());
+    act[24..32].copy_from_slice(&mask.to_ne_bytes());
+
+    let mut old = golden_buf(32);
+    unsafe {
+        assert_eq!(
+            libc::syscall(
+                libc::SYS_rt_sigaction,
+                libc::SIGUSR2,
+                act.as_ptr(),
+                std::ptr::null_mut::<u8>(),
+                8
+            ),
+            0
+        );
+        assert_eq!(
+            libc::syscall(
+                libc::SYS_rt_sigaction,
+                libc::SIGUSR2,
+                std::ptr::null::<u8>(),
+                old.as_mut_ptr(),
+                8
+            ),
+            0
+        );
+    }
+    assert_untouched_after(&old, 32);
+    assert_eq!(u64_at(&old, 0), abi_handler as *const () as u64);
+    assert_eq!(u64_at(&old, 8), flags, "sa_flags");
+    assert_eq!(u64_at(&old, 24), mask, "sa_mask");
+
+    // The mask is a single word here; any other size is refused.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_rt_sigaction,
+            libc::SIGUSR2,
+            std::ptr::null::<u8>(),
+            old.as_mut_ptr(),
+            16,
+        )
+    };
+    assert_eq!(ret, -1);
+    assert_eq!(errno(), libc::EINVAL);
+}
+
+register_test!(test_abi_sigaction);
+
+fn test_abi_statfs() {
+    let root = CString::new("/").unwrap();
+    let mut buf = golden_buf(120);
+    let ret = unsafe { libc::syscall(libc::SYS_statfs, root.as_ptr(), buf.as_mut_ptr()) };
+    assert_eq!(ret, 0);
+    assert_untouched_after(&buf, 120);
+
+    assert_eq!(u64_at(&buf, 0), 0xef53, "f_type");
+    assert!(u64_at(&buf, 8).is_power_of_two(), "f_bsize");
+    assert!(u64_at(&buf, 24) <= u64_at(&buf, 16), "f_bfree");
+    assert!(u64_at(&buf, 32) <= u64_at(&buf, 24), "f_bavail");
+    assert!(u64_at(&buf, 48) <= u64_at(&buf, 40), "f_ffree");
+    assert_eq!(u64_at(&buf, 64), 255, "f_namelen");
+    assert_eq!(u64_at(&buf, 72), u64_at(&buf, 8), "f_frsize");
+}
+
+register_test!(test_abi_statfs);
+
+fn test_abi_epoll_event() {
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    let epfd = unsafe { libc::epoll_create1(0) };
+    assert!(epfd >= 0);
+
+    // events, then four bytes of padding, then the data word.
+    let cookie = 0x1122_3344_5566_7788u64;
+    let mut ev = [0u8; 16];
+    ev[0..4].copy_from_slice(&(libc::EPOLLIN as u32).to_ne_bytes());
+    ev[8..16].copy_from_slice(&cookie.to_ne_bytes());
+
+    let mut out = golden_buf(16);
+    unsafe {
+        assert_eq!(
+            libc::syscall(
+                libc::SYS_epoll_ctl,
+                epfd,
+                libc::EPOLL_CTL_ADD,
+                pipe[0],
+                ev.as_ptr()
+            ),
+            0
+        );
+        assert_eq!(libc::write(pipe[1], b"x".as_ptr().cast(), 1), 1);
+        assert_eq!(
+            libc::syscall(
+                libc::SYS_epoll_pwait,
+                epfd,
+                out.as_mut_ptr(),
+                1,
+                1000,
+                std::ptr::null::<u8>(),
+                8
+            ),
+            1
+        );
+    }
+    assert_untouched_after(&out, 16);
+    assert_eq!(u32_at(&out, 0), libc::EPOLLIN as u32);
+    assert_eq!(u64_at(&out, 8), cookie);
+
+    unsafe {
+        libc::close(epfd);
+        libc::close(pipe[0]);
+        libc::close(pipe[1]);
+    }
+}
+
+register_test!(test_abi_epoll_event);
+
+fn test_abi_stack_t() {
+    let stack = vec![0u8; 4 * 4096];
+
+    // ss_sp, ss_flags and padding, ss_size.
+    let mut ss = [0u8; 24];
+    ss[0..8].copy_from_slice(&(stack.as_ptr() as u64).to_ne_bytes());
+    ss[16..24].copy_from_slice(&(stack.len() as u64).to_ne_bytes());
+
+    let mut old = golden_buf(24);
+    unsafe {
+        assert_eq!(
+            libc::syscall(
+                libc::SYS_sigaltstack,
+                ss.as_ptr(),
+                std::ptr::null_mut::<u8>()
+            ),
+            0
+        );
+        assert_eq!(
+            libc::syscall(
+                libc::SYS_sigaltstack,
+                std::ptr::null::<u8>(),
+                old.as_mut_ptr()
+            ),
+            0
+        );
+    }
+    assert_untouched_after(&old, 24);
+    assert_eq!(u64_at(&old, 0), stack.as_ptr() as u64, "ss_sp");
+    assert_eq!(u32_at(&old, 8), 0, "ss_flags");
+    assert_eq!(u64_at(&old, 16), stack.len() as u64, "ss_size");
+
+    let off = libc::stack_t {
+        ss_sp: std::ptr::null_mut(),
+        ss_flags: libc::SS_DISABLE,
+        ss_size: 0,
+    };
+    assert_eq!(unsafe { libc::sigaltstack(&off, std::ptr::null_mut()) }, 0);
+}
+
+register_test!(test_abi_stack_t);
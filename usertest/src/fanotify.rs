@@ -0,0 +1,217 @@
+use crate::register_test;
+use std::{ffi::CString, fs, mem::size_of};
+
+fn read_event(fd: i32) -> libc::fanotify_event_metadata {
+    let mut event = std::mem::MaybeUninit::<libc::fanotify_event_metadata>::uninit();
+    let n = unsafe {
+        libc::read(
+            fd,
+            event.as_mut_ptr().cast(),
+            size_of::<libc::fanotify_event_metadata>(),
+        )
+    };
+    assert_eq!(n as usize, size_of::<libc::fanotify_event_metadata>());
+    unsafe { event.assume_init() }
+}
+
+fn respond(fd: i32, event_fd: i32, response: u32) {
+    let resp = libc::fanotify_response {
+        fd: event_fd,
+        response,
+    };
+    let n = unsafe {
+        libc::write(
+            fd,
+            (&resp as *const libc::fanotify_response).cast(),
+            size_of::<libc::fanotify_response>(),
+        )
+    };
+    assert_eq!(n as usize, size_of::<libc::fanotify_response>());
+}
+
+fn test_fanotify_open_perm() {
+    let path = "/tmp/fanotify_perm_test";
+    fs::write(path, b"data").unwrap();
+    let c_path = CString::new(path).unwrap();
+
+    unsafe {
+        let fan = libc::fanotify_init(libc::FAN_CLASS_CONTENT, libc::O_RDONLY as _);
+        assert!(fan >= 0, "fanotify_init failed");
+
+        let ret = libc::fanotify_mark(
+            fan,
+            libc::FAN_MARK_ADD,
+            libc::FAN_OPEN_PERM,
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+        );
+        assert_eq!(ret, 0, "fanotify_mark failed");
+
+        for (response, expected) in [(libc::FAN_DENY, false), (libc::FAN_ALLOW, true)] {
+            let pid = libc::fork();
+            assert!(pid >= 0, "fork failed");
+
+            if pid == 0 {
+                let fd = libc::open(c_path.as_ptr(), libc::O_RDONLY);
+                let ok = if expected {
+                    fd >= 0
+                } else {
+                    fd == -1 && *libc::__errno_location() == libc::EPERM
+                };
+                libc::_exit(if ok { 0 } else { 1 });
+            }
+
+            let event = read_event(fan);
+            assert_eq!(event.mask, libc::FAN_OPEN_PERM);
+            assert_eq!(event.pid, pid);
+            assert!(event.fd >= 0);
+
+            respond(fan, event.fd, response);
+            libc::close(event.fd);
+
+            let mut status = 0;
+            libc::waitpid(pid, &mut status, 0);
+            assert!(libc::WIFEXITED(status));
+            assert_eq!(libc::WEXITSTATUS(status), 0, "unexpected open result");
+        }
+
+        libc::close(fan);
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+register_test!(test_fanotify_open_perm);
+
+fn test_fanotify_mount_mark() {
+    let path = "/tmp/fanotify_mount_test";
+    fs::write(path, b"data").unwrap();
+    let c_path = CString::new(path).unwrap();
+    let c_dir = CString::new("/tmp").unwrap();
+
+    unsafe {
+        let fan = libc::fanotify_init(
+            libc::FAN_CLASS_NOTIF | libc::FAN_NONBLOCK,
+            libc::O_RDONLY as _,
+        );
+        assert!(fan >= 0, "fanotify_init failed");
+
+        let ret = libc::fanotify_mark(
+            fan,
+            libc::FAN_MARK_ADD | libc::FAN_MARK_MOUNT,
+            libc::FAN_OPEN,
+            libc::AT_FDCWD,
+            c_dir.as_ptr(),
+        );
+        assert_eq!(ret, 0, "fanotify_mark failed");
+
+        let fd = libc::open(c_path.as_ptr(), libc::O_RDONLY);
+        assert!(fd >= 0);
+        libc::close(fd);
+
+        // The event comes with a descriptor for the file that was opened.
+        let event = read_event(fan);
+        assert_eq!(event.mask, libc::FAN_OPEN);
+        assert_eq!(event.pid, libc::getpid());
+        assert!(event.fd >= 0);
+
+        let mut ev_stat: libc::stat = std::mem::zeroed();
+        let mut stat: libc::stat = std::mem::zeroed();
+        assert_eq!(libc::fstat(event.fd, &mut ev_stat), 0);
+        assert_eq!(libc::stat(c_path.as_ptr(), &mut stat), 0);
+        assert_eq!(ev_stat.st_ino, stat.st_ino);
+        libc::close(event.fd);
+
+        // Mount and filesystem marks are exclusive.
+        let ret = libc::fanotify_mark(
+            fan,
+            libc::FAN_MARK_ADD | libc::FAN_MARK_MOUNT | libc::FAN_MARK_FILESYSTEM,
+            libc::FAN_OPEN,
+            libc::AT_FDCWD,
+            c_dir.as_ptr(),
+        );
+        assert_eq!(ret, -1);
+
+        // Once the mount mark is flushed, nothing more is reported.
+        let ret = libc::fanotify_mark(
+            fan,
+            libc::FAN_MARK_FLUSH | libc::FAN_MARK_MOUNT,
+            0,
+            libc::AT_FDCWD,
+            std::ptr::null(),
+        );
+        assert_eq!(ret, 0);
+
+        let fd = libc::open(c_path.as_ptr(), libc::O_RDONLY);
+        assert!(fd >= 0);
+        libc::close(fd);
+
+        let mut buf = [0u8; 64];
+        let n = libc::read(fan, buf.as_mut_ptr().cast(), buf.len());
+        assert_eq!(n, -1);
+        assert_eq!(*libc::__errno_location(), libc::EAGAIN);
+
+        libc::close(fan);
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+register_test!(test_fanotify_mount_mark);
+
+fn test_fanotify_read_fault() {
+    let path = "/tmp/fanotify_fault_test";
+    fs::write(path, b"data").unwrap();
+    let c_path = CString::new(path).unwrap();
+
+    unsafe {
+        let fan = libc::fanotify_init(libc::FAN_CLASS_CONTENT, libc::O_RDONLY as _);
+        assert!(fan >= 0, "fanotify_init failed");
+
+        let ret = libc::fanotify_mark(
+            fan,
+            libc::FAN_MARK_ADD,
+            libc::FAN_OPEN_PERM,
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+        );
+        assert_eq!(ret, 0, "fanotify_mark failed");
+
+        // The lowest free descriptor, which the event's fd would take.
+        let free_fd = libc::dup(0);
+        libc::close(free_fd);
+
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            let fd = libc::open(c_path.as_ptr(), libc::O_RDONLY);
+            let denied = fd == -1 && *libc::__errno_location() == libc::EPERM;
+            libc::_exit(if denied { 0 } else { 1 });
+        }
+
+        // An event that can't be handed over is denied and its fd closed.
+        let n = libc::read(
+            fan,
+            8 as *mut libc::c_void,
+            size_of::<libc::fanotify_event_metadata>(),
+        );
+        assert_eq!(n, -1);
+        assert_eq!(*libc::__errno_location(), libc::EFAULT);
+
+        let mut status = 0;
+        libc::waitpid(pid, &mut status, 0);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0, "open wasn't denied");
+
+        let fd = libc::dup(0);
+        assert_eq!(fd, free_fd);
+        libc::close(fd);
+
+        libc::close(fan);
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+register_test!(test_fanotify_read_fault);
@@ -91,6 +91,70 @@ fn test_fchdir() {
 
 register_test!(test_fchdir);
 
+fn test_getcwd_tracking() {
+    use std::env::{current_dir, set_current_dir};
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::{OpenOptionsExt, symlink};
+    use std::path::Path;
+
+    let saved = current_dir().unwrap();
+    let base = "/tmp/getcwd_test";
+    fs::create_dir_all(format!("{base}/a/b")).unwrap();
+    symlink("a", format!("{base}/link")).unwrap();
+
+    // Renaming a directory above the cwd shows up in its path.
+    set_current_dir(format!("{base}/a/b")).unwrap();
+    fs::rename(format!("{base}/a"), format!("{base}/c")).unwrap();
+    assert_eq!(current_dir().unwrap(), Path::new("/tmp/getcwd_test/c/b"));
+    assert_eq!(
+        fs::read_link("/proc/self/cwd").unwrap(),
+        Path::new("/tmp/getcwd_test/c/b")
+    );
+
+    // The path is the real one, without the symlinks it was reached by.
+    fs::remove_file(format!("{base}/link")).unwrap();
+    symlink("c", format!("{base}/link")).unwrap();
+    set_current_dir(format!("{base}/link/b")).unwrap();
+    assert_eq!(current_dir().unwrap(), Path::new("/tmp/getcwd_test/c/b"));
+
+    // A buffer that's too small is refused.
+    let mut buf = [0u8; 8];
+    unsafe {
+        assert!(libc::getcwd(buf.as_mut_ptr().cast(), buf.len()).is_null());
+        assert_eq!(*libc::__errno_location(), libc::ERANGE);
+    }
+
+    // fchdir() works from a descriptor that was only opened for its path,
+    // but only onto a directory.
+    let dir = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+        .open(base)
+        .unwrap();
+    assert_eq!(unsafe { libc::fchdir(dir.as_raw_fd()) }, 0);
+    assert_eq!(current_dir().unwrap(), Path::new(base));
+
+    let file = fs::File::create(format!("{base}/file")).unwrap();
+    unsafe {
+        assert_eq!(libc::fchdir(file.as_raw_fd()), -1);
+        assert_eq!(*libc::__errno_location(), libc::ENOTDIR);
+    }
+
+    // Once the cwd has been removed, it has no path.
+    set_current_dir(format!("{base}/c/b")).unwrap();
+    fs::remove_dir(format!("{base}/c/b")).unwrap();
+    assert_eq!(
+        current_dir().unwrap_err().raw_os_error(),
+        Some(libc::ENOENT)
+    );
+
+    set_current_dir(saved).unwrap();
+    drop((dir, file));
+    fs::remove_dir_all(base).unwrap();
+}
+
+register_test!(test_getcwd_tracking);
+
 fn test_chroot() {
     let file = "/bin/busybox";
     let c_file = CString::new(file).unwrap();
@@ -332,6 +396,117 @@ fn test_symlink() {
 
 register_test!(test_symlink);
 
+fn test_symlink_resolution() {
+    use std::os::unix::fs::{MetadataExt, symlink};
+
+    let errno = || std::io::Error::last_os_error().raw_os_error().unwrap();
+    let symlink_err = |target: &str, link: &str| {
+        let target = CString::new(target).unwrap();
+        let link = CString::new(link).unwrap();
+        assert_eq!(unsafe { libc::symlink(target.as_ptr(), link.as_ptr()) }, -1);
+        errno()
+    };
+
+    let dir = "/tmp/symlink_resolution";
+    fs::create_dir(dir).unwrap();
+    fs::create_dir(format!("{dir}/sub")).unwrap();
+    fs::write(format!("{dir}/sub/file"), b"data").unwrap();
+
+    // A link through a symlinked directory reads back, and lstat gives the
+    // length of its target.
+    symlink("sub", format!("{dir}/subl")).unwrap();
+    symlink("file", format!("{dir}/sub/rel")).unwrap();
+    assert_eq!(
+        fs::read_link(format!("{dir}/subl/rel")).unwrap().to_str(),
+        Some("file")
+    );
+    assert_eq!(fs::read(format!("{dir}/subl/rel")).unwrap(), b"data");
+    assert_eq!(
+        fs::symlink_metadata(format!("{dir}/sub/rel"))
+            .unwrap()
+            .size(),
+        4
+    );
+
+    // A name held by a dangling or looping link is taken.
+    symlink("missing", format!("{dir}/dangling")).unwrap();
+    assert_eq!(symlink_err("x", &format!("{dir}/dangling")), libc::EEXIST);
+    symlink("loop_b", format!("{dir}/loop_a")).unwrap();
+    symlink("loop_a", format!("{dir}/loop_b")).unwrap();
+    assert_eq!(symlink_err("x", &format!("{dir}/loop_a")), libc::EEXIST);
+    assert_eq!(
+        fs::read(format!("{dir}/loop_a"))
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ELOOP)
+    );
+
+    assert_eq!(symlink_err("", &format!("{dir}/empty")), libc::ENOENT);
+
+    // Targets are bounded by PATH_MAX, including the NUL.
+    let long = "a/".repeat(2047) + "a";
+    symlink(&long, format!("{dir}/long")).unwrap();
+    assert_eq!(
+        fs::read_link(format!("{dir}/long")).unwrap().to_str(),
+        Some(long.as_str())
+    );
+    assert_eq!(
+        symlink_err(&(long + "a"), &format!("{dir}/too_long")),
+        libc::ENAMETOOLONG
+    );
+
+    let c_file = CString::new(format!("{dir}/sub/file")).unwrap();
+    let mut buf = [0u8; 16];
+    let ret = unsafe { libc::readlink(c_file.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) };
+    assert_eq!((ret, errno()), (-1, libc::EINVAL));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+register_test!(test_symlink_resolution);
+
+fn test_ext4_symlink() {
+    use std::os::unix::fs::{MetadataExt, symlink};
+
+    // The root filesystem is ext4. Targets of up to 60 bytes are stored in
+    // the inode, and longer ones in a data block.
+    let dir = "/ext4_symlink_test";
+    let file = format!("{dir}/file");
+    let fast = format!("{dir}/fast");
+    let slow = format!("{dir}/slow");
+    let long_target = format!("{}/file", ["."; 100].join("/"));
+
+    fs::create_dir(dir).unwrap();
+    fs::write(&file, b"ext4").unwrap();
+
+    symlink("file", &fast).unwrap();
+    symlink(&long_target, &slow).unwrap();
+
+    for (link, target) in [(&fast, "file"), (&slow, long_target.as_str())] {
+        assert_eq!(fs::read_link(link).unwrap().to_str(), Some(target));
+        assert_eq!(
+            fs::symlink_metadata(link).unwrap().size(),
+            target.len() as u64
+        );
+        assert_eq!(fs::read(link).unwrap(), b"ext4");
+    }
+
+    // Both kinds are still there once read back from disk.
+    fs::write("/proc/sys/vm/drop_caches", "3").unwrap();
+    assert_eq!(fs::read_link(&fast).unwrap().to_str(), Some("file"));
+    assert_eq!(
+        fs::read_link(&slow).unwrap().to_str(),
+        Some(long_target.as_str())
+    );
+
+    fs::remove_file(&fast).unwrap();
+    fs::remove_file(&slow).unwrap();
+    fs::remove_file(&file).unwrap();
+    fs::remove_dir(dir).unwrap();
+}
+
+register_test!(test_ext4_symlink);
+
 fn test_rename() {
     use std::fs::{self, File};
     use std::io::{Read, Write};
@@ -368,6 +543,113 @@ fn test_rename() {
 
 register_test!(test_rename);
 
+fn test_renameat2() {
+    fn renameat2(old: &str, new: &str, flags: u32) -> Result<(), i32> {
+        let old = CString::new(old).unwrap();
+        let new = CString::new(new).unwrap();
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_renameat2,
+                libc::AT_FDCWD,
+                old.as_ptr(),
+                libc::AT_FDCWD,
+                new.as_ptr(),
+                flags,
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error().raw_os_error().unwrap());
+        }
+        Ok(())
+    }
+
+    fn mount_tmpfs(dir: &str) {
+        let c_dir = CString::new(dir).unwrap();
+        let none = CString::new("none").unwrap();
+        let tmpfs = CString::new("tmpfs").unwrap();
+        let ret = unsafe {
+            libc::mount(
+                none.as_ptr(),
+                c_dir.as_ptr(),
+                tmpfs.as_ptr(),
+                0,
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(ret, 0);
+    }
+
+    let dir = "/tmp/renameat2_test";
+    fs::create_dir(dir).unwrap();
+    mount_tmpfs(dir);
+
+    let a = format!("{dir}/a");
+    let b = format!("{dir}/b");
+    fs::write(&a, b"a").unwrap();
+    fs::write(&b, b"b").unwrap();
+    fs::create_dir_all(format!("{dir}/sub/inner")).unwrap();
+
+    // RENAME_NOREPLACE only moves a file somewhere free.
+    assert_eq!(renameat2(&a, &b, libc::RENAME_NOREPLACE), Err(libc::EEXIST));
+    let c = format!("{dir}/c");
+    assert_eq!(renameat2(&a, &c, libc::RENAME_NOREPLACE), Ok(()));
+    assert_eq!(fs::read(&c).unwrap(), b"a");
+
+    // RENAME_EXCHANGE swaps two files, which both have to be there.
+    assert_eq!(renameat2(&c, &b, libc::RENAME_EXCHANGE), Ok(()));
+    assert_eq!(fs::read(&b).unwrap(), b"a");
+    assert_eq!(fs::read(&c).unwrap(), b"b");
+    assert_eq!(renameat2(&c, &a, libc::RENAME_EXCHANGE), Err(libc::ENOENT));
+
+    // Files in different directories can be swapped as well.
+    let sub = format!("{dir}/sub");
+    let moved = format!("{dir}/sub/inner/c");
+    fs::rename(&c, &moved).unwrap();
+    assert_eq!(renameat2(&moved, &b, libc::RENAME_EXCHANGE), Ok(()));
+    assert_eq!(fs::read(&moved).unwrap(), b"a");
+    assert_eq!(fs::read(&b).unwrap(), b"b");
+
+    // Nothing can be moved inside itself.
+    assert_eq!(
+        renameat2(&sub, &format!("{dir}/sub/inner/sub"), 0),
+        Err(libc::EINVAL)
+    );
+    assert_eq!(
+        renameat2(&sub, &moved, libc::RENAME_EXCHANGE),
+        Err(libc::EINVAL)
+    );
+
+    // The flags don't go together, or with ones we don't know.
+    assert_eq!(
+        renameat2(&a, &b, libc::RENAME_EXCHANGE | libc::RENAME_NOREPLACE),
+        Err(libc::EINVAL)
+    );
+    assert_eq!(renameat2(&b, &c, 0x8), Err(libc::EINVAL));
+
+    // Nor does either move a file off its filesystem.
+    let mnt = format!("{dir}/mnt");
+    fs::create_dir(&mnt).unwrap();
+    mount_tmpfs(&mnt);
+    fs::write(format!("{mnt}/m"), b"m").unwrap();
+    assert_eq!(renameat2(&b, &format!("{mnt}/b"), 0), Err(libc::EXDEV));
+    assert_eq!(
+        renameat2(&b, &format!("{mnt}/m"), libc::RENAME_EXCHANGE),
+        Err(libc::EXDEV)
+    );
+
+    fs::remove_file(format!("{mnt}/m")).unwrap();
+    let c_mnt = CString::new(mnt.as_str()).unwrap();
+    assert_eq!(unsafe { libc::umount(c_mnt.as_ptr()) }, 0);
+    fs::remove_dir(&mnt).unwrap();
+    fs::remove_file(&b).unwrap();
+    fs::remove_dir_all(&sub).unwrap();
+    let c_dir = CString::new(dir).unwrap();
+    assert_eq!(unsafe { libc::umount(c_dir.as_ptr()) }, 0);
+    fs::remove_dir(dir).unwrap();
+}
+
+register_test!(test_renameat2);
+
 fn test_truncate() {
     use std::fs::{self, File};
     use std::io::{Read, Seek, Write};
@@ -425,6 +707,189 @@ fn test_ftruncate() {
 
 register_test!(test_ftruncate);
 
+fn test_truncate_coherence() {
+    use std::os::fd::AsRawFd;
+
+    let errno = || std::io::Error::last_os_error().raw_os_error().unwrap();
+    let dir = "/tmp/truncate_coherence";
+    fs::create_dir(dir).unwrap();
+    let path = format!("{dir}/file");
+    fs::write(&path, b"Hello, world!").unwrap();
+
+    // A relative path is resolved from the cwd, and growing zero-fills.
+    let cwd = std::env::current_dir().unwrap();
+    std::env::set_current_dir(dir).unwrap();
+    let rel = CString::new("file").unwrap();
+    assert_eq!(unsafe { libc::truncate(rel.as_ptr(), 8) }, 0);
+    std::env::set_current_dir(&cwd).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"Hello, \0");
+
+    let c_dir = CString::new(dir).unwrap();
+    assert_eq!(unsafe { libc::truncate(c_dir.as_ptr(), 0) }, -1);
+    assert_eq!(errno(), libc::EISDIR);
+    let c_path = CString::new(path.as_str()).unwrap();
+    assert_eq!(unsafe { libc::truncate(c_path.as_ptr(), -1) }, -1);
+    assert_eq!(errno(), libc::EINVAL);
+
+    let rdonly = fs::File::open(&path).unwrap();
+    assert_eq!(unsafe { libc::ftruncate(rdonly.as_raw_fd(), 0) }, -1);
+    assert_eq!(errno(), libc::EINVAL);
+    drop(rdonly);
+
+    // Data written through a shared mapping past the new end is gone once
+    // the file is shrunk, and the hole reads back as zeroes when it regrows.
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    let fd = file.as_raw_fd();
+    assert_eq!(unsafe { libc::ftruncate(fd, (page * 2) as _) }, 0);
+    let map = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            page * 2,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        )
+    };
+    assert_ne!(map, libc::MAP_FAILED);
+    let bytes = unsafe { std::slice::from_raw_parts_mut(map as *mut u8, page * 2) };
+    bytes[page..].fill(0xaa);
+    assert_eq!(unsafe { libc::msync(map, page * 2, libc::MS_SYNC) }, 0);
+
+    assert_eq!(unsafe { libc::ftruncate(fd, page as _) }, 0);
+    assert_eq!(unsafe { libc::ftruncate(fd, (page * 2) as _) }, 0);
+
+    let mut buf = vec![0xffu8; page];
+    let ret = unsafe { libc::pread64(fd, buf.as_mut_ptr().cast(), page, page as _) };
+    assert_eq!(ret as usize, page);
+    assert!(buf.iter().all(|&b| b == 0));
+    assert!(bytes[page..].iter().all(|&b| b == 0));
+    assert_eq!(&bytes[..7], b"Hello, ");
+
+    assert_eq!(unsafe { libc::munmap(map, page * 2) }, 0);
+    drop(file);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+register_test!(test_truncate_coherence);
+
+fn test_fadvise() {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+
+    let path = "/bin/busybox";
+
+    // A sequential read, which reads ahead...
+    let mut seq = Vec::new();
+    let mut file = File::open(path).unwrap();
+    let fd = file.as_raw_fd();
+    let ret = unsafe { libc::posix_fadvise(fd, 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+    assert_eq!(ret, 0);
+    file.read_to_end(&mut seq).unwrap();
+    assert!(!seq.is_empty());
+
+    // ...must see the same data as scattered reads that don't.
+    let ret = unsafe { libc::posix_fadvise(fd, 0, 0, libc::POSIX_FADV_RANDOM) };
+    assert_eq!(ret, 0);
+
+    let mut buf = [0u8; 1000];
+    for off in (0..seq.len()).rev().step_by(seq.len() / 7 + 1) {
+        let ret = unsafe {
+            libc::pread(
+                fd,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                off as libc::off_t,
+            )
+        };
+        let n = ret as usize;
+        assert_eq!(n, buf.len().min(seq.len() - off));
+        assert_eq!(&buf[..n], &seq[off..off + n]);
+    }
+
+    unsafe {
+        assert_eq!(libc::posix_fadvise(fd, 0, 0, libc::POSIX_FADV_WILLNEED), 0);
+        assert_eq!(libc::posix_fadvise(fd, 0, 4096, libc::POSIX_FADV_NORMAL), 0);
+        assert_eq!(
+            libc::posix_fadvise(fd, 0, -1, libc::POSIX_FADV_NORMAL),
+            libc::EINVAL
+        );
+        assert_eq!(libc::posix_fadvise(fd, 0, 0, 42), libc::EINVAL);
+
+        let mut fds = [0; 2];
+        assert_eq!(libc::pipe(fds.as_mut_ptr()), 0);
+        assert_eq!(
+            libc::posix_fadvise(fds[0], 0, 0, libc::POSIX_FADV_NORMAL),
+            libc::ESPIPE
+        );
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+register_test!(test_fadvise);
+
+fn test_mmap_private_shares_file_pages() {
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+
+    let path = "/bin/busybox";
+    let data = fs::read(path).unwrap();
+    let len = 4 * 4096;
+    assert!(data.len() > len);
+
+    let file = File::open(path).unwrap();
+    let map = |prot| unsafe {
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            prot,
+            libc::MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        );
+        if addr == libc::MAP_FAILED {
+            panic!("mmap failed: {}", std::io::Error::last_os_error());
+        }
+        addr.cast::<u8>()
+    };
+
+    let ro = map(libc::PROT_READ);
+    let rw = map(libc::PROT_READ | libc::PROT_WRITE);
+
+    unsafe {
+        // Both mappings are served the same file data...
+        assert_eq!(std::slice::from_raw_parts(ro, len), &data[..len]);
+        assert_eq!(std::slice::from_raw_parts(rw, len), &data[..len]);
+
+        // ...but a write to one only changes its own copy.
+        rw.add(4096).write_volatile(!data[4096]);
+        assert_eq!(rw.add(4096).read_volatile(), !data[4096]);
+        assert_eq!(ro.add(4096).read_volatile(), data[4096]);
+
+        // Likewise once a read-only mapping is made writable.
+        assert_eq!(
+            libc::mprotect(ro.cast(), len, libc::PROT_READ | libc::PROT_WRITE),
+            0
+        );
+        ro.write_volatile(!data[0]);
+        assert_eq!(rw.read_volatile(), data[0]);
+
+        libc::munmap(ro.cast(), len);
+        libc::munmap(rw.cast(), len);
+    }
+
+    assert_eq!(fs::read(path).unwrap(), data);
+}
+
+register_test!(test_mmap_private_shares_file_pages);
+
 fn test_utimens() {
     let file = "/tmp/utimens_test";
     let c_file = CString::new(file).unwrap();
@@ -612,3 +1077,2881 @@ fn test_rust_dir() {
 }
 
 register_test!(test_rust_dir);
+
+fn test_remount_readonly() {
+    let target = CString::new("/tmp").unwrap();
+    let path = CString::new("/tmp/remount_ro_test").unwrap();
+
+    unsafe {
+        // A file open for writing keeps the filesystem writable.
+        let fd = libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CREAT, 0o644);
+        assert!(fd >= 0, "create failed");
+
+        let ret = libc::mount(
+            std::ptr::null(),
+            target.as_ptr(),
+            std::ptr::null(),
+            libc::MS_REMOUNT | libc::MS_RDONLY,
+            std::ptr::null(),
+        );
+        let err = std::io::Error::last_os_error();
+        assert_eq!(ret, -1);
+        assert_eq!(err.raw_os_error(), Some(libc::EBUSY));
+
+        libc::close(fd);
+        libc::unlink(path.as_ptr());
+
+        let ret = libc::mount(
+            std::ptr::null(),
+            target.as_ptr(),
+            std::ptr::null(),
+            libc::MS_REMOUNT | libc::MS_RDONLY,
+            std::ptr::null(),
+        );
+        assert_eq!(ret, 0, "remount read-only failed");
+
+        let fd = libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CREAT, 0o644);
+        let err = std::io::Error::last_os_error();
+        assert_eq!(fd, -1);
+        assert_eq!(err.raw_os_error(), Some(libc::EROFS));
+
+        let ret = libc::mount(
+            std::ptr::null(),
+            target.as_ptr(),
+            std::ptr::null(),
+            libc::MS_REMOUNT,
+            std::ptr::null(),
+        );
+        assert_eq!(ret, 0, "remount read-write failed");
+
+        let fd = libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CREAT, 0o644);
+        assert!(fd >= 0, "create after remount failed");
+        libc::close(fd);
+        libc::unlink(path.as_ptr());
+    }
+}
+
+register_test!(test_remount_readonly);
+
+fn test_freeze_thaw() {
+    const FIFREEZE: libc::c_ulong = 0xc0045877;
+    const FITHAW: libc::c_ulong = 0xc0045878;
+
+    let dir = CString::new("/tmp").unwrap();
+
+    unsafe {
+        let fd = libc::open(dir.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY);
+        assert!(fd >= 0);
+
+        assert_eq!(libc::ioctl(fd, FIFREEZE as _, 0), 0, "FIFREEZE failed");
+
+        // Freezing twice is an error.
+        assert_eq!(libc::ioctl(fd, FIFREEZE as _, 0), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EBUSY)
+        );
+
+        assert_eq!(libc::ioctl(fd, FITHAW as _, 0), 0, "FITHAW failed");
+
+        // Thawing an unfrozen filesystem is an error.
+        assert_eq!(libc::ioctl(fd, FITHAW as _, 0), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+
+        libc::close(fd);
+    }
+}
+
+register_test!(test_freeze_thaw);
+
+fn test_quota() {
+    const Q_QUOTAON: libc::c_int = 0x800002;
+    const Q_QUOTAOFF: libc::c_int = 0x800003;
+    const Q_GETQUOTA: libc::c_int = 0x800007;
+    const Q_SETQUOTA: libc::c_int = 0x800008;
+    const QIF_BLIMITS: u32 = 1;
+    const USER: u32 = 1234;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct IfDqblk {
+        dqb_bhardlimit: u64,
+        dqb_bsoftlimit: u64,
+        dqb_curspace: u64,
+        dqb_ihardlimit: u64,
+        dqb_isoftlimit: u64,
+        dqb_curinodes: u64,
+        dqb_btime: u64,
+        dqb_itime: u64,
+        dqb_valid: u32,
+    }
+
+    fn quotactl(cmd: libc::c_int, special: &CStr, id: u32, addr: *mut IfDqblk) -> libc::c_long {
+        // QCMD(cmd, USRQUOTA)
+        unsafe { libc::syscall(libc::SYS_quotactl, cmd << 8, special.as_ptr(), id, addr) }
+    }
+
+    let tmp = CString::new("/tmp").unwrap();
+    let path = CString::new("/tmp/quota_test").unwrap();
+
+    unsafe {
+        let mut dq = IfDqblk {
+            dqb_bhardlimit: 4, // 4KiB
+            dqb_valid: QIF_BLIMITS,
+            ..Default::default()
+        };
+        assert_eq!(quotactl(Q_SETQUOTA, &tmp, USER, &mut dq), 0);
+        assert_eq!(quotactl(Q_QUOTAON, &tmp, 0, std::ptr::null_mut()), 0);
+
+        let fd = libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CREAT, 0o644);
+        assert!(fd >= 0);
+        assert_eq!(libc::fchown(fd, USER, USER), 0);
+
+        let buf = [0xaau8; 4096];
+        assert_eq!(libc::write(fd, buf.as_ptr().cast(), buf.len()), 4096);
+
+        let ret = libc::write(fd, buf.as_ptr().cast(), buf.len());
+        let err = std::io::Error::last_os_error();
+        assert_eq!(ret, -1);
+        assert_eq!(err.raw_os_error(), Some(libc::EDQUOT));
+
+        let mut dq = IfDqblk::default();
+        assert_eq!(quotactl(Q_GETQUOTA, &tmp, USER, &mut dq), 0);
+        assert_eq!(dq.dqb_curspace, 4096);
+        assert_eq!(dq.dqb_curinodes, 1);
+
+        libc::close(fd);
+        libc::unlink(path.as_ptr());
+
+        let mut dq = IfDqblk::default();
+        assert_eq!(quotactl(Q_GETQUOTA, &tmp, USER, &mut dq), 0);
+        assert_eq!(dq.dqb_curspace, 0);
+        assert_eq!(dq.dqb_curinodes, 0);
+
+        assert_eq!(quotactl(Q_QUOTAOFF, &tmp, 0, std::ptr::null_mut()), 0);
+    }
+}
+
+register_test!(test_quota);
+
+fn test_memfd_seals() {
+    fn errno_of(ret: isize) -> Option<i32> {
+        assert_eq!(ret, -1);
+        std::io::Error::last_os_error().raw_os_error()
+    }
+
+    let name = CString::new("seal_test").unwrap();
+
+    unsafe {
+        // Without MFD_ALLOW_SEALING the file starts out sealed against
+        // further seals.
+        let fd = libc::memfd_create(name.as_ptr(), 0);
+        assert!(fd >= 0);
+        assert_eq!(libc::fcntl(fd, libc::F_GET_SEALS), libc::F_SEAL_SEAL);
+        assert_eq!(
+            errno_of(libc::fcntl(fd, libc::F_ADD_SEALS, libc::F_SEAL_WRITE) as _),
+            Some(libc::EPERM)
+        );
+        libc::close(fd);
+
+        let fd = libc::memfd_create(name.as_ptr(), libc::MFD_ALLOW_SEALING | libc::MFD_CLOEXEC);
+        assert!(fd >= 0);
+        assert_eq!(libc::fcntl(fd, libc::F_GETFD), libc::FD_CLOEXEC);
+
+        let data = b"hello, memfd";
+        assert_eq!(
+            libc::write(fd, data.as_ptr().cast(), data.len()),
+            data.len() as isize
+        );
+
+        let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+        assert_eq!(libc::fcntl(fd, libc::F_ADD_SEALS, seals), 0);
+        assert_eq!(libc::fcntl(fd, libc::F_GET_SEALS), seals);
+
+        assert_eq!(
+            errno_of(libc::pwrite(fd, data.as_ptr().cast(), 1, 0)),
+            Some(libc::EPERM)
+        );
+        assert_eq!(errno_of(libc::ftruncate(fd, 0) as _), Some(libc::EPERM));
+        assert_eq!(errno_of(libc::ftruncate(fd, 4096) as _), Some(libc::EPERM));
+
+        // Sealed contents remain readable.
+        let mut buf = [0u8; 32];
+        assert_eq!(
+            libc::pread(fd, buf.as_mut_ptr().cast(), buf.len(), 0),
+            data.len() as isize
+        );
+        assert_eq!(&buf[..data.len()], data);
+
+        assert_eq!(libc::fcntl(fd, libc::F_ADD_SEALS, libc::F_SEAL_SEAL), 0);
+        assert_eq!(
+            errno_of(libc::fcntl(fd, libc::F_ADD_SEALS, libc::F_SEAL_EXEC) as _),
+            Some(libc::EPERM)
+        );
+
+        libc::close(fd);
+    }
+}
+
+register_test!(test_memfd_seals);
+
+fn test_memfd_seal_write_mapped() {
+    let name = CString::new("seal_mapped").unwrap();
+
+    unsafe {
+        let fd = libc::memfd_create(name.as_ptr(), libc::MFD_ALLOW_SEALING);
+        assert!(fd >= 0);
+        assert_eq!(libc::ftruncate(fd, 4096), 0);
+
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            4096,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        );
+        assert_ne!(addr, libc::MAP_FAILED);
+
+        // The mapping could still write to the file.
+        assert_eq!(libc::fcntl(fd, libc::F_ADD_SEALS, libc::F_SEAL_WRITE), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EBUSY)
+        );
+
+        assert_eq!(libc::munmap(addr, 4096), 0);
+        assert_eq!(libc::fcntl(fd, libc::F_ADD_SEALS, libc::F_SEAL_WRITE), 0);
+
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            4096,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        );
+        assert_eq!(addr, libc::MAP_FAILED);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EPERM)
+        );
+
+        libc::close(fd);
+    }
+}
+
+register_test!(test_memfd_seal_write_mapped);
+
+fn test_mmap_shared_write_only() {
+    let path = CString::new("/tmp/mmap_wronly_test").unwrap();
+
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CREAT, 0o644);
+        assert!(fd >= 0);
+        assert_eq!(libc::ftruncate(fd, 4096), 0);
+
+        // A shared mapping is readable whatever the protection, so a
+        // write-only fd can't back one.
+        for prot in [libc::PROT_WRITE, libc::PROT_READ | libc::PROT_WRITE] {
+            let addr = libc::mmap(std::ptr::null_mut(), 4096, prot, libc::MAP_SHARED, fd, 0);
+            assert_eq!(addr, libc::MAP_FAILED);
+            assert_eq!(
+                std::io::Error::last_os_error().raw_os_error(),
+                Some(libc::EACCES)
+            );
+        }
+
+        libc::close(fd);
+        assert_eq!(libc::unlink(path.as_ptr()), 0);
+    }
+}
+
+register_test!(test_mmap_shared_write_only);
+
+// The device-mapper control interface, as driven through /dev/dm-control.
+const DM_DEV_CREATE: u32 = 0x4030_fd00;
+const DM_DEV_REMOVE: u32 = 0x4020_fd01;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct DmDevSpec {
+    name: [u8; 32],
+    offset: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct DmTargetSpec {
+    start: u64,
+    len: u64,
+    kind: u32,
+    num_devs: u32,
+    chunk: u64,
+    devs: [DmDevSpec; 8],
+}
+
+#[repr(C)]
+struct DmCreate {
+    name: [u8; 32],
+    num_targets: u32,
+    _pad: u32,
+    targets: *const DmTargetSpec,
+}
+
+fn dm_name(name: &str) -> [u8; 32] {
+    let mut buf = [0; 32];
+    buf[..name.len()].copy_from_slice(name.as_bytes());
+    buf
+}
+
+fn test_stacked_block_devices() {
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::AsRawFd;
+
+    use std::fs::File;
+
+    const BLKGETSIZE64: u32 = 0x8008_1272;
+
+    fn read_blocks(path: &str, block: u64, count: usize, bs: usize) -> Vec<u8> {
+        let mut buf = vec![0; count * bs];
+        File::open(path)
+            .expect("Failed to open block device")
+            .read_exact_at(&mut buf, block * bs as u64)
+            .expect("Failed to read block device");
+        buf
+    }
+
+    let ram0 = File::open("/dev/ram0").expect("Failed to open /dev/ram0");
+    let mut bs: libc::c_int = 0;
+    let mut size: u64 = 0;
+
+    unsafe {
+        assert_eq!(libc::ioctl(ram0.as_raw_fd(), libc::BLKSSZGET, &mut bs), 0);
+        assert_eq!(
+            libc::ioctl(ram0.as_raw_fd(), BLKGETSIZE64 as _, &mut size),
+            0
+        );
+    }
+
+    let bs = bs as usize;
+    assert!(size >= 16 * bs as u64);
+    drop(ram0);
+
+    let ctl = File::open("/dev/dm-control").expect("Failed to open /dev/dm-control");
+    let create = |name: &str, targets: &[DmTargetSpec]| {
+        let req = DmCreate {
+            name: dm_name(name),
+            num_targets: targets.len() as u32,
+            _pad: 0,
+            targets: targets.as_ptr(),
+        };
+        assert_eq!(
+            unsafe { libc::ioctl(ctl.as_raw_fd(), DM_DEV_CREATE as _, &req) },
+            0,
+            "Failed to create {name}"
+        );
+    };
+    let remove = |name: &str| {
+        let name = dm_name(name);
+        unsafe { libc::ioctl(ctl.as_raw_fd(), DM_DEV_REMOVE as _, &name) }
+    };
+
+    // Two stripes of ram0 with a two-block chunk: blocks 0-1 come from
+    // ram0 block 0, blocks 2-3 from ram0 block 8 and so on.
+    let mut striped = DmTargetSpec {
+        len: 8,
+        kind: 1,
+        num_devs: 2,
+        chunk: 2,
+        ..Default::default()
+    };
+    striped.devs[0] = DmDevSpec {
+        name: dm_name("ram0"),
+        offset: 0,
+    };
+    striped.devs[1] = DmDevSpec {
+        name: dm_name("ram0"),
+        offset: 8,
+    };
+    create("ut-striped", &[striped]);
+
+    let expected = [
+        read_blocks("/dev/ram0", 0, 2, bs),
+        read_blocks("/dev/ram0", 8, 2, bs),
+        read_blocks("/dev/ram0", 2, 2, bs),
+        read_blocks("/dev/ram0", 10, 2, bs),
+    ]
+    .concat();
+    assert_eq!(read_blocks("/dev/ut-striped", 0, 8, bs), expected);
+
+    // A linear device stacked on the striped one, skipping its first block.
+    let mut linear = DmTargetSpec {
+        len: 7,
+        kind: 0,
+        num_devs: 1,
+        ..Default::default()
+    };
+    linear.devs[0] = DmDevSpec {
+        name: dm_name("ut-striped"),
+        offset: 1,
+    };
+    create("ut-linear", &[linear]);
+    assert_eq!(
+        read_blocks("/dev/ut-linear", 0, 7, bs),
+        expected[bs..].to_vec()
+    );
+
+    // Unaligned reads go through a bounce buffer.
+    let mut buf = [0u8; 10];
+    File::open("/dev/ut-linear")
+        .unwrap()
+        .read_exact_at(&mut buf, bs as u64 - 3)
+        .unwrap();
+    assert_eq!(buf, expected[2 * bs - 3..2 * bs + 7]);
+
+    // The striped device can't go away while the linear one uses it.
+    assert_eq!(remove("ut-striped"), -1);
+    assert_eq!(
+        std::io::Error::last_os_error().raw_os_error(),
+        Some(libc::EBUSY)
+    );
+    assert_eq!(remove("ut-linear"), 0);
+    assert_eq!(remove("ut-striped"), 0);
+    assert!(!std::path::Path::new("/dev/ut-striped").exists());
+
+    // Only mapped devices can be removed.
+    assert_eq!(remove("ram0"), -1);
+}
+
+register_test!(test_stacked_block_devices);
+
+fn test_devfs_metadata() {
+    use std::os::unix::fs::{DirEntryExt, FileTypeExt, MetadataExt, PermissionsExt};
+    use std::os::unix::io::AsRawFd;
+
+    // Device numbers and modes match a stock Linux /dev.
+    for (path, major, minor, mode) in [
+        ("/dev/null", 1, 3, 0o666),
+        ("/dev/zero", 1, 5, 0o666),
+        ("/dev/random", 1, 8, 0o666),
+        ("/dev/tty", 5, 0, 0o666),
+        ("/dev/console", 5, 1, 0o600),
+        ("/dev/dm-control", 10, 236, 0o600),
+    ] {
+        let meta = fs::metadata(path).unwrap();
+        assert!(meta.file_type().is_char_device(), "{path}");
+        assert_eq!(
+            (libc::major(meta.rdev()), libc::minor(meta.rdev())),
+            (major, minor),
+            "{path}"
+        );
+        assert_eq!(meta.permissions().mode() & 0o7777, mode, "{path}");
+        assert_ne!(meta.mtime(), 0, "{path}");
+    }
+
+    let ram0 = fs::metadata("/dev/ram0").unwrap();
+    assert!(ram0.file_type().is_block_device());
+    assert!(!ram0.file_type().is_char_device());
+
+    let dev = fs::metadata("/dev").unwrap();
+    let shm = fs::metadata("/dev/shm").unwrap();
+    assert!(dev.nlink() >= 3);
+    assert_eq!(shm.permissions().mode() & 0o7777, 0o1777);
+    assert_ne!(dev.ino(), shm.ino());
+
+    // Entries come back with the type and inode number that stat gives.
+    for entry in fs::read_dir("/dev").unwrap() {
+        let entry = entry.unwrap();
+        let path = entry.path();
+
+        if entry.file_name() == "null" || entry.file_name() == "ram0" {
+            let meta = fs::symlink_metadata(&path).unwrap();
+            assert_eq!(entry.ino(), meta.ino(), "{path:?}");
+            assert_eq!(entry.file_type().unwrap(), meta.file_type(), "{path:?}");
+        }
+    }
+
+    // Reads one entry at a time, so that devices can be added part way
+    // through a listing.
+    fn next_name(dir: &fs::File) -> Option<String> {
+        let mut buf = [0u8; 64];
+        let len = unsafe {
+            libc::syscall(
+                libc::SYS_getdents64,
+                dir.as_raw_fd(),
+                buf.as_mut_ptr(),
+                buf.len(),
+            )
+        };
+        assert!(len >= 0);
+
+        let reclen = u16::from_ne_bytes([buf[16], buf[17]]) as usize;
+        (len > 0).then(|| {
+            let name = &buf[19..reclen];
+            let end = name.iter().position(|&b| b == 0).unwrap();
+            String::from_utf8(name[..end].to_vec()).unwrap()
+        })
+    }
+
+    let dir = fs::File::open("/dev").unwrap();
+    let mut names = vec![next_name(&dir).unwrap()];
+
+    let ctl = fs::File::open("/dev/dm-control").unwrap();
+    let mut linear = DmTargetSpec {
+        len: 1,
+        kind: 0,
+        num_devs: 1,
+        ..Default::default()
+    };
+    linear.devs[0] = DmDevSpec {
+        name: dm_name("ram0"),
+        offset: 0,
+    };
+    let req = DmCreate {
+        name: dm_name("ut-devfs"),
+        num_targets: 1,
+        _pad: 0,
+        targets: &linear,
+    };
+    assert_eq!(
+        unsafe { libc::ioctl(ctl.as_raw_fd(), DM_DEV_CREATE as _, &req) },
+        0
+    );
+
+    while let Some(name) = next_name(&dir) {
+        names.push(name);
+    }
+
+    let remove = dm_name("ut-devfs");
+    assert_eq!(
+        unsafe { libc::ioctl(ctl.as_raw_fd(), DM_DEV_REMOVE as _, &remove) },
+        0
+    );
+
+    // The new device turns up once, and nothing is listed twice.
+    let mut sorted = names.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted.len(), names.len(), "{names:?}");
+    assert!(names.iter().any(|n| n == "ut-devfs"), "{names:?}");
+    assert!(names.iter().any(|n| n == "null"), "{names:?}");
+
+    assert!(
+        !fs::read_dir("/dev")
+            .unwrap()
+            .any(|e| e.unwrap().file_name() == "ut-devfs")
+    );
+}
+
+register_test!(test_devfs_metadata);
+
+fn test_dentry_cache() {
+    use std::path::Path;
+
+    fn dentry_state() -> Vec<u64> {
+        fs::read_to_string("/proc/sys/fs/dentry-state")
+            .unwrap()
+            .split_whitespace()
+            .map(|field| field.parse().unwrap())
+            .collect()
+    }
+
+    let dir = "/tmp/dcache_test";
+    let a = "/tmp/dcache_test/a";
+    let b = "/tmp/dcache_test/b";
+
+    fs::create_dir(dir).unwrap();
+
+    // Cache a negative entry, then make sure creating the name replaces it.
+    assert!(!Path::new(a).exists());
+    assert!(!Path::new(a).exists());
+    assert_eq!(dentry_state().len(), 6);
+    assert!(dentry_state()[4] > 0);
+
+    fs::write(a, b"a").unwrap();
+    assert_eq!(fs::read(a).unwrap(), b"a");
+
+    fs::rename(a, b).unwrap();
+    assert!(!Path::new(a).exists());
+    assert_eq!(fs::read(b).unwrap(), b"a");
+
+    // Replacing a cached name must not leave the old inode behind.
+    fs::write(a, b"new").unwrap();
+    fs::rename(a, b).unwrap();
+    assert_eq!(fs::read(b).unwrap(), b"new");
+
+    fs::remove_file(b).unwrap();
+    assert!(!Path::new(b).exists());
+
+    fs::remove_dir(dir).unwrap();
+    assert!(!Path::new(dir).exists());
+    fs::create_dir(dir).unwrap();
+    assert!(!Path::new(b).exists());
+    fs::remove_dir(dir).unwrap();
+
+    assert!(fs::write("/proc/sys/fs/dentry-state", "0").is_err());
+}
+
+register_test!(test_dentry_cache);
+
+fn test_inode_cache() {
+    use std::os::unix::fs::MetadataExt;
+
+    fn inode_nr() -> Vec<u64> {
+        fs::read_to_string("/proc/sys/fs/inode-nr")
+            .unwrap()
+            .split_whitespace()
+            .map(|field| field.parse().unwrap())
+            .collect()
+    }
+
+    // The root filesystem is ext4, whose inodes are cached.
+    let dir = "/icache_test";
+    let a = "/icache_test/a";
+    let b = "/icache_test/b";
+
+    fs::create_dir(dir).unwrap();
+    fs::write(a, b"cached").unwrap();
+
+    let ino = fs::metadata(a).unwrap().ino();
+    assert_eq!(fs::metadata(a).unwrap().ino(), ino);
+    assert_eq!(inode_nr().len(), 2);
+    assert!(inode_nr()[0] > 0);
+
+    // Link counts changed through one name must show through the other.
+    fs::hard_link(a, b).unwrap();
+    assert_eq!(fs::metadata(a).unwrap().nlink(), 2);
+    fs::remove_file(b).unwrap();
+    assert_eq!(fs::metadata(a).unwrap().nlink(), 1);
+
+    fs::write("/proc/sys/vm/drop_caches", "3").unwrap();
+    assert!(fs::write("/proc/sys/vm/drop_caches", "4").is_err());
+    assert!(fs::write("/proc/sys/fs/inode-nr", "0").is_err());
+
+    // Everything is read back from disk after the caches are dropped.
+    assert_eq!(fs::read(a).unwrap(), b"cached");
+    assert_eq!(fs::metadata(a).unwrap().ino(), ino);
+
+    fs::remove_file(a).unwrap();
+    fs::remove_dir(dir).unwrap();
+}
+
+register_test!(test_inode_cache);
+
+fn test_block_cache() {
+    let limit = "/proc/sys/vm/block_cache_kbytes";
+    let file = "/block_cache_test";
+    let old = fs::read_to_string(limit).unwrap();
+
+    // The root filesystem's device is cached.
+    let stats = fs::read_to_string("/proc/block_cache").unwrap();
+    assert!(stats.lines().count() > 2);
+    assert!(
+        fs::read_to_string("/proc/meminfo")
+            .unwrap()
+            .contains("Buffers:")
+    );
+
+    // With no room to cache anything, writes go straight to the device.
+    fs::write(limit, "0").unwrap();
+    assert_eq!(fs::read_to_string(limit).unwrap(), "0\n");
+    assert!(fs::write(limit, "-1").is_err());
+
+    let data: Vec<u8> = (0..64 * 1024).map(|i| i as u8).collect();
+    fs::write(file, &data).unwrap();
+    fs::write("/proc/sys/vm/drop_caches", "1").unwrap();
+    assert_eq!(fs::read(file).unwrap(), data);
+
+    fs::write(limit, old.trim()).unwrap();
+    fs::remove_file(file).unwrap();
+}
+
+register_test!(test_block_cache);
+
+fn test_path_resolution() {
+    use std::io::ErrorKind;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::{MetadataExt, OpenOptionsExt, symlink};
+
+    let dir = "/tmp/namei_test";
+    let file = "/tmp/namei_test/file";
+
+    fs::create_dir(dir).unwrap();
+    fs::write(file, b"namei").unwrap();
+
+    // `..` stops at the root, and climbs out of /tmp's tmpfs.
+    let root = fs::metadata("/").unwrap();
+    for path in ["/..", "/../..", "/tmp/..", "/tmp/namei_test/../.."] {
+        let meta = fs::metadata(path).unwrap();
+        assert_eq!((meta.dev(), meta.ino()), (root.dev(), root.ino()), "{path}");
+    }
+
+    // Relative to a directory, `..` has to come from the filesystem itself.
+    let tmp = fs::metadata("/tmp").unwrap();
+    let dirfd = fs::File::open(dir).unwrap();
+    unsafe {
+        let mut st: libc::stat = std::mem::zeroed();
+        let dotdot = CString::new("..").unwrap();
+        assert_eq!(
+            libc::fstatat(dirfd.as_raw_fd(), dotdot.as_ptr(), &mut st, 0),
+            0
+        );
+        assert_eq!((st.st_dev, st.st_ino), (tmp.dev(), tmp.ino()));
+    }
+
+    // Trailing slashes only resolve to directories.
+    assert!(fs::metadata("/tmp/namei_test/").unwrap().is_dir());
+    assert_eq!(
+        fs::metadata("/tmp/namei_test/file/")
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+    assert_eq!(
+        fs::metadata("/tmp/namei_test/file/x")
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+
+    symlink("file", "/tmp/namei_test/rel").unwrap();
+    symlink(dir, "/tmp/namei_test/abs").unwrap();
+    assert_eq!(fs::read("/tmp/namei_test/rel").unwrap(), b"namei");
+    assert_eq!(fs::read("/tmp/namei_test/abs/abs/rel").unwrap(), b"namei");
+    assert!(fs::metadata("/tmp/namei_test/abs/").unwrap().is_dir());
+    assert!(
+        fs::symlink_metadata("/tmp/namei_test/abs")
+            .unwrap()
+            .file_type()
+            .is_symlink()
+    );
+
+    // O_NOFOLLOW refuses a symlink in the final component only.
+    let err = fs::File::options()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open("/tmp/namei_test/rel")
+        .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+    fs::File::options()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open("/tmp/namei_test/abs/file")
+        .unwrap();
+
+    // A chain of 40 links resolves; one more is too many.
+    let mut prev = String::from("file");
+    for i in 0..=40 {
+        let link = format!("{dir}/chain{i}");
+        symlink(&prev, &link).unwrap();
+        prev = format!("chain{i}");
+    }
+    assert_eq!(fs::read(format!("{dir}/chain39")).unwrap(), b"namei");
+    assert_eq!(
+        fs::read(format!("{dir}/chain40"))
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ELOOP)
+    );
+
+    symlink("loop_b", "/tmp/namei_test/loop_a").unwrap();
+    symlink("loop_a", "/tmp/namei_test/loop_b").unwrap();
+    let err = fs::metadata("/tmp/namei_test/loop_a").unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+
+    assert_eq!(fs::metadata("").unwrap_err().kind(), ErrorKind::NotFound);
+
+    for entry in fs::read_dir(dir).unwrap() {
+        fs::remove_file(entry.unwrap().path()).unwrap();
+    }
+    fs::remove_dir(dir).unwrap();
+}
+
+register_test!(test_path_resolution);
+
+fn test_openat2() {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::symlink;
+
+    fn openat2(dirfd: i32, path: &str, flags: i32, mode: u64, resolve: u64) -> Result<(), i32> {
+        let path = CString::new(path).unwrap();
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = flags as u64;
+        how.mode = mode;
+        how.resolve = resolve;
+
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                dirfd,
+                path.as_ptr(),
+                &how,
+                std::mem::size_of::<libc::open_how>(),
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().raw_os_error().unwrap());
+        }
+        unsafe { libc::close(fd as i32) };
+        Ok(())
+    }
+
+    let dir = "/tmp/openat2_test";
+    fs::create_dir(dir).unwrap();
+    fs::create_dir(format!("{dir}/sub")).unwrap();
+    fs::write(format!("{dir}/file"), b"openat2").unwrap();
+    symlink("file", format!("{dir}/rel")).unwrap();
+    symlink(format!("{dir}/file"), format!("{dir}/abs")).unwrap();
+
+    let dirfd = fs::File::open(dir).unwrap();
+    let dirfd = dirfd.as_raw_fd();
+    let rdonly = libc::O_RDONLY;
+
+    assert_eq!(openat2(dirfd, "file", rdonly, 0, 0), Ok(()));
+    assert_eq!(openat2(dirfd, "abs", rdonly, 0, 0), Ok(()));
+
+    // RESOLVE_BENEATH refuses anything that leaves the directory.
+    let beneath = libc::RESOLVE_BENEATH;
+    assert_eq!(openat2(dirfd, "sub/../file", rdonly, 0, beneath), Ok(()));
+    assert_eq!(openat2(dirfd, "rel", rdonly, 0, beneath), Ok(()));
+    for path in ["../openat2_test/file", "/tmp/openat2_test/file", "abs"] {
+        assert_eq!(
+            openat2(dirfd, path, rdonly, 0, beneath),
+            Err(libc::EXDEV),
+            "{path}"
+        );
+    }
+    assert_eq!(
+        openat2(
+            dirfd,
+            "sub/new",
+            libc::O_CREAT | libc::O_WRONLY,
+            0o644,
+            beneath
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        openat2(
+            dirfd,
+            "../escape",
+            libc::O_CREAT | libc::O_WRONLY,
+            0o644,
+            beneath
+        ),
+        Err(libc::EXDEV)
+    );
+
+    // RESOLVE_IN_ROOT treats the directory as the root instead.
+    let in_root = libc::RESOLVE_IN_ROOT;
+    assert_eq!(openat2(dirfd, "/file", rdonly, 0, in_root), Ok(()));
+    assert_eq!(
+        openat2(dirfd, "../../sub/../file", rdonly, 0, in_root),
+        Ok(())
+    );
+
+    // RESOLVE_NO_SYMLINKS refuses every symlink.
+    let no_symlinks = libc::RESOLVE_NO_SYMLINKS;
+    assert_eq!(openat2(dirfd, "file", rdonly, 0, no_symlinks), Ok(()));
+    assert_eq!(
+        openat2(dirfd, "rel", rdonly, 0, no_symlinks),
+        Err(libc::ELOOP)
+    );
+
+    // RESOLVE_NO_MAGICLINKS refuses the links in /proc/<pid>/fd, but not
+    // ordinary ones.
+    let no_magic = libc::RESOLVE_NO_MAGICLINKS;
+    assert_eq!(openat2(dirfd, "rel", rdonly, 0, no_magic), Ok(()));
+    assert_eq!(
+        openat2(
+            libc::AT_FDCWD,
+            &format!("/proc/self/fd/{dirfd}"),
+            rdonly,
+            0,
+            no_magic
+        ),
+        Err(libc::ELOOP)
+    );
+
+    // RESOLVE_NO_XDEV refuses to cross between / and /tmp's tmpfs.
+    let no_xdev = libc::RESOLVE_NO_XDEV;
+    let rootfd = fs::File::open("/").unwrap();
+    assert_eq!(openat2(dirfd, "sub/../file", rdonly, 0, no_xdev), Ok(()));
+    assert_eq!(
+        openat2(
+            rootfd.as_raw_fd(),
+            "tmp/openat2_test/file",
+            rdonly,
+            0,
+            no_xdev
+        ),
+        Err(libc::EXDEV)
+    );
+    assert_eq!(
+        openat2(dirfd, "../..", libc::O_DIRECTORY, 0, no_xdev),
+        Err(libc::EXDEV)
+    );
+    assert_eq!(openat2(dirfd, "abs", rdonly, 0, no_xdev), Err(libc::EXDEV));
+
+    // Bad arguments.
+    assert_eq!(
+        openat2(dirfd, "file", rdonly, 0, beneath | in_root),
+        Err(libc::EINVAL)
+    );
+    assert_eq!(openat2(dirfd, "file", rdonly, 0, 0x40), Err(libc::EINVAL));
+    assert_eq!(openat2(dirfd, "file", rdonly, 0o644, 0), Err(libc::EINVAL));
+
+    // A larger struct is accepted as long as the fields we don't know are zero.
+    let path = CString::new("file").unwrap();
+    let mut how = [0u64; 4];
+    for (size, last, expected) in [(8, 0, libc::EINVAL), (32, 1, libc::E2BIG)] {
+        how[3] = last;
+        let ret = unsafe { libc::syscall(libc::SYS_openat2, dirfd, path.as_ptr(), &how, size) };
+        assert_eq!(ret, -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(expected)
+        );
+    }
+    how[3] = 0;
+    let fd = unsafe { libc::syscall(libc::SYS_openat2, dirfd, path.as_ptr(), &how, 32) };
+    assert!(fd >= 0);
+    unsafe { libc::close(fd as i32) };
+
+    fs::remove_file(format!("{dir}/sub/new")).unwrap();
+    fs::remove_dir(format!("{dir}/sub")).unwrap();
+    for name in ["file", "rel", "abs"] {
+        fs::remove_file(format!("{dir}/{name}")).unwrap();
+    }
+    fs::remove_dir(dir).unwrap();
+}
+
+register_test!(test_openat2);
+
+fn test_opath_tmpfile() {
+    use std::io::{Read, Seek, Write};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::fs::{MetadataExt, symlink};
+
+    fn errno() -> i32 {
+        std::io::Error::last_os_error().raw_os_error().unwrap()
+    }
+
+    let dir = "/tmp/opath_test";
+    fs::create_dir(dir).unwrap();
+
+    let c_dir = CString::new(dir).unwrap();
+    let none = CString::new("none").unwrap();
+    let tmpfs = CString::new("tmpfs").unwrap();
+    let ret = unsafe {
+        libc::mount(
+            none.as_ptr(),
+            c_dir.as_ptr(),
+            tmpfs.as_ptr(),
+            0,
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(ret, 0);
+
+    fs::write(format!("{dir}/file"), b"opath").unwrap();
+    symlink("file", format!("{dir}/link")).unwrap();
+    let file = CString::new(format!("{dir}/file")).unwrap();
+    let link = CString::new(format!("{dir}/link")).unwrap();
+    let empty = CString::new("").unwrap();
+
+    unsafe {
+        // An O_PATH descriptor can be looked at, but not read from.
+        let fd = libc::open(file.as_ptr(), libc::O_PATH | libc::O_RDWR | libc::O_TRUNC);
+        assert!(fd >= 0);
+        let mut st: libc::stat = std::mem::zeroed();
+        assert_eq!(libc::fstat(fd, &mut st), 0);
+        assert_eq!(st.st_size, 5);
+        assert_eq!(
+            libc::fstatat(fd, empty.as_ptr(), &mut st, libc::AT_EMPTY_PATH),
+            0
+        );
+        assert_eq!(st.st_size, 5);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(libc::read(fd, buf.as_mut_ptr().cast(), buf.len()), -1);
+        assert_eq!(errno(), libc::EBADF);
+        assert_eq!(libc::write(fd, buf.as_ptr().cast(), 1), -1);
+        assert_eq!(errno(), libc::EBADF);
+        assert_eq!(libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK), -1);
+        assert_eq!(errno(), libc::EBADF);
+        assert_eq!(libc::fcntl(fd, libc::F_GETFL) & libc::O_PATH, libc::O_PATH);
+
+        // Without AT_EMPTY_PATH, the empty path is just a missing file.
+        assert_eq!(libc::fstatat(fd, empty.as_ptr(), &mut st, 0), -1);
+
+        // It survives dup(), and can be reopened properly through /proc.
+        let dup = libc::dup(fd);
+        assert!(dup >= 0);
+        let proc = CString::new(format!("/proc/self/fd/{dup}")).unwrap();
+        let real = libc::open(proc.as_ptr(), libc::O_RDONLY);
+        assert!(real >= 0);
+        assert_eq!(libc::read(real, buf.as_mut_ptr().cast(), buf.len()), 5);
+        assert_eq!(&buf[..5], b"opath");
+        libc::close(real);
+        libc::close(dup);
+        libc::close(fd);
+
+        // With O_NOFOLLOW, it names the symlink itself.
+        let fd = libc::open(link.as_ptr(), libc::O_PATH | libc::O_NOFOLLOW);
+        assert!(fd >= 0);
+        assert_eq!(
+            libc::fstatat(fd, empty.as_ptr(), &mut st, libc::AT_EMPTY_PATH),
+            0
+        );
+        assert_eq!(st.st_mode & libc::S_IFMT, libc::S_IFLNK);
+        let n = libc::readlinkat(fd, empty.as_ptr(), buf.as_mut_ptr().cast(), buf.len());
+        assert_eq!(n, 4);
+        assert_eq!(&buf[..4], b"file");
+        libc::close(fd);
+
+        // A directory opened with O_PATH still works as a dirfd.
+        let dirfd = libc::open(c_dir.as_ptr(), libc::O_PATH | libc::O_DIRECTORY);
+        assert!(dirfd >= 0);
+        let name = CString::new("file").unwrap();
+        let fd = libc::openat(dirfd, name.as_ptr(), libc::O_RDONLY);
+        assert!(fd >= 0);
+        libc::close(fd);
+        assert_eq!(libc::fstatat(dirfd, name.as_ptr(), &mut st, 0), 0);
+        assert_eq!(st.st_size, 5);
+        let mut ents = [0u8; 256];
+        assert_eq!(
+            libc::syscall(libc::SYS_getdents64, dirfd, ents.as_mut_ptr(), ents.len()),
+            -1
+        );
+        assert_eq!(errno(), libc::EBADF);
+        libc::close(dirfd);
+    }
+
+    // O_TMPFILE makes a file with no name, which can be given one later.
+    let tmp = unsafe { libc::open(c_dir.as_ptr(), libc::O_TMPFILE | libc::O_RDWR, 0o600) };
+    assert!(tmp >= 0);
+    let mut tmp = unsafe { fs::File::from_raw_fd(tmp) };
+    tmp.write_all(b"anonymous").unwrap();
+    tmp.rewind().unwrap();
+    let mut contents = String::new();
+    tmp.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "anonymous");
+    assert_eq!(tmp.metadata().unwrap().nlink(), 0);
+    assert_eq!(fs::read_dir(dir).unwrap().count(), 2);
+
+    let named = CString::new(format!("{dir}/named")).unwrap();
+    let ret = unsafe {
+        libc::linkat(
+            tmp.as_raw_fd(),
+            empty.as_ptr(),
+            libc::AT_FDCWD,
+            named.as_ptr(),
+            libc::AT_EMPTY_PATH,
+        )
+    };
+    assert_eq!(ret, 0);
+    assert_eq!(fs::read(format!("{dir}/named")).unwrap(), b"anonymous");
+    assert_eq!(tmp.metadata().unwrap().nlink(), 1);
+    drop(tmp);
+
+    // With O_EXCL it can never be linked in.
+    let tmp = unsafe {
+        libc::open(
+            c_dir.as_ptr(),
+            libc::O_TMPFILE | libc::O_WRONLY | libc::O_EXCL,
+            0o600,
+        )
+    };
+    assert!(tmp >= 0);
+    let excl = CString::new(format!("{dir}/excl")).unwrap();
+    let proc = CString::new(format!("/proc/self/fd/{tmp}")).unwrap();
+    unsafe {
+        assert_eq!(
+            libc::linkat(
+                libc::AT_FDCWD,
+                proc.as_ptr(),
+                libc::AT_FDCWD,
+                excl.as_ptr(),
+                libc::AT_SYMLINK_FOLLOW,
+            ),
+            -1
+        );
+        assert_eq!(errno(), libc::ENOENT);
+        libc::close(tmp);
+
+        // It has to be opened for writing, and names a directory.
+        assert_eq!(
+            libc::open(c_dir.as_ptr(), libc::O_TMPFILE | libc::O_RDONLY),
+            -1
+        );
+        assert_eq!(errno(), libc::EINVAL);
+        assert_eq!(
+            libc::open(file.as_ptr(), libc::O_TMPFILE | libc::O_RDWR, 0o600),
+            -1
+        );
+        assert_eq!(errno(), libc::ENOTDIR);
+    }
+    assert!(!fs::exists(format!("{dir}/excl")).unwrap());
+
+    fs::remove_file(format!("{dir}/named")).unwrap();
+    fs::remove_file(format!("{dir}/link")).unwrap();
+    fs::remove_file(format!("{dir}/file")).unwrap();
+    assert_eq!(unsafe { libc::umount(c_dir.as_ptr()) }, 0);
+    fs::remove_dir(dir).unwrap();
+}
+
+register_test!(test_opath_tmpfile);
+
+fn test_mount_umount() {
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mount(target: &str, flags: libc::c_ulong) -> i32 {
+        let source = CString::new("none").unwrap();
+        let target = CString::new(target).unwrap();
+        let fstype = CString::new("tmpfs").unwrap();
+        unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                fstype.as_ptr(),
+                flags,
+                std::ptr::null(),
+            )
+        }
+    }
+
+    fn umount2(target: &str, flags: i32) -> Result<(), i32> {
+        let target = CString::new(target).unwrap();
+        if unsafe { libc::umount2(target.as_ptr(), flags) } == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error().raw_os_error().unwrap())
+        }
+    }
+
+    let dir = "/tmp/mount_test";
+    fs::create_dir(dir).unwrap();
+    assert_eq!(mount(dir, libc::MS_NOEXEC | libc::MS_NOSUID), 0);
+
+    let mounts = fs::read_to_string("/proc/mounts").unwrap();
+    assert!(
+        mounts.contains("none /tmp/mount_test tmpfs rw,nosuid,noexec 0 0\n"),
+        "{mounts}"
+    );
+
+    // Nothing on a noexec mount can be executed or mapped executable.
+    let script = format!("{dir}/script");
+    fs::write(&script, b"#!/bin/sh\nexit 0\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let c_script = CString::new(script.as_str()).unwrap();
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            let argv = [c_script.as_ptr(), std::ptr::null()];
+            libc::execv(c_script.as_ptr(), argv.as_ptr());
+            libc::_exit(*libc::__errno_location());
+        }
+
+        let mut status = 0;
+        libc::waitpid(pid, &mut status, 0);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), libc::EACCES);
+
+        let fd = libc::open(c_script.as_ptr(), libc::O_RDONLY);
+        assert!(fd >= 0);
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            4096,
+            libc::PROT_READ | libc::PROT_EXEC,
+            libc::MAP_PRIVATE,
+            fd,
+            0,
+        );
+        assert_eq!(addr, libc::MAP_FAILED);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EPERM)
+        );
+        libc::close(fd);
+    }
+
+    // A mount is busy while a file in it is open, or something is mounted
+    // inside it.
+    let file = fs::File::open(&script).unwrap();
+    assert_eq!(umount2(dir, 0), Err(libc::EBUSY));
+    drop(file);
+
+    let inner = format!("{dir}/inner");
+    fs::create_dir(&inner).unwrap();
+    assert_eq!(mount(&inner, 0), 0);
+    assert_eq!(umount2(dir, 0), Err(libc::EBUSY));
+    assert_eq!(umount2(&inner, 0), Ok(()));
+    assert_eq!(umount2(&inner, 0), Err(libc::EINVAL));
+
+    // A lazy unmount takes effect at once, but open files keep working.
+    let file = fs::File::open(&script).unwrap();
+    assert_eq!(umount2(dir, libc::MNT_DETACH), Ok(()));
+    assert!(fs::read_dir(dir).unwrap().next().is_none());
+    assert!(!fs::read_to_string("/proc/mounts").unwrap().contains(dir));
+    let mut contents = String::new();
+    std::io::Read::read_to_string(&mut &file, &mut contents).unwrap();
+    assert_eq!(contents, "#!/bin/sh\nexit 0\n");
+    drop(file);
+
+    assert_eq!(umount2(dir, 0), Err(libc::EINVAL));
+    assert_eq!(umount2("/", 0), Err(libc::EBUSY));
+    fs::remove_dir(dir).unwrap();
+}
+
+register_test!(test_mount_umount);
+
+fn test_bind_mount() {
+    fn bind(source: &str, target: &str, flags: libc::c_ulong) -> Result<(), i32> {
+        let source = CString::new(source).unwrap();
+        let target = CString::new(target).unwrap();
+        let ret = unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                std::ptr::null(),
+                libc::MS_BIND | flags,
+                std::ptr::null(),
+            )
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error().raw_os_error().unwrap())
+        }
+    }
+
+    fn umount(target: &str) -> Result<(), i32> {
+        let target = CString::new(target).unwrap();
+        if unsafe { libc::umount2(target.as_ptr(), 0) } == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error().raw_os_error().unwrap())
+        }
+    }
+
+    let src = "/tmp/bind_src";
+    let dst = "/tmp/bind_dst";
+    let rdst = "/tmp/bind_rdst";
+    for dir in [src, dst, rdst] {
+        fs::create_dir(dir).unwrap();
+    }
+    fs::write(format!("{src}/file"), b"hello").unwrap();
+
+    // Both paths show the same subtree.
+    assert_eq!(bind(src, dst, 0), Ok(()));
+    assert_eq!(fs::read(format!("{dst}/file")).unwrap(), b"hello");
+    fs::write(format!("{dst}/new"), b"").unwrap();
+    assert!(fs::exists(format!("{src}/new")).unwrap());
+    assert!(
+        fs::read_to_string("/proc/mounts")
+            .unwrap()
+            .contains(" /tmp/bind_dst "),
+    );
+
+    // What's mounted inside the source shows through a recursive bind.
+    let sub = format!("{src}/sub");
+    fs::create_dir(&sub).unwrap();
+    let c_sub = CString::new(sub.as_str()).unwrap();
+    let tmpfs = CString::new("tmpfs").unwrap();
+    assert_eq!(
+        unsafe {
+            libc::mount(
+                tmpfs.as_ptr(),
+                c_sub.as_ptr(),
+                tmpfs.as_ptr(),
+                0,
+                std::ptr::null(),
+            )
+        },
+        0
+    );
+    fs::write(format!("{sub}/inner"), b"").unwrap();
+    assert_eq!(bind(src, rdst, libc::MS_REC), Ok(()));
+    assert!(fs::exists(format!("{rdst}/sub/inner")).unwrap());
+
+    // Files can be bound onto files, but not onto directories.
+    let file = format!("{dst}/target");
+    fs::write(&file, b"").unwrap();
+    assert_eq!(bind(&format!("{src}/file"), &file, 0), Ok(()));
+    assert_eq!(fs::read(&file).unwrap(), b"hello");
+    assert_eq!(umount(&file), Ok(()));
+    assert_eq!(fs::read(&file).unwrap(), b"");
+    assert_eq!(bind(&format!("{src}/file"), rdst, 0), Err(libc::ENOTDIR));
+
+    // Unmounting a bind leaves the source alone.
+    assert_eq!(umount(rdst), Ok(()));
+    assert_eq!(umount(dst), Ok(()));
+    assert!(!fs::exists(format!("{dst}/file")).unwrap());
+    assert_eq!(fs::read(format!("{src}/file")).unwrap(), b"hello");
+    assert_eq!(umount(dst), Err(libc::EINVAL));
+    assert_eq!(umount(&sub), Ok(()));
+
+    fs::remove_dir(&sub).unwrap();
+    fs::remove_dir_all(src).unwrap();
+    fs::remove_dir(dst).unwrap();
+    fs::remove_dir(rdst).unwrap();
+}
+
+register_test!(test_bind_mount);
+
+fn test_mount_namespaces() {
+    fn mount(target: &str, flags: libc::c_ulong) -> i32 {
+        let target = CString::new(target).unwrap();
+        let tmpfs = CString::new("tmpfs").unwrap();
+        unsafe {
+            libc::mount(
+                tmpfs.as_ptr(),
+                target.as_ptr(),
+                tmpfs.as_ptr(),
+                flags,
+                std::ptr::null(),
+            )
+        }
+    }
+
+    fn umount(target: &str) -> i32 {
+        let target = CString::new(target).unwrap();
+        unsafe { libc::umount2(target.as_ptr(), 0) }
+    }
+
+    fn mounted(mounts: &str, path: &str) -> bool {
+        mounts.contains(&format!(" {path} "))
+    }
+
+    let private = "/tmp/mntns_private";
+    let shared = "/tmp/mntns_shared";
+    let sub = "/tmp/mntns_shared/sub";
+    let slave_sub = "/tmp/mntns_shared/slave_sub";
+    let parent_sub = "/tmp/mntns_shared/parent_sub";
+
+    fs::create_dir(private).unwrap();
+    fs::create_dir(shared).unwrap();
+    assert_eq!(mount(shared, 0), 0);
+    assert_eq!(mount(shared, libc::MS_SHARED), 0);
+    for dir in [sub, slave_sub, parent_sub] {
+        fs::create_dir(dir).unwrap();
+    }
+
+    let mut to_parent = [0; 2];
+    let mut to_child = [0; 2];
+    unsafe {
+        assert_eq!(libc::pipe(to_parent.as_mut_ptr()), 0);
+        assert_eq!(libc::pipe(to_child.as_mut_ptr()), 0);
+    }
+
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+
+    if pid == 0 {
+        let check = |ok: bool, code: i32| {
+            if !ok {
+                unsafe { libc::_exit(code) };
+            }
+        };
+
+        check(unsafe { libc::unshare(libc::CLONE_NEWNS) } == 0, 1);
+        // Seen only here.
+        check(mount(private, 0) == 0, 2);
+        // Seen in the parent too, through the shared mount.
+        check(mount(sub, 0) == 0, 3);
+        // Once a slave, what's mounted here stays here.
+        check(mount(shared, libc::MS_SLAVE) == 0, 4);
+        check(mount(slave_sub, 0) == 0, 5);
+
+        let mut byte = [0u8];
+        unsafe {
+            libc::write(to_parent[1], byte.as_ptr().cast(), 1);
+            libc::read(to_child[0], byte.as_mut_ptr().cast(), 1);
+        }
+
+        // But what the parent mounts still arrives.
+        let mounts = fs::read_to_string("/proc/self/mounts").unwrap();
+        check(mounted(&mounts, parent_sub), 6);
+        check(mounted(&mounts, private), 7);
+
+        unsafe { libc::_exit(0) };
+    }
+
+    let mut byte = [0u8];
+    unsafe { libc::read(to_parent[0], byte.as_mut_ptr().cast(), 1) };
+
+    let ours = fs::read_to_string("/proc/self/mounts").unwrap();
+    let theirs = fs::read_to_string(format!("/proc/{pid}/mounts")).unwrap();
+    assert!(!mounted(&ours, private), "{ours}");
+    assert!(mounted(&theirs, private), "{theirs}");
+    assert!(mounted(&ours, sub), "{ours}");
+    assert!(!mounted(&ours, slave_sub), "{ours}");
+    assert!(mounted(&theirs, slave_sub), "{theirs}");
+
+    assert_eq!(mount(parent_sub, 0), 0);
+    unsafe { libc::write(to_child[1], byte.as_ptr().cast(), 1) };
+
+    let mut status = 0;
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+    assert!(libc::WIFEXITED(status));
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+
+    unsafe {
+        for fd in to_parent.into_iter().chain(to_child) {
+            libc::close(fd);
+        }
+    }
+
+    assert_eq!(umount(sub), 0);
+    assert_eq!(umount(parent_sub), 0);
+    assert_eq!(umount(shared), 0);
+    fs::remove_dir(private).unwrap();
+    fs::remove_dir(shared).unwrap();
+}
+
+register_test!(test_mount_namespaces);
+
+fn test_tmpfs_mount_limits() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = "/tmp/tmpfs_limits";
+    fs::create_dir(dir).unwrap();
+
+    let mount = |data: &str| {
+        let source = CString::new("none").unwrap();
+        let target = CString::new(dir).unwrap();
+        let fstype = CString::new("tmpfs").unwrap();
+        let data = CString::new(data).unwrap();
+        unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                fstype.as_ptr(),
+                0,
+                data.as_ptr().cast(),
+            )
+        }
+    };
+    let umount = || {
+        let target = CString::new(dir).unwrap();
+        assert_eq!(unsafe { libc::umount(target.as_ptr()) }, 0);
+    };
+
+    assert_eq!(mount("size=1x"), -1);
+    assert_eq!(
+        std::io::Error::last_os_error().raw_os_error(),
+        Some(libc::EINVAL)
+    );
+
+    assert_eq!(mount("size=16k,nr_inodes=3,mode=700"), 0);
+    assert_eq!(fs::metadata(dir).unwrap().mode() & 0o7777, 0o700);
+
+    // The limits show up in statfs(), with the root directory using an inode.
+    let st = statfs(dir);
+    assert_eq!(st.f_type, 0x01021994);
+    assert_eq!((st.f_bsize, st.f_blocks, st.f_bfree), (4096, 4, 4));
+    assert_eq!((st.f_files, st.f_ffree), (3, 2));
+
+    // Writes stop once the mount's size is used up.
+    let file = format!("{dir}/file");
+    let mut f = fs::File::create(&file).unwrap();
+    let data = vec![0x5a; 32 * 1024];
+    assert_eq!(std::io::Write::write(&mut f, &data).unwrap(), 16 * 1024);
+    let err = std::io::Write::write(&mut f, &data).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+    assert_eq!((statfs(dir).f_bfree, statfs(dir).f_ffree), (0, 1));
+
+    // Truncating gives the space back.
+    f.set_len(4096).unwrap();
+    std::io::Seek::seek(&mut f, std::io::SeekFrom::Start(4096)).unwrap();
+    assert_eq!(std::io::Write::write(&mut f, &data).unwrap(), 12 * 1024);
+    drop(f);
+
+    // The root directory and the file take up two of the three inodes.
+    fs::create_dir(format!("{dir}/sub")).unwrap();
+    let err = fs::File::create(format!("{dir}/another")).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+
+    fs::remove_file(&file).unwrap();
+    fs::remove_dir(format!("{dir}/sub")).unwrap();
+    umount();
+    fs::remove_dir(dir).unwrap();
+}
+
+register_test!(test_tmpfs_mount_limits);
+
+fn statfs(path: &str) -> libc::statfs {
+    let path = CString::new(path).unwrap();
+    let mut buf = MaybeUninit::<libc::statfs>::uninit();
+
+    unsafe {
+        assert_eq!(libc::statfs(path.as_ptr(), buf.as_mut_ptr()), 0);
+        buf.assume_init()
+    }
+}
+
+fn test_statfs() {
+    const PROC_SUPER_MAGIC: libc::c_ulong = 0x9fa0;
+    const PID_FS_MAGIC: libc::c_ulong = 0x5049_4446;
+
+    let st = statfs("/proc/self");
+    assert_eq!(st.f_type, PROC_SUPER_MAGIC);
+    assert_eq!(st.f_namelen, 255);
+    assert_eq!(st.f_flags & libc::ST_RDONLY, 0);
+
+    unsafe {
+        let pidfd = libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) as i32;
+        assert!(pidfd >= 0);
+
+        let mut buf = MaybeUninit::<libc::statfs>::uninit();
+        assert_eq!(libc::fstatfs(pidfd, buf.as_mut_ptr()), 0);
+        assert_eq!(buf.assume_init().f_type, PID_FS_MAGIC);
+
+        libc::close(pidfd);
+
+        assert_eq!(libc::fstatfs(pidfd, buf.as_mut_ptr()), -1);
+        assert_eq!(*libc::__errno_location(), libc::EBADF);
+    }
+}
+
+register_test!(test_statfs);
+
+fn test_mknod() {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let null_rdev = fs::metadata("/dev/null").unwrap().rdev();
+    let path = "/tmp/mknod_null";
+    let c_path = CString::new(path).unwrap();
+
+    unsafe {
+        assert_eq!(
+            libc::mknod(c_path.as_ptr(), libc::S_IFCHR | 0o666, null_rdev),
+            0
+        );
+        assert_eq!(
+            libc::mknod(c_path.as_ptr(), libc::S_IFCHR | 0o666, null_rdev),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EEXIST)
+        );
+    }
+
+    // The node opens the device it names.
+    let meta = fs::metadata(path).unwrap();
+    assert!(meta.file_type().is_char_device());
+    assert_eq!(meta.rdev(), null_rdev);
+    assert_eq!(meta.mode() & 0o777, 0o666);
+    fs::write(path, b"discarded").unwrap();
+    assert!(fs::read(path).unwrap().is_empty());
+    fs::remove_file(path).unwrap();
+
+    // A plain mknod makes an empty regular file.
+    unsafe {
+        assert_eq!(libc::mknod(c_path.as_ptr(), 0o644, 0), 0);
+    }
+    let meta = fs::metadata(path).unwrap();
+    assert!(meta.is_file());
+    assert_eq!(meta.len(), 0);
+    fs::remove_file(path).unwrap();
+
+    unsafe {
+        assert_eq!(libc::mknod(c_path.as_ptr(), libc::S_IFDIR | 0o755, 0), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+}
+
+register_test!(test_mknod);
+
+fn test_overlayfs() {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let base = "/tmp/overlay_test";
+    let lower = format!("{base}/lower");
+    let rw = format!("{base}/rw");
+    let upper = format!("{rw}/upper");
+    let work = format!("{rw}/work");
+    let merged = format!("{base}/merged");
+    for dir in [base, &lower, &rw, &merged] {
+        fs::create_dir(dir).unwrap();
+    }
+
+    // The upper layer gets a tmpfs of its own, as the overlay stops the
+    // dentry cache from serving the filesystem it's on.
+    let rw_dir = CString::new(rw.as_str()).unwrap();
+    unsafe {
+        let tmpfs = CString::new("tmpfs").unwrap();
+        assert_eq!(
+            libc::mount(
+                tmpfs.as_ptr(),
+                rw_dir.as_ptr(),
+                tmpfs.as_ptr(),
+                0,
+                std::ptr::null()
+            ),
+            0
+        );
+    }
+    fs::create_dir(&upper).unwrap();
+    fs::create_dir(&work).unwrap();
+
+    fs::write(format!("{lower}/file"), b"lower").unwrap();
+    fs::create_dir(format!("{lower}/dir")).unwrap();
+    fs::write(format!("{lower}/dir/a"), b"a").unwrap();
+    fs::write(format!("{upper}/top"), b"upper").unwrap();
+    // Looked up so that a stale entry would be cached for it.
+    assert!(fs::metadata(format!("{upper}/new")).is_err());
+
+    let mount = |data: &str| {
+        let source = CString::new("overlay").unwrap();
+        let target = CString::new(merged.as_str()).unwrap();
+        let fstype = CString::new("overlay").unwrap();
+        let data = CString::new(data).unwrap();
+        unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                fstype.as_ptr(),
+                0,
+                data.as_ptr().cast(),
+            )
+        }
+    };
+
+    assert_eq!(mount(&format!("upperdir={upper}")), -1);
+    assert_eq!(
+        std::io::Error::last_os_error().raw_os_error(),
+        Some(libc::EINVAL)
+    );
+    assert_eq!(
+        mount(&format!("lowerdir={lower},upperdir={upper},workdir={work}")),
+        0
+    );
+
+    let mut names: Vec<_> = fs::read_dir(&merged)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["dir", "file", "top"]);
+
+    // Writing to a lower file copies it up first.
+    let ino = fs::metadata(format!("{merged}/file")).unwrap().ino();
+    fs::write(format!("{merged}/file"), b"changed").unwrap();
+    assert_eq!(fs::read(format!("{merged}/file")).unwrap(), b"changed");
+    assert_eq!(fs::metadata(format!("{merged}/file")).unwrap().ino(), ino);
+    assert_eq!(fs::read(format!("{upper}/file")).unwrap(), b"changed");
+    assert_eq!(fs::read(format!("{lower}/file")).unwrap(), b"lower");
+
+    // Names made through the overlay show up in the upper directory.
+    fs::write(format!("{merged}/new"), b"new").unwrap();
+    assert_eq!(fs::read(format!("{upper}/new")).unwrap(), b"new");
+
+    // Removing a lower file leaves a whiteout in the upper directory.
+    fs::remove_file(format!("{merged}/dir/a")).unwrap();
+    assert!(fs::metadata(format!("{merged}/dir/a")).is_err());
+    let whiteout = fs::metadata(format!("{upper}/dir/a")).unwrap();
+    assert!(whiteout.file_type().is_char_device());
+    assert_eq!(whiteout.rdev(), 0);
+    assert!(fs::metadata(format!("{lower}/dir/a")).is_ok());
+
+    // A directory remade over a removed one doesn't show the old contents.
+    fs::remove_dir(format!("{merged}/dir")).unwrap();
+    fs::create_dir(format!("{merged}/dir")).unwrap();
+    assert!(
+        fs::read_dir(format!("{merged}/dir"))
+            .unwrap()
+            .next()
+            .is_none()
+    );
+
+    let target = CString::new(merged.as_str()).unwrap();
+    assert_eq!(unsafe { libc::umount(target.as_ptr()) }, 0);
+
+    // Without an upper directory, the overlay is read-only.
+    assert_eq!(mount(&format!("lowerdir={upper}:{lower}")), 0);
+    assert_eq!(fs::read(format!("{merged}/file")).unwrap(), b"changed");
+    assert!(fs::metadata(format!("{merged}/dir/a")).is_err());
+    let err = fs::write(format!("{merged}/top"), b"x").unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EROFS));
+    assert_eq!(unsafe { libc::umount(target.as_ptr()) }, 0);
+    assert_eq!(unsafe { libc::umount(rw_dir.as_ptr()) }, 0);
+
+    fs::remove_dir_all(base).unwrap();
+}
+
+register_test!(test_overlayfs);
+
+fn test_fifo() {
+    use std::io::{Read, Write};
+    use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+
+    let path = "/tmp/fifo_test";
+    let c_path = CString::new(path).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+    assert!(fs::metadata(path).unwrap().file_type().is_fifo());
+
+    let open = |flags: i32| {
+        fs::OpenOptions::new()
+            .read(flags & libc::O_ACCMODE != libc::O_WRONLY)
+            .write(flags & libc::O_ACCMODE != libc::O_RDONLY)
+            .custom_flags(flags & !libc::O_ACCMODE)
+            .open(path)
+    };
+
+    // Without a reader, a non-blocking writer can't open, but a non-blocking
+    // reader can, and sees the end of the file.
+    let err = open(libc::O_WRONLY | libc::O_NONBLOCK).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENXIO));
+    let mut reader = open(libc::O_RDONLY | libc::O_NONBLOCK).unwrap();
+    assert_eq!(reader.read(&mut [0; 8]).unwrap(), 0);
+    drop(reader);
+
+    // A blocking open waits for the other end.
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            let mut writer = open(libc::O_WRONLY).unwrap();
+            writer.write_all(b"hello").unwrap();
+            libc::_exit(0);
+        }
+
+        let mut reader = open(libc::O_RDONLY).unwrap();
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hello");
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    // Writing once the readers have gone is a broken pipe.
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            drop(open(libc::O_RDONLY).unwrap());
+            libc::_exit(0);
+        }
+
+        let mut writer = open(libc::O_WRONLY).unwrap();
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        let err = writer.write(b"lost").unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EPIPE));
+    }
+
+    // Both ends at once never wait, and the data is shared between opens.
+    let mut both = open(libc::O_RDWR).unwrap();
+    let mut reader = open(libc::O_RDONLY).unwrap();
+    both.write_all(b"abc").unwrap();
+    let mut buf = [0; 3];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"abc");
+    drop((both, reader));
+
+    fs::remove_file(path).unwrap();
+}
+
+register_test!(test_fifo);
+
+fn test_file_locks() {
+    use std::os::fd::AsRawFd;
+
+    let path = "/tmp/lock_test";
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .unwrap();
+    let fd = file.as_raw_fd();
+
+    let record = |fd: i32, cmd: i32, l_type: i32, start: i64, len: i64| unsafe {
+        let mut fl: libc::flock = std::mem::zeroed();
+        fl.l_type = l_type as _;
+        fl.l_whence = libc::SEEK_SET as _;
+        fl.l_start = start;
+        fl.l_len = len;
+
+        if libc::fcntl(fd, cmd, &mut fl) == 0 {
+            Ok(fl)
+        } else {
+            Err(std::io::Error::last_os_error().raw_os_error().unwrap())
+        }
+    };
+
+    let in_child = |f: &dyn Fn() -> i32| unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            libc::_exit(f());
+        }
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        libc::WEXITSTATUS(status)
+    };
+
+    // flock() locks belong to the open file description: another open of the
+    // file is blocked, but an inherited descriptor shares the lock.
+    unsafe {
+        assert_eq!(libc::flock(fd, libc::LOCK_EX), 0);
+        assert_eq!(
+            in_child(&|| {
+                let other = fs::File::open(path).unwrap();
+                if libc::flock(other.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) == 0 {
+                    return 1;
+                }
+                if *libc::__errno_location() != libc::EWOULDBLOCK {
+                    return 2;
+                }
+                if libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) != 0 {
+                    return 3;
+                }
+                0
+            }),
+            0
+        );
+        assert_eq!(libc::flock(fd, libc::LOCK_UN), 0);
+    }
+
+    // Record locks belong to the process, and only conflict where they overlap.
+    record(fd, libc::F_SETLK, libc::F_WRLCK, 0, 10).unwrap();
+    let parent = std::process::id() as i32;
+    assert_eq!(
+        in_child(&|| {
+            let fl = record(fd, libc::F_GETLK, libc::F_RDLCK, 5, 1).unwrap();
+            if fl.l_type != libc::F_WRLCK as i16
+                || fl.l_start != 0
+                || fl.l_len != 10
+                || fl.l_pid != parent
+            {
+                return 1;
+            }
+            if record(fd, libc::F_SETLK, libc::F_RDLCK, 9, 2).err() != Some(libc::EAGAIN) {
+                return 2;
+            }
+            if record(fd, libc::F_SETLK, libc::F_WRLCK, 10, 0).is_err() {
+                return 3;
+            }
+            0
+        }),
+        0
+    );
+
+    // Unlocking the middle of a lock splits it.
+    record(fd, libc::F_SETLK, libc::F_UNLCK, 3, 2).unwrap();
+    assert_eq!(
+        in_child(&|| {
+            let free = record(fd, libc::F_GETLK, libc::F_WRLCK, 3, 2).unwrap();
+            let held = record(fd, libc::F_GETLK, libc::F_WRLCK, 0, 0).unwrap();
+            (free.l_type != libc::F_UNLCK as i16 || held.l_len != 3) as i32
+        }),
+        0
+    );
+
+    // Closing any descriptor for the file drops the process's record locks.
+    drop(fs::File::open(path).unwrap());
+    assert_eq!(
+        in_child(&|| record(fd, libc::F_SETLK, libc::F_WRLCK, 0, 0).is_err() as i32),
+        0
+    );
+
+    // Two processes each waiting on the other's lock is a deadlock, and one of
+    // them is told so.
+    record(fd, libc::F_SETLK, libc::F_WRLCK, 0, 1).unwrap();
+    unsafe {
+        let mut pipe = [0; 2];
+        assert_eq!(libc::pipe(pipe.as_mut_ptr()), 0);
+
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            record(fd, libc::F_SETLK, libc::F_WRLCK, 1, 1).unwrap();
+            libc::write(pipe[1], [0u8].as_ptr().cast(), 1);
+            let res = record(fd, libc::F_SETLKW, libc::F_WRLCK, 0, 1);
+            libc::_exit((res.err() == Some(libc::EDEADLK)) as i32);
+        }
+
+        let mut buf = [0u8];
+        assert_eq!(libc::read(pipe[0], buf.as_mut_ptr().cast(), 1), 1);
+        libc::usleep(100_000);
+
+        let res = record(fd, libc::F_SETLKW, libc::F_WRLCK, 1, 1);
+        if res.is_err_and(|e| e == libc::EDEADLK) {
+            record(fd, libc::F_SETLK, libc::F_UNLCK, 0, 0).unwrap();
+        }
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        let child_deadlocked = libc::WEXITSTATUS(status) == 1;
+        assert!(child_deadlocked != res.is_err_and(|e| e == libc::EDEADLK));
+
+        libc::close(pipe[0]);
+        libc::close(pipe[1]);
+    }
+
+    drop(file);
+    fs::remove_file(path).unwrap();
+}
+
+register_test!(test_file_locks);
+
+fn test_xattr() {
+    let path = "/tmp/xattr_test";
+    fs::write(path, b"data").unwrap();
+    let c_path = CString::new(path).unwrap();
+    let name = CString::new("user.test").unwrap();
+    let value = b"hello";
+
+    let errno = || unsafe { *libc::__errno_location() };
+
+    unsafe {
+        let ret = libc::setxattr(
+            c_path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        );
+        assert_eq!(ret, 0, "setxattr failed");
+
+        // Creating it again fails.
+        let ret = libc::setxattr(
+            c_path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            libc::XATTR_CREATE,
+        );
+        assert_eq!(ret, -1);
+        assert_eq!(errno(), libc::EEXIST);
+
+        // A zero size asks for the length.
+        let len = libc::getxattr(c_path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0);
+        assert_eq!(len, value.len() as isize);
+
+        let mut buf = [0u8; 16];
+        let len = libc::getxattr(
+            c_path.as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+        );
+        assert_eq!(&buf[..len as usize], value);
+
+        let len = libc::getxattr(c_path.as_ptr(), name.as_ptr(), buf.as_mut_ptr().cast(), 2);
+        assert_eq!(len, -1);
+        assert_eq!(errno(), libc::ERANGE);
+
+        let mut list = [0u8; 64];
+        let len = libc::listxattr(c_path.as_ptr(), list.as_mut_ptr().cast(), list.len());
+        assert_eq!(&list[..len as usize], b"user.test\0");
+
+        // Only the known namespaces are allowed.
+        let bad = CString::new("bogus.test").unwrap();
+        let ret = libc::setxattr(
+            c_path.as_ptr(),
+            bad.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        );
+        assert_eq!(ret, -1);
+        assert_eq!(errno(), libc::EOPNOTSUPP);
+
+        assert_eq!(libc::removexattr(c_path.as_ptr(), name.as_ptr()), 0);
+
+        let len = libc::getxattr(c_path.as_ptr(), name.as_ptr(), buf.as_mut_ptr().cast(), 16);
+        assert_eq!(len, -1);
+        assert_eq!(errno(), libc::ENODATA);
+
+        assert_eq!(libc::removexattr(c_path.as_ptr(), name.as_ptr()), -1);
+        assert_eq!(errno(), libc::ENODATA);
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+register_test!(test_xattr);
+
+fn test_fallocate() {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::FileExt;
+
+    let dir = "/tmp/fallocate";
+    fs::create_dir(dir).unwrap();
+
+    let c_dir = CString::new(dir).unwrap();
+    let none = CString::new("none").unwrap();
+    let tmpfs = CString::new("tmpfs").unwrap();
+    let data = CString::new("size=32k").unwrap();
+    let ret = unsafe {
+        libc::mount(
+            none.as_ptr(),
+            c_dir.as_ptr(),
+            tmpfs.as_ptr(),
+            0,
+            data.as_ptr().cast(),
+        )
+    };
+    assert_eq!(ret, 0);
+
+    let path = format!("{dir}/file");
+    let f = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    let fd = f.as_raw_fd();
+    let errno = || unsafe { *libc::__errno_location() };
+
+    unsafe {
+        // Preallocation extends the file and uses up space.
+        assert_eq!(libc::fallocate(fd, 0, 0, 8192), 0);
+        assert_eq!(f.metadata().unwrap().len(), 8192);
+        assert_eq!(statfs(dir).f_bfree, 6);
+
+        // Unless the size is to be kept.
+        assert_eq!(
+            libc::fallocate(fd, libc::FALLOC_FL_KEEP_SIZE, 8192, 4096),
+            0
+        );
+        assert_eq!(f.metadata().unwrap().len(), 8192);
+        assert_eq!(statfs(dir).f_bfree, 5);
+
+        // Punching a hole frees the whole pages in it and zeroes the rest.
+        f.write_all_at(&[0xaa; 12288], 0).unwrap();
+        assert_eq!(
+            libc::fallocate(
+                fd,
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                100,
+                8192
+            ),
+            0
+        );
+        assert_eq!(f.metadata().unwrap().len(), 12288);
+        assert_eq!(statfs(dir).f_bfree, 6);
+
+        let mut buf = vec![0; 12288];
+        f.read_exact_at(&mut buf, 0).unwrap();
+        assert!(buf[..100].iter().all(|&b| b == 0xaa));
+        assert!(buf[100..8292].iter().all(|&b| b == 0));
+        assert!(buf[8292..].iter().all(|&b| b == 0xaa));
+
+        // Running out of space.
+        assert_eq!(libc::fallocate(fd, 0, 0, 64 * 1024), -1);
+        assert_eq!(errno(), libc::ENOSPC);
+
+        // A hole can't change the size, so it must be asked for as such.
+        assert_eq!(libc::fallocate(fd, libc::FALLOC_FL_PUNCH_HOLE, 0, 4096), -1);
+        assert_eq!(errno(), libc::EOPNOTSUPP);
+        assert_eq!(libc::fallocate(fd, 0, 0, 0), -1);
+        assert_eq!(errno(), libc::EINVAL);
+        assert_eq!(libc::fallocate(fd, 0, -1, 4096), -1);
+        assert_eq!(errno(), libc::EINVAL);
+
+        // The file has to be open for writing, and seekable.
+        let ro = fs::File::open(&path).unwrap();
+        assert_eq!(libc::fallocate(ro.as_raw_fd(), 0, 0, 4096), -1);
+        assert_eq!(errno(), libc::EBADF);
+
+        let mut fds = [0; 2];
+        assert_eq!(libc::pipe(fds.as_mut_ptr()), 0);
+        assert_eq!(libc::fallocate(fds[1], 0, 0, 4096), -1);
+        assert_eq!(errno(), libc::ESPIPE);
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+
+    drop(f);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(unsafe { libc::umount(c_dir.as_ptr()) }, 0);
+    fs::remove_dir(dir).unwrap();
+}
+
+register_test!(test_fallocate);
+
+fn test_seek_data_hole() {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::FileExt;
+
+    let dir = "/tmp/seek_hole";
+    fs::create_dir(dir).unwrap();
+
+    let c_dir = CString::new(dir).unwrap();
+    let none = CString::new("none").unwrap();
+    let tmpfs = CString::new("tmpfs").unwrap();
+    let ret = unsafe {
+        libc::mount(
+            none.as_ptr(),
+            c_dir.as_ptr(),
+            tmpfs.as_ptr(),
+            0,
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(ret, 0);
+
+    let path = format!("{dir}/file");
+    let f = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    let fd = f.as_raw_fd();
+    let errno = || unsafe { *libc::__errno_location() };
+    const PG: i64 = 4096;
+
+    // Data, a punched hole, data, then a hole left by extending the file.
+    f.write_all_at(&[0x55; 4 * PG as usize], 0).unwrap();
+    unsafe {
+        assert_eq!(
+            libc::fallocate(
+                fd,
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                PG,
+                2 * PG
+            ),
+            0
+        );
+    }
+    f.set_len(6 * PG as u64).unwrap();
+
+    unsafe {
+        assert_eq!(libc::lseek(fd, 0, libc::SEEK_DATA), 0);
+        assert_eq!(libc::lseek(fd, 0, libc::SEEK_HOLE), PG);
+        assert_eq!(libc::lseek(fd, PG + 10, libc::SEEK_DATA), 3 * PG);
+        assert_eq!(libc::lseek(fd, 3 * PG, libc::SEEK_HOLE), 4 * PG);
+        assert_eq!(libc::lseek(fd, 5 * PG, libc::SEEK_HOLE), 5 * PG);
+
+        // The position moves to where the data was found.
+        assert_eq!(libc::lseek(fd, 0, libc::SEEK_CUR), 5 * PG);
+
+        // Nothing but hole is left after the last data, and nothing at all
+        // past the end.
+        assert_eq!(libc::lseek(fd, 4 * PG, libc::SEEK_DATA), -1);
+        assert_eq!(errno(), libc::ENXIO);
+        assert_eq!(libc::lseek(fd, 6 * PG, libc::SEEK_HOLE), -1);
+        assert_eq!(errno(), libc::ENXIO);
+        assert_eq!(libc::lseek(fd, -1, libc::SEEK_DATA), -1);
+        assert_eq!(errno(), libc::ENXIO);
+    }
+
+    // A file with no holes has only the one at its end.
+    fs::write(format!("{dir}/dense"), b"dense").unwrap();
+    let dense = fs::File::open(format!("{dir}/dense")).unwrap();
+    unsafe {
+        assert_eq!(libc::lseek(dense.as_raw_fd(), 1, libc::SEEK_DATA), 1);
+        assert_eq!(libc::lseek(dense.as_raw_fd(), 1, libc::SEEK_HOLE), 5);
+    }
+
+    drop((f, dense));
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(format!("{dir}/dense")).unwrap();
+    assert_eq!(unsafe { libc::umount(c_dir.as_ptr()) }, 0);
+    fs::remove_dir(dir).unwrap();
+}
+
+register_test!(test_seek_data_hole);
+
+fn test_sendfile() {
+    use std::io::{Read, Seek, SeekFrom};
+    use std::os::fd::AsRawFd;
+
+    let src = "/tmp/sendfile_src";
+    let dst = "/tmp/sendfile_dst";
+    let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(src, &data).unwrap();
+
+    let mut input = fs::File::open(src).unwrap();
+    let output = fs::File::create(dst).unwrap();
+    let (in_fd, out_fd) = (input.as_raw_fd(), output.as_raw_fd());
+    let errno = || unsafe { *libc::__errno_location() };
+
+    unsafe {
+        // From the file position, across several pages, into a file.
+        assert_eq!(
+            libc::sendfile(out_fd, in_fd, std::ptr::null_mut(), 20000),
+            10000
+        );
+        assert_eq!(fs::read(dst).unwrap(), data);
+        assert_eq!(libc::sendfile(out_fd, in_fd, std::ptr::null_mut(), 100), 0);
+
+        // From an offset, which moves on while the file position stays put.
+        input.seek(SeekFrom::Start(0)).unwrap();
+        let mut off: libc::off_t = 4090;
+        let mut fds = [0; 2];
+        assert_eq!(libc::pipe(fds.as_mut_ptr()), 0);
+        assert_eq!(libc::sendfile(fds[1], in_fd, &mut off, 100), 100);
+        assert_eq!(off, 4190);
+        assert_eq!(input.stream_position().unwrap(), 0);
+
+        let mut buf = [0u8; 100];
+        assert_eq!(libc::read(fds[0], buf.as_mut_ptr().cast(), 100), 100);
+        assert_eq!(buf[..], data[4090..4190]);
+
+        // Into a socket.
+        let mut sv = [0; 2];
+        assert_eq!(
+            libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, sv.as_mut_ptr()),
+            0
+        );
+        let mut off: libc::off_t = 9900;
+        assert_eq!(libc::sendfile(sv[0], in_fd, &mut off, 1000), 100);
+        assert_eq!(off, 10000);
+        assert_eq!(libc::read(sv[1], buf.as_mut_ptr().cast(), 100), 100);
+        assert_eq!(buf[..], data[9900..]);
+
+        // The ends have to be open the right way round.
+        assert_eq!(libc::sendfile(in_fd, out_fd, std::ptr::null_mut(), 1), -1);
+        assert_eq!(errno(), libc::EBADF);
+
+        let mut off: libc::off_t = -1;
+        assert_eq!(libc::sendfile(fds[1], in_fd, &mut off, 1), -1);
+        assert_eq!(errno(), libc::EINVAL);
+
+        for fd in fds.into_iter().chain(sv) {
+            libc::close(fd);
+        }
+    }
+
+    let mut rest = Vec::new();
+    input.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, data);
+
+    drop((input, output));
+    fs::remove_file(src).unwrap();
+    fs::remove_file(dst).unwrap();
+}
+
+register_test!(test_sendfile);
+
+fn test_splice_tee() {
+    use std::os::fd::AsRawFd;
+
+    let path = "/tmp/splice_file";
+    let data: Vec<u8> = (0..6000u32).map(|i| (i % 253) as u8).collect();
+    fs::write(path, &data).unwrap();
+
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .unwrap();
+    let fd = file.as_raw_fd();
+    let errno = || unsafe { *libc::__errno_location() };
+    let null = std::ptr::null_mut();
+
+    unsafe {
+        let mut a = [0; 2];
+        let mut b = [0; 2];
+        assert_eq!(libc::pipe(a.as_mut_ptr()), 0);
+        assert_eq!(libc::pipe(b.as_mut_ptr()), 0);
+
+        // File to pipe from an offset, leaving the file position alone.
+        let mut off: libc::loff_t = 4000;
+        assert_eq!(libc::splice(fd, &mut off, a[1], null, 100, 0), 100);
+        assert_eq!(off, 4100);
+        assert_eq!(libc::lseek(fd, 0, libc::SEEK_CUR), 0);
+
+        // Copy it into the second pipe, then move it there.
+        assert_eq!(libc::tee(a[0], b[1], 100, 0), 100);
+        assert_eq!(libc::splice(a[0], null, b[1], null, 100, 0), 100);
+
+        let mut buf = [0u8; 200];
+        assert_eq!(libc::read(b[0], buf.as_mut_ptr().cast(), 200), 200);
+        assert_eq!(buf[..100], data[4000..4100]);
+        assert_eq!(buf[100..], data[4000..4100]);
+
+        // Pipe to file at an offset.
+        assert_eq!(libc::write(a[1], b"spliced".as_ptr().cast(), 7), 7);
+        let mut off: libc::loff_t = 10;
+        assert_eq!(libc::splice(a[0], null, fd, &mut off, 100, 0), 7);
+        assert_eq!(off, 17);
+        assert_eq!(&fs::read(path).unwrap()[10..17], b"spliced");
+
+        // Nothing to move.
+        assert_eq!(
+            libc::splice(a[0], null, b[1], null, 10, libc::SPLICE_F_NONBLOCK),
+            -1
+        );
+        assert_eq!(errno(), libc::EAGAIN);
+        assert_eq!(libc::tee(a[0], b[1], 10, libc::SPLICE_F_NONBLOCK), -1);
+        assert_eq!(errno(), libc::EAGAIN);
+
+        // Pipe ends can't take an offset, and one end has to be a pipe.
+        let mut off: libc::loff_t = 0;
+        assert_eq!(libc::splice(a[0], &mut off, b[1], null, 10, 0), -1);
+        assert_eq!(errno(), libc::ESPIPE);
+        assert_eq!(libc::splice(fd, null, fd, null, 10, 0), -1);
+        assert_eq!(errno(), libc::EINVAL);
+        assert_eq!(libc::tee(a[0], a[1], 10, 0), -1);
+        assert_eq!(errno(), libc::EINVAL);
+
+        // Once the writers have gone, an empty pipe is at end of file.
+        libc::close(a[1]);
+        assert_eq!(libc::splice(a[0], null, b[1], null, 10, 0), 0);
+        assert_eq!(libc::tee(a[0], b[1], 10, 0), 0);
+
+        for fd in [a[0], b[0], b[1]] {
+            libc::close(fd);
+        }
+    }
+
+    drop(file);
+    fs::remove_file(path).unwrap();
+}
+
+register_test!(test_splice_tee);
+
+fn test_copy_file_range() {
+    use std::io::{Seek, SeekFrom, Write};
+    use std::os::fd::AsRawFd;
+
+    let src = "/tmp/copy_file_range_src";
+    let dst = "/tmp/copy_file_range_dst";
+    let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+
+    // Data, then a hole, then more data.
+    let mut input = fs::File::create(src).unwrap();
+    input.write_all(&data).unwrap();
+    input.seek(SeekFrom::Start(200000)).unwrap();
+    input.write_all(&data[..100]).unwrap();
+    drop(input);
+
+    let mut expected = data.clone();
+    expected.resize(200000, 0);
+    expected.extend_from_slice(&data[..100]);
+
+    let input = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(src)
+        .unwrap();
+    let mut output = fs::File::create(dst).unwrap();
+    let (in_fd, out_fd) = (input.as_raw_fd(), output.as_raw_fd());
+    let null = std::ptr::null_mut();
+    let errno = || unsafe { *libc::__errno_location() };
+
+    unsafe {
+        // From the file positions, stopping at the end of the source.
+        assert_eq!(
+            libc::copy_file_range(in_fd, null, out_fd, null, 1 << 20, 0),
+            200100
+        );
+        assert_eq!(fs::read(dst).unwrap(), expected);
+        assert_eq!(libc::copy_file_range(in_fd, null, out_fd, null, 100, 0), 0);
+
+        // From and to offsets, which move on while the positions stay put.
+        let (mut off_in, mut off_out): (libc::off64_t, libc::off64_t) = (4090, 10);
+        assert_eq!(
+            libc::copy_file_range(in_fd, &mut off_in, out_fd, &mut off_out, 100, 0),
+            100
+        );
+        assert_eq!((off_in, off_out), (4190, 110));
+        assert_eq!(output.stream_position().unwrap(), 200100);
+        assert_eq!(fs::read(dst).unwrap()[10..110], data[4090..4190]);
+
+        // Within one file, the ranges mustn't overlap.
+        let (mut off_in, mut off_out): (libc::off64_t, libc::off64_t) = (0, 50);
+        assert_eq!(
+            libc::copy_file_range(in_fd, &mut off_in, in_fd, &mut off_out, 100, 0),
+            -1
+        );
+        assert_eq!(errno(), libc::EINVAL);
+
+        let (mut off_in, mut off_out): (libc::off64_t, libc::off64_t) = (0, 300000);
+        assert_eq!(
+            libc::copy_file_range(in_fd, &mut off_in, in_fd, &mut off_out, 100, 0),
+            100
+        );
+
+        // Only regular files, with no flags.
+        assert_eq!(libc::copy_file_range(in_fd, null, out_fd, null, 1, 1), -1);
+        assert_eq!(errno(), libc::EINVAL);
+
+        let mut fds = [0; 2];
+        assert_eq!(libc::pipe(fds.as_mut_ptr()), 0);
+        assert_eq!(libc::copy_file_range(in_fd, null, fds[1], null, 1, 0), -1);
+        assert_eq!(errno(), libc::EINVAL);
+
+        for fd in fds {
+            libc::close(fd);
+        }
+    }
+
+    drop((input, output));
+
+    // `std::fs::copy` goes through copy_file_range.
+    fs::remove_file(dst).unwrap();
+    assert_eq!(fs::copy(src, dst).unwrap(), 300100);
+    assert_eq!(fs::read(dst).unwrap(), fs::read(src).unwrap());
+
+    fs::remove_file(src).unwrap();
+    fs::remove_file(dst).unwrap();
+}
+
+register_test!(test_copy_file_range);
+
+fn test_vectored_io() {
+    use std::io::{Seek, SeekFrom};
+    use std::os::fd::AsRawFd;
+
+    let path = "/tmp/vectored_io";
+    let mut file = fs::File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .unwrap();
+    let fd = file.as_raw_fd();
+    let errno = || unsafe { *libc::__errno_location() };
+    let iov = |buf: &[u8]| libc::iovec {
+        iov_base: buf.as_ptr() as *mut _,
+        iov_len: buf.len(),
+    };
+
+    unsafe {
+        // The pieces land one after another, and the position moves past
+        // them all.
+        let parts = [iov(b"hello"), iov(b""), iov(b", "), iov(b"world")];
+        assert_eq!(libc::writev(fd, parts.as_ptr(), parts.len() as _), 12);
+        assert_eq!(file.stream_position().unwrap(), 12);
+
+        // Each piece of a positioned write follows on from the last, and the
+        // position stays put.
+        let parts = [iov(b"HE"), iov(b"LLO")];
+        assert_eq!(libc::pwritev(fd, parts.as_ptr(), parts.len() as _, 0), 5);
+        assert_eq!(file.stream_position().unwrap(), 12);
+        assert_eq!(fs::read(path).unwrap(), b"HELLO, world");
+
+        // Reading runs out at the end of the file, part way through a piece.
+        let (mut a, mut b) = ([0u8; 4], [0u8; 16]);
+        let parts = [iov(&a), iov(&b)];
+        assert_eq!(libc::preadv(fd, parts.as_ptr(), parts.len() as _, 3), 9);
+        assert_eq!(&a, b"LO, ");
+        assert_eq!(&b[..5], b"world");
+
+        // The `2` variants take -1 to mean the file position.
+        file.seek(SeekFrom::Start(7)).unwrap();
+        (a, b) = ([0u8; 4], [0u8; 16]);
+        let parts = [iov(&a), iov(&b)];
+        assert_eq!(
+            libc::preadv2(fd, parts.as_ptr(), parts.len() as _, -1, 0),
+            5
+        );
+        assert_eq!(&a, b"worl");
+        assert_eq!(b[0], b'd');
+        assert_eq!(file.stream_position().unwrap(), 12);
+
+        assert_eq!(libc::preadv(fd, parts.as_ptr(), parts.len() as _, -1), -1);
+        assert_eq!(errno(), libc::EINVAL);
+        assert_eq!(
+            libc::preadv2(fd, parts.as_ptr(), parts.len() as _, 0, 0x10),
+            -1
+        );
+        assert_eq!(errno(), libc::EOPNOTSUPP);
+
+        // Too many pieces.
+        let many = vec![iov(&a); 1025];
+        assert_eq!(libc::readv(fd, many.as_ptr(), many.len() as _), -1);
+        assert_eq!(errno(), libc::EINVAL);
+
+        // A pipe hands over what it has, rather than waiting to fill every
+        // piece.
+        let mut pipe = [0; 2];
+        assert_eq!(libc::pipe(pipe.as_mut_ptr()), 0);
+        assert_eq!(libc::write(pipe[1], b"abcdef".as_ptr().cast(), 6), 6);
+        (a, b) = ([0u8; 4], [0u8; 16]);
+        let parts = [iov(&a), iov(&b)];
+        assert_eq!(libc::readv(pipe[0], parts.as_ptr(), parts.len() as _), 6);
+        assert_eq!(&a, b"abcd");
+        assert_eq!(&b[..2], b"ef");
+        libc::close(pipe[0]);
+        libc::close(pipe[1]);
+    }
+
+    drop(file);
+    fs::remove_file(path).unwrap();
+}
+
+register_test!(test_vectored_io);
+
+fn test_pread_pwrite() {
+    use std::io::{Seek, SeekFrom};
+    use std::os::fd::AsRawFd;
+
+    const THREADS: usize = 4;
+    const CHUNK: usize = 1024;
+
+    let path = "/tmp/pread_pwrite";
+    let mut file = fs::File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .unwrap();
+    let fd = file.as_raw_fd();
+    let errno = || unsafe { *libc::__errno_location() };
+    let pattern = |i: usize| (i * 7 % 251) as u8;
+
+    // Threads write and then read back their own chunks through the one
+    // descriptor, none of them moving its position.
+    file.seek(SeekFrom::Start(100)).unwrap();
+
+    std::thread::scope(|s| {
+        for t in 0..THREADS {
+            s.spawn(move || {
+                let base = t * CHUNK;
+                let data: Vec<u8> = (base..base + CHUNK).map(pattern).collect();
+
+                for _ in 0..50 {
+                    let ret = unsafe {
+                        libc::pwrite(fd, data.as_ptr().cast(), CHUNK, base as libc::off_t)
+                    };
+                    assert_eq!(ret, CHUNK as isize);
+
+                    let mut back = vec![0u8; CHUNK];
+                    let ret = unsafe {
+                        libc::pread(fd, back.as_mut_ptr().cast(), CHUNK, base as libc::off_t)
+                    };
+                    assert_eq!(ret, CHUNK as isize);
+                    assert_eq!(back, data);
+                }
+            });
+        }
+    });
+
+    assert_eq!(file.stream_position().unwrap(), 100);
+    let contents = fs::read(path).unwrap();
+    assert_eq!(contents.len(), THREADS * CHUNK);
+    assert!(contents.iter().enumerate().all(|(i, &b)| b == pattern(i)));
+
+    unsafe {
+        // Writing past the end leaves a hole that reads back as zeroes.
+        let end = (THREADS * CHUNK) as libc::off_t;
+        assert_eq!(libc::pwrite(fd, b"tail".as_ptr().cast(), 4, end + 100), 4);
+        let mut buf = [0xffu8; 104];
+        assert_eq!(libc::pread(fd, buf.as_mut_ptr().cast(), 104, end), 104);
+        assert!(buf[..100].iter().all(|&b| b == 0));
+        assert_eq!(&buf[100..], b"tail");
+
+        // At and past the end there's nothing to read.
+        assert_eq!(libc::pread(fd, buf.as_mut_ptr().cast(), 1, end + 104), 0);
+        assert_eq!(libc::pread(fd, buf.as_mut_ptr().cast(), 1, end + 4096), 0);
+        assert_eq!(file.stream_position().unwrap(), 100);
+
+        assert_eq!(libc::pread(fd, buf.as_mut_ptr().cast(), 1, -1), -1);
+        assert_eq!(errno(), libc::EINVAL);
+        assert_eq!(libc::pwrite(fd, buf.as_ptr().cast(), 1, -4096), -1);
+        assert_eq!(errno(), libc::EINVAL);
+
+        // A pipe has no position to read or write at.
+        let mut pipe = [0; 2];
+        assert_eq!(libc::pipe(pipe.as_mut_ptr()), 0);
+        assert_eq!(libc::write(pipe[1], b"abc".as_ptr().cast(), 3), 3);
+        assert_eq!(libc::pread(pipe[0], buf.as_mut_ptr().cast(), 3, 0), -1);
+        assert_eq!(errno(), libc::ESPIPE);
+        assert_eq!(libc::pwrite(pipe[1], b"abc".as_ptr().cast(), 3, 0), -1);
+        assert_eq!(errno(), libc::ESPIPE);
+
+        let iov = libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: 3,
+        };
+        assert_eq!(libc::preadv(pipe[0], &iov, 1, 0), -1);
+        assert_eq!(errno(), libc::ESPIPE);
+
+        // What was written is still there for an ordinary read.
+        assert_eq!(libc::read(pipe[0], buf.as_mut_ptr().cast(), 3), 3);
+        assert_eq!(&buf[..3], b"abc");
+        libc::close(pipe[0]);
+        libc::close(pipe[1]);
+    }
+
+    drop(file);
+    fs::remove_file(path).unwrap();
+}
+
+register_test!(test_pread_pwrite);
+
+fn test_direct_io() {
+    use std::os::unix::fs::FileExt;
+
+    let path = CString::new("/dev/ram0").unwrap();
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_DIRECT) };
+    assert!(fd >= 0);
+    let errno = || unsafe { *libc::__errno_location() };
+
+    let mut bs: libc::c_int = 0;
+    assert_eq!(unsafe { libc::ioctl(fd, libc::BLKSSZGET, &mut bs) }, 0);
+    let bs = bs as usize;
+    assert_ne!(
+        unsafe { libc::fcntl(fd, libc::F_GETFL) } & libc::O_DIRECT,
+        0
+    );
+
+    // Untouched anonymous pages, so that the reads have to fault them in.
+    let len = 3 * 4096;
+    let buf = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    assert_ne!(buf, libc::MAP_FAILED);
+    let bytes = unsafe { std::slice::from_raw_parts_mut(buf.cast::<u8>(), len) };
+
+    // Starting part way into a page, the transfer runs across into the next
+    // two, and matches what a buffered read sees.
+    let start = bs;
+    let count = 2 * 4096;
+    let offset = 2 * bs as libc::off_t;
+    let ret = unsafe { libc::pread(fd, bytes[start..].as_mut_ptr().cast(), count, offset) };
+    assert_eq!(ret, count as isize);
+
+    let mut expected = vec![0u8; count];
+    fs::File::open("/dev/ram0")
+        .unwrap()
+        .read_exact_at(&mut expected, offset as u64)
+        .unwrap();
+    assert_eq!(&bytes[start..start + count], &expected[..]);
+
+    // The buffer, the length and the offset all have to be whole blocks.
+    unsafe {
+        assert_eq!(libc::pread(fd, buf.cast::<u8>().add(1).cast(), bs, 0), -1);
+        assert_eq!(errno(), libc::EINVAL);
+        assert_eq!(libc::pread(fd, buf, bs + 1, 0), -1);
+        assert_eq!(errno(), libc::EINVAL);
+        assert_eq!(libc::pread(fd, buf, bs, 1), -1);
+        assert_eq!(errno(), libc::EINVAL);
+
+        // A plain read goes from, and moves, the file position.
+        assert_eq!(libc::read(fd, buf, bs), bs as isize);
+        assert_eq!(libc::lseek(fd, 0, libc::SEEK_CUR), bs as libc::off_t);
+
+        libc::munmap(buf, len);
+        libc::close(fd);
+    }
+}
+
+register_test!(test_direct_io);
+
+fn test_blkdev_seek_bounds() {
+    use std::io::{Seek, SeekFrom};
+    use std::os::fd::AsRawFd;
+
+    let mut ram0 = fs::File::open("/dev/ram0").unwrap();
+    let size = ram0.seek(SeekFrom::End(0)).unwrap();
+
+    // Anywhere up to the end is fine, but nothing past it.
+    assert_eq!(ram0.seek(SeekFrom::Start(size)).unwrap(), size);
+    for pos in [
+        SeekFrom::Start(size + 1),
+        SeekFrom::Start(u64::MAX - 1),
+        SeekFrom::End(1),
+        SeekFrom::Current(i64::MAX),
+    ] {
+        let err = ram0.seek(pos).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+    }
+    assert_eq!(ram0.stream_position().unwrap(), size);
+
+    // Reads at or beyond the end see nothing.
+    let mut buf = [0u8; 512];
+    let ret = unsafe {
+        libc::pread(
+            ram0.as_raw_fd(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+            i64::MAX - 1,
+        )
+    };
+    assert_eq!(ret, 0);
+}
+
+register_test!(test_blkdev_seek_bounds);
+
+fn test_faccessat2() {
+    use std::os::unix::fs::{PermissionsExt, symlink};
+
+    let file = "/tmp/faccessat2_file";
+    let link = "/tmp/faccessat2_link";
+    fs::write(file, b"x").unwrap();
+    fs::set_permissions(file, fs::Permissions::from_mode(0o600)).unwrap();
+    symlink("/tmp/faccessat2_missing", link).unwrap();
+
+    let c_file = CString::new(file).unwrap();
+    let c_link = CString::new(link).unwrap();
+    let access = |path: &CStr, mode: libc::c_int, flags: libc::c_int| unsafe {
+        libc::syscall(
+            libc::SYS_faccessat2,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            mode,
+            flags,
+        )
+    };
+    let errno = || unsafe { *libc::__errno_location() };
+
+    // Unknown flags and mode bits are refused.
+    assert_eq!(access(&c_file, libc::R_OK, 0x4), -1);
+    assert_eq!(errno(), libc::EINVAL);
+    assert_eq!(access(&c_file, 0x8, 0), -1);
+    assert_eq!(errno(), libc::EINVAL);
+
+    // A dangling symlink exists only if it isn't followed.
+    assert_eq!(access(&c_link, libc::F_OK, 0), -1);
+    assert_eq!(errno(), libc::ENOENT);
+    assert_eq!(access(&c_link, libc::F_OK, libc::AT_SYMLINK_NOFOLLOW), 0);
+
+    // As a set-user-ID-root program would be, with an unprivileged invoker.
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+
+    if pid == 0 {
+        let check = |ok: bool, code: i32| {
+            if !ok {
+                unsafe { libc::_exit(code) };
+            }
+        };
+
+        check(unsafe { libc::setresuid(1000, 0, 0) } == 0, 1);
+        // The invoker can't read the file...
+        check(access(&c_file, libc::R_OK, 0) == -1, 2);
+        check(errno() == libc::EACCES, 3);
+        // ...but the program itself can.
+        check(
+            access(&c_file, libc::R_OK | libc::W_OK, libc::AT_EACCESS) == 0,
+            4,
+        );
+
+        unsafe { libc::_exit(0) };
+    }
+
+    let mut status = 0;
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+    fs::remove_file(link).unwrap();
+    fs::remove_file(file).unwrap();
+    assert!(
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0,
+        "child failed at step {}",
+        libc::WEXITSTATUS(status)
+    );
+}
+
+register_test!(test_faccessat2);
+
+fn test_mountinfo() {
+    use std::os::unix::fs::MetadataExt;
+
+    let outer = "/tmp/mountinfo_outer";
+    let bound = "/tmp/mountinfo_bound";
+    let tmpfs = CString::new("tmpfs").unwrap();
+    let c_outer = CString::new(outer).unwrap();
+    let c_bound = CString::new(bound).unwrap();
+
+    fs::create_dir(outer).unwrap();
+    fs::create_dir(bound).unwrap();
+    unsafe {
+        assert_eq!(
+            libc::mount(
+                tmpfs.as_ptr(),
+                c_outer.as_ptr(),
+                tmpfs.as_ptr(),
+                libc::MS_NOEXEC,
+                std::ptr::null(),
+            ),
+            0
+        );
+    }
+    fs::create_dir(format!("{outer}/sub dir")).unwrap();
+    let c_sub = CString::new(format!("{outer}/sub dir")).unwrap();
+    unsafe {
+        assert_eq!(
+            libc::mount(
+                c_sub.as_ptr(),
+                c_bound.as_ptr(),
+                std::ptr::null(),
+                libc::MS_BIND,
+                std::ptr::null(),
+            ),
+            0
+        );
+    }
+
+    let info = fs::read_to_string("/proc/self/mountinfo").unwrap();
+    let entry = |mount_point: &str| -> Vec<String> {
+        info.lines()
+            .map(|line| line.split(' ').map(str::to_string).collect::<Vec<_>>())
+            .find(|fields| fields[4] == mount_point)
+            .unwrap_or_else(|| panic!("{mount_point} missing from\n{info}"))
+    };
+
+    let root = entry("/");
+    let outer_entry = entry(outer);
+    let bound_entry = entry(bound);
+
+    // Every line has the separator, then the type, source and super options.
+    for line in info.lines() {
+        let fields: Vec<_> = line.split(' ').collect();
+        let sep = fields.iter().position(|f| *f == "-").unwrap();
+        assert_eq!(fields.len(), sep + 4, "{line}");
+    }
+
+    // The tmpfs is mounted inside the root filesystem, and the bind shows
+    // where in the tmpfs it comes from, escaped.
+    assert_ne!(outer_entry[0], root[0]);
+    assert_eq!(outer_entry[3], "/");
+    assert!(outer_entry[5].split(',').any(|opt| opt == "noexec"));
+    assert_eq!(bound_entry[3], "/sub\\040dir");
+    assert_eq!(bound_entry[2], outer_entry[2]);
+
+    let dev = fs::metadata(outer).unwrap().dev();
+    assert_eq!(
+        outer_entry[2],
+        format!("{}:{}", libc::major(dev), libc::minor(dev))
+    );
+
+    unsafe {
+        assert_eq!(libc::umount2(c_bound.as_ptr(), 0), 0);
+        assert_eq!(libc::umount2(c_outer.as_ptr(), 0), 0);
+    }
+    fs::remove_dir(bound).unwrap();
+    fs::remove_dir(outer).unwrap();
+}
+
+register_test!(test_mountinfo);
@@ -237,6 +237,55 @@ fn test_futex2_waitv() {
 
 register_test!(test_futex2_waitv);
 
+fn test_futex2_waitv_max() {
+    // The most futexes one call can wait on, half of them shared, with only
+    // the last one woken.
+    let words: Arc<Vec<AtomicU32>> = Arc::new((0..128).map(|_| AtomicU32::new(0)).collect());
+    let words_clone = words.clone();
+
+    let flags = |idx: usize| {
+        if idx % 2 == 0 {
+            FUTEX2_SIZE_U32 | FUTEX2_PRIVATE
+        } else {
+            FUTEX2_SIZE_U32
+        }
+    };
+
+    let t = thread::spawn(move || {
+        let waiters: Vec<FutexWaitv> = words_clone
+            .iter()
+            .enumerate()
+            .map(|(idx, w)| FutexWaitv::new(w.as_ptr() as *const u32, 0, flags(idx)))
+            .collect();
+
+        unsafe {
+            futex2_waitv(
+                waiters.as_ptr(),
+                waiters.len() as u32,
+                0,
+                std::ptr::null(),
+                libc::CLOCK_MONOTONIC,
+            )
+        }
+    });
+
+    thread::sleep(Duration::from_millis(100));
+
+    unsafe {
+        let ret = futex2_wake(words[127].as_ptr() as *const u32, MATCH_ANY, 1, flags(127));
+        if ret != 1 {
+            panic!("expected to wake 1 waitv waiter, woke {ret}");
+        }
+    }
+
+    let ret = t.join().expect("waitv thread panicked");
+    if ret != 127 {
+        panic!("futex_waitv returned {ret}, expected woken index 127");
+    }
+}
+
+register_test!(test_futex2_waitv_max);
+
 fn test_futex2_invalid_args() {
     let word: u32 = 0;
     let addr = &word as *const u32;
@@ -0,0 +1,40 @@
+//! Resource snapshots the runner takes around each test to catch leaks.
+
+/// How far free memory may drop across a test before it counts as a leak.
+/// Kernel caches and heap growth aren't given back straight away, so a little
+/// has to be let go.
+pub const MEM_SLACK_KB: u64 = 1024;
+
+/// Exit status of a test child that left file descriptors open.
+pub const FD_LEAK_EXIT: i32 = 2;
+
+/// Writes back and drops the page cache, so that what's left of free memory
+/// isn't hidden behind whatever files the test touched.
+fn settle() {
+    unsafe {
+        libc::sync();
+    }
+
+    // Only root may drop caches; without that the slack has to do.
+    let _ = std::fs::write("/proc/sys/vm/drop_caches", "3");
+}
+
+/// Returns `MemFree` from `/proc/meminfo`, in kB.
+pub fn free_kb() -> u64 {
+    settle();
+
+    let meminfo = std::fs::read_to_string("/proc/meminfo").expect("Failed to read /proc/meminfo");
+
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemFree:"))
+        .and_then(|rest| rest.trim().trim_end_matches("kB").trim().parse().ok())
+        .expect("No MemFree in /proc/meminfo")
+}
+
+/// Returns how many file descriptors this process has open.
+pub fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd")
+        .expect("Failed to read /proc/self/fd")
+        .count()
+}
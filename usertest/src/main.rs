@@ -5,18 +5,27 @@ use std::{
     thread,
 };
 
+mod abi;
 mod epoll;
+mod fanotify;
 mod fs;
 mod futex;
 mod futex2;
 mod inotify;
+mod leak;
+mod net;
 mod signalfd;
 mod signals;
+mod soak;
 mod socket;
+mod torture;
+mod userfaultfd;
 
 pub struct Test {
     pub test_text: &'static str,
     pub test_fn: fn(),
+    /// Long-running stress tests only run when asked for with `--soak`.
+    pub soak: bool,
 }
 
 inventory::collect!(Test);
@@ -29,6 +38,7 @@ macro_rules! register_test {
             $crate::Test {
                 test_text: concat!(module_path!(), "::", stringify!($name)),
                 test_fn: $name,
+                soak: false,
             }
         }
     };
@@ -38,10 +48,25 @@ macro_rules! register_test {
             $crate::Test {
                 test_text: $text,
                 test_fn: $name,
+                soak: false,
             }
         }
     };
 }
+
+#[macro_export]
+macro_rules! register_soak_test {
+    ($name:ident) => {
+        inventory::submit! {
+            $crate::Test {
+                test_text: concat!(module_path!(), "::", stringify!($name)),
+                test_fn: $name,
+                soak: true,
+            }
+        }
+    };
+}
+
 fn test_sync() {
     unsafe {
         libc::sync();
@@ -63,6 +88,36 @@ fn test_clock_sleep() {
 
 register_test!(test_clock_sleep);
 
+fn test_clocksource_switch() {
+    use std::time::Instant;
+
+    const DIR: &str = "/sys/devices/system/clocksource/clocksource0";
+
+    let read = |name: &str| std::fs::read_to_string(format!("{DIR}/{name}")).unwrap();
+    let select = |name: &str| std::fs::write(format!("{DIR}/current_clocksource"), name);
+
+    let current = read("current_clocksource");
+    let current = current.trim();
+    let available = read("available_clocksource");
+    assert!(available.split_whitespace().any(|s| s == current));
+
+    assert!(select("no-such-clock").is_err());
+
+    // Time keeps going forwards across every switch.
+    for source in available.split_whitespace() {
+        let before = Instant::now();
+        select(source).unwrap();
+        assert_eq!(read("current_clocksource").trim(), source);
+        assert!(Instant::now() >= before);
+    }
+
+    // Going back to picking by rating.
+    select("\n").unwrap();
+    assert_eq!(read("current_clocksource").trim(), current);
+}
+
+register_test!(test_clocksource_switch);
+
 fn test_fork() {
     unsafe {
         let pid = libc::fork();
@@ -129,6 +184,97 @@ fn test_vfork_exec() {
 
 register_test!(test_vfork_exec);
 
+fn test_waitid_pidfd() {
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            // Burn some CPU so there's usage to report.
+            let mut x = 0u64;
+            for i in 0..10_000_000 {
+                x = std::hint::black_box(x.wrapping_add(i));
+            }
+            libc::_exit(7);
+        }
+
+        let pidfd = libc::syscall(libc::SYS_pidfd_open, pid, 0) as i32;
+        assert!(pidfd >= 0);
+
+        // WNOWAIT leaves the child waitable, even after blocking for it.
+        for _ in 0..2 {
+            let mut info: libc::siginfo_t = std::mem::zeroed();
+            let ret = libc::waitid(
+                libc::P_PIDFD,
+                pidfd as _,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            );
+            assert_eq!(ret, 0);
+            assert_eq!(info.si_signo, libc::SIGCHLD);
+            assert_eq!(info.si_code, libc::CLD_EXITED);
+            assert_eq!(info.si_pid(), pid);
+            assert_eq!(info.si_uid(), libc::getuid());
+            assert_eq!(info.si_status(), 7);
+        }
+
+        let mut status = 0;
+        let mut usage: libc::rusage = std::mem::zeroed();
+        assert_eq!(libc::wait4(pid, &mut status, 0, &mut usage), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 7);
+        assert!(usage.ru_utime.tv_usec < 1_000_000);
+        assert!(usage.ru_stime.tv_usec < 1_000_000);
+
+        // The child has been reaped.
+        assert_eq!(libc::waitpid(pid, &mut status, libc::WNOHANG), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ECHILD)
+        );
+
+        libc::close(pidfd);
+
+        // A process can't wait for its own threads.
+        assert_eq!(libc::waitpid(libc::getpid(), &mut status, 0), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ECHILD)
+        );
+    }
+}
+
+register_test!(test_waitid_pidfd);
+
+fn test_pid_allocation() {
+    unsafe {
+        let mut pids = Vec::new();
+
+        // A reaped child's pid isn't handed straight out again.
+        for _ in 0..3 {
+            let pid = libc::fork();
+            assert!(pid >= 0, "fork failed");
+
+            if pid == 0 {
+                libc::_exit(0);
+            }
+
+            let mut status = 0;
+            assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+            assert!(!pids.contains(&pid));
+            pids.push(pid);
+        }
+
+        assert_eq!(libc::syscall(libc::SYS_pidfd_open, pids[0], 0), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ESRCH)
+        );
+    }
+}
+
+register_test!(test_pid_allocation);
+
 fn test_rust_thread() {
     let handle = thread::spawn(|| 24);
 
@@ -202,6 +348,73 @@ fn test_thread_with_name() {
 
 register_test!(test_thread_with_name);
 
+fn test_fork_multithreaded() {
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::time::Duration;
+
+    let word = Arc::new(AtomicU32::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    // One thread blocks on a futex, and another keeps writing memory, while
+    // the main thread forks.
+    let waiter = {
+        let word = word.clone();
+        thread::spawn(move || unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                word.as_ptr(),
+                libc::FUTEX_WAIT,
+                0,
+                std::ptr::null::<libc::c_void>(),
+            )
+        })
+    };
+    let writer = {
+        let stop = stop.clone();
+        let counter = Arc::new(AtomicU32::new(0));
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+    };
+
+    thread::sleep(Duration::from_millis(10));
+
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            // Only the forking thread is copied.
+            let threads = std::fs::read_dir("/proc/self/task").map_or(1, |dir| dir.count());
+            // The child's copy of the futex word is separate memory, so waking
+            // it mustn't wake the parent's waiter.
+            let woken = libc::syscall(libc::SYS_futex, word.as_ptr(), libc::FUTEX_WAKE, 1);
+
+            libc::_exit(if threads == 1 && woken == 0 { 0 } else { 1 });
+        }
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    assert!(!waiter.is_finished());
+
+    word.store(1, Ordering::Relaxed);
+    unsafe {
+        libc::syscall(libc::SYS_futex, word.as_ptr(), libc::FUTEX_WAKE, 1);
+    }
+    assert_eq!(waiter.join().unwrap(), 0);
+
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+}
+
+register_test!(test_fork_multithreaded);
+
 fn test_mincore() {
     use std::ptr;
 
@@ -246,124 +459,1913 @@ fn test_mincore() {
 
 register_test!(test_mincore);
 
-fn test_itimer() {
-    use libc::{ITIMER_REAL, itimerval};
-    use std::mem::MaybeUninit;
+fn test_madvise_dontneed() {
+    use std::ptr;
 
     unsafe {
-        // Set signal handler for SIGALRM to avoid process termination when the timer expires
-        // We'll flip a bit in a static variable when the signal handler is called, and check that bit at the end of the test to verify the timer actually expired.
-        static TIMER_EXPIRED: std::sync::atomic::AtomicBool =
-            std::sync::atomic::AtomicBool::new(false);
-        extern "C" fn sigalrm_handler(_signum: libc::c_int) {
-            TIMER_EXPIRED.store(true, std::sync::atomic::Ordering::SeqCst);
-        }
-        let mut sa: libc::sigaction = std::mem::zeroed();
-        sa.sa_sigaction = sigalrm_handler as *const () as usize;
-        sa.sa_flags = libc::SA_RESTART;
-        libc::sigemptyset(&mut sa.sa_mask);
-        if libc::sigaction(libc::SIGALRM, &sa, std::ptr::null_mut()) != 0 {
-            panic!("sigaction failed: {}", std::io::Error::last_os_error());
-        }
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+        let len = page_size * 2;
 
-        let mut old_timer = MaybeUninit::<itimerval>::uninit();
-        let new_timer = itimerval {
-            it_interval: libc::timeval {
-                tv_sec: 0,
-                tv_usec: 0,
-            },
-            it_value: libc::timeval {
-                tv_sec: 1,
-                tv_usec: 0,
-            },
-        };
-        let ret = libc::setitimer(
-            ITIMER_REAL,
-            &new_timer as *const itimerval,
-            old_timer.as_mut_ptr(),
+        let addr = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
         );
-        if ret != 0 {
-            panic!("setitimer failed: {}", std::io::Error::last_os_error());
+        if addr == libc::MAP_FAILED {
+            panic!("mmap failed: {}", std::io::Error::last_os_error());
         }
-        let old_timer = old_timer.assume_init();
-        assert_eq!(old_timer.it_value.tv_sec, 0);
-        assert_eq!(old_timer.it_value.tv_usec, 0);
-        // Wait for 2 seconds to ensure the timer has time to expire
-        std::thread::sleep(std::time::Duration::from_secs(2));
-        assert!(
-            TIMER_EXPIRED.load(std::sync::atomic::Ordering::SeqCst),
-            "Expected timer to have expired and signal handler to have been called"
+
+        ptr::write_bytes(addr as *mut u8, 0xaa, len);
+
+        let ret = libc::madvise(addr, len, libc::MADV_DONTNEED);
+        assert_eq!(
+            ret,
+            0,
+            "madvise failed: {}",
+            std::io::Error::last_os_error()
+        );
+
+        // The pages should have been dropped...
+        let mut vec = [0u8; 2];
+        assert_eq!(libc::mincore(addr, len, vec.as_mut_ptr()), 0);
+        assert_eq!(vec, [0, 0]);
+
+        // ...and be zero-filled on the next touch.
+        let slice = std::slice::from_raw_parts(addr as *const u8, len);
+        assert!(slice.iter().all(|&b| b == 0));
+
+        let ret = libc::madvise(addr, len, libc::MADV_WILLNEED);
+        assert_eq!(ret, 0);
+
+        // Unknown advice is rejected.
+        assert_eq!(libc::madvise(addr, len, 0x1234), -1);
+
+        let rc = libc::munmap(addr, len);
+        assert_eq!(rc, 0, "munmap failed: {}", std::io::Error::last_os_error());
+
+        // Advice on an unmapped range fails with ENOMEM.
+        assert_eq!(libc::madvise(addr, len, libc::MADV_DONTNEED), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ENOMEM)
         );
     }
 }
 
-register_test!(test_itimer);
+register_test!(test_madvise_dontneed);
+
+fn test_posix_shm() {
+    use std::ffi::CString;
+    use std::ptr;
+
+    unsafe fn map_shared(fd: libc::c_int, len: usize) -> *mut u8 {
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | if fd < 0 { libc::MAP_ANONYMOUS } else { 0 },
+                fd,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            panic!("mmap failed: {}", std::io::Error::last_os_error());
+        }
+        addr.cast()
+    }
+
+    fn wait_child(pid: libc::pid_t) {
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+    }
 
-fn run_test(test_fn: fn()) -> Result<(), i32> {
-    // Fork a new process to run the test
     unsafe {
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+        let len = page_size * 2;
+        let name = CString::new("/usertest-shm").unwrap();
+
+        let fd = libc::shm_open(
+            name.as_ptr(),
+            libc::O_RDWR | libc::O_CREAT | libc::O_EXCL,
+            0o600,
+        );
+        assert!(
+            fd >= 0,
+            "shm_open failed: {}",
+            std::io::Error::last_os_error()
+        );
+        assert_eq!(libc::ftruncate(fd, len as _), 0);
+
+        let parent = map_shared(fd, len);
+        *parent = 1;
+
+        // The child maps the object by name, independently of the parent's
+        // mapping.
         let pid = libc::fork();
-        if pid < 0 {
-            panic!("fork failed");
-        } else if pid == 0 {
-            // Child process
-            let result = std::panic::catch_unwind(|| {
-                test_fn();
-            });
-            let exit_code = if let Err(e) = result {
-                // Get the panic info
-                eprintln!("Test panicked: {:?}", e);
-                let error = std::io::Error::last_os_error();
-                eprintln!("Last OS error: {}", error);
-                1
-            } else {
-                0
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            let fd = libc::shm_open(name.as_ptr(), libc::O_RDWR, 0);
+            let ok = fd >= 0 && {
+                let child = map_shared(fd, len);
+                let seen = *child;
+                *child.add(page_size) = 0xcd;
+                seen == 1
             };
-            libc::_exit(exit_code);
-        } else {
-            // Parent process
-            let mut status = 0;
-            libc::waitpid(pid, &mut status, 0);
-            if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
-                Err(status)
-            } else {
-                Ok(())
-            }
+            libc::_exit(if ok { 0 } else { 1 });
+        }
+        wait_child(pid);
+        assert_eq!(*parent.add(page_size), 0xcd);
+
+        // The object's contents are those of the mapping.
+        let mut byte = 0u8;
+        assert_eq!(
+            libc::pread(fd, (&raw mut byte).cast(), 1, page_size as _),
+            1
+        );
+        assert_eq!(byte, 0xcd);
+
+        assert_eq!(libc::munmap(parent.cast(), len), 0);
+        libc::close(fd);
+
+        assert_eq!(libc::shm_unlink(name.as_ptr()), 0);
+        assert_eq!(libc::shm_open(name.as_ptr(), libc::O_RDWR, 0), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+
+        // Shared anonymous memory stays shared across fork().
+        let anon = map_shared(-1, page_size);
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            *anon = 42;
+            libc::_exit(0);
         }
+        wait_child(pid);
+        assert_eq!(*anon, 42);
+        assert_eq!(libc::munmap(anon.cast(), page_size), 0);
     }
 }
 
-fn main() {
-    println!("Running userspace tests ...");
-    // Get all args
-    let args: Vec<String> = std::env::args().collect();
-    let filter = args.get(1).map(|s| s.as_str());
-    let start = std::time::Instant::now();
-    let mut failures = 0;
-    for test in inventory::iter::<Test> {
-        if let Some(filter) = filter
-            && !test.test_text.contains(filter)
-        {
-            continue;
+register_test!(test_posix_shm);
+
+fn test_mmap_hugetlb() {
+    use std::ptr;
+
+    const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+    unsafe {
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+        let len = HUGE_PAGE_SIZE * 2;
+
+        let addr = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_HUGETLB,
+            -1,
+            0,
+        );
+        if addr == libc::MAP_FAILED {
+            panic!("mmap failed: {}", std::io::Error::last_os_error());
         }
-        print!("{} ...", test.test_text);
-        let _ = stdout().flush();
-        match run_test(test.test_fn) {
-            Ok(()) => println!("{}", " OK".green()),
-            Err(code) => {
-                println!(" {}", "FAILED".red());
-                eprintln!("Test '{}' failed with exit code {}", test.test_text, code);
-                failures += 1;
-            }
+        assert_eq!(addr as usize % HUGE_PAGE_SIZE, 0);
+
+        let mem = std::slice::from_raw_parts_mut(addr as *mut u8, len);
+        assert!(mem.iter().all(|&b| b == 0));
+
+        for (i, b) in mem.iter_mut().enumerate() {
+            *b = (i / page_size) as u8;
         }
-    }
-    let end = std::time::Instant::now();
-    if failures > 0 {
-        eprintln!(
-            "{failures} tests failed in {} ms",
-            (end - start).as_millis()
+
+        // The child gets a private copy.
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            let ok = mem
+                .iter()
+                .enumerate()
+                .all(|(i, &b)| b == (i / page_size) as u8);
+            mem.fill(0xff);
+            libc::_exit(if ok { 0 } else { 1 });
+        }
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+
+        assert!(
+            mem.iter()
+                .enumerate()
+                .all(|(i, &b)| b == (i / page_size) as u8)
+        );
+
+        // Page-granular operations still work within a huge page.
+        let hole = (addr as *mut u8).add(page_size * 3);
+        assert_eq!(libc::munmap(hole.cast(), page_size), 0);
+        assert_eq!(
+            libc::mprotect(hole.add(page_size).cast(), page_size, libc::PROT_READ),
+            0
+        );
+        assert_eq!(*hole.sub(1), 2);
+        assert_eq!(*hole.add(page_size), 4);
+        *hole.add(page_size * 2) = 0xaa;
+        assert_eq!(*hole.add(page_size * 2), 0xaa);
+
+        assert_eq!(libc::munmap(addr, len), 0);
+
+        // Only private anonymous memory can use huge pages.
+        let shared = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_ANONYMOUS | libc::MAP_HUGETLB,
+            -1,
+            0,
+        );
+        assert_eq!(shared, libc::MAP_FAILED);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
         );
-        std::process::exit(1);
     }
-    println!("All tests passed in {} ms", (end - start).as_millis());
+}
+
+register_test!(test_mmap_hugetlb);
+
+fn test_overcommit() {
+    use std::ptr;
+
+    const SYSCTL: &str = "/proc/sys/vm/overcommit_memory";
+    // Far more than the machine has, but well within the address space.
+    const LEN: usize = 1 << 40;
+
+    unsafe fn map(prot: libc::c_int, flags: libc::c_int) -> *mut libc::c_void {
+        unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                LEN,
+                prot,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags,
+                -1,
+                0,
+            )
+        }
+    }
+
+    fn last_errno() -> Option<i32> {
+        std::io::Error::last_os_error().raw_os_error()
+    }
+
+    let meminfo = std::fs::read_to_string("/proc/meminfo").unwrap();
+    assert!(meminfo.contains("Committed_AS:"));
+    assert!(meminfo.contains("CommitLimit:"));
+
+    let orig = std::fs::read_to_string(SYSCTL).unwrap();
+    assert_eq!(orig.trim(), "0");
+    assert!(std::fs::write(SYSCTL, "3").is_err());
+
+    unsafe {
+        // Heuristic: only requests that could never be satisfied are refused.
+        assert_eq!(map(libc::PROT_READ | libc::PROT_WRITE, 0), libc::MAP_FAILED);
+        assert_eq!(last_errno(), Some(libc::ENOMEM));
+
+        let addr = map(libc::PROT_READ | libc::PROT_WRITE, libc::MAP_NORESERVE);
+        assert_ne!(addr, libc::MAP_FAILED);
+        *(addr as *mut u8) = 1;
+        assert_eq!(libc::munmap(addr, LEN), 0);
+
+        // Never: every private writable mapping is accounted, even with
+        // MAP_NORESERVE, but read-only ones are free.
+        std::fs::write(SYSCTL, "2").unwrap();
+
+        let noreserve = map(libc::PROT_READ | libc::PROT_WRITE, libc::MAP_NORESERVE);
+        let noreserve_errno = last_errno();
+
+        let ro = map(libc::PROT_READ, 0);
+        let mprotect = if ro == libc::MAP_FAILED {
+            0
+        } else {
+            libc::mprotect(ro, LEN, libc::PROT_READ | libc::PROT_WRITE)
+        };
+        let mprotect_errno = last_errno();
+
+        std::fs::write(SYSCTL, orig).unwrap();
+
+        assert_eq!(noreserve, libc::MAP_FAILED);
+        assert_eq!(noreserve_errno, Some(libc::ENOMEM));
+        assert_ne!(ro, libc::MAP_FAILED);
+        assert_eq!(mprotect, -1);
+        assert_eq!(mprotect_errno, Some(libc::ENOMEM));
+        assert_eq!(libc::munmap(ro, LEN), 0);
+    }
+}
+
+register_test!(test_overcommit);
+
+fn test_aslr() {
+    const SYSCTL: &str = "/proc/sys/kernel/randomize_va_space";
+
+    // Returns the start of the `[stack]` mapping of a freshly exec'd process.
+    fn stack_start() -> String {
+        let out = std::process::Command::new("/bin/cat")
+            .arg("/proc/self/maps")
+            .output()
+            .expect("failed to run cat");
+        assert!(out.status.success());
+
+        let maps = String::from_utf8(out.stdout).unwrap();
+        let line = maps
+            .lines()
+            .find(|l| l.ends_with("[stack]"))
+            .expect("no [stack] mapping");
+        line.split('-').next().unwrap().to_string()
+    }
+
+    let orig = std::fs::read_to_string(SYSCTL).unwrap();
+    assert_eq!(orig.trim(), "2");
+
+    assert!(std::fs::write(SYSCTL, "3").is_err());
+
+    // With the full 18 bits of entropy, two runs colliding would be a fluke.
+    assert_ne!(stack_start(), stack_start());
+
+    std::fs::write(SYSCTL, "0").unwrap();
+    let fixed = (stack_start(), stack_start());
+    std::fs::write(SYSCTL, orig).unwrap();
+
+    assert_eq!(fixed.0, fixed.1);
+}
+
+register_test!(test_aslr);
+
+fn test_global_limits() {
+    const PID_MAX: &str = "/proc/sys/kernel/pid_max";
+    const THREADS_MAX: &str = "/proc/sys/kernel/threads-max";
+    const FILE_MAX: &str = "/proc/sys/fs/file-max";
+
+    fn read_sysctl(path: &str) -> usize {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .split_whitespace()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    fn last_errno() -> Option<i32> {
+        std::io::Error::last_os_error().raw_os_error()
+    }
+
+    let pid_max = read_sysctl(PID_MAX);
+    let threads_max = read_sysctl(THREADS_MAX);
+    let file_max = read_sysctl(FILE_MAX);
+
+    assert_eq!(pid_max, 32768);
+    assert!(std::fs::write(PID_MAX, "300").is_err());
+    assert!(std::fs::write(THREADS_MAX, "19").is_err());
+    assert!(std::fs::write("/proc/sys/fs/file-nr", "0").is_err());
+
+    // Tids wrap around below a lowered `pid_max`.
+    std::fs::write(PID_MAX, "400").unwrap();
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        unsafe { libc::_exit(0) };
+    }
+    std::fs::write(PID_MAX, pid_max.to_string()).unwrap();
+    assert!(pid > 0 && pid < 400, "pid {pid} not below pid_max");
+    unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) };
+
+    // Only a few more files can be opened than are open already.
+    let open = read_sysctl("/proc/sys/fs/file-nr");
+    std::fs::write(FILE_MAX, (open + 4).to_string()).unwrap();
+    let mut files = Vec::new();
+    let errno = loop {
+        match std::fs::File::open("/proc/self/stat") {
+            Ok(file) if files.len() < 8 => files.push(file),
+            Ok(_) => break None,
+            Err(e) => break e.raw_os_error(),
+        }
+    };
+    let opened = files.len();
+    drop(files);
+    std::fs::write(FILE_MAX, file_max.to_string()).unwrap();
+    assert_eq!(errno, Some(libc::ENFILE));
+    assert!(opened <= 4);
+
+    // And only a few more tasks can be created than exist already.
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    std::fs::write(THREADS_MAX, "20").unwrap();
+    let mut children = Vec::new();
+    let errno = loop {
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            // Wait for the parent to close its end of the pipe.
+            let mut byte = 0u8;
+            unsafe {
+                libc::close(pipe[1]);
+                libc::read(pipe[0], (&raw mut byte).cast(), 1);
+                libc::_exit(0);
+            }
+        }
+        if pid < 0 {
+            break last_errno();
+        }
+        children.push(pid);
+        if children.len() > 20 {
+            break None;
+        }
+    };
+    std::fs::write(THREADS_MAX, threads_max.to_string()).unwrap();
+    unsafe { libc::close(pipe[1]) };
+    for pid in &children {
+        unsafe { libc::waitpid(*pid, std::ptr::null_mut(), 0) };
+    }
+    unsafe { libc::close(pipe[0]) };
+    assert_eq!(errno, Some(libc::EAGAIN));
+    assert!(children.len() < 20);
+}
+
+register_test!(test_global_limits);
+
+fn test_time_namespace() {
+    const CLONE_NEWTIME: libc::c_int = 0x80;
+    const OFFSETS: &str = "/proc/self/timens_offsets";
+
+    fn clock_secs(clock: libc::clockid_t) -> i64 {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        assert_eq!(unsafe { libc::clock_gettime(clock, &mut ts) }, 0);
+        ts.tv_sec
+    }
+
+    assert!(clock_secs(libc::CLOCK_BOOTTIME) >= clock_secs(libc::CLOCK_MONOTONIC));
+
+    let offsets = std::fs::read_to_string(OFFSETS).unwrap();
+    assert_eq!(offsets, "monotonic 0 0\nboottime 0 0\n");
+
+    // The init namespace has been entered, so its offsets are fixed.
+    assert!(std::fs::write(OFFSETS, "monotonic 100 0").is_err());
+
+    // Fork first so the test runner stays in the init namespace.
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            let code = (|| {
+                if libc::unshare(CLONE_NEWTIME) != 0 {
+                    return 1;
+                }
+
+                // The caller stays where it is.
+                let before = clock_secs(libc::CLOCK_MONOTONIC);
+                if std::fs::write(OFFSETS, "monotonic 1000 0\n7 2000 500000000\n").is_err() {
+                    return 2;
+                }
+                if clock_secs(libc::CLOCK_MONOTONIC) - before > 1 {
+                    return 3;
+                }
+
+                let offsets = std::fs::read_to_string(OFFSETS).unwrap();
+                if offsets != "monotonic 1000 0\nboottime 2000 500000000\n" {
+                    return 4;
+                }
+
+                // Its children are born into the new namespace.
+                let child = libc::fork();
+                if child == 0 {
+                    let shifted = clock_secs(libc::CLOCK_MONOTONIC) - before;
+                    libc::_exit(if (1000..1002).contains(&shifted) {
+                        0
+                    } else {
+                        1
+                    });
+                }
+                let mut status = 0;
+                libc::waitpid(child, &mut status, 0);
+                if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+                    return 5;
+                }
+
+                // Once entered, the offsets can't change.
+                if std::fs::write(OFFSETS, "monotonic 0 0").is_ok() {
+                    return 6;
+                }
+
+                0
+            })();
+            libc::_exit(code);
+        }
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+}
+
+register_test!(test_time_namespace);
+
+fn test_stack_growth() {
+    // Each frame holds 1KiB, so deep recursion runs well past the initial
+    // stack mapping.
+    fn recurse(depth: usize) -> usize {
+        let buf = std::hint::black_box([depth as u8; 1024]);
+
+        if depth == 0 {
+            0
+        } else {
+            recurse(depth - 1) + buf[1] as usize
+        }
+    }
+
+    unsafe {
+        // Past RLIMIT_STACK, the guard gap turns the overflow into SIGSEGV.
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            libc::signal(libc::SIGSEGV, libc::SIG_DFL);
+            let lim = libc::rlimit {
+                rlim_cur: 1024 * 1024,
+                rlim_max: libc::RLIM_INFINITY,
+            };
+            libc::setrlimit(libc::RLIMIT_STACK, &lim);
+            recurse(usize::MAX);
+            libc::_exit(0);
+        }
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFSIGNALED(status));
+        assert_eq!(libc::WTERMSIG(status), libc::SIGSEGV);
+    }
+
+    let depth = 4096;
+    let expected: usize = (1..=depth).map(|d| d as u8 as usize).sum();
+    assert_eq!(recurse(depth), expected);
+}
+
+register_test!(test_stack_growth);
+
+fn test_exec_auxv() {
+    use std::ffi::CStr;
+
+    unsafe {
+        let random = libc::getauxval(libc::AT_RANDOM) as usize;
+        let execfn = libc::getauxval(libc::AT_EXECFN) as *const libc::c_char;
+        assert_ne!(random, 0);
+        assert!(!execfn.is_null());
+
+        // The random bytes sit below the strings, rather than overlapping
+        // them.
+        assert!(random + 16 <= execfn as usize);
+        assert!(!CStr::from_ptr(execfn).to_bytes().is_empty());
+
+        assert_eq!(libc::getauxval(libc::AT_UID), libc::getuid() as u64);
+        assert_eq!(libc::getauxval(libc::AT_EUID), libc::geteuid() as u64);
+        assert_eq!(libc::getauxval(libc::AT_GID), libc::getgid() as u64);
+        assert_eq!(libc::getauxval(libc::AT_EGID), libc::getegid() as u64);
+        assert_eq!(libc::getauxval(libc::AT_SECURE), 0);
+        assert_eq!(libc::getauxval(libc::AT_CLKTCK), 100);
+
+        // Every arm64 CPU we run on has FP and Advanced SIMD.
+        let hwcap = libc::getauxval(libc::AT_HWCAP);
+        assert_eq!(hwcap & 0b11, 0b11);
+
+        // AT_PHDR points at our own program headers.
+        let phdr = libc::getauxval(libc::AT_PHDR) as *const libc::Elf64_Phdr;
+        let phnum = libc::getauxval(libc::AT_PHNUM) as usize;
+        assert!(!phdr.is_null());
+        assert!(
+            std::slice::from_raw_parts(phdr, phnum)
+                .iter()
+                .any(|h| h.p_type == libc::PT_LOAD)
+        );
+    }
+
+    // Without a PT_GNU_STACK header asking for it, the stack isn't
+    // executable.
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+    let stack = maps
+        .lines()
+        .find(|l| l.ends_with("[stack]"))
+        .expect("no [stack] mapping");
+    assert_eq!(stack.split_whitespace().nth(1), Some("rw-p"));
+}
+
+register_test!(test_exec_auxv);
+
+fn test_exec_setuid() {
+    use std::{ffi::CString, fs, os::unix::fs::PermissionsExt};
+
+    const PATH: &str = "/tmp/setuid_usertest";
+
+    fs::copy(std::env::current_exe().unwrap(), PATH).unwrap();
+
+    let c_path = CString::new(PATH).unwrap();
+    unsafe {
+        assert_eq!(libc::chown(c_path.as_ptr(), 1, 2), 0);
+    }
+    fs::set_permissions(PATH, fs::Permissions::from_mode(0o6755)).unwrap();
+
+    // The copy reports the IDs it runs with, and AT_SECURE.
+    let out = std::process::Command::new(PATH)
+        .env("USERTEST_PRINT_IDS", "1")
+        .output()
+        .expect("failed to run setuid copy");
+    fs::remove_file(PATH).unwrap();
+    assert!(out.status.success());
+
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap().trim(),
+        format!("{uid} 1 {gid} 2 1")
+    );
+}
+
+register_test!(test_exec_setuid);
+
+fn test_nice() {
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            // Have a process group to ourselves.
+            assert_eq!(libc::setpgid(0, 0), 0);
+            assert_eq!(libc::getpriority(libc::PRIO_PROCESS, 0), 0);
+
+            assert_eq!(libc::setpriority(libc::PRIO_PROCESS, 0, 10), 0);
+            assert_eq!(libc::getpriority(libc::PRIO_PROCESS, 0), 10);
+            assert_eq!(libc::getpriority(libc::PRIO_PGRP, 0), 10);
+
+            // /proc reports the priority as 20 + nice, then the nice value.
+            let stat = std::fs::read_to_string("/proc/self/stat").unwrap();
+            let fields: Vec<&str> = stat
+                .rsplit_once(')')
+                .unwrap()
+                .1
+                .split_whitespace()
+                .collect();
+            assert_eq!(fields[15], "30");
+            assert_eq!(fields[16], "10");
+
+            // Out-of-range values are clamped.
+            assert_eq!(libc::setpriority(libc::PRIO_PROCESS, 0, 100), 0);
+            assert_eq!(libc::getpriority(libc::PRIO_PROCESS, 0), 19);
+            assert_eq!(libc::setpriority(libc::PRIO_PROCESS, 0, -100), 0);
+            assert_eq!(libc::getpriority(libc::PRIO_PROCESS, 0), -20);
+
+            // The most favourable value among all of the user's processes.
+            assert_eq!(libc::getpriority(libc::PRIO_USER, 0), -20);
+
+            assert_eq!(libc::setpriority(3, 0, 0), -1);
+            assert_eq!(*libc::__errno_location(), libc::EINVAL);
+            assert_eq!(libc::setpriority(libc::PRIO_PROCESS, 999_999, 0), -1);
+            assert_eq!(*libc::__errno_location(), libc::ESRCH);
+
+            libc::_exit(0);
+        }
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+}
+
+register_test!(test_nice);
+
+fn test_ioprio() {
+    const IOPRIO_WHO_PROCESS: i32 = 1;
+    // IOPRIO_PRIO_VALUE(class, level)
+    const BE_4: i32 = (2 << 13) | 4;
+    const IDLE: i32 = 3 << 13;
+
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            assert_eq!(
+                libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, 0),
+                0
+            );
+
+            assert_eq!(
+                libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, BE_4),
+                0
+            );
+            assert_eq!(
+                libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, 0),
+                BE_4 as libc::c_long
+            );
+
+            // Best effort only has eight levels.
+            assert_eq!(
+                libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, (2 << 13) | 8),
+                -1
+            );
+            assert_eq!(*libc::__errno_location(), libc::EINVAL);
+            assert_eq!(libc::syscall(libc::SYS_ioprio_get, 4, 0), -1);
+            assert_eq!(*libc::__errno_location(), libc::EINVAL);
+
+            // A child starts with its parent's priority.
+            assert_eq!(
+                libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, IDLE),
+                0
+            );
+
+            let child = libc::fork();
+            assert!(child >= 0, "fork failed");
+
+            if child == 0 {
+                let ioprio = libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, 0);
+                libc::_exit(if ioprio == IDLE as libc::c_long { 0 } else { 1 });
+            }
+
+            let mut status = 0;
+            assert_eq!(libc::waitpid(child, &mut status, 0), child);
+            assert!(libc::WIFEXITED(status));
+            assert_eq!(libc::WEXITSTATUS(status), 0);
+
+            libc::_exit(0);
+        }
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+}
+
+register_test!(test_ioprio);
+
+#[repr(C)]
+#[derive(Default)]
+struct SchedAttr {
+    size: u32,
+    sched_policy: u32,
+    sched_flags: u64,
+    sched_nice: i32,
+    sched_priority: u32,
+    sched_runtime: u64,
+    sched_deadline: u64,
+    sched_period: u64,
+}
+
+fn sched_setattr(attr: &SchedAttr) -> i64 {
+    unsafe { libc::syscall(libc::SYS_sched_setattr, 0, attr as *const SchedAttr, 0) }
+}
+
+fn test_sched_deadline() {
+    const SCHED_DEADLINE: u32 = 6;
+    const MS: u64 = 1_000_000;
+
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            let dl = |runtime, deadline, period| SchedAttr {
+                size: size_of::<SchedAttr>() as u32,
+                sched_policy: SCHED_DEADLINE,
+                sched_runtime: runtime,
+                sched_deadline: deadline,
+                sched_period: period,
+                ..Default::default()
+            };
+
+            // More runtime than deadline, and more bandwidth than a CPU has.
+            assert_eq!(sched_setattr(&dl(20 * MS, 10 * MS, 100 * MS)), -1);
+            assert_eq!(*libc::__errno_location(), libc::EINVAL);
+            assert_eq!(sched_setattr(&dl(100 * MS, 100 * MS, 100 * MS)), -1);
+            assert_eq!(*libc::__errno_location(), libc::EBUSY);
+
+            assert_eq!(sched_setattr(&dl(10 * MS, 50 * MS, 100 * MS)), 0);
+
+            let mut attr = SchedAttr::default();
+            assert_eq!(
+                libc::syscall(
+                    libc::SYS_sched_getattr,
+                    0,
+                    &mut attr as *mut SchedAttr,
+                    size_of::<SchedAttr>(),
+                    0
+                ),
+                0
+            );
+            assert_eq!(attr.size as usize, size_of::<SchedAttr>());
+            assert_eq!(attr.sched_policy, SCHED_DEADLINE);
+            assert_eq!(attr.sched_runtime, 10 * MS);
+            assert_eq!(attr.sched_deadline, 50 * MS);
+            assert_eq!(attr.sched_period, 100 * MS);
+
+            let stat = std::fs::read_to_string("/proc/self/stat").unwrap();
+            let fields: Vec<&str> = stat
+                .rsplit_once(')')
+                .unwrap()
+                .1
+                .split_whitespace()
+                .collect();
+            assert_eq!(fields[38], "6");
+
+            // Spin across a few periods, so we're throttled and replenished.
+            let start = std::time::Instant::now();
+            while start.elapsed() < std::time::Duration::from_millis(300) {
+                std::hint::spin_loop();
+            }
+
+            assert_eq!(
+                sched_setattr(&SchedAttr {
+                    size: size_of::<SchedAttr>() as u32,
+                    ..Default::default()
+                }),
+                0
+            );
+            assert_eq!(
+                libc::syscall(
+                    libc::SYS_sched_getattr,
+                    0,
+                    &mut attr as *mut SchedAttr,
+                    size_of::<SchedAttr>(),
+                    0
+                ),
+                0
+            );
+            assert_eq!(attr.sched_policy, 0);
+
+            libc::_exit(0);
+        }
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+}
+
+register_test!(test_sched_deadline);
+
+fn test_cpu_affinity() {
+    fn cpu_list(path: &str) -> Vec<usize> {
+        let list = std::fs::read_to_string(path).unwrap();
+        let mut cpus = Vec::new();
+
+        for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+            let (first, last) = range.split_once('-').unwrap_or((range, range));
+            cpus.extend(first.parse::<usize>().unwrap()..=last.parse().unwrap());
+        }
+
+        cpus
+    }
+
+    fn affinity() -> Vec<usize> {
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            assert_eq!(
+                libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set),
+                0
+            );
+            (0..libc::CPU_SETSIZE as usize)
+                .filter(|cpu| libc::CPU_ISSET(*cpu, &set))
+                .collect()
+        }
+    }
+
+    fn pin(cpu: usize) -> i32 {
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_SET(cpu, &mut set);
+            libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set)
+        }
+    }
+
+    let possible = cpu_list("/sys/devices/system/cpu/possible");
+    let isolated = cpu_list("/sys/devices/system/cpu/isolated");
+    let housekeeping: Vec<usize> = possible
+        .iter()
+        .copied()
+        .filter(|cpu| !isolated.contains(cpu))
+        .collect();
+
+    // Nothing runs on an isolated CPU unless it's pinned there.
+    assert_eq!(affinity(), housekeeping);
+
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            // A CPU that doesn't exist can't be the only one.
+            assert_eq!(pin(possible.len()), -1);
+            assert_eq!(*libc::__errno_location(), libc::EINVAL);
+
+            // Pinning works for isolated CPUs too, and moves us at once.
+            let target = *possible.last().unwrap();
+            assert_eq!(pin(target), 0);
+            assert_eq!(affinity(), [target]);
+            assert_eq!(libc::sched_getcpu(), target as i32);
+
+            // Children stay where their parent was pinned.
+            let child = libc::fork();
+            if child == 0 {
+                let ok = affinity() == [target] && libc::sched_getcpu() == target as i32;
+                libc::_exit(if ok { 0 } else { 1 });
+            }
+            let mut status = 0;
+            assert_eq!(libc::waitpid(child, &mut status, 0), child);
+            assert!(libc::WIFEXITED(status));
+            assert_eq!(libc::WEXITSTATUS(status), 0);
+
+            libc::_exit(0);
+        }
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+}
+
+register_test!(test_cpu_affinity);
+
+fn test_tty_job_control() {
+    unsafe {
+        let fd = libc::open(c"/dev/tty".as_ptr(), libc::O_RDWR | libc::O_NOCTTY);
+        assert!(fd >= 0);
+
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            // Take the terminal the way a shell hands it to a job.
+            assert_eq!(libc::setpgid(0, 0), 0);
+            libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+            assert_eq!(libc::tcsetpgrp(fd, libc::getpgrp()), 0);
+            assert_eq!(libc::tcgetpgrp(fd), libc::getpgrp());
+
+            assert_eq!(libc::tcsetpgrp(fd, 999_999), -1);
+            assert_eq!(*libc::__errno_location(), libc::ESRCH);
+
+            // Without CAP_SYS_ADMIN, input can't be faked.
+            let mut hdr = [0x2008_0522u32, 0];
+            let data = [0u32; 6];
+            assert_eq!(
+                libc::syscall(libc::SYS_capset, hdr.as_mut_ptr(), data.as_ptr()),
+                0
+            );
+            let byte = b'x';
+            assert_eq!(libc::ioctl(fd, libc::TIOCSTI, &byte), -1);
+            assert_eq!(*libc::__errno_location(), libc::EPERM);
+
+            libc::_exit(0);
+        }
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+
+        // We're in the background now, so take the terminal back.
+        libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        assert_eq!(libc::tcsetpgrp(fd, libc::getpgrp()), 0);
+        libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+        libc::close(fd);
+    }
+}
+
+register_test!(test_tty_job_control);
+
+fn test_mprotect_spanning_mappings() {
+    let page_size = 4096;
+
+    unsafe {
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            3 * page_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        if addr == libc::MAP_FAILED {
+            panic!("mmap failed: {}", std::io::Error::last_os_error());
+        }
+        let addr = addr as *mut u8;
+
+        // Split the mapping in three.
+        assert_eq!(
+            libc::mprotect(addr.add(page_size).cast(), page_size, libc::PROT_READ),
+            0
+        );
+        *addr = 1;
+        *addr.add(2 * page_size) = 3;
+
+        // One call covers all three; the length is rounded up to whole pages.
+        assert_eq!(
+            libc::mprotect(
+                addr.cast(),
+                2 * page_size + 1,
+                libc::PROT_READ | libc::PROT_WRITE
+            ),
+            0
+        );
+        *addr.add(page_size) = 2;
+        assert_eq!(*addr + *addr.add(page_size) + *addr.add(2 * page_size), 6);
+
+        // A hole in the range fails.
+        assert_eq!(libc::munmap(addr.add(page_size).cast(), page_size), 0);
+        assert_eq!(
+            libc::mprotect(addr.cast(), 3 * page_size, libc::PROT_READ),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ENOMEM)
+        );
+
+        assert_eq!(libc::munmap(addr.cast(), 3 * page_size), 0);
+    }
+}
+
+register_test!(test_mprotect_spanning_mappings);
+
+fn test_proc_allocator_stats() {
+    let slabinfo = std::fs::read_to_string("/proc/slabinfo").unwrap();
+    let mut lines = slabinfo.lines();
+    assert_eq!(lines.next(), Some("slabinfo - version: 2.1"));
+    assert!(lines.next().unwrap().starts_with("# name"));
+
+    let mut total_allocs = 0;
+    let (mut cache_hits, mut cache_misses) = (0, 0);
+
+    for line in lines {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        assert!(fields[0].starts_with("kmalloc-"));
+
+        let num = |i: usize| fields[i].parse::<u64>().unwrap();
+        let (active_objs, num_objs) = (num(1), num(2));
+        let (active_slabs, num_slabs) = (num(7), num(8));
+        let peak_objs = num(11);
+
+        assert!(active_objs <= num_objs);
+        assert!(active_slabs <= num_slabs);
+        assert!(peak_objs >= active_objs);
+        total_allocs += num(12);
+
+        assert_eq!(fields[14], "cpustat");
+        cache_hits += num(15) + num(17);
+        cache_misses += num(16) + num(18);
+    }
+
+    // The kernel can't have got this far without a heap.
+    assert!(total_allocs > 0);
+
+    // Most allocations and frees should never have touched a slab lock.
+    assert!(cache_hits > cache_misses);
+
+    let buddyinfo = std::fs::read_to_string("/proc/buddyinfo").unwrap();
+    assert!(buddyinfo.lines().count() > 0);
+
+    for line in buddyinfo.lines() {
+        let (zone, counts) = line.split_once("zone").unwrap();
+        assert_eq!(zone, "Node 0, ");
+
+        // The zone name followed by one count per order.
+        let counts: Vec<&str> = counts.split_whitespace().collect();
+        assert_eq!(counts.len(), 12);
+        assert!(counts[1..].iter().all(|c| c.parse::<u64>().is_ok()));
+    }
+}
+
+register_test!(test_proc_allocator_stats);
+
+fn test_proc_task_listing() {
+    let listed = |pid: libc::pid_t| {
+        std::fs::read_dir("/proc")
+            .unwrap()
+            .any(|entry| entry.unwrap().file_name().to_str() == Some(&pid.to_string()))
+    };
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0);
+
+    if pid == 0 {
+        // Wait for the parent to close its end of the pipe.
+        let mut byte = 0u8;
+        unsafe {
+            libc::close(fds[1]);
+            libc::read(fds[0], (&mut byte as *mut u8).cast(), 1);
+            libc::_exit(0);
+        }
+    }
+
+    unsafe { libc::close(fds[0]) };
+
+    // Listings made while nothing changes match, however they're served.
+    assert!(listed(pid));
+    assert!(listed(pid));
+    assert!(listed(unsafe { libc::getpid() }));
+
+    unsafe { libc::close(fds[1]) };
+
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(!listed(pid));
+}
+
+register_test!(test_proc_task_listing);
+
+fn test_proc_start_time() {
+    // Field 22 of `/proc/[pid]/stat`, counting from 1.
+    let start_time = |pid: libc::pid_t| -> u64 {
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+        let (_, rest) = stat.rsplit_once(')').unwrap();
+        rest.split_whitespace().nth(19).unwrap().parse().unwrap()
+    };
+
+    let parent = start_time(unsafe { libc::getpid() });
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0);
+
+    if pid == 0 {
+        let mut byte = 0u8;
+        unsafe {
+            libc::close(fds[1]);
+            libc::read(fds[0], (&mut byte as *mut u8).cast(), 1);
+            libc::_exit(0);
+        }
+    }
+
+    unsafe { libc::close(fds[0]) };
+
+    let child = start_time(pid);
+    assert!(child >= parent);
+    assert_eq!(start_time(pid), child);
+
+    unsafe { libc::close(fds[1]) };
+
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+
+    let boot_id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id").unwrap();
+    let groups: Vec<&str> = boot_id.trim_end().split('-').collect();
+    assert_eq!(
+        groups.iter().map(|g| g.len()).collect::<Vec<_>>(),
+        [8, 4, 4, 4, 12]
+    );
+    assert!(groups[2].starts_with('4'));
+    assert_eq!(
+        std::fs::read_to_string("/proc/sys/kernel/random/boot_id").unwrap(),
+        boot_id
+    );
+}
+
+register_test!(test_proc_start_time);
+
+fn test_proc_interrupts() {
+    let interrupts = std::fs::read_to_string("/proc/interrupts").unwrap();
+    let mut lines = interrupts.lines();
+
+    let cpus: Vec<&str> = lines.next().unwrap().split_whitespace().collect();
+    assert!(!cpus.is_empty());
+    assert!(
+        cpus.iter()
+            .enumerate()
+            .all(|(i, cpu)| *cpu == format!("CPU{i}"))
+    );
+
+    let mut total = 0;
+    let mut seen_err = false;
+
+    for line in lines {
+        let (label, rest) = line.split_once(':').unwrap();
+        let fields: Vec<&str> = rest.split_whitespace().collect();
+        let counts: u64 = fields[..cpus.len()]
+            .iter()
+            .map(|count| count.parse::<u64>().unwrap())
+            .sum();
+
+        if label.trim() == "Err" {
+            assert_eq!(fields.len(), cpus.len());
+            seen_err = true;
+        } else {
+            // The controller, the interrupt, its trigger and who claimed it.
+            assert!(fields.len() > cpus.len() + 4);
+            total += counts;
+        }
+    }
+
+    // At the very least, the timer has been ticking.
+    assert!(seen_err);
+    assert!(total > 0);
+}
+
+register_test!(test_proc_interrupts);
+
+fn test_proc_config() {
+    let gz = std::fs::read("/proc/config.gz").unwrap();
+    assert_eq!(gz[..4], [0x1f, 0x8b, 8, 0]);
+
+    // The kernel only writes stored deflate blocks, so the text can be pulled
+    // straight out of them.
+    let mut config = Vec::new();
+    let mut off = 10;
+
+    loop {
+        let header = gz[off];
+        assert_eq!(header & !1, 0);
+        let len = u16::from_le_bytes([gz[off + 1], gz[off + 2]]) as usize;
+        assert_eq!(!len as u16, u16::from_le_bytes([gz[off + 3], gz[off + 4]]));
+        config.extend_from_slice(&gz[off + 5..off + 5 + len]);
+        off += 5 + len;
+
+        if header & 1 != 0 {
+            break;
+        }
+    }
+
+    let size = u32::from_le_bytes(gz[off + 4..off + 8].try_into().unwrap());
+    assert_eq!(size as usize, config.len());
+    assert_eq!(off + 8, gz.len());
+
+    let config = String::from_utf8(config).unwrap();
+    let mut net = None;
+
+    for line in config.lines() {
+        if let Some(opt) = line.strip_prefix("# CONFIG_") {
+            let opt = opt.strip_suffix(" is not set").unwrap();
+            if opt == "NET" {
+                net = Some(false);
+            }
+        } else if !line.starts_with('#') {
+            let opt = line.strip_prefix("CONFIG_").unwrap();
+            assert!(opt.ends_with("=y"), "{line}");
+            if opt == "NET=y" {
+                net = Some(true);
+            }
+        }
+    }
+
+    // TCP/IP sockets are there only if the configuration says so.
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
+    if net.unwrap() {
+        assert!(fd >= 0);
+        unsafe { libc::close(fd) };
+    } else {
+        assert_eq!(fd, -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EAFNOSUPPORT)
+        );
+    }
+}
+
+register_test!(test_proc_config);
+
+fn test_unhandled_syscalls() {
+    // Neither io_setup nor a number past the end of the table is handled;
+    // both fail cleanly.
+    for nr in [libc::SYS_io_setup, 4000] {
+        let ret = unsafe { libc::syscall(nr, 0, 0, 0) };
+        assert_eq!(ret, -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ENOSYS)
+        );
+    }
+
+    let table = std::fs::read_to_string("/proc/syscalls").unwrap();
+    let support = |nr: libc::c_long| {
+        table
+            .lines()
+            .find(|line| line.split(' ').next() == Some(&nr.to_string()))
+            .map(|line| line.rsplit(' ').next().unwrap().to_string())
+    };
+
+    assert_eq!(support(libc::SYS_read).as_deref(), Some("implemented"));
+    assert_eq!(support(libc::SYS_rseq).as_deref(), Some("stub"));
+    assert_eq!(support(libc::SYS_io_setup).as_deref(), Some("missing"));
+    assert!(table.lines().all(|line| line.split(' ').count() == 3));
+}
+
+register_test!(test_unhandled_syscalls);
+
+fn test_close_range() {
+    use std::ffi::CString;
+
+    const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
+
+    let path = CString::new("/dev/null").unwrap();
+    let fds: Vec<libc::c_int> = (0..4)
+        .map(|_| unsafe { libc::open(path.as_ptr(), libc::O_RDONLY) })
+        .collect();
+    assert!(fds.iter().all(|&fd| fd >= 0));
+    assert!(fds.windows(2).all(|w| w[1] == w[0] + 1));
+
+    let close_range = |first: libc::c_int, last: libc::c_uint, flags: libc::c_uint| unsafe {
+        libc::syscall(libc::SYS_close_range, first as libc::c_uint, last, flags)
+    };
+
+    // Mark the middle two close-on-exec, leaving the rest alone.
+    assert_eq!(close_range(fds[1], fds[2] as _, CLOSE_RANGE_CLOEXEC), 0);
+    let cloexec: Vec<bool> = fds
+        .iter()
+        .map(|&fd| unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC != 0)
+        .collect();
+    assert_eq!(cloexec, [false, true, true, false]);
+
+    // A backwards range is refused.
+    assert_eq!(close_range(fds[2], fds[1] as _, 0), -1);
+    assert_eq!(
+        std::io::Error::last_os_error().raw_os_error(),
+        Some(libc::EINVAL)
+    );
+
+    // Closing up to the largest descriptor skips the ones that aren't open.
+    // Only go that far if nothing the runner holds lies beyond.
+    let clear_above =
+        (fds[3] + 1..fds[3] + 64).all(|fd| unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1);
+    let last = if clear_above {
+        !0
+    } else {
+        fds[3] as libc::c_uint
+    };
+    unsafe { libc::close(fds[1]) };
+    assert_eq!(close_range(fds[0], last, 0), 0);
+    for fd in fds {
+        assert_eq!(unsafe { libc::fcntl(fd, libc::F_GETFD) }, -1);
+    }
+}
+
+register_test!(test_close_range);
+
+fn test_cloexec() {
+    use std::ffi::CString;
+
+    let cloexec = |fd: libc::c_int| unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC;
+
+    let mut pipe = [0; 2];
+    assert_eq!(
+        unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC) },
+        0
+    );
+    assert_eq!(cloexec(pipe[0]), libc::FD_CLOEXEC);
+    assert_eq!(cloexec(pipe[1]), libc::FD_CLOEXEC);
+
+    // Each way of duplicating a descriptor sets the flag as asked, however
+    // the original was marked.
+    unsafe {
+        assert_eq!(libc::dup3(pipe[1], 100, libc::O_CLOEXEC), 100);
+        assert_eq!(cloexec(100), libc::FD_CLOEXEC);
+        assert_eq!(libc::dup3(pipe[1], 101, 0), 101);
+        assert_eq!(cloexec(101), 0);
+        assert_eq!(libc::dup3(101, 101, 0), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+
+        let fd = libc::fcntl(101, libc::F_DUPFD_CLOEXEC, 102);
+        assert!(fd >= 102);
+        assert_eq!(cloexec(fd), libc::FD_CLOEXEC);
+        assert_eq!(libc::fcntl(fd, libc::F_SETFD, 0), 0);
+        assert_eq!(cloexec(fd), 0);
+        libc::close(fd);
+
+        let fd = libc::fcntl(100, libc::F_DUPFD, 102);
+        assert!(fd >= 102);
+        assert_eq!(cloexec(fd), 0);
+        libc::close(fd);
+    }
+
+    let path = CString::new("/dev/null").unwrap();
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+    assert!(fd >= 0);
+    assert_eq!(cloexec(fd), libc::FD_CLOEXEC);
+    unsafe { libc::close(fd) };
+
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    assert!(fd >= 0);
+    assert_eq!(cloexec(fd), libc::FD_CLOEXEC);
+    unsafe { libc::close(fd) };
+
+    let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+    assert!(fd >= 0);
+    assert_eq!(cloexec(fd), libc::FD_CLOEXEC);
+    unsafe { libc::close(fd) };
+
+    // Only the descriptor without the flag makes it across an exec.
+    let sh = CString::new("/bin/sh").unwrap();
+    let dash_c = CString::new("-c").unwrap();
+    let script = CString::new("exec 2>/dev/null; echo kept >&101; echo leaked >&100").unwrap();
+
+    unsafe {
+        let pid = libc::fork();
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            let argv = [
+                sh.as_ptr(),
+                dash_c.as_ptr(),
+                script.as_ptr(),
+                std::ptr::null(),
+            ];
+            libc::execv(sh.as_ptr(), argv.as_ptr());
+            libc::_exit(127);
+        }
+
+        for fd in [pipe[1], 100, 101] {
+            libc::close(fd);
+        }
+
+        let mut out = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            let n = libc::read(pipe[0], buf.as_mut_ptr().cast(), buf.len());
+            assert!(n >= 0);
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n as usize]);
+        }
+        libc::close(pipe[0]);
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(out, b"kept\n");
+    }
+}
+
+register_test!(test_cloexec);
+
+fn test_rlimit_nofile() {
+    use std::os::unix::fs::MetadataExt;
+
+    fn errno() -> Option<i32> {
+        std::io::Error::last_os_error().raw_os_error()
+    }
+
+    let mut saved = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    assert_eq!(
+        unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut saved) },
+        0
+    );
+
+    let limits = std::fs::read_to_string("/proc/self/limits").unwrap();
+    let line = limits
+        .lines()
+        .find(|l| l.starts_with("Max open files"))
+        .unwrap();
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    assert_eq!(
+        fields[3..],
+        [
+            saved.rlim_cur.to_string().as_str(),
+            saved.rlim_max.to_string().as_str(),
+            "files"
+        ]
+    );
+
+    // Hold the soft limit a few descriptors above the lowest free one.
+    let base = unsafe { libc::dup(0) };
+    assert!(base >= 0);
+    unsafe { libc::close(base) };
+    let limit = base + 3;
+    let set_soft = |soft: libc::c_int| {
+        let rlim = libc::rlimit {
+            rlim_cur: soft as _,
+            rlim_max: saved.rlim_max,
+        };
+        assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &rlim) }, 0);
+    };
+    set_soft(limit);
+
+    let mut fds = Vec::new();
+    loop {
+        let fd = unsafe { libc::dup(0) };
+        if fd < 0 {
+            assert_eq!(errno(), Some(libc::EMFILE));
+            break;
+        }
+        assert!(fd < limit);
+        fds.push(fd);
+    }
+    assert!(!fds.is_empty());
+
+    unsafe {
+        assert_eq!(libc::fcntl(0, libc::F_DUPFD, limit), -1);
+        assert_eq!(errno(), Some(libc::EINVAL));
+        assert_eq!(libc::dup3(0, limit, 0), -1);
+        assert_eq!(errno(), Some(libc::EBADF));
+
+        // A freed gap is reused, at or above the minimum asked for.
+        let last = *fds.last().unwrap();
+        libc::close(last);
+        assert_eq!(libc::fcntl(0, libc::F_DUPFD, last), last);
+
+        // Descriptors above a lowered limit stay open, but no new ones can be
+        // made up there.
+        set_soft(base);
+        assert_eq!(libc::fcntl(last, libc::F_GETFD), 0);
+        assert_eq!(libc::dup(0), -1);
+        assert_eq!(errno(), Some(libc::EMFILE));
+    }
+
+    for fd in fds {
+        unsafe { libc::close(fd) };
+    }
+    set_soft(saved.rlim_cur as _);
+
+    // The hard limit can't be raised past `nr_open`.
+    let nr_open: u64 = std::fs::read_to_string("/proc/sys/fs/nr_open")
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    let too_big = libc::rlimit {
+        rlim_cur: saved.rlim_cur,
+        rlim_max: nr_open + 1,
+    };
+    assert_eq!(
+        unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &too_big) },
+        -1
+    );
+    assert_eq!(errno(), Some(libc::EPERM));
+
+    // The table grows to fit a high descriptor, which shows up in /proc.
+    let high = saved.rlim_cur as libc::c_int - 1;
+    assert_eq!(unsafe { libc::dup3(0, high, 0) }, high);
+    let fd_size: usize = std::fs::read_to_string("/proc/self/status")
+        .unwrap()
+        .lines()
+        .find_map(|l| l.strip_prefix("FDSize:"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    assert!(fd_size > high as usize && fd_size.is_power_of_two());
+    assert!(
+        std::fs::read_dir("/proc/self/fd")
+            .unwrap()
+            .any(|e| e.unwrap().file_name().to_str() == Some(&high.to_string()))
+    );
+    unsafe { libc::close(high) };
+
+    // fdinfo shows the close-on-exec flag with the open flags, as Linux does.
+    let path = std::ffi::CString::new("/dev/null").unwrap();
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+    assert!(fd >= 0);
+    let info = std::fs::read_to_string(format!("/proc/self/fdinfo/{fd}")).unwrap();
+    let ino = std::fs::metadata("/dev/null").unwrap().ino();
+    let field = |name: &str| {
+        info.lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(":\t"))
+            .unwrap_or_else(|| panic!("no {name} in {info}"))
+            .to_string()
+    };
+    let flags = field("flags");
+    assert!(flags.starts_with('0'), "{info}");
+    assert_eq!(
+        i32::from_str_radix(&flags, 8).unwrap() & (libc::O_ACCMODE | libc::O_CLOEXEC),
+        libc::O_CLOEXEC
+    );
+    assert_eq!(field("pos"), "0");
+    assert_eq!(field("ino"), ino.to_string());
+    unsafe { libc::close(fd) };
+}
+
+register_test!(test_rlimit_nofile);
+
+fn test_fd_reopen() {
+    use std::{
+        fs::OpenOptions,
+        io::{Read, Seek},
+        os::unix::io::AsRawFd,
+    };
+
+    const PATH: &str = "/tmp/fd_reopen_usertest";
+
+    fn errno() -> Option<i32> {
+        std::io::Error::last_os_error().raw_os_error()
+    }
+
+    // Reopening gives a new open file, with flags and a position of its own,
+    // even once the path has gone.
+    std::fs::write(PATH, b"hello").unwrap();
+    let mut file = std::fs::File::open(PATH).unwrap();
+    let mut buf = [0u8; 2];
+    file.read_exact(&mut buf).unwrap();
+    std::fs::remove_file(PATH).unwrap();
+
+    let mut rw = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(format!("/dev/fd/{}", file.as_raw_fd()))
+        .unwrap();
+    let mut contents = String::new();
+    rw.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "hello");
+    rw.write_all(b", world").unwrap();
+
+    assert_eq!(file.stream_position().unwrap(), 2);
+    let mut rest = String::new();
+    file.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "llo, world");
+
+    // A pipe reopened through /proc joins the same pipe, as process
+    // substitution in a shell relies on.
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    let link = std::fs::read_link(format!("/proc/self/fd/{}", pipe[0])).unwrap();
+    assert!(link.to_str().unwrap().starts_with("pipe:["), "{link:?}");
+
+    let mut reader = std::fs::File::open(format!("/proc/self/fd/{}", pipe[0])).unwrap();
+    assert_eq!(
+        unsafe { libc::write(pipe[1], b"ping".as_ptr().cast(), 4) },
+        4
+    );
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+
+    // Files with nothing behind them to open can't be reopened.
+    let sock = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0) };
+    assert!(sock >= 0);
+    let path = std::ffi::CString::new(format!("/dev/fd/{sock}")).unwrap();
+    assert_eq!(unsafe { libc::open(path.as_ptr(), libc::O_RDWR) }, -1);
+    assert_eq!(errno(), Some(libc::ENXIO));
+
+    // Not following the link leaves it a link.
+    let meta = std::fs::symlink_metadata("/dev/fd").unwrap();
+    assert!(meta.file_type().is_symlink());
+    assert_eq!(
+        std::fs::read_link("/dev/stdin").unwrap().to_str(),
+        Some("/proc/self/fd/0")
+    );
+
+    // dup2 onto an open descriptor closes what was there, so the last writer
+    // of the pipe goes and its reader sees the end.
+    unsafe {
+        assert_eq!(libc::dup2(pipe[1], 100), 100);
+        libc::close(pipe[1]);
+        assert_eq!(libc::dup2(file.as_raw_fd(), 100), 100);
+        assert_eq!(libc::fcntl(pipe[0], libc::F_SETFL, libc::O_NONBLOCK), 0);
+        assert_eq!(libc::read(pipe[0], buf.as_mut_ptr().cast(), buf.len()), 0);
+
+        for fd in [pipe[0], sock, 100] {
+            libc::close(fd);
+        }
+    }
+}
+
+register_test!(test_fd_reopen);
+
+fn test_mprotect_shared_readonly_file() {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let page_size = 4096;
+    let path = "/dev/shm/mprotect_ro";
+
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o644)
+        .open(path)
+        .unwrap();
+    file.set_len(page_size as u64).unwrap();
+    drop(file);
+
+    // A shared mapping of a file opened read-only can't be made writable
+    // later, even by the file's owner.
+    let file = std::fs::File::open(path).unwrap();
+
+    unsafe {
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            page_size,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        );
+        assert_ne!(addr, libc::MAP_FAILED);
+
+        assert_eq!(
+            libc::mprotect(addr, page_size, libc::PROT_READ | libc::PROT_WRITE),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EACCES)
+        );
+
+        assert_eq!(libc::munmap(addr, page_size), 0);
+    }
+
+    drop(file);
+    std::fs::remove_file(path).unwrap();
+}
+
+register_test!(test_mprotect_shared_readonly_file);
+
+fn test_itimer() {
+    use libc::{ITIMER_REAL, itimerval};
+    use std::mem::MaybeUninit;
+
+    unsafe {
+        // Set signal handler for SIGALRM to avoid process termination when the timer expires
+        // We'll flip a bit in a static variable when the signal handler is called, and check that bit at the end of the test to verify the timer actually expired.
+        static TIMER_EXPIRED: std::sync::atomic::AtomicBool =
+            std::sync::atomic::AtomicBool::new(false);
+        extern "C" fn sigalrm_handler(_signum: libc::c_int) {
+            TIMER_EXPIRED.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+        let mut sa: libc::sigaction = std::mem::zeroed();
+        sa.sa_sigaction = sigalrm_handler as *const () as usize;
+        sa.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut sa.sa_mask);
+        if libc::sigaction(libc::SIGALRM, &sa, std::ptr::null_mut()) != 0 {
+            panic!("sigaction failed: {}", std::io::Error::last_os_error());
+        }
+
+        let mut old_timer = MaybeUninit::<itimerval>::uninit();
+        let new_timer = itimerval {
+            it_interval: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            it_value: libc::timeval {
+                tv_sec: 1,
+                tv_usec: 0,
+            },
+        };
+        let ret = libc::setitimer(
+            ITIMER_REAL,
+            &new_timer as *const itimerval,
+            old_timer.as_mut_ptr(),
+        );
+        if ret != 0 {
+            panic!("setitimer failed: {}", std::io::Error::last_os_error());
+        }
+        let old_timer = old_timer.assume_init();
+        assert_eq!(old_timer.it_value.tv_sec, 0);
+        assert_eq!(old_timer.it_value.tv_usec, 0);
+        // Wait for 2 seconds to ensure the timer has time to expire
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        assert!(
+            TIMER_EXPIRED.load(std::sync::atomic::Ordering::SeqCst),
+            "Expected timer to have expired and signal handler to have been called"
+        );
+    }
+}
+
+register_test!(test_itimer);
+
+/// Exit status of a test child that found nothing to test, as with automake.
+pub const SKIP_EXIT: i32 = 77;
+
+/// Ends the running test as skipped, for tests that need something the kernel
+/// doesn't have yet.
+pub fn skip(reason: &str) -> ! {
+    eprintln!("Skipping: {reason}");
+    unsafe { libc::_exit(SKIP_EXIT) }
+}
+
+fn run_test(test_fn: fn()) -> Result<(), i32> {
+    // Fork a new process to run the test
+    unsafe {
+        let pid = libc::fork();
+        if pid < 0 {
+            panic!("fork failed");
+        } else if pid == 0 {
+            // Child process
+            let fds_before = leak::open_fds();
+            let result = std::panic::catch_unwind(|| {
+                test_fn();
+            });
+            let fds_leaked = leak::open_fds().saturating_sub(fds_before);
+            let exit_code = if let Err(e) = result {
+                // Get the panic info
+                eprintln!("Test panicked: {:?}", e);
+                let error = std::io::Error::last_os_error();
+                eprintln!("Last OS error: {}", error);
+                1
+            } else if fds_leaked > 0 {
+                eprintln!("Test leaked {fds_leaked} file descriptors");
+                leak::FD_LEAK_EXIT
+            } else {
+                0
+            };
+            libc::_exit(exit_code);
+        } else {
+            // Parent process
+            let mut status = 0;
+            libc::waitpid(pid, &mut status, 0);
+            if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+                Err(status)
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+fn main() {
+    // Run as a set-user-ID program by `test_exec_setuid`.
+    if std::env::var_os("USERTEST_PRINT_IDS").is_some() {
+        unsafe {
+            println!(
+                "{} {} {} {} {}",
+                libc::getuid(),
+                libc::geteuid(),
+                libc::getgid(),
+                libc::getegid(),
+                libc::getauxval(libc::AT_SECURE)
+            );
+        }
+        return;
+    }
+
+    println!("Running userspace tests ...");
+    // Get all args
+    let args: Vec<String> = std::env::args().collect();
+    let soak = args.iter().skip(1).any(|arg| arg == "--soak");
+    let filter = args
+        .iter()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .map(|s| s.as_str());
+    let start = std::time::Instant::now();
+    let mut failures = 0;
+    let mut skipped = 0;
+    for test in inventory::iter::<Test> {
+        if test.soak && !soak {
+            continue;
+        }
+        if let Some(filter) = filter
+            && !test.test_text.contains(filter)
+        {
+            continue;
+        }
+        print!("{} ...", test.test_text);
+        let _ = stdout().flush();
+        let free_before = leak::free_kb();
+        let result = run_test(test.test_fn);
+        let free_after = leak::free_kb();
+        match result {
+            Ok(()) if free_after + leak::MEM_SLACK_KB < free_before => {
+                println!(" {}", "FAILED".red());
+                eprintln!(
+                    "Test '{}' leaked {} kB of memory",
+                    test.test_text,
+                    free_before - free_after
+                );
+                failures += 1;
+            }
+            Ok(()) => println!("{}", " OK".green()),
+            Err(code) if libc::WIFEXITED(code) && libc::WEXITSTATUS(code) == SKIP_EXIT => {
+                println!("{}", " SKIPPED".yellow());
+                skipped += 1;
+            }
+            Err(code) => {
+                println!(" {}", "FAILED".red());
+                eprintln!("Test '{}' failed with exit code {}", test.test_text, code);
+                failures += 1;
+            }
+        }
+    }
+    let end = std::time::Instant::now();
+    if failures > 0 {
+        eprintln!(
+            "{failures} tests failed in {} ms",
+            (end - start).as_millis()
+        );
+        std::process::exit(1);
+    }
+    println!("All tests passed in {} ms", (end - start).as_millis());
+    if skipped > 0 {
+        println!("{skipped} tests skipped");
+    }
 }
@@ -0,0 +1,568 @@
+use crate::{register_test, skip};
+use std::mem::{size_of, size_of_val, zeroed};
+
+const TCP_PORT: u16 = 40123;
+const UDP_PORT: u16 = 40124;
+const CLIENTS: usize = 3;
+
+fn errno() -> i32 {
+    unsafe { *libc::__errno_location() }
+}
+
+fn loopback(port: u16) -> libc::sockaddr_in {
+    libc::sockaddr_in {
+        sin_family: libc::AF_INET as _,
+        sin_port: port.to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(std::net::Ipv4Addr::LOCALHOST).to_be(),
+        },
+        sin_zero: [0; 8],
+    }
+}
+
+fn unix_addr(path: &str) -> libc::sockaddr_un {
+    let mut addr: libc::sockaddr_un = unsafe { zeroed() };
+    addr.sun_family = libc::AF_UNIX as _;
+    for (dst, src) in addr.sun_path.iter_mut().zip(path.as_bytes()) {
+        *dst = *src as _;
+    }
+    addr
+}
+
+/// Skips the test unless there's a loopback interface to talk over: a
+/// connection to a closed port on it has to be refused.
+fn require_loopback() {
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0);
+        if fd < 0 {
+            skip("no TCP sockets");
+        }
+
+        let addr = loopback(1);
+        let ret = libc::connect(fd, (&raw const addr).cast(), size_of_val(&addr) as _);
+        let err = errno();
+        libc::close(fd);
+
+        if ret == 0 || err != libc::ECONNREFUSED {
+            skip("no loopback interface");
+        }
+    }
+}
+
+/// Ends a forked helper, reporting whether it did its part.
+fn exit_child(ok: bool) -> ! {
+    unsafe { libc::_exit(if ok { 0 } else { 1 }) }
+}
+
+fn wait_child(pid: libc::pid_t) {
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+}
+
+/// Writes all of `buf`, however many goes the stream takes.
+fn write_all(fd: i32, buf: &[u8]) -> bool {
+    let mut done = 0;
+
+    while done < buf.len() {
+        let n = unsafe { libc::write(fd, buf[done..].as_ptr().cast(), buf.len() - done) };
+        if n <= 0 {
+            return false;
+        }
+        done += n as usize;
+    }
+
+    true
+}
+
+/// Reads exactly `buf.len()` bytes, however the stream splits them up.
+fn read_exact(fd: i32, buf: &mut [u8]) -> bool {
+    let mut done = 0;
+
+    while done < buf.len() {
+        let n = unsafe { libc::read(fd, buf[done..].as_mut_ptr().cast(), buf.len() - done) };
+        if n <= 0 {
+            return false;
+        }
+        done += n as usize;
+    }
+
+    true
+}
+
+fn socketpair(type_: i32) -> [i32; 2] {
+    let mut sv = [0; 2];
+    assert_eq!(
+        unsafe { libc::socketpair(libc::AF_UNIX, type_, 0, sv.as_mut_ptr()) },
+        0
+    );
+    sv
+}
+
+fn test_net_tcp_loopback() {
+    require_loopback();
+
+    unsafe {
+        let server = libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0);
+        assert!(server >= 0);
+        let addr = loopback(TCP_PORT);
+        let len = size_of_val(&addr) as libc::socklen_t;
+        assert_eq!(libc::bind(server, (&raw const addr).cast(), len), 0);
+        assert_eq!(libc::listen(server, CLIENTS as _), 0);
+
+        let pid = libc::fork();
+        assert!(pid >= 0);
+
+        if pid == 0 {
+            let fd = libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0);
+            let mut buf = [0u8; 4];
+            let ok = fd >= 0
+                && libc::connect(fd, (&raw const addr).cast(), len) == 0
+                && write_all(fd, b"ping")
+                && read_exact(fd, &mut buf)
+                && &buf == b"pong";
+            exit_child(ok);
+        }
+
+        let mut peer: libc::sockaddr_in = zeroed();
+        let mut peer_len = size_of_val(&peer) as libc::socklen_t;
+        let conn = libc::accept(server, (&raw mut peer).cast(), &mut peer_len);
+        assert!(conn >= 0);
+        assert_eq!(peer.sin_family, libc::AF_INET as libc::sa_family_t);
+        assert_eq!(peer.sin_addr.s_addr, addr.sin_addr.s_addr);
+
+        let mut buf = [0u8; 4];
+        assert!(read_exact(conn, &mut buf));
+        assert_eq!(&buf, b"ping");
+        assert!(write_all(conn, b"pong"));
+
+        wait_child(pid);
+
+        // The client has gone, so the stream is at its end.
+        assert_eq!(libc::read(conn, buf.as_mut_ptr().cast(), buf.len()), 0);
+
+        libc::close(conn);
+        libc::close(server);
+    }
+}
+
+register_test!(test_net_tcp_loopback);
+
+fn test_net_udp_echo() {
+    require_loopback();
+
+    unsafe {
+        let server = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+        if server < 0 {
+            skip("no UDP sockets");
+        }
+        let addr = loopback(UDP_PORT);
+        let len = size_of_val(&addr) as libc::socklen_t;
+        assert_eq!(libc::bind(server, (&raw const addr).cast(), len), 0);
+
+        let pid = libc::fork();
+        assert!(pid >= 0);
+
+        if pid == 0 {
+            let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+            let mut ok = fd >= 0;
+
+            for msg in [&b"one"[..], b"two!", b"three"] {
+                let mut buf = [0u8; 16];
+                ok = ok
+                    && libc::sendto(
+                        fd,
+                        msg.as_ptr().cast(),
+                        msg.len(),
+                        0,
+                        (&raw const addr).cast(),
+                        len,
+                    ) == msg.len() as isize
+                    && libc::recv(fd, buf.as_mut_ptr().cast(), buf.len(), 0) == msg.len() as isize
+                    && &buf[..msg.len()] == msg;
+            }
+
+            exit_child(ok);
+        }
+
+        // Echo each datagram back to whoever sent it, boundaries and all.
+        for _ in 0..3 {
+            let mut buf = [0u8; 16];
+            let mut from: libc::sockaddr_in = zeroed();
+            let mut from_len = size_of_val(&from) as libc::socklen_t;
+            let n = libc::recvfrom(
+                server,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                0,
+                (&raw mut from).cast(),
+                &mut from_len,
+            );
+            assert!(n > 0);
+            assert_eq!(from.sin_addr.s_addr, addr.sin_addr.s_addr);
+            assert_ne!(from.sin_port, addr.sin_port);
+            assert_eq!(
+                libc::sendto(
+                    server,
+                    buf.as_ptr().cast(),
+                    n as usize,
+                    0,
+                    (&raw const from).cast(),
+                    from_len,
+                ),
+                n
+            );
+        }
+
+        wait_child(pid);
+        libc::close(server);
+    }
+}
+
+register_test!(test_net_udp_echo);
+
+fn test_net_socketpair_stream() {
+    let [a, b] = socketpair(libc::SOCK_STREAM);
+
+    assert!(write_all(a, b"ping"));
+    let mut buf = [0u8; 4];
+    assert!(read_exact(b, &mut buf));
+    assert_eq!(&buf, b"ping");
+
+    assert!(write_all(b, b"pong"));
+    assert!(read_exact(a, &mut buf));
+    assert_eq!(&buf, b"pong");
+
+    // Push through more than the socket holds at once, so that the writer
+    // has to wait on the reader.
+    let data: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+    let expected = data.clone();
+    let writer = std::thread::spawn(move || {
+        assert!(write_all(a, &data));
+        a
+    });
+
+    let mut got = vec![0u8; expected.len()];
+    assert!(read_exact(b, &mut got));
+    assert!(got == expected);
+
+    let a = writer.join().unwrap();
+    unsafe {
+        libc::close(a);
+        libc::close(b);
+    }
+}
+
+register_test!(test_net_socketpair_stream);
+
+fn test_net_socketpair_dgram() {
+    let [a, b] = socketpair(libc::SOCK_DGRAM);
+
+    let msgs: [&[u8]; 3] = [b"a", b"bcd", b"efghij"];
+    for msg in msgs {
+        assert_eq!(
+            unsafe { libc::send(a, msg.as_ptr().cast(), msg.len(), 0) },
+            msg.len() as isize
+        );
+    }
+
+    // Each message comes back on its own, however big the buffer.
+    for msg in msgs {
+        let mut buf = [0u8; 64];
+        let n = unsafe { libc::recv(b, buf.as_mut_ptr().cast(), buf.len(), 0) };
+        assert_eq!(n, msg.len() as isize);
+        assert_eq!(&buf[..msg.len()], msg);
+    }
+
+    // The other way, and a short buffer cuts the message off.
+    assert_eq!(unsafe { libc::send(b, b"xyz".as_ptr().cast(), 3, 0) }, 3);
+    let mut buf = [0u8; 2];
+    assert_eq!(
+        unsafe { libc::recv(a, buf.as_mut_ptr().cast(), buf.len(), 0) },
+        2
+    );
+    assert_eq!(&buf, b"xy");
+
+    unsafe {
+        libc::close(a);
+        libc::close(b);
+    }
+}
+
+register_test!(test_net_socketpair_dgram);
+
+fn test_net_socketpair_seqpacket() {
+    let [a, b] = socketpair(libc::SOCK_SEQPACKET);
+
+    assert!(write_all(a, b"hello"));
+    let mut buf = [0u8; 5];
+    assert!(read_exact(b, &mut buf));
+    assert_eq!(&buf, b"hello");
+
+    unsafe {
+        libc::close(a);
+        libc::close(b);
+    }
+}
+
+register_test!(test_net_socketpair_seqpacket);
+
+fn test_net_unix_many_clients() {
+    let addr = unix_addr("/tmp/net_many_clients");
+    let len = size_of::<libc::sockaddr_un>() as libc::socklen_t;
+
+    unsafe {
+        let server = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0);
+        assert!(server >= 0);
+        assert_eq!(libc::bind(server, (&raw const addr).cast(), len), 0);
+        assert_eq!(libc::listen(server, CLIENTS as _), 0);
+
+        let mut pids = Vec::new();
+
+        for id in 0..CLIENTS as u8 {
+            let pid = libc::fork();
+            assert!(pid >= 0);
+
+            if pid == 0 {
+                let fd = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0);
+                let mut buf = [0u8; 1];
+                let ok = fd >= 0
+                    && libc::connect(fd, (&raw const addr).cast(), len) == 0
+                    && write_all(fd, &[id])
+                    && read_exact(fd, &mut buf)
+                    && buf[0] == id + 100;
+                exit_child(ok);
+            }
+
+            pids.push(pid);
+        }
+
+        // Clients may come in any order, but each must get its own reply.
+        let mut seen = [false; CLIENTS];
+
+        for _ in 0..CLIENTS {
+            let conn = libc::accept(server, std::ptr::null_mut(), std::ptr::null_mut());
+            assert!(conn >= 0);
+
+            let mut buf = [0u8; 1];
+            assert!(read_exact(conn, &mut buf));
+            let id = buf[0] as usize;
+            assert!(id < CLIENTS && !seen[id]);
+            seen[id] = true;
+
+            assert!(write_all(conn, &[buf[0] + 100]));
+            libc::close(conn);
+        }
+
+        for pid in pids {
+            wait_child(pid);
+        }
+
+        libc::close(server);
+    }
+}
+
+register_test!(test_net_unix_many_clients);
+
+/// Sends `fds` over `sock` with a byte of data to carry them.
+fn send_fds(sock: i32, fds: &[i32]) -> isize {
+    unsafe {
+        let data_len = size_of_val(fds) as u32;
+        let mut control = vec![0u8; libc::CMSG_SPACE(data_len) as usize];
+        let mut byte = *b"f";
+        let mut iov = libc::iovec {
+            iov_base: byte.as_mut_ptr().cast(),
+            iov_len: 1,
+        };
+
+        let mut msg: libc::msghdr = zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = control.len() as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+        libc::CMSG_DATA(cmsg)
+            .cast::<i32>()
+            .copy_from_nonoverlapping(fds.as_ptr(), fds.len());
+
+        libc::sendmsg(sock, &msg, 0)
+    }
+}
+
+/// Receives up to `max` fds from `sock`, returning them along with the
+/// message flags.
+fn recv_fds(sock: i32, max: usize, flags: i32) -> (Vec<i32>, i32) {
+    unsafe {
+        let mut control = vec![0u8; libc::CMSG_SPACE((max * size_of::<i32>()) as u32) as usize];
+        let mut byte = [0u8; 1];
+        let mut iov = libc::iovec {
+            iov_base: byte.as_mut_ptr().cast(),
+            iov_len: 1,
+        };
+
+        let mut msg: libc::msghdr = zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = control.len() as _;
+
+        assert_eq!(libc::recvmsg(sock, &mut msg, flags), 1);
+        assert_eq!(&byte, b"f");
+
+        let mut fds = Vec::new();
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+
+        while !cmsg.is_null() {
+            assert_eq!((*cmsg).cmsg_level, libc::SOL_SOCKET);
+            assert_eq!((*cmsg).cmsg_type, libc::SCM_RIGHTS);
+
+            let n = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / size_of::<i32>();
+            let data = libc::CMSG_DATA(cmsg).cast::<i32>();
+            fds.extend((0..n).map(|i| data.add(i).read_unaligned()));
+
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+
+        (fds, msg.msg_flags)
+    }
+}
+
+fn test_net_scm_rights() {
+    let [a, b] = socketpair(libc::SOCK_STREAM);
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+
+    // Pass the read end over and close it here; the copy that arrives has to
+    // keep the pipe going.
+    assert_eq!(send_fds(a, &[pipe[0]]), 1);
+    unsafe { libc::close(pipe[0]) };
+
+    let (fds, flags) = recv_fds(b, 1, libc::MSG_CMSG_CLOEXEC);
+    assert_eq!(flags & libc::MSG_CTRUNC, 0);
+    assert_eq!(fds.len(), 1);
+    let passed = fds[0];
+    assert_eq!(
+        unsafe { libc::fcntl(passed, libc::F_GETFD) } & libc::FD_CLOEXEC,
+        libc::FD_CLOEXEC
+    );
+
+    assert!(write_all(pipe[1], b"through"));
+    let mut buf = [0u8; 7];
+    assert!(read_exact(passed, &mut buf));
+    assert_eq!(&buf, b"through");
+
+    // Two files with room for only one: the other is closed and the message
+    // marked as cut short.
+    assert_eq!(send_fds(a, &[pipe[1], passed]), 1);
+    let (fds, flags) = recv_fds(b, 1, 0);
+    assert_eq!(flags & libc::MSG_CTRUNC, libc::MSG_CTRUNC);
+    assert_eq!(fds.len(), 1);
+    assert_eq!(
+        unsafe { libc::fcntl(fds[0], libc::F_GETFD) } & libc::FD_CLOEXEC,
+        0
+    );
+
+    // Not a file descriptor at all.
+    assert_eq!(send_fds(a, &[-1]), -1);
+    assert_eq!(errno(), libc::EBADF);
+
+    unsafe {
+        for fd in [a, b, pipe[1], passed, fds[0]] {
+            libc::close(fd);
+        }
+    }
+}
+
+register_test!(test_net_scm_rights);
+
+fn test_net_epoll_echo_server() {
+    let addr = unix_addr("/tmp/net_epoll_echo");
+    let len = size_of::<libc::sockaddr_un>() as libc::socklen_t;
+
+    unsafe {
+        let server = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0);
+        assert!(server >= 0);
+        assert_eq!(libc::bind(server, (&raw const addr).cast(), len), 0);
+        assert_eq!(libc::listen(server, CLIENTS as _), 0);
+
+        let epfd = libc::epoll_create1(libc::EPOLL_CLOEXEC);
+        assert!(epfd >= 0);
+        let mut ev = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: server as u64,
+        };
+        assert_eq!(
+            libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, server, &mut ev),
+            0
+        );
+
+        let mut pids = Vec::new();
+
+        for id in 0..CLIENTS as u8 {
+            let pid = libc::fork();
+            assert!(pid >= 0);
+
+            if pid == 0 {
+                let fd = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0);
+                let msg = [b'e', b'0' + id];
+                let mut buf = [0u8; 2];
+                let ok = fd >= 0
+                    && libc::connect(fd, (&raw const addr).cast(), len) == 0
+                    && write_all(fd, &msg)
+                    && read_exact(fd, &mut buf)
+                    && buf == msg;
+                exit_child(ok);
+            }
+
+            pids.push(pid);
+        }
+
+        // Accept and echo from one thread, going wherever epoll says.
+        let mut served = 0;
+
+        while served < CLIENTS {
+            let mut events = [libc::epoll_event { events: 0, u64: 0 }; 8];
+            let n = libc::epoll_wait(epfd, events.as_mut_ptr(), events.len() as _, 5000);
+            assert!(n > 0, "epoll_wait timed out or failed");
+
+            for ev in &events[..n as usize] {
+                let fd = ev.u64 as i32;
+
+                if fd == server {
+                    let conn = libc::accept(server, std::ptr::null_mut(), std::ptr::null_mut());
+                    assert!(conn >= 0);
+                    let mut ev = libc::epoll_event {
+                        events: libc::EPOLLIN as u32,
+                        u64: conn as u64,
+                    };
+                    assert_eq!(libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, conn, &mut ev), 0);
+                    continue;
+                }
+
+                let mut buf = [0u8; 2];
+                assert!(read_exact(fd, &mut buf));
+                assert!(write_all(fd, &buf));
+
+                assert_eq!(
+                    libc::epoll_ctl(epfd, libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut()),
+                    0
+                );
+                libc::close(fd);
+                served += 1;
+            }
+        }
+
+        for pid in pids {
+            wait_child(pid);
+        }
+
+        libc::close(epfd);
+        libc::close(server);
+    }
+}
+
+register_test!(test_net_epoll_echo_server);
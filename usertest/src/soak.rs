@@ -0,0 +1,147 @@
+use crate::register_soak_test;
+
+const FORK_ROUNDS: usize = 20;
+const FORK_DEPTH: u32 = 5;
+const FD_ROUNDS: usize = 5000;
+const MMAP_ROUNDS: usize = 2000;
+const THREAD_ROUNDS: usize = 500;
+
+/// Forks two children, each of which does the same until `depth` runs out,
+/// and reaps them. A fork turned away by `RLIMIT_NPROC` is fine; the depth
+/// keeps the tree finite either way.
+fn fork_tree(depth: u32) {
+    if depth == 0 {
+        return;
+    }
+
+    let mut children = Vec::new();
+
+    for _ in 0..2 {
+        let pid = unsafe { libc::fork() };
+
+        if pid == 0 {
+            fork_tree(depth - 1);
+            unsafe { libc::_exit(0) };
+        } else if pid > 0 {
+            children.push(pid);
+        } else {
+            let errno = unsafe { *libc::__errno_location() };
+            assert_eq!(errno, libc::EAGAIN, "fork failed unexpectedly");
+        }
+    }
+
+    for pid in children {
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+}
+
+fn test_soak_fork_bomb() {
+    let lim = libc::rlimit {
+        rlim_cur: 48,
+        rlim_max: 48,
+    };
+    assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_NPROC, &lim) }, 0);
+
+    for _ in 0..FORK_ROUNDS {
+        fork_tree(FORK_DEPTH);
+    }
+
+    // Every child has been reaped, so there's nothing left to wait for.
+    let errno = || unsafe { *libc::__errno_location() };
+    assert_eq!(unsafe { libc::wait(std::ptr::null_mut()) }, -1);
+    assert_eq!(errno(), libc::ECHILD);
+}
+
+register_soak_test!(test_soak_fork_bomb);
+
+fn test_soak_fd_churn() {
+    let path = c"/dev/null";
+
+    for _ in 0..FD_ROUNDS {
+        unsafe {
+            let file = libc::open(path.as_ptr(), libc::O_RDWR);
+            assert!(file >= 0);
+
+            let mut pipe = [0; 2];
+            assert_eq!(libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC), 0);
+
+            let mut pair = [0; 2];
+            assert_eq!(
+                libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, pair.as_mut_ptr()),
+                0
+            );
+
+            let dup = libc::dup(file);
+            assert!(dup >= 0);
+            let high = libc::fcntl(pipe[0], libc::F_DUPFD_CLOEXEC, 100);
+            assert!(high >= 100);
+
+            assert_eq!(libc::write(pair[0], b"x".as_ptr().cast(), 1), 1);
+            assert_eq!(libc::write(pipe[1], b"y".as_ptr().cast(), 1), 1);
+
+            for fd in [file, pipe[0], pipe[1], pair[0], pair[1], dup, high] {
+                assert_eq!(libc::close(fd), 0);
+            }
+        }
+    }
+}
+
+register_soak_test!(test_soak_fd_churn);
+
+fn test_soak_mmap_churn() {
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+
+    for round in 0..MMAP_ROUNDS {
+        let pages = round % 64 + 1;
+        let len = pages * page;
+
+        unsafe {
+            let addr = libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(addr, libc::MAP_FAILED);
+
+            let bytes = addr.cast::<u8>();
+            for i in 0..pages {
+                bytes.add(i * page).write_volatile(round as u8);
+            }
+
+            // Split the larger mappings, so that the pieces have to be
+            // torn down separately.
+            if pages > 2 {
+                assert_eq!(
+                    libc::mprotect(addr.byte_add(page), page, libc::PROT_READ),
+                    0
+                );
+                assert_eq!(libc::munmap(addr.byte_add(page), page), 0);
+                assert_eq!(libc::munmap(addr, page), 0);
+                assert_eq!(libc::munmap(addr.byte_add(2 * page), len - 2 * page), 0);
+            } else {
+                assert_eq!(libc::munmap(addr, len), 0);
+            }
+        }
+    }
+}
+
+register_soak_test!(test_soak_mmap_churn);
+
+fn test_soak_thread_churn() {
+    for round in 0..THREAD_ROUNDS {
+        let handles: Vec<_> = (0..4)
+            .map(|i| std::thread::spawn(move || round * 4 + i))
+            .collect();
+
+        let sum: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(sum, round * 16 + 6);
+    }
+}
+
+register_soak_test!(test_soak_thread_churn);
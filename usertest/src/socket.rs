@@ -10,6 +10,7 @@ pub fn test_tcp_socket_creation() {
         if sockfd < 0 {
             panic!("Failed to create TCP socket");
         }
+        libc::close(sockfd);
     }
 }
 
@@ -21,12 +22,14 @@ pub fn test_unix_socket_creation() {
         if sockfd < 0 {
             panic!("Failed to create UNIX stream socket");
         }
+        libc::close(sockfd);
     }
     unsafe {
         let sockfd = socket(AF_UNIX, SOCK_DGRAM, 0);
         if sockfd < 0 {
             panic!("Failed to create UNIX datagram socket");
         }
+        libc::close(sockfd);
     }
 }
 
@@ -66,6 +69,7 @@ pub fn test_unix_socket_basic_functions() {
     if shutdown_result < 0 {
         panic!("Failed to shutdown UNIX socket");
     }
+    unsafe { libc::close(sockfd) };
 }
 
 register_test!(test_unix_socket_basic_functions);
@@ -186,7 +190,7 @@ pub fn test_rust_unix_socket() {
     let path = "/tmp/rust_uds_test";
     let listener = UnixListener::bind(path).expect("Failed to bind UNIX socket");
 
-    thread::spawn(move || {
+    let server = thread::spawn(move || {
         let (mut stream, _) = listener.accept().expect("Failed to accept connection");
         let mut buf = [0u8; 5];
         stream
@@ -211,6 +215,8 @@ pub fn test_rust_unix_socket() {
     // if &buf != b"world" {
     //     panic!("Client read incorrect data");
     // }
+    drop(stream);
+    server.join().expect("Server thread panicked");
 }
 
 register_test!(test_rust_unix_socket);
@@ -0,0 +1,325 @@
+use crate::register_test;
+use std::{
+    os::unix::thread::JoinHandleExt,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+const SMALL_STACK: usize = 16 * 1024;
+const SMALL_STACK_ROUNDS: usize = 20;
+const SMALL_STACK_THREADS: usize = 16;
+const PING_PONGS: usize = 1000;
+const STORM_THREADS: usize = 4;
+const STORM_SIGNALS: usize = 200;
+const FORKS_PER_THREAD: usize = 10;
+
+fn gettid() -> i32 {
+    unsafe { libc::syscall(libc::SYS_gettid) as i32 }
+}
+
+/// Burns through a little of the stack, to show that it's really there.
+#[inline(never)]
+fn recurse(depth: u32) -> u32 {
+    let buf = [depth as u8; 64];
+    let sum = std::hint::black_box(&buf)
+        .iter()
+        .map(|&b| b as u32)
+        .sum::<u32>();
+
+    if depth == 0 {
+        sum
+    } else {
+        sum + recurse(depth - 1)
+    }
+}
+
+extern "C" fn small_stack_thread(arg: *mut libc::c_void) -> *mut libc::c_void {
+    let depth = arg as usize as u32;
+    recurse(depth) as usize as *mut libc::c_void
+}
+
+fn test_torture_small_stacks() {
+    unsafe {
+        let mut attr: libc::pthread_attr_t = std::mem::zeroed();
+        assert_eq!(libc::pthread_attr_init(&mut attr), 0);
+        assert_eq!(
+            libc::pthread_attr_setstacksize(&mut attr, SMALL_STACK.max(libc::PTHREAD_STACK_MIN)),
+            0
+        );
+
+        for _ in 0..SMALL_STACK_ROUNDS {
+            let mut threads = Vec::new();
+
+            for i in 0..SMALL_STACK_THREADS {
+                let mut thread = std::ptr::null_mut();
+                assert_eq!(
+                    libc::pthread_create(
+                        &mut thread,
+                        &attr,
+                        small_stack_thread,
+                        (i * 2) as *mut libc::c_void,
+                    ),
+                    0
+                );
+                threads.push((thread, i * 2));
+            }
+
+            for (thread, depth) in threads {
+                let mut ret = std::ptr::null_mut();
+                assert_eq!(libc::pthread_join(thread, &mut ret), 0);
+                assert_eq!(ret as usize as u32, recurse(depth as u32));
+            }
+        }
+
+        libc::pthread_attr_destroy(&mut attr);
+    }
+}
+
+register_test!(test_torture_small_stacks);
+
+fn test_torture_condvar_timeouts() {
+    // Nobody signals, so the wait has to run its course.
+    unsafe {
+        let mut mutex = libc::PTHREAD_MUTEX_INITIALIZER;
+        let mut cond = libc::PTHREAD_COND_INITIALIZER;
+        let mut deadline: libc::timespec = std::mem::zeroed();
+        libc::clock_gettime(libc::CLOCK_REALTIME, &mut deadline);
+        deadline.tv_nsec += 50_000_000;
+        if deadline.tv_nsec >= 1_000_000_000 {
+            deadline.tv_sec += 1;
+            deadline.tv_nsec -= 1_000_000_000;
+        }
+
+        let start = Instant::now();
+        assert_eq!(libc::pthread_mutex_lock(&mut mutex), 0);
+        assert_eq!(
+            libc::pthread_cond_timedwait(&mut cond, &mut mutex, &deadline),
+            libc::ETIMEDOUT
+        );
+        assert_eq!(libc::pthread_mutex_unlock(&mut mutex), 0);
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
+
+    // Two threads take turns, each waiting with a timeout that should never
+    // be reached.
+    let state = Arc::new((Mutex::new(0usize), Condvar::new()));
+    let other = state.clone();
+
+    let odd = std::thread::spawn(move || {
+        let (lock, cond) = &*other;
+        let mut turn = lock.lock().unwrap();
+
+        while *turn < PING_PONGS {
+            if *turn % 2 == 1 {
+                *turn += 1;
+                cond.notify_one();
+            }
+
+            let (next, res) = cond.wait_timeout(turn, Duration::from_secs(5)).unwrap();
+            assert!(!res.timed_out() || *next >= PING_PONGS);
+            turn = next;
+        }
+    });
+
+    let (lock, cond) = &*state;
+    let mut turn = lock.lock().unwrap();
+
+    while *turn < PING_PONGS {
+        if *turn % 2 == 0 {
+            *turn += 1;
+            cond.notify_one();
+        }
+
+        let (next, res) = cond.wait_timeout(turn, Duration::from_secs(5)).unwrap();
+        assert!(!res.timed_out() || *next >= PING_PONGS);
+        turn = next;
+    }
+
+    cond.notify_all();
+    drop(turn);
+    odd.join().unwrap();
+}
+
+register_test!(test_torture_condvar_timeouts);
+
+static STORM_TIDS: [AtomicI32; STORM_THREADS] = [const { AtomicI32::new(0) }; STORM_THREADS];
+static STORM_HANDLED: [AtomicUsize; STORM_THREADS] = [const { AtomicUsize::new(0) }; STORM_THREADS];
+static STORM_WRONG: AtomicUsize = AtomicUsize::new(0);
+static STORM_STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn storm_handler(_: libc::c_int) {
+    let tid = gettid();
+
+    // The signal must land on the thread it was sent to, and run on that
+    // thread's alternate stack.
+    let mut ss: libc::stack_t = unsafe { std::mem::zeroed() };
+    let on_stack = unsafe { libc::sigaltstack(std::ptr::null(), &mut ss) } == 0
+        && ss.ss_flags & libc::SS_ONSTACK != 0;
+
+    match STORM_TIDS
+        .iter()
+        .position(|t| t.load(Ordering::Relaxed) == tid)
+    {
+        Some(i) if on_stack => {
+            STORM_HANDLED[i].fetch_add(1, Ordering::Relaxed);
+        }
+        _ => {
+            STORM_WRONG.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn storm_thread(i: usize) {
+    let stack = vec![0u8; libc::SIGSTKSZ.max(4 * 4096)];
+    let ss = libc::stack_t {
+        ss_sp: stack.as_ptr() as *mut _,
+        ss_flags: 0,
+        ss_size: stack.len(),
+    };
+    assert_eq!(unsafe { libc::sigaltstack(&ss, std::ptr::null_mut()) }, 0);
+
+    STORM_TIDS[i].store(gettid(), Ordering::Release);
+
+    // Keep going in and out of the kernel, sometimes blocking, so that
+    // signals turn up at all sorts of points.
+    while !STORM_STOP.load(Ordering::Acquire) {
+        unsafe {
+            libc::getppid();
+            libc::usleep(50);
+        }
+    }
+
+    // Nothing may be delivered to the stack once it's gone.
+    let off = libc::stack_t {
+        ss_sp: std::ptr::null_mut(),
+        ss_flags: libc::SS_DISABLE,
+        ss_size: 0,
+    };
+    assert_eq!(unsafe { libc::sigaltstack(&off, std::ptr::null_mut()) }, 0);
+}
+
+fn test_torture_signal_storm() {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = storm_handler as *const () as usize;
+        action.sa_flags = libc::SA_ONSTACK | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        assert_eq!(
+            libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()),
+            0
+        );
+    }
+
+    let threads: Vec<_> = (0..STORM_THREADS)
+        .map(|i| std::thread::spawn(move || storm_thread(i)))
+        .collect();
+
+    while STORM_TIDS.iter().any(|t| t.load(Ordering::Acquire) == 0) {
+        std::thread::yield_now();
+    }
+
+    // Standard signals don't queue, so each thread is only sent another once
+    // it has dealt with the last. Alternate between pthread_kill and tgkill.
+    let pid = unsafe { libc::getpid() };
+    let mut sent = [0usize; STORM_THREADS];
+    let deadline = Instant::now() + Duration::from_secs(30);
+
+    while sent.iter().any(|&n| n < STORM_SIGNALS) {
+        assert!(Instant::now() < deadline, "signals went missing: {sent:?}");
+
+        for (i, thread) in threads.iter().enumerate() {
+            if sent[i] == STORM_SIGNALS || STORM_HANDLED[i].load(Ordering::Relaxed) < sent[i] {
+                continue;
+            }
+
+            let ret = if sent[i] % 2 == 0 {
+                unsafe { libc::pthread_kill(thread.as_pthread_t() as usize as _, libc::SIGUSR1) }
+            } else {
+                let tid = STORM_TIDS[i].load(Ordering::Relaxed);
+                unsafe { libc::syscall(libc::SYS_tgkill, pid, tid, libc::SIGUSR1) as i32 }
+            };
+            assert_eq!(ret, 0);
+            sent[i] += 1;
+        }
+
+        std::thread::yield_now();
+    }
+
+    while (0..STORM_THREADS).any(|i| STORM_HANDLED[i].load(Ordering::Relaxed) < STORM_SIGNALS) {
+        assert!(Instant::now() < deadline, "signals went missing");
+        std::thread::yield_now();
+    }
+
+    // A thread has to be looked for in the group it's said to be in.
+    let tid = STORM_TIDS[0].load(Ordering::Relaxed);
+    unsafe {
+        assert_eq!(
+            libc::syscall(libc::SYS_tgkill, libc::getppid(), tid, libc::SIGUSR1),
+            -1
+        );
+        assert_eq!(*libc::__errno_location(), libc::ESRCH);
+    }
+
+    STORM_STOP.store(true, Ordering::Release);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(STORM_WRONG.load(Ordering::Relaxed), 0);
+}
+
+register_test!(test_torture_signal_storm);
+
+fn test_torture_fork_in_threads() {
+    // One thread holds the allocator busy while the others fork, so that the
+    // children have to cope with whatever state the parent was in.
+    let stop = Arc::new(AtomicBool::new(false));
+    let churn = {
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                let v: Vec<u8> = vec![1; 4096];
+                std::hint::black_box(v);
+            }
+        })
+    };
+
+    let forkers: Vec<_> = (0..4)
+        .map(|t| {
+            std::thread::spawn(move || {
+                for i in 0..FORKS_PER_THREAD {
+                    let code = (t * FORKS_PER_THREAD + i) as i32;
+                    let pid = unsafe { libc::fork() };
+                    assert!(pid >= 0);
+
+                    if pid == 0 {
+                        // Only the forking thread comes across; it can still
+                        // allocate and make threads of its own.
+                        let v: Vec<u8> = vec![code as u8; 8192];
+                        let ok = std::thread::spawn(move || v.iter().all(|&b| b == code as u8))
+                            .join()
+                            .unwrap_or(false);
+                        unsafe { libc::_exit(if ok { code } else { 255 }) };
+                    }
+
+                    let mut status = 0;
+                    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+                    assert!(libc::WIFEXITED(status));
+                    assert_eq!(libc::WEXITSTATUS(status), code);
+                }
+            })
+        })
+        .collect();
+
+    for forker in forkers {
+        forker.join().unwrap();
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    churn.join().unwrap();
+}
+
+register_test!(test_torture_fork_in_threads);
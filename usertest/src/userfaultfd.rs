@@ -0,0 +1,211 @@
+use crate::register_test;
+use std::{mem::size_of, thread};
+
+const UFFD_API: u64 = 0xaa;
+const UFFD_USER_MODE_ONLY: libc::c_int = 1;
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+const UFFD_PAGEFAULT_FLAG_WRITE: u64 = 1;
+const UFFD_FEATURE_THREAD_ID: u64 = 1 << 8;
+const UFFDIO_REGISTER_MODE_MISSING: u64 = 1;
+
+const UFFDIO_API: u32 = 0xc018_aa3f;
+const UFFDIO_REGISTER: u32 = 0xc020_aa00;
+const UFFDIO_COPY: u32 = 0xc028_aa03;
+const UFFDIO_ZEROPAGE: u32 = 0xc020_aa04;
+
+const PAGE_SIZE: usize = 4096;
+
+#[repr(C)]
+#[derive(Default)]
+struct UffdMsg {
+    event: u8,
+    _reserved1: u8,
+    _reserved2: u16,
+    _reserved3: u32,
+    flags: u64,
+    address: u64,
+    ptid: u32,
+    _pad: u32,
+}
+
+#[repr(C)]
+struct UffdioApi {
+    api: u64,
+    features: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+struct UffdioRange {
+    start: u64,
+    len: u64,
+}
+
+#[repr(C)]
+struct UffdioRegister {
+    range: UffdioRange,
+    mode: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+struct UffdioCopy {
+    dst: u64,
+    src: u64,
+    len: u64,
+    mode: u64,
+    copy: i64,
+}
+
+#[repr(C)]
+struct UffdioZeropage {
+    range: UffdioRange,
+    mode: u64,
+    zeropage: i64,
+}
+
+/// Opens a userfaultfd, negotiates the API and registers `pages` pages of
+/// fresh anonymous memory with it.
+unsafe fn setup(pages: usize) -> (libc::c_int, *mut u8) {
+    unsafe {
+        let fd = libc::syscall(libc::SYS_userfaultfd, libc::O_CLOEXEC | UFFD_USER_MODE_ONLY)
+            as libc::c_int;
+        assert!(
+            fd >= 0,
+            "userfaultfd failed: {}",
+            std::io::Error::last_os_error()
+        );
+
+        let mut api = UffdioApi {
+            api: UFFD_API,
+            features: UFFD_FEATURE_THREAD_ID,
+            ioctls: 0,
+        };
+        assert_eq!(libc::ioctl(fd, UFFDIO_API as _, &mut api), 0);
+        assert_ne!(api.features & UFFD_FEATURE_THREAD_ID, 0);
+
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            pages * PAGE_SIZE,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        if addr == libc::MAP_FAILED {
+            panic!("mmap failed: {}", std::io::Error::last_os_error());
+        }
+
+        let mut reg = UffdioRegister {
+            range: UffdioRange {
+                start: addr as u64,
+                len: (pages * PAGE_SIZE) as u64,
+            },
+            mode: UFFDIO_REGISTER_MODE_MISSING,
+            ioctls: 0,
+        };
+        assert_eq!(
+            libc::ioctl(fd, UFFDIO_REGISTER as _, &mut reg),
+            0,
+            "UFFDIO_REGISTER failed: {}",
+            std::io::Error::last_os_error()
+        );
+        assert_ne!(reg.ioctls & (1 << 3), 0, "UFFDIO_COPY not available");
+
+        (fd, addr.cast())
+    }
+}
+
+fn test_userfaultfd_copy() {
+    unsafe {
+        let (fd, addr) = setup(1);
+        let addr_val = addr as usize;
+
+        // The faulting thread sleeps until the page is provided below.
+        let toucher = thread::spawn(move || {
+            let p = addr_val as *mut u8;
+            let val = p.add(10).read_volatile();
+            p.write_volatile(val + 1);
+            (val, libc::gettid())
+        });
+
+        let mut msg = UffdMsg::default();
+        let n = libc::read(fd, (&mut msg as *mut UffdMsg).cast(), size_of::<UffdMsg>());
+        assert_eq!(n, size_of::<UffdMsg>() as isize);
+        assert_eq!(msg.event, UFFD_EVENT_PAGEFAULT);
+        assert_eq!(msg.address, addr as u64);
+        assert_eq!(msg.flags & UFFD_PAGEFAULT_FLAG_WRITE, 0);
+
+        let src = [0x5au8; PAGE_SIZE];
+        let mut copy = UffdioCopy {
+            dst: addr as u64,
+            src: src.as_ptr() as u64,
+            len: PAGE_SIZE as u64,
+            mode: 0,
+            copy: 0,
+        };
+        assert_eq!(libc::ioctl(fd, UFFDIO_COPY as _, &mut copy), 0);
+        assert_eq!(copy.copy, PAGE_SIZE as i64);
+
+        let (val, tid) = toucher.join().unwrap();
+        assert_eq!(val, 0x5a);
+        assert_eq!(msg.ptid, tid as u32);
+        assert_eq!(addr.read_volatile(), 0x5a);
+        assert_eq!(addr.add(10).read_volatile(), 0x5b);
+
+        // The page is populated now.
+        assert_eq!(libc::ioctl(fd, UFFDIO_COPY as _, &mut copy), -1);
+        assert_eq!(*libc::__errno_location(), libc::EEXIST);
+        assert_eq!(copy.copy, -libc::EEXIST as i64);
+
+        libc::munmap(addr.cast(), PAGE_SIZE);
+        libc::close(fd);
+    }
+}
+
+register_test!(test_userfaultfd_copy);
+
+fn test_userfaultfd_zeropage() {
+    unsafe {
+        let (fd, addr) = setup(2);
+        let addr_val = addr as usize;
+
+        let toucher = thread::spawn(move || {
+            let p = (addr_val + PAGE_SIZE) as *mut u8;
+            p.write_volatile(7);
+            p.read_volatile()
+        });
+
+        let mut msg = UffdMsg::default();
+        let n = libc::read(fd, (&mut msg as *mut UffdMsg).cast(), size_of::<UffdMsg>());
+        assert_eq!(n, size_of::<UffdMsg>() as isize);
+        assert_eq!(msg.address, (addr_val + PAGE_SIZE) as u64);
+        assert_ne!(msg.flags & UFFD_PAGEFAULT_FLAG_WRITE, 0);
+
+        let mut zero = UffdioZeropage {
+            range: UffdioRange {
+                start: addr as u64,
+                len: (2 * PAGE_SIZE) as u64,
+            },
+            mode: 0,
+            zeropage: 0,
+        };
+        assert_eq!(libc::ioctl(fd, UFFDIO_ZEROPAGE as _, &mut zero), 0);
+        assert_eq!(zero.zeropage, (2 * PAGE_SIZE) as i64);
+
+        assert_eq!(toucher.join().unwrap(), 7);
+        assert_eq!(addr.read_volatile(), 0);
+
+        // Nothing is pending, so a non-blocking read finds nothing.
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        assert_eq!(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK), 0);
+        let n = libc::read(fd, (&mut msg as *mut UffdMsg).cast(), size_of::<UffdMsg>());
+        assert_eq!(n, -1);
+        assert_eq!(*libc::__errno_location(), libc::EAGAIN);
+
+        libc::munmap(addr.cast(), 2 * PAGE_SIZE);
+        libc::close(fd);
+    }
+}
+
+register_test!(test_userfaultfd_zeropage);